@@ -0,0 +1,99 @@
+//! Embeds build details for `environment.json` in report bundles: the git
+//! revision, target, profile, and the locked versions of the workspace
+//! crates and their direct dependencies.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+use std::process::Command;
+
+fn main() {
+    let lock = Path::new(env!("CARGO_MANIFEST_DIR")).join("../../Cargo.lock");
+    println!("cargo:rerun-if-changed={}", lock.display());
+    if Path::new("../../.git/HEAD").exists() {
+        println!("cargo:rerun-if-changed=../../.git/HEAD");
+    }
+
+    let versions = std::fs::read_to_string(&lock)
+        .map(|text| locked_versions(&text))
+        .unwrap_or_default();
+    println!("cargo:rustc-env=PHOENIX_CRATE_VERSIONS={}", versions);
+
+    let git_rev = Command::new("git")
+        .args(["rev-parse", "--short=12", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+        .unwrap_or_default();
+    println!("cargo:rustc-env=PHOENIX_GIT_REV={}", git_rev);
+    for name in ["TARGET", "PROFILE"] {
+        println!(
+            "cargo:rustc-env=PHOENIX_BUILD_{}={}",
+            name,
+            std::env::var(name).unwrap_or_default()
+        );
+    }
+}
+
+struct Package {
+    name: String,
+    version: String,
+    local: bool,
+    dependencies: Vec<String>,
+}
+
+/// `name@version` pairs, comma separated, for workspace crates and the
+/// crates they depend on directly.
+fn locked_versions(lock: &str) -> String {
+    let mut packages = Vec::new();
+    for block in lock.split("[[package]]").skip(1) {
+        let mut package = Package {
+            name: String::new(),
+            version: String::new(),
+            local: true,
+            dependencies: Vec::new(),
+        };
+        let mut in_dependencies = false;
+        for line in block.lines().map(str::trim) {
+            if in_dependencies {
+                if line.starts_with(']') {
+                    in_dependencies = false;
+                } else if let Some(dep) = line.trim_end_matches(',').strip_prefix('"') {
+                    let dep = dep.trim_end_matches('"');
+                    package
+                        .dependencies
+                        .push(dep.split(' ').next().unwrap_or(dep).to_string());
+                }
+            } else if let Some(value) = line.strip_prefix("name = ") {
+                package.name = value.trim_matches('"').to_string();
+            } else if let Some(value) = line.strip_prefix("version = ") {
+                package.version = value.trim_matches('"').to_string();
+            } else if line.starts_with("source = ") {
+                package.local = false;
+            } else if line.starts_with("dependencies = [") {
+                in_dependencies = true;
+            }
+        }
+        packages.push(package);
+    }
+
+    let wanted: BTreeSet<&str> = packages
+        .iter()
+        .filter(|package| package.local)
+        .flat_map(|package| {
+            std::iter::once(package.name.as_str())
+                .chain(package.dependencies.iter().map(String::as_str))
+        })
+        .collect();
+    let mut versions: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+    for package in &packages {
+        if wanted.contains(package.name.as_str()) {
+            versions.entry(&package.name).or_default().push(&package.version);
+        }
+    }
+    versions
+        .iter()
+        .flat_map(|(name, versions)| versions.iter().map(move |version| format!("{}@{}", name, version)))
+        .collect::<Vec<_>>()
+        .join(",")
+}
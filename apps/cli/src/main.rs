@@ -1,25 +1,101 @@
 use anyhow::{anyhow, Result};
 use clap::{Parser, Subcommand};
-use phoenix_imaging::{HashProgress, ProgressObserver};
 use phoenix_workflow_engine::{
-    run_disk_hash_report, run_unix_installer_usb, run_windows_apply_image,
-    run_windows_installer_usb, validate_workflow_definition, DiskHashReportParams,
-    UnixInstallerUsbParams, WindowsApplyImageParams, WindowsInstallerUsbParams,
-    run_stage_bootloader, BootloaderStageParams, run_macos_kext_stage, MacosKextStageParams,
+    run_disk_hash_report, validate_workflow_definition, DiskHashReportParams,
+    run_bad_block_scan, parse_scan_mode, BadBlockScanParams,
+    parse_data_encryption, run_combo_stick, ComboStickParams,
+    run_ab_stick, run_ab_update, AbStickParams, AbUpdateParams,
+    parse_resize_mode, run_resize_partition, ResizePartitionParams,
+    run_stage_bootloader, BootloaderStageParams, recovery_guidance, DeviceRegistry, RunLedger,
+    read_audit_log, audit_log_path,
+    run_validate_source, ValidateSourceParams, run_slim_windows_media, MediaKeepList,
+    SlimWindowsMediaParams, run_merge_windows_languages, MergeWindowsLanguagesParams,
+    run_stage_files, StageFileRule, StageFilesParams, StageOverwrite, SourceFilter,
+    run_media_audit, MediaAuditParams, run_kiosk, KioskConfirm, KioskEvent, KioskObserver,
+    KioskParams, KioskPolicy, run_agent, AgentEvent, AgentObserver, AgentParams, RemoteAllowList,
+    init_fleet_ca, issue_fleet_cert, CertKind, IssuedCert,
+    plan_duplicate_to_all, run_duplicate_to_all, DuplicateParams, run_clone_device,
+    CloneDeviceParams, run_restore_report, RestoreReportParams,
+    describe_destruction, DestructionParams, DestructiveAction, run_stage_provisioning,
+    ProvisioningLayout, StageProvisioningParams, run_macos_erase_install, resolve_secret,
+    MacosEraseInstallParams, list_dfu_devices, run_ipsw_restore, IpswRestoreParams, RestoreMode,
+    RestoreTool, run_boot_entry, BootEntryParams, WorkflowTimeout, with_log_listener,
+    with_progress_listener, LogEntry, TransferProgress,
+};
+#[cfg(windows)]
+use phoenix_workflow_engine::{
+    run_windows_apply_image, run_windows_installer_usb, WindowsApplyImageParams,
+    WindowsInstallerUsbParams,
 };
+#[cfg(unix)]
+use phoenix_workflow_engine::{run_unix_installer_usb, UnixInstallerUsbParams};
+#[cfg(target_os = "macos")]
+use phoenix_workflow_engine::{run_macos_kext_stage, MacosKextStageParams};
+#[cfg(windows)]
+use phoenix_imaging::{HashProgress, ProgressObserver};
+#[cfg(windows)]
 use phoenix_host_windows::format::parse_filesystem;
 use phoenix_content::{
-    export_pack_zip, load_pack_manifest, load_workflow_definition, pack_signature_exists,
-    resolve_pack_workflows, resolve_windows_image, sign_pack_manifest,
-    verify_pack_manifest, PACK_SCHEMA_VERSION,
+    cache_pack_assets, export_pack_zip, load_pack_manifest, load_workflow_definition, pack_signature_exists,
+    payload_file_hashes, read_media_manifest, resolve_pack_workflows, sign_pack_manifest, verify_pack_manifest,
+    write_media_manifest, MediaManifest, PACK_SCHEMA_VERSION,
 };
+#[cfg(windows)]
+use phoenix_content::resolve_windows_image;
+#[cfg(windows)]
 use phoenix_wim::{apply_image as wim_apply_image, list_images as wim_list_images};
-use phoenix_core::{DeviceGraph, WorkflowDefinition};
+use phoenix_core::{
+    display_format, format_bytes, format_duration_ms, DeviceGraph, DisplayFormat, UnitSystem,
+    WorkflowDefinition,
+};
 use phoenix_legacy_patcher::{LegacyPatchParams, run_legacy_patch};
+use std::cell::RefCell;
+use std::io::{IsTerminal, Write};
+use std::rc::Rc;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
 
 #[derive(Parser)]
 #[command(name = "phoenix-cli", version, about = "Phoenix Core CLI (Windows-first)")]
 struct Cli {
+    /// External reference (ticket, work order) recorded in reports, run
+    /// records and notifications (default: $PHOENIX_CORRELATION_ID)
+    #[arg(long, global = true)]
+    correlation_id: Option<String>,
+
+    /// Refuse every format, write, apply and patch regardless of --force
+    /// and --token (also: PHOENIX_READONLY=1)
+    #[arg(long, global = true)]
+    read_only: bool,
+
+    /// Force approvals expire this many minutes after the command starts
+    /// (also: PHOENIX_ARMED_UNTIL=<unix time>)
+    #[arg(long, global = true, value_name = "MINUTES")]
+    armed_for: Option<u64>,
+
+    /// JSON report artifacts: pretty, compact or zstd (`<name>.zst`)
+    /// (also: PHOENIX_ARTIFACT_ENCODING)
+    #[arg(long, global = true, value_name = "ENCODING")]
+    artifact_encoding: Option<String>,
+
+    /// Export workflow and phase spans to this OTLP/HTTP collector, e.g.
+    /// http://localhost:4318 (also: OTEL_EXPORTER_OTLP_ENDPOINT)
+    #[arg(long, global = true, value_name = "URL")]
+    otlp_endpoint: Option<String>,
+
+    /// Progress of long copies, writes and hashes on stderr: plain (a line
+    /// every few seconds, for screen readers and CI logs), json (one event
+    /// per line) or fancy (a redrawn bar) (default: fancy on a terminal,
+    /// plain otherwise)
+    #[arg(long, global = true, value_name = "MODE")]
+    progress: Option<String>,
+
+    /// Units for sizes shown to people: decimal (GB) or binary (GiB); the
+    /// decimal separator follows the locale (also: PHOENIX_UNITS,
+    /// PHOENIX_LOCALE)
+    #[arg(long, global = true, value_name = "UNITS")]
+    units: Option<String>,
+
     #[command(subcommand)]
     cmd: Commands,
 }
@@ -31,6 +107,36 @@ enum Commands {
         /// Pretty JSON output
         #[arg(long)]
         pretty: bool,
+
+        /// Load the graph from a report bundle (or its device_graph.json)
+        /// instead of this host
+        #[arg(long)]
+        from_report: Option<String>,
+
+        /// Explain whether this disk id, device path or mount point would be
+        /// accepted as a workflow target
+        #[arg(long)]
+        target: Option<String>,
+    },
+
+    /// Say in plain words what a destructive run would wipe on a disk
+    DescribeDestruction {
+        /// Disk id, device path, mount point or USB port label
+        #[arg(long)]
+        target: String,
+
+        /// erase (whole disk) or format (existing volumes)
+        #[arg(long, default_value = "erase")]
+        action: String,
+
+        /// Describe a disk from a report bundle's device graph; volume
+        /// contents are not inspected
+        #[arg(long)]
+        from_report: Option<String>,
+
+        /// Pretty JSON output
+        #[arg(long)]
+        pretty: bool,
     },
 
     /// Create a report bundle (reports/<run_id>/)
@@ -60,6 +166,14 @@ enum Commands {
         /// Output zip file path
         #[arg(long)]
         out: String,
+
+        /// Deflate compression level (0-9)
+        #[arg(long)]
+        compression_level: Option<i64>,
+
+        /// Store entries without compression
+        #[arg(long)]
+        store: bool,
     },
 
     /// Verify all report bundles under a root directory
@@ -73,6 +187,90 @@ enum Commands {
         key: Option<String>,
     },
 
+    /// List indexed runs newest first, with status, target and duration
+    ReportList {
+        /// Report base (the directory holding `reports/`)
+        #[arg(long)]
+        base: String,
+
+        /// Only runs of this workflow
+        #[arg(long)]
+        workflow: Option<String>,
+
+        /// Only runs with this status (succeeded, failed, dry_run)
+        #[arg(long)]
+        status: Option<String>,
+
+        /// Only runs whose target contains this text
+        #[arg(long)]
+        target: Option<String>,
+
+        /// Show at most this many runs
+        #[arg(long)]
+        limit: Option<usize>,
+
+        /// Print the runs as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// List report bundles recorded under a correlation id
+    ReportFind {
+        /// Report base (the directory holding `reports/`)
+        #[arg(long)]
+        base: String,
+
+        /// Correlation id to look up
+        #[arg(long = "id")]
+        id: String,
+
+        /// Rebuild reports/index.jsonl from every bundle's run.json first
+        #[arg(long)]
+        rebuild_index: bool,
+    },
+
+    /// Apply a retention policy to a report base (default: <base>/retention.json)
+    ReportPrune {
+        /// Report base (the directory holding `reports/`)
+        #[arg(long)]
+        base: String,
+
+        /// Keep at most this many bundles
+        #[arg(long)]
+        max_bundles: Option<usize>,
+
+        /// Prune bundles older than this many days
+        #[arg(long)]
+        max_age_days: Option<u64>,
+
+        /// Prune oldest bundles until the total is under this many bytes
+        #[arg(long)]
+        max_total_bytes: Option<u64>,
+
+        /// Directory or http(s) URL that receives <run_id>.zip before deleting
+        #[arg(long)]
+        archive: Option<String>,
+
+        /// Only list what would be pruned
+        #[arg(long)]
+        dry_run: bool,
+    },
+
+    /// Summarize every report bundle under a directory (fleet summary)
+    ReportAggregate {
+        /// Directory searched recursively for report bundles
+        #[arg(long)]
+        root: String,
+
+        /// Output JSON path (default: <root>/fleet-summary.json)
+        #[arg(long)]
+        out: Option<String>,
+
+        /// Signing key hex for signature verification
+        #[arg(long)]
+        key: Option<String>,
+    },
+
     /// Read-only hash chunks from a PhysicalDrive (Windows)
     HashDisk {
         /// Disk id like: PhysicalDrive0
@@ -90,10 +288,6 @@ enum Commands {
         /// Max chunks to hash (safety for quick runs)
         #[arg(long)]
         max_chunks: Option<u64>,
-
-        /// Print progress per chunk
-        #[arg(long)]
-        progress: bool,
     },
 
     /// Create a Windows installer USB (MVP)
@@ -122,14 +316,38 @@ enum Commands {
         #[arg(long)]
         token: Option<String>,
 
+        /// Proceed even though the target disk is outside the safety size
+        /// limits; recorded in the report
+        #[arg(long)]
+        acknowledge_target_size: bool,
+
+        /// Proceed even though the device is past the wear limits (flash
+        /// count or throughput drop); recorded in the report and audit log
+        #[arg(long)]
+        acknowledge_device_wear: bool,
+
+        /// Wipe the target even if its label or contents look like personal
+        /// data (DCIM/, Documents/, many user files)
+        #[arg(long)]
+        confirm_overwrite: bool,
+
         /// Execute copy (omit for dry-run)
         #[arg(long)]
         execute: bool,
 
-        /// Repartition disk (single GPT partition)
+        /// Repartition disk (single GPT partition unless --partition is given)
         #[arg(long)]
         repartition: bool,
 
+        /// GPT partition spec NAME:TYPE[:SIZE][:ATTR+ATTR], repeatable
+        /// (e.g. RECOVERY:recovery:1G:hidden+no-automount)
+        #[arg(long = "partition")]
+        partitions: Vec<String>,
+
+        /// Mirror the first partition into a hybrid MBR for old BIOSes
+        #[arg(long)]
+        hybrid_mbr: bool,
+
         /// Format existing volume before staging
         #[arg(long)]
         format: bool,
@@ -142,6 +360,10 @@ enum Commands {
         #[arg(long)]
         label: Option<String>,
 
+        /// Cluster size for formatting, e.g. 4K or 64K (default: Windows picks)
+        #[arg(long)]
+        cluster_size: Option<String>,
+
         /// Driver source directory to stage into $OEM$
         #[arg(long)]
         drivers: Option<String>,
@@ -153,6 +375,50 @@ enum Commands {
         /// Emit SHA-256 copy manifest into report
         #[arg(long)]
         hash_manifest: bool,
+
+        /// Read copied files back and check them against the manifest hashes
+        #[arg(long, requires = "hash_manifest")]
+        hash_destination: bool,
+
+        /// EditionID to write to sources/EI.cfg (e.g. Professional)
+        #[arg(long)]
+        edition: Option<String>,
+
+        /// install.wim image Setup should use: index, EditionID or name
+        #[arg(long)]
+        edition_selector: Option<String>,
+
+        /// Product key to write to sources/PID.txt
+        #[arg(long)]
+        pid_txt: Option<String>,
+
+        /// Only copy source files matching this glob (repeatable)
+        #[arg(long)]
+        include: Vec<String>,
+
+        /// Skip source files matching this glob, e.g. `*.mui` or
+        /// `sources/sxs` (repeatable)
+        #[arg(long)]
+        exclude: Vec<String>,
+
+        /// Hardlink identical source files instead of copying each
+        /// (NTFS, ext4, APFS targets)
+        #[arg(long)]
+        dedupe: bool,
+
+        /// Sync the target every SIZE bytes written (e.g. 256M)
+        #[arg(long, value_name = "SIZE")]
+        flush_every: Option<String>,
+
+        /// Skip files that fail to copy, finish the rest and mark the run
+        /// degraded
+        #[arg(long)]
+        continue_on_error: bool,
+
+        /// Pack manifest whose transforms edit matching files as they are
+        /// copied (grub.cfg timeout, kernel args, branding)
+        #[arg(long)]
+        transform_pack: Option<String>,
     },
 
     /// List images in a WIM/ESD file
@@ -183,9 +449,13 @@ enum Commands {
         #[arg(long)]
         source: String,
 
-        /// Image index (1-based)
+        /// Image index (1-based); optional with --edition-selector
         #[arg(long)]
-        index: u32,
+        index: Option<u32>,
+
+        /// Image to apply by index, EditionID or name
+        #[arg(long)]
+        edition_selector: Option<String>,
 
         /// Target directory (will be created)
         #[arg(long)]
@@ -199,10 +469,14 @@ enum Commands {
         #[arg(long)]
         force: bool,
 
-        /// Confirmation token (PHX-...)
+        /// Confirmation token (PHX-...; PHX-SYS-... for a system-disk target)
         #[arg(long)]
         token: Option<String>,
 
+        /// Allow the target to be on the running system's disk
+        #[arg(long)]
+        allow_system_target: bool,
+
         /// Execute apply (omit for dry-run)
         #[arg(long)]
         execute: bool,
@@ -234,6 +508,21 @@ enum Commands {
         #[arg(long)]
         token: Option<String>,
 
+        /// Proceed even though the target disk is outside the safety size
+        /// limits; recorded in the report
+        #[arg(long)]
+        acknowledge_target_size: bool,
+
+        /// Proceed even though the device is past the wear limits (flash
+        /// count or throughput drop); recorded in the report and audit log
+        #[arg(long)]
+        acknowledge_device_wear: bool,
+
+        /// Wipe the target even if its label or contents look like personal
+        /// data (DCIM/, Documents/, many user files)
+        #[arg(long)]
+        confirm_overwrite: bool,
+
         /// Execute copy (omit for dry-run)
         #[arg(long)]
         execute: bool,
@@ -242,6 +531,10 @@ enum Commands {
         #[arg(long)]
         hash_manifest: bool,
 
+        /// Read copied files back and check them against the manifest hashes
+        #[arg(long, requires = "hash_manifest")]
+        hash_destination: bool,
+
         /// Optional device path to format as FAT32 before staging
         #[arg(long)]
         format_device: Option<String>,
@@ -253,6 +546,46 @@ enum Commands {
         /// Volume label for FAT32 formatting
         #[arg(long)]
         format_label: Option<String>,
+
+        /// FAT32 cluster size, e.g. 32K (default: smallest legal size)
+        #[arg(long)]
+        format_cluster_size: Option<String>,
+
+        /// Use udisks2 (polkit) for unmount/format/mount instead of root
+        #[arg(long)]
+        udisks: bool,
+
+        /// Power off the drive via udisks2 after staging
+        #[arg(long)]
+        power_off: bool,
+
+        /// Only copy source files matching this glob (repeatable)
+        #[arg(long)]
+        include: Vec<String>,
+
+        /// Skip source files matching this glob, e.g. `*.mui` or
+        /// `sources/sxs` (repeatable)
+        #[arg(long)]
+        exclude: Vec<String>,
+
+        /// Hardlink identical source files instead of copying each
+        /// (NTFS, ext4, APFS targets)
+        #[arg(long)]
+        dedupe: bool,
+
+        /// Sync the target every SIZE bytes written (e.g. 256M)
+        #[arg(long, value_name = "SIZE")]
+        flush_every: Option<String>,
+
+        /// Skip files that fail to copy, finish the rest and mark the run
+        /// degraded
+        #[arg(long)]
+        continue_on_error: bool,
+
+        /// Pack manifest whose transforms edit matching files as they are
+        /// copied (grub.cfg timeout, kernel args, branding)
+        #[arg(long)]
+        transform_pack: Option<String>,
     },
 
     /// Create a macOS installer USB (copy-only, preformatted)
@@ -277,6 +610,21 @@ enum Commands {
         #[arg(long)]
         token: Option<String>,
 
+        /// Proceed even though the target disk is outside the safety size
+        /// limits; recorded in the report
+        #[arg(long)]
+        acknowledge_target_size: bool,
+
+        /// Proceed even though the device is past the wear limits (flash
+        /// count or throughput drop); recorded in the report and audit log
+        #[arg(long)]
+        acknowledge_device_wear: bool,
+
+        /// Wipe the target even if its label or contents look like personal
+        /// data (DCIM/, Documents/, many user files)
+        #[arg(long)]
+        confirm_overwrite: bool,
+
         /// Execute copy (omit for dry-run)
         #[arg(long)]
         execute: bool,
@@ -285,6 +633,10 @@ enum Commands {
         #[arg(long)]
         hash_manifest: bool,
 
+        /// Read copied files back and check them against the manifest hashes
+        #[arg(long, requires = "hash_manifest")]
+        hash_destination: bool,
+
         /// Optional device path to format as FAT32 before staging
         #[arg(long)]
         format_device: Option<String>,
@@ -296,52 +648,62 @@ enum Commands {
         /// Volume label for FAT32 formatting
         #[arg(long)]
         format_label: Option<String>,
-    },
 
-    /// Write a raw Linux image to a device (destructive)
-    LinuxWriteImage {
-        /// Source image file (iso/img)
+        /// FAT32 cluster size, e.g. 32K (default: smallest legal size)
         #[arg(long)]
-        source: String,
+        format_cluster_size: Option<String>,
 
-        /// Target block device (e.g. /dev/sdb)
+        /// Only copy source files matching this glob (repeatable)
         #[arg(long)]
-        device: String,
+        include: Vec<String>,
 
-        /// Base path for reports (default: current directory)
-        #[arg(long, default_value = ".")]
-        report_base: String,
+        /// Skip source files matching this glob, e.g. `*.mui` or
+        /// `sources/sxs` (repeatable)
+        #[arg(long)]
+        exclude: Vec<String>,
 
-        /// Force destructive operations
+        /// Hardlink identical source files instead of copying each
+        /// (NTFS, ext4, APFS targets)
         #[arg(long)]
-        force: bool,
+        dedupe: bool,
 
-        /// Confirmation token (PHX-...)
+        /// Sync the target every SIZE bytes written (e.g. 256M)
+        #[arg(long, value_name = "SIZE")]
+        flush_every: Option<String>,
+
+        /// Skip files that fail to copy, finish the rest and mark the run
+        /// degraded
         #[arg(long)]
-        token: Option<String>,
+        continue_on_error: bool,
 
-        /// Execute write (omit for dry-run)
+        /// Pack manifest whose transforms edit matching files as they are
+        /// copied (grub.cfg timeout, kernel args, branding)
         #[arg(long)]
-        execute: bool,
+        transform_pack: Option<String>,
+    },
 
-        /// Verify by hashing device after write
+    /// Erase this Mac's internal disk and reinstall macOS with
+    /// startosinstall --eraseinstall (destructive, system disk)
+    MacosEraseInstall {
+        /// Install macOS app containing startosinstall
         #[arg(long)]
-        verify: bool,
+        source_app: String,
 
-        /// Chunk size (default 8MB)
-        #[arg(long, default_value_t = 8 * 1024 * 1024)]
-        chunk_size: u64,
-    },
+        /// Name for the reinstalled volume
+        #[arg(long)]
+        new_volume_name: Option<String>,
 
-    /// Write a raw macOS image to a device (destructive)
-    MacosWriteImage {
-        /// Source image file (iso/img)
+        /// Package to install after macOS (repeatable)
+        #[arg(long = "package")]
+        packages: Vec<String>,
+
+        /// Administrator authorizing the erase (required on Apple Silicon)
         #[arg(long)]
-        source: String,
+        admin_user: Option<String>,
 
-        /// Target block device (e.g. /dev/disk2)
+        /// Secret holding the administrator's password (see Secret Parameters)
         #[arg(long)]
-        device: String,
+        admin_password_secret: Option<String>,
 
         /// Base path for reports (default: current directory)
         #[arg(long, default_value = ".")]
@@ -351,33 +713,40 @@ enum Commands {
         #[arg(long)]
         force: bool,
 
-        /// Confirmation token (PHX-...)
+        /// Confirmation token (PHX-SYS-...)
         #[arg(long)]
         token: Option<String>,
 
-        /// Execute write (omit for dry-run)
+        /// Allow erasing the running system's disk
         #[arg(long)]
-        execute: bool,
+        allow_system_target: bool,
 
-        /// Verify by hashing device after write
+        /// Run startosinstall (omit for dry-run)
         #[arg(long)]
-        verify: bool,
-
-        /// Chunk size (default 8MB)
-        #[arg(long, default_value_t = 8 * 1024 * 1024)]
-        chunk_size: u64,
+        execute: bool,
     },
 
-    /// Prepare Linux boot files on target mount
-    LinuxBootPrep {
-        /// Path to source files
-        #[arg(long)]
-        source: String,
+    /// List the firmware's UEFI boot entries
+    BootEntries,
 
-        /// Target mount path
+    /// Add a UEFI boot entry for a staged system (bless on macOS)
+    BootEntry {
+        /// Mounted ESP of the staged system; on macOS, the volume to bless
         #[arg(long)]
         target_mount: String,
 
+        /// Loader on the ESP, e.g. \EFI\ubuntu\shimx64.efi
+        #[arg(long)]
+        loader: Option<String>,
+
+        /// Entry name shown by the firmware
+        #[arg(long, default_value = "Phoenix")]
+        description: String,
+
+        /// first, last or next (boot once)
+        #[arg(long, default_value = "first")]
+        position: String,
+
         /// Base path for reports (default: current directory)
         #[arg(long, default_value = ".")]
         report_base: String,
@@ -390,24 +759,35 @@ enum Commands {
         #[arg(long)]
         token: Option<String>,
 
-        /// Execute copy (omit for dry-run)
+        /// Write the entry (omit for dry-run)
         #[arg(long)]
         execute: bool,
+    },
 
-        /// Emit SHA-256 copy manifest into report
-        #[arg(long)]
-        hash_manifest: bool,
+    /// List Macs in DFU mode
+    DfuList {
+        /// cfgutil or idevicerestore (default: cfgutil)
+        #[arg(long, default_value = "cfgutil")]
+        tool: String,
     },
 
-    /// Prepare macOS boot files on target mount
-    MacosBootPrep {
-        /// Path to source files
+    /// Revive or restore a Mac in DFU mode from an IPSW
+    IpswRestore {
+        /// IPSW file (omit to let the tool fetch the latest)
         #[arg(long)]
-        source: String,
+        ipsw: Option<String>,
 
-        /// Target mount path
+        /// ECID of the device (required with several in DFU mode)
         #[arg(long)]
-        target_mount: String,
+        ecid: Option<String>,
+
+        /// revive (keeps data) or restore (erases)
+        #[arg(long, default_value = "revive")]
+        mode: String,
+
+        /// cfgutil or idevicerestore (default: whichever is installed)
+        #[arg(long)]
+        tool: Option<String>,
 
         /// Base path for reports (default: current directory)
         #[arg(long, default_value = ".")]
@@ -421,36 +801,29 @@ enum Commands {
         #[arg(long)]
         token: Option<String>,
 
-        /// Execute copy (omit for dry-run)
+        /// Run the restore (omit for dry-run)
         #[arg(long)]
         execute: bool,
-
-        /// Emit SHA-256 copy manifest into report
-        #[arg(long)]
-        hash_manifest: bool,
     },
 
-    /// Create a macOS installer USB (uses Apple tools)
-    MacosCreateInstaller {
-        /// Source path (Install macOS.app or .dmg)
+    /// Write a raw Linux image to a device (destructive)
+    LinuxWriteImage {
+        /// Source image (img, iso, dmg, sparsebundle, vhd or qcow2) or http(s) URL to stream
         #[arg(long)]
         source: String,
 
-        /// Target device (e.g. /dev/disk2)
-        #[arg(long)]
-        target_device: String,
-
-        /// Volume name for createinstallmedia
-        #[arg(long, default_value = "PHOENIX-MACOS")]
-        volume_name: String,
+        /// Another URL serving the same image as `--source`, used when it
+        /// is faster or the source fails (repeatable)
+        #[arg(long = "mirror")]
+        mirrors: Vec<String>,
 
-        /// macOS version or codename (e.g. 10.6, snow leopard, tahoe)
+        /// Expected SHA-256 of the image (required for http:// URLs)
         #[arg(long)]
-        macos_version: Option<String>,
+        sha256: Option<String>,
 
-        /// Filesystem override (APFS or JHFS+)
+        /// Target block device (e.g. /dev/sdb)
         #[arg(long)]
-        filesystem: Option<String>,
+        device: String,
 
         /// Base path for reports (default: current directory)
         #[arg(long, default_value = ".")]
@@ -464,24 +837,60 @@ enum Commands {
         #[arg(long)]
         token: Option<String>,
 
-        /// Execute creation (omit for dry-run)
+        /// Proceed even though the target disk is outside the safety size
+        /// limits; recorded in the report
+        #[arg(long)]
+        acknowledge_target_size: bool,
+
+        /// Proceed even though the device is past the wear limits (flash
+        /// count or throughput drop); recorded in the report and audit log
+        #[arg(long)]
+        acknowledge_device_wear: bool,
+
+        /// Wipe the target even if its label or contents look like personal
+        /// data (DCIM/, Documents/, many user files)
+        #[arg(long)]
+        confirm_overwrite: bool,
+
+        /// Execute write (omit for dry-run)
         #[arg(long)]
         execute: bool,
+
+        /// Verify by hashing device after write
+        #[arg(long)]
+        verify: bool,
+
+        /// Chunk size in bytes (default: probed from the device)
+        #[arg(long)]
+        chunk_size: Option<u64>,
+
+        /// Overlap image reads with device writes (double-buffered)
+        #[arg(long)]
+        fast_io: bool,
+
+        /// Sync the target every SIZE bytes written (e.g. 256M)
+        #[arg(long, value_name = "SIZE")]
+        flush_every: Option<String>,
     },
 
-    /// Patch macOS installer for unsupported Macs (Legacy Patcher)
-    MacosLegacyPatch {
-        /// Source path (Install macOS.app or mounted DMG)
+    /// Write a raw macOS image to a device (destructive)
+    MacosWriteImage {
+        /// Source image (img, iso, dmg, sparsebundle, vhd or qcow2) or http(s) URL to stream
         #[arg(long)]
         source: String,
 
-        /// Override model identifier
+        /// Another URL serving the same image as `--source`, used when it
+        /// is faster or the source fails (repeatable)
+        #[arg(long = "mirror")]
+        mirrors: Vec<String>,
+
+        /// Expected SHA-256 of the image (required for http:// URLs)
         #[arg(long)]
-        model: Option<String>,
+        sha256: Option<String>,
 
-        /// Override board-id
+        /// Target block device (e.g. /dev/disk2)
         #[arg(long)]
-        board_id: Option<String>,
+        device: String,
 
         /// Base path for reports (default: current directory)
         #[arg(long, default_value = ".")]
@@ -495,14 +904,45 @@ enum Commands {
         #[arg(long)]
         token: Option<String>,
 
-        /// Execute patch (omit for dry-run)
+        /// Proceed even though the target disk is outside the safety size
+        /// limits; recorded in the report
         #[arg(long)]
-        execute: bool,
+        acknowledge_target_size: bool,
+
+        /// Proceed even though the device is past the wear limits (flash
+        /// count or throughput drop); recorded in the report and audit log
+        #[arg(long)]
+        acknowledge_device_wear: bool,
+
+        /// Wipe the target even if its label or contents look like personal
+        /// data (DCIM/, Documents/, many user files)
+        #[arg(long)]
+        confirm_overwrite: bool,
+
+        /// Execute write (omit for dry-run)
+        #[arg(long)]
+        execute: bool,
+
+        /// Verify by hashing device after write
+        #[arg(long)]
+        verify: bool,
+
+        /// Chunk size in bytes (default: probed from the device)
+        #[arg(long)]
+        chunk_size: Option<u64>,
+
+        /// Overlap image reads with device writes (double-buffered)
+        #[arg(long)]
+        fast_io: bool,
+
+        /// Sync the target every SIZE bytes written (e.g. 256M)
+        #[arg(long, value_name = "SIZE")]
+        flush_every: Option<String>,
     },
 
-    /// Stage a custom bootloader package onto a target mount
-    StageBootloader {
-        /// Bootloader package root (must include EFI/BOOT/*.EFI)
+    /// Prepare Linux boot files on target mount
+    LinuxBootPrep {
+        /// Path to source files
         #[arg(long)]
         source: String,
 
@@ -510,10 +950,6 @@ enum Commands {
         #[arg(long)]
         target_mount: String,
 
-        /// Optional target subdirectory (default: root)
-        #[arg(long)]
-        target_subdir: Option<String>,
-
         /// Base path for reports (default: current directory)
         #[arg(long, default_value = ".")]
         report_base: String,
@@ -526,18 +962,22 @@ enum Commands {
         #[arg(long)]
         token: Option<String>,
 
-        /// Execute staging (omit for dry-run)
+        /// Execute copy (omit for dry-run)
         #[arg(long)]
         execute: bool,
 
         /// Emit SHA-256 copy manifest into report
         #[arg(long)]
         hash_manifest: bool,
+
+        /// Read copied files back and check them against the manifest hashes
+        #[arg(long, requires = "hash_manifest")]
+        hash_destination: bool,
     },
 
-    /// Stage macOS kext bundles into EFI/OC/Kexts
-    MacosKextStage {
-        /// Source directory containing .kext bundles
+    /// Prepare macOS boot files on target mount
+    MacosBootPrep {
+        /// Path to source files
         #[arg(long)]
         source: String,
 
@@ -545,10 +985,6 @@ enum Commands {
         #[arg(long)]
         target_mount: String,
 
-        /// Optional target subdirectory (default: EFI/OC/Kexts)
-        #[arg(long)]
-        target_subdir: Option<String>,
-
         /// Base path for reports (default: current directory)
         #[arg(long, default_value = ".")]
         report_base: String,
@@ -561,774 +997,4613 @@ enum Commands {
         #[arg(long)]
         token: Option<String>,
 
-        /// Execute staging (omit for dry-run)
+        /// Execute copy (omit for dry-run)
         #[arg(long)]
         execute: bool,
 
         /// Emit SHA-256 copy manifest into report
         #[arg(long)]
         hash_manifest: bool,
+
+        /// Read copied files back and check them against the manifest hashes
+        #[arg(long, requires = "hash_manifest")]
+        hash_destination: bool,
     },
 
-    /// Run a workflow definition JSON file
-    WorkflowRun {
-        /// Path to workflow JSON file
+    /// Create a macOS installer USB (uses Apple tools)
+    MacosCreateInstaller {
+        /// Source path (Install macOS.app or .dmg)
         #[arg(long)]
-        file: String,
+        source: String,
 
-        /// Default report base for steps without report_base
+        /// Target device (e.g. /dev/disk2)
+        #[arg(long)]
+        target_device: String,
+
+        /// Volume name for createinstallmedia
+        #[arg(long, default_value = "PHOENIX-MACOS")]
+        volume_name: String,
+
+        /// macOS version or codename (e.g. 10.6, snow leopard, tahoe)
+        #[arg(long)]
+        macos_version: Option<String>,
+
+        /// Filesystem override (APFS or JHFS+)
+        #[arg(long)]
+        filesystem: Option<String>,
+
+        /// Mac model the installer must boot, e.g. MacBookPro18,3
+        #[arg(long)]
+        target_model: Option<String>,
+
+        /// Base path for reports (default: current directory)
         #[arg(long, default_value = ".")]
         report_base: String,
-    },
 
-    /// Validate a workflow definition file
-    WorkflowValidate {
-        /// Path to workflow JSON/YAML file
+        /// Force destructive operations
         #[arg(long)]
-        file: String,
+        force: bool,
+
+        /// Confirmation token (PHX-...)
+        #[arg(long)]
+        token: Option<String>,
+
+        /// Proceed even though the target disk is outside the safety size
+        /// limits; recorded in the report
+        #[arg(long)]
+        acknowledge_target_size: bool,
+
+        /// Proceed even though the device is past the wear limits (flash
+        /// count or throughput drop); recorded in the report and audit log
+        #[arg(long)]
+        acknowledge_device_wear: bool,
+
+        /// Wipe the target even if its label or contents look like personal
+        /// data (DCIM/, Documents/, many user files)
+        #[arg(long)]
+        confirm_overwrite: bool,
+
+        /// Execute creation (omit for dry-run)
+        #[arg(long)]
+        execute: bool,
     },
 
-    /// Hash a disk and emit a report bundle
-    DiskHashReport {
-        /// Disk id like: PhysicalDrive0
+    /// Patch macOS installer for unsupported Macs (Legacy Patcher)
+    MacosLegacyPatch {
+        /// Source path (Install macOS.app or mounted DMG)
         #[arg(long)]
-        disk: String,
+        source: String,
 
-        /// Chunk size in bytes (default: 8MB)
-        #[arg(long, default_value_t = 8 * 1024 * 1024)]
-        chunk_size: u64,
+        /// Override model identifier
+        #[arg(long)]
+        model: Option<String>,
 
-        /// Max chunks to hash
+        /// Override board-id
         #[arg(long)]
-        max_chunks: Option<u64>,
+        board_id: Option<String>,
 
         /// Base path for reports (default: current directory)
         #[arg(long, default_value = ".")]
         report_base: String,
+
+        /// Force destructive operations
+        #[arg(long)]
+        force: bool,
+
+        /// Confirmation token (PHX-...)
+        #[arg(long)]
+        token: Option<String>,
+
+        /// Execute patch (omit for dry-run)
+        #[arg(long)]
+        execute: bool,
     },
 
-    /// Validate a Phoenix pack manifest and workflows
-    PackValidate {
-        /// Path to pack manifest JSON
+    /// Stage a custom bootloader package onto a target mount
+    StageBootloader {
+        /// Bootloader package root (must include EFI/BOOT/*.EFI)
         #[arg(long)]
-        manifest: String,
+        source: String,
 
-        /// Optional signing key hex (verifies signature if present)
+        /// Target mount path
         #[arg(long)]
-        key: Option<String>,
+        target_mount: String,
+
+        /// Optional target subdirectory (default: root)
+        #[arg(long)]
+        target_subdir: Option<String>,
+
+        /// Base path for reports (default: current directory)
+        #[arg(long, default_value = ".")]
+        report_base: String,
+
+        /// Force destructive operations
+        #[arg(long)]
+        force: bool,
+
+        /// Confirmation token (PHX-...)
+        #[arg(long)]
+        token: Option<String>,
+
+        /// Execute staging (omit for dry-run)
+        #[arg(long)]
+        execute: bool,
+
+        /// Emit SHA-256 copy manifest into report
+        #[arg(long)]
+        hash_manifest: bool,
+
+        /// Read copied files back and check them against the manifest hashes
+        #[arg(long, requires = "hash_manifest")]
+        hash_destination: bool,
+
+        /// Signing certificate (PEM or DER) to stage for MokManager
+        #[arg(long)]
+        mok_certificate: Option<String>,
+
+        /// Write Authenticode hashes of the package's .efi binaries for MokManager
+        #[arg(long)]
+        mok_hashes: bool,
+
+        /// MokManager binary (mmx64.efi etc.) to stage next to shim
+        #[arg(long)]
+        mok_manager: Option<String>,
+
+        /// Pack manifest whose grub menu and theme are rendered onto the media
+        #[arg(long)]
+        grub_pack: Option<String>,
+
+        /// GRUB directory under the staging root (default: boot/grub)
+        #[arg(long)]
+        grub_dir: Option<String>,
     },
 
-    /// Run all workflows listed in a pack manifest
-    PackRun {
-        /// Path to pack manifest JSON
+    /// Copy extra files (tools, scripts, docs) onto a target mount
+    StageFiles {
+        /// SOURCE=DESTINATION; SOURCE may be a file, directory or glob
+        /// (`*`, `?`, `**`), DESTINATION is relative to the mount
+        #[arg(long = "file", required = true)]
+        files: Vec<String>,
+
+        /// Target mount path
         #[arg(long)]
-        manifest: String,
+        target_mount: String,
 
-        /// Default report base for workflow runs
+        /// Existing destination files: fail, skip or replace
+        #[arg(long, default_value = "fail")]
+        overwrite: String,
+
+        /// Base path for reports (default: current directory)
         #[arg(long, default_value = ".")]
         report_base: String,
 
-        /// Require pack signature verification
+        /// Force destructive operations
         #[arg(long)]
-        require_signed: bool,
+        force: bool,
 
-        /// Signing key hex (overrides env PHOENIX_PACK_KEY)
+        /// Confirmation token (PHX-...)
         #[arg(long)]
-        key: Option<String>,
+        token: Option<String>,
+
+        /// Execute staging (omit for dry-run)
+        #[arg(long)]
+        execute: bool,
     },
 
-    /// Sign a pack manifest (writes .sig)
-    PackSign {
-        /// Path to pack manifest JSON/YAML
+    /// Validate and stage a Windows Autopilot profile and/or .ppkg
+    /// provisioning packages onto Windows media
+    StageProvisioning {
+        /// AutopilotConfigurationFile.json to stage
         #[arg(long)]
-        manifest: String,
+        autopilot: Option<String>,
 
-        /// Signing key hex
+        /// Provisioning package (.ppkg) to stage (repeatable)
+        #[arg(long = "package")]
+        packages: Vec<String>,
+
+        /// Media mount, or the directory Windows was applied to
         #[arg(long)]
-        key: String,
+        target_mount: String,
+
+        /// installer (Windows Setup media) or applied (applied image)
+        #[arg(long, default_value = "installer")]
+        layout: String,
+
+        /// Existing destination files: fail, skip or replace
+        #[arg(long, default_value = "fail")]
+        overwrite: String,
+
+        /// Base path for reports (default: current directory)
+        #[arg(long, default_value = ".")]
+        report_base: String,
+
+        /// Force destructive operations
+        #[arg(long)]
+        force: bool,
+
+        /// Confirmation token (PHX-...)
+        #[arg(long)]
+        token: Option<String>,
+
+        /// Execute staging (omit for dry-run)
+        #[arg(long)]
+        execute: bool,
     },
 
-    /// Verify a pack manifest signature
-    PackVerify {
-        /// Path to pack manifest JSON/YAML
+    /// Show the phoenix_media.toml label of finished media
+    MediaInfo {
+        /// Mount path of the media
         #[arg(long)]
-        manifest: String,
+        mount: String,
 
-        /// Signing key hex
+        /// Print the label as JSON
         #[arg(long)]
-        key: String,
+        json: bool,
     },
 
-    /// Export a pack as a zip archive
-    PackExport {
-        /// Path to pack manifest JSON/YAML
+    /// Write a pack's phoenix_media.toml label onto finished media
+    MediaStamp {
+        /// Mount path of the media
+        #[arg(long)]
+        mount: String,
+
+        /// Pack manifest the media was built from
         #[arg(long)]
         manifest: String,
+    },
 
-        /// Output zip path
+    /// Re-hash finished media and diff it against the run that built it
+    MediaAudit {
+        /// Mount path of the media
         #[arg(long)]
-        out: String,
+        mount: String,
 
-        /// Optional signing key hex (writes .sig before export)
+        /// Path to the reports/<run_id> directory of the installer run
+        #[arg(long)]
+        against: String,
+
+        /// Files written after the copy to leave out of `added` (repeatable glob)
+        #[arg(long)]
+        exclude: Vec<String>,
+
+        /// Signing key hex for a signed report (default: $PHOENIX_SIGNING_KEY)
         #[arg(long)]
         key: Option<String>,
     },
-}
 
-fn main() -> Result<()> {
-    let cli = Cli::parse();
+    /// Stage macOS kext bundles into EFI/OC/Kexts
+    MacosKextStage {
+        /// Source directory containing .kext bundles
+        #[arg(long)]
+        source: String,
 
-    match cli.cmd {
-        Commands::DeviceGraph { pretty } => {
-            let graph = build_device_graph()?;
-            if pretty {
-                println!("{}", serde_json::to_string_pretty(&graph)?);
-            } else {
-                println!("{}", serde_json::to_string(&graph)?);
-            }
-            Ok(())
-        }
-        Commands::Report { base } => {
-            let graph = build_device_graph()?;
-            let key = std::env::var("PHOENIX_SIGNING_KEY").ok();
-            let paths = phoenix_report::create_report_bundle_with_meta_and_signing(
+        /// Target mount path
+        #[arg(long)]
+        target_mount: String,
+
+        /// Optional target subdirectory (default: EFI/OC/Kexts)
+        #[arg(long)]
+        target_subdir: Option<String>,
+
+        /// Base path for reports (default: current directory)
+        #[arg(long, default_value = ".")]
+        report_base: String,
+
+        /// Force destructive operations
+        #[arg(long)]
+        force: bool,
+
+        /// Confirmation token (PHX-...)
+        #[arg(long)]
+        token: Option<String>,
+
+        /// Execute staging (omit for dry-run)
+        #[arg(long)]
+        execute: bool,
+
+        /// Emit SHA-256 copy manifest into report
+        #[arg(long)]
+        hash_manifest: bool,
+
+        /// Read copied files back and check them against the manifest hashes
+        #[arg(long, requires = "hash_manifest")]
+        hash_destination: bool,
+    },
+
+    /// Run a workflow definition JSON file
+    WorkflowRun {
+        /// Path to workflow JSON file
+        #[arg(long)]
+        file: String,
+
+        /// Default report base for steps without report_base
+        #[arg(long, default_value = ".")]
+        report_base: String,
+
+        /// Idempotency key; overrides the definition's idempotency_key
+        #[arg(long)]
+        idempotency_key: Option<String>,
+    },
+
+    /// Validate a workflow definition file
+    WorkflowValidate {
+        /// Path to workflow JSON/YAML file
+        #[arg(long)]
+        file: String,
+
+        /// Skip the checks of this host, for workflows that run elsewhere
+        #[arg(long)]
+        plan_only: bool,
+    },
+
+    /// Plan a workflow on any host and write the plan as a dry-run report
+    WorkflowPlan {
+        /// Path to workflow JSON/YAML file
+        #[arg(long)]
+        file: String,
+
+        /// Report base for the plan report
+        #[arg(long, default_value = ".")]
+        report_base: String,
+
+        /// Print the plan as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Estimate how long a workflow takes per stick and how many fit in a shift
+    WorkflowEstimate {
+        /// Path to workflow JSON/YAML file
+        #[arg(long)]
+        file: String,
+
+        /// Source size for a step, as step_id=SIZE (repeatable; e.g. install=5G)
+        #[arg(long = "source-size")]
+        source_sizes: Vec<String>,
+
+        /// Target stick capacity, for steps that scan the whole stick (e.g. 32G)
+        #[arg(long)]
+        target_size: Option<String>,
+
+        /// Use this registered stick's last write rate
+        #[arg(long)]
+        device_key: Option<String>,
+
+        /// Write rate in bytes per second (e.g. 25M); overrides the registry
+        #[arg(long)]
+        write_rate: Option<String>,
+
+        /// Read-back rate in bytes per second (e.g. 80M)
+        #[arg(long)]
+        read_rate: Option<String>,
+
+        /// Sticks written at once
+        #[arg(long, default_value_t = 1)]
+        parallel: u32,
+
+        /// Shift length in hours, to report how many sticks fit
+        #[arg(long)]
+        shift_hours: Option<f64>,
+
+        /// Print the estimate as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Check everything a workflow needs from this host, before running it
+    WorkflowPreflight {
+        /// Path to workflow JSON/YAML file
+        #[arg(long)]
+        file: String,
+
+        /// Print the checklist as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// List the workflows of installed packs and whether they run here
+    WorkflowList {
+        /// Pack directory to scan (repeatable; overrides PHOENIX_PACK_DIRS)
+        #[arg(long)]
+        pack_dir: Vec<String>,
+
+        /// Print the catalog as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Duplication station: run a workflow on each removable disk plugged in
+    Kiosk {
+        /// Workflow JSON/YAML; string params may use {target_disk},
+        /// {target_device}, {target_mount}, {target_serial}, {target_port}
+        #[arg(long)]
+        file: String,
+
+        /// Report base for the runs and the session report
+        #[arg(long, default_value = ".")]
+        report_base: String,
+
+        /// operator (ask before each disk) or auto
+        #[arg(long, default_value = "operator")]
+        confirm: String,
+
+        /// Skip disks smaller than this many bytes
+        #[arg(long)]
+        min_size: Option<u64>,
+
+        /// Skip disks larger than this many bytes
+        #[arg(long)]
+        max_size: Option<u64>,
+
+        /// Only serve these USB port labels or paths (repeatable)
+        #[arg(long)]
+        port: Vec<String>,
+
+        /// Device poll interval in milliseconds
+        #[arg(long, default_value_t = 1000)]
+        poll_ms: u64,
+
+        /// Stop after this many runs (default: run until killed)
+        #[arg(long)]
+        max_runs: Option<usize>,
+    },
+
+    /// Poll an orchestration server, run the jobs it hands out on attached disks and upload their reports
+    Agent {
+        /// Server base URL, e.g. https://fleet.example
+        #[arg(long)]
+        server: String,
+
+        /// How the server knows this bench
+        #[arg(long)]
+        agent_id: String,
+
+        /// Secret holding the bearer token (looked up like any other secret)
+        #[arg(long)]
+        token_secret: Option<String>,
+
+        /// Report base for the runs and the session report
+        #[arg(long, default_value = ".")]
+        report_base: String,
+
+        /// Only offer disks on these USB port labels or paths (repeatable)
+        #[arg(long)]
+        port: Vec<String>,
+
+        /// Server poll interval in milliseconds
+        #[arg(long, default_value_t = 5000)]
+        poll_ms: u64,
+
+        /// Stop after this many jobs (default: run until killed)
+        #[arg(long)]
+        max_jobs: Option<usize>,
+
+        /// Client certificate from the fleet CA, for mutual TLS
+        #[arg(long, requires = "client_key")]
+        client_cert: Option<String>,
+
+        /// Private key of --client-cert
+        #[arg(long, requires = "client_cert")]
+        client_key: Option<String>,
+
+        /// Only trust server certificates from this CA
+        #[arg(long, requires = "client_cert")]
+        server_ca: Option<String>,
+
+        /// Hook program or plugin server-sent jobs may run (repeatable)
+        #[arg(long)]
+        allow_hook: Vec<String>,
+
+        /// Secret server-sent jobs may reference (repeatable)
+        #[arg(long)]
+        allow_secret: Vec<String>,
+    },
+
+    /// Create the fleet CA that issues agent and server certificates
+    FleetCaInit {
+        /// Directory for ca.pem, ca.key and the issued certificates
+        #[arg(long)]
+        dir: String,
+
+        /// Fleet name, put in the CA's common name
+        #[arg(long)]
+        name: String,
+
+        /// Days the CA is valid
+        #[arg(long, default_value_t = 3650)]
+        days: u32,
+    },
+
+    /// Issue an agent or server certificate from the fleet CA
+    FleetCaIssue {
+        /// Directory holding the fleet CA
+        #[arg(long)]
+        dir: String,
+
+        /// Agent id for a client certificate
+        #[arg(long, conflicts_with = "server_name", required_unless_present = "server_name")]
+        agent_id: Option<String>,
+
+        /// Host name or IP address for a server certificate
+        #[arg(long)]
+        server_name: Option<String>,
+
+        /// Days the certificate is valid
+        #[arg(long, default_value_t = 365)]
+        days: u32,
+    },
+
+    /// Run a workflow on every attached removable disk at once
+    DuplicateToAll {
+        /// Workflow JSON/YAML, with the same placeholders as kiosk
+        #[arg(long)]
+        file: String,
+
+        /// Report base for the runs and the consolidated report
+        #[arg(long, default_value = ".")]
+        report_base: String,
+
+        /// Leave out disks smaller than this many bytes
+        #[arg(long, default_value_t = 0)]
+        min_size: u64,
+
+        /// Serial that is never targeted (repeatable)
+        #[arg(long)]
+        never_touch: Vec<String>,
+
+        /// Only use these USB port labels or paths (repeatable)
+        #[arg(long)]
+        port: Vec<String>,
+
+        /// Targets written at the same time (default: all)
+        #[arg(long, default_value_t = 0)]
+        parallel: usize,
+
+        /// Skip the confirmation prompt
+        #[arg(long)]
+        yes: bool,
+    },
+
+    /// Copy one removable disk onto another in a single pass, optionally
+    /// keeping a zstd archive of it, then compare the two (destructive)
+    CloneDevice {
+        /// Source disk id like: sdb
+        #[arg(long)]
+        source: String,
+
+        /// Target disk id like: sdc
+        #[arg(long)]
+        target: String,
+
+        /// Also write the source, zstd-compressed, to this file
+        #[arg(long)]
+        archive: Option<String>,
+
+        /// Keep the archive in the report bundle as disk.img.zst, for
+        /// `restore --report`
+        #[arg(long)]
+        archive_in_report: bool,
+
+        /// Base path for reports (default: current directory)
+        #[arg(long, default_value = ".")]
+        report_base: String,
+
+        /// Force destructive operations
+        #[arg(long)]
+        force: bool,
+
+        /// Confirmation token (PHX-...)
+        #[arg(long)]
+        token: Option<String>,
+
+        /// Proceed even though the target disk is outside the safety size
+        /// limits; recorded in the report
+        #[arg(long)]
+        acknowledge_target_size: bool,
+
+        /// Proceed even though the target is past the wear limits (flash
+        /// count or throughput drop); recorded in the report and audit log
+        #[arg(long)]
+        acknowledge_device_wear: bool,
+
+        /// Wipe the target even if its label or contents look like personal
+        /// data (DCIM/, Documents/, many user files)
+        #[arg(long)]
+        confirm_overwrite: bool,
+
+        /// Execute (omit for dry-run)
+        #[arg(long)]
+        execute: bool,
+
+        /// Chunk size in bytes (default: probed from the target)
+        #[arg(long)]
+        chunk_size: Option<u64>,
+
+        /// Sync the target every SIZE bytes written (e.g. 256M)
+        #[arg(long, value_name = "SIZE")]
+        flush_every: Option<String>,
+    },
+
+    /// Write the disk image kept in a report bundle (a clone's
+    /// disk.img.zst) back onto a disk, after verifying the bundle's
+    /// signature and hashes (destructive)
+    Restore {
+        /// Report bundle directory holding the image
+        #[arg(long)]
+        report: String,
+
+        /// Target disk id like: sdc
+        #[arg(long)]
+        target: String,
+
+        /// Restore from a bundle without manifest.sig; hashes are still
+        /// checked
+        #[arg(long)]
+        allow_unsigned: bool,
+
+        /// Base path for reports (default: current directory)
+        #[arg(long, default_value = ".")]
+        report_base: String,
+
+        /// Force destructive operations
+        #[arg(long)]
+        force: bool,
+
+        /// Confirmation token (PHX-...)
+        #[arg(long)]
+        token: Option<String>,
+
+        /// Proceed even though the target disk is outside the safety size
+        /// limits; recorded in the report
+        #[arg(long)]
+        acknowledge_target_size: bool,
+
+        /// Proceed even though the target is past the wear limits (flash
+        /// count or throughput drop); recorded in the report and audit log
+        #[arg(long)]
+        acknowledge_device_wear: bool,
+
+        /// Wipe the target even if its label or contents look like personal
+        /// data (DCIM/, Documents/, many user files)
+        #[arg(long)]
+        confirm_overwrite: bool,
+
+        /// Execute (omit for dry-run)
+        #[arg(long)]
+        execute: bool,
+
+        /// Chunk size in bytes (default: probed from the target)
+        #[arg(long)]
+        chunk_size: Option<u64>,
+
+        /// Sync the target every SIZE bytes written (e.g. 256M)
+        #[arg(long, value_name = "SIZE")]
+        flush_every: Option<String>,
+    },
+
+    /// Hash a disk and emit a report bundle
+    DiskHashReport {
+        /// Disk id like: PhysicalDrive0
+        #[arg(long)]
+        disk: String,
+
+        /// Chunk size in bytes (default: probed from the device)
+        #[arg(long)]
+        chunk_size: Option<u64>,
+
+        /// Max chunks to hash
+        #[arg(long)]
+        max_chunks: Option<u64>,
+
+        /// Base path for reports (default: current directory)
+        #[arg(long, default_value = ".")]
+        report_base: String,
+
+        /// Hash only this partition, e.g. sdb1
+        #[arg(long)]
+        partition: Option<String>,
+
+        /// Compare against this stored baseline; fails if the disk drifted
+        #[arg(long)]
+        compare_baseline: Option<String>,
+
+        /// Store the chunk map as this named baseline
+        #[arg(long)]
+        save_baseline: Option<String>,
+    },
+
+    /// Scan a disk for bad sectors; the write mode is destructive
+    BadBlockScan {
+        /// Disk id like: sdb
+        #[arg(long)]
+        disk: String,
+
+        /// read, or write (fills the disk with a pattern and reads it back)
+        #[arg(long, default_value = "read")]
+        mode: String,
+
+        /// Chunk size in bytes (default: probed from the device)
+        #[arg(long)]
+        chunk_size: Option<u64>,
+
+        /// Granularity of the bad ranges (default: 512)
+        #[arg(long, value_name = "SIZE")]
+        sector_size: Option<String>,
+
+        /// Stop once more than SIZE bytes are bad (e.g. 1M)
+        #[arg(long, value_name = "SIZE")]
+        max_bad: Option<String>,
+
+        /// Base path for reports (default: current directory)
+        #[arg(long, default_value = ".")]
+        report_base: String,
+
+        /// Force destructive operations
+        #[arg(long)]
+        force: bool,
+
+        /// Confirmation token (PHX-...)
+        #[arg(long)]
+        token: Option<String>,
+
+        /// Proceed even though the target disk is outside the safety size
+        /// limits; recorded in the report
+        #[arg(long)]
+        acknowledge_target_size: bool,
+
+        /// Proceed even though the device is past the wear limits (flash
+        /// count or throughput drop); recorded in the report and audit log
+        #[arg(long)]
+        acknowledge_device_wear: bool,
+
+        /// Wipe the target even if its label or contents look like personal
+        /// data (DCIM/, Documents/, many user files)
+        #[arg(long)]
+        confirm_overwrite: bool,
+
+        /// Execute a write scan (omit for dry-run); read scans always run
+        #[arg(long)]
+        execute: bool,
+    },
+
+    /// Partition a large stick into a Windows installer, a Linux rescue
+    /// system and a shared exFAT data partition behind one GRUB menu
+    ComboStick {
+        /// Disk id like: sdb
+        #[arg(long)]
+        disk: String,
+
+        /// Extracted Windows installer files
+        #[arg(long)]
+        windows_source: String,
+
+        /// Linux rescue files with their own EFI/BOOT/BOOTX64.EFI
+        #[arg(long)]
+        rescue_source: String,
+
+        /// GRUB EFI package for the boot partition
+        #[arg(long)]
+        bootloader: String,
+
+        /// Size of the Windows partition (default: source plus headroom)
+        #[arg(long, value_name = "SIZE")]
+        windows_size: Option<String>,
+
+        /// Size of the rescue partition (default: source plus headroom)
+        #[arg(long, value_name = "SIZE")]
+        rescue_size: Option<String>,
+
+        /// Signed pack manifest whose payloads go on a TOOLS partition
+        /// (key from env PHOENIX_PACK_KEY)
+        #[arg(long)]
+        payload_pack: Option<String>,
+
+        /// Payload of the pack to stage; repeatable (default: all)
+        #[arg(long = "payload")]
+        payloads: Vec<String>,
+
+        /// Size of the tools partition (default: payloads plus headroom)
+        #[arg(long, value_name = "SIZE")]
+        tools_size: Option<String>,
+
+        /// Label of the exFAT data partition (default: DATA)
+        #[arg(long)]
+        data_label: Option<String>,
+
+        /// Encrypt the data partition: luks2 or veracrypt
+        #[arg(long)]
+        data_encryption: Option<String>,
+
+        /// Secret holding the data partition passphrase (env
+        /// PHOENIX_SECRET_<NAME> or the secrets directory)
+        #[arg(long)]
+        data_key_secret: Option<String>,
+
+        /// Title shown above the boot menu
+        #[arg(long)]
+        menu_title: Option<String>,
+
+        /// Seconds before the first menu entry boots (default: 10)
+        #[arg(long)]
+        menu_timeout: Option<u32>,
+
+        /// Base path for reports (default: current directory)
+        #[arg(long, default_value = ".")]
+        report_base: String,
+
+        /// Force destructive operations
+        #[arg(long)]
+        force: bool,
+
+        /// Confirmation token (PHX-...)
+        #[arg(long)]
+        token: Option<String>,
+
+        /// Proceed even though the target disk is outside the safety size
+        /// limits; recorded in the report
+        #[arg(long)]
+        acknowledge_target_size: bool,
+
+        /// Proceed even though the device is past the wear limits (flash
+        /// count or throughput drop); recorded in the report and audit log
+        #[arg(long)]
+        acknowledge_device_wear: bool,
+
+        /// Wipe the target even if its label or contents look like personal
+        /// data (DCIM/, Documents/, many user files)
+        #[arg(long)]
+        confirm_overwrite: bool,
+
+        /// Execute (omit for dry-run)
+        #[arg(long)]
+        execute: bool,
+    },
+
+    /// Make an A/B slot stick: the same appliance image in two slots behind
+    /// a GRUB menu that boots the active one
+    AbStick {
+        /// Disk id like: sdb
+        #[arg(long)]
+        disk: String,
+
+        /// Appliance image files with their own EFI/BOOT/BOOTX64.EFI
+        #[arg(long)]
+        image_source: String,
+
+        /// GRUB EFI package for the boot partition
+        #[arg(long)]
+        bootloader: String,
+
+        /// Size of each slot (default: half of the stick)
+        #[arg(long, value_name = "SIZE")]
+        slot_size: Option<String>,
+
+        /// Image version, shown in the menu and kept in the slot table
+        #[arg(long)]
+        version: Option<String>,
+
+        /// Title shown above the boot menu
+        #[arg(long)]
+        menu_title: Option<String>,
+
+        /// Seconds before the active slot boots (default: 5)
+        #[arg(long)]
+        menu_timeout: Option<u32>,
+
+        /// Base path for reports (default: current directory)
+        #[arg(long, default_value = ".")]
+        report_base: String,
+
+        /// Force destructive operations
+        #[arg(long)]
+        force: bool,
+
+        /// Confirmation token (PHX-...)
+        #[arg(long)]
+        token: Option<String>,
+
+        /// Proceed even though the target disk is outside the safety size
+        /// limits; recorded in the report
+        #[arg(long)]
+        acknowledge_target_size: bool,
+
+        /// Proceed even though the device is past the wear limits (flash
+        /// count or throughput drop); recorded in the report and audit log
+        #[arg(long)]
+        acknowledge_device_wear: bool,
+
+        /// Wipe the target even if its label or contents look like personal
+        /// data (DCIM/, Documents/, many user files)
+        #[arg(long)]
+        confirm_overwrite: bool,
+
+        /// Execute (omit for dry-run)
+        #[arg(long)]
+        execute: bool,
+    },
+
+    /// Restage the inactive slot of an A/B stick and boot it by default,
+    /// or roll back to the other slot
+    AbUpdate {
+        /// Disk id like: sdb
+        #[arg(long)]
+        disk: String,
+
+        /// New appliance image files for the inactive slot
+        #[arg(long, conflicts_with = "rollback", required_unless_present = "rollback")]
+        image_source: Option<String>,
+
+        /// Version of the new image
+        #[arg(long)]
+        version: Option<String>,
+
+        /// Boot the other slot by default again, without restaging it
+        #[arg(long)]
+        rollback: bool,
+
+        /// Base path for reports (default: current directory)
+        #[arg(long, default_value = ".")]
+        report_base: String,
+
+        /// Force destructive operations
+        #[arg(long)]
+        force: bool,
+
+        /// Confirmation token (PHX-...)
+        #[arg(long)]
+        token: Option<String>,
+
+        /// Execute (omit for dry-run)
+        #[arg(long)]
+        execute: bool,
+    },
+
+    /// Shrink a staged FAT32 partition to its contents, or grow it to fill
+    /// the stick
+    ResizePartition {
+        /// Disk id like: sdb
+        #[arg(long)]
+        disk: String,
+
+        /// GPT partition number (default: the last partition)
+        #[arg(long)]
+        partition: Option<u32>,
+
+        /// shrink or expand
+        #[arg(long)]
+        mode: String,
+
+        /// Free space a shrink leaves (default: 64M)
+        #[arg(long, value_name = "SIZE")]
+        margin: Option<String>,
+
+        /// Base path for reports (default: current directory)
+        #[arg(long, default_value = ".")]
+        report_base: String,
+
+        /// Force destructive operations
+        #[arg(long)]
+        force: bool,
+
+        /// Confirmation token (PHX-...)
+        #[arg(long)]
+        token: Option<String>,
+
+        /// Execute (omit for dry-run)
+        #[arg(long)]
+        execute: bool,
+    },
+
+    /// Check an installer source (folder or ISO) without a target device
+    ValidateSource {
+        /// Source folder or ISO
+        #[arg(long)]
+        source: String,
+
+        /// windows, linux or macos (default: detected from the source)
+        #[arg(long)]
+        os: Option<String>,
+
+        /// Filesystem the media will use (fat32 enforces the 4 GiB file limit)
+        #[arg(long, default_value = "fat32")]
+        filesystem: String,
+
+        /// Base path for reports (default: current directory)
+        #[arg(long, default_value = ".")]
+        report_base: String,
+
+        /// Only copy source files matching this glob (repeatable)
+        #[arg(long)]
+        include: Vec<String>,
+
+        /// Skip source files matching this glob, e.g. `*.mui` or
+        /// `sources/sxs` (repeatable)
+        #[arg(long)]
+        exclude: Vec<String>,
+
+        /// Mac model a macOS installer must boot, e.g. MacBookPro18,3
+        #[arg(long)]
+        target_model: Option<String>,
+    },
+
+    /// Remove setup languages and install.wim editions not on a keep-list
+    SlimWindowsMedia {
+        /// Extracted Windows media folder (modified in place)
+        #[arg(long)]
+        source: String,
+
+        /// Keep-list JSON with `languages` and `editions`
+        #[arg(long)]
+        keep_list: Option<String>,
+
+        /// Language to keep (repeatable)
+        #[arg(long = "language")]
+        languages: Vec<String>,
+
+        /// Edition name or image index to keep (repeatable)
+        #[arg(long = "edition")]
+        editions: Vec<String>,
+
+        /// Base path for reports (default: current directory)
+        #[arg(long, default_value = ".")]
+        report_base: String,
+
+        /// Remove files (omit for dry-run)
+        #[arg(long)]
+        execute: bool,
+    },
+
+    /// Add setup languages from localized media to extracted Windows media
+    MergeWindowsLanguages {
+        /// Extracted Windows media folder (modified in place)
+        #[arg(long)]
+        source: String,
+
+        /// Localized media folder or ISO to take languages from (repeatable)
+        #[arg(long = "from", required = true)]
+        language_sources: Vec<String>,
+
+        /// Language to add (repeatable; default: all the sources have)
+        #[arg(long = "language")]
+        languages: Vec<String>,
+
+        /// Base path for reports (default: current directory)
+        #[arg(long, default_value = ".")]
+        report_base: String,
+
+        /// Copy files (omit for dry-run)
+        #[arg(long)]
+        execute: bool,
+    },
+
+    /// Download an image from a .torrent's web seeds or a .meta4's mirrors,
+    /// verifying every piece (needs the torrent/metalink features)
+    Fetch {
+        /// .torrent or .meta4 file or URL
+        #[arg(long)]
+        source: String,
+
+        /// Directory to write the image into
+        #[arg(long, default_value = ".")]
+        output_dir: String,
+    },
+
+    /// Compare two chunk hash maps (disk_hashes.json)
+    HashmapCompare {
+        /// Left hash map path
+        #[arg(long)]
+        left: String,
+
+        /// Right hash map path
+        #[arg(long)]
+        right: String,
+    },
+
+    /// Verify the protective MBR and GPT header CRCs of a device or image
+    PartitionVerify {
+        /// Device or image path
+        #[arg(long)]
+        device: String,
+
+        /// Device size in bytes (defaults to the end of the file)
+        #[arg(long)]
+        size_bytes: Option<u64>,
+
+        /// Logical sector size in bytes
+        #[arg(long, default_value_t = 512)]
+        sector_size: u64,
+    },
+
+    /// Check an exFAT volume's boot regions, checksums and layout
+    ExfatVerify {
+        /// Device, volume or image path
+        #[arg(long)]
+        device: String,
+
+        /// Byte offset of the volume, e.g. a partition inside a disk image
+        #[arg(long, default_value_t = 0)]
+        offset_bytes: u64,
+
+        /// Volume size in bytes (defaults to the end of the file)
+        #[arg(long)]
+        size_bytes: Option<u64>,
+    },
+
+    /// Check a FAT32 volume's boot sectors, FSInfo and FAT layout against a
+    /// recomputed one
+    Fat32Verify {
+        /// Device, volume or image path
+        #[arg(long)]
+        device: String,
+
+        /// Byte offset of the volume, e.g. a partition inside a disk image
+        #[arg(long, default_value_t = 0)]
+        offset_bytes: u64,
+
+        /// Volume size in bytes (defaults to the end of the file)
+        #[arg(long)]
+        size_bytes: Option<u64>,
+    },
+
+    /// Copy a captured device image without its unused space, as a sparse
+    /// file that ends after the last byte in use
+    TrimImage {
+        /// Captured image
+        #[arg(long)]
+        source: String,
+
+        /// Trimmed image to write (replaced if it exists)
+        #[arg(long)]
+        output: String,
+    },
+
+    /// Convert a disk image between raw, VHD, VHDX and qcow2 containers,
+    /// e.g. a captured stick to a VHDX Hyper-V boots
+    ConvertImage {
+        /// Image to read (raw, vhd, vhdx or qcow2; detected from its contents)
+        #[arg(long)]
+        source: String,
+
+        /// Image to write (replaced if it exists)
+        #[arg(long)]
+        output: String,
+
+        /// raw, vhd or vhdx (default: from the output extension)
+        #[arg(long)]
+        format: Option<String>,
+    },
+
+    /// List runs interrupted mid-workflow and how to recover them
+    RunsRecover {
+        /// Run ledger directory (default: $PHOENIX_STATE_DIR/runs or the platform state dir)
+        #[arg(long)]
+        ledger: Option<String>,
+
+        /// Mark an interrupted run as handled
+        #[arg(long)]
+        dismiss: Option<String>,
+    },
+
+    /// List every disk this host has seen, with how often each was flashed
+    DeviceHistory {
+        /// Device registry file (default: $PHOENIX_DEVICE_REGISTRY or devices.json in the state dir)
+        #[arg(long)]
+        registry: Option<String>,
+
+        /// Only this serial (or `<name>|<size>` key)
+        #[arg(long)]
+        device: Option<String>,
+
+        /// Print JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Label a disk in the device registry, optionally warning or refusing writes to it
+    DeviceAnnotate {
+        /// Serial (or `<name>|<size>` key) from device-history
+        #[arg(long)]
+        device: String,
+
+        /// Label shown next to the disk, e.g. "Customer loaner - never wipe"
+        #[arg(long, required_unless_present = "clear")]
+        label: Option<String>,
+
+        /// Free-form notes kept with the label
+        #[arg(long)]
+        notes: Option<String>,
+
+        /// What picking the disk as a target does: label, warn or deny
+        #[arg(long, default_value = "label")]
+        action: String,
+
+        /// Remove the disk's label
+        #[arg(long, conflicts_with_all = ["label", "notes"])]
+        clear: bool,
+
+        /// Device registry file (default: $PHOENIX_DEVICE_REGISTRY or devices.json in the state dir)
+        #[arg(long)]
+        registry: Option<String>,
+    },
+
+    /// List safety overrides recorded in the audit log, oldest first
+    AuditLog {
+        /// Print JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Undo temp mounts, scratch directories and write-test files left by crashed runs
+    Cleanup {
+        /// List the leftovers without removing anything
+        #[arg(long)]
+        dry_run: bool,
+    },
+
+    /// Show the verification policy workflows apply
+    VerifyPolicy,
+
+    /// Show the host's write limits and the image writes running or queued
+    IoStatus {
+        /// Print JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Send a sample run event to the configured notification channels
+    NotifyTest {
+        /// Notification config JSON (default: $PHOENIX_NOTIFY_CONFIG or notify.json in the state dir)
+        #[arg(long)]
+        config: Option<String>,
+
+        /// Send a failure event instead of a completion
+        #[arg(long)]
+        failed: bool,
+
+        /// Report bundle to link or attach
+        #[arg(long)]
+        report: Option<String>,
+    },
+
+    /// Update this binary from a signed release manifest
+    SelfUpdate {
+        /// Release manifest URL or path (default: $PHOENIX_UPDATE_URL or the build's URL)
+        #[arg(long)]
+        manifest_url: Option<String>,
+
+        /// Only report whether an update is available
+        #[arg(long)]
+        check: bool,
+
+        /// Install even if the manifest version is not newer, including a downgrade
+        #[arg(long)]
+        force: bool,
+
+        /// Restore the binary replaced by the last update
+        #[arg(long)]
+        rollback: bool,
+    },
+
+    /// Sign a release binary and print its manifest entry
+    ReleaseSign {
+        /// Binary to sign
+        #[arg(long)]
+        artifact: String,
+
+        /// File holding the 32-byte ed25519 seed as hex
+        #[arg(long)]
+        key_file: String,
+
+        /// Version of the release, as written in the manifest
+        #[arg(long)]
+        release_version: String,
+
+        /// Platform like linux-x86_64 (default: this host)
+        #[arg(long)]
+        platform: Option<String>,
+
+        /// URL recorded in the manifest (default: the artifact file name)
+        #[arg(long)]
+        url: Option<String>,
+    },
+
+    /// Check this host's prerequisites and suggest fixes
+    Doctor {
+        /// Print the checks as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Show which operations this host can perform
+    Capabilities {
+        /// Print the capabilities as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Show the settings this process runs with and where each came from
+    ConfigShow {
+        /// Every setting, not only those set by a flag, variable, file or the build
+        #[arg(long)]
+        effective: bool,
+
+        /// Print the settings as JSON
+        #[arg(long)]
+        json: bool,
+
+        /// List settings that differ from another process's `--effective --json` dump
+        #[arg(long)]
+        compare: Option<String>,
+    },
+
+    /// Register the Phoenix Event Log source (Windows, needs Administrator)
+    SystemLogRegister,
+
+    /// Validate a Phoenix pack manifest and workflows
+    PackValidate {
+        /// Path to pack manifest JSON
+        #[arg(long)]
+        manifest: String,
+
+        /// Optional signing key hex (verifies signature if present)
+        #[arg(long)]
+        key: Option<String>,
+    },
+
+    /// Scaffold a new pack: manifest, an example workflow and its asset
+    PackNew {
+        /// Directory to create the pack in
+        #[arg(long)]
+        dir: String,
+
+        /// Pack name
+        #[arg(long)]
+        name: String,
+    },
+
+    /// Check a pack's workflows, asset paths and templates without running them
+    PackLint {
+        /// Path to pack manifest JSON/YAML
+        #[arg(long)]
+        manifest: String,
+
+        /// Print JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Lint a pack, then run its workflows on the mock host
+    PackTest {
+        /// Path to pack manifest JSON/YAML
+        #[arg(long)]
+        manifest: String,
+
+        /// Report base for the runs and the test report
+        #[arg(long, default_value = ".")]
+        report_base: String,
+
+        /// Device graph JSON, or a report bundle holding one, to test against
+        /// (default: the built-in mock graph)
+        #[arg(long)]
+        graph: Option<String>,
+
+        /// Sandbox for the mock disks and state (default: a new temp directory)
+        #[arg(long)]
+        sandbox: Option<String>,
+    },
+
+    /// Run all workflows listed in a pack manifest
+    PackRun {
+        /// Path to pack manifest JSON
+        #[arg(long)]
+        manifest: String,
+
+        /// Default report base for workflow runs
+        #[arg(long, default_value = ".")]
+        report_base: String,
+
+        /// Require pack signature verification
+        #[arg(long)]
+        require_signed: bool,
+
+        /// Signing key hex (overrides env PHOENIX_PACK_KEY)
+        #[arg(long)]
+        key: Option<String>,
+    },
+
+    /// Sign a pack manifest (writes .sig)
+    PackSign {
+        /// Path to pack manifest JSON/YAML
+        #[arg(long)]
+        manifest: String,
+
+        /// Signing key hex
+        #[arg(long)]
+        key: String,
+    },
+
+    /// Verify a pack manifest signature
+    PackVerify {
+        /// Path to pack manifest JSON/YAML
+        #[arg(long)]
+        manifest: String,
+
+        /// Signing key hex
+        #[arg(long)]
+        key: String,
+    },
+
+    /// Print the SHA-256 of every file under a payload directory, as a
+    /// pack manifest's payload `files` lists them
+    PackPayloadHash {
+        /// Payload directory
+        #[arg(long)]
+        path: String,
+    },
+
+    /// Put a pack's cached_assets into the local asset store
+    PackCache {
+        /// Path to pack manifest JSON/YAML
+        #[arg(long)]
+        manifest: String,
+
+        /// Print the stored assets as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// List the local asset store, or evict from it
+    AssetPrune {
+        /// Evict least recently used assets until the total is under this many bytes
+        #[arg(long)]
+        max_total_bytes: Option<u64>,
+
+        /// Evict assets unused for this many days
+        #[arg(long)]
+        max_age_days: Option<u64>,
+
+        /// Only list what would be evicted
+        #[arg(long)]
+        dry_run: bool,
+    },
+
+    /// Export a pack as a zip archive
+    PackExport {
+        /// Path to pack manifest JSON/YAML
+        #[arg(long)]
+        manifest: String,
+
+        /// Output zip path
+        #[arg(long)]
+        out: String,
+
+        /// Optional signing key hex (writes .sig before export)
+        #[arg(long)]
+        key: Option<String>,
+    },
+}
+
+/// Build details recorded in every report's `environment.json`.
+fn build_info() -> phoenix_report::BuildInfo {
+    let non_empty = |value: &str| (!value.is_empty()).then(|| value.to_string());
+    let features = [
+        ("udisks2", cfg!(feature = "udisks2")),
+        ("torrent", cfg!(feature = "torrent")),
+        ("metalink", cfg!(feature = "metalink")),
+    ];
+    phoenix_report::BuildInfo {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        git_rev: non_empty(env!("PHOENIX_GIT_REV")),
+        target: non_empty(env!("PHOENIX_BUILD_TARGET")),
+        profile: non_empty(env!("PHOENIX_BUILD_PROFILE")),
+        features: features
+            .iter()
+            .filter(|(_, enabled)| *enabled)
+            .map(|(name, _)| name.to_string())
+            .collect(),
+        crates: phoenix_report::BuildInfo::parse_crate_versions(env!("PHOENIX_CRATE_VERSIONS")),
+    }
+}
+
+impl Commands {
+    /// Where the command writes its report, and so its failure diagnostics.
+    fn report_base(&self) -> Option<&str> {
+        match self {
+            Commands::WindowsInstallerUsb { report_base, .. }
+            | Commands::WindowsApplyImage { report_base, .. }
+            | Commands::LinuxInstallerUsb { report_base, .. }
+            | Commands::MacosInstallerUsb { report_base, .. }
+            | Commands::MacosEraseInstall { report_base, .. }
+            | Commands::BootEntry { report_base, .. }
+            | Commands::IpswRestore { report_base, .. }
+            | Commands::LinuxWriteImage { report_base, .. }
+            | Commands::MacosWriteImage { report_base, .. }
+            | Commands::LinuxBootPrep { report_base, .. }
+            | Commands::MacosBootPrep { report_base, .. }
+            | Commands::MacosCreateInstaller { report_base, .. }
+            | Commands::MacosLegacyPatch { report_base, .. }
+            | Commands::StageBootloader { report_base, .. }
+            | Commands::StageFiles { report_base, .. }
+            | Commands::StageProvisioning { report_base, .. }
+            | Commands::MacosKextStage { report_base, .. }
+            | Commands::WorkflowRun { report_base, .. }
+            | Commands::Kiosk { report_base, .. }
+            | Commands::Agent { report_base, .. }
+            | Commands::DuplicateToAll { report_base, .. }
+            | Commands::CloneDevice { report_base, .. }
+            | Commands::Restore { report_base, .. }
+            | Commands::DiskHashReport { report_base, .. }
+            | Commands::BadBlockScan { report_base, .. }
+            | Commands::ComboStick { report_base, .. }
+            | Commands::AbStick { report_base, .. }
+            | Commands::AbUpdate { report_base, .. }
+            | Commands::ResizePartition { report_base, .. }
+            | Commands::ValidateSource { report_base, .. }
+            | Commands::SlimWindowsMedia { report_base, .. }
+            | Commands::MergeWindowsLanguages { report_base, .. }
+            | Commands::PackTest { report_base, .. }
+            | Commands::PackRun { report_base, .. } => Some(report_base),
+            _ => None,
+        }
+    }
+}
+
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+    phoenix_safety::set_read_only(cli.read_only);
+    if let Some(minutes) = cli.armed_for {
+        phoenix_safety::arm_for(std::time::Duration::from_secs(minutes.saturating_mul(60)));
+    }
+    if let Some(endpoint) = &cli.otlp_endpoint {
+        phoenix_notify::set_otlp_endpoint(Some(endpoint));
+    }
+    if let Some(encoding) = &cli.artifact_encoding {
+        phoenix_report::set_artifact_encoding(phoenix_report::ArtifactEncoding::parse(encoding)?);
+    }
+    phoenix_report::set_build_info(build_info());
+    let correlation_id = cli
+        .correlation_id
+        .clone()
+        .or_else(|| std::env::var("PHOENIX_CORRELATION_ID").ok().filter(|id| !id.is_empty()));
+    phoenix_report::set_correlation_id(correlation_id.as_deref())?;
+    phoenix_fetch::configure(phoenix_workflow_engine::network_config()?)?;
+
+    if let Some(units) = &cli.units {
+        let units = UnitSystem::parse(units).map_err(|err| anyhow!(err))?;
+        phoenix_core::set_display_format(DisplayFormat {
+            units,
+            ..DisplayFormat::from_env()
+        });
+    }
+    let progress = match &cli.progress {
+        Some(mode) => ProgressMode::parse(mode)?,
+        None => ProgressMode::detect(),
+    };
+    let _ = PROGRESS_MODE.set(progress);
+
+    let report_base = cli.cmd.report_base().map(std::path::PathBuf::from);
+    let result = with_progress_output(progress, || run_command(cli.cmd));
+    if let (Err(err), Some(base)) = (&result, report_base) {
+        match phoenix_workflow_engine::write_failure_diagnostics(&base, err) {
+            Ok(Some(report)) => eprintln!("diagnostics: {}", report.root.display()),
+            Ok(None) => {}
+            Err(diag_err) => eprintln!("diagnostics not written: {:#}", diag_err),
+        }
+    }
+    if let Err(err) = &result {
+        if err.downcast_ref::<WorkflowTimeout>().is_some() {
+            eprintln!("Error: {:?}", err);
+            std::process::exit(WorkflowTimeout::EXIT_CODE);
+        }
+    }
+    result
+}
+
+fn run_command(cmd: Commands) -> Result<()> {
+    match cmd {
+        Commands::DeviceGraph {
+            pretty,
+            from_report,
+            target,
+        } => {
+            let graph = match from_report {
+                Some(path) => DeviceGraph::from_report(&path)?,
+                None => build_device_graph()?,
+            };
+            if let Some(target) = target {
+                let explanation = phoenix_workflow_engine::explain_target(&graph, &target);
+                if pretty {
+                    println!("{}", serde_json::to_string_pretty(&explanation)?);
+                    return Ok(());
+                }
+                println!("target: {}", explanation.target);
+                println!("disk_id: {}", explanation.disk_id.as_deref().unwrap_or("-"));
+                println!("matched_by: {}", explanation.matched_by.as_deref().unwrap_or("-"));
+                println!("eligible: {}", explanation.eligible);
+                for reason in &explanation.reasons {
+                    println!("reason: {}", reason);
+                }
+                return Ok(());
+            }
+            if pretty {
+                println!("{}", serde_json::to_string_pretty(&graph)?);
+            } else {
+                println!("{}", serde_json::to_string(&graph)?);
+            }
+            Ok(())
+        }
+        Commands::DescribeDestruction {
+            target,
+            action,
+            from_report,
+            pretty,
+        } => {
+            let params = DestructionParams {
+                target,
+                action: DestructiveAction::parse(&action)?,
+                inspect_contents: from_report.is_none(),
+            };
+            let graph = match from_report {
+                Some(path) => DeviceGraph::from_report(&path)?,
+                None => build_device_graph()?,
+            };
+            let summary = describe_destruction(&params, &graph)?;
+            if pretty {
+                println!("{}", serde_json::to_string_pretty(&summary)?);
+            } else {
+                println!("{}", summary.text());
+            }
+            Ok(())
+        }
+        Commands::Report { base } => {
+            let graph = build_device_graph()?;
+            let key = std::env::var("PHOENIX_SIGNING_KEY").ok();
+            let paths = phoenix_report::create_report_bundle_with_meta_and_signing(
                 base,
                 &graph,
                 None,
                 None,
                 key.as_deref(),
             )?;
-            println!("Report created:");
-            println!("  run_id: {}", paths.run_id);
-            println!("  root:   {}", paths.root.display());
-            println!("  device_graph: {}", paths.device_graph_json.display());
-            println!("  run.json:     {}", paths.run_json.display());
-            println!("  logs:         {}", paths.logs_path.display());
-            println!("  manifest:     {}", paths.manifest_path.display());
-            if let Some(sig) = paths.signature_path.as_ref() {
-                println!("  signature:    {}", sig.display());
+            println!("Report created:");
+            println!("  run_id: {}", paths.run_id);
+            println!("  root:   {}", paths.root.display());
+            println!("  device_graph: {}", paths.device_graph_json.display());
+            println!("  run.json:     {}", paths.run_json.display());
+            println!("  environment:  {}", paths.environment_json.display());
+            println!("  logs:         {}", paths.logs_path.display());
+            println!("  manifest:     {}", paths.manifest_path.display());
+            if let Some(sig) = paths.signature_path.as_ref() {
+                println!("  signature:    {}", sig.display());
+            }
+            Ok(())
+        }
+
+        Commands::ReportVerify { path, key } => {
+            let result = phoenix_report::verify_report_bundle(path, key.as_deref())?;
+            println!("verified_entries: {}", result.entries_checked);
+            println!("signature_valid: {:?}", result.signature_valid);
+            if !result.mismatches.is_empty() {
+                println!("mismatches:");
+                for mismatch in &result.mismatches {
+                    println!("  - {}", mismatch);
+                }
+            }
+            if !result.findings.is_empty() {
+                println!("findings:");
+                for finding in &result.findings {
+                    println!(
+                        "  - {} {}: {}",
+                        finding.severity.as_str(),
+                        finding.code,
+                        finding.message
+                    );
+                }
+            }
+            if result.ok {
+                Ok(())
+            } else {
+                Err(anyhow!("report verification failed"))
+            }
+        }
+
+        Commands::ReportExport {
+            path,
+            out,
+            compression_level,
+            store,
+        } => {
+            let options = phoenix_report::ZipExportOptions {
+                compression: if store {
+                    phoenix_report::ZipCompression::Stored
+                } else {
+                    phoenix_report::ZipCompression::Deflated
+                },
+                level: compression_level,
+            };
+            let output = phoenix_report::export_report_zip_with_options(path, out, &options)?;
+            println!("exported: {}", output.display());
+            Ok(())
+        }
+
+        Commands::ReportVerifyTree { root, key } => {
+            let result = phoenix_report::verify_report_tree(root, key.as_deref())?;
+            println!("total_reports: {}", result.total_reports);
+            println!("ok_reports: {}", result.ok_reports);
+            if !result.failed_reports.is_empty() {
+                println!("failed_reports:");
+                for report in &result.failed_reports {
+                    println!("  - {}", report);
+                }
+            }
+            if result.failed_reports.is_empty() {
+                Ok(())
+            } else {
+                Err(anyhow!("one or more reports failed verification"))
+            }
+        }
+        Commands::ReportList {
+            base,
+            workflow,
+            status,
+            target,
+            limit,
+            json,
+        } => {
+            let filter = phoenix_report::RunFilter {
+                workflow,
+                status,
+                target,
+                limit,
+                ..Default::default()
+            };
+            let runs = phoenix_report::list_runs(&base, &filter)?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&runs)?);
+                return Ok(());
+            }
+            for run in &runs {
+                println!(
+                    "{} {} {} {} {} {}",
+                    run.generated_at_utc,
+                    run.run_id,
+                    run.workflow.as_deref().unwrap_or("-"),
+                    run.status.as_deref().unwrap_or("-"),
+                    run.target.as_deref().unwrap_or("-"),
+                    run.duration_ms.map_or("-".to_string(), |ms| format!("{}ms", ms))
+                );
+            }
+            Ok(())
+        }
+        Commands::ReportFind {
+            base,
+            id,
+            rebuild_index,
+        } => {
+            if rebuild_index {
+                let indexed = phoenix_report::rebuild_report_index(&base)?;
+                println!("indexed: {}", indexed);
+            }
+            let reports = phoenix_report::find_reports_by_correlation_id(&base, &id)?;
+            println!("correlation_id: {}", id);
+            println!("reports: {}", reports.len());
+            for report in &reports {
+                println!(
+                    "  {} {}",
+                    report.generated_at_utc,
+                    std::path::Path::new(&base).join("reports").join(&report.path).display()
+                );
+            }
+            Ok(())
+        }
+
+        Commands::ReportPrune {
+            base,
+            max_bundles,
+            max_age_days,
+            max_total_bytes,
+            archive,
+            dry_run,
+        } => {
+            let mut policy = phoenix_report::RetentionPolicy::load(&base)?.unwrap_or_default();
+            policy.max_bundles = max_bundles.or(policy.max_bundles);
+            policy.max_age_days = max_age_days.or(policy.max_age_days);
+            policy.max_total_bytes = max_total_bytes.or(policy.max_total_bytes);
+            policy.archive = archive.or(policy.archive);
+            if policy.is_empty() {
+                return Err(anyhow!(
+                    "no retention limits; pass --max-bundles, --max-age-days or --max-total-bytes, or write {}",
+                    std::path::Path::new(&base).join(phoenix_report::RETENTION_POLICY_FILE).display()
+                ));
+            }
+            let result = phoenix_report::prune_reports(&base, &policy, None, dry_run)?;
+            println!("dry_run: {}", dry_run);
+            println!("pruned: {} ({})", result.pruned.len(), format_bytes(result.pruned_bytes));
+            for path in &result.pruned {
+                println!("  {}", path);
+            }
+            for location in &result.archived {
+                println!("archived: {}", location);
+            }
+            println!("kept: {} ({})", result.kept, format_bytes(result.kept_bytes));
+            if !result.errors.is_empty() {
+                for error in &result.errors {
+                    println!("error: {}", error);
+                }
+                return Err(anyhow!("{} bundle(s) could not be pruned", result.errors.len()));
+            }
+            Ok(())
+        }
+
+        Commands::ReportAggregate { root, out, key } => {
+            let summary = phoenix_report::aggregate_reports(&root, key.as_deref())?;
+            let out = out
+                .map(std::path::PathBuf::from)
+                .unwrap_or_else(|| std::path::Path::new(&root).join("fleet-summary.json"));
+            std::fs::write(&out, serde_json::to_vec_pretty(&summary)?)?;
+            println!("total_reports: {}", summary.total_reports);
+            println!("succeeded: {}", summary.succeeded);
+            println!("failed: {}", summary.failed);
+            println!("dry_runs: {}", summary.dry_runs);
+            if let Some(duration) = &summary.duration_ms {
+                println!(
+                    "duration: p50={} p90={} p99={} max={} (n={})",
+                    format_duration_ms(duration.p50),
+                    format_duration_ms(duration.p90),
+                    format_duration_ms(duration.p99),
+                    format_duration_ms(duration.max),
+                    duration.samples
+                );
+            }
+            println!("devices:");
+            for device in &summary.devices {
+                println!(
+                    "  {}: runs={} ok={} failed={} last={} ({})",
+                    device.device,
+                    device.runs,
+                    device.succeeded,
+                    device.failed,
+                    device.last_status,
+                    device.last_run_utc.as_deref().unwrap_or("-")
+                );
+            }
+            println!("summary: {}", out.display());
+            Ok(())
+        }
+
+        Commands::HashDisk {
+            disk,
+            size_bytes,
+            chunk_size,
+            max_chunks,
+        } => {
+            #[cfg(windows)]
+            {
+                let mut observer = CliProgress::new();
+                let hashes = phoenix_imaging::hash_disk_readonly_physicaldrive_with_progress(
+                    &disk,
+                    size_bytes,
+                    chunk_size,
+                    max_chunks,
+                    &mut observer,
+                )?;
+                for (index, hash) in hashes {
+                    println!("chunk {}: {}", index, hash);
+                }
+                Ok(())
+            }
+            #[cfg(not(windows))]
+            {
+                let _ = (disk, size_bytes, chunk_size, max_chunks);
+                Err(anyhow!("Windows-first in M0"))
+            }
+        }
+
+        Commands::WindowsInstallerUsb {
+            disk,
+            source,
+            mount,
+            report_base,
+            force,
+            token,
+            acknowledge_target_size,
+            acknowledge_device_wear,
+            confirm_overwrite,
+            execute,
+            repartition,
+            partitions,
+            hybrid_mbr,
+            format,
+            fs,
+            label,
+            cluster_size,
+            drivers,
+            drivers_target,
+            hash_manifest,
+            hash_destination,
+            edition,
+            edition_selector,
+            pid_txt,
+            include,
+            exclude,
+            dedupe,
+            flush_every,
+            continue_on_error,
+            transform_pack,
+        } => {
+            #[cfg(windows)]
+            {
+                let filesystem = parse_filesystem(&fs)
+                    .ok_or_else(|| anyhow!("unsupported filesystem: {}", fs))?;
+                let cluster_bytes = cluster_size
+                    .as_deref()
+                    .map(phoenix_partition::parse_size)
+                    .transpose()?;
+                let partitions = partitions
+                    .iter()
+                    .map(|spec| phoenix_partition::PartitionSpec::parse(spec))
+                    .collect::<Result<Vec<_>>>()?;
+                let params = WindowsInstallerUsbParams {
+                    target_disk_id: disk,
+                    source_path: source.into(),
+                    target_mount: mount.map(Into::into),
+                    report_base: report_base.into(),
+                    force,
+                    confirmation_token: token,
+                    acknowledge_target_size,
+                    acknowledge_device_wear,
+                    confirm_overwrite,
+                    dry_run: !execute,
+                    repartition,
+                    format,
+                    filesystem,
+                    label,
+                    cluster_bytes,
+                    driver_source: drivers.map(Into::into),
+                    driver_target: drivers_target.map(Into::into),
+                    hash_manifest,
+                    hash_destination,
+                    partitions,
+                    hybrid_mbr,
+                    edition,
+                    edition_selector,
+                    pid_txt,
+                    source_filter: SourceFilter::new(include, exclude)?,
+                    dedupe,
+                    flush_every_bytes: flush_every
+                        .as_deref()
+                        .map(phoenix_partition::parse_size)
+                        .transpose()?,
+                    continue_on_error,
+                    transform_pack: transform_pack.map(Into::into),
+                };
+                let result = run_windows_installer_usb(&params)?;
+                println!("Workflow complete:");
+                println!("  dry_run: {}", result.dry_run);
+                println!("  target_mount: {}", result.target_mount.display());
+                println!("  copied_files: {}", result.copied_files);
+                println!(
+                    "  copied_bytes: {} ({})",
+                    result.copied_bytes,
+                    format_bytes(result.copied_bytes)
+                );
+                print_copy_failures(&result.copy_failures);
+                print_transformed_files(&result.transformed_files);
+                println!("  driver_files: {}", result.driver_files);
+                println!(
+                    "  driver_bytes: {} ({})",
+                    result.driver_bytes,
+                    format_bytes(result.driver_bytes)
+                );
+                if let Some(capacity) = &result.format_capacity {
+                    println!(
+                        "  format_capacity: {} of {} usable bytes needed ({}, {} byte clusters)",
+                        capacity.required_bytes,
+                        capacity.estimate.usable_bytes,
+                        capacity.estimate.filesystem,
+                        capacity.estimate.cluster_bytes
+                    );
+                }
+                println!("  report_root: {}", result.report.root.display());
+                println!("  logs: {}", result.report.logs_path.display());
+                println!("  manifest: {}", result.report.manifest_path.display());
+                if let Some(sig) = result.report.signature_path.as_ref() {
+                    println!("  signature: {}", sig.display());
+                }
+                Ok(())
+            }
+            #[cfg(not(windows))]
+            {
+                let _ = (
+                    disk, source, mount, report_base, force, token, execute, repartition,
+                    partitions, hybrid_mbr, format, fs, label, cluster_size, drivers,
+                    drivers_target, hash_manifest, hash_destination, edition, edition_selector, pid_txt,
+                    acknowledge_target_size, acknowledge_device_wear, confirm_overwrite, include, exclude,
+                    dedupe, flush_every, continue_on_error, transform_pack,
+                );
+                Err(anyhow!("Windows-first in M0"))
+            }
+        }
+
+        Commands::WimInfo { path } => {
+            #[cfg(windows)]
+            {
+                let (image_path, _prepared) = resolve_windows_image(path)?;
+                let images = wim_list_images(image_path)?;
+                for image in images {
+                    println!("Image {}", image.index);
+                    if let Some(name) = image.name {
+                        println!("  name: {}", name);
+                    }
+                    if let Some(desc) = image.description {
+                        println!("  description: {}", desc);
+                    }
+                    if let Some(bytes) = image.total_bytes {
+                        println!("  total_bytes: {} ({})", bytes, format_bytes(bytes));
+                    }
+                    if let Some(edition_id) = image.edition_id {
+                        println!("  edition_id: {}", edition_id);
+                    }
+                    if let Some(arch) = image.architecture {
+                        println!("  architecture: {}", arch);
+                    }
+                }
+                Ok(())
+            }
+            #[cfg(not(windows))]
+            {
+                let _ = path;
+                Err(anyhow!("Windows-first in M0"))
+            }
+        }
+
+        Commands::WimApply { path, index, target } => {
+            #[cfg(windows)]
+            {
+                let (image_path, _prepared) = resolve_windows_image(path)?;
+                wim_apply_image(image_path, index, target)?;
+                println!("WIM apply complete.");
+                Ok(())
+            }
+            #[cfg(not(windows))]
+            {
+                let _ = (path, index, target);
+                Err(anyhow!("Windows-first in M0"))
+            }
+        }
+
+        Commands::WindowsApplyImage {
+            source,
+            index,
+            edition_selector,
+            target,
+            report_base,
+            force,
+            token,
+            allow_system_target,
+            execute,
+            verify,
+        } => {
+            #[cfg(windows)]
+            {
+                let params = WindowsApplyImageParams {
+                    source_path: source.into(),
+                    image_index: index,
+                    edition_selector,
+                    target_dir: target.into(),
+                    report_base: report_base.into(),
+                    force,
+                    confirmation_token: token,
+                    allow_system_target,
+                    dry_run: !execute,
+                    verify,
+                };
+                let result = run_windows_apply_image(&params)?;
+                println!("Apply complete:");
+                println!("  dry_run: {}", result.dry_run);
+                println!("  target_dir: {}", result.target_dir.display());
+                println!("  file_count: {}", result.file_count);
+                println!(
+                    "  total_bytes: {} ({})",
+                    result.total_bytes,
+                    format_bytes(result.total_bytes)
+                );
+                println!("  report_root: {}", result.report.root.display());
+                println!("  manifest: {}", result.report.manifest_path.display());
+                if let Some(sig) = result.report.signature_path.as_ref() {
+                    println!("  signature: {}", sig.display());
+                }
+                Ok(())
+            }
+            #[cfg(not(windows))]
+            {
+                let _ = (
+                    source, index, edition_selector, target, report_base, force, token,
+                    allow_system_target, execute, verify,
+                );
+                Err(anyhow!("Windows-first in M0"))
+            }
+        }
+
+        Commands::LinuxInstallerUsb {
+            source,
+            target_mount,
+            report_base,
+            force,
+            token,
+            acknowledge_target_size,
+            acknowledge_device_wear,
+            confirm_overwrite,
+            execute,
+            hash_manifest,
+            hash_destination,
+            format_device,
+            format_size_bytes,
+            format_label,
+            format_cluster_size,
+            udisks,
+            power_off,
+            include,
+            exclude,
+            dedupe,
+            flush_every,
+            continue_on_error,
+            transform_pack,
+        } => {
+            #[cfg(target_os = "linux")]
+            {
+                let params = UnixInstallerUsbParams {
+                    source_path: source.into(),
+                    target_mount: target_mount.into(),
+                    report_base: report_base.into(),
+                    force,
+                    confirmation_token: token,
+                    acknowledge_target_size,
+                    acknowledge_device_wear,
+                    confirm_overwrite,
+                    dry_run: !execute,
+                    hash_manifest,
+                    hash_destination,
+                    format_device: format_device.map(Into::into),
+                    format_size_bytes,
+                    format_label,
+                    format_cluster_bytes: format_cluster_size
+                        .as_deref()
+                        .map(phoenix_partition::parse_size)
+                        .transpose()?,
+                    udisks,
+                    power_off,
+                    source_filter: SourceFilter::new(include, exclude)?,
+                    dedupe,
+                    flush_every_bytes: flush_every
+                        .as_deref()
+                        .map(phoenix_partition::parse_size)
+                        .transpose()?,
+                    continue_on_error,
+                    transform_pack: transform_pack.map(Into::into),
+                };
+                let result = run_unix_installer_usb(&params)?;
+                println!("Linux USB staging complete:");
+                println!("  dry_run: {}", result.dry_run);
+                println!("  target_mount: {}", result.target_mount.display());
+                println!("  copied_files: {}", result.copied_files);
+                println!(
+                    "  copied_bytes: {} ({})",
+                    result.copied_bytes,
+                    format_bytes(result.copied_bytes)
+                );
+                print_copy_failures(&result.copy_failures);
+                print_transformed_files(&result.transformed_files);
+                if let Some(capacity) = &result.format_capacity {
+                    println!(
+                        "  format_capacity: {} of {} usable bytes needed ({}, {} byte clusters)",
+                        capacity.required_bytes,
+                        capacity.estimate.usable_bytes,
+                        capacity.estimate.filesystem,
+                        capacity.estimate.cluster_bytes
+                    );
+                }
+                println!("  report_root: {}", result.report.root.display());
+                Ok(())
+            }
+            #[cfg(not(target_os = "linux"))]
+            {
+                let _ = (
+                    source, target_mount, report_base, force, token, execute, hash_manifest, hash_destination,
+                    format_device, format_size_bytes, format_label, format_cluster_size, udisks,
+                    power_off, acknowledge_target_size, acknowledge_device_wear, confirm_overwrite, include,
+                    exclude, dedupe, flush_every, continue_on_error, transform_pack,
+                );
+                Err(anyhow!("linux-only command"))
+            }
+        }
+
+        Commands::BootEntries => {
+            let boot = phoenix_efivars::list_boot_entries()?;
+            if boot.entries.is_empty() {
+                println!("No boot entries.");
+            }
+            for entry in &boot.entries {
+                let mut marks = String::new();
+                if boot.current == Some(entry.number) {
+                    marks.push_str(" [current]");
+                }
+                if boot.next == Some(entry.number) {
+                    marks.push_str(" [next]");
+                }
+                println!(
+                    "{}{} {}  {}{}",
+                    entry.name,
+                    if entry.active { "*" } else { " " },
+                    entry.description,
+                    entry.loader.as_deref().unwrap_or("-"),
+                    marks
+                );
+            }
+            Ok(())
+        }
+
+        Commands::BootEntry {
+            target_mount,
+            loader,
+            description,
+            position,
+            report_base,
+            force,
+            token,
+            execute,
+        } => {
+            let params = BootEntryParams {
+                target_mount: target_mount.into(),
+                loader,
+                description,
+                position: phoenix_efivars::BootPosition::parse(&position)?,
+                report_base: report_base.into(),
+                force,
+                confirmation_token: token,
+                dry_run: !execute,
+            };
+            let result = run_boot_entry(&params)?;
+            println!("Boot entry complete:");
+            println!("  dry_run: {}", result.dry_run);
+            if let Some(number) = result.boot_number {
+                println!("  entry: Boot{:04X}", number);
+            }
+            println!("  report_root: {}", result.report.root.display());
+            Ok(())
+        }
+
+        Commands::DfuList { tool } => {
+            let devices = list_dfu_devices(RestoreTool::parse(&tool)?)?;
+            if devices.is_empty() {
+                println!("No devices in DFU mode.");
+            }
+            for device in &devices {
+                println!(
+                    "{}  {}  {}",
+                    device.ecid,
+                    device.product.as_deref().unwrap_or("-"),
+                    device.location.as_deref().unwrap_or("-")
+                );
+            }
+            Ok(())
+        }
+
+        Commands::IpswRestore {
+            ipsw,
+            ecid,
+            mode,
+            tool,
+            report_base,
+            force,
+            token,
+            execute,
+        } => {
+            let params = IpswRestoreParams {
+                ipsw: ipsw.map(Into::into),
+                ecid,
+                mode: RestoreMode::parse(&mode)?,
+                tool: tool.as_deref().map(RestoreTool::parse).transpose()?,
+                report_base: report_base.into(),
+                force,
+                confirmation_token: token,
+                dry_run: !execute,
+            };
+            let result = run_ipsw_restore(&params)?;
+            println!("IPSW {} complete:", params.mode.as_str());
+            println!("  dry_run: {}", result.dry_run);
+            println!("  ecid: {}", result.device.ecid);
+            if let Some(product) = &result.device.product {
+                println!("  product: {}", product);
+            }
+            println!("  report_root: {}", result.report.root.display());
+            Ok(())
+        }
+
+        Commands::MacosEraseInstall {
+            source_app,
+            new_volume_name,
+            packages,
+            admin_user,
+            admin_password_secret,
+            report_base,
+            force,
+            token,
+            allow_system_target,
+            execute,
+        } => {
+            let params = MacosEraseInstallParams {
+                source_app: source_app.into(),
+                report_base: report_base.into(),
+                new_volume_name,
+                packages: packages.into_iter().map(Into::into).collect(),
+                admin_user,
+                admin_password: admin_password_secret
+                    .as_deref()
+                    .map(resolve_secret)
+                    .transpose()?,
+                force,
+                confirmation_token: token,
+                allow_system_target,
+                dry_run: !execute,
+            };
+            let result = run_macos_erase_install(&params)?;
+            println!("macOS erase install complete:");
+            println!("  dry_run: {}", result.dry_run);
+            println!("  target_disk: {}", result.target_disk);
+            if let Some(percent) = result.progress_percent {
+                println!("  progress: {:.0}%", percent);
+            }
+            println!("  report_root: {}", result.report.root.display());
+            Ok(())
+        }
+
+        Commands::MacosInstallerUsb {
+            source,
+            target_mount,
+            report_base,
+            force,
+            token,
+            acknowledge_target_size,
+            acknowledge_device_wear,
+            confirm_overwrite,
+            execute,
+            hash_manifest,
+            hash_destination,
+            format_device,
+            format_size_bytes,
+            format_label,
+            format_cluster_size,
+            include,
+            exclude,
+            dedupe,
+            flush_every,
+            continue_on_error,
+            transform_pack,
+        } => {
+            #[cfg(target_os = "macos")]
+            {
+                let params = UnixInstallerUsbParams {
+                    source_path: source.into(),
+                    target_mount: target_mount.into(),
+                    report_base: report_base.into(),
+                    force,
+                    confirmation_token: token,
+                    acknowledge_target_size,
+                    acknowledge_device_wear,
+                    confirm_overwrite,
+                    dry_run: !execute,
+                    hash_manifest,
+                    hash_destination,
+                    format_device: format_device.map(Into::into),
+                    format_size_bytes,
+                    format_label,
+                    format_cluster_bytes: format_cluster_size
+                        .as_deref()
+                        .map(phoenix_partition::parse_size)
+                        .transpose()?,
+                    udisks: false,
+                    power_off: false,
+                    source_filter: SourceFilter::new(include, exclude)?,
+                    dedupe,
+                    flush_every_bytes: flush_every
+                        .as_deref()
+                        .map(phoenix_partition::parse_size)
+                        .transpose()?,
+                    continue_on_error,
+                    transform_pack: transform_pack.map(Into::into),
+                };
+                let result = run_unix_installer_usb(&params)?;
+                println!("macOS USB staging complete:");
+                println!("  dry_run: {}", result.dry_run);
+                println!("  target_mount: {}", result.target_mount.display());
+                println!("  copied_files: {}", result.copied_files);
+                println!(
+                    "  copied_bytes: {} ({})",
+                    result.copied_bytes,
+                    format_bytes(result.copied_bytes)
+                );
+                print_copy_failures(&result.copy_failures);
+                print_transformed_files(&result.transformed_files);
+                if let Some(capacity) = &result.format_capacity {
+                    println!(
+                        "  format_capacity: {} of {} usable bytes needed ({}, {} byte clusters)",
+                        capacity.required_bytes,
+                        capacity.estimate.usable_bytes,
+                        capacity.estimate.filesystem,
+                        capacity.estimate.cluster_bytes
+                    );
+                }
+                println!("  report_root: {}", result.report.root.display());
+                Ok(())
+            }
+            #[cfg(not(target_os = "macos"))]
+            {
+                let _ = (
+                    source, target_mount, report_base, force, token, execute, hash_manifest, hash_destination,
+                    format_device, format_size_bytes, format_label, format_cluster_size,
+                    acknowledge_target_size, acknowledge_device_wear, confirm_overwrite, include, exclude,
+                    dedupe, flush_every, continue_on_error, transform_pack,
+                );
+                Err(anyhow!("macos-only command"))
+            }
+        }
+
+        Commands::LinuxWriteImage {
+            source,
+            mirrors,
+            sha256,
+            device,
+            report_base,
+            force,
+            token,
+            acknowledge_target_size,
+            acknowledge_device_wear,
+            confirm_overwrite,
+            execute,
+            verify,
+            chunk_size,
+            fast_io,
+            flush_every,
+        } => {
+            #[cfg(target_os = "linux")]
+            {
+                let params = phoenix_workflow_engine::UnixWriteImageParams {
+                    source_image: source.into(),
+                    source_mirrors: mirrors,
+                    target_device: device.into(),
+                    report_base: report_base.into(),
+                    force,
+                    confirmation_token: token,
+                    acknowledge_target_size,
+                    acknowledge_device_wear,
+                    confirm_overwrite,
+                    dry_run: !execute,
+                    verify,
+                    chunk_size,
+                    fast_io,
+                    source_sha256: sha256,
+                    flush_every_bytes: flush_every
+                        .as_deref()
+                        .map(phoenix_partition::parse_size)
+                        .transpose()?,
+                };
+                let result = phoenix_workflow_engine::run_unix_write_image(&params)?;
+                println!("Linux image write complete:");
+                println!("  dry_run: {}", result.dry_run);
+                println!(
+                    "  bytes_written: {} ({})",
+                    result.bytes_written,
+                    format_bytes(result.bytes_written)
+                );
+                println!("  sha256: {}", result.sha256);
+                println!("  verify_ok: {:?}", result.verify_ok);
+                println!("  chunk_size: {}", result.chunk_size);
+                println!(
+                    "  throughput_bytes_per_sec: {} ({})",
+                    result.throughput_bytes_per_sec,
+                    display_format().rate(result.throughput_bytes_per_sec)
+                );
+                println!("  report_root: {}", result.report.root.display());
+                Ok(())
+            }
+            #[cfg(not(target_os = "linux"))]
+            {
+                let _ = (
+                    source, mirrors, sha256, device, report_base, force, token, execute, verify,
+                    chunk_size, fast_io, flush_every,
+                );
+                Err(anyhow!("linux-only command"))
+            }
+        }
+
+        Commands::MacosWriteImage {
+            source,
+            mirrors,
+            sha256,
+            device,
+            report_base,
+            force,
+            token,
+            acknowledge_target_size,
+            acknowledge_device_wear,
+            confirm_overwrite,
+            execute,
+            verify,
+            chunk_size,
+            fast_io,
+            flush_every,
+        } => {
+            #[cfg(target_os = "macos")]
+            {
+                let params = phoenix_workflow_engine::UnixWriteImageParams {
+                    source_image: source.into(),
+                    source_mirrors: mirrors,
+                    target_device: device.into(),
+                    report_base: report_base.into(),
+                    force,
+                    confirmation_token: token,
+                    acknowledge_target_size,
+                    acknowledge_device_wear,
+                    confirm_overwrite,
+                    dry_run: !execute,
+                    verify,
+                    chunk_size,
+                    fast_io,
+                    source_sha256: sha256,
+                    flush_every_bytes: flush_every
+                        .as_deref()
+                        .map(phoenix_partition::parse_size)
+                        .transpose()?,
+                };
+                let result = phoenix_workflow_engine::run_unix_write_image(&params)?;
+                println!("macOS image write complete:");
+                println!("  dry_run: {}", result.dry_run);
+                println!(
+                    "  bytes_written: {} ({})",
+                    result.bytes_written,
+                    format_bytes(result.bytes_written)
+                );
+                println!("  sha256: {}", result.sha256);
+                println!("  verify_ok: {:?}", result.verify_ok);
+                println!("  chunk_size: {}", result.chunk_size);
+                println!(
+                    "  throughput_bytes_per_sec: {} ({})",
+                    result.throughput_bytes_per_sec,
+                    display_format().rate(result.throughput_bytes_per_sec)
+                );
+                println!("  report_root: {}", result.report.root.display());
+                Ok(())
+            }
+            #[cfg(not(target_os = "macos"))]
+            {
+                let _ = (
+                    source, mirrors, sha256, device, report_base, force, token, execute, verify,
+                    chunk_size, fast_io, acknowledge_target_size, acknowledge_device_wear, confirm_overwrite,
+                    flush_every,
+                );
+                Err(anyhow!("macos-only command"))
+            }
+        }
+
+        Commands::LinuxBootPrep {
+            source,
+            target_mount,
+            report_base,
+            force,
+            token,
+            execute,
+            hash_manifest,
+            hash_destination,
+        } => {
+            #[cfg(target_os = "linux")]
+            {
+                let params = phoenix_workflow_engine::UnixBootPrepParams {
+                    source_path: source.into(),
+                    target_mount: target_mount.into(),
+                    report_base: report_base.into(),
+                    force,
+                    confirmation_token: token,
+                    dry_run: !execute,
+                    hash_manifest,
+                    hash_destination,
+                };
+                let result = phoenix_workflow_engine::run_unix_boot_prep(&params)?;
+                println!("Linux boot prep complete:");
+                println!("  dry_run: {}", result.dry_run);
+                println!("  copied_files: {}", result.copied_files);
+                println!(
+                    "  copied_bytes: {} ({})",
+                    result.copied_bytes,
+                    format_bytes(result.copied_bytes)
+                );
+                println!("  report_root: {}", result.report.root.display());
+                Ok(())
+            }
+            #[cfg(not(target_os = "linux"))]
+            {
+                let _ = (
+                    source, target_mount, report_base, force, token, execute, hash_manifest,
+                    hash_destination,
+                );
+                Err(anyhow!("linux-only command"))
+            }
+        }
+
+        Commands::MacosBootPrep {
+            source,
+            target_mount,
+            report_base,
+            force,
+            token,
+            execute,
+            hash_manifest,
+            hash_destination,
+        } => {
+            #[cfg(target_os = "macos")]
+            {
+                let params = phoenix_workflow_engine::UnixBootPrepParams {
+                    source_path: source.into(),
+                    target_mount: target_mount.into(),
+                    report_base: report_base.into(),
+                    force,
+                    confirmation_token: token,
+                    dry_run: !execute,
+                    hash_manifest,
+                    hash_destination,
+                };
+                let result = phoenix_workflow_engine::run_unix_boot_prep(&params)?;
+                println!("macOS boot prep complete:");
+                println!("  dry_run: {}", result.dry_run);
+                println!("  copied_files: {}", result.copied_files);
+                println!(
+                    "  copied_bytes: {} ({})",
+                    result.copied_bytes,
+                    format_bytes(result.copied_bytes)
+                );
+                println!("  report_root: {}", result.report.root.display());
+                Ok(())
+            }
+            #[cfg(not(target_os = "macos"))]
+            {
+                let _ = (
+                    source, target_mount, report_base, force, token, execute, hash_manifest,
+                    hash_destination,
+                );
+                Err(anyhow!("macos-only command"))
+            }
+        }
+
+        Commands::MacosCreateInstaller {
+            source,
+            target_device,
+            volume_name,
+            macos_version,
+            filesystem,
+            target_model,
+            report_base,
+            force,
+            token,
+            acknowledge_target_size,
+            acknowledge_device_wear,
+            confirm_overwrite,
+            execute,
+        } => {
+            #[cfg(target_os = "macos")]
+            {
+                let params = phoenix_workflow_engine::MacosInstallerUsbParams {
+                    source_path: source.into(),
+                    target_device: target_device.into(),
+                    report_base: report_base.into(),
+                    volume_name,
+                    macos_version,
+                    filesystem,
+                    target_model,
+                    force,
+                    confirmation_token: token,
+                    acknowledge_target_size,
+                    acknowledge_device_wear,
+                    confirm_overwrite,
+                    dry_run: !execute,
+                };
+                let result = phoenix_workflow_engine::run_macos_installer_usb(&params)?;
+                println!("macOS installer complete:");
+                println!("  dry_run: {}", result.dry_run);
+                println!("  mode: {}", result.mode);
+                println!("  target_volume: {}", result.target_volume.display());
+                println!("  report_root: {}", result.report.root.display());
+                Ok(())
+            }
+            #[cfg(not(target_os = "macos"))]
+            {
+                let _ = (
+                    source, target_device, volume_name, macos_version, filesystem, target_model,
+                    report_base, force, token, execute, acknowledge_target_size, acknowledge_device_wear,
+                    confirm_overwrite,
+                );
+                Err(anyhow!("macos-only command"))
+            }
+        }
+
+        Commands::MacosLegacyPatch {
+            source,
+            model,
+            board_id,
+            report_base,
+            force,
+            token,
+            execute,
+        } => {
+            let params = LegacyPatchParams {
+                source_path: source.into(),
+                report_base: report_base.into(),
+                model,
+                board_id,
+                force,
+                confirmation_token: token,
+                dry_run: !execute,
+            };
+            let result = run_legacy_patch(&params)?;
+            println!("Legacy patch complete:");
+            println!("  dry_run: {}", result.dry_run);
+            println!("  patched_files: {}", result.patched_files.len());
+            for file in &result.patched_files {
+                println!("  patched: {}", file);
+            }
+            println!("  report_root: {}", result.report.root.display());
+            Ok(())
+        }
+
+        Commands::StageBootloader {
+            source,
+            target_mount,
+            target_subdir,
+            report_base,
+            force,
+            token,
+            execute,
+            hash_manifest,
+            hash_destination,
+            mok_certificate,
+            mok_hashes,
+            mok_manager,
+            grub_pack,
+            grub_dir,
+        } => {
+            let params = BootloaderStageParams {
+                source_path: source.into(),
+                target_mount: target_mount.into(),
+                target_subdir: target_subdir.map(Into::into),
+                report_base: report_base.into(),
+                force,
+                confirmation_token: token,
+                dry_run: !execute,
+                hash_manifest,
+                hash_destination,
+                mok_certificate: mok_certificate.map(Into::into),
+                mok_hashes,
+                mok_manager: mok_manager.map(Into::into),
+                grub_pack: grub_pack.map(Into::into),
+                grub_dir,
+            };
+            let result = run_stage_bootloader(&params)?;
+            println!("Bootloader staging complete:");
+            println!("  dry_run: {}", result.dry_run);
+            println!("  copied_files: {}", result.copied_files);
+            println!(
+                "  copied_bytes: {} ({})",
+                result.copied_bytes,
+                format_bytes(result.copied_bytes)
+            );
+            println!("  report_root: {}", result.report.root.display());
+            Ok(())
+        }
+
+        Commands::StageFiles {
+            files,
+            target_mount,
+            overwrite,
+            report_base,
+            force,
+            token,
+            execute,
+        } => {
+            let params = StageFilesParams {
+                rules: files
+                    .iter()
+                    .map(|rule| StageFileRule::parse(rule))
+                    .collect::<Result<_>>()?,
+                target_mount: target_mount.into(),
+                overwrite: StageOverwrite::parse(&overwrite)?,
+                report_base: report_base.into(),
+                force,
+                confirmation_token: token,
+                dry_run: !execute,
+            };
+            let result = run_stage_files(&params)?;
+            println!("File staging complete:");
+            println!("  dry_run: {}", result.dry_run);
+            for file in &result.files {
+                println!("  {} {} <- {}", file.action, file.destination, file.source);
+            }
+            println!("  copied_files: {}", result.copied_files);
+            println!(
+                "  copied_bytes: {} ({})",
+                result.copied_bytes,
+                format_bytes(result.copied_bytes)
+            );
+            println!("  skipped_files: {}", result.skipped_files);
+            println!("  report_root: {}", result.report.root.display());
+            Ok(())
+        }
+
+        Commands::StageProvisioning {
+            autopilot,
+            packages,
+            target_mount,
+            layout,
+            overwrite,
+            report_base,
+            force,
+            token,
+            execute,
+        } => {
+            let params = StageProvisioningParams {
+                autopilot: autopilot.map(Into::into),
+                packages: packages.into_iter().map(Into::into).collect(),
+                target_mount: target_mount.into(),
+                layout: ProvisioningLayout::parse(&layout)?,
+                overwrite: StageOverwrite::parse(&overwrite)?,
+                report_base: report_base.into(),
+                force,
+                confirmation_token: token,
+                dry_run: !execute,
+            };
+            let result = run_stage_provisioning(&params)?;
+            println!("Provisioning staging complete:");
+            println!("  dry_run: {}", result.dry_run);
+            if let Some(profile) = &result.autopilot {
+                println!("  autopilot_tenant: {} ({})", profile.tenant_domain, profile.tenant_id);
+                for key in &profile.unknown_keys {
+                    println!("  autopilot_unknown_key: {}", key);
+                }
+            }
+            for file in &result.files {
+                println!("  {} {} <- {}", file.action, file.destination, file.source);
+            }
+            println!("  report_root: {}", result.report.root.display());
+            Ok(())
+        }
+
+        Commands::MediaInfo { mount, json } => {
+            let manifest = read_media_manifest(&mount)?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&manifest)?);
+                return Ok(());
+            }
+            let Some(manifest) = manifest else {
+                return Err(anyhow!("{} has no phoenix_media.toml", mount));
+            };
+            println!("pack: {} {}", manifest.pack_name, manifest.pack_version);
+            println!("build_date: {}", manifest.build_date);
+            if !manifest.supported_targets.is_empty() {
+                println!("supported_targets: {}", manifest.supported_targets.join(", "));
+            }
+            if let Some(contact) = &manifest.support_contact {
+                println!("support_contact: {}", contact);
+            }
+            Ok(())
+        }
+
+        Commands::MediaStamp { mount, manifest } => {
+            let pack = load_pack_manifest(&manifest)?;
+            let path = write_media_manifest(&mount, &MediaManifest::for_pack(&pack))?;
+            println!("wrote {}", path.display());
+            Ok(())
+        }
+
+        Commands::MediaAudit {
+            mount,
+            against,
+            exclude,
+            key,
+        } => {
+            let result = run_media_audit(&MediaAuditParams {
+                mount: mount.into(),
+                report: against.into(),
+                exclude,
+                signing_key: key,
+            })?;
+            println!("run_id: {}", result.run_id);
+            println!("checked_files: {}", result.checked_files);
+            println!("matching_files: {}", result.matching_files);
+            println!("added: {}", result.added.len());
+            for path in &result.added {
+                println!("  + {}", path);
+            }
+            println!("modified: {}", result.modified.len());
+            for file in &result.modified {
+                println!(
+                    "  ~ {} ({} bytes, expected {})",
+                    file.path, file.actual_bytes, file.expected_bytes
+                );
+            }
+            println!("removed: {}", result.removed.len());
+            for path in &result.removed {
+                println!("  - {}", path);
+            }
+            if result.unchanged() {
+                Ok(())
+            } else {
+                Err(anyhow!("media differs from report {}", result.run_id))
+            }
+        }
+
+        Commands::MacosKextStage {
+            source,
+            target_mount,
+            target_subdir,
+            report_base,
+            force,
+            token,
+            execute,
+            hash_manifest,
+            hash_destination,
+        } => {
+            #[cfg(target_os = "macos")]
+            {
+                let params = MacosKextStageParams {
+                    source_path: source.into(),
+                    target_mount: target_mount.into(),
+                    target_subdir: target_subdir.map(Into::into),
+                    report_base: report_base.into(),
+                    force,
+                    confirmation_token: token,
+                    dry_run: !execute,
+                    hash_manifest,
+                    hash_destination,
+                };
+                let result = run_macos_kext_stage(&params)?;
+                println!("macOS kext staging complete:");
+                println!("  dry_run: {}", result.dry_run);
+                println!("  copied_files: {}", result.copied_files);
+                println!(
+                    "  copied_bytes: {} ({})",
+                    result.copied_bytes,
+                    format_bytes(result.copied_bytes)
+                );
+                println!("  report_root: {}", result.report.root.display());
+                Ok(())
+            }
+            #[cfg(not(target_os = "macos"))]
+            {
+                let _ = (
+                    source, target_mount, target_subdir, report_base, force, token, execute,
+                    hash_manifest,
+                    hash_destination,
+                );
+                Err(anyhow!("macos-only command"))
+            }
+        }
+
+        Commands::WorkflowRun {
+            file,
+            report_base,
+            idempotency_key,
+        } => {
+            let mut definition: WorkflowDefinition = load_workflow_definition(&file)?;
+            if idempotency_key.is_some() {
+                definition.idempotency_key = idempotency_key;
+            }
+            validate_workflow_definition(&definition)?;
+            let result = phoenix_workflow_engine::run_workflow_definition_with_report(
+                &definition,
+                report_base.into(),
+            )?;
+            println!("workflow: {}", definition.name);
+            for step in &result.steps {
+                println!(
+                    "step {}: {} ({} ms)",
+                    step.id, step.action, step.duration_ms
+                );
+                if let Some(root) = &step.report_root {
+                    println!("  report: {}", root.display());
+                }
+                if step.reused {
+                    println!("  reused: true");
+                }
+                for hook in &step.hooks {
+                    println!(
+                        "  hook {:?} {}: {} exit {:?}{}",
+                        hook.phase,
+                        hook.index,
+                        hook.program,
+                        hook.exit_code,
+                        if hook.timed_out { " (timed out)" } else { "" }
+                    );
+                }
+            }
+            println!("workflow_report: {}", result.report.root.display());
+            Ok(())
+        }
+
+        Commands::WorkflowValidate { file, plan_only } => {
+            let definition: WorkflowDefinition = load_workflow_definition(&file)?;
+            if plan_only {
+                let plan = phoenix_workflow_engine::plan_workflow_definition(&definition)?;
+                println!("workflow valid: {}", definition.name);
+                println!("target_os: {}", plan.target_os.unwrap_or("any"));
+            } else {
+                validate_workflow_definition(&definition)?;
+                println!("workflow valid: {}", definition.name);
+            }
+            Ok(())
+        }
+
+        Commands::WorkflowPlan {
+            file,
+            report_base,
+            json,
+        } => {
+            let definition: WorkflowDefinition = load_workflow_definition(&file)?;
+            let result = phoenix_workflow_engine::write_plan_report(
+                &definition,
+                std::path::Path::new(&report_base),
+            )?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&result)?);
+                return Ok(());
+            }
+            println!("workflow: {}", result.plan.name);
+            println!("target_os: {}", result.plan.target_os.unwrap_or("any"));
+            for step in &result.plan.steps {
+                let capabilities: Vec<&str> =
+                    step.capabilities.iter().map(|need| need.name).collect();
+                println!(
+                    "step {}: {}{}{}",
+                    step.id,
+                    step.action,
+                    if step.dry_run { " (dry run)" } else { "" },
+                    if capabilities.is_empty() {
+                        String::new()
+                    } else {
+                        format!(" needs {}", capabilities.join(", "))
+                    }
+                );
+            }
+            match &result.host_problem {
+                Some(problem) => println!("executable_here: false ({})", problem),
+                None => println!("executable_here: true"),
+            }
+            if let Some(estimate) = &result.estimate {
+                println!(
+                    "estimate: {} per stick ({} write rate)",
+                    format_duration_ms(estimate.total_ms),
+                    estimate.throughput_source.as_str()
+                );
+            }
+            println!("report: {}", result.report.root.display());
+            Ok(())
+        }
+
+        Commands::WorkflowEstimate {
+            file,
+            source_sizes,
+            target_size,
+            device_key,
+            write_rate,
+            read_rate,
+            parallel,
+            shift_hours,
+            json,
+        } => {
+            let definition: WorkflowDefinition = load_workflow_definition(&file)?;
+            let mut params = phoenix_workflow_engine::EstimateParams {
+                target_bytes: target_size
+                    .as_deref()
+                    .map(phoenix_partition::parse_size)
+                    .transpose()?,
+                device_key,
+                write_bytes_per_sec: write_rate
+                    .as_deref()
+                    .map(phoenix_partition::parse_size)
+                    .transpose()?,
+                read_bytes_per_sec: read_rate
+                    .as_deref()
+                    .map(phoenix_partition::parse_size)
+                    .transpose()?,
+                parallel,
+                shift_secs: shift_hours
+                    .filter(|hours| *hours > 0.0)
+                    .map(|hours| (hours * 3600.0) as u64),
+                ..Default::default()
+            };
+            for entry in &source_sizes {
+                let (step, size) = entry
+                    .split_once('=')
+                    .ok_or_else(|| anyhow!("--source-size expects step_id=SIZE: {}", entry))?;
+                params
+                    .source_sizes
+                    .insert(step.trim().to_string(), phoenix_partition::parse_size(size)?);
+            }
+            let estimate = phoenix_workflow_engine::estimate_workflow(&definition, &params)?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&estimate)?);
+                return Ok(());
+            }
+            println!("workflow: {}", estimate.workflow);
+            println!(
+                "write_rate: {} ({}{})",
+                display_format().rate(estimate.write_bytes_per_sec),
+                estimate.throughput_source.as_str(),
+                if estimate.devices_sampled > 1 {
+                    format!(", median of {} sticks", estimate.devices_sampled)
+                } else {
+                    String::new()
+                }
+            );
+            println!("read_rate: {}", display_format().rate(estimate.read_bytes_per_sec));
+            for step in &estimate.steps {
+                println!(
+                    "step {}: {} {} ({})",
+                    step.id,
+                    step.action,
+                    format_duration_ms(step.duration_ms),
+                    step.basis
+                );
+            }
+            println!("total: {} per stick", format_duration_ms(estimate.total_ms));
+            println!("parallel: {}", estimate.parallel);
+            println!("sticks_per_hour: {:.1}", estimate.sticks_per_hour);
+            if let Some(count) = estimate.sticks_per_shift {
+                println!("sticks_per_shift: {}", count);
+            }
+            for note in &estimate.notes {
+                println!("note: {}", note);
             }
             Ok(())
         }
 
-        Commands::ReportVerify { path, key } => {
-            let result = phoenix_report::verify_report_bundle(path, key.as_deref())?;
-            println!("verified_entries: {}", result.entries_checked);
-            println!("signature_valid: {:?}", result.signature_valid);
-            if !result.mismatches.is_empty() {
-                println!("mismatches:");
-                for mismatch in &result.mismatches {
-                    println!("  - {}", mismatch);
+        Commands::WorkflowPreflight { file, json } => {
+            let definition: WorkflowDefinition = load_workflow_definition(&file)?;
+            let checklist = phoenix_workflow_engine::validate_workflow_against_host(&definition)?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&checklist)?);
+            } else {
+                println!("workflow: {}", definition.name);
+                for check in &checklist.checks {
+                    println!(
+                        "{} {} ({}): {}",
+                        if check.passed { "pass" } else { "FAIL" },
+                        check.requirement,
+                        check.steps.join(", "),
+                        check.detail
+                    );
                 }
             }
-            if result.ok {
-                Ok(())
+            if !checklist.passed() {
+                let failed = checklist.failures().count();
+                return Err(anyhow!("{} pre-flight requirement(s) not met", failed));
+            }
+            Ok(())
+        }
+
+        Commands::WorkflowList { pack_dir, json } => {
+            let dirs = if pack_dir.is_empty() {
+                phoenix_workflow_engine::pack_dirs()?
             } else {
-                Err(anyhow!("report verification failed"))
+                pack_dir.into_iter().map(std::path::PathBuf::from).collect()
+            };
+            let catalog = phoenix_workflow_engine::list_workflows(&dirs);
+            if json {
+                println!("{}", serde_json::to_string_pretty(&catalog)?);
+                return Ok(());
+            }
+            for entry in &catalog.entries {
+                println!(
+                    "{} ({} {}): {}",
+                    entry.name,
+                    entry.pack,
+                    entry.pack_version,
+                    if entry.runnable() { "ok" } else { "unavailable" }
+                );
+                if let Some(description) = &entry.description {
+                    println!("  description: {}", description);
+                }
+                println!("  path: {}", entry.path.display());
+                if !entry.required_os.is_empty() {
+                    println!("  os: {}", entry.required_os.join(", "));
+                }
+                if !entry.required_capabilities.is_empty() {
+                    println!("  capabilities: {}", entry.required_capabilities.join(", "));
+                }
+                if let Some(error) = &entry.error {
+                    println!("  error: {}", error);
+                }
+            }
+            for problem in &catalog.problems {
+                println!("broken: {} ({})", problem.path.display(), problem.error);
             }
+            println!("workflows: {}", catalog.entries.len());
+            Ok(())
         }
 
-        Commands::ReportExport { path, out } => {
-            let output = phoenix_report::export_report_zip(path, out)?;
-            println!("exported: {}", output.display());
+        Commands::Kiosk {
+            file,
+            report_base,
+            confirm,
+            min_size,
+            max_size,
+            port,
+            poll_ms,
+            max_runs,
+        } => {
+            let definition: WorkflowDefinition = load_workflow_definition(&file)?;
+            validate_workflow_definition(&definition)?;
+            let params = KioskParams {
+                definition,
+                report_base: report_base.into(),
+                policy: KioskPolicy {
+                    confirm: KioskConfirm::parse(&confirm)?,
+                    min_size_bytes: min_size,
+                    max_size_bytes: max_size,
+                    ports: port,
+                },
+                poll_interval: std::time::Duration::from_millis(poll_ms),
+                max_runs,
+            };
+            let result = run_kiosk(&params, &mut CliKiosk)?;
+            let failed = result.runs.iter().filter(|run| run.error.is_some()).count();
+            println!("runs: {}", result.runs.len());
+            println!("failed_runs: {}", failed);
+            println!("session_report: {}", result.report.root.display());
             Ok(())
         }
 
-        Commands::ReportVerifyTree { root, key } => {
-            let result = phoenix_report::verify_report_tree(root, key.as_deref())?;
-            println!("total_reports: {}", result.total_reports);
-            println!("ok_reports: {}", result.ok_reports);
-            if !result.failed_reports.is_empty() {
-                println!("failed_reports:");
-                for report in &result.failed_reports {
-                    println!("  - {}", report);
+        Commands::Agent {
+            server,
+            agent_id,
+            token_secret,
+            report_base,
+            port,
+            poll_ms,
+            max_jobs,
+            client_cert,
+            client_key,
+            server_ca,
+            allow_hook,
+            allow_secret,
+        } => {
+            let params = AgentParams {
+                server,
+                agent_id,
+                token_secret,
+                client_cert: client_cert.map(std::path::PathBuf::from),
+                client_key: client_key.map(std::path::PathBuf::from),
+                server_ca: server_ca.map(std::path::PathBuf::from),
+                report_base: report_base.into(),
+                ports: port,
+                poll_interval: std::time::Duration::from_millis(poll_ms),
+                max_jobs,
+                allow: RemoteAllowList {
+                    hooks: allow_hook,
+                    secrets: allow_secret,
+                },
+            };
+            let result = run_agent(&params, &mut CliAgent)?;
+            let failed = result.jobs.iter().filter(|job| job.error.is_some()).count();
+            println!("jobs: {}", result.jobs.len());
+            println!("failed_jobs: {}", failed);
+            println!("session_report: {}", result.report.root.display());
+            Ok(())
+        }
+
+        Commands::FleetCaInit { dir, name, days } => {
+            let ca = init_fleet_ca(std::path::Path::new(&dir), &name, days)?;
+            print_issued_cert(&ca);
+            Ok(())
+        }
+
+        Commands::FleetCaIssue {
+            dir,
+            agent_id,
+            server_name,
+            days,
+        } => {
+            let (kind, name) = match (agent_id, server_name) {
+                (Some(agent_id), None) => (CertKind::Agent, agent_id),
+                (None, Some(server_name)) => (CertKind::Server, server_name),
+                _ => return Err(anyhow!("give one of --agent-id or --server-name")),
+            };
+            let cert = issue_fleet_cert(std::path::Path::new(&dir), kind, &name, days)?;
+            print_issued_cert(&cert);
+            Ok(())
+        }
+
+        Commands::DuplicateToAll {
+            file,
+            report_base,
+            min_size,
+            never_touch,
+            port,
+            parallel,
+            yes,
+        } => {
+            let definition: WorkflowDefinition = load_workflow_definition(&file)?;
+            validate_workflow_definition(&definition)?;
+            let params = DuplicateParams {
+                definition,
+                report_base: report_base.into(),
+                min_size_bytes: min_size,
+                never_touch_serials: never_touch,
+                ports: port,
+                max_parallel: parallel,
+            };
+            let plan = plan_duplicate_to_all(&params)?;
+            for excluded in &plan.excluded {
+                println!("excluded: {} ({})", excluded.disk_id, excluded.reason);
+            }
+            for target in &plan.targets {
+                println!(
+                    "target: {} {} serial {} port {} ({} bytes)",
+                    target.disk_id,
+                    target.friendly_name,
+                    target.serial.as_deref().unwrap_or("-"),
+                    target.port.as_deref().unwrap_or("-"),
+                    target.size_bytes
+                );
+            }
+            if plan.targets.is_empty() {
+                return Err(anyhow!("no target disks to duplicate to"));
+            }
+            if !yes {
+                let graph = build_device_graph()?;
+                for target in &plan.targets {
+                    println!("{}", destruction_text(&graph, &target.disk_id));
+                }
+                print!("run {} on {} disks? [y/N] ", params.definition.name, plan.targets.len());
+                std::io::Write::flush(&mut std::io::stdout())?;
+                let mut answer = String::new();
+                std::io::stdin().read_line(&mut answer)?;
+                if !answer.trim().eq_ignore_ascii_case("y") {
+                    return Err(anyhow!("not confirmed"));
                 }
             }
-            if result.failed_reports.is_empty() {
-                Ok(())
-            } else {
-                Err(anyhow!("one or more reports failed verification"))
+            let result = run_duplicate_to_all(&params, &plan)?;
+            for run in &result.runs {
+                match &run.error {
+                    None => println!(
+                        "completed: {} ({})",
+                        run.disk_id,
+                        format_duration_ms(run.duration_ms as u64)
+                    ),
+                    Some(err) => println!("failed: {}: {}", run.disk_id, err),
+                }
             }
+            println!("report: {}", result.report.root.display());
+            if result.failed() > 0 {
+                return Err(anyhow!("{} of {} targets failed", result.failed(), result.runs.len()));
+            }
+            Ok(())
         }
-        Commands::HashDisk {
+
+        Commands::CloneDevice {
+            source,
+            target,
+            archive,
+            archive_in_report,
+            report_base,
+            force,
+            token,
+            acknowledge_target_size,
+            acknowledge_device_wear,
+            confirm_overwrite,
+            execute,
+            chunk_size,
+            flush_every,
+        } => {
+            let params = CloneDeviceParams {
+                source_disk_id: source,
+                target_disk_id: target,
+                archive: archive.map(Into::into),
+                archive_in_report,
+                report_base: report_base.into(),
+                force,
+                confirmation_token: token,
+                acknowledge_target_size,
+                acknowledge_device_wear,
+                confirm_overwrite,
+                dry_run: !execute,
+                chunk_size,
+                flush_every_bytes: flush_every
+                    .as_deref()
+                    .map(phoenix_partition::parse_size)
+                    .transpose()?,
+            };
+            let result = run_clone_device(&params)?;
+            println!("Device clone complete:");
+            println!("  source: {}", result.source_disk_id);
+            println!("  target: {}", result.target_disk_id);
+            println!("  dry_run: {}", result.dry_run);
+            println!(
+                "  bytes_copied: {} ({})",
+                result.bytes_copied,
+                format_bytes(result.bytes_copied)
+            );
+            println!("  sha256: {}", result.sha256);
+            if let Some(comparison) = &result.comparison {
+                println!(
+                    "  compare: {} bytes, {} mismatched chunk(s)",
+                    comparison.bytes_compared, comparison.mismatched_chunks
+                );
+            }
+            if let Some(archive) = &result.archive {
+                println!(
+                    "  archive: {} ({}, sha256 {})",
+                    archive.path.display(),
+                    format_bytes(archive.bytes),
+                    archive.sha256
+                );
+            }
+            println!(
+                "  throughput_bytes_per_sec: {} ({})",
+                result.throughput_bytes_per_sec,
+                display_format().rate(result.throughput_bytes_per_sec)
+            );
+            println!("  report_root: {}", result.report.root.display());
+            Ok(())
+        }
+
+        Commands::Restore {
+            report,
+            target,
+            allow_unsigned,
+            report_base,
+            force,
+            token,
+            acknowledge_target_size,
+            acknowledge_device_wear,
+            confirm_overwrite,
+            execute,
+            chunk_size,
+            flush_every,
+        } => {
+            let params = RestoreReportParams {
+                report: report.into(),
+                target_disk_id: target,
+                report_base: report_base.into(),
+                force,
+                confirmation_token: token,
+                acknowledge_target_size,
+                acknowledge_device_wear,
+                confirm_overwrite,
+                allow_unsigned,
+                dry_run: !execute,
+                chunk_size,
+                flush_every_bytes: flush_every
+                    .as_deref()
+                    .map(phoenix_partition::parse_size)
+                    .transpose()?,
+            };
+            let result = run_restore_report(&params)?;
+            println!("Restore from report complete:");
+            println!("  source_report: {}", result.source_report.display());
+            if let Some(run_id) = &result.source_run_id {
+                println!("  source_run_id: {}", run_id);
+            }
+            println!(
+                "  signature: {}",
+                match result.signature_valid {
+                    Some(true) => "valid",
+                    Some(false) => "invalid",
+                    None => "unsigned",
+                }
+            );
+            println!("  entries_checked: {}", result.entries_checked);
+            println!(
+                "  image: {} ({}, {})",
+                result.image.name,
+                result.image.compression,
+                format_bytes(result.image.image_bytes)
+            );
+            println!("  target: {}", result.target_disk_id);
+            println!("  dry_run: {}", result.dry_run);
+            println!(
+                "  bytes_written: {} ({})",
+                result.bytes_written,
+                format_bytes(result.bytes_written)
+            );
+            println!("  sha256: {}", result.sha256);
+            if let Some(ok) = result.verify_ok {
+                println!("  verify_ok: {}", ok);
+            }
+            println!(
+                "  throughput_bytes_per_sec: {} ({})",
+                result.throughput_bytes_per_sec,
+                display_format().rate(result.throughput_bytes_per_sec)
+            );
+            println!("  report_root: {}", result.report.root.display());
+            Ok(())
+        }
+
+        Commands::DiskHashReport {
             disk,
-            size_bytes,
             chunk_size,
             max_chunks,
-            progress,
+            report_base,
+            partition,
+            compare_baseline,
+            save_baseline,
         } => {
-            #[cfg(windows)]
-            {
-                let hashes = if progress {
-                    let mut observer = CliProgress::new();
-                    phoenix_imaging::hash_disk_readonly_physicaldrive_with_progress(
-                        &disk,
-                        size_bytes,
-                        chunk_size,
-                        max_chunks,
-                        &mut observer,
-                    )?
-                } else {
-                    phoenix_imaging::hash_disk_readonly_physicaldrive(
-                        &disk,
-                        size_bytes,
-                        chunk_size,
-                        max_chunks,
-                    )?
-                };
-                for (index, hash) in hashes {
-                    println!("chunk {}: {}", index, hash);
+            let params = DiskHashReportParams {
+                disk_id: disk,
+                chunk_size,
+                max_chunks,
+                report_base: report_base.into(),
+                partition_id: partition,
+                compare_baseline,
+                save_baseline,
+            };
+            let result = run_disk_hash_report(&params)?;
+            println!("Disk hash report:");
+            println!("  disk_id: {}", result.disk_id);
+            if let Some(partition_id) = &result.partition_id {
+                println!("  partition_id: {}", partition_id);
+            }
+            println!("  chunk_count: {}", result.chunk_count);
+            println!("  chunk_size: {}", result.chunk_size);
+            println!(
+                "  throughput_bytes_per_sec: {} ({})",
+                result.throughput_bytes_per_sec,
+                display_format().rate(result.throughput_bytes_per_sec)
+            );
+            println!("  report_root: {}", result.report.root.display());
+            println!("  manifest: {}", result.report.manifest_path.display());
+            if let Some(sig) = result.report.signature_path.as_ref() {
+                println!("  signature: {}", sig.display());
+            }
+            if let Some(drift) = &result.drift {
+                println!("  baseline: {}", drift.baseline);
+                println!("  drifted: {}", drift.drifted);
+                for range in &drift.changed_ranges {
+                    println!("    changed: offset={} length={}", range.offset, range.length);
                 }
-                Ok(())
+                if drift.drifted {
+                    return Err(anyhow!(
+                        "disk drifted from baseline {}: {} bytes changed",
+                        drift.baseline,
+                        drift.changed_bytes
+                    ));
+                }
+            }
+            Ok(())
+        }
+
+        Commands::BadBlockScan {
+            disk,
+            mode,
+            chunk_size,
+            sector_size,
+            max_bad,
+            report_base,
+            force,
+            token,
+            acknowledge_target_size,
+            acknowledge_device_wear,
+            confirm_overwrite,
+            execute,
+        } => {
+            let params = BadBlockScanParams {
+                disk_id: disk,
+                mode: parse_scan_mode(&mode)?,
+                chunk_size,
+                sector_size: sector_size.as_deref().map(phoenix_partition::parse_size).transpose()?,
+                max_bad_bytes: max_bad.as_deref().map(phoenix_partition::parse_size).transpose()?,
+                report_base: report_base.into(),
+                force,
+                confirmation_token: token,
+                acknowledge_target_size,
+                acknowledge_device_wear,
+                confirm_overwrite,
+                dry_run: !execute,
+            };
+            let result = run_bad_block_scan(&params)?;
+            println!("Bad block scan:");
+            println!("  disk_id: {}", result.disk_id);
+            println!("  mode: {}", result.mode.as_str());
+            println!("  dry_run: {}", result.dry_run);
+            println!(
+                "  scanned_bytes: {} ({})",
+                result.scan.scanned_bytes,
+                format_bytes(result.scan.scanned_bytes)
+            );
+            println!(
+                "  bad_bytes: {} ({})",
+                result.scan.bad_bytes(),
+                format_bytes(result.scan.bad_bytes())
+            );
+            for range in &result.scan.bad_ranges {
+                println!(
+                    "    {}: offset={} length={}",
+                    range.kind.as_str(),
+                    range.offset,
+                    range.length
+                );
+            }
+            if result.scan.stopped_early {
+                println!("  stopped_early: true");
+            }
+            println!("  report_root: {}", result.report.root.display());
+            if !result.passed() {
+                return Err(anyhow!("{} has bad sectors", result.disk_id));
+            }
+            Ok(())
+        }
+
+        Commands::ComboStick {
+            disk,
+            windows_source,
+            rescue_source,
+            bootloader,
+            windows_size,
+            rescue_size,
+            payload_pack,
+            payloads,
+            tools_size,
+            data_label,
+            data_encryption,
+            data_key_secret,
+            menu_title,
+            menu_timeout,
+            report_base,
+            force,
+            token,
+            acknowledge_target_size,
+            acknowledge_device_wear,
+            confirm_overwrite,
+            execute,
+        } => {
+            let params = ComboStickParams {
+                disk_id: disk,
+                windows_source: windows_source.into(),
+                rescue_source: rescue_source.into(),
+                bootloader_source: bootloader.into(),
+                windows_size: windows_size.as_deref().map(phoenix_partition::parse_size).transpose()?,
+                rescue_size: rescue_size.as_deref().map(phoenix_partition::parse_size).transpose()?,
+                payload_pack: payload_pack.map(Into::into),
+                payloads,
+                tools_size: tools_size.as_deref().map(phoenix_partition::parse_size).transpose()?,
+                data_label,
+                data_encryption: data_encryption.as_deref().map(parse_data_encryption).transpose()?,
+                data_key_secret,
+                menu_title,
+                menu_timeout,
+                report_base: report_base.into(),
+                force,
+                confirmation_token: token,
+                acknowledge_target_size,
+                acknowledge_device_wear,
+                confirm_overwrite,
+                dry_run: !execute,
+            };
+            let result = run_combo_stick(&params)?;
+            println!("Combo stick:");
+            println!("  disk_id: {}", result.disk_id);
+            println!("  dry_run: {}", result.dry_run);
+            for partition in &result.partitions {
+                println!(
+                    "  partition {}: {} {} {} ({} files, {})",
+                    partition.number,
+                    partition.label,
+                    partition.filesystem,
+                    format_bytes(partition.size_bytes),
+                    partition.files,
+                    format_bytes(partition.bytes)
+                );
+            }
+            for finding in &result.boot_lint {
+                println!("  boot_lint: {}: {}", finding.entry, finding.problem);
+            }
+            println!("  report_root: {}", result.report.root.display());
+            if !result.boot_lint.is_empty() {
+                return Err(anyhow!("boot lint found problems on {}", result.disk_id));
+            }
+            Ok(())
+        }
+
+        Commands::AbStick {
+            disk,
+            image_source,
+            bootloader,
+            slot_size,
+            version,
+            menu_title,
+            menu_timeout,
+            report_base,
+            force,
+            token,
+            acknowledge_target_size,
+            acknowledge_device_wear,
+            confirm_overwrite,
+            execute,
+        } => {
+            let params = AbStickParams {
+                disk_id: disk,
+                image_source: image_source.into(),
+                bootloader_source: bootloader.into(),
+                slot_size: slot_size.as_deref().map(phoenix_partition::parse_size).transpose()?,
+                version,
+                menu_title,
+                menu_timeout,
+                report_base: report_base.into(),
+                force,
+                confirmation_token: token,
+                acknowledge_target_size,
+                acknowledge_device_wear,
+                confirm_overwrite,
+                dry_run: !execute,
+            };
+            let result = run_ab_stick(&params)?;
+            println!("A/B stick:");
+            println!("  disk_id: {}", result.disk_id);
+            println!("  dry_run: {}", result.dry_run);
+            for partition in &result.partitions {
+                println!(
+                    "  partition {}: {} {} ({} files, {})",
+                    partition.number,
+                    partition.label,
+                    format_bytes(partition.size_bytes),
+                    partition.files,
+                    format_bytes(partition.bytes)
+                );
+            }
+            println!("  active_slot: {}", result.slots.active.as_str());
+            for finding in &result.boot_lint {
+                println!("  boot_lint: {}: {}", finding.entry, finding.problem);
             }
-            #[cfg(not(windows))]
-            {
-                Err(anyhow!("Windows-first in M0"))
+            println!("  report_root: {}", result.report.root.display());
+            if !result.boot_lint.is_empty() {
+                return Err(anyhow!("boot lint found problems on {}", result.disk_id));
             }
+            Ok(())
         }
 
-        Commands::WindowsInstallerUsb {
+        Commands::AbUpdate {
             disk,
-            source,
-            mount,
+            image_source,
+            version,
+            rollback,
             report_base,
             force,
             token,
             execute,
-            repartition,
-            format,
-            fs,
-            label,
-            drivers,
-            drivers_target,
-            hash_manifest,
         } => {
-            #[cfg(windows)]
-            {
-                let filesystem = parse_filesystem(&fs)
-                    .ok_or_else(|| anyhow!("unsupported filesystem: {}", fs))?;
-                let params = WindowsInstallerUsbParams {
-                    target_disk_id: disk,
-                    source_path: source.into(),
-                    target_mount: mount.map(Into::into),
-                    report_base: report_base.into(),
-                    force,
-                    confirmation_token: token,
-                    dry_run: !execute,
-                    repartition,
-                    format,
-                    filesystem,
-                    label,
-                    driver_source: drivers.map(Into::into),
-                    driver_target: drivers_target.map(Into::into),
-                    hash_manifest,
-                };
-                let result = run_windows_installer_usb(&params)?;
-                println!("Workflow complete:");
-                println!("  dry_run: {}", result.dry_run);
-                println!("  target_mount: {}", result.target_mount.display());
-                println!("  copied_files: {}", result.copied_files);
-                println!("  copied_bytes: {}", result.copied_bytes);
-                println!("  driver_files: {}", result.driver_files);
-                println!("  driver_bytes: {}", result.driver_bytes);
-                println!("  report_root: {}", result.report.root.display());
-                println!("  logs: {}", result.report.logs_path.display());
-                println!("  manifest: {}", result.report.manifest_path.display());
-                if let Some(sig) = result.report.signature_path.as_ref() {
-                    println!("  signature: {}", sig.display());
-                }
-                Ok(())
-            }
-            #[cfg(not(windows))]
-            {
-                Err(anyhow!("Windows-first in M0"))
-            }
-        }
-
-        Commands::WimInfo { path } => {
-            #[cfg(windows)]
-            {
-                let (image_path, _prepared) = resolve_windows_image(path)?;
-                let images = wim_list_images(image_path)?;
-                for image in images {
-                    println!("Image {}", image.index);
-                    if let Some(name) = image.name {
-                        println!("  name: {}", name);
-                    }
-                    if let Some(desc) = image.description {
-                        println!("  description: {}", desc);
-                    }
-                    if let Some(bytes) = image.total_bytes {
-                        println!("  total_bytes: {}", bytes);
-                    }
-                }
-                Ok(())
-            }
-            #[cfg(not(windows))]
-            {
-                Err(anyhow!("Windows-first in M0"))
+            let params = AbUpdateParams {
+                disk_id: disk,
+                image_source: image_source.map(Into::into),
+                version,
+                rollback,
+                report_base: report_base.into(),
+                force,
+                confirmation_token: token,
+                dry_run: !execute,
+            };
+            let result = run_ab_update(&params)?;
+            println!("A/B update:");
+            println!("  disk_id: {}", result.disk_id);
+            println!("  previous_slot: {}", result.previous.as_str());
+            println!("  active_slot: {}", result.active.as_str());
+            if let Some(version) = &result.slots.image(result.active).version {
+                println!("  version: {}", version);
             }
-        }
-
-        Commands::WimApply { path, index, target } => {
-            #[cfg(windows)]
-            {
-                let (image_path, _prepared) = resolve_windows_image(path)?;
-                wim_apply_image(image_path, index, target)?;
-                println!("WIM apply complete.");
-                Ok(())
+            println!("  rollback: {}", result.rollback);
+            println!("  dry_run: {}", result.dry_run);
+            for finding in &result.boot_lint {
+                println!("  boot_lint: {}: {}", finding.entry, finding.problem);
             }
-            #[cfg(not(windows))]
-            {
-                Err(anyhow!("Windows-first in M0"))
+            println!("  report_root: {}", result.report.root.display());
+            if !result.boot_lint.is_empty() {
+                return Err(anyhow!("boot lint found problems on {}", result.disk_id));
             }
+            Ok(())
         }
 
-        Commands::WindowsApplyImage {
-            source,
-            index,
-            target,
+        Commands::ResizePartition {
+            disk,
+            partition,
+            mode,
+            margin,
             report_base,
             force,
             token,
             execute,
-            verify,
         } => {
-            #[cfg(windows)]
-            {
-                let params = WindowsApplyImageParams {
-                    source_path: source.into(),
-                    image_index: index,
-                    target_dir: target.into(),
-                    report_base: report_base.into(),
-                    force,
-                    confirmation_token: token,
-                    dry_run: !execute,
-                    verify,
-                };
-                let result = run_windows_apply_image(&params)?;
-                println!("Apply complete:");
-                println!("  dry_run: {}", result.dry_run);
-                println!("  target_dir: {}", result.target_dir.display());
-                println!("  file_count: {}", result.file_count);
-                println!("  total_bytes: {}", result.total_bytes);
-                println!("  report_root: {}", result.report.root.display());
-                println!("  manifest: {}", result.report.manifest_path.display());
-                if let Some(sig) = result.report.signature_path.as_ref() {
-                    println!("  signature: {}", sig.display());
-                }
-                Ok(())
+            let params = ResizePartitionParams {
+                disk_id: disk,
+                partition,
+                mode: parse_resize_mode(&mode)?,
+                margin: margin.as_deref().map(phoenix_partition::parse_size).transpose()?,
+                report_base: report_base.into(),
+                force,
+                confirmation_token: token,
+                dry_run: !execute,
+            };
+            let result = run_resize_partition(&params)?;
+            println!("Partition resize:");
+            println!("  disk_id: {}", result.disk_id);
+            println!("  partition: {} ({})", result.partition, result.label);
+            println!("  mode: {}", result.mode.as_str());
+            println!("  old_size: {}", format_bytes(result.old_bytes));
+            println!("  new_size: {}", format_bytes(result.new_bytes));
+            println!("  used: {}", format_bytes(result.used_bytes));
+            println!("  image_bytes: {} ({})", result.image_bytes, format_bytes(result.image_bytes));
+            println!("  dry_run: {}", result.dry_run);
+            println!("  report_root: {}", result.report.root.display());
+            Ok(())
+        }
+
+        Commands::ValidateSource {
+            source,
+            os,
+            filesystem,
+            report_base,
+            include,
+            exclude,
+            target_model,
+        } => {
+            let params = ValidateSourceParams {
+                source_path: source.into(),
+                os,
+                filesystem: phoenix_host_windows::format::parse_filesystem(&filesystem)
+                    .ok_or_else(|| anyhow!("unsupported filesystem: {}", filesystem))?,
+                source_filter: SourceFilter::new(include, exclude)?,
+                target_model,
+                report_base: report_base.into(),
+            };
+            let result = run_validate_source(&params)?;
+            println!("Source validation:");
+            println!("  os: {}", result.os);
+            println!("  distro: {}", result.distro.as_deref().unwrap_or("unknown"));
+            println!("  file_count: {}", result.file_count);
+            println!(
+                "  total_bytes: {} ({})",
+                result.total_bytes,
+                format_bytes(result.total_bytes)
+            );
+            println!(
+                "  max_file_bytes: {} ({})",
+                result.max_file_bytes,
+                format_bytes(result.max_file_bytes)
+            );
+            for problem in &result.problems {
+                println!("  problem: {}", problem);
             }
-            #[cfg(not(windows))]
-            {
-                Err(anyhow!("Windows-first in M0"))
+            println!("  report_root: {}", result.report.root.display());
+            if result.problems.is_empty() {
+                Ok(())
+            } else {
+                Err(anyhow!("source has {} problem(s)", result.problems.len()))
             }
         }
 
-        Commands::LinuxInstallerUsb {
+        Commands::SlimWindowsMedia {
             source,
-            target_mount,
+            keep_list,
+            languages,
+            editions,
             report_base,
-            force,
-            token,
             execute,
-            hash_manifest,
-            format_device,
-            format_size_bytes,
-            format_label,
         } => {
-            #[cfg(target_os = "linux")]
-            {
-                let params = UnixInstallerUsbParams {
-                    source_path: source.into(),
-                    target_mount: target_mount.into(),
-                    report_base: report_base.into(),
-                    force,
-                    confirmation_token: token,
-                    dry_run: !execute,
-                    hash_manifest,
-                    format_device: format_device.map(Into::into),
-                    format_size_bytes,
-                    format_label,
-                };
-                let result = run_unix_installer_usb(&params)?;
-                println!("Linux USB staging complete:");
-                println!("  dry_run: {}", result.dry_run);
-                println!("  target_mount: {}", result.target_mount.display());
-                println!("  copied_files: {}", result.copied_files);
-                println!("  copied_bytes: {}", result.copied_bytes);
-                println!("  report_root: {}", result.report.root.display());
-                Ok(())
+            let mut keep = match keep_list {
+                Some(path) => MediaKeepList::load(std::path::Path::new(&path))?,
+                None => MediaKeepList::default(),
+            };
+            keep.languages.extend(languages);
+            keep.editions.extend(editions);
+            let params = SlimWindowsMediaParams {
+                source_path: source.into(),
+                keep,
+                report_base: report_base.into(),
+                dry_run: !execute,
+            };
+            let result = run_slim_windows_media(&params)?;
+            println!("Media slimming complete:");
+            println!("  dry_run: {}", result.dry_run);
+            for language in &result.removed_languages {
+                println!("  removed_language: {}", language);
             }
-            #[cfg(not(target_os = "linux"))]
-            {
-                Err(anyhow!("linux-only command"))
+            for edition in &result.removed_editions {
+                println!("  removed_edition: {}", edition);
             }
+            println!(
+                "  freed_bytes: {} ({})",
+                result.freed_bytes,
+                format_bytes(result.freed_bytes)
+            );
+            println!("  report_root: {}", result.report.root.display());
+            Ok(())
         }
 
-        Commands::MacosInstallerUsb {
+        Commands::MergeWindowsLanguages {
             source,
-            target_mount,
+            language_sources,
+            languages,
             report_base,
-            force,
-            token,
             execute,
-            hash_manifest,
-            format_device,
-            format_size_bytes,
-            format_label,
         } => {
-            #[cfg(target_os = "macos")]
-            {
-                let params = UnixInstallerUsbParams {
-                    source_path: source.into(),
-                    target_mount: target_mount.into(),
-                    report_base: report_base.into(),
-                    force,
-                    confirmation_token: token,
-                    dry_run: !execute,
-                    hash_manifest,
-                    format_device: format_device.map(Into::into),
-                    format_size_bytes,
-                    format_label,
-                };
-                let result = run_unix_installer_usb(&params)?;
-                println!("macOS USB staging complete:");
-                println!("  dry_run: {}", result.dry_run);
-                println!("  target_mount: {}", result.target_mount.display());
-                println!("  copied_files: {}", result.copied_files);
-                println!("  copied_bytes: {}", result.copied_bytes);
-                println!("  report_root: {}", result.report.root.display());
+            let params = MergeWindowsLanguagesParams {
+                source_path: source.into(),
+                language_sources: language_sources.into_iter().map(Into::into).collect(),
+                languages,
+                report_base: report_base.into(),
+                dry_run: !execute,
+            };
+            let result = run_merge_windows_languages(&params)?;
+            println!("Language merge complete:");
+            println!("  dry_run: {}", result.dry_run);
+            for language in &result.added_languages {
+                println!("  added_language: {}", language);
+            }
+            println!("  copied_files: {}", result.copied_files);
+            println!(
+                "  copied_bytes: {} ({})",
+                result.copied_bytes,
+                format_bytes(result.copied_bytes)
+            );
+            println!("  report_root: {}", result.report.root.display());
+            Ok(())
+        }
+
+        Commands::Fetch { source, output_dir } => {
+            let result = phoenix_fetch::fetch_image(&source, std::path::Path::new(&output_dir))?;
+            println!("Fetch complete:");
+            println!("  path: {}", result.path.display());
+            println!(
+                "  total_bytes: {} ({})",
+                result.total_bytes,
+                format_bytes(result.total_bytes)
+            );
+            println!("  pieces: {}", result.pieces);
+            println!("  pieces_reused: {}", result.pieces_reused);
+            println!("  pieces_refetched: {}", result.pieces_refetched);
+            Ok(())
+        }
+
+        Commands::HashmapCompare { left, right } => {
+            let left = phoenix_hashmap::read_hashmap(&left)?;
+            let right = phoenix_hashmap::read_hashmap(&right)?;
+            let result = left.compare(&right)?;
+            println!("matching: {}", result.matching.len());
+            println!("differing: {}", result.differing.len());
+            println!("only_left: {}", result.only_left.len());
+            println!("only_right: {}", result.only_right.len());
+            for index in &result.differing {
+                println!("  - chunk {}", index);
+            }
+            if result.identical() {
                 Ok(())
+            } else {
+                Err(anyhow!("hash maps differ"))
+            }
+        }
+
+        Commands::PartitionVerify {
+            device,
+            size_bytes,
+            sector_size,
+        } => {
+            let check =
+                phoenix_partition::verify_device_partition_tables(&device, size_bytes, sector_size)?;
+            if let Some(header) = &check.primary {
+                println!("disk_guid: {}", header.disk_guid);
+                println!(
+                    "usable_lba: {}-{}",
+                    header.first_usable_lba, header.last_usable_lba
+                );
+            }
+            println!("partitions: {}", check.partition_count);
+            println!("mbr_entries: {}", check.mbr_entries.len());
+            for warning in &check.warnings {
+                println!("warning: {}", warning);
+            }
+            println!("ok: {}", check.ok);
+            for issue in &check.issues {
+                println!("  - {}", issue);
             }
-            #[cfg(not(target_os = "macos"))]
-            {
-                Err(anyhow!("macos-only command"))
+            if check.ok {
+                Ok(())
+            } else {
+                Err(anyhow!("partition table verification failed"))
             }
         }
 
-        Commands::LinuxWriteImage {
-            source,
+        Commands::ExfatVerify {
             device,
-            report_base,
-            force,
-            token,
-            execute,
-            verify,
-            chunk_size,
+            offset_bytes,
+            size_bytes,
         } => {
-            #[cfg(target_os = "linux")]
-            {
-                let params = phoenix_workflow_engine::UnixWriteImageParams {
-                    source_image: source.into(),
-                    target_device: device.into(),
-                    report_base: report_base.into(),
-                    force,
-                    confirmation_token: token,
-                    dry_run: !execute,
-                    verify,
-                    chunk_size,
-                };
-                let result = phoenix_workflow_engine::run_unix_write_image(&params)?;
-                println!("Linux image write complete:");
-                println!("  dry_run: {}", result.dry_run);
-                println!("  bytes_written: {}", result.bytes_written);
-                println!("  sha256: {}", result.sha256);
-                println!("  verify_ok: {:?}", result.verify_ok);
-                println!("  report_root: {}", result.report.root.display());
-                Ok(())
+            let check = phoenix_fs_exfat::verify_exfat_device(&device, offset_bytes, size_bytes)?;
+            if let Some(boot) = &check.boot_sector {
+                println!(
+                    "volume_bytes: {} ({})",
+                    boot.volume_bytes(),
+                    format_bytes(boot.volume_bytes())
+                );
+                println!(
+                    "cluster_bytes: {} ({})",
+                    boot.cluster_bytes(),
+                    format_bytes(boot.cluster_bytes())
+                );
+                println!("cluster_count: {}", boot.cluster_count);
+                println!("serial: {:08X}", boot.volume_serial_number);
             }
-            #[cfg(not(target_os = "linux"))]
-            {
-                Err(anyhow!("linux-only command"))
+            if let Some(checksum) = check.checksum {
+                println!("checksum: {:08X}", checksum);
+            }
+            for warning in &check.warnings {
+                println!("warning: {}", warning);
+            }
+            println!("ok: {}", check.ok);
+            for issue in &check.issues {
+                println!("  - {}", issue);
+            }
+            if check.ok {
+                Ok(())
+            } else {
+                Err(anyhow!("exFAT verification failed"))
             }
         }
 
-        Commands::MacosWriteImage {
-            source,
+        Commands::Fat32Verify {
             device,
-            report_base,
-            force,
-            token,
-            execute,
-            verify,
-            chunk_size,
+            offset_bytes,
+            size_bytes,
         } => {
-            #[cfg(target_os = "macos")]
-            {
-                let params = phoenix_workflow_engine::UnixWriteImageParams {
-                    source_image: source.into(),
-                    target_device: device.into(),
-                    report_base: report_base.into(),
-                    force,
-                    confirmation_token: token,
-                    dry_run: !execute,
-                    verify,
-                    chunk_size,
-                };
-                let result = phoenix_workflow_engine::run_unix_write_image(&params)?;
-                println!("macOS image write complete:");
-                println!("  dry_run: {}", result.dry_run);
-                println!("  bytes_written: {}", result.bytes_written);
-                println!("  sha256: {}", result.sha256);
-                println!("  verify_ok: {:?}", result.verify_ok);
-                println!("  report_root: {}", result.report.root.display());
+            let check = phoenix_fs_fat32::verify_fat32_device(&device, offset_bytes, size_bytes)?;
+            if let Some(boot) = &check.boot {
+                println!(
+                    "volume_bytes: {} ({})",
+                    boot.volume_bytes(),
+                    format_bytes(boot.volume_bytes())
+                );
+                println!(
+                    "cluster_bytes: {} ({})",
+                    boot.cluster_bytes(),
+                    format_bytes(boot.cluster_bytes())
+                );
+                println!("cluster_count: {}", boot.cluster_count());
+                match check.reference_sectors_per_fat {
+                    Some(reference) => {
+                        println!("sectors_per_fat: {} (reference {})", boot.sectors_per_fat, reference)
+                    }
+                    None => println!("sectors_per_fat: {}", boot.sectors_per_fat),
+                }
+                println!("serial: {:08X}", boot.volume_id);
+            }
+            for warning in &check.warnings {
+                println!("warning: {}", warning);
+            }
+            println!("ok: {}", check.ok);
+            for issue in &check.issues {
+                println!("  - {}", issue);
+            }
+            if check.ok {
                 Ok(())
+            } else {
+                Err(anyhow!("FAT32 verification failed"))
             }
-            #[cfg(not(target_os = "macos"))]
-            {
-                Err(anyhow!("macos-only command"))
+        }
+
+        Commands::TrimImage { source, output } => {
+            let result =
+                phoenix_imaging::trim_image(std::path::Path::new(&source), std::path::Path::new(&output))?;
+            println!("table: {}", result.table);
+            for partition in &result.partitions {
+                println!(
+                    "partition {}: {} of {} kept ({})",
+                    partition.number,
+                    format_bytes(partition.used_bytes),
+                    format_bytes(partition.length),
+                    partition.filesystem.as_deref().unwrap_or("kept whole")
+                );
+            }
+            println!(
+                "source_bytes: {} ({})",
+                result.source_bytes,
+                format_bytes(result.source_bytes)
+            );
+            println!(
+                "image_bytes: {} ({})",
+                result.image_bytes,
+                format_bytes(result.image_bytes)
+            );
+            println!("data_bytes: {} ({})", result.data_bytes, format_bytes(result.data_bytes));
+            println!("sha256: {}", result.sha256);
+            for warning in &result.warnings {
+                println!("warning: {}", warning);
             }
+            Ok(())
         }
 
-        Commands::LinuxBootPrep {
+        Commands::ConvertImage {
             source,
-            target_mount,
-            report_base,
-            force,
-            token,
-            execute,
-            hash_manifest,
+            output,
+            format,
         } => {
-            #[cfg(target_os = "linux")]
-            {
-                let params = phoenix_workflow_engine::UnixBootPrepParams {
-                    source_path: source.into(),
-                    target_mount: target_mount.into(),
-                    report_base: report_base.into(),
-                    force,
-                    confirmation_token: token,
-                    dry_run: !execute,
-                    hash_manifest,
-                };
-                let result = phoenix_workflow_engine::run_unix_boot_prep(&params)?;
-                println!("Linux boot prep complete:");
-                println!("  dry_run: {}", result.dry_run);
-                println!("  copied_files: {}", result.copied_files);
-                println!("  copied_bytes: {}", result.copied_bytes);
-                println!("  report_root: {}", result.report.root.display());
-                Ok(())
+            let output = std::path::Path::new(&output);
+            let format = match format {
+                Some(format) => phoenix_imaging::parse_image_format(&format)?,
+                None => phoenix_imaging::ImageFormat::from_extension(output),
+            };
+            let result = phoenix_imaging::convert_image(std::path::Path::new(&source), output, format)?;
+            println!("{} -> {}", result.source_format.as_str(), result.format.as_str());
+            println!("disk_bytes: {} ({})", result.disk_bytes, format_bytes(result.disk_bytes));
+            println!("data_bytes: {} ({})", result.data_bytes, format_bytes(result.data_bytes));
+            println!("sha256: {}", result.sha256);
+            Ok(())
+        }
+
+        Commands::VerifyPolicy => {
+            let path = phoenix_workflow_engine::verify_policy_path()?;
+            println!("path: {}", path.display());
+            match phoenix_workflow_engine::verify_policy()? {
+                Some(policy) => println!("{}", serde_json::to_string_pretty(&policy)?),
+                None => println!("no verification policy; workflows verify what their steps ask for"),
             }
-            #[cfg(not(target_os = "linux"))]
-            {
-                Err(anyhow!("linux-only command"))
+            Ok(())
+        }
+
+        Commands::IoStatus { json } => {
+            let status = phoenix_workflow_engine::io_status()?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&status)?);
+                return Ok(());
+            }
+            println!("limits: {}", phoenix_workflow_engine::io_limits_path()?.display());
+            match status.limits.max_concurrent_writes {
+                Some(max) => println!("max_concurrent_writes: {}", max),
+                None => println!("max_concurrent_writes: unlimited"),
+            }
+            match status.limits.max_write_bytes_per_sec {
+                Some(rate) => println!(
+                    "max_write_bytes_per_sec: {} ({})",
+                    rate,
+                    display_format().rate(rate)
+                ),
+                None => println!("max_write_bytes_per_sec: unlimited"),
+            }
+            println!("writes: {}", status.writes.len());
+            for write in &status.writes {
+                println!(
+                    "  slot {} {} {} -> {} ({}, pid {}, since {})",
+                    write.slot.unwrap_or_default(),
+                    write.phase.as_str(),
+                    write.source,
+                    write.target_disk,
+                    write.workflow,
+                    write.pid,
+                    write.since_utc
+                );
+            }
+            println!("queued: {}", status.queued.len());
+            for queued in &status.queued {
+                println!(
+                    "  {} -> {} ({}, pid {}, since {})",
+                    queued.source, queued.target_disk, queued.workflow, queued.pid, queued.since_utc
+                );
             }
+            Ok(())
         }
 
-        Commands::MacosBootPrep {
-            source,
-            target_mount,
-            report_base,
-            force,
-            token,
-            execute,
-            hash_manifest,
+        Commands::NotifyTest {
+            config,
+            failed,
+            report,
         } => {
-            #[cfg(target_os = "macos")]
-            {
-                let params = phoenix_workflow_engine::UnixBootPrepParams {
-                    source_path: source.into(),
-                    target_mount: target_mount.into(),
-                    report_base: report_base.into(),
-                    force,
-                    confirmation_token: token,
-                    dry_run: !execute,
-                    hash_manifest,
-                };
-                let result = phoenix_workflow_engine::run_unix_boot_prep(&params)?;
-                println!("macOS boot prep complete:");
-                println!("  dry_run: {}", result.dry_run);
-                println!("  copied_files: {}", result.copied_files);
-                println!("  copied_bytes: {}", result.copied_bytes);
-                println!("  report_root: {}", result.report.root.display());
-                Ok(())
+            let config = match config {
+                Some(path) => phoenix_notify::NotifyConfig::load(std::path::Path::new(&path))?,
+                None => phoenix_workflow_engine::notify_config()?,
+            };
+            if config.is_empty() {
+                return Err(anyhow!("no notification channels configured"));
             }
-            #[cfg(not(target_os = "macos"))]
-            {
-                Err(anyhow!("macos-only command"))
+            let event = phoenix_notify::RunEvent {
+                run_id: "notify-test".to_string(),
+                workflow: "notify-test".to_string(),
+                status: if failed {
+                    phoenix_notify::RunOutcome::Failed
+                } else {
+                    phoenix_notify::RunOutcome::Completed
+                },
+                target_disk: "-".to_string(),
+                target_serial: None,
+                target_port: None,
+                target_device: None,
+                phase: "test".to_string(),
+                report: report
+                    .as_ref()
+                    .map(|root| config.report_link(std::path::Path::new(root))),
+                report_root: report.map(std::path::PathBuf::from),
+                error: failed.then(|| "test failure".to_string()),
+                duration_secs: 0,
+                correlation_id: phoenix_report::correlation_id(),
+            };
+            let deliveries = phoenix_notify::notify(&config, &event);
+            let mut failures = 0;
+            for delivery in &deliveries {
+                match &delivery.error {
+                    None => println!("{}: ok", delivery.channel),
+                    Some(err) => {
+                        failures += 1;
+                        println!("{}: {}", delivery.channel, err);
+                    }
+                }
+            }
+            if failures == 0 {
+                Ok(())
+            } else {
+                Err(anyhow!("{} of {} deliveries failed", failures, deliveries.len()))
             }
         }
 
-        Commands::MacosCreateInstaller {
-            source,
-            target_device,
-            volume_name,
-            macos_version,
-            filesystem,
-            report_base,
+        Commands::SelfUpdate {
+            manifest_url,
+            check,
             force,
-            token,
-            execute,
+            rollback,
         } => {
-            #[cfg(target_os = "macos")]
-            {
-                let params = phoenix_workflow_engine::MacosInstallerUsbParams {
-                    source_path: source.into(),
-                    target_device: target_device.into(),
-                    report_base: report_base.into(),
-                    volume_name,
-                    macos_version,
-                    filesystem,
-                    force,
-                    confirmation_token: token,
-                    dry_run: !execute,
-                };
-                let result = phoenix_workflow_engine::run_macos_installer_usb(&params)?;
-                println!("macOS installer complete:");
-                println!("  dry_run: {}", result.dry_run);
-                println!("  mode: {}", result.mode);
-                println!("  target_volume: {}", result.target_volume.display());
-                println!("  report_root: {}", result.report.root.display());
-                Ok(())
+            let exe = std::env::current_exe()?;
+            if rollback {
+                phoenix_update::rollback(&exe)?;
+                println!("rolled_back: {}", exe.display());
+                return Ok(());
             }
-            #[cfg(not(target_os = "macos"))]
-            {
-                Err(anyhow!("macos-only command"))
+            let source = manifest_url
+                .or_else(|| update_url_setting().value)
+                .ok_or_else(|| anyhow!("no release manifest URL configured"))?;
+            let manifest = phoenix_update::fetch_manifest(&source)?;
+            let update = phoenix_update::check_update(&manifest, env!("CARGO_PKG_VERSION"));
+            println!("current_version: {}", update.current_version);
+            println!("latest_version: {}", update.latest_version);
+            let Some(artifact) = &update.artifact else {
+                return Err(anyhow!(
+                    "release has no artifact for {}",
+                    phoenix_update::current_platform()
+                ));
+            };
+            if update.is_downgrade() && !force {
+                return Err(anyhow!(
+                    "release {} is older than {}; pass --force to downgrade",
+                    update.latest_version,
+                    update.current_version
+                ));
+            }
+            if !update.update_available() && !force {
+                println!("up_to_date: true");
+                return Ok(());
             }
+            if check {
+                println!("update_available: true");
+                return Ok(());
+            }
+            let key_hex = option_env!("PHOENIX_UPDATE_PUBLIC_KEY")
+                .ok_or_else(|| anyhow!("this build has no release public key"))?;
+            let key = phoenix_update::decode_key_hex(key_hex)?;
+            let bytes =
+                phoenix_update::download_artifact(&source, &update.latest_version, artifact, &key)?;
+            println!("signature_valid: true");
+            let backup = phoenix_update::install_binary(&exe, &bytes)?;
+            println!("installed: {}", exe.display());
+            println!("previous: {}", backup.display());
+            Ok(())
         }
 
-        Commands::MacosLegacyPatch {
-            source,
-            model,
-            board_id,
-            report_base,
-            force,
-            token,
-            execute,
+        Commands::ReleaseSign {
+            artifact,
+            key_file,
+            release_version,
+            platform,
+            url,
         } => {
-            let params = LegacyPatchParams {
-                source_path: source.into(),
-                report_base: report_base.into(),
-                model,
-                board_id,
-                force,
-                confirmation_token: token,
-                dry_run: !execute,
-            };
-            let result = run_legacy_patch(&params)?;
-            println!("Legacy patch complete:");
-            println!("  dry_run: {}", result.dry_run);
-            println!("  patched_files: {}", result.patched_files.len());
-            for file in &result.patched_files {
-                println!("  patched: {}", file);
+            let seed = phoenix_update::decode_key_hex(&std::fs::read_to_string(&key_file)?)
+                .map_err(|_| anyhow!("key file must hold a 32-byte seed as hex"))?;
+            let bytes = std::fs::read(&artifact)?;
+            let platform = platform.unwrap_or_else(phoenix_update::current_platform);
+            let url = url.unwrap_or_else(|| {
+                std::path::Path::new(&artifact)
+                    .file_name()
+                    .map(|name| name.to_string_lossy().to_string())
+                    .unwrap_or_default()
+            });
+            let entry =
+                phoenix_update::sign_artifact(&bytes, &release_version, &platform, &url, &seed);
+            println!("public_key: {}", phoenix_update::public_key_for_seed(&seed));
+            println!("{}", serde_json::to_string_pretty(&entry)?);
+            Ok(())
+        }
+
+        Commands::Doctor { json } => {
+            let checks = phoenix_workflow_engine::run_doctor();
+            if json {
+                println!("{}", serde_json::to_string_pretty(&checks)?);
+            } else {
+                for check in &checks {
+                    let status = match check.status {
+                        phoenix_workflow_engine::CheckStatus::Ok => "ok",
+                        phoenix_workflow_engine::CheckStatus::Warn => "warn",
+                        phoenix_workflow_engine::CheckStatus::Fail => "FAIL",
+                    };
+                    println!("[{}] {}: {}", status, check.name, check.detail);
+                    if let Some(fix) = &check.remediation {
+                        println!("       fix: {}", fix);
+                    }
+                }
+            }
+            let failed = checks
+                .iter()
+                .filter(|check| check.status == phoenix_workflow_engine::CheckStatus::Fail)
+                .count();
+            if failed == 0 {
+                Ok(())
+            } else {
+                Err(anyhow!("{} prerequisite check(s) failed", failed))
+            }
+        }
+
+        Commands::ConfigShow {
+            effective,
+            json,
+            compare,
+        } => {
+            let config = cli_effective_config();
+            if let Some(path) = compare {
+                let other = phoenix_workflow_engine::EffectiveConfig::load(std::path::Path::new(&path))?;
+                let drift = phoenix_workflow_engine::config_drift(&config, &other);
+                if json {
+                    println!("{}", serde_json::to_string_pretty(&drift)?);
+                    return Ok(());
+                }
+                println!("left: {} (pid {})", config.process, config.pid);
+                println!("right: {} (pid {})", other.process, other.pid);
+                for entry in &drift {
+                    let side = |value: &Option<String>, source: Option<phoenix_workflow_engine::ConfigSource>| {
+                        format!(
+                            "{} [{}]",
+                            value.as_deref().unwrap_or("unset"),
+                            source.map_or("missing", |source| source.as_str())
+                        )
+                    };
+                    println!(
+                        "{}: {} vs {}",
+                        entry.key,
+                        side(&entry.left, entry.left_source),
+                        side(&entry.right, entry.right_source)
+                    );
+                }
+                println!("differences: {}", drift.len());
+                return Ok(());
+            }
+            let settings: Vec<_> = config
+                .settings
+                .iter()
+                .filter(|setting| {
+                    effective
+                        || setting.source != phoenix_workflow_engine::ConfigSource::Default
+                        || setting.error.is_some()
+                })
+                .collect();
+            if json {
+                if effective {
+                    println!("{}", serde_json::to_string_pretty(&config)?);
+                } else {
+                    println!("{}", serde_json::to_string_pretty(&settings)?);
+                }
+                return Ok(());
+            }
+            for setting in settings {
+                let mut origin = setting.source.as_str().to_string();
+                if let Some(env) = &setting.env {
+                    origin.push_str(&format!(" ${}", env));
+                }
+                if let Some(path) = &setting.path {
+                    origin.push_str(&format!(" {}", path.display()));
+                }
+                println!(
+                    "{}: {} [{}]",
+                    setting.key,
+                    setting.value.as_deref().unwrap_or("unset"),
+                    origin
+                );
+                if let Some(error) = &setting.error {
+                    println!("  error: {}", error);
+                }
             }
-            println!("  report_root: {}", result.report.root.display());
             Ok(())
         }
 
-        Commands::StageBootloader {
-            source,
-            target_mount,
-            target_subdir,
-            report_base,
-            force,
-            token,
-            execute,
-            hash_manifest,
-        } => {
-            let params = BootloaderStageParams {
-                source_path: source.into(),
-                target_mount: target_mount.into(),
-                target_subdir: target_subdir.map(Into::into),
-                report_base: report_base.into(),
-                force,
-                confirmation_token: token,
-                dry_run: !execute,
-                hash_manifest,
-            };
-            let result = run_stage_bootloader(&params)?;
-            println!("Bootloader staging complete:");
-            println!("  dry_run: {}", result.dry_run);
-            println!("  copied_files: {}", result.copied_files);
-            println!("  copied_bytes: {}", result.copied_bytes);
-            println!("  report_root: {}", result.report.root.display());
+        Commands::Capabilities { json } => {
+            let capabilities = phoenix_workflow_engine::host_capabilities();
+            if json {
+                println!("{}", serde_json::to_string_pretty(&capabilities)?);
+                return Ok(());
+            }
+            println!("os: {}", capabilities.os);
+            println!("elevated: {}", capabilities.elevated);
+            println!("read_only: {}", capabilities.read_only);
+            for (name, capability) in capabilities.iter() {
+                let state = if !capability.available {
+                    "no"
+                } else if capability.needs_elevation {
+                    "needs elevation"
+                } else {
+                    "yes"
+                };
+                println!("{}: {} ({})", name, state, capability.detail);
+            }
             Ok(())
         }
 
-        Commands::MacosKextStage {
-            source,
-            target_mount,
-            target_subdir,
-            report_base,
-            force,
-            token,
-            execute,
-            hash_manifest,
-        } => {
-            #[cfg(target_os = "macos")]
-            {
-                let params = MacosKextStageParams {
-                    source_path: source.into(),
-                    target_mount: target_mount.into(),
-                    target_subdir: target_subdir.map(Into::into),
-                    report_base: report_base.into(),
-                    force,
-                    confirmation_token: token,
-                    dry_run: !execute,
-                    hash_manifest,
+        Commands::SystemLogRegister => {
+            phoenix_notify::register_event_source()?;
+            println!("registered: {}", phoenix_notify::SYSTEM_LOG_SOURCE);
+            Ok(())
+        }
+
+        Commands::Cleanup { dry_run } => {
+            let leftovers = phoenix_workflow_engine::reap_leftovers(dry_run)?;
+            println!("leftovers: {}", leftovers.len());
+            for leftover in &leftovers {
+                let state = match (&leftover.error, leftover.removed) {
+                    (Some(err), _) => format!("failed: {}", err),
+                    (None, true) => "removed".to_string(),
+                    (None, false) => "found".to_string(),
                 };
-                let result = run_macos_kext_stage(&params)?;
-                println!("macOS kext staging complete:");
-                println!("  dry_run: {}", result.dry_run);
-                println!("  copied_files: {}", result.copied_files);
-                println!("  copied_bytes: {}", result.copied_bytes);
-                println!("  report_root: {}", result.report.root.display());
-                Ok(())
+                println!(
+                    "  {} {} ({}, pid {}): {}",
+                    leftover.action.as_str(),
+                    leftover.path,
+                    leftover.found_in,
+                    leftover.pid.map_or("-".to_string(), |pid| pid.to_string()),
+                    state
+                );
             }
-            #[cfg(not(target_os = "macos"))]
-            {
-                Err(anyhow!("macos-only command"))
+            if leftovers.iter().any(|leftover| leftover.error.is_some()) {
+                return Err(anyhow!("some leftovers could not be removed"));
             }
+            Ok(())
         }
 
-        Commands::WorkflowRun { file, report_base } => {
-            let definition: WorkflowDefinition = load_workflow_definition(&file)?;
-            validate_workflow_definition(&definition)?;
-            let result = phoenix_workflow_engine::run_workflow_definition_with_report(
-                &definition,
-                report_base.into(),
-            )?;
-            println!("workflow: {}", definition.name);
-            for step in &result.steps {
-                println!(
-                    "step {}: {} ({} ms)",
-                    step.id, step.action, step.duration_ms
-                );
-                if let Some(root) = step.report_root {
-                    println!("  report: {}", root.display());
+        Commands::RunsRecover { ledger, dismiss } => {
+            let ledger = match ledger {
+                Some(dir) => RunLedger::open(dir),
+                None => RunLedger::open_default()?,
+            };
+            if let Some(run_id) = dismiss {
+                let record = ledger.dismiss(&run_id)?;
+                println!("dismissed: {}", record.run_id);
+                return Ok(());
+            }
+            let interrupted = ledger.interrupted()?;
+            println!("ledger: {}", ledger.dir().display());
+            println!("interrupted: {}", interrupted.len());
+            for record in &interrupted {
+                println!("run: {}", record.run_id);
+                println!("  workflow: {}", record.workflow);
+                println!("  target_disk: {}", record.target_disk);
+                println!("  target_name: {}", record.target_name);
+                if let Some(serial) = &record.target_serial {
+                    println!("  target_serial: {}", serial);
+                }
+                println!("  phase: {}", record.phase);
+                println!("  destructive: {}", record.destructive);
+                println!("  started_unix: {}", record.started_unix);
+                println!("  updated_unix: {}", record.updated_unix);
+                for step in recovery_guidance(record) {
+                    println!("  - {}", step);
                 }
             }
-            println!("workflow_report: {}", result.report.root.display());
             Ok(())
         }
 
-        Commands::WorkflowValidate { file } => {
-            let definition: WorkflowDefinition = load_workflow_definition(&file)?;
-            validate_workflow_definition(&definition)?;
-            println!("workflow valid: {}", definition.name);
+        Commands::DeviceHistory {
+            registry,
+            device,
+            json,
+        } => {
+            let registry = match registry {
+                Some(path) => DeviceRegistry::open(path),
+                None => DeviceRegistry::open_default()?,
+            };
+            let records: Vec<_> = registry
+                .list()?
+                .into_iter()
+                .filter(|record| device.as_deref().is_none_or(|key| record.key == key))
+                .collect();
+            if json {
+                println!("{}", serde_json::to_string_pretty(&records)?);
+                return Ok(());
+            }
+            println!("registry: {}", registry.path().display());
+            println!("devices: {}", records.len());
+            for record in &records {
+                println!("device: {}", record.key);
+                println!("  name: {}", record.friendly_name);
+                println!("  size_bytes: {} ({})", record.size_bytes, format_bytes(record.size_bytes));
+                println!("  first_seen: {}", record.first_seen_utc);
+                println!("  last_seen: {}", record.last_seen_utc);
+                println!("  flash_count: {}", record.flash_count);
+                if let Some(workflow) = &record.last_workflow {
+                    println!("  last_workflow: {}", workflow);
+                }
+                if let Some(flashed) = &record.last_flashed_utc {
+                    println!("  last_flashed: {}", flashed);
+                }
+                if let Some(rate) = record.best_write_bytes_per_sec {
+                    println!("  best_write_bytes_per_sec: {} ({})", rate, display_format().rate(rate));
+                }
+                if let Some(rate) = record.last_write_bytes_per_sec {
+                    println!("  last_write_bytes_per_sec: {} ({})", rate, display_format().rate(rate));
+                }
+                if let Some(claim) = &record.claim {
+                    println!("  label: {} ({})", claim.label, claim.action.as_str());
+                    if let Some(notes) = &claim.notes {
+                        println!("  notes: {}", notes);
+                    }
+                }
+            }
             Ok(())
         }
 
-        Commands::DiskHashReport {
-            disk,
-            chunk_size,
-            max_chunks,
-            report_base,
+        Commands::DeviceAnnotate {
+            device,
+            label,
+            notes,
+            action,
+            clear,
+            registry,
         } => {
-            let params = DiskHashReportParams {
-                disk_id: disk,
-                chunk_size,
-                max_chunks,
-                report_base: report_base.into(),
+            let registry = match registry {
+                Some(path) => DeviceRegistry::open(path),
+                None => DeviceRegistry::open_default()?,
             };
-            let result = run_disk_hash_report(&params)?;
-            println!("Disk hash report:");
-            println!("  disk_id: {}", result.disk_id);
-            println!("  chunk_count: {}", result.chunk_count);
-            println!("  report_root: {}", result.report.root.display());
-            println!("  manifest: {}", result.report.manifest_path.display());
-            if let Some(sig) = result.report.signature_path.as_ref() {
-                println!("  signature: {}", sig.display());
+            let claim = match label {
+                Some(label) if !clear => Some(phoenix_core::DeviceClaim {
+                    label,
+                    notes,
+                    action: phoenix_core::ClaimAction::parse(&action)?,
+                    claimed_utc: phoenix_core::now_utc_rfc3339(),
+                }),
+                _ => None,
+            };
+            let record = registry.set_claim(&device, claim)?;
+            println!("device: {}", record.key);
+            match &record.claim {
+                Some(claim) => println!("label: {} ({})", claim.label, claim.action.as_str()),
+                None => println!("label: cleared"),
+            }
+            Ok(())
+        }
+
+        Commands::AuditLog { json } => {
+            let entries = read_audit_log()?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&entries)?);
+                return Ok(());
+            }
+            println!("audit_log: {}", audit_log_path()?.display());
+            println!("entries: {}", entries.len());
+            for entry in &entries {
+                println!("{} {} {} {}", entry.time_utc, entry.event, entry.workflow, entry.target_disk);
+                if let Some(serial) = &entry.target_serial {
+                    println!("  target_serial: {}", serial);
+                }
+                if let Some(user) = &entry.user {
+                    println!("  user: {}", user);
+                }
+                if let Some(agent) = &entry.agent {
+                    println!("  agent: {} (cert {})", agent.agent_id, agent.serial);
+                }
+                println!("  reason: {}", entry.reason);
             }
             Ok(())
         }
@@ -1359,6 +5634,83 @@ fn main() -> Result<()> {
             Ok(())
         }
 
+        Commands::PackNew { dir, name } => {
+            for path in phoenix_workflow_engine::scaffold_pack(std::path::Path::new(&dir), &name)? {
+                println!("created: {}", path.display());
+            }
+            Ok(())
+        }
+
+        Commands::PackLint { manifest, json } => {
+            let lint = phoenix_workflow_engine::lint_pack(std::path::Path::new(&manifest))?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&lint)?);
+            } else {
+                print_pack_lint(&lint);
+            }
+            match lint.errors() {
+                0 => Ok(()),
+                errors => Err(anyhow!("pack lint found {} errors", errors)),
+            }
+        }
+
+        Commands::PackTest {
+            manifest,
+            report_base,
+            graph,
+            sandbox,
+        } => {
+            let manifest = std::fs::canonicalize(&manifest)
+                .map_err(|err| anyhow!("pack manifest {}: {}", manifest, err))?;
+            let report_base = std::path::absolute(&report_base)?;
+            let sandbox = match sandbox {
+                Some(dir) => std::path::absolute(dir)?,
+                None => std::env::temp_dir().join(format!("phoenix-pack-test-{}", std::process::id())),
+            };
+            std::fs::create_dir_all(&sandbox)?;
+            // Everything below runs on the mock host with its own state, and
+            // relative paths in the workflows resolve from the pack, as they
+            // do for pack-run started there.
+            std::env::set_var(phoenix_core::mock::HOST_ENV, "mock");
+            std::env::set_var(phoenix_core::mock::SANDBOX_ENV, &sandbox);
+            std::env::set_var("PHOENIX_STATE_DIR", sandbox.join("state"));
+            if let Some(graph) = graph {
+                std::env::set_var(phoenix_core::mock::GRAPH_ENV, std::path::absolute(graph)?);
+            }
+            if let Some(pack_dir) = manifest.parent() {
+                std::env::set_current_dir(pack_dir)?;
+            }
+            println!("sandbox: {}", sandbox.display());
+            let result = phoenix_workflow_engine::run_pack_test(&phoenix_workflow_engine::PackTestParams {
+                manifest,
+                report_base,
+            })?;
+            print_pack_lint(&result.lint);
+            for case in &result.cases {
+                let disk = case.disk_id.as_deref().map(|disk| format!(" on {}", disk)).unwrap_or_default();
+                match &case.error {
+                    None => println!("passed: {}{} ({} ms)", case.workflow, disk, case.duration_ms),
+                    Some(err) => println!("failed: {}{}: {}", case.workflow, disk, err),
+                }
+            }
+            println!("pack_test_report: {}", result.report.root.display());
+            if result.passed() {
+                return Ok(());
+            }
+            if result.lint.errors() > 0 {
+                return Err(anyhow!(
+                    "pack test failed: {} lint errors; no workflow was run",
+                    result.lint.errors()
+                ));
+            }
+            let failed = result.cases.iter().filter(|case| case.error.is_some()).count();
+            Err(anyhow!(
+                "pack test failed: {} of {} workflows failed",
+                failed,
+                result.cases.len()
+            ))
+        }
+
         Commands::PackRun {
             manifest,
             report_base,
@@ -1379,6 +5731,12 @@ fn main() -> Result<()> {
                     return Err(anyhow!("pack signature invalid"));
                 }
             }
+            if !manifest_data.cached_assets.is_empty() {
+                let store = phoenix_workflow_engine::asset_store()?;
+                let (stored, _) = cache_pack_assets(&manifest, &store)?;
+                let reused = stored.iter().filter(|asset| asset.reused).count();
+                println!("cached_assets: {} ({} reused)", stored.len(), reused);
+            }
             let workflows = resolve_pack_workflows(&manifest)?;
             let mut workflow_reports = Vec::new();
             for (path, workflow) in workflows {
@@ -1413,6 +5771,60 @@ fn main() -> Result<()> {
             Ok(())
         }
 
+        Commands::PackCache { manifest, json } => {
+            let store = phoenix_workflow_engine::asset_store()?;
+            let (stored, evicted) = cache_pack_assets(&manifest, &store)?;
+            if json {
+                let value = serde_json::json!({ "assets": stored, "evicted": evicted });
+                println!("{}", serde_json::to_string_pretty(&value)?);
+                return Ok(());
+            }
+            println!("store: {}", store.root().display());
+            for asset in &stored {
+                println!(
+                    "{} {}: {} ({} bytes)",
+                    if asset.reused { "reused" } else { "stored" },
+                    asset.name,
+                    asset.sha256,
+                    asset.size_bytes
+                );
+            }
+            println!(
+                "evicted: {} ({})",
+                evicted.evicted.len(),
+                format_bytes(evicted.evicted_bytes)
+            );
+            Ok(())
+        }
+
+        Commands::AssetPrune {
+            max_total_bytes,
+            max_age_days,
+            dry_run,
+        } => {
+            let store = phoenix_workflow_engine::asset_store()?;
+            let mut policy = store.policy()?;
+            policy.max_total_bytes = max_total_bytes.or(policy.max_total_bytes);
+            policy.max_age_days = max_age_days.or(policy.max_age_days);
+            for asset in store.list()? {
+                println!(
+                    "asset: {} ({} bytes, last used {})",
+                    asset.sha256, asset.size_bytes, asset.last_used_unix
+                );
+            }
+            if policy.is_empty() {
+                return Ok(());
+            }
+            let result = store.evict(&policy, &Default::default(), dry_run)?;
+            println!("dry_run: {}", dry_run);
+            println!("evicted: {} ({})", result.evicted.len(), format_bytes(result.evicted_bytes));
+            for sha256 in &result.evicted {
+                println!("  {}", sha256);
+            }
+            println!("kept: {} ({})", result.kept, format_bytes(result.kept_bytes));
+            Ok(())
+        }
+
         Commands::PackSign { manifest, key } => {
             let sig_path = sign_pack_manifest(&manifest, &key)?;
             println!("signature: {}", sig_path.display());
@@ -1429,6 +5841,12 @@ fn main() -> Result<()> {
             }
         }
 
+        Commands::PackPayloadHash { path } => {
+            let hashes = payload_file_hashes(path.as_ref())?;
+            println!("{}", serde_json::to_string_pretty(&hashes)?);
+            Ok(())
+        }
+
         Commands::PackExport { manifest, out, key } => {
             if let Some(key) = resolve_pack_key(key) {
                 let sig_path = sign_pack_manifest(&manifest, &key)?;
@@ -1441,45 +5859,358 @@ fn main() -> Result<()> {
     }
 }
 
+static PROGRESS_MODE: OnceLock<ProgressMode> = OnceLock::new();
+
+/// How `--progress` shows long operations on stderr.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ProgressMode {
+    Plain,
+    Json,
+    Fancy,
+}
+
+impl ProgressMode {
+    fn parse(mode: &str) -> Result<Self> {
+        match mode.trim().to_ascii_lowercase().as_str() {
+            "plain" => Ok(Self::Plain),
+            "json" => Ok(Self::Json),
+            "fancy" => Ok(Self::Fancy),
+            other => Err(anyhow!("unknown progress mode {}; use plain, json or fancy", other)),
+        }
+    }
+
+    fn detect() -> Self {
+        if std::io::stderr().is_terminal() {
+            Self::Fancy
+        } else {
+            Self::Plain
+        }
+    }
+
+    /// Least time between two updates; the last one of a transfer always
+    /// shows.
+    fn interval(self) -> Duration {
+        match self {
+            Self::Plain => Duration::from_secs(5),
+            Self::Json => Duration::from_secs(1),
+            Self::Fancy => Duration::from_millis(100),
+        }
+    }
+}
+
+/// Runs `f` with phase starts and transfer progress of every workflow it
+/// runs shown on stderr.
+fn with_progress_output<T>(mode: ProgressMode, f: impl FnOnce() -> T) -> T {
+    let printer = Rc::new(RefCell::new(ProgressPrinter::new(mode)));
+    let phases = Rc::clone(&printer);
+    let on_log = Box::new(move |workflow: &str, entry: &LogEntry| {
+        if let Some(phase) = entry.message.strip_prefix("phase=") {
+            phases.borrow_mut().phase(workflow, phase);
+        }
+    });
+    let on_progress = Box::new(move |progress: &TransferProgress| {
+        printer.borrow_mut().transfer(progress);
+    });
+    with_log_listener(on_log, || with_progress_listener(on_progress, f))
+}
+
+struct ProgressPrinter {
+    mode: ProgressMode,
+    last_shown: Option<Instant>,
+    /// A fancy bar is on the current line.
+    bar_drawn: bool,
+}
+
+impl ProgressPrinter {
+    fn new(mode: ProgressMode) -> Self {
+        Self {
+            mode,
+            last_shown: None,
+            bar_drawn: false,
+        }
+    }
+
+    fn phase(&mut self, workflow: &str, phase: &str) {
+        self.end_bar();
+        self.last_shown = None;
+        match self.mode {
+            ProgressMode::Json => eprintln!(
+                "{}",
+                serde_json::json!({"event": "phase", "workflow": workflow, "phase": phase})
+            ),
+            ProgressMode::Plain | ProgressMode::Fancy => eprintln!("{}: {}", workflow, phase),
+        }
+    }
+
+    fn transfer(&mut self, progress: &TransferProgress) {
+        let finished = progress.done_bytes >= progress.total_bytes;
+        let due = self
+            .last_shown
+            .is_none_or(|shown| shown.elapsed() >= self.mode.interval());
+        if !(due || finished) {
+            return;
+        }
+        self.last_shown = Some(Instant::now());
+        let percent = (progress.done_bytes as f64 * 100.0 / progress.total_bytes.max(1) as f64).min(100.0);
+        let rate = progress.done_bytes as f64 / (progress.elapsed_ms.max(1) as f64 / 1000.0);
+        match self.mode {
+            ProgressMode::Plain => {
+                let format = display_format();
+                eprintln!(
+                    "progress: {} {} {:.0}% ({} of {}, {})",
+                    progress.workflow,
+                    progress.phase,
+                    percent,
+                    format.bytes(progress.done_bytes),
+                    format.bytes(progress.total_bytes),
+                    format.rate(rate as u64)
+                )
+            }
+            ProgressMode::Json => eprintln!(
+                "{}",
+                serde_json::json!({
+                    "event": "progress",
+                    "workflow": progress.workflow,
+                    "phase": progress.phase,
+                    "done_bytes": progress.done_bytes,
+                    "total_bytes": progress.total_bytes,
+                    "elapsed_ms": progress.elapsed_ms,
+                })
+            ),
+            ProgressMode::Fancy => {
+                const WIDTH: usize = 30;
+                let filled = (percent / 100.0 * WIDTH as f64) as usize;
+                eprint!(
+                    "\r{} [{}{}] {:>3.0}% {} ",
+                    progress.phase,
+                    "#".repeat(filled),
+                    "-".repeat(WIDTH - filled),
+                    percent,
+                    display_format().rate(rate as u64)
+                );
+                let _ = std::io::stderr().flush();
+                self.bar_drawn = true;
+                if finished {
+                    self.end_bar();
+                }
+            }
+        }
+    }
+
+    fn end_bar(&mut self) {
+        if self.bar_drawn {
+            eprintln!();
+            self.bar_drawn = false;
+        }
+    }
+}
+
+#[cfg(windows)]
 struct CliProgress {
-    last_percent: u64,
+    printer: ProgressPrinter,
+    started: Instant,
 }
 
+#[cfg(windows)]
 impl CliProgress {
     fn new() -> Self {
-        Self { last_percent: 0 }
+        let mode = PROGRESS_MODE.get().copied().unwrap_or(ProgressMode::Plain);
+        Self {
+            printer: ProgressPrinter::new(mode),
+            started: Instant::now(),
+        }
     }
 }
 
+#[cfg(windows)]
 impl ProgressObserver for CliProgress {
     fn on_progress(&mut self, progress: HashProgress) -> bool {
         if progress.total_bytes == 0 {
             return true;
         }
-        let percent = (progress.bytes_hashed * 100) / progress.total_bytes;
-        if percent >= self.last_percent + 5 || percent == 100 {
-            println!(
-                "progress: {}% (chunk {}/{})",
-                percent, progress.chunk_index + 1, progress.total_chunks
-            );
-            self.last_percent = percent;
-        }
+        self.printer.transfer(&TransferProgress {
+            workflow: "hash-disk".to_string(),
+            phase: "hash".to_string(),
+            done_bytes: progress.bytes_hashed,
+            total_bytes: progress.total_bytes,
+            elapsed_ms: self.started.elapsed().as_millis() as u64,
+        });
         true
     }
 }
 
+/// Prints kiosk status lines and asks on stdin before each disk.
+struct CliKiosk;
+
+impl KioskObserver for CliKiosk {
+    fn event(&mut self, event: &KioskEvent) {
+        match event {
+            KioskEvent::Waiting { known } => {
+                println!("waiting: plug in a disk ({} already present ignored)", known)
+            }
+            KioskEvent::Skipped { disk, reason } => println!("skipped: {} ({})", disk.id, reason),
+            KioskEvent::Declined { disk } => println!("declined: {}", disk.id),
+            KioskEvent::Running { disk } => println!("running: {}", kiosk_disk_label(disk)),
+            KioskEvent::Finished { run } => match &run.error {
+                None => println!(
+                    "completed: {} ({})",
+                    run.disk_id,
+                    format_duration_ms(run.duration_ms as u64)
+                ),
+                Some(err) => println!("failed: {}: {}", run.disk_id, err),
+            },
+            KioskEvent::AwaitingRemoval { disk } => println!("remove: {}", kiosk_disk_label(disk)),
+            KioskEvent::Removed { disk_id } => println!("removed: {}", disk_id),
+        }
+    }
+
+    fn confirm(&mut self, disk: &phoenix_core::Disk) -> bool {
+        if let Ok(graph) = build_device_graph() {
+            println!("{}", destruction_text(&graph, &disk.id));
+        }
+        print!("run on {}? [y/N] ", kiosk_disk_label(disk));
+        let _ = std::io::Write::flush(&mut std::io::stdout());
+        let mut answer = String::new();
+        std::io::stdin().read_line(&mut answer).is_ok()
+            && answer.trim().eq_ignore_ascii_case("y")
+    }
+}
+
+struct CliAgent;
+
+impl AgentObserver for CliAgent {
+    fn event(&mut self, event: &AgentEvent) {
+        match event {
+            AgentEvent::Polling { devices } => println!("polling: {} disks on offer", devices),
+            AgentEvent::ServerError { error } => println!("server error: {}", error),
+            AgentEvent::Claimed { job, disk } => match disk {
+                Some(disk) => println!(
+                    "claimed: {} {} on {}",
+                    job.job_id,
+                    job.definition.name,
+                    kiosk_disk_label(disk)
+                ),
+                None => println!("claimed: {} {}", job.job_id, job.definition.name),
+            },
+            AgentEvent::Finished { job } => match &job.error {
+                None => println!(
+                    "completed: {} (report {})",
+                    job.job_id,
+                    if job.report_uploaded { "uploaded" } else { "not uploaded" }
+                ),
+                Some(err) => println!("failed: {}: {}", job.job_id, err),
+            },
+        }
+    }
+}
+
+fn print_pack_lint(lint: &phoenix_workflow_engine::PackLint) {
+    println!("workflows: {}", lint.workflows);
+    for problem in &lint.problems {
+        let level = match problem.level {
+            phoenix_workflow_engine::LintLevel::Error => "error",
+            phoenix_workflow_engine::LintLevel::Warning => "warning",
+        };
+        match &problem.step {
+            Some(step) => println!(
+                "{}: {} step {}: {}",
+                level,
+                problem.file.display(),
+                step,
+                problem.message
+            ),
+            None => println!("{}: {}: {}", level, problem.file.display(), problem.message),
+        }
+    }
+    println!("lint_errors: {}", lint.errors());
+}
+
+fn print_issued_cert(cert: &IssuedCert) {
+    println!("name: {}", cert.name);
+    println!("serial: {}", cert.serial);
+    println!("cert_sha256: {}", cert.cert_sha256);
+    println!("not_after: {}", cert.not_after_utc);
+    println!("cert: {}", cert.cert_path.display());
+    println!("key: {}", cert.key_path.display());
+}
+
+/// Files a `--continue-on-error` run skipped.
+fn print_copy_failures(failures: &[phoenix_workflow_engine::CopyFailure]) {
+    if failures.is_empty() {
+        return;
+    }
+    println!("  status: degraded ({} files not copied)", failures.len());
+    for failure in failures {
+        println!(
+            "    {} ({}, {} side): {}",
+            failure.path,
+            format_bytes(failure.bytes),
+            failure.side.as_str(),
+            failure.error
+        );
+    }
+}
+
+/// Files a `--transform-pack` run edited, with both hashes.
+fn print_transformed_files(files: &[phoenix_workflow_engine::TransformedFile]) {
+    for file in files {
+        println!(
+            "  transformed: {} [{}] sha256 {} -> {}",
+            file.path,
+            file.transforms.join(", "),
+            file.original_sha256,
+            file.sha256
+        );
+    }
+}
+
+/// Erase summary for a prompt; falls back to the disk id when the disk
+/// cannot be described.
+fn destruction_text(graph: &DeviceGraph, disk_id: &str) -> String {
+    let params = DestructionParams {
+        target: disk_id.to_string(),
+        action: DestructiveAction::Erase,
+        inspect_contents: true,
+    };
+    match describe_destruction(&params, graph) {
+        Ok(summary) => summary.text(),
+        Err(err) => format!("This will ERASE {} ({:#})", disk_id, err),
+    }
+}
+
+fn kiosk_disk_label(disk: &phoenix_core::Disk) -> String {
+    let mut label = format!("{} {}", disk.id, disk.friendly_name);
+    if let Some(serial) = &disk.serial {
+        label.push_str(&format!(" serial {}", serial));
+    }
+    if let Some(port) = &disk.usb_port {
+        label.push_str(&format!(" port {}", port.label.as_deref().unwrap_or(&port.path)));
+    }
+    if let Some(claim) = disk.history.as_ref().and_then(|history| history.claim.as_ref()) {
+        label.push_str(&format!(" [{}]", claim.label));
+    }
+    label
+}
+
 fn build_device_graph() -> Result<DeviceGraph> {
+    let mut graph = host_device_graph()?;
+    graph.apply_usb_port_labels(&phoenix_workflow_engine::usb_port_labels()?);
+    phoenix_workflow_engine::observe_devices(&mut graph);
+    Ok(graph)
+}
+
+fn host_device_graph() -> Result<DeviceGraph> {
     #[cfg(target_os = "windows")]
     {
-        return phoenix_host_windows::build_device_graph();
+        phoenix_host_windows::build_device_graph()
     }
     #[cfg(target_os = "linux")]
     {
-        return phoenix_host_linux::build_device_graph();
+        phoenix_host_linux::build_device_graph()
     }
     #[cfg(target_os = "macos")]
     {
-        return phoenix_host_macos::build_device_graph();
+        phoenix_host_macos::build_device_graph()
     }
     #[cfg(not(any(target_os = "windows", target_os = "linux", target_os = "macos")))]
     {
@@ -1493,3 +6224,26 @@ fn resolve_pack_key(key: Option<String>) -> Option<String> {
     }
     std::env::var("PHOENIX_PACK_KEY").ok()
 }
+
+/// The engine's settings plus the CLI's own.
+fn cli_effective_config() -> phoenix_workflow_engine::EffectiveConfig {
+    let process = format!("phoenix-cli {}", env!("CARGO_PKG_VERSION"));
+    let mut config = phoenix_workflow_engine::effective_config(&process);
+    config.set(update_url_setting());
+    config
+}
+
+/// `$PHOENIX_UPDATE_URL`, else the URL the binary was built with.
+fn update_url_setting() -> phoenix_workflow_engine::ConfigSetting {
+    use phoenix_workflow_engine::{ConfigSetting, ConfigSource};
+    if let Ok(url) = std::env::var("PHOENIX_UPDATE_URL") {
+        return ConfigSetting {
+            env: Some("PHOENIX_UPDATE_URL".to_string()),
+            ..ConfigSetting::new("update_url", Some(url), ConfigSource::Env)
+        };
+    }
+    match option_env!("PHOENIX_UPDATE_URL") {
+        Some(url) => ConfigSetting::new("update_url", Some(url.to_string()), ConfigSource::Build),
+        None => ConfigSetting::new("update_url", None, ConfigSource::Default),
+    }
+}
@@ -0,0 +1,291 @@
+//! Boot menu configuration rendered onto staged media. Packs declare a
+//! GRUB menu (title, timeout, default entry, theme assets and a curated
+//! entry list) and staging turns it into `grub.cfg` plus a theme
+//! directory GRUB can load.
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const DEFAULT_GRUB_DIR: &str = "boot/grub";
+const THEME_FILE: &str = "theme.txt";
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct GrubMenu {
+    /// Shown above the menu, as the theme's `title-text`.
+    #[serde(default)]
+    pub title: Option<String>,
+    /// Seconds before the default entry boots; `0` boots it at once.
+    #[serde(default)]
+    pub timeout: Option<u32>,
+    /// Title or index of the default entry.
+    #[serde(default)]
+    pub default: Option<String>,
+    /// Theme directory in the pack, holding `theme.txt` and the images and
+    /// `.pf2` fonts it uses.
+    #[serde(default)]
+    pub theme: Option<String>,
+    /// Replaces the staged `grub.cfg` when given; otherwise the branding is
+    /// appended to the one the bootloader package brought.
+    #[serde(default)]
+    pub entries: Vec<GrubMenuEntry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GrubMenuEntry {
+    pub title: String,
+    /// Theme icon classes, such as `linux` or `windows`.
+    #[serde(default)]
+    pub class: Vec<String>,
+    /// GRUB commands run when the entry is chosen.
+    pub commands: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StagedGrubMenu {
+    pub config: PathBuf,
+    pub theme_dir: Option<PathBuf>,
+    pub theme_files: usize,
+    /// `false` when the branding was appended to an existing menu.
+    pub replaced_menu: bool,
+}
+
+/// Checks `menu` against the pack at `pack_root` before anything is staged.
+pub fn validate_grub_menu(menu: &GrubMenu, pack_root: &Path) -> Result<()> {
+    if let Some(theme) = &menu.theme {
+        let dir = pack_path(pack_root, theme)?;
+        if !dir.join(THEME_FILE).is_file() {
+            return Err(anyhow!("grub theme {} has no {}", theme, THEME_FILE));
+        }
+    }
+    for entry in &menu.entries {
+        if entry.title.trim().is_empty() {
+            return Err(anyhow!("grub menu entry has an empty title"));
+        }
+        if entry.commands.is_empty() {
+            return Err(anyhow!("grub menu entry {} has no commands", entry.title));
+        }
+    }
+    if let (Some(default), false) = (&menu.default, menu.entries.is_empty()) {
+        let known = match default.parse::<usize>() {
+            Ok(index) => index < menu.entries.len(),
+            Err(_) => menu.entries.iter().any(|entry| &entry.title == default),
+        };
+        if !known {
+            return Err(anyhow!("grub default {} matches no menu entry", default));
+        }
+    }
+    Ok(())
+}
+
+/// Writes `menu` under `target_root`: the theme goes to
+/// `<grub_dir>/themes/<slug>` and the menu to `<grub_dir>/grub.cfg`.
+/// `media_prefix` is where `target_root` sits on the volume, if not at
+/// its root.
+pub fn stage_grub_menu(
+    menu: &GrubMenu,
+    pack_root: &Path,
+    slug: &str,
+    target_root: &Path,
+    grub_dir: &str,
+    media_prefix: Option<&Path>,
+) -> Result<StagedGrubMenu> {
+    validate_grub_menu(menu, pack_root)?;
+    let theme_dir = target_root.join(theme_path(grub_dir, slug, None).trim_start_matches('/'));
+    let theme_path = theme_path(grub_dir, slug, media_prefix);
+
+    let mut theme_files = 0;
+    let mut fonts = Vec::new();
+    let has_theme = menu.theme.is_some() || menu.title.is_some();
+    if let Some(theme) = &menu.theme {
+        theme_files = copy_theme(&pack_path(pack_root, theme)?, &theme_dir, &mut fonts)?;
+        fonts.sort();
+    }
+    if has_theme {
+        let path = theme_dir.join(THEME_FILE);
+        let existing = if menu.theme.is_some() {
+            fs::read_to_string(&path).with_context(|| format!("read {}", path.display()))?
+        } else {
+            fs::create_dir_all(&theme_dir)?;
+            theme_files += 1;
+            DEFAULT_THEME.to_string()
+        };
+        let text = match &menu.title {
+            Some(title) => set_title_text(&existing, title),
+            None => existing,
+        };
+        fs::write(&path, text).with_context(|| format!("write {}", path.display()))?;
+    }
+
+    let config = target_root
+        .join(grub_dir.trim_matches(['/', '\\']))
+        .join("grub.cfg");
+    if let Some(parent) = config.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let branding = render_branding(menu, has_theme.then_some(theme_path.as_str()), &fonts);
+    let replaced_menu = !menu.entries.is_empty() || !config.exists();
+    let text = if replaced_menu {
+        format!("{}{}", branding, render_entries(&menu.entries))
+    } else {
+        // GRUB runs the whole file before drawing the menu, so settings
+        // appended here win over the package's own.
+        let existing =
+            fs::read_to_string(&config).with_context(|| format!("read {}", config.display()))?;
+        format!("{}\n{}", existing.trim_end(), branding)
+    };
+    fs::write(&config, text).with_context(|| format!("write {}", config.display()))?;
+
+    Ok(StagedGrubMenu {
+        config,
+        theme_dir: has_theme.then_some(theme_dir),
+        theme_files,
+        replaced_menu,
+    })
+}
+
+/// Timeout, default entry and theme settings. `theme_path` is from the
+/// root of the staged volume.
+pub fn render_branding(menu: &GrubMenu, theme_path: Option<&str>, fonts: &[String]) -> String {
+    let mut out = String::from("# Phoenix boot menu\n");
+    if let Some(timeout) = menu.timeout {
+        out.push_str(&format!("set timeout={}\n", timeout));
+        out.push_str("set timeout_style=menu\n");
+    }
+    if let Some(default) = &menu.default {
+        out.push_str(&format!("set default={}\n", quote(default)));
+    }
+    if let Some(theme_path) = theme_path {
+        out.push_str("insmod all_video\ninsmod gfxterm\ninsmod png\ninsmod jpeg\n");
+        for font in fonts {
+            out.push_str(&format!("loadfont {}\n", quote(&format!("{}/{}", theme_path, font))));
+        }
+        out.push_str("terminal_output gfxterm\n");
+        out.push_str(&format!(
+            "set theme={}\nexport theme\n",
+            quote(&format!("{}/{}", theme_path, THEME_FILE))
+        ));
+    }
+    out
+}
+
+pub fn render_entries(entries: &[GrubMenuEntry]) -> String {
+    let mut out = String::new();
+    for entry in entries {
+        out.push_str(&format!("\nmenuentry {}", quote(&entry.title)));
+        for class in &entry.class {
+            out.push_str(&format!(" --class {}", quote(class)));
+        }
+        out.push_str(" {\n");
+        for command in &entry.commands {
+            out.push_str(&format!("    {}\n", command));
+        }
+        out.push_str("}\n");
+    }
+    out
+}
+
+/// Where the theme for `slug` is staged, from the root of the volume.
+pub fn theme_path(grub_dir: &str, slug: &str, media_prefix: Option<&Path>) -> String {
+    let mut path = String::new();
+    if let Some(prefix) = media_prefix {
+        let prefix = prefix.to_string_lossy().replace('\\', "/");
+        path.push('/');
+        path.push_str(prefix.trim_matches('/'));
+    }
+    format!(
+        "{}/{}/themes/{}",
+        path,
+        grub_dir.trim_matches(['/', '\\']),
+        theme_slug(slug)
+    )
+}
+
+/// Lowercase ASCII letters, digits and dashes, for a theme directory name.
+pub fn theme_slug(name: &str) -> String {
+    let slug: String = name
+        .trim()
+        .chars()
+        .map(|ch| {
+            if ch.is_ascii_alphanumeric() {
+                ch.to_ascii_lowercase()
+            } else {
+                '-'
+            }
+        })
+        .collect();
+    let slug = slug.trim_matches('-').to_string();
+    if slug.is_empty() {
+        "phoenix".to_string()
+    } else {
+        slug
+    }
+}
+
+/// A single-quoted GRUB word; GRUB has no escape inside single quotes.
+fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+/// Without a boot_menu component a theme draws no menu at all.
+const DEFAULT_THEME: &str = "desktop-color: \"#000000\"\n\
+title-color: \"#ffffff\"\n\
++ boot_menu {\n\
+\x20 left = 15%\n\
+\x20 top = 20%\n\
+\x20 width = 70%\n\
+\x20 height = 60%\n\
+\x20 item_color = \"#cccccc\"\n\
+\x20 selected_item_color = \"#ffffff\"\n\
+}\n";
+
+fn set_title_text(theme: &str, title: &str) -> String {
+    let line = format!("title-text: \"{}\"", title.replace('"', "'"));
+    let mut lines: Vec<String> = theme
+        .lines()
+        .filter(|existing| !existing.trim_start().starts_with("title-text"))
+        .map(str::to_string)
+        .collect();
+    lines.insert(0, line);
+    lines.join("\n") + "\n"
+}
+
+fn pack_path(pack_root: &Path, relative: &str) -> Result<PathBuf> {
+    let path = Path::new(relative);
+    if path.is_absolute()
+        || path
+            .components()
+            .any(|part| matches!(part, std::path::Component::ParentDir))
+    {
+        return Err(anyhow!("{} must stay inside the pack", relative));
+    }
+    Ok(pack_root.join(path))
+}
+
+/// Copies the theme and collects its fonts, relative to the theme root.
+fn copy_theme(source: &Path, dest: &Path, fonts: &mut Vec<String>) -> Result<usize> {
+    fn walk(root: &Path, dir: &Path, dest: &Path, fonts: &mut Vec<String>) -> Result<usize> {
+        let mut files = 0;
+        for entry in fs::read_dir(dir).with_context(|| format!("read {}", dir.display()))? {
+            let path = entry?.path();
+            let relative = path.strip_prefix(root)?;
+            let target = dest.join(relative);
+            if path.is_dir() {
+                files += walk(root, &path, dest, fonts)?;
+                continue;
+            }
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::copy(&path, &target).with_context(|| format!("copy {}", path.display()))?;
+            files += 1;
+            let relative = relative.to_string_lossy().replace('\\', "/");
+            if relative.to_ascii_lowercase().ends_with(".pf2") {
+                fonts.push(relative);
+            }
+        }
+        Ok(files)
+    }
+    walk(source, source, dest, fonts)
+}
@@ -1,6 +1,8 @@
 use anyhow::{anyhow, Result};
 use std::path::{Path, PathBuf};
 
+pub mod mok;
+
 #[derive(Debug, Clone)]
 pub struct BootloaderPackage {
     pub root: PathBuf,
@@ -13,7 +15,7 @@ pub struct BootEntry {
     pub arch: BootArch,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum BootArch {
     X64,
     Aarch64,
@@ -0,0 +1,399 @@
+//! Secure Boot enrollment material for bootloaders signed with a key the
+//! firmware does not know. shim refuses them and starts MokManager, which
+//! can enroll either the signing certificate ("Enroll key from disk", DER
+//! only) or each binary's Authenticode SHA-256 ("Enroll hash from disk").
+
+use crate::{BootArch, BootloaderPackage};
+use anyhow::{anyhow, Context, Result};
+use rustls_pki_types::pem::PemObject;
+use rustls_pki_types::CertificateDer;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Name shim's own media use, so MokManager users recognise it.
+pub const MOK_CERTIFICATE_FILE: &str = "ENROLL_THIS_KEY_IN_MOKMANAGER.cer";
+pub const ENROLLMENT_GUIDE_FILE: &str = "SECURE_BOOT_ENROLLMENT.txt";
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MokCertificate {
+    /// Where it sits on the media, ESP-style.
+    pub media_path: String,
+    /// SHA-256 of the DER encoding, as MokManager shows it.
+    pub fingerprint: String,
+    #[serde(skip)]
+    pub der: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MokHash {
+    pub media_path: String,
+    /// Authenticode SHA-256, the digest shim compares against MokList.
+    pub sha256: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MokManager {
+    pub source: PathBuf,
+    pub media_path: String,
+    /// `EFI/BOOT/mmx64.efi` and so on, relative to the staging root.
+    pub relative_path: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MokEnrollment {
+    pub certificate: Option<MokCertificate>,
+    pub hashes: Vec<MokHash>,
+    pub mok_manager: Option<MokManager>,
+    pub guide_path: String,
+}
+
+/// Collects what a machine needs to enroll `package`: the DER form of
+/// `certificate`, the Authenticode hash of every `.efi` in the package
+/// when `hashes` is set, and `mok_manager` under the name shim loads for
+/// its architecture. `media_prefix` is the staging directory on the
+/// media, if any.
+pub fn plan_mok_enrollment(
+    package: &BootloaderPackage,
+    certificate: Option<&Path>,
+    hashes: bool,
+    mok_manager: Option<&Path>,
+    media_prefix: Option<&Path>,
+) -> Result<MokEnrollment> {
+    let media_path = |relative: &str| {
+        let full = match media_prefix {
+            Some(prefix) => prefix.join(relative),
+            None => PathBuf::from(relative),
+        };
+        format!("\\{}", full.to_string_lossy().replace('/', "\\"))
+    };
+
+    let certificate = match certificate {
+        Some(path) => {
+            let der = certificate_der(path)?;
+            Some(MokCertificate {
+                media_path: media_path(MOK_CERTIFICATE_FILE),
+                fingerprint: hex(&Sha256::digest(&der)),
+                der,
+            })
+        }
+        None => None,
+    };
+
+    let mut hashed = Vec::new();
+    if hashes {
+        let mut files = Vec::new();
+        collect_efi_files(&package.root, &mut files)?;
+        files.sort();
+        for file in files {
+            let relative = file
+                .strip_prefix(&package.root)?
+                .to_string_lossy()
+                .replace('\\', "/");
+            let data = fs::read(&file).with_context(|| format!("read {}", file.display()))?;
+            let digest = authenticode_sha256(&data)
+                .with_context(|| format!("{} is not a PE image", relative))?;
+            hashed.push(MokHash {
+                media_path: media_path(&relative),
+                sha256: hex(&digest),
+            });
+        }
+        if hashed.is_empty() {
+            return Err(anyhow!("bootloader package has no .efi binaries to hash"));
+        }
+    }
+
+    let mok_manager = match mok_manager {
+        Some(path) => {
+            let data = fs::read(path).with_context(|| format!("read {}", path.display()))?;
+            let arch = pe_machine(&data)?;
+            if !package
+                .boot_entries
+                .iter()
+                .any(|entry| entry.arch == arch)
+            {
+                return Err(anyhow!(
+                    "MokManager is {:?} but the package has no {:?} boot entry",
+                    arch,
+                    arch
+                ));
+            }
+            let name = mok_manager_name(arch)
+                .ok_or_else(|| anyhow!("unsupported MokManager architecture"))?;
+            let relative = format!("EFI/BOOT/{}", name);
+            Some(MokManager {
+                source: path.to_path_buf(),
+                media_path: media_path(&relative),
+                relative_path: relative,
+            })
+        }
+        None => None,
+    };
+
+    Ok(MokEnrollment {
+        certificate,
+        hashes: hashed,
+        mok_manager,
+        guide_path: media_path(ENROLLMENT_GUIDE_FILE),
+    })
+}
+
+impl MokEnrollment {
+    /// Writes the certificate, MokManager and guide under `root`; returns
+    /// the files written, relative to it.
+    pub fn stage(&self, root: &Path) -> Result<Vec<String>> {
+        let mut written = Vec::new();
+        if let Some(certificate) = &self.certificate {
+            let path = root.join(MOK_CERTIFICATE_FILE);
+            fs::write(&path, &certificate.der)
+                .with_context(|| format!("write {}", path.display()))?;
+            written.push(MOK_CERTIFICATE_FILE.to_string());
+        }
+        if let Some(manager) = &self.mok_manager {
+            let path = root.join(&manager.relative_path);
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::copy(&manager.source, &path)
+                .with_context(|| format!("copy {}", manager.source.display()))?;
+            written.push(manager.relative_path.clone());
+        }
+        let path = root.join(ENROLLMENT_GUIDE_FILE);
+        fs::write(&path, self.guide()).with_context(|| format!("write {}", path.display()))?;
+        written.push(ENROLLMENT_GUIDE_FILE.to_string());
+        Ok(written)
+    }
+
+    /// Step-by-step instructions for the person at the machine.
+    pub fn guide(&self) -> String {
+        let mut out = String::from(
+            "Secure Boot enrollment\n\
+             ======================\n\n\
+             This media's bootloader is not signed by a key the firmware trusts.\n\
+             Booted through shim, it stops at a blue MokManager screen on first\n\
+             boot. Enroll once per machine, then it boots normally.\n",
+        );
+        if let Some(manager) = &self.mok_manager {
+            out.push_str(&format!(
+                "\nMokManager is on the media at {}.\n",
+                manager.media_path
+            ));
+        }
+        if let Some(certificate) = &self.certificate {
+            out.push_str(&format!(
+                "\nEnroll the signing key\n\
+                 ----------------------\n\
+                 1. Press a key within 10 seconds when shim asks, to start MokManager.\n\
+                 2. Choose \"Enroll key from disk\" and pick this media.\n\
+                 3. Select {}.\n\
+                 4. Check the fingerprint reads {} and choose \"Continue\", then \"Yes\".\n\
+                 5. Choose \"Reboot\".\n",
+                certificate.media_path, certificate.fingerprint
+            ));
+        }
+        if !self.hashes.is_empty() {
+            out.push_str(
+                "\nEnroll binary hashes\n\
+                 --------------------\n",
+            );
+            if self.certificate.is_some() {
+                out.push_str("Only needed on machines where the key was not enrolled.\n");
+            }
+            out.push_str(
+                "1. In MokManager choose \"Enroll hash from disk\" and pick this media.\n\
+                 2. Select each file below, confirm with \"Continue\", then \"Yes\".\n",
+            );
+            for hash in &self.hashes {
+                out.push_str(&format!("   {}  {}\n", hash.media_path, hash.sha256));
+            }
+            out.push_str(
+                "3. Choose \"Reboot\". Any rebuilt binary needs its new hash enrolled.\n",
+            );
+        }
+        out.push_str(
+            "\nFrom a running Linux system\n\
+             ---------------------------\n",
+        );
+        if self.certificate.is_some() {
+            out.push_str(&format!("   mokutil --import {}\n", MOK_CERTIFICATE_FILE));
+        }
+        for hash in &self.hashes {
+            out.push_str(&format!("   mokutil --import-hash {}\n", hash.sha256));
+        }
+        out.push_str(
+            "Set a one-time password when asked, reboot, and confirm it in\n\
+             MokManager's \"Enroll MOK\" screen.\n\
+             \nWithout shim (db and KEK)\n\
+             -------------------------\n\
+             Firmware that boots the loader directly only trusts its db. Enroll\n\
+             the certificate from the firmware setup (Secure Boot > Key\n\
+             Management > db > Append from file). Updating db from an OS needs\n\
+             an update signed with one of the firmware's KEKs, normally the\n\
+             platform vendor's; this media carries none.\n",
+        );
+        out
+    }
+}
+
+/// Accepts PEM or DER; MokManager only reads DER.
+pub fn certificate_der(path: &Path) -> Result<Vec<u8>> {
+    let data = fs::read(path).with_context(|| format!("read {}", path.display()))?;
+    if data.starts_with(b"-----BEGIN") {
+        let certificate = CertificateDer::from_pem_slice(&data)
+            .map_err(|err| anyhow!("{}: {:?}", path.display(), err))?;
+        return Ok(certificate.as_ref().to_vec());
+    }
+    // A DER certificate is a SEQUENCE with a long-form length.
+    if data.len() < 4 || data[0] != 0x30 || data[1] & 0x80 == 0 {
+        return Err(anyhow!(
+            "{} is neither a PEM nor a DER certificate",
+            path.display()
+        ));
+    }
+    Ok(data)
+}
+
+/// Architecture from the COFF header's machine field.
+pub fn pe_machine(data: &[u8]) -> Result<BootArch> {
+    let header = PeHeader::parse(data)?;
+    Ok(match header.machine {
+        0x8664 => BootArch::X64,
+        0xaa64 => BootArch::Aarch64,
+        0x014c => BootArch::Ia32,
+        _ => BootArch::Unknown,
+    })
+}
+
+pub fn mok_manager_name(arch: BootArch) -> Option<&'static str> {
+    match arch {
+        BootArch::X64 => Some("mmx64.efi"),
+        BootArch::Aarch64 => Some("mmaa64.efi"),
+        BootArch::Ia32 => Some("mmia32.efi"),
+        BootArch::Unknown => None,
+    }
+}
+
+/// The Authenticode digest of a PE image: the headers without the
+/// checksum and certificate table entry, then the sections in file order,
+/// then anything after them except the certificate table itself.
+pub fn authenticode_sha256(data: &[u8]) -> Result<[u8; 32]> {
+    let header = PeHeader::parse(data)?;
+    let mut hasher = Sha256::new();
+    let headers_end = header.size_of_headers.min(data.len());
+    match header.cert_entry {
+        Some(entry) => {
+            hasher.update(slice(data, 0, header.checksum)?);
+            hasher.update(slice(data, header.checksum + 4, entry)?);
+            hasher.update(slice(data, entry + 8, headers_end)?);
+        }
+        None => {
+            hasher.update(slice(data, 0, header.checksum)?);
+            hasher.update(slice(data, header.checksum + 4, headers_end)?);
+        }
+    }
+
+    let mut sections = Vec::new();
+    for index in 0..header.sections {
+        let at = header.section_table + index * 40;
+        let size = read_u32(data, at + 16)? as usize;
+        let offset = read_u32(data, at + 20)? as usize;
+        if size > 0 {
+            sections.push((offset, size));
+        }
+    }
+    sections.sort_unstable();
+    let mut hashed = headers_end;
+    for (offset, size) in sections {
+        hasher.update(slice(data, offset, offset + size)?);
+        hashed = hashed.max(offset + size);
+    }
+
+    let trailer_end = data.len().saturating_sub(header.cert_size);
+    if trailer_end > hashed {
+        hasher.update(&data[hashed..trailer_end]);
+    }
+    Ok(hasher.finalize().into())
+}
+
+struct PeHeader {
+    machine: u16,
+    sections: usize,
+    section_table: usize,
+    checksum: usize,
+    size_of_headers: usize,
+    /// Offset of the certificate table's data directory entry.
+    cert_entry: Option<usize>,
+    cert_size: usize,
+}
+
+impl PeHeader {
+    fn parse(data: &[u8]) -> Result<Self> {
+        if data.get(0..2) != Some(b"MZ") {
+            return Err(anyhow!("missing MZ header"));
+        }
+        let pe = read_u32(data, 0x3c)? as usize;
+        if data.get(pe..pe + 4) != Some(b"PE\0\0") {
+            return Err(anyhow!("missing PE signature"));
+        }
+        let coff = pe + 4;
+        let optional = coff + 20;
+        let (rva_count, directories) = match read_u16(data, optional)? {
+            0x10b => (optional + 92, optional + 96),
+            0x20b => (optional + 108, optional + 112),
+            other => return Err(anyhow!("unknown optional header magic {:#x}", other)),
+        };
+        let cert_entry =
+            (read_u32(data, rva_count)? > 4).then_some(directories + 4 * 8);
+        let cert_size = match cert_entry {
+            Some(entry) => read_u32(data, entry + 4)? as usize,
+            None => 0,
+        };
+        Ok(Self {
+            machine: read_u16(data, coff)?,
+            sections: read_u16(data, coff + 2)? as usize,
+            section_table: optional + read_u16(data, coff + 16)? as usize,
+            checksum: optional + 64,
+            size_of_headers: read_u32(data, optional + 60)? as usize,
+            cert_entry,
+            cert_size,
+        })
+    }
+}
+
+fn slice(data: &[u8], start: usize, end: usize) -> Result<&[u8]> {
+    data.get(start..end)
+        .ok_or_else(|| anyhow!("PE image truncated at {:#x}", end))
+}
+
+fn read_u16(data: &[u8], at: usize) -> Result<u16> {
+    Ok(u16::from_le_bytes(slice(data, at, at + 2)?.try_into()?))
+}
+
+fn read_u32(data: &[u8], at: usize) -> Result<u32> {
+    Ok(u32::from_le_bytes(slice(data, at, at + 4)?.try_into()?))
+}
+
+/// Every `.efi` under `dir` except shim's MokManager and fallback, which
+/// shim trusts by its own signature.
+fn collect_efi_files(dir: &Path, out: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in fs::read_dir(dir).with_context(|| format!("read {}", dir.display()))? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_efi_files(&path, out)?;
+            continue;
+        }
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_ascii_lowercase())
+            .unwrap_or_default();
+        if name.ends_with(".efi") && !name.starts_with("mm") && !name.starts_with("fb") {
+            out.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
@@ -1,12 +1,24 @@
 use anyhow::{anyhow, Result};
+use phoenix_bootcfg::GrubMenu;
 use phoenix_core::WorkflowDefinition;
 use serde::de::DeserializeOwned;
 use sha2::{Digest, Sha256};
-use std::io::Write;
 use std::path::{Path, PathBuf};
 use zip::write::FileOptions;
 use zip::ZipWriter;
 
+pub mod media;
+pub mod payload;
+pub mod store;
+pub mod transform;
+
+pub use media::{read_media_manifest, write_media_manifest, MediaManifest, PackMedia, MEDIA_MANIFEST_FILE};
+pub use payload::{
+    payload_file_hashes, select_payloads, validate_payloads, verify_payload, PackPayload, PayloadFile,
+};
+pub use store::{cache_pack_assets, AssetStore, CachePolicy, CachedAsset, EvictResult, PackAsset, StoredAsset};
+pub use transform::{apply_transform, validate_transforms, FileTransform, TransformContext, TransformEdit};
+
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct PackManifest {
     pub schema_version: String,
@@ -15,6 +27,24 @@ pub struct PackManifest {
     pub description: Option<String>,
     pub workflows: Vec<String>,
     pub assets: Option<String>,
+    /// Branded GRUB menu rendered onto media that stage this pack's
+    /// bootloader.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub grub: Option<GrubMenu>,
+    /// Large files kept in the local asset store and referenced from
+    /// steps as `asset://<sha256>`; see `store`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub cached_assets: Vec<PackAsset>,
+    /// Written to finished media as `phoenix_media.toml`; see `media`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub media: Option<PackMedia>,
+    /// Rescue and diagnostics tools for combo sticks; see `payload`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub payloads: Vec<PackPayload>,
+    /// Edits applied to source files as installer copies stage them; see
+    /// `transform`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub transforms: Vec<FileTransform>,
 }
 
 pub const PACK_SCHEMA_VERSION: &str = "1.0.0";
@@ -29,6 +59,14 @@ pub fn load_pack_manifest(path: impl AsRef<Path>) -> Result<PackManifest> {
             manifest.schema_version
         ));
     }
+    let base = path
+        .parent()
+        .ok_or_else(|| anyhow!("pack manifest has no parent directory"))?;
+    if let Some(grub) = &manifest.grub {
+        phoenix_bootcfg::validate_grub_menu(grub, base)?;
+    }
+    validate_payloads(&manifest.payloads)?;
+    validate_transforms(&manifest.transforms, base)?;
     Ok(manifest)
 }
 
@@ -72,6 +110,33 @@ pub fn export_pack_zip(
         let assets_path = base.join(assets);
         add_dir_to_zip(&mut zip, base, &assets_path, options)?;
     }
+    if let Some(theme) = manifest.grub.as_ref().and_then(|grub| grub.theme.as_ref()) {
+        let in_assets = manifest
+            .assets
+            .as_ref()
+            .is_some_and(|assets| Path::new(theme).starts_with(assets));
+        if !in_assets {
+            add_dir_to_zip(&mut zip, base, &base.join(theme), options)?;
+        }
+    }
+    for asset in &manifest.cached_assets {
+        let in_assets = manifest
+            .assets
+            .as_ref()
+            .is_some_and(|assets| Path::new(&asset.source).starts_with(assets));
+        if !phoenix_fetch::is_url(&asset.source) && !in_assets {
+            add_file_to_zip(&mut zip, base, &base.join(&asset.source), options)?;
+        }
+    }
+    for payload in &manifest.payloads {
+        let in_assets = manifest
+            .assets
+            .as_ref()
+            .is_some_and(|assets| Path::new(&payload.path).starts_with(assets));
+        if !in_assets {
+            add_dir_to_zip(&mut zip, base, &base.join(&payload.path), options)?;
+        }
+    }
     let sig_path = manifest_path.with_extension("sig");
     if sig_path.exists() {
         add_file_to_zip(&mut zip, base, &sig_path, options)?;
@@ -81,10 +146,88 @@ pub fn export_pack_zip(
     Ok(output_path)
 }
 
+/// Step action replaced on load by the steps of another definition file,
+/// named by `params.path` relative to the including file. The included
+/// step ids are prefixed with the include step's id, e.g. `prep.format`.
+pub const INCLUDE_ACTION: &str = "include";
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct IncludeParams {
+    path: String,
+}
+
+/// Loads a definition and expands its `include` steps, recursively.
 pub fn load_workflow_definition(path: impl AsRef<Path>) -> Result<WorkflowDefinition> {
-    let path = path.as_ref();
+    load_with_includes(path.as_ref(), &mut Vec::new())
+}
+
+/// `stack` holds the canonical paths of the files being expanded, so a
+/// file that includes itself, directly or not, fails instead of looping.
+fn load_with_includes(path: &Path, stack: &mut Vec<PathBuf>) -> Result<WorkflowDefinition> {
+    let canonical = path
+        .canonicalize()
+        .map_err(|err| anyhow!("read workflow {} failed: {}", path.display(), err))?;
+    if let Some(start) = stack.iter().position(|seen| *seen == canonical) {
+        let cycle = stack[start..]
+            .iter()
+            .chain(std::iter::once(&canonical))
+            .map(|path| path.display().to_string())
+            .collect::<Vec<_>>()
+            .join(" -> ");
+        return Err(anyhow!("workflow include cycle: {}", cycle));
+    }
     let data = std::fs::read_to_string(path)?;
-    parse_by_extension(path, &data)
+    let mut definition: WorkflowDefinition = parse_by_extension(path, &data)?;
+    if !definition.steps.iter().any(|step| step.action == INCLUDE_ACTION) {
+        return Ok(definition);
+    }
+
+    let base = canonical
+        .parent()
+        .ok_or_else(|| anyhow!("workflow has no parent directory"))?
+        .to_path_buf();
+    stack.push(canonical);
+    let mut steps = Vec::with_capacity(definition.steps.len());
+    for step in std::mem::take(&mut definition.steps) {
+        if step.action != INCLUDE_ACTION {
+            steps.push(step);
+            continue;
+        }
+        let included = expand_include(&step, &base, stack)
+            .map_err(|err| anyhow!("step {}: {}", step.id, err))?;
+        if included.schema_version != definition.schema_version {
+            return Err(anyhow!(
+                "step {}: included schema version {} does not match {}",
+                step.id,
+                included.schema_version,
+                definition.schema_version
+            ));
+        }
+        steps.extend(included.steps.into_iter().map(|mut inner| {
+            inner.id = format!("{}.{}", step.id, inner.id);
+            inner
+        }));
+    }
+    stack.pop();
+    definition.steps = steps;
+    Ok(definition)
+}
+
+fn expand_include(
+    step: &phoenix_core::WorkflowStep,
+    base: &Path,
+    stack: &mut Vec<PathBuf>,
+) -> Result<WorkflowDefinition> {
+    let params: IncludeParams = serde_json::from_value(step.params.clone())
+        .map_err(|err| anyhow!("invalid include params: {}", err))?;
+    if step.timeout_secs.is_some() {
+        return Err(anyhow!("timeout_secs belongs on the included steps"));
+    }
+    if step.id.trim().is_empty() {
+        return Err(anyhow!("include step id is empty"));
+    }
+    load_with_includes(&base.join(params.path), stack)
 }
 
 pub fn sign_pack_manifest(path: impl AsRef<Path>, signing_key_hex: &str) -> Result<PathBuf> {
@@ -131,7 +274,7 @@ fn add_file_to_zip(
     zip: &mut ZipWriter<std::fs::File>,
     base: &Path,
     path: &Path,
-    options: FileOptions,
+    options: FileOptions<'static, ()>,
 ) -> Result<()> {
     if !path.exists() {
         return Err(anyhow!("missing pack file {}", path.display()));
@@ -141,9 +284,10 @@ fn add_file_to_zip(
         .unwrap_or(path)
         .to_string_lossy()
         .replace('\\', "/");
-    zip.start_file(rel, options)?;
-    let data = std::fs::read(path)?;
-    zip.write_all(&data)?;
+    let large = std::fs::metadata(path)?.len() >= u32::MAX as u64;
+    zip.start_file(rel, options.large_file(large))?;
+    let mut input = std::fs::File::open(path)?;
+    std::io::copy(&mut input, zip)?;
     Ok(())
 }
 
@@ -151,7 +295,7 @@ fn add_dir_to_zip(
     zip: &mut ZipWriter<std::fs::File>,
     base: &Path,
     dir: &Path,
-    options: FileOptions,
+    options: FileOptions<'static, ()>,
 ) -> Result<()> {
     if !dir.exists() {
         return Ok(());
@@ -170,7 +314,7 @@ fn add_dir_to_zip(
 
 fn decode_hex(value: &str) -> Result<Vec<u8>> {
     let value = value.trim();
-    if value.len() % 2 != 0 {
+    if !value.len().is_multiple_of(2) {
         return Err(anyhow!("signing key hex must be even length"));
     }
     let raw = value.as_bytes();
@@ -200,12 +344,12 @@ fn hmac_sha256(key: &[u8], message: &[u8]) -> [u8; 32] {
     }
 
     let mut inner = Sha256::new();
-    inner.update(&i_key);
+    inner.update(i_key);
     inner.update(message);
     let inner_hash = inner.finalize();
 
     let mut outer = Sha256::new();
-    outer.update(&o_key);
+    outer.update(o_key);
     outer.update(inner_hash);
     let digest = outer.finalize();
 
@@ -314,43 +458,76 @@ fn is_wim(path: &Path) -> bool {
 mod windows_impl {
     use super::{PreparedSource, SourceKind};
     use anyhow::{anyhow, Result};
-    use std::collections::HashSet;
+    use std::ffi::c_void;
     use std::path::{Path, PathBuf};
     use std::time::{Duration, Instant};
 
     use windows::core::PCWSTR;
     use windows::Win32::Foundation::{CloseHandle, HANDLE, INVALID_HANDLE_VALUE};
-    use windows::Win32::Storage::FileSystem::GetLogicalDrives;
+    use windows::Win32::Storage::FileSystem::{
+        CreateFileW, DeleteVolumeMountPointW, FindFirstVolumeW, FindNextVolumeW,
+        FindVolumeClose, GetLogicalDrives, GetVolumePathNamesForVolumeNameW,
+        SetVolumeMountPointW, FILE_ATTRIBUTE_NORMAL, FILE_SHARE_READ, FILE_SHARE_WRITE,
+        OPEN_EXISTING,
+    };
     use windows::Win32::Storage::Vhd::{
-        AttachVirtualDisk, DetachVirtualDisk, OpenVirtualDisk, ATTACH_VIRTUAL_DISK_FLAG_READ_ONLY,
-        ATTACH_VIRTUAL_DISK_PARAMETERS, ATTACH_VIRTUAL_DISK_VERSION_1,
-        DETACH_VIRTUAL_DISK_FLAG_NONE, OPEN_VIRTUAL_DISK_FLAG_NONE,
-        OPEN_VIRTUAL_DISK_PARAMETERS, OPEN_VIRTUAL_DISK_VERSION_1, VIRTUAL_DISK_ACCESS_READ,
-        VIRTUAL_STORAGE_TYPE, VIRTUAL_STORAGE_TYPE_DEVICE_ISO,
+        AttachVirtualDisk, DetachVirtualDisk, GetVirtualDiskPhysicalPath, OpenVirtualDisk,
+        ATTACH_VIRTUAL_DISK_FLAG_READ_ONLY, ATTACH_VIRTUAL_DISK_PARAMETERS,
+        ATTACH_VIRTUAL_DISK_VERSION_1, DETACH_VIRTUAL_DISK_FLAG_NONE,
+        OPEN_VIRTUAL_DISK_FLAG_NONE, OPEN_VIRTUAL_DISK_PARAMETERS, OPEN_VIRTUAL_DISK_VERSION_1,
+        VIRTUAL_DISK_ACCESS_READ, VIRTUAL_STORAGE_TYPE, VIRTUAL_STORAGE_TYPE_DEVICE_ISO,
         VIRTUAL_STORAGE_TYPE_VENDOR_MICROSOFT,
     };
+    use windows::Win32::System::Ioctl::{IOCTL_STORAGE_GET_DEVICE_NUMBER, STORAGE_DEVICE_NUMBER};
+    use windows::Win32::System::IO::DeviceIoControl;
 
+    /// How long the attached disc's volume may take to arrive.
+    const VOLUME_WAIT: Duration = Duration::from_secs(20);
+
+    /// Finds the volume of the attached disc itself rather than waiting for
+    /// a new drive letter: with automount disabled none ever appears. A
+    /// volume without a mount point gets the first free letter, or is used
+    /// through its `\\?\Volume{GUID}\` name when none is free.
     pub fn mount_iso(path: &Path) -> Result<PreparedSource> {
-        let before = logical_drive_letters();
         let handle = open_virtual_disk(path)?;
+        // Dropping it detaches the disc, so no error below leaves it attached.
+        let mut mount = IsoMount {
+            handle,
+            assigned: None,
+        };
         attach_read_only(handle)?;
-        let letter = wait_for_new_drive_letter(&before, Duration::from_secs(20))?;
-        let root = PathBuf::from(format!("{}:\\", letter));
+        let volume = wait_for_volume(&physical_path(handle)?, VOLUME_WAIT)?;
+        let root = match volume_path_names(&volume).into_iter().next() {
+            Some(root) => PathBuf::from(root),
+            None => match assign_drive_letter(&volume) {
+                Some(root) => {
+                    mount.assigned = Some(root.clone());
+                    PathBuf::from(root)
+                }
+                None => PathBuf::from(&volume),
+            },
+        };
         Ok(PreparedSource {
             root,
             kind: SourceKind::Iso,
-            _mount: Some(IsoMount { handle }),
+            _mount: Some(mount),
         })
     }
 
     #[derive(Debug)]
     struct IsoMount {
         handle: HANDLE,
+        /// Drive root assigned by `mount_iso`, removed before detaching.
+        assigned: Option<String>,
     }
 
     impl Drop for IsoMount {
         fn drop(&mut self) {
             unsafe {
+                if let Some(root) = &self.assigned {
+                    let root = wide(Path::new(root));
+                    let _ = DeleteVolumeMountPointW(PCWSTR(root.as_ptr()));
+                }
                 let _ = DetachVirtualDisk(self.handle, DETACH_VIRTUAL_DISK_FLAG_NONE, 0);
                 let _ = CloseHandle(self.handle);
             }
@@ -408,36 +585,133 @@ mod windows_impl {
         Ok(())
     }
 
-    fn logical_drive_letters() -> Vec<char> {
+    /// `\\.\CDROMn` of the attached disc.
+    fn physical_path(handle: HANDLE) -> Result<String> {
+        let mut buf = [0u16; 260];
+        let mut size = (buf.len() * 2) as u32;
         unsafe {
-            let mask = GetLogicalDrives();
-            let mut letters = Vec::new();
-            for (idx, letter) in ('A'..='Z').enumerate() {
-                if mask & (1u32 << idx) != 0 {
-                    letters.push(letter);
-                }
-            }
-            letters
+            GetVirtualDiskPhysicalPath(handle, &mut size, windows::core::PWSTR(buf.as_mut_ptr()))
+                .ok()
+                .map_err(|error| anyhow!("GetVirtualDiskPhysicalPath failed: {:?}", error))?;
         }
+        Ok(from_wide(&buf))
     }
 
-    fn wait_for_new_drive_letter(before: &[char], timeout: Duration) -> Result<char> {
-        let before_set: HashSet<char> = before.iter().copied().collect();
+    /// The volume whose storage device is `physical`, polled until it
+    /// arrives.
+    fn wait_for_volume(physical: &str, timeout: Duration) -> Result<String> {
+        let wanted = device_number(physical)
+            .ok_or_else(|| anyhow!("no device number for {}", physical))?;
         let start = Instant::now();
         loop {
-            let now = logical_drive_letters();
-            for letter in now {
-                if !before_set.contains(&letter) {
-                    return Ok(letter);
-                }
+            let found = volume_names()?.into_iter().find(|volume| {
+                device_number(volume.trim_end_matches('\\')).is_some_and(|number| {
+                    number.DeviceType == wanted.DeviceType
+                        && number.DeviceNumber == wanted.DeviceNumber
+                })
+            });
+            if let Some(volume) = found {
+                return Ok(volume);
             }
             if start.elapsed() > timeout {
-                return Err(anyhow!("timed out waiting for ISO mount"));
+                return Err(anyhow!("no volume appeared on {}", physical));
             }
             std::thread::sleep(Duration::from_millis(250));
         }
     }
 
+    fn device_number(path: &str) -> Option<STORAGE_DEVICE_NUMBER> {
+        let path = wide(Path::new(path));
+        unsafe {
+            // The storage number query needs no access rights.
+            let handle = CreateFileW(
+                PCWSTR(path.as_ptr()),
+                0,
+                FILE_SHARE_READ | FILE_SHARE_WRITE,
+                None,
+                OPEN_EXISTING,
+                FILE_ATTRIBUTE_NORMAL,
+                None,
+            )
+            .ok()?;
+            if handle == INVALID_HANDLE_VALUE {
+                return None;
+            }
+            let mut number = STORAGE_DEVICE_NUMBER::default();
+            let mut returned = 0u32;
+            let status = DeviceIoControl(
+                handle,
+                IOCTL_STORAGE_GET_DEVICE_NUMBER,
+                None,
+                0,
+                Some(&mut number as *mut STORAGE_DEVICE_NUMBER as *mut c_void),
+                std::mem::size_of::<STORAGE_DEVICE_NUMBER>() as u32,
+                Some(&mut returned),
+                None,
+            );
+            let _ = CloseHandle(handle);
+            status.ok().map(|_| number)
+        }
+    }
+
+    /// `\\?\Volume{GUID}\` of every volume.
+    fn volume_names() -> Result<Vec<String>> {
+        let mut names = Vec::new();
+        let mut buf = [0u16; 64];
+        unsafe {
+            let find = FindFirstVolumeW(&mut buf)
+                .map_err(|error| anyhow!("FindFirstVolumeW failed: {:?}", error))?;
+            loop {
+                names.push(from_wide(&buf));
+                if FindNextVolumeW(find, &mut buf).is_err() {
+                    break;
+                }
+            }
+            let _ = FindVolumeClose(find);
+        }
+        Ok(names)
+    }
+
+    fn volume_path_names(volume: &str) -> Vec<String> {
+        let name = wide(Path::new(volume));
+        let mut buf = vec![0u16; 1024];
+        let mut needed = 0u32;
+        let listed = unsafe {
+            GetVolumePathNamesForVolumeNameW(PCWSTR(name.as_ptr()), Some(&mut buf), &mut needed)
+        };
+        if listed.is_err() {
+            return Vec::new();
+        }
+        // A double-NUL-terminated list of NUL-terminated paths.
+        buf.split(|c| *c == 0)
+            .take_while(|path| !path.is_empty())
+            .map(String::from_utf16_lossy)
+            .collect()
+    }
+
+    /// Mounts `volume` on the last free drive letter, away from the ones
+    /// removable media usually take, and returns its root.
+    fn assign_drive_letter(volume: &str) -> Option<String> {
+        let used = unsafe { GetLogicalDrives() };
+        let name = wide(Path::new(volume));
+        ('D'..='Z').rev().find_map(|letter| {
+            let index = letter as u32 - 'A' as u32;
+            if used & (1 << index) != 0 {
+                return None;
+            }
+            let root = format!("{}:\\", letter);
+            let root_wide = wide(Path::new(&root));
+            unsafe { SetVolumeMountPointW(PCWSTR(root_wide.as_ptr()), PCWSTR(name.as_ptr())) }
+                .ok()
+                .map(|_| root)
+        })
+    }
+
+    fn from_wide(buf: &[u16]) -> String {
+        let len = buf.iter().position(|c| *c == 0).unwrap_or(buf.len());
+        String::from_utf16_lossy(&buf[..len])
+    }
+
     fn wide(path: &Path) -> Vec<u16> {
         use std::os::windows::prelude::*;
         path.as_os_str()
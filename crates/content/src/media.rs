@@ -0,0 +1,157 @@
+//! `phoenix_media.toml`, the label a finished stick carries at its root:
+//! the pack that built it, when, the machines it is for and whom to call.
+//! `media-info` and desktop hosts read it so a tech can identify an
+//! unlabeled stick without running anything from it.
+//!
+//! ```toml
+//! pack_name = "Shop Windows 11"
+//! pack_version = "2.3.0"
+//! build_date = "2026-10-16T21:04:11Z"
+//! supported_targets = ["x64 UEFI", "Surface Pro 7"]
+//! support_contact = "helpdesk@example.com"
+//! ```
+
+use crate::PackManifest;
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const MEDIA_MANIFEST_FILE: &str = "phoenix_media.toml";
+
+/// Branding a pack puts on the media it builds.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PackMedia {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub supported_targets: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub support_contact: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS))]
+pub struct MediaManifest {
+    pub pack_name: String,
+    pub pack_version: String,
+    /// RFC 3339, UTC.
+    pub build_date: String,
+    #[serde(default)]
+    pub supported_targets: Vec<String>,
+    #[serde(default)]
+    pub support_contact: Option<String>,
+}
+
+impl MediaManifest {
+    /// The label for media `pack` builds now.
+    pub fn for_pack(pack: &PackManifest) -> Self {
+        let media = pack.media.clone().unwrap_or_default();
+        Self {
+            pack_name: pack.name.clone(),
+            pack_version: pack.version.clone(),
+            build_date: phoenix_core::now_utc_rfc3339(),
+            supported_targets: media.supported_targets,
+            support_contact: media.support_contact,
+        }
+    }
+
+    /// Parses the file's text. Keys this version does not know are
+    /// ignored, so newer media still identifies.
+    pub fn from_toml(text: &str) -> Result<Self> {
+        let document: toml_edit::DocumentMut = text
+            .parse()
+            .map_err(|err| anyhow!("invalid {}: {}", MEDIA_MANIFEST_FILE, err))?;
+        let string = |key: &str| -> Result<Option<String>> {
+            match document.get(key) {
+                None => Ok(None),
+                Some(item) => item
+                    .as_str()
+                    .map(|value| Some(value.to_string()))
+                    .ok_or_else(|| anyhow!("{} must be a string", key)),
+            }
+        };
+        let required =
+            |key: &str| string(key)?.ok_or_else(|| anyhow!("{} is missing {}", MEDIA_MANIFEST_FILE, key));
+        let supported_targets = match document.get("supported_targets") {
+            None => Vec::new(),
+            Some(item) => item
+                .as_array()
+                .ok_or_else(|| anyhow!("supported_targets must be an array"))?
+                .iter()
+                .map(|value| {
+                    value
+                        .as_str()
+                        .map(str::to_string)
+                        .ok_or_else(|| anyhow!("supported_targets entries must be strings"))
+                })
+                .collect::<Result<_>>()?,
+        };
+        Ok(Self {
+            pack_name: required("pack_name")?,
+            pack_version: required("pack_version")?,
+            build_date: required("build_date")?,
+            supported_targets,
+            support_contact: string("support_contact")?,
+        })
+    }
+
+    pub fn to_toml(&self) -> String {
+        let mut text = format!(
+            "pack_name = {}\npack_version = {}\nbuild_date = {}\n",
+            toml_string(&self.pack_name),
+            toml_string(&self.pack_version),
+            toml_string(&self.build_date)
+        );
+        if !self.supported_targets.is_empty() {
+            let targets: Vec<String> = self
+                .supported_targets
+                .iter()
+                .map(|target| toml_string(target))
+                .collect();
+            text.push_str(&format!("supported_targets = [{}]\n", targets.join(", ")));
+        }
+        if let Some(contact) = &self.support_contact {
+            text.push_str(&format!("support_contact = {}\n", toml_string(contact)));
+        }
+        text
+    }
+}
+
+/// The label at the root of `mount`, or `None` when it has none.
+pub fn read_media_manifest(mount: impl AsRef<Path>) -> Result<Option<MediaManifest>> {
+    let path = mount.as_ref().join(MEDIA_MANIFEST_FILE);
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(anyhow!("read {} failed: {}", path.display(), err)),
+    };
+    MediaManifest::from_toml(&text)
+        .map(Some)
+        .with_context(|| format!("parse {}", path.display()))
+}
+
+/// Writes `manifest` to the root of `mount`, replacing any label there.
+pub fn write_media_manifest(mount: impl AsRef<Path>, manifest: &MediaManifest) -> Result<PathBuf> {
+    let path = mount.as_ref().join(MEDIA_MANIFEST_FILE);
+    fs::write(&path, manifest.to_toml()).with_context(|| format!("write {}", path.display()))?;
+    Ok(path)
+}
+
+/// A TOML basic string.
+fn toml_string(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04X}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
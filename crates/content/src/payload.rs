@@ -0,0 +1,168 @@
+//! Rescue and diagnostics payloads a pack ships for combo sticks: memtest,
+//! partition editors, SMART tools. Each one is a directory in the pack
+//! with the SHA-256 of every file listed in the manifest, so a signed
+//! manifest vouches for the payload files as well.
+//!
+//! ```json
+//! "payloads": [{
+//!   "name": "memtest86plus",
+//!   "title": "Memory Test (memtest86+)",
+//!   "path": "payloads/memtest86plus",
+//!   "efi": "memtest64.efi",
+//!   "files": {"memtest64.efi": "9f2c..."}
+//! }]
+//! ```
+
+use crate::{to_hex, PackManifest};
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PackPayload {
+    /// Directory the payload is staged to; letters, digits, `-` and `_`.
+    pub name: String,
+    /// Boot menu title of an EFI payload; the name when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    /// Directory in the pack holding the payload.
+    pub path: String,
+    /// EFI program the boot menu chainloads, relative to `path`. Tools run
+    /// from the rescue system have none.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub efi: Option<String>,
+    /// SHA-256 of every file under `path`, keyed by `/`-separated path.
+    pub files: BTreeMap<String, String>,
+}
+
+/// A payload file checked against its manifest hash.
+#[derive(Debug, Clone)]
+pub struct PayloadFile {
+    pub absolute_path: PathBuf,
+    /// Relative to the payload directory, `/`-separated.
+    pub relative_path: String,
+    pub size: u64,
+}
+
+/// Checks names and the `efi` entry; called when the manifest loads.
+pub fn validate_payloads(payloads: &[PackPayload]) -> Result<()> {
+    let mut names = HashSet::new();
+    for payload in payloads {
+        let valid_name = !payload.name.is_empty()
+            && payload
+                .name
+                .chars()
+                .all(|ch| ch.is_ascii_alphanumeric() || ch == '-' || ch == '_');
+        if !valid_name {
+            return Err(anyhow!("invalid payload name: {:?}", payload.name));
+        }
+        if !names.insert(payload.name.to_ascii_lowercase()) {
+            return Err(anyhow!("duplicate payload name: {}", payload.name));
+        }
+        if payload.files.is_empty() {
+            return Err(anyhow!("payload {} lists no files", payload.name));
+        }
+        if let Some(efi) = &payload.efi {
+            if !payload.files.contains_key(efi) {
+                return Err(anyhow!("payload {} efi {} is not in its files", payload.name, efi));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The payloads of `manifest` named in `names`, in manifest order; all of
+/// them when `names` is empty.
+pub fn select_payloads<'a>(manifest: &'a PackManifest, names: &[String]) -> Result<Vec<&'a PackPayload>> {
+    for name in names {
+        if !manifest.payloads.iter().any(|payload| payload.name.eq_ignore_ascii_case(name)) {
+            return Err(anyhow!("pack {} has no payload {}", manifest.name, name));
+        }
+    }
+    Ok(manifest
+        .payloads
+        .iter()
+        .filter(|payload| {
+            names.is_empty() || names.iter().any(|name| payload.name.eq_ignore_ascii_case(name))
+        })
+        .collect())
+}
+
+/// The files of `payload` in the pack at `pack_root`, each hashed and
+/// compared with the manifest. A changed, missing or unlisted file fails.
+pub fn verify_payload(pack_root: &Path, payload: &PackPayload) -> Result<Vec<PayloadFile>> {
+    let root = pack_root.join(&payload.path);
+    let found = payload_file_hashes(&root)
+        .with_context(|| format!("payload {}", payload.name))?;
+    for path in found.keys() {
+        if !payload.files.contains_key(path) {
+            return Err(anyhow!("payload {} has unlisted file {}", payload.name, path));
+        }
+    }
+    let mut files = Vec::new();
+    for (path, expected) in &payload.files {
+        let actual = found
+            .get(path)
+            .ok_or_else(|| anyhow!("payload {} is missing {}", payload.name, path))?;
+        if !actual.eq_ignore_ascii_case(expected) {
+            return Err(anyhow!(
+                "payload {} file {} has sha256 {} but the manifest lists {}",
+                payload.name,
+                path,
+                actual,
+                expected
+            ));
+        }
+        let absolute_path = root.join(path);
+        files.push(PayloadFile {
+            size: fs::metadata(&absolute_path)?.len(),
+            absolute_path,
+            relative_path: path.clone(),
+        });
+    }
+    Ok(files)
+}
+
+/// SHA-256 of every file under `root`, keyed by `/`-separated relative
+/// path; what a pack author pastes into `files`.
+pub fn payload_file_hashes(root: &Path) -> Result<BTreeMap<String, String>> {
+    let mut hashes = BTreeMap::new();
+    hash_dir(root, root, &mut hashes)?;
+    Ok(hashes)
+}
+
+fn hash_dir(root: &Path, dir: &Path, hashes: &mut BTreeMap<String, String>) -> Result<()> {
+    for entry in fs::read_dir(dir).with_context(|| format!("read {}", dir.display()))? {
+        let path = entry?.path();
+        if path.is_dir() {
+            hash_dir(root, &path, hashes)?;
+            continue;
+        }
+        let relative = path
+            .strip_prefix(root)
+            .context("strip payload prefix")?
+            .to_string_lossy()
+            .replace('\\', "/");
+        hashes.insert(relative, hash_file(&path)?);
+    }
+    Ok(())
+}
+
+fn hash_file(path: &Path) -> Result<String> {
+    let mut file = fs::File::open(path).with_context(|| format!("open {}", path.display()))?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; 1024 * 1024];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(to_hex(&hasher.finalize()))
+}
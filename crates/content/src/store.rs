@@ -0,0 +1,408 @@
+//! Content-addressed store for the large assets packs reference (driver
+//! bundles, ISOs), so runs and packs sharing an asset copy or download it
+//! once. Assets are named by their SHA-256: `<root>/sha256/<digest>` holds
+//! the bytes and `<digest>.json` next to it the `CachedAsset` record.
+//! `<root>/named/<digest>/` holds hard links giving an asset a file name.
+//! The eviction policy lives in `<root>/cache.json`.
+
+use crate::{load_pack_manifest, to_hex};
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub const CACHE_POLICY_FILE: &str = "cache.json";
+
+/// Names partial files, so concurrent adds in one process do not collide.
+static NEXT_TMP: AtomicU64 = AtomicU64::new(0);
+
+/// ```json
+/// { "max_total_bytes": 214748364800, "max_age_days": 60 }
+/// ```
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CachePolicy {
+    #[serde(default)]
+    pub max_total_bytes: Option<u64>,
+    /// Assets not used for this long are evicted.
+    #[serde(default)]
+    pub max_age_days: Option<u64>,
+}
+
+impl CachePolicy {
+    pub fn is_empty(&self) -> bool {
+        self.max_total_bytes.is_none() && self.max_age_days.is_none()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CachedAsset {
+    pub sha256: String,
+    pub size_bytes: u64,
+    /// Paths and URLs the bytes were taken from.
+    #[serde(default)]
+    pub sources: Vec<String>,
+    pub added_unix: u64,
+    pub last_used_unix: u64,
+}
+
+/// A large file a pack references by digest; `source` is a path relative
+/// to the manifest or an http(s) URL.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PackAsset {
+    pub name: String,
+    pub sha256: String,
+    pub source: String,
+    /// Other URLs serving the same file as a URL `source`; the download
+    /// uses the fastest healthy one and fails over between them.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub mirrors: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StoredAsset {
+    pub name: String,
+    pub sha256: String,
+    pub path: PathBuf,
+    pub size_bytes: u64,
+    /// Already in the store; nothing was copied or downloaded.
+    pub reused: bool,
+    /// What each URL did when the asset was downloaded.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub mirrors: Vec<phoenix_fetch::MirrorStats>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct EvictResult {
+    /// Digests removed (or, in a dry run, that would be).
+    pub evicted: Vec<String>,
+    pub evicted_bytes: u64,
+    pub kept: usize,
+    pub kept_bytes: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct AssetStore {
+    root: PathBuf,
+}
+
+impl AssetStore {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// `<root>/cache.json`; the empty policy when there is none.
+    pub fn policy(&self) -> Result<CachePolicy> {
+        let path = self.root.join(CACHE_POLICY_FILE);
+        let bytes = match fs::read(&path) {
+            Ok(bytes) => bytes,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(CachePolicy::default())
+            }
+            Err(err) => return Err(anyhow!("read {} failed: {}", path.display(), err)),
+        };
+        serde_json::from_slice(&bytes).with_context(|| format!("parse {}", path.display()))
+    }
+
+    /// Path of the asset with `sha256`, marked as used; `None` when the
+    /// store does not hold it.
+    pub fn get(&self, sha256: &str) -> Result<Option<PathBuf>> {
+        let sha256 = normalize_digest(sha256)?;
+        let path = self.blob_path(&sha256);
+        if !path.is_file() {
+            return Ok(None);
+        }
+        let mut record = self.record(&sha256)?;
+        record.last_used_unix = now_unix();
+        self.write_record(&record)?;
+        Ok(Some(path))
+    }
+
+    /// `get`, as a hard link called `name` under `<root>/named/<digest>/`,
+    /// for readers that go by the file extension (ISO mounting, WIM
+    /// detection).
+    pub fn get_named(&self, sha256: &str, name: &str) -> Result<Option<PathBuf>> {
+        if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\\']) {
+            return Err(anyhow!("asset name {:?} is not a file name", name));
+        }
+        let Some(blob) = self.get(sha256)? else {
+            return Ok(None);
+        };
+        let dir = self.named_dir(&normalize_digest(sha256)?);
+        let link = dir.join(name);
+        if !link.is_file() {
+            fs::create_dir_all(&dir).with_context(|| format!("create {}", dir.display()))?;
+            fs::hard_link(&blob, &link).with_context(|| format!("link {}", link.display()))?;
+        }
+        Ok(Some(link))
+    }
+
+    /// Copies `path` into the store, unless its digest is already there.
+    /// With `expected`, bytes with another digest are refused.
+    pub fn add_file(&self, path: &Path, expected: Option<&str>) -> Result<(CachedAsset, bool)> {
+        if let Some(expected) = expected {
+            if let Some(record) = self.reuse(expected, &path.display().to_string())? {
+                return Ok((record, true));
+            }
+        }
+        let file = fs::File::open(path).with_context(|| format!("open {}", path.display()))?;
+        self.add_reader(file, expected, &path.display().to_string())
+    }
+
+    /// Stores what `reader` yields, e.g. a download. Returns the record and
+    /// whether the digest was already stored.
+    pub fn add_reader(
+        &self,
+        mut reader: impl Read,
+        expected: Option<&str>,
+        source: &str,
+    ) -> Result<(CachedAsset, bool)> {
+        let expected = expected.map(normalize_digest).transpose()?;
+        let tmp_dir = self.root.join("tmp");
+        fs::create_dir_all(&tmp_dir).with_context(|| format!("create {}", tmp_dir.display()))?;
+        let tmp = tmp_dir.join(format!(
+            "{}-{}.part",
+            std::process::id(),
+            NEXT_TMP.fetch_add(1, Ordering::Relaxed)
+        ));
+        let mut out = fs::File::create(&tmp).with_context(|| format!("create {}", tmp.display()))?;
+        let mut hasher = Sha256::new();
+        let mut size_bytes = 0u64;
+        let mut buf = vec![0u8; 1024 * 1024];
+        let copied = (|| -> Result<()> {
+            loop {
+                let read = reader.read(&mut buf).with_context(|| format!("read {}", source))?;
+                if read == 0 {
+                    break;
+                }
+                hasher.update(&buf[..read]);
+                out.write_all(&buf[..read])?;
+                size_bytes += read as u64;
+            }
+            out.sync_all()?;
+            Ok(())
+        })();
+        drop(out);
+        if let Err(err) = copied {
+            let _ = fs::remove_file(&tmp);
+            return Err(err);
+        }
+
+        let sha256 = to_hex(&hasher.finalize());
+        if let Some(expected) = &expected {
+            if *expected != sha256 {
+                let _ = fs::remove_file(&tmp);
+                return Err(anyhow!(
+                    "{} has sha256 {}, expected {}",
+                    source,
+                    sha256,
+                    expected
+                ));
+            }
+        }
+        if let Some(record) = self.reuse(&sha256, source)? {
+            let _ = fs::remove_file(&tmp);
+            return Ok((record, true));
+        }
+        let blob = self.blob_path(&sha256);
+        fs::create_dir_all(blob.parent().unwrap_or(&self.root))?;
+        fs::rename(&tmp, &blob).with_context(|| format!("store {}", blob.display()))?;
+        let now = now_unix();
+        let record = CachedAsset {
+            sha256,
+            size_bytes,
+            sources: vec![source.to_string()],
+            added_unix: now,
+            last_used_unix: now,
+        };
+        self.write_record(&record)?;
+        Ok((record, false))
+    }
+
+    /// Every stored asset, least recently used first.
+    pub fn list(&self) -> Result<Vec<CachedAsset>> {
+        let dir = self.root.join("sha256");
+        let mut assets = Vec::new();
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(assets),
+            Err(err) => return Err(anyhow!("read {} failed: {}", dir.display(), err)),
+        };
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "json") {
+                continue;
+            }
+            let Some(sha256) = path.file_name().and_then(|name| name.to_str()) else {
+                continue;
+            };
+            if let Ok(record) = self.record(sha256) {
+                assets.push(record);
+            }
+        }
+        assets.sort_by_key(|asset| asset.last_used_unix);
+        Ok(assets)
+    }
+
+    /// Applies `policy`, least recently used first. Digests in `keep` are
+    /// never evicted, so the assets of the pack about to run survive even
+    /// a policy smaller than them.
+    pub fn evict(
+        &self,
+        policy: &CachePolicy,
+        keep: &HashSet<String>,
+        dry_run: bool,
+    ) -> Result<EvictResult> {
+        let assets = self.list()?;
+        let mut total_bytes: u64 = assets.iter().map(|asset| asset.size_bytes).sum();
+        let cutoff = policy.max_age_days.map(|days| {
+            now_unix().saturating_sub(Duration::from_secs(days * 24 * 60 * 60).as_secs())
+        });
+        let mut result = EvictResult::default();
+        for asset in &assets {
+            let too_old = cutoff.is_some_and(|cutoff| asset.last_used_unix < cutoff);
+            let over_size = policy.max_total_bytes.is_some_and(|max| total_bytes > max);
+            if keep.contains(&asset.sha256) || !(too_old || over_size) {
+                result.kept += 1;
+                result.kept_bytes += asset.size_bytes;
+                continue;
+            }
+            if !dry_run {
+                let blob = self.blob_path(&asset.sha256);
+                fs::remove_file(&blob).with_context(|| format!("remove {}", blob.display()))?;
+                let _ = fs::remove_file(blob.with_extension("json"));
+                let _ = fs::remove_dir_all(self.named_dir(&asset.sha256));
+            }
+            total_bytes -= asset.size_bytes;
+            result.evicted.push(asset.sha256.clone());
+            result.evicted_bytes += asset.size_bytes;
+        }
+        Ok(result)
+    }
+
+    fn reuse(&self, sha256: &str, source: &str) -> Result<Option<CachedAsset>> {
+        let sha256 = normalize_digest(sha256)?;
+        if !self.blob_path(&sha256).is_file() {
+            return Ok(None);
+        }
+        let mut record = self.record(&sha256)?;
+        record.last_used_unix = now_unix();
+        if !record.sources.iter().any(|known| known == source) {
+            record.sources.push(source.to_string());
+        }
+        self.write_record(&record)?;
+        Ok(Some(record))
+    }
+
+    fn blob_path(&self, sha256: &str) -> PathBuf {
+        self.root.join("sha256").join(sha256)
+    }
+
+    fn named_dir(&self, sha256: &str) -> PathBuf {
+        self.root.join("named").join(sha256)
+    }
+
+    /// The record of a stored blob; one lost with a crash is rebuilt from
+    /// the blob's size.
+    fn record(&self, sha256: &str) -> Result<CachedAsset> {
+        let blob = self.blob_path(sha256);
+        if let Ok(bytes) = fs::read(blob.with_extension("json")) {
+            if let Ok(record) = serde_json::from_slice(&bytes) {
+                return Ok(record);
+            }
+        }
+        let metadata = fs::metadata(&blob).with_context(|| format!("stat {}", blob.display()))?;
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |age| age.as_secs());
+        Ok(CachedAsset {
+            sha256: sha256.to_string(),
+            size_bytes: metadata.len(),
+            sources: Vec::new(),
+            added_unix: modified,
+            last_used_unix: modified,
+        })
+    }
+
+    fn write_record(&self, record: &CachedAsset) -> Result<()> {
+        let path = self.blob_path(&record.sha256).with_extension("json");
+        fs::write(&path, serde_json::to_vec_pretty(record)?)
+            .with_context(|| format!("write {}", path.display()))
+    }
+}
+
+/// Puts every `cached_assets` entry of a pack into `store`, copying or
+/// downloading only the ones it lacks, then applies the store's policy
+/// without evicting this pack's assets.
+pub fn cache_pack_assets(
+    manifest_path: impl AsRef<Path>,
+    store: &AssetStore,
+) -> Result<(Vec<StoredAsset>, EvictResult)> {
+    let manifest_path = manifest_path.as_ref();
+    let manifest = load_pack_manifest(manifest_path)?;
+    let base = manifest_path
+        .parent()
+        .ok_or_else(|| anyhow!("pack manifest has no parent directory"))?;
+    let mut stored = Vec::new();
+    for asset in &manifest.cached_assets {
+        if !asset.mirrors.is_empty() && !phoenix_fetch::is_url(&asset.source) {
+            return Err(anyhow!("asset {}: mirrors need a URL source", asset.name));
+        }
+        let mut mirrors = Vec::new();
+        let (record, reused) = if phoenix_fetch::is_url(&asset.source) {
+            match store.reuse(&asset.sha256, &asset.source)? {
+                Some(record) => (record, true),
+                None => {
+                    let urls: Vec<String> = std::iter::once(asset.source.clone())
+                        .chain(asset.mirrors.iter().cloned())
+                        .collect();
+                    let mut source = phoenix_fetch::HttpSource::open_mirrors(&urls)?;
+                    let added = store.add_reader(&mut source, Some(&asset.sha256), &asset.source);
+                    if urls.len() > 1 {
+                        mirrors = source.mirror_stats();
+                    }
+                    added?
+                }
+            }
+        } else {
+            store.add_file(&base.join(&asset.source), Some(&asset.sha256))?
+        };
+        stored.push(StoredAsset {
+            name: asset.name.clone(),
+            path: store.blob_path(&record.sha256),
+            sha256: record.sha256,
+            size_bytes: record.size_bytes,
+            reused,
+            mirrors,
+        });
+    }
+    let keep: HashSet<String> = stored.iter().map(|asset| asset.sha256.clone()).collect();
+    let evicted = store.evict(&store.policy()?, &keep, false)?;
+    Ok((stored, evicted))
+}
+
+/// Lowercase hex of a SHA-256 digest.
+fn normalize_digest(sha256: &str) -> Result<String> {
+    let digest = sha256.trim().to_ascii_lowercase();
+    if digest.len() != 64 || !digest.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(anyhow!("{} is not a sha256 digest", sha256));
+    }
+    Ok(digest)
+}
+
+fn now_unix() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |age| age.as_secs())
+}
@@ -0,0 +1,268 @@
+//! Edits a pack makes to source files while they are staged: a GRUB
+//! timeout, extra kernel arguments, branding strings. Each transform names
+//! the files it applies to with a source-filter glob and is one of a regex
+//! substitution, a literal replacement, a unified diff or a template from
+//! the pack. Files are edited as UTF-8 text.
+
+use anyhow::{anyhow, Context, Result};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileTransform {
+    /// Shown in logs and the copy manifest; `<kind>#<index>` when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Source-filter glob of the files to edit, e.g. `boot/grub/grub.cfg`
+    /// or `*.cfg`.
+    pub path: String,
+    #[serde(flatten)]
+    pub edit: TransformEdit,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum TransformEdit {
+    /// Every match of `pattern` replaced; `$1` and `${name}` refer to
+    /// capture groups.
+    Regex { pattern: String, replacement: String },
+    /// Every occurrence of `find` replaced.
+    Replace { find: String, replace: String },
+    /// Unified diff in the pack, relative to the manifest. Each hunk must
+    /// find its context, though it may have moved.
+    Patch { file: String },
+    /// Template in the pack that replaces the file. `{{name}}` expands to
+    /// `vars`, `original` (the source text), `path`, `pack_name` or
+    /// `pack_version`.
+    Template {
+        file: String,
+        #[serde(default)]
+        vars: BTreeMap<String, String>,
+    },
+}
+
+impl TransformEdit {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Regex { .. } => "regex",
+            Self::Replace { .. } => "replace",
+            Self::Patch { .. } => "patch",
+            Self::Template { .. } => "template",
+        }
+    }
+}
+
+impl FileTransform {
+    pub fn label(&self, index: usize) -> String {
+        self.name
+            .clone()
+            .unwrap_or_else(|| format!("{}#{}", self.edit.kind(), index))
+    }
+}
+
+/// What a template can refer to besides its own `vars`.
+pub struct TransformContext<'a> {
+    /// Directory holding the pack manifest.
+    pub pack_root: &'a Path,
+    pub pack_name: &'a str,
+    pub pack_version: &'a str,
+    /// The file's path relative to the source root, `/`-separated.
+    pub path: &'a str,
+}
+
+/// Checks patterns and pack files; called when the manifest loads.
+pub fn validate_transforms(transforms: &[FileTransform], pack_root: &Path) -> Result<()> {
+    for (index, transform) in transforms.iter().enumerate() {
+        let label = transform.label(index);
+        if transform.path.trim().is_empty() {
+            return Err(anyhow!("transform {} has an empty path", label));
+        }
+        match &transform.edit {
+            TransformEdit::Regex { pattern, .. } => {
+                Regex::new(pattern).with_context(|| format!("transform {} pattern", label))?;
+            }
+            TransformEdit::Replace { find, .. } if find.is_empty() => {
+                return Err(anyhow!("transform {} has an empty find string", label));
+            }
+            TransformEdit::Replace { .. } => {}
+            TransformEdit::Patch { file } => {
+                parse_hunks(&read_pack_text(pack_root, file)?)
+                    .with_context(|| format!("transform {} patch {}", label, file))?;
+            }
+            TransformEdit::Template { file, .. } => {
+                read_pack_text(pack_root, file)
+                    .with_context(|| format!("transform {}", label))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// `text` with `transform` applied.
+pub fn apply_transform(
+    transform: &FileTransform,
+    context: &TransformContext,
+    text: &str,
+) -> Result<String> {
+    match &transform.edit {
+        TransformEdit::Regex { pattern, replacement } => {
+            let regex = Regex::new(pattern)?;
+            Ok(regex.replace_all(text, replacement.as_str()).into_owned())
+        }
+        TransformEdit::Replace { find, replace } => Ok(text.replace(find.as_str(), replace)),
+        TransformEdit::Patch { file } => {
+            let hunks = parse_hunks(&read_pack_text(context.pack_root, file)?)?;
+            apply_hunks(text, &hunks)
+        }
+        TransformEdit::Template { file, vars } => {
+            let template = read_pack_text(context.pack_root, file)?;
+            render_template(&template, |name| match name {
+                "original" => Some(text),
+                "path" => Some(context.path),
+                "pack_name" => Some(context.pack_name),
+                "pack_version" => Some(context.pack_version),
+                other => vars.get(other).map(String::as_str),
+            })
+        }
+    }
+}
+
+fn read_pack_text(pack_root: &Path, file: &str) -> Result<String> {
+    if Path::new(file).is_absolute() || file.split(['/', '\\']).any(|part| part == "..") {
+        return Err(anyhow!("{} is outside the pack", file));
+    }
+    let path = pack_root.join(file);
+    std::fs::read_to_string(&path).with_context(|| format!("read {}", path.display()))
+}
+
+fn render_template<'a>(
+    template: &str,
+    lookup: impl Fn(&str) -> Option<&'a str>,
+) -> Result<String> {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let end = after
+            .find("}}")
+            .ok_or_else(|| anyhow!("unterminated {{{{ placeholder"))?;
+        let name = after[..end].trim();
+        let value = lookup(name).ok_or_else(|| anyhow!("undefined variable {}", name))?;
+        out.push_str(value);
+        rest = &after[end + 2..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+struct Hunk {
+    /// 1-based line the hunk expects to start at.
+    old_start: usize,
+    old: Vec<String>,
+    new: Vec<String>,
+}
+
+/// Reads a one-file unified diff. Lines before the first `@@` are headers;
+/// after that, the counts in each `@@` header say where a hunk ends, so a
+/// removed `-- x` or added `++ x` line is never taken for a header.
+fn parse_hunks(diff: &str) -> Result<Vec<Hunk>> {
+    let mut hunks: Vec<Hunk> = Vec::new();
+    let (mut old_left, mut new_left) = (0usize, 0usize);
+    for line in diff.lines() {
+        if line.starts_with('\\') {
+            // "\ No newline at end of file"
+            continue;
+        }
+        if let Some(hunk) = hunks.last_mut().filter(|_| old_left + new_left > 0) {
+            let (old, new) = match line.chars().next() {
+                Some(' ') => (true, true),
+                Some('-') => (true, false),
+                Some('+') => (false, true),
+                // Some editors strip the space from empty context lines.
+                None => (true, true),
+                _ => return Err(anyhow!("unexpected line in hunk: {}", line)),
+            };
+            if (old && old_left == 0) || (new && new_left == 0) {
+                return Err(anyhow!("hunk is longer than its header: {}", line));
+            }
+            let text = line.get(1..).unwrap_or_default().to_string();
+            if old {
+                hunk.old.push(text.clone());
+                old_left -= 1;
+            }
+            if new {
+                hunk.new.push(text);
+                new_left -= 1;
+            }
+            continue;
+        }
+        if let Some(header) = line.strip_prefix("@@ ") {
+            let bad = || anyhow!("bad hunk header: {}", line);
+            let mut ranges = header.split_whitespace();
+            let old = ranges.next().and_then(|range| range.strip_prefix('-')).ok_or_else(bad)?;
+            let new = ranges.next().and_then(|range| range.strip_prefix('+')).ok_or_else(bad)?;
+            let range = |range: &str| -> Option<(usize, usize)> {
+                match range.split_once(',') {
+                    Some((start, count)) => Some((start.parse().ok()?, count.parse().ok()?)),
+                    None => Some((range.parse().ok()?, 1)),
+                }
+            };
+            let (old_start, old_count) = range(old).ok_or_else(bad)?;
+            let (_, new_count) = range(new).ok_or_else(bad)?;
+            old_left = old_count;
+            new_left = new_count;
+            hunks.push(Hunk {
+                old_start,
+                old: Vec::new(),
+                new: Vec::new(),
+            });
+            continue;
+        }
+        // `diff` and `---`/`+++` headers come before the first hunk.
+        if !hunks.is_empty() && !line.trim().is_empty() {
+            return Err(anyhow!("unexpected line between hunks: {}", line));
+        }
+    }
+    if hunks.is_empty() {
+        return Err(anyhow!("no hunks"));
+    }
+    if old_left + new_left > 0 {
+        return Err(anyhow!("last hunk is shorter than its header"));
+    }
+    Ok(hunks)
+}
+
+/// Applies the hunks in order. A hunk is placed where its old lines
+/// match, nearest to where its header says, and never before the end of
+/// the previous hunk.
+fn apply_hunks(text: &str, hunks: &[Hunk]) -> Result<String> {
+    let eol = if text.contains("\r\n") { "\r\n" } else { "\n" };
+    let trailing = text.ends_with('\n');
+    let mut lines: Vec<String> = text.lines().map(str::to_string).collect();
+    let mut floor = 0usize;
+    let mut offset = 0isize;
+    for (index, hunk) in hunks.iter().enumerate() {
+        let expected = (hunk.old_start.saturating_sub(1) as isize + offset).max(0) as usize;
+        let fits = |at: usize| {
+            at + hunk.old.len() <= lines.len() && lines[at..at + hunk.old.len()] == hunk.old[..]
+        };
+        let last = lines.len().saturating_sub(hunk.old.len());
+        let at = (0..=last.max(expected))
+            .flat_map(|distance| [expected.checked_sub(distance), Some(expected + distance)])
+            .flatten()
+            .filter(|at| *at >= floor && *at <= last)
+            .find(|at| fits(*at))
+            .ok_or_else(|| anyhow!("hunk {} (line {}) does not apply", index + 1, hunk.old_start))?;
+        lines.splice(at..at + hunk.old.len(), hunk.new.iter().cloned());
+        floor = at + hunk.new.len();
+        offset += hunk.new.len() as isize - hunk.old.len() as isize;
+    }
+    let mut out = lines.join(eol);
+    if trailing && !out.is_empty() {
+        out.push_str(eol);
+    }
+    Ok(out)
+}
@@ -0,0 +1,326 @@
+//! Typed construction of workflow definitions, so Rust integrators get
+//! the param keys of each action checked at compile time instead of
+//! assembling JSON by hand:
+//!
+//! ```text
+//! let definition = WorkflowBuilder::new("windows-usb")
+//!     .step(WindowsInstallerUsb::new("2", "D:\\Win11.iso").dry_run(true))
+//!     .step(ReportVerify::new("reports/latest"))
+//!     .build()?;
+//! ```
+//!
+//! Params without a typed setter can still be set with `param`.
+
+use crate::{CoreError, WorkflowDefinition, WorkflowStep};
+use serde_json::{Map, Value};
+
+/// Action and params of one step.
+pub trait StepSpec {
+    fn action(&self) -> &'static str;
+    fn into_params(self) -> Value;
+}
+
+pub struct WorkflowBuilder {
+    definition: WorkflowDefinition,
+}
+
+impl WorkflowBuilder {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            definition: WorkflowDefinition::new(name, Vec::new()),
+        }
+    }
+
+    pub fn description(mut self, description: impl Into<String>) -> Self {
+        self.definition.description = Some(description.into());
+        self
+    }
+
+    pub fn idempotency_key(mut self, key: impl Into<String>) -> Self {
+        self.definition.idempotency_key = Some(key.into());
+        self
+    }
+
+    pub fn correlation_id(mut self, id: impl Into<String>) -> Self {
+        self.definition.correlation_id = Some(id.into());
+        self
+    }
+
+    pub fn timeout_secs(mut self, secs: u64) -> Self {
+        self.definition.timeout_secs = Some(secs);
+        self
+    }
+
+    /// Adds a step with the id `<action>-<position>`, e.g.
+    /// `report_verify-2`.
+    pub fn step(self, spec: impl StepSpec) -> Self {
+        let id = format!("{}-{}", spec.action(), self.definition.steps.len() + 1);
+        self.step_with_id(id, spec)
+    }
+
+    pub fn step_with_id(mut self, id: impl Into<String>, spec: impl StepSpec) -> Self {
+        self.definition.steps.push(WorkflowStep {
+            id: id.into(),
+            action: spec.action().to_string(),
+            params: spec.into_params(),
+            timeout_secs: None,
+        });
+        self
+    }
+
+    /// Longest the last added step may take.
+    pub fn step_timeout_secs(mut self, secs: u64) -> Self {
+        if let Some(step) = self.definition.steps.last_mut() {
+            step.timeout_secs = Some(secs);
+        }
+        self
+    }
+
+    /// Fails on what the builder itself can see: no steps, an empty or
+    /// duplicate step id, or a zero timeout. Host checks are left to the
+    /// engine's validation.
+    pub fn build(self) -> Result<WorkflowDefinition, CoreError> {
+        let definition = self.definition;
+        if definition.steps.is_empty() {
+            return Err(CoreError::new("workflow has no steps"));
+        }
+        if definition.timeout_secs == Some(0) {
+            return Err(CoreError::new("workflow timeout_secs must be positive"));
+        }
+        let mut seen = std::collections::HashSet::new();
+        for step in &definition.steps {
+            if step.id.trim().is_empty() {
+                return Err(CoreError::new("workflow step id is empty"));
+            }
+            if !seen.insert(step.id.as_str()) {
+                return Err(CoreError::new(format!("duplicate step id {}", step.id)));
+            }
+            if step.timeout_secs == Some(0) {
+                return Err(CoreError::new(format!(
+                    "step {}: timeout_secs must be positive",
+                    step.id
+                )));
+            }
+        }
+        Ok(definition)
+    }
+}
+
+macro_rules! step_spec {
+    (
+        $(#[$meta:meta])*
+        $name:ident => $action:literal,
+        new($($required:ident),*),
+        { $($setter:ident: $kind:ident),* $(,)? }
+    ) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone)]
+        pub struct $name {
+            params: Map<String, Value>,
+        }
+
+        impl $name {
+            pub fn new($($required: impl Into<String>),*) -> Self {
+                let mut params = Map::new();
+                $(params.insert(stringify!($required).to_string(), Value::String($required.into()));)*
+                Self { params }
+            }
+
+            /// Report base of this step instead of the run's default.
+            pub fn report_base(self, path: impl Into<String>) -> Self {
+                self.param("report_base", path.into())
+            }
+
+            /// Pre/post hooks; see the engine's `StepHooks`.
+            pub fn hooks(self, hooks: Value) -> Self {
+                self.param("hooks", hooks)
+            }
+
+            /// Host requirements checked before the run; see the engine's
+            /// `StepRequirements`.
+            pub fn requires(self, requires: Value) -> Self {
+                self.param("requires", requires)
+            }
+
+            /// Sets a param that has no typed setter.
+            pub fn param(mut self, key: &str, value: impl Into<Value>) -> Self {
+                self.params.insert(key.to_string(), value.into());
+                self
+            }
+
+            $(step_spec!(@setter $setter: $kind);)*
+        }
+
+        impl StepSpec for $name {
+            fn action(&self) -> &'static str {
+                $action
+            }
+
+            fn into_params(self) -> Value {
+                Value::Object(self.params)
+            }
+        }
+    };
+    (@setter $setter:ident: bool) => {
+        pub fn $setter(self, value: bool) -> Self {
+            self.param(stringify!($setter), value)
+        }
+    };
+    (@setter $setter:ident: number) => {
+        pub fn $setter(self, value: u64) -> Self {
+            self.param(stringify!($setter), value)
+        }
+    };
+    (@setter $setter:ident: text) => {
+        pub fn $setter(self, value: impl Into<String>) -> Self {
+            self.param(stringify!($setter), value.into())
+        }
+    };
+}
+
+step_spec! {
+    /// `windows_installer_usb`: a bootable Windows installer stick.
+    WindowsInstallerUsb => "windows_installer_usb",
+    new(target_disk_id, source_path),
+    {
+        target_mount: text,
+        force: bool,
+        confirmation_token: text,
+        acknowledge_target_size: bool,
+        acknowledge_device_wear: bool,
+        confirm_overwrite: bool,
+        dry_run: bool,
+        repartition: bool,
+        format: bool,
+        filesystem: text,
+        label: text,
+        cluster_bytes: number,
+        driver_source: text,
+        driver_target: text,
+        hash_manifest: bool,
+        hash_destination: bool,
+        hybrid_mbr: bool,
+        edition: text,
+        edition_selector: text,
+        pid_txt: text,
+        dedupe: bool,
+        flush_every_bytes: number,
+        continue_on_error: bool,
+        transform_pack: text,
+    }
+}
+
+step_spec! {
+    /// `linux_installer_usb`: installer files staged onto a mounted stick.
+    LinuxInstallerUsb => "linux_installer_usb",
+    new(source_path, target_mount),
+    {
+        force: bool,
+        confirmation_token: text,
+        acknowledge_target_size: bool,
+        acknowledge_device_wear: bool,
+        confirm_overwrite: bool,
+        dry_run: bool,
+        hash_manifest: bool,
+        hash_destination: bool,
+        format_device: text,
+        format_size_bytes: number,
+        format_label: text,
+        format_cluster_bytes: number,
+        udisks: bool,
+        power_off: bool,
+        dedupe: bool,
+        flush_every_bytes: number,
+        continue_on_error: bool,
+        transform_pack: text,
+    }
+}
+
+step_spec! {
+    /// `linux_write_image`: a raw image written to a whole device.
+    LinuxWriteImage => "linux_write_image",
+    new(source_image, target_device),
+    {
+        force: bool,
+        confirmation_token: text,
+        acknowledge_target_size: bool,
+        acknowledge_device_wear: bool,
+        confirm_overwrite: bool,
+        dry_run: bool,
+        verify: bool,
+        chunk_size: number,
+        fast_io: bool,
+        flush_every_bytes: number,
+        source_sha256: text,
+    }
+}
+
+step_spec! {
+    /// `macos_write_image`: a raw image written to a whole device.
+    MacosWriteImage => "macos_write_image",
+    new(source_image, target_device),
+    {
+        force: bool,
+        confirmation_token: text,
+        acknowledge_target_size: bool,
+        acknowledge_device_wear: bool,
+        confirm_overwrite: bool,
+        dry_run: bool,
+        verify: bool,
+        chunk_size: number,
+        fast_io: bool,
+        flush_every_bytes: number,
+        source_sha256: text,
+    }
+}
+
+step_spec! {
+    /// `disk_hash_report`: per-chunk hashes of a disk or partition.
+    DiskHashReport => "disk_hash_report",
+    new(disk_id),
+    {
+        chunk_size: number,
+        max_chunks: number,
+        partition_id: text,
+        compare_baseline: text,
+        save_baseline: text,
+    }
+}
+
+step_spec! {
+    /// `bad_block_scan`: a read, or destructive write, surface scan.
+    BadBlockScan => "bad_block_scan",
+    new(disk_id),
+    {
+        mode: text,
+        chunk_size: number,
+        sector_size: number,
+        max_bad_bytes: number,
+        force: bool,
+        confirmation_token: text,
+        acknowledge_target_size: bool,
+        acknowledge_device_wear: bool,
+        confirm_overwrite: bool,
+        dry_run: bool,
+    }
+}
+
+step_spec! {
+    /// `validate_source`: checks installer media before it is used.
+    ValidateSource => "validate_source",
+    new(source_path),
+    {
+        os: text,
+        filesystem: text,
+        target_model: text,
+    }
+}
+
+step_spec! {
+    /// `report_verify`: checks a report bundle's hashes and signature.
+    ReportVerify => "report_verify",
+    new(path),
+    {
+        signing_key: text,
+    }
+}
@@ -3,18 +3,39 @@ use serde_json::Value;
 use time::format_description::well_known::Rfc3339;
 use time::OffsetDateTime;
 use uuid::Uuid;
+use std::collections::BTreeMap;
 
-pub const DEVICE_GRAPH_SCHEMA_VERSION: &str = "1.1.0";
+pub mod builder;
+pub mod mock;
+pub mod units;
+
+pub use builder::{
+    BadBlockScan, DiskHashReport, LinuxInstallerUsb, LinuxWriteImage, MacosWriteImage,
+    ReportVerify, StepSpec, ValidateSource, WindowsInstallerUsb, WorkflowBuilder,
+};
+pub use units::{
+    display_format, format_bytes, format_duration_ms, set_display_format, DisplayFormat,
+    UnitSystem,
+};
+
+pub const DEVICE_GRAPH_SCHEMA_VERSION: &str = "1.5.0";
 pub const WORKFLOW_SCHEMA_VERSION: &str = "1.0.0";
 pub const CONTRACTS_VERSION: &str = "1.0.0";
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS))]
 pub struct DeviceGraph {
+    /// Version the graph was written with. Graphs from before versioning
+    /// read as 1.0.0.
+    #[serde(default = "legacy_schema_version")]
     pub schema_version: String,
     pub graph_id: Uuid,
     pub generated_at_utc: String,
     pub host: HostInfo,
     pub disks: Vec<Disk>,
+    /// Device-mapper and md devices assembled from the disks (Linux).
+    #[serde(default)]
+    pub stacks: Vec<StackedDevice>,
 }
 
 impl DeviceGraph {
@@ -25,8 +46,100 @@ impl DeviceGraph {
             generated_at_utc,
             host,
             disks,
+            stacks: Vec::new(),
+        }
+    }
+
+    /// Sets each disk's USB port label from `labels`, keyed by port path.
+    pub fn apply_usb_port_labels(&mut self, labels: &BTreeMap<String, String>) {
+        for port in self.disks.iter_mut().filter_map(|disk| disk.usb_port.as_mut()) {
+            if let Some(label) = labels.get(&port.path) {
+                port.label = Some(label.clone());
+            }
         }
     }
+
+    /// Stacked devices built on `disk` or its partitions, directly or
+    /// through other stacks, bottom-up.
+    pub fn stacks_on_disk(&self, disk: &Disk) -> Vec<&StackedDevice> {
+        let mut members: Vec<&str> = vec![disk.id.as_str()];
+        members.extend(disk.partitions.iter().map(|partition| partition.id.as_str()));
+        let mut found: Vec<&StackedDevice> = Vec::new();
+        loop {
+            let before = found.len();
+            for stack in &self.stacks {
+                if found.iter().any(|seen| seen.id == stack.id) {
+                    continue;
+                }
+                if stack.slaves.iter().any(|slave| members.contains(&slave.as_str())) {
+                    members.push(stack.id.as_str());
+                    found.push(stack);
+                }
+            }
+            if found.len() == before {
+                return found;
+            }
+        }
+    }
+
+    /// Loads the `device_graph.json` captured in a report bundle. `path` is
+    /// the bundle directory or the JSON file itself.
+    pub fn from_report(path: impl AsRef<std::path::Path>) -> CoreResult<Self> {
+        let path = path.as_ref();
+        let file = if path.is_dir() {
+            path.join("device_graph.json")
+        } else {
+            path.to_path_buf()
+        };
+        let bytes = std::fs::read(&file)
+            .map_err(|err| CoreError::new(format!("read {} failed: {}", file.display(), err)))?;
+        Self::from_json(&bytes)
+            .map_err(|err| CoreError::new(format!("{}: {}", file.display(), err)))
+    }
+
+    /// Parses a serialized graph of any compatible schema version. Fields
+    /// added since the graph was written take their defaults; fields from
+    /// a newer minor version are ignored.
+    pub fn from_json(bytes: &[u8]) -> CoreResult<Self> {
+        let value: Value = serde_json::from_slice(bytes)
+            .map_err(|err| CoreError::new(format!("parse device graph failed: {}", err)))?;
+        let version = value
+            .get("schema_version")
+            .and_then(Value::as_str)
+            .unwrap_or(LEGACY_SCHEMA_VERSION);
+        check_device_graph_schema(version)?;
+        serde_json::from_value(value)
+            .map_err(|err| CoreError::new(format!("parse device graph failed: {}", err)))
+    }
+}
+
+const LEGACY_SCHEMA_VERSION: &str = "1.0.0";
+
+fn legacy_schema_version() -> String {
+    LEGACY_SCHEMA_VERSION.to_string()
+}
+
+/// Accepts any device graph with the same major version as
+/// `DEVICE_GRAPH_SCHEMA_VERSION`. Minor versions only add fields, so both
+/// older and newer ones deserialize.
+pub fn check_device_graph_schema(version: &str) -> CoreResult<()> {
+    let major = |version: &str| {
+        version
+            .split('.')
+            .next()
+            .and_then(|major| major.trim().parse::<u64>().ok())
+    };
+    match (major(version), major(DEVICE_GRAPH_SCHEMA_VERSION)) {
+        (Some(found), Some(supported)) if found == supported => Ok(()),
+        (Some(_), Some(supported)) => Err(CoreError::new(format!(
+            "unsupported device graph schema {} (expected {}.x)",
+            version, supported
+        ))),
+        _ => Err(CoreError::new(format!(
+            "invalid device graph schema version {:?}",
+            version
+        ))),
+    }
 }
 
 pub fn now_utc_rfc3339() -> String {
@@ -36,43 +149,216 @@ pub fn now_utc_rfc3339() -> String {
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS))]
 pub struct HostInfo {
     pub os: String,        // "windows", "linux", "macos"
     pub os_version: String,
     pub machine: String,
+    /// Windows EditionID, e.g. `Professional`.
+    #[serde(default)]
+    pub os_edition: Option<String>,
+    /// Windows feature update, e.g. `23H2`.
+    #[serde(default)]
+    pub os_display_version: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS))]
 pub struct Disk {
     pub id: String,                // stable id per provider
     pub friendly_name: String,
+    #[serde(default)]
+    pub serial: Option<String>,    // hardware serial when the provider can read it
+    #[cfg_attr(feature = "ts", ts(as = "f64"))]
     pub size_bytes: u64,
     pub removable: bool,
     pub is_system_disk: bool,      // provider best-effort
+    #[serde(default)]
     pub partitions: Vec<Partition>,
+    /// Physical USB port the disk hangs off, when the provider can tell.
+    #[serde(default)]
+    pub usb_port: Option<UsbPort>,
+    /// What the local device registry remembers about this disk.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub history: Option<DiskHistory>,
+    /// Partition GUID of the EFI system partition the firmware booted this
+    /// host from, when it is on this disk. Such a disk is a system disk
+    /// whatever its removable flag says.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "ts", ts(optional))]
+    pub booted_esp: Option<String>,
+}
+
+/// Registry entry for a disk as of this graph: when it was first and last
+/// seen on this host and how often Phoenix has written to it.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS))]
+pub struct DiskHistory {
+    pub first_seen_utc: String,
+    pub last_seen_utc: String,
+    /// Completed destructive runs against the disk.
+    pub flash_count: u32,
+    #[serde(default)]
+    pub last_workflow: Option<String>,
+    #[serde(default)]
+    pub last_flashed_utc: Option<String>,
+    /// Fastest raw image write seen, for spotting a stick that slowed down.
+    #[serde(default)]
+    #[cfg_attr(feature = "ts", ts(as = "Option<f64>"))]
+    pub best_write_bytes_per_sec: Option<u64>,
+    #[serde(default)]
+    #[cfg_attr(feature = "ts", ts(as = "Option<f64>"))]
+    pub last_write_bytes_per_sec: Option<u64>,
+    /// The operator's label for the disk, if it has one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "ts", ts(optional))]
+    pub claim: Option<DeviceClaim>,
+}
+
+/// A label an operator attached to a disk, e.g. "Customer loaner - never
+/// wipe". It stays with the disk across runs.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS))]
+pub struct DeviceClaim {
+    pub label: String,
+    #[serde(default)]
+    pub notes: Option<String>,
+    #[serde(default)]
+    pub action: ClaimAction,
+    pub claimed_utc: String,
 }
 
+/// What picking a claimed disk as a write target does.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS))]
+#[serde(rename_all = "snake_case")]
+pub enum ClaimAction {
+    /// Only shown next to the disk.
+    #[default]
+    Label,
+    /// The run goes on, with the label in its report and the audit log.
+    Warn,
+    /// The run is refused until the claim is cleared.
+    Deny,
+}
+
+impl ClaimAction {
+    pub fn parse(value: &str) -> CoreResult<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "label" => Ok(Self::Label),
+            "warn" => Ok(Self::Warn),
+            "deny" => Ok(Self::Deny),
+            other => Err(CoreError::new(format!(
+                "unknown claim action {} (expected label, warn or deny)",
+                other
+            ))),
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Label => "label",
+            Self::Warn => "warn",
+            Self::Deny => "deny",
+        }
+    }
+}
+
+/// Where a USB disk is plugged in. `path` is stable for a given physical
+/// port across replugs and reboots, so it can key a human label.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS))]
+pub struct UsbPort {
+    /// Provider's location string: sysfs port path (`2-1.3`), Windows
+    /// location path (`PCIROOT(0)#PCI(1400)#USBROOT(0)#USB(1)#USB(3)`) or
+    /// IOKit location id (`0x14130000`).
+    pub path: String,
+    #[serde(default)]
+    pub bus: Option<u32>,
+    /// Port numbers from the root hub down to the disk.
+    #[serde(default)]
+    pub ports: Vec<u32>,
+    /// Operator label such as `front-left`, from the port label config.
+    #[serde(default)]
+    pub label: Option<String>,
+}
+
+/// An unlocked LUKS container, LVM volume, RAID array or other
+/// device-mapper/md device.
 #[derive(Debug, Serialize, Deserialize, Clone)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS))]
+pub struct StackedDevice {
+    pub id: String,                // kernel name: dm-0, md127
+    #[serde(default)]
+    pub name: Option<String>,      // mapper or array name: luks-<uuid>, vg-root
+    pub kind: String,              // crypt, lvm, multipath, dm, raid1, raid5, ...
+    #[cfg_attr(feature = "ts", ts(as = "f64"))]
+    pub size_bytes: u64,
+    /// Ids of the partitions, disks or stacked devices it is built on.
+    pub slaves: Vec<String>,
+    pub mount_points: Vec<String>,
+    #[serde(default)]
+    pub fs: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS))]
 pub struct Partition {
     pub id: String,
     pub label: Option<String>,
     pub fs: Option<String>,
+    #[cfg_attr(feature = "ts", ts(as = "f64"))]
     pub size_bytes: u64,
     pub mount_points: Vec<String>,
+    /// GPT partition GUID, or `<MBR signature>-<number>` (Linux PARTUUID).
+    #[serde(default)]
+    pub part_uuid: Option<String>,
+    /// Filesystem UUID or serial, as in `root=UUID=...`.
+    #[serde(default)]
+    pub fs_uuid: Option<String>,
+    /// Byte offset of the partition on its disk, when the host reports it.
+    #[serde(default)]
+    #[cfg_attr(feature = "ts", ts(as = "Option<f64>"))]
+    pub offset_bytes: Option<u64>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS))]
 pub struct WorkflowDefinition {
     pub schema_version: String,
     pub name: String,
+    /// Shown in workflow pickers next to the name.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "ts", ts(optional))]
+    pub description: Option<String>,
     pub steps: Vec<WorkflowStep>,
+    /// Resubmitting a definition with the same key returns the reports of
+    /// destructive steps that already completed instead of re-running them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "ts", ts(optional))]
+    pub idempotency_key: Option<String>,
+    /// External reference (ticket, work order) recorded in every report,
+    /// run record and notification of the run.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "ts", ts(optional))]
+    pub correlation_id: Option<String>,
+    /// Longest the whole run may take before it is cancelled and fails
+    /// with a timeout.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "ts", ts(as = "Option<f64>", optional))]
+    pub timeout_secs: Option<u64>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS))]
 pub struct WorkflowStep {
     pub id: String,
     pub action: String,
     pub params: Value,
+    /// Longest this step, hooks included, may take.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "ts", ts(as = "Option<f64>", optional))]
+    pub timeout_secs: Option<u64>,
 }
 
 impl WorkflowDefinition {
@@ -80,7 +366,11 @@ impl WorkflowDefinition {
         Self {
             schema_version: WORKFLOW_SCHEMA_VERSION.to_string(),
             name: name.into(),
+            description: None,
             steps,
+            idempotency_key: None,
+            correlation_id: None,
+            timeout_secs: None,
         }
     }
 }
@@ -98,6 +388,14 @@ impl CoreError {
     }
 }
 
+impl std::fmt::Display for CoreError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for CoreError {}
+
 pub type CoreResult<T> = std::result::Result<T, CoreError>;
 
 pub trait HostProvider {
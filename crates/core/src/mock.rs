@@ -0,0 +1,179 @@
+//! `PHOENIX_HOST=mock`: host crates report a synthetic (or replayed) device
+//! graph and destructive operations land in files under a sandbox directory
+//! instead of real disks.
+
+use crate::{
+    now_utc_rfc3339, CoreError, CoreResult, DeviceGraph, Disk, HostInfo, Partition, UsbPort,
+};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const HOST_ENV: &str = "PHOENIX_HOST";
+/// Device graph JSON (or a report bundle holding one) to replay.
+pub const GRAPH_ENV: &str = "PHOENIX_MOCK_GRAPH";
+/// Defaults to `<temp>/phoenix-mock`.
+pub const SANDBOX_ENV: &str = "PHOENIX_MOCK_SANDBOX";
+
+pub fn is_active() -> bool {
+    std::env::var(HOST_ENV)
+        .map(|value| value.eq_ignore_ascii_case("mock"))
+        .unwrap_or(false)
+}
+
+pub fn sandbox_dir() -> PathBuf {
+    std::env::var_os(SANDBOX_ENV)
+        .map(PathBuf::from)
+        .unwrap_or_else(|| std::env::temp_dir().join("phoenix-mock"))
+}
+
+/// The graph from `PHOENIX_MOCK_GRAPH`, or a built-in one with a system disk
+/// and two removable drives. Mount points are moved into the sandbox (and
+/// created) so copy workflows write there too.
+pub fn device_graph() -> CoreResult<DeviceGraph> {
+    let mut graph = match std::env::var_os(GRAPH_ENV) {
+        Some(path) => DeviceGraph::from_report(Path::new(&path))?,
+        None => synthetic_graph(),
+    };
+    for partition in graph.disks.iter_mut().flat_map(|disk| disk.partitions.iter_mut()) {
+        if partition.mount_points.is_empty() {
+            continue;
+        }
+        let mount = mount_dir(&partition.id)?;
+        partition.mount_points = vec![mount.display().to_string()];
+    }
+    Ok(graph)
+}
+
+/// Sandbox directory standing in for the volume `id` is mounted at.
+pub fn mount_dir(id: &str) -> CoreResult<PathBuf> {
+    let mount = sandbox_dir().join("mnt").join(file_safe(id));
+    fs::create_dir_all(&mount).map_err(|err| io_error(&mount, err))?;
+    Ok(mount)
+}
+
+/// Simulated format: empties a sandbox volume. Paths outside the sandbox
+/// are refused.
+pub fn format_volume(mount: &Path) -> CoreResult<()> {
+    let sandbox = sandbox_dir();
+    if !mount.starts_with(&sandbox) {
+        return Err(CoreError::new(format!(
+            "{} is outside the mock sandbox {}",
+            mount.display(),
+            sandbox.display()
+        )));
+    }
+    for entry in fs::read_dir(mount).map_err(|err| io_error(mount, err))? {
+        let path = entry.map_err(|err| io_error(mount, err))?.path();
+        let removed = if path.is_dir() {
+            fs::remove_dir_all(&path)
+        } else {
+            fs::remove_file(&path)
+        };
+        removed.map_err(|err| io_error(&path, err))?;
+    }
+    Ok(())
+}
+
+/// External tools are not run; the command line is appended to
+/// `<sandbox>/commands.log`.
+pub fn record_command(cmd: &str, args: &[&str]) -> CoreResult<()> {
+    use std::io::Write;
+
+    let sandbox = sandbox_dir();
+    fs::create_dir_all(&sandbox).map_err(|err| io_error(&sandbox, err))?;
+    let path = sandbox.join("commands.log");
+    let mut log = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .map_err(|err| io_error(&path, err))?;
+    writeln!(log, "{} {}", cmd, args.join(" ")).map_err(|err| io_error(&path, err))
+}
+
+/// Backing file standing in for `disk`'s raw device, created sparse at the
+/// disk's size on first use.
+pub fn disk_file(disk: &Disk) -> CoreResult<PathBuf> {
+    backing_file(&disk.id, disk.size_bytes)
+}
+
+/// Backing file for a partition device node, sized like the partition.
+pub fn partition_file(partition: &Partition) -> CoreResult<PathBuf> {
+    backing_file(&partition.id, partition.size_bytes)
+}
+
+fn backing_file(id: &str, size_bytes: u64) -> CoreResult<PathBuf> {
+    let dir = sandbox_dir().join("devices");
+    fs::create_dir_all(&dir).map_err(|err| io_error(&dir, err))?;
+    let path = dir.join(format!("{}.img", file_safe(id)));
+    if !path.exists() {
+        let file = fs::File::create(&path).map_err(|err| io_error(&path, err))?;
+        file.set_len(size_bytes).map_err(|err| io_error(&path, err))?;
+    }
+    Ok(path)
+}
+
+fn synthetic_graph() -> DeviceGraph {
+    const GIB: u64 = 1024 * 1024 * 1024;
+    let os = std::env::consts::OS;
+    let (system, usb, card) = match os {
+        "windows" => ("PhysicalDrive0", "PhysicalDrive1", "PhysicalDrive2"),
+        "macos" => ("disk0", "disk4", "disk5"),
+        _ => ("sda", "sdb", "sdc"),
+    };
+    let partition_id = |disk: &str| match os {
+        "windows" => format!("{}-Partition1", disk),
+        "macos" => format!("{}s1", disk),
+        _ => format!("{}1", disk),
+    };
+    let disk = |id: &str, name: &str, serial: &str, size_bytes: u64, system: bool, fs: &str| Disk {
+        id: id.to_string(),
+        friendly_name: name.to_string(),
+        serial: Some(serial.to_string()),
+        size_bytes,
+        removable: !system,
+        is_system_disk: system,
+        partitions: vec![Partition {
+            id: partition_id(id),
+            label: Some(if system { "SYSTEM" } else { "PHOENIX" }.to_string()),
+            fs: Some(fs.to_string()),
+            size_bytes: size_bytes - 1024 * 1024,
+            mount_points: vec!["mock".to_string()],
+            part_uuid: None,
+            fs_uuid: None,
+            offset_bytes: Some(1024 * 1024),
+        }],
+        usb_port: None,
+        history: None,
+        booted_esp: None,
+    };
+    let host = HostInfo {
+        os: os.to_string(),
+        os_version: "mock".to_string(),
+        machine: "phoenix-mock".to_string(),
+        os_edition: None,
+        os_display_version: None,
+    };
+    let mut usb_disk = disk(usb, "Mock USB Flash Drive", "MOCK-USB-0001", 16 * GIB, false, "vfat");
+    usb_disk.usb_port = Some(UsbPort {
+        path: "1-2".to_string(),
+        bus: Some(1),
+        ports: vec![2],
+        label: None,
+    });
+    let disks = vec![
+        disk(system, "Mock System SSD", "MOCK-SYS-0001", 256 * GIB, true, "ntfs"),
+        usb_disk,
+        disk(card, "Mock SD Card", "MOCK-SD-0001", 32 * GIB, false, "exfat"),
+    ];
+    DeviceGraph::new(host, disks, now_utc_rfc3339())
+}
+
+fn file_safe(id: &str) -> String {
+    id.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .collect()
+}
+
+fn io_error(path: &Path, err: std::io::Error) -> CoreError {
+    CoreError::new(format!("{}: {}", path.display(), err))
+}
@@ -0,0 +1,173 @@
+//! Sizes and durations as operators read them (`4.7 GB` or `4.4 GiB`,
+//! `1 h 12 min`), with the decimal separator of their locale. JSON keeps
+//! the raw counts; these strings sit next to them.
+
+use crate::{CoreError, CoreResult};
+use std::sync::RwLock;
+use std::time::Duration;
+
+/// `decimal` or `binary`; see `UnitSystem::parse`.
+pub const UNITS_ENV: &str = "PHOENIX_UNITS";
+/// Locale tag such as `de_DE.UTF-8` or `pt-BR`; falls back to `LC_ALL`,
+/// `LC_NUMERIC` and `LANG`.
+pub const LOCALE_ENV: &str = "PHOENIX_LOCALE";
+
+/// Languages written with a decimal comma.
+const DECIMAL_COMMA_LANGUAGES: [&str; 31] = [
+    "be", "bg", "ca", "cs", "da", "de", "el", "es", "et", "fi", "fr", "hr", "hu", "id", "it", "lt",
+    "lv", "nb", "nl", "nn", "no", "pl", "pt", "ro", "ru", "sk", "sl", "sr", "sv", "tr", "uk",
+];
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum UnitSystem {
+    /// Powers of 1000: kB, MB, GB, as drive vendors label capacity.
+    #[default]
+    Decimal,
+    /// Powers of 1024: KiB, MiB, GiB, as Windows and most tools count.
+    Binary,
+}
+
+impl UnitSystem {
+    pub fn parse(value: &str) -> CoreResult<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "decimal" | "si" => Ok(Self::Decimal),
+            "binary" | "iec" => Ok(Self::Binary),
+            other => Err(CoreError::new(format!(
+                "unknown units {} (expected decimal or binary)",
+                other
+            ))),
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Decimal => "decimal",
+            Self::Binary => "binary",
+        }
+    }
+
+    fn base(self) -> f64 {
+        match self {
+            Self::Decimal => 1000.0,
+            Self::Binary => 1024.0,
+        }
+    }
+
+    fn prefixes(self) -> [&'static str; 6] {
+        match self {
+            Self::Decimal => ["kB", "MB", "GB", "TB", "PB", "EB"],
+            Self::Binary => ["KiB", "MiB", "GiB", "TiB", "PiB", "EiB"],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DisplayFormat {
+    pub units: UnitSystem,
+    /// Write `4,7 GB` instead of `4.7 GB`.
+    pub decimal_comma: bool,
+}
+
+impl DisplayFormat {
+    /// `units` with the decimal separator `locale` uses.
+    pub fn for_locale(units: UnitSystem, locale: &str) -> Self {
+        let language = locale
+            .split(['_', '-', '.', '@'])
+            .next()
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        Self {
+            units,
+            decimal_comma: DECIMAL_COMMA_LANGUAGES.contains(&language.as_str()),
+        }
+    }
+
+    /// From `PHOENIX_UNITS` and the locale variables. An unrecognized
+    /// units value is ignored.
+    pub fn from_env() -> Self {
+        let units = std::env::var(UNITS_ENV)
+            .ok()
+            .and_then(|value| UnitSystem::parse(&value).ok())
+            .unwrap_or_default();
+        let locale = [LOCALE_ENV, "LC_ALL", "LC_NUMERIC", "LANG"]
+            .into_iter()
+            .find_map(|name| std::env::var(name).ok().filter(|value| !value.is_empty()))
+            .unwrap_or_default();
+        Self::for_locale(units, &locale)
+    }
+
+    /// `512 B`, `4.7 GB` or `4.4 GiB`: one decimal below 100 of a unit,
+    /// none above.
+    pub fn bytes(&self, bytes: u64) -> String {
+        let base = self.units.base();
+        if (bytes as f64) < base {
+            return format!("{} B", bytes);
+        }
+        let mut value = bytes as f64;
+        let mut unit = "";
+        for prefix in self.units.prefixes() {
+            value /= base;
+            unit = prefix;
+            if value < base {
+                break;
+            }
+        }
+        let decimals = if value < 100.0 { 1 } else { 0 };
+        format!("{} {}", self.number(value, decimals), unit)
+    }
+
+    pub fn rate(&self, bytes_per_sec: u64) -> String {
+        format!("{}/s", self.bytes(bytes_per_sec))
+    }
+
+    /// `250 ms`, `4.2 s`, `3 min 5 s`, `1 h 12 min` or `2 d 3 h`.
+    pub fn duration(&self, duration: Duration) -> String {
+        let millis = duration.as_millis() as u64;
+        let secs = duration.as_secs();
+        match secs {
+            0 => format!("{} ms", millis),
+            1..=59 => format!("{} s", self.number(millis as f64 / 1000.0, 1)),
+            60..=3_599 => format!("{} min {} s", secs / 60, secs % 60),
+            3_600..=86_399 => format!("{} h {} min", secs / 3_600, secs % 3_600 / 60),
+            _ => format!("{} d {} h", secs / 86_400, secs % 86_400 / 3_600),
+        }
+    }
+
+    pub fn duration_ms(&self, millis: u64) -> String {
+        self.duration(Duration::from_millis(millis))
+    }
+
+    fn number(&self, value: f64, decimals: usize) -> String {
+        let text = format!("{:.*}", decimals, value);
+        if self.decimal_comma {
+            text.replace('.', ",")
+        } else {
+            text
+        }
+    }
+}
+
+/// Unset falls back to `DisplayFormat::from_env`.
+static DISPLAY_FORMAT: RwLock<Option<DisplayFormat>> = RwLock::new(None);
+
+/// Overrides the environment for this process, e.g. from a CLI flag or a
+/// desktop setting.
+pub fn set_display_format(format: DisplayFormat) {
+    *DISPLAY_FORMAT.write().unwrap_or_else(|err| err.into_inner()) = Some(format);
+}
+
+/// The format set for this process, else the one from the environment.
+pub fn display_format() -> DisplayFormat {
+    let set = *DISPLAY_FORMAT.read().unwrap_or_else(|err| err.into_inner());
+    set.unwrap_or_else(DisplayFormat::from_env)
+}
+
+/// `bytes` in the process's display format.
+pub fn format_bytes(bytes: u64) -> String {
+    display_format().bytes(bytes)
+}
+
+/// `millis` in the process's display format.
+pub fn format_duration_ms(millis: u64) -> String {
+    display_format().duration_ms(millis)
+}
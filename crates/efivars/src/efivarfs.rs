@@ -0,0 +1,102 @@
+//! efivarfs: one file per variable, named `<Name>-<vendor GUID>`, holding
+//! the 4-byte attributes and then the data. The mock store keeps the same
+//! layout in a sandbox directory.
+
+use crate::{EFI_GLOBAL_VARIABLE, VARIABLE_ATTRIBUTES};
+use anyhow::{anyhow, Context, Result};
+use std::fs;
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+pub const EFIVARFS: &str = "/sys/firmware/efi/efivars";
+
+fn path(dir: &Path, name: &str) -> PathBuf {
+    dir.join(format!("{}-{}", name, EFI_GLOBAL_VARIABLE))
+}
+
+pub fn read(dir: &Path, name: &str) -> Result<Option<Vec<u8>>> {
+    let path = path(dir, name);
+    match fs::read(&path) {
+        Ok(bytes) if bytes.len() >= 4 => Ok(Some(bytes[4..].to_vec())),
+        Ok(_) => Err(anyhow!("{} is shorter than its attributes", path.display())),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err).with_context(|| format!("read {}", path.display())),
+    }
+}
+
+pub fn write(dir: &Path, name: &str, data: &[u8]) -> Result<()> {
+    let path = path(dir, name);
+    let mut bytes = VARIABLE_ATTRIBUTES.to_le_bytes().to_vec();
+    bytes.extend_from_slice(data);
+    if path.exists() {
+        clear_immutable(&path)?;
+    }
+    // efivarfs replaces the whole variable on each write and has no
+    // truncate; a plain directory needs one.
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(dir != Path::new(EFIVARFS))
+        .open(&path)
+        .with_context(|| format!("open {}", path.display()))?;
+    // The variable has to arrive in a single write.
+    let written = file
+        .write(&bytes)
+        .with_context(|| format!("write {}", path.display()))?;
+    if written != bytes.len() {
+        return Err(anyhow!("short write to {}", path.display()));
+    }
+    Ok(())
+}
+
+pub fn delete(dir: &Path, name: &str) -> Result<()> {
+    let path = path(dir, name);
+    if !path.exists() {
+        return Ok(());
+    }
+    clear_immutable(&path)?;
+    fs::remove_file(&path).with_context(|| format!("remove {}", path.display()))
+}
+
+/// Names of the global variables in `dir`.
+pub fn names(dir: &Path) -> Result<Vec<String>> {
+    let suffix = format!("-{}", EFI_GLOBAL_VARIABLE);
+    let mut names = Vec::new();
+    for entry in fs::read_dir(dir).with_context(|| format!("read {}", dir.display()))? {
+        let file_name = entry?.file_name();
+        if let Some(name) = file_name.to_string_lossy().strip_suffix(&suffix) {
+            names.push(name.to_string());
+        }
+    }
+    Ok(names)
+}
+
+/// The kernel marks most efivarfs files immutable so a stray `rm` cannot
+/// brick firmware; the flag is cleared before a variable is replaced
+/// or removed.
+#[cfg(target_os = "linux")]
+fn clear_immutable(path: &Path) -> Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    const FS_IMMUTABLE_FL: libc::c_long = 0x10;
+    let file = fs::File::open(path).with_context(|| format!("open {}", path.display()))?;
+    let mut flags: libc::c_long = 0;
+    if unsafe { libc::ioctl(file.as_raw_fd(), libc::FS_IOC_GETFLAGS, &mut flags) } != 0 {
+        // Not supported by the mock sandbox's filesystem, say.
+        return Ok(());
+    }
+    if flags & FS_IMMUTABLE_FL == 0 {
+        return Ok(());
+    }
+    let flags = flags & !FS_IMMUTABLE_FL;
+    if unsafe { libc::ioctl(file.as_raw_fd(), libc::FS_IOC_SETFLAGS, &flags) } != 0 {
+        return Err(std::io::Error::last_os_error())
+            .with_context(|| format!("clear immutable flag on {}", path.display()));
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn clear_immutable(_path: &Path) -> Result<()> {
+    Ok(())
+}
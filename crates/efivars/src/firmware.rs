@@ -0,0 +1,172 @@
+//! Windows: `Get/SetFirmwareEnvironmentVariableExW`, which need the
+//! SeSystemEnvironmentPrivilege of an elevated token, and the ESP's
+//! partition record from `IOCTL_DISK_GET_PARTITION_INFO_EX`.
+
+use crate::{EspLocation, VARIABLE_ATTRIBUTES};
+use anyhow::{anyhow, Context, Result};
+use std::ffi::c_void;
+use std::path::Path;
+use windows::core::HSTRING;
+use windows::Win32::Foundation::{
+    CloseHandle, GetLastError, ERROR_ENVVAR_NOT_FOUND, ERROR_NOT_ALL_ASSIGNED, HANDLE, LUID,
+};
+use windows::Win32::Security::{
+    AdjustTokenPrivileges, LookupPrivilegeValueW, LUID_AND_ATTRIBUTES, SE_PRIVILEGE_ENABLED,
+    SE_SYSTEM_ENVIRONMENT_NAME, TOKEN_ADJUST_PRIVILEGES, TOKEN_PRIVILEGES, TOKEN_QUERY,
+};
+use windows::Win32::Storage::FileSystem::{
+    CreateFileW, FILE_FLAGS_AND_ATTRIBUTES, FILE_SHARE_READ, FILE_SHARE_WRITE, OPEN_EXISTING,
+};
+use windows::Win32::System::Ioctl::{
+    DISK_GEOMETRY_EX, IOCTL_DISK_GET_DRIVE_GEOMETRY_EX, IOCTL_DISK_GET_PARTITION_INFO_EX,
+    PARTITION_INFORMATION_EX, PARTITION_STYLE_GPT,
+};
+use windows::Win32::System::Threading::{GetCurrentProcess, OpenProcessToken};
+use windows::Win32::System::WindowsProgramming::{
+    GetFirmwareEnvironmentVariableExW, SetFirmwareEnvironmentVariableExW,
+};
+use windows::Win32::System::IO::DeviceIoControl;
+
+const GLOBAL_GUID: &str = "{8BE4DF61-93CA-11D2-AA0D-00E098032B8C}";
+/// Larger than any boot manager variable.
+const MAX_VARIABLE_BYTES: usize = 64 * 1024;
+
+pub fn enable_privilege() -> Result<()> {
+    unsafe {
+        let mut token = HANDLE::default();
+        OpenProcessToken(
+            GetCurrentProcess(),
+            TOKEN_ADJUST_PRIVILEGES | TOKEN_QUERY,
+            &mut token,
+        )
+        .context("open process token")?;
+        let mut luid = LUID::default();
+        let result = LookupPrivilegeValueW(None, SE_SYSTEM_ENVIRONMENT_NAME, &mut luid)
+            .and_then(|_| {
+                let privileges = TOKEN_PRIVILEGES {
+                    PrivilegeCount: 1,
+                    Privileges: [LUID_AND_ATTRIBUTES {
+                        Luid: luid,
+                        Attributes: SE_PRIVILEGE_ENABLED,
+                    }],
+                };
+                AdjustTokenPrivileges(token, false, Some(&privileges), 0, None, None)
+            });
+        // Succeeds without granting a privilege the token does not hold.
+        let not_assigned = GetLastError() == ERROR_NOT_ALL_ASSIGNED;
+        let _ = CloseHandle(token);
+        result.context("enable SeSystemEnvironmentPrivilege")?;
+        if not_assigned {
+            return Err(anyhow!(
+                "SeSystemEnvironmentPrivilege not held; run from an elevated prompt"
+            ));
+        }
+    }
+    Ok(())
+}
+
+pub fn read(name: &str) -> Result<Option<Vec<u8>>> {
+    let mut buffer = vec![0u8; MAX_VARIABLE_BYTES];
+    let len = unsafe {
+        GetFirmwareEnvironmentVariableExW(
+            &HSTRING::from(name),
+            &HSTRING::from(GLOBAL_GUID),
+            Some(buffer.as_mut_ptr() as *mut c_void),
+            buffer.len() as u32,
+            None,
+        )
+    };
+    if len == 0 {
+        let err = std::io::Error::last_os_error();
+        if err.raw_os_error() == Some(ERROR_ENVVAR_NOT_FOUND.0 as i32) {
+            return Ok(None);
+        }
+        return Err(anyhow!("read {}: {}", name, err));
+    }
+    buffer.truncate(len as usize);
+    Ok(Some(buffer))
+}
+
+/// Empty `data` deletes the variable.
+pub fn write(name: &str, data: &[u8]) -> Result<()> {
+    let value = (!data.is_empty()).then_some(data.as_ptr() as *const c_void);
+    unsafe {
+        SetFirmwareEnvironmentVariableExW(
+            &HSTRING::from(name),
+            &HSTRING::from(GLOBAL_GUID),
+            value,
+            data.len() as u32,
+            VARIABLE_ATTRIBUTES,
+        )
+    }
+    .with_context(|| format!("write {}", name))
+}
+
+/// `mount` is a drive letter; the ESP has none by default, so assign one
+/// first (`mountvol S: /s`).
+pub fn esp_location(mount: &Path) -> Result<EspLocation> {
+    let text = mount.to_string_lossy();
+    let letter = text.trim_end_matches(['\\', '/']);
+    if letter.len() != 2 || !letter.ends_with(':') {
+        return Err(anyhow!(
+            "{} is not a drive letter; mount the ESP with mountvol S: /s",
+            text
+        ));
+    }
+    let handle = unsafe {
+        CreateFileW(
+            &HSTRING::from(format!("\\\\.\\{}", letter)),
+            0,
+            FILE_SHARE_READ | FILE_SHARE_WRITE,
+            None,
+            OPEN_EXISTING,
+            FILE_FLAGS_AND_ATTRIBUTES(0),
+            None,
+        )
+    }
+    .with_context(|| format!("open volume {}", letter))?;
+
+    let mut info: PARTITION_INFORMATION_EX = unsafe { std::mem::zeroed() };
+    // DISK_GEOMETRY_EX ends in variable-length data; leave room for it.
+    let mut geometry = [0u64; 32];
+    let mut returned = 0u32;
+    let result = unsafe {
+        DeviceIoControl(
+            handle,
+            IOCTL_DISK_GET_PARTITION_INFO_EX,
+            None,
+            0,
+            Some(&mut info as *mut _ as *mut c_void),
+            std::mem::size_of::<PARTITION_INFORMATION_EX>() as u32,
+            Some(&mut returned),
+            None,
+        )
+        .and_then(|_| {
+            DeviceIoControl(
+                handle,
+                IOCTL_DISK_GET_DRIVE_GEOMETRY_EX,
+                None,
+                0,
+                Some(geometry.as_mut_ptr() as *mut c_void),
+                std::mem::size_of_val(&geometry) as u32,
+                Some(&mut returned),
+                None,
+            )
+        })
+    };
+    let _ = unsafe { CloseHandle(handle) };
+    result.with_context(|| format!("query partition of {}", letter))?;
+
+    if info.PartitionStyle != PARTITION_STYLE_GPT {
+        return Err(anyhow!("{} is not on a GPT disk", letter));
+    }
+    let geometry = unsafe { &*(geometry.as_ptr() as *const DISK_GEOMETRY_EX) };
+    let block = (geometry.Geometry.BytesPerSector as u64).max(512);
+    let guid = unsafe { info.Anonymous.Gpt.PartitionId };
+    Ok(EspLocation {
+        partition_number: info.PartitionNumber,
+        start_lba: info.StartingOffset as u64 / block,
+        size_lba: info.PartitionLength as u64 / block,
+        partition_guid: uuid::Uuid::from_u128(guid.to_u128()).to_string(),
+    })
+}
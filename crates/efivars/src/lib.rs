@@ -0,0 +1,521 @@
+//! UEFI boot entries (`Boot####`, `BootOrder`, `BootNext`) pointing at a
+//! system a workflow has just staged: efivarfs on Linux, the firmware
+//! environment API on Windows. Macs have no UEFI load options to write;
+//! `bless` picks their startup volume instead.
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use uuid::Uuid;
+
+mod efivarfs;
+#[cfg(windows)]
+mod firmware;
+#[cfg(target_os = "linux")]
+mod linux;
+
+/// EFI_GLOBAL_VARIABLE, the vendor GUID of the boot manager variables.
+pub const EFI_GLOBAL_VARIABLE: &str = "8be4df61-93ca-11d2-aa0d-00e098032b8c";
+/// Non-volatile, boot service and runtime access.
+const VARIABLE_ATTRIBUTES: u32 = 0x7;
+const LOAD_OPTION_ACTIVE: u32 = 0x1;
+const BLESS: &str = "/usr/sbin/bless";
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BootEntry {
+    pub number: u16,
+    /// `Boot0003`.
+    pub name: String,
+    pub description: String,
+    pub active: bool,
+    /// GPT partition GUID of the hard-drive node, when the path has one.
+    pub partition_guid: Option<String>,
+    /// Loader on that partition, such as `\EFI\ubuntu\shimx64.efi`.
+    pub loader: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BootEntries {
+    /// In boot order; entries missing from `BootOrder` come last.
+    pub entries: Vec<BootEntry>,
+    pub order: Vec<u16>,
+    pub current: Option<u16>,
+    pub next: Option<u16>,
+}
+
+/// An EFI system partition as the firmware's hard-drive device path node
+/// names it.
+#[derive(Debug, Clone, Serialize)]
+pub struct EspLocation {
+    pub partition_number: u32,
+    /// In the disk's logical blocks.
+    pub start_lba: u64,
+    pub size_lba: u64,
+    pub partition_guid: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct NewBootEntry {
+    pub description: String,
+    pub esp: EspLocation,
+    /// Loader path on the ESP; `/` separators are converted.
+    pub loader: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BootPosition {
+    /// Ahead of every other entry in `BootOrder`.
+    First,
+    /// Appended to `BootOrder` when not already in it.
+    Last,
+    /// `BootNext`: booted once, then the old order applies again.
+    Next,
+}
+
+impl BootPosition {
+    pub fn parse(value: &str) -> Result<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "first" => Ok(Self::First),
+            "last" => Ok(Self::Last),
+            "next" => Ok(Self::Next),
+            other => Err(anyhow!("unknown boot position: {} (first, last, next)", other)),
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::First => "first",
+            Self::Last => "last",
+            Self::Next => "next",
+        }
+    }
+}
+
+/// Where the variables live. The mock store is an efivarfs-style
+/// directory in the mock sandbox.
+enum Store {
+    Efivarfs(PathBuf),
+    #[cfg(windows)]
+    Firmware,
+}
+
+impl Store {
+    fn open() -> Result<Self> {
+        if phoenix_core::mock::is_active() {
+            let dir = phoenix_core::mock::sandbox_dir().join("efivars");
+            std::fs::create_dir_all(&dir).with_context(|| format!("create {}", dir.display()))?;
+            return Ok(Self::Efivarfs(dir));
+        }
+        Self::open_host()
+    }
+
+    #[cfg(target_os = "linux")]
+    fn open_host() -> Result<Self> {
+        let dir = Path::new(efivarfs::EFIVARFS);
+        if !dir.is_dir() {
+            return Err(anyhow!(
+                "{} is not mounted; the host did not boot through UEFI",
+                dir.display()
+            ));
+        }
+        Ok(Self::Efivarfs(dir.to_path_buf()))
+    }
+
+    #[cfg(windows)]
+    fn open_host() -> Result<Self> {
+        firmware::enable_privilege()?;
+        Ok(Self::Firmware)
+    }
+
+    #[cfg(not(any(target_os = "linux", windows)))]
+    fn open_host() -> Result<Self> {
+        Err(anyhow!("UEFI boot entries are written on Linux and Windows; use bless on macOS"))
+    }
+
+    fn read(&self, name: &str) -> Result<Option<Vec<u8>>> {
+        match self {
+            Self::Efivarfs(dir) => efivarfs::read(dir, name),
+            #[cfg(windows)]
+            Self::Firmware => firmware::read(name),
+        }
+    }
+
+    fn write(&self, name: &str, data: &[u8]) -> Result<()> {
+        match self {
+            Self::Efivarfs(dir) => efivarfs::write(dir, name, data),
+            #[cfg(windows)]
+            Self::Firmware => firmware::write(name, data),
+        }
+    }
+
+    fn delete(&self, name: &str) -> Result<()> {
+        match self {
+            Self::Efivarfs(dir) => efivarfs::delete(dir, name),
+            #[cfg(windows)]
+            Self::Firmware => firmware::write(name, &[]),
+        }
+    }
+
+    /// Every `Boot####` variable; Windows cannot enumerate variables, so
+    /// only the ones `BootOrder` names are found there.
+    fn boot_numbers(&self) -> Result<Vec<u16>> {
+        match self {
+            Self::Efivarfs(dir) => Ok(efivarfs::names(dir)?
+                .iter()
+                .filter_map(|name| parse_boot_name(name))
+                .collect()),
+            #[cfg(windows)]
+            Self::Firmware => Ok(Vec::new()),
+        }
+    }
+}
+
+/// Whether this host can read and write boot entries.
+pub fn is_supported() -> bool {
+    Store::open().is_ok()
+}
+
+pub fn list_boot_entries() -> Result<BootEntries> {
+    list_entries(&Store::open()?)
+}
+
+/// GPT partition GUID of the ESP the firmware booted this host from: the
+/// hard-drive node of the `BootCurrent` entry. `None` when the host did
+/// not boot through UEFI, the variables cannot be read (Windows without
+/// elevation, macOS), or the entry names no partition, as for a network
+/// boot.
+pub fn booted_esp_guid() -> Result<Option<String>> {
+    let Ok(store) = Store::open() else {
+        return Ok(None);
+    };
+    let Some(current) = store
+        .read("BootCurrent")?
+        .filter(|data| data.len() >= 2)
+        .map(|data| u16::from_le_bytes([data[0], data[1]]))
+    else {
+        return Ok(None);
+    };
+    match store.read(&boot_name(current))? {
+        Some(data) => Ok(decode_load_option(current, &data)?.partition_guid),
+        None => Ok(None),
+    }
+}
+
+/// Writes `entry` and places it at `position`. An entry for the same
+/// partition and loader is updated in place rather than duplicated, so
+/// re-running a workflow does not pile up entries. Returns its number.
+pub fn create_boot_entry(entry: &NewBootEntry, position: BootPosition) -> Result<u16> {
+    let store = Store::open()?;
+    let data = encode_load_option(entry)?;
+    let existing = list_entries(&store)?;
+    let loader = normalize_loader(&entry.loader)?;
+    let number = match existing.entries.iter().find(|candidate| {
+        candidate
+            .partition_guid
+            .as_deref()
+            .is_some_and(|guid| guid.eq_ignore_ascii_case(&entry.esp.partition_guid))
+            && candidate
+                .loader
+                .as_deref()
+                .is_some_and(|path| path.eq_ignore_ascii_case(&loader))
+    }) {
+        Some(found) => found.number,
+        None => free_number(&store, &existing)?,
+    };
+    store.write(&boot_name(number), &data)?;
+
+    let mut order = existing.order;
+    match position {
+        BootPosition::First => {
+            order.retain(|candidate| *candidate != number);
+            order.insert(0, number);
+        }
+        BootPosition::Last | BootPosition::Next => {
+            // Some firmware drops load options no BootOrder names.
+            if !order.contains(&number) {
+                order.push(number);
+            }
+        }
+    }
+    store.write("BootOrder", &encode_order(&order))?;
+    if position == BootPosition::Next {
+        store.write("BootNext", &number.to_le_bytes())?;
+    }
+    Ok(number)
+}
+
+pub fn set_boot_order(order: &[u16]) -> Result<()> {
+    let store = Store::open()?;
+    for number in order {
+        if store.read(&boot_name(*number))?.is_none() {
+            return Err(anyhow!("{} does not exist", boot_name(*number)));
+        }
+    }
+    store.write("BootOrder", &encode_order(order))
+}
+
+pub fn set_boot_next(number: u16) -> Result<()> {
+    let store = Store::open()?;
+    if store.read(&boot_name(number))?.is_none() {
+        return Err(anyhow!("{} does not exist", boot_name(number)));
+    }
+    store.write("BootNext", &number.to_le_bytes())
+}
+
+/// Removes the entry and its place in `BootOrder`.
+pub fn delete_boot_entry(number: u16) -> Result<()> {
+    let store = Store::open()?;
+    let mut order = read_order(&store, "BootOrder")?;
+    if order.contains(&number) {
+        order.retain(|candidate| *candidate != number);
+        store.write("BootOrder", &encode_order(&order))?;
+    }
+    store.delete(&boot_name(number))
+}
+
+/// The EFI system partition mounted at `mount`.
+pub fn esp_location(mount: &Path) -> Result<EspLocation> {
+    if phoenix_core::mock::is_active() {
+        return mock_esp_location(mount);
+    }
+    host_esp_location(mount)
+}
+
+#[cfg(target_os = "linux")]
+fn host_esp_location(mount: &Path) -> Result<EspLocation> {
+    linux::esp_location(mount)
+}
+
+#[cfg(windows)]
+fn host_esp_location(mount: &Path) -> Result<EspLocation> {
+    firmware::esp_location(mount)
+}
+
+#[cfg(not(any(target_os = "linux", windows)))]
+fn host_esp_location(_mount: &Path) -> Result<EspLocation> {
+    Err(anyhow!("ESP lookup is implemented for Linux and Windows"))
+}
+
+/// Makes the volume at `mount` the Mac's startup disk, or only for the
+/// next boot.
+pub fn bless_volume(mount: &Path, next_only: bool) -> Result<()> {
+    let mount = mount.to_string_lossy();
+    let mut args = vec!["--mount", mount.as_ref(), "--setBoot"];
+    if next_only {
+        args.push("--nextonly");
+    }
+    if phoenix_core::mock::is_active() {
+        return Ok(phoenix_core::mock::record_command(BLESS, &args)?);
+    }
+    if !cfg!(target_os = "macos") {
+        return Err(anyhow!("bless requires macOS"));
+    }
+    let output = Command::new(BLESS)
+        .args(&args)
+        .output()
+        .with_context(|| format!("run {}", BLESS))?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "bless failed ({}): {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(())
+}
+
+/// `EFI_LOAD_OPTION` for `entry`: attributes, device path length,
+/// description, then a GPT hard-drive node, a file path node and the end
+/// node.
+pub fn encode_load_option(entry: &NewBootEntry) -> Result<Vec<u8>> {
+    let guid = Uuid::parse_str(&entry.esp.partition_guid)
+        .with_context(|| format!("partition GUID {}", entry.esp.partition_guid))?;
+    let loader = utf16z(&normalize_loader(&entry.loader)?);
+
+    let mut path = vec![0x04, 0x01];
+    path.extend(42u16.to_le_bytes());
+    path.extend(entry.esp.partition_number.to_le_bytes());
+    path.extend(entry.esp.start_lba.to_le_bytes());
+    path.extend(entry.esp.size_lba.to_le_bytes());
+    path.extend(guid.to_bytes_le());
+    // GPT partition, GUID signature.
+    path.extend([0x02, 0x02]);
+    path.extend([0x04, 0x04]);
+    path.extend(((4 + loader.len()) as u16).to_le_bytes());
+    path.extend(loader);
+    path.extend([0x7f, 0xff, 0x04, 0x00]);
+
+    let mut data = LOAD_OPTION_ACTIVE.to_le_bytes().to_vec();
+    data.extend((path.len() as u16).to_le_bytes());
+    data.extend(utf16z(&entry.description));
+    data.extend(path);
+    Ok(data)
+}
+
+pub fn decode_load_option(number: u16, data: &[u8]) -> Result<BootEntry> {
+    let invalid = || anyhow!("{} is not a valid load option", boot_name(number));
+    let attributes = u32::from_le_bytes(data.get(0..4).ok_or_else(invalid)?.try_into()?);
+    let path_len = u16::from_le_bytes(data.get(4..6).ok_or_else(invalid)?.try_into()?) as usize;
+    let (description, path_start) = read_utf16z(data, 6).ok_or_else(invalid)?;
+    let mut path = data
+        .get(path_start..path_start + path_len)
+        .ok_or_else(invalid)?;
+
+    let mut entry = BootEntry {
+        number,
+        name: boot_name(number),
+        description,
+        active: attributes & LOAD_OPTION_ACTIVE != 0,
+        partition_guid: None,
+        loader: None,
+    };
+    while path.len() >= 4 {
+        let len = u16::from_le_bytes([path[2], path[3]]) as usize;
+        let Some(node) = path.get(..len).filter(|_| len >= 4) else {
+            break;
+        };
+        match (node[0], node[1]) {
+            (0x04, 0x01) if len >= 42 && node[41] == 0x02 => {
+                let guid: [u8; 16] = node[24..40].try_into()?;
+                entry.partition_guid = Some(Uuid::from_bytes_le(guid).to_string());
+            }
+            (0x04, 0x04) => {
+                entry.loader = read_utf16z(node, 4).map(|(text, _)| text);
+            }
+            (0x7f, 0xff) => break,
+            _ => {}
+        }
+        path = &path[len..];
+    }
+    Ok(entry)
+}
+
+fn list_entries(store: &Store) -> Result<BootEntries> {
+    let order = read_order(store, "BootOrder")?;
+    let single = |name: &str| -> Result<Option<u16>> {
+        Ok(store
+            .read(name)?
+            .filter(|data| data.len() >= 2)
+            .map(|data| u16::from_le_bytes([data[0], data[1]])))
+    };
+    let mut numbers = order.clone();
+    let mut others = store.boot_numbers()?;
+    others.sort_unstable();
+    numbers.extend(others.into_iter().filter(|number| !order.contains(number)));
+    numbers.dedup();
+
+    let mut entries = Vec::new();
+    for number in numbers {
+        // A dangling BootOrder number has no variable behind it.
+        if let Some(data) = store.read(&boot_name(number))? {
+            entries.push(decode_load_option(number, &data)?);
+        }
+    }
+    Ok(BootEntries {
+        entries,
+        order,
+        current: single("BootCurrent")?,
+        next: single("BootNext")?,
+    })
+}
+
+fn read_order(store: &Store, name: &str) -> Result<Vec<u16>> {
+    Ok(store
+        .read(name)?
+        .unwrap_or_default()
+        .chunks_exact(2)
+        .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+        .collect())
+}
+
+fn encode_order(order: &[u16]) -> Vec<u8> {
+    order.iter().flat_map(|number| number.to_le_bytes()).collect()
+}
+
+fn free_number(store: &Store, existing: &BootEntries) -> Result<u16> {
+    for number in 0..=u16::MAX {
+        let taken = existing.order.contains(&number)
+            || existing.entries.iter().any(|entry| entry.number == number);
+        if !taken && store.read(&boot_name(number))?.is_none() {
+            return Ok(number);
+        }
+    }
+    Err(anyhow!("no free Boot#### number"))
+}
+
+fn boot_name(number: u16) -> String {
+    format!("Boot{:04X}", number)
+}
+
+fn parse_boot_name(name: &str) -> Option<u16> {
+    let digits = name.strip_prefix("Boot")?;
+    if digits.len() != 4 {
+        return None;
+    }
+    u16::from_str_radix(digits, 16).ok()
+}
+
+/// `\EFI\BOOT\BOOTX64.EFI`, whichever separators were given.
+fn normalize_loader(loader: &str) -> Result<String> {
+    let trimmed = loader.trim().trim_start_matches(['/', '\\']);
+    if trimmed.is_empty() {
+        return Err(anyhow!("loader path is empty"));
+    }
+    if trimmed.split(['/', '\\']).any(|part| part == "..") {
+        return Err(anyhow!("loader path {} leaves the ESP", loader));
+    }
+    Ok(format!("\\{}", trimmed.replace('/', "\\")))
+}
+
+fn utf16z(text: &str) -> Vec<u8> {
+    text.encode_utf16()
+        .chain(std::iter::once(0))
+        .flat_map(|unit| unit.to_le_bytes())
+        .collect()
+}
+
+/// The NUL-terminated UTF-16 string at `start` and the offset after it.
+fn read_utf16z(data: &[u8], start: usize) -> Option<(String, usize)> {
+    let mut units = Vec::new();
+    let mut at = start;
+    loop {
+        let unit = u16::from_le_bytes(data.get(at..at + 2)?.try_into().ok()?);
+        at += 2;
+        if unit == 0 {
+            break;
+        }
+        units.push(unit);
+    }
+    Some((String::from_utf16_lossy(&units), at))
+}
+
+/// The mock graph has no partition offsets; the partition starts at 1 MiB
+/// and its GUID is derived from the id when the graph has none.
+fn mock_esp_location(mount: &Path) -> Result<EspLocation> {
+    let graph = phoenix_core::mock::device_graph()?;
+    let partition = graph
+        .disks
+        .iter()
+        .flat_map(|disk| disk.partitions.iter())
+        .find(|partition| {
+            partition
+                .mount_points
+                .iter()
+                .any(|point| Path::new(point) == mount)
+        })
+        .ok_or_else(|| anyhow!("{} is not a mounted mock partition", mount.display()))?;
+    let partition_guid = partition.part_uuid.clone().unwrap_or_else(|| {
+        let hash = partition.id.bytes().fold(0x811c_9dc5u32, |hash, byte| {
+            (hash ^ byte as u32).wrapping_mul(0x0100_0193)
+        });
+        format!("{:08x}-0000-4000-8000-000000000001", hash)
+    });
+    Ok(EspLocation {
+        partition_number: 1,
+        start_lba: 2048,
+        size_lba: partition.size_bytes / 512,
+        partition_guid,
+    })
+}
@@ -0,0 +1,90 @@
+//! Finds the partition behind an ESP mount through `/proc/self/mountinfo`
+//! and sysfs.
+
+use crate::EspLocation;
+use anyhow::{anyhow, Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub fn esp_location(mount: &Path) -> Result<EspLocation> {
+    let mount = fs::canonicalize(mount).with_context(|| format!("resolve {}", mount.display()))?;
+    let device = mount_device(&mount)?;
+    let sys = fs::canonicalize(format!("/sys/dev/block/{}", device))
+        .with_context(|| format!("no block device {} behind {}", device, mount.display()))?;
+    let number = read_number(&sys.join("partition"))
+        .ok_or_else(|| anyhow!("{} is not mounted from a partition", mount.display()))?;
+    let start = read_number(&sys.join("start")).ok_or_else(|| anyhow!("partition start unknown"))?;
+    let size = read_number(&sys.join("size")).ok_or_else(|| anyhow!("partition size unknown"))?;
+    // sysfs counts 512-byte sectors whatever the disk's block size.
+    let block = sys
+        .parent()
+        .and_then(|disk| read_number(&disk.join("queue/logical_block_size")))
+        .unwrap_or(512)
+        .max(512);
+    let name = sys
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let partition_guid = part_uuid(&name).ok_or_else(|| {
+        anyhow!("{} has no GPT partition GUID; boot entries need a GPT disk", name)
+    })?;
+    Ok(EspLocation {
+        partition_number: number as u32,
+        start_lba: start * 512 / block,
+        size_lba: size * 512 / block,
+        partition_guid,
+    })
+}
+
+/// `major:minor` of the last filesystem mounted at `mount`.
+fn mount_device(mount: &Path) -> Result<String> {
+    let info = fs::read_to_string("/proc/self/mountinfo").context("read /proc/self/mountinfo")?;
+    info.lines()
+        .rev()
+        .find_map(|line| {
+            let fields: Vec<&str> = line.split(' ').collect();
+            let point = unescape(fields.get(4)?);
+            (Path::new(&point) == mount).then(|| fields[2].to_string())
+        })
+        .ok_or_else(|| anyhow!("{} is not a mount point", mount.display()))
+}
+
+/// mountinfo escapes space, tab, newline and backslash as `\ooo`.
+fn unescape(field: &str) -> String {
+    let mut out = Vec::new();
+    let bytes = field.as_bytes();
+    let mut at = 0;
+    while at < bytes.len() {
+        let octal = bytes
+            .get(at + 1..at + 4)
+            .and_then(|digits| std::str::from_utf8(digits).ok())
+            .and_then(|digits| u8::from_str_radix(digits, 8).ok());
+        match (bytes[at], octal) {
+            (b'\\', Some(byte)) => {
+                out.push(byte);
+                at += 4;
+            }
+            (byte, _) => {
+                out.push(byte);
+                at += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).to_string()
+}
+
+fn read_number(path: &Path) -> Option<u64> {
+    fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+/// PARTUUID from the `/dev/disk/by-partuuid` links; MBR ones
+/// (`xxxxxxxx-01`) are not GUIDs and are skipped.
+fn part_uuid(device: &str) -> Option<String> {
+    let target = PathBuf::from("/dev").join(device);
+    fs::read_dir("/dev/disk/by-partuuid")
+        .ok()?
+        .flatten()
+        .find(|entry| fs::canonicalize(entry.path()).ok().as_ref() == Some(&target))
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        .filter(|uuid| uuid::Uuid::parse_str(uuid).is_ok())
+}
@@ -0,0 +1,319 @@
+//! Remote image sources read as a stream, so they can be written to a
+//! device without a full local download first.
+
+use anyhow::{anyhow, Result};
+use std::io::{self, Read};
+use std::time::Duration;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+/// A stalled connection is dropped and resumed after this long.
+const READ_TIMEOUT: Duration = Duration::from_secs(60);
+/// Reconnects allowed per transfer before the read error is returned.
+pub const MAX_RESUMES: u32 = 8;
+
+mod mirrors;
+mod network;
+#[cfg(any(feature = "torrent", feature = "metalink"))]
+mod pieces;
+mod schedule;
+#[cfg(feature = "metalink")]
+pub mod metalink;
+#[cfg(feature = "torrent")]
+pub mod torrent;
+
+use mirrors::Mirror;
+pub use mirrors::MirrorStats;
+pub use network::{
+    agent_builder, agent_builder_with_tls, configure, redact_proxy, ClientTls, NetworkConfig,
+};
+pub use schedule::{window_wait, FetchWindow};
+
+pub fn is_url(source: &str) -> bool {
+    let lower = source.to_ascii_lowercase();
+    lower.starts_with("https://") || lower.starts_with("http://")
+}
+
+pub fn is_https(source: &str) -> bool {
+    source.to_ascii_lowercase().starts_with("https://")
+}
+
+fn agent(url: &str) -> Result<ureq::Agent> {
+    Ok(agent_builder(url)?
+        .timeout_connect(CONNECT_TIMEOUT)
+        .timeout_read(READ_TIMEOUT)
+        .build())
+}
+
+/// An image downloaded piece by piece from a torrent's web seeds or a
+/// metalink's mirrors.
+#[derive(Debug, Clone)]
+pub struct FetchResult {
+    pub path: std::path::PathBuf,
+    pub total_bytes: u64,
+    pub pieces: usize,
+    /// Pieces already present and valid from an earlier, interrupted fetch.
+    pub pieces_reused: usize,
+    /// Pieces that failed their hash and were fetched again from another
+    /// source.
+    pub pieces_refetched: usize,
+}
+
+/// Downloads a `.torrent` (web seeds) or `.meta4` descriptor's image into
+/// `dest_dir`, verifying every piece. Needs the matching cargo feature.
+pub fn fetch_image(descriptor: &str, dest_dir: &std::path::Path) -> Result<FetchResult> {
+    let lower = descriptor.to_ascii_lowercase();
+    if lower.ends_with(".torrent") {
+        #[cfg(feature = "torrent")]
+        return torrent::fetch(descriptor, dest_dir);
+        #[cfg(not(feature = "torrent"))]
+        return Err(anyhow!("built without the torrent feature"));
+    }
+    if lower.ends_with(".meta4") || lower.ends_with(".metalink") {
+        #[cfg(feature = "metalink")]
+        return metalink::fetch(descriptor, dest_dir);
+        #[cfg(not(feature = "metalink"))]
+        return Err(anyhow!("built without the metalink feature"));
+    }
+    let _ = dest_dir;
+    Err(anyhow!("{} is not a .torrent or .meta4 descriptor", descriptor))
+}
+
+/// `Read` over an HTTP(S) download. TLS certificates are verified against
+/// the bundled web PKI roots plus any configured CA bundles. When the connection drops and the server
+/// advertises byte ranges, the transfer continues from the current offset
+/// with a `Range` request instead of failing. With mirrors, it continues
+/// on the next mirror that accepts ranges when the current one fails.
+///
+/// Transfers keep to the configured download window and bandwidth cap: a
+/// ranged transfer pauses when the window closes and reconnects from its
+/// offset when it opens; one the server cannot resume runs to the end.
+pub struct HttpSource {
+    mirrors: Vec<Mirror>,
+    current: usize,
+    total_bytes: u64,
+    position: u64,
+    resumes: u32,
+    failovers: u32,
+    reader: Box<dyn Read + Send + Sync>,
+}
+
+impl HttpSource {
+    /// Starts the download, waiting for the download window first; the
+    /// server must send a `Content-Length`.
+    pub fn open(url: &str) -> Result<Self> {
+        Self::open_mirrors(&[url.to_string()])
+    }
+
+    /// Starts the download from the fastest healthy mirror of `urls`,
+    /// which must all serve the same file. A single URL is not health
+    /// checked.
+    pub fn open_mirrors(urls: &[String]) -> Result<Self> {
+        if urls.is_empty() {
+            return Err(anyhow!("no source URLs"));
+        }
+        if let Some(url) = urls.iter().find(|url| !is_url(url)) {
+            return Err(anyhow!("not an http(s) URL: {}", url));
+        }
+        schedule::wait_for_window();
+        let mut mirrors = urls
+            .iter()
+            .map(|url| Mirror::new(url))
+            .collect::<Result<Vec<_>>>()?;
+        let expected = if mirrors.len() > 1 {
+            mirrors::check_health(&mut mirrors)
+        } else {
+            None
+        };
+
+        let mut failures = Vec::new();
+        for index in mirrors::by_speed(&mirrors) {
+            let mirror = &mut mirrors[index];
+            match open_whole(mirror, expected) {
+                Ok((total_bytes, reader)) => {
+                    mirror.stats.connections += 1;
+                    return Ok(Self {
+                        mirrors,
+                        current: index,
+                        total_bytes,
+                        position: 0,
+                        resumes: 0,
+                        failovers: 0,
+                        reader,
+                    });
+                }
+                Err(err) => {
+                    mirror.record_failure(&err);
+                    failures.push(err);
+                }
+            }
+        }
+        if mirrors.len() == 1 {
+            return Err(failures.remove(0));
+        }
+        let errors: Vec<String> = mirrors
+            .iter()
+            .map(|mirror| {
+                format!(
+                    "{}: {}",
+                    mirror.url,
+                    mirror.stats.last_error.as_deref().unwrap_or("unknown error")
+                )
+            })
+            .collect();
+        Err(anyhow!("every mirror failed: {}", errors.join("; ")))
+    }
+
+    pub fn total_bytes(&self) -> u64 {
+        self.total_bytes
+    }
+
+    /// Whether the mirror currently in use accepts byte ranges.
+    pub fn accepts_ranges(&self) -> bool {
+        self.mirrors[self.current].stats.accepts_ranges
+    }
+
+    /// Times the transfer was resumed after a dropped connection.
+    pub fn resumes(&self) -> u32 {
+        self.resumes
+    }
+
+    /// Times the transfer moved to another mirror.
+    pub fn failovers(&self) -> u32 {
+        self.failovers
+    }
+
+    pub fn mirror_stats(&self) -> Vec<MirrorStats> {
+        self.mirrors.iter().map(|mirror| mirror.stats.clone()).collect()
+    }
+
+    fn resume(&mut self, cause: io::Error) -> io::Result<()> {
+        let resumable = self.mirrors.iter().any(|mirror| mirror.stats.accepts_ranges);
+        if !resumable || self.resumes >= MAX_RESUMES {
+            return Err(cause);
+        }
+        self.mirrors[self.current].record_failure(&cause);
+        self.resumes += 1;
+        std::thread::sleep(Duration::from_secs(u64::from(self.resumes)));
+        self.reconnect()
+    }
+
+    /// Continues the transfer at the current offset on a new connection,
+    /// to the current mirror or else the next one that answers.
+    fn reconnect(&mut self) -> io::Result<()> {
+        let count = self.mirrors.len();
+        let mut last_error = None;
+        for step in 0..count {
+            let index = (self.current + step) % count;
+            let mirror = &mut self.mirrors[index];
+            if !mirror.stats.healthy || !mirror.stats.accepts_ranges {
+                continue;
+            }
+            match open_range(mirror, self.position, self.total_bytes) {
+                Ok(reader) => {
+                    mirror.stats.connections += 1;
+                    if index != self.current {
+                        self.failovers += 1;
+                        self.current = index;
+                    }
+                    self.reader = reader;
+                    return Ok(());
+                }
+                Err(err) => {
+                    mirror.record_failure(&err);
+                    last_error = Some(err);
+                }
+            }
+        }
+        Err(last_error.unwrap_or_else(|| io::Error::other("no mirror accepts byte ranges")))
+    }
+}
+
+type BodyReader = Box<dyn Read + Send + Sync>;
+
+/// The whole file from `mirror`, which must be `expected` bytes if known.
+fn open_whole(mirror: &mut Mirror, expected: Option<u64>) -> Result<(u64, BodyReader)> {
+    let response = mirror
+        .agent
+        .get(&mirror.url)
+        .call()
+        .map_err(|err| anyhow!("fetch {} failed: {}", mirror.url, err))?;
+    let total_bytes = response
+        .header("Content-Length")
+        .and_then(|value| value.trim().parse::<u64>().ok())
+        .ok_or_else(|| anyhow!("{} did not send a Content-Length", mirror.url))?;
+    if let Some(expected) = expected.filter(|expected| *expected != total_bytes) {
+        return Err(anyhow!(
+            "{} sent {} bytes but its health check reported {}",
+            mirror.url,
+            total_bytes,
+            expected
+        ));
+    }
+    if response
+        .header("Accept-Ranges")
+        .is_some_and(|value| value.trim().eq_ignore_ascii_case("bytes"))
+    {
+        mirror.stats.accepts_ranges = true;
+    }
+    Ok((total_bytes, response.into_reader()))
+}
+
+/// `mirror`'s bytes from `position` on, of a file `total_bytes` long.
+fn open_range(mirror: &Mirror, position: u64, total_bytes: u64) -> io::Result<BodyReader> {
+    let response = mirror
+        .agent
+        .get(&mirror.url)
+        .set("Range", &format!("bytes={}-", position))
+        .call()
+        .map_err(|err| io::Error::other(format!("resume at {} failed: {}", position, err)))?;
+    let expected = format!("bytes {}-", position);
+    let content_range = response.header("Content-Range").map(str::trim);
+    let range_ok = content_range.is_some_and(|value| value.starts_with(&expected));
+    if response.status() != 206 || !range_ok {
+        return Err(io::Error::other(format!(
+            "server ignored the range request at offset {} (status {})",
+            position,
+            response.status()
+        )));
+    }
+    let total = content_range
+        .and_then(|value| value.rsplit_once('/'))
+        .and_then(|(_, total)| total.parse::<u64>().ok());
+    if let Some(total) = total.filter(|total| *total != total_bytes) {
+        return Err(io::Error::other(format!(
+            "{} serves {} bytes, not {}",
+            mirror.url, total, total_bytes
+        )));
+    }
+    Ok(response.into_reader())
+}
+
+impl Read for HttpSource {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let remaining = self.total_bytes - self.position;
+        if remaining == 0 || buf.is_empty() {
+            return Ok(0);
+        }
+        let len = buf.len().min(remaining.min(usize::MAX as u64) as usize);
+        if self.accepts_ranges() && schedule::wait_for_window() {
+            // The idle connection has likely been dropped by now.
+            self.reconnect()?;
+        }
+        loop {
+            match self.reader.read(&mut buf[..len]) {
+                Ok(0) => self.resume(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    format!("connection closed at {} of {} bytes", self.position, self.total_bytes),
+                ))?,
+                Ok(read) => {
+                    self.position += read as u64;
+                    self.mirrors[self.current].stats.bytes += read as u64;
+                    schedule::throttle(read);
+                    return Ok(read);
+                }
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => self.resume(err)?,
+            }
+        }
+    }
+}
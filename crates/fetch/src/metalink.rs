@@ -0,0 +1,157 @@
+//! Metalink 4 (RFC 5854, `.meta4`) descriptors: the first `<file>` is
+//! fetched from its mirrors in priority order, piece by piece against the
+//! SHA-256 `<pieces>` hashes, or as one piece against the file's SHA-256.
+
+use crate::pieces::{download, read_descriptor, PieceHash, PieceLayout};
+use crate::FetchResult;
+use anyhow::{anyhow, Result};
+use std::path::Path;
+
+pub fn fetch(metalink: &str, dest_dir: &Path) -> Result<FetchResult> {
+    let text = String::from_utf8(read_descriptor(metalink)?)
+        .map_err(|_| anyhow!("metalink is not UTF-8"))?;
+    let (layout, file_sha256) = parse_metalink(&text)?;
+    let result = download(&layout, dest_dir)?;
+    if let Some(expected) = file_sha256 {
+        use sha2::Digest;
+        let mut hasher = sha2::Sha256::new();
+        std::io::copy(&mut std::fs::File::open(&result.path)?, &mut hasher)?;
+        if hasher.finalize().to_vec() != expected {
+            return Err(anyhow!("{} does not match the metalink sha-256", result.path.display()));
+        }
+    }
+    Ok(result)
+}
+
+/// The piece layout plus the whole-file SHA-256 when the file also has
+/// piece hashes (otherwise that hash is the single piece).
+fn parse_metalink(text: &str) -> Result<(PieceLayout, Option<Vec<u8>>)> {
+    let (file_tag, file) = element(text, "file").ok_or_else(|| anyhow!("metalink has no <file>"))?;
+    let name = attribute(file_tag, "name").ok_or_else(|| anyhow!("metalink <file> has no name"))?;
+    let total_bytes = element(file, "size")
+        .and_then(|(_, size)| size.trim().parse::<u64>().ok())
+        .ok_or_else(|| anyhow!("metalink <file> has no size"))?;
+
+    // Piece hashes carry no type, so only the file's own hash matches here.
+    let mut file_sha256 = None;
+    let mut rest = file;
+    while let Some((tag, body)) = element(rest, "hash") {
+        if attribute(tag, "type").is_some_and(|kind| kind.eq_ignore_ascii_case("sha-256")) {
+            file_sha256 = Some(decode_hex(body)?);
+        }
+        rest = after(rest, body);
+    }
+
+    let pieces = element(file, "pieces").filter(|(tag, _)| {
+        attribute(tag, "type").is_some_and(|kind| kind.eq_ignore_ascii_case("sha-256"))
+    });
+    let (piece_bytes, hashes, file_sha256) = match pieces {
+        Some((tag, body)) => {
+            let length = attribute(tag, "length")
+                .and_then(|length| length.parse::<u64>().ok())
+                .ok_or_else(|| anyhow!("metalink <pieces> has no length"))?;
+            let mut hashes = Vec::new();
+            let mut rest = body;
+            while let Some((_, hash)) = element(rest, "hash") {
+                hashes.push(decode_hex(hash)?);
+                rest = after(rest, hash);
+            }
+            (length, hashes, file_sha256)
+        }
+        None => {
+            let whole = file_sha256.ok_or_else(|| anyhow!("metalink <file> has no sha-256 hash"))?;
+            (total_bytes.max(1), vec![whole], None)
+        }
+    };
+
+    let mut mirrors = Vec::new();
+    let mut rest = file;
+    while let Some((tag, url)) = element(rest, "url") {
+        let priority = attribute(tag, "priority")
+            .and_then(|priority| priority.parse::<u32>().ok())
+            .unwrap_or(u32::MAX);
+        mirrors.push((priority, unescape(url.trim())));
+        rest = after(rest, url);
+    }
+    mirrors.sort_by_key(|(priority, _)| *priority);
+
+    Ok((
+        PieceLayout {
+            name: unescape(name),
+            total_bytes,
+            piece_bytes,
+            hashes,
+            algorithm: PieceHash::Sha256,
+            sources: mirrors.into_iter().map(|(_, url)| url).collect(),
+        },
+        file_sha256,
+    ))
+}
+
+/// First `<name ...>body</name>` in `xml`: the opening tag's attributes and
+/// the body.
+fn element<'a>(xml: &'a str, name: &str) -> Option<(&'a str, &'a str)> {
+    let open = format!("<{}", name);
+    let mut from = 0;
+    loop {
+        let start = from + xml[from..].find(&open)?;
+        let after_name = start + open.len();
+        // Skip longer names sharing the prefix (`<hashes` for `<hash`).
+        if !matches!(xml[after_name..].chars().next(), Some('>' | ' ' | '\t' | '\r' | '\n')) {
+            from = after_name;
+            continue;
+        }
+        let tag_end = after_name + xml[after_name..].find('>')?;
+        let close = format!("</{}>", name);
+        let body_end = tag_end + 1 + xml[tag_end + 1..].find(&close)?;
+        return Some((&xml[after_name..tag_end], &xml[tag_end + 1..body_end]));
+    }
+}
+
+/// The part of `xml` after `body`, which must be a slice of it.
+fn after<'a>(xml: &'a str, body: &str) -> &'a str {
+    let offset = body.as_ptr() as usize - xml.as_ptr() as usize + body.len();
+    &xml[offset..]
+}
+
+fn attribute<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let key = format!("{}=", name);
+    let mut from = 0;
+    loop {
+        let start = from + tag[from..].find(&key)?;
+        let boundary = start == 0 || tag[..start].ends_with(char::is_whitespace);
+        let value_start = start + key.len();
+        if !boundary {
+            from = value_start;
+            continue;
+        }
+        let quote = tag[value_start..].chars().next().filter(|c| *c == '"' || *c == '\'')?;
+        let end = value_start + 1 + tag[value_start + 1..].find(quote)?;
+        return Some(&tag[value_start + 1..end]);
+    }
+}
+
+fn unescape(value: &str) -> String {
+    value
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+fn decode_hex(value: &str) -> Result<Vec<u8>> {
+    let value = value.trim();
+    if !value.len().is_multiple_of(2) {
+        return Err(anyhow!("hex must be even length"));
+    }
+    (0..value.len())
+        .step_by(2)
+        .map(|idx| {
+            value
+                .get(idx..idx + 2)
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                .ok_or_else(|| anyhow!("invalid hex in metalink"))
+        })
+        .collect()
+}
@@ -0,0 +1,140 @@
+//! Alternate URLs for one download. Before a multi-mirror transfer every
+//! mirror is health checked with a one-byte range request; the transfer
+//! starts on the fastest healthy one and fails over to the next when a
+//! connection cannot be resumed.
+
+use crate::agent;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+const PROBE_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// What one mirror did during a transfer, for reports.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MirrorStats {
+    pub url: String,
+    /// Passed the health check; a lone source is not checked.
+    pub healthy: bool,
+    /// Round trip of the health check.
+    pub probe_ms: Option<u64>,
+    pub accepts_ranges: bool,
+    /// Connections that were opened and delivered the expected range.
+    pub connections: u32,
+    pub failures: u32,
+    pub bytes: u64,
+    pub last_error: Option<String>,
+}
+
+pub(crate) struct Mirror {
+    pub(crate) url: String,
+    pub(crate) agent: ureq::Agent,
+    pub(crate) stats: MirrorStats,
+    /// Size reported by the health check.
+    total_bytes: Option<u64>,
+}
+
+impl Mirror {
+    pub(crate) fn new(url: &str) -> Result<Self> {
+        Ok(Self {
+            url: url.to_string(),
+            agent: agent(url)?,
+            stats: MirrorStats {
+                url: url.to_string(),
+                healthy: true,
+                probe_ms: None,
+                accepts_ranges: false,
+                connections: 0,
+                failures: 0,
+                bytes: 0,
+                last_error: None,
+            },
+            total_bytes: None,
+        })
+    }
+
+    pub(crate) fn record_failure(&mut self, err: &dyn std::fmt::Display) {
+        self.stats.failures += 1;
+        self.stats.last_error = Some(err.to_string());
+    }
+
+    fn probe(&mut self) {
+        let started = Instant::now();
+        let response = match self
+            .agent
+            .get(&self.url)
+            .set("Range", "bytes=0-0")
+            .timeout(PROBE_TIMEOUT)
+            .call()
+        {
+            Ok(response) => response,
+            Err(err) => return self.fail_probe(format!("health check failed: {}", err)),
+        };
+        self.stats.probe_ms = Some(started.elapsed().as_millis() as u64);
+        let total_bytes = if response.status() == 206 {
+            self.stats.accepts_ranges = true;
+            response
+                .header("Content-Range")
+                .and_then(|value| value.rsplit_once('/'))
+                .and_then(|(_, total)| total.trim().parse::<u64>().ok())
+        } else {
+            self.stats.accepts_ranges = response
+                .header("Accept-Ranges")
+                .map(|value| value.trim().eq_ignore_ascii_case("bytes"))
+                .unwrap_or(false);
+            response
+                .header("Content-Length")
+                .and_then(|value| value.trim().parse::<u64>().ok())
+        };
+        match total_bytes {
+            Some(total) => self.total_bytes = Some(total),
+            None => self.fail_probe("health check did not report the file size".to_string()),
+        }
+    }
+
+    fn fail_probe(&mut self, error: String) {
+        self.stats.healthy = false;
+        self.record_failure(&error);
+    }
+}
+
+/// Probes every mirror, then marks unhealthy the ones whose size differs
+/// from the size most healthy mirrors agree on (ties go to the fastest).
+/// Returns that size, if any mirror is healthy.
+pub(crate) fn check_health(mirrors: &mut [Mirror]) -> Option<u64> {
+    for mirror in mirrors.iter_mut() {
+        mirror.probe();
+    }
+    let mut votes: BTreeMap<u64, (usize, u64)> = BTreeMap::new();
+    for mirror in mirrors.iter().filter(|mirror| mirror.stats.healthy) {
+        let Some(total) = mirror.total_bytes else {
+            continue;
+        };
+        let vote = votes.entry(total).or_insert((0, u64::MAX));
+        vote.0 += 1;
+        vote.1 = vote.1.min(mirror.stats.probe_ms.unwrap_or(u64::MAX));
+    }
+    let expected = votes
+        .into_iter()
+        .max_by(|(_, a), (_, b)| a.0.cmp(&b.0).then(b.1.cmp(&a.1)))
+        .map(|(total, _)| total)?;
+    for mirror in mirrors.iter_mut().filter(|mirror| mirror.stats.healthy) {
+        if let Some(total) = mirror.total_bytes.filter(|total| *total != expected) {
+            mirror.fail_probe(format!(
+                "serves {} bytes where the other mirrors serve {}",
+                total, expected
+            ));
+        }
+    }
+    Some(expected)
+}
+
+/// Healthy mirrors, fastest health check first.
+pub(crate) fn by_speed(mirrors: &[Mirror]) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..mirrors.len())
+        .filter(|index| mirrors[*index].stats.healthy)
+        .collect();
+    order.sort_by_key(|index| mirrors[*index].stats.probe_ms.unwrap_or(u64::MAX));
+    order
+}
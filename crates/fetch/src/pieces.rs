@@ -0,0 +1,171 @@
+use crate::schedule::Throttled;
+use crate::{agent, FetchResult};
+use anyhow::{anyhow, Context, Result};
+use std::fs;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// Upper bound for a descriptor file fetched over HTTP.
+const MAX_DESCRIPTOR_BYTES: u64 = 16 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum PieceHash {
+    #[cfg(feature = "torrent")]
+    Sha1,
+    #[cfg(feature = "metalink")]
+    Sha256,
+}
+
+impl PieceHash {
+    fn digest(self, data: &[u8]) -> Vec<u8> {
+        match self {
+            #[cfg(feature = "torrent")]
+            PieceHash::Sha1 => {
+                use sha1::Digest;
+                sha1::Sha1::digest(data).to_vec()
+            }
+            #[cfg(feature = "metalink")]
+            PieceHash::Sha256 => {
+                use sha2::Digest;
+                sha2::Sha256::digest(data).to_vec()
+            }
+        }
+    }
+}
+
+/// One file split into equally sized pieces (the last may be shorter), each
+/// with a known hash, available from several HTTP sources.
+#[derive(Debug, Clone)]
+pub(crate) struct PieceLayout {
+    pub name: String,
+    pub total_bytes: u64,
+    pub piece_bytes: u64,
+    pub hashes: Vec<Vec<u8>>,
+    pub algorithm: PieceHash,
+    pub sources: Vec<String>,
+}
+
+/// Reads a descriptor from a path or an http(s) URL.
+pub(crate) fn read_descriptor(location: &str) -> Result<Vec<u8>> {
+    if !crate::is_url(location) {
+        return fs::read(location).with_context(|| format!("read {}", location));
+    }
+    let response = agent(location)?
+        .get(location)
+        .call()
+        .map_err(|err| anyhow!("fetch {} failed: {}", location, err))?;
+    let mut bytes = Vec::new();
+    response
+        .into_reader()
+        .take(MAX_DESCRIPTOR_BYTES)
+        .read_to_end(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// Downloads every piece of `layout` into `dest_dir/<name>`. Pieces already
+/// on disk with a matching hash are kept, so an interrupted fetch resumes;
+/// a piece that fails its hash is fetched again from the next source.
+pub(crate) fn download(layout: &PieceLayout, dest_dir: &Path) -> Result<FetchResult> {
+    let name = Path::new(&layout.name)
+        .file_name()
+        .filter(|name| *name != "..")
+        .ok_or_else(|| anyhow!("invalid file name {}", layout.name))?;
+    if layout.sources.is_empty() {
+        return Err(anyhow!("{} has no HTTP sources", layout.name));
+    }
+    let expected_pieces = layout.total_bytes.div_ceil(layout.piece_bytes.max(1));
+    if layout.piece_bytes == 0 || layout.hashes.len() as u64 != expected_pieces {
+        return Err(anyhow!(
+            "{} lists {} piece hashes for {} pieces",
+            layout.name,
+            layout.hashes.len(),
+            expected_pieces
+        ));
+    }
+
+    fs::create_dir_all(dest_dir).with_context(|| format!("create dir {}", dest_dir.display()))?;
+    let path = dest_dir.join(name);
+    let existing = fs::metadata(&path)
+        .map(|meta| meta.len() == layout.total_bytes)
+        .unwrap_or(false);
+    let mut file = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&path)
+        .with_context(|| format!("open {}", path.display()))?;
+    file.set_len(layout.total_bytes)?;
+
+    let mut pieces_reused = 0;
+    let mut pieces_refetched = 0;
+    let mut buffer = Vec::new();
+    for (index, expected) in layout.hashes.iter().enumerate() {
+        let offset = index as u64 * layout.piece_bytes;
+        let len = layout.piece_bytes.min(layout.total_bytes - offset);
+        if existing {
+            buffer.resize(len as usize, 0);
+            file.seek(SeekFrom::Start(offset))?;
+            file.read_exact(&mut buffer)?;
+            if layout.algorithm.digest(&buffer) == *expected {
+                pieces_reused += 1;
+                continue;
+            }
+        }
+
+        let mut failures = Vec::new();
+        for attempt in 0..layout.sources.len() {
+            let source = &layout.sources[(index + attempt) % layout.sources.len()];
+            match fetch_range(source, offset, len, layout.total_bytes) {
+                Ok(data) if layout.algorithm.digest(&data) == *expected => {
+                    file.seek(SeekFrom::Start(offset))?;
+                    file.write_all(&data)?;
+                    break;
+                }
+                Ok(_) => failures.push(format!("{}: hash mismatch", source)),
+                Err(err) => failures.push(format!("{}: {}", source, err)),
+            }
+        }
+        if failures.len() == layout.sources.len() {
+            return Err(anyhow!(
+                "piece {} of {} failed from every source: {}",
+                index,
+                layout.name,
+                failures.join("; ")
+            ));
+        }
+        if !failures.is_empty() {
+            pieces_refetched += 1;
+        }
+    }
+    file.sync_all().ok();
+
+    Ok(FetchResult {
+        path,
+        total_bytes: layout.total_bytes,
+        pieces: layout.hashes.len(),
+        pieces_reused,
+        pieces_refetched,
+    })
+}
+
+fn fetch_range(url: &str, offset: u64, len: u64, total_bytes: u64) -> Result<Vec<u8>> {
+    crate::schedule::wait_for_window();
+    let whole = offset == 0 && len == total_bytes;
+    let response = agent(url)?
+        .get(url)
+        .set("Range", &format!("bytes={}-{}", offset, offset + len - 1))
+        .call()
+        .map_err(|err| anyhow!("request failed: {}", err))?;
+    if response.status() != 206 && !(whole && response.status() == 200) {
+        return Err(anyhow!("range request answered with status {}", response.status()));
+    }
+    let mut data = Vec::with_capacity(len as usize);
+    Throttled(response.into_reader())
+        .take(len + 1)
+        .read_to_end(&mut data)?;
+    if data.len() as u64 != len {
+        return Err(anyhow!("expected {} bytes, got {}", len, data.len()));
+    }
+    Ok(data)
+}
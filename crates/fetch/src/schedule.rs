@@ -0,0 +1,188 @@
+//! The download window and bandwidth cap from the network settings, for
+//! shops whose WAN cannot absorb daytime ISO downloads. Both apply to
+//! `HttpSource` and to torrent and metalink piece fetches.
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Longest single sleep while waiting for the window, so clock and
+/// time-zone changes are noticed.
+const WINDOW_POLL: Duration = Duration::from_secs(60);
+
+/// Local times of day downloads may run, as `HH:MM`. An `end` earlier
+/// than `start` crosses midnight, e.g. `22:00`–`06:00`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FetchWindow {
+    pub start: String,
+    pub end: String,
+}
+
+impl FetchWindow {
+    /// `start` and `end` as minutes after midnight.
+    fn bounds(&self) -> Result<(u32, u32)> {
+        let start = parse_time_of_day(&self.start)?;
+        let end = parse_time_of_day(&self.end)?;
+        if start == end {
+            return Err(anyhow!("fetch window {}-{} is empty", self.start, self.end));
+        }
+        Ok((start, end))
+    }
+}
+
+fn parse_time_of_day(text: &str) -> Result<u32> {
+    let invalid = || anyhow!("invalid time of day {:?}; expected HH:MM", text);
+    let (hours, minutes) = text.trim().split_once(':').ok_or_else(invalid)?;
+    let hours: u32 = hours.parse().map_err(|_| invalid())?;
+    let minutes: u32 = minutes.parse().map_err(|_| invalid())?;
+    if hours > 23 || minutes > 59 {
+        return Err(invalid());
+    }
+    Ok(hours * 60 + minutes)
+}
+
+/// Minutes from `now` until the window `(start, end)` opens; 0 when open.
+fn minutes_until_open((start, end): (u32, u32), now: u32) -> u32 {
+    let open = if start < end {
+        now >= start && now < end
+    } else {
+        now >= start || now < end
+    };
+    if open {
+        0
+    } else {
+        (start + 24 * 60 - now) % (24 * 60)
+    }
+}
+
+pub(crate) struct Schedule {
+    window: Option<(u32, u32)>,
+    bytes_per_sec: Option<f64>,
+    /// When the bandwidth already granted to readers is used up.
+    next_free: Mutex<Option<Instant>>,
+}
+
+impl Schedule {
+    pub(crate) fn new(window: Option<&FetchWindow>, max_mbps: Option<f64>) -> Result<Self> {
+        let bytes_per_sec = match max_mbps {
+            Some(mbps) if !(mbps.is_finite() && mbps > 0.0) => {
+                return Err(anyhow!("max_mbps must be a positive number"))
+            }
+            Some(mbps) => Some(mbps * 1_000_000.0 / 8.0),
+            None => None,
+        };
+        Ok(Self {
+            window: window.map(FetchWindow::bounds).transpose()?,
+            bytes_per_sec,
+            next_free: Mutex::new(None),
+        })
+    }
+
+    pub(crate) fn unlimited() -> Self {
+        Self {
+            window: None,
+            bytes_per_sec: None,
+            next_free: Mutex::new(None),
+        }
+    }
+
+    fn until_open(&self) -> Option<Duration> {
+        let window = self.window?;
+        match minutes_until_open(window, local_minute_of_day()) {
+            0 => None,
+            minutes => Some(Duration::from_secs(u64::from(minutes) * 60)),
+        }
+    }
+
+    /// Blocks until the window is open. Returns whether it had to wait.
+    fn wait_for_window(&self) -> bool {
+        let mut waited = false;
+        while let Some(wait) = self.until_open() {
+            std::thread::sleep(wait.min(WINDOW_POLL));
+            waited = true;
+        }
+        waited
+    }
+
+    /// Charges `bytes` against the cap, sleeping until the process is back
+    /// under it. The cap is shared by every transfer in the process.
+    fn throttle(&self, bytes: usize) {
+        let Some(rate) = self.bytes_per_sec else {
+            return;
+        };
+        let cost = Duration::from_secs_f64(bytes as f64 / rate);
+        let now = Instant::now();
+        let done = {
+            let mut next_free = self.next_free.lock().unwrap_or_else(|err| err.into_inner());
+            let start = next_free.map_or(now, |free| free.max(now));
+            *next_free = Some(start + cost);
+            start + cost
+        };
+        std::thread::sleep(done.saturating_duration_since(now));
+    }
+}
+
+/// How long until the configured window opens; `None` when downloads may
+/// run now. Lets callers log a wait before they start a transfer.
+pub fn window_wait() -> Option<Duration> {
+    crate::network::schedule().until_open()
+}
+
+/// Blocks until the configured window is open. Returns whether it waited.
+pub(crate) fn wait_for_window() -> bool {
+    crate::network::schedule().wait_for_window()
+}
+
+pub(crate) fn throttle(bytes: usize) {
+    crate::network::schedule().throttle(bytes)
+}
+
+/// A reader held to the bandwidth cap.
+#[cfg(any(feature = "torrent", feature = "metalink"))]
+pub(crate) struct Throttled<R>(pub R);
+
+#[cfg(any(feature = "torrent", feature = "metalink"))]
+impl<R: std::io::Read> std::io::Read for Throttled<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.0.read(buf)?;
+        throttle(read);
+        Ok(read)
+    }
+}
+
+#[cfg(unix)]
+fn local_minute_of_day() -> u32 {
+    // SAFETY: `localtime_r` only writes the `tm` it is given.
+    unsafe {
+        let now = libc::time(std::ptr::null_mut());
+        let mut tm: libc::tm = std::mem::zeroed();
+        if libc::localtime_r(&now, &mut tm).is_null() {
+            return utc_minute_of_day();
+        }
+        (tm.tm_hour * 60 + tm.tm_min) as u32
+    }
+}
+
+#[cfg(windows)]
+fn local_minute_of_day() -> u32 {
+    // SAFETY: `GetLocalTime` has no preconditions.
+    let now = unsafe { windows::Win32::System::SystemInformation::GetLocalTime() };
+    u32::from(now.wHour) * 60 + u32::from(now.wMinute)
+}
+
+#[cfg(not(any(unix, windows)))]
+fn local_minute_of_day() -> u32 {
+    utc_minute_of_day()
+}
+
+#[cfg_attr(windows, allow(dead_code))]
+fn utc_minute_of_day() -> u32 {
+    let secs = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0);
+    ((secs % 86_400) / 60) as u32
+}
+
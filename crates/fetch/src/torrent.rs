@@ -0,0 +1,148 @@
+//! Single-file torrents fetched from their web seeds (BEP 19 `url-list`),
+//! with every piece checked against the torrent's SHA-1 piece hashes. Peer
+//! connections are not made.
+
+use crate::pieces::{download, read_descriptor, PieceHash, PieceLayout};
+use crate::FetchResult;
+use anyhow::{anyhow, Result};
+use std::path::Path;
+
+pub fn fetch(torrent: &str, dest_dir: &Path) -> Result<FetchResult> {
+    let layout = parse_torrent(&read_descriptor(torrent)?)?;
+    download(&layout, dest_dir)
+}
+
+fn parse_torrent(data: &[u8]) -> Result<PieceLayout> {
+    let (root, _) = Bencode::parse(data)?;
+    let info = root.get("info").ok_or_else(|| anyhow!("torrent has no info dictionary"))?;
+    if info.get("files").is_some() {
+        return Err(anyhow!("multi-file torrents are not supported; images are single files"));
+    }
+    let name = info
+        .get("name")
+        .and_then(Bencode::as_str)
+        .ok_or_else(|| anyhow!("torrent has no name"))?
+        .to_string();
+    let total_bytes = info
+        .get("length")
+        .and_then(Bencode::as_u64)
+        .ok_or_else(|| anyhow!("torrent has no length"))?;
+    let piece_bytes = info
+        .get("piece length")
+        .and_then(Bencode::as_u64)
+        .ok_or_else(|| anyhow!("torrent has no piece length"))?;
+    let pieces = match info.get("pieces") {
+        Some(Bencode::Bytes(bytes)) if bytes.len().is_multiple_of(20) => bytes,
+        _ => return Err(anyhow!("torrent pieces must be 20-byte SHA-1 hashes")),
+    };
+
+    let seeds: Vec<String> = match root.get("url-list") {
+        Some(Bencode::List(items)) => items.iter().filter_map(Bencode::as_str).map(str::to_string).collect(),
+        Some(item) => item.as_str().map(str::to_string).into_iter().collect(),
+        None => Vec::new(),
+    };
+    if seeds.is_empty() {
+        return Err(anyhow!(
+            "torrent has no web seeds (url-list); peer-to-peer transfer is not supported"
+        ));
+    }
+    // A seed ending in `/` names a directory holding the file.
+    let sources = seeds
+        .into_iter()
+        .map(|seed| if seed.ends_with('/') { format!("{}{}", seed, name) } else { seed })
+        .collect();
+
+    Ok(PieceLayout {
+        name,
+        total_bytes,
+        piece_bytes,
+        hashes: pieces.chunks(20).map(<[u8]>::to_vec).collect(),
+        algorithm: PieceHash::Sha1,
+        sources,
+    })
+}
+
+enum Bencode {
+    Int(i64),
+    Bytes(Vec<u8>),
+    List(Vec<Bencode>),
+    Dict(Vec<(Vec<u8>, Bencode)>),
+}
+
+impl Bencode {
+    /// Parses one value and returns it with the bytes that follow it.
+    fn parse(data: &[u8]) -> Result<(Bencode, &[u8])> {
+        match data.first() {
+            Some(b'i') => {
+                let end = data
+                    .iter()
+                    .position(|byte| *byte == b'e')
+                    .ok_or_else(|| anyhow!("unterminated bencode integer"))?;
+                let value = std::str::from_utf8(&data[1..end])?.parse()?;
+                Ok((Bencode::Int(value), &data[end + 1..]))
+            }
+            Some(b'l') => {
+                let mut rest = &data[1..];
+                let mut items = Vec::new();
+                while rest.first() != Some(&b'e') {
+                    let (item, next) = Bencode::parse(rest)?;
+                    items.push(item);
+                    rest = next;
+                }
+                Ok((Bencode::List(items), &rest[1..]))
+            }
+            Some(b'd') => {
+                let mut rest = &data[1..];
+                let mut entries = Vec::new();
+                while rest.first() != Some(&b'e') {
+                    let (key, next) = Bencode::parse(rest)?;
+                    let Bencode::Bytes(key) = key else {
+                        return Err(anyhow!("bencode dictionary key is not a string"));
+                    };
+                    let (value, next) = Bencode::parse(next)?;
+                    entries.push((key, value));
+                    rest = next;
+                }
+                Ok((Bencode::Dict(entries), &rest[1..]))
+            }
+            Some(b'0'..=b'9') => {
+                let colon = data
+                    .iter()
+                    .position(|byte| *byte == b':')
+                    .ok_or_else(|| anyhow!("bencode string without length"))?;
+                let len: usize = std::str::from_utf8(&data[..colon])?.parse()?;
+                let start = colon + 1;
+                let bytes = data
+                    .get(start..start + len)
+                    .ok_or_else(|| anyhow!("bencode string runs past the end"))?;
+                Ok((Bencode::Bytes(bytes.to_vec()), &data[start + len..]))
+            }
+            Some(other) => Err(anyhow!("unexpected bencode byte {:#x}", other)),
+            None => Err(anyhow!("truncated bencode data")),
+        }
+    }
+
+    fn get(&self, key: &str) -> Option<&Bencode> {
+        match self {
+            Bencode::Dict(entries) => entries
+                .iter()
+                .find(|(candidate, _)| candidate == key.as_bytes())
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    fn as_str(&self) -> Option<&str> {
+        match self {
+            Bencode::Bytes(bytes) => std::str::from_utf8(bytes).ok(),
+            _ => None,
+        }
+    }
+
+    fn as_u64(&self) -> Option<u64> {
+        match self {
+            Bencode::Int(value) => u64::try_from(*value).ok(),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,46 @@
+/* C ABI of the Phoenix engine (phoenix-ffi).
+ *
+ * Functions returning `char *` return a JSON envelope,
+ * {"ok": true, "result": ...} or {"ok": false, "error": "..."},
+ * which the caller frees with phoenix_string_free. Strings passed in are
+ * NUL-terminated UTF-8. */
+
+#ifndef PHOENIX_H
+#define PHOENIX_H
+
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define PHOENIX_FFI_ABI_VERSION 1
+
+/* Called for every log line of a run; returning non-zero cancels it. */
+typedef int32_t (*phoenix_progress_callback)(void *user_data, const char *workflow,
+                                             const char *message, uint64_t elapsed_ms);
+
+/* PHOENIX_FFI_ABI_VERSION of the loaded library. */
+uint32_t phoenix_abi_version(void);
+
+void phoenix_string_free(char *value);
+
+/* Used by later runs; NULL removes it. user_data must stay valid until
+ * the callback is replaced. */
+void phoenix_set_progress_callback(phoenix_progress_callback callback, void *user_data);
+
+char *phoenix_device_graph_json(void);
+
+/* definition_json, or the file at definition_path when it is NULL.
+ * report_base may be NULL for the current directory. */
+char *phoenix_run_workflow_json(const char *definition_json, const char *definition_path,
+                                const char *report_base);
+
+/* signing_key_hex may be NULL. */
+char *phoenix_verify_report_json(const char *report_root, const char *signing_key_hex);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
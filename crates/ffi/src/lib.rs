@@ -0,0 +1,189 @@
+//! C ABI of the engine for non-Rust GUIs and vendor tools; the header is
+//! `include/phoenix.h`. Every call that returns data returns a JSON
+//! envelope, `{"ok": true, "result": ...}` or `{"ok": false, "error": ...}`,
+//! as a string the caller frees with `phoenix_string_free`. Breaking
+//! changes bump `PHOENIX_FFI_ABI_VERSION`.
+
+use anyhow::{anyhow, Result};
+use phoenix_workflow_engine::{
+    build_device_graph, run_workflow_definition_with_report, with_cancel_token, with_log_listener,
+    CancelToken, LogEntry,
+};
+use std::ffi::{c_char, c_void, CStr, CString};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+pub const PHOENIX_FFI_ABI_VERSION: u32 = 1;
+
+/// Called for every log line of a run. Returning non-zero cancels it.
+pub type PhoenixProgressCallback = extern "C" fn(
+    user_data: *mut c_void,
+    workflow: *const c_char,
+    message: *const c_char,
+    elapsed_ms: u64,
+) -> i32;
+
+struct Registered {
+    callback: PhoenixProgressCallback,
+    user_data: *mut c_void,
+}
+
+// The caller owns `user_data` and promises it may be used from the
+// thread running the workflow.
+unsafe impl Send for Registered {}
+
+static PROGRESS: Mutex<Option<Registered>> = Mutex::new(None);
+
+#[no_mangle]
+pub extern "C" fn phoenix_abi_version() -> u32 {
+    PHOENIX_FFI_ABI_VERSION
+}
+
+/// Frees a string returned by this library.
+///
+/// # Safety
+/// `value` must come from this library and not be freed twice.
+#[no_mangle]
+pub unsafe extern "C" fn phoenix_string_free(value: *mut c_char) {
+    if !value.is_null() {
+        drop(CString::from_raw(value));
+    }
+}
+
+/// Registers the progress callback used by later runs; `NULL` removes it.
+///
+/// # Safety
+/// `user_data` is passed back as is and must stay valid until the
+/// callback is replaced.
+#[no_mangle]
+pub unsafe extern "C" fn phoenix_set_progress_callback(
+    callback: Option<PhoenixProgressCallback>,
+    user_data: *mut c_void,
+) {
+    let registered = callback.map(|callback| Registered {
+        callback,
+        user_data,
+    });
+    *PROGRESS.lock().unwrap_or_else(|err| err.into_inner()) = registered;
+}
+
+/// The device graph of this host.
+#[no_mangle]
+pub extern "C" fn phoenix_device_graph_json() -> *mut c_char {
+    envelope(build_device_graph().and_then(|graph| Ok(serde_json::to_value(graph)?)))
+}
+
+/// Runs a workflow definition given as JSON text, or loaded from
+/// `definition_path` when `definition_json` is `NULL`. `report_base` may be
+/// `NULL` for the current directory.
+///
+/// # Safety
+/// Non-null arguments must be NUL-terminated UTF-8 strings.
+#[no_mangle]
+pub unsafe extern "C" fn phoenix_run_workflow_json(
+    definition_json: *const c_char,
+    definition_path: *const c_char,
+    report_base: *const c_char,
+) -> *mut c_char {
+    let run = || -> Result<serde_json::Value> {
+        let definition = match (read_str(definition_json)?, read_str(definition_path)?) {
+            (Some(json), _) => serde_json::from_str(json)
+                .map_err(|err| anyhow!("invalid workflow definition: {}", err))?,
+            (None, Some(path)) => phoenix_content::load_workflow_definition(path)?,
+            (None, None) => return Err(anyhow!("no workflow definition given")),
+        };
+        let report_base = PathBuf::from(read_str(report_base)?.unwrap_or("."));
+        let token = CancelToken::new();
+        let listener_token = token.clone();
+        let listener = Box::new(move |workflow: &str, entry: &LogEntry| {
+            if report_progress(workflow, entry) {
+                listener_token.cancel();
+            }
+        });
+        let result = with_log_listener(listener, || {
+            with_cancel_token(&token, || {
+                run_workflow_definition_with_report(&definition, report_base)
+            })
+        })?;
+        let steps: Vec<serde_json::Value> = result
+            .steps
+            .iter()
+            .map(|step| {
+                serde_json::json!({
+                    "id": step.id,
+                    "action": step.action,
+                    "report_root": step.report_root.as_ref().map(|root| root.display().to_string()),
+                    "duration_ms": step.duration_ms as u64,
+                    "reused": step.reused,
+                    "hooks": step.hooks
+                })
+            })
+            .collect();
+        Ok(serde_json::json!({
+            "report_root": result.report.root.display().to_string(),
+            "steps": steps
+        }))
+    };
+    envelope(run())
+}
+
+/// Verifies a report bundle; `signing_key_hex` may be `NULL`.
+///
+/// # Safety
+/// Non-null arguments must be NUL-terminated UTF-8 strings.
+#[no_mangle]
+pub unsafe extern "C" fn phoenix_verify_report_json(
+    report_root: *const c_char,
+    signing_key_hex: *const c_char,
+) -> *mut c_char {
+    let verify = || -> Result<serde_json::Value> {
+        let root = read_str(report_root)?.ok_or_else(|| anyhow!("no report root given"))?;
+        let verification = phoenix_report::verify_report_bundle(root, read_str(signing_key_hex)?)?;
+        Ok(serde_json::to_value(verification)?)
+    };
+    envelope(verify())
+}
+
+/// Passes a log line to the registered callback; `true` asks to cancel.
+fn report_progress(workflow: &str, entry: &LogEntry) -> bool {
+    // Copied out, so the callback may register another one.
+    let registered = PROGRESS
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .as_ref()
+        .map(|registered| (registered.callback, registered.user_data));
+    let Some((callback, user_data)) = registered else {
+        return false;
+    };
+    let (Ok(workflow), Ok(message)) = (CString::new(workflow), CString::new(entry.message.as_str()))
+    else {
+        return false;
+    };
+    callback(
+        user_data,
+        workflow.as_ptr(),
+        message.as_ptr(),
+        entry.elapsed_ms,
+    ) != 0
+}
+
+unsafe fn read_str<'a>(value: *const c_char) -> Result<Option<&'a str>> {
+    if value.is_null() {
+        return Ok(None);
+    }
+    CStr::from_ptr(value)
+        .to_str()
+        .map(Some)
+        .map_err(|_| anyhow!("argument is not valid UTF-8"))
+}
+
+fn envelope(result: Result<serde_json::Value>) -> *mut c_char {
+    let value = match result {
+        Ok(result) => serde_json::json!({ "ok": true, "result": result }),
+        Err(err) => serde_json::json!({ "ok": false, "error": format!("{:#}", err) }),
+    };
+    // JSON text escapes NUL, so this cannot fail.
+    CString::new(value.to_string())
+        .unwrap_or_default()
+        .into_raw()
+}
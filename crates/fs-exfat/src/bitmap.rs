@@ -0,0 +1,127 @@
+//! Which parts of an exFAT volume are in use, from its allocation bitmap.
+//! The bitmap is found through its entry in the root directory; both are
+//! read through the FAT, or as contiguous clusters where the FAT has no
+//! chain for them.
+
+use crate::{parse_boot_sector, read_bytes, read_u32, read_u64, FILE_SYSTEM_NAME};
+use anyhow::{anyhow, Result};
+use std::io::{Read, Seek};
+use std::ops::Range;
+
+const ENTRY_BYTES: usize = 32;
+const BITMAP_ENTRY: u8 = 0x81;
+const END_OF_DIRECTORY: u8 = 0x00;
+const LAST_CLUSTER: u32 = 0xFFFF_FFF7;
+
+/// Byte ranges of the exFAT volume starting `offset` bytes into `device`
+/// that hold anything, relative to the volume start: everything before
+/// the cluster heap, then every allocated cluster. Adjacent clusters are
+/// merged into one range.
+pub fn exfat_used_ranges<D: Read + Seek>(device: &mut D, offset: u64) -> Result<Vec<Range<u64>>> {
+    let first = read_bytes(device, offset, 512)?;
+    if &first[3..11] != FILE_SYSTEM_NAME || !(9..=12).contains(&first[108]) {
+        return Err(anyhow!("not an exFAT volume"));
+    }
+    let boot = parse_boot_sector(&read_bytes(device, offset, 1 << first[108])?);
+    if boot.sectors_per_cluster == 0 || boot.cluster_heap_offset == 0 {
+        return Err(anyhow!("exFAT boot sector has an invalid layout"));
+    }
+    let volume = Volume {
+        offset,
+        fat: offset + boot.fat_offset as u64 * boot.bytes_per_sector as u64,
+        heap: boot.cluster_heap_offset as u64 * boot.bytes_per_sector as u64,
+        cluster_bytes: boot.cluster_bytes(),
+        cluster_count: boot.cluster_count,
+    };
+
+    let (bitmap_cluster, bitmap_bytes) = find_bitmap(device, &volume, boot.first_cluster_of_root_directory)?;
+    let bitmap_bytes = bitmap_bytes.min(boot.cluster_count.div_ceil(8) as u64);
+    let bitmap = volume.read_chain(device, bitmap_cluster, bitmap_bytes)?;
+
+    let mut ranges = Vec::new();
+    ranges.push(0..volume.heap);
+    for (index, byte) in bitmap.iter().enumerate() {
+        if *byte == 0 {
+            continue;
+        }
+        for bit in 0..8 {
+            let cluster = index as u64 * 8 + bit;
+            if byte & (1 << bit) == 0 || cluster >= boot.cluster_count as u64 {
+                continue;
+            }
+            let start = volume.heap + cluster * volume.cluster_bytes;
+            match ranges.last_mut() {
+                Some(last) if last.end == start => last.end += volume.cluster_bytes,
+                _ => ranges.push(start..start + volume.cluster_bytes),
+            }
+        }
+    }
+    Ok(ranges)
+}
+
+struct Volume {
+    offset: u64,
+    /// Device offset of the first FAT.
+    fat: u64,
+    /// Offset of the cluster heap from the volume start.
+    heap: u64,
+    cluster_bytes: u64,
+    cluster_count: u32,
+}
+
+impl Volume {
+    fn cluster_offset(&self, cluster: u32) -> u64 {
+        self.offset + self.heap + (cluster as u64 - 2) * self.cluster_bytes
+    }
+
+    fn is_valid(&self, cluster: u32) -> bool {
+        (2..self.cluster_count + 2).contains(&cluster)
+    }
+
+    /// The first `len` bytes of the chain starting at `cluster`.
+    fn read_chain<D: Read + Seek>(&self, device: &mut D, mut cluster: u32, len: u64) -> Result<Vec<u8>> {
+        let mut data = Vec::with_capacity(len as usize);
+        while (data.len() as u64) < len {
+            if !self.is_valid(cluster) {
+                return Err(anyhow!("exFAT cluster chain leaves the cluster heap at {}", cluster));
+            }
+            let want = self.cluster_bytes.min(len - data.len() as u64);
+            data.extend(read_bytes(device, self.cluster_offset(cluster), want)?);
+            let next = read_u32(&read_bytes(device, self.fat + cluster as u64 * 4, 4)?, 0);
+            cluster = match next {
+                0 => cluster + 1,
+                next if next >= LAST_CLUSTER => break,
+                next => next,
+            };
+        }
+        if (data.len() as u64) < len {
+            return Err(anyhow!("exFAT cluster chain ends after {} of {} bytes", data.len(), len));
+        }
+        Ok(data)
+    }
+}
+
+/// First cluster and length of the first allocation bitmap, from the root
+/// directory.
+fn find_bitmap<D: Read + Seek>(device: &mut D, volume: &Volume, root: u32) -> Result<(u32, u64)> {
+    let mut cluster = root;
+    for _ in 0..volume.cluster_count {
+        if !volume.is_valid(cluster) {
+            break;
+        }
+        let entries = read_bytes(device, volume.cluster_offset(cluster), volume.cluster_bytes)?;
+        for entry in entries.chunks_exact(ENTRY_BYTES) {
+            match entry[0] {
+                END_OF_DIRECTORY => return Err(anyhow!("exFAT root directory has no allocation bitmap")),
+                // Bit 0 of the flags picks the bitmap of the second FAT.
+                BITMAP_ENTRY if entry[1] & 1 == 0 => return Ok((read_u32(entry, 20), read_u64(entry, 24))),
+                _ => {}
+            }
+        }
+        cluster = match read_u32(&read_bytes(device, volume.fat + cluster as u64 * 4, 4)?, 0) {
+            0 => cluster + 1,
+            next => next,
+        };
+    }
+    Err(anyhow!("exFAT root directory has no allocation bitmap"))
+}
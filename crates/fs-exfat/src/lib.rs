@@ -0,0 +1,339 @@
+//! Read-only checks of an exFAT boot region: the main and backup boot
+//! sectors, their checksum sectors and the layout parameters. Interrupted
+//! formats tend to leave a stale checksum or a backup region that no longer
+//! matches the main one, which some firmware refuses to boot.
+
+use anyhow::{anyhow, Context, Result};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+pub mod bitmap;
+
+pub use bitmap::exfat_used_ranges;
+
+/// Sectors in each boot region: boot sector, eight extended boot sectors,
+/// OEM parameters, reserved, checksum.
+const BOOT_REGION_SECTORS: u64 = 12;
+const FILE_SYSTEM_NAME: &[u8; 8] = b"EXFAT   ";
+const JUMP_BOOT: [u8; 3] = [0xEB, 0x76, 0x90];
+const EXTENDED_BOOT_SIGNATURE: u32 = 0xAA55_0000;
+const MAX_CLUSTER_COUNT: u32 = 0xFFFF_FFF5;
+/// Byte offsets of VolumeFlags and PercentInUse, which change at runtime
+/// and are excluded from the checksum.
+const VOLUME_FLAGS: usize = 106;
+const PERCENT_IN_USE: usize = 112;
+
+#[derive(Debug, Clone)]
+pub struct ExfatBootSector {
+    pub partition_offset: u64,
+    /// In sectors.
+    pub volume_length: u64,
+    pub fat_offset: u32,
+    pub fat_length: u32,
+    pub cluster_heap_offset: u32,
+    pub cluster_count: u32,
+    pub first_cluster_of_root_directory: u32,
+    pub volume_serial_number: u32,
+    pub file_system_revision: u16,
+    pub volume_flags: u16,
+    pub bytes_per_sector: u32,
+    pub sectors_per_cluster: u32,
+    pub number_of_fats: u8,
+    pub percent_in_use: u8,
+}
+
+impl ExfatBootSector {
+    pub fn cluster_bytes(&self) -> u64 {
+        self.bytes_per_sector as u64 * self.sectors_per_cluster as u64
+    }
+
+    pub fn volume_bytes(&self) -> u64 {
+        self.volume_length * self.bytes_per_sector as u64
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ExfatCheck {
+    pub ok: bool,
+    pub issues: Vec<String>,
+    /// Non-fatal findings, e.g. the volume being marked dirty.
+    pub warnings: Vec<String>,
+    pub boot_sector: Option<ExfatBootSector>,
+    /// Checksum computed over the main boot region.
+    pub checksum: Option<u32>,
+}
+
+/// Checks the boot regions of the exFAT volume starting `offset` bytes into
+/// `device`. `volume_bytes`, when known, bounds the volume length.
+pub fn verify_exfat<D: Read + Seek>(
+    device: &mut D,
+    offset: u64,
+    volume_bytes: Option<u64>,
+) -> Result<ExfatCheck> {
+    let mut issues = Vec::new();
+    let mut warnings = Vec::new();
+
+    let first = read_bytes(device, offset, 512)?;
+    if &first[3..11] != FILE_SYSTEM_NAME {
+        issues.push("boot sector does not name an exFAT file system".to_string());
+        return Ok(ExfatCheck {
+            ok: false,
+            issues,
+            warnings,
+            boot_sector: None,
+            checksum: None,
+        });
+    }
+    let shift = first[108];
+    if !(9..=12).contains(&shift) {
+        issues.push(format!("BytesPerSectorShift {} is outside 9-12", shift));
+        return Ok(ExfatCheck {
+            ok: false,
+            issues,
+            warnings,
+            boot_sector: None,
+            checksum: None,
+        });
+    }
+    let sector_size = 1u64 << shift;
+
+    let main = read_bytes(device, offset, BOOT_REGION_SECTORS * sector_size)?;
+    let backup = read_bytes(
+        device,
+        offset + BOOT_REGION_SECTORS * sector_size,
+        BOOT_REGION_SECTORS * sector_size,
+    )?;
+
+    let boot = parse_boot_sector(&main);
+    check_boot_sector(&main, &boot, volume_bytes, &mut issues, &mut warnings);
+    let checksum = boot_checksum(&main, sector_size as usize);
+    check_region(&main, sector_size as usize, checksum, "main", &mut issues, &mut warnings);
+
+    if &backup[3..11] != FILE_SYSTEM_NAME {
+        issues.push("backup boot sector does not name an exFAT file system".to_string());
+    } else {
+        let backup_checksum = boot_checksum(&backup, sector_size as usize);
+        check_region(
+            &backup,
+            sector_size as usize,
+            backup_checksum,
+            "backup",
+            &mut issues,
+            &mut warnings,
+        );
+        if backup_checksum != checksum {
+            issues.push(format!(
+                "backup boot region differs from the main one (checksum {:08X} vs {:08X})",
+                backup_checksum, checksum
+            ));
+        }
+    }
+
+    Ok(ExfatCheck {
+        ok: issues.is_empty(),
+        issues,
+        warnings,
+        boot_sector: Some(boot),
+        checksum: Some(checksum),
+    })
+}
+
+/// Opens `device_path` read-only and checks the volume at `offset`.
+/// Without `volume_bytes`, a regular file's length (less `offset`) is used.
+pub fn verify_exfat_device(
+    device_path: impl AsRef<Path>,
+    offset: u64,
+    volume_bytes: Option<u64>,
+) -> Result<ExfatCheck> {
+    let path = device_path.as_ref();
+    let mut file = File::open(path).with_context(|| format!("open {}", path.display()))?;
+    let volume_bytes = match volume_bytes {
+        Some(bytes) => Some(bytes),
+        None => file
+            .metadata()
+            .ok()
+            .filter(|meta| meta.is_file())
+            .map(|meta| meta.len().saturating_sub(offset)),
+    };
+    verify_exfat(&mut file, offset, volume_bytes)
+}
+
+/// The boot checksum over the first eleven sectors of a boot region,
+/// skipping VolumeFlags and PercentInUse.
+pub fn boot_checksum(region: &[u8], sector_size: usize) -> u32 {
+    let mut checksum = 0u32;
+    for (index, byte) in region[..sector_size * 11].iter().enumerate() {
+        if index == VOLUME_FLAGS || index == VOLUME_FLAGS + 1 || index == PERCENT_IN_USE {
+            continue;
+        }
+        checksum = checksum.rotate_right(1).wrapping_add(*byte as u32);
+    }
+    checksum
+}
+
+fn parse_boot_sector(sector: &[u8]) -> ExfatBootSector {
+    ExfatBootSector {
+        partition_offset: read_u64(sector, 64),
+        volume_length: read_u64(sector, 72),
+        fat_offset: read_u32(sector, 80),
+        fat_length: read_u32(sector, 84),
+        cluster_heap_offset: read_u32(sector, 88),
+        cluster_count: read_u32(sector, 92),
+        first_cluster_of_root_directory: read_u32(sector, 96),
+        volume_serial_number: read_u32(sector, 100),
+        file_system_revision: read_u16(sector, 104),
+        volume_flags: read_u16(sector, VOLUME_FLAGS),
+        bytes_per_sector: 1 << sector[108],
+        sectors_per_cluster: 1u32.checked_shl(sector[109] as u32).unwrap_or(0),
+        number_of_fats: sector[110],
+        percent_in_use: sector[PERCENT_IN_USE],
+    }
+}
+
+fn check_boot_sector(
+    sector: &[u8],
+    boot: &ExfatBootSector,
+    volume_bytes: Option<u64>,
+    issues: &mut Vec<String>,
+    warnings: &mut Vec<String>,
+) {
+    if sector[0..3] != JUMP_BOOT {
+        issues.push(format!("JumpBoot is {:02X?}, expected EB 76 90", &sector[0..3]));
+    }
+    if sector[11..64].iter().any(|byte| *byte != 0) {
+        issues.push("MustBeZero field (bytes 11-63) is not zero".to_string());
+    }
+    if sector[510..512] != [0x55, 0xAA] {
+        issues.push("boot sector is missing the 55 AA signature".to_string());
+    }
+    let shift = sector[108] as u32;
+    let cluster_shift = sector[109] as u32;
+    if shift + cluster_shift > 25 {
+        issues.push(format!(
+            "cluster size 2^{} bytes exceeds the 32 MiB maximum",
+            shift + cluster_shift
+        ));
+        return;
+    }
+    if boot.file_system_revision >> 8 != 1 {
+        issues.push(format!(
+            "unsupported file system revision {}.{:02}",
+            boot.file_system_revision >> 8,
+            boot.file_system_revision & 0xFF
+        ));
+    }
+    if !(1..=2).contains(&boot.number_of_fats) {
+        issues.push(format!("NumberOfFats is {}, expected 1 or 2", boot.number_of_fats));
+    }
+    if boot.volume_length < (1 << 20) >> shift {
+        issues.push(format!("VolumeLength {} sectors is below 1 MiB", boot.volume_length));
+    }
+    if let Some(bytes) = volume_bytes {
+        if boot.volume_bytes() > bytes {
+            issues.push(format!(
+                "VolumeLength covers {} bytes but the volume holds {}",
+                boot.volume_bytes(),
+                bytes
+            ));
+        }
+    }
+    if boot.fat_offset < 24 {
+        issues.push(format!("FatOffset {} overlaps the boot regions", boot.fat_offset));
+    }
+    let fats_end = boot.fat_offset as u64 + boot.fat_length as u64 * boot.number_of_fats as u64;
+    if fats_end > boot.cluster_heap_offset as u64 {
+        issues.push(format!(
+            "FATs end at sector {} past ClusterHeapOffset {}",
+            fats_end, boot.cluster_heap_offset
+        ));
+    }
+    let fat_bytes = boot.fat_length as u64 * boot.bytes_per_sector as u64;
+    if fat_bytes < (boot.cluster_count as u64 + 2) * 4 {
+        issues.push(format!(
+            "FatLength {} sectors cannot hold {} clusters",
+            boot.fat_length, boot.cluster_count
+        ));
+    }
+    if boot.cluster_count > MAX_CLUSTER_COUNT {
+        issues.push(format!("ClusterCount {} exceeds the maximum", boot.cluster_count));
+    }
+    let heap_end = boot.cluster_heap_offset as u64
+        + boot.cluster_count as u64 * boot.sectors_per_cluster as u64;
+    if heap_end > boot.volume_length {
+        issues.push(format!(
+            "cluster heap ends at sector {} past VolumeLength {}",
+            heap_end, boot.volume_length
+        ));
+    }
+    if boot.first_cluster_of_root_directory < 2
+        || boot.first_cluster_of_root_directory as u64 > boot.cluster_count as u64 + 1
+    {
+        issues.push(format!(
+            "FirstClusterOfRootDirectory {} is outside the cluster heap",
+            boot.first_cluster_of_root_directory
+        ));
+    }
+    if boot.volume_flags & 0x0002 != 0 {
+        warnings.push("volume is marked dirty".to_string());
+    }
+    if boot.volume_flags & 0x0004 != 0 {
+        warnings.push("volume reports media failure".to_string());
+    }
+}
+
+/// Checks the extended boot signatures and that the checksum sector
+/// repeats `checksum`.
+fn check_region(
+    region: &[u8],
+    sector_size: usize,
+    checksum: u32,
+    name: &str,
+    issues: &mut Vec<String>,
+    warnings: &mut Vec<String>,
+) {
+    for sector in 1..9 {
+        let end = (sector + 1) * sector_size;
+        if read_u32(region, end - 4) != EXTENDED_BOOT_SIGNATURE {
+            warnings.push(format!(
+                "{} extended boot sector {} has no AA550000 signature",
+                name, sector
+            ));
+        }
+    }
+    let stored = &region[11 * sector_size..12 * sector_size];
+    let mismatched = stored
+        .chunks_exact(4)
+        .filter(|chunk| u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]) != checksum)
+        .count();
+    if mismatched > 0 {
+        issues.push(format!(
+            "{} boot checksum sector does not match the computed {:08X} ({} of {} entries differ)",
+            name,
+            checksum,
+            mismatched,
+            sector_size / 4
+        ));
+    }
+}
+
+fn read_bytes<D: Read + Seek>(device: &mut D, offset: u64, len: u64) -> Result<Vec<u8>> {
+    let mut buffer = vec![0u8; len as usize];
+    device.seek(SeekFrom::Start(offset))?;
+    device
+        .read_exact(&mut buffer)
+        .map_err(|err| anyhow!("read {} bytes at offset {}: {}", len, offset, err))?;
+    Ok(buffer)
+}
+
+fn read_u16(buffer: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([buffer[offset], buffer[offset + 1]])
+}
+
+fn read_u32(buffer: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(buffer[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(buffer: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(buffer[offset..offset + 8].try_into().unwrap())
+}
@@ -0,0 +1,105 @@
+use anyhow::{anyhow, Result};
+
+/// OEM codepages supported for short names and volume labels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OemCodepage {
+    #[default]
+    Cp437,
+    Cp850,
+}
+
+const CP437_HIGH: &str = "ÇüéâäàåçêëèïîìÄÅÉæÆôöòûùÿÖÜ¢£¥₧ƒáíóúñÑªº¿⌐¬½¼¡«»\
+░▒▓│┤╡╢╖╕╣║╗╝╜╛┐└┴┬├─┼╞╟╚╔╩╦╠═╬╧╨╤╥╙╘╒╓╫╪┘┌█▄▌▐▀\
+αßΓπΣσµτΦΘΩδ∞φε∩≡±≥≤⌠⌡÷≈°∙·√ⁿ²■\u{a0}";
+
+const CP850_HIGH: &str = "ÇüéâäàåçêëèïîìÄÅÉæÆôöòûùÿÖÜø£Ø×ƒáíóúñÑªº¿®¬½¼¡«»\
+░▒▓│┤ÁÂÀ©╣║╗╝¢¥┐└┴┬├─┼ãÃ╚╔╩╦╠═╬¤ðÐÊËÈıÍÎÏ┘┌█▄¦Ì▀\
+ÓßÔÒõÕµþÞÚÛÙýÝ¯´\u{ad}±‗¾¶§÷¸°¨·¹³²■\u{a0}";
+
+impl OemCodepage {
+    pub fn parse(value: &str) -> Result<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "437" | "cp437" => Ok(Self::Cp437),
+            "850" | "cp850" => Ok(Self::Cp850),
+            other => Err(anyhow!("unsupported OEM codepage: {}", other)),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Cp437 => "cp437",
+            Self::Cp850 => "cp850",
+        }
+    }
+
+    fn high_table(&self) -> &'static str {
+        match self {
+            Self::Cp437 => CP437_HIGH,
+            Self::Cp850 => CP850_HIGH,
+        }
+    }
+
+    pub fn encode_char(&self, ch: char) -> Option<u8> {
+        if ch.is_ascii() {
+            return Some(ch as u8);
+        }
+        self.high_table()
+            .chars()
+            .position(|candidate| candidate == ch)
+            .map(|pos| 0x80 + pos as u8)
+    }
+
+    pub fn decode_byte(&self, byte: u8) -> char {
+        if byte < 0x80 {
+            return byte as char;
+        }
+        self.high_table()
+            .chars()
+            .nth((byte - 0x80) as usize)
+            .unwrap_or('?')
+    }
+}
+
+/// Result of mapping a Unicode volume label onto the 11-byte OEM field.
+#[derive(Debug, Clone)]
+pub struct EncodedLabel {
+    pub bytes: [u8; 11],
+    pub warnings: Vec<String>,
+}
+
+const LABEL_FORBIDDEN: &[char] = &[
+    '"', '*', '+', ',', '.', '/', ':', ';', '<', '=', '>', '?', '[', '\\', ']', '|',
+];
+
+/// Encodes a volume label. Characters that are forbidden in labels are an
+/// error; characters missing from the codepage become `_` and truncation to
+/// 11 bytes is reported rather than applied silently.
+pub fn encode_label(label: &str, codepage: OemCodepage) -> Result<EncodedLabel> {
+    let mut bytes = [b' '; 11];
+    let mut warnings = Vec::new();
+    for (idx, ch) in label.chars().flat_map(char::to_uppercase).enumerate() {
+        if ch < ' ' || LABEL_FORBIDDEN.contains(&ch) {
+            return Err(anyhow!("invalid character {:?} in volume label", ch));
+        }
+        if idx == bytes.len() {
+            warnings.push(format!("label truncated to 11 bytes: {}", label));
+            break;
+        }
+        let byte = match codepage.encode_char(ch) {
+            Some(byte) => byte,
+            None => {
+                warnings.push(format!(
+                    "label character {:?} not in {}, replaced with '_'",
+                    ch,
+                    codepage.as_str()
+                ));
+                b'_'
+            }
+        };
+        bytes[idx] = byte;
+    }
+    if bytes[0] == 0xE5 {
+        bytes[0] = 0x05;
+    }
+    Ok(EncodedLabel { bytes, warnings })
+}
@@ -1,9 +1,26 @@
 use anyhow::{anyhow, Context, Result};
-use std::fs::OpenOptions;
-use std::io::{Seek, SeekFrom, Write};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
 use std::path::Path;
 use std::time::{SystemTime, UNIX_EPOCH};
 
+pub mod codepage;
+pub mod names;
+pub mod resize;
+pub mod verify;
+#[cfg(windows)]
+mod volume;
+
+pub use codepage::{encode_label, EncodedLabel, OemCodepage};
+pub use names::{fat_path_warnings, lfn_entries, long_name_issue, short_name};
+pub use resize::{fat32_used_ranges, read_fat32_usage, resize_fat32, Fat32Usage};
+pub use verify::{
+    reference_fat_sectors, verify_fat32, verify_fat32_device, Fat32Boot, Fat32Check, ReferenceLayout,
+    REFERENCE_LAYOUTS,
+};
+#[cfg(windows)]
+pub use volume::{format_fat32_volume, volume_length};
+
 const BYTES_PER_SECTOR: u16 = 512;
 const RESERVED_SECTORS: u16 = 32;
 const NUM_FATS: u8 = 2;
@@ -18,34 +35,100 @@ pub struct Fat32Layout {
     pub sectors_per_cluster: u8,
     pub sectors_per_fat: u32,
     pub root_dir_sector: u32,
+    pub label_warnings: Vec<String>,
 }
 
 pub fn format_fat32(
     device_path: impl AsRef<Path>,
     total_bytes: u64,
     label: Option<&str>,
+) -> Result<Fat32Layout> {
+    format_fat32_with_codepage(device_path, total_bytes, label, OemCodepage::default())
+}
+
+pub fn format_fat32_with_codepage(
+    device_path: impl AsRef<Path>,
+    total_bytes: u64,
+    label: Option<&str>,
+    codepage: OemCodepage,
+) -> Result<Fat32Layout> {
+    let mut device = open_device(device_path.as_ref())?;
+    format_fat32_device(&mut device, total_bytes, label, codepage, None)
+}
+
+/// Formats with a fixed cluster size, for targets whose firmware only boots
+/// from specific ones. `None` picks the Windows default for the size.
+pub fn format_fat32_with_cluster_size(
+    device_path: impl AsRef<Path>,
+    total_bytes: u64,
+    label: Option<&str>,
+    cluster_bytes: Option<u64>,
+) -> Result<Fat32Layout> {
+    let mut device = open_device(device_path.as_ref())?;
+    format_fat32_device(
+        &mut device,
+        total_bytes,
+        label,
+        OemCodepage::default(),
+        cluster_bytes,
+    )
+}
+
+fn open_device(path: &Path) -> Result<File> {
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(path)
+        .with_context(|| format!("open {}", path.display()))
+}
+
+/// Formats an already-open device, e.g. a descriptor obtained through a
+/// privileged helper rather than opened by path.
+pub fn format_fat32_device(
+    device: &mut File,
+    total_bytes: u64,
+    label: Option<&str>,
+    codepage: OemCodepage,
+    cluster_bytes: Option<u64>,
+) -> Result<Fat32Layout> {
+    phoenix_safety::ensure_writable("FAT32 format")?;
+    let layout = write_volume(device, total_bytes, label, codepage, cluster_bytes)?;
+    device.sync_all().ok();
+    check_formatted(device, total_bytes, &layout)?;
+    Ok(layout)
+}
+
+fn write_volume<D: Write + Seek>(
+    device: &mut D,
+    total_bytes: u64,
+    label: Option<&str>,
+    codepage: OemCodepage,
+    cluster_bytes: Option<u64>,
 ) -> Result<Fat32Layout> {
     if total_bytes < (BYTES_PER_SECTOR as u64) * 1000 {
         return Err(anyhow!("device too small for FAT32"));
     }
-    if total_bytes % (BYTES_PER_SECTOR as u64) != 0 {
+    if !total_bytes.is_multiple_of(BYTES_PER_SECTOR as u64) {
         return Err(anyhow!("device size must be multiple of 512 bytes"));
     }
-
-    let total_sectors = (total_bytes / (BYTES_PER_SECTOR as u64)) as u32;
-    let sectors_per_cluster = select_sectors_per_cluster(total_sectors)?;
+    let total_sectors = u32::try_from(total_bytes / BYTES_PER_SECTOR as u64).map_err(|_| {
+        anyhow!(
+            "FAT32 with 512-byte sectors ends at 2 TiB; {} bytes is too large",
+            total_bytes
+        )
+    })?;
+    let sectors_per_cluster = match cluster_bytes {
+        Some(cluster_bytes) => check_cluster_size(total_bytes, cluster_bytes)?,
+        None => select_sectors_per_cluster(total_sectors)?,
+    };
     let sectors_per_fat = compute_fat_size(total_sectors, sectors_per_cluster)?;
     let data_start = RESERVED_SECTORS as u32 + (NUM_FATS as u32 * sectors_per_fat);
     let root_dir_sector = data_start + ((ROOT_CLUSTER - 2) * sectors_per_cluster as u32);
 
-    let mut device = OpenOptions::new()
-        .read(true)
-        .write(true)
-        .open(device_path.as_ref())
-        .with_context(|| format!("open {}", device_path.as_ref().display()))?;
+    let encoded_label = encode_label(label.unwrap_or("PHOENIX"), codepage)?;
+    let volume_label = encoded_label.bytes;
 
     let volume_id = volume_id();
-    let volume_label = label_bytes(label.unwrap_or("PHOENIX"));
 
     let boot_sector = build_boot_sector(
         total_sectors,
@@ -54,67 +137,167 @@ pub fn format_fat32(
         volume_id,
         &volume_label,
     );
-    write_sector(&mut device, 0, &boot_sector)?;
-    write_sector(&mut device, BACKUP_BOOT_SECTOR as u32, &boot_sector)?;
+    write_sector(device, 0, &boot_sector)?;
+    write_sector(device, BACKUP_BOOT_SECTOR as u32, &boot_sector)?;
 
     let fsinfo = build_fsinfo();
-    write_sector(&mut device, FSINFO_SECTOR as u32, &fsinfo)?;
-    write_sector(&mut device, BACKUP_BOOT_SECTOR as u32 + 1, &fsinfo)?;
+    write_sector(device, FSINFO_SECTOR as u32, &fsinfo)?;
+    write_sector(device, BACKUP_BOOT_SECTOR as u32 + 1, &fsinfo)?;
 
     let fat_start = RESERVED_SECTORS as u32;
-    write_fat(&mut device, fat_start, sectors_per_fat, true)?;
+    write_fat(device, fat_start, sectors_per_fat, true)?;
     write_fat(
-        &mut device,
+        device,
         fat_start + sectors_per_fat,
         sectors_per_fat,
         false,
     )?;
 
-    zero_cluster(&mut device, root_dir_sector, sectors_per_cluster)?;
+    zero_cluster(device, root_dir_sector, sectors_per_cluster)?;
     if !volume_label.iter().all(|b| *b == b' ') {
-        write_volume_label(&mut device, root_dir_sector, &volume_label)?;
+        write_volume_label(device, root_dir_sector, &volume_label)?;
     }
 
-    device.sync_all().ok();
-
     Ok(Fat32Layout {
         total_sectors,
         sectors_per_cluster,
         sectors_per_fat,
         root_dir_sector,
+        label_warnings: encoded_label.warnings,
     })
 }
 
+/// Reads a fresh format back through `verify_fat32` and holds it to the
+/// reference layout, so a bad size fails the format instead of leaving an
+/// unbootable volume.
+fn check_formatted<D: Read + Seek>(device: &mut D, total_bytes: u64, layout: &Fat32Layout) -> Result<()> {
+    let check = verify_fat32(device, 0, Some(total_bytes)).context("read back FAT32 format")?;
+    if !check.ok {
+        return Err(anyhow!("FAT32 format failed its read-back: {}", check.issues.join("; ")));
+    }
+    let computed = reference_fat_sectors(
+        layout.total_sectors,
+        RESERVED_SECTORS as u32,
+        NUM_FATS as u32,
+        layout.sectors_per_cluster as u32,
+        BYTES_PER_SECTOR as u32,
+    );
+    let expected = REFERENCE_LAYOUTS
+        .iter()
+        .find(|reference| {
+            reference.total_bytes == total_bytes
+                && reference.sectors_per_cluster == layout.sectors_per_cluster
+        })
+        .map_or(computed, |reference| reference.sectors_per_fat);
+    if layout.sectors_per_fat != computed || layout.sectors_per_fat != expected {
+        return Err(anyhow!(
+            "FAT32 format of {} bytes has {} sectors per FAT; the reference layout has {}",
+            total_bytes,
+            layout.sectors_per_fat,
+            expected
+        ));
+    }
+    Ok(())
+}
+
+/// Checks that `cluster_bytes` is legal for a FAT32 volume of
+/// `total_bytes`: a power of two from 512 B to 64 KiB leaving between
+/// 65,525 and 268,435,445 clusters. Returns the sectors per cluster.
+pub fn check_cluster_size(total_bytes: u64, cluster_bytes: u64) -> Result<u8> {
+    let total_sectors = (total_bytes / BYTES_PER_SECTOR as u64).min(u32::MAX as u64) as u32;
+    let legal = legal_sectors_per_cluster(total_sectors);
+    if let Some(spc) = legal
+        .iter()
+        .copied()
+        .find(|spc| *spc as u64 * BYTES_PER_SECTOR as u64 == cluster_bytes)
+    {
+        return Ok(spc);
+    }
+    let sizes: Vec<String> = legal
+        .iter()
+        .map(|spc| (*spc as u64 * BYTES_PER_SECTOR as u64).to_string())
+        .collect();
+    if sizes.is_empty() {
+        return Err(anyhow!(
+            "no FAT32 cluster size fits a {} byte volume",
+            total_bytes
+        ));
+    }
+    Err(anyhow!(
+        "{} byte clusters are not valid for a {} byte FAT32 volume; use one of {}",
+        cluster_bytes,
+        total_bytes,
+        sizes.join(", ")
+    ))
+}
+
+/// The cluster size Windows picks for a FAT32 volume of `total_bytes`.
+pub fn default_cluster_size(total_bytes: u64) -> u64 {
+    const MIB: u64 = 1024 * 1024;
+    const GIB: u64 = 1024 * MIB;
+    match total_bytes {
+        size if size < 64 * MIB => 512,
+        size if size < 128 * MIB => 1024,
+        size if size < 256 * MIB => 2048,
+        size if size < 8 * GIB => 4096,
+        size if size < 16 * GIB => 8192,
+        size if size < 32 * GIB => 16384,
+        _ => 32768,
+    }
+}
+
+/// The Windows default when it is legal for this volume, else the
+/// smallest legal size.
 fn select_sectors_per_cluster(total_sectors: u32) -> Result<u8> {
+    let legal = legal_sectors_per_cluster(total_sectors);
+    let preferred = default_cluster_size(total_sectors as u64 * BYTES_PER_SECTOR as u64)
+        / BYTES_PER_SECTOR as u64;
+    legal
+        .iter()
+        .copied()
+        .find(|spc| *spc as u64 == preferred)
+        .or_else(|| legal.first().copied())
+        .ok_or_else(|| anyhow!("unable to select sectors per cluster for FAT32"))
+}
+
+fn legal_sectors_per_cluster(total_sectors: u32) -> Vec<u8> {
     let candidates = [1u8, 2, 4, 8, 16, 32, 64, 128];
-    for spc in candidates {
-        let fat = compute_fat_size(total_sectors, spc)?;
-        let data_sectors = total_sectors
-            .saturating_sub(RESERVED_SECTORS as u32 + NUM_FATS as u32 * fat);
-        let clusters = data_sectors / spc as u32;
-        if clusters >= 65525 && clusters <= 0x0FFFFFF5 {
-            return Ok(spc);
-        }
-    }
-    Err(anyhow!("unable to select sectors per cluster for FAT32"))
+    candidates
+        .into_iter()
+        .filter(|spc| {
+            let Ok(fat) = compute_fat_size(total_sectors, *spc) else {
+                return false;
+            };
+            let data_sectors = total_sectors
+                .saturating_sub(RESERVED_SECTORS as u32 + NUM_FATS as u32 * fat);
+            let clusters = data_sectors / *spc as u32;
+            (65525..=0x0FFFFFF5).contains(&clusters)
+        })
+        .collect()
 }
 
+/// The smallest FAT with an entry for every cluster it leaves. A shorter
+/// FAT leaves more clusters, so chasing a fixed point can flip between two
+/// lengths forever; the smallest length that is large enough is searched
+/// for instead.
 fn compute_fat_size(total_sectors: u32, spc: u8) -> Result<u32> {
-    let mut fat_size = 1u32;
-    loop {
-        let data_sectors = total_sectors
-            .saturating_sub(RESERVED_SECTORS as u32 + NUM_FATS as u32 * fat_size);
-        let clusters = data_sectors / spc as u32;
-        if clusters == 0 {
-            return Err(anyhow!("invalid FAT32 size"));
+    let needed = |fat_size: u32| {
+        let data_sectors = (total_sectors as u64)
+            .saturating_sub(RESERVED_SECTORS as u64 + NUM_FATS as u64 * fat_size as u64);
+        let clusters = data_sectors / spc as u64;
+        // In u64: 512-byte clusters on volumes past 512 GiB overflow u32.
+        (clusters > 0).then(|| ((clusters + 2) * 4).div_ceil(BYTES_PER_SECTOR as u64) as u32)
+    };
+    let mut low = 1u32;
+    let mut high = needed(1).ok_or_else(|| anyhow!("invalid FAT32 size"))?;
+    while low < high {
+        let middle = low + (high - low) / 2;
+        match needed(middle) {
+            Some(entries) if entries > middle => low = middle + 1,
+            _ => high = middle,
         }
-        let needed = ((clusters + 2) * 4 + (BYTES_PER_SECTOR as u32 - 1))
-            / BYTES_PER_SECTOR as u32;
-        if needed == fat_size {
-            return Ok(fat_size);
-        }
-        fat_size = needed;
     }
+    needed(low).map(|_| low).ok_or_else(|| anyhow!("invalid FAT32 size"))
 }
 
 fn build_boot_sector(
@@ -151,11 +334,11 @@ fn build_boot_sector(
     write_u32(&mut sector, 0x2C, ROOT_CLUSTER);
     write_u16(&mut sector, 0x30, FSINFO_SECTOR);
     write_u16(&mut sector, 0x32, BACKUP_BOOT_SECTOR);
-    sector[0x36] = 0x80;
-    sector[0x38] = 0x29;
-    write_u32(&mut sector, 0x39, volume_id);
-    sector[0x3D..0x48].copy_from_slice(volume_label);
-    sector[0x47..0x4F].copy_from_slice(b"FAT32   ");
+    sector[0x40] = 0x80;
+    sector[0x42] = 0x29;
+    write_u32(&mut sector, 0x43, volume_id);
+    sector[0x47..0x52].copy_from_slice(volume_label);
+    sector[0x52..0x5A].copy_from_slice(b"FAT32   ");
     sector[510] = 0x55;
     sector[511] = 0xAA;
     sector
@@ -172,11 +355,14 @@ fn build_fsinfo() -> [u8; 512] {
     sector
 }
 
-fn write_fat(
-    device: &mut std::fs::File,
+/// FAT sectors zeroed per write.
+const ZERO_CHUNK_SECTORS: u32 = 128;
+
+fn write_fat<D: Write + Seek>(
+    device: &mut D,
     start_sector: u32,
     sectors_per_fat: u32,
-    primary: bool,
+    _primary: bool,
 ) -> Result<()> {
     let mut first_sector = vec![0u8; BYTES_PER_SECTOR as usize];
     write_u32_slice(&mut first_sector, 0, 0x0FFFFFF8);
@@ -184,19 +370,22 @@ fn write_fat(
     write_u32_slice(&mut first_sector, 2, 0x0FFFFFFF);
     write_sector(device, start_sector, &first_sector)?;
 
-    let zero_sector = vec![0u8; BYTES_PER_SECTOR as usize];
-    for sector in 1..sectors_per_fat {
-        write_sector(device, start_sector + sector, &zero_sector)?;
+    let zero_chunk = vec![0u8; ZERO_CHUNK_SECTORS as usize * BYTES_PER_SECTOR as usize];
+    let mut sector = 1;
+    while sector < sectors_per_fat {
+        let count = ZERO_CHUNK_SECTORS.min(sectors_per_fat - sector);
+        write_sector(
+            device,
+            start_sector + sector,
+            &zero_chunk[..count as usize * BYTES_PER_SECTOR as usize],
+        )?;
+        sector += count;
     }
 
-    if primary {
-        Ok(())
-    } else {
-        Ok(())
-    }
+    Ok(())
 }
 
-fn zero_cluster(device: &mut std::fs::File, start_sector: u32, spc: u8) -> Result<()> {
+fn zero_cluster<D: Write + Seek>(device: &mut D, start_sector: u32, spc: u8) -> Result<()> {
     let zero_sector = vec![0u8; BYTES_PER_SECTOR as usize];
     for offset in 0..spc as u32 {
         write_sector(device, start_sector + offset, &zero_sector)?;
@@ -204,19 +393,20 @@ fn zero_cluster(device: &mut std::fs::File, start_sector: u32, spc: u8) -> Resul
     Ok(())
 }
 
-fn write_volume_label(
-    device: &mut std::fs::File,
+fn write_volume_label<D: Write + Seek>(
+    device: &mut D,
     root_sector: u32,
     label: &[u8; 11],
 ) -> Result<()> {
-    let mut entry = [0u8; 32];
-    entry[0..11].copy_from_slice(label);
-    entry[11] = 0x08;
-    write_sector(device, root_sector, &entry)?;
+    // A whole sector, since raw Windows volumes reject partial writes.
+    let mut sector = [0u8; BYTES_PER_SECTOR as usize];
+    sector[0..11].copy_from_slice(label);
+    sector[11] = 0x08;
+    write_sector(device, root_sector, &sector)?;
     Ok(())
 }
 
-fn write_sector(device: &mut std::fs::File, sector: u32, data: &[u8]) -> Result<()> {
+fn write_sector<D: Write + Seek>(device: &mut D, sector: u32, data: &[u8]) -> Result<()> {
     device.seek(SeekFrom::Start(sector as u64 * BYTES_PER_SECTOR as u64))?;
     device.write_all(data)?;
     Ok(())
@@ -235,16 +425,6 @@ fn write_u32_slice(buffer: &mut [u8], index: usize, value: u32) {
     buffer[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
 }
 
-fn label_bytes(label: &str) -> [u8; 11] {
-    let mut out = [b' '; 11];
-    let upper = label.to_ascii_uppercase();
-    let bytes = upper.as_bytes();
-    for (idx, byte) in bytes.iter().take(11).enumerate() {
-        out[idx] = *byte;
-    }
-    out
-}
-
 fn volume_id() -> u32 {
     SystemTime::now()
         .duration_since(UNIX_EPOCH)
@@ -0,0 +1,249 @@
+use crate::codepage::OemCodepage;
+use std::collections::HashSet;
+use std::path::{Component, Path};
+
+const LFN_FORBIDDEN: &[char] = &['"', '*', '/', ':', '<', '>', '?', '\\', '|'];
+const SHORT_EXTRA: &[char] = &[
+    '!', '#', '$', '%', '&', '\'', '(', ')', '-', '@', '^', '_', '`', '{', '}', '~',
+];
+const LFN_MAX_UNITS: usize = 255;
+const LFN_CHARS_PER_ENTRY: usize = 13;
+
+/// Returns why `name` cannot be stored as a FAT long file name, if it can't.
+pub fn long_name_issue(name: &str) -> Option<String> {
+    if name.is_empty() {
+        return Some("empty name".to_string());
+    }
+    if let Some(ch) = name.chars().find(|ch| *ch < ' ' || LFN_FORBIDDEN.contains(ch)) {
+        return Some(format!("invalid character {:?}", ch));
+    }
+    if name.ends_with('.') || name.ends_with(' ') {
+        return Some("trailing dot or space is dropped on FAT".to_string());
+    }
+    if name.encode_utf16().count() > LFN_MAX_UNITS {
+        return Some(format!("longer than {} UTF-16 units", LFN_MAX_UNITS));
+    }
+    None
+}
+
+fn short_char(ch: char, codepage: OemCodepage) -> Option<u8> {
+    if ch.is_ascii_alphanumeric() || SHORT_EXTRA.contains(&ch) {
+        return Some(ch.to_ascii_uppercase() as u8);
+    }
+    if ch.is_ascii() {
+        return None;
+    }
+    codepage.encode_char(ch)
+}
+
+fn short_part(value: &str, max: usize, codepage: OemCodepage, lossy: &mut bool) -> Vec<u8> {
+    let mut out = Vec::new();
+    for ch in value.chars().flat_map(char::to_uppercase) {
+        if ch == ' ' || ch == '.' {
+            *lossy = true;
+            continue;
+        }
+        if out.len() == max {
+            *lossy = true;
+            break;
+        }
+        match short_char(ch, codepage) {
+            Some(byte) => out.push(byte),
+            None => {
+                *lossy = true;
+                out.push(b'_');
+            }
+        }
+    }
+    out
+}
+
+/// Generates an 8.3 directory name for `long_name`, adding a `~N` tail when
+/// the mapping lost information or collides with `existing`. The second
+/// value is true when an LFN entry set is needed to keep the original name.
+pub fn short_name(
+    long_name: &str,
+    existing: &HashSet<[u8; 11]>,
+    codepage: OemCodepage,
+) -> ([u8; 11], bool) {
+    let trimmed = long_name.trim_start_matches(['.', ' ']);
+    let (base, ext) = match trimmed.rfind('.') {
+        Some(pos) if pos > 0 => (&trimmed[..pos], &trimmed[pos + 1..]),
+        _ => (trimmed, ""),
+    };
+    let mut lossy = trimmed.len() != long_name.len();
+    let base_bytes = short_part(base, 8, codepage, &mut lossy);
+    let ext_bytes = short_part(ext, 3, codepage, &mut lossy);
+
+    let mut name = [b' '; 11];
+    name[8..8 + ext_bytes.len()].copy_from_slice(&ext_bytes);
+    let base_bytes = if base_bytes.is_empty() {
+        lossy = true;
+        vec![b'_']
+    } else {
+        base_bytes
+    };
+    name[..base_bytes.len()].copy_from_slice(&base_bytes);
+    escape_lead_byte(&mut name);
+
+    let needs_lfn = lossy || decode_short(&name, codepage) != long_name;
+    if !lossy && !existing.contains(&name) {
+        return (name, needs_lfn);
+    }
+
+    for tail in 1u32..=999_999 {
+        let suffix = format!("~{}", tail);
+        let keep = (8 - suffix.len()).min(base_bytes.len());
+        let mut candidate = [b' '; 11];
+        candidate[..keep].copy_from_slice(&base_bytes[..keep]);
+        candidate[keep..keep + suffix.len()].copy_from_slice(suffix.as_bytes());
+        candidate[8..].copy_from_slice(&name[8..]);
+        escape_lead_byte(&mut candidate);
+        if !existing.contains(&candidate) {
+            return (candidate, true);
+        }
+    }
+    (name, true)
+}
+
+/// A leading 0xE5 marks a deleted entry, so it is stored as 0x05.
+fn escape_lead_byte(name: &mut [u8; 11]) {
+    if name[0] == 0xE5 {
+        name[0] = 0x05;
+    }
+}
+
+fn decode_short(name: &[u8; 11], codepage: OemCodepage) -> String {
+    let mut name = *name;
+    if name[0] == 0x05 {
+        name[0] = 0xE5;
+    }
+    let decode = |bytes: &[u8]| -> String {
+        bytes
+            .iter()
+            .map(|byte| codepage.decode_byte(*byte))
+            .collect::<String>()
+            .trim_end()
+            .to_string()
+    };
+    let base = decode(&name[..8]);
+    let ext = decode(&name[8..]);
+    if ext.is_empty() {
+        base
+    } else {
+        format!("{}.{}", base, ext)
+    }
+}
+
+pub fn short_name_checksum(name: &[u8; 11]) -> u8 {
+    name.iter()
+        .fold(0u8, |sum, byte| sum.rotate_right(1).wrapping_add(*byte))
+}
+
+/// Builds the LFN directory entries for `long_name`, in on-disk order
+/// (highest ordinal first), to be followed by the 8.3 entry.
+pub fn lfn_entries(long_name: &str, short: &[u8; 11]) -> Vec<[u8; 32]> {
+    let mut units: Vec<u16> = long_name.encode_utf16().collect();
+    if !units.len().is_multiple_of(LFN_CHARS_PER_ENTRY) {
+        units.push(0);
+        while !units.len().is_multiple_of(LFN_CHARS_PER_ENTRY) {
+            units.push(0xFFFF);
+        }
+    }
+    let checksum = short_name_checksum(short);
+    let count = units.len() / LFN_CHARS_PER_ENTRY;
+    let mut entries = Vec::with_capacity(count);
+    for (idx, chunk) in units.chunks(LFN_CHARS_PER_ENTRY).enumerate() {
+        let mut entry = [0u8; 32];
+        let mut ordinal = (idx + 1) as u8;
+        if idx + 1 == count {
+            ordinal |= 0x40;
+        }
+        entry[0] = ordinal;
+        entry[11] = 0x0F;
+        entry[13] = checksum;
+        let offsets = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+        for (unit, offset) in chunk.iter().zip(offsets) {
+            entry[offset..offset + 2].copy_from_slice(&unit.to_le_bytes());
+        }
+        entries.push(entry);
+    }
+    entries.reverse();
+    entries
+}
+
+/// Checks staged relative paths for names that cannot round-trip through a
+/// FAT directory: invalid or non-UTF-8 components, and paths that collide
+/// once FAT's case-insensitive matching is applied.
+pub fn fat_path_warnings<'a>(paths: impl IntoIterator<Item = &'a Path>) -> Vec<String> {
+    let mut warnings = Vec::new();
+    let mut seen = HashSet::new();
+    for path in paths {
+        let display = path.to_string_lossy().replace('\\', "/");
+        for component in path.components() {
+            let Component::Normal(part) = component else {
+                continue;
+            };
+            match part.to_str() {
+                Some(name) => {
+                    if let Some(issue) = long_name_issue(name) {
+                        warnings.push(format!("{}: {}", display, issue));
+                    }
+                }
+                None => warnings.push(format!("{}: name is not valid Unicode", display)),
+            }
+        }
+        if !seen.insert(display.to_lowercase()) {
+            warnings.push(format!("{}: collides with another path ignoring case", display));
+        }
+    }
+    warnings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn short(value: &str) -> String {
+        let (name, _) = short_name(value, &HashSet::new(), OemCodepage::Cp437);
+        String::from_utf8_lossy(&name).to_string()
+    }
+
+    #[test]
+    fn keeps_plain_8_3_names() {
+        let (name, needs_lfn) = short_name("SETUP.EXE", &HashSet::new(), OemCodepage::Cp437);
+        assert_eq!(&name, b"SETUP   EXE");
+        assert!(!needs_lfn);
+    }
+
+    #[test]
+    fn adds_numeric_tail_for_long_or_lossy_names() {
+        assert_eq!(short("autounattend.xml"), "AUTOUN~1XML");
+        assert_eq!(short("a+b.txt"), "A_B~1   TXT");
+    }
+
+    #[test]
+    fn escapes_a_leading_e5_byte() {
+        let (name, needs_lfn) = short_name("Õ.TXT", &HashSet::new(), OemCodepage::Cp850);
+        assert_eq!(&name, b"\x05       TXT");
+        assert!(!needs_lfn);
+        let (name, _) = short_name("Õversized.txt", &HashSet::new(), OemCodepage::Cp850);
+        assert_eq!(&name, b"\x05VERSI~1TXT");
+    }
+
+    #[test]
+    fn lfn_entries_are_reverse_ordered() {
+        let (name, _) = short_name("Program Files Setup.log", &HashSet::new(), OemCodepage::Cp437);
+        let entries = lfn_entries("Program Files Setup.log", &name);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0][0], 0x42);
+        assert_eq!(entries[1][0], 0x01);
+        assert_eq!(entries[1][13], short_name_checksum(&name));
+    }
+
+    #[test]
+    fn flags_case_collisions_and_bad_names() {
+        let paths = [Path::new("EFI/Boot"), Path::new("efi/boot"), Path::new("a?b")];
+        assert_eq!(fat_path_warnings(paths).len(), 2);
+    }
+}
@@ -0,0 +1,279 @@
+//! Shrinking and growing a FAT32 volume in place. The FATs and the data
+//! region stay where they are; only the sector count in the boot sectors
+//! changes, so a shrink needs every used cluster below the new end and a
+//! grow stops where the FAT runs out of entries. The same FAT scan tells
+//! image trimming which clusters hold data.
+
+use anyhow::{anyhow, Context, Result};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+
+const MIN_CLUSTERS: u64 = 65525;
+const MAX_CLUSTERS: u64 = 0x0FFF_FFF5;
+const FAT_ENTRY_MASK: u32 = 0x0FFF_FFFF;
+/// FAT entries read at a time while looking for used clusters.
+const SCAN_ENTRIES: u64 = 256 * 1024;
+
+/// The layout of a FAT32 volume and how much of it is in use.
+#[derive(Debug, Clone)]
+pub struct Fat32Usage {
+    pub bytes_per_sector: u32,
+    pub sectors_per_cluster: u32,
+    pub total_sectors: u32,
+    /// Sectors before the first FAT.
+    pub reserved_sectors: u32,
+    /// First sector of cluster 2.
+    pub data_start_sector: u32,
+    pub cluster_count: u64,
+    pub used_clusters: u64,
+    /// Highest cluster with a FAT entry, or 1 when none is used.
+    pub highest_used_cluster: u64,
+    /// Clusters the FAT has entries for.
+    pub fat_clusters: u64,
+}
+
+impl Fat32Usage {
+    pub fn total_bytes(&self) -> u64 {
+        self.total_sectors as u64 * self.bytes_per_sector as u64
+    }
+
+    pub fn cluster_bytes(&self) -> u64 {
+        self.sectors_per_cluster as u64 * self.bytes_per_sector as u64
+    }
+
+    /// Smallest volume that keeps every used cluster and stays FAT32.
+    pub fn min_bytes(&self) -> u64 {
+        let clusters = self.highest_used_cluster.saturating_sub(1).max(MIN_CLUSTERS);
+        self.data_start_sector as u64 * self.bytes_per_sector as u64 + clusters * self.cluster_bytes()
+    }
+
+    /// Largest volume the FAT can address.
+    pub fn max_bytes(&self) -> u64 {
+        let clusters = self.fat_clusters.min(MAX_CLUSTERS);
+        self.data_start_sector as u64 * self.bytes_per_sector as u64 + clusters * self.cluster_bytes()
+    }
+}
+
+/// Reads the boot sector and FAT of the volume at the start of `device`.
+pub fn read_fat32_usage<D: Read + Seek>(device: &mut D) -> Result<Fat32Usage> {
+    let mut boot = [0u8; 512];
+    device.seek(SeekFrom::Start(0))?;
+    device.read_exact(&mut boot).context("read boot sector")?;
+    if &boot[0x52..0x5A] != b"FAT32   " || boot[510] != 0x55 || boot[511] != 0xAA {
+        return Err(anyhow!("not a FAT32 volume"));
+    }
+    let bytes_per_sector = read_u16(&boot, 0x0B) as u32;
+    let sectors_per_cluster = boot[0x0D] as u32;
+    let reserved = read_u16(&boot, 0x0E) as u32;
+    let fats = boot[0x10] as u32;
+    let total_sectors = read_u32(&boot, 0x20);
+    let sectors_per_fat = read_u32(&boot, 0x24);
+    if !(512..=4096).contains(&bytes_per_sector)
+        || !bytes_per_sector.is_power_of_two()
+        || sectors_per_cluster == 0
+        || !sectors_per_cluster.is_power_of_two()
+        || fats == 0
+        || sectors_per_fat == 0
+    {
+        return Err(anyhow!("FAT32 boot sector has an invalid layout"));
+    }
+    let data_start_sector = reserved + fats * sectors_per_fat;
+    if total_sectors <= data_start_sector {
+        return Err(anyhow!("FAT32 volume has no data region"));
+    }
+    let cluster_count = ((total_sectors - data_start_sector) / sectors_per_cluster) as u64;
+    let fat_clusters = (sectors_per_fat as u64 * bytes_per_sector as u64 / 4).saturating_sub(2);
+
+    let mut usage = Fat32Usage {
+        bytes_per_sector,
+        sectors_per_cluster,
+        total_sectors,
+        reserved_sectors: reserved,
+        data_start_sector,
+        cluster_count,
+        used_clusters: 0,
+        highest_used_cluster: 1,
+        fat_clusters,
+    };
+    let (mut used_clusters, mut highest_used_cluster) = (0, 1);
+    for_each_used_cluster(device, &usage, |cluster| {
+        used_clusters += 1;
+        highest_used_cluster = cluster;
+    })?;
+    usage.used_clusters = used_clusters;
+    usage.highest_used_cluster = highest_used_cluster;
+    Ok(usage)
+}
+
+/// Byte ranges of the volume at the start of `device` that hold anything:
+/// the reserved sectors and FATs, then every cluster with a FAT entry.
+/// Adjacent clusters are merged into one range.
+pub fn fat32_used_ranges<D: Read + Seek>(device: &mut D) -> Result<Vec<Range<u64>>> {
+    let usage = read_fat32_usage(device)?;
+    let data_start = usage.data_start_sector as u64 * usage.bytes_per_sector as u64;
+    let cluster_bytes = usage.cluster_bytes();
+    let mut ranges = Vec::new();
+    ranges.push(0..data_start);
+    for_each_used_cluster(device, &usage, |cluster| {
+        let start = data_start + (cluster - 2) * cluster_bytes;
+        match ranges.last_mut() {
+            Some(last) if last.end == start => last.end += cluster_bytes,
+            _ => ranges.push(start..start + cluster_bytes),
+        }
+    })?;
+    Ok(ranges)
+}
+
+/// Calls `visit` with each cluster whose FAT entry is not free, in order.
+fn for_each_used_cluster<D: Read + Seek>(
+    device: &mut D,
+    usage: &Fat32Usage,
+    mut visit: impl FnMut(u64),
+) -> Result<()> {
+    let fat_offset = usage.reserved_sectors as u64 * usage.bytes_per_sector as u64;
+    let mut cluster = 2u64;
+    let end = usage.cluster_count.min(usage.fat_clusters) + 2;
+    let mut buffer = Vec::new();
+    while cluster < end {
+        let count = SCAN_ENTRIES.min(end - cluster);
+        buffer.resize(count as usize * 4, 0);
+        device.seek(SeekFrom::Start(fat_offset + cluster * 4))?;
+        device.read_exact(&mut buffer).context("read FAT")?;
+        for (index, entry) in buffer.chunks_exact(4).enumerate() {
+            if read_u32(entry, 0) & FAT_ENTRY_MASK != 0 {
+                visit(cluster + index as u64);
+            }
+        }
+        cluster += count;
+    }
+    Ok(())
+}
+
+/// Sets the volume at the start of `device` to `total_bytes`, rounded down
+/// to a whole cluster, and returns the new layout. Shrinking below
+/// `min_bytes` or growing past `max_bytes` fails without writing anything.
+pub fn resize_fat32<D: Read + Write + Seek>(device: &mut D, total_bytes: u64) -> Result<Fat32Usage> {
+    phoenix_safety::ensure_writable("FAT32 resize")?;
+    let usage = read_fat32_usage(device)?;
+    let sector = usage.bytes_per_sector as u64;
+    let clusters = (total_bytes / sector).saturating_sub(usage.data_start_sector as u64)
+        / usage.sectors_per_cluster as u64;
+    let new_bytes = (usage.data_start_sector as u64 + clusters * usage.sectors_per_cluster as u64) * sector;
+    if new_bytes < usage.min_bytes() {
+        return Err(anyhow!(
+            "FAT32 volume cannot shrink below {} bytes; data ends at cluster {}",
+            usage.min_bytes(),
+            usage.highest_used_cluster
+        ));
+    }
+    if new_bytes > usage.max_bytes() {
+        return Err(anyhow!(
+            "FAT32 volume cannot grow past {} bytes; its FAT has no more entries",
+            usage.max_bytes()
+        ));
+    }
+    let new_sectors = u32::try_from(new_bytes / sector)
+        .map_err(|_| anyhow!("FAT32 volume of {} bytes is too large", new_bytes))?;
+
+    let mut boot = [0u8; 512];
+    device.seek(SeekFrom::Start(0))?;
+    device.read_exact(&mut boot)?;
+    let reserved = read_u16(&boot, 0x0E) as u64;
+    let sectors_per_fat = read_u32(&boot, 0x24) as u64;
+    let fsinfo_sector = read_u16(&boot, 0x30) as u64;
+    let backup_boot_sector = read_u16(&boot, 0x32) as u64;
+
+    // Entries past the old end are not part of the volume and may hold
+    // anything; they become free clusters.
+    if clusters > usage.cluster_count {
+        for fat in 0..boot[0x10] as u64 {
+            let fat_offset = (reserved + fat * sectors_per_fat) * sector;
+            let mut cluster = usage.cluster_count + 2;
+            while cluster < clusters + 2 {
+                let count = SCAN_ENTRIES.min(clusters + 2 - cluster);
+                device.seek(SeekFrom::Start(fat_offset + cluster * 4))?;
+                device.write_all(&vec![0u8; count as usize * 4])?;
+                cluster += count;
+            }
+        }
+    }
+
+    let mut boot_sectors = vec![0];
+    if backup_boot_sector != 0 && backup_boot_sector != 0xFFFF {
+        boot_sectors.push(backup_boot_sector);
+    }
+    for lba in boot_sectors {
+        let mut boot = vec![0u8; sector as usize];
+        device.seek(SeekFrom::Start(lba * sector))?;
+        device.read_exact(&mut boot)?;
+        boot[0x20..0x24].copy_from_slice(&new_sectors.to_le_bytes());
+        device.seek(SeekFrom::Start(lba * sector))?;
+        device.write_all(&boot)?;
+        if fsinfo_sector != 0 && fsinfo_sector != 0xFFFF {
+            write_fsinfo(device, (lba + fsinfo_sector) * sector, clusters - usage.used_clusters)?;
+        }
+    }
+    device.flush()?;
+    read_fat32_usage(device)
+}
+
+/// Sets the free count and clears the next-free hint, which may point past
+/// the new end.
+fn write_fsinfo<D: Read + Write + Seek>(device: &mut D, offset: u64, free_clusters: u64) -> Result<()> {
+    let mut fsinfo = [0u8; 512];
+    device.seek(SeekFrom::Start(offset))?;
+    device.read_exact(&mut fsinfo)?;
+    if fsinfo[0..4] != [0x52, 0x52, 0x61, 0x41] {
+        return Ok(());
+    }
+    fsinfo[0x1E8..0x1EC].copy_from_slice(&(free_clusters as u32).to_le_bytes());
+    fsinfo[0x1EC..0x1F0].copy_from_slice(&0xFFFF_FFFFu32.to_le_bytes());
+    device.seek(SeekFrom::Start(offset))?;
+    device.write_all(&fsinfo)?;
+    Ok(())
+}
+
+fn read_u16(buffer: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([buffer[offset], buffer[offset + 1]])
+}
+
+fn read_u32(buffer: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([buffer[offset], buffer[offset + 1], buffer[offset + 2], buffer[offset + 3]])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{format_fat32_device, OemCodepage};
+
+    const MIB: u64 = 1024 * 1024;
+
+    #[test]
+    fn shrinks_and_grows_back() {
+        let path = std::env::temp_dir().join(format!("phoenix-fat32-resize-{}.img", std::process::id()));
+        let mut file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)
+            .unwrap();
+        file.set_len(600 * MIB).unwrap();
+        format_fat32_device(&mut file, 600 * MIB, Some("TEST"), OemCodepage::Cp437, None).unwrap();
+        let formatted = read_fat32_usage(&mut file).unwrap();
+        assert_eq!(formatted.total_bytes(), 600 * MIB);
+        assert_eq!(formatted.used_clusters, 1);
+        assert_eq!(formatted.highest_used_cluster, 2);
+
+        let shrunk = resize_fat32(&mut file, 300 * MIB).unwrap();
+        assert!(300 * MIB - shrunk.total_bytes() < shrunk.cluster_bytes());
+        assert!(shrunk.cluster_count < formatted.cluster_count);
+        assert!(resize_fat32(&mut file, 100 * MIB).is_err());
+        assert!(resize_fat32(&mut file, 700 * MIB).is_err());
+
+        let grown = resize_fat32(&mut file, formatted.max_bytes()).unwrap();
+        assert!(grown.cluster_count >= formatted.cluster_count);
+        drop(file);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,612 @@
+//! Read-only checks of a FAT32 volume: the boot sector and its backup, the
+//! FSInfo sectors, the head of each FAT and a layout recomputed from the
+//! sector count. A FAT too short for its clusters, or a cluster count
+//! outside the FAT32 range, is what leaves a volume some firmware will not
+//! boot, so those are recomputed independently of the formatter.
+
+use anyhow::{anyhow, Context, Result};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+const MIN_CLUSTERS: u64 = 65525;
+const MAX_CLUSTERS: u64 = 0x0FFF_FFF5;
+const FAT_ENTRY_MASK: u32 = 0x0FFF_FFFF;
+const END_OF_CHAIN: u32 = 0x0FFF_FFF8;
+/// Bits of FAT entry 1 that are cleared while the volume is mounted and
+/// after a hard error.
+const CLEAN_SHUTDOWN: u32 = 0x0800_0000;
+const NO_HARD_ERROR: u32 = 0x0400_0000;
+const FSINFO_LEAD: u32 = 0x4161_5252;
+const FSINFO_STRUCT: u32 = 0x6141_7272;
+const FSINFO_TRAIL: u32 = 0xAA55_0000;
+const UNKNOWN: u32 = 0xFFFF_FFFF;
+
+/// A layout `format_fat32` is known to produce, cross-checked against
+/// dosfstools' `mkfs.fat -F 32 -R 32 -f 2 -a`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReferenceLayout {
+    pub total_bytes: u64,
+    pub sectors_per_cluster: u8,
+    pub sectors_per_fat: u32,
+    pub cluster_count: u32,
+}
+
+const fn reference(
+    total_bytes: u64,
+    sectors_per_cluster: u8,
+    sectors_per_fat: u32,
+    cluster_count: u32,
+) -> ReferenceLayout {
+    ReferenceLayout {
+        total_bytes,
+        sectors_per_cluster,
+        sectors_per_fat,
+        cluster_count,
+    }
+}
+
+/// Power-of-two sizes, the sizes of common 8 to 64 GB sticks and the
+/// largest volume 512-byte sectors allow, each with the default cluster
+/// size.
+pub const REFERENCE_LAYOUTS: &[ReferenceLayout] = &[
+    reference(64 << 20, 1, 1009, 129022),
+    reference(128 << 20, 1, 2017, 258078),
+    reference(256 << 20, 1, 4033, 516190),
+    reference(512 << 20, 8, 1022, 130812),
+    reference(1 << 30, 8, 2044, 261629),
+    reference(2 << 30, 8, 4088, 523262),
+    reference(4 << 30, 8, 8177, 1046527),
+    reference(8 << 30, 16, 8185, 1047550),
+    reference(16 << 30, 32, 8189, 1048063),
+    reference(32 << 30, 64, 8191, 1048319),
+    reference(64 << 30, 64, 16381, 2096639),
+    reference(128 << 30, 64, 32761, 4193279),
+    reference(8_004_304_896, 8, 15238, 1950362),
+    reference(15_518_924_800, 16, 14786, 1892549),
+    reference(31_029_460_992, 32, 14789, 1892962),
+    reference(62_026_416_128, 64, 14785, 1892433),
+    reference(2_199_023_255_040, 64, 524161, 67092483),
+];
+
+/// The BIOS parameter block of a FAT32 boot sector.
+#[derive(Debug, Clone)]
+pub struct Fat32Boot {
+    pub bytes_per_sector: u32,
+    pub sectors_per_cluster: u32,
+    pub reserved_sectors: u32,
+    pub number_of_fats: u32,
+    pub media: u8,
+    pub total_sectors: u32,
+    pub sectors_per_fat: u32,
+    pub root_cluster: u32,
+    pub fsinfo_sector: u32,
+    pub backup_boot_sector: u32,
+    pub volume_id: u32,
+}
+
+impl Fat32Boot {
+    pub fn cluster_bytes(&self) -> u64 {
+        self.bytes_per_sector as u64 * self.sectors_per_cluster as u64
+    }
+
+    pub fn volume_bytes(&self) -> u64 {
+        self.total_sectors as u64 * self.bytes_per_sector as u64
+    }
+
+    /// First sector of cluster 2.
+    pub fn data_start_sector(&self) -> u64 {
+        self.reserved_sectors as u64 + self.number_of_fats as u64 * self.sectors_per_fat as u64
+    }
+
+    pub fn cluster_count(&self) -> u64 {
+        (self.total_sectors as u64).saturating_sub(self.data_start_sector())
+            / self.sectors_per_cluster as u64
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Fat32Check {
+    pub ok: bool,
+    pub issues: Vec<String>,
+    /// Non-fatal findings, e.g. the volume not being cleanly unmounted.
+    pub warnings: Vec<String>,
+    pub boot: Option<Fat32Boot>,
+    /// FAT length recomputed from the sector count and cluster size.
+    pub reference_sectors_per_fat: Option<u32>,
+}
+
+/// Checks the FAT32 volume starting `offset` bytes into `device`.
+/// `volume_bytes`, when known, bounds the volume length.
+pub fn verify_fat32<D: Read + Seek>(
+    device: &mut D,
+    offset: u64,
+    volume_bytes: Option<u64>,
+) -> Result<Fat32Check> {
+    let mut issues = Vec::new();
+    let mut warnings = Vec::new();
+
+    let first = read_bytes(device, offset, 512)?;
+    if &first[0x52..0x5A] != b"FAT32   " || first[510..512] != [0x55, 0xAA] {
+        issues.push("boot sector does not name a FAT32 file system".to_string());
+        return Ok(failed(issues, warnings, None));
+    }
+    let boot = parse_boot_sector(&first);
+    if !matches!(boot.bytes_per_sector, 512 | 1024 | 2048 | 4096) {
+        issues.push(format!("BytesPerSector {} is not 512 to 4096", boot.bytes_per_sector));
+    }
+    if boot.sectors_per_cluster == 0 || !boot.sectors_per_cluster.is_power_of_two() {
+        issues.push(format!(
+            "SectorsPerCluster {} is not a power of two",
+            boot.sectors_per_cluster
+        ));
+    }
+    if boot.reserved_sectors == 0 || boot.number_of_fats == 0 || boot.sectors_per_fat == 0 {
+        issues.push("reserved sectors, FAT count and FAT length must not be zero".to_string());
+    }
+    if !issues.is_empty() {
+        return Ok(failed(issues, warnings, Some(boot)));
+    }
+    let sector = boot.bytes_per_sector as u64;
+    let sector_bytes = |number: u64| offset + number * sector;
+
+    check_boot_sector(&first, &boot, volume_bytes, &mut issues, &mut warnings);
+    if boot.data_start_sector() >= boot.total_sectors as u64 {
+        issues.push(format!(
+            "FATs end at sector {} past TotalSectors {}",
+            boot.data_start_sector(),
+            boot.total_sectors
+        ));
+        return Ok(failed(issues, warnings, Some(boot)));
+    }
+
+    let clusters = boot.cluster_count();
+    if !(MIN_CLUSTERS..=MAX_CLUSTERS).contains(&clusters) {
+        issues.push(format!(
+            "{} clusters is outside the FAT32 range of {} to {}; other systems will read it as another FAT type",
+            clusters, MIN_CLUSTERS, MAX_CLUSTERS
+        ));
+    }
+    let fat_entries = boot.sectors_per_fat as u64 * sector / 4;
+    if fat_entries < clusters + 2 {
+        issues.push(format!(
+            "FAT of {} sectors holds {} entries, short of the {} its clusters need",
+            boot.sectors_per_fat,
+            fat_entries,
+            clusters + 2
+        ));
+    }
+    let reference = reference_fat_sectors(
+        boot.total_sectors,
+        boot.reserved_sectors,
+        boot.number_of_fats,
+        boot.sectors_per_cluster,
+        boot.bytes_per_sector,
+    );
+    let largest = spec_fat_sectors(&boot).max(reference.next_multiple_of(boot.sectors_per_cluster));
+    if boot.sectors_per_fat > largest {
+        warnings.push(format!(
+            "FAT is {} sectors; the volume needs {}",
+            boot.sectors_per_fat, reference
+        ));
+    }
+    if boot.root_cluster < 2 || boot.root_cluster as u64 > clusters + 1 {
+        issues.push(format!("RootCluster {} is outside the data region", boot.root_cluster));
+    }
+
+    if boot.fsinfo_sector == 0 || boot.fsinfo_sector >= boot.reserved_sectors {
+        issues.push(format!(
+            "FSInfo sector {} is outside the reserved sectors",
+            boot.fsinfo_sector
+        ));
+    } else {
+        let fsinfo = read_bytes(device, sector_bytes(boot.fsinfo_sector as u64), sector)?;
+        check_fsinfo(&fsinfo, clusters, "FSInfo", &mut issues, &mut warnings);
+    }
+    if boot.backup_boot_sector == 0 || boot.backup_boot_sector == 0xFFFF {
+        warnings.push("volume has no backup boot sector".to_string());
+    } else if boot.backup_boot_sector + 1 >= boot.reserved_sectors {
+        issues.push(format!(
+            "backup boot sector {} is outside the reserved sectors",
+            boot.backup_boot_sector
+        ));
+    } else {
+        let primary = read_bytes(device, offset, sector)?;
+        let backup = read_bytes(device, sector_bytes(boot.backup_boot_sector as u64), sector)?;
+        if backup != primary {
+            issues.push(format!(
+                "backup boot sector {} differs from the boot sector",
+                boot.backup_boot_sector
+            ));
+        }
+        let fsinfo = read_bytes(device, sector_bytes(boot.backup_boot_sector as u64 + 1), sector)?;
+        check_fsinfo(&fsinfo, clusters, "backup FSInfo", &mut issues, &mut warnings);
+    }
+
+    let mut heads = Vec::new();
+    for fat in 0..boot.number_of_fats as u64 {
+        let start = boot.reserved_sectors as u64 + fat * boot.sectors_per_fat as u64;
+        heads.push(read_bytes(device, sector_bytes(start), sector)?);
+    }
+    check_fat_head(&heads[0], &boot, &mut issues, &mut warnings);
+    let mirrored = read_u16(&first, 0x28) & 0x80 == 0;
+    if mirrored && heads.iter().any(|head| *head != heads[0]) {
+        issues.push("FAT copies differ in their first sector".to_string());
+    }
+    if (2..=clusters + 1).contains(&(boot.root_cluster as u64)) {
+        let entry_offset = boot.root_cluster as u64 * 4;
+        let fat_sector = boot.reserved_sectors as u64 + entry_offset / sector;
+        let bytes = read_bytes(device, sector_bytes(fat_sector), sector)?;
+        if read_u32(&bytes, (entry_offset % sector) as usize) & FAT_ENTRY_MASK == 0 {
+            issues.push(format!("root directory cluster {} is marked free", boot.root_cluster));
+        }
+    }
+
+    Ok(Fat32Check {
+        ok: issues.is_empty(),
+        issues,
+        warnings,
+        boot: Some(boot),
+        reference_sectors_per_fat: Some(reference),
+    })
+}
+
+/// Opens `device_path` read-only and checks the volume at `offset`.
+/// Without `volume_bytes`, a regular file's length (less `offset`) is used.
+pub fn verify_fat32_device(
+    device_path: impl AsRef<Path>,
+    offset: u64,
+    volume_bytes: Option<u64>,
+) -> Result<Fat32Check> {
+    let path = device_path.as_ref();
+    let mut file = File::open(path).with_context(|| format!("open {}", path.display()))?;
+    let volume_bytes = match volume_bytes {
+        Some(bytes) => Some(bytes),
+        None => file
+            .metadata()
+            .ok()
+            .filter(|meta| meta.is_file())
+            .map(|meta| meta.len().saturating_sub(offset)),
+    };
+    verify_fat32(&mut file, offset, volume_bytes)
+}
+
+/// The FAT length in sectors that leaves exactly enough entries for the
+/// clusters after it, in the closed form dosfstools' `mkfs.fat` uses.
+pub fn reference_fat_sectors(
+    total_sectors: u32,
+    reserved_sectors: u32,
+    number_of_fats: u32,
+    sectors_per_cluster: u32,
+    bytes_per_sector: u32,
+) -> u32 {
+    let data = (total_sectors as u64).saturating_sub(reserved_sectors as u64);
+    let sector = bytes_per_sector as u64;
+    let fats = number_of_fats as u64;
+    let clusters = (data * sector + fats * 8) / (sectors_per_cluster as u64 * sector + fats * 4);
+    ((clusters + 2) * 4).div_ceil(sector) as u32
+}
+
+/// The FAT length the Microsoft FAT specification computes, which may
+/// exceed what the volume needs by a few sectors.
+fn spec_fat_sectors(boot: &Fat32Boot) -> u32 {
+    let data = (boot.total_sectors as u64).saturating_sub(boot.reserved_sectors as u64);
+    let per_sector =
+        (boot.bytes_per_sector as u64 / 2 * boot.sectors_per_cluster as u64 + boot.number_of_fats as u64) / 2;
+    data.div_ceil(per_sector) as u32
+}
+
+fn failed(issues: Vec<String>, warnings: Vec<String>, boot: Option<Fat32Boot>) -> Fat32Check {
+    Fat32Check {
+        ok: false,
+        issues,
+        warnings,
+        boot,
+        reference_sectors_per_fat: None,
+    }
+}
+
+fn parse_boot_sector(sector: &[u8]) -> Fat32Boot {
+    Fat32Boot {
+        bytes_per_sector: read_u16(sector, 0x0B) as u32,
+        sectors_per_cluster: sector[0x0D] as u32,
+        reserved_sectors: read_u16(sector, 0x0E) as u32,
+        number_of_fats: sector[0x10] as u32,
+        media: sector[0x15],
+        total_sectors: read_u32(sector, 0x20),
+        sectors_per_fat: read_u32(sector, 0x24),
+        root_cluster: read_u32(sector, 0x2C),
+        fsinfo_sector: read_u16(sector, 0x30) as u32,
+        backup_boot_sector: read_u16(sector, 0x32) as u32,
+        volume_id: read_u32(sector, 0x43),
+    }
+}
+
+fn check_boot_sector(
+    sector: &[u8],
+    boot: &Fat32Boot,
+    volume_bytes: Option<u64>,
+    issues: &mut Vec<String>,
+    warnings: &mut Vec<String>,
+) {
+    if !(sector[0] == 0xEB && sector[2] == 0x90) && sector[0] != 0xE9 {
+        issues.push(format!("jump instruction is {:02X?}, expected EB xx 90", &sector[0..3]));
+    }
+    if read_u16(sector, 0x11) != 0 || read_u16(sector, 0x13) != 0 || read_u16(sector, 0x16) != 0 {
+        issues.push("RootEntryCount, TotalSectors16 and FATSize16 must be zero on FAT32".to_string());
+    }
+    if boot.media != 0xF0 && boot.media < 0xF8 {
+        issues.push(format!("media descriptor {:02X} is not F0 or F8-FF", boot.media));
+    }
+    if read_u16(sector, 0x2A) != 0 {
+        issues.push(format!("file system version {:04X} is not 0.0", read_u16(sector, 0x2A)));
+    }
+    if boot.total_sectors == 0 {
+        issues.push("TotalSectors32 is zero".to_string());
+    }
+    if let Some(bytes) = volume_bytes {
+        if boot.volume_bytes() > bytes {
+            issues.push(format!(
+                "TotalSectors32 covers {} bytes but the volume holds {}",
+                boot.volume_bytes(),
+                bytes
+            ));
+        }
+    }
+    if boot.number_of_fats != 2 {
+        warnings.push(format!("{} FATs; most systems expect 2", boot.number_of_fats));
+    }
+    if boot.cluster_bytes() > 32 * 1024 {
+        warnings.push(format!(
+            "{} byte clusters are larger than many systems accept",
+            boot.cluster_bytes()
+        ));
+    }
+    if sector[0x42] != 0x29 {
+        warnings.push("boot sector has no extended boot signature".to_string());
+    }
+}
+
+fn check_fsinfo(
+    sector: &[u8],
+    clusters: u64,
+    name: &str,
+    issues: &mut Vec<String>,
+    warnings: &mut Vec<String>,
+) {
+    if read_u32(sector, 0) != FSINFO_LEAD
+        || read_u32(sector, 0x1E4) != FSINFO_STRUCT
+        || read_u32(sector, 0x1FC) != FSINFO_TRAIL
+    {
+        issues.push(format!("{} sector is missing its signatures", name));
+        return;
+    }
+    let free = read_u32(sector, 0x1E8);
+    if free != UNKNOWN && free as u64 > clusters {
+        warnings.push(format!("{} free count {} exceeds {} clusters", name, free, clusters));
+    }
+    let next = read_u32(sector, 0x1EC);
+    if next != UNKNOWN && !(2..=clusters + 1).contains(&(next as u64)) {
+        warnings.push(format!("{} next free cluster {} is outside the data region", name, next));
+    }
+}
+
+/// Entry 0 repeats the media descriptor and entry 1 marks end of chain,
+/// with the clean-shutdown and hard-error bits in its top.
+fn check_fat_head(
+    sector: &[u8],
+    boot: &Fat32Boot,
+    issues: &mut Vec<String>,
+    warnings: &mut Vec<String>,
+) {
+    let media = read_u32(sector, 0) & FAT_ENTRY_MASK;
+    if media != 0x0FFF_FF00 | boot.media as u32 {
+        issues.push(format!(
+            "FAT entry 0 is {:08X}, expected {:08X}",
+            media,
+            0x0FFF_FF00 | boot.media as u32
+        ));
+    }
+    let flags = read_u32(sector, 4) & FAT_ENTRY_MASK;
+    if flags | CLEAN_SHUTDOWN | NO_HARD_ERROR < END_OF_CHAIN {
+        issues.push(format!("FAT entry 1 is {:08X}, expected end of chain", flags));
+        return;
+    }
+    if flags & CLEAN_SHUTDOWN == 0 {
+        warnings.push("volume was not cleanly unmounted".to_string());
+    }
+    if flags & NO_HARD_ERROR == 0 {
+        warnings.push("volume reports a disk error".to_string());
+    }
+}
+
+fn read_bytes<D: Read + Seek>(device: &mut D, offset: u64, len: u64) -> Result<Vec<u8>> {
+    let mut buffer = vec![0u8; len as usize];
+    device.seek(SeekFrom::Start(offset))?;
+    device
+        .read_exact(&mut buffer)
+        .map_err(|err| anyhow!("read {} bytes at offset {}: {}", len, offset, err))?;
+    Ok(buffer)
+}
+
+fn read_u16(buffer: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([buffer[offset], buffer[offset + 1]])
+}
+
+fn read_u32(buffer: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([buffer[offset], buffer[offset + 1], buffer[offset + 2], buffer[offset + 3]])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        check_formatted, compute_fat_size, legal_sectors_per_cluster, write_volume, OemCodepage,
+        NUM_FATS, RESERVED_SECTORS,
+    };
+    use std::collections::BTreeMap;
+    use std::io::Write;
+
+    static ZEROS: [u8; 64 * 1024] = [0; 64 * 1024];
+
+    /// A device that keeps only the sectors holding something other than
+    /// zeros, so terabyte layouts fit in memory.
+    struct SparseDisk {
+        len: u64,
+        position: u64,
+        sectors: BTreeMap<u64, Vec<u8>>,
+    }
+
+    impl SparseDisk {
+        fn new(len: u64) -> Self {
+            Self { len, position: 0, sectors: BTreeMap::new() }
+        }
+    }
+
+    impl Read for SparseDisk {
+        fn read(&mut self, buffer: &mut [u8]) -> std::io::Result<usize> {
+            let len = (buffer.len() as u64).min(self.len.saturating_sub(self.position)) as usize;
+            for (index, byte) in buffer[..len].iter_mut().enumerate() {
+                let at = self.position + index as u64;
+                *byte = self.sectors.get(&(at / 512)).map_or(0, |sector| sector[(at % 512) as usize]);
+            }
+            self.position += len as u64;
+            Ok(len)
+        }
+    }
+
+    impl Write for SparseDisk {
+        fn write(&mut self, buffer: &[u8]) -> std::io::Result<usize> {
+            assert!(self.position.is_multiple_of(512) && buffer.len().is_multiple_of(512));
+            assert!(self.position + buffer.len() as u64 <= self.len, "write past the device end");
+            let first = self.position / 512;
+            if buffer == &ZEROS[..buffer.len().min(ZEROS.len())] {
+                let written: Vec<u64> = self
+                    .sectors
+                    .range(first..first + buffer.len() as u64 / 512)
+                    .map(|(number, _)| *number)
+                    .collect();
+                for number in written {
+                    self.sectors.remove(&number);
+                }
+            } else {
+                for (index, chunk) in buffer.chunks(512).enumerate() {
+                    self.sectors.insert(first + index as u64, chunk.to_vec());
+                }
+            }
+            self.position += buffer.len() as u64;
+            Ok(buffer.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Seek for SparseDisk {
+        fn seek(&mut self, from: SeekFrom) -> std::io::Result<u64> {
+            let position = match from {
+                SeekFrom::Start(position) => Some(position),
+                SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+                SeekFrom::End(offset) => self.len.checked_add_signed(offset),
+            };
+            self.position = position.ok_or_else(|| {
+                std::io::Error::new(std::io::ErrorKind::InvalidInput, "seek before the device start")
+            })?;
+            Ok(self.position)
+        }
+    }
+
+    fn format(total_bytes: u64, cluster_bytes: Option<u64>) -> (SparseDisk, crate::Fat32Layout) {
+        let mut disk = SparseDisk::new(total_bytes);
+        let layout = write_volume(&mut disk, total_bytes, Some("TEST"), OemCodepage::Cp437, cluster_bytes)
+            .unwrap_or_else(|err| panic!("format {} bytes: {}", total_bytes, err));
+        check_formatted(&mut disk, total_bytes, &layout)
+            .unwrap_or_else(|err| panic!("format {} bytes: {}", total_bytes, err));
+        (disk, layout)
+    }
+
+    #[test]
+    fn formats_match_reference_layouts() {
+        for reference in REFERENCE_LAYOUTS {
+            let (mut disk, layout) = format(reference.total_bytes, None);
+            assert_eq!(layout.sectors_per_cluster, reference.sectors_per_cluster, "{:?}", reference);
+            assert_eq!(layout.sectors_per_fat, reference.sectors_per_fat, "{:?}", reference);
+            let check = verify_fat32(&mut disk, 0, Some(reference.total_bytes)).unwrap();
+            assert!(check.ok && check.warnings.is_empty(), "{:?}", check);
+            let boot = check.boot.unwrap();
+            assert_eq!(boot.cluster_count(), reference.cluster_count as u64, "{:?}", reference);
+        }
+    }
+
+    /// Sizes drawn log-uniformly up to 2 TiB, plus every size around the
+    /// smallest FAT32 cluster count for each cluster size.
+    #[test]
+    fn layouts_hold_across_sizes() {
+        let mut state = 0x9E37_79B9_7F4A_7C15u64;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+        let mut sizes: Vec<u32> = (0..300)
+            .map(|_| {
+                let bits = 17 + next() % 16;
+                ((1u64 << bits) + next() % (1u64 << bits)).min(u32::MAX as u64) as u32
+            })
+            .collect();
+        let smallest_fat = ((MIN_CLUSTERS + 2) * 4).div_ceil(512) as u32;
+        for spc in [1u32, 2, 4, 8, 16, 32, 64, 128] {
+            let edge =
+                RESERVED_SECTORS as u32 + NUM_FATS as u32 * smallest_fat + spc * MIN_CLUSTERS as u32;
+            sizes.extend(edge - 3 * spc - 8..edge + 3 * spc + 8);
+        }
+        sizes.push(u32::MAX);
+
+        for total_sectors in sizes {
+            for spc in legal_sectors_per_cluster(total_sectors) {
+                let fat = compute_fat_size(total_sectors, spc).unwrap();
+                let clusters = (total_sectors - RESERVED_SECTORS as u32 - NUM_FATS as u32 * fat) / spc as u32;
+                assert!(fat as u64 * 128 >= clusters as u64 + 2, "{} sectors, spc {}", total_sectors, spc);
+                assert!(compute_fat_size(total_sectors, spc).is_ok_and(|again| again == fat));
+                assert_eq!(
+                    fat,
+                    reference_fat_sectors(total_sectors, RESERVED_SECTORS as u32, NUM_FATS as u32, spc as u32, 512),
+                    "{} sectors, spc {}",
+                    total_sectors,
+                    spc
+                );
+            }
+            if !legal_sectors_per_cluster(total_sectors).is_empty() {
+                let (mut disk, _) = format(total_sectors as u64 * 512, None);
+                let check = verify_fat32(&mut disk, 0, Some(total_sectors as u64 * 512)).unwrap();
+                assert!(check.ok, "{} sectors: {:?}", total_sectors, check.issues);
+            }
+        }
+        let too_large = (u32::MAX as u64 + 1) * 512;
+        assert!(write_volume(&mut SparseDisk::new(too_large), too_large, None, OemCodepage::Cp437, None).is_err());
+    }
+
+    #[test]
+    fn flags_damaged_volumes() {
+        let total_bytes = 1u64 << 30;
+        let (mut disk, layout) = format(total_bytes, None);
+
+        let mut boot = disk.sectors[&0].clone();
+        boot[0x24..0x28].copy_from_slice(&(layout.sectors_per_fat - 1).to_le_bytes());
+        disk.seek(SeekFrom::Start(0)).unwrap();
+        disk.write_all(&boot).unwrap();
+        let check = verify_fat32(&mut disk, 0, Some(total_bytes)).unwrap();
+        assert!(check.issues.iter().any(|issue| issue.contains("short of")), "{:?}", check.issues);
+        assert!(check.issues.iter().any(|issue| issue.contains("backup boot sector")));
+
+        let check = verify_fat32(&mut disk, 0, Some(total_bytes / 2)).unwrap();
+        assert!(check.issues.iter().any(|issue| issue.contains("volume holds")));
+
+        disk.seek(SeekFrom::Start(512)).unwrap();
+        disk.write_all(&[0u8; 512]).unwrap();
+        let check = verify_fat32(&mut disk, 0, Some(total_bytes)).unwrap();
+        assert!(check.issues.iter().any(|issue| issue.contains("FSInfo sector")));
+    }
+}
@@ -0,0 +1,105 @@
+//! Raw-volume backend for Windows. `FormatEx` refuses FAT32 above 32 GiB,
+//! so large volumes are locked, dismounted and written directly.
+
+use crate::{format_fat32_device, Fat32Layout, OemCodepage, BYTES_PER_SECTOR};
+use anyhow::{anyhow, Context, Result};
+use std::ffi::c_void;
+use std::fs::{File, OpenOptions};
+use std::os::windows::fs::OpenOptionsExt;
+use std::os::windows::io::AsRawHandle;
+
+use windows::Win32::Foundation::HANDLE;
+use windows::Win32::System::IO::DeviceIoControl;
+use windows::Win32::System::Ioctl::{
+    FSCTL_ALLOW_EXTENDED_DASD_IO, FSCTL_DISMOUNT_VOLUME, FSCTL_LOCK_VOLUME, FSCTL_UNLOCK_VOLUME,
+    GET_LENGTH_INFORMATION, IOCTL_DISK_GET_LENGTH_INFO,
+};
+
+const FILE_SHARE_READ: u32 = 0x1;
+const FILE_SHARE_WRITE: u32 = 0x2;
+
+/// Formats the volume at `volume` (`E:`, `E:\`, `\\.\E:` or
+/// `\\?\Volume{GUID}\`) as FAT32. The volume stays locked and dismounted
+/// while the structures are written; Windows mounts the new filesystem on
+/// next access.
+pub fn format_fat32_volume(
+    volume: &str,
+    label: Option<&str>,
+    cluster_bytes: Option<u64>,
+) -> Result<Fat32Layout> {
+    phoenix_safety::ensure_writable("FAT32 format")?;
+    let mut file = open_volume(volume)?;
+    let handle = HANDLE(file.as_raw_handle() as isize);
+    control(handle, FSCTL_LOCK_VOLUME, "lock")?;
+    let result = control(handle, FSCTL_DISMOUNT_VOLUME, "dismount")
+        // Lets writes reach the whole partition, not just the old
+        // filesystem's extent.
+        .and_then(|_| control(handle, FSCTL_ALLOW_EXTENDED_DASD_IO, "extended access"))
+        .and_then(|_| length(handle))
+        .and_then(|bytes| {
+            let total_bytes = bytes - bytes % BYTES_PER_SECTOR as u64;
+            format_fat32_device(
+                &mut file,
+                total_bytes,
+                label,
+                OemCodepage::default(),
+                cluster_bytes,
+            )
+        });
+    let _ = control(handle, FSCTL_UNLOCK_VOLUME, "unlock");
+    result
+}
+
+/// Size of a volume in bytes, whether or not it holds a filesystem.
+pub fn volume_length(volume: &str) -> Result<u64> {
+    let file = open_volume(volume)?;
+    length(HANDLE(file.as_raw_handle() as isize))
+}
+
+fn open_volume(volume: &str) -> Result<File> {
+    let path = device_path(volume);
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .share_mode(FILE_SHARE_READ | FILE_SHARE_WRITE)
+        .open(&path)
+        .with_context(|| format!("open {}", path))
+}
+
+/// `\\.\E:` for drive letters, the GUID path without its trailing
+/// backslash (which would open the root directory) otherwise.
+fn device_path(volume: &str) -> String {
+    let trimmed = volume.trim_end_matches('\\');
+    let bytes = trimmed.as_bytes();
+    if bytes.len() == 2 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':' {
+        return format!(r"\\.\{}", trimmed.to_ascii_uppercase());
+    }
+    trimmed.to_string()
+}
+
+fn control(handle: HANDLE, code: u32, what: &str) -> Result<()> {
+    let mut returned = 0u32;
+    unsafe {
+        DeviceIoControl(handle, code, None, 0, None, 0, Some(&mut returned), None)
+            .map_err(|err| anyhow!("volume {} failed: {}", what, err))
+    }
+}
+
+fn length(handle: HANDLE) -> Result<u64> {
+    let mut info = GET_LENGTH_INFORMATION::default();
+    let mut returned = 0u32;
+    unsafe {
+        DeviceIoControl(
+            handle,
+            IOCTL_DISK_GET_LENGTH_INFO,
+            None,
+            0,
+            Some(&mut info as *mut _ as *mut c_void),
+            std::mem::size_of::<GET_LENGTH_INFORMATION>() as u32,
+            Some(&mut returned),
+            None,
+        )
+        .map_err(|err| anyhow!("IOCTL_DISK_GET_LENGTH_INFO failed: {}", err))?;
+    }
+    Ok(info.Length.max(0) as u64)
+}
@@ -0,0 +1,316 @@
+use anyhow::{anyhow, Context, Result};
+use phoenix_core::now_utc_rfc3339;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+pub const HASHMAP_SCHEMA_VERSION: &str = "1.0.0";
+pub const HASHMAP_ALGORITHM: &str = "sha256";
+pub const HASHMAP_FILE_NAME: &str = "disk_hashes.json";
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct ChunkHash {
+    pub index: u64,
+    pub offset: u64,
+    pub length: u64,
+    pub sha256: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ChunkHashMap {
+    pub schema_version: String,
+    pub algorithm: String,
+    pub chunk_size: u64,
+    pub total_bytes: u64,
+    #[serde(default)]
+    pub source: Option<String>,
+    #[serde(default)]
+    pub generated_at_utc: Option<String>,
+    pub chunks: Vec<ChunkHash>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct HashMapComparison {
+    pub matching: Vec<u64>,
+    pub differing: Vec<u64>,
+    pub only_left: Vec<u64>,
+    pub only_right: Vec<u64>,
+}
+
+/// A run of adjacent chunks, in bytes of the left map's device.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct ChunkRange {
+    pub first_index: u64,
+    pub last_index: u64,
+    pub offset: u64,
+    pub length: u64,
+}
+
+impl HashMapComparison {
+    pub fn identical(&self) -> bool {
+        self.differing.is_empty() && self.only_left.is_empty() && self.only_right.is_empty()
+    }
+
+    /// Differing chunks of `map` (the left side) merged into ranges of
+    /// adjacent chunks.
+    pub fn differing_ranges(&self, map: &ChunkHashMap) -> Vec<ChunkRange> {
+        let mut ranges: Vec<ChunkRange> = Vec::new();
+        for chunk in self.differing.iter().filter_map(|index| map.get(*index)) {
+            match ranges.last_mut() {
+                Some(range) if range.last_index + 1 == chunk.index => {
+                    range.last_index = chunk.index;
+                    range.length += chunk.length;
+                }
+                _ => ranges.push(ChunkRange {
+                    first_index: chunk.index,
+                    last_index: chunk.index,
+                    offset: chunk.offset,
+                    length: chunk.length,
+                }),
+            }
+        }
+        ranges
+    }
+}
+
+impl ChunkHashMap {
+    pub fn new(chunk_size: u64, total_bytes: u64) -> Self {
+        Self {
+            schema_version: HASHMAP_SCHEMA_VERSION.to_string(),
+            algorithm: HASHMAP_ALGORITHM.to_string(),
+            chunk_size,
+            total_bytes,
+            source: None,
+            generated_at_utc: Some(now_utc_rfc3339()),
+            chunks: Vec::new(),
+        }
+    }
+
+    /// Builds a map from `(index, sha256)` pairs as produced by the imaging
+    /// hashers, deriving offsets and lengths from the chunk size.
+    pub fn from_hashes(
+        chunk_size: u64,
+        total_bytes: u64,
+        hashes: impl IntoIterator<Item = (u64, String)>,
+    ) -> Result<Self> {
+        if chunk_size == 0 {
+            return Err(anyhow!("chunk size must be non-zero"));
+        }
+        let mut map = Self::new(chunk_size, total_bytes);
+        for (index, sha256) in hashes {
+            let offset = index
+                .checked_mul(chunk_size)
+                .filter(|offset| *offset < total_bytes)
+                .ok_or_else(|| anyhow!("chunk {} outside device size", index))?;
+            let length = (total_bytes - offset).min(chunk_size);
+            map.chunks.push(ChunkHash {
+                index,
+                offset,
+                length,
+                sha256,
+            });
+        }
+        map.chunks.sort_by_key(|chunk| chunk.index);
+        Ok(map)
+    }
+
+    pub fn with_source(mut self, source: impl Into<String>) -> Self {
+        self.source = Some(source.into());
+        self
+    }
+
+    pub fn get(&self, index: u64) -> Option<&ChunkHash> {
+        self.chunks
+            .binary_search_by_key(&index, |chunk| chunk.index)
+            .ok()
+            .map(|pos| &self.chunks[pos])
+    }
+
+    pub fn covered_bytes(&self) -> u64 {
+        self.chunks.iter().map(|chunk| chunk.length).sum()
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.covered_bytes() == self.total_bytes
+    }
+
+    pub fn to_json_bytes(&self) -> Result<Vec<u8>> {
+        Ok(serde_json::to_vec_pretty(self)?)
+    }
+
+    pub fn compare(&self, other: &ChunkHashMap) -> Result<HashMapComparison> {
+        ensure_compatible(self, other)?;
+        let left = index_chunks(self);
+        let right = index_chunks(other);
+        let mut result = HashMapComparison::default();
+        for (index, chunk) in &left {
+            match right.get(index) {
+                Some(other) if other.sha256.eq_ignore_ascii_case(&chunk.sha256) => {
+                    result.matching.push(*index)
+                }
+                Some(_) => result.differing.push(*index),
+                None => result.only_left.push(*index),
+            }
+        }
+        for index in right.keys() {
+            if !left.contains_key(index) {
+                result.only_right.push(*index);
+            }
+        }
+        Ok(result)
+    }
+
+    /// Merges chunks from a partial map covering the same device. Chunks
+    /// present in both must agree on their hash.
+    pub fn merge(&mut self, other: &ChunkHashMap) -> Result<()> {
+        ensure_compatible(self, other)?;
+        let mut merged = index_chunks(self)
+            .into_iter()
+            .map(|(index, chunk)| (index, chunk.clone()))
+            .collect::<BTreeMap<_, _>>();
+        for chunk in &other.chunks {
+            match merged.get(&chunk.index) {
+                Some(existing) if !existing.sha256.eq_ignore_ascii_case(&chunk.sha256) => {
+                    return Err(anyhow!("conflicting hash for chunk {}", chunk.index));
+                }
+                Some(_) => {}
+                None => {
+                    merged.insert(chunk.index, chunk.clone());
+                }
+            }
+        }
+        self.chunks = merged.into_values().collect();
+        if self.source.is_none() {
+            self.source = other.source.clone();
+        }
+        Ok(())
+    }
+}
+
+pub fn read_hashmap(path: impl AsRef<Path>) -> Result<ChunkHashMap> {
+    let path = path.as_ref();
+    let data = fs::read(path).with_context(|| format!("read {}", path.display()))?;
+    parse_hashmap(&data)
+}
+
+pub fn write_hashmap(path: impl AsRef<Path>, map: &ChunkHashMap) -> Result<()> {
+    let path = path.as_ref();
+    fs::write(path, map.to_json_bytes()?).with_context(|| format!("write {}", path.display()))
+}
+
+/// Parses a chunk map, accepting the unversioned array of entries that
+/// earlier disk-hash reports wrote.
+pub fn parse_hashmap(data: &[u8]) -> Result<ChunkHashMap> {
+    let value: serde_json::Value = serde_json::from_slice(data)?;
+    if value.is_array() {
+        let mut chunks: Vec<ChunkHash> = serde_json::from_value(value)?;
+        chunks.sort_by_key(|chunk| chunk.index);
+        let chunk_size = chunks.iter().map(|chunk| chunk.length).max().unwrap_or(0);
+        let total_bytes = chunks
+            .last()
+            .map(|chunk| chunk.offset + chunk.length)
+            .unwrap_or(0);
+        return Ok(ChunkHashMap {
+            schema_version: "0".to_string(),
+            algorithm: HASHMAP_ALGORITHM.to_string(),
+            chunk_size,
+            total_bytes,
+            source: None,
+            generated_at_utc: None,
+            chunks,
+        });
+    }
+    let mut map: ChunkHashMap = serde_json::from_value(value)?;
+    if map.schema_version != HASHMAP_SCHEMA_VERSION {
+        return Err(anyhow!("unsupported hashmap schema {}", map.schema_version));
+    }
+    if !map.algorithm.eq_ignore_ascii_case(HASHMAP_ALGORITHM) {
+        return Err(anyhow!("unsupported hashmap algorithm {}", map.algorithm));
+    }
+    map.chunks.sort_by_key(|chunk| chunk.index);
+    Ok(map)
+}
+
+fn ensure_compatible(left: &ChunkHashMap, right: &ChunkHashMap) -> Result<()> {
+    if left.chunk_size != right.chunk_size {
+        return Err(anyhow!(
+            "chunk size mismatch: {} vs {}",
+            left.chunk_size,
+            right.chunk_size
+        ));
+    }
+    if !left.algorithm.eq_ignore_ascii_case(&right.algorithm) {
+        return Err(anyhow!(
+            "algorithm mismatch: {} vs {}",
+            left.algorithm,
+            right.algorithm
+        ));
+    }
+    Ok(())
+}
+
+fn index_chunks(map: &ChunkHashMap) -> BTreeMap<u64, &ChunkHash> {
+    map.chunks.iter().map(|chunk| (chunk.index, chunk)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn map(hashes: &[(u64, &str)]) -> ChunkHashMap {
+        ChunkHashMap::from_hashes(
+            4,
+            10,
+            hashes.iter().map(|(index, hash)| (*index, hash.to_string())),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn derives_offsets_and_tail_length() {
+        let map = map(&[(2, "c"), (0, "a"), (1, "b")]);
+        assert_eq!(map.chunks[0].index, 0);
+        assert_eq!(map.chunks[2].offset, 8);
+        assert_eq!(map.chunks[2].length, 2);
+        assert!(map.is_complete());
+    }
+
+    #[test]
+    fn compares_chunks() {
+        let left = map(&[(0, "a"), (1, "b")]);
+        let right = map(&[(1, "x"), (2, "c")]);
+        let result = left.compare(&right).unwrap();
+        assert_eq!(result.differing, vec![1]);
+        assert_eq!(result.only_left, vec![0]);
+        assert_eq!(result.only_right, vec![2]);
+        assert!(!result.identical());
+    }
+
+    #[test]
+    fn collapses_differing_ranges() {
+        let left = map(&[(0, "a"), (1, "b"), (2, "c")]);
+        let right = map(&[(0, "x"), (1, "y"), (2, "c")]);
+        let ranges = left.compare(&right).unwrap().differing_ranges(&left);
+        assert_eq!(ranges.len(), 1);
+        assert_eq!((ranges[0].first_index, ranges[0].last_index), (0, 1));
+        assert_eq!((ranges[0].offset, ranges[0].length), (0, 8));
+    }
+
+    #[test]
+    fn merges_partial_maps() {
+        let mut left = map(&[(0, "a")]);
+        left.merge(&map(&[(0, "A"), (2, "c")])).unwrap();
+        assert_eq!(left.chunks.len(), 2);
+        assert!(left.merge(&map(&[(2, "z")])).is_err());
+    }
+
+    #[test]
+    fn reads_legacy_array() {
+        let data = br#"[{"index":0,"offset":0,"length":4,"sha256":"a"},
+                        {"index":1,"offset":4,"length":2,"sha256":"b"}]"#;
+        let map = parse_hashmap(data).unwrap();
+        assert_eq!(map.chunk_size, 4);
+        assert_eq!(map.total_bytes, 6);
+    }
+}
@@ -1,22 +1,193 @@
-use anyhow::{Context, Result};
-use phoenix_core::{now_utc_rfc3339, DeviceGraph, Disk, HostInfo, Partition};
-use std::collections::HashMap;
+use anyhow::{anyhow, Context, Result};
+use phoenix_core::{now_utc_rfc3339, DeviceGraph, Disk, HostInfo, Partition, StackedDevice, UsbPort};
+use std::collections::{HashMap, HashSet};
 use std::fs;
 use std::path::{Path, PathBuf};
 
+#[cfg(all(target_os = "linux", feature = "udisks2"))]
+pub mod udisks;
+
 pub fn build_device_graph() -> Result<DeviceGraph> {
+    if phoenix_core::mock::is_active() {
+        return Ok(phoenix_core::mock::device_graph()?);
+    }
     let host = HostInfo {
         os: "linux".to_string(),
         os_version: read_os_release(),
         machine: read_machine(),
+        os_edition: None,
+        os_display_version: None,
     };
     let disks = enumerate_disks()?;
-    Ok(DeviceGraph::new(host, disks, now_utc_rfc3339()))
+    let mut graph = DeviceGraph::new(host, disks, now_utc_rfc3339());
+    graph.stacks = enumerate_stacks();
+    Ok(graph)
+}
+
+/// Unmounts every mounted partition of `disk`, deepest mount points first.
+/// A busy filesystem is reported as an error instead of being lazily
+/// detached. Returns the mount points that were unmounted.
+pub fn unmount_disk_partitions(disk: &Disk) -> Result<Vec<String>> {
+    let mut mount_points: Vec<&String> = disk
+        .partitions
+        .iter()
+        .flat_map(|partition| partition.mount_points.iter())
+        .collect();
+    mount_points.sort_by_key(|mount| std::cmp::Reverse(mount.len()));
+    let mut unmounted = Vec::new();
+    for mount in mount_points {
+        unmount(mount)?;
+        unmounted.push(mount.clone());
+    }
+    Ok(unmounted)
+}
+
+/// Unmounts every mount point of one partition, leaving the rest of its
+/// disk mounted. Returns the mount points that were unmounted.
+pub fn unmount_partition(partition: &Partition) -> Result<Vec<String>> {
+    let mut mount_points: Vec<&String> = partition.mount_points.iter().collect();
+    mount_points.sort_by_key(|mount| std::cmp::Reverse(mount.len()));
+    let mut unmounted = Vec::new();
+    for mount in mount_points {
+        unmount(mount)?;
+        unmounted.push(mount.clone());
+    }
+    Ok(unmounted)
+}
+
+#[cfg(target_os = "linux")]
+fn unmount(mount_point: &str) -> Result<()> {
+    let path = std::ffi::CString::new(mount_point)?;
+    if unsafe { libc::umount2(path.as_ptr(), 0) } == 0 {
+        return Ok(());
+    }
+    let err = std::io::Error::last_os_error();
+    if err.raw_os_error() == Some(libc::EBUSY) {
+        return Err(anyhow!(
+            "{} is busy; close programs using it and retry",
+            mount_point
+        ));
+    }
+    Err(anyhow!("unmount {} failed: {}", mount_point, err))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn unmount(_mount_point: &str) -> Result<()> {
+    Err(anyhow!("unmount requires linux"))
+}
+
+/// Mounts a freshly formatted partition back at `mount_point`.
+#[cfg(target_os = "linux")]
+pub fn mount_partition(device: &Path, mount_point: &Path, fs_type: &str) -> Result<()> {
+    use std::os::unix::ffi::OsStrExt;
+    let source = std::ffi::CString::new(device.as_os_str().as_bytes())?;
+    let target = std::ffi::CString::new(mount_point.as_os_str().as_bytes())?;
+    let fs_type = std::ffi::CString::new(fs_type)?;
+    fs::create_dir_all(mount_point)
+        .with_context(|| format!("create mount point {}", mount_point.display()))?;
+    let rc = unsafe {
+        libc::mount(
+            source.as_ptr(),
+            target.as_ptr(),
+            fs_type.as_ptr(),
+            0,
+            std::ptr::null(),
+        )
+    };
+    if rc != 0 {
+        return Err(anyhow!(
+            "mount {} at {} failed: {}",
+            device.display(),
+            mount_point.display(),
+            std::io::Error::last_os_error()
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn mount_partition(_device: &Path, _mount_point: &Path, _fs_type: &str) -> Result<()> {
+    Err(anyhow!("mount requires linux"))
+}
+
+/// Asks the kernel to re-read the partition table of a whole disk after an
+/// image or a new table was written, as `blockdev --rereadpt` does, then
+/// waits for udev to create the partition nodes. Without it the kernel
+/// keeps the old layout and a later step mounts a stale partition. EBUSY,
+/// which udev's own probe of the changed disk causes for a moment, is
+/// retried. Returns the partitions the kernel now sees.
+#[cfg(target_os = "linux")]
+pub fn reread_partition_table(device: &Path) -> Result<Vec<String>> {
+    use std::os::unix::io::AsRawFd;
+    // _IO(0x12, 95); libc does not define it.
+    const BLKRRPART: libc::Ioctl = 0x125f;
+    const ATTEMPTS: u32 = 10;
+
+    let file = fs::File::open(device).with_context(|| format!("open {}", device.display()))?;
+    let mut attempt = 1;
+    loop {
+        if unsafe { libc::ioctl(file.as_raw_fd(), BLKRRPART) } == 0 {
+            break;
+        }
+        let err = std::io::Error::last_os_error();
+        if err.raw_os_error() != Some(libc::EBUSY) || attempt == ATTEMPTS {
+            return Err(anyhow!(
+                "re-read partition table of {} failed: {}",
+                device.display(),
+                err
+            ));
+        }
+        attempt += 1;
+        std::thread::sleep(std::time::Duration::from_millis(200));
+    }
+    drop(file);
+    // Best effort: udevadm is missing in some minimal images.
+    let _ = std::process::Command::new("udevadm")
+        .args(["settle", "--timeout=10"])
+        .status();
+
+    let name = device
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let mut partitions: Vec<String> = fs::read_dir(Path::new("/sys/class/block").join(&name))
+        .map(|entries| {
+            entries
+                .flatten()
+                .filter(|entry| entry.path().join("partition").exists())
+                .map(|entry| entry.file_name().to_string_lossy().to_string())
+                .collect()
+        })
+        .unwrap_or_default();
+    partitions.sort();
+    Ok(partitions)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn reread_partition_table(_device: &Path) -> Result<Vec<String>> {
+    Err(anyhow!("partition table re-read requires linux"))
 }
 
+/// Whether `device` (e.g. `/dev/sdb`) is a whole disk rather than a
+/// partition of one.
+pub fn is_whole_disk(device: &Path) -> bool {
+    let Some(name) = device.file_name() else {
+        return false;
+    };
+    let block = Path::new("/sys/class/block").join(name);
+    block.exists() && !block.join("partition").exists()
+}
+
+/// Mount points whose disk counts as the system disk: losing any of them
+/// takes the running system down, not just `/`.
+const SYSTEM_MOUNTS: &[&str] = &["/", "/boot", "/boot/efi", "/efi", "/usr", "/var", "/home"];
+
 fn enumerate_disks() -> Result<Vec<Disk>> {
     let mounts = read_mounts();
     let labels = read_labels();
+    let part_uuids = read_disk_links("/dev/disk/by-partuuid");
+    let fs_uuids = read_disk_links("/dev/disk/by-uuid");
+    let system_disks = system_disk_names();
     let mut disks = Vec::new();
     let entries = fs::read_dir("/sys/block").context("read /sys/block")?;
     for entry in entries {
@@ -31,27 +202,46 @@ fn enumerate_disks() -> Result<Vec<Disk>> {
         let removable = read_u64(entry.path().join("removable")).unwrap_or(0) == 1;
         let model = read_string(entry.path().join("device/model"))
             .unwrap_or_else(|| disk_name.clone());
-        let partitions = enumerate_partitions(&disk_name, entry.path(), &mounts, &labels)?;
-        let is_system_disk = partitions.iter().any(|partition| {
-            partition.mount_points.iter().any(|mount| mount == "/")
-        });
+        let partitions = enumerate_partitions(
+            &disk_name,
+            entry.path(),
+            &mounts,
+            &labels,
+            &part_uuids,
+            &fs_uuids,
+        )?;
+        let is_system_disk = system_disks.contains(&disk_name)
+            || partitions.iter().any(|partition| {
+                partition
+                    .mount_points
+                    .iter()
+                    .any(|mount| SYSTEM_MOUNTS.contains(&mount.as_str()))
+            });
+        let serial = read_serial(&entry.path());
+        let usb_port = read_usb_port(&entry.path());
         disks.push(Disk {
             id: disk_name,
             friendly_name: model,
+            serial,
             size_bytes,
             removable,
             is_system_disk,
             partitions,
+            usb_port,
+            history: None,
+            booted_esp: None,
         });
     }
     Ok(disks)
 }
 
 fn enumerate_partitions(
-    disk: &str,
+    _disk: &str,
     disk_path: PathBuf,
     mounts: &HashMap<String, Vec<MountInfo>>,
     labels: &HashMap<String, String>,
+    part_uuids: &HashMap<String, String>,
+    fs_uuids: &HashMap<String, String>,
 ) -> Result<Vec<Partition>> {
     let mut partitions = Vec::new();
     let entries = fs::read_dir(&disk_path).context("read disk entries")?;
@@ -65,21 +255,181 @@ fn enumerate_partitions(
         let size_bytes = read_u64(path.join("size"))
             .map(|sectors| sectors.saturating_mul(512))
             .unwrap_or(0);
+        let offset_bytes = read_u64(path.join("start")).map(|sectors| sectors.saturating_mul(512));
         let mount_infos = mounts.get(&part_name).cloned().unwrap_or_default();
         let mount_points = mount_infos.iter().map(|info| info.mount_point.clone()).collect();
         let fs_type = mount_infos.first().map(|info| info.fs_type.clone());
         let label = labels.get(&part_name).cloned();
+        let part_uuid = part_uuids.get(&part_name).cloned();
+        let fs_uuid = fs_uuids.get(&part_name).cloned();
         partitions.push(Partition {
             id: part_name,
             label,
             fs: fs_type,
             size_bytes,
             mount_points,
+            part_uuid,
+            fs_uuid,
+            offset_bytes,
         });
     }
     Ok(partitions)
 }
 
+/// Device-mapper (`dm-N`) and md (`mdN`) devices with the devices they are
+/// built on. Both live under `/sys/devices/virtual`, so `enumerate_disks`
+/// skips them.
+fn enumerate_stacks() -> Vec<StackedDevice> {
+    let mounts = read_mounts();
+    let Ok(entries) = fs::read_dir("/sys/block") else {
+        return Vec::new();
+    };
+    let mut stacks = Vec::new();
+    for entry in entries.flatten() {
+        let id = entry.file_name().to_string_lossy().to_string();
+        let sys = entry.path();
+        let (kind, name) = if id.starts_with("dm-") {
+            let uuid = read_string(sys.join("dm/uuid")).unwrap_or_default();
+            (dm_kind(&uuid).to_string(), read_string(sys.join("dm/name")))
+        } else if id.starts_with("md") {
+            let level = read_string(sys.join("md/level")).unwrap_or_else(|| "md".to_string());
+            (level, None)
+        } else {
+            continue;
+        };
+        let slaves = fs::read_dir(sys.join("slaves"))
+            .map(|entries| {
+                entries
+                    .flatten()
+                    .map(|entry| entry.file_name().to_string_lossy().to_string())
+                    .collect()
+            })
+            .unwrap_or_default();
+        // Mounted as /dev/mapper/<name> or /dev/<id>.
+        let mount_infos: Vec<&MountInfo> = [Some(&id), name.as_ref()]
+            .into_iter()
+            .flatten()
+            .filter_map(|key| mounts.get(key))
+            .flatten()
+            .collect();
+        stacks.push(StackedDevice {
+            size_bytes: read_u64(sys.join("size"))
+                .map(|sectors| sectors.saturating_mul(512))
+                .unwrap_or(0),
+            mount_points: mount_infos.iter().map(|info| info.mount_point.clone()).collect(),
+            fs: mount_infos.first().map(|info| info.fs_type.clone()),
+            id,
+            name,
+            kind,
+            slaves,
+        });
+    }
+    stacks.sort_by(|a, b| a.id.cmp(&b.id));
+    stacks
+}
+
+/// Target type from the dm uuid prefix that cryptsetup, LVM and multipathd
+/// set.
+fn dm_kind(uuid: &str) -> &'static str {
+    if uuid.starts_with("CRYPT-") {
+        "crypt"
+    } else if uuid.starts_with("LVM-") {
+        "lvm"
+    } else if uuid.starts_with("mpath-") {
+        "multipath"
+    } else {
+        "dm"
+    }
+}
+
+/// Whole disks (`sda`, `nvme0n1`) behind a system mount or an active swap
+/// partition, followed down through device-mapper holders so LVM, dm-crypt
+/// and md layouts are caught too.
+fn system_disk_names() -> HashSet<String> {
+    let mut pending: Vec<String> = SYSTEM_MOUNTS
+        .iter()
+        .filter_map(|mount| block_name_for_path(Path::new(mount)))
+        .collect();
+    // btrfs and other filesystems report an anonymous st_dev, so the
+    // mount table's device is used as well.
+    let mounts = fs::read_to_string("/proc/self/mounts").unwrap_or_default();
+    for line in mounts.lines() {
+        let mut parts = line.split_whitespace();
+        let (Some(device), Some(mount_point)) = (parts.next(), parts.next()) else {
+            continue;
+        };
+        if SYSTEM_MOUNTS.contains(&unescape_mount(mount_point).as_str()) {
+            pending.extend(block_name_for_device(device));
+        }
+    }
+    let swaps = fs::read_to_string("/proc/swaps").unwrap_or_default();
+    for line in swaps.lines().skip(1) {
+        let mut parts = line.split_whitespace();
+        if let (Some(device), Some("partition")) = (parts.next(), parts.next()) {
+            pending.extend(block_name_for_device(&unescape_mount(device)));
+        }
+    }
+
+    let mut disks = HashSet::new();
+    let mut seen = HashSet::new();
+    while let Some(name) = pending.pop() {
+        if !seen.insert(name.clone()) {
+            continue;
+        }
+        let sys = Path::new("/sys/class/block").join(&name);
+        let slaves: Vec<String> = fs::read_dir(sys.join("slaves"))
+            .map(|entries| {
+                entries
+                    .flatten()
+                    .map(|entry| entry.file_name().to_string_lossy().to_string())
+                    .collect()
+            })
+            .unwrap_or_default();
+        if !slaves.is_empty() {
+            pending.extend(slaves);
+        } else if sys.join("partition").exists() {
+            let parent = fs::canonicalize(&sys).ok().and_then(|path| {
+                path.parent()
+                    .and_then(|parent| parent.file_name())
+                    .map(|parent| parent.to_string_lossy().to_string())
+            });
+            disks.extend(parent);
+        } else {
+            disks.insert(name);
+        }
+    }
+    disks
+}
+
+/// `/dev/mapper/vg-root` -> `dm-0`, `/dev/sda2` -> `sda2`.
+fn block_name_for_device(device: &str) -> Option<String> {
+    if !device.starts_with("/dev/") {
+        return None;
+    }
+    let path = fs::canonicalize(device).ok()?;
+    path.file_name().map(|name| name.to_string_lossy().to_string())
+}
+
+/// Block device holding the filesystem at `path`, from its `st_dev`.
+#[cfg(target_os = "linux")]
+fn block_name_for_path(path: &Path) -> Option<String> {
+    use std::os::unix::fs::MetadataExt;
+
+    let dev = fs::metadata(path).ok()?.dev();
+    let major = ((dev >> 8) & 0xfff) | ((dev >> 32) & !0xfff);
+    let minor = (dev & 0xff) | ((dev >> 12) & !0xff);
+    if major == 0 {
+        return None;
+    }
+    let target = fs::canonicalize(format!("/sys/dev/block/{}:{}", major, minor)).ok()?;
+    target.file_name().map(|name| name.to_string_lossy().to_string())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn block_name_for_path(_path: &Path) -> Option<String> {
+    None
+}
+
 #[derive(Debug, Clone)]
 struct MountInfo {
     mount_point: String,
@@ -120,18 +470,24 @@ fn read_mounts() -> HashMap<String, Vec<MountInfo>> {
 }
 
 fn read_labels() -> HashMap<String, String> {
-    let mut labels = HashMap::new();
-    let path = Path::new("/dev/disk/by-label");
+    read_disk_links("/dev/disk/by-label")
+}
+
+/// Maps block device names to the link names in a `/dev/disk/by-*`
+/// directory (`by-label`, `by-uuid`, `by-partuuid`).
+fn read_disk_links(dir: &str) -> HashMap<String, String> {
+    let mut links = HashMap::new();
+    let path = Path::new(dir);
     if let Ok(entries) = fs::read_dir(path) {
         for entry in entries.flatten() {
             if let Ok(target) = fs::read_link(entry.path()) {
                 if let Some(name) = target.file_name().and_then(|v| v.to_str()) {
-                    labels.insert(name.to_string(), entry.file_name().to_string_lossy().to_string());
+                    links.insert(name.to_string(), entry.file_name().to_string_lossy().to_string());
                 }
             }
         }
     }
-    labels
+    links
 }
 
 fn read_os_release() -> String {
@@ -153,7 +509,7 @@ fn read_os_release() -> String {
 }
 
 fn trim_os_value(line: &str) -> String {
-    let value = line.splitn(2, '=').nth(1).unwrap_or("").trim();
+    let value = line.split_once('=').map(|x| x.1).unwrap_or("").trim();
     value.trim_matches('"').to_string()
 }
 
@@ -168,6 +524,52 @@ fn read_machine() -> String {
     }
 }
 
+/// NVMe and MMC expose the serial in sysfs; USB and SATA disks only have it
+/// in the udev database (`ID_SERIAL_SHORT`).
+fn read_serial(disk_path: &Path) -> Option<String> {
+    if let Some(serial) = read_string(disk_path.join("device/serial")).filter(|s| !s.is_empty()) {
+        return Some(serial);
+    }
+    let dev = read_string(disk_path.join("dev"))?;
+    let udev = fs::read_to_string(format!("/run/udev/data/b{}", dev)).ok()?;
+    let lookup = |key: &str| {
+        udev.lines()
+            .find_map(|line| line.strip_prefix(key))
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty())
+    };
+    lookup("E:ID_SERIAL_SHORT=").or_else(|| lookup("E:ID_SERIAL="))
+}
+
+/// The USB device a disk hangs off, from its sysfs path:
+/// `.../usb2/2-1/2-1.3/2-1.3:1.0/host6/.../block/sdb` is bus 2, port 1 of
+/// the root hub, then port 3 of the hub behind it.
+fn read_usb_port(disk_path: &Path) -> Option<UsbPort> {
+    let device = fs::canonicalize(disk_path).ok()?;
+    let path = device
+        .iter()
+        .filter_map(|component| component.to_str())
+        .rfind(|component| is_usb_port_path(component))?
+        .to_string();
+    let (bus, ports) = path.split_once('-')?;
+    Some(UsbPort {
+        bus: bus.parse().ok(),
+        ports: ports.split('.').filter_map(|port| port.parse().ok()).collect(),
+        path,
+        label: None,
+    })
+}
+
+/// `<bus>-<port>[.<port>...]`, as opposed to `usb2` or the interface
+/// `2-1.3:1.0`.
+fn is_usb_port_path(name: &str) -> bool {
+    let Some((bus, ports)) = name.split_once('-') else {
+        return false;
+    };
+    let digits = |part: &str| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit());
+    digits(bus) && ports.split('.').all(digits)
+}
+
 fn read_string(path: impl AsRef<Path>) -> Option<String> {
     fs::read_to_string(path).ok().map(|value| value.trim().to_string())
 }
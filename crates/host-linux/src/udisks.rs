@@ -0,0 +1,117 @@
+use anyhow::{anyhow, Context, Result};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use zbus::blocking::{Connection, Proxy};
+use zbus::zvariant::{OwnedObjectPath, Value};
+
+const UDISKS_SERVICE: &str = "org.freedesktop.UDisks2";
+const MANAGER_PATH: &str = "/org/freedesktop/UDisks2/Manager";
+const MANAGER_IFACE: &str = "org.freedesktop.UDisks2.Manager";
+const BLOCK_IFACE: &str = "org.freedesktop.UDisks2.Block";
+const FILESYSTEM_IFACE: &str = "org.freedesktop.UDisks2.Filesystem";
+const DRIVE_IFACE: &str = "org.freedesktop.UDisks2.Drive";
+
+/// Mount, unmount, format and power-off through udisks2 on the system bus.
+/// Authorization goes through polkit, so an unprivileged desktop user gets
+/// the usual authentication prompt instead of needing sudo.
+pub struct UdisksClient {
+    connection: Connection,
+}
+
+impl UdisksClient {
+    pub fn connect() -> Result<Self> {
+        let connection = Connection::system().context("connect to system D-Bus")?;
+        Ok(Self { connection })
+    }
+
+    /// Resolves a device node such as `/dev/sdb1` to its udisks block object.
+    pub fn block_object(&self, device: &Path) -> Result<OwnedObjectPath> {
+        let manager = self.proxy(MANAGER_PATH, MANAGER_IFACE)?;
+        let device_str = device.to_string_lossy().to_string();
+        let mut spec = HashMap::new();
+        spec.insert("path", Value::from(device_str.as_str()));
+        let objects: Vec<OwnedObjectPath> = manager
+            .call("ResolveDevice", &(spec, options()))
+            .with_context(|| format!("udisks ResolveDevice {}", device.display()))?;
+        objects
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow!("udisks does not know {}", device.display()))
+    }
+
+    /// Mounts the filesystem on `device` and returns the mount point udisks
+    /// chose (normally under `/run/media/<user>`).
+    pub fn mount(&self, device: &Path) -> Result<PathBuf> {
+        let object = self.block_object(device)?;
+        let filesystem = self.proxy(object.as_str(), FILESYSTEM_IFACE)?;
+        let mount_point: String = filesystem
+            .call("Mount", &(options(),))
+            .map_err(|err| dbus_error("mount", device, err))?;
+        Ok(PathBuf::from(mount_point))
+    }
+
+    pub fn unmount(&self, device: &Path) -> Result<()> {
+        let object = self.block_object(device)?;
+        let filesystem = self.proxy(object.as_str(), FILESYSTEM_IFACE)?;
+        filesystem
+            .call::<_, _, ()>("Unmount", &(options(),))
+            .map_err(|err| dbus_error("unmount", device, err))
+    }
+
+    /// Creates a filesystem (`vfat`, `exfat`, `ntfs`, ...) on `device`,
+    /// replacing whatever was there.
+    pub fn format(&self, device: &Path, fs_type: &str, label: Option<&str>) -> Result<()> {
+        phoenix_safety::ensure_writable("format")?;
+        let object = self.block_object(device)?;
+        let block = self.proxy(object.as_str(), BLOCK_IFACE)?;
+        let mut opts = options();
+        if let Some(label) = label {
+            opts.insert("label", Value::from(label.to_string()));
+        }
+        opts.insert("update-partition-type", Value::from(true));
+        block
+            .call::<_, _, ()>("Format", &(fs_type, opts))
+            .map_err(|err| dbus_error("format", device, err))
+    }
+
+    /// Powers off the drive that `device` belongs to so it can be removed.
+    pub fn power_off(&self, device: &Path) -> Result<()> {
+        let object = self.block_object(device)?;
+        let block = self.proxy(object.as_str(), BLOCK_IFACE)?;
+        let drive: OwnedObjectPath = block
+            .get_property("Drive")
+            .with_context(|| format!("udisks drive for {}", device.display()))?;
+        if drive.as_str() == "/" {
+            return Err(anyhow!("{} has no backing drive", device.display()));
+        }
+        let drive = self.proxy(drive.as_str(), DRIVE_IFACE)?;
+        drive
+            .call::<_, _, ()>("PowerOff", &(options(),))
+            .map_err(|err| dbus_error("power off", device, err))
+    }
+
+    fn proxy(&self, path: &str, interface: &'static str) -> Result<Proxy<'_>> {
+        Proxy::new(&self.connection, UDISKS_SERVICE, path.to_string(), interface)
+            .with_context(|| format!("udisks proxy {} {}", path, interface))
+    }
+}
+
+fn options() -> HashMap<&'static str, Value<'static>> {
+    let mut options = HashMap::new();
+    options.insert("auth.no_user_interaction", Value::from(false));
+    options
+}
+
+fn dbus_error(action: &str, device: &Path, err: zbus::Error) -> anyhow::Error {
+    let message = err.to_string();
+    if message.contains("NotAuthorized") {
+        return anyhow!("not authorized to {} {}: {}", action, device.display(), message);
+    }
+    if message.contains("DeviceBusy") || message.contains("busy") {
+        return anyhow!(
+            "{} is busy; close programs using it and retry",
+            device.display()
+        );
+    }
+    anyhow!("udisks {} {} failed: {}", action, device.display(), message)
+}
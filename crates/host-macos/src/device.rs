@@ -0,0 +1,327 @@
+use anyhow::{anyhow, Result};
+use std::fs::{File, OpenOptions};
+use std::path::{Path, PathBuf};
+
+/// A raw device handle plus the DiskArbitration claim that keeps the
+/// volumes from being remounted while it is in use. Dropping it releases the
+/// claim.
+pub struct ExclusiveDevice {
+    pub file: File,
+    pub via_authopen: bool,
+    _claim: DiskClaim,
+}
+
+/// Unmounts and claims the whole disk behind `device`, then opens it. When
+/// the process lacks permission the descriptor is obtained through
+/// `authopen`, which shows the standard macOS authorization prompt.
+pub fn open_device_exclusive(device: &Path, write: bool) -> Result<ExclusiveDevice> {
+    if write {
+        phoenix_safety::ensure_writable("device write")?;
+    }
+    let bsd_name = whole_disk_bsd_name(device)?;
+    let claim = DiskClaim::acquire(&bsd_name)?;
+    match OpenOptions::new().read(true).write(write).open(device) {
+        Ok(file) => Ok(ExclusiveDevice {
+            file,
+            via_authopen: false,
+            _claim: claim,
+        }),
+        Err(err) if err.kind() == std::io::ErrorKind::PermissionDenied => {
+            let file = authopen(device, write)?;
+            Ok(ExclusiveDevice {
+                file,
+                via_authopen: true,
+                _claim: claim,
+            })
+        }
+        Err(err) => Err(anyhow!("open {} failed: {}", device.display(), err)),
+    }
+}
+
+/// `/dev/rdisk4s1` -> `disk4`.
+pub fn whole_disk_bsd_name(device: &Path) -> Result<String> {
+    let name = device
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .ok_or_else(|| anyhow!("invalid device path {}", device.display()))?;
+    let name = name.strip_prefix('r').unwrap_or(&name);
+    let rest = name
+        .strip_prefix("disk")
+        .ok_or_else(|| anyhow!("not a disk device: {}", device.display()))?;
+    let digits: String = rest.chars().take_while(|ch| ch.is_ascii_digit()).collect();
+    if digits.is_empty() {
+        return Err(anyhow!("not a disk device: {}", device.display()));
+    }
+    Ok(format!("disk{}", digits))
+}
+
+/// `/dev/disk4` -> `/dev/rdisk4`. The raw node bypasses the buffer cache and
+/// writes at the device's nominal speed. Paths that already name a raw node,
+/// or whose raw node does not exist, are returned unchanged.
+pub fn raw_device_path(device: &Path) -> PathBuf {
+    let (Some(parent), Some(name)) = (device.parent(), device.file_name()) else {
+        return device.to_path_buf();
+    };
+    let name = name.to_string_lossy();
+    if !name.starts_with("disk") {
+        return device.to_path_buf();
+    }
+    let raw = parent.join(format!("r{}", name));
+    if raw.exists() {
+        raw
+    } else {
+        device.to_path_buf()
+    }
+}
+
+#[cfg(target_os = "macos")]
+pub fn authopen(device: &Path, write: bool) -> Result<File> {
+    use anyhow::Context;
+    use std::os::fd::{FromRawFd, OwnedFd};
+    use std::process::{Command, Stdio};
+
+    let mut fds = [0i32; 2];
+    if unsafe { libc::socketpair(libc::AF_UNIX, libc::SOCK_STREAM, 0, fds.as_mut_ptr()) } != 0 {
+        return Err(anyhow!(
+            "socketpair failed: {}",
+            std::io::Error::last_os_error()
+        ));
+    }
+    let parent = unsafe { OwnedFd::from_raw_fd(fds[0]) };
+    let child = unsafe { OwnedFd::from_raw_fd(fds[1]) };
+
+    let flags = if write { libc::O_RDWR } else { libc::O_RDONLY };
+    let mut process = Command::new("/usr/libexec/authopen")
+        .arg("-stdoutpipe")
+        .arg("-o")
+        .arg(flags.to_string())
+        .arg(device)
+        .stdout(Stdio::from(child))
+        .spawn()
+        .context("run authopen")?;
+
+    let received = receive_fd(&parent);
+    let status = process.wait().context("wait for authopen")?;
+    let fd = received?;
+    if !status.success() {
+        return Err(anyhow!(
+            "authopen {} was denied or failed ({})",
+            device.display(),
+            status
+        ));
+    }
+    Ok(unsafe { File::from_raw_fd(fd) })
+}
+
+#[cfg(not(target_os = "macos"))]
+pub fn authopen(_device: &Path, _write: bool) -> Result<File> {
+    Err(anyhow!("authopen requires macOS"))
+}
+
+/// Reads the descriptor authopen sends over the socket as SCM_RIGHTS.
+#[cfg(target_os = "macos")]
+fn receive_fd(socket: &std::os::fd::OwnedFd) -> Result<i32> {
+    use std::os::fd::AsRawFd;
+
+    let mut data = [0u8; 64];
+    let mut iov = libc::iovec {
+        iov_base: data.as_mut_ptr() as *mut libc::c_void,
+        iov_len: data.len(),
+    };
+    let space = unsafe { libc::CMSG_SPACE(std::mem::size_of::<i32>() as u32) } as usize;
+    let mut control = vec![0u8; space];
+    let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    msg.msg_controllen = space as _;
+
+    let read = unsafe { libc::recvmsg(socket.as_raw_fd(), &mut msg, 0) };
+    if read < 0 {
+        return Err(anyhow!(
+            "recvmsg from authopen failed: {}",
+            std::io::Error::last_os_error()
+        ));
+    }
+    let header = unsafe { libc::CMSG_FIRSTHDR(&msg) };
+    if header.is_null() {
+        return Err(anyhow!("authopen did not return a descriptor"));
+    }
+    unsafe {
+        if (*header).cmsg_level != libc::SOL_SOCKET || (*header).cmsg_type != libc::SCM_RIGHTS {
+            return Err(anyhow!("unexpected control message from authopen"));
+        }
+        Ok(std::ptr::read_unaligned(libc::CMSG_DATA(header) as *const i32))
+    }
+}
+
+#[cfg(target_os = "macos")]
+pub use arbitration::DiskClaim;
+
+#[cfg(not(target_os = "macos"))]
+pub struct DiskClaim;
+
+#[cfg(not(target_os = "macos"))]
+impl DiskClaim {
+    pub fn acquire(_bsd_name: &str) -> Result<Self> {
+        Err(anyhow!("DiskArbitration requires macOS"))
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod arbitration {
+    use anyhow::{anyhow, Result};
+    use std::cell::Cell;
+    use std::ffi::{c_void, CString};
+    use std::time::{Duration, Instant};
+
+    type CFTypeRef = *const c_void;
+    type DASessionRef = *mut c_void;
+    type DADiskRef = *mut c_void;
+    type DADissenterRef = *mut c_void;
+    type DACallback = extern "C" fn(DADiskRef, DADissenterRef, *mut c_void);
+
+    const UNMOUNT_OPTION_WHOLE: u32 = 0x0000_0001;
+    const CLAIM_OPTION_DEFAULT: u32 = 0;
+    const CALLBACK_TIMEOUT: Duration = Duration::from_secs(30);
+
+    #[link(name = "CoreFoundation", kind = "framework")]
+    extern "C" {
+        static kCFRunLoopDefaultMode: CFTypeRef;
+        fn CFRunLoopGetCurrent() -> *mut c_void;
+        fn CFRunLoopRunInMode(mode: CFTypeRef, seconds: f64, return_after: u8) -> i32;
+        fn CFRelease(value: CFTypeRef);
+    }
+
+    #[link(name = "DiskArbitration", kind = "framework")]
+    extern "C" {
+        fn DASessionCreate(allocator: CFTypeRef) -> DASessionRef;
+        fn DASessionScheduleWithRunLoop(session: DASessionRef, run_loop: *mut c_void, mode: CFTypeRef);
+        fn DASessionUnscheduleFromRunLoop(
+            session: DASessionRef,
+            run_loop: *mut c_void,
+            mode: CFTypeRef,
+        );
+        fn DADiskCreateFromBSDName(
+            allocator: CFTypeRef,
+            session: DASessionRef,
+            name: *const libc::c_char,
+        ) -> DADiskRef;
+        fn DADiskUnmount(disk: DADiskRef, options: u32, callback: DACallback, context: *mut c_void);
+        fn DADiskClaim(
+            disk: DADiskRef,
+            options: u32,
+            release: *const c_void,
+            release_context: *mut c_void,
+            callback: DACallback,
+            context: *mut c_void,
+        );
+        fn DADiskUnclaim(disk: DADiskRef);
+        fn DADissenterGetStatus(dissenter: DADissenterRef) -> i32;
+    }
+
+    #[derive(Default)]
+    struct Outcome {
+        done: Cell<bool>,
+        status: Cell<i32>,
+    }
+
+    extern "C" fn on_complete(_disk: DADiskRef, dissenter: DADissenterRef, context: *mut c_void) {
+        let outcome = unsafe { &*(context as *const Outcome) };
+        outcome.done.set(true);
+        if !dissenter.is_null() {
+            outcome.status.set(unsafe { DADissenterGetStatus(dissenter) });
+        }
+    }
+
+    /// DiskArbitration session holding an unmount + claim on a whole disk.
+    pub struct DiskClaim {
+        session: DASessionRef,
+        disk: DADiskRef,
+    }
+
+    impl DiskClaim {
+        pub fn acquire(bsd_name: &str) -> Result<Self> {
+            let name = CString::new(bsd_name)?;
+            let session = unsafe { DASessionCreate(std::ptr::null()) };
+            if session.is_null() {
+                return Err(anyhow!("DASessionCreate failed"));
+            }
+            unsafe {
+                DASessionScheduleWithRunLoop(session, CFRunLoopGetCurrent(), kCFRunLoopDefaultMode)
+            };
+            let disk = unsafe { DADiskCreateFromBSDName(std::ptr::null(), session, name.as_ptr()) };
+            let claim = Self { session, disk };
+            if disk.is_null() {
+                return Err(anyhow!("DiskArbitration does not know {}", bsd_name));
+            }
+
+            let unmount = Outcome::default();
+            unsafe {
+                DADiskUnmount(
+                    disk,
+                    UNMOUNT_OPTION_WHOLE,
+                    on_complete,
+                    &unmount as *const Outcome as *mut c_void,
+                )
+            };
+            wait_for(&unmount, "unmount", bsd_name)?;
+            if unmount.status.get() != 0 {
+                return Err(anyhow!(
+                    "{} is busy; unmount refused (status 0x{:08x})",
+                    bsd_name,
+                    unmount.status.get()
+                ));
+            }
+
+            let claimed = Outcome::default();
+            unsafe {
+                DADiskClaim(
+                    disk,
+                    CLAIM_OPTION_DEFAULT,
+                    std::ptr::null(),
+                    std::ptr::null_mut(),
+                    on_complete,
+                    &claimed as *const Outcome as *mut c_void,
+                )
+            };
+            wait_for(&claimed, "claim", bsd_name)?;
+            if claimed.status.get() != 0 {
+                return Err(anyhow!(
+                    "{} is claimed by another process (status 0x{:08x})",
+                    bsd_name,
+                    claimed.status.get()
+                ));
+            }
+            Ok(claim)
+        }
+    }
+
+    fn wait_for(outcome: &Outcome, action: &str, bsd_name: &str) -> Result<()> {
+        let start = Instant::now();
+        while !outcome.done.get() {
+            if start.elapsed() > CALLBACK_TIMEOUT {
+                return Err(anyhow!("timed out waiting for {} of {}", action, bsd_name));
+            }
+            unsafe { CFRunLoopRunInMode(kCFRunLoopDefaultMode, 0.1, 1) };
+        }
+        Ok(())
+    }
+
+    impl Drop for DiskClaim {
+        fn drop(&mut self) {
+            unsafe {
+                if !self.disk.is_null() {
+                    DADiskUnclaim(self.disk);
+                    CFRelease(self.disk as CFTypeRef);
+                }
+                DASessionUnscheduleFromRunLoop(
+                    self.session,
+                    CFRunLoopGetCurrent(),
+                    kCFRunLoopDefaultMode,
+                );
+                CFRelease(self.session as CFTypeRef);
+            }
+        }
+    }
+}
@@ -1,13 +1,24 @@
 use anyhow::{anyhow, Result};
-use phoenix_core::{now_utc_rfc3339, DeviceGraph, Disk, HostInfo, Partition};
+use phoenix_core::DeviceGraph;
+#[cfg(target_os = "macos")]
+use phoenix_core::{now_utc_rfc3339, Disk, HostInfo, Partition, UsbPort};
+
+pub mod device;
+
+pub use device::{open_device_exclusive, raw_device_path, ExclusiveDevice};
 
 pub fn build_device_graph() -> Result<DeviceGraph> {
+    if phoenix_core::mock::is_active() {
+        return Ok(phoenix_core::mock::device_graph()?);
+    }
     #[cfg(target_os = "macos")]
     {
         let host = HostInfo {
             os: "macos".to_string(),
             os_version: read_os_version(),
             machine: read_machine(),
+            os_edition: None,
+            os_display_version: None,
         };
         let disks = enumerate_disks()?;
         Ok(DeviceGraph::new(host, disks, now_utc_rfc3339()))
@@ -19,9 +30,66 @@ pub fn build_device_graph() -> Result<DeviceGraph> {
     }
 }
 
+/// Unmounts every volume on `disk_id` (e.g. `disk4`) with
+/// `diskutil unmountDisk`. A dissenting or busy volume is reported as an
+/// error rather than force-unmounted.
+#[cfg(target_os = "macos")]
+pub fn unmount_disk(disk_id: &str) -> Result<()> {
+    let device = format!("/dev/{}", disk_id);
+    let output = diskutil(&["unmountDisk", &device])?;
+    if output.status.success() {
+        return Ok(());
+    }
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if stderr.contains("busy") || stderr.contains("dissent") {
+        return Err(anyhow!(
+            "{} is busy; close programs using it and retry ({})",
+            device,
+            stderr.trim()
+        ));
+    }
+    Err(anyhow!("diskutil unmountDisk {} failed: {}", device, stderr.trim()))
+}
+
+#[cfg(not(target_os = "macos"))]
+pub fn unmount_disk(_disk_id: &str) -> Result<()> {
+    Err(anyhow!("phoenix-host-macos requires macOS"))
+}
+
+/// Mounts a freshly formatted partition back at `mount_point`.
+#[cfg(target_os = "macos")]
+pub fn mount_volume(device: &std::path::Path, mount_point: &std::path::Path) -> Result<()> {
+    let device = device.to_string_lossy();
+    let mount_point = mount_point.to_string_lossy();
+    let output = diskutil(&["mount", "-mountPoint", &mount_point, &device])?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "diskutil mount {} failed: {}",
+            device,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+    }
+}
+
+#[cfg(not(target_os = "macos"))]
+pub fn mount_volume(_device: &std::path::Path, _mount_point: &std::path::Path) -> Result<()> {
+    Err(anyhow!("phoenix-host-macos requires macOS"))
+}
+
+#[cfg(target_os = "macos")]
+fn diskutil(args: &[&str]) -> Result<std::process::Output> {
+    std::process::Command::new("/usr/sbin/diskutil")
+        .args(args)
+        .output()
+        .map_err(|err| anyhow!("run diskutil: {}", err))
+}
+
 #[cfg(target_os = "macos")]
 fn enumerate_disks() -> Result<Vec<Disk>> {
     let mounts = read_mounts()?;
+    let usb_ports = read_usb_ports();
     let mut disks = std::collections::HashMap::new();
     for mount in mounts {
         if !mount.device.starts_with("/dev/") {
@@ -40,18 +108,26 @@ fn enumerate_disks() -> Result<Vec<Disk>> {
         let entry = disks.entry(disk_id.clone()).or_insert_with(|| Disk {
             id: disk_id.clone(),
             friendly_name: disk_id.clone(),
+            serial: None,
             size_bytes: 0,
             removable: false,
             is_system_disk: false,
             partitions: Vec::new(),
+            usb_port: usb_ports.get(&disk_id).cloned(),
+            history: None,
+            booted_esp: None,
         });
 
+        let (part_uuid, fs_uuid, offset_bytes) = read_partition_info(&mount.device);
         let partition = Partition {
             id: device_name,
             label: None,
             fs: Some(mount.fs_type.clone()),
             size_bytes: mount.size_bytes,
             mount_points: vec![mount.mount_point.clone()],
+            part_uuid,
+            fs_uuid,
+            offset_bytes,
         };
         entry.size_bytes = entry.size_bytes.saturating_add(mount.size_bytes);
         if mount.mount_point == "/" {
@@ -120,11 +196,90 @@ fn read_mounts() -> Result<Vec<MountEntry>> {
     Ok(entries)
 }
 
+/// `Disk / Partition UUID`, `Volume UUID` and `Partition Offset` from
+/// `diskutil info`.
+#[cfg(target_os = "macos")]
+fn read_partition_info(device: &str) -> (Option<String>, Option<String>, Option<u64>) {
+    let Ok(output) = diskutil(&["info", device]) else {
+        return (None, None, None);
+    };
+    let text = String::from_utf8_lossy(&output.stdout);
+    let field = |key: &str| {
+        text.lines().find_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            let value = value.trim();
+            (name.trim() == key && !value.is_empty()).then(|| value.to_string())
+        })
+    };
+    // `Partition Offset: 209735680 Bytes (409640 512-Byte-Device-Blocks)`
+    let offset = field("Partition Offset")
+        .and_then(|value| value.split_whitespace().next()?.parse().ok());
+    (field("Disk / Partition UUID"), field("Volume UUID"), offset)
+}
+
+/// USB port of each whole disk (`disk4`), from the `locationID` of the
+/// USB device whose IORegistry subtree holds its BSD name.
+#[cfg(target_os = "macos")]
+fn read_usb_ports() -> std::collections::HashMap<String, UsbPort> {
+    let mut ports: std::collections::HashMap<String, UsbPort> = std::collections::HashMap::new();
+    let Ok(output) = std::process::Command::new("/usr/sbin/ioreg")
+        .args(["-r", "-c", "IOUSBHostDevice", "-l", "-w0"])
+        .output()
+    else {
+        return ports;
+    };
+    let text = String::from_utf8_lossy(&output.stdout);
+    // `-r` prints each USB device as a top-level `+-o` subtree; a hub's
+    // subtree repeats the devices behind it, so the deepest port wins.
+    let mut location: Option<u32> = None;
+    for line in text.lines() {
+        if line.starts_with("+-o") {
+            location = None;
+            continue;
+        }
+        let Some((key, value)) = line.trim_start_matches([' ', '|', '+', '-', 'o']).split_once(" = ")
+        else {
+            continue;
+        };
+        let value = value.trim().trim_matches('"');
+        match key.trim().trim_matches('"') {
+            "locationID" if location.is_none() => location = value.parse().ok(),
+            "BSD Name" => {
+                let Some(id) = location else {
+                    continue;
+                };
+                let port = usb_port_from_location(id);
+                let disk = split_disk_id(value);
+                if !ports.get(&disk).is_some_and(|known| known.ports.len() >= port.ports.len()) {
+                    ports.insert(disk, port);
+                }
+            }
+            _ => {}
+        }
+    }
+    ports
+}
+
+/// `0x14130000`: bus 0x14, then one nibble per hub port until a zero.
+#[cfg(target_os = "macos")]
+fn usb_port_from_location(id: u32) -> UsbPort {
+    let ports = (0..6)
+        .map(|nibble| (id >> (20 - nibble * 4)) & 0xf)
+        .take_while(|port| *port != 0)
+        .collect();
+    UsbPort {
+        path: format!("0x{:08x}", id),
+        bus: Some(id >> 24),
+        ports,
+        label: None,
+    }
+}
+
 #[cfg(target_os = "macos")]
 fn split_disk_id(device_name: &str) -> String {
-    if device_name.starts_with("disk") {
-        if let Some(idx) = device_name.find('s') {
-            return device_name[..idx].to_string();
+    if let Some(rest) = device_name.strip_prefix("disk") {
+        if let Some(idx) = rest.find('s') {
+            return device_name[..4 + idx].to_string();
         }
     }
     device_name.to_string()
@@ -1,28 +1,50 @@
-use anyhow::{anyhow, Result};
-use std::collections::HashSet;
+use anyhow::{anyhow, Context, Result};
 use std::ffi::c_void;
+use std::fs::OpenOptions;
+use std::io::{Seek, SeekFrom, Write};
+use std::os::windows::fs::OpenOptionsExt;
 use std::sync::atomic::{AtomicI8, Ordering};
 use std::time::{Duration, Instant};
 
 use windows::core::{GUID, PCSTR, PCWSTR};
 use windows::Win32::Foundation::{CloseHandle, BOOL, HANDLE, INVALID_HANDLE_VALUE};
 use windows::Win32::Storage::FileSystem::{
-    CreateFileW, GetLogicalDrives, FILE_ATTRIBUTE_NORMAL, FILE_GENERIC_READ, FILE_GENERIC_WRITE,
+    CreateFileW, FILE_ATTRIBUTE_NORMAL, FILE_GENERIC_READ, FILE_GENERIC_WRITE,
     FILE_SHARE_READ, FILE_SHARE_WRITE, OPEN_EXISTING,
 };
 use windows::Win32::System::Ioctl::{
     DeviceIoControl, CREATE_DISK, CREATE_DISK_GPT, DRIVE_LAYOUT_INFORMATION_EX,
-    DRIVE_LAYOUT_INFORMATION_GPT, IOCTL_DISK_CREATE_DISK, IOCTL_DISK_SET_DRIVE_LAYOUT_EX,
+    DRIVE_LAYOUT_INFORMATION_GPT, FSCTL_DISMOUNT_VOLUME, FSCTL_LOCK_VOLUME,
+    GET_LENGTH_INFORMATION, IOCTL_DISK_CREATE_DISK, IOCTL_DISK_DELETE_DRIVE_LAYOUT, IOCTL_DISK_GET_LENGTH_INFO, IOCTL_DISK_SET_DRIVE_LAYOUT_EX,
     IOCTL_DISK_UPDATE_PROPERTIES, PARTITION_INFORMATION_EX, PARTITION_INFORMATION_GPT,
-    PARTITION_STYLE_GPT,
+    PARTITION_STYLE_GPT, GPT_ATTRIBUTES,
 };
 use windows::Win32::System::LibraryLoader::{FreeLibrary, GetProcAddress, LoadLibraryW};
+use phoenix_partition::plan::GPT_ENTRY_COUNT;
+use phoenix_partition::{
+    gpt_name_units, verify_device_partition_tables, PartitionPlan, DEFAULT_SECTOR_SIZE,
+};
+use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+use crate::volumes;
+
+/// Largest FAT32 volume `FormatEx` will create; bigger ones are written
+/// by `phoenix_fs_fat32`.
+pub const FORMATEX_FAT32_MAX_BYTES: u64 = 32 * 1024 * 1024 * 1024;
+
 const FMIFS_DONE: u32 = 0;
 const FMIFS_HARDDISK: u32 = 0x0C;
 static FORMAT_RESULT: AtomicI8 = AtomicI8::new(-1);
 
+/// Bytes zeroed at each end of the disk by `clean_disk`: the MBR, the
+/// primary GPT and any boot sector at the start, the backup GPT at the end.
+pub const CLEAN_ZERO_BYTES: u64 = 1024 * 1024;
+/// Layout attempts after a clean before `create_gpt_partitions` gives up.
+const CLEAN_LAYOUT_ATTEMPTS: u32 = 3;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum FileSystem {
     Fat32,
     Ntfs,
@@ -48,131 +70,353 @@ pub fn parse_filesystem(value: &str) -> Option<FileSystem> {
     }
 }
 
-pub fn logical_drive_letters() -> Vec<char> {
-    unsafe {
-        let mask = GetLogicalDrives();
-        let mut letters = Vec::new();
-        for (idx, letter) in ('A'..='Z').enumerate() {
-            if mask & (1u32 << idx) != 0 {
-                letters.push(letter);
-            }
-        }
-        letters
-    }
-}
-
+/// Writes `plan` to the disk and formats its one mountable partition.
+/// Returns its drive root, or its `\\?\Volume{GUID}\` path when no
+/// drive letter is free. `cluster_bytes` of `None` lets Windows choose.
 pub fn prepare_usb_disk(
     disk_number: u32,
-    disk_size: u64,
+    plan: &PartitionPlan,
     fs: FileSystem,
     label: Option<&str>,
-) -> Result<char> {
-    let before = logical_drive_letters();
-    create_single_gpt_partition(disk_number, disk_size, label)?;
-    let letter = wait_for_new_drive_letter(&before, Duration::from_secs(15))?;
-    format_volume(letter, fs, label, true)?;
-    Ok(letter)
+    cluster_bytes: Option<u32>,
+) -> Result<String> {
+    phoenix_safety::ensure_writable("repartition")?;
+    let mut mountable = plan.mountable();
+    let (Some(target), None) = (mountable.next(), mountable.next()) else {
+        return Err(anyhow!(
+            "partition plan must contain exactly one mountable partition to format"
+        ));
+    };
+    let offset = target.offset_bytes(plan.sector_size);
+    let before: Vec<String> = volumes::volumes_on_disk(disk_number)?
+        .into_iter()
+        .map(|(name, _)| name)
+        .collect();
+    create_gpt_partitions(disk_number, plan)?;
+    let volume = wait_for_new_volume(disk_number, offset, &before, Duration::from_secs(15))?;
+    format_volume(&volume, fs, label, cluster_bytes, true)?;
+    Ok(wait_for_drive_letter(&volume, Duration::from_secs(5)))
 }
 
+/// Formats the volume mounted at `mount` (`E:\`, a folder mount point or
+/// a volume GUID path).
 pub fn format_existing_volume(
-    drive_letter: char,
+    mount: &str,
     fs: FileSystem,
     label: Option<&str>,
+    cluster_bytes: Option<u32>,
 ) -> Result<()> {
-    format_volume(drive_letter, fs, label, true)
+    phoenix_safety::ensure_writable("format")?;
+    let mut root = mount.to_string();
+    if !root.ends_with('\\') {
+        root.push('\\');
+    }
+    format_volume(&root, fs, label, cluster_bytes, true)
 }
 
-fn wait_for_new_drive_letter(before: &[char], timeout: Duration) -> Result<char> {
-    let before_set: HashSet<char> = before.iter().copied().collect();
+/// Waits for a volume that was not on the disk before partitioning to
+/// appear at `offset`. Watching the target disk, rather than the set of
+/// drive letters, ignores other devices arriving at the same time and
+/// works when every letter is taken.
+fn wait_for_new_volume(
+    disk_number: u32,
+    offset: u64,
+    before: &[String],
+    timeout: Duration,
+) -> Result<String> {
     let start = Instant::now();
     loop {
-        let now = logical_drive_letters();
-        for letter in now {
-            if !before_set.contains(&letter) {
-                return Ok(letter);
-            }
+        let found = volumes::volumes_on_disk(disk_number)?
+            .into_iter()
+            .find(|(name, volume_offset)| *volume_offset == offset && !before.contains(name));
+        if let Some((name, _)) = found {
+            return Ok(name);
         }
         if start.elapsed() > timeout {
-            return Err(anyhow!("timed out waiting for new volume mount"));
+            return Err(anyhow!(
+                "no volume appeared on PhysicalDrive{} at offset {} within {}s after partitioning",
+                disk_number,
+                offset,
+                timeout.as_secs()
+            ));
         }
         std::thread::sleep(Duration::from_millis(300));
     }
 }
 
-fn create_single_gpt_partition(disk_number: u32, disk_size: u64, label: Option<&str>) -> Result<()> {
-    let handle = open_physical_drive_rw(disk_number)?;
-    let disk_id = GUID::from_u128(Uuid::new_v4().as_u128());
-    initialize_gpt(handle, disk_id)?;
-
-    let alignment = 1 * 1024 * 1024u64;
-    let usable = disk_size.saturating_sub(alignment * 2);
-    if usable == 0 {
-        unsafe { CloseHandle(handle) };
-        return Err(anyhow!("disk too small for partitioning"));
+/// The volume's drive root once the mount manager assigns one, else its
+/// volume GUID path.
+fn wait_for_drive_letter(volume_name: &str, timeout: Duration) -> String {
+    let start = Instant::now();
+    loop {
+        let paths = volumes::volume_path_names(volume_name);
+        if let Some(root) = paths.into_iter().find(|path| path.len() == 3) {
+            return root;
+        }
+        if start.elapsed() > timeout {
+            return volume_name.to_string();
+        }
+        std::thread::sleep(Duration::from_millis(300));
     }
+}
 
-    let mut layout: DRIVE_LAYOUT_INFORMATION_EX = unsafe { std::mem::zeroed() };
-    layout.PartitionStyle = PARTITION_STYLE_GPT;
-    layout.PartitionCount = 1;
+fn create_gpt_partitions(disk_number: u32, plan: &PartitionPlan) -> Result<()> {
+    let disk_id = GUID::from_u128(plan.disk_guid.as_u128());
+
+    // DRIVE_LAYOUT_INFORMATION_EX ends in a one-element array; size the
+    // buffer for every planned entry and keep it 8-byte aligned.
+    let sector_size = plan.sector_size;
+    let extra = plan.partitions.len().saturating_sub(1);
+    let layout_size = std::mem::size_of::<DRIVE_LAYOUT_INFORMATION_EX>()
+        + extra * std::mem::size_of::<PARTITION_INFORMATION_EX>();
+    let mut buffer = vec![0u64; layout_size.div_ceil(8)];
+    let layout = buffer.as_mut_ptr() as *mut DRIVE_LAYOUT_INFORMATION_EX;
+    let usable_offset = plan.first_usable_lba * sector_size;
+    let usable_length = (plan.last_usable_lba + 1) * sector_size - usable_offset;
     unsafe {
-        layout.Anonymous.Gpt = DRIVE_LAYOUT_INFORMATION_GPT {
+        (*layout).PartitionStyle = PARTITION_STYLE_GPT.0 as u32;
+        (*layout).PartitionCount = plan.partitions.len() as u32;
+        (*layout).Anonymous.Gpt = DRIVE_LAYOUT_INFORMATION_GPT {
             DiskId: disk_id,
-            StartingUsableOffset: alignment as i64,
-            UsableLength: usable as i64,
-            MaxPartitionCount: 128,
+            StartingUsableOffset: usable_offset as i64,
+            UsableLength: usable_length as i64,
+            MaxPartitionCount: GPT_ENTRY_COUNT,
         };
     }
 
-    let partition_id = GUID::from_u128(Uuid::new_v4().as_u128());
-    let mut entry: PARTITION_INFORMATION_EX = unsafe { std::mem::zeroed() };
-    entry.PartitionStyle = PARTITION_STYLE_GPT;
-    entry.StartingOffset = alignment as i64;
-    entry.PartitionLength = usable as i64;
-    entry.PartitionNumber = 1;
-    entry.RewritePartition = BOOL(1);
-    unsafe {
+    let entries = unsafe { (*layout).PartitionEntry.as_mut_ptr() };
+    for (idx, partition) in plan.partitions.iter().enumerate() {
+        let mut entry: PARTITION_INFORMATION_EX = unsafe { std::mem::zeroed() };
+        entry.PartitionStyle = PARTITION_STYLE_GPT;
+        entry.StartingOffset = partition.offset_bytes(sector_size) as i64;
+        entry.PartitionLength = partition.length_bytes(sector_size) as i64;
+        entry.PartitionNumber = partition.number;
+        entry.RewritePartition = BOOL(1);
         entry.Anonymous.Gpt = PARTITION_INFORMATION_GPT {
-            PartitionType: GUID::from_u128(0xEBD0A0A2_B9E5_4433_87C0_68B6B72699C7),
-            PartitionId: partition_id,
-            Attributes: 0,
-            Name: gpt_name_from_label(label),
+            PartitionType: GUID::from_u128(partition.spec.type_guid.as_u128()),
+            PartitionId: GUID::from_u128(partition.unique_guid.as_u128()),
+            Attributes: GPT_ATTRIBUTES(partition.spec.attributes.bits()),
+            Name: gpt_name_units(&partition.spec.name)?,
         };
-        layout.PartitionEntry[0] = entry;
+        unsafe { entries.add(idx).write(entry) };
     }
 
-    unsafe {
-        let ok = DeviceIoControl(
-            handle,
-            IOCTL_DISK_SET_DRIVE_LAYOUT_EX,
-            Some(&layout as *const _ as *const c_void),
-            std::mem::size_of::<DRIVE_LAYOUT_INFORMATION_EX>() as u32,
-            None,
-            0,
-            None,
-            None,
-        );
-        if !ok.as_bool() {
-            CloseHandle(handle);
-            return Err(anyhow!("IOCTL_DISK_SET_DRIVE_LAYOUT_EX failed"));
+    // A stick with a corrupt or hybrid table left by another tool can make
+    // the layout IOCTLs fail; clean it and try again.
+    if let Err(first) = write_layout(disk_number, disk_id, layout, layout_size) {
+        clean_disk(disk_number)
+            .with_context(|| format!("clean after failed layout ({:#})", first))?;
+        let mut attempt = 1;
+        while let Err(err) = write_layout(disk_number, disk_id, layout, layout_size) {
+            if attempt == CLEAN_LAYOUT_ATTEMPTS {
+                return Err(anyhow!(
+                    "{:#}; still failing after clean: {:#}",
+                    first,
+                    err
+                ));
+            }
+            attempt += 1;
+            std::thread::sleep(Duration::from_secs(1));
         }
+    }
 
-        let ok = DeviceIoControl(
+    if plan.hybrid_mbr.is_some() {
+        write_hybrid_mbr(disk_number, plan)?;
+    }
+    verify_written_layout(disk_number, plan.disk_size)
+}
+
+/// Initializes the disk as GPT, writes `layout` and has the partition
+/// manager re-read it.
+fn write_layout(
+    disk_number: u32,
+    disk_id: GUID,
+    layout: *const DRIVE_LAYOUT_INFORMATION_EX,
+    layout_size: usize,
+) -> Result<()> {
+    let handle = open_physical_drive_rw(disk_number)?;
+    let result = initialize_gpt(handle, disk_id)
+        .and_then(|_| {
+            disk_control(
+                handle,
+                IOCTL_DISK_SET_DRIVE_LAYOUT_EX,
+                Some(layout as *const c_void),
+                layout_size as u32,
+                "IOCTL_DISK_SET_DRIVE_LAYOUT_EX",
+            )
+        })
+        .and_then(|_| {
+            disk_control(
+                handle,
+                IOCTL_DISK_UPDATE_PROPERTIES,
+                None,
+                0,
+                "IOCTL_DISK_UPDATE_PROPERTIES",
+            )
+        });
+    unsafe { CloseHandle(handle) };
+    result
+}
+
+/// Returns the disk to a blank, uninitialized state without going through
+/// the Virtual Disk Service: dismounts its volumes, zeroes the first and
+/// last `CLEAN_ZERO_BYTES`, deletes the layout and has the partition
+/// manager rescan. Recovers sticks whose hybrid or corrupt tables make
+/// `IOCTL_DISK_CREATE_DISK` or `IOCTL_DISK_SET_DRIVE_LAYOUT_EX` fail.
+pub fn clean_disk(disk_number: u32) -> Result<()> {
+    phoenix_safety::ensure_writable("clean")?;
+    // Held until the layout is gone so nothing remounts mid-clean.
+    let _volumes = dismount_disk_volumes(disk_number)?;
+
+    let handle = open_physical_drive_rw(disk_number)?;
+    let length = disk_length(handle);
+    unsafe { CloseHandle(handle) };
+    zero_disk_ends(disk_number, length?)?;
+
+    let handle = open_physical_drive_rw(disk_number)?;
+    let result = disk_control(
+        handle,
+        IOCTL_DISK_DELETE_DRIVE_LAYOUT,
+        None,
+        0,
+        "IOCTL_DISK_DELETE_DRIVE_LAYOUT",
+    )
+    .and_then(|_| {
+        disk_control(
             handle,
             IOCTL_DISK_UPDATE_PROPERTIES,
             None,
             0,
+            "IOCTL_DISK_UPDATE_PROPERTIES",
+        )
+    });
+    unsafe { CloseHandle(handle) };
+    result?;
+    // The partition manager tears down the old volumes asynchronously.
+    std::thread::sleep(Duration::from_millis(500));
+    Ok(())
+}
+
+/// Locks and dismounts every volume on the disk, so raw writes inside
+/// their extents are allowed. The locks last as long as the files.
+fn dismount_disk_volumes(disk_number: u32) -> Result<Vec<std::fs::File>> {
+    let mut locked = Vec::new();
+    for (name, _) in volumes::volumes_on_disk(disk_number)? {
+        let path = name.trim_end_matches('\\').to_string();
+        let Ok(file) = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .share_mode(FILE_SHARE_READ.0 | FILE_SHARE_WRITE.0)
+            .open(&path)
+        else {
+            continue;
+        };
+        let handle = HANDLE(std::os::windows::io::AsRawHandle::as_raw_handle(&file) as isize);
+        disk_control(handle, FSCTL_LOCK_VOLUME, None, 0, "lock")
+            .and_then(|_| disk_control(handle, FSCTL_DISMOUNT_VOLUME, None, 0, "dismount"))
+            .with_context(|| {
+                format!("volume {} on PhysicalDrive{} is in use", path, disk_number)
+            })?;
+        locked.push(file);
+    }
+    Ok(locked)
+}
+
+fn zero_disk_ends(disk_number: u32, length: u64) -> Result<()> {
+    let path = format!(r"\\.\PhysicalDrive{}", disk_number);
+    let mut device = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(&path)
+        .with_context(|| format!("open {}", path))?;
+    let zeros = vec![0u8; CLEAN_ZERO_BYTES as usize];
+    let head = CLEAN_ZERO_BYTES.min(length);
+    device.seek(SeekFrom::Start(0))?;
+    device
+        .write_all(&zeros[..head as usize])
+        .with_context(|| format!("zero start of {}", path))?;
+    if length > CLEAN_ZERO_BYTES {
+        let tail_start = (length - CLEAN_ZERO_BYTES).max(head);
+        device.seek(SeekFrom::Start(tail_start))?;
+        device
+            .write_all(&zeros[..(length - tail_start) as usize])
+            .with_context(|| format!("zero end of {}", path))?;
+    }
+    device.flush()?;
+    Ok(())
+}
+
+fn disk_length(handle: HANDLE) -> Result<u64> {
+    let mut info = GET_LENGTH_INFORMATION::default();
+    unsafe {
+        let ok = DeviceIoControl(
+            handle,
+            IOCTL_DISK_GET_LENGTH_INFO,
             None,
             0,
+            Some(&mut info as *mut _ as *mut c_void),
+            std::mem::size_of::<GET_LENGTH_INFORMATION>() as u32,
             None,
             None,
         );
         if !ok.as_bool() {
-            CloseHandle(handle);
-            return Err(anyhow!("IOCTL_DISK_UPDATE_PROPERTIES failed"));
+            return Err(anyhow!(
+                "IOCTL_DISK_GET_LENGTH_INFO failed: {}",
+                windows::core::Error::from_win32()
+            ));
         }
     }
+    Ok(info.Length.max(0) as u64)
+}
 
-    unsafe { CloseHandle(handle) };
+/// An input-only IOCTL, with the Win32 error in the message on failure.
+fn disk_control(
+    handle: HANDLE,
+    code: u32,
+    input: Option<*const c_void>,
+    input_len: u32,
+    what: &str,
+) -> Result<()> {
+    unsafe {
+        let ok = DeviceIoControl(handle, code, input, input_len, None, 0, None, None);
+        if !ok.as_bool() {
+            return Err(anyhow!(
+                "{} failed: {}",
+                what,
+                windows::core::Error::from_win32()
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Windows always writes a protective-only MBR, so the hybrid sector is
+/// written over LBA 0 after the layout IOCTLs.
+fn write_hybrid_mbr(disk_number: u32, plan: &PartitionPlan) -> Result<()> {
+    phoenix_safety::ensure_writable("MBR write")?;
+    let path = format!(r"\\.\PhysicalDrive{}", disk_number);
+    let mut device = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(&path)
+        .with_context(|| format!("open {}", path))?;
+    device.seek(SeekFrom::Start(0))?;
+    device.write_all(&plan.mbr_sector())?;
+    device.flush()?;
+    Ok(())
+}
+
+/// Reads back the protective MBR and both GPT headers so a layout IOCTL that
+/// reported success but left a broken table is caught before any copy starts.
+fn verify_written_layout(disk_number: u32, disk_size: u64) -> Result<()> {
+    let path = format!(r"\\.\PhysicalDrive{}", disk_number);
+    let check = verify_device_partition_tables(&path, Some(disk_size), DEFAULT_SECTOR_SIZE)?;
+    if !check.ok {
+        return Err(anyhow!(
+            "partition table verification failed: {}",
+            check.issues.join("; ")
+        ));
+    }
     Ok(())
 }
 
@@ -182,7 +426,7 @@ fn initialize_gpt(handle: HANDLE, disk_id: GUID) -> Result<()> {
     unsafe {
         create.Anonymous.Gpt = CREATE_DISK_GPT {
             DiskId: disk_id,
-            MaxPartitionCount: 128,
+            MaxPartitionCount: GPT_ENTRY_COUNT,
         };
     }
 
@@ -198,13 +442,29 @@ fn initialize_gpt(handle: HANDLE, disk_id: GUID) -> Result<()> {
             None,
         );
         if !ok.as_bool() {
-            return Err(anyhow!("IOCTL_DISK_CREATE_DISK failed"));
+            return Err(anyhow!(
+                "IOCTL_DISK_CREATE_DISK failed: {}",
+                windows::core::Error::from_win32()
+            ));
         }
     }
     Ok(())
 }
 
-fn format_volume(drive_letter: char, fs: FileSystem, label: Option<&str>, quick: bool) -> Result<()> {
+fn format_volume(
+    root: &str,
+    fs: FileSystem,
+    label: Option<&str>,
+    cluster_bytes: Option<u32>,
+    quick: bool,
+) -> Result<()> {
+    if matches!(fs, FileSystem::Fat32)
+        && phoenix_fs_fat32::volume_length(root)? > FORMATEX_FAT32_MAX_BYTES
+    {
+        phoenix_fs_fat32::format_fat32_volume(root, label, cluster_bytes.map(u64::from))?;
+        return Ok(());
+    }
+
     FORMAT_RESULT.store(-1, Ordering::SeqCst);
 
     let module = unsafe { LoadLibraryW(PCWSTR(wide("fmifs.dll").as_ptr())) };
@@ -219,28 +479,45 @@ fn format_volume(drive_letter: char, fs: FileSystem, label: Option<&str>, quick:
     }
 
     let format_ex: FormatExFn = unsafe { std::mem::transmute(proc) };
-    let drive_root = format!("{}:\\", drive_letter);
     let fs_name = fs.as_str().to_string();
     let label = label.unwrap_or("PHOENIX");
 
     unsafe {
         format_ex(
-            PCWSTR(wide(&drive_root).as_ptr()),
+            PCWSTR(wide(root).as_ptr()),
             FMIFS_HARDDISK,
             PCWSTR(wide(&fs_name).as_ptr()),
             PCWSTR(wide(label).as_ptr()),
             BOOL(if quick { 1 } else { 0 }),
-            0,
+            cluster_bytes.unwrap_or(0),
             Some(format_callback),
         );
         FreeLibrary(module);
     }
 
     match FORMAT_RESULT.load(Ordering::SeqCst) {
-        1 => Ok(()),
-        0 => Err(anyhow!("format failed")),
-        _ => Err(anyhow!("format did not report completion")),
+        1 => {}
+        0 => return Err(anyhow!("format failed")),
+        _ => return Err(anyhow!("format did not report completion")),
     }
+    if matches!(fs, FileSystem::ExFat) {
+        verify_exfat_format(root)?;
+    }
+    Ok(())
+}
+
+/// Reads the new boot regions back, catching a format that reported
+/// success but left a stale checksum or mismatched backup region.
+fn verify_exfat_format(root: &str) -> Result<()> {
+    let device = volumes::volume_device_path(root)?;
+    let check = phoenix_fs_exfat::verify_exfat_device(&device, 0, None)?;
+    if !check.ok {
+        return Err(anyhow!(
+            "exFAT boot region check failed after format: {}",
+            check.issues.join("; ")
+        ));
+    }
+    Ok(())
 }
 
 fn open_physical_drive_rw(n: u32) -> Result<HANDLE> {
@@ -289,17 +566,3 @@ fn wide(s: &str) -> Vec<u16> {
         .chain(std::iter::once(0))
         .collect()
 }
-
-fn gpt_name_from_label(label: Option<&str>) -> [u16; 36] {
-    let mut name = [0u16; 36];
-    let value = label.unwrap_or("PHOENIX");
-    let mut iter = value.encode_utf16();
-    for slot in name.iter_mut() {
-        if let Some(ch) = iter.next() {
-            *slot = ch;
-        } else {
-            break;
-        }
-    }
-    name
-}
@@ -1,12 +1,25 @@
 use anyhow::{anyhow, Result};
+use phoenix_partition::PartitionPlan;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum FileSystem {
     Fat32,
     Ntfs,
     ExFat,
 }
 
+impl FileSystem {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FileSystem::Fat32 => "FAT32",
+            FileSystem::Ntfs => "NTFS",
+            FileSystem::ExFat => "exFAT",
+        }
+    }
+}
+
 pub fn parse_filesystem(_value: &str) -> Option<FileSystem> {
     match _value.trim().to_ascii_lowercase().as_str() {
         "fat32" => Some(FileSystem::Fat32),
@@ -16,23 +29,25 @@ pub fn parse_filesystem(_value: &str) -> Option<FileSystem> {
     }
 }
 
-pub fn logical_drive_letters() -> Vec<char> {
-    Vec::new()
-}
-
 pub fn prepare_usb_disk(
     _disk_number: u32,
-    _disk_size: u64,
+    _plan: &PartitionPlan,
     _fs: FileSystem,
     _label: Option<&str>,
-) -> Result<char> {
+    _cluster_bytes: Option<u32>,
+) -> Result<String> {
     Err(anyhow!("phoenix-host-windows format requires Windows"))
 }
 
 pub fn format_existing_volume(
-    _drive_letter: char,
+    _mount: &str,
     _fs: FileSystem,
     _label: Option<&str>,
+    _cluster_bytes: Option<u32>,
 ) -> Result<()> {
     Err(anyhow!("phoenix-host-windows format requires Windows"))
 }
+
+pub fn clean_disk(_disk_number: u32) -> Result<()> {
+    Err(anyhow!("phoenix-host-windows format requires Windows"))
+}
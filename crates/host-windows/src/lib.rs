@@ -1,46 +1,58 @@
 use anyhow::{anyhow, Result};
-use phoenix_core::{now_utc_rfc3339, DeviceGraph, HostInfo, Partition};
+use phoenix_core::DeviceGraph;
+#[cfg(windows)]
+use phoenix_core::{now_utc_rfc3339, HostInfo, Partition};
 
 #[cfg(windows)]
 pub mod format;
 #[cfg(windows)]
 pub mod space;
 #[cfg(windows)]
+mod usb;
+#[cfg(windows)]
 mod volumes;
 #[cfg(windows)]
 mod win;
 #[cfg(not(windows))]
-mod format_stub;
+pub mod format_stub;
 #[cfg(not(windows))]
 pub use format_stub as format;
 #[cfg(not(windows))]
-mod space_stub;
+pub mod space_stub;
 #[cfg(not(windows))]
 pub use space_stub as space;
 
 pub fn build_device_graph() -> Result<DeviceGraph> {
+    if phoenix_core::mock::is_active() {
+        return Ok(phoenix_core::mock::device_graph()?);
+    }
     #[cfg(windows)]
     {
         let host = HostInfo {
             os: "windows".to_string(),
             os_version: win::os_version_string(),
             machine: win::machine_name_string(),
+            os_edition: win::os_edition_string(),
+            os_display_version: win::os_display_version_string(),
         };
 
         let mut disks = win::enumerate_physical_disks()?;
         let sys_drive = volumes::system_drive_letter()?;
         let mounts = volumes::enumerate_volume_mounts()?;
+        let usb_ports = usb::usb_ports();
 
         for disk in disks.iter_mut() {
             let Some(disk_number) = parse_disk_number(&disk.id) else {
                 continue;
             };
 
+            disk.usb_port = usb_ports.get(&disk_number).cloned();
             let partition_entries = win::enumerate_partitions(disk_number)?;
             let mut partitions = Vec::new();
             for entry in partition_entries {
                 let mut label = None;
                 let mut fs = None;
+                let mut fs_uuid = None;
                 let mut mount_points = Vec::new();
                 for mount in mounts.iter().filter(|m| m.disk_number == disk_number) {
                     if mount.offset_bytes >= entry.offset_bytes
@@ -53,6 +65,9 @@ pub fn build_device_graph() -> Result<DeviceGraph> {
                         if fs.is_none() {
                             fs = mount.fs.clone();
                         }
+                        if fs_uuid.is_none() {
+                            fs_uuid = mount.fs_uuid.clone();
+                        }
                     }
                 }
 
@@ -62,6 +77,9 @@ pub fn build_device_graph() -> Result<DeviceGraph> {
                     fs,
                     size_bytes: entry.length_bytes,
                     mount_points,
+                    part_uuid: entry.part_uuid,
+                    fs_uuid,
+                    offset_bytes: Some(entry.offset_bytes),
                 });
             }
 
@@ -70,7 +88,7 @@ pub fn build_device_graph() -> Result<DeviceGraph> {
                 partition
                     .mount_points
                     .iter()
-                    .any(|mount| mount.to_ascii_uppercase().starts_with(&sys_drive))
+                    .any(|mount| mount.eq_ignore_ascii_case(&sys_drive))
             });
         }
 
@@ -84,6 +102,7 @@ pub fn build_device_graph() -> Result<DeviceGraph> {
     }
 }
 
+#[cfg(windows)]
 fn parse_disk_number(id: &str) -> Option<u32> {
     let suffix = id.strip_prefix("PhysicalDrive")?;
     suffix.parse().ok()
@@ -0,0 +1,203 @@
+use phoenix_core::UsbPort;
+use std::collections::HashMap;
+use std::ffi::c_void;
+use std::mem::size_of;
+
+use windows::core::PCWSTR;
+use windows::Win32::Devices::DeviceAndDriverInstallation::{
+    CM_Get_DevNode_Registry_PropertyW, CM_Get_Device_IDW, CM_Get_Parent,
+    SetupDiDestroyDeviceInfoList, SetupDiEnumDeviceInterfaces, SetupDiGetClassDevsW,
+    SetupDiGetDeviceInterfaceDetailW, CM_DRP_LOCATION_INFORMATION, CM_DRP_LOCATION_PATHS,
+    CR_SUCCESS, DIGCF_DEVICEINTERFACE, DIGCF_PRESENT, SP_DEVICE_INTERFACE_DATA,
+    SP_DEVICE_INTERFACE_DETAIL_DATA_W, SP_DEVINFO_DATA,
+};
+use windows::Win32::Foundation::{CloseHandle, HWND};
+use windows::Win32::Storage::FileSystem::{
+    CreateFileW, FILE_ATTRIBUTE_NORMAL, FILE_SHARE_READ, FILE_SHARE_WRITE, OPEN_EXISTING,
+};
+use windows::Win32::System::Ioctl::{
+    GUID_DEVINTERFACE_DISK, IOCTL_STORAGE_GET_DEVICE_NUMBER, STORAGE_DEVICE_NUMBER,
+};
+use windows::Win32::System::IO::DeviceIoControl;
+
+/// Device tree levels walked from a disk towards its USB device.
+const MAX_PARENTS: usize = 8;
+
+/// USB port of each attached disk, keyed by PhysicalDrive number.
+/// Best-effort: a disk whose device node cannot be read is left out.
+pub fn usb_ports() -> HashMap<u32, UsbPort> {
+    let mut ports = HashMap::new();
+    unsafe {
+        let Ok(set) = SetupDiGetClassDevsW(
+            Some(&GUID_DEVINTERFACE_DISK),
+            PCWSTR::null(),
+            HWND::default(),
+            DIGCF_PRESENT | DIGCF_DEVICEINTERFACE,
+        ) else {
+            return ports;
+        };
+        let mut index = 0;
+        loop {
+            let mut interface = SP_DEVICE_INTERFACE_DATA {
+                cbSize: size_of::<SP_DEVICE_INTERFACE_DATA>() as u32,
+                ..Default::default()
+            };
+            if SetupDiEnumDeviceInterfaces(set, None, &GUID_DEVINTERFACE_DISK, index, &mut interface)
+                .is_err()
+            {
+                break;
+            }
+            index += 1;
+
+            let mut required = 0u32;
+            let _ = SetupDiGetDeviceInterfaceDetailW(set, &interface, None, 0, Some(&mut required), None);
+            if required == 0 {
+                continue;
+            }
+            // u32 words keep the detail struct aligned.
+            let mut buffer = vec![0u32; (required as usize).div_ceil(4)];
+            let detail = buffer.as_mut_ptr() as *mut SP_DEVICE_INTERFACE_DETAIL_DATA_W;
+            (*detail).cbSize = size_of::<SP_DEVICE_INTERFACE_DETAIL_DATA_W>() as u32;
+            let mut info = SP_DEVINFO_DATA {
+                cbSize: size_of::<SP_DEVINFO_DATA>() as u32,
+                ..Default::default()
+            };
+            if SetupDiGetDeviceInterfaceDetailW(
+                set,
+                &interface,
+                Some(detail),
+                required,
+                None,
+                Some(&mut info),
+            )
+            .is_err()
+            {
+                continue;
+            }
+            let path = PCWSTR(std::ptr::addr_of!((*detail).DevicePath) as *const u16);
+            let Some(number) = device_number(path) else {
+                continue;
+            };
+            if let Some(port) = usb_port(info.DevInst) {
+                ports.insert(number, port);
+            }
+        }
+        let _ = SetupDiDestroyDeviceInfoList(set);
+    }
+    ports
+}
+
+fn device_number(path: PCWSTR) -> Option<u32> {
+    unsafe {
+        // No access rights are needed for the storage number query.
+        let handle = CreateFileW(
+            path,
+            0,
+            FILE_SHARE_READ | FILE_SHARE_WRITE,
+            None,
+            OPEN_EXISTING,
+            FILE_ATTRIBUTE_NORMAL,
+            None,
+        )
+        .ok()?;
+        let mut number = STORAGE_DEVICE_NUMBER::default();
+        let mut returned = 0u32;
+        let status = DeviceIoControl(
+            handle,
+            IOCTL_STORAGE_GET_DEVICE_NUMBER,
+            None,
+            0,
+            Some(&mut number as *mut STORAGE_DEVICE_NUMBER as *mut c_void),
+            size_of::<STORAGE_DEVICE_NUMBER>() as u32,
+            Some(&mut returned),
+            None,
+        );
+        let _ = CloseHandle(handle);
+        status.ok().map(|_| number.DeviceNumber)
+    }
+}
+
+/// Walks up from the disk to the USB device node (skipping composite
+/// interface nodes, `&MI_xx`) and reads where it is plugged in.
+fn usb_port(disk: u32) -> Option<UsbPort> {
+    let mut node = disk;
+    for _ in 0..MAX_PARENTS {
+        let mut parent = 0u32;
+        if unsafe { CM_Get_Parent(&mut parent, node, 0) } != CR_SUCCESS {
+            return None;
+        }
+        node = parent;
+        let id = device_id(node)?.to_ascii_uppercase();
+        if !id.starts_with(r"USB\") || id.contains("&MI_") {
+            continue;
+        }
+        let location_path = registry_strings(node, CM_DRP_LOCATION_PATHS)
+            .and_then(|paths| paths.into_iter().next());
+        let location_info = registry_strings(node, CM_DRP_LOCATION_INFORMATION)
+            .and_then(|info| info.into_iter().next());
+        return match (location_path, location_info) {
+            (Some(path), _) => Some(UsbPort {
+                ports: location_path_ports(&path),
+                path,
+                bus: None,
+                label: None,
+            }),
+            // `Port_#0003.Hub_#0001` when location paths are unavailable.
+            (None, Some(info)) => Some(UsbPort {
+                ports: info
+                    .strip_prefix("Port_#")
+                    .and_then(|rest| rest.split('.').next())
+                    .and_then(|port| port.parse().ok())
+                    .into_iter()
+                    .collect(),
+                path: info,
+                bus: None,
+                label: None,
+            }),
+            (None, None) => None,
+        };
+    }
+    None
+}
+
+/// Port numbers from the `USB(n)` segments of
+/// `PCIROOT(0)#PCI(1400)#USBROOT(0)#USB(2)#USB(3)`.
+fn location_path_ports(path: &str) -> Vec<u32> {
+    path.split('#')
+        .filter_map(|segment| segment.strip_prefix("USB(")?.strip_suffix(')')?.parse().ok())
+        .collect()
+}
+
+fn device_id(node: u32) -> Option<String> {
+    let mut buf = [0u16; 512];
+    if unsafe { CM_Get_Device_IDW(node, &mut buf, 0) } != CR_SUCCESS {
+        return None;
+    }
+    let len = buf.iter().position(|&c| c == 0).unwrap_or(buf.len());
+    Some(String::from_utf16_lossy(&buf[..len]))
+}
+
+/// A `REG_SZ` or `REG_MULTI_SZ` device property as its strings.
+fn registry_strings(node: u32, property: u32) -> Option<Vec<String>> {
+    let mut buf = [0u16; 1024];
+    let mut size = (buf.len() * 2) as u32;
+    let status = unsafe {
+        CM_Get_DevNode_Registry_PropertyW(
+            node,
+            property,
+            None,
+            Some(buf.as_mut_ptr() as *mut c_void),
+            &mut size,
+            0,
+        )
+    };
+    if status != CR_SUCCESS {
+        return None;
+    }
+    let strings: Vec<String> = buf[..(size as usize / 2).min(buf.len())]
+        .split(|&c| c == 0)
+        .filter(|part| !part.is_empty())
+        .map(String::from_utf16_lossy)
+        .collect();
+    (!strings.is_empty()).then_some(strings)
+}
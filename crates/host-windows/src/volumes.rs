@@ -4,8 +4,10 @@ use std::ffi::c_void;
 use windows::core::PCWSTR;
 use windows::Win32::Foundation::{CloseHandle, HANDLE, INVALID_HANDLE_VALUE};
 use windows::Win32::Storage::FileSystem::{
-    CreateFileW, GetDiskFreeSpaceExW, GetLogicalDrives, GetVolumeInformationW,
-    FILE_ATTRIBUTE_NORMAL, FILE_GENERIC_READ, FILE_SHARE_READ, FILE_SHARE_WRITE, OPEN_EXISTING,
+    CreateFileW, FindFirstVolumeW, FindNextVolumeW, FindVolumeClose, GetDiskFreeSpaceExW,
+    GetVolumeInformationW, GetVolumeNameForVolumeMountPointW, GetVolumePathNamesForVolumeNameW,
+    FILE_ATTRIBUTE_NORMAL,
+    FILE_GENERIC_READ, FILE_SHARE_READ, FILE_SHARE_WRITE, OPEN_EXISTING,
 };
 use windows::Win32::System::Ioctl::{
     DeviceIoControl, IOCTL_VOLUME_GET_VOLUME_DISK_EXTENTS, VOLUME_DISK_EXTENTS,
@@ -17,6 +19,8 @@ pub struct VolumeMount {
     pub id: String,
     pub label: Option<String>,
     pub fs: Option<String>,
+    /// Volume serial number as `XXXX-XXXX`.
+    pub fs_uuid: Option<String>,
     pub size_bytes: u64,
     pub mount_points: Vec<String>,
     pub disk_number: u32,
@@ -32,9 +36,11 @@ fn wide(s: &str) -> Vec<u16> {
         .collect()
 }
 
-fn open_volume_handle(drive_letter: char) -> Result<HANDLE> {
-    let path = format!(r"\\.\{}:", drive_letter);
-    let w = wide(&path);
+/// Opens `\\?\Volume{GUID}` (the volume name without its trailing
+/// backslash, which would open the root directory instead).
+fn open_volume_handle(volume_name: &str) -> Result<HANDLE> {
+    let path = volume_name.trim_end_matches('\\');
+    let w = wide(path);
 
     unsafe {
         let handle = CreateFileW(
@@ -66,29 +72,106 @@ pub fn system_drive_letter() -> Result<String> {
     }
 }
 
-fn list_logical_drive_letters() -> Vec<char> {
+/// Volume names (`\\?\Volume{GUID}\`) of every volume, including
+/// those mounted only on a folder or not mounted at all.
+fn list_volume_names() -> Result<Vec<String>> {
+    let mut names = Vec::new();
+    let mut buf = [0u16; 64];
     unsafe {
-        let mask = GetLogicalDrives();
-        let mut letters = Vec::new();
-        for (idx, letter) in ('A'..='Z').enumerate() {
-            if mask & (1u32 << idx) != 0 {
-                letters.push(letter);
+        let find = FindFirstVolumeW(&mut buf);
+        if find == INVALID_HANDLE_VALUE {
+            return Err(anyhow!("FindFirstVolumeW failed"));
+        }
+        loop {
+            names.push(from_wide(&buf));
+            if !FindNextVolumeW(find, &mut buf).as_bool() {
+                break;
             }
         }
-        letters
+        FindVolumeClose(find);
+    }
+    Ok(names)
+}
+
+/// Drive roots (`E:\`) and folder mount points (`C:\mnt\usb\`) of a
+/// volume, drive letters first.
+pub(crate) fn volume_path_names(volume_name: &str) -> Vec<String> {
+    let wname = wide(volume_name);
+    let mut buf = vec![0u16; 1024];
+    let mut needed = 0u32;
+    unsafe {
+        let mut ok = GetVolumePathNamesForVolumeNameW(
+            PCWSTR(wname.as_ptr()),
+            Some(&mut buf),
+            &mut needed,
+        );
+        if !ok.as_bool() && needed as usize > buf.len() {
+            buf = vec![0u16; needed as usize];
+            ok = GetVolumePathNamesForVolumeNameW(
+                PCWSTR(wname.as_ptr()),
+                Some(&mut buf),
+                &mut needed,
+            );
+        }
+        if !ok.as_bool() {
+            return Vec::new();
+        }
+    }
+
+    // A double-NUL-terminated list of NUL-terminated paths.
+    let mut paths: Vec<String> = buf
+        .split(|c| *c == 0)
+        .take_while(|path| !path.is_empty())
+        .map(String::from_utf16_lossy)
+        .collect();
+    paths.sort_by_key(|path| path.len() != 3);
+    paths
+}
+
+/// Volume names on `disk_number`, with the byte offset of each.
+pub(crate) fn volumes_on_disk(disk_number: u32) -> Result<Vec<(String, u64)>> {
+    Ok(list_volume_names()?
+        .into_iter()
+        .filter_map(|name| match volume_extent(&name) {
+            Ok((disk, offset, _)) if disk == disk_number => Some((name, offset)),
+            _ => None,
+        })
+        .collect())
+}
+
+/// `\\?\Volume{GUID}` (no trailing backslash, so it opens the volume
+/// itself) for a drive root, folder mount point or volume GUID path.
+pub(crate) fn volume_device_path(mount: &str) -> Result<String> {
+    let mut root = mount.to_string();
+    if !root.ends_with('\\') {
+        root.push('\\');
+    }
+    let wroot = wide(&root);
+    let mut buf = [0u16; 64];
+    unsafe {
+        if !GetVolumeNameForVolumeMountPointW(PCWSTR(wroot.as_ptr()), &mut buf).as_bool() {
+            return Err(anyhow!("GetVolumeNameForVolumeMountPointW failed for {}", root));
+        }
     }
+    Ok(from_wide(&buf).trim_end_matches('\\').to_string())
+}
+
+fn from_wide(buf: &[u16]) -> String {
+    let len = buf.iter().position(|c| *c == 0).unwrap_or(buf.len());
+    String::from_utf16_lossy(&buf[..len])
 }
 
-fn get_volume_info(root: &str) -> Result<(Option<String>, Option<String>)> {
+fn get_volume_info(root: &str) -> Result<(Option<String>, Option<String>, Option<String>)> {
     let wroot = wide(root);
     let mut name_buf = [0u16; 256];
     let mut fs_buf = [0u16; 256];
+    let mut serial = 0u32;
 
     unsafe {
         let ok = GetVolumeInformationW(
             PCWSTR(wroot.as_ptr()),
             Some(&mut name_buf),
-            None,
+            Some(&mut serial as *mut u32),
             None,
             None,
             Some(&mut fs_buf),
@@ -109,8 +192,9 @@ fn get_volume_info(root: &str) -> Result<(Option<String>, Option<String>)> {
 
     let label = if label.is_empty() { None } else { Some(label) };
     let fs = if fs.is_empty() { None } else { Some(fs) };
+    let fs_uuid = (serial != 0).then(|| format!("{:04X}-{:04X}", serial >> 16, serial & 0xFFFF));
 
-    Ok((label, fs))
+    Ok((label, fs, fs_uuid))
 }
 
 fn get_volume_size(root: &str) -> Result<u64> {
@@ -134,8 +218,8 @@ fn get_volume_size(root: &str) -> Result<u64> {
     Ok(total)
 }
 
-fn volume_extent_for_drive(drive_letter: char) -> Result<(u32, u64, u64)> {
-    let handle = open_volume_handle(drive_letter)?;
+fn volume_extent(volume_name: &str) -> Result<(u32, u64, u64)> {
+    let handle = open_volume_handle(volume_name)?;
     let mut out = [0u8; 1024];
     let mut returned = 0u32;
 
@@ -154,8 +238,8 @@ fn volume_extent_for_drive(drive_letter: char) -> Result<(u32, u64, u64)> {
 
         if !ok.as_bool() {
             return Err(anyhow!(
-                "IOCTL_VOLUME_GET_VOLUME_DISK_EXTENTS failed for {}:",
-                drive_letter
+                "IOCTL_VOLUME_GET_VOLUME_DISK_EXTENTS failed for {}",
+                volume_name
             ));
         }
     }
@@ -167,7 +251,7 @@ fn volume_extent_for_drive(drive_letter: char) -> Result<(u32, u64, u64)> {
     let extents: VOLUME_DISK_EXTENTS =
         unsafe { std::ptr::read_unaligned(out.as_ptr() as *const _) };
     if extents.NumberOfDiskExtents == 0 {
-        return Err(anyhow!("No extents for {}:", drive_letter));
+        return Err(anyhow!("No extents for {}", volume_name));
     }
 
     let extent = extents.Extents[0];
@@ -176,32 +260,41 @@ fn volume_extent_for_drive(drive_letter: char) -> Result<(u32, u64, u64)> {
     Ok((extent.DiskNumber, offset, length))
 }
 
+/// Every volume with its drive letters, folder mount points and its
+/// `\\?\Volume{GUID}\` path, so volumes without a letter still map to
+/// a partition.
 pub fn enumerate_volume_mounts() -> Result<Vec<VolumeMount>> {
     let mut mounts = Vec::new();
 
-    for letter in list_logical_drive_letters() {
-        let root = format!("{}:\\", letter);
-
-        let (label, fs) = match get_volume_info(&root) {
+    for volume_name in list_volume_names()? {
+        let (label, fs, fs_uuid) = match get_volume_info(&volume_name) {
             Ok(value) => value,
             Err(_) => continue,
         };
 
-        let size_bytes = get_volume_size(&root).unwrap_or(0);
+        let size_bytes = get_volume_size(&volume_name).unwrap_or(0);
 
-        let (disk_number, offset_bytes, length_bytes) =
-            match volume_extent_for_drive(letter) {
-                Ok(value) => value,
-                Err(_) => continue,
-            };
+        let (disk_number, offset_bytes, length_bytes) = match volume_extent(&volume_name) {
+            Ok(value) => value,
+            Err(_) => continue,
+        };
 
-        let volume_id = format!("Drive{}", letter);
+        let mut mount_points = volume_path_names(&volume_name);
+        let volume_id = match mount_points.first().filter(|path| path.len() == 3) {
+            Some(root) => format!("Drive{}", &root[..1]),
+            None => volume_name
+                .trim_start_matches(r"\\?\")
+                .trim_end_matches('\\')
+                .to_string(),
+        };
+        mount_points.push(volume_name);
         mounts.push(VolumeMount {
             id: volume_id,
             label,
             fs,
+            fs_uuid,
             size_bytes,
-            mount_points: vec![root],
+            mount_points,
             disk_number,
             offset_bytes,
             length_bytes,
@@ -12,9 +12,16 @@ use windows::Win32::Storage::FileSystem::{
 use windows::Win32::System::Ioctl::{
     DeviceIoControl, DRIVE_LAYOUT_INFORMATION_EX, IOCTL_DISK_GET_DRIVE_GEOMETRY_EX,
     IOCTL_DISK_GET_DRIVE_LAYOUT_EX, IOCTL_STORAGE_QUERY_PROPERTY, PARTITION_INFORMATION_EX,
+    PARTITION_STYLE_GPT, PARTITION_STYLE_MBR,
     STORAGE_PROPERTY_QUERY, StorageDeviceProperty, STORAGE_QUERY_TYPE,
 };
-use windows::Win32::System::SystemInformation::{GetComputerNameW, GetVersionExW, OSVERSIONINFOW};
+use windows::Wdk::System::SystemServices::RtlGetVersion;
+use windows::Win32::System::Registry::{
+    RegGetValueW, HKEY_LOCAL_MACHINE, RRF_RT_REG_DWORD, RRF_RT_REG_SZ,
+};
+use windows::Win32::System::SystemInformation::{GetComputerNameW, OSVERSIONINFOW};
+
+const CURRENT_VERSION_KEY: &str = r"SOFTWARE\Microsoft\Windows NT\CurrentVersion";
 
 fn wide(s: &str) -> Vec<u16> {
     use std::os::windows::prelude::*;
@@ -76,7 +83,15 @@ fn query_size_bytes(handle: HANDLE) -> Result<u64> {
     Ok(disk_size.max(0) as u64)
 }
 
-fn query_friendly_and_removable(handle: HANDLE) -> Result<(String, bool)> {
+struct DeviceDescriptor {
+    friendly_name: String,
+    removable: bool,
+    serial: Option<String>,
+}
+
+/// Parses STORAGE_DEVICE_DESCRIPTOR: RemovableMedia at byte 10, then the
+/// vendor/product/revision/serial string offsets at 12/16/20/24.
+fn query_device_descriptor(handle: HANDLE) -> Result<DeviceDescriptor> {
     let mut query = STORAGE_PROPERTY_QUERY {
         PropertyId: StorageDeviceProperty,
         QueryType: STORAGE_QUERY_TYPE(0),
@@ -99,11 +114,15 @@ fn query_friendly_and_removable(handle: HANDLE) -> Result<(String, bool)> {
         );
 
         if !ok.as_bool() {
-            return Ok(("Unknown Disk".to_string(), false));
+            return Ok(DeviceDescriptor {
+                friendly_name: "Unknown Disk".to_string(),
+                removable: false,
+                serial: None,
+            });
         }
     }
 
-    let removable = out.get(8).copied().unwrap_or(0) != 0;
+    let removable = out.get(10).copied().unwrap_or(0) != 0;
     let vendor_slice = out.get(12..16).unwrap_or(&[0, 0, 0, 0]);
     let prod_slice = out.get(16..20).unwrap_or(&[0, 0, 0, 0]);
     let vendor_off = u32::from_le_bytes(vendor_slice.try_into().unwrap_or([0; 4])) as usize;
@@ -123,22 +142,93 @@ fn query_friendly_and_removable(handle: HANDLE) -> Result<(String, bool)> {
     let product = read_cstr(&out, prod_off).unwrap_or_default();
     let name = format!("{} {}", vendor, product).trim().to_string();
     let name = if name.is_empty() { "Unknown Disk".to_string() } else { name };
-
-    Ok((name, removable))
+    let serial_slice = out.get(24..28).unwrap_or(&[0, 0, 0, 0]);
+    let serial_off = u32::from_le_bytes(serial_slice.try_into().unwrap_or([0; 4])) as usize;
+    let serial = read_cstr(&out, serial_off);
+
+    Ok(DeviceDescriptor {
+        friendly_name: name,
+        removable,
+        serial,
+    })
 }
 
+/// `major.minor.build.ubr`, e.g. `10.0.22631.3447`. `GetVersionExW`
+/// reports 6.2 on Windows 10 and later unless the executable's manifest
+/// opts in, so the version comes from ntdll and the update build revision
+/// from the registry.
 pub fn os_version_string() -> String {
-    unsafe {
-        let mut info = OSVERSIONINFOW::default();
-        info.dwOSVersionInfoSize = size_of::<OSVERSIONINFOW>() as u32;
-        if GetVersionExW(&mut info).as_bool() {
-            return format!(
-                "{}.{}.{}",
-                info.dwMajorVersion, info.dwMinorVersion, info.dwBuildNumber
-            );
-        }
+    let mut info = OSVERSIONINFOW {
+        dwOSVersionInfoSize: size_of::<OSVERSIONINFOW>() as u32,
+        ..Default::default()
+    };
+    if unsafe { RtlGetVersion(&mut info) }.is_err() {
+        return "unknown".to_string();
     }
-    "unknown".to_string()
+    let mut version = format!(
+        "{}.{}.{}",
+        info.dwMajorVersion, info.dwMinorVersion, info.dwBuildNumber
+    );
+    if let Some(ubr) = current_version_dword("UBR") {
+        version.push_str(&format!(".{}", ubr));
+    }
+    version
+}
+
+/// EditionID, e.g. `Professional`, `Enterprise` or `ServerStandard`.
+pub fn os_edition_string() -> Option<String> {
+    current_version_string("EditionID")
+}
+
+/// Feature update name such as `23H2`; builds before 20H2 only have the
+/// numeric `ReleaseId`.
+pub fn os_display_version_string() -> Option<String> {
+    current_version_string("DisplayVersion").or_else(|| current_version_string("ReleaseId"))
+}
+
+fn current_version_string(name: &str) -> Option<String> {
+    let key = wide(CURRENT_VERSION_KEY);
+    let value = wide(name);
+    let mut buf = [0u16; 256];
+    let mut size = (buf.len() * 2) as u32;
+    let status = unsafe {
+        RegGetValueW(
+            HKEY_LOCAL_MACHINE,
+            PCWSTR(key.as_ptr()),
+            PCWSTR(value.as_ptr()),
+            RRF_RT_REG_SZ,
+            None,
+            Some(buf.as_mut_ptr() as *mut c_void),
+            Some(&mut size),
+        )
+    };
+    if status.is_err() {
+        return None;
+    }
+    let len = buf[..size as usize / 2]
+        .iter()
+        .position(|&c| c == 0)
+        .unwrap_or(size as usize / 2);
+    Some(String::from_utf16_lossy(&buf[..len])).filter(|text| !text.is_empty())
+}
+
+fn current_version_dword(name: &str) -> Option<u32> {
+    let key = wide(CURRENT_VERSION_KEY);
+    let value = wide(name);
+    let mut data = 0u32;
+    let mut size = size_of::<u32>() as u32;
+    let status = unsafe {
+        RegGetValueW(
+            HKEY_LOCAL_MACHINE,
+            PCWSTR(key.as_ptr()),
+            PCWSTR(value.as_ptr()),
+            RRF_RT_REG_DWORD,
+            None,
+            Some(&mut data as *mut u32 as *mut c_void),
+            Some(&mut size),
+        )
+    };
+    status.is_ok().then_some(data)
 }
 
 pub fn machine_name_string() -> String {
@@ -162,8 +252,11 @@ pub fn enumerate_physical_disks() -> Result<Vec<Disk>> {
         };
 
         let size_bytes = query_size_bytes(handle).unwrap_or(0);
-        let (friendly, removable) = query_friendly_and_removable(handle)
-            .unwrap_or(("Unknown Disk".to_string(), false));
+        let descriptor = query_device_descriptor(handle).unwrap_or(DeviceDescriptor {
+            friendly_name: "Unknown Disk".to_string(),
+            removable: false,
+            serial: None,
+        });
 
         unsafe {
             CloseHandle(handle);
@@ -171,11 +264,15 @@ pub fn enumerate_physical_disks() -> Result<Vec<Disk>> {
 
         disks.push(Disk {
             id: format!("PhysicalDrive{}", n),
-            friendly_name: friendly,
+            friendly_name: descriptor.friendly_name,
+            serial: descriptor.serial,
             size_bytes,
-            removable,
+            removable: descriptor.removable,
             is_system_disk: false,
             partitions: Vec::new(),
+            usb_port: None,
+            history: None,
+            booted_esp: None,
         });
     }
 
@@ -191,6 +288,9 @@ pub struct PartitionEntry {
     pub number: u32,
     pub offset_bytes: u64,
     pub length_bytes: u64,
+    /// GPT partition GUID, or `<MBR signature>-<number>` as Linux
+    /// reports PARTUUID for MBR disks.
+    pub part_uuid: Option<String>,
 }
 
 pub fn enumerate_partitions(disk_number: u32) -> Result<Vec<PartitionEntry>> {
@@ -238,10 +338,21 @@ pub fn enumerate_partitions(disk_number: u32) -> Result<Vec<PartitionEntry>> {
             continue;
         }
 
+        let part_uuid = if entry.PartitionStyle == PARTITION_STYLE_GPT {
+            let id = unsafe { entry.Anonymous.Gpt.PartitionId };
+            Some(uuid::Uuid::from_u128(id.to_u128()).to_string())
+        } else if entry.PartitionStyle == PARTITION_STYLE_MBR {
+            let signature = unsafe { layout.Anonymous.Mbr.Signature };
+            Some(format!("{:08x}-{:02x}", signature, entry.PartitionNumber))
+        } else {
+            None
+        };
+
         partitions.push(PartitionEntry {
             number: entry.PartitionNumber,
             offset_bytes: offset,
             length_bytes: length,
+            part_uuid,
         });
     }
 
@@ -0,0 +1,300 @@
+//! Disk image containers. A raw image is read as it is; qcow2, fixed
+//! VHD, DMG and sparse bundle images are decoded in Rust on every OS;
+//! VHDX and dynamic VHD images are attached read-only through the Windows
+//! Virtual Disk API, and DMGs with LZFSE, LZMA or ADC chunks through
+//! `hdiutil` on macOS, and read through the disk the OS gives them.
+//! Whatever the container, `open_image` reads as the plain disk it holds.
+
+use crate::dmg::{Udif, UdifReader, ENCRYPTED_MAGIC, KOLY_MAGIC};
+use crate::qcow2::{Qcow2Reader, QCOW2_MAGIC};
+use crate::sparsebundle::{is_sparse_bundle, SparseBundleReader};
+use crate::vhd::{parse_vhd_footer, VhdKind, VHD_FOOTER_BYTES};
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+
+const VHDX_SIGNATURE: &[u8; 8] = b"vhdxfile";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImageFormat {
+    Raw,
+    Vhd,
+    Vhdx,
+    Qcow2,
+    Dmg,
+    SparseBundle,
+}
+
+impl ImageFormat {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Raw => "raw",
+            Self::Vhd => "vhd",
+            Self::Vhdx => "vhdx",
+            Self::Qcow2 => "qcow2",
+            Self::Dmg => "dmg",
+            Self::SparseBundle => "sparsebundle",
+        }
+    }
+
+    /// The format a file name asks for; raw unless the extension names a
+    /// container.
+    pub fn from_extension(path: &Path) -> Self {
+        let extension = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        match extension.as_str() {
+            "vhd" => Self::Vhd,
+            "vhdx" => Self::Vhdx,
+            "qcow2" => Self::Qcow2,
+            "dmg" => Self::Dmg,
+            "sparsebundle" => Self::SparseBundle,
+            _ => Self::Raw,
+        }
+    }
+}
+
+pub fn parse_image_format(value: &str) -> Result<ImageFormat> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "raw" | "img" => Ok(ImageFormat::Raw),
+        "vhd" => Ok(ImageFormat::Vhd),
+        "vhdx" => Ok(ImageFormat::Vhdx),
+        "qcow2" => Ok(ImageFormat::Qcow2),
+        "dmg" => Ok(ImageFormat::Dmg),
+        "sparsebundle" => Ok(ImageFormat::SparseBundle),
+        other => Err(anyhow!(
+            "unknown image format: {} (expected raw, vhd, vhdx, qcow2, dmg or sparsebundle)",
+            other
+        )),
+    }
+}
+
+/// The container of the image at `path`, from its signature. A file
+/// without one is raw.
+pub fn detect_image_format(path: &Path) -> Result<ImageFormat> {
+    if path.is_dir() {
+        return bundle_format(path);
+    }
+    let mut file = File::open(path).with_context(|| format!("open {}", path.display()))?;
+    Ok(probe(&mut file)?.0)
+}
+
+/// The format and, for a VHD, the kind and disk size from its footer.
+fn probe(file: &mut File) -> Result<(ImageFormat, Option<(VhdKind, u64)>)> {
+    let len = file.metadata()?.len();
+    let mut head = [0u8; 8];
+    file.seek(SeekFrom::Start(0))?;
+    let head_len = file.read(&mut head)?;
+    if head_len >= 4 && &head[..4] == QCOW2_MAGIC {
+        return Ok((ImageFormat::Qcow2, None));
+    }
+    if head_len == 8 && &head == VHDX_SIGNATURE {
+        return Ok((ImageFormat::Vhdx, None));
+    }
+    if head_len == 8 && &head == ENCRYPTED_MAGIC {
+        return Ok((ImageFormat::Dmg, None));
+    }
+    // A VHD footer and a DMG trailer both take the last 512 bytes.
+    if len >= VHD_FOOTER_BYTES {
+        let mut footer = [0u8; VHD_FOOTER_BYTES as usize];
+        file.seek(SeekFrom::Start(len - VHD_FOOTER_BYTES))?;
+        file.read_exact(&mut footer)?;
+        if &footer[..4] == KOLY_MAGIC {
+            return Ok((ImageFormat::Dmg, None));
+        }
+        if let Some(vhd) = parse_vhd_footer(&footer) {
+            return Ok((ImageFormat::Vhd, Some(vhd)));
+        }
+    }
+    Ok((ImageFormat::Raw, None))
+}
+
+fn bundle_format(path: &Path) -> Result<ImageFormat> {
+    if is_sparse_bundle(path) {
+        Ok(ImageFormat::SparseBundle)
+    } else {
+        Err(anyhow!("{} is a directory, not a disk image", path.display()))
+    }
+}
+
+trait DiskRead: Read + Seek + Send {}
+
+impl<T: Read + Seek + Send> DiskRead for T {}
+
+/// An image opened as the disk it holds.
+pub struct OpenImage {
+    pub format: ImageFormat,
+    /// Size of the disk in the image, not of the file.
+    pub disk_bytes: u64,
+    reader: Box<dyn DiskRead>,
+    // Keeps an attached image attached while it is read; dropped after
+    // `reader`.
+    _attached: Option<Box<dyn Send>>,
+}
+
+impl std::fmt::Debug for OpenImage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("OpenImage")
+            .field("format", &self.format)
+            .field("disk_bytes", &self.disk_bytes)
+            .finish()
+    }
+}
+
+impl OpenImage {
+    fn new(format: ImageFormat, disk_bytes: u64, reader: Box<dyn DiskRead>) -> Self {
+        Self {
+            format,
+            disk_bytes,
+            reader,
+            _attached: None,
+        }
+    }
+}
+
+impl Read for OpenImage {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reader.read(buf)
+    }
+}
+
+impl Seek for OpenImage {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.reader.seek(pos)
+    }
+}
+
+/// Opens the image at `path` read-only, in whatever container it is in.
+pub fn open_image(path: &Path) -> Result<OpenImage> {
+    if path.is_dir() {
+        bundle_format(path)?;
+        let reader = SparseBundleReader::open(path)?;
+        return Ok(OpenImage::new(ImageFormat::SparseBundle, reader.disk_bytes(), Box::new(reader)));
+    }
+    let mut file = File::open(path).with_context(|| format!("open {}", path.display()))?;
+    let len = file.metadata()?.len();
+    let (format, vhd) = probe(&mut file)?;
+    file.seek(SeekFrom::Start(0))?;
+    match (format, vhd) {
+        (ImageFormat::Raw, _) => Ok(OpenImage::new(format, len, Box::new(file))),
+        (ImageFormat::Qcow2, _) => {
+            let reader = Qcow2Reader::new(file).with_context(|| format!("read {}", path.display()))?;
+            Ok(OpenImage::new(format, reader.virtual_size(), Box::new(reader)))
+        }
+        (ImageFormat::Vhd, Some((VhdKind::Fixed, disk_bytes))) => {
+            if disk_bytes > len - VHD_FOOTER_BYTES {
+                return Err(anyhow!(
+                    "{} claims a {} byte disk but holds {}",
+                    path.display(),
+                    disk_bytes,
+                    len - VHD_FOOTER_BYTES
+                ));
+            }
+            Ok(OpenImage::new(format, disk_bytes, Box::new(Window::new(file, disk_bytes))))
+        }
+        (ImageFormat::Dmg, _) => open_dmg(path, file),
+        _ => open_attached(path, format),
+    }
+}
+
+fn open_dmg(path: &Path, mut file: File) -> Result<OpenImage> {
+    let mut head = [0u8; 8];
+    if file.read(&mut head)? == 8 && &head == ENCRYPTED_MAGIC {
+        return Err(anyhow!("{} is an encrypted DMG; encrypted images are not supported", path.display()));
+    }
+    let udif = Udif::read(&mut file).with_context(|| format!("read {}", path.display()))?;
+    if let Some(compression) = udif.unsupported() {
+        return attach_dmg(path, compression, udif.disk_bytes());
+    }
+    let reader = UdifReader::new(file)?;
+    Ok(OpenImage::new(ImageFormat::Dmg, reader.disk_bytes(), Box::new(reader)))
+}
+
+/// Reads a DMG this crate cannot inflate through the disk `hdiutil`
+/// attaches it as.
+#[cfg(target_os = "macos")]
+fn attach_dmg(path: &Path, _compression: &str, disk_bytes: u64) -> Result<OpenImage> {
+    let attached = crate::hdiutil::AttachedImage::attach(path)?;
+    let device = File::open(attached.device()).with_context(|| format!("open {}", attached.device()))?;
+    let mut image = OpenImage::new(ImageFormat::Dmg, disk_bytes, Box::new(Window::new(device, disk_bytes)));
+    image._attached = Some(Box::new(attached));
+    Ok(image)
+}
+
+#[cfg(not(target_os = "macos"))]
+fn attach_dmg(path: &Path, compression: &str, _disk_bytes: u64) -> Result<OpenImage> {
+    Err(anyhow!(
+        "{} is a {}-compressed DMG, which only macOS can read (hdiutil); convert it with `hdiutil convert -format UDZO`",
+        path.display(),
+        compression
+    ))
+}
+
+#[cfg(windows)]
+fn open_attached(path: &Path, format: ImageFormat) -> Result<OpenImage> {
+    let mut disk = crate::vdisk::VirtualDisk::open(path, format, true)?;
+    let physical = disk.attach(true)?;
+    let device = File::open(&physical).with_context(|| format!("open {}", physical))?;
+    let disk_bytes = crate::vdisk::disk_length(&device)?;
+    let mut image = OpenImage::new(format, disk_bytes, Box::new(Window::new(device, disk_bytes)));
+    image._attached = Some(Box::new(disk));
+    Ok(image)
+}
+
+#[cfg(not(windows))]
+fn open_attached(path: &Path, format: ImageFormat) -> Result<OpenImage> {
+    Err(anyhow!(
+        "{} is a {} image, which only Windows can open (Virtual Disk API); convert it to raw or qcow2",
+        path.display(),
+        if format == ImageFormat::Vhd { "dynamic VHD" } else { "VHDX" }
+    ))
+}
+
+/// The first `len` bytes of `inner`, which EOF ends rather than a footer
+/// or the rest of a device.
+struct Window<R> {
+    inner: R,
+    len: u64,
+    position: u64,
+}
+
+impl<R> Window<R> {
+    fn new(inner: R, len: u64) -> Self {
+        Self {
+            inner,
+            len,
+            position: 0,
+        }
+    }
+}
+
+impl<R: Read + Seek> Read for Window<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = (buf.len() as u64).min(self.len.saturating_sub(self.position)) as usize;
+        if len == 0 {
+            return Ok(0);
+        }
+        self.inner.seek(SeekFrom::Start(self.position))?;
+        let read = self.inner.read(&mut buf[..len])?;
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+impl<R: Read + Seek> Seek for Window<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(at) => Some(at),
+            SeekFrom::End(delta) => self.len.checked_add_signed(delta),
+            SeekFrom::Current(delta) => self.position.checked_add_signed(delta),
+        };
+        self.position = target
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "seek before the start of the image"))?;
+        Ok(self.position)
+    }
+}
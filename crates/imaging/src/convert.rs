@@ -0,0 +1,145 @@
+//! Converting a disk image between containers: a captured raw image to a
+//! VHD or VHDX that Hyper-V or QEMU boots as it is, or a VM-built VHD,
+//! VHDX, qcow2 or DMG back to raw. The source is read through `open_image`, so
+//! any container it reads converts. Zero blocks are left as holes in raw
+//! and VHD output and unwritten in VHDX output.
+
+use crate::container::{open_image, ImageFormat};
+use crate::vhd::fixed_vhd_footer;
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::{self, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// Zero blocks this long are skipped rather than written.
+const HOLE_BYTES: usize = 64 * 1024;
+const COPY_BYTES: usize = 8 * 1024 * 1024;
+/// VHD and VHDX disks are whole sectors.
+const SECTOR_BYTES: u64 = 512;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConvertResult {
+    pub source_format: ImageFormat,
+    pub format: ImageFormat,
+    /// Size of the disk in the output; the source's, rounded up to whole
+    /// sectors for VHD and VHDX.
+    pub disk_bytes: u64,
+    /// Bytes written; the rest of the disk is holes.
+    pub data_bytes: u64,
+    /// Of the disk, as `hash-image` would compute it for a raw copy.
+    pub sha256: String,
+}
+
+/// Copies the disk in the image at `source` to `destination` in `format`.
+/// `destination` is replaced.
+pub fn convert_image(source: &Path, destination: &Path, format: ImageFormat) -> Result<ConvertResult> {
+    if fs::canonicalize(destination).ok() == Some(fs::canonicalize(source)?) {
+        return Err(anyhow!("converted image must not replace {}", source.display()));
+    }
+    let mut image = open_image(source)?;
+    let source_format = image.format;
+    let disk_bytes = match format {
+        ImageFormat::Raw => image.disk_bytes,
+        _ => image.disk_bytes.div_ceil(SECTOR_BYTES) * SECTOR_BYTES,
+    };
+
+    let (data_bytes, sha256) = match format {
+        ImageFormat::Raw | ImageFormat::Vhd => {
+            let mut output = OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(destination)
+                .with_context(|| format!("create {}", destination.display()))?;
+            let copied = copy_disk(&mut image, disk_bytes, &mut output)?;
+            output.set_len(disk_bytes)?;
+            if format == ImageFormat::Vhd {
+                output.seek(SeekFrom::Start(disk_bytes))?;
+                output.write_all(&fixed_vhd_footer(disk_bytes, copied.1.as_bytes()))?;
+            }
+            output.sync_all()?;
+            copied
+        }
+        ImageFormat::Vhdx => write_vhdx(&mut image, disk_bytes, destination)?,
+        ImageFormat::Qcow2 | ImageFormat::Dmg | ImageFormat::SparseBundle => {
+            return Err(anyhow!(
+                "{} images are read, not written; convert to raw, vhd or vhdx",
+                format.as_str()
+            ))
+        }
+    };
+
+    Ok(ConvertResult {
+        source_format,
+        format,
+        disk_bytes,
+        data_bytes,
+        sha256,
+    })
+}
+
+/// Copies `disk_bytes` from `image` to the same offsets of `output`,
+/// seeking over zero blocks; the source's end reads as zeros. Returns the
+/// bytes written and the SHA-256 of the disk.
+fn copy_disk<R: Read, W: Write + Seek>(image: &mut R, disk_bytes: u64, output: &mut W) -> Result<(u64, String)> {
+    let mut buffer = vec![0u8; COPY_BYTES];
+    let mut hasher = Sha256::new();
+    let mut position = 0u64;
+    let mut data_bytes = 0u64;
+    output.seek(SeekFrom::Start(0))?;
+    while position < disk_bytes {
+        let len = (disk_bytes - position).min(COPY_BYTES as u64) as usize;
+        let chunk = &mut buffer[..len];
+        let mut filled = 0;
+        while filled < len {
+            match image
+                .read(&mut chunk[filled..])
+                .with_context(|| format!("read image at offset {}", position + filled as u64))?
+            {
+                0 => break,
+                read => filled += read,
+            }
+        }
+        chunk[filled..].fill(0);
+        hasher.update(&*chunk);
+        for block in chunk.chunks(HOLE_BYTES) {
+            if block.iter().all(|byte| *byte == 0) {
+                output.seek(SeekFrom::Current(block.len() as i64))?;
+            } else {
+                output.write_all(block)?;
+                data_bytes += block.len() as u64;
+            }
+        }
+        position += len as u64;
+    }
+    Ok((data_bytes, crate::to_hex(&hasher.finalize())))
+}
+
+/// Creates a dynamic VHDX and writes the disk through the drive Windows
+/// attaches it as.
+#[cfg(windows)]
+fn write_vhdx<R: Read>(image: &mut R, disk_bytes: u64, destination: &Path) -> Result<(u64, String)> {
+    if destination.exists() {
+        fs::remove_file(destination).with_context(|| format!("replace {}", destination.display()))?;
+    }
+    let mut disk = crate::vdisk::VirtualDisk::create_vhdx(destination, disk_bytes)?;
+    let physical = disk.attach(false)?;
+    let mut device = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(&physical)
+        .with_context(|| format!("open {}", physical))?;
+    let copied = copy_disk(image, disk_bytes, &mut device)?;
+    device.sync_all()?;
+    Ok(copied)
+}
+
+#[cfg(not(windows))]
+fn write_vhdx<R: Read>(_image: &mut R, _disk_bytes: u64, _destination: &Path) -> Result<(u64, String)> {
+    Err(anyhow!(
+        "VHDX images are written through the Windows Virtual Disk API; convert to vhd, which Hyper-V also boots"
+    ))
+}
+
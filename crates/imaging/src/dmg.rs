@@ -0,0 +1,266 @@
+//! A reader for UDIF disk images (`.dmg`), the format `hdiutil` and Disk
+//! Utility write. The `koly` trailer points at a plist whose `blkx`
+//! tables map runs of disk sectors to chunks of the file. Raw, zero,
+//! zlib (UDZO) and bzip2 (UDBZ) chunks are read here on every OS; LZFSE
+//! (ULFO), LZMA (ULMO) and ADC images are left to `hdiutil` on macOS.
+
+use anyhow::{anyhow, Context, Result};
+use bzip2::read::BzDecoder;
+use flate2::read::ZlibDecoder;
+use std::io::{self, Read, Seek, SeekFrom};
+
+pub const KOLY_MAGIC: &[u8; 4] = b"koly";
+/// The header of an encrypted image, which hides the `koly` trailer.
+pub const ENCRYPTED_MAGIC: &[u8; 8] = b"encrcdsa";
+const KOLY_BYTES: u64 = 512;
+const MISH_MAGIC: &[u8; 4] = b"mish";
+const MISH_HEADER_BYTES: usize = 204;
+const CHUNK_ENTRY_BYTES: usize = 40;
+const SECTOR_BYTES: u64 = 512;
+/// Larger chunks than `hdiutil` ever writes are taken as corruption.
+const MAX_CHUNK_BYTES: u64 = 64 * 1024 * 1024;
+
+const CHUNK_ZERO: u32 = 0x0000_0000;
+const CHUNK_RAW: u32 = 0x0000_0001;
+const CHUNK_IGNORE: u32 = 0x0000_0002;
+const CHUNK_ADC: u32 = 0x8000_0004;
+const CHUNK_ZLIB: u32 = 0x8000_0005;
+const CHUNK_BZIP2: u32 = 0x8000_0006;
+const CHUNK_LZFSE: u32 = 0x8000_0007;
+const CHUNK_LZMA: u32 = 0x8000_0008;
+const CHUNK_COMMENT: u32 = 0x7FFF_FFFE;
+const CHUNK_LAST: u32 = 0xFFFF_FFFF;
+
+#[derive(Debug, Clone)]
+struct Chunk {
+    kind: u32,
+    /// Disk offset and length, in bytes.
+    start: u64,
+    len: u64,
+    /// File offset and length of the stored data.
+    offset: u64,
+    stored: u64,
+}
+
+/// The chunk map of a UDIF image.
+#[derive(Debug, Clone)]
+pub struct Udif {
+    disk_bytes: u64,
+    chunks: Vec<Chunk>,
+}
+
+impl Udif {
+    pub fn read<R: Read + Seek>(file: &mut R) -> Result<Self> {
+        let len = file.seek(SeekFrom::End(0))?;
+        if len < KOLY_BYTES {
+            return Err(anyhow!("not a UDIF image"));
+        }
+        let mut koly = [0u8; KOLY_BYTES as usize];
+        file.seek(SeekFrom::Start(len - KOLY_BYTES))?;
+        file.read_exact(&mut koly)?;
+        if &koly[..4] != KOLY_MAGIC {
+            return Err(anyhow!("not a UDIF image"));
+        }
+        let data_fork = be_u64(&koly, 24);
+        let xml_offset = be_u64(&koly, 216);
+        let xml_len = be_u64(&koly, 224);
+        let disk_bytes = be_u64(&koly, 492) * SECTOR_BYTES;
+        if xml_len == 0 || xml_offset.saturating_add(xml_len) > len {
+            return Err(anyhow!("UDIF image has no chunk table"));
+        }
+
+        let mut xml = vec![0u8; xml_len as usize];
+        file.seek(SeekFrom::Start(xml_offset))?;
+        file.read_exact(&mut xml).context("read UDIF plist")?;
+        let plist = plist::Value::from_reader_xml(&xml[..]).context("parse UDIF plist")?;
+        let tables = plist
+            .as_dictionary()
+            .and_then(|root| root.get("resource-fork"))
+            .and_then(|fork| fork.as_dictionary())
+            .and_then(|fork| fork.get("blkx"))
+            .and_then(|blkx| blkx.as_array())
+            .ok_or_else(|| anyhow!("UDIF plist has no blkx tables"))?;
+
+        let mut chunks = Vec::new();
+        for table in tables {
+            let data = table
+                .as_dictionary()
+                .and_then(|table| table.get("Data"))
+                .and_then(|data| data.as_data())
+                .ok_or_else(|| anyhow!("UDIF blkx entry has no Data"))?;
+            parse_mish(data, data_fork, &mut chunks)?;
+        }
+        chunks.sort_by_key(|chunk| chunk.start);
+        for chunk in &chunks {
+            if chunk.start.saturating_add(chunk.len) > disk_bytes
+                || chunk.offset.saturating_add(chunk.stored) > len
+                || chunk.len > MAX_CHUNK_BYTES
+            {
+                return Err(anyhow!("UDIF chunk at sector {} is out of range", chunk.start / SECTOR_BYTES));
+            }
+        }
+        Ok(Self { disk_bytes, chunks })
+    }
+
+    pub fn disk_bytes(&self) -> u64 {
+        self.disk_bytes
+    }
+
+    /// The compression of a chunk this reader cannot inflate, if any.
+    pub fn unsupported(&self) -> Option<&'static str> {
+        self.chunks.iter().find_map(|chunk| match chunk.kind {
+            CHUNK_ADC => Some("ADC"),
+            CHUNK_LZFSE => Some("LZFSE"),
+            CHUNK_LZMA => Some("LZMA"),
+            _ => None,
+        })
+    }
+}
+
+/// Appends the data chunks of one `mish` table.
+fn parse_mish(data: &[u8], data_fork: u64, chunks: &mut Vec<Chunk>) -> Result<()> {
+    if data.len() < MISH_HEADER_BYTES || &data[..4] != MISH_MAGIC {
+        return Err(anyhow!("UDIF blkx table is not a mish table"));
+    }
+    let first_sector = be_u64(data, 8);
+    let data_offset = be_u64(data, 24);
+    let count = be_u32(data, 200) as usize;
+    if data.len() < MISH_HEADER_BYTES + count * CHUNK_ENTRY_BYTES {
+        return Err(anyhow!("UDIF mish table is truncated"));
+    }
+    for entry in data[MISH_HEADER_BYTES..].chunks_exact(CHUNK_ENTRY_BYTES).take(count) {
+        let kind = be_u32(entry, 0);
+        match kind {
+            CHUNK_COMMENT | CHUNK_IGNORE => continue,
+            CHUNK_LAST => break,
+            CHUNK_ZERO | CHUNK_RAW | CHUNK_ADC | CHUNK_ZLIB | CHUNK_BZIP2 | CHUNK_LZFSE | CHUNK_LZMA => {}
+            other => return Err(anyhow!("UDIF chunk type {:#x} is not supported", other)),
+        }
+        if kind == CHUNK_ZERO {
+            continue;
+        }
+        chunks.push(Chunk {
+            kind,
+            start: (first_sector + be_u64(entry, 8)) * SECTOR_BYTES,
+            len: be_u64(entry, 16) * SECTOR_BYTES,
+            offset: data_fork + data_offset + be_u64(entry, 24),
+            stored: be_u64(entry, 32),
+        });
+    }
+    Ok(())
+}
+
+/// Reads the disk a UDIF image holds. Sectors outside every chunk, and
+/// zero chunks, read as zeros.
+pub struct UdifReader<R> {
+    inner: R,
+    udif: Udif,
+    /// The chunk inflated last, by its index.
+    inflated: Option<(usize, Vec<u8>)>,
+    position: u64,
+}
+
+impl<R: Read + Seek> UdifReader<R> {
+    pub fn new(mut inner: R) -> Result<Self> {
+        let udif = Udif::read(&mut inner)?;
+        if let Some(compression) = udif.unsupported() {
+            return Err(anyhow!("{}-compressed DMG images are only read through hdiutil", compression));
+        }
+        Ok(Self {
+            inner,
+            udif,
+            inflated: None,
+            position: 0,
+        })
+    }
+
+    pub fn disk_bytes(&self) -> u64 {
+        self.udif.disk_bytes
+    }
+
+    fn inflate(&mut self, index: usize) -> io::Result<()> {
+        if self.inflated.as_ref().map(|(inflated, _)| *inflated) == Some(index) {
+            return Ok(());
+        }
+        let chunk = &self.udif.chunks[index];
+        let mut stored = Vec::with_capacity(chunk.stored as usize);
+        self.inner.seek(SeekFrom::Start(chunk.offset))?;
+        (&mut self.inner).take(chunk.stored).read_to_end(&mut stored)?;
+        let mut data = Vec::with_capacity(chunk.len as usize);
+        if chunk.kind == CHUNK_ZLIB {
+            ZlibDecoder::new(&stored[..]).take(chunk.len).read_to_end(&mut data)?;
+        } else {
+            BzDecoder::new(&stored[..]).take(chunk.len).read_to_end(&mut data)?;
+        }
+        if (data.len() as u64) < chunk.len {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("UDIF chunk at {} inflates to {} bytes", chunk.start, data.len()),
+            ));
+        }
+        self.inflated = Some((index, data));
+        Ok(())
+    }
+}
+
+impl<R: Read + Seek> Read for UdifReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position >= self.udif.disk_bytes || buf.is_empty() {
+            return Ok(0);
+        }
+        let position = self.position;
+        let chunks = &self.udif.chunks;
+        // The last chunk starting at or before `position`, and whether it
+        // covers it; otherwise zeros run up to the next chunk.
+        let next = chunks.partition_point(|chunk| chunk.start <= position);
+        let covering = next
+            .checked_sub(1)
+            .filter(|index| position < chunks[*index].start + chunks[*index].len);
+        let end = match covering {
+            Some(index) => chunks[index].start + chunks[index].len,
+            None => chunks.get(next).map_or(self.udif.disk_bytes, |chunk| chunk.start),
+        };
+        let len = (buf.len() as u64).min(end - position) as usize;
+        match covering {
+            None => buf[..len].fill(0),
+            Some(index) => {
+                let within = position - chunks[index].start;
+                if chunks[index].kind == CHUNK_RAW {
+                    let offset = chunks[index].offset + within;
+                    let stored = chunks[index].stored.saturating_sub(within).min(len as u64) as usize;
+                    self.inner.seek(SeekFrom::Start(offset))?;
+                    self.inner.read_exact(&mut buf[..stored])?;
+                    buf[stored..len].fill(0);
+                } else {
+                    self.inflate(index)?;
+                    if let Some((_, data)) = &self.inflated {
+                        buf[..len].copy_from_slice(&data[within as usize..within as usize + len]);
+                    }
+                }
+            }
+        }
+        self.position += len as u64;
+        Ok(len)
+    }
+}
+
+impl<R: Read + Seek> Seek for UdifReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(at) => Some(at),
+            SeekFrom::End(delta) => self.udif.disk_bytes.checked_add_signed(delta),
+            SeekFrom::Current(delta) => self.position.checked_add_signed(delta),
+        };
+        self.position = target
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "seek before the start of the image"))?;
+        Ok(self.position)
+    }
+}
+
+fn be_u32(buffer: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(buffer[offset..offset + 4].try_into().unwrap())
+}
+
+fn be_u64(buffer: &[u8], offset: usize) -> u64 {
+    u64::from_be_bytes(buffer[offset..offset + 8].try_into().unwrap())
+}
@@ -0,0 +1,68 @@
+//! Periodic flushes during long writes. Without them a power cut loses
+//! whatever the page cache or the drive's write cache still held, and a
+//! progress figure says nothing about what survived. With
+//! `flush_every_bytes` set, the device is synced each time that many more
+//! bytes have been written, and progress reports how far is durable.
+
+use crate::retry::retry_io;
+use std::fs::File;
+use std::io;
+
+#[derive(Debug, Clone, Default)]
+pub struct Flusher {
+    /// `0` flushes only at the end.
+    every: u64,
+    written: u64,
+    durable: u64,
+    flushes: u64,
+}
+
+impl Flusher {
+    pub fn new(flush_every_bytes: Option<u64>) -> Self {
+        Self {
+            every: flush_every_bytes.unwrap_or(0),
+            ..Self::default()
+        }
+    }
+
+    /// Counts `bytes` more written to `device` and syncs it when a flush is
+    /// due. Nothing past the returned sync is reported durable.
+    pub fn wrote(&mut self, device: &File, bytes: u64) -> io::Result<()> {
+        self.written = self.written.saturating_add(bytes);
+        if self.every > 0 && self.written - self.durable >= self.every {
+            self.flush(device)?;
+        }
+        Ok(())
+    }
+
+    /// Syncs everything written so far; a no-op when nothing is pending.
+    pub fn flush(&mut self, device: &File) -> io::Result<()> {
+        if self.written == self.durable {
+            return Ok(());
+        }
+        let written = self.written;
+        retry_io(|| format!("flush device at {}", written), || device.sync_all())?;
+        self.durable = written;
+        self.flushes += 1;
+        Ok(())
+    }
+
+    /// The final sync after the last write. Before periodic flushes existed
+    /// this sync was best effort, and it still is when none were asked for.
+    pub fn finish(&mut self, device: &File) -> io::Result<()> {
+        let result = self.flush(device);
+        if self.every == 0 {
+            return Ok(());
+        }
+        result
+    }
+
+    /// Bytes known to be on the media.
+    pub fn durable_bytes(&self) -> u64 {
+        self.durable
+    }
+
+    pub fn flushes(&self) -> u64 {
+        self.flushes
+    }
+}
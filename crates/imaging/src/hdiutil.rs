@@ -0,0 +1,55 @@
+//! Disk images attached through `hdiutil`, without mounting any of their
+//! volumes. Used for DMGs whose chunks this crate cannot inflate; the
+//! image is detached when the handle is dropped.
+
+use anyhow::{anyhow, Context, Result};
+use std::path::Path;
+use std::process::Command;
+
+const HDIUTIL: &str = "/usr/bin/hdiutil";
+
+#[derive(Debug)]
+pub struct AttachedImage {
+    /// The whole disk, e.g. `/dev/disk4`.
+    device: String,
+}
+
+impl AttachedImage {
+    pub fn attach(path: &Path) -> Result<Self> {
+        let output = Command::new(HDIUTIL)
+            .args(["attach", "-nomount", "-readonly", "-noverify", "-noautofsck", "-plist"])
+            .arg(path)
+            .output()
+            .context("run hdiutil")?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "hdiutil attach {} failed: {}",
+                path.display(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        let plist = plist::Value::from_reader_xml(&output.stdout[..]).context("parse hdiutil attach output")?;
+        // Every partition is listed too; the whole disk has the shortest name.
+        let device = plist
+            .as_dictionary()
+            .and_then(|root| root.get("system-entities"))
+            .and_then(|entities| entities.as_array())
+            .into_iter()
+            .flatten()
+            .filter_map(|entity| entity.as_dictionary()?.get("dev-entry")?.as_string())
+            .min_by_key(|device| device.len())
+            .ok_or_else(|| anyhow!("hdiutil attach {} listed no device", path.display()))?
+            .to_string();
+        Ok(Self { device })
+    }
+
+    pub fn device(&self) -> &str {
+        &self.device
+    }
+}
+
+impl Drop for AttachedImage {
+    fn drop(&mut self) {
+        let _ = Command::new(HDIUTIL).args(["detach", &self.device]).output();
+    }
+}
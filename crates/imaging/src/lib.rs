@@ -1,6 +1,43 @@
 use anyhow::{anyhow, Result};
 use sha2::{Digest, Sha256};
 use std::fmt::Write as _;
+use std::path::Path;
+
+pub mod container;
+pub mod convert;
+pub mod dmg;
+pub mod flush;
+#[cfg(target_os = "macos")]
+mod hdiutil;
+#[cfg(any(unix, windows))]
+pub mod pipeline;
+pub mod qcow2;
+pub mod retry;
+pub mod sample;
+pub mod scan;
+pub mod sparsebundle;
+pub mod trim;
+pub mod tune;
+#[cfg(windows)]
+mod vdisk;
+pub mod vhd;
+
+pub use container::{detect_image_format, open_image, parse_image_format, ImageFormat, OpenImage};
+pub use convert::{convert_image, ConvertResult};
+pub use dmg::UdifReader;
+pub use flush::Flusher;
+
+#[cfg(any(unix, windows))]
+pub use pipeline::write_image_pipelined;
+pub use qcow2::Qcow2Reader;
+pub use sample::{verify_image_sampled, SampledVerify};
+pub use scan::{
+    scan_device, scan_open_device, BadRange, BadSectorKind, ScanMode, ScanObserver, ScanOptions,
+    ScanProgress, ScanResult,
+};
+pub use sparsebundle::SparseBundleReader;
+pub use trim::{trim_image, TrimResult, TrimmedPartition};
+pub use tune::{tune_chunk_size, ChunkSample, ChunkTuning, IoHints, DEFAULT_CHUNK_SIZE};
 
 #[derive(Debug, Clone)]
 pub struct ChunkPlan {
@@ -33,6 +70,8 @@ pub struct WriteProgress {
     pub total_bytes: u64,
     pub chunk_index: u64,
     pub total_chunks: u64,
+    /// Bytes synced to the device so far; see `flush`.
+    pub durable_bytes: u64,
 }
 
 pub trait WriteObserver {
@@ -97,6 +136,47 @@ pub fn hash_disk_readonly_physicaldrive_with_progress(
     chunk_size: u64,
     max_chunks: Option<u64>,
     observer: &mut dyn ProgressObserver,
+) -> Result<Vec<(u64, String)>> {
+    hash_disk_range_readonly_physicaldrive_with_progress(
+        disk_id,
+        0,
+        total_size,
+        chunk_size,
+        max_chunks,
+        observer,
+    )
+}
+
+/// Hashes `length` bytes from `range_offset`, e.g. one partition. Chunk
+/// indices count from the start of the range; the offset must be
+/// sector-aligned, as partition offsets are.
+#[cfg(windows)]
+pub fn hash_disk_range_readonly_physicaldrive(
+    disk_id: &str,
+    range_offset: u64,
+    length: u64,
+    chunk_size: u64,
+    max_chunks: Option<u64>,
+) -> Result<Vec<(u64, String)>> {
+    let mut observer = NoopObserver;
+    hash_disk_range_readonly_physicaldrive_with_progress(
+        disk_id,
+        range_offset,
+        length,
+        chunk_size,
+        max_chunks,
+        &mut observer,
+    )
+}
+
+#[cfg(windows)]
+pub fn hash_disk_range_readonly_physicaldrive_with_progress(
+    disk_id: &str,
+    range_offset: u64,
+    total_size: u64,
+    chunk_size: u64,
+    max_chunks: Option<u64>,
+    observer: &mut dyn ProgressObserver,
 ) -> Result<Vec<(u64, String)>> {
     use windows::core::PCWSTR;
     use windows::Win32::Foundation::{CloseHandle, INVALID_HANDLE_VALUE};
@@ -140,14 +220,17 @@ pub fn hash_disk_readonly_physicaldrive_with_progress(
             let mut new_pos = 0i64;
             let ok_seek = SetFilePointerEx(
                 handle,
-                chunk.offset as i64,
+                (range_offset + chunk.offset) as i64,
                 Some(&mut new_pos),
                 FILE_BEGIN,
             )
             .as_bool();
             if !ok_seek {
                 CloseHandle(handle);
-                return Err(anyhow!("SetFilePointerEx failed at offset {}", chunk.offset));
+                return Err(anyhow!(
+                    "SetFilePointerEx failed at offset {}",
+                    range_offset + chunk.offset
+                ));
             }
 
             if chunk.size > u32::MAX as u64 {
@@ -213,12 +296,48 @@ pub fn hash_disk_readonly_physicaldrive_with_progress(
     Err(anyhow!("Windows-only in M0"))
 }
 
+#[cfg(not(windows))]
+pub fn hash_disk_range_readonly_physicaldrive(
+    _disk_id: &str,
+    _range_offset: u64,
+    _length: u64,
+    _chunk_size: u64,
+    _max_chunks: Option<u64>,
+) -> Result<Vec<(u64, String)>> {
+    Err(anyhow!("Windows-only in M0"))
+}
+
+#[cfg(not(windows))]
+pub fn hash_disk_range_readonly_physicaldrive_with_progress(
+    _disk_id: &str,
+    _range_offset: u64,
+    _length: u64,
+    _chunk_size: u64,
+    _max_chunks: Option<u64>,
+    _observer: &mut dyn ProgressObserver,
+) -> Result<Vec<(u64, String)>> {
+    Err(anyhow!("Windows-only in M0"))
+}
+
 #[cfg(unix)]
 pub fn hash_device_readonly(
     device_path: &str,
     total_size: u64,
     chunk_size: u64,
     max_chunks: Option<u64>,
+) -> Result<Vec<(u64, String)>> {
+    hash_device_range_readonly(device_path, 0, total_size, chunk_size, max_chunks)
+}
+
+/// Hashes `length` bytes from `range_offset`, e.g. one partition. Chunk
+/// indices count from the start of the range.
+#[cfg(unix)]
+pub fn hash_device_range_readonly(
+    device_path: &str,
+    range_offset: u64,
+    length: u64,
+    chunk_size: u64,
+    max_chunks: Option<u64>,
 ) -> Result<Vec<(u64, String)>> {
     use std::fs::File;
     use std::io::{Read, Seek, SeekFrom};
@@ -228,13 +347,13 @@ pub fn hash_device_readonly(
     }
     let mut file = File::open(device_path)
         .map_err(|err| anyhow!("open {} failed: {}", device_path, err))?;
-    let plan = make_chunk_plan(total_size, chunk_size);
+    let plan = make_chunk_plan(length, chunk_size);
     let limit = max_chunks.unwrap_or(u64::MAX) as usize;
     let mut results = Vec::new();
     let mut buffer = vec![0u8; chunk_size as usize];
 
     for chunk in plan.chunks.iter().take(limit) {
-        file.seek(SeekFrom::Start(chunk.offset))?;
+        file.seek(SeekFrom::Start(range_offset + chunk.offset))?;
         let mut remaining = chunk.size as usize;
         let mut hasher = Sha256::new();
         while remaining > 0 {
@@ -263,15 +382,34 @@ pub fn hash_device_readonly(
     Err(anyhow!("device hashing requires Unix-like OS"))
 }
 
+#[cfg(not(unix))]
+pub fn hash_device_range_readonly(
+    _device_path: &str,
+    _range_offset: u64,
+    _length: u64,
+    _chunk_size: u64,
+    _max_chunks: Option<u64>,
+) -> Result<Vec<(u64, String)>> {
+    Err(anyhow!("device hashing requires Unix-like OS"))
+}
+
 #[cfg(unix)]
 pub fn write_image_to_device(
     image_path: &Path,
     device_path: &Path,
     chunk_size: u64,
     verify: bool,
+    flush_every_bytes: Option<u64>,
 ) -> Result<WriteResult> {
     let mut observer = NoopWriteObserver;
-    write_image_to_device_with_progress(image_path, device_path, chunk_size, verify, &mut observer)
+    write_image_to_device_with_progress(
+        image_path,
+        device_path,
+        chunk_size,
+        verify,
+        flush_every_bytes,
+        &mut observer,
+    )
 }
 
 #[cfg(unix)]
@@ -280,66 +418,134 @@ pub fn write_image_to_device_with_progress(
     device_path: &Path,
     chunk_size: u64,
     verify: bool,
+    flush_every_bytes: Option<u64>,
     observer: &mut dyn WriteObserver,
 ) -> Result<WriteResult> {
-    use std::fs::{File, OpenOptions};
-    use std::io::{Read, Seek, SeekFrom, Write};
-
-    if chunk_size == 0 {
-        return Err(anyhow!("chunk_size must be greater than zero"));
-    }
+    use std::fs::OpenOptions;
 
-    let mut image = File::open(image_path)
-        .map_err(|err| anyhow!("open {} failed: {}", image_path.display(), err))?;
-    let total_bytes = image.metadata()?.len();
+    phoenix_safety::ensure_writable("image write")?;
     let mut device = OpenOptions::new()
+        .read(true)
         .write(true)
         .open(device_path)
         .map_err(|err| anyhow!("open {} failed: {}", device_path.display(), err))?;
+    write_image_to_open_device(
+        image_path,
+        &mut device,
+        chunk_size,
+        verify,
+        flush_every_bytes,
+        observer,
+    )
+}
+
+/// Writes an image through an already-open read/write device handle (for
+/// example one passed back by a privileged helper). Verification re-reads
+/// through the same handle. VHD and qcow2 images are written as the disk
+/// they hold; see `open_image`.
+#[cfg(unix)]
+pub fn write_image_to_open_device(
+    image_path: &Path,
+    device: &mut std::fs::File,
+    chunk_size: u64,
+    verify: bool,
+    flush_every_bytes: Option<u64>,
+    observer: &mut dyn WriteObserver,
+) -> Result<WriteResult> {
+    let mut image = open_image(image_path)?;
+    let total_bytes = image.disk_bytes;
+    write_stream_to_open_device(
+        &mut image,
+        total_bytes,
+        device,
+        chunk_size,
+        verify,
+        flush_every_bytes,
+        observer,
+    )
+}
+
+/// Like `write_image_to_open_device`, but reads `total_bytes` sequentially
+/// from `source`, e.g. a network stream that is never stored locally.
+#[cfg(unix)]
+pub fn write_stream_to_open_device(
+    source: &mut dyn std::io::Read,
+    total_bytes: u64,
+    device: &mut std::fs::File,
+    chunk_size: u64,
+    verify: bool,
+    flush_every_bytes: Option<u64>,
+    observer: &mut dyn WriteObserver,
+) -> Result<WriteResult> {
+    use std::io::{Seek, SeekFrom, Write};
+
+    phoenix_safety::ensure_writable("image write")?;
+    if chunk_size == 0 {
+        return Err(anyhow!("chunk_size must be greater than zero"));
+    }
+
+    device.seek(SeekFrom::Start(0))?;
 
     let plan = make_chunk_plan(total_bytes, chunk_size);
     let total_chunks = plan.chunks.len() as u64;
     let mut buffer = vec![0u8; chunk_size as usize];
     let mut hasher = Sha256::new();
     let mut bytes_written = 0u64;
+    let mut flusher = Flusher::new(flush_every_bytes);
 
     for chunk in &plan.chunks {
-        image.seek(SeekFrom::Start(chunk.offset))?;
         let mut remaining = chunk.size as usize;
         while remaining > 0 {
             let read_len = remaining.min(buffer.len());
-            let read = image.read(&mut buffer[..read_len])?;
+            let read = source.read(&mut buffer[..read_len])?;
             if read == 0 {
                 return Err(anyhow!("unexpected EOF while reading image"));
             }
-            device.write_all(&buffer[..read])?;
+            // A retry rewrites the whole piece from where it started.
+            let offset = bytes_written;
+            retry::retry_io(
+                || format!("write device at {}", offset),
+                || {
+                    device.seek(SeekFrom::Start(offset))?;
+                    device.write_all(&buffer[..read])
+                },
+            )?;
             hasher.update(&buffer[..read]);
             bytes_written = bytes_written.saturating_add(read as u64);
             remaining -= read;
         }
+        flusher.wrote(device, chunk.size)?;
         let progress = WriteProgress {
             bytes_written,
             total_bytes,
             chunk_index: chunk.index,
             total_chunks,
+            durable_bytes: flusher.durable_bytes(),
         };
         if !observer.on_progress(progress) {
             return Err(anyhow!("write operation cancelled"));
         }
     }
-    device.sync_all().ok();
+    flusher.finish(device)?;
 
     let sha256 = to_hex(&hasher.finalize());
 
     let mut verify_ok = None;
     if verify {
+        use std::io::Read;
+
         let mut verify_hasher = Sha256::new();
-        let mut device_reader = File::open(device_path)?;
-        device_reader.seek(SeekFrom::Start(0))?;
         let mut remaining = total_bytes;
         while remaining > 0 {
             let read_len = (remaining as usize).min(buffer.len());
-            let read = device_reader.read(&mut buffer[..read_len])?;
+            let offset = total_bytes - remaining;
+            let read = retry::retry_io(
+                || format!("verify device at {}", offset),
+                || {
+                    device.seek(SeekFrom::Start(offset))?;
+                    device.read(&mut buffer[..read_len])
+                },
+            )?;
             if read == 0 {
                 return Err(anyhow!("unexpected EOF while verifying device"));
             }
@@ -355,6 +561,7 @@ pub fn write_image_to_device_with_progress(
         total_bytes,
         sha256,
         verify_ok,
+        flushes: flusher.flushes(),
     })
 }
 
@@ -364,6 +571,7 @@ pub fn write_image_to_device(
     _device_path: &Path,
     _chunk_size: u64,
     _verify: bool,
+    _flush_every_bytes: Option<u64>,
 ) -> Result<WriteResult> {
     Err(anyhow!("device writing requires Unix-like OS"))
 }
@@ -374,6 +582,20 @@ pub fn write_image_to_device_with_progress(
     _device_path: &Path,
     _chunk_size: u64,
     _verify: bool,
+    _flush_every_bytes: Option<u64>,
+    _observer: &mut dyn WriteObserver,
+) -> Result<WriteResult> {
+    Err(anyhow!("device writing requires Unix-like OS"))
+}
+
+#[cfg(not(unix))]
+pub fn write_stream_to_open_device(
+    _source: &mut dyn std::io::Read,
+    _total_bytes: u64,
+    _device: &mut std::fs::File,
+    _chunk_size: u64,
+    _verify: bool,
+    _flush_every_bytes: Option<u64>,
     _observer: &mut dyn WriteObserver,
 ) -> Result<WriteResult> {
     Err(anyhow!("device writing requires Unix-like OS"))
@@ -385,10 +607,14 @@ pub struct WriteResult {
     pub total_bytes: u64,
     pub sha256: String,
     pub verify_ok: Option<bool>,
+    /// Device syncs made, the final one included.
+    pub flushes: u64,
 }
 
+#[cfg(windows)]
 struct NoopObserver;
 
+#[cfg(windows)]
 impl ProgressObserver for NoopObserver {
     fn on_progress(&mut self, _progress: HashProgress) -> bool {
         true
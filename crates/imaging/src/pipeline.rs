@@ -0,0 +1,201 @@
+use crate::retry::{record_worker, retry_io, take_failures, take_retries};
+use crate::{make_chunk_plan, to_hex, Flusher, WriteObserver, WriteProgress, WriteResult};
+use anyhow::{anyhow, Result};
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::path::Path;
+use std::sync::mpsc;
+use std::thread;
+
+/// Buffers in flight: one being filled from the image while the other is
+/// written to the device.
+const BUFFERS: usize = 2;
+
+struct Filled {
+    index: u64,
+    offset: u64,
+    buffer: Vec<u8>,
+    len: usize,
+}
+
+/// Double-buffered image write. A reader thread fills the next chunk with
+/// positional reads (`pread` on Unix, overlapped-offset `ReadFile` on
+/// Windows) and hashes it while the calling thread writes the previous one
+/// with positional writes, so source reads overlap device writes. Progress
+/// and cancellation behave like `write_image_to_open_device`.
+pub fn write_image_pipelined(
+    image_path: &Path,
+    device: &File,
+    chunk_size: u64,
+    verify: bool,
+    flush_every_bytes: Option<u64>,
+    observer: &mut dyn WriteObserver,
+) -> Result<WriteResult> {
+    phoenix_safety::ensure_writable("image write")?;
+    if chunk_size == 0 {
+        return Err(anyhow!("chunk_size must be greater than zero"));
+    }
+    if chunk_size > usize::MAX as u64 {
+        return Err(anyhow!("chunk_size too large for buffer allocation"));
+    }
+
+    let image = File::open(image_path)
+        .map_err(|err| anyhow!("open {} failed: {}", image_path.display(), err))?;
+    let total_bytes = image.metadata()?.len();
+    let plan = make_chunk_plan(total_bytes, chunk_size);
+    let total_chunks = plan.chunks.len() as u64;
+
+    let (filled_tx, filled_rx) = mpsc::sync_channel::<Filled>(BUFFERS);
+    let (empty_tx, empty_rx) = mpsc::channel::<Vec<u8>>();
+    for _ in 0..BUFFERS {
+        empty_tx.send(vec![0u8; chunk_size as usize]).ok();
+    }
+
+    let mut bytes_written = 0u64;
+    let mut flusher = Flusher::new(flush_every_bytes);
+    let (read_result, write_result) = thread::scope(|scope| {
+        let reader = scope.spawn(move || {
+            let result = (|| -> Result<String> {
+                let mut hasher = Sha256::new();
+                for chunk in &plan.chunks {
+                    let Ok(mut buffer) = empty_rx.recv() else {
+                        // Writer stopped; its error is reported instead.
+                        return Ok(String::new());
+                    };
+                    let len = chunk.size as usize;
+                    retry_io(
+                        || format!("read image at {}", chunk.offset),
+                        || read_exact_at(&image, &mut buffer[..len], chunk.offset),
+                    )
+                    .map_err(|err| anyhow!("read image at {} failed: {}", chunk.offset, err))?;
+                    hasher.update(&buffer[..len]);
+                    let filled = Filled {
+                        index: chunk.index,
+                        offset: chunk.offset,
+                        buffer,
+                        len,
+                    };
+                    if filled_tx.send(filled).is_err() {
+                        return Ok(String::new());
+                    }
+                }
+                Ok(to_hex(&hasher.finalize()))
+            })();
+            // Retries are recorded per thread; hand the reader's back.
+            (result, take_retries(), take_failures())
+        });
+
+        let write_result = (|| -> Result<()> {
+            for filled in filled_rx.iter() {
+                retry_io(
+                    || format!("write device at {}", filled.offset),
+                    || write_all_at(device, &filled.buffer[..filled.len], filled.offset),
+                )
+                .map_err(|err| anyhow!("write device at {} failed: {}", filled.offset, err))?;
+                bytes_written = bytes_written.saturating_add(filled.len as u64);
+                empty_tx.send(filled.buffer).ok();
+                flusher.wrote(device, filled.len as u64)?;
+                let progress = WriteProgress {
+                    bytes_written,
+                    total_bytes,
+                    chunk_index: filled.index,
+                    total_chunks,
+                    durable_bytes: flusher.durable_bytes(),
+                };
+                if !observer.on_progress(progress) {
+                    return Err(anyhow!("write operation cancelled"));
+                }
+            }
+            Ok(())
+        })();
+        // Unblocks the reader if the writer bailed out early.
+        drop(filled_rx);
+        drop(empty_tx);
+        let read_result = match reader.join() {
+            Ok((result, retries, failures)) => {
+                record_worker(retries, failures);
+                result
+            }
+            Err(_) => Err(anyhow!("image reader thread panicked")),
+        };
+        (read_result, write_result)
+    });
+    write_result?;
+    let sha256 = read_result?;
+    if bytes_written != total_bytes {
+        return Err(anyhow!(
+            "wrote {} of {} bytes",
+            bytes_written,
+            total_bytes
+        ));
+    }
+    flusher.finish(device)?;
+
+    let mut verify_ok = None;
+    if verify {
+        let mut buffer = vec![0u8; chunk_size as usize];
+        let mut hasher = Sha256::new();
+        let mut offset = 0u64;
+        while offset < total_bytes {
+            let len = (total_bytes - offset).min(chunk_size) as usize;
+            retry_io(
+                || format!("verify device at {}", offset),
+                || read_exact_at(device, &mut buffer[..len], offset),
+            )
+            .map_err(|err| anyhow!("verify read at {} failed: {}", offset, err))?;
+            hasher.update(&buffer[..len]);
+            offset += len as u64;
+        }
+        verify_ok = Some(to_hex(&hasher.finalize()) == sha256);
+    }
+
+    Ok(WriteResult {
+        bytes_written,
+        total_bytes,
+        sha256,
+        verify_ok,
+        flushes: flusher.flushes(),
+    })
+}
+
+#[cfg(unix)]
+fn read_exact_at(file: &File, buf: &mut [u8], offset: u64) -> std::io::Result<()> {
+    use std::os::unix::fs::FileExt;
+    file.read_exact_at(buf, offset)
+}
+
+#[cfg(unix)]
+fn write_all_at(file: &File, buf: &[u8], offset: u64) -> std::io::Result<()> {
+    use std::os::unix::fs::FileExt;
+    file.write_all_at(buf, offset)
+}
+
+#[cfg(windows)]
+fn read_exact_at(file: &File, mut buf: &mut [u8], mut offset: u64) -> std::io::Result<()> {
+    use std::os::windows::fs::FileExt;
+    while !buf.is_empty() {
+        match file.seek_read(buf, offset)? {
+            0 => return Err(std::io::ErrorKind::UnexpectedEof.into()),
+            read => {
+                buf = &mut buf[read..];
+                offset += read as u64;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(windows)]
+fn write_all_at(file: &File, mut buf: &[u8], mut offset: u64) -> std::io::Result<()> {
+    use std::os::windows::fs::FileExt;
+    while !buf.is_empty() {
+        match file.seek_write(buf, offset)? {
+            0 => return Err(std::io::ErrorKind::WriteZero.into()),
+            written => {
+                buf = &buf[written..];
+                offset += written as u64;
+            }
+        }
+    }
+    Ok(())
+}
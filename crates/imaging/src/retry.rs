@@ -0,0 +1,147 @@
+//! Retries for transient device errors. A flaky hub or cable surfaces as
+//! EIO or `ERROR_IO_DEVICE` on one chunk and then recovers; those are
+//! retried with exponential backoff a bounded number of times, while
+//! errors that will not go away (no space, read-only, permission) fail at
+//! once. Every retry, and the last errors given up on, are recorded on the
+//! calling thread for the report.
+
+use serde::Serialize;
+use std::cell::RefCell;
+use std::io;
+use std::time::Duration;
+
+/// Retries after the first attempt; `0` turns retrying off.
+pub const RETRIES_ENV: &str = "PHOENIX_IO_RETRIES";
+const DEFAULT_RETRIES: u32 = 4;
+const BASE_DELAY: Duration = Duration::from_millis(500);
+const MAX_DELAY: Duration = Duration::from_secs(8);
+/// I/O errors kept per thread for failure diagnostics.
+const KEPT_FAILURES: usize = 20;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RetryEvent {
+    pub utc: String,
+    /// What failed, such as `write device at 1048576`.
+    pub target: String,
+    /// 1 for the first retry.
+    pub attempt: u32,
+    pub error: String,
+    pub delay_ms: u64,
+}
+
+/// An I/O error returned to the caller, transient or not.
+#[derive(Debug, Clone, Serialize)]
+pub struct IoFailure {
+    pub utc: String,
+    pub target: String,
+    /// Attempts made, the first included.
+    pub attempts: u32,
+    pub transient: bool,
+    pub error: String,
+}
+
+thread_local! {
+    static RETRIES: RefCell<Vec<RetryEvent>> = const { RefCell::new(Vec::new()) };
+    static FAILURES: RefCell<Vec<IoFailure>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Retries recorded on this thread since the last call.
+pub fn take_retries() -> Vec<RetryEvent> {
+    RETRIES.with(|retries| std::mem::take(&mut *retries.borrow_mut()))
+}
+
+/// The last I/O errors returned on this thread since the last call,
+/// oldest first.
+pub fn take_failures() -> Vec<IoFailure> {
+    FAILURES.with(|failures| std::mem::take(&mut *failures.borrow_mut()))
+}
+
+/// Adds what a worker thread recorded to this thread's record.
+pub fn record_worker(retries: Vec<RetryEvent>, failures: Vec<IoFailure>) {
+    RETRIES.with(|kept| kept.borrow_mut().extend(retries));
+    for failure in failures {
+        keep_failure(failure);
+    }
+}
+
+fn keep_failure(failure: IoFailure) {
+    FAILURES.with(|failures| {
+        let mut failures = failures.borrow_mut();
+        if failures.len() == KEPT_FAILURES {
+            failures.remove(0);
+        }
+        failures.push(failure);
+    });
+}
+
+/// `PHOENIX_IO_RETRIES`, else 4. An unparsable value is ignored.
+pub fn max_retries() -> u32 {
+    std::env::var(RETRIES_ENV)
+        .ok()
+        .and_then(|value| value.trim().parse().ok())
+        .unwrap_or(DEFAULT_RETRIES)
+}
+
+/// Errors a reconnecting device or a busy hub produces and later stops
+/// producing.
+pub fn is_transient(err: &io::Error) -> bool {
+    if matches!(
+        err.kind(),
+        io::ErrorKind::Interrupted | io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock
+    ) {
+        return true;
+    }
+    let Some(code) = err.raw_os_error() else {
+        return false;
+    };
+    if cfg!(windows) {
+        // ERROR_NOT_READY, ERROR_CRC, ERROR_GEN_FAILURE, ERROR_SEM_TIMEOUT,
+        // ERROR_IO_DEVICE.
+        matches!(code, 21 | 23 | 31 | 121 | 1117)
+    } else {
+        // EIO on Linux and macOS.
+        code == 5
+    }
+}
+
+/// Runs `op` until it succeeds, fails with a non-transient error, or the
+/// retries run out. `op` must be safe to repeat: positional I/O, or a
+/// seek back before a sequential one.
+pub fn retry_io<T>(
+    target: impl Fn() -> String,
+    mut op: impl FnMut() -> io::Result<T>,
+) -> io::Result<T> {
+    let retries = max_retries();
+    let mut attempt = 0;
+    loop {
+        match op() {
+            Ok(value) => return Ok(value),
+            Err(err) if attempt < retries && is_transient(&err) => {
+                attempt += 1;
+                let delay = BASE_DELAY
+                    .saturating_mul(1 << (attempt - 1).min(16))
+                    .min(MAX_DELAY);
+                RETRIES.with(|events| {
+                    events.borrow_mut().push(RetryEvent {
+                        utc: phoenix_core::now_utc_rfc3339(),
+                        target: target(),
+                        attempt,
+                        error: err.to_string(),
+                        delay_ms: delay.as_millis() as u64,
+                    })
+                });
+                std::thread::sleep(delay);
+            }
+            Err(err) => {
+                keep_failure(IoFailure {
+                    utc: phoenix_core::now_utc_rfc3339(),
+                    target: target(),
+                    attempts: attempt + 1,
+                    transient: is_transient(&err),
+                    error: err.to_string(),
+                });
+                return Err(err);
+            }
+        }
+    }
+}
@@ -0,0 +1,301 @@
+//! Surface scans that find bad sectors before a device is used. A read
+//! scan reads every sector. A write scan first fills the device with a
+//! pattern derived from each byte offset and then reads it back, which
+//! also catches sticks that claim more capacity than they have. A chunk
+//! that fails is rescanned sector by sector, so the result maps the bad
+//! regions rather than whole chunks.
+//!
+//! Reads and writes here skip the transient-error retries: a sector that
+//! only works on the third attempt is what the scan is looking for.
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScanMode {
+    Read,
+    /// Destroys the device's contents.
+    Write,
+}
+
+impl ScanMode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ScanMode::Read => "read",
+            ScanMode::Write => "write",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BadSectorKind {
+    Unreadable,
+    Unwritable,
+    /// Read back other data than the write scan wrote.
+    Mismatch,
+}
+
+impl BadSectorKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            BadSectorKind::Unreadable => "unreadable",
+            BadSectorKind::Unwritable => "unwritable",
+            BadSectorKind::Mismatch => "mismatch",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BadRange {
+    pub offset: u64,
+    pub length: u64,
+    pub kind: BadSectorKind,
+}
+
+#[derive(Debug, Clone)]
+pub struct ScanOptions {
+    /// A multiple of `sector_size`.
+    pub chunk_size: u64,
+    /// Granularity of the rescan of failed chunks.
+    pub sector_size: u64,
+    /// Stop once more than this many bytes are bad.
+    pub max_bad_bytes: Option<u64>,
+    /// Mixed into the write pattern, so data left by an earlier scan does
+    /// not pass.
+    pub seed: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ScanProgress {
+    /// The pass running: a write scan writes, then reads.
+    pub pass: ScanMode,
+    pub scanned_bytes: u64,
+    pub total_bytes: u64,
+    pub bad_bytes: u64,
+}
+
+pub trait ScanObserver {
+    fn on_progress(&mut self, progress: ScanProgress) -> bool;
+}
+
+pub struct NoopScanObserver;
+
+impl ScanObserver for NoopScanObserver {
+    fn on_progress(&mut self, _progress: ScanProgress) -> bool {
+        true
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ScanResult {
+    /// Bytes covered by the read pass, or by the write pass if the scan
+    /// stopped during it.
+    pub scanned_bytes: u64,
+    /// Sorted by offset; adjacent sectors of one kind are merged.
+    pub bad_ranges: Vec<BadRange>,
+    /// Set when `max_bad_bytes` was passed before the end of the device.
+    pub stopped_early: bool,
+}
+
+impl ScanResult {
+    pub fn bad_bytes(&self) -> u64 {
+        self.bad_ranges.iter().map(|range| range.length).sum()
+    }
+}
+
+pub fn scan_device(
+    device: &Path,
+    total_size: u64,
+    mode: ScanMode,
+    options: &ScanOptions,
+    observer: &mut dyn ScanObserver,
+) -> Result<ScanResult> {
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(mode == ScanMode::Write)
+        .open(device)
+        .map_err(|err| anyhow!("open {} failed: {}", device.display(), err))?;
+    scan_open_device(&mut file, total_size, mode, options, observer)
+}
+
+/// Scans an already opened device, e.g. one claimed exclusively.
+pub fn scan_open_device(
+    file: &mut File,
+    total_size: u64,
+    mode: ScanMode,
+    options: &ScanOptions,
+    observer: &mut dyn ScanObserver,
+) -> Result<ScanResult> {
+    if options.sector_size == 0 || options.chunk_size == 0 {
+        return Err(anyhow!("chunk_size and sector_size must be greater than zero"));
+    }
+    if !options.chunk_size.is_multiple_of(options.sector_size) {
+        return Err(anyhow!(
+            "chunk_size {} is not a multiple of sector_size {}",
+            options.chunk_size,
+            options.sector_size
+        ));
+    }
+    if mode == ScanMode::Write {
+        phoenix_safety::ensure_writable("write scan")?;
+    }
+    let mut scan = Scan {
+        file,
+        total_size,
+        options,
+        observer,
+        result: ScanResult::default(),
+        buffer: vec![0u8; options.chunk_size as usize],
+        pattern: Vec::new(),
+    };
+    if mode == ScanMode::Write {
+        scan.pattern = vec![0u8; options.chunk_size as usize];
+        if scan.pass(ScanMode::Write)? {
+            scan.file.sync_all()?;
+            drop_cached(scan.file);
+            scan.pass(ScanMode::Read)?;
+        }
+    } else {
+        scan.pass(ScanMode::Read)?;
+    }
+    let mut result = scan.result;
+    result.bad_ranges.sort_by_key(|range| range.offset);
+    Ok(result)
+}
+
+struct Scan<'a> {
+    file: &'a mut File,
+    total_size: u64,
+    options: &'a ScanOptions,
+    observer: &'a mut dyn ScanObserver,
+    result: ScanResult,
+    buffer: Vec<u8>,
+    /// Expected contents of the current chunk; empty for a read scan.
+    pattern: Vec<u8>,
+}
+
+impl Scan<'_> {
+    /// One pass over the device. `false` when it stopped early.
+    fn pass(&mut self, pass: ScanMode) -> Result<bool> {
+        let mut offset = 0u64;
+        while offset < self.total_size {
+            let len = (self.total_size - offset).min(self.options.chunk_size) as usize;
+            if !self.pattern.is_empty() {
+                fill_pattern(&mut self.pattern[..len], offset, self.options.seed);
+            }
+            let ok = match pass {
+                ScanMode::Write => write_at(self.file, offset, &self.pattern[..len]).is_ok(),
+                ScanMode::Read => {
+                    read_at(self.file, offset, &mut self.buffer[..len]).is_ok()
+                        && (self.pattern.is_empty() || self.buffer[..len] == self.pattern[..len])
+                }
+            };
+            if !ok {
+                self.rescan_sectors(pass, offset, len);
+            }
+            offset += len as u64;
+            self.result.scanned_bytes = offset;
+            let bad_bytes = self.result.bad_bytes();
+            let progress = ScanProgress {
+                pass,
+                scanned_bytes: offset,
+                total_bytes: self.total_size,
+                bad_bytes,
+            };
+            if !self.observer.on_progress(progress) {
+                return Err(anyhow!("scan cancelled"));
+            }
+            if self.options.max_bad_bytes.is_some_and(|max| bad_bytes > max) {
+                self.result.stopped_early = true;
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    fn rescan_sectors(&mut self, pass: ScanMode, chunk_offset: u64, chunk_len: usize) {
+        let sector = self.options.sector_size as usize;
+        let mut start = 0usize;
+        while start < chunk_len {
+            let end = (start + sector).min(chunk_len);
+            let offset = chunk_offset + start as u64;
+            let kind = match pass {
+                ScanMode::Write => write_at(self.file, offset, &self.pattern[start..end])
+                    .err()
+                    .map(|_| BadSectorKind::Unwritable),
+                ScanMode::Read => match read_at(self.file, offset, &mut self.buffer[start..end]) {
+                    Err(_) => Some(BadSectorKind::Unreadable),
+                    Ok(()) if !self.pattern.is_empty()
+                        && self.buffer[start..end] != self.pattern[start..end] =>
+                    {
+                        Some(BadSectorKind::Mismatch)
+                    }
+                    Ok(()) => None,
+                },
+            };
+            if let Some(kind) = kind {
+                self.mark(offset, (end - start) as u64, kind);
+            }
+            start = end;
+        }
+    }
+
+    /// Records a bad sector, unless the write pass already did.
+    fn mark(&mut self, offset: u64, length: u64, kind: BadSectorKind) {
+        let ranges = &mut self.result.bad_ranges;
+        if ranges
+            .iter()
+            .any(|range| offset >= range.offset && offset + length <= range.offset + range.length)
+        {
+            return;
+        }
+        match ranges.last_mut() {
+            Some(last) if last.kind == kind && last.offset + last.length == offset => {
+                last.length += length;
+            }
+            _ => ranges.push(BadRange {
+                offset,
+                length,
+                kind,
+            }),
+        }
+    }
+}
+
+fn read_at(file: &mut File, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(buf)
+}
+
+fn write_at(file: &mut File, offset: u64, buf: &[u8]) -> io::Result<()> {
+    file.seek(SeekFrom::Start(offset))?;
+    file.write_all(buf)
+}
+
+/// Each 8-byte word holds its own offset mixed with the seed, so a stick
+/// that wraps writes around to lower addresses reads back wrong data.
+fn fill_pattern(buf: &mut [u8], offset: u64, seed: u64) {
+    for (index, word) in buf.chunks_mut(8).enumerate() {
+        let value = (offset + index as u64 * 8) ^ seed;
+        let bytes = value.to_le_bytes();
+        word.copy_from_slice(&bytes[..word.len()]);
+    }
+}
+
+/// Drops the written pattern from the page cache, so the read pass sees
+/// the media. Raw devices on other hosts are uncached.
+#[cfg(target_os = "linux")]
+fn drop_cached(file: &File) {
+    use std::os::unix::io::AsRawFd;
+    unsafe {
+        libc::posix_fadvise(file.as_raw_fd(), 0, 0, libc::POSIX_FADV_DONTNEED);
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn drop_cached(_file: &File) {}
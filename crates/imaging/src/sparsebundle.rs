@@ -0,0 +1,122 @@
+//! A reader for sparse bundles (`.sparsebundle`), the directory images
+//! `hdiutil` makes with `-type SPARSEBUNDLE`. `Info.plist` gives the disk
+//! size and band size; band `n` of the disk is the file `bands/<n in
+//! hex>`, and a missing or short band reads as zeros. Encrypted bundles
+//! are refused.
+
+use crate::dmg::ENCRYPTED_MAGIC;
+use anyhow::{anyhow, Context, Result};
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+const BUNDLE_TYPE: &str = "com.apple.diskimage.sparsebundle";
+
+/// Whether `path` is a sparse bundle directory.
+pub fn is_sparse_bundle(path: &Path) -> bool {
+    read_info(path).is_ok()
+}
+
+/// Disk size and band size from the bundle's `Info.plist`.
+fn read_info(path: &Path) -> Result<(u64, u64)> {
+    let info_path = path.join("Info.plist");
+    let info = plist::Value::from_file(&info_path).with_context(|| format!("read {}", info_path.display()))?;
+    let info = info
+        .as_dictionary()
+        .ok_or_else(|| anyhow!("{} is not a dictionary", info_path.display()))?;
+    if info.get("diskimage-bundle-type").and_then(|kind| kind.as_string()) != Some(BUNDLE_TYPE) {
+        return Err(anyhow!("{} is not a sparse bundle", path.display()));
+    }
+    let size = info.get("size").and_then(|size| size.as_unsigned_integer());
+    let band_size = info.get("band-size").and_then(|size| size.as_unsigned_integer());
+    match (size, band_size) {
+        (Some(size), Some(band_size)) if band_size > 0 => Ok((size, band_size)),
+        _ => Err(anyhow!("{} has no size or band-size", info_path.display())),
+    }
+}
+
+pub struct SparseBundleReader {
+    bands: PathBuf,
+    disk_bytes: u64,
+    band_bytes: u64,
+    /// The band read last, by index; `None` when it does not exist.
+    band: Option<(u64, Option<File>)>,
+    position: u64,
+}
+
+impl SparseBundleReader {
+    pub fn open(path: &Path) -> Result<Self> {
+        let (disk_bytes, band_bytes) = read_info(path)?;
+        let mut token = [0u8; 8];
+        let encrypted = File::open(path.join("token"))
+            .and_then(|mut file| file.read_exact(&mut token))
+            .is_ok()
+            && &token == ENCRYPTED_MAGIC;
+        if encrypted {
+            return Err(anyhow!("{} is encrypted; encrypted sparse bundles are not supported", path.display()));
+        }
+        Ok(Self {
+            bands: path.join("bands"),
+            disk_bytes,
+            band_bytes,
+            band: None,
+            position: 0,
+        })
+    }
+
+    pub fn disk_bytes(&self) -> u64 {
+        self.disk_bytes
+    }
+
+    fn band_file(&mut self, index: u64) -> io::Result<Option<&mut File>> {
+        if self.band.as_ref().map(|(band, _)| *band) != Some(index) {
+            let file = match File::open(self.bands.join(format!("{:x}", index))) {
+                Ok(file) => Some(file),
+                Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+                Err(err) => return Err(err),
+            };
+            self.band = Some((index, file));
+        }
+        Ok(self.band.as_mut().and_then(|(_, file)| file.as_mut()))
+    }
+}
+
+impl Read for SparseBundleReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position >= self.disk_bytes || buf.is_empty() {
+            return Ok(0);
+        }
+        let index = self.position / self.band_bytes;
+        let within = self.position % self.band_bytes;
+        let len = (buf.len() as u64)
+            .min(self.band_bytes - within)
+            .min(self.disk_bytes - self.position) as usize;
+        let mut filled = 0;
+        if let Some(file) = self.band_file(index)? {
+            file.seek(SeekFrom::Start(within))?;
+            while filled < len {
+                match file.read(&mut buf[filled..len])? {
+                    0 => break,
+                    read => filled += read,
+                }
+            }
+        }
+        buf[filled..len].fill(0);
+        self.position += len as u64;
+        Ok(len)
+    }
+}
+
+impl Seek for SparseBundleReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(at) => Some(at),
+            SeekFrom::End(delta) => self.disk_bytes.checked_add_signed(delta),
+            SeekFrom::Current(delta) => self.position.checked_add_signed(delta),
+        };
+        self.position = target
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "seek before the start of the image"))?;
+        Ok(self.position)
+    }
+}
+
@@ -0,0 +1,375 @@
+//! Trimming a captured device image. The partition table and the
+//! allocation of each FAT32 or exFAT volume tell which bytes hold
+//! anything; the trimmed copy keeps those, leaves unused space and zeros
+//! as holes in a sparse file, and ends after the last byte in use. A 64 GB
+//! stick with 5 GB on it makes a file of about 5 GB.
+//!
+//! Partitions with other file systems, and everything outside partitions
+//! up to the last one, are kept whole. A GPT's backup at the end of the
+//! device is dropped with the rest of the tail; the primary table still
+//! describes every partition, and GPT tools rebuild the backup once the
+//! image is on a stick.
+
+use anyhow::{anyhow, Context, Result};
+use phoenix_partition::{verify_partition_tables, DEFAULT_SECTOR_SIZE};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+use std::path::Path;
+
+/// Runs of zeros at least this long become holes.
+const HOLE_BYTES: usize = 64 * 1024;
+const COPY_BYTES: usize = 8 * 1024 * 1024;
+const MBR_EXTENDED_TYPES: [u8; 3] = [0x05, 0x0F, 0x85];
+const MBR_PROTECTIVE_TYPE: u8 = 0xEE;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrimmedPartition {
+    /// Partition number in its table; 0 for a volume without a table.
+    pub number: u32,
+    pub offset: u64,
+    pub length: u64,
+    /// `fat32` or `exfat`; unset when the partition was kept whole.
+    pub filesystem: Option<String>,
+    /// Bytes of the partition kept.
+    pub used_bytes: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrimResult {
+    pub source_bytes: u64,
+    /// Length of the trimmed image.
+    pub image_bytes: u64,
+    /// Bytes written; the rest of the image is holes.
+    pub data_bytes: u64,
+    /// `gpt`, `mbr` or `none`.
+    pub table: String,
+    pub partitions: Vec<TrimmedPartition>,
+    /// Of the trimmed image.
+    pub sha256: String,
+    pub warnings: Vec<String>,
+}
+
+/// Copies the image at `source` to `destination`, keeping only the bytes
+/// in use. `destination` is replaced.
+pub fn trim_image(source: &Path, destination: &Path) -> Result<TrimResult> {
+    let mut image = File::open(source).with_context(|| format!("open {}", source.display()))?;
+    let source_bytes = image.metadata()?.len();
+    if fs::canonicalize(destination).ok() == Some(fs::canonicalize(source)?) {
+        return Err(anyhow!("trimmed image must not replace {}", source.display()));
+    }
+
+    let mut warnings = Vec::new();
+    let PartitionTable {
+        kind: table,
+        sector_size,
+        extents,
+    } = read_partitions(&mut image, source_bytes, &mut warnings)?;
+    let table_end = extents.iter().map(|(_, extent)| extent.end).max().unwrap_or(source_bytes);
+
+    // Everything outside the partitions is kept, up to the last partition.
+    let mut keep = Vec::new();
+    let mut position = 0;
+    let mut sorted: Vec<&Range<u64>> = extents.iter().map(|(_, extent)| extent).collect();
+    sorted.sort_by_key(|extent| extent.start);
+    for extent in sorted {
+        if extent.start > position {
+            keep.push(position..extent.start);
+        }
+        position = position.max(extent.end);
+    }
+
+    let mut partitions = Vec::new();
+    for (number, extent) in &extents {
+        let (filesystem, used) = match volume_ranges(&mut image, extent) {
+            Ok(Some(volume)) => (Some(volume.filesystem.to_string()), volume.ranges),
+            Ok(None) => (None, vec![extent.clone()]),
+            Err(err) => {
+                warnings.push(format!("partition {} kept whole: {:#}", number, err));
+                (None, vec![extent.clone()])
+            }
+        };
+        partitions.push(TrimmedPartition {
+            number: *number,
+            offset: extent.start,
+            length: extent.end - extent.start,
+            filesystem,
+            used_bytes: used.iter().map(|range| range.end - range.start).sum(),
+        });
+        keep.extend(used);
+    }
+    keep.sort_by_key(|range| range.start);
+
+    let image_bytes = keep
+        .iter()
+        .map(|range| range.end)
+        .max()
+        .unwrap_or(0)
+        .div_ceil(sector_size)
+        * sector_size;
+    let image_bytes = image_bytes.min(source_bytes);
+    if table == "gpt" && image_bytes < source_bytes {
+        warnings.push("backup GPT dropped with the unused tail".to_string());
+    }
+    if table_end > image_bytes {
+        warnings.push(format!(
+            "image ends {} bytes before the end of its last partition",
+            table_end - image_bytes
+        ));
+    }
+
+    let mut output = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(destination)
+        .with_context(|| format!("create {}", destination.display()))?;
+    let mut copier = SparseCopy::new(&mut image, &mut output);
+    for range in &keep {
+        copier.copy(range.start.max(copier.position)..range.end.min(image_bytes))?;
+    }
+    copier.skip_to(image_bytes);
+    let (data_bytes, sha256) = copier.finish();
+    output.set_len(image_bytes)?;
+    output.sync_all()?;
+
+    Ok(TrimResult {
+        source_bytes,
+        image_bytes,
+        data_bytes,
+        table: table.to_string(),
+        partitions,
+        sha256,
+        warnings,
+    })
+}
+
+struct PartitionTable {
+    /// `gpt`, `mbr` or `none`.
+    kind: &'static str,
+    sector_size: u64,
+    /// Number and byte range of each partition.
+    extents: Vec<(u32, Range<u64>)>,
+}
+
+impl PartitionTable {
+    /// No table: one partition, numbered 0, covering the whole image.
+    fn whole(source_bytes: u64) -> Self {
+        Self {
+            kind: "none",
+            sector_size: DEFAULT_SECTOR_SIZE,
+            extents: vec![(0, 0..source_bytes)],
+        }
+    }
+}
+
+/// The partition table of the image. An image that starts with a volume
+/// has no table.
+fn read_partitions(image: &mut File, source_bytes: u64, warnings: &mut Vec<String>) -> Result<PartitionTable> {
+    if volume_name(image, 0)?.is_some() {
+        return Ok(PartitionTable::whole(source_bytes));
+    }
+    for sector_size in [DEFAULT_SECTOR_SIZE, 4096] {
+        if source_bytes < 4 * sector_size {
+            break;
+        }
+        let check = verify_partition_tables(image, source_bytes, sector_size)?;
+        let Some(header) = check.primary else {
+            continue;
+        };
+        if check.backup.is_none() {
+            warnings.push("image has no backup GPT".to_string());
+        }
+        let array_bytes = header.entry_count as u64 * header.entry_size as u64;
+        let mut entries = vec![0u8; array_bytes as usize];
+        image.seek(SeekFrom::Start(header.entries_lba * sector_size))?;
+        image.read_exact(&mut entries).context("read GPT entries")?;
+        let extents = entries
+            .chunks(header.entry_size as usize)
+            .zip(1..)
+            .filter(|(entry, _)| entry[..16].iter().any(|byte| *byte != 0))
+            .map(|(entry, number)| {
+                let first = read_u64(entry, 32) * sector_size;
+                let end = (read_u64(entry, 40) + 1) * sector_size;
+                (number, first.min(source_bytes)..end.min(source_bytes))
+            })
+            .filter(|(_, extent)| !extent.is_empty())
+            .collect();
+        return Ok(PartitionTable {
+            kind: "gpt",
+            sector_size,
+            extents,
+        });
+    }
+
+    let mut mbr = [0u8; 512];
+    image.seek(SeekFrom::Start(0))?;
+    image.read_exact(&mut mbr).context("read MBR")?;
+    if mbr[510..512] != [0x55, 0xAA] {
+        warnings.push("image has no partition table or known file system; kept whole".to_string());
+        return Ok(PartitionTable::whole(source_bytes));
+    }
+    let extents = mbr[446..510]
+        .chunks(16)
+        .zip(1..)
+        .filter(|(entry, _)| entry[4] != 0 && entry[4] != MBR_PROTECTIVE_TYPE)
+        .map(|(entry, number)| {
+            if MBR_EXTENDED_TYPES.contains(&entry[4]) {
+                warnings.push("extended MBR partition kept whole".to_string());
+            }
+            let first = read_u32(entry, 8) as u64 * DEFAULT_SECTOR_SIZE;
+            let count = read_u32(entry, 12) as u64 * DEFAULT_SECTOR_SIZE;
+            (number, first.min(source_bytes)..(first + count).min(source_bytes))
+        })
+        .filter(|(_, extent)| !extent.is_empty())
+        .collect();
+    Ok(PartitionTable {
+        kind: "mbr",
+        sector_size: DEFAULT_SECTOR_SIZE,
+        extents,
+    })
+}
+
+/// The file system at `offset`, if it is one whose allocation can be read.
+fn volume_name(image: &mut File, offset: u64) -> Result<Option<&'static str>> {
+    let mut boot = [0u8; 512];
+    image.seek(SeekFrom::Start(offset))?;
+    if image.read_exact(&mut boot).is_err() {
+        return Ok(None);
+    }
+    if &boot[3..11] == b"EXFAT   " {
+        return Ok(Some("exfat"));
+    }
+    if &boot[0x52..0x5A] == b"FAT32   " && boot[510..512] == [0x55, 0xAA] {
+        return Ok(Some("fat32"));
+    }
+    Ok(None)
+}
+
+struct VolumeUse {
+    filesystem: &'static str,
+    /// Image offsets.
+    ranges: Vec<Range<u64>>,
+}
+
+/// The ranges of `extent` its file system uses.
+fn volume_ranges(image: &mut File, extent: &Range<u64>) -> Result<Option<VolumeUse>> {
+    let Some(name) = volume_name(image, extent.start)? else {
+        return Ok(None);
+    };
+    let ranges = if name == "exfat" {
+        phoenix_fs_exfat::exfat_used_ranges(image, extent.start)?
+    } else {
+        phoenix_fs_fat32::fat32_used_ranges(&mut Window {
+            inner: image,
+            offset: extent.start,
+        })?
+    };
+    let ranges = ranges
+        .into_iter()
+        .map(|range| extent.start + range.start..(extent.start + range.end).min(extent.end))
+        .filter(|range| !range.is_empty())
+        .collect();
+    Ok(Some(VolumeUse {
+        filesystem: name,
+        ranges,
+    }))
+}
+
+/// `inner` seen from `offset` on, so a volume reader can start at 0.
+struct Window<'a> {
+    inner: &'a mut File,
+    offset: u64,
+}
+
+impl Read for Window<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl Seek for Window<'_> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(at) => SeekFrom::Start(self.offset + at),
+            other => other,
+        };
+        Ok(self.inner.seek(pos)?.saturating_sub(self.offset))
+    }
+}
+
+/// Copies ranges in order, seeking over gaps and zero runs and hashing
+/// every byte of the result, holes included.
+struct SparseCopy<'a> {
+    source: &'a mut File,
+    output: &'a mut File,
+    position: u64,
+    data_bytes: u64,
+    hasher: Sha256,
+    buffer: Vec<u8>,
+}
+
+impl<'a> SparseCopy<'a> {
+    fn new(source: &'a mut File, output: &'a mut File) -> Self {
+        Self {
+            source,
+            output,
+            position: 0,
+            data_bytes: 0,
+            hasher: Sha256::new(),
+            buffer: vec![0u8; COPY_BYTES],
+        }
+    }
+
+    fn copy(&mut self, range: Range<u64>) -> Result<()> {
+        if range.is_empty() {
+            return Ok(());
+        }
+        self.skip_to(range.start);
+        self.source.seek(SeekFrom::Start(range.start))?;
+        self.output.seek(SeekFrom::Start(range.start))?;
+        while self.position < range.end {
+            let len = (range.end - self.position).min(COPY_BYTES as u64) as usize;
+            let chunk = &mut self.buffer[..len];
+            self.source
+                .read_exact(chunk)
+                .with_context(|| format!("read image at offset {}", self.position))?;
+            self.hasher.update(&*chunk);
+            for block in chunk.chunks(HOLE_BYTES) {
+                if block.len() == HOLE_BYTES && block.iter().all(|byte| *byte == 0) {
+                    self.output.seek(SeekFrom::Current(block.len() as i64))?;
+                } else {
+                    self.output.write_all(block)?;
+                    self.data_bytes += block.len() as u64;
+                }
+            }
+            self.position += len as u64;
+        }
+        Ok(())
+    }
+
+    /// Leaves a hole up to `offset`.
+    fn skip_to(&mut self, offset: u64) {
+        let zeros = [0u8; HOLE_BYTES];
+        while self.position < offset {
+            let len = (offset - self.position).min(HOLE_BYTES as u64) as usize;
+            self.hasher.update(&zeros[..len]);
+            self.position += len as u64;
+        }
+    }
+
+    /// Bytes written and the SHA-256 of everything up to `position`.
+    fn finish(self) -> (u64, String) {
+        (self.data_bytes, crate::to_hex(&self.hasher.finalize()))
+    }
+}
+
+fn read_u32(buffer: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(buffer[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(buffer: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(buffer[offset..offset + 8].try_into().unwrap())
+}
@@ -0,0 +1,151 @@
+use anyhow::{anyhow, Result};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+use std::time::Instant;
+
+pub const DEFAULT_CHUNK_SIZE: u64 = 8 * 1024 * 1024;
+const MIB: u64 = 1024 * 1024;
+const CANDIDATES: [u64; 6] = [MIB, 2 * MIB, 4 * MIB, 8 * MIB, 16 * MIB, 32 * MIB];
+const SAMPLE_BYTES: u64 = 32 * MIB;
+/// A larger chunk has to beat a smaller one by this much to be chosen, so
+/// noise in the micro-benchmark does not inflate memory use.
+const MIN_GAIN: f64 = 1.05;
+
+/// What the OS reports about a block device before any IO is issued.
+#[derive(Debug, Clone, Default)]
+pub struct IoHints {
+    pub bus: Option<String>,
+    pub preferred_io_bytes: Option<u64>,
+    pub max_transfer_bytes: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ChunkSample {
+    pub chunk_size: u64,
+    pub bytes_per_sec: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct ChunkTuning {
+    pub chunk_size: u64,
+    pub hints: IoHints,
+    pub samples: Vec<ChunkSample>,
+}
+
+/// Probes `device` and returns the chunk size with the best sequential read
+/// throughput. The benchmark only reads, so it is safe to run before a write.
+pub fn tune_chunk_size(device: &Path, total_size: u64) -> Result<ChunkTuning> {
+    let hints = probe_io_hints(device);
+    let mut file = File::open(device)
+        .map_err(|err| anyhow!("open {} failed: {}", device.display(), err))?;
+    tune_chunk_size_with(&mut file, total_size, hints)
+}
+
+pub fn tune_chunk_size_with<R: Read + Seek>(
+    reader: &mut R,
+    total_size: u64,
+    hints: IoHints,
+) -> Result<ChunkTuning> {
+    let candidates = candidate_sizes(&hints);
+    let sample = SAMPLE_BYTES.min(total_size / candidates.len() as u64);
+    let mut samples = Vec::new();
+    if sample < candidates[candidates.len() - 1] {
+        return Ok(ChunkTuning {
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            hints,
+            samples,
+        });
+    }
+
+    let mut buffer = vec![0u8; candidates[candidates.len() - 1] as usize];
+    for (index, &chunk_size) in candidates.iter().enumerate() {
+        // Each candidate reads a fresh region so the page cache cannot help.
+        reader.seek(SeekFrom::Start(index as u64 * sample))?;
+        let start = Instant::now();
+        let mut remaining = sample;
+        while remaining > 0 {
+            let want = remaining.min(chunk_size) as usize;
+            let read = reader.read(&mut buffer[..want])?;
+            if read == 0 {
+                return Err(anyhow!("unexpected EOF while probing chunk size"));
+            }
+            remaining -= read as u64;
+        }
+        let secs = start.elapsed().as_secs_f64().max(1e-6);
+        samples.push(ChunkSample {
+            chunk_size,
+            bytes_per_sec: (sample as f64 / secs) as u64,
+        });
+    }
+
+    let mut best = samples[0];
+    for candidate in &samples[1..] {
+        if candidate.bytes_per_sec as f64 > best.bytes_per_sec as f64 * MIN_GAIN {
+            best = *candidate;
+        }
+    }
+    Ok(ChunkTuning {
+        chunk_size: best.chunk_size,
+        hints,
+        samples,
+    })
+}
+
+/// Candidate sizes rounded up to the device's preferred IO size.
+fn candidate_sizes(hints: &IoHints) -> Vec<u64> {
+    let align = hints.preferred_io_bytes.filter(|size| *size > 0).unwrap_or(1);
+    let mut sizes: Vec<u64> = CANDIDATES
+        .iter()
+        .map(|size| size.div_ceil(align) * align)
+        .collect();
+    sizes.dedup();
+    sizes
+}
+
+#[cfg(target_os = "linux")]
+pub fn probe_io_hints(device: &Path) -> IoHints {
+    use std::fs;
+
+    let Some(name) = device.file_name().map(|name| name.to_string_lossy().to_string()) else {
+        return IoHints::default();
+    };
+    let block = Path::new("/sys/class/block").join(&name);
+    // Partitions keep their queue limits on the parent disk.
+    let queue = if block.join("partition").exists() {
+        block.join("..").join("queue")
+    } else {
+        block.join("queue")
+    };
+    let read_u64 = |path: &Path| -> Option<u64> {
+        fs::read_to_string(path).ok()?.trim().parse().ok()
+    };
+    let bus = fs::canonicalize(&block).ok().and_then(|path| {
+        let path = path.to_string_lossy().to_string();
+        ["usb", "nvme", "mmc", "ata", "virtio", "scsi"]
+            .iter()
+            .find(|bus| path.contains(&format!("/{}", bus)))
+            .map(|bus| bus.to_string())
+    });
+    IoHints {
+        bus,
+        preferred_io_bytes: read_u64(&queue.join("optimal_io_size")).filter(|size| *size > 0),
+        max_transfer_bytes: read_u64(&queue.join("max_sectors_kb")).map(|kb| kb * 1024),
+    }
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+pub fn probe_io_hints(device: &Path) -> IoHints {
+    use std::os::unix::fs::MetadataExt;
+
+    IoHints {
+        bus: None,
+        preferred_io_bytes: std::fs::metadata(device).ok().map(|meta| meta.blksize()),
+        max_transfer_bytes: None,
+    }
+}
+
+#[cfg(not(unix))]
+pub fn probe_io_hints(_device: &Path) -> IoHints {
+    IoHints::default()
+}
@@ -0,0 +1,172 @@
+//! VHD and VHDX files through the Windows Virtual Disk API. An attached
+//! virtual disk shows up as a `\\.\PhysicalDriveN` that is read or written
+//! like any other disk; it is detached when the handle is dropped.
+
+use crate::container::ImageFormat;
+use anyhow::{anyhow, Result};
+use std::fs::File;
+use std::os::windows::io::AsRawHandle;
+use std::path::Path;
+
+use windows::core::{GUID, PCWSTR, PWSTR};
+use windows::Win32::Foundation::{CloseHandle, BOOL, HANDLE, INVALID_HANDLE_VALUE};
+use windows::Win32::Storage::Vhd::{
+    AttachVirtualDisk, CreateVirtualDisk, DetachVirtualDisk, GetVirtualDiskPhysicalPath,
+    OpenVirtualDisk, ATTACH_VIRTUAL_DISK_FLAG_NO_DRIVE_LETTER, ATTACH_VIRTUAL_DISK_FLAG_READ_ONLY,
+    ATTACH_VIRTUAL_DISK_PARAMETERS, ATTACH_VIRTUAL_DISK_VERSION_1, CREATE_VIRTUAL_DISK_FLAG_NONE,
+    CREATE_VIRTUAL_DISK_PARAMETERS, CREATE_VIRTUAL_DISK_VERSION_2, DETACH_VIRTUAL_DISK_FLAG_NONE,
+    OPEN_VIRTUAL_DISK_FLAG_NONE, OPEN_VIRTUAL_DISK_PARAMETERS, OPEN_VIRTUAL_DISK_VERSION_2,
+    VIRTUAL_DISK_ACCESS_NONE, VIRTUAL_STORAGE_TYPE, VIRTUAL_STORAGE_TYPE_DEVICE_VHD,
+    VIRTUAL_STORAGE_TYPE_DEVICE_VHDX, VIRTUAL_STORAGE_TYPE_VENDOR_MICROSOFT,
+};
+use windows::Win32::System::Ioctl::{GET_LENGTH_INFORMATION, IOCTL_DISK_GET_LENGTH_INFO};
+use windows::Win32::System::IO::DeviceIoControl;
+
+#[derive(Debug)]
+pub struct VirtualDisk {
+    handle: HANDLE,
+    attached: bool,
+}
+
+// The handle is only used through `&mut self` or on drop.
+unsafe impl Send for VirtualDisk {}
+
+impl VirtualDisk {
+    pub fn open(path: &Path, format: ImageFormat, read_only: bool) -> Result<Self> {
+        let path_wide = wide(path);
+        let storage_type = storage_type(format);
+        let mut params = OPEN_VIRTUAL_DISK_PARAMETERS {
+            Version: OPEN_VIRTUAL_DISK_VERSION_2,
+            ..Default::default()
+        };
+        params.Anonymous.Version2.ReadOnly = BOOL::from(read_only);
+        let mut handle = HANDLE::default();
+        unsafe {
+            OpenVirtualDisk(
+                &storage_type,
+                PCWSTR(path_wide.as_ptr()),
+                VIRTUAL_DISK_ACCESS_NONE,
+                OPEN_VIRTUAL_DISK_FLAG_NONE,
+                Some(&params),
+                &mut handle,
+            )
+            .ok()
+            .map_err(|error| anyhow!("OpenVirtualDisk {} failed: {:?}", path.display(), error))?;
+        }
+        if handle == INVALID_HANDLE_VALUE {
+            return Err(anyhow!("OpenVirtualDisk returned invalid handle"));
+        }
+        Ok(Self {
+            handle,
+            attached: false,
+        })
+    }
+
+    /// Creates a dynamically expanding VHDX for a disk of `disk_bytes`, so
+    /// only the blocks written take space.
+    pub fn create_vhdx(path: &Path, disk_bytes: u64) -> Result<Self> {
+        let path_wide = wide(path);
+        let storage_type = storage_type(ImageFormat::Vhdx);
+        let mut params = CREATE_VIRTUAL_DISK_PARAMETERS {
+            Version: CREATE_VIRTUAL_DISK_VERSION_2,
+            ..Default::default()
+        };
+        params.Anonymous.Version2.UniqueId = GUID::new().unwrap_or_default();
+        params.Anonymous.Version2.MaximumSize = disk_bytes;
+        let mut handle = HANDLE::default();
+        unsafe {
+            CreateVirtualDisk(
+                &storage_type,
+                PCWSTR(path_wide.as_ptr()),
+                VIRTUAL_DISK_ACCESS_NONE,
+                None,
+                CREATE_VIRTUAL_DISK_FLAG_NONE,
+                0,
+                &params,
+                None,
+                &mut handle,
+            )
+            .ok()
+            .map_err(|error| anyhow!("CreateVirtualDisk {} failed: {:?}", path.display(), error))?;
+        }
+        Ok(Self {
+            handle,
+            attached: false,
+        })
+    }
+
+    /// Attaches the disk without drive letters and returns its
+    /// `\\.\PhysicalDriveN`.
+    pub fn attach(&mut self, read_only: bool) -> Result<String> {
+        let params = ATTACH_VIRTUAL_DISK_PARAMETERS {
+            Version: ATTACH_VIRTUAL_DISK_VERSION_1,
+            ..Default::default()
+        };
+        let mut flags = ATTACH_VIRTUAL_DISK_FLAG_NO_DRIVE_LETTER;
+        if read_only {
+            flags |= ATTACH_VIRTUAL_DISK_FLAG_READ_ONLY;
+        }
+        unsafe {
+            AttachVirtualDisk(self.handle, None, flags, 0, Some(&params), None)
+                .ok()
+                .map_err(|error| anyhow!("AttachVirtualDisk failed: {:?}", error))?;
+        }
+        self.attached = true;
+
+        let mut buf = [0u16; 260];
+        let mut size = (buf.len() * 2) as u32;
+        unsafe {
+            GetVirtualDiskPhysicalPath(self.handle, &mut size, PWSTR(buf.as_mut_ptr()))
+                .ok()
+                .map_err(|error| anyhow!("GetVirtualDiskPhysicalPath failed: {:?}", error))?;
+        }
+        let len = buf.iter().position(|unit| *unit == 0).unwrap_or(buf.len());
+        Ok(String::from_utf16_lossy(&buf[..len]))
+    }
+}
+
+impl Drop for VirtualDisk {
+    fn drop(&mut self) {
+        unsafe {
+            if self.attached {
+                let _ = DetachVirtualDisk(self.handle, DETACH_VIRTUAL_DISK_FLAG_NONE, 0);
+            }
+            let _ = CloseHandle(self.handle);
+        }
+    }
+}
+
+/// Size of the disk open as `device`.
+pub fn disk_length(device: &File) -> Result<u64> {
+    let mut info = GET_LENGTH_INFORMATION::default();
+    unsafe {
+        DeviceIoControl(
+            HANDLE(device.as_raw_handle() as isize),
+            IOCTL_DISK_GET_LENGTH_INFO,
+            None,
+            0,
+            Some(&mut info as *mut _ as *mut _),
+            std::mem::size_of::<GET_LENGTH_INFORMATION>() as u32,
+            None,
+            None,
+        )
+        .map_err(|error| anyhow!("IOCTL_DISK_GET_LENGTH_INFO failed: {:?}", error))?;
+    }
+    Ok(info.Length as u64)
+}
+
+fn storage_type(format: ImageFormat) -> VIRTUAL_STORAGE_TYPE {
+    VIRTUAL_STORAGE_TYPE {
+        DeviceId: if format == ImageFormat::Vhd {
+            VIRTUAL_STORAGE_TYPE_DEVICE_VHD
+        } else {
+            VIRTUAL_STORAGE_TYPE_DEVICE_VHDX
+        },
+        VendorId: VIRTUAL_STORAGE_TYPE_VENDOR_MICROSOFT,
+    }
+}
+
+fn wide(path: &Path) -> Vec<u16> {
+    use std::os::windows::ffi::OsStrExt;
+    path.as_os_str().encode_wide().chain(std::iter::once(0)).collect()
+}
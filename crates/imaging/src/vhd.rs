@@ -0,0 +1,107 @@
+//! The footer of a VHD image. A fixed VHD is the raw disk followed by a
+//! 512-byte footer, so it is read and written here without the Virtual
+//! Disk API; Hyper-V and QEMU boot it as it is. Dynamic and differencing
+//! VHDs are left to the API on Windows.
+
+use sha2::{Digest, Sha256};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const VHD_FOOTER_BYTES: u64 = 512;
+const COOKIE: &[u8; 8] = b"conectix";
+/// Seconds from the Unix epoch to the VHD epoch, 2000-01-01.
+const VHD_EPOCH: u64 = 946_684_800;
+const FIXED_DISK: u32 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VhdKind {
+    Fixed,
+    /// Dynamic or differencing: blocks allocated through a BAT.
+    Sparse,
+}
+
+/// The kind and disk size of the VHD whose footer is `footer`, or `None`
+/// when it is not a VHD footer.
+pub fn parse_vhd_footer(footer: &[u8]) -> Option<(VhdKind, u64)> {
+    if footer.len() < VHD_FOOTER_BYTES as usize || &footer[..8] != COOKIE {
+        return None;
+    }
+    let mut zeroed = footer[..VHD_FOOTER_BYTES as usize].to_vec();
+    zeroed[64..68].fill(0);
+    if checksum(&zeroed) != be_u32(footer, 64) {
+        return None;
+    }
+    let kind = if be_u32(footer, 60) == FIXED_DISK {
+        VhdKind::Fixed
+    } else {
+        VhdKind::Sparse
+    };
+    Some((kind, u64::from_be_bytes(footer[48..56].try_into().unwrap())))
+}
+
+/// The footer of a fixed VHD holding `disk_bytes`. `seed` makes the
+/// unique id differ between images captured at the same second.
+pub fn fixed_vhd_footer(disk_bytes: u64, seed: &[u8]) -> [u8; VHD_FOOTER_BYTES as usize] {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_secs())
+        .unwrap_or(VHD_EPOCH);
+    let mut footer = [0u8; VHD_FOOTER_BYTES as usize];
+    footer[..8].copy_from_slice(COOKIE);
+    footer[8..12].copy_from_slice(&2u32.to_be_bytes());
+    footer[12..16].copy_from_slice(&0x0001_0000u32.to_be_bytes());
+    footer[16..24].copy_from_slice(&u64::MAX.to_be_bytes());
+    footer[24..28].copy_from_slice(&(now.saturating_sub(VHD_EPOCH) as u32).to_be_bytes());
+    footer[28..32].copy_from_slice(b"phx ");
+    footer[32..36].copy_from_slice(&0x0001_0000u32.to_be_bytes());
+    footer[36..40].copy_from_slice(b"Wi2k");
+    footer[40..48].copy_from_slice(&disk_bytes.to_be_bytes());
+    footer[48..56].copy_from_slice(&disk_bytes.to_be_bytes());
+    let (cylinders, heads, sectors) = geometry(disk_bytes);
+    footer[56..58].copy_from_slice(&cylinders.to_be_bytes());
+    footer[58] = heads;
+    footer[59] = sectors;
+    footer[60..64].copy_from_slice(&FIXED_DISK.to_be_bytes());
+    let mut id = Sha256::new();
+    id.update(seed);
+    id.update(now.to_le_bytes());
+    id.update(disk_bytes.to_le_bytes());
+    footer[68..84].copy_from_slice(&id.finalize()[..16]);
+    let sum = checksum(&footer);
+    footer[64..68].copy_from_slice(&sum.to_be_bytes());
+    footer
+}
+
+/// The CHS geometry the VHD specification derives from the disk size.
+fn geometry(disk_bytes: u64) -> (u16, u8, u8) {
+    let total = (disk_bytes / 512).min(65535 * 16 * 255);
+    let (sectors, heads, cylinder_heads) = if total >= 65535 * 16 * 63 {
+        (255, 16, total / 255)
+    } else {
+        let mut sectors = 17;
+        let mut cylinder_heads = total / sectors;
+        let mut heads = cylinder_heads.div_ceil(1024).max(4);
+        if cylinder_heads >= heads * 1024 || heads > 16 {
+            sectors = 31;
+            heads = 16;
+            cylinder_heads = total / sectors;
+        }
+        if cylinder_heads >= heads * 1024 {
+            sectors = 63;
+            heads = 16;
+            cylinder_heads = total / sectors;
+        }
+        (sectors, heads, cylinder_heads)
+    };
+    ((cylinder_heads / heads) as u16, heads as u8, sectors as u8)
+}
+
+/// One's complement of the byte sum, with the checksum field zeroed.
+fn checksum(footer: &[u8]) -> u32 {
+    !footer[..VHD_FOOTER_BYTES as usize]
+        .iter()
+        .fold(0u32, |sum, byte| sum.wrapping_add(*byte as u32))
+}
+
+fn be_u32(buffer: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(buffer[offset..offset + 4].try_into().unwrap())
+}
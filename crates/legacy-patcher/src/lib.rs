@@ -4,21 +4,36 @@ use phoenix_core::DeviceGraph;
 use phoenix_report::{create_report_bundle_with_meta_and_signing, ReportPaths};
 use phoenix_safety::{can_write_to_disk, SafetyContext, SafetyDecision};
 use plist::Value;
+use serde::{Deserialize, Serialize};
 use std::fs;
 use std::path::{Path, PathBuf};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LegacyPatchParams {
     pub source_path: PathBuf,
+    #[serde(default = "default_report_base")]
     pub report_base: PathBuf,
+    #[serde(default)]
     pub model: Option<String>,
+    #[serde(default)]
     pub board_id: Option<String>,
+    #[serde(default)]
     pub force: bool,
+    #[serde(default)]
     pub confirmation_token: Option<String>,
+    #[serde(default = "default_dry_run")]
     pub dry_run: bool,
 }
 
-#[derive(Debug, Clone)]
+fn default_report_base() -> PathBuf {
+    PathBuf::from(".")
+}
+
+fn default_dry_run() -> bool {
+    true
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LegacyPatchResult {
     pub report: ReportPaths,
     pub patched_files: Vec<String>,
@@ -30,10 +45,13 @@ pub fn run_legacy_patch(params: &LegacyPatchParams) -> Result<LegacyPatchResult>
     let ctx = SafetyContext {
         force_mode: params.force,
         confirmation_token: params.confirmation_token.clone(),
+        allow_system_disk: false,
+        armed_until: phoenix_safety::armed_until(),
     };
     match can_write_to_disk(&ctx, false) {
         SafetyDecision::Allow => {}
         SafetyDecision::Deny(reason) => return Err(anyhow!(reason)),
+        SafetyDecision::ReadOnly(err) => return Err(err.into()),
     }
 
     let prepared = prepare_source(&params.source_path)?;
@@ -141,8 +159,10 @@ fn update_plist_arrays(value: &mut Value, keys: &[&str], entry: &str) -> bool {
 
 fn find_array_mut<'a>(value: &'a mut Value, key: &str) -> Option<&'a mut Vec<Value>> {
     let dict = value.as_dictionary_mut()?;
-    let entry = dict.entry(key.to_string()).or_insert_with(|| Value::Array(Vec::new()));
-    entry.as_array_mut()
+    if !dict.contains_key(key) {
+        dict.insert(key.to_string(), Value::Array(Vec::new()));
+    }
+    dict.get_mut(key)?.as_array_mut()
 }
 
 fn find_install_app(root: &Path) -> Option<PathBuf> {
@@ -174,15 +194,15 @@ fn signing_key_from_env() -> Option<String> {
 fn build_device_graph() -> Result<DeviceGraph> {
     #[cfg(target_os = "windows")]
     {
-        return phoenix_host_windows::build_device_graph();
+        phoenix_host_windows::build_device_graph()
     }
     #[cfg(target_os = "linux")]
     {
-        return phoenix_host_linux::build_device_graph();
+        phoenix_host_linux::build_device_graph()
     }
     #[cfg(target_os = "macos")]
     {
-        return phoenix_host_macos::build_device_graph();
+        phoenix_host_macos::build_device_graph()
     }
     #[cfg(not(any(target_os = "windows", target_os = "linux", target_os = "macos")))]
     {
@@ -0,0 +1,89 @@
+// Types of the phoenixcore addon. Objects keep the engine's snake_case keys;
+// the engine's own types are generated into `types/` by phoenix-typegen.
+
+import type {
+  DeviceGraph,
+  DeviceRecord,
+  EffectiveConfig,
+  MediaManifest,
+  ProgressEvent,
+  ReportIndexEntry,
+  ReportVerification,
+  RunDetails,
+  RunFilter,
+  WizardQuestion,
+  WizardSession,
+  WorkflowDefinition,
+  WorkflowStep,
+} from './types'
+
+export * from './types'
+
+export interface RunOptions {
+  /** Directory reports are written under; defaults to the working directory. */
+  reportBase?: string
+  onProgress?: (event: ProgressEvent) => void
+  /** Aborting cancels the run at its next safe point. */
+  signal?: AbortSignal
+}
+
+export interface RunStep {
+  id: string
+  action: string
+  report_root: string | null
+  duration_ms: number
+  reused: boolean
+  hooks: Array<Record<string, unknown>>
+}
+
+export interface RunResult {
+  report_root: string
+  steps: RunStep[]
+}
+
+export interface WizardState {
+  /** Pass back unchanged with the next answer. */
+  session: WizardSession
+  /** Null once every question is answered. */
+  question: WizardQuestion | null
+}
+
+/** The device graph of this host. */
+export function deviceGraph(): Promise<DeviceGraph>
+/** Every disk this host has seen, with its flash count, most recently seen first. */
+export function deviceHistory(): Promise<DeviceRecord[]>
+/** Labels a disk in the registry, or clears its label when `label` is null. `deny` refuses writes to it; `warn` lets them through with the label in the report and audit log. */
+export function setDeviceClaim(key: string, label: string | null, notes?: string | null, action?: 'label' | 'warn' | 'deny' | null): Promise<DeviceRecord>
+/** Loads a definition file, expanding its includes. */
+export function loadWorkflow(path: string): WorkflowDefinition
+/** Throws when the definition cannot run on this host. */
+export function validateWorkflow(definition: WorkflowDefinition | string): void
+/** Runs a definition and resolves to its report root and steps. */
+export function runWorkflow(
+  definition: WorkflowDefinition | string,
+  options?: RunOptions,
+): Promise<RunResult>
+/** Verifies a report bundle; `ok` is false on any mismatch or error finding. */
+export function verifyReport(path: string, signingKey?: string | null): Promise<ReportVerification>
+/** Runs indexed under `reportBase/reports` that match `filter`, newest first. */
+export function listRuns(reportBase: string, filter?: RunFilter | null): Promise<ReportIndexEntry[]>
+/** One indexed run with its `run.json`. */
+export function getRun(reportBase: string, runId: string): Promise<RunDetails>
+/** Zips the bundle of an indexed run to `outputPath` and resolves to that path. */
+export function exportRunZip(reportBase: string, runId: string, outputPath: string): Promise<string>
+/** The `phoenix_media.toml` label of the media mounted at `mount`, or null when it has none. */
+export function mediaInfo(mount: string): MediaManifest | null
+/** Starts a wizard (`windows_installer_usb`, `linux_write_image`, `macos_write_image`) over this host's disks. */
+export function wizardStart(action: string): Promise<WizardState>
+/** Re-reads the disks and checks `value` as the answer to `key`; rejects with the reason when refused. */
+export function wizardAnswer(session: WizardSession, key: string, value: unknown): Promise<WizardState>
+/** The step the answers make; throws while a question is unanswered. */
+export function wizardFinish(session: WizardSession): WorkflowStep
+/** Units (`decimal` or `binary`) and locale for formatted sizes and durations; unset parts come from the environment. */
+export function setDisplayFormat(units?: 'decimal' | 'binary' | null, locale?: string | null): void
+/** Settings this process runs with and where each came from (flag, env, file, build or default). */
+export function effectiveConfig(): EffectiveConfig
+/** `bytes` as `4.7 GB` (or `4.4 GiB`), as the CLI and reports show it. */
+export function formatBytes(bytes: number): string
+/** `ms` as `1 h 12 min`, as the CLI and reports show it. */
+export function formatDuration(ms: number): string
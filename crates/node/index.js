@@ -0,0 +1,2 @@
+// `phoenix.node` is the `phoenix-node` library built by cargo, renamed.
+module.exports = require('./phoenix.node');
@@ -0,0 +1,348 @@
+//! N-API addon for Electron and Tauri hosts, mirroring the C ABI: device
+//! graph enumeration and history, workflow runs with progress events, run history,
+//! report verification, the label of finished media, guided wizards and
+//! size and duration formatting. Calls that touch disks
+//! return a Promise and run on the libuv thread pool. Results are plain
+//! objects shaped like the engine's JSON; their types are in `index.d.ts`.
+
+use napi::bindgen_prelude::*;
+use napi::threadsafe_function::{
+    ErrorStrategy, ThreadSafeCallContext, ThreadsafeFunction, ThreadsafeFunctionCallMode,
+};
+use napi::{Env, JsFunction, JsObject, JsUnknown};
+use napi_derive::napi;
+use phoenix_core::WorkflowDefinition;
+use phoenix_workflow_engine::{
+    build_device_graph, run_workflow_definition_with_report, DeviceRegistry, validate_workflow_definition,
+    with_cancel_token, with_log_listener, CancelToken, LogEntry, ProgressEvent, WizardSession,
+    WorkflowRunResult,
+};
+use std::path::PathBuf;
+
+type Job = Box<dyn FnOnce() -> anyhow::Result<serde_json::Value> + Send>;
+
+/// Blocking engine work, run off the JS thread.
+pub struct EngineTask(Option<Job>);
+
+impl EngineTask {
+    fn new(job: impl FnOnce() -> anyhow::Result<serde_json::Value> + Send + 'static) -> AsyncTask<Self> {
+        AsyncTask::new(Self(Some(Box::new(job))))
+    }
+}
+
+impl Task for EngineTask {
+    type Output = serde_json::Value;
+    type JsValue = JsUnknown;
+
+    fn compute(&mut self) -> Result<Self::Output> {
+        let job = self
+            .0
+            .take()
+            .ok_or_else(|| Error::from_reason("task already ran"))?;
+        job().map_err(engine_error)
+    }
+
+    fn resolve(&mut self, env: Env, output: Self::Output) -> Result<Self::JsValue> {
+        env.to_js_value(&output)
+    }
+}
+
+fn engine_error(err: anyhow::Error) -> Error {
+    Error::from_reason(format!("{:#}", err))
+}
+
+/// A definition given as an object or as JSON text.
+fn parse_definition(definition: serde_json::Value) -> Result<WorkflowDefinition> {
+    let parsed = match definition {
+        serde_json::Value::String(text) => serde_json::from_str(&text),
+        value => serde_json::from_value(value),
+    };
+    parsed.map_err(|err| {
+        Error::new(
+            Status::InvalidArg,
+            format!("invalid workflow definition: {}", err),
+        )
+    })
+}
+
+/// The device graph of this host.
+#[napi(ts_return_type = "Promise<DeviceGraph>")]
+pub fn device_graph() -> AsyncTask<EngineTask> {
+    EngineTask::new(|| Ok(serde_json::to_value(build_device_graph()?)?))
+}
+
+/// Every disk this host has seen, most recently seen first.
+#[napi(ts_return_type = "Promise<DeviceRecord[]>")]
+pub fn device_history() -> AsyncTask<EngineTask> {
+    EngineTask::new(|| Ok(serde_json::to_value(DeviceRegistry::open_default()?.list()?)?))
+}
+
+/// Labels a disk in the registry (`action`: `label`, `warn` or `deny`), or
+/// clears its label when `label` is null.
+#[napi(ts_return_type = "Promise<DeviceRecord>")]
+pub fn set_device_claim(
+    key: String,
+    label: Option<String>,
+    notes: Option<String>,
+    action: Option<String>,
+) -> Result<AsyncTask<EngineTask>> {
+    let claim = match label {
+        Some(label) => Some(phoenix_core::DeviceClaim {
+            label,
+            notes,
+            action: phoenix_core::ClaimAction::parse(action.as_deref().unwrap_or("label"))
+                .map_err(|err| Error::new(Status::InvalidArg, err.to_string()))?,
+            claimed_utc: phoenix_core::now_utc_rfc3339(),
+        }),
+        None => None,
+    };
+    Ok(EngineTask::new(move || {
+        Ok(serde_json::to_value(DeviceRegistry::open_default()?.set_claim(&key, claim)?)?)
+    }))
+}
+
+/// Loads a definition file, expanding its includes.
+#[napi(ts_return_type = "WorkflowDefinition")]
+pub fn load_workflow(path: String) -> Result<serde_json::Value> {
+    let definition = phoenix_content::load_workflow_definition(&path).map_err(engine_error)?;
+    serde_json::to_value(definition).map_err(|err| engine_error(err.into()))
+}
+
+/// Throws when the definition cannot run on this host.
+#[napi(ts_args_type = "definition: WorkflowDefinition | string")]
+pub fn validate_workflow(definition: serde_json::Value) -> Result<()> {
+    validate_workflow_definition(&parse_definition(definition)?).map_err(engine_error)
+}
+
+/// Runs a definition and resolves to its report root and steps.
+/// `options.onProgress` receives every log line; aborting
+/// `options.signal` cancels the run at its next safe point.
+#[napi(
+    ts_args_type = "definition: WorkflowDefinition | string, options?: RunOptions",
+    ts_return_type = "Promise<RunResult>"
+)]
+pub fn run_workflow(
+    env: Env,
+    definition: serde_json::Value,
+    options: Option<JsObject>,
+) -> Result<AsyncTask<EngineTask>> {
+    let definition = parse_definition(definition)?;
+    let token = CancelToken::new();
+    let mut report_base = PathBuf::from(".");
+    let mut progress = None;
+    if let Some(options) = options {
+        if let Some(base) = options.get::<_, String>("reportBase")? {
+            report_base = PathBuf::from(base);
+        }
+        if let Some(callback) = options.get::<_, JsFunction>("onProgress")? {
+            let callback: ThreadsafeFunction<ProgressEvent, ErrorStrategy::Fatal> = callback
+                .create_threadsafe_function(0, |ctx: ThreadSafeCallContext<ProgressEvent>| {
+                    Ok(vec![ctx.env.to_js_value(&ctx.value)?])
+                })?;
+            progress = Some(callback);
+        }
+        if let Some(signal) = options.get::<_, JsObject>("signal")? {
+            cancel_on_abort(&env, &signal, &token)?;
+        }
+    }
+    Ok(EngineTask::new(move || {
+        let run = || {
+            with_cancel_token(&token, || {
+                run_workflow_definition_with_report(&definition, report_base)
+            })
+        };
+        let result = match progress {
+            Some(progress) => {
+                let listener = Box::new(move |workflow: &str, entry: &LogEntry| {
+                    progress.call(
+                        ProgressEvent::new(workflow, entry),
+                        ThreadsafeFunctionCallMode::NonBlocking,
+                    );
+                });
+                with_log_listener(listener, run)?
+            }
+            None => run()?,
+        };
+        Ok(run_result_json(&result))
+    }))
+}
+
+/// Cancels `token` when `signal` aborts, or right away if it already has.
+fn cancel_on_abort(env: &Env, signal: &JsObject, token: &CancelToken) -> Result<()> {
+    if signal.get::<_, bool>("aborted")?.unwrap_or(false) {
+        token.cancel();
+        return Ok(());
+    }
+    let token = token.clone();
+    let on_abort = env.create_function_from_closure("onAbort", move |_| {
+        token.cancel();
+        Ok(())
+    })?;
+    let add_listener: JsFunction = signal.get_named_property("addEventListener")?;
+    add_listener.call(
+        Some(signal),
+        &[
+            env.create_string("abort")?.into_unknown(),
+            on_abort.into_unknown(),
+        ],
+    )?;
+    Ok(())
+}
+
+fn run_result_json(result: &WorkflowRunResult) -> serde_json::Value {
+    let steps: Vec<serde_json::Value> = result
+        .steps
+        .iter()
+        .map(|step| {
+            serde_json::json!({
+                "id": step.id,
+                "action": step.action,
+                "report_root": step.report_root.as_ref().map(|root| root.display().to_string()),
+                "duration_ms": step.duration_ms as u64,
+                "reused": step.reused,
+                "hooks": step.hooks
+            })
+        })
+        .collect();
+    serde_json::json!({
+        "report_root": result.report.root.display().to_string(),
+        "steps": steps
+    })
+}
+
+/// Verifies a report bundle; `ok` is false on any mismatch or error
+/// finding.
+#[napi(ts_return_type = "Promise<ReportVerification>")]
+pub fn verify_report(path: String, signing_key: Option<String>) -> AsyncTask<EngineTask> {
+    EngineTask::new(move || {
+        let verification = phoenix_report::verify_report_bundle(&path, signing_key.as_deref())?;
+        Ok(serde_json::to_value(verification)?)
+    })
+}
+
+/// Runs indexed under `reportBase/reports` that match `filter`, newest
+/// first.
+#[napi(
+    ts_args_type = "reportBase: string, filter?: RunFilter | null",
+    ts_return_type = "Promise<ReportIndexEntry[]>"
+)]
+pub fn list_runs(report_base: String, filter: Option<serde_json::Value>) -> Result<AsyncTask<EngineTask>> {
+    let filter: phoenix_report::RunFilter = match filter {
+        Some(value) if !value.is_null() => serde_json::from_value(value)
+            .map_err(|err| Error::new(Status::InvalidArg, format!("invalid run filter: {}", err)))?,
+        _ => Default::default(),
+    };
+    Ok(EngineTask::new(move || {
+        Ok(serde_json::to_value(phoenix_report::list_runs(&report_base, &filter)?)?)
+    }))
+}
+
+/// One indexed run with its `run.json`.
+#[napi(ts_return_type = "Promise<RunDetails>")]
+pub fn get_run(report_base: String, run_id: String) -> AsyncTask<EngineTask> {
+    EngineTask::new(move || Ok(serde_json::to_value(phoenix_report::get_run(&report_base, &run_id)?)?))
+}
+
+/// Zips the bundle of an indexed run to `outputPath` and resolves to that
+/// path.
+#[napi(ts_return_type = "Promise<string>")]
+pub fn export_run_zip(report_base: String, run_id: String, output_path: String) -> AsyncTask<EngineTask> {
+    EngineTask::new(move || {
+        let run = phoenix_report::get_run(&report_base, &run_id)?;
+        let path = phoenix_report::export_report_zip(&run.root, &output_path)?;
+        Ok(serde_json::Value::String(path.display().to_string()))
+    })
+}
+
+/// The `phoenix_media.toml` label of the media mounted at `mount`, or
+/// `null` when it has none.
+#[napi(ts_return_type = "MediaManifest | null")]
+pub fn media_info(mount: String) -> Result<serde_json::Value> {
+    let manifest = phoenix_content::read_media_manifest(&mount).map_err(engine_error)?;
+    serde_json::to_value(manifest).map_err(|err| engine_error(err.into()))
+}
+
+/// Starts a wizard for `action` over this host's disks.
+#[napi(ts_return_type = "Promise<WizardState>")]
+pub fn wizard_start(action: String) -> AsyncTask<EngineTask> {
+    EngineTask::new(move || wizard_state(&WizardSession::start(&action)?))
+}
+
+/// Re-reads the disks and checks `value` as the answer to `key`. Rejects
+/// with the reason when the answer is refused.
+#[napi(
+    ts_args_type = "session: WizardSession, key: string, value: unknown",
+    ts_return_type = "Promise<WizardState>"
+)]
+pub fn wizard_answer(
+    session: serde_json::Value,
+    key: String,
+    value: serde_json::Value,
+) -> Result<AsyncTask<EngineTask>> {
+    let mut session = parse_wizard(session)?;
+    Ok(EngineTask::new(move || {
+        session.refresh()?;
+        session.answer(&key, value)?;
+        wizard_state(&session)
+    }))
+}
+
+/// The step the answers make; throws while a question is unanswered.
+#[napi(ts_args_type = "session: WizardSession", ts_return_type = "WorkflowStep")]
+pub fn wizard_finish(session: serde_json::Value) -> Result<serde_json::Value> {
+    let step = parse_wizard(session)?.finish().map_err(engine_error)?;
+    serde_json::to_value(step).map_err(|err| engine_error(err.into()))
+}
+
+fn parse_wizard(session: serde_json::Value) -> Result<WizardSession> {
+    serde_json::from_value(session)
+        .map_err(|err| Error::new(Status::InvalidArg, format!("invalid wizard session: {}", err)))
+}
+
+fn wizard_state(session: &WizardSession) -> anyhow::Result<serde_json::Value> {
+    Ok(serde_json::json!({
+        "session": session,
+        "question": session.next_question()
+    }))
+}
+
+/// Sets the units (`decimal` or `binary`) and locale used by
+/// `formatBytes`, `formatDuration` and the `display` block of reports
+/// written from this process. Unset parts come from the environment.
+#[napi]
+pub fn set_display_format(units: Option<String>, locale: Option<String>) -> Result<()> {
+    let current = phoenix_core::DisplayFormat::from_env();
+    let units = match units {
+        Some(units) => phoenix_core::UnitSystem::parse(&units)
+            .map_err(|err| Error::new(Status::InvalidArg, err.to_string()))?,
+        None => current.units,
+    };
+    let format = match locale {
+        Some(locale) => phoenix_core::DisplayFormat::for_locale(units, &locale),
+        None => phoenix_core::DisplayFormat { units, ..current },
+    };
+    phoenix_core::set_display_format(format);
+    Ok(())
+}
+
+/// The settings this process runs with and where each came from. Compare
+/// it with `phoenix config-show --effective --json` when the app and the
+/// CLI behave differently.
+#[napi(ts_return_type = "EffectiveConfig")]
+pub fn effective_config() -> Result<serde_json::Value> {
+    let process = format!("phoenix-node {}", env!("CARGO_PKG_VERSION"));
+    serde_json::to_value(phoenix_workflow_engine::effective_config(&process))
+        .map_err(|err| engine_error(err.into()))
+}
+
+/// `bytes` as `4.7 GB` (or `4.4 GiB`), the way the CLI and reports show it.
+#[napi]
+pub fn format_bytes(bytes: f64) -> String {
+    phoenix_core::format_bytes(bytes.max(0.0) as u64)
+}
+
+/// `ms` as `1 h 12 min`, the way the CLI and reports show it.
+#[napi]
+pub fn format_duration(ms: f64) -> String {
+    phoenix_core::format_duration_ms(ms.max(0.0) as u64)
+}
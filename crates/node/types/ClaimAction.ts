@@ -0,0 +1,6 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * What picking a claimed disk as a write target does.
+ */
+export type ClaimAction = "label" | "warn" | "deny";
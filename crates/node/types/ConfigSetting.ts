@@ -0,0 +1,21 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { ConfigSource } from "./ConfigSource";
+
+export type ConfigSetting = { key: string, 
+/**
+ * `None` when unset. Keys and tokens show as a fingerprint.
+ */
+value: string | null, source: ConfigSource, 
+/**
+ * The variable that sets it, when it came from one.
+ */
+env?: string | null, 
+/**
+ * The config file it is read from, whether or not it exists.
+ */
+path?: string | null, 
+/**
+ * Why the value could not be worked out, e.g. a file that does not
+ * parse. The process fails the same way when it needs the setting.
+ */
+error?: string | null, };
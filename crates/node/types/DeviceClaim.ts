@@ -0,0 +1,8 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { ClaimAction } from "./ClaimAction";
+
+/**
+ * A label an operator attached to a disk, e.g. "Customer loaner - never
+ * wipe". It stays with the disk across runs.
+ */
+export type DeviceClaim = { label: string, notes: string | null, action: ClaimAction, claimed_utc: string, };
@@ -0,0 +1,15 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { Disk } from "./Disk";
+import type { HostInfo } from "./HostInfo";
+import type { StackedDevice } from "./StackedDevice";
+
+export type DeviceGraph = { 
+/**
+ * Version the graph was written with. Graphs from before versioning
+ * read as 1.0.0.
+ */
+schema_version: string, graph_id: string, generated_at_utc: string, host: HostInfo, disks: Array<Disk>, 
+/**
+ * Device-mapper and md devices assembled from the disks (Linux).
+ */
+stacks: Array<StackedDevice>, };
@@ -0,0 +1,8 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { DeviceClaim } from "./DeviceClaim";
+
+export type DeviceRecord = { 
+/**
+ * Serial, else `<friendly name>|<size>` for disks without one.
+ */
+key: string, serial: string | null, friendly_name: string, size_bytes: number, first_seen_utc: string, last_seen_utc: string, flash_count: number, last_workflow: string | null, last_flashed_utc: string | null, best_write_bytes_per_sec: number | null, last_write_bytes_per_sec: number | null, claim?: DeviceClaim, };
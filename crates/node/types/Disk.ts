@@ -0,0 +1,20 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { DiskHistory } from "./DiskHistory";
+import type { Partition } from "./Partition";
+import type { UsbPort } from "./UsbPort";
+
+export type Disk = { id: string, friendly_name: string, serial: string | null, size_bytes: number, removable: boolean, is_system_disk: boolean, partitions: Array<Partition>, 
+/**
+ * Physical USB port the disk hangs off, when the provider can tell.
+ */
+usb_port: UsbPort | null, 
+/**
+ * What the local device registry remembers about this disk.
+ */
+history?: DiskHistory | null, 
+/**
+ * Partition GUID of the EFI system partition the firmware booted this
+ * host from, when it is on this disk. Such a disk is a system disk
+ * whatever its removable flag says.
+ */
+booted_esp?: string, };
@@ -0,0 +1,20 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { DeviceClaim } from "./DeviceClaim";
+
+/**
+ * Registry entry for a disk as of this graph: when it was first and last
+ * seen on this host and how often Phoenix has written to it.
+ */
+export type DiskHistory = { first_seen_utc: string, last_seen_utc: string, 
+/**
+ * Completed destructive runs against the disk.
+ */
+flash_count: number, last_workflow: string | null, last_flashed_utc: string | null, 
+/**
+ * Fastest raw image write seen, for spotting a stick that slowed down.
+ */
+best_write_bytes_per_sec: number | null, last_write_bytes_per_sec: number | null, 
+/**
+ * The operator's label for the disk, if it has one.
+ */
+claim?: DeviceClaim, };
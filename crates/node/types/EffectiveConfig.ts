@@ -0,0 +1,8 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { ConfigSetting } from "./ConfigSetting";
+
+export type EffectiveConfig = { generated_utc: string, 
+/**
+ * The process that reported it, e.g. `phoenix-cli 0.1.0`.
+ */
+process: string, pid: number, settings: Array<ConfigSetting>, };
@@ -0,0 +1,11 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export type HostInfo = { os: string, os_version: string, machine: string, 
+/**
+ * Windows EditionID, e.g. `Professional`.
+ */
+os_edition: string | null, 
+/**
+ * Windows feature update, e.g. `23H2`.
+ */
+os_display_version: string | null, };
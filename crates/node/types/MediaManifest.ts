@@ -0,0 +1,7 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export type MediaManifest = { pack_name: string, pack_version: string, 
+/**
+ * RFC 3339, UTC.
+ */
+build_date: string, supported_targets: Array<string>, support_contact: string | null, };
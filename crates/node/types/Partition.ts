@@ -0,0 +1,15 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export type Partition = { id: string, label: string | null, fs: string | null, size_bytes: number, mount_points: Array<string>, 
+/**
+ * GPT partition GUID, or `<MBR signature>-<number>` (Linux PARTUUID).
+ */
+part_uuid: string | null, 
+/**
+ * Filesystem UUID or serial, as in `root=UUID=...`.
+ */
+fs_uuid: string | null, 
+/**
+ * Byte offset of the partition on its disk, when the host reports it.
+ */
+offset_bytes: number | null, };
@@ -0,0 +1,6 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * A log line as the language bindings pass it to progress callbacks.
+ */
+export type ProgressEvent = { workflow: string, message: string, elapsed_ms: number, };
@@ -0,0 +1,8 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { FindingSeverity } from "./FindingSeverity";
+
+export type ReportFinding = { severity: FindingSeverity, 
+/**
+ * Stable identifier such as `artifact_missing`, for scripts.
+ */
+code: string, message: string, };
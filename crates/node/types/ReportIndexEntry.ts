@@ -0,0 +1,20 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * One line of `reports/index.jsonl`. The summary fields are absent from
+ * lines written before they existed.
+ */
+export type ReportIndexEntry = { run_id: string, correlation_id?: string | null, generated_at_utc: string, 
+/**
+ * Bundle directory, relative to the `reports` directory.
+ */
+path: string, workflow?: string | null, 
+/**
+ * `succeeded`, `failed` or `dry_run`, as `report-aggregate` counts it
+ * (without verifying the manifest).
+ */
+status?: string | null, 
+/**
+ * Target serial, disk id, device or mount.
+ */
+target?: string | null, duration_ms?: number | null, };
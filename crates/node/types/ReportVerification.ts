@@ -0,0 +1,9 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { ReportFinding } from "./ReportFinding";
+
+export type ReportVerification = { ok: boolean, entries_checked: number, mismatches: Array<string>, signature_valid: boolean | null, 
+/**
+ * Semantic checks on `run.json`, the artifacts and the device graph.
+ * Any error finding fails the verification.
+ */
+findings: Array<ReportFinding>, };
@@ -0,0 +1,21 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { JsonValue } from "./serde_json/JsonValue";
+
+export type RunDetails = { root: string, 
+/**
+ * The bundle's `run.json`.
+ */
+meta: JsonValue, run_id: string, correlation_id?: string | null, generated_at_utc: string, 
+/**
+ * Bundle directory, relative to the `reports` directory.
+ */
+path: string, workflow?: string | null, 
+/**
+ * `succeeded`, `failed` or `dry_run`, as `report-aggregate` counts it
+ * (without verifying the manifest).
+ */
+status?: string | null, 
+/**
+ * Target serial, disk id, device or mount.
+ */
+target?: string | null, duration_ms?: number | null, };
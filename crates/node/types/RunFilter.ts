@@ -0,0 +1,27 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * Criteria a run must all meet to be listed; unset ones match anything.
+ */
+export type RunFilter = { workflow?: string, 
+/**
+ * `succeeded`, `failed` or `dry_run`.
+ */
+status?: string, 
+/**
+ * Case-insensitive substring of the target.
+ */
+target?: string, correlation_id?: string, 
+/**
+ * RFC 3339 bounds on `generated_at_utc`, inclusive.
+ */
+since?: string, until?: string, 
+/**
+ * Case-insensitive substring of the run id, workflow, target or
+ * correlation id.
+ */
+text?: string, 
+/**
+ * Newest runs kept after filtering.
+ */
+limit?: number, };
@@ -0,0 +1,13 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { HostInfo } from "./HostInfo";
+import type { JsonValue } from "./serde_json/JsonValue";
+
+/**
+ * The keys every `run.json` starts with. Workflows add their own
+ * (`workflow`, `status`, `timing`, ...) next to them, kept in `extra`.
+ */
+export type RunMetadata = { run_id: string, 
+/**
+ * Schema version of the device graph captured with the run.
+ */
+schema_version: string, generated_at_utc: string, host: HostInfo, disk_count: number, correlation_id: string | null, } & ({ [key in string]?: number | string | boolean | Array<JsonValue> | { [key in string]?: JsonValue } | null });
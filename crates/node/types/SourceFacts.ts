@@ -0,0 +1,6 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * What the accepted source answer found.
+ */
+export type SourceFacts = { max_file_bytes: number, total_bytes: number, };
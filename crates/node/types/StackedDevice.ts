@@ -0,0 +1,11 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * An unlocked LUKS container, LVM volume, RAID array or other
+ * device-mapper/md device.
+ */
+export type StackedDevice = { id: string, name: string | null, kind: string, size_bytes: number, 
+/**
+ * Ids of the partitions, disks or stacked devices it is built on.
+ */
+slaves: Array<string>, mount_points: Array<string>, fs: string | null, };
@@ -0,0 +1,14 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * What the accepted target answer found, for the questions after it.
+ */
+export type TargetFacts = { disk_id: string, size_bytes: number, 
+/**
+ * Why the disk is outside `TargetSizeLimits`, if it is.
+ */
+size_problem: string | null, 
+/**
+ * Why the disk is past the wear limits, from its registry history.
+ */
+wear_problem: string | null, overwrite_triggers: Array<string>, };
@@ -0,0 +1,21 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * Where a USB disk is plugged in. `path` is stable for a given physical
+ * port across replugs and reboots, so it can key a human label.
+ */
+export type UsbPort = { 
+/**
+ * Provider's location string: sysfs port path (`2-1.3`), Windows
+ * location path (`PCIROOT(0)#PCI(1400)#USBROOT(0)#USB(1)#USB(3)`) or
+ * IOKit location id (`0x14130000`).
+ */
+path: string, bus: number | null, 
+/**
+ * Port numbers from the root hub down to the disk.
+ */
+ports: Array<number>, 
+/**
+ * Operator label such as `front-left`, from the port label config.
+ */
+label: string | null, };
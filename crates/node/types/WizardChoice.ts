@@ -0,0 +1,7 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export type WizardChoice = { value: string, label: string, 
+/**
+ * Why the choice would be refused; `None` when it can be picked.
+ */
+refused: string | null, };
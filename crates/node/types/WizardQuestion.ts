@@ -0,0 +1,14 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { QuestionKind } from "./QuestionKind";
+import type { WizardChoice } from "./WizardChoice";
+import type { JsonValue } from "./serde_json/JsonValue";
+
+export type WizardQuestion = { 
+/**
+ * The param the answer becomes.
+ */
+key: string, prompt: string, kind: QuestionKind, choices: Array<WizardChoice>, default: JsonValue | null, 
+/**
+ * Optional questions are skipped by answering `null`.
+ */
+required: boolean, };
@@ -0,0 +1,11 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { DeviceGraph } from "./DeviceGraph";
+import type { SourceFacts } from "./SourceFacts";
+import type { TargetFacts } from "./TargetFacts";
+import type { JsonValue } from "./serde_json/JsonValue";
+
+export type WizardSession = { action: string, graph: DeviceGraph, 
+/**
+ * Accepted answers by param name; `null` for skipped ones.
+ */
+answers: { [key in string]?: JsonValue }, target: TargetFacts | null, source: SourceFacts | null, };
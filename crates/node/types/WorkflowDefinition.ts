@@ -0,0 +1,23 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { WorkflowStep } from "./WorkflowStep";
+
+export type WorkflowDefinition = { schema_version: string, name: string, 
+/**
+ * Shown in workflow pickers next to the name.
+ */
+description?: string, steps: Array<WorkflowStep>, 
+/**
+ * Resubmitting a definition with the same key returns the reports of
+ * destructive steps that already completed instead of re-running them.
+ */
+idempotency_key?: string, 
+/**
+ * External reference (ticket, work order) recorded in every report,
+ * run record and notification of the run.
+ */
+correlation_id?: string, 
+/**
+ * Longest the whole run may take before it is cancelled and fails
+ * with a timeout.
+ */
+timeout_secs?: number, };
@@ -0,0 +1,8 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { JsonValue } from "./serde_json/JsonValue";
+
+export type WorkflowStep = { id: string, action: string, params: JsonValue, 
+/**
+ * Longest this step, hooks included, may take.
+ */
+timeout_secs?: number, };
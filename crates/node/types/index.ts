@@ -0,0 +1,31 @@
+// Generated by phoenix-typegen. Do not edit.
+export type { ClaimAction } from "./ClaimAction";
+export type { ConfigSetting } from "./ConfigSetting";
+export type { ConfigSource } from "./ConfigSource";
+export type { DeviceClaim } from "./DeviceClaim";
+export type { DeviceGraph } from "./DeviceGraph";
+export type { DeviceRecord } from "./DeviceRecord";
+export type { Disk } from "./Disk";
+export type { DiskHistory } from "./DiskHistory";
+export type { EffectiveConfig } from "./EffectiveConfig";
+export type { FindingSeverity } from "./FindingSeverity";
+export type { HostInfo } from "./HostInfo";
+export type { MediaManifest } from "./MediaManifest";
+export type { Partition } from "./Partition";
+export type { ProgressEvent } from "./ProgressEvent";
+export type { QuestionKind } from "./QuestionKind";
+export type { ReportFinding } from "./ReportFinding";
+export type { ReportIndexEntry } from "./ReportIndexEntry";
+export type { ReportVerification } from "./ReportVerification";
+export type { RunDetails } from "./RunDetails";
+export type { RunFilter } from "./RunFilter";
+export type { RunMetadata } from "./RunMetadata";
+export type { SourceFacts } from "./SourceFacts";
+export type { StackedDevice } from "./StackedDevice";
+export type { TargetFacts } from "./TargetFacts";
+export type { UsbPort } from "./UsbPort";
+export type { WizardChoice } from "./WizardChoice";
+export type { WizardQuestion } from "./WizardQuestion";
+export type { WizardSession } from "./WizardSession";
+export type { WorkflowDefinition } from "./WorkflowDefinition";
+export type { WorkflowStep } from "./WorkflowStep";
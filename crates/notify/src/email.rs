@@ -0,0 +1,190 @@
+use crate::{RunEvent, RunOutcome};
+use anyhow::{anyhow, Context, Result};
+use lettre::message::header::ContentType;
+use lettre::message::{Attachment, Mailbox, MultiPart, SinglePart};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{Message, SmtpTransport, Transport};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::time::Duration;
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+const DEFAULT_SUBJECT: &str = "[Phoenix] {workflow} {status} on {target}";
+const DEFAULT_BODY: &str = "{summary}
+
+run_id: {run_id}
+correlation_id: {correlation_id}
+workflow: {workflow}
+status: {status}
+target: {target}
+phase: {phase}
+duration_secs: {duration_secs}
+report: {report}
+error: {error}
+";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SmtpSecurity {
+    /// Plain SMTP, typical for lab relays on an isolated network.
+    #[default]
+    None,
+    Starttls,
+    /// Implicit TLS (SMTPS, usually port 465).
+    Tls,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EmailConfig {
+    pub smtp_host: String,
+    #[serde(default)]
+    pub smtp_port: Option<u16>,
+    #[serde(default)]
+    pub security: SmtpSecurity,
+    #[serde(default)]
+    pub username: Option<String>,
+    /// Environment variable holding the SMTP password, so it stays out of
+    /// the config file.
+    #[serde(default)]
+    pub password_env: Option<String>,
+    pub from: String,
+    pub to: Vec<String>,
+    /// Template with `{placeholder}` fields; see `render`.
+    #[serde(default)]
+    pub subject: Option<String>,
+    #[serde(default)]
+    pub body: Option<String>,
+    /// Attach the report bundle as a zip when it is at most
+    /// `max_attachment_bytes`; larger reports are only linked.
+    #[serde(default)]
+    pub attach_report: bool,
+    #[serde(default = "default_max_attachment_bytes")]
+    pub max_attachment_bytes: u64,
+    /// Statuses to send on; empty means all.
+    #[serde(default)]
+    pub on: Vec<RunOutcome>,
+}
+
+fn default_max_attachment_bytes() -> u64 {
+    10 * 1024 * 1024
+}
+
+impl EmailConfig {
+    pub(crate) fn wants(&self, status: RunOutcome) -> bool {
+        self.on.is_empty() || self.on.contains(&status)
+    }
+
+    pub(crate) fn label(&self) -> String {
+        format!("email:{}", self.smtp_host)
+    }
+}
+
+pub(crate) fn send(config: &EmailConfig, event: &RunEvent) -> Result<()> {
+    let subject = render(config.subject.as_deref().unwrap_or(DEFAULT_SUBJECT), event);
+    let body = render(config.body.as_deref().unwrap_or(DEFAULT_BODY), event);
+
+    let mut builder = Message::builder()
+        .from(parse_mailbox(&config.from)?)
+        .subject(subject.lines().next().unwrap_or_default());
+    if config.to.is_empty() {
+        return Err(anyhow!("no recipients"));
+    }
+    for to in &config.to {
+        builder = builder.to(parse_mailbox(to)?);
+    }
+
+    let attachment = if config.attach_report {
+        report_zip(config, event)?
+    } else {
+        None
+    };
+    let message = match attachment {
+        Some((name, bytes)) => builder.multipart(
+            MultiPart::mixed()
+                .singlepart(SinglePart::plain(body))
+                .singlepart(
+                    Attachment::new(name).body(bytes, ContentType::parse("application/zip")?),
+                ),
+        )?,
+        None => builder.header(ContentType::TEXT_PLAIN).body(body)?,
+    };
+
+    let mut transport = match config.security {
+        SmtpSecurity::None => SmtpTransport::builder_dangerous(&config.smtp_host),
+        SmtpSecurity::Starttls => SmtpTransport::starttls_relay(&config.smtp_host)?,
+        SmtpSecurity::Tls => SmtpTransport::relay(&config.smtp_host)?,
+    }
+    .timeout(Some(TIMEOUT));
+    if let Some(port) = config.smtp_port {
+        transport = transport.port(port);
+    }
+    if let Some(username) = &config.username {
+        let password = match &config.password_env {
+            Some(var) => std::env::var(var).map_err(|_| anyhow!("{} not set", var))?,
+            None => String::new(),
+        };
+        transport = transport.credentials(Credentials::new(username.clone(), password));
+    }
+    transport
+        .build()
+        .send(&message)
+        .map_err(|err| anyhow!("smtp send failed: {}", err))?;
+    Ok(())
+}
+
+fn parse_mailbox(address: &str) -> Result<Mailbox> {
+    address
+        .parse()
+        .map_err(|err| anyhow!("invalid address {}: {}", address, err))
+}
+
+/// Zips the local report bundle. Reports over the size limit return `None`
+/// and are only linked in the body.
+fn report_zip(config: &EmailConfig, event: &RunEvent) -> Result<Option<(String, Vec<u8>)>> {
+    let Some(root) = &event.report_root else {
+        return Ok(None);
+    };
+    let name = format!("phoenix-report-{}.zip", event.run_id);
+    let path = std::env::temp_dir().join(&name);
+    phoenix_report::export_report_zip(root, &path)?;
+    let bytes = fs::read(&path).with_context(|| format!("read {}", path.display()));
+    fs::remove_file(&path).ok();
+    let bytes = bytes?;
+    if bytes.len() as u64 > config.max_attachment_bytes {
+        return Ok(None);
+    }
+    Ok(Some((name, bytes)))
+}
+
+/// Replaces `{run_id}`, `{correlation_id}`, `{workflow}`, `{status}`, `{target}`,
+/// `{target_disk}`, `{target_serial}`, `{phase}`, `{duration_secs}`,
+/// `{report}`, `{error}` and `{summary}`. Unknown placeholders are left as-is.
+pub fn render(template: &str, event: &RunEvent) -> String {
+    let status = match event.status {
+        RunOutcome::Completed => "completed",
+        RunOutcome::Failed => "failed",
+    };
+    let target = match &event.target_serial {
+        Some(serial) => format!("{} (serial {})", event.target_disk, serial),
+        None => event.target_disk.clone(),
+    };
+    let fields = [
+        ("run_id", event.run_id.clone()),
+        ("correlation_id", event.correlation_id.clone().unwrap_or_else(|| "-".to_string())),
+        ("workflow", event.workflow.clone()),
+        ("status", status.to_string()),
+        ("target", target),
+        ("target_disk", event.target_disk.clone()),
+        ("target_serial", event.target_serial.clone().unwrap_or_else(|| "-".to_string())),
+        ("phase", event.phase.clone()),
+        ("duration_secs", event.duration_secs.to_string()),
+        ("report", event.report.clone().unwrap_or_else(|| "-".to_string())),
+        ("error", event.error.clone().unwrap_or_else(|| "-".to_string())),
+        ("summary", event.summary()),
+    ];
+    let mut out = template.to_string();
+    for (name, value) in fields {
+        out = out.replace(&format!("{{{}}}", name), &value);
+    }
+    out
+}
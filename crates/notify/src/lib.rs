@@ -0,0 +1,177 @@
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+mod email;
+mod otlp;
+mod signal;
+mod system_log;
+mod webhook;
+
+pub use email::{render, EmailConfig, SmtpSecurity};
+pub use otlp::{
+    export_spans, new_span_id, new_trace_id, otlp_traces_endpoint, set_otlp_endpoint, Span,
+    OTLP_ENDPOINT_ENV, OTLP_HEADERS_ENV, OTLP_TRACES_ENDPOINT_ENV, SERVICE_NAME_ENV,
+};
+pub use signal::SignalConfig;
+pub use system_log::{
+    log_run_finished, log_run_started, register_event_source, SOURCE as SYSTEM_LOG_SOURCE,
+};
+pub use webhook::{WebhookConfig, WebhookKind};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RunOutcome {
+    Completed,
+    Failed,
+}
+
+/// What a channel is told about a finished run.
+#[derive(Debug, Clone, Serialize)]
+pub struct RunEvent {
+    pub run_id: String,
+    pub workflow: String,
+    pub status: RunOutcome,
+    pub target_disk: String,
+    pub target_serial: Option<String>,
+    /// USB port label (or path) of the target, from the device graph.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target_port: Option<String>,
+    /// Raw device of the target, blinked by `signal`.
+    #[serde(skip)]
+    pub target_device: Option<PathBuf>,
+    /// Last phase the run entered.
+    pub phase: String,
+    /// Report URL when `report_url_base` is configured, else the local path.
+    pub report: Option<String>,
+    /// Local report bundle, used for email attachments.
+    #[serde(skip)]
+    pub report_root: Option<PathBuf>,
+    pub error: Option<String>,
+    pub duration_secs: u64,
+    /// Caller's reference for the run (ticket, work order).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub correlation_id: Option<String>,
+}
+
+impl RunEvent {
+    pub fn summary(&self) -> String {
+        let mut target = match &self.target_serial {
+            Some(serial) => format!("{} (serial {})", self.target_disk, serial),
+            None => self.target_disk.clone(),
+        };
+        if let Some(port) = &self.target_port {
+            target.push_str(&format!(" at port {}", port));
+        }
+        let summary = match self.status {
+            RunOutcome::Completed => format!(
+                "Phoenix {} completed on {} in {}s",
+                self.workflow, target, self.duration_secs
+            ),
+            RunOutcome::Failed => format!(
+                "Phoenix {} FAILED on {} during {}",
+                self.workflow, target, self.phase
+            ),
+        };
+        match &self.correlation_id {
+            Some(id) => format!("{} [{}]", summary, id),
+            None => summary,
+        }
+    }
+}
+
+/// Notification channels, read from a JSON file:
+///
+/// ```json
+/// { "report_url_base": "https://reports.example/phoenix",
+///   "webhooks": [ { "kind": "slack", "url": "https://hooks.slack.com/..." } ],
+///   "email": [ { "smtp_host": "mail.lab", "from": "phoenix@lab", "to": ["ops@lab"] } ] }
+/// ```
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct NotifyConfig {
+    /// Reports are linked as `<report_url_base>/<report run id>`.
+    #[serde(default)]
+    pub report_url_base: Option<String>,
+    #[serde(default)]
+    pub webhooks: Vec<WebhookConfig>,
+    #[serde(default)]
+    pub email: Vec<EmailConfig>,
+    /// Also log run start/finish to the OS log (Event Log, journald,
+    /// unified logging).
+    #[serde(default)]
+    pub system_log: bool,
+    /// Bell, desktop notification and LED blink for the bench operator.
+    #[serde(default)]
+    pub signal: Option<SignalConfig>,
+}
+
+impl NotifyConfig {
+    /// A missing file means no channels are configured.
+    pub fn load(path: &Path) -> Result<Self> {
+        let bytes = match fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(err) => return Err(anyhow!("read {} failed: {}", path.display(), err)),
+        };
+        serde_json::from_slice(&bytes).with_context(|| format!("parse {}", path.display()))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.webhooks.is_empty()
+            && self.email.is_empty()
+            && !self.system_log
+            && self.signal.is_none()
+    }
+
+    /// Link for a report bundle directory.
+    pub fn report_link(&self, report_root: &Path) -> String {
+        match (&self.report_url_base, report_root.file_name()) {
+            (Some(base), Some(name)) => {
+                format!("{}/{}", base.trim_end_matches('/'), name.to_string_lossy())
+            }
+            _ => report_root.display().to_string(),
+        }
+    }
+}
+
+/// Outcome of one channel delivery.
+#[derive(Debug, Clone)]
+pub struct Delivery {
+    pub channel: String,
+    pub error: Option<String>,
+}
+
+/// Sends `event` to every channel subscribed to its status. Failures are
+/// returned per channel rather than aborting the remaining deliveries.
+pub fn notify(config: &NotifyConfig, event: &RunEvent) -> Vec<Delivery> {
+    let hooks = config
+        .webhooks
+        .iter()
+        .filter(|hook| hook.wants(event.status))
+        .map(|hook| Delivery {
+            channel: hook.label(),
+            error: webhook::send(hook, event).err().map(|err| format!("{:#}", err)),
+        });
+    let mails = config
+        .email
+        .iter()
+        .filter(|mail| mail.wants(event.status))
+        .map(|mail| Delivery {
+            channel: mail.label(),
+            error: email::send(mail, event).err().map(|err| format!("{:#}", err)),
+        });
+    let system = config.system_log.then(|| Delivery {
+        channel: "system_log".to_string(),
+        error: log_run_finished(event).err().map(|err| format!("{:#}", err)),
+    });
+    let signal = config
+        .signal
+        .as_ref()
+        .filter(|signal| signal.wants(event.status))
+        .map(|signal| Delivery {
+            channel: "signal".to_string(),
+            error: signal::send(signal, event).err().map(|err| format!("{:#}", err)),
+        });
+    hooks.chain(mails).chain(system).chain(signal).collect()
+}
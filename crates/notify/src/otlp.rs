@@ -0,0 +1,173 @@
+//! OTLP/HTTP trace export (JSON encoding), so Phoenix runs show up in an
+//! existing tracing backend next to the rest of the provisioning
+//! automation. Off unless an endpoint is configured; the standard
+//! `OTEL_EXPORTER_OTLP_*` variables are honoured.
+
+use anyhow::{anyhow, Result};
+use std::sync::Mutex;
+use std::time::Duration;
+use uuid::Uuid;
+
+pub const OTLP_ENDPOINT_ENV: &str = "OTEL_EXPORTER_OTLP_ENDPOINT";
+pub const OTLP_TRACES_ENDPOINT_ENV: &str = "OTEL_EXPORTER_OTLP_TRACES_ENDPOINT";
+pub const OTLP_HEADERS_ENV: &str = "OTEL_EXPORTER_OTLP_HEADERS";
+pub const SERVICE_NAME_ENV: &str = "OTEL_SERVICE_NAME";
+
+const DEFAULT_SERVICE_NAME: &str = "phoenix-core";
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Set by `set_otlp_endpoint`; takes precedence over the environment.
+static ENDPOINT: Mutex<Option<String>> = Mutex::new(None);
+
+/// Overrides the environment for this process. The value is a base URL,
+/// like `OTEL_EXPORTER_OTLP_ENDPOINT`; `/v1/traces` is appended.
+pub fn set_otlp_endpoint(endpoint: Option<&str>) {
+    if let Ok(mut current) = ENDPOINT.lock() {
+        *current = endpoint.map(str::to_string);
+    }
+}
+
+/// Full URL spans are posted to, or `None` when export is off.
+pub fn otlp_traces_endpoint() -> Option<String> {
+    let base = ENDPOINT.lock().ok().and_then(|current| current.clone());
+    if let Some(base) = base {
+        return Some(traces_url(&base));
+    }
+    if let Some(url) = non_empty_env(OTLP_TRACES_ENDPOINT_ENV) {
+        return Some(url);
+    }
+    non_empty_env(OTLP_ENDPOINT_ENV).map(|base| traces_url(&base))
+}
+
+fn traces_url(base: &str) -> String {
+    format!("{}/v1/traces", base.trim_end_matches('/'))
+}
+
+fn non_empty_env(name: &str) -> Option<String> {
+    std::env::var(name).ok().filter(|value| !value.trim().is_empty())
+}
+
+/// 32 lowercase hex digits.
+pub fn new_trace_id() -> String {
+    Uuid::new_v4().simple().to_string()
+}
+
+/// 16 lowercase hex digits.
+pub fn new_span_id() -> String {
+    Uuid::new_v4().simple().to_string()[..16].to_string()
+}
+
+#[derive(Debug, Clone)]
+pub struct Span {
+    pub trace_id: String,
+    pub span_id: String,
+    pub parent_span_id: Option<String>,
+    pub name: String,
+    pub start_unix_nanos: u64,
+    pub end_unix_nanos: u64,
+    /// Marks the span failed with this message.
+    pub error: Option<String>,
+    pub attributes: Vec<(String, String)>,
+}
+
+/// Posts `spans` in one request. Headers come from
+/// `OTEL_EXPORTER_OTLP_HEADERS` (`key=value,key=value`).
+pub fn export_spans(endpoint: &str, spans: &[Span]) -> Result<()> {
+    let mut request = phoenix_fetch::agent_builder(endpoint)?
+        .build()
+        .post(endpoint)
+        .timeout(TIMEOUT)
+        .set("Content-Type", "application/json");
+    for (name, value) in parse_headers(&std::env::var(OTLP_HEADERS_ENV).unwrap_or_default()) {
+        request = request.set(&name, &value);
+    }
+    match request.send_string(&payload(spans).to_string()) {
+        Ok(_) => Ok(()),
+        Err(ureq::Error::Status(code, _)) => Err(anyhow!("HTTP {}", code)),
+        // Keep the URL, which may carry credentials, out of the message.
+        Err(ureq::Error::Transport(transport)) => Err(anyhow!(
+            "{}: {}",
+            transport.kind(),
+            transport.message().unwrap_or("no details")
+        )),
+    }
+}
+
+/// `ExportTraceServiceRequest` in the OTLP JSON mapping: ids as hex,
+/// 64-bit times as decimal strings.
+fn payload(spans: &[Span]) -> serde_json::Value {
+    let service = non_empty_env(SERVICE_NAME_ENV).unwrap_or_else(|| DEFAULT_SERVICE_NAME.into());
+    let spans: Vec<serde_json::Value> = spans
+        .iter()
+        .map(|span| {
+            let mut value = serde_json::json!({
+                "traceId": span.trace_id,
+                "spanId": span.span_id,
+                "name": span.name,
+                "kind": 1,
+                "startTimeUnixNano": span.start_unix_nanos.to_string(),
+                "endTimeUnixNano": span.end_unix_nanos.to_string(),
+                "attributes": attributes(&span.attributes),
+                "status": match &span.error {
+                    Some(message) => serde_json::json!({ "code": 2, "message": message }),
+                    None => serde_json::json!({ "code": 1 }),
+                },
+            });
+            if let Some(parent) = &span.parent_span_id {
+                value["parentSpanId"] = serde_json::json!(parent);
+            }
+            value
+        })
+        .collect();
+    serde_json::json!({
+        "resourceSpans": [{
+            "resource": {
+                "attributes": attributes(&[("service.name".to_string(), service)]),
+            },
+            "scopeSpans": [{
+                "scope": { "name": "phoenix-core", "version": env!("CARGO_PKG_VERSION") },
+                "spans": spans,
+            }],
+        }],
+    })
+}
+
+fn attributes(pairs: &[(String, String)]) -> Vec<serde_json::Value> {
+    pairs
+        .iter()
+        .map(|(key, value)| serde_json::json!({ "key": key, "value": { "stringValue": value } }))
+        .collect()
+}
+
+/// Values may be percent-encoded, as the OpenTelemetry spec allows.
+fn parse_headers(value: &str) -> Vec<(String, String)> {
+    value
+        .split(',')
+        .filter_map(|pair| pair.split_once('='))
+        .map(|(name, value)| (name.trim().to_string(), percent_decode(value.trim())))
+        .filter(|(name, _)| !name.is_empty())
+        .collect()
+}
+
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        let hex = bytes
+            .get(index + 1..index + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[index], hex) {
+            (b'%', Some(byte)) => {
+                out.push(byte);
+                index += 3;
+            }
+            (byte, _) => {
+                out.push(byte);
+                index += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).to_string()
+}
@@ -0,0 +1,186 @@
+//! Signals a finished run to whoever is at the bench: the terminal bell, a
+//! desktop notification, and the target's activity LED blinking under
+//! harmless reads, so the operator can tell which stick to pull.
+
+use crate::{RunEvent, RunOutcome};
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// Reads are whole 4 KiB blocks at 4 KiB offsets, which raw Windows
+/// devices require.
+const BLINK_BLOCK: usize = 4096;
+/// Step between reads. Each read lands on a block not read before, so
+/// the page cache cannot absorb it and the device has to do the work.
+const BLINK_STRIDE: u64 = 7 * 1024 * 1024 + BLINK_BLOCK as u64;
+/// Span read when the device size is unknown; smaller than any stick.
+const BLINK_FALLBACK_SPAN: u64 = 64 * 1024 * 1024;
+const BLINK_ON: Duration = Duration::from_millis(250);
+const BLINK_OFF: Duration = Duration::from_millis(250);
+const BELL_GAP: Duration = Duration::from_millis(300);
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SignalConfig {
+    /// Ring the terminal bell: once on completion, three times on failure.
+    #[serde(default)]
+    pub bell: bool,
+    /// Show a desktop notification with the run summary.
+    #[serde(default)]
+    pub toast: bool,
+    /// Seconds to blink the target's activity LED; 0 turns it off.
+    #[serde(default)]
+    pub blink_secs: u64,
+    /// Statuses to signal; empty means all.
+    #[serde(default)]
+    pub on: Vec<RunOutcome>,
+}
+
+impl SignalConfig {
+    pub(crate) fn wants(&self, status: RunOutcome) -> bool {
+        self.on.is_empty() || self.on.contains(&status)
+    }
+}
+
+/// Runs every configured signal, blinking last since it blocks for
+/// `blink_secs`. One failing signal does not stop the others.
+pub(crate) fn send(config: &SignalConfig, event: &RunEvent) -> Result<()> {
+    let mut errors = Vec::new();
+    if config.bell {
+        if let Err(err) = ring_bell(event.status) {
+            errors.push(format!("bell: {:#}", err));
+        }
+    }
+    if config.toast {
+        let title = match event.status {
+            RunOutcome::Completed => "Phoenix run completed",
+            RunOutcome::Failed => "Phoenix run FAILED",
+        };
+        if let Err(err) = toast(title, &event.summary()) {
+            errors.push(format!("toast: {:#}", err));
+        }
+    }
+    if config.blink_secs > 0 {
+        if let Some(device) = &event.target_device {
+            if let Err(err) = blink(device, Duration::from_secs(config.blink_secs)) {
+                errors.push(format!("blink: {:#}", err));
+            }
+        }
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(anyhow!(errors.join("; ")))
+    }
+}
+
+/// On stderr, so output piped as JSON stays clean.
+fn ring_bell(status: RunOutcome) -> Result<()> {
+    let rings = match status {
+        RunOutcome::Completed => 1,
+        RunOutcome::Failed => 3,
+    };
+    let mut stderr = std::io::stderr();
+    for ring in 0..rings {
+        if ring > 0 {
+            std::thread::sleep(BELL_GAP);
+        }
+        stderr.write_all(b"\x07")?;
+        stderr.flush()?;
+    }
+    Ok(())
+}
+
+/// Scripted notifiers read the text from the environment, so nothing
+/// has to be quoted into the script.
+fn toast(title: &str, body: &str) -> Result<()> {
+    let mut command = toast_command(title, body)?;
+    let output = command
+        .env("PHOENIX_TOAST_TITLE", title)
+        .env("PHOENIX_TOAST_BODY", body)
+        .output()
+        .with_context(|| format!("run {:?}", command.get_program()))?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "{:?} failed: {}",
+            command.get_program(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn toast_command(title: &str, body: &str) -> Result<std::process::Command> {
+    let mut command = std::process::Command::new("notify-send");
+    command.args(["-a", "Phoenix", title, body]);
+    Ok(command)
+}
+
+#[cfg(target_os = "macos")]
+fn toast_command(_title: &str, _body: &str) -> Result<std::process::Command> {
+    let mut command = std::process::Command::new("/usr/bin/osascript");
+    command.args([
+        "-e",
+        r#"display notification (system attribute "PHOENIX_TOAST_BODY") with title (system attribute "PHOENIX_TOAST_TITLE")"#,
+    ]);
+    Ok(command)
+}
+
+/// Shown under PowerShell's app id, which every Windows install registers.
+#[cfg(windows)]
+fn toast_command(_title: &str, _body: &str) -> Result<std::process::Command> {
+    const SCRIPT: &str = r#"$ErrorActionPreference = 'Stop'
+$manager = [Windows.UI.Notifications.ToastNotificationManager, Windows.UI.Notifications, ContentType = WindowsRuntime]
+$template = $manager::GetTemplateContent([Windows.UI.Notifications.ToastTemplateType]::ToastText02)
+$text = $template.GetElementsByTagName('text')
+[void]$text.Item(0).AppendChild($template.CreateTextNode($env:PHOENIX_TOAST_TITLE))
+[void]$text.Item(1).AppendChild($template.CreateTextNode($env:PHOENIX_TOAST_BODY))
+$toast = [Windows.UI.Notifications.ToastNotification]::new($template)
+$manager::CreateToastNotifier('{1AC14E77-02E7-4E5D-B744-2EB1AE5198B7}\WindowsPowerShell\v1.0\powershell.exe').Show($toast)"#;
+    let mut command = std::process::Command::new("powershell.exe");
+    command.args(["-NoProfile", "-NonInteractive", "-Command", SCRIPT]);
+    Ok(command)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+fn toast_command(_title: &str, _body: &str) -> Result<std::process::Command> {
+    Err(anyhow!("desktop notifications are not supported on this platform"))
+}
+
+/// Reads in bursts (`BLINK_ON` reading, `BLINK_OFF` idle) for `duration`.
+/// Opened read-only; nothing is written. Stops early, without an error,
+/// once the device goes away, which usually means the stick was pulled.
+fn blink(device: &Path, duration: Duration) -> Result<()> {
+    let mut file = File::open(device).with_context(|| format!("open {}", device.display()))?;
+    // Raw Windows drives do not report a size through seeking.
+    let span = match file.seek(SeekFrom::End(0)) {
+        Ok(size) if size > 0 => size,
+        _ => BLINK_FALLBACK_SPAN,
+    };
+    let blocks = (span / BLINK_BLOCK as u64).max(1);
+    let stride = BLINK_STRIDE / BLINK_BLOCK as u64;
+    let mut buf = vec![0u8; BLINK_BLOCK];
+    let mut block = 0u64;
+    let mut reads = 0u64;
+    let deadline = Instant::now() + duration;
+    while Instant::now() < deadline {
+        let burst_end = Instant::now() + BLINK_ON;
+        while Instant::now() < burst_end {
+            block = (block + stride) % blocks;
+            let read = file
+                .seek(SeekFrom::Start(block * BLINK_BLOCK as u64))
+                .and_then(|_| file.read_exact(&mut buf));
+            match read {
+                Ok(()) => reads += 1,
+                Err(_) if reads > 0 => return Ok(()),
+                Err(err) => return Err(anyhow!("read {}: {}", device.display(), err)),
+            }
+        }
+        std::thread::sleep(BLINK_OFF);
+    }
+    Ok(())
+}
@@ -0,0 +1,345 @@
+use crate::{RunEvent, RunOutcome};
+use anyhow::Result;
+
+/// Source / identifier runs are logged under.
+pub const SOURCE: &str = "Phoenix";
+
+/// Windows event ids, one per lifecycle stage.
+const EVENT_STARTED: u32 = 1000;
+const EVENT_COMPLETED: u32 = 1001;
+const EVENT_FAILED: u32 = 1002;
+
+struct Entry {
+    event_id: u32,
+    error: bool,
+    message: String,
+    fields: Vec<(&'static str, String)>,
+}
+
+/// Run start, logged before the first destructive phase.
+pub fn log_run_started(
+    run_id: &str,
+    workflow: &str,
+    target_disk: &str,
+    target_serial: Option<&str>,
+    correlation_id: Option<&str>,
+) -> Result<()> {
+    let target = target_label(target_disk, target_serial);
+    let mut fields = vec![
+        ("PHOENIX_EVENT", "run_started".to_string()),
+        ("PHOENIX_RUN_ID", run_id.to_string()),
+        ("PHOENIX_WORKFLOW", workflow.to_string()),
+        ("PHOENIX_TARGET_DISK", target_disk.to_string()),
+    ];
+    if let Some(serial) = target_serial {
+        fields.push(("PHOENIX_TARGET_SERIAL", serial.to_string()));
+    }
+    if let Some(id) = correlation_id {
+        fields.push(("PHOENIX_CORRELATION_ID", id.to_string()));
+    }
+    write(&Entry {
+        event_id: EVENT_STARTED,
+        error: false,
+        message: format!("Phoenix {} started on {} (run {})", workflow, target, run_id),
+        fields,
+    })
+}
+
+pub fn log_run_finished(event: &RunEvent) -> Result<()> {
+    let failed = event.status == RunOutcome::Failed;
+    let mut fields = vec![
+        (
+            "PHOENIX_EVENT",
+            if failed { "run_failed" } else { "run_completed" }.to_string(),
+        ),
+        ("PHOENIX_RUN_ID", event.run_id.clone()),
+        ("PHOENIX_WORKFLOW", event.workflow.clone()),
+        ("PHOENIX_TARGET_DISK", event.target_disk.clone()),
+        ("PHOENIX_PHASE", event.phase.clone()),
+        ("PHOENIX_DURATION_SECS", event.duration_secs.to_string()),
+    ];
+    if let Some(serial) = &event.target_serial {
+        fields.push(("PHOENIX_TARGET_SERIAL", serial.clone()));
+    }
+    if let Some(id) = &event.correlation_id {
+        fields.push(("PHOENIX_CORRELATION_ID", id.clone()));
+    }
+    if let Some(report) = &event.report {
+        fields.push(("PHOENIX_REPORT", report.clone()));
+    }
+    if let Some(error) = &event.error {
+        fields.push(("PHOENIX_ERROR", error.clone()));
+    }
+    let mut message = format!("{} (run {})", event.summary(), event.run_id);
+    if let Some(error) = &event.error {
+        message.push_str(&format!(": {}", error));
+    }
+    write(&Entry {
+        event_id: if failed { EVENT_FAILED } else { EVENT_COMPLETED },
+        error: failed,
+        message,
+        fields,
+    })
+}
+
+fn target_label(disk: &str, serial: Option<&str>) -> String {
+    match serial {
+        Some(serial) => format!("{} (serial {})", disk, serial),
+        None => disk.to_string(),
+    }
+}
+
+/// journald's native protocol, so the `PHOENIX_*` fields stay queryable
+/// (`journalctl PHOENIX_EVENT=run_failed`). Falls back to syslog when the
+/// journal socket is absent.
+#[cfg(target_os = "linux")]
+fn write(entry: &Entry) -> Result<()> {
+    use std::os::unix::net::UnixDatagram;
+
+    const JOURNAL_SOCKET: &str = "/run/systemd/journal/socket";
+    if !std::path::Path::new(JOURNAL_SOCKET).exists() {
+        return write_syslog(entry);
+    }
+    let priority = if entry.error { "3" } else { "6" };
+    let mut datagram = Vec::new();
+    journal_field(&mut datagram, "MESSAGE", &entry.message);
+    journal_field(&mut datagram, "PRIORITY", priority);
+    journal_field(&mut datagram, "SYSLOG_IDENTIFIER", &SOURCE.to_lowercase());
+    journal_field(&mut datagram, "PHOENIX_EVENT_ID", &entry.event_id.to_string());
+    for (name, value) in &entry.fields {
+        journal_field(&mut datagram, name, value);
+    }
+    let socket = UnixDatagram::unbound()?;
+    socket
+        .send_to(&datagram, JOURNAL_SOCKET)
+        .map_err(|err| anyhow::anyhow!("journald send failed: {}", err))?;
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn journal_field(out: &mut Vec<u8>, name: &str, value: &str) {
+    out.extend_from_slice(name.as_bytes());
+    if value.contains('\n') {
+        // Multi-line values use the length-prefixed binary form.
+        out.push(b'\n');
+        out.extend_from_slice(&(value.len() as u64).to_le_bytes());
+        out.extend_from_slice(value.as_bytes());
+    } else {
+        out.push(b'=');
+        out.extend_from_slice(value.as_bytes());
+    }
+    out.push(b'\n');
+}
+
+/// On macOS, syslog(3) feeds the unified log (`log show --predicate
+/// 'process == "phoenix-cli"'`).
+#[cfg(all(unix, not(target_os = "linux")))]
+fn write(entry: &Entry) -> Result<()> {
+    write_syslog(entry)
+}
+
+#[cfg(unix)]
+fn write_syslog(entry: &Entry) -> Result<()> {
+    use std::ffi::CString;
+
+    let mut line = format!("{} event_id={}", entry.message, entry.event_id);
+    for (name, value) in &entry.fields {
+        line.push_str(&format!(
+            " {}={}",
+            name.trim_start_matches("PHOENIX_").to_lowercase(),
+            value.replace('\n', " ")
+        ));
+    }
+    let line = CString::new(line.replace('\0', " "))?;
+    let priority = if entry.error { libc::LOG_ERR } else { libc::LOG_INFO };
+    unsafe {
+        libc::openlog(c"phoenix".as_ptr(), libc::LOG_PID, libc::LOG_USER);
+        libc::syslog(priority, c"%s".as_ptr(), line.as_ptr());
+    }
+    Ok(())
+}
+
+#[cfg(windows)]
+fn write(entry: &Entry) -> Result<()> {
+    let mut text = entry.message.clone();
+    for (name, value) in &entry.fields {
+        text.push_str(&format!("\r\n{}={}", name, value));
+    }
+    event_log::report(entry.event_id, entry.error, &text)
+}
+
+#[cfg(not(any(unix, windows)))]
+fn write(_entry: &Entry) -> Result<()> {
+    Err(anyhow::anyhow!("no system log on this platform"))
+}
+
+/// Registers the `Phoenix` Event Log source (needs Administrator). Without
+/// it events are still written, but Event Viewer cannot render their text.
+#[cfg(windows)]
+pub fn register_event_source() -> Result<()> {
+    event_log::register()
+}
+
+#[cfg(not(windows))]
+pub fn register_event_source() -> Result<()> {
+    Err(anyhow::anyhow!("event source registration requires Windows"))
+}
+
+#[cfg(windows)]
+mod event_log {
+    use super::SOURCE;
+    use anyhow::{anyhow, Result};
+    use std::ffi::c_void;
+
+    type Handle = *mut c_void;
+
+    const HKEY_LOCAL_MACHINE: isize = 0x8000_0002u32 as i32 as isize;
+    const KEY_WRITE: u32 = 0x20006;
+    const REG_EXPAND_SZ: u32 = 2;
+    const REG_DWORD: u32 = 4;
+    const EVENTLOG_ERROR_TYPE: u16 = 0x0001;
+    const EVENTLOG_INFORMATION_TYPE: u16 = 0x0004;
+    /// Message file shipped with .NET that maps every event id to `%1`.
+    const MESSAGE_FILE: &str =
+        "%SystemRoot%\\Microsoft.NET\\Framework\\v4.0.30319\\EventLogMessages.dll";
+
+    #[link(name = "advapi32")]
+    extern "system" {
+        fn RegisterEventSourceW(server: *const u16, source: *const u16) -> Handle;
+        fn DeregisterEventSource(log: Handle) -> i32;
+        fn ReportEventW(
+            log: Handle,
+            kind: u16,
+            category: u16,
+            event_id: u32,
+            user_sid: *const c_void,
+            num_strings: u16,
+            data_size: u32,
+            strings: *const *const u16,
+            raw_data: *const c_void,
+        ) -> i32;
+        fn RegCreateKeyExW(
+            key: isize,
+            subkey: *const u16,
+            reserved: u32,
+            class: *const u16,
+            options: u32,
+            desired: u32,
+            security: *const c_void,
+            result: *mut isize,
+            disposition: *mut u32,
+        ) -> i32;
+        fn RegSetValueExW(
+            key: isize,
+            name: *const u16,
+            reserved: u32,
+            kind: u32,
+            data: *const u8,
+            size: u32,
+        ) -> i32;
+        fn RegCloseKey(key: isize) -> i32;
+    }
+
+    fn wide(value: &str) -> Vec<u16> {
+        value.encode_utf16().chain(std::iter::once(0)).collect()
+    }
+
+    pub fn report(event_id: u32, error: bool, text: &str) -> Result<()> {
+        let source = wide(SOURCE);
+        let log = unsafe { RegisterEventSourceW(std::ptr::null(), source.as_ptr()) };
+        if log.is_null() {
+            return Err(anyhow!(
+                "RegisterEventSource failed: {}",
+                std::io::Error::last_os_error()
+            ));
+        }
+        let text = wide(text);
+        let strings = [text.as_ptr()];
+        let kind = if error {
+            EVENTLOG_ERROR_TYPE
+        } else {
+            EVENTLOG_INFORMATION_TYPE
+        };
+        let ok = unsafe {
+            ReportEventW(
+                log,
+                kind,
+                0,
+                event_id,
+                std::ptr::null(),
+                1,
+                0,
+                strings.as_ptr(),
+                std::ptr::null(),
+            )
+        };
+        let err = std::io::Error::last_os_error();
+        unsafe { DeregisterEventSource(log) };
+        if ok == 0 {
+            return Err(anyhow!("ReportEvent failed: {}", err));
+        }
+        Ok(())
+    }
+
+    pub fn register() -> Result<()> {
+        let path = wide(&format!(
+            "SYSTEM\\CurrentControlSet\\Services\\EventLog\\Application\\{}",
+            SOURCE
+        ));
+        let mut key = 0isize;
+        let status = unsafe {
+            RegCreateKeyExW(
+                HKEY_LOCAL_MACHINE,
+                path.as_ptr(),
+                0,
+                std::ptr::null(),
+                0,
+                KEY_WRITE,
+                std::ptr::null(),
+                &mut key,
+                std::ptr::null_mut(),
+            )
+        };
+        if status != 0 {
+            return Err(anyhow!(
+                "create event source key failed: {}",
+                std::io::Error::from_raw_os_error(status)
+            ));
+        }
+        let file = wide(MESSAGE_FILE);
+        let types: u32 = 0x7;
+        let name = wide("EventMessageFile");
+        let types_name = wide("TypesSupported");
+        let status = unsafe {
+            let first = RegSetValueExW(
+                key,
+                name.as_ptr(),
+                0,
+                REG_EXPAND_SZ,
+                file.as_ptr() as *const u8,
+                (file.len() * 2) as u32,
+            );
+            let second = RegSetValueExW(
+                key,
+                types_name.as_ptr(),
+                0,
+                REG_DWORD,
+                &types as *const u32 as *const u8,
+                4,
+            );
+            RegCloseKey(key);
+            if first != 0 {
+                first
+            } else {
+                second
+            }
+        };
+        if status != 0 {
+            return Err(anyhow!(
+                "write event source values failed: {}",
+                std::io::Error::from_raw_os_error(status)
+            ));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,98 @@
+use crate::{RunEvent, RunOutcome};
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookKind {
+    /// Slack incoming webhook (`{"text": ...}`).
+    Slack,
+    /// Microsoft Teams incoming webhook (MessageCard).
+    Teams,
+    /// The `RunEvent` itself as a JSON body.
+    Json,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebhookConfig {
+    pub kind: WebhookKind,
+    pub url: String,
+    /// Statuses to fire on; empty means all.
+    #[serde(default)]
+    pub on: Vec<RunOutcome>,
+    /// Extra request headers, e.g. `Authorization` for generic endpoints.
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+}
+
+impl WebhookConfig {
+    pub(crate) fn wants(&self, status: RunOutcome) -> bool {
+        self.on.is_empty() || self.on.contains(&status)
+    }
+
+    /// Channel name without the URL, which usually embeds a secret.
+    pub(crate) fn label(&self) -> String {
+        let host = self
+            .url
+            .split("://")
+            .nth(1)
+            .and_then(|rest| rest.split('/').next())
+            .unwrap_or("?");
+        format!("{:?}:{}", self.kind, host).to_lowercase()
+    }
+}
+
+pub(crate) fn send(hook: &WebhookConfig, event: &RunEvent) -> Result<()> {
+    let body = payload(hook.kind, event);
+    let mut request = phoenix_fetch::agent_builder(&hook.url)?
+        .build()
+        .post(&hook.url)
+        .timeout(TIMEOUT)
+        .set("Content-Type", "application/json");
+    for (name, value) in &hook.headers {
+        request = request.set(name, value);
+    }
+    match request.send_string(&body.to_string()) {
+        Ok(_) => Ok(()),
+        Err(ureq::Error::Status(code, _)) => Err(anyhow!("HTTP {}", code)),
+        // The transport error's Display includes the URL; keep secrets out.
+        Err(ureq::Error::Transport(transport)) => Err(anyhow!(
+            "{}: {}",
+            transport.kind(),
+            transport.message().unwrap_or("no details")
+        )),
+    }
+}
+
+pub(crate) fn payload(kind: WebhookKind, event: &RunEvent) -> serde_json::Value {
+    let mut lines = vec![event.summary()];
+    lines.push(format!("run_id: {}", event.run_id));
+    if let Some(id) = &event.correlation_id {
+        lines.push(format!("correlation_id: {}", id));
+    }
+    if let Some(report) = &event.report {
+        lines.push(format!("report: {}", report));
+    }
+    if let Some(error) = &event.error {
+        lines.push(format!("error: {}", error));
+    }
+    match kind {
+        WebhookKind::Slack => serde_json::json!({ "text": lines.join("\n") }),
+        WebhookKind::Teams => serde_json::json!({
+            "@type": "MessageCard",
+            "@context": "https://schema.org/extensions",
+            "summary": event.summary(),
+            "themeColor": match event.status {
+                RunOutcome::Completed => "2EB67D",
+                RunOutcome::Failed => "E01E5A",
+            },
+            "title": event.summary(),
+            "text": lines[1..].join("<br>"),
+        }),
+        WebhookKind::Json => serde_json::to_value(event).unwrap_or_default(),
+    }
+}
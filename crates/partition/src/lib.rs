@@ -0,0 +1,563 @@
+use anyhow::{anyhow, Context, Result};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+use uuid::Uuid;
+
+pub mod plan;
+
+pub use plan::{
+    gpt_name_units, parse_partition_type, parse_size, plan_partitions, GptAttributes, HybridMbr,
+    PartitionPlan, PartitionSpec, PlannedPartition,
+};
+
+pub const DEFAULT_SECTOR_SIZE: u64 = 512;
+
+const GPT_SIGNATURE: &[u8; 8] = b"EFI PART";
+const GPT_REVISION: u32 = 0x0001_0000;
+const GPT_MIN_HEADER_SIZE: u32 = 92;
+/// Upper bounds for a header's partition array, checked before it is read
+/// so a corrupt header cannot make us allocate gigabytes.
+const GPT_MAX_ENTRY_SIZE: u32 = 4096;
+const GPT_MAX_ARRAY_BYTES: u64 = 1024 * 1024;
+const MBR_PROTECTIVE_TYPE: u8 = 0xEE;
+
+#[derive(Debug, Clone)]
+pub struct GptHeader {
+    pub my_lba: u64,
+    pub alternate_lba: u64,
+    pub first_usable_lba: u64,
+    pub last_usable_lba: u64,
+    pub disk_guid: Uuid,
+    pub entries_lba: u64,
+    pub entry_count: u32,
+    pub entry_size: u32,
+    pub entries_crc32: u32,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct MbrEntry {
+    pub bootable: bool,
+    pub partition_type: u8,
+    pub first_lba: u32,
+    pub sector_count: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct PartitionTableCheck {
+    pub ok: bool,
+    pub issues: Vec<String>,
+    pub mbr_entries: Vec<MbrEntry>,
+    pub primary: Option<GptHeader>,
+    pub backup: Option<GptHeader>,
+    pub partition_count: usize,
+    /// Non-fatal findings, e.g. a hybrid MBR being present.
+    pub warnings: Vec<String>,
+}
+
+/// Reads back the protective MBR and both GPT headers and checks their
+/// signatures, CRCs and cross-references.
+pub fn verify_partition_tables<D: Read + Seek>(
+    device: &mut D,
+    disk_size: u64,
+    sector_size: u64,
+) -> Result<PartitionTableCheck> {
+    if sector_size < 512 || !sector_size.is_power_of_two() {
+        return Err(anyhow!("invalid sector size {}", sector_size));
+    }
+    let total_sectors = disk_size / sector_size;
+    if total_sectors < 4 {
+        return Err(anyhow!("device too small for GPT"));
+    }
+    let last_lba = total_sectors - 1;
+    let mut issues = Vec::new();
+
+    let mbr = read_sector(device, 0, sector_size)?;
+    let mbr_entries = parse_mbr(&mbr, &mut issues);
+    if !mbr_entries
+        .iter()
+        .any(|entry| entry.partition_type == MBR_PROTECTIVE_TYPE && entry.first_lba == 1)
+    {
+        issues.push("MBR has no protective 0xEE entry starting at LBA 1".to_string());
+    }
+
+    let primary = check_header(
+        device,
+        1,
+        last_lba,
+        total_sectors,
+        sector_size,
+        "primary",
+        &mut issues,
+    )?;
+    let backup = check_header(
+        device,
+        last_lba,
+        1,
+        total_sectors,
+        sector_size,
+        "backup",
+        &mut issues,
+    )?;
+
+    let mut ranges = Vec::new();
+    if let (Some((primary, entries)), Some((backup, _))) = (&primary, &backup) {
+        if primary.disk_guid != backup.disk_guid {
+            issues.push("primary and backup GPT disk GUIDs differ".to_string());
+        }
+        if primary.entries_crc32 != backup.entries_crc32 {
+            issues.push("primary and backup GPT partition arrays differ".to_string());
+        }
+        if primary.first_usable_lba != backup.first_usable_lba
+            || primary.last_usable_lba != backup.last_usable_lba
+        {
+            issues.push("primary and backup GPT usable ranges differ".to_string());
+        }
+        ranges = entries
+            .chunks(primary.entry_size as usize)
+            .filter(|entry| entry[..16].iter().any(|byte| *byte != 0))
+            .map(|entry| (read_u64(entry, 32), read_u64(entry, 40)))
+            .collect();
+    }
+    let mut warnings = Vec::new();
+    lint_hybrid_mbr(&mbr_entries, &ranges, &mut issues, &mut warnings);
+
+    Ok(PartitionTableCheck {
+        ok: issues.is_empty(),
+        issues,
+        mbr_entries,
+        primary: primary.map(|(header, _)| header),
+        backup: backup.map(|(header, _)| header),
+        partition_count: ranges.len(),
+        warnings,
+    })
+}
+
+/// Checks MBR entries other than the protective one. Each must mirror a GPT
+/// partition exactly and must not overlap the protective range, otherwise
+/// legacy firmware and the OS see different layouts.
+fn lint_hybrid_mbr(
+    mbr_entries: &[MbrEntry],
+    gpt_ranges: &[(u64, u64)],
+    issues: &mut Vec<String>,
+    warnings: &mut Vec<String>,
+) {
+    let hybrid: Vec<&MbrEntry> = mbr_entries
+        .iter()
+        .filter(|entry| entry.partition_type != MBR_PROTECTIVE_TYPE)
+        .collect();
+    if hybrid.is_empty() {
+        return;
+    }
+    warnings.push(format!(
+        "hybrid MBR present with {} mirrored entr{}",
+        hybrid.len(),
+        if hybrid.len() == 1 { "y" } else { "ies" }
+    ));
+    if hybrid.iter().filter(|entry| entry.bootable).count() > 1 {
+        issues.push("hybrid MBR marks more than one entry bootable".to_string());
+    }
+    for entry in &hybrid {
+        let first = entry.first_lba as u64;
+        let last = first + (entry.sector_count as u64).saturating_sub(1);
+        if !gpt_ranges.contains(&(first, last)) {
+            issues.push(format!(
+                "hybrid MBR entry type 0x{:02X} at LBA {}-{} does not match a GPT partition",
+                entry.partition_type, first, last
+            ));
+        }
+        for protective in mbr_entries
+            .iter()
+            .filter(|entry| entry.partition_type == MBR_PROTECTIVE_TYPE)
+        {
+            let p_first = protective.first_lba as u64;
+            let p_last = p_first + (protective.sector_count as u64).saturating_sub(1);
+            if first <= p_last && p_first <= last {
+                issues.push(format!(
+                    "hybrid MBR entry at LBA {} overlaps the protective 0xEE entry",
+                    first
+                ));
+            }
+        }
+    }
+}
+
+pub fn verify_device_partition_tables(
+    device_path: impl AsRef<Path>,
+    disk_size: Option<u64>,
+    sector_size: u64,
+) -> Result<PartitionTableCheck> {
+    let path = device_path.as_ref();
+    let mut file = File::open(path).with_context(|| format!("open {}", path.display()))?;
+    let size = match disk_size {
+        Some(size) => size,
+        None => file.seek(SeekFrom::End(0))?,
+    };
+    verify_partition_tables(&mut file, size, sector_size)
+}
+
+/// Reads the GPT on `device` back into a plan, so a partition can be
+/// resized and the tables written again. The tables must verify and the
+/// partitions must be numbered without gaps.
+pub fn read_gpt<D: Read + Seek>(device: &mut D, disk_size: u64, sector_size: u64) -> Result<PartitionPlan> {
+    let check = verify_partition_tables(device, disk_size, sector_size)?;
+    if !check.ok {
+        return Err(anyhow!(
+            "partition table does not verify: {}",
+            check.issues.join("; ")
+        ));
+    }
+    let header = check
+        .primary
+        .ok_or_else(|| anyhow!("device has no primary GPT"))?;
+    if header.first_usable_lba < 2 + plan::entry_array_sectors(sector_size) {
+        return Err(anyhow!(
+            "GPT partition array is smaller than {} entries",
+            plan::GPT_ENTRY_COUNT
+        ));
+    }
+    let array_bytes = header.entry_count as u64 * header.entry_size as u64;
+    let entries = read_sectors(
+        device,
+        header.entries_lba,
+        array_bytes.div_ceil(sector_size),
+        sector_size,
+    )?;
+    let mut partitions = Vec::new();
+    let mut gap = false;
+    for (index, entry) in entries[..array_bytes as usize]
+        .chunks(header.entry_size as usize)
+        .enumerate()
+    {
+        if entry[..16].iter().all(|byte| *byte == 0) {
+            gap = true;
+            continue;
+        }
+        if gap || index >= plan::GPT_ENTRY_COUNT as usize {
+            return Err(anyhow!("GPT partition {} follows an empty entry", index + 1));
+        }
+        let units: Vec<u16> = entry[56..56 + plan::GPT_NAME_UNITS * 2]
+            .chunks(2)
+            .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+            .take_while(|unit| *unit != 0)
+            .collect();
+        let first_lba = read_u64(entry, 32);
+        let last_lba = read_u64(entry, 40);
+        partitions.push(PlannedPartition {
+            number: index as u32 + 1,
+            unique_guid: Uuid::from_bytes_le(entry[16..32].try_into().unwrap_or([0u8; 16])),
+            first_lba,
+            last_lba,
+            spec: PartitionSpec {
+                name: String::from_utf16_lossy(&units),
+                type_guid: Uuid::from_bytes_le(entry[..16].try_into().unwrap_or([0u8; 16])),
+                size_bytes: Some((last_lba - first_lba + 1) * sector_size),
+                attributes: GptAttributes::from_bits(read_u64(entry, 48)),
+            },
+        });
+    }
+    let hybrid_mbr = check
+        .mbr_entries
+        .iter()
+        .filter(|entry| entry.partition_type != MBR_PROTECTIVE_TYPE)
+        .find_map(|entry| {
+            partitions
+                .iter()
+                .find(|partition| partition.first_lba == entry.first_lba as u64)
+                .map(|partition| HybridMbr {
+                    partition_number: partition.number,
+                    mbr_type: entry.partition_type,
+                })
+        });
+    Ok(PartitionPlan {
+        disk_guid: header.disk_guid,
+        disk_size,
+        sector_size,
+        first_usable_lba: header.first_usable_lba,
+        last_usable_lba: header.last_usable_lba,
+        partitions,
+        hybrid_mbr,
+    })
+}
+
+pub fn gpt_crc32(bytes: &[u8]) -> u32 {
+    crc32fast::hash(bytes)
+}
+
+fn read_sector<D: Read + Seek>(device: &mut D, lba: u64, sector_size: u64) -> Result<Vec<u8>> {
+    read_sectors(device, lba, 1, sector_size)
+}
+
+fn read_sectors<D: Read + Seek>(
+    device: &mut D,
+    lba: u64,
+    count: u64,
+    sector_size: u64,
+) -> Result<Vec<u8>> {
+    let mut buffer = vec![0u8; (count * sector_size) as usize];
+    device.seek(SeekFrom::Start(lba * sector_size))?;
+    device
+        .read_exact(&mut buffer)
+        .with_context(|| format!("read LBA {}", lba))?;
+    Ok(buffer)
+}
+
+fn parse_mbr(sector: &[u8], issues: &mut Vec<String>) -> Vec<MbrEntry> {
+    if sector[510] != 0x55 || sector[511] != 0xAA {
+        issues.push("MBR boot signature 0x55AA missing".to_string());
+    }
+    let mut entries = Vec::new();
+    for idx in 0..4 {
+        let raw = &sector[446 + idx * 16..446 + (idx + 1) * 16];
+        if raw[4] == 0 {
+            continue;
+        }
+        entries.push(MbrEntry {
+            bootable: raw[0] == 0x80,
+            partition_type: raw[4],
+            first_lba: read_u32(raw, 8),
+            sector_count: read_u32(raw, 12),
+        });
+    }
+    entries
+}
+
+fn check_header<D: Read + Seek>(
+    device: &mut D,
+    lba: u64,
+    expected_alternate: u64,
+    total_sectors: u64,
+    sector_size: u64,
+    which: &str,
+    issues: &mut Vec<String>,
+) -> Result<Option<(GptHeader, Vec<u8>)>> {
+    let sector = read_sector(device, lba, sector_size)?;
+    if &sector[0..8] != GPT_SIGNATURE {
+        issues.push(format!("{} GPT header signature missing at LBA {}", which, lba));
+        return Ok(None);
+    }
+    if read_u32(&sector, 8) != GPT_REVISION {
+        issues.push(format!("{} GPT header has unexpected revision", which));
+    }
+    let header_size = read_u32(&sector, 12);
+    if header_size < GPT_MIN_HEADER_SIZE || header_size as u64 > sector_size {
+        issues.push(format!("{} GPT header size {} invalid", which, header_size));
+        return Ok(None);
+    }
+    let stored_crc = read_u32(&sector, 16);
+    let mut header_bytes = sector[..header_size as usize].to_vec();
+    header_bytes[16..20].fill(0);
+    if gpt_crc32(&header_bytes) != stored_crc {
+        issues.push(format!("{} GPT header CRC32 mismatch", which));
+    }
+
+    let header = GptHeader {
+        my_lba: read_u64(&sector, 24),
+        alternate_lba: read_u64(&sector, 32),
+        first_usable_lba: read_u64(&sector, 40),
+        last_usable_lba: read_u64(&sector, 48),
+        disk_guid: Uuid::from_bytes_le(sector[56..72].try_into().unwrap_or([0u8; 16])),
+        entries_lba: read_u64(&sector, 72),
+        entry_count: read_u32(&sector, 80),
+        entry_size: read_u32(&sector, 84),
+        entries_crc32: read_u32(&sector, 88),
+    };
+    if header.my_lba != lba {
+        issues.push(format!(
+            "{} GPT header claims LBA {} but was read at {}",
+            which, header.my_lba, lba
+        ));
+    }
+    if header.alternate_lba != expected_alternate {
+        issues.push(format!(
+            "{} GPT header alternate LBA {} (expected {})",
+            which, header.alternate_lba, expected_alternate
+        ));
+    }
+    if header.first_usable_lba > header.last_usable_lba {
+        issues.push(format!("{} GPT usable range is empty", which));
+    }
+    if header.entry_size < 128
+        || header.entry_size > GPT_MAX_ENTRY_SIZE
+        || !header.entry_size.is_power_of_two()
+    {
+        issues.push(format!("{} GPT entry size {} invalid", which, header.entry_size));
+        return Ok(Some((header, Vec::new())));
+    }
+
+    let array_bytes = header.entry_count as u64 * header.entry_size as u64;
+    let array_sectors = array_bytes.div_ceil(sector_size);
+    let fits = header
+        .entries_lba
+        .checked_add(array_sectors)
+        .is_some_and(|end| end <= total_sectors);
+    if array_bytes > GPT_MAX_ARRAY_BYTES || !fits {
+        issues.push(format!(
+            "{} GPT partition array of {} entries at LBA {} is corrupt ({} bytes)",
+            which, header.entry_count, header.entries_lba, array_bytes
+        ));
+        return Ok(Some((header, Vec::new())));
+    }
+    let entries = read_sectors(device, header.entries_lba, array_sectors, sector_size)?;
+    let entries = entries[..array_bytes as usize].to_vec();
+    if gpt_crc32(&entries) != header.entries_crc32 {
+        issues.push(format!("{} GPT partition array CRC32 mismatch", which));
+    }
+    Ok(Some((header, entries)))
+}
+
+fn read_u32(buffer: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        buffer[offset],
+        buffer[offset + 1],
+        buffer[offset + 2],
+        buffer[offset + 3],
+    ])
+}
+
+fn read_u64(buffer: &[u8], offset: usize) -> u64 {
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&buffer[offset..offset + 8]);
+    u64::from_le_bytes(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    const DISK: u64 = 64 * 1024 * 1024;
+
+    fn image(specs: &[PartitionSpec]) -> (PartitionPlan, Cursor<Vec<u8>>) {
+        let plan = plan_partitions(DISK, 512, specs).unwrap();
+        let mut image = Cursor::new(vec![0u8; DISK as usize]);
+        plan.write_gpt(&mut image).unwrap();
+        (plan, image)
+    }
+
+    #[test]
+    fn written_gpt_verifies() {
+        let (plan, mut image) = image(&[
+            PartitionSpec::parse("RECOVERY:recovery:8M:hidden+no-automount").unwrap(),
+            PartitionSpec::basic_data("PHOENIX"),
+        ]);
+        let check = verify_partition_tables(&mut image, DISK, 512).unwrap();
+        assert!(check.ok, "{:?}", check.issues);
+        assert_eq!(check.partition_count, 2);
+        assert_eq!(check.primary.unwrap().disk_guid, plan.disk_guid);
+        assert_eq!(plan.partitions[0].first_lba, 2048);
+        assert_eq!(plan.mountable().count(), 1);
+    }
+
+    #[test]
+    fn detects_corrupted_tables() {
+        let (_, mut image) = image(&[PartitionSpec::basic_data("PHOENIX")]);
+        image.get_mut()[512 + 40] ^= 0xFF;
+        image.get_mut()[1024] ^= 0xFF;
+        let check = verify_partition_tables(&mut image, DISK, 512).unwrap();
+        assert!(!check.ok);
+        assert!(check
+            .issues
+            .iter()
+            .any(|issue| issue.contains("primary GPT header CRC32")));
+        assert!(check
+            .issues
+            .iter()
+            .any(|issue| issue.contains("primary GPT partition array")));
+    }
+
+    #[test]
+    fn oversized_partition_arrays_are_corrupt() {
+        let (_, mut image) = image(&[PartitionSpec::basic_data("PHOENIX")]);
+        image.get_mut()[512 + 80..512 + 84].copy_from_slice(&u32::MAX.to_le_bytes());
+        let last = DISK as usize - 512;
+        image.get_mut()[last + 84..last + 88].copy_from_slice(&(1u32 << 20).to_le_bytes());
+        let check = verify_partition_tables(&mut image, DISK, 512).unwrap();
+        assert!(!check.ok);
+        assert!(check
+            .issues
+            .iter()
+            .any(|issue| issue.starts_with("primary GPT partition array of 4294967295 entries")));
+        assert!(check
+            .issues
+            .iter()
+            .any(|issue| issue.contains("backup GPT entry size 1048576 invalid")));
+        assert!(read_gpt(&mut image, DISK, 512).is_err());
+    }
+
+    #[test]
+    fn hybrid_mbr_mirrors_first_partition() {
+        let mut plan = plan_partitions(DISK, 512, &[PartitionSpec::basic_data("PHOENIX")]).unwrap();
+        let warnings = plan.enable_hybrid_mbr(plan::MBR_TYPE_FAT32_LBA).unwrap();
+        assert!(!warnings.is_empty());
+        let mut image = Cursor::new(vec![0u8; DISK as usize]);
+        plan.write_gpt(&mut image).unwrap();
+        let check = verify_partition_tables(&mut image, DISK, 512).unwrap();
+        assert!(check.ok, "{:?}", check.issues);
+        assert_eq!(check.mbr_entries.len(), 2);
+        assert_eq!(check.warnings.len(), 1);
+
+        image.get_mut()[462 + 8] ^= 0x01;
+        let check = verify_partition_tables(&mut image, DISK, 512).unwrap();
+        assert!(check
+            .issues
+            .iter()
+            .any(|issue| issue.contains("does not match")));
+    }
+
+    #[test]
+    fn read_back_gpt_resizes() {
+        let mut specs = vec![
+            PartitionSpec::parse("EFI:esp:8M").unwrap(),
+            PartitionSpec::basic_data("DATA"),
+        ];
+        specs[1].size_bytes = Some(16 * 1024 * 1024);
+        let (plan, mut image) = image(&specs);
+        let mut read = read_gpt(&mut image, DISK, 512).unwrap();
+        assert_eq!(read.disk_guid, plan.disk_guid);
+        assert_eq!(read.partitions.len(), 2);
+        assert_eq!(read.partitions[0].spec.name, "EFI");
+        assert_eq!(read.partitions[1].last_lba, plan.partitions[1].last_lba);
+
+        let max = read.max_length_bytes(2).unwrap();
+        assert!(max > 16 * 1024 * 1024);
+        assert!(read.resize_partition(2, max + 1024 * 1024).is_err());
+        read.resize_partition(2, max).unwrap();
+        read.write_gpt(&mut image).unwrap();
+        let check = verify_partition_tables(&mut image, DISK, 512).unwrap();
+        assert!(check.ok, "{:?}", check.issues);
+        let shrunk = read.resize_partition(2, 5 * 1024 * 1024 + 1).unwrap();
+        assert_eq!(shrunk.length_bytes(512), 6 * 1024 * 1024);
+        assert!(read.resize_partition(1, 64 * 1024 * 1024).is_err());
+    }
+
+    #[test]
+    fn parses_specs_and_attributes() {
+        let spec = PartitionSpec::parse("EFI:esp:100M:required").unwrap();
+        assert_eq!(spec.type_guid, plan::TYPE_EFI_SYSTEM);
+        assert_eq!(spec.size_bytes, Some(100 * 1024 * 1024));
+        assert_eq!(spec.attributes.bits(), 1);
+        let attrs = GptAttributes::from_bits((1 << 62) | (1 << 63) | (1 << 2));
+        assert!(attrs.hidden && attrs.no_automount && attrs.legacy_bios_bootable);
+        assert!(PartitionSpec::parse("X:bogus").is_err());
+        let written = serde_json::to_value(&spec).unwrap();
+        assert_eq!(
+            written,
+            serde_json::json!({"name": "EFI", "type": "esp", "size": 104857600, "attributes": ["platform_required"]})
+        );
+        let read: PartitionSpec = serde_json::from_value(written).unwrap();
+        assert_eq!((read.type_guid, read.size_bytes, read.attributes), (spec.type_guid, spec.size_bytes, spec.attributes));
+        let read: PartitionSpec = serde_json::from_value(serde_json::json!("EFI:esp:100M:required")).unwrap();
+        assert_eq!(read.attributes, spec.attributes);
+        assert!(serde_json::from_value::<PartitionSpec>(serde_json::json!({"name": "X", "size": "lots"})).is_err());
+        assert!(plan_partitions(
+            DISK,
+            512,
+            &[
+                PartitionSpec::basic_data("A"),
+                PartitionSpec::basic_data("B")
+            ]
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,613 @@
+use anyhow::{anyhow, Result};
+use serde::de::Error as _;
+use serde::ser::SerializeStruct;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::io::{Seek, SeekFrom, Write};
+use uuid::Uuid;
+
+use crate::gpt_crc32;
+
+pub const GPT_ENTRY_COUNT: u32 = 128;
+pub const GPT_ENTRY_SIZE: u32 = 128;
+pub const GPT_NAME_UNITS: usize = 36;
+pub const PARTITION_ALIGNMENT: u64 = 1024 * 1024;
+
+pub const TYPE_BASIC_DATA: Uuid = Uuid::from_u128(0xEBD0A0A2_B9E5_4433_87C0_68B6B72699C7);
+pub const TYPE_EFI_SYSTEM: Uuid = Uuid::from_u128(0xC12A7328_F81F_11D2_BA4B_00A0C93EC93B);
+pub const TYPE_MS_RESERVED: Uuid = Uuid::from_u128(0xE3C9E316_0B5C_4DB8_817D_F92DF00215AE);
+pub const TYPE_MS_RECOVERY: Uuid = Uuid::from_u128(0xDE94BBA4_06D1_4D40_A16A_BFD50179D6AC);
+pub const TYPE_LINUX_DATA: Uuid = Uuid::from_u128(0x0FC63DAF_8483_4772_8E79_3D69D8477DE4);
+pub const TYPE_BIOS_BOOT: Uuid = Uuid::from_u128(0x21686148_6449_6E6F_744E_656564454649);
+pub const TYPE_APPLE_HFS: Uuid = Uuid::from_u128(0x48465300_0000_11AA_AA11_00306543ECAC);
+
+const PARTITION_TYPES: &[(&str, Uuid)] = &[
+    ("basic", TYPE_BASIC_DATA),
+    ("esp", TYPE_EFI_SYSTEM),
+    ("msr", TYPE_MS_RESERVED),
+    ("recovery", TYPE_MS_RECOVERY),
+    ("linux", TYPE_LINUX_DATA),
+    ("bios-boot", TYPE_BIOS_BOOT),
+    ("hfs", TYPE_APPLE_HFS),
+];
+
+/// Resolves a partition type alias (`basic`, `esp`, `recovery`, ...) or a
+/// literal GUID.
+pub fn parse_partition_type(value: &str) -> Result<Uuid> {
+    let value = value.trim();
+    let alias = value.to_ascii_lowercase();
+    if let Some((_, guid)) = PARTITION_TYPES.iter().find(|(name, _)| *name == alias) {
+        return Ok(*guid);
+    }
+    Uuid::parse_str(value).map_err(|_| anyhow!("unknown partition type: {}", value))
+}
+
+pub fn partition_type_name(guid: &Uuid) -> Option<&'static str> {
+    PARTITION_TYPES
+        .iter()
+        .find(|(_, candidate)| candidate == guid)
+        .map(|(name, _)| *name)
+}
+
+/// GPT partition attribute bits. Bits 0-2 are defined by UEFI; 60-63 are the
+/// Microsoft basic-data bits that Windows honours for mounting.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GptAttributes {
+    pub platform_required: bool,
+    pub no_block_io: bool,
+    pub legacy_bios_bootable: bool,
+    pub read_only: bool,
+    pub hidden: bool,
+    pub no_automount: bool,
+}
+
+impl GptAttributes {
+    const PLATFORM_REQUIRED: u64 = 1 << 0;
+    const NO_BLOCK_IO: u64 = 1 << 1;
+    const LEGACY_BIOS_BOOTABLE: u64 = 1 << 2;
+    const READ_ONLY: u64 = 1 << 60;
+    const HIDDEN: u64 = 1 << 62;
+    const NO_AUTOMOUNT: u64 = 1 << 63;
+
+    pub fn bits(&self) -> u64 {
+        let mut bits = 0;
+        for (set, bit) in [
+            (self.platform_required, Self::PLATFORM_REQUIRED),
+            (self.no_block_io, Self::NO_BLOCK_IO),
+            (self.legacy_bios_bootable, Self::LEGACY_BIOS_BOOTABLE),
+            (self.read_only, Self::READ_ONLY),
+            (self.hidden, Self::HIDDEN),
+            (self.no_automount, Self::NO_AUTOMOUNT),
+        ] {
+            if set {
+                bits |= bit;
+            }
+        }
+        bits
+    }
+
+    pub fn from_bits(bits: u64) -> Self {
+        Self {
+            platform_required: bits & Self::PLATFORM_REQUIRED != 0,
+            no_block_io: bits & Self::NO_BLOCK_IO != 0,
+            legacy_bios_bootable: bits & Self::LEGACY_BIOS_BOOTABLE != 0,
+            read_only: bits & Self::READ_ONLY != 0,
+            hidden: bits & Self::HIDDEN != 0,
+            no_automount: bits & Self::NO_AUTOMOUNT != 0,
+        }
+    }
+
+    pub fn set(&mut self, name: &str) -> Result<()> {
+        match name.trim().to_ascii_lowercase().replace('-', "_").as_str() {
+            "required" | "platform_required" => self.platform_required = true,
+            "no_block_io" => self.no_block_io = true,
+            "legacy_bios_bootable" | "bios_bootable" => self.legacy_bios_bootable = true,
+            "read_only" => self.read_only = true,
+            "hidden" => self.hidden = true,
+            "no_automount" | "no_drive_letter" => self.no_automount = true,
+            other => return Err(anyhow!("unknown GPT attribute: {}", other)),
+        }
+        Ok(())
+    }
+
+    /// Names of the set attributes, in the form `set` takes.
+    pub fn names(&self) -> Vec<&'static str> {
+        [
+            (self.platform_required, "platform_required"),
+            (self.no_block_io, "no_block_io"),
+            (self.legacy_bios_bootable, "legacy_bios_bootable"),
+            (self.read_only, "read_only"),
+            (self.hidden, "hidden"),
+            (self.no_automount, "no_automount"),
+        ]
+        .into_iter()
+        .filter(|(set, _)| *set)
+        .map(|(_, name)| name)
+        .collect()
+    }
+
+    /// True when the OS will assign a mount point / drive letter.
+    pub fn is_mountable(&self) -> bool {
+        !self.hidden && !self.no_automount
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PartitionSpec {
+    pub name: String,
+    pub type_guid: Uuid,
+    /// `None` takes the rest of the disk; only allowed on the last partition.
+    pub size_bytes: Option<u64>,
+    pub attributes: GptAttributes,
+}
+
+impl PartitionSpec {
+    pub fn basic_data(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            type_guid: TYPE_BASIC_DATA,
+            size_bytes: None,
+            attributes: GptAttributes::default(),
+        }
+    }
+
+    /// Parses `NAME:TYPE[:SIZE][:ATTR+ATTR...]`, for example
+    /// `RECOVERY:recovery:1G:hidden+no-automount`. An empty or `rest` size
+    /// takes the remaining space.
+    pub fn parse(value: &str) -> Result<Self> {
+        let mut parts = value.split(':');
+        let name = parts.next().unwrap_or_default().trim();
+        let type_guid = parse_partition_type(
+            parts
+                .next()
+                .ok_or_else(|| anyhow!("partition spec missing type: {}", value))?,
+        )?;
+        let size_bytes = match parts.next().map(str::trim) {
+            None | Some("") | Some("rest") => None,
+            Some(size) => Some(parse_size(size)?),
+        };
+        let mut attributes = GptAttributes::default();
+        if let Some(attrs) = parts.next() {
+            for attr in attrs.split('+').filter(|attr| !attr.trim().is_empty()) {
+                attributes.set(attr)?;
+            }
+        }
+        if parts.next().is_some() {
+            return Err(anyhow!("too many fields in partition spec: {}", value));
+        }
+        Ok(Self {
+            name: name.to_string(),
+            type_guid,
+            size_bytes,
+            attributes,
+        })
+    }
+}
+
+/// Written as `{"name", "type", "size", "attributes"}`, the object form
+/// workflow params take; `type` is the alias when there is one and `size`
+/// is `null` for the rest of the disk. Reads that form or a spec string.
+impl Serialize for PartitionSpec {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        let kind = partition_type_name(&self.type_guid)
+            .map(str::to_string)
+            .unwrap_or_else(|| self.type_guid.to_string());
+        let mut fields = serializer.serialize_struct("PartitionSpec", 4)?;
+        fields.serialize_field("name", &self.name)?;
+        fields.serialize_field("type", &kind)?;
+        fields.serialize_field("size", &self.size_bytes)?;
+        fields.serialize_field("attributes", &self.attributes.names())?;
+        fields.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for PartitionSpec {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Size {
+            Bytes(u64),
+            Text(String),
+        }
+
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Form {
+            Spec(String),
+            Fields {
+                name: String,
+                #[serde(rename = "type")]
+                kind: Option<String>,
+                size: Option<Size>,
+                #[serde(default)]
+                attributes: Vec<String>,
+            },
+        }
+
+        let parsed = match Form::deserialize(deserializer)? {
+            Form::Spec(spec) => PartitionSpec::parse(&spec),
+            Form::Fields {
+                name,
+                kind,
+                size,
+                attributes,
+            } => (|| {
+                let mut spec = PartitionSpec::basic_data(name);
+                if let Some(kind) = kind {
+                    spec.type_guid = parse_partition_type(&kind)?;
+                }
+                spec.size_bytes = match size {
+                    None => None,
+                    Some(Size::Bytes(bytes)) => Some(bytes),
+                    Some(Size::Text(text)) if text == "rest" => None,
+                    Some(Size::Text(text)) => Some(parse_size(&text)?),
+                };
+                for attribute in &attributes {
+                    spec.attributes.set(attribute)?;
+                }
+                Ok(spec)
+            })(),
+        };
+        parsed.map_err(D::Error::custom)
+    }
+}
+
+/// Parses a byte count with an optional binary `K`/`M`/`G`/`T` suffix.
+pub fn parse_size(value: &str) -> Result<u64> {
+    let value = value.trim();
+    let upper = value.to_ascii_uppercase();
+    let trimmed = upper.trim_end_matches("IB").trim_end_matches('B');
+    let (digits, shift) = match trimmed.chars().last() {
+        Some('K') => (&trimmed[..trimmed.len() - 1], 10),
+        Some('M') => (&trimmed[..trimmed.len() - 1], 20),
+        Some('G') => (&trimmed[..trimmed.len() - 1], 30),
+        Some('T') => (&trimmed[..trimmed.len() - 1], 40),
+        _ => (trimmed, 0),
+    };
+    let number: u64 = digits
+        .trim()
+        .parse()
+        .map_err(|_| anyhow!("invalid size: {}", value))?;
+    number
+        .checked_mul(1u64 << shift)
+        .ok_or_else(|| anyhow!("size overflows: {}", value))
+}
+
+pub fn gpt_name_units(name: &str) -> Result<[u16; GPT_NAME_UNITS]> {
+    let mut units = [0u16; GPT_NAME_UNITS];
+    let encoded: Vec<u16> = name.encode_utf16().collect();
+    if encoded.len() > GPT_NAME_UNITS {
+        return Err(anyhow!(
+            "partition name longer than {} UTF-16 units: {}",
+            GPT_NAME_UNITS,
+            name
+        ));
+    }
+    units[..encoded.len()].copy_from_slice(&encoded);
+    Ok(units)
+}
+
+#[derive(Debug, Clone)]
+pub struct PlannedPartition {
+    pub number: u32,
+    pub unique_guid: Uuid,
+    pub first_lba: u64,
+    pub last_lba: u64,
+    pub spec: PartitionSpec,
+}
+
+impl PlannedPartition {
+    pub fn offset_bytes(&self, sector_size: u64) -> u64 {
+        self.first_lba * sector_size
+    }
+
+    pub fn length_bytes(&self, sector_size: u64) -> u64 {
+        (self.last_lba - self.first_lba + 1) * sector_size
+    }
+}
+
+/// MBR type for the hybrid entry that mirrors a GPT partition.
+pub const MBR_TYPE_FAT32_LBA: u8 = 0x0C;
+pub const MBR_TYPE_NTFS_EXFAT: u8 = 0x07;
+
+#[derive(Debug, Clone, Copy)]
+pub struct HybridMbr {
+    pub partition_number: u32,
+    pub mbr_type: u8,
+}
+
+#[derive(Debug, Clone)]
+pub struct PartitionPlan {
+    pub disk_guid: Uuid,
+    pub disk_size: u64,
+    pub sector_size: u64,
+    pub first_usable_lba: u64,
+    pub last_usable_lba: u64,
+    pub partitions: Vec<PlannedPartition>,
+    pub hybrid_mbr: Option<HybridMbr>,
+}
+
+/// Lays out `specs` in order on 1 MiB boundaries inside the GPT usable
+/// range.
+pub fn plan_partitions(
+    disk_size: u64,
+    sector_size: u64,
+    specs: &[PartitionSpec],
+) -> Result<PartitionPlan> {
+    if sector_size < 512 || !sector_size.is_power_of_two() {
+        return Err(anyhow!("invalid sector size {}", sector_size));
+    }
+    if specs.is_empty() {
+        return Err(anyhow!("partition plan needs at least one partition"));
+    }
+    if specs.len() > GPT_ENTRY_COUNT as usize {
+        return Err(anyhow!("too many partitions: {}", specs.len()));
+    }
+    let total_sectors = disk_size / sector_size;
+    let array_sectors = entry_array_sectors(sector_size);
+    if total_sectors < 3 + array_sectors * 2 {
+        return Err(anyhow!("disk too small for partitioning"));
+    }
+    let first_usable_lba = 2 + array_sectors;
+    let last_usable_lba = total_sectors - 2 - array_sectors;
+    let align = (PARTITION_ALIGNMENT / sector_size).max(1);
+    let usable_end = ((last_usable_lba + 1) / align) * align;
+
+    let mut partitions = Vec::with_capacity(specs.len());
+    let mut next = first_usable_lba.div_ceil(align) * align;
+    for (idx, spec) in specs.iter().enumerate() {
+        gpt_name_units(&spec.name)?;
+        let end = match spec.size_bytes {
+            Some(size) => {
+                let sectors = size.div_ceil(sector_size).div_ceil(align) * align;
+                if sectors == 0 {
+                    return Err(anyhow!("partition {} has zero size", idx + 1));
+                }
+                next + sectors
+            }
+            None if idx + 1 == specs.len() => usable_end,
+            None => {
+                return Err(anyhow!(
+                    "only the last partition may take the remaining space"
+                ))
+            }
+        };
+        if end > usable_end || end <= next {
+            return Err(anyhow!(
+                "partition {} ({}) does not fit on a {} byte disk",
+                idx + 1,
+                spec.name,
+                disk_size
+            ));
+        }
+        partitions.push(PlannedPartition {
+            number: idx as u32 + 1,
+            unique_guid: Uuid::new_v4(),
+            first_lba: next,
+            last_lba: end - 1,
+            spec: spec.clone(),
+        });
+        next = end;
+    }
+
+    Ok(PartitionPlan {
+        disk_guid: Uuid::new_v4(),
+        disk_size,
+        sector_size,
+        first_usable_lba,
+        last_usable_lba,
+        partitions,
+        hybrid_mbr: None,
+    })
+}
+
+fn write_mbr_entry(entry: &mut [u8], bootable: bool, kind: u8, first_lba: u64, last_lba: u64) {
+    entry[0] = if bootable { 0x80 } else { 0x00 };
+    // CHS values are the "use LBA" markers; nothing we target reads them.
+    entry[1..4].copy_from_slice(&[0xFE, 0xFF, 0xFF]);
+    if first_lba == 1 {
+        entry[1..4].copy_from_slice(&[0x00, 0x02, 0x00]);
+    }
+    entry[4] = kind;
+    entry[5..8].copy_from_slice(&[0xFE, 0xFF, 0xFF]);
+    let count = (last_lba - first_lba + 1).min(u32::MAX as u64) as u32;
+    entry[8..12].copy_from_slice(&(first_lba as u32).to_le_bytes());
+    entry[12..16].copy_from_slice(&count.to_le_bytes());
+}
+
+pub(crate) fn entry_array_sectors(sector_size: u64) -> u64 {
+    (GPT_ENTRY_COUNT as u64 * GPT_ENTRY_SIZE as u64).div_ceil(sector_size)
+}
+
+impl PartitionPlan {
+    pub fn last_lba(&self) -> u64 {
+        self.disk_size / self.sector_size - 1
+    }
+
+    /// Longest partition `number` can grow to: up to the next partition,
+    /// or to the end of the usable range for the last one.
+    pub fn max_length_bytes(&self, number: u32) -> Result<u64> {
+        let (partition, limit) = self.resize_limit(number)?;
+        Ok((limit - partition.first_lba + 1) * self.sector_size)
+    }
+
+    /// Moves the end of partition `number` so it is `length_bytes` long,
+    /// rounded up to the partition alignment but no further than
+    /// `max_length_bytes`. The file system on it must already fit.
+    pub fn resize_partition(&mut self, number: u32, length_bytes: u64) -> Result<&PlannedPartition> {
+        let (partition, limit) = self.resize_limit(number)?;
+        let align = (PARTITION_ALIGNMENT / self.sector_size).max(1);
+        let sectors = length_bytes.div_ceil(self.sector_size);
+        if sectors == 0 {
+            return Err(anyhow!("partition {} cannot be empty", number));
+        }
+        let last_lba = partition.first_lba + sectors - 1;
+        if last_lba > limit {
+            return Err(anyhow!(
+                "partition {} ({}) can grow to {} bytes at most",
+                number,
+                partition.spec.name,
+                (limit - partition.first_lba + 1) * self.sector_size
+            ));
+        }
+        let aligned_end = (partition.first_lba + sectors).div_ceil(align) * align;
+        let last_lba = (aligned_end - 1).min(limit);
+        let sector_size = self.sector_size;
+        let partition = self
+            .partitions
+            .iter_mut()
+            .find(|partition| partition.number == number)
+            .ok_or_else(|| anyhow!("no partition {}", number))?;
+        partition.last_lba = last_lba;
+        partition.spec.size_bytes = Some(partition.length_bytes(sector_size));
+        Ok(partition)
+    }
+
+    fn resize_limit(&self, number: u32) -> Result<(&PlannedPartition, u64)> {
+        let partition = self
+            .partitions
+            .iter()
+            .find(|partition| partition.number == number)
+            .ok_or_else(|| anyhow!("no partition {}", number))?;
+        let limit = self
+            .partitions
+            .iter()
+            .filter(|other| other.first_lba > partition.first_lba)
+            .map(|other| other.first_lba - 1)
+            .min()
+            .unwrap_or(self.last_usable_lba);
+        Ok((partition, limit))
+    }
+
+    /// Partitions the OS will mount and assign a drive letter to.
+    pub fn mountable(&self) -> impl Iterator<Item = &PlannedPartition> {
+        self.partitions
+            .iter()
+            .filter(|partition| partition.spec.attributes.is_mountable())
+    }
+
+    pub fn entry_array(&self) -> Result<Vec<u8>> {
+        let mut array = vec![0u8; (GPT_ENTRY_COUNT * GPT_ENTRY_SIZE) as usize];
+        for (idx, partition) in self.partitions.iter().enumerate() {
+            let entry = &mut array[idx * GPT_ENTRY_SIZE as usize..][..GPT_ENTRY_SIZE as usize];
+            entry[0..16].copy_from_slice(&partition.spec.type_guid.to_bytes_le());
+            entry[16..32].copy_from_slice(&partition.unique_guid.to_bytes_le());
+            entry[32..40].copy_from_slice(&partition.first_lba.to_le_bytes());
+            entry[40..48].copy_from_slice(&partition.last_lba.to_le_bytes());
+            entry[48..56].copy_from_slice(&partition.spec.attributes.bits().to_le_bytes());
+            for (slot, unit) in gpt_name_units(&partition.spec.name)?.iter().enumerate() {
+                entry[56 + slot * 2..58 + slot * 2].copy_from_slice(&unit.to_le_bytes());
+            }
+        }
+        Ok(array)
+    }
+
+    /// Opts into a hybrid MBR that mirrors the first GPT partition as a
+    /// bootable legacy entry, for BIOSes that ignore a protective-only MBR.
+    /// Returns warnings to surface to the user.
+    pub fn enable_hybrid_mbr(&mut self, mbr_type: u8) -> Result<Vec<String>> {
+        let first = self
+            .partitions
+            .first()
+            .ok_or_else(|| anyhow!("hybrid MBR needs at least one partition"))?;
+        if mbr_type == 0 || mbr_type == 0xEE {
+            return Err(anyhow!("invalid hybrid MBR type 0x{:02X}", mbr_type));
+        }
+        if first.last_lba > u32::MAX as u64 {
+            return Err(anyhow!(
+                "partition {} ends beyond the 2 TiB MBR limit",
+                first.spec.name
+            ));
+        }
+        self.hybrid_mbr = Some(HybridMbr {
+            partition_number: first.number,
+            mbr_type,
+        });
+        let mut warnings = vec![
+            "hybrid MBR is non-standard; UEFI firmware and some OSes may treat the disk as MBR-only"
+                .to_string(),
+            "later GPT edits by other tools will not update the hybrid MBR".to_string(),
+        ];
+        if self.partitions.len() > 1 {
+            warnings.push(format!(
+                "only partition {} is visible to legacy BIOS",
+                first.spec.name
+            ));
+        }
+        Ok(warnings)
+    }
+
+    /// The MBR written at LBA 0: protective-only, or protective plus the
+    /// mirrored entry when a hybrid MBR was requested.
+    pub fn mbr_sector(&self) -> Vec<u8> {
+        let mut sector = vec![0u8; self.sector_size as usize];
+        let mirrored = self.hybrid_mbr.and_then(|hybrid| {
+            self.partitions
+                .iter()
+                .find(|partition| partition.number == hybrid.partition_number)
+                .map(|partition| (hybrid, partition))
+        });
+        let protective_end = match mirrored {
+            Some((_, partition)) => partition.first_lba - 1,
+            None => self.last_lba().min(u32::MAX as u64),
+        };
+        write_mbr_entry(&mut sector[446..462], false, 0xEE, 1, protective_end);
+        if let Some((hybrid, partition)) = mirrored {
+            write_mbr_entry(
+                &mut sector[462..478],
+                true,
+                hybrid.mbr_type,
+                partition.first_lba,
+                partition.last_lba,
+            );
+        }
+        sector[510] = 0x55;
+        sector[511] = 0xAA;
+        sector
+    }
+
+    fn header(&self, my_lba: u64, alternate_lba: u64, entries_lba: u64, array_crc: u32) -> Vec<u8> {
+        let mut sector = vec![0u8; self.sector_size as usize];
+        sector[0..8].copy_from_slice(b"EFI PART");
+        sector[8..12].copy_from_slice(&0x0001_0000u32.to_le_bytes());
+        sector[12..16].copy_from_slice(&92u32.to_le_bytes());
+        sector[24..32].copy_from_slice(&my_lba.to_le_bytes());
+        sector[32..40].copy_from_slice(&alternate_lba.to_le_bytes());
+        sector[40..48].copy_from_slice(&self.first_usable_lba.to_le_bytes());
+        sector[48..56].copy_from_slice(&self.last_usable_lba.to_le_bytes());
+        sector[56..72].copy_from_slice(&self.disk_guid.to_bytes_le());
+        sector[72..80].copy_from_slice(&entries_lba.to_le_bytes());
+        sector[80..84].copy_from_slice(&GPT_ENTRY_COUNT.to_le_bytes());
+        sector[84..88].copy_from_slice(&GPT_ENTRY_SIZE.to_le_bytes());
+        sector[88..92].copy_from_slice(&array_crc.to_le_bytes());
+        let crc = gpt_crc32(&sector[..92]);
+        sector[16..20].copy_from_slice(&crc.to_le_bytes());
+        sector
+    }
+
+    /// Writes the protective MBR, primary and backup GPT to an image or raw
+    /// device.
+    pub fn write_gpt<D: Write + Seek>(&self, device: &mut D) -> Result<()> {
+        phoenix_safety::ensure_writable("partition table write")?;
+        let last_lba = self.last_lba();
+        let array = self.entry_array()?;
+        let array_crc = gpt_crc32(&array);
+        let backup_entries_lba = last_lba - entry_array_sectors(self.sector_size);
+
+        let writes = [
+            (0, self.mbr_sector()),
+            (1, self.header(1, last_lba, 2, array_crc)),
+            (2, array.clone()),
+            (backup_entries_lba, array),
+            (
+                last_lba,
+                self.header(last_lba, 1, backup_entries_lba, array_crc),
+            ),
+        ];
+        for (lba, bytes) in writes {
+            device.seek(SeekFrom::Start(lba * self.sector_size))?;
+            device.write_all(&bytes)?;
+        }
+        device.flush()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,164 @@
+//! The `phoenixcore` Python module: device graph enumeration, workflow
+//! runs with live progress, and report verification, so Python tooling can
+//! drive the engine instead of reimplementing it. Results are plain dicts
+//! and lists shaped like the engine's JSON.
+
+use phoenix_core::WorkflowDefinition;
+use phoenix_workflow_engine::{
+    build_device_graph, run_workflow_definition_with_report, validate_workflow_definition,
+    with_cancel_token, with_log_listener, CancelToken, WorkflowRunResult,
+};
+use pyo3::exceptions::{PyRuntimeError, PyValueError};
+use pyo3::prelude::*;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+fn runtime_error(err: anyhow::Error) -> PyErr {
+    PyRuntimeError::new_err(format!("{:#}", err))
+}
+
+/// Converts through the `json` module, so callers get builtin types.
+fn to_python(py: Python<'_>, value: &serde_json::Value) -> PyResult<Py<PyAny>> {
+    let text = serde_json::to_string(value).map_err(|err| PyRuntimeError::new_err(err.to_string()))?;
+    Ok(py.import("json")?.call_method1("loads", (text,))?.unbind())
+}
+
+/// A definition given as a dict or as JSON text.
+fn parse_definition(py: Python<'_>, definition: &Bound<'_, PyAny>) -> PyResult<WorkflowDefinition> {
+    let text: String = match definition.extract::<String>() {
+        Ok(text) => text,
+        Err(_) => py
+            .import("json")?
+            .call_method1("dumps", (definition,))?
+            .extract()?,
+    };
+    serde_json::from_str(&text)
+        .map_err(|err| PyValueError::new_err(format!("invalid workflow definition: {}", err)))
+}
+
+/// The device graph of this host.
+#[pyfunction]
+fn device_graph(py: Python<'_>) -> PyResult<Py<PyAny>> {
+    let graph = py.detach(build_device_graph).map_err(runtime_error)?;
+    let value = serde_json::to_value(&graph).map_err(|err| runtime_error(err.into()))?;
+    to_python(py, &value)
+}
+
+/// Loads a definition file, expanding its includes, into a dict.
+#[pyfunction]
+fn load_workflow(py: Python<'_>, path: &str) -> PyResult<Py<PyAny>> {
+    let definition = phoenix_content::load_workflow_definition(path)
+        .map_err(|err| PyValueError::new_err(format!("{:#}", err)))?;
+    let value = serde_json::to_value(&definition).map_err(|err| runtime_error(err.into()))?;
+    to_python(py, &value)
+}
+
+/// Raises `ValueError` when the definition cannot run on this host.
+#[pyfunction]
+fn validate_workflow(py: Python<'_>, definition: &Bound<'_, PyAny>) -> PyResult<()> {
+    let definition = parse_definition(py, definition)?;
+    py.detach(|| validate_workflow_definition(&definition))
+        .map_err(|err| PyValueError::new_err(format!("{:#}", err)))
+}
+
+/// Runs a definition and returns its report root and steps. `progress`,
+/// if given, is called as `progress(workflow, message, elapsed_ms)` for
+/// every log line; an exception it raises cancels the run and is
+/// re-raised here.
+#[pyfunction]
+#[pyo3(signature = (definition, report_base = ".", progress = None))]
+fn run_workflow(
+    py: Python<'_>,
+    definition: &Bound<'_, PyAny>,
+    report_base: &str,
+    progress: Option<Py<PyAny>>,
+) -> PyResult<Py<PyAny>> {
+    let definition = parse_definition(py, definition)?;
+    let token = CancelToken::new();
+    let callback_error: Arc<Mutex<Option<PyErr>>> = Arc::new(Mutex::new(None));
+    let report_base = PathBuf::from(report_base);
+    let result = py.detach(|| {
+        let run = || {
+            with_cancel_token(&token, || {
+                run_workflow_definition_with_report(&definition, report_base)
+            })
+        };
+        let Some(progress) = progress else {
+            return run();
+        };
+        let token = token.clone();
+        let callback_error = callback_error.clone();
+        let listener = Box::new(move |workflow: &str, entry: &phoenix_workflow_engine::LogEntry| {
+            Python::attach(|py| {
+                // Ctrl-C in the interpreter cancels the run as well.
+                let called = py
+                    .check_signals()
+                    .and_then(|_| progress.call1(py, (workflow, &entry.message, entry.elapsed_ms)));
+                if let Err(err) = called {
+                    callback_error.lock().unwrap().get_or_insert(err);
+                    token.cancel();
+                }
+            })
+        });
+        with_log_listener(listener, run)
+    });
+    if let Some(err) = callback_error.lock().unwrap().take() {
+        return Err(err);
+    }
+    let result = result.map_err(runtime_error)?;
+    to_python(py, &run_result_json(&result))
+}
+
+fn run_result_json(result: &WorkflowRunResult) -> serde_json::Value {
+    let steps: Vec<serde_json::Value> = result
+        .steps
+        .iter()
+        .map(|step| {
+            serde_json::json!({
+                "id": step.id,
+                "action": step.action,
+                "report_root": step.report_root.as_ref().map(|root| root.display().to_string()),
+                "duration_ms": step.duration_ms as u64,
+                "reused": step.reused,
+                "hooks": step.hooks
+            })
+        })
+        .collect();
+    serde_json::json!({
+        "report_root": result.report.root.display().to_string(),
+        "steps": steps
+    })
+}
+
+/// Verifies a report bundle; `ok` is false on any mismatch or error
+/// finding.
+#[pyfunction]
+#[pyo3(signature = (path, signing_key = None))]
+fn verify_report(py: Python<'_>, path: &str, signing_key: Option<&str>) -> PyResult<Py<PyAny>> {
+    let verification = py
+        .detach(|| phoenix_report::verify_report_bundle(path, signing_key))
+        .map_err(runtime_error)?;
+    let value = serde_json::to_value(&verification).map_err(|err| runtime_error(err.into()))?;
+    to_python(py, &value)
+}
+
+/// The settings this process runs with and where each came from.
+#[pyfunction]
+fn effective_config(py: Python<'_>) -> PyResult<Py<PyAny>> {
+    let process = format!("phoenixcore {}", env!("CARGO_PKG_VERSION"));
+    let config = phoenix_workflow_engine::effective_config(&process);
+    let value = serde_json::to_value(&config).map_err(|err| runtime_error(err.into()))?;
+    to_python(py, &value)
+}
+
+#[pymodule]
+fn phoenixcore(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add("__version__", env!("CARGO_PKG_VERSION"))?;
+    m.add_function(wrap_pyfunction!(device_graph, m)?)?;
+    m.add_function(wrap_pyfunction!(load_workflow, m)?)?;
+    m.add_function(wrap_pyfunction!(validate_workflow, m)?)?;
+    m.add_function(wrap_pyfunction!(run_workflow, m)?)?;
+    m.add_function(wrap_pyfunction!(verify_report, m)?)?;
+    m.add_function(wrap_pyfunction!(effective_config, m)?)?;
+    Ok(())
+}
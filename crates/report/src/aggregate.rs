@@ -0,0 +1,263 @@
+use crate::{verify_report_bundle, FindingSeverity, ReportVerification};
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Fleet-wide summary over every report bundle under a directory.
+#[derive(Debug, Clone, Serialize)]
+pub struct FleetSummary {
+    pub root: String,
+    pub total_reports: usize,
+    pub succeeded: usize,
+    pub failed: usize,
+    pub dry_runs: usize,
+    pub by_workflow: BTreeMap<String, OutcomeCounts>,
+    pub devices: Vec<DeviceSummary>,
+    pub duration_ms: Option<DurationPercentiles>,
+    pub failures: Vec<FailedReport>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct OutcomeCounts {
+    pub succeeded: usize,
+    pub failed: usize,
+    pub dry_runs: usize,
+}
+
+/// One row per target, keyed by serial when the report has one.
+#[derive(Debug, Clone, Serialize)]
+pub struct DeviceSummary {
+    pub device: String,
+    pub runs: usize,
+    pub succeeded: usize,
+    pub failed: usize,
+    pub last_run_utc: Option<String>,
+    pub last_status: String,
+}
+
+/// Nearest-rank percentiles over runs that recorded a duration.
+#[derive(Debug, Clone, Serialize)]
+pub struct DurationPercentiles {
+    pub samples: usize,
+    pub min: u64,
+    pub p50: u64,
+    pub p90: u64,
+    pub p99: u64,
+    pub max: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FailedReport {
+    pub path: String,
+    pub reason: String,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Outcome {
+    Succeeded,
+    Failed,
+    DryRun,
+}
+
+impl Outcome {
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            Outcome::Succeeded => "succeeded",
+            Outcome::Failed => "failed",
+            Outcome::DryRun => "dry_run",
+        }
+    }
+}
+
+/// Walks `root` for report bundles (directories holding `manifest.json`)
+/// and summarizes them. A bundle counts as failed when its manifest does not
+/// verify, its `run.json` is unreadable, its status is `failed` or
+/// `degraded`, or a requested verification reported a mismatch.
+pub fn aggregate_reports(root: impl AsRef<Path>, signing_key_hex: Option<&str>) -> Result<FleetSummary> {
+    let root = root.as_ref();
+    if !root.exists() {
+        return Err(anyhow!("root path does not exist"));
+    }
+    let mut bundles = Vec::new();
+    find_bundles(root, &mut bundles)?;
+    bundles.sort();
+
+    let mut summary = FleetSummary {
+        root: root.display().to_string(),
+        total_reports: bundles.len(),
+        succeeded: 0,
+        failed: 0,
+        dry_runs: 0,
+        by_workflow: BTreeMap::new(),
+        devices: Vec::new(),
+        duration_ms: None,
+        failures: Vec::new(),
+    };
+    let mut devices: BTreeMap<String, DeviceSummary> = BTreeMap::new();
+    let mut durations = Vec::new();
+
+    for bundle in &bundles {
+        let meta = fs::read(bundle.join("run.json"))
+            .ok()
+            .and_then(|bytes| serde_json::from_slice::<Value>(&bytes).ok());
+        let (outcome, reason) = match (&meta, verify_report_bundle(bundle, signing_key_hex)) {
+            (None, _) => (Outcome::Failed, Some("run.json unreadable".to_string())),
+            (_, Err(err)) => (Outcome::Failed, Some(format!("verification error: {}", err))),
+            (_, Ok(result)) if !result.mismatches.is_empty() => (
+                Outcome::Failed,
+                Some(format!("manifest mismatch: {}", result.mismatches.join(", "))),
+            ),
+            (_, Ok(result)) if !result.ok => (Outcome::Failed, Some(finding_reason(&result))),
+            (Some(meta), Ok(_)) => classify(meta),
+        };
+        let meta = meta.unwrap_or(Value::Null);
+
+        match outcome {
+            Outcome::Succeeded => summary.succeeded += 1,
+            Outcome::Failed => summary.failed += 1,
+            Outcome::DryRun => summary.dry_runs += 1,
+        }
+        let workflow = meta
+            .get("workflow")
+            .and_then(Value::as_str)
+            .unwrap_or("unknown")
+            .to_string();
+        let counts = summary.by_workflow.entry(workflow).or_default();
+        match outcome {
+            Outcome::Succeeded => counts.succeeded += 1,
+            Outcome::Failed => counts.failed += 1,
+            Outcome::DryRun => counts.dry_runs += 1,
+        }
+        if let Some(reason) = reason {
+            summary.failures.push(FailedReport {
+                path: bundle.display().to_string(),
+                reason,
+            });
+        }
+        if outcome != Outcome::DryRun {
+            if let Some(duration) = duration_ms(&meta) {
+                durations.push(duration);
+            }
+        }
+
+        let device = device_key(&meta);
+        let generated = meta
+            .get("generated_at_utc")
+            .and_then(Value::as_str)
+            .map(str::to_string);
+        let row = devices.entry(device.clone()).or_insert_with(|| DeviceSummary {
+            device,
+            runs: 0,
+            succeeded: 0,
+            failed: 0,
+            last_run_utc: None,
+            last_status: String::new(),
+        });
+        row.runs += 1;
+        match outcome {
+            Outcome::Succeeded => row.succeeded += 1,
+            Outcome::Failed => row.failed += 1,
+            Outcome::DryRun => {}
+        }
+        if row.last_run_utc.is_none() || generated > row.last_run_utc {
+            row.last_run_utc = generated;
+            row.last_status = outcome.as_str().to_string();
+        }
+    }
+
+    summary.devices = devices.into_values().collect();
+    summary.duration_ms = percentiles(durations);
+    Ok(summary)
+}
+
+fn find_bundles(dir: &Path, out: &mut Vec<PathBuf>) -> Result<()> {
+    if dir.join("manifest.json").is_file() {
+        out.push(dir.to_path_buf());
+        return Ok(());
+    }
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            find_bundles(&path, out)?;
+        }
+    }
+    Ok(())
+}
+
+/// Error findings, or the signature when that is what failed.
+fn finding_reason(result: &ReportVerification) -> String {
+    let errors: Vec<String> = result
+        .findings
+        .iter()
+        .filter(|finding| finding.severity == FindingSeverity::Error)
+        .map(|finding| format!("{}: {}", finding.code, finding.message))
+        .collect();
+    if errors.is_empty() {
+        "signature invalid".to_string()
+    } else {
+        format!("invalid report: {}", errors.join(", "))
+    }
+}
+
+pub(crate) fn classify(meta: &Value) -> (Outcome, Option<String>) {
+    if meta.get("dry_run").and_then(Value::as_bool) == Some(true) {
+        return (Outcome::DryRun, None);
+    }
+    if meta.get("status").and_then(Value::as_str) == Some("failed") {
+        let error = meta.get("error").and_then(Value::as_str).unwrap_or("status failed");
+        return (Outcome::Failed, Some(error.to_string()));
+    }
+    // A `continue_on_error` copy that skipped files still ran to the end,
+    // but the stick is incomplete.
+    if meta.get("status").and_then(Value::as_str) == Some("degraded") {
+        let skipped = meta.get("copy_failures").and_then(Value::as_u64).unwrap_or(0);
+        return (Outcome::Failed, Some(format!("degraded: {} files not copied", skipped)));
+    }
+    if meta.get("verify_ok").and_then(Value::as_bool) == Some(false) {
+        return (Outcome::Failed, Some("verify_ok=false".to_string()));
+    }
+    (Outcome::Succeeded, None)
+}
+
+/// Target identity: serial, then disk id, then device path.
+pub(crate) fn device_key(meta: &Value) -> String {
+    ["target_serial", "target_disk_id", "target_device", "target_mount"]
+        .iter()
+        .find_map(|key| meta.get(*key).and_then(Value::as_str))
+        .unwrap_or("-")
+        .to_string()
+}
+
+/// `duration_ms`, else the sum of workflow step durations.
+pub(crate) fn duration_ms(meta: &Value) -> Option<u64> {
+    if let Some(duration) = meta.get("duration_ms").and_then(Value::as_u64) {
+        return Some(duration);
+    }
+    let steps = meta.get("steps")?.as_array()?;
+    steps
+        .iter()
+        .map(|step| step.get("duration_ms").and_then(Value::as_u64))
+        .sum()
+}
+
+fn percentiles(mut samples: Vec<u64>) -> Option<DurationPercentiles> {
+    if samples.is_empty() {
+        return None;
+    }
+    samples.sort_unstable();
+    let rank = |p: f64| {
+        let index = ((p / 100.0) * samples.len() as f64).ceil() as usize;
+        samples[index.clamp(1, samples.len()) - 1]
+    };
+    Some(DurationPercentiles {
+        samples: samples.len(),
+        min: samples[0],
+        p50: rank(50.0),
+        p90: rank(90.0),
+        p99: rank(99.0),
+        max: samples[samples.len() - 1],
+    })
+}
@@ -0,0 +1,207 @@
+//! Semantic checks on a report bundle. Hashes prove the files were not
+//! changed after the run; these prove the run record makes sense: the
+//! workflow is one this build knows, the artifacts it names were written,
+//! the device graph still parses and the status agrees with the counts.
+
+use crate::Manifest;
+use phoenix_core::DeviceGraph;
+use serde::Serialize;
+use serde_json::Value;
+use std::fs;
+use std::path::Path;
+
+/// Workflows that write `run.json`, and whether a completed run must
+/// record `copied_files`.
+const KNOWN_WORKFLOWS: &[(&str, bool)] = &[
+    ("boot-entry", false),
+    ("clone-device", false),
+    ("disk-hash-report", false),
+    ("duplicate-to-all", false),
+    ("ipsw-restore", false),
+    ("kiosk", false),
+    ("macos-erase-install", false),
+    ("macos-installer-usb", false),
+    ("macos-kext-stage", true),
+    ("merge-windows-languages", true),
+    ("restore-report", false),
+    ("slim-windows-media", false),
+    ("stage-bootloader", true),
+    ("stage-firstboot", true),
+    ("stage-files", true),
+    ("stage-provisioning", true),
+    ("unix-boot-prep", true),
+    ("unix-installer-usb", true),
+    ("unix-write-image", false),
+    ("validate-source", false),
+    ("windows-apply-image", false),
+    ("windows-installer-usb", true),
+];
+
+const KNOWN_STATUSES: &[&str] = &["completed", "dry_run", "failed", "valid", "invalid"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS))]
+#[serde(rename_all = "snake_case")]
+pub enum FindingSeverity {
+    /// The bundle does not verify.
+    Error,
+    /// Worth a look, but the bundle still verifies.
+    Warning,
+}
+
+impl FindingSeverity {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Error => "error",
+            Self::Warning => "warning",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS))]
+pub struct ReportFinding {
+    pub severity: FindingSeverity,
+    /// Stable identifier such as `artifact_missing`, for scripts.
+    pub code: &'static str,
+    pub message: String,
+}
+
+impl ReportFinding {
+    fn error(code: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            severity: FindingSeverity::Error,
+            code,
+            message: message.into(),
+        }
+    }
+
+    fn warning(code: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            severity: FindingSeverity::Warning,
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+pub(crate) fn check_bundle(root: &Path, manifest: &Manifest) -> Vec<ReportFinding> {
+    let mut findings = Vec::new();
+    match fs::read(root.join("device_graph.json")) {
+        Ok(bytes) => {
+            if let Err(err) = DeviceGraph::from_json(&bytes) {
+                findings.push(ReportFinding::error("device_graph_invalid", err.to_string()));
+            }
+        }
+        Err(_) => findings.push(ReportFinding::error(
+            "device_graph_missing",
+            "device_graph.json is unreadable",
+        )),
+    }
+
+    let meta = match fs::read(root.join("run.json")) {
+        Ok(bytes) => match serde_json::from_slice::<Value>(&bytes) {
+            Ok(Value::Object(meta)) => meta,
+            Ok(_) => {
+                findings.push(ReportFinding::error("run_invalid", "run.json is not an object"));
+                return findings;
+            }
+            Err(err) => {
+                findings.push(ReportFinding::error(
+                    "run_invalid",
+                    format!("run.json does not parse: {}", err),
+                ));
+                return findings;
+            }
+        },
+        Err(_) => {
+            findings.push(ReportFinding::error("run_missing", "run.json is unreadable"));
+            return findings;
+        }
+    };
+
+    match meta.get("run_id").and_then(Value::as_str) {
+        Some(run_id) if run_id == manifest.run_id => {}
+        Some(run_id) => findings.push(ReportFinding::error(
+            "run_id_mismatch",
+            format!("run.json run_id {} differs from manifest {}", run_id, manifest.run_id),
+        )),
+        None => findings.push(ReportFinding::error("run_id_missing", "run.json has no run_id")),
+    }
+
+    if let Some(artifacts) = meta.get("artifacts") {
+        let Some(names) = artifacts.as_array() else {
+            findings.push(ReportFinding::error("artifacts_invalid", "artifacts is not a list"));
+            return findings;
+        };
+        for name in names {
+            let Some(name) = name.as_str() else {
+                findings.push(ReportFinding::error(
+                    "artifacts_invalid",
+                    format!("artifact name {} is not a string", name),
+                ));
+                continue;
+            };
+            if !manifest.entries.iter().any(|entry| entry.path == name) {
+                findings.push(ReportFinding::error(
+                    "artifact_unlisted",
+                    format!("artifact {} is not in the manifest", name),
+                ));
+            } else if !root.join(name).is_file() {
+                findings.push(ReportFinding::error(
+                    "artifact_missing",
+                    format!("artifact {} is missing", name),
+                ));
+            }
+        }
+    }
+
+    // Bundles written by `report-create` carry no workflow.
+    let Some(workflow) = meta.get("workflow") else {
+        return findings;
+    };
+    let Some(workflow) = workflow.as_str() else {
+        findings.push(ReportFinding::error("workflow_invalid", "workflow is not a string"));
+        return findings;
+    };
+    let copies = match KNOWN_WORKFLOWS.iter().find(|(name, _)| *name == workflow) {
+        Some((_, copies)) => *copies,
+        None => {
+            findings.push(ReportFinding::warning(
+                "unknown_workflow",
+                format!("workflow {} is not known to this build", workflow),
+            ));
+            false
+        }
+    };
+
+    let dry_run = meta.get("dry_run").and_then(Value::as_bool);
+    let status = meta.get("status").and_then(Value::as_str);
+    if let Some(status) = status {
+        if !KNOWN_STATUSES.contains(&status) {
+            findings.push(ReportFinding::warning(
+                "unknown_status",
+                format!("status {} is not known to this build", status),
+            ));
+        }
+    }
+    match (status, dry_run) {
+        (Some("completed"), Some(true)) => findings.push(ReportFinding::error(
+            "status_inconsistent",
+            "status is completed but dry_run is true",
+        )),
+        (Some("dry_run"), Some(false)) => findings.push(ReportFinding::error(
+            "status_inconsistent",
+            "status is dry_run but dry_run is false",
+        )),
+        _ => {}
+    }
+    if copies && status == Some("completed") && !meta.get("copied_files").is_some_and(Value::is_u64)
+    {
+        findings.push(ReportFinding::error(
+            "copy_count_missing",
+            format!("completed {} run has no copied_files count", workflow),
+        ));
+    }
+    findings
+}
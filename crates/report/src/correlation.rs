@@ -0,0 +1,191 @@
+//! External correlation ids (ticket, work order) attached to the reports a
+//! thread produces, and the `reports/index.jsonl` index used to find
+//! bundles by them and to list run history.
+
+use crate::aggregate;
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::cell::RefCell;
+use std::fs;
+use std::io::{BufRead, Write};
+use std::path::Path;
+
+pub const REPORT_INDEX_FILE: &str = "index.jsonl";
+const MAX_CORRELATION_ID_LEN: usize = 64;
+
+thread_local! {
+    static CURRENT: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Ids end up in directory names, so only `[A-Za-z0-9._-]` is allowed.
+pub fn validate_correlation_id(id: &str) -> Result<()> {
+    if id.is_empty() || id.len() > MAX_CORRELATION_ID_LEN {
+        return Err(anyhow!(
+            "correlation id must be 1-{} characters",
+            MAX_CORRELATION_ID_LEN
+        ));
+    }
+    if id.starts_with('.')
+        || !id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+    {
+        return Err(anyhow!(
+            "correlation id may only contain letters, digits, '.', '_' and '-': {}",
+            id
+        ));
+    }
+    Ok(())
+}
+
+/// Sets the correlation id for reports created on this thread.
+pub fn set_correlation_id(id: Option<&str>) -> Result<()> {
+    if let Some(id) = id {
+        validate_correlation_id(id)?;
+    }
+    CURRENT.with(|current| *current.borrow_mut() = id.map(str::to_string));
+    Ok(())
+}
+
+pub fn correlation_id() -> Option<String> {
+    CURRENT.with(|current| current.borrow().clone())
+}
+
+/// Runs `f` with `id` as this thread's correlation id, restoring the
+/// previous one afterwards. `None` keeps the current id.
+pub fn with_correlation_id<T>(id: Option<&str>, f: impl FnOnce() -> Result<T>) -> Result<T> {
+    let Some(id) = id else {
+        return f();
+    };
+    validate_correlation_id(id)?;
+    let previous = CURRENT.with(|current| current.borrow_mut().replace(id.to_string()));
+    let result = f();
+    CURRENT.with(|current| *current.borrow_mut() = previous);
+    result
+}
+
+/// One line of `reports/index.jsonl`. The summary fields are absent from
+/// lines written before they existed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS))]
+pub struct ReportIndexEntry {
+    pub run_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub correlation_id: Option<String>,
+    pub generated_at_utc: String,
+    /// Bundle directory, relative to the `reports` directory.
+    pub path: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub workflow: Option<String>,
+    /// `succeeded`, `failed` or `dry_run`, as `report-aggregate` counts it
+    /// (without verifying the manifest).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<String>,
+    /// Target serial, disk id, device or mount.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "ts", ts(as = "Option<f64>"))]
+    pub duration_ms: Option<u64>,
+}
+
+impl ReportIndexEntry {
+    /// The entry for the bundle at `path` whose `run.json` is `meta`.
+    pub(crate) fn from_meta(path: String, meta: &Value) -> Self {
+        let text = |key: &str| meta.get(key).and_then(Value::as_str).map(str::to_string);
+        let target = aggregate::device_key(meta);
+        Self {
+            run_id: text("run_id").unwrap_or_default(),
+            correlation_id: text("correlation_id"),
+            generated_at_utc: text("generated_at_utc").unwrap_or_default(),
+            path,
+            workflow: text("workflow"),
+            status: Some(aggregate::classify(meta).0.as_str().to_string()),
+            target: (target != "-").then_some(target),
+            duration_ms: aggregate::duration_ms(meta),
+        }
+    }
+}
+
+pub(crate) fn append_index(reports_dir: &Path, entry: &ReportIndexEntry) -> Result<()> {
+    let path = reports_dir.join(REPORT_INDEX_FILE);
+    let mut line = serde_json::to_vec(entry)?;
+    line.push(b'\n');
+    // One write per line keeps concurrent appends from interleaving.
+    fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .and_then(|mut file| file.write_all(&line))
+        .with_context(|| format!("append {}", path.display()))
+}
+
+/// Bundles under `base/reports` recorded with `correlation_id`, oldest
+/// first. Bundles created before the index existed need
+/// `rebuild_report_index` first.
+pub fn find_reports_by_correlation_id(
+    base: impl AsRef<Path>,
+    correlation_id: &str,
+) -> Result<Vec<ReportIndexEntry>> {
+    let reports_dir = base.as_ref().join("reports");
+    Ok(read_index(&reports_dir)?
+        .into_iter()
+        .filter(|entry| {
+            entry.correlation_id.as_deref() == Some(correlation_id)
+                && reports_dir.join(&entry.path).is_dir()
+        })
+        .collect())
+}
+
+/// Every line of `reports_dir/index.jsonl`, in file order; empty when
+/// there is no index.
+pub(crate) fn read_index(reports_dir: &Path) -> Result<Vec<ReportIndexEntry>> {
+    let path = reports_dir.join(REPORT_INDEX_FILE);
+    let file = match fs::File::open(&path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(anyhow!("read {} failed: {}", path.display(), err)),
+    };
+    let mut entries = Vec::new();
+    for line in std::io::BufReader::new(file).lines() {
+        let line = line?;
+        // A torn last line from a crashed writer is skipped.
+        if let Ok(entry) = serde_json::from_str::<ReportIndexEntry>(&line) {
+            entries.push(entry);
+        }
+    }
+    Ok(entries)
+}
+
+/// Rewrites `base/reports/index.jsonl` from the `run.json` of every bundle
+/// there. Returns the number of bundles indexed.
+pub fn rebuild_report_index(base: impl AsRef<Path>) -> Result<usize> {
+    let reports_dir = base.as_ref().join("reports");
+    let mut entries = Vec::new();
+    for dir in fs::read_dir(&reports_dir)
+        .with_context(|| format!("read {}", reports_dir.display()))?
+    {
+        let dir = dir?.path();
+        let Ok(bytes) = fs::read(dir.join("run.json")) else {
+            continue;
+        };
+        let Ok(meta) = serde_json::from_slice::<Value>(&bytes) else {
+            continue;
+        };
+        let path = dir.file_name().unwrap_or_default().to_string_lossy().to_string();
+        entries.push(ReportIndexEntry::from_meta(path, &meta));
+    }
+    entries.sort_by(|a, b| a.generated_at_utc.cmp(&b.generated_at_utc));
+
+    let mut bytes = Vec::new();
+    for entry in &entries {
+        serde_json::to_writer(&mut bytes, entry)?;
+        bytes.push(b'\n');
+    }
+    let path = reports_dir.join(REPORT_INDEX_FILE);
+    let tmp = path.with_extension("jsonl.tmp");
+    fs::write(&tmp, &bytes).with_context(|| format!("write {}", tmp.display()))?;
+    fs::rename(&tmp, &path).with_context(|| format!("write {}", path.display()))?;
+    Ok(entries.len())
+}
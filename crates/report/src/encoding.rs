@@ -0,0 +1,126 @@
+//! How JSON artifacts are written. A copy manifest for a full Windows
+//! source is tens of MB pretty-printed, so runs over huge trees can write
+//! compact JSON or a zstd-compressed `<name>.zst` instead.
+
+use crate::ReportArtifact;
+use anyhow::{anyhow, Context, Result};
+use serde::Serialize;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+pub const ARTIFACT_ENCODING_ENV: &str = "PHOENIX_ARTIFACT_ENCODING";
+
+/// Suffix appended to the artifact name when compressed.
+pub const ZSTD_SUFFIX: &str = ".zst";
+
+const ZSTD_LEVEL: i32 = 9;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ArtifactEncoding {
+    #[default]
+    Pretty,
+    Compact,
+    /// Compact JSON compressed with zstd, stored as `<name>.zst`.
+    Zstd,
+}
+
+impl ArtifactEncoding {
+    pub fn parse(value: &str) -> Result<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "pretty" => Ok(Self::Pretty),
+            "compact" => Ok(Self::Compact),
+            "zstd" => Ok(Self::Zstd),
+            other => Err(anyhow!(
+                "unknown artifact encoding {} (expected pretty, compact or zstd)",
+                other
+            )),
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Pretty => "pretty",
+            Self::Compact => "compact",
+            Self::Zstd => "zstd",
+        }
+    }
+}
+
+/// 0 means unset: fall back to `PHOENIX_ARTIFACT_ENCODING`.
+static ENCODING: AtomicU8 = AtomicU8::new(0);
+
+/// Overrides `PHOENIX_ARTIFACT_ENCODING` for this process.
+pub fn set_artifact_encoding(encoding: ArtifactEncoding) {
+    let value = match encoding {
+        ArtifactEncoding::Pretty => 1,
+        ArtifactEncoding::Compact => 2,
+        ArtifactEncoding::Zstd => 3,
+    };
+    ENCODING.store(value, Ordering::SeqCst);
+}
+
+/// The encoding set for this process, else `PHOENIX_ARTIFACT_ENCODING`,
+/// else pretty. An unrecognized variable value is ignored.
+pub fn artifact_encoding() -> ArtifactEncoding {
+    match ENCODING.load(Ordering::SeqCst) {
+        1 => ArtifactEncoding::Pretty,
+        2 => ArtifactEncoding::Compact,
+        3 => ArtifactEncoding::Zstd,
+        _ => std::env::var(ARTIFACT_ENCODING_ENV)
+            .ok()
+            .and_then(|value| ArtifactEncoding::parse(&value).ok())
+            .unwrap_or_default(),
+    }
+}
+
+impl ReportArtifact {
+    /// Serializes `value` with the process's `artifact_encoding`. With
+    /// zstd the stored name is `<name>.zst`; `read_report_artifact` takes
+    /// the plain name either way.
+    pub fn json(name: impl Into<String>, value: &impl Serialize) -> Result<Self> {
+        let name = name.into();
+        Ok(match artifact_encoding() {
+            ArtifactEncoding::Pretty => Self::bytes(name, serde_json::to_vec_pretty(value)?),
+            ArtifactEncoding::Compact => Self::bytes(name, serde_json::to_vec(value)?),
+            ArtifactEncoding::Zstd => {
+                let json = serde_json::to_vec(value)?;
+                let compressed = zstd::encode_all(json.as_slice(), ZSTD_LEVEL)
+                    .context("zstd compress artifact")?;
+                Self::bytes(format!("{}{}", name, ZSTD_SUFFIX), compressed)
+            }
+        })
+    }
+}
+
+/// Contents of artifact `name` in a report bundle, decompressing
+/// `<name>.zst` when only the compressed form exists.
+pub fn read_report_artifact(report_root: impl AsRef<Path>, name: &str) -> Result<Vec<u8>> {
+    let root = report_root.as_ref();
+    let plain = root.join(name);
+    if plain.is_file() {
+        if name.ends_with(ZSTD_SUFFIX) {
+            return decode_zstd_file(&plain);
+        }
+        return fs::read(&plain).with_context(|| format!("read {}", plain.display()));
+    }
+    let compressed = root.join(format!("{}{}", name, ZSTD_SUFFIX));
+    if compressed.is_file() {
+        return decode_zstd_file(&compressed);
+    }
+    Err(anyhow!("artifact {} not found in {}", name, root.display()))
+}
+
+fn decode_zstd_file(path: &Path) -> Result<Vec<u8>> {
+    let file = fs::File::open(path).with_context(|| format!("open {}", path.display()))?;
+    zstd::decode_all(file).with_context(|| format!("decompress {}", path.display()))
+}
+
+/// Checks that a `.zst` artifact decompresses to the end, without keeping
+/// the output.
+pub(crate) fn check_zstd_file(path: &Path) -> Result<()> {
+    let file = fs::File::open(path)?;
+    zstd::stream::copy_decode(file, io::sink())?;
+    Ok(())
+}
@@ -0,0 +1,176 @@
+//! `environment.json`: what produced a report bundle (Phoenix build, OS
+//! build, relevant environment), so old reports stay interpretable after
+//! the toolchain moves on.
+
+use phoenix_core::DeviceGraph;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::OnceLock;
+
+pub const ENVIRONMENT_SCHEMA_VERSION: &str = "1.0.0";
+
+/// Build details of the binary writing reports. Set once by the
+/// application; library users get the report crate's own version.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct BuildInfo {
+    pub version: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub git_rev: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+    /// Enabled cargo features.
+    pub features: Vec<String>,
+    /// Crate name to locked version(s).
+    pub crates: BTreeMap<String, String>,
+}
+
+impl BuildInfo {
+    /// Parses `name@version,name@version` as embedded by the CLI build
+    /// script; a crate locked at two versions lists both.
+    pub fn parse_crate_versions(list: &str) -> BTreeMap<String, String> {
+        let mut crates: BTreeMap<String, String> = BTreeMap::new();
+        for (name, version) in list.split(',').filter_map(|pair| pair.split_once('@')) {
+            crates
+                .entry(name.to_string())
+                .and_modify(|existing| {
+                    existing.push_str(", ");
+                    existing.push_str(version);
+                })
+                .or_insert_with(|| version.to_string());
+        }
+        crates
+    }
+}
+
+static BUILD_INFO: OnceLock<BuildInfo> = OnceLock::new();
+
+/// Records the application's build details; later calls are ignored.
+pub fn set_build_info(info: BuildInfo) {
+    let _ = BUILD_INFO.set(info);
+}
+
+fn build_info() -> &'static BuildInfo {
+    BUILD_INFO.get_or_init(|| BuildInfo {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        ..BuildInfo::default()
+    })
+}
+
+/// The fleet agent a process runs jobs as, from the client certificate it
+/// presents to the orchestration server.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AgentIdentity {
+    /// Subject common name of the certificate.
+    pub agent_id: String,
+    /// SHA-256 of the certificate, lowercase hex.
+    pub cert_sha256: String,
+    /// Serial number, lowercase hex.
+    pub serial: String,
+    /// Issuer common name, e.g. the fleet CA.
+    pub issuer: String,
+    pub not_after_utc: String,
+}
+
+static AGENT_IDENTITY: OnceLock<AgentIdentity> = OnceLock::new();
+
+/// Records the agent identity in every later `environment.json`; later
+/// calls are ignored.
+pub fn set_agent_identity(identity: AgentIdentity) {
+    let _ = AGENT_IDENTITY.set(identity);
+}
+
+pub fn agent_identity() -> Option<&'static AgentIdentity> {
+    AGENT_IDENTITY.get()
+}
+
+/// Environment variables that change Phoenix behaviour, besides `PHOENIX_*`.
+const RELEVANT_ENV: &[&str] = &[
+    "HTTPS_PROXY", "https_proxy", "HTTP_PROXY", "http_proxy", "ALL_PROXY", "all_proxy",
+    "NO_PROXY", "no_proxy", "LANG", "LC_ALL", "TZ", "XDG_STATE_HOME",
+];
+
+pub(crate) fn capture_environment(graph: &DeviceGraph) -> serde_json::Value {
+    let mut environment = serde_json::json!({
+        "schema_version": ENVIRONMENT_SCHEMA_VERSION,
+        "phoenix": build_info(),
+        "os": {
+            "family": std::env::consts::OS,
+            "arch": std::env::consts::ARCH,
+            "version": graph.host.os_version,
+            "build": os_build(),
+        },
+        "env": relevant_env(),
+    });
+    if let Some(agent) = agent_identity() {
+        environment["agent"] = serde_json::json!(agent);
+    }
+    environment
+}
+
+fn relevant_env() -> BTreeMap<String, String> {
+    std::env::vars()
+        .filter(|(name, _)| name.starts_with("PHOENIX_") || RELEVANT_ENV.contains(&name.as_str()))
+        .map(|(name, value)| {
+            let upper = name.to_ascii_uppercase();
+            let value = if ["KEY", "TOKEN", "SECRET", "PASSWORD"]
+                .iter()
+                .any(|word| upper.contains(word))
+                && !upper.contains("PUBLIC_KEY")
+            {
+                "<redacted>".to_string()
+            } else {
+                strip_userinfo(&value)
+            };
+            (name, value)
+        })
+        .collect()
+}
+
+/// Drops `user:pass@` from URL-like values such as proxy settings.
+fn strip_userinfo(value: &str) -> String {
+    let Some((scheme, rest)) = value.split_once("://") else {
+        return value.to_string();
+    };
+    let authority_end = rest.find('/').unwrap_or(rest.len());
+    match rest[..authority_end].rfind('@') {
+        Some(at) => format!("{}://***{}", scheme, &rest[at..]),
+        None => value.to_string(),
+    }
+}
+
+/// Kernel or OS build string, read once per process.
+fn os_build() -> &'static str {
+    static BUILD: OnceLock<String> = OnceLock::new();
+    BUILD.get_or_init(|| read_os_build().unwrap_or_else(|| "unknown".to_string()))
+}
+
+#[cfg(target_os = "linux")]
+fn read_os_build() -> Option<String> {
+    std::fs::read_to_string("/proc/sys/kernel/osrelease")
+        .ok()
+        .map(|release| release.trim().to_string())
+}
+
+#[cfg(target_os = "macos")]
+fn read_os_build() -> Option<String> {
+    command_output("sysctl", &["-n", "kern.osversion"])
+}
+
+#[cfg(windows)]
+fn read_os_build() -> Option<String> {
+    command_output("cmd", &["/C", "ver"])
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+fn read_os_build() -> Option<String> {
+    None
+}
+
+#[cfg(any(target_os = "macos", windows))]
+fn command_output(program: &str, args: &[&str]) -> Option<String> {
+    let output = std::process::Command::new(program).args(args).output().ok()?;
+    let text = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (output.status.success() && !text.is_empty()).then_some(text)
+}
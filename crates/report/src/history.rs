@@ -0,0 +1,138 @@
+//! Run history over `reports/index.jsonl`, for history screens: filtered
+//! listings newest first and the full `run.json` of one run.
+
+use crate::correlation::read_index;
+use crate::ReportIndexEntry;
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Criteria a run must all meet to be listed; unset ones match anything.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS), ts(optional_fields))]
+#[serde(default, deny_unknown_fields)]
+pub struct RunFilter {
+    pub workflow: Option<String>,
+    /// `succeeded`, `failed` or `dry_run`.
+    pub status: Option<String>,
+    /// Case-insensitive substring of the target.
+    pub target: Option<String>,
+    pub correlation_id: Option<String>,
+    /// RFC 3339 bounds on `generated_at_utc`, inclusive.
+    pub since: Option<String>,
+    pub until: Option<String>,
+    /// Case-insensitive substring of the run id, workflow, target or
+    /// correlation id.
+    pub text: Option<String>,
+    /// Newest runs kept after filtering.
+    pub limit: Option<usize>,
+}
+
+impl RunFilter {
+    fn matches(&self, entry: &ReportIndexEntry) -> bool {
+        let contains = |value: Option<&str>, needle: &str| {
+            value.is_some_and(|value| value.to_lowercase().contains(&needle.to_lowercase()))
+        };
+        self.workflow
+            .as_ref()
+            .is_none_or(|workflow| entry.workflow.as_ref() == Some(workflow))
+            && self
+                .status
+                .as_ref()
+                .is_none_or(|status| entry.status.as_ref() == Some(status))
+            && self
+                .target
+                .as_deref()
+                .is_none_or(|target| contains(entry.target.as_deref(), target))
+            && self
+                .correlation_id
+                .as_ref()
+                .is_none_or(|id| entry.correlation_id.as_ref() == Some(id))
+            && self
+                .since
+                .as_deref()
+                .is_none_or(|since| entry.generated_at_utc.as_str() >= since)
+            && self
+                .until
+                .as_deref()
+                .is_none_or(|until| entry.generated_at_utc.as_str() <= until)
+            && self.text.as_deref().is_none_or(|text| {
+                [
+                    Some(entry.run_id.as_str()),
+                    entry.workflow.as_deref(),
+                    entry.target.as_deref(),
+                    entry.correlation_id.as_deref(),
+                ]
+                .into_iter()
+                .any(|value| contains(value, text))
+            })
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS))]
+pub struct RunDetails {
+    #[serde(flatten)]
+    #[cfg_attr(feature = "ts", ts(flatten))]
+    pub entry: ReportIndexEntry,
+    pub root: PathBuf,
+    /// The bundle's `run.json`.
+    pub meta: Value,
+}
+
+/// Indexed runs under `base/reports` that still exist and match
+/// `filter`, newest first. Index lines from before the summary fields
+/// existed are completed from the bundle's `run.json`.
+pub fn list_runs(base: impl AsRef<Path>, filter: &RunFilter) -> Result<Vec<ReportIndexEntry>> {
+    let reports_dir = base.as_ref().join("reports");
+    let mut runs = Vec::new();
+    for entry in read_index(&reports_dir)? {
+        let root = reports_dir.join(&entry.path);
+        if !root.is_dir() {
+            continue;
+        }
+        let entry = match entry.status {
+            Some(_) => entry,
+            None => match read_meta(&root) {
+                Ok(meta) => ReportIndexEntry::from_meta(entry.path, &meta),
+                Err(_) => entry,
+            },
+        };
+        if filter.matches(&entry) {
+            runs.push(entry);
+        }
+    }
+    runs.sort_by(|a, b| b.generated_at_utc.cmp(&a.generated_at_utc));
+    if let Some(limit) = filter.limit {
+        runs.truncate(limit);
+    }
+    Ok(runs)
+}
+
+/// The indexed run `run_id` under `base/reports`.
+pub fn get_run(base: impl AsRef<Path>, run_id: &str) -> Result<RunDetails> {
+    let reports_dir = base.as_ref().join("reports");
+    let entry = read_index(&reports_dir)?
+        .into_iter()
+        .rev()
+        .find(|entry| entry.run_id == run_id)
+        .ok_or_else(|| anyhow!("run {} is not in {}", run_id, reports_dir.display()))?;
+    let root = reports_dir.join(&entry.path);
+    if !root.is_dir() {
+        return Err(anyhow!("report bundle {} no longer exists", root.display()));
+    }
+    let meta = read_meta(&root)?;
+    Ok(RunDetails {
+        entry: ReportIndexEntry::from_meta(entry.path, &meta),
+        root,
+        meta,
+    })
+}
+
+fn read_meta(root: &Path) -> Result<Value> {
+    let path = root.join("run.json");
+    let bytes = fs::read(&path).with_context(|| format!("read {}", path.display()))?;
+    serde_json::from_slice(&bytes).with_context(|| format!("parse {}", path.display()))
+}
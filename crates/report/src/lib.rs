@@ -1,19 +1,52 @@
-use anyhow::{anyhow, Result};
-use phoenix_core::DeviceGraph;
+use anyhow::{anyhow, Context, Result};
+use phoenix_core::{DeviceGraph, HostInfo};
 use serde_json::Value;
 use sha2::{Digest, Sha256};
 use std::fs;
-use std::io::Write;
+use std::io::{self, Read, Write};
 use std::path::{Path, PathBuf};
 use uuid::Uuid;
 use zip::write::FileOptions;
+use zip::CompressionMethod;
 use zip::ZipWriter;
 
+mod aggregate;
+mod checks;
+mod correlation;
+mod encoding;
+mod environment;
+mod history;
+mod redact;
+mod retention;
+
+pub use aggregate::{
+    aggregate_reports, DeviceSummary, DurationPercentiles, FailedReport, FleetSummary,
+    OutcomeCounts,
+};
+pub use checks::{FindingSeverity, ReportFinding};
+pub use correlation::{
+    correlation_id, find_reports_by_correlation_id, rebuild_report_index, set_correlation_id,
+    validate_correlation_id, with_correlation_id, ReportIndexEntry, REPORT_INDEX_FILE,
+};
+pub use encoding::{
+    artifact_encoding, read_report_artifact, set_artifact_encoding, ArtifactEncoding,
+    ARTIFACT_ENCODING_ENV, ZSTD_SUFFIX,
+};
+pub use environment::{
+    agent_identity, set_agent_identity, set_build_info, AgentIdentity, BuildInfo,
+    ENVIRONMENT_SCHEMA_VERSION,
+};
+pub use history::{get_run, list_runs, RunDetails, RunFilter};
+pub use redact::{redact_secrets, register_secret, REDACTED};
+pub use retention::{prune_reports, PruneResult, RetentionPolicy, RETENTION_POLICY_FILE};
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
 pub struct ReportPaths {
     pub run_id: String,
     pub root: PathBuf,
     pub device_graph_json: PathBuf,
     pub run_json: PathBuf,
+    pub environment_json: PathBuf,
     pub logs_path: PathBuf,
     pub manifest_path: PathBuf,
     pub signature_path: Option<PathBuf>,
@@ -22,7 +55,140 @@ pub struct ReportPaths {
 #[derive(Debug, Clone)]
 pub struct ReportArtifact {
     pub name: String,
-    pub bytes: Vec<u8>,
+    pub source: ArtifactSource,
+}
+
+#[derive(Debug, Clone)]
+pub enum ArtifactSource {
+    Bytes(Vec<u8>),
+    File(PathBuf),
+    /// Binary disk image copied verbatim: never redacted, never buffered.
+    Image(PathBuf),
+}
+
+impl ReportArtifact {
+    pub fn bytes(name: impl Into<String>, bytes: Vec<u8>) -> Self {
+        Self {
+            name: name.into(),
+            source: ArtifactSource::Bytes(bytes),
+        }
+    }
+
+    pub fn file(name: impl Into<String>, path: impl Into<PathBuf>) -> Self {
+        Self {
+            name: name.into(),
+            source: ArtifactSource::File(path.into()),
+        }
+    }
+
+    pub fn image(name: impl Into<String>, path: impl Into<PathBuf>) -> Self {
+        Self {
+            name: name.into(),
+            source: ArtifactSource::Image(path.into()),
+        }
+    }
+}
+
+/// Streams an artifact into a report directory, hashing as it writes so
+/// large artifacts never need to be held in memory. Only `write_artifact`
+/// uses it, so every file it writes is redacted and in the manifest.
+struct ArtifactWriter {
+    rel_path: String,
+    file: io::BufWriter<fs::File>,
+    hasher: Sha256,
+    bytes: u64,
+}
+
+impl ArtifactWriter {
+    fn create(report_root: impl AsRef<Path>, name: &str) -> Result<Self> {
+        let rel_path = normalize_artifact_name(name)?;
+        let path = report_root.as_ref().join(&rel_path);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let file = fs::File::create(&path)
+            .with_context(|| format!("create artifact {}", path.display()))?;
+        Ok(Self {
+            rel_path,
+            file: io::BufWriter::new(file),
+            hasher: Sha256::new(),
+            bytes: 0,
+        })
+    }
+
+    fn finish(mut self) -> Result<ManifestEntry> {
+        self.file.flush()?;
+        Ok(ManifestEntry {
+            path: self.rel_path,
+            bytes: self.bytes,
+            sha256: to_hex(&self.hasher.finalize()),
+        })
+    }
+}
+
+impl Write for ArtifactWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.file.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        self.bytes += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+fn normalize_artifact_name(name: &str) -> Result<String> {
+    let normalized = name.replace('\\', "/");
+    let mut parts = Vec::new();
+    for part in normalized.split('/') {
+        match part {
+            "" | "." => continue,
+            ".." => return Err(anyhow!("artifact name must not contain '..': {}", name)),
+            other => parts.push(other),
+        }
+    }
+    if normalized.starts_with('/') || parts.first().map(|p| p.contains(':')).unwrap_or(false) {
+        return Err(anyhow!("artifact name must be relative: {}", name));
+    }
+    if parts.is_empty() {
+        return Err(anyhow!("artifact name is empty"));
+    }
+    let rel = parts.join("/");
+    if matches!(
+        rel.as_str(),
+        "device_graph.json"
+            | "run.json"
+            | "environment.json"
+            | "logs.txt"
+            | "manifest.json"
+            | "manifest.sig"
+    ) {
+        return Err(anyhow!("artifact name is reserved: {}", rel));
+    }
+    Ok(rel)
+}
+
+fn write_artifact(root: &Path, artifact: &ReportArtifact) -> Result<ManifestEntry> {
+    let mut writer = ArtifactWriter::create(root, &artifact.name)?;
+    match &artifact.source {
+        ArtifactSource::Bytes(bytes) => match redact::redact_bytes(bytes) {
+            Some(redacted) => writer.write_all(&redacted)?,
+            None => writer.write_all(bytes)?,
+        },
+        ArtifactSource::File(path) if redact::has_secrets() => {
+            let mut input = fs::File::open(path)
+                .with_context(|| format!("open artifact source {}", path.display()))?;
+            redact::copy_redacted(&mut input, &mut writer)?;
+        }
+        ArtifactSource::File(path) | ArtifactSource::Image(path) => {
+            let mut input = fs::File::open(path)
+                .with_context(|| format!("open artifact source {}", path.display()))?;
+            io::copy(&mut input, &mut writer)?;
+        }
+    }
+    writer.finish()
 }
 
 pub fn create_report_bundle(base: impl AsRef<Path>, graph: &DeviceGraph) -> Result<ReportPaths> {
@@ -55,6 +221,30 @@ pub fn create_report_bundle_with_meta_and_signing(
     )
 }
 
+/// `run.json`'s top-level sizes, rates and durations as people read them
+/// (`display_format`), under the same keys; the raw counts stay.
+fn display_fields(meta: &Value) -> Option<Value> {
+    let format = phoenix_core::display_format();
+    let fields: serde_json::Map<String, Value> = meta
+        .as_object()?
+        .iter()
+        .filter_map(|(key, value)| {
+            let count = value.as_u64()?;
+            let text = if key.ends_with("_bytes_per_sec") {
+                format.rate(count)
+            } else if key.ends_with("_bytes") || key.starts_with("bytes_") {
+                format.bytes(count)
+            } else if key.ends_with("_ms") {
+                format.duration_ms(count)
+            } else {
+                return None;
+            };
+            Some((key.clone(), Value::String(text)))
+        })
+        .collect();
+    (!fields.is_empty()).then_some(Value::Object(fields))
+}
+
 pub fn create_report_bundle_with_meta_signing_and_artifacts(
     base: impl AsRef<Path>,
     graph: &DeviceGraph,
@@ -63,25 +253,33 @@ pub fn create_report_bundle_with_meta_signing_and_artifacts(
     signing_key_hex: Option<&str>,
     artifacts: &[ReportArtifact],
 ) -> Result<ReportPaths> {
-    let run_id = Uuid::new_v4().to_string();
-    let root = base.as_ref().join("reports").join(&run_id);
+    let correlation_id = correlation::correlation_id();
+    let run_id = match &correlation_id {
+        Some(id) => format!("{}-{}", id, Uuid::new_v4()),
+        None => Uuid::new_v4().to_string(),
+    };
+    let reports_dir = base.as_ref().join("reports");
+    let root = reports_dir.join(&run_id);
     fs::create_dir_all(&root)?;
 
     let device_graph_json = root.join("device_graph.json");
     let run_json = root.join("run.json");
+    let environment_json = root.join("environment.json");
     let logs_path = root.join("logs.txt");
     let manifest_path = root.join("manifest.json");
     let mut signature_path = None;
 
     fs::write(&device_graph_json, serde_json::to_vec_pretty(graph)?)?;
 
-    let mut meta = serde_json::json!({
-        "run_id": run_id,
-        "schema_version": graph.schema_version,
-        "generated_at_utc": graph.generated_at_utc,
-        "host": graph.host,
-        "disk_count": graph.disks.len()
-    });
+    let mut meta = serde_json::to_value(RunMetadata {
+        run_id: run_id.clone(),
+        schema_version: graph.schema_version.clone(),
+        generated_at_utc: graph.generated_at_utc.clone(),
+        host: graph.host.clone(),
+        disk_count: graph.disks.len(),
+        correlation_id: correlation_id.clone(),
+        extra: serde_json::Map::new(),
+    })?;
     if let Some(extra) = extra_meta {
         match (&mut meta, extra) {
             (Value::Object(base), Value::Object(extra)) => {
@@ -93,25 +291,29 @@ pub fn create_report_bundle_with_meta_signing_and_artifacts(
             _ => {}
         }
     }
+    if let Some(display) = display_fields(&meta) {
+        meta["display"] = display;
+    }
+    redact::redact_value(&mut meta);
     fs::write(&run_json, serde_json::to_vec_pretty(&meta)?)?;
-    fs::write(&logs_path, logs.unwrap_or_default())?;
+    fs::write(
+        &environment_json,
+        serde_json::to_vec_pretty(&environment::capture_environment(graph))?,
+    )?;
+    fs::write(&logs_path, redact::redact_secrets(logs.unwrap_or_default()))?;
 
-    let mut artifact_paths = Vec::new();
+    let mut artifact_entries = Vec::new();
     for artifact in artifacts {
-        if artifact.name.contains('/') || artifact.name.contains('\\') {
-            return Err(anyhow!("artifact name must be a filename only"));
-        }
-        let path = root.join(&artifact.name);
-        fs::write(&path, &artifact.bytes)?;
-        artifact_paths.push(path);
+        artifact_entries.push(write_artifact(&root, artifact)?);
     }
 
     let manifest = build_manifest(
         &run_id,
         &device_graph_json,
         &run_json,
+        &environment_json,
         &logs_path,
-        &artifact_paths,
+        artifact_entries,
     )?;
     let manifest_bytes = serde_json::to_vec_pretty(&manifest)?;
     fs::write(&manifest_path, &manifest_bytes)?;
@@ -123,12 +325,21 @@ pub fn create_report_bundle_with_meta_signing_and_artifacts(
         fs::write(&sig_path, to_hex(&signature))?;
         signature_path = Some(sig_path);
     }
+    correlation::append_index(&reports_dir, &ReportIndexEntry::from_meta(run_id.clone(), &meta))?;
+    // Retention never fails the run that produced the report;
+    // `report-prune` shows what could not be pruned.
+    if let Ok(Some(policy)) = RetentionPolicy::load(base.as_ref()) {
+        if !policy.is_empty() {
+            let _ = prune_reports(base.as_ref(), &policy, Some(&run_id), false);
+        }
+    }
 
     Ok(ReportPaths {
         run_id,
         root,
         device_graph_json,
         run_json,
+        environment_json,
         logs_path,
         manifest_path,
         signature_path,
@@ -137,9 +348,26 @@ pub fn create_report_bundle_with_meta_signing_and_artifacts(
 
 #[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
 pub struct ManifestEntry {
-    path: String,
-    bytes: u64,
-    sha256: String,
+    pub path: String,
+    pub bytes: u64,
+    pub sha256: String,
+}
+
+/// The keys every `run.json` starts with. Workflows add their own
+/// (`workflow`, `status`, `timing`, ...) next to them, kept in `extra`.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS))]
+pub struct RunMetadata {
+    pub run_id: String,
+    /// Schema version of the device graph captured with the run.
+    pub schema_version: String,
+    pub generated_at_utc: String,
+    pub host: HostInfo,
+    pub disk_count: usize,
+    pub correlation_id: Option<String>,
+    #[serde(flatten)]
+    #[cfg_attr(feature = "ts", ts(flatten))]
+    pub extra: serde_json::Map<String, Value>,
 }
 
 pub const MANIFEST_SCHEMA_VERSION: &str = "1.0.0";
@@ -151,12 +379,22 @@ pub struct Manifest {
     entries: Vec<ManifestEntry>,
 }
 
-#[derive(Debug)]
+impl Manifest {
+    pub fn entries(&self) -> &[ManifestEntry] {
+        &self.entries
+    }
+}
+
+#[derive(Debug, serde::Serialize)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS))]
 pub struct ReportVerification {
     pub ok: bool,
     pub entries_checked: usize,
     pub mismatches: Vec<String>,
     pub signature_valid: Option<bool>,
+    /// Semantic checks on `run.json`, the artifacts and the device graph.
+    /// Any error finding fails the verification.
+    pub findings: Vec<ReportFinding>,
 }
 
 #[derive(Debug)]
@@ -170,28 +408,20 @@ fn build_manifest(
     run_id: &str,
     device_graph: &Path,
     run_json: &Path,
+    environment_json: &Path,
     logs: &Path,
-    artifacts: &[PathBuf],
+    artifacts: Vec<ManifestEntry>,
 ) -> Result<Manifest> {
     let mut entries = Vec::new();
-    for path in [device_graph, run_json, logs] {
-        let data = fs::read(path)?;
-        let hash = Sha256::digest(&data);
+    for path in [device_graph, run_json, environment_json, logs] {
+        let (sha256, bytes) = hash_file(path)?;
         entries.push(ManifestEntry {
             path: path.file_name().unwrap_or_default().to_string_lossy().to_string(),
-            bytes: data.len() as u64,
-            sha256: to_hex(&hash),
-        });
-    }
-    for path in artifacts {
-        let data = fs::read(path)?;
-        let hash = Sha256::digest(&data);
-        entries.push(ManifestEntry {
-            path: path.file_name().unwrap_or_default().to_string_lossy().to_string(),
-            bytes: data.len() as u64,
-            sha256: to_hex(&hash),
+            bytes,
+            sha256,
         });
     }
+    entries.extend(artifacts);
     Ok(Manifest {
         schema_version: MANIFEST_SCHEMA_VERSION.to_string(),
         run_id: run_id.to_string(),
@@ -199,9 +429,25 @@ fn build_manifest(
     })
 }
 
+fn hash_file(path: &Path) -> Result<(String, u64)> {
+    let mut file = fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; 1024 * 1024];
+    let mut total = 0u64;
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+        total += read as u64;
+    }
+    Ok((to_hex(&hasher.finalize()), total))
+}
+
 fn decode_hex(value: &str) -> Result<Vec<u8>> {
     let value = value.trim();
-    if value.len() % 2 != 0 {
+    if !value.len().is_multiple_of(2) {
         return Err(anyhow!("signing key hex must be even length"));
     }
     let raw = value.as_bytes();
@@ -231,12 +477,12 @@ fn hmac_sha256(key: &[u8], message: &[u8]) -> [u8; 32] {
     }
 
     let mut inner = Sha256::new();
-    inner.update(&i_key);
+    inner.update(i_key);
     inner.update(message);
     let inner_hash = inner.finalize();
 
     let mut outer = Sha256::new();
-    outer.update(&o_key);
+    outer.update(o_key);
     outer.update(inner_hash);
     let digest = outer.finalize();
 
@@ -280,13 +526,13 @@ pub fn verify_report_bundle(
             mismatches.push(format!("missing {}", entry.path));
             continue;
         }
-        let data = fs::read(&path)?;
-        let hash = Sha256::digest(&data);
-        let sha = to_hex(&hash);
+        let (sha, bytes) = hash_file(&path)?;
         if sha != entry.sha256 {
             mismatches.push(format!("hash mismatch {}", entry.path));
+        } else if entry.path.ends_with(ZSTD_SUFFIX) && encoding::check_zstd_file(&path).is_err() {
+            mismatches.push(format!("corrupt zstd {}", entry.path));
         }
-        if data.len() as u64 != entry.bytes {
+        if bytes != entry.bytes {
             mismatches.push(format!("size mismatch {}", entry.path));
         }
         entries_checked += 1;
@@ -303,12 +549,18 @@ pub fn verify_report_bundle(
         None
     };
 
-    let ok = mismatches.is_empty() && signature_valid.unwrap_or(true);
+    let findings = checks::check_bundle(root, &manifest);
+    let ok = mismatches.is_empty()
+        && signature_valid.unwrap_or(true)
+        && findings
+            .iter()
+            .all(|finding| finding.severity != FindingSeverity::Error);
     Ok(ReportVerification {
         ok,
         entries_checked,
         mismatches,
         signature_valid,
+        findings,
     })
 }
 
@@ -349,25 +601,84 @@ pub fn verify_report_tree(
     })
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ZipCompression {
+    Stored,
+    Deflated,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ZipExportOptions {
+    pub compression: ZipCompression,
+    pub level: Option<i64>,
+}
+
+impl Default for ZipExportOptions {
+    fn default() -> Self {
+        Self {
+            compression: ZipCompression::Deflated,
+            level: None,
+        }
+    }
+}
+
+impl ZipExportOptions {
+    fn file_options(&self) -> Result<FileOptions<'static, ()>> {
+        let method = match self.compression {
+            ZipCompression::Stored => CompressionMethod::Stored,
+            ZipCompression::Deflated => CompressionMethod::Deflated,
+        };
+        if let Some(level) = self.level {
+            if self.compression == ZipCompression::Stored {
+                return Err(anyhow!("compression level not supported for stored entries"));
+            }
+            if !(0..=9).contains(&level) {
+                return Err(anyhow!("compression level must be 0-9"));
+            }
+        }
+        Ok(FileOptions::default()
+            .compression_method(method)
+            .compression_level(self.level))
+    }
+}
+
 pub fn export_report_zip(
     report_root: impl AsRef<Path>,
     output_path: impl AsRef<Path>,
 ) -> Result<PathBuf> {
-    let root = report_root.as_ref();
+    export_report_zip_with_options(report_root, output_path, &ZipExportOptions::default())
+}
+
+pub fn export_report_zip_with_options(
+    report_root: impl AsRef<Path>,
+    output_path: impl AsRef<Path>,
+    export_options: &ZipExportOptions,
+) -> Result<PathBuf> {
     let output_path = output_path.as_ref().to_path_buf();
     let file = fs::File::create(&output_path)?;
-    let mut zip = ZipWriter::new(file);
-    let options = FileOptions::default();
-    add_dir_to_zip(root, root, &mut zip, options)?;
-    zip.finish()?;
+    write_report_zip(report_root, io::BufWriter::new(file), export_options)?.flush()?;
     Ok(output_path)
 }
 
-fn add_dir_to_zip(
+/// Zips the bundle at `report_root` into `writer` and returns the writer,
+/// for callers that own the output file.
+pub fn write_report_zip<W: Write + io::Seek>(
+    report_root: impl AsRef<Path>,
+    writer: W,
+    export_options: &ZipExportOptions,
+) -> Result<W> {
+    let root = report_root.as_ref();
+    let options = export_options.file_options()?;
+    let mut zip = ZipWriter::new(writer);
+    add_dir_to_zip(root, root, &mut zip, options)?;
+    Ok(zip.finish()?)
+}
+
+fn add_dir_to_zip<W: Write + io::Seek>(
     base: &Path,
     current: &Path,
-    zip: &mut ZipWriter<fs::File>,
-    options: FileOptions,
+    zip: &mut ZipWriter<W>,
+    options: FileOptions<'static, ()>,
 ) -> Result<()> {
     for entry in fs::read_dir(current)? {
         let entry = entry?;
@@ -381,9 +692,10 @@ fn add_dir_to_zip(
                 .unwrap_or(&path)
                 .to_string_lossy()
                 .replace('\\', "/");
-            zip.start_file(rel, options)?;
-            let data = fs::read(&path)?;
-            zip.write_all(&data)?;
+            let large = meta.len() >= u32::MAX as u64;
+            zip.start_file(rel, options.large_file(large))?;
+            let mut input = fs::File::open(&path)?;
+            io::copy(&mut input, zip)?;
         }
     }
     Ok(())
@@ -0,0 +1,157 @@
+//! Secret values resolved for a run (Wi-Fi PSKs, admin passwords) are
+//! registered here and replaced in everything a report bundle writes, so
+//! they never reach `logs.txt`, `run.json`, the artifacts or the manifest
+//! hashes of them.
+
+use serde_json::Value;
+use std::io::{self, Read, Write};
+use std::sync::Mutex;
+
+pub const REDACTED: &str = "<secret>";
+/// Read size for `copy_redacted`.
+const CHUNK: usize = 64 * 1024;
+
+/// Process-wide: workers of a parallel run share their secrets.
+static SECRETS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// Redacts `value` from every report written by this process from now on.
+pub fn register_secret(value: &str) {
+    if value.is_empty() {
+        return;
+    }
+    let Ok(mut secrets) = SECRETS.lock() else {
+        return;
+    };
+    if !secrets.iter().any(|known| known == value) {
+        secrets.push(value.to_string());
+        // Longest first, so a secret containing another is replaced whole.
+        secrets.sort_by_key(|secret| std::cmp::Reverse(secret.len()));
+    }
+}
+
+pub(crate) fn has_secrets() -> bool {
+    SECRETS.lock().is_ok_and(|secrets| !secrets.is_empty())
+}
+
+fn secrets() -> Vec<String> {
+    SECRETS.lock().map(|secrets| secrets.clone()).unwrap_or_default()
+}
+
+/// `text` with every registered secret replaced by `REDACTED`.
+pub fn redact_secrets(text: &str) -> String {
+    let mut text = text.to_string();
+    for secret in secrets() {
+        if text.contains(&secret) {
+            text = text.replace(&secret, REDACTED);
+        }
+    }
+    text
+}
+
+pub(crate) fn redact_value(value: &mut Value) {
+    match value {
+        Value::String(text) => *text = redact_secrets(text),
+        Value::Array(items) => items.iter_mut().for_each(redact_value),
+        Value::Object(map) => map.values_mut().for_each(redact_value),
+        _ => {}
+    }
+}
+
+fn secret_bytes() -> Vec<Vec<u8>> {
+    secrets().into_iter().map(String::into_bytes).collect()
+}
+
+/// Byte-level replacement for artifacts, which need not be UTF-8.
+pub(crate) fn redact_bytes(bytes: &[u8]) -> Option<Vec<u8>> {
+    let secrets = secret_bytes();
+    if !secrets
+        .iter()
+        .any(|secret| bytes.windows(secret.len()).any(|window| window == secret))
+    {
+        return None;
+    }
+    let mut out = Vec::with_capacity(bytes.len());
+    redact_prefix(bytes, &secrets, bytes.len(), &mut out).ok()?;
+    Some(out)
+}
+
+/// Copies `input` to `output` with every registered secret replaced,
+/// holding one chunk in memory. The last `max_secret_len - 1` bytes of each
+/// chunk are scanned again with the next one, so a secret split across two
+/// reads is still caught.
+pub(crate) fn copy_redacted(input: &mut impl Read, output: &mut impl Write) -> io::Result<()> {
+    let secrets = secret_bytes();
+    let overlap = secrets.iter().map(Vec::len).max().unwrap_or(1) - 1;
+    let mut pending = Vec::with_capacity(CHUNK + overlap);
+    let mut chunk = vec![0u8; CHUNK];
+    loop {
+        let read = match input.read(&mut chunk) {
+            Ok(read) => read,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+        pending.extend_from_slice(&chunk[..read]);
+        let limit = if read == 0 {
+            pending.len()
+        } else {
+            pending.len().saturating_sub(overlap)
+        };
+        let consumed = redact_prefix(&pending, &secrets, limit, output)?;
+        pending.drain(..consumed);
+        if read == 0 {
+            return Ok(());
+        }
+    }
+}
+
+/// Writes `buffer` with secrets replaced, checking matches that start
+/// before `limit`. Returns how many bytes were consumed, which is past
+/// `limit` when a match runs over it. `secrets` is longest first.
+fn redact_prefix(
+    buffer: &[u8],
+    secrets: &[Vec<u8>],
+    limit: usize,
+    output: &mut impl Write,
+) -> io::Result<usize> {
+    let mut plain = 0;
+    let mut at = 0;
+    while at < limit {
+        match secrets.iter().find(|secret| buffer[at..].starts_with(secret)) {
+            Some(secret) => {
+                output.write_all(&buffer[plain..at])?;
+                output.write_all(REDACTED.as_bytes())?;
+                at += secret.len();
+                plain = at;
+            }
+            None => at += 1,
+        }
+    }
+    output.write_all(&buffer[plain..at])?;
+    Ok(at)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn secrets_split_across_chunks_are_redacted() {
+        let secret = "redact-test-9f3c1a77e2";
+        register_secret(secret);
+        for offset in [CHUNK - 5, CHUNK - 1, CHUNK, 2 * CHUNK - secret.len()] {
+            let mut input = vec![b'x'; 3 * CHUNK];
+            input[offset..offset + secret.len()].copy_from_slice(secret.as_bytes());
+            input.extend_from_slice(secret.as_bytes());
+            let mut output = Vec::new();
+            copy_redacted(&mut input.as_slice(), &mut output).unwrap();
+            assert!(!output.windows(secret.len()).any(|window| window == secret.as_bytes()));
+            assert_eq!(
+                output.len(),
+                input.len() - 2 * (secret.len() - REDACTED.len())
+            );
+            assert_eq!(redact_bytes(&input), Some(output));
+        }
+        assert_eq!(redact_bytes(b"nothing here"), None);
+        assert_eq!(redact_secrets(&format!("a {} b", secret)), "a <secret> b");
+    }
+}
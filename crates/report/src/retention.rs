@@ -0,0 +1,186 @@
+//! Retention for a report base, so unattended machines do not fill their
+//! disks with bundles. The policy lives in `<base>/retention.json` and is
+//! applied after every new bundle; `prune_reports` applies it on demand.
+
+use crate::export_report_zip;
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+pub const RETENTION_POLICY_FILE: &str = "retention.json";
+
+/// ```json
+/// { "max_bundles": 500, "max_age_days": 90, "max_total_bytes": 2147483648,
+///   "archive": "https://reports.example/upload" }
+/// ```
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RetentionPolicy {
+    #[serde(default)]
+    pub max_bundles: Option<usize>,
+    #[serde(default)]
+    pub max_age_days: Option<u64>,
+    #[serde(default)]
+    pub max_total_bytes: Option<u64>,
+    /// Directory or http(s) URL that receives `<run_id>.zip` before a
+    /// bundle is deleted. A bundle whose archive fails is kept.
+    #[serde(default)]
+    pub archive: Option<String>,
+}
+
+impl RetentionPolicy {
+    /// `<base>/retention.json`; `None` when the base has no policy.
+    pub fn load(base: impl AsRef<Path>) -> Result<Option<Self>> {
+        let path = base.as_ref().join(RETENTION_POLICY_FILE);
+        let bytes = match fs::read(&path) {
+            Ok(bytes) => bytes,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(anyhow!("read {} failed: {}", path.display(), err)),
+        };
+        let policy: Self =
+            serde_json::from_slice(&bytes).with_context(|| format!("parse {}", path.display()))?;
+        Ok(Some(policy))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.max_bundles.is_none() && self.max_age_days.is_none() && self.max_total_bytes.is_none()
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct PruneResult {
+    pub kept: usize,
+    pub kept_bytes: u64,
+    /// Bundle directories deleted (or, in a dry run, that would be).
+    pub pruned: Vec<String>,
+    pub pruned_bytes: u64,
+    /// Archive locations written before deleting.
+    pub archived: Vec<String>,
+    /// Bundles kept because archiving or deleting them failed.
+    pub errors: Vec<String>,
+}
+
+struct Bundle {
+    path: PathBuf,
+    modified: SystemTime,
+    bytes: u64,
+}
+
+/// Applies `policy` to the bundles in `<base>/reports`, oldest first.
+/// `keep` (a run id) is never pruned, so the bundle just written survives
+/// even a policy that is smaller than it.
+pub fn prune_reports(
+    base: impl AsRef<Path>,
+    policy: &RetentionPolicy,
+    keep: Option<&str>,
+    dry_run: bool,
+) -> Result<PruneResult> {
+    let reports_dir = base.as_ref().join("reports");
+    let mut bundles = Vec::new();
+    if reports_dir.is_dir() {
+        for entry in fs::read_dir(&reports_dir)? {
+            let path = entry?.path();
+            let Ok(manifest) = fs::metadata(path.join("manifest.json")) else {
+                continue;
+            };
+            bundles.push(Bundle {
+                modified: manifest.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+                bytes: dir_bytes(&path),
+                path,
+            });
+        }
+    }
+    bundles.sort_by_key(|bundle| bundle.modified);
+
+    let mut result = PruneResult::default();
+    let mut remaining = bundles.len();
+    let mut total_bytes: u64 = bundles.iter().map(|bundle| bundle.bytes).sum();
+    let cutoff = policy
+        .max_age_days
+        .and_then(|days| SystemTime::now().checked_sub(Duration::from_secs(days * 24 * 60 * 60)));
+    for bundle in &bundles {
+        let name = bundle.path.file_name().unwrap_or_default().to_string_lossy();
+        if keep == Some(name.as_ref()) {
+            continue;
+        }
+        let over_count = policy.max_bundles.is_some_and(|max| remaining > max);
+        let too_old = cutoff.is_some_and(|cutoff| bundle.modified < cutoff);
+        let over_size = policy.max_total_bytes.is_some_and(|max| total_bytes > max);
+        if !(over_count || too_old || over_size) {
+            continue;
+        }
+        if !dry_run {
+            if let Some(archive) = &policy.archive {
+                match archive_bundle(&bundle.path, archive) {
+                    Ok(location) => result.archived.push(location),
+                    Err(err) => {
+                        result.errors.push(format!("{}: archive failed: {:#}", name, err));
+                        continue;
+                    }
+                }
+            }
+            if let Err(err) = fs::remove_dir_all(&bundle.path) {
+                result.errors.push(format!("{}: delete failed: {}", name, err));
+                continue;
+            }
+        }
+        remaining -= 1;
+        total_bytes -= bundle.bytes;
+        result.pruned.push(bundle.path.display().to_string());
+        result.pruned_bytes += bundle.bytes;
+    }
+    result.kept = remaining;
+    result.kept_bytes = total_bytes;
+    Ok(result)
+}
+
+/// Zips the bundle into a directory, or PUTs the zip to an http(s) URL.
+fn archive_bundle(bundle: &Path, archive: &str) -> Result<String> {
+    let name = format!(
+        "{}.zip",
+        bundle.file_name().unwrap_or_default().to_string_lossy()
+    );
+    if !phoenix_fetch::is_url(archive) {
+        let dir = Path::new(archive);
+        fs::create_dir_all(dir).with_context(|| format!("create {}", dir.display()))?;
+        let out = dir.join(&name);
+        export_report_zip(bundle, &out)?;
+        return Ok(out.display().to_string());
+    }
+
+    let zip = std::env::temp_dir().join(format!("phoenix-archive-{}-{}", std::process::id(), name));
+    if let Err(err) = export_report_zip(bundle, &zip) {
+        fs::remove_file(&zip).ok();
+        return Err(err);
+    }
+    let url = format!("{}/{}", archive.trim_end_matches('/'), name);
+    let uploaded = fs::File::open(&zip)
+        .map_err(anyhow::Error::from)
+        .and_then(|file| {
+            phoenix_fetch::agent_builder(&url)?
+                .build()
+                .put(&url)
+                .set("Content-Type", "application/zip")
+                .send(file)
+                .map_err(|err| anyhow!("upload failed: {}", err))
+        });
+    fs::remove_file(&zip).ok();
+    uploaded?;
+    Ok(url)
+}
+
+fn dir_bytes(dir: &Path) -> u64 {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return 0;
+    };
+    entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| match entry.metadata() {
+            Ok(meta) if meta.is_dir() => dir_bytes(&entry.path()),
+            Ok(meta) => meta.len(),
+            Err(_) => 0,
+        })
+        .sum()
+}
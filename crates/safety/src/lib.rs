@@ -1,22 +1,132 @@
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use uuid::Uuid;
 
+/// Environment switch for read-only mode; `1`, `true` or `yes` enables it.
+pub const READONLY_ENV: &str = "PHOENIX_READONLY";
+
+static READ_ONLY: AtomicBool = AtomicBool::new(false);
+
+/// Built with the `read-only` feature: read-only mode cannot be turned off.
+pub const READ_ONLY_BUILD: bool = cfg!(feature = "read-only");
+
+/// Unix time after which force approvals stop working.
+pub const ARMED_UNTIL_ENV: &str = "PHOENIX_ARMED_UNTIL";
+
+/// 0 means not armed by the process.
+static ARMED_UNTIL: AtomicU64 = AtomicU64::new(0);
+
+/// Token class required, on top of policy opt-in, to write a system disk.
+pub const SYSTEM_TOKEN_PREFIX: &str = "PHX-SYS-";
+
 #[derive(Debug, Clone)]
 pub struct SafetyContext {
     pub force_mode: bool,
     pub confirmation_token: Option<String>,
+    /// Policy opt-in for system-disk targets. Without it they are denied
+    /// whatever the token.
+    pub allow_system_disk: bool,
+    /// Unix time after which this approval is void; `None` never expires.
+    pub armed_until: Option<u64>,
 }
 
 #[derive(Debug, Clone)]
 pub enum SafetyDecision {
     Allow,
     Deny(String),
+    /// Read-only mode is on; force and token are not consulted.
+    ReadOnly(ReadOnlyError),
+}
+
+/// Returned by every destructive path while read-only mode is on.
+#[derive(Debug, Clone)]
+pub struct ReadOnlyError {
+    pub operation: String,
+}
+
+impl fmt::Display for ReadOnlyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "read-only mode: {} is disabled", self.operation)
+    }
+}
+
+impl std::error::Error for ReadOnlyError {}
+
+/// Turns read-only mode on for the rest of the process. It cannot be turned
+/// off again once set, nor when enabled by the environment or the
+/// `read-only` build feature.
+pub fn set_read_only(enabled: bool) {
+    if enabled {
+        READ_ONLY.store(true, Ordering::SeqCst);
+    }
+}
+
+pub fn is_read_only() -> bool {
+    if READ_ONLY_BUILD || READ_ONLY.load(Ordering::SeqCst) {
+        return true;
+    }
+    std::env::var(READONLY_ENV).is_ok_and(|value| {
+        matches!(
+            value.trim().to_ascii_lowercase().as_str(),
+            "1" | "true" | "yes"
+        )
+    })
+}
+
+/// Guard for low-level writers, so a caller that skips `can_write_to_disk`
+/// still cannot write in read-only mode.
+pub fn ensure_writable(operation: &str) -> Result<(), ReadOnlyError> {
+    if is_read_only() {
+        return Err(ReadOnlyError {
+            operation: operation.to_string(),
+        });
+    }
+    Ok(())
+}
+
+/// Arms the process for `duration` from now and returns the expiry.
+pub fn arm_for(duration: Duration) -> u64 {
+    let until = unix_now().saturating_add(duration.as_secs());
+    ARMED_UNTIL.store(until, Ordering::SeqCst);
+    until
+}
+
+/// The earlier of `arm_for` and `PHOENIX_ARMED_UNTIL`. An unparsable
+/// environment value counts as already expired.
+pub fn armed_until() -> Option<u64> {
+    let process = match ARMED_UNTIL.load(Ordering::SeqCst) {
+        0 => None,
+        until => Some(until),
+    };
+    let env = std::env::var(ARMED_UNTIL_ENV)
+        .ok()
+        .map(|value| value.trim().parse().unwrap_or(0));
+    match (process, env) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0)
 }
 
 pub fn require_confirmation_token() -> String {
     format!("PHX-{}", Uuid::new_v4())
 }
 
+pub fn require_system_confirmation_token() -> String {
+    format!("{}{}", SYSTEM_TOKEN_PREFIX, Uuid::new_v4())
+}
+
 pub fn can_write_to_disk(ctx: &SafetyContext, is_system_disk: bool) -> SafetyDecision {
+    if let Err(err) = ensure_writable("disk write") {
+        return SafetyDecision::ReadOnly(err);
+    }
     if !ctx.force_mode {
         return SafetyDecision::Deny("Denied: destructive ops require force-mode".to_string());
     }
@@ -27,14 +137,224 @@ pub fn can_write_to_disk(ctx: &SafetyContext, is_system_disk: bool) -> SafetyDec
     if !token.starts_with("PHX-") {
         return SafetyDecision::Deny("Denied: invalid confirmation token".to_string());
     }
+    if let Some(until) = ctx.armed_until {
+        if unix_now() > until {
+            return SafetyDecision::Deny(format!(
+                "Denied: force approval expired (armed until unix time {})",
+                until
+            ));
+        }
+    }
 
     if is_system_disk {
-        return SafetyDecision::Allow;
+        if !ctx.allow_system_disk {
+            return SafetyDecision::Deny(
+                "Denied: system-disk writes are not enabled by policy".to_string(),
+            );
+        }
+        if !token.starts_with(SYSTEM_TOKEN_PREFIX) {
+            return SafetyDecision::Deny(format!(
+                "Denied: system-disk writes require a {} token",
+                SYSTEM_TOKEN_PREFIX
+            ));
+        }
     }
 
     SafetyDecision::Allow
 }
 
+/// Overrides `TargetSizeLimits::min_bytes`, in bytes.
+pub const TARGET_MIN_BYTES_ENV: &str = "PHOENIX_TARGET_MIN_BYTES";
+/// Overrides `TargetSizeLimits::max_bytes`, in bytes.
+pub const TARGET_MAX_BYTES_ENV: &str = "PHOENIX_TARGET_MAX_BYTES";
+
+/// Plausible size range for removable installer targets. A disk outside it
+/// (a card reader with no card, a 4 TB backup drive) is almost certainly
+/// the wrong one.
+#[derive(Debug, Clone, Copy)]
+pub struct TargetSizeLimits {
+    pub min_bytes: u64,
+    pub max_bytes: u64,
+}
+
+impl Default for TargetSizeLimits {
+    fn default() -> Self {
+        Self {
+            min_bytes: 256 * 1024 * 1024,
+            max_bytes: 1024 * 1024 * 1024 * 1024,
+        }
+    }
+}
+
+impl TargetSizeLimits {
+    /// Defaults, with `PHOENIX_TARGET_MIN_BYTES` / `PHOENIX_TARGET_MAX_BYTES`
+    /// applied when set.
+    pub fn from_env() -> Result<Self, String> {
+        let mut limits = Self::default();
+        for (var, slot) in [
+            (TARGET_MIN_BYTES_ENV, &mut limits.min_bytes),
+            (TARGET_MAX_BYTES_ENV, &mut limits.max_bytes),
+        ] {
+            if let Ok(value) = std::env::var(var) {
+                *slot = value
+                    .trim()
+                    .parse()
+                    .map_err(|_| format!("{} must be a byte count, got {}", var, value))?;
+            }
+        }
+        Ok(limits)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum TargetSizeDecision {
+    Allow,
+    /// Outside the limits, but acknowledged for this run. The reason
+    /// belongs in the report.
+    Acknowledged(String),
+    Deny(String),
+}
+
+/// Checks a target disk against `limits`. `acknowledged` is the per-run
+/// override; it turns a denial into `Acknowledged`.
+pub fn check_target_size(
+    target_bytes: u64,
+    limits: &TargetSizeLimits,
+    acknowledged: bool,
+) -> TargetSizeDecision {
+    let reason = if target_bytes < limits.min_bytes {
+        format!(
+            "target is {} bytes, below the {} byte minimum",
+            target_bytes, limits.min_bytes
+        )
+    } else if target_bytes > limits.max_bytes {
+        format!(
+            "target is {} bytes, above the {} byte maximum",
+            target_bytes, limits.max_bytes
+        )
+    } else {
+        return TargetSizeDecision::Allow;
+    };
+    if acknowledged {
+        TargetSizeDecision::Acknowledged(reason)
+    } else {
+        TargetSizeDecision::Deny(format!("Denied: {}", reason))
+    }
+}
+
+/// Flash count at which a device is worn out.
+pub const MAX_FLASH_COUNT_ENV: &str = "PHOENIX_MAX_FLASH_COUNT";
+/// How far, in percent, the last write may fall below the device's best
+/// before it is worn out.
+pub const MAX_THROUGHPUT_DROP_ENV: &str = "PHOENIX_MAX_THROUGHPUT_DROP_PERCENT";
+/// `deny` (default) or `warn`: what a worn-out device does to a run.
+pub const WEAR_ACTION_ENV: &str = "PHOENIX_WEAR_ACTION";
+
+/// Retirement policy for sticks that have been flashed too often or have
+/// slowed down. Both limits are off unless configured.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WearLimits {
+    pub max_flash_count: Option<u32>,
+    pub max_throughput_drop_percent: Option<u32>,
+    /// Let worn-out devices through with a warning instead of denying.
+    pub warn_only: bool,
+}
+
+impl WearLimits {
+    /// From `PHOENIX_MAX_FLASH_COUNT`, `PHOENIX_MAX_THROUGHPUT_DROP_PERCENT`
+    /// and `PHOENIX_WEAR_ACTION`.
+    pub fn from_env() -> Result<Self, String> {
+        let mut limits = Self::default();
+        if let Ok(value) = std::env::var(MAX_FLASH_COUNT_ENV) {
+            limits.max_flash_count = Some(value.trim().parse().map_err(|_| {
+                format!("{} must be a count, got {}", MAX_FLASH_COUNT_ENV, value)
+            })?);
+        }
+        if let Ok(value) = std::env::var(MAX_THROUGHPUT_DROP_ENV) {
+            let percent: u32 = value.trim().parse().map_err(|_| {
+                format!("{} must be a percentage, got {}", MAX_THROUGHPUT_DROP_ENV, value)
+            })?;
+            if percent > 100 {
+                return Err(format!("{} must be at most 100, got {}", MAX_THROUGHPUT_DROP_ENV, percent));
+            }
+            limits.max_throughput_drop_percent = Some(percent);
+        }
+        if let Ok(value) = std::env::var(WEAR_ACTION_ENV) {
+            limits.warn_only = match value.trim().to_ascii_lowercase().as_str() {
+                "deny" => false,
+                "warn" => true,
+                _ => return Err(format!("{} must be deny or warn, got {}", WEAR_ACTION_ENV, value)),
+            };
+        }
+        Ok(limits)
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.max_flash_count.is_some() || self.max_throughput_drop_percent.is_some()
+    }
+}
+
+/// What the device registry knows about a target's wear.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DeviceWear {
+    pub flash_count: u32,
+    pub best_write_bytes_per_sec: Option<u64>,
+    pub last_write_bytes_per_sec: Option<u64>,
+}
+
+#[derive(Debug, Clone)]
+pub enum WearDecision {
+    Allow,
+    /// Worn out, but policy only warns. The reason belongs in the report.
+    Warn(String),
+    /// Worn out, but acknowledged for this run. The reason belongs in the
+    /// report and the audit log.
+    Acknowledged(String),
+    Deny(String),
+}
+
+/// Checks a device's wear against `limits`. `acknowledged` is the per-run
+/// override; it turns a denial into `Acknowledged`.
+pub fn check_device_wear(wear: &DeviceWear, limits: &WearLimits, acknowledged: bool) -> WearDecision {
+    let mut reasons = Vec::new();
+    if let Some(max) = limits.max_flash_count {
+        if wear.flash_count >= max {
+            reasons.push(format!(
+                "flashed {} times, at or above the {} flash limit",
+                wear.flash_count, max
+            ));
+        }
+    }
+    if let (Some(max_drop), Some(best), Some(last)) = (
+        limits.max_throughput_drop_percent,
+        wear.best_write_bytes_per_sec,
+        wear.last_write_bytes_per_sec,
+    ) {
+        let drop = best
+            .saturating_sub(last)
+            .saturating_mul(100)
+            .checked_div(best)
+            .unwrap_or(0);
+        if drop > u64::from(max_drop) {
+            reasons.push(format!(
+                "last write ran at {} bytes/s, {}% below its best of {} bytes/s (limit {}%)",
+                last, drop, best, max_drop
+            ));
+        }
+    }
+    if reasons.is_empty() {
+        return WearDecision::Allow;
+    }
+    let reason = format!("device is worn out: {}", reasons.join("; "));
+    if acknowledged {
+        WearDecision::Acknowledged(reason)
+    } else if limits.warn_only {
+        WearDecision::Warn(reason)
+    } else {
+        WearDecision::Deny(format!("Denied: {}", reason))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -44,6 +364,8 @@ mod tests {
         let ctx = SafetyContext {
             force_mode: false,
             confirmation_token: None,
+            allow_system_disk: false,
+            armed_until: None,
         };
         assert!(matches!(
             can_write_to_disk(&ctx, false),
@@ -56,6 +378,8 @@ mod tests {
         let ctx = SafetyContext {
             force_mode: true,
             confirmation_token: None,
+            allow_system_disk: false,
+            armed_until: None,
         };
         assert!(matches!(
             can_write_to_disk(&ctx, false),
@@ -68,6 +392,8 @@ mod tests {
         let ctx = SafetyContext {
             force_mode: true,
             confirmation_token: Some("BAD".to_string()),
+            allow_system_disk: false,
+            armed_until: None,
         };
         assert!(matches!(
             can_write_to_disk(&ctx, false),
@@ -80,10 +406,106 @@ mod tests {
         let ctx = SafetyContext {
             force_mode: true,
             confirmation_token: Some("PHX-123".to_string()),
+            allow_system_disk: false,
+            armed_until: None,
         };
         assert!(matches!(
             can_write_to_disk(&ctx, false),
             SafetyDecision::Allow
         ));
     }
+
+    #[test]
+    fn system_disk_needs_policy_and_system_token() {
+        let mut ctx = SafetyContext {
+            force_mode: true,
+            confirmation_token: Some("PHX-123".to_string()),
+            allow_system_disk: false,
+            armed_until: None,
+        };
+        assert!(matches!(
+            can_write_to_disk(&ctx, true),
+            SafetyDecision::Deny(_)
+        ));
+        ctx.allow_system_disk = true;
+        assert!(matches!(
+            can_write_to_disk(&ctx, true),
+            SafetyDecision::Deny(_)
+        ));
+        ctx.confirmation_token = Some("PHX-SYS-123".to_string());
+        assert!(matches!(
+            can_write_to_disk(&ctx, true),
+            SafetyDecision::Allow
+        ));
+    }
+
+    #[test]
+    fn target_size_limits() {
+        let limits = TargetSizeLimits::default();
+        assert!(matches!(
+            check_target_size(16 << 30, &limits, false),
+            TargetSizeDecision::Allow
+        ));
+        assert!(matches!(
+            check_target_size(4 << 40, &limits, false),
+            TargetSizeDecision::Deny(_)
+        ));
+        assert!(matches!(
+            check_target_size(4 << 40, &limits, true),
+            TargetSizeDecision::Acknowledged(_)
+        ));
+        assert!(matches!(
+            check_target_size(0, &limits, false),
+            TargetSizeDecision::Deny(_)
+        ));
+    }
+
+    #[test]
+    fn wear_limits() {
+        let mut limits = WearLimits {
+            max_flash_count: Some(400),
+            max_throughput_drop_percent: Some(50),
+            warn_only: false,
+        };
+        let mut wear = DeviceWear {
+            flash_count: 12,
+            best_write_bytes_per_sec: Some(40_000_000),
+            last_write_bytes_per_sec: Some(30_000_000),
+        };
+        assert!(matches!(check_device_wear(&wear, &limits, false), WearDecision::Allow));
+        wear.last_write_bytes_per_sec = Some(10_000_000);
+        assert!(matches!(check_device_wear(&wear, &limits, false), WearDecision::Deny(_)));
+        wear.last_write_bytes_per_sec = Some(30_000_000);
+        wear.flash_count = 412;
+        assert!(matches!(check_device_wear(&wear, &limits, false), WearDecision::Deny(_)));
+        assert!(matches!(
+            check_device_wear(&wear, &limits, true),
+            WearDecision::Acknowledged(_)
+        ));
+        limits.warn_only = true;
+        assert!(matches!(check_device_wear(&wear, &limits, false), WearDecision::Warn(_)));
+        assert!(matches!(
+            check_device_wear(&wear, &WearLimits::default(), false),
+            WearDecision::Allow
+        ));
+    }
+
+    #[test]
+    fn denies_after_arming_expires() {
+        let mut ctx = SafetyContext {
+            force_mode: true,
+            confirmation_token: Some("PHX-123".to_string()),
+            allow_system_disk: false,
+            armed_until: Some(unix_now() - 1),
+        };
+        assert!(matches!(
+            can_write_to_disk(&ctx, false),
+            SafetyDecision::Deny(_)
+        ));
+        ctx.armed_until = Some(unix_now() + 60);
+        assert!(matches!(
+            can_write_to_disk(&ctx, false),
+            SafetyDecision::Allow
+        ));
+    }
 }
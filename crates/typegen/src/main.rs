@@ -0,0 +1,132 @@
+//! Writes the TypeScript definitions of the JSON the engine produces and
+//! takes (device graph and history, `run.json`, progress events, workflow definitions,
+//! report verification, run history, media labels, wizards, effective configuration) for the Node addon and the Tauri frontend, so
+//! neither keeps its own copy of the interfaces.
+//!
+//! ```text
+//! cargo run -p phoenix-typegen [-- --check] [out_dir]
+//! ```
+//!
+//! `out_dir` defaults to `crates/node/types`. `--check` writes nothing and
+//! fails when the files there are stale.
+
+use anyhow::{anyhow, Context, Result};
+use phoenix_content::MediaManifest;
+use phoenix_core::{DeviceGraph, WorkflowDefinition};
+use phoenix_report::{ReportIndexEntry, ReportVerification, RunDetails, RunFilter, RunMetadata};
+use phoenix_workflow_engine::{
+    DeviceRecord, EffectiveConfig, ProgressEvent, WizardQuestion, WizardSession,
+};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use ts_rs::TS;
+
+const INDEX_FILE: &str = "index.ts";
+
+fn main() -> Result<()> {
+    let mut check = false;
+    let mut out_dir = None;
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "--check" => check = true,
+            flag if flag.starts_with('-') => return Err(anyhow!("unknown flag {}", flag)),
+            dir => out_dir = Some(PathBuf::from(dir)),
+        }
+    }
+    let out_dir = out_dir.unwrap_or_else(|| {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .with_file_name("node")
+            .join("types")
+    });
+
+    if !check {
+        if out_dir.exists() {
+            fs::remove_dir_all(&out_dir)
+                .with_context(|| format!("clear {}", out_dir.display()))?;
+        }
+        export(&out_dir)?;
+        println!("wrote {}", out_dir.display());
+        return Ok(());
+    }
+
+    let fresh = std::env::temp_dir().join(format!("phoenix-typegen-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&fresh);
+    export(&fresh)?;
+    let expected = read_tree(&fresh)?;
+    let _ = fs::remove_dir_all(&fresh);
+    let found = if out_dir.exists() {
+        read_tree(&out_dir)?
+    } else {
+        BTreeMap::new()
+    };
+    let stale: Vec<&String> = expected
+        .keys()
+        .chain(found.keys())
+        .filter(|name| expected.get(*name) != found.get(*name))
+        .collect::<std::collections::BTreeSet<_>>()
+        .into_iter()
+        .collect();
+    if !stale.is_empty() {
+        return Err(anyhow!(
+            "{} is stale ({}); run cargo run -p phoenix-typegen",
+            out_dir.display(),
+            stale.iter().map(|name| name.as_str()).collect::<Vec<_>>().join(", ")
+        ));
+    }
+    println!("{} is up to date", out_dir.display());
+    Ok(())
+}
+
+/// Writes every exported type, the types they use and an index
+/// re-exporting the top-level files.
+fn export(dir: &Path) -> Result<()> {
+    DeviceGraph::export_all_to(dir)?;
+    DeviceRecord::export_all_to(dir)?;
+    WorkflowDefinition::export_all_to(dir)?;
+    RunMetadata::export_all_to(dir)?;
+    ProgressEvent::export_all_to(dir)?;
+    ReportVerification::export_all_to(dir)?;
+    ReportIndexEntry::export_all_to(dir)?;
+    RunFilter::export_all_to(dir)?;
+    RunDetails::export_all_to(dir)?;
+    MediaManifest::export_all_to(dir)?;
+    WizardSession::export_all_to(dir)?;
+    WizardQuestion::export_all_to(dir)?;
+    EffectiveConfig::export_all_to(dir)?;
+
+    let mut index = String::from("// Generated by phoenix-typegen. Do not edit.\n");
+    for name in read_tree(dir)?.keys() {
+        if let Some(module) = name.strip_suffix(".ts") {
+            if !module.contains('/') {
+                index.push_str(&format!("export type {{ {0} }} from \"./{0}\";\n", module));
+            }
+        }
+    }
+    fs::write(dir.join(INDEX_FILE), index)?;
+    Ok(())
+}
+
+/// Contents of every file under `dir`, keyed by `/`-separated relative
+/// path.
+fn read_tree(dir: &Path) -> Result<BTreeMap<String, String>> {
+    let mut files = BTreeMap::new();
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(current) = pending.pop() {
+        for entry in fs::read_dir(&current).with_context(|| format!("read {}", current.display()))? {
+            let path = entry?.path();
+            if path.is_dir() {
+                pending.push(path);
+                continue;
+            }
+            let relative = path
+                .strip_prefix(dir)?
+                .components()
+                .map(|part| part.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            files.insert(relative, fs::read_to_string(&path)?);
+        }
+    }
+    Ok(files)
+}
@@ -0,0 +1,393 @@
+use anyhow::{anyhow, Context, Result};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+
+const TIMEOUT: Duration = Duration::from_secs(60);
+/// Upper bound for a downloaded binary, so a bad manifest cannot fill the disk.
+const MAX_ARTIFACT_BYTES: u64 = 512 * 1024 * 1024;
+
+/// Release manifest published next to the binaries:
+///
+/// ```json
+/// { "version": "0.2.0",
+///   "artifacts": [ { "platform": "linux-x86_64", "url": "phoenix-cli-linux-x86_64",
+///                    "size": 12345, "sha256": "...", "signature": "..." } ] }
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReleaseManifest {
+    pub version: String,
+    pub artifacts: Vec<ReleaseArtifact>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReleaseArtifact {
+    /// `<os>-<arch>` as in `std::env::consts`, e.g. `windows-x86_64`.
+    pub platform: String,
+    /// Absolute URL or path, or relative to the manifest location.
+    pub url: String,
+    pub size: u64,
+    pub sha256: String,
+    /// Hex ed25519 signature over [`release_statement`] for this artifact.
+    pub signature: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct UpdateCheck {
+    pub current_version: String,
+    pub latest_version: String,
+    pub artifact: Option<ReleaseArtifact>,
+}
+
+impl UpdateCheck {
+    pub fn update_available(&self) -> bool {
+        self.artifact.is_some()
+            && compare_versions(&self.latest_version, &self.current_version).is_gt()
+    }
+
+    pub fn is_downgrade(&self) -> bool {
+        compare_versions(&self.latest_version, &self.current_version).is_lt()
+    }
+}
+
+pub fn current_platform() -> String {
+    format!("{}-{}", std::env::consts::OS, std::env::consts::ARCH)
+}
+
+/// Reads the manifest from an http(s) URL or a local path.
+pub fn fetch_manifest(source: &str) -> Result<ReleaseManifest> {
+    let bytes = fetch(source, 16 * 1024 * 1024)?;
+    serde_json::from_slice(&bytes).with_context(|| format!("parse release manifest {}", source))
+}
+
+pub fn check_update(manifest: &ReleaseManifest, current_version: &str) -> UpdateCheck {
+    let platform = current_platform();
+    UpdateCheck {
+        current_version: current_version.to_string(),
+        latest_version: manifest.version.clone(),
+        artifact: manifest
+            .artifacts
+            .iter()
+            .find(|artifact| artifact.platform == platform)
+            .cloned(),
+    }
+}
+
+/// Downloads `artifact` and checks size, SHA-256 and the ed25519 signature
+/// against `public_key` before returning the bytes.
+pub fn download_artifact(
+    manifest_source: &str,
+    version: &str,
+    artifact: &ReleaseArtifact,
+    public_key: &[u8; 32],
+) -> Result<Vec<u8>> {
+    if artifact.size > MAX_ARTIFACT_BYTES {
+        return Err(anyhow!("artifact too large: {} bytes", artifact.size));
+    }
+    let url = resolve_url(manifest_source, &artifact.url);
+    let bytes = fetch(&url, artifact.size + 1)?;
+    verify_artifact(&bytes, version, artifact, public_key)?;
+    Ok(bytes)
+}
+
+/// The signed text for one artifact. Covering the version and platform
+/// stops an old or foreign build from being replayed under a new manifest.
+pub fn release_statement(version: &str, platform: &str, sha256: &str) -> String {
+    format!(
+        "phoenix-release-v1\n{}\n{}\n{}\n",
+        version.trim(),
+        platform.trim(),
+        sha256.trim().to_ascii_lowercase()
+    )
+}
+
+pub fn verify_artifact(
+    bytes: &[u8],
+    version: &str,
+    artifact: &ReleaseArtifact,
+    public_key: &[u8; 32],
+) -> Result<()> {
+    if bytes.len() as u64 != artifact.size {
+        return Err(anyhow!(
+            "artifact size {} does not match manifest {}",
+            bytes.len(),
+            artifact.size
+        ));
+    }
+    let digest = sha256_hex(bytes);
+    if !digest.eq_ignore_ascii_case(artifact.sha256.trim()) {
+        return Err(anyhow!("artifact sha256 mismatch"));
+    }
+    let key = VerifyingKey::from_bytes(public_key).map_err(|_| anyhow!("invalid public key"))?;
+    let signature: [u8; 64] = decode_hex(&artifact.signature)?
+        .try_into()
+        .map_err(|_| anyhow!("signature must be 64 bytes"))?;
+    let statement = release_statement(version, &artifact.platform, &digest);
+    key.verify(statement.as_bytes(), &Signature::from_bytes(&signature))
+        .map_err(|_| anyhow!("artifact signature is not valid for the release key"))
+}
+
+/// Builds the signed manifest entry for `bytes` with a 32-byte ed25519 seed.
+pub fn sign_artifact(
+    bytes: &[u8],
+    version: &str,
+    platform: &str,
+    url: &str,
+    seed: &[u8; 32],
+) -> ReleaseArtifact {
+    let sha256 = sha256_hex(bytes);
+    let statement = release_statement(version, platform, &sha256);
+    let signature = SigningKey::from_bytes(seed).sign(statement.as_bytes());
+    ReleaseArtifact {
+        platform: platform.to_string(),
+        url: url.to_string(),
+        size: bytes.len() as u64,
+        signature: to_hex(&signature.to_bytes()),
+        sha256,
+    }
+}
+
+pub fn public_key_for_seed(seed: &[u8; 32]) -> String {
+    to_hex(SigningKey::from_bytes(seed).verifying_key().as_bytes())
+}
+
+/// Replaces `exe` with `new_binary`. The current binary is kept as
+/// `<exe>.old`; if the swap fails or the new binary does not answer
+/// `--version`, the old one is put back.
+pub fn install_binary(exe: &Path, new_binary: &[u8]) -> Result<PathBuf> {
+    let staged = sibling(exe, ".new");
+    let backup = sibling(exe, ".old");
+    fs::write(&staged, new_binary).with_context(|| format!("write {}", staged.display()))?;
+    copy_permissions(exe, &staged)?;
+    if let Err(err) = smoke_test(&staged) {
+        fs::remove_file(&staged).ok();
+        return Err(err.context("new binary failed to start; not installed"));
+    }
+
+    if backup.exists() {
+        fs::remove_file(&backup).with_context(|| format!("remove {}", backup.display()))?;
+    }
+    // Renaming the running binary works on Windows too, unlike overwriting it.
+    fs::rename(exe, &backup).with_context(|| format!("move {} aside", exe.display()))?;
+    if let Err(err) = fs::rename(&staged, exe) {
+        fs::rename(&backup, exe).ok();
+        return Err(anyhow!("install {} failed: {}", exe.display(), err));
+    }
+    if let Err(err) = smoke_test(exe) {
+        rollback(exe)?;
+        return Err(err.context("installed binary failed to start; rolled back"));
+    }
+    Ok(backup)
+}
+
+/// Restores `<exe>.old` over `exe`.
+pub fn rollback(exe: &Path) -> Result<()> {
+    let backup = sibling(exe, ".old");
+    if !backup.exists() {
+        return Err(anyhow!("no previous binary at {}", backup.display()));
+    }
+    let failed = sibling(exe, ".failed");
+    fs::remove_file(&failed).ok();
+    if exe.exists() {
+        fs::rename(exe, &failed).with_context(|| format!("move {} aside", exe.display()))?;
+    }
+    fs::rename(&backup, exe).with_context(|| format!("restore {}", backup.display()))?;
+    fs::remove_file(&failed).ok();
+    Ok(())
+}
+
+/// Orders dotted numeric versions; a `-pre` suffix sorts before the release.
+pub fn compare_versions(left: &str, right: &str) -> std::cmp::Ordering {
+    let parse = |version: &str| {
+        let version = version.trim().trim_start_matches('v');
+        let (core, pre) = match version.split_once('-') {
+            Some((core, pre)) => (core, Some(pre.to_string())),
+            None => (version, None),
+        };
+        let numbers: Vec<u64> = core
+            .split('.')
+            .map(|part| part.parse().unwrap_or(0))
+            .collect();
+        (numbers, pre)
+    };
+    let (left_numbers, left_pre) = parse(left);
+    let (right_numbers, right_pre) = parse(right);
+    let len = left_numbers.len().max(right_numbers.len());
+    for index in 0..len {
+        let a = left_numbers.get(index).copied().unwrap_or(0);
+        let b = right_numbers.get(index).copied().unwrap_or(0);
+        if a != b {
+            return a.cmp(&b);
+        }
+    }
+    match (left_pre, right_pre) {
+        (None, None) => std::cmp::Ordering::Equal,
+        (None, Some(_)) => std::cmp::Ordering::Greater,
+        (Some(_), None) => std::cmp::Ordering::Less,
+        (Some(a), Some(b)) => a.cmp(&b),
+    }
+}
+
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    to_hex(&Sha256::digest(bytes))
+}
+
+/// Parses 32 bytes of hex (a public key or a signing seed).
+pub fn decode_key_hex(value: &str) -> Result<[u8; 32]> {
+    decode_hex(value)?
+        .try_into()
+        .map_err(|_| anyhow!("public key must be 32 bytes of hex"))
+}
+
+fn fetch(source: &str, limit: u64) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    if source.starts_with("http://") || source.starts_with("https://") {
+        let response = phoenix_fetch::agent_builder(source)?
+            .build()
+            .get(source)
+            .timeout(TIMEOUT)
+            .call()
+            .map_err(|err| anyhow!("fetch {} failed: {}", source, err))?;
+        response
+            .into_reader()
+            .take(limit)
+            .read_to_end(&mut bytes)?;
+    } else {
+        let path = source.strip_prefix("file://").unwrap_or(source);
+        fs::File::open(path)
+            .with_context(|| format!("open {}", path))?
+            .take(limit)
+            .read_to_end(&mut bytes)?;
+    }
+    Ok(bytes)
+}
+
+fn resolve_url(manifest_source: &str, url: &str) -> String {
+    if url.contains("://") || Path::new(url).is_absolute() {
+        return url.to_string();
+    }
+    match manifest_source.rfind('/') {
+        Some(index) => format!("{}/{}", &manifest_source[..index], url),
+        None => url.to_string(),
+    }
+}
+
+fn sibling(exe: &Path, suffix: &str) -> PathBuf {
+    let mut name = exe.file_name().unwrap_or_default().to_os_string();
+    name.push(suffix);
+    exe.with_file_name(name)
+}
+
+#[cfg(unix)]
+fn copy_permissions(from: &Path, to: &Path) -> Result<()> {
+    let permissions = fs::metadata(from)?.permissions();
+    fs::set_permissions(to, permissions)?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn copy_permissions(_from: &Path, _to: &Path) -> Result<()> {
+    Ok(())
+}
+
+fn smoke_test(binary: &Path) -> Result<()> {
+    let output = Command::new(binary)
+        .arg("--version")
+        .output()
+        .with_context(|| format!("run {}", binary.display()))?;
+    if !output.status.success() {
+        return Err(anyhow!("{} --version exited with {}", binary.display(), output.status));
+    }
+    Ok(())
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn decode_hex(value: &str) -> Result<Vec<u8>> {
+    let value = value.trim();
+    if !value.len().is_multiple_of(2) {
+        return Err(anyhow!("hex must be even length"));
+    }
+    (0..value.len())
+        .step_by(2)
+        .map(|idx| {
+            value
+                .get(idx..idx + 2)
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                .ok_or_else(|| anyhow!("invalid hex"))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cmp::Ordering;
+
+    const SEED: [u8; 32] = [7; 32];
+
+    fn public_key() -> [u8; 32] {
+        decode_key_hex(&public_key_for_seed(&SEED)).unwrap()
+    }
+
+    #[test]
+    fn verify_artifact_accepts_the_signed_release() {
+        let bytes = b"phoenix-cli 0.2.0";
+        let artifact = sign_artifact(bytes, "0.2.0", "linux-x86_64", "phoenix-cli", &SEED);
+        verify_artifact(bytes, "0.2.0", &artifact, &public_key()).unwrap();
+    }
+
+    #[test]
+    fn verify_artifact_rejects_tampering() {
+        let bytes = b"phoenix-cli 0.2.0";
+        let artifact = sign_artifact(bytes, "0.2.0", "linux-x86_64", "phoenix-cli", &SEED);
+        let key = public_key();
+
+        let mut flipped = bytes.to_vec();
+        flipped[0] ^= 1;
+        assert!(verify_artifact(&flipped, "0.2.0", &artifact, &key).is_err());
+        assert!(verify_artifact(b"short", "0.2.0", &artifact, &key).is_err());
+
+        // An old signed build replayed under a newer version or another platform.
+        assert!(verify_artifact(bytes, "9.0.0", &artifact, &key).is_err());
+        let mut moved = artifact.clone();
+        moved.platform = "windows-x86_64".to_string();
+        assert!(verify_artifact(bytes, "0.2.0", &moved, &key).is_err());
+
+        let other = decode_key_hex(&public_key_for_seed(&[8; 32])).unwrap();
+        assert!(verify_artifact(bytes, "0.2.0", &artifact, &other).is_err());
+
+        let mut forged = artifact.clone();
+        forged.signature = "00".repeat(64);
+        assert!(verify_artifact(bytes, "0.2.0", &forged, &key).is_err());
+    }
+
+    #[test]
+    fn compare_versions_orders_releases() {
+        assert_eq!(compare_versions("0.2.0", "0.1.9"), Ordering::Greater);
+        assert_eq!(compare_versions("0.10.0", "0.9.0"), Ordering::Greater);
+        assert_eq!(compare_versions("v1.2", "1.2.0"), Ordering::Equal);
+        assert_eq!(compare_versions("1.0.0-rc1", "1.0.0"), Ordering::Less);
+        assert_eq!(compare_versions("1.0.0-rc2", "1.0.0-rc1"), Ordering::Greater);
+        assert_eq!(compare_versions("0.1.0", "0.2.0"), Ordering::Less);
+    }
+
+    #[test]
+    fn check_update_refuses_older_releases() {
+        let manifest = ReleaseManifest {
+            version: "0.1.0".to_string(),
+            artifacts: vec![sign_artifact(b"old", "0.1.0", &current_platform(), "old", &SEED)],
+        };
+        let update = check_update(&manifest, "0.2.0");
+        assert!(update.is_downgrade());
+        assert!(!update.update_available());
+        assert!(!check_update(&manifest, "0.1.0").is_downgrade());
+    }
+}
@@ -0,0 +1,190 @@
+//! DISM fallback for hosts without a usable wimgapi.dll: `dism.exe` from
+//! the Windows ADK Deployment Tools, else the one Windows ships.
+
+use super::WimImageInfo;
+use anyhow::{anyhow, Context, Result};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+#[cfg(target_arch = "x86_64")]
+const ADK_ARCH: &str = "amd64";
+#[cfg(target_arch = "aarch64")]
+const ADK_ARCH: &str = "arm64";
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+const ADK_ARCH: &str = "x86";
+
+/// `PHOENIX_DISM`, then the ADK's Deployment Tools, then System32.
+pub fn find() -> Option<PathBuf> {
+    if let Some(path) = std::env::var_os("PHOENIX_DISM") {
+        return Some(PathBuf::from(path)).filter(|path| path.is_file());
+    }
+    let adk = ["ProgramFiles(x86)", "ProgramFiles"].iter().find_map(|var| {
+        let root = PathBuf::from(std::env::var_os(var)?);
+        let path = root
+            .join("Windows Kits\\10\\Assessment and Deployment Kit\\Deployment Tools")
+            .join(ADK_ARCH)
+            .join("DISM\\dism.exe");
+        path.is_file().then_some(path)
+    });
+    adk.or_else(|| {
+        let root = PathBuf::from(std::env::var_os("SystemRoot")?);
+        Some(root.join("System32\\Dism.exe")).filter(|path| path.is_file())
+    })
+}
+
+/// The summary lacks edition and architecture; each image is queried
+/// again for them.
+pub fn list_images(dism: &Path, path: &Path) -> Result<Vec<WimImageInfo>> {
+    let output = run(dism, &["/Get-WimInfo".to_string(), image_file(path)])?;
+    let mut images = parse_wim_info(&output);
+    for image in &mut images {
+        let detail = run(
+            dism,
+            &[
+                "/Get-WimInfo".to_string(),
+                image_file(path),
+                format!("/Index:{}", image.index),
+            ],
+        )?;
+        if let Some(found) = parse_wim_info(&detail).pop() {
+            image.edition_id = found.edition_id;
+            image.architecture = found.architecture;
+        }
+    }
+    Ok(images)
+}
+
+pub fn apply_image(dism: &Path, path: &Path, index: u32, target_dir: &Path) -> Result<()> {
+    if !target_dir.is_dir() {
+        return Err(anyhow!("target dir does not exist"));
+    }
+    run(
+        dism,
+        &[
+            "/Apply-Image".to_string(),
+            image_file(path),
+            format!("/Index:{}", index),
+            format!("/ApplyDir:{}", target_dir.display()),
+        ],
+    )
+    .map(|_| ())
+}
+
+pub fn export_images(dism: &Path, source: &Path, indices: &[u32], dest: &Path) -> Result<()> {
+    // DISM appends to an existing destination; the API replaces it.
+    if dest.exists() {
+        std::fs::remove_file(dest).with_context(|| format!("remove {}", dest.display()))?;
+    }
+    for &index in indices {
+        run(
+            dism,
+            &[
+                "/Export-Image".to_string(),
+                format!("/SourceImageFile:{}", source.display()),
+                format!("/SourceIndex:{}", index),
+                format!("/DestinationImageFile:{}", dest.display()),
+                "/Compress:max".to_string(),
+            ],
+        )?;
+    }
+    Ok(())
+}
+
+pub fn split_image(dism: &Path, source: &Path, dest: &Path, part_bytes: u64) -> Result<()> {
+    let part_mb = (part_bytes / (1024 * 1024)).max(1);
+    run(
+        dism,
+        &[
+            "/Split-Image".to_string(),
+            image_file(source),
+            format!("/SWMFile:{}", dest.display()),
+            format!("/FileSize:{}", part_mb),
+        ],
+    )
+    .map(|_| ())
+}
+
+fn image_file(path: &Path) -> String {
+    format!("/ImageFile:{}", path.display())
+}
+
+fn run(dism: &Path, args: &[String]) -> Result<String> {
+    let output = Command::new(dism)
+        .arg("/English")
+        .args(args)
+        .output()
+        .with_context(|| format!("run {}", dism.display()))?;
+    let stdout = String::from_utf8_lossy(&output.stdout).to_string();
+    if output.status.success() {
+        return Ok(stdout);
+    }
+    let message = stdout
+        .lines()
+        .map(str::trim)
+        .filter(|line| line.starts_with("Error") || line.contains("error"))
+        .collect::<Vec<_>>()
+        .join(" ");
+    Err(anyhow!(
+        "dism {} failed ({}): {}",
+        args.first().map(String::as_str).unwrap_or_default(),
+        output.status,
+        message
+    ))
+}
+
+/// `Index : 1` starts an image; `Name`, `Description` and
+/// `Size : 15,338,102,271 bytes` follow it, and with `/Index` also
+/// `Architecture : x64` and `Edition : Professional`.
+fn parse_wim_info(output: &str) -> Vec<WimImageInfo> {
+    let mut images: Vec<WimImageInfo> = Vec::new();
+    for line in output.lines() {
+        let Some((key, value)) = line.split_once(" : ") else {
+            continue;
+        };
+        let value = value.trim();
+        match key.trim() {
+            "Index" => {
+                if let Ok(index) = value.parse() {
+                    images.push(WimImageInfo {
+                        index,
+                        name: None,
+                        description: None,
+                        total_bytes: None,
+                        edition_id: None,
+                        architecture: None,
+                    });
+                }
+            }
+            "Name" => {
+                if let Some(image) = images.last_mut() {
+                    image.name = Some(value.to_string()).filter(|name| !name.is_empty());
+                }
+            }
+            "Description" => {
+                if let Some(image) = images.last_mut() {
+                    image.description = Some(value.to_string()).filter(|text| !text.is_empty());
+                }
+            }
+            "Size" => {
+                if let Some(image) = images.last_mut() {
+                    let digits: String = value.chars().filter(char::is_ascii_digit).collect();
+                    image.total_bytes = digits.parse().ok();
+                }
+            }
+            "Edition" => {
+                if let Some(image) = images.last_mut() {
+                    image.edition_id = Some(value.to_string()).filter(|id| !id.is_empty());
+                }
+            }
+            "Architecture" => {
+                if let Some(image) = images.last_mut() {
+                    // DISM prints `x64`, `x86`, `arm64`, sometimes `** Unknown **`.
+                    image.architecture = Some(value.to_ascii_lowercase())
+                        .filter(|arch| !arch.contains("unknown"));
+                }
+            }
+            _ => {}
+        }
+    }
+    images
+}
@@ -1,5 +1,8 @@
 use anyhow::{anyhow, Result};
-use std::path::Path;
+use std::path::{Path, PathBuf};
+
+#[cfg(windows)]
+mod dism;
 
 #[derive(Debug, Clone)]
 pub struct WimImageInfo {
@@ -7,77 +10,164 @@ pub struct WimImageInfo {
     pub name: Option<String>,
     pub description: Option<String>,
     pub total_bytes: Option<u64>,
+    /// `Professional`, `Core` and so on; also what `EI.cfg` names.
+    pub edition_id: Option<String>,
+    /// `x86`, `x64`, `arm` or `arm64`.
+    pub architecture: Option<String>,
+}
+
+/// Name for the `<ARCH>` number in a WIM's image XML, the
+/// PROCESSOR_ARCHITECTURE value.
+pub fn architecture_name(arch: &str) -> Option<&'static str> {
+    match arch.trim() {
+        "0" => Some("x86"),
+        "5" => Some("arm"),
+        "9" => Some("x64"),
+        "12" => Some("arm64"),
+        _ => None,
+    }
 }
 
 #[cfg(windows)]
 mod windows_impl {
     use super::WimImageInfo;
     use anyhow::{anyhow, Result};
-    use std::ffi::c_void;
+    use std::ffi::{c_void, CString};
     use std::path::Path;
     use std::ptr;
+    use std::mem::transmute;
+    use std::sync::OnceLock;
     use windows::core::PCWSTR;
-    use windows::Win32::Foundation::{BOOL, HANDLE, INVALID_HANDLE_VALUE};
+    use windows::Win32::Foundation::{BOOL, HANDLE};
 
     const WIM_GENERIC_READ: u32 = 0x80000000;
+    const WIM_GENERIC_WRITE: u32 = 0x40000000;
+    const WIM_CREATE_ALWAYS: u32 = 2;
     const WIM_OPEN_EXISTING: u32 = 3;
     const WIM_FLAG_SHARE_READ: u32 = 0x00000001;
     const WIM_FLAG_SHARE_WRITE: u32 = 0x00000002;
     const WIM_COMPRESS_NONE: u32 = 0;
+    const WIM_COMPRESS_LZX: u32 = 2;
 
-    #[link(name = "wimgapi")]
+    #[link(name = "kernel32")]
     extern "system" {
-        fn WIMCreateFile(
-            path: PCWSTR,
-            desired_access: u32,
-            creation_disposition: u32,
-            flags_and_attributes: u32,
-            compression_type: u32,
-            creation_result: *mut u32,
-        ) -> HANDLE;
-        fn WIMCloseHandle(handle: HANDLE) -> BOOL;
-        fn WIMGetImageCount(handle: HANDLE, count: *mut u32) -> BOOL;
-        fn WIMLoadImage(handle: HANDLE, index: u32) -> HANDLE;
-        fn WIMGetImageInformation(
-            handle: HANDLE,
-            info: *mut *mut c_void,
-            size: *mut u32,
-        ) -> BOOL;
-        fn WIMFreeMemory(ptr: *mut c_void);
-        fn WIMApplyImage(handle: HANDLE, path: PCWSTR, flags: u32) -> BOOL;
+        fn LoadLibraryW(name: *const u16) -> *mut c_void;
+        fn GetProcAddress(module: *mut c_void, name: *const i8) -> *mut c_void;
+    }
+
+    type CreateFile = unsafe extern "system" fn(PCWSTR, u32, u32, u32, u32, *mut u32) -> HANDLE;
+    type CloseHandle = unsafe extern "system" fn(HANDLE) -> BOOL;
+    type GetImageCount = unsafe extern "system" fn(HANDLE) -> u32;
+    type LoadImage = unsafe extern "system" fn(HANDLE, u32) -> HANDLE;
+    type GetImageInformation = unsafe extern "system" fn(HANDLE, *mut *mut c_void, *mut u32) -> BOOL;
+    type FreeMemory = unsafe extern "system" fn(*mut c_void) -> u32;
+    type ApplyImage = unsafe extern "system" fn(HANDLE, PCWSTR, u32) -> BOOL;
+    type ExportImage = unsafe extern "system" fn(HANDLE, HANDLE, u32) -> BOOL;
+    type SetTemporaryPath = unsafe extern "system" fn(HANDLE, PCWSTR) -> BOOL;
+    type SplitFile = unsafe extern "system" fn(HANDLE, PCWSTR, *mut i64, u32) -> BOOL;
+
+    /// wimgapi entry points, resolved at run time so a host without
+    /// wimgapi.dll can still start and fall back to DISM.
+    struct Wimgapi {
+        create_file: CreateFile,
+        close_handle: CloseHandle,
+        get_image_count: GetImageCount,
+        load_image: LoadImage,
+        get_image_information: GetImageInformation,
+        free_memory: FreeMemory,
+        apply_image: ApplyImage,
+        export_image: ExportImage,
+        set_temporary_path: SetTemporaryPath,
+        split_file: SplitFile,
+    }
+
+    static WIMGAPI: OnceLock<Result<Wimgapi, String>> = OnceLock::new();
+
+    /// Errors with the reason when wimgapi.dll cannot be used.
+    pub fn available() -> Result<()> {
+        api().map(|_| ())
+    }
+
+    fn api() -> Result<&'static Wimgapi> {
+        WIMGAPI
+            .get_or_init(load_wimgapi)
+            .as_ref()
+            .map_err(|err| anyhow!("{}", err))
+    }
+
+    fn load_wimgapi() -> Result<Wimgapi, String> {
+        let name: Vec<u16> = "wimgapi.dll".encode_utf16().chain(std::iter::once(0)).collect();
+        // Stays loaded for the life of the process.
+        let module = unsafe { LoadLibraryW(name.as_ptr()) };
+        if module.is_null() {
+            return Err(format!(
+                "wimgapi.dll could not be loaded: {}",
+                std::io::Error::last_os_error()
+            ));
+        }
+        let symbol = |name: &str| -> Result<*mut c_void, String> {
+            let c_name = CString::new(name).map_err(|err| err.to_string())?;
+            let address = unsafe { GetProcAddress(module, c_name.as_ptr()) };
+            if address.is_null() {
+                Err(format!("wimgapi.dll does not export {}", name))
+            } else {
+                Ok(address)
+            }
+        };
+        unsafe {
+            Ok(Wimgapi {
+                create_file: transmute::<*mut c_void, CreateFile>(symbol("WIMCreateFile")?),
+                close_handle: transmute::<*mut c_void, CloseHandle>(symbol("WIMCloseHandle")?),
+                get_image_count: transmute::<*mut c_void, GetImageCount>(symbol("WIMGetImageCount")?),
+                load_image: transmute::<*mut c_void, LoadImage>(symbol("WIMLoadImage")?),
+                get_image_information: transmute::<*mut c_void, GetImageInformation>(symbol("WIMGetImageInformation")?),
+                free_memory: transmute::<*mut c_void, FreeMemory>(symbol("WIMFreeMemory")?),
+                apply_image: transmute::<*mut c_void, ApplyImage>(symbol("WIMApplyImage")?),
+                export_image: transmute::<*mut c_void, ExportImage>(symbol("WIMExportImage")?),
+                set_temporary_path: transmute::<*mut c_void, SetTemporaryPath>(symbol("WIMSetTemporaryPath")?),
+                split_file: transmute::<*mut c_void, SplitFile>(symbol("WIMSplitFile")?),
+            })
+        }
     }
 
     pub fn list_images(path: &Path) -> Result<Vec<WimImageInfo>> {
+        let api = api()?;
         let handle = open_wim_file(path)?;
-        let count = get_image_count(handle)?;
+        let count = unsafe { (api.get_image_count)(handle) };
         let mut images = Vec::new();
 
         for index in 1..=count {
-            let image_handle = unsafe { WIMLoadImage(handle, index) };
-            if image_handle == INVALID_HANDLE_VALUE {
+            let image_handle = unsafe { (api.load_image)(handle, index) };
+            if image_handle.is_invalid() {
                 continue;
             }
 
-            let xml = get_image_information(image_handle)?;
+            let xml = get_image_information(image_handle);
+            unsafe {
+                let _ = (api.close_handle)(image_handle);
+            }
+            let xml = xml?;
             let name = extract_tag(&xml, "NAME");
             let description = extract_tag(&xml, "DESCRIPTION");
             let total_bytes = extract_tag(&xml, "TOTALBYTES")
                 .and_then(|value| value.parse::<u64>().ok());
-
-            unsafe {
-                WIMCloseHandle(image_handle);
-            }
+            let edition_id = extract_tag(&xml, "EDITIONID");
+            let architecture = extract_tag(&xml, "ARCH")
+                .and_then(|arch| super::architecture_name(&arch))
+                .map(str::to_string);
 
             images.push(WimImageInfo {
                 index,
                 name,
                 description,
                 total_bytes,
+                edition_id,
+                architecture,
             });
         }
 
         unsafe {
-            WIMCloseHandle(handle);
+            let _ = (api.close_handle)(handle);
         }
 
         Ok(images)
@@ -88,19 +178,20 @@ mod windows_impl {
             return Err(anyhow!("target dir does not exist"));
         }
 
+        let api = api()?;
         let handle = open_wim_file(path)?;
-        let image_handle = unsafe { WIMLoadImage(handle, index) };
-        if image_handle == INVALID_HANDLE_VALUE {
-            unsafe { WIMCloseHandle(handle) };
+        let image_handle = unsafe { (api.load_image)(handle, index) };
+        if image_handle.is_invalid() {
+            unsafe { let _ = (api.close_handle)(handle); }
             return Err(anyhow!("failed to load image {}", index));
         }
 
         let wide = wide(target_dir);
-        let ok = unsafe { WIMApplyImage(image_handle, PCWSTR(wide.as_ptr()), 0) };
+        let ok = unsafe { (api.apply_image)(image_handle, PCWSTR(wide.as_ptr()), 0) };
 
         unsafe {
-            WIMCloseHandle(image_handle);
-            WIMCloseHandle(handle);
+            let _ = (api.close_handle)(image_handle);
+            let _ = (api.close_handle)(handle);
         }
 
         if ok.as_bool() {
@@ -110,11 +201,67 @@ mod windows_impl {
         }
     }
 
+    pub fn export_images(source: &Path, indices: &[u32], dest: &Path) -> Result<()> {
+        let api = api()?;
+        let temp = wide(&std::env::temp_dir());
+        let source_handle = open_wim_file(source)?;
+        let dest_handle = match create_wim_file(dest) {
+            Ok(handle) => handle,
+            Err(err) => {
+                unsafe { let _ = (api.close_handle)(source_handle); }
+                return Err(err);
+            }
+        };
+        let result = (|| {
+            unsafe {
+                let _ = (api.set_temporary_path)(source_handle, PCWSTR(temp.as_ptr()));
+                let _ = (api.set_temporary_path)(dest_handle, PCWSTR(temp.as_ptr()));
+            }
+            for &index in indices {
+                let image_handle = unsafe { (api.load_image)(source_handle, index) };
+                if image_handle.is_invalid() {
+                    return Err(anyhow!("failed to load image {}", index));
+                }
+                let ok = unsafe { (api.export_image)(image_handle, dest_handle, 0) };
+                unsafe { let _ = (api.close_handle)(image_handle); }
+                if !ok.as_bool() {
+                    return Err(anyhow!("WIMExportImage failed for image {}", index));
+                }
+            }
+            Ok(())
+        })();
+        unsafe {
+            let _ = (api.close_handle)(dest_handle);
+            let _ = (api.close_handle)(source_handle);
+        }
+        result
+    }
+
+    pub fn split_image(source: &Path, dest: &Path, part_bytes: u64) -> Result<()> {
+        let api = api()?;
+        let temp = wide(&std::env::temp_dir());
+        let handle = open_wim_file(source)?;
+        let part = wide(dest);
+        let mut part_size = i64::try_from(part_bytes).unwrap_or(i64::MAX);
+        let ok = unsafe {
+            let _ = (api.set_temporary_path)(handle, PCWSTR(temp.as_ptr()));
+            let ok = (api.split_file)(handle, PCWSTR(part.as_ptr()), &mut part_size, 0);
+            let _ = (api.close_handle)(handle);
+            ok
+        };
+        if ok.as_bool() {
+            Ok(())
+        } else {
+            Err(anyhow!("WIMSplitFile failed for {}", source.display()))
+        }
+    }
+
     fn open_wim_file(path: &Path) -> Result<HANDLE> {
+        let api = api()?;
         let wide = wide(path);
         let mut creation_result = 0u32;
         let handle = unsafe {
-            WIMCreateFile(
+            (api.create_file)(
                 PCWSTR(wide.as_ptr()),
                 WIM_GENERIC_READ,
                 WIM_OPEN_EXISTING,
@@ -124,35 +271,49 @@ mod windows_impl {
             )
         };
 
-        if handle == INVALID_HANDLE_VALUE {
+        if handle.is_invalid() {
             return Err(anyhow!("WIMCreateFile failed"));
         }
 
         Ok(handle)
     }
 
-    fn get_image_count(handle: HANDLE) -> Result<u32> {
-        let mut count = 0u32;
-        let ok = unsafe { WIMGetImageCount(handle, &mut count) };
-        if ok.as_bool() {
-            Ok(count)
-        } else {
-            Err(anyhow!("WIMGetImageCount failed"))
+    fn create_wim_file(path: &Path) -> Result<HANDLE> {
+        let api = api()?;
+        let wide = wide(path);
+        let mut creation_result = 0u32;
+        let handle = unsafe {
+            (api.create_file)(
+                PCWSTR(wide.as_ptr()),
+                WIM_GENERIC_WRITE,
+                WIM_CREATE_ALWAYS,
+                0,
+                WIM_COMPRESS_LZX,
+                &mut creation_result,
+            )
+        };
+
+        if handle.is_invalid() {
+            return Err(anyhow!("WIMCreateFile failed for {}", path.display()));
         }
+
+        Ok(handle)
     }
 
     fn get_image_information(handle: HANDLE) -> Result<String> {
+        let api = api()?;
         let mut ptr: *mut c_void = ptr::null_mut();
         let mut size = 0u32;
-        let ok = unsafe { WIMGetImageInformation(handle, &mut ptr, &mut size) };
+        let ok = unsafe { (api.get_image_information)(handle, &mut ptr, &mut size) };
         if !ok.as_bool() || ptr.is_null() || size == 0 {
             return Err(anyhow!("WIMGetImageInformation failed"));
         }
 
-        let bytes = unsafe { std::slice::from_raw_parts(ptr as *const u8, size as usize) };
-        let xml = String::from_utf8_lossy(bytes).to_string();
+        // The XML comes back as UTF-16 with a byte order mark.
+        let units = unsafe { std::slice::from_raw_parts(ptr as *const u16, size as usize / 2) };
+        let xml = String::from_utf16_lossy(units).trim_start_matches('\u{feff}').to_string();
         unsafe {
-            WIMFreeMemory(ptr);
+            (api.free_memory)(ptr);
         }
         Ok(xml)
     }
@@ -179,9 +340,82 @@ mod windows_impl {
     }
 }
 
+/// Which implementation WIM operations go through on this host.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WimBackend {
+    Wimgapi,
+    /// `dism.exe` at this path.
+    Dism(PathBuf),
+}
+
+impl WimBackend {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Wimgapi => "wimgapi",
+            Self::Dism(_) => "dism",
+        }
+    }
+
+    pub fn detail(&self) -> String {
+        match self {
+            Self::Wimgapi => "wimgapi.dll".to_string(),
+            Self::Dism(path) => format!("DISM at {}", path.display()),
+        }
+    }
+}
+
+/// wimgapi when it loads, else DISM. `PHOENIX_WIM_BACKEND=wimgapi|dism`
+/// pins one. Decided once per process.
+#[cfg(windows)]
+pub fn backend() -> Result<WimBackend> {
+    static BACKEND: std::sync::OnceLock<Result<WimBackend, String>> = std::sync::OnceLock::new();
+    BACKEND
+        .get_or_init(|| detect_backend().map_err(|err| err.to_string()))
+        .clone()
+        .map_err(|err| anyhow!(err))
+}
+
+#[cfg(not(windows))]
+pub fn backend() -> Result<WimBackend> {
+    Err(anyhow!("WIM operations require Windows"))
+}
+
+#[cfg(windows)]
+fn detect_backend() -> Result<WimBackend> {
+    let dism = || {
+        dism::find()
+            .map(WimBackend::Dism)
+            .ok_or_else(|| anyhow!("dism.exe not found (install the Windows ADK Deployment Tools)"))
+    };
+    let pinned = std::env::var("PHOENIX_WIM_BACKEND").unwrap_or_default();
+    match pinned.trim().to_ascii_lowercase().as_str() {
+        "" => match windows_impl::available() {
+            Ok(()) => Ok(WimBackend::Wimgapi),
+            Err(wimgapi) => dism().map_err(|dism| anyhow!("{}; {}", wimgapi, dism)),
+        },
+        "wimgapi" => windows_impl::available().map(|_| WimBackend::Wimgapi),
+        "dism" => dism(),
+        other => Err(anyhow!("unknown PHOENIX_WIM_BACKEND: {} (wimgapi, dism)", other)),
+    }
+}
+
+/// Path of the DISM fallback, whether or not wimgapi is usable.
+#[cfg(windows)]
+pub fn dism_path() -> Option<PathBuf> {
+    dism::find()
+}
+
+#[cfg(not(windows))]
+pub fn dism_path() -> Option<PathBuf> {
+    None
+}
+
 #[cfg(windows)]
 pub fn list_images(path: impl AsRef<Path>) -> Result<Vec<WimImageInfo>> {
-    windows_impl::list_images(path.as_ref())
+    match backend()? {
+        WimBackend::Wimgapi => windows_impl::list_images(path.as_ref()),
+        WimBackend::Dism(dism) => dism::list_images(&dism, path.as_ref()),
+    }
 }
 
 #[cfg(not(windows))]
@@ -191,10 +425,50 @@ pub fn list_images(_path: impl AsRef<Path>) -> Result<Vec<WimImageInfo>> {
 
 #[cfg(windows)]
 pub fn apply_image(path: impl AsRef<Path>, index: u32, target_dir: impl AsRef<Path>) -> Result<()> {
-    windows_impl::apply_image(path.as_ref(), index, target_dir.as_ref())
+    phoenix_safety::ensure_writable("WIM apply")?;
+    match backend()? {
+        WimBackend::Wimgapi => windows_impl::apply_image(path.as_ref(), index, target_dir.as_ref()),
+        WimBackend::Dism(dism) => {
+            dism::apply_image(&dism, path.as_ref(), index, target_dir.as_ref())
+        }
+    }
 }
 
 #[cfg(not(windows))]
 pub fn apply_image(_path: impl AsRef<Path>, _index: u32, _target_dir: impl AsRef<Path>) -> Result<()> {
     Err(anyhow!("WIM operations require Windows"))
 }
+
+/// Exports `indices` of `source` (in that order) into a new LZX-compressed
+/// WIM at `dest`, replacing any file there.
+#[cfg(windows)]
+pub fn export_images(source: impl AsRef<Path>, indices: &[u32], dest: impl AsRef<Path>) -> Result<()> {
+    match backend()? {
+        WimBackend::Wimgapi => windows_impl::export_images(source.as_ref(), indices, dest.as_ref()),
+        WimBackend::Dism(dism) => {
+            dism::export_images(&dism, source.as_ref(), indices, dest.as_ref())
+        }
+    }
+}
+
+#[cfg(not(windows))]
+pub fn export_images(_source: impl AsRef<Path>, _indices: &[u32], _dest: impl AsRef<Path>) -> Result<()> {
+    Err(anyhow!("WIM operations require Windows"))
+}
+
+/// Splits `source` into `.swm` parts of at most `part_bytes`, the first at
+/// `dest` (`install.swm`, then `install2.swm`, ...), e.g. to fit FAT32.
+#[cfg(windows)]
+pub fn split_image(source: impl AsRef<Path>, dest: impl AsRef<Path>, part_bytes: u64) -> Result<()> {
+    match backend()? {
+        WimBackend::Wimgapi => windows_impl::split_image(source.as_ref(), dest.as_ref(), part_bytes),
+        WimBackend::Dism(dism) => {
+            dism::split_image(&dism, source.as_ref(), dest.as_ref(), part_bytes)
+        }
+    }
+}
+
+#[cfg(not(windows))]
+pub fn split_image(_source: impl AsRef<Path>, _dest: impl AsRef<Path>, _part_bytes: u64) -> Result<()> {
+    Err(anyhow!("WIM operations require Windows"))
+}
@@ -0,0 +1,142 @@
+use anyhow::{anyhow, Result};
+use phoenix_core::WorkflowDefinition;
+use phoenix_workflow_engine::{
+    run_disk_hash_report, run_macos_installer_usb, run_macos_kext_stage, run_stage_bootloader,
+    run_unix_boot_prep, run_unix_installer_usb, run_unix_write_image, run_windows_apply_image,
+    run_windows_installer_usb, run_workflow_definition_with_report, with_cancel_token,
+    BootloaderStageParams, BootloaderStageResult, CancelToken, DiskHashReportParams,
+    DiskHashReportResult, MacosInstallerUsbParams, MacosInstallerUsbResult, MacosKextStageParams,
+    MacosKextStageResult, UnixBootPrepParams, UnixBootPrepResult, UnixInstallerUsbParams,
+    UnixInstallerUsbResult, UnixWriteImageParams, UnixWriteImageResult, WindowsApplyImageParams,
+    WindowsApplyImageResult, WindowsInstallerUsbParams, WindowsInstallerUsbResult,
+    WorkflowRunResult,
+};
+use std::path::PathBuf;
+
+/// Cancels the token when the owning future is dropped before the workflow
+/// finished.
+struct CancelOnDrop {
+    token: CancelToken,
+    armed: bool,
+}
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        if self.armed {
+            self.token.cancel();
+        }
+    }
+}
+
+/// Runs a blocking workflow on tokio's blocking pool, where it holds one
+/// thread until it returns. Dropping the returned future cancels the workflow at its next cancellation point (between
+/// copied files, written chunks or definition steps); the blocking thread
+/// then finishes with a "workflow cancelled" error that nobody observes.
+async fn run_blocking<P, R>(params: P, run: fn(&P) -> Result<R>) -> Result<R>
+where
+    P: Send + 'static,
+    R: Send + 'static,
+{
+    let token = CancelToken::new();
+    let mut guard = CancelOnDrop {
+        token: token.clone(),
+        armed: true,
+    };
+    let result = tokio::task::spawn_blocking(move || with_cancel_token(&token, || run(&params)))
+        .await
+        .map_err(|err| anyhow!("workflow task failed: {}", err))?;
+    guard.armed = false;
+    result
+}
+
+pub async fn run_windows_installer_usb_async(
+    params: WindowsInstallerUsbParams,
+) -> Result<WindowsInstallerUsbResult> {
+    run_blocking(params, run_windows_installer_usb).await
+}
+
+pub async fn run_windows_apply_image_async(
+    params: WindowsApplyImageParams,
+) -> Result<WindowsApplyImageResult> {
+    run_blocking(params, run_windows_apply_image).await
+}
+
+pub async fn run_unix_installer_usb_async(
+    params: UnixInstallerUsbParams,
+) -> Result<UnixInstallerUsbResult> {
+    run_blocking(params, run_unix_installer_usb).await
+}
+
+pub async fn run_unix_write_image_async(params: UnixWriteImageParams) -> Result<UnixWriteImageResult> {
+    run_blocking(params, run_unix_write_image).await
+}
+
+pub async fn run_unix_boot_prep_async(params: UnixBootPrepParams) -> Result<UnixBootPrepResult> {
+    run_blocking(params, run_unix_boot_prep).await
+}
+
+pub async fn run_macos_installer_usb_async(
+    params: MacosInstallerUsbParams,
+) -> Result<MacosInstallerUsbResult> {
+    run_blocking(params, run_macos_installer_usb).await
+}
+
+pub async fn run_macos_kext_stage_async(params: MacosKextStageParams) -> Result<MacosKextStageResult> {
+    run_blocking(params, run_macos_kext_stage).await
+}
+
+pub async fn run_stage_bootloader_async(
+    params: BootloaderStageParams,
+) -> Result<BootloaderStageResult> {
+    run_blocking(params, run_stage_bootloader).await
+}
+
+pub async fn run_disk_hash_report_async(params: DiskHashReportParams) -> Result<DiskHashReportResult> {
+    run_blocking(params, run_disk_hash_report).await
+}
+
+pub async fn run_workflow_definition_with_report_async(
+    definition: WorkflowDefinition,
+    report_base: PathBuf,
+) -> Result<WorkflowRunResult> {
+    run_blocking((definition, report_base), |(definition, report_base)| {
+        run_workflow_definition_with_report(definition, report_base.clone())
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use phoenix_workflow_engine::is_cancelled;
+    use std::future::Future;
+    use std::sync::mpsc;
+    use std::task::Poll;
+    use std::time::{Duration, Instant};
+
+    fn wait_for_cancel(seen: &mpsc::Sender<bool>) -> Result<()> {
+        let start = Instant::now();
+        while !is_cancelled() && start.elapsed() < Duration::from_secs(10) {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        let _ = seen.send(is_cancelled());
+        Ok(())
+    }
+
+    #[test]
+    fn dropping_the_future_cancels_the_run() {
+        let (seen, cancelled) = mpsc::channel();
+        let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        runtime.block_on(async {
+            let mut run = Box::pin(run_blocking(seen, wait_for_cancel));
+            // One poll starts the blocking task; then the caller gives up.
+            std::future::poll_fn(|cx| {
+                assert!(run.as_mut().poll(cx).is_pending());
+                Poll::Ready(())
+            })
+            .await;
+            drop(run);
+        });
+        assert_eq!(cancelled.recv_timeout(Duration::from_secs(10)), Ok(true));
+    }
+}
@@ -0,0 +1,963 @@
+//! A/B slot sticks for appliance field updates: two equal FAT32 slots,
+//! each holding a complete appliance image with its own
+//! `EFI/BOOT/BOOTX64.EFI`, and a small EFI system partition whose GRUB
+//! menu chainloads either one. `phoenix/slots.json` on the boot partition
+//! records what each slot holds and which one boots by default. An update
+//! restages only the inactive slot and then makes it the default, so the
+//! image that booted before stays on the stick to roll back to.
+
+use crate::boot_lint::{lint_boot_menu, BootLintFinding, BootVolume};
+use crate::combo::{chainload_commands, describe_findings, efi_loader, ComboPartition, BOOT_LABEL};
+use crate::ledger::{now_unix, RunTracker};
+use crate::operation::{DestructiveOperation, DestructiveSession};
+use crate::resize::{device_path, partition_node};
+use crate::{
+    build_device_graph, check_format_capacity, check_overwrite, check_target_claim,
+    check_target_disk_size, check_target_wear, collect_files, copy_file_with_mtime,
+    estimate_capacity, join_reasons, max_file_size, signing_key_from_env, staging_backend, target,
+    verify_copy, FileEntry, StepLog,
+};
+use anyhow::{anyhow, Context, Result};
+use phoenix_bootcfg::{stage_grub_menu, GrubMenu, GrubMenuEntry, DEFAULT_GRUB_DIR};
+use phoenix_content::prepare_source;
+use phoenix_core::Disk;
+use phoenix_host_windows::format::FileSystem;
+use phoenix_partition::plan::{PartitionPlan, PlannedPartition, TYPE_EFI_SYSTEM};
+use phoenix_partition::{plan_partitions, read_gpt, PartitionSpec, DEFAULT_SECTOR_SIZE};
+use phoenix_report::{create_report_bundle_with_meta_signing_and_artifacts, ReportPaths};
+use phoenix_safety::SafetyContext;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+pub const SLOT_A_LABEL: &str = "SLOT-A";
+pub const SLOT_B_LABEL: &str = "SLOT-B";
+/// The slot table, on the boot partition.
+pub const SLOT_TABLE_PATH: &str = "phoenix/slots.json";
+const DEFAULT_MENU_TIMEOUT: u32 = 5;
+const BOOT_PARTITION_BYTES: u64 = 128 * 1024 * 1024;
+/// Left out of the even split for the GPT and 1 MiB alignment.
+const LAYOUT_OVERHEAD_BYTES: u64 = 4 * 1024 * 1024;
+const MIB: u64 = 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Slot {
+    A,
+    B,
+}
+
+impl Slot {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::A => "a",
+            Self::B => "b",
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::A => SLOT_A_LABEL,
+            Self::B => SLOT_B_LABEL,
+        }
+    }
+
+    pub fn other(self) -> Self {
+        match self {
+            Self::A => Self::B,
+            Self::B => Self::A,
+        }
+    }
+
+    /// Position of the slot's entry in the boot menu.
+    fn index(self) -> usize {
+        match self {
+            Self::A => 0,
+            Self::B => 1,
+        }
+    }
+}
+
+/// The appliance image staged in one slot.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SlotImage {
+    #[serde(default)]
+    pub version: Option<String>,
+    pub source: PathBuf,
+    /// The image's EFI loader, as the menu chainloads it.
+    pub loader: String,
+    pub files: usize,
+    pub bytes: u64,
+    pub staged_unix: u64,
+}
+
+/// `phoenix/slots.json`: what each slot holds and which one boots.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SlotTable {
+    pub active: Slot,
+    #[serde(default)]
+    pub menu_title: Option<String>,
+    pub menu_timeout: u32,
+    pub slot_a: SlotImage,
+    pub slot_b: SlotImage,
+}
+
+impl SlotTable {
+    pub fn image(&self, slot: Slot) -> &SlotImage {
+        match slot {
+            Slot::A => &self.slot_a,
+            Slot::B => &self.slot_b,
+        }
+    }
+
+    fn image_mut(&mut self, slot: Slot) -> &mut SlotImage {
+        match slot {
+            Slot::A => &mut self.slot_a,
+            Slot::B => &mut self.slot_b,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AbStickParams {
+    pub disk_id: String,
+    /// Appliance image with its own `EFI/BOOT/BOOTX64.EFI`; staged in both
+    /// slots.
+    pub image_source: PathBuf,
+    /// GRUB EFI package for the boot partition, as `stage_bootloader`
+    /// takes it.
+    pub bootloader_source: PathBuf,
+    /// Size of each slot; half of the stick past the boot partition when
+    /// unset.
+    #[serde(default, with = "crate::params::byte_size")]
+    pub slot_size: Option<u64>,
+    /// Recorded in the slot table and shown in the menu.
+    #[serde(default)]
+    pub version: Option<String>,
+    #[serde(default)]
+    pub menu_title: Option<String>,
+    /// Seconds before the active slot boots; 5 by default.
+    #[serde(default)]
+    pub menu_timeout: Option<u32>,
+    #[serde(default = "crate::params::default_report_base")]
+    pub report_base: PathBuf,
+    #[serde(default)]
+    pub force: bool,
+    #[serde(default)]
+    pub confirmation_token: Option<String>,
+    #[serde(default)]
+    pub acknowledge_target_size: bool,
+    /// Proceed on a device past the wear limits; recorded in the report
+    /// and the audit log.
+    #[serde(default)]
+    pub acknowledge_device_wear: bool,
+    #[serde(default)]
+    pub confirm_overwrite: bool,
+    #[serde(default = "crate::params::default_true")]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AbStickResult {
+    pub report: ReportPaths,
+    pub disk_id: String,
+    pub partitions: Vec<ComboPartition>,
+    pub slots: SlotTable,
+    /// Problems boot lint found on the stick after staging; the run fails
+    /// on any found before.
+    pub boot_lint: Vec<BootLintFinding>,
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AbUpdateParams {
+    pub disk_id: String,
+    /// New appliance image for the inactive slot; unset with `rollback`.
+    #[serde(default)]
+    pub image_source: Option<PathBuf>,
+    #[serde(default)]
+    pub version: Option<String>,
+    /// Makes the inactive slot the default again without restaging it.
+    #[serde(default)]
+    pub rollback: bool,
+    #[serde(default = "crate::params::default_report_base")]
+    pub report_base: PathBuf,
+    #[serde(default)]
+    pub force: bool,
+    #[serde(default)]
+    pub confirmation_token: Option<String>,
+    #[serde(default = "crate::params::default_true")]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AbUpdateResult {
+    pub report: ReportPaths,
+    pub disk_id: String,
+    /// The slot that booted by default before the run.
+    pub previous: Slot,
+    /// The slot that boots by default after it.
+    pub active: Slot,
+    pub rollback: bool,
+    pub slots: SlotTable,
+    pub boot_lint: Vec<BootLintFinding>,
+    pub dry_run: bool,
+}
+
+pub fn run_ab_stick(params: &AbStickParams) -> Result<AbStickResult> {
+    let started = Instant::now();
+    if !cfg!(target_os = "linux") {
+        return Err(anyhow!("A/B stick workflow requires linux"));
+    }
+    let graph = build_device_graph()?;
+    let disk = find_target(&graph, &params.disk_id)?;
+    let target_size_acknowledged = check_target_disk_size(disk, params.acknowledge_target_size)?;
+    let device_claim = check_target_claim(disk, "ab-stick", params.dry_run)?;
+    let device_wear = join_reasons(
+        device_claim,
+        check_target_wear(disk, "ab-stick", params.acknowledge_device_wear, params.dry_run)?,
+    );
+    let overwrite_triggers = check_overwrite(disk, params.confirm_overwrite, params.dry_run)?;
+
+    let bootloader = phoenix_bootloader_core::validate_bootloader_package(&params.bootloader_source)?;
+    let boot_files = collect_files(&bootloader.root)?;
+    let image = prepare_source(&params.image_source)?;
+    let image_files = collect_files(&image.root)?;
+    let loader = efi_loader("image", &image_files)?;
+    if let Some(problem) = staging_backend(FileSystem::Fat32).file_size_problem(max_file_size(&image_files)) {
+        return Err(anyhow!("image source: {}", problem));
+    }
+
+    let slot_size = params.slot_size.unwrap_or_else(|| {
+        disk.size_bytes
+            .saturating_sub(BOOT_PARTITION_BYTES + LAYOUT_OVERHEAD_BYTES)
+            / 2
+            / MIB
+            * MIB
+    });
+    let specs = [
+        PartitionSpec {
+            size_bytes: Some(BOOT_PARTITION_BYTES),
+            type_guid: TYPE_EFI_SYSTEM,
+            ..PartitionSpec::basic_data(BOOT_LABEL)
+        },
+        PartitionSpec {
+            size_bytes: Some(slot_size),
+            ..PartitionSpec::basic_data(SLOT_A_LABEL)
+        },
+        PartitionSpec {
+            size_bytes: Some(slot_size),
+            ..PartitionSpec::basic_data(SLOT_B_LABEL)
+        },
+    ];
+    let plan = plan_partitions(disk.size_bytes, DEFAULT_SECTOR_SIZE, &specs)
+        .with_context(|| format!("{} is too small for two {} byte slots", disk.id, slot_size))?;
+
+    let mut logs = StepLog::new("ab-stick");
+    logs.push(format!("disk_id={}", disk.id));
+    if let Some(reason) = &target_size_acknowledged {
+        logs.push(format!("target_size_acknowledged={}", reason));
+    }
+    if let Some(reason) = &device_wear {
+        logs.push(format!("device_wear={}", reason));
+    }
+    for trigger in &overwrite_triggers {
+        logs.push(format!("overwrite_trigger={}", trigger));
+    }
+    logs.push(format!("image_source={}", params.image_source.display()));
+    logs.push(format!("bootloader_source={}", bootloader.root.display()));
+    if let Some(version) = &params.version {
+        logs.push(format!("version={}", version));
+    }
+    for partition in &plan.partitions {
+        logs.push(format!(
+            "partition={} name={} type={} first_lba={} last_lba={}",
+            partition.number,
+            partition.spec.name,
+            partition.spec.type_guid,
+            partition.first_lba,
+            partition.last_lba
+        ));
+    }
+    let estimate = estimate_capacity(FileSystem::Fat32, slot_size, None);
+    let format_capacity = check_format_capacity(&estimate, &image_files, &mut logs).context("slot partition")?;
+
+    let staged_unix = now_unix();
+    let slot_image = SlotImage {
+        version: params.version.clone(),
+        source: params.image_source.clone(),
+        loader,
+        files: image_files.len(),
+        bytes: files_bytes(&image_files),
+        staged_unix,
+    };
+    let table = SlotTable {
+        active: Slot::A,
+        menu_title: params.menu_title.clone(),
+        menu_timeout: params.menu_timeout.unwrap_or(DEFAULT_MENU_TIMEOUT),
+        slot_a: slot_image.clone(),
+        slot_b: slot_image,
+    };
+    let menu = slot_menu(&table);
+    let planned = [
+        planned_volume(BOOT_LABEL, &boot_files),
+        planned_volume(SLOT_A_LABEL, &image_files),
+        planned_volume(SLOT_B_LABEL, &image_files),
+    ];
+    let findings = lint_boot_menu(&menu, BOOT_LABEL, &planned);
+    if !findings.is_empty() {
+        return Err(anyhow!("boot lint failed: {}", describe_findings(&findings)));
+    }
+    logs.push(format!("boot_lint=ok entries={}", menu.entries.len()));
+
+    let ctx = safety_context(params.force, &params.confirmation_token);
+    let mut session = DestructiveSession::begin(
+        "ab-stick",
+        Some(disk),
+        &ctx,
+        disk.is_system_disk,
+        params.dry_run,
+        &mut logs,
+    )?;
+    let mut partition = PartitionAbStick { disk, plan: &plan };
+    let mounts = session.perform(&mut partition, &mut logs)?;
+
+    let mut staged_config = None;
+    let mut boot_lint = Vec::new();
+    if let Some(mounts) = &mounts {
+        session.phase("copy", &mut logs)?;
+        let contents = [&boot_files, &image_files, &image_files];
+        let total_bytes = contents.iter().map(|files| files_bytes(files)).sum::<u64>();
+        let mut copied_bytes = 0u64;
+        for ((files, mount), partition) in contents.iter().zip(mounts).zip(&plan.partitions) {
+            logs.push(format!("stage={} mount={}", partition.spec.name, mount.display()));
+            copy_files(files, mount, &mut copied_bytes, total_bytes, &mut logs)?;
+            session.checkpoint(copied_bytes);
+        }
+        logs.push("copy_complete".to_string());
+
+        session.phase("boot_menu", &mut logs)?;
+        let config = write_selector(&table, &bootloader.root, &mounts[0])?;
+        logs.push(format!("boot_menu={}", config.display()));
+        logs.push(format!("active_slot={}", table.active.as_str()));
+        staged_config = Some(config);
+
+        session.phase("verify", &mut logs)?;
+        for (files, mount) in contents.iter().zip(mounts) {
+            verify_copy(mount, *files)?;
+        }
+        logs.push("verify_complete".to_string());
+        let staged_volumes = plan
+            .partitions
+            .iter()
+            .zip(mounts)
+            .map(|(partition, mount)| BootVolume::scan(partition.spec.name.as_str(), mount))
+            .collect::<Result<Vec<_>>>()?;
+        boot_lint = lint_boot_menu(&menu, BOOT_LABEL, &staged_volumes);
+        for finding in &boot_lint {
+            logs.push(format!("boot_lint_finding={}: {}", finding.entry, finding.problem));
+        }
+    } else {
+        logs.push("dry_run=true".to_string());
+    }
+
+    let partitions: Vec<ComboPartition> = plan
+        .partitions
+        .iter()
+        .map(|partition| {
+            let (source, files) = if partition.spec.name == BOOT_LABEL {
+                (&bootloader.root, &boot_files)
+            } else {
+                (&params.image_source, &image_files)
+            };
+            ComboPartition {
+                number: partition.number,
+                label: partition.spec.name.clone(),
+                filesystem: "FAT32".to_string(),
+                size_bytes: partition.length_bytes(DEFAULT_SECTOR_SIZE),
+                source: Some(source.clone()),
+                files: files.len(),
+                bytes: files_bytes(files),
+            }
+        })
+        .collect();
+
+    let (log_text, timing) = logs.finish()?;
+    let meta = serde_json::json!({
+        "workflow": "ab-stick",
+        "status": if params.dry_run { "dry_run" } else { "completed" },
+        "disk_id": disk.id,
+        "target_serial": disk.serial,
+        "partitions": partitions,
+        "slots": table,
+        "format_capacity": format_capacity,
+        "boot_menu": {
+            "title": menu.title,
+            "timeout": menu.timeout,
+            "default": menu.default,
+            "entries": menu.entries.iter().map(|entry| entry.title.clone()).collect::<Vec<_>>(),
+            "config": staged_config.as_ref().map(|config| config.display().to_string()),
+        },
+        "boot_lint": boot_lint,
+        "destructive_operations": session.operations(),
+        "target_size_acknowledged": target_size_acknowledged,
+        "device_wear": device_wear,
+        "device_wear_acknowledged": params.acknowledge_device_wear,
+        "overwrite_triggers": overwrite_triggers,
+        "overwrite_confirmed": params.confirm_overwrite,
+        "dry_run": params.dry_run,
+        "duration_ms": started.elapsed().as_millis() as u64
+    });
+
+    let report = create_report_bundle_with_meta_signing_and_artifacts(
+        &params.report_base,
+        &graph,
+        Some(meta),
+        Some(&log_text),
+        signing_key_from_env().as_deref(),
+        &[timing],
+    )?;
+    session.complete(&report.root)?;
+
+    Ok(AbStickResult {
+        report,
+        disk_id: disk.id.clone(),
+        partitions,
+        slots: table,
+        boot_lint,
+        dry_run: params.dry_run,
+    })
+}
+
+pub fn run_ab_update(params: &AbUpdateParams) -> Result<AbUpdateResult> {
+    let started = Instant::now();
+    if !cfg!(target_os = "linux") {
+        return Err(anyhow!("A/B update workflow requires linux"));
+    }
+    match (&params.image_source, params.rollback) {
+        (Some(_), true) => return Err(anyhow!("rollback takes no image_source")),
+        (None, false) => return Err(anyhow!("A/B update needs image_source, or rollback")),
+        _ => {}
+    }
+    let graph = build_device_graph()?;
+    let disk = find_target(&graph, &params.disk_id)?;
+
+    let device = device_path(disk)?;
+    let mut file = fs::File::open(&device).with_context(|| format!("open {}", device.display()))?;
+    let plan = read_gpt(&mut file, disk.size_bytes, DEFAULT_SECTOR_SIZE)?;
+    drop(file);
+    let find = |label: &str| {
+        plan.partitions
+            .iter()
+            .find(|partition| partition.spec.name == label)
+            .ok_or_else(|| anyhow!("{} is not an A/B slot stick: no {} partition", disk.id, label))
+    };
+    let boot = find(BOOT_LABEL)?;
+    let boot_mount = mount_volume(disk, boot.number)?;
+    let table = read_slot_table(&boot_mount)?;
+    let previous = table.active;
+    let active = previous.other();
+    let slot = find(active.label())?;
+
+    let mut logs = StepLog::new("ab-update");
+    logs.push(format!("disk_id={}", disk.id));
+    logs.push(format!("previous_slot={} active_slot={}", previous.as_str(), active.as_str()));
+    logs.push(format!("boot_mount={}", boot_mount.display()));
+
+    let mut updated = table.clone();
+    updated.active = active;
+    let mut image_files = Vec::new();
+    let mut format_capacity = None;
+    if let Some(image_source) = &params.image_source {
+        let image = prepare_source(image_source)?;
+        image_files = collect_files(&image.root)?;
+        if let Some(problem) = staging_backend(FileSystem::Fat32).file_size_problem(max_file_size(&image_files)) {
+            return Err(anyhow!("image source: {}", problem));
+        }
+        logs.push(format!("image_source={}", image_source.display()));
+        if let Some(version) = &params.version {
+            logs.push(format!("version={}", version));
+        }
+        let estimate =
+            estimate_capacity(FileSystem::Fat32, slot.length_bytes(DEFAULT_SECTOR_SIZE), None);
+        format_capacity = Some(
+            check_format_capacity(&estimate, &image_files, &mut logs)
+                .with_context(|| format!("{} partition", active.label()))?,
+        );
+        *updated.image_mut(active) = SlotImage {
+            version: params.version.clone(),
+            source: image_source.clone(),
+            loader: efi_loader("image", &image_files)?,
+            files: image_files.len(),
+            bytes: files_bytes(&image_files),
+            staged_unix: now_unix(),
+        };
+    } else {
+        logs.push("rollback=true".to_string());
+    }
+
+    // The other slot is left as it is, so only the entry that changes is
+    // linted: the updated slot's files, or the rolled back slot as it is
+    // on the stick.
+    let menu = slot_menu(&updated);
+    let mut active_mount = None;
+    let active_volume = if params.rollback {
+        let mount = mount_volume(disk, slot.number)?;
+        let volume = BootVolume::scan(active.label(), &mount)?;
+        active_mount = Some(mount);
+        volume
+    } else {
+        planned_volume(active.label(), &image_files)
+    };
+    let findings = lint_slot(&menu, active, &boot_mount, active_volume)?;
+    if !findings.is_empty() {
+        return Err(anyhow!("boot lint failed: {}", describe_findings(&findings)));
+    }
+    logs.push(format!("boot_lint=ok slot={}", active.as_str()));
+
+    let ctx = safety_context(params.force, &params.confirmation_token);
+    let mut session = DestructiveSession::begin(
+        "ab-update",
+        Some(disk),
+        &ctx,
+        disk.is_system_disk,
+        params.dry_run,
+        &mut logs,
+    )?;
+    if params.image_source.is_some() {
+        let mut format = FormatSlot {
+            disk,
+            partition: slot,
+            slot: active,
+        };
+        active_mount = session.perform(&mut format, &mut logs)?;
+        if let Some(mount) = &active_mount {
+            session.phase("copy", &mut logs)?;
+            let mut copied_bytes = 0u64;
+            let total_bytes = files_bytes(&image_files);
+            copy_files(&image_files, mount, &mut copied_bytes, total_bytes, &mut logs)?;
+            session.checkpoint(copied_bytes);
+            logs.push("copy_complete".to_string());
+            session.phase("verify", &mut logs)?;
+            verify_copy(mount, &image_files)?;
+            logs.push("verify_complete".to_string());
+        }
+    }
+    let mut switch = SwitchSlot {
+        disk,
+        boot_mount: &boot_mount,
+        table: &updated,
+    };
+    let config = session.perform(&mut switch, &mut logs)?;
+
+    let mut boot_lint = Vec::new();
+    if let (Some(_), Some(mount)) = (&config, &active_mount) {
+        boot_lint = lint_slot(&menu, active, &boot_mount, BootVolume::scan(active.label(), mount)?)?;
+        for finding in &boot_lint {
+            logs.push(format!("boot_lint_finding={}: {}", finding.entry, finding.problem));
+        }
+    } else {
+        logs.push("dry_run=true".to_string());
+    }
+
+    let (log_text, timing) = logs.finish()?;
+    let meta = serde_json::json!({
+        "workflow": "ab-update",
+        "status": if params.dry_run { "dry_run" } else { "completed" },
+        "disk_id": disk.id,
+        "target_serial": disk.serial,
+        "previous_slot": previous,
+        "active_slot": active,
+        "rollback": params.rollback,
+        "slots": updated,
+        "format_capacity": format_capacity,
+        "boot_menu": {
+            "default": menu.default,
+            "entries": menu.entries.iter().map(|entry| entry.title.clone()).collect::<Vec<_>>(),
+            "config": config.as_ref().map(|config| config.display().to_string()),
+        },
+        "boot_lint": boot_lint,
+        "destructive_operations": session.operations(),
+        "dry_run": params.dry_run,
+        "duration_ms": started.elapsed().as_millis() as u64
+    });
+
+    let report = create_report_bundle_with_meta_signing_and_artifacts(
+        &params.report_base,
+        &graph,
+        Some(meta),
+        Some(&log_text),
+        signing_key_from_env().as_deref(),
+        &[timing],
+    )?;
+    session.complete(&report.root)?;
+
+    Ok(AbUpdateResult {
+        report,
+        disk_id: disk.id.clone(),
+        previous,
+        active,
+        rollback: params.rollback,
+        slots: updated,
+        boot_lint,
+        dry_run: params.dry_run,
+    })
+}
+
+/// Reads `phoenix/slots.json` from a mounted boot partition.
+pub fn read_slot_table(boot_mount: &Path) -> Result<SlotTable> {
+    let path = boot_mount.join(SLOT_TABLE_PATH);
+    let text = fs::read_to_string(&path)
+        .with_context(|| format!("read {}; was the stick made by ab-stick?", path.display()))?;
+    serde_json::from_str(&text).with_context(|| format!("parse {}", path.display()))
+}
+
+/// Boot lint findings for `slot`'s menu entry alone.
+fn lint_slot(menu: &GrubMenu, slot: Slot, boot_mount: &Path, volume: BootVolume) -> Result<Vec<BootLintFinding>> {
+    let volumes = [BootVolume::scan(BOOT_LABEL, boot_mount)?, volume];
+    let title = &menu.entries[slot.index()].title;
+    Ok(lint_boot_menu(menu, BOOT_LABEL, &volumes)
+        .into_iter()
+        .filter(|finding| &finding.entry == title)
+        .collect())
+}
+
+fn find_target<'a>(graph: &'a phoenix_core::DeviceGraph, disk_id: &str) -> Result<&'a Disk> {
+    let disk = graph
+        .disks
+        .iter()
+        .find(|disk| disk.id.eq_ignore_ascii_case(disk_id))
+        .ok_or_else(|| anyhow!("disk not found: {}", disk_id))?;
+    if disk.is_system_disk {
+        return Err(anyhow!("refusing to target system disk: {}", disk.id));
+    }
+    if !disk.removable {
+        return Err(anyhow!("target disk is not marked removable: {}", disk.id));
+    }
+    if let Some(reason) = target::stack_usage(graph, disk) {
+        return Err(anyhow!(reason));
+    }
+    Ok(disk)
+}
+
+fn safety_context(force: bool, confirmation_token: &Option<String>) -> SafetyContext {
+    SafetyContext {
+        force_mode: force,
+        confirmation_token: confirmation_token.clone(),
+        allow_system_disk: false,
+        armed_until: phoenix_safety::armed_until(),
+    }
+}
+
+fn files_bytes(files: &[FileEntry]) -> u64 {
+    files.iter().map(|entry| entry.size).sum()
+}
+
+/// The files as boot lint sees them before anything is copied.
+fn planned_volume(label: &str, files: &[FileEntry]) -> BootVolume {
+    let mut volume = BootVolume::new(label);
+    for entry in files {
+        volume.insert(&entry.relative_path, entry.absolute_path.clone());
+    }
+    volume
+}
+
+fn copy_files(
+    files: &[FileEntry],
+    mount: &Path,
+    copied_bytes: &mut u64,
+    total_bytes: u64,
+    logs: &mut StepLog,
+) -> Result<()> {
+    for entry in files {
+        let dest = mount.join(&entry.relative_path);
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent).with_context(|| format!("create dir {}", parent.display()))?;
+        }
+        copy_file_with_mtime(&entry.absolute_path, &dest)
+            .with_context(|| format!("copy {} to {}", entry.absolute_path.display(), dest.display()))?;
+        *copied_bytes = copied_bytes.saturating_add(entry.size);
+        logs.progress(*copied_bytes, total_bytes);
+    }
+    Ok(())
+}
+
+/// One entry per slot, in slot order, and firmware setup; the active
+/// slot boots by default.
+fn slot_menu(table: &SlotTable) -> GrubMenu {
+    let entry = |slot: Slot| {
+        let image = table.image(slot);
+        let name = slot.as_str().to_ascii_uppercase();
+        GrubMenuEntry {
+            title: match &image.version {
+                Some(version) => format!("Slot {} ({})", name, version),
+                None => format!("Slot {}", name),
+            },
+            class: vec!["efi".to_string()],
+            commands: chainload_commands(slot.label(), &image.loader),
+        }
+    };
+    GrubMenu {
+        title: table.menu_title.clone(),
+        timeout: Some(table.menu_timeout),
+        default: Some(table.active.index().to_string()),
+        theme: None,
+        entries: vec![
+            entry(Slot::A),
+            entry(Slot::B),
+            GrubMenuEntry {
+                title: "Firmware Setup".to_string(),
+                class: vec!["efi".to_string()],
+                commands: vec!["fwsetup".to_string()],
+            },
+        ],
+    }
+}
+
+/// Writes the menu and the slot table to the boot partition. Returns the
+/// path of `grub.cfg`.
+fn write_selector(table: &SlotTable, pack_root: &Path, boot_mount: &Path) -> Result<PathBuf> {
+    let staged = stage_grub_menu(&slot_menu(table), pack_root, "ab", boot_mount, DEFAULT_GRUB_DIR, None)?;
+    let path = boot_mount.join(SLOT_TABLE_PATH);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).with_context(|| format!("create dir {}", parent.display()))?;
+    }
+    fs::write(&path, serde_json::to_vec_pretty(table)?)
+        .with_context(|| format!("write {}", path.display()))?;
+    Ok(staged.config)
+}
+
+/// Where partition `number` can be read: its sandbox volume under
+/// `PHOENIX_HOST=mock`, otherwise where it is mounted, mounting it first
+/// if it is not.
+fn mount_volume(disk: &Disk, number: u32) -> Result<PathBuf> {
+    if phoenix_core::mock::is_active() {
+        return Ok(phoenix_core::mock::mount_dir(&format!("{}-Partition{}", disk.id, number))?);
+    }
+    let node = partition_node(disk, number)?;
+    let name = node.file_name().unwrap_or_default().to_string_lossy().to_string();
+    if let Some(mount) = disk
+        .partitions
+        .iter()
+        .find(|partition| partition.id == name)
+        .and_then(|partition| partition.mount_points.first())
+    {
+        return Ok(PathBuf::from(mount));
+    }
+    let mount = std::env::temp_dir().join(format!("phoenix-ab-{}-{}", disk.id, name));
+    phoenix_host_linux::mount_partition(&node, &mount, "vfat")?;
+    Ok(mount)
+}
+
+/// Writes the A/B GPT and formats all three partitions FAT32. Returns
+/// where they are mounted, in partition order.
+struct PartitionAbStick<'a> {
+    disk: &'a Disk,
+    plan: &'a PartitionPlan,
+}
+
+impl DestructiveOperation for PartitionAbStick<'_> {
+    type Output = Vec<PathBuf>;
+
+    fn description(&self) -> String {
+        format!(
+            "repartition {} as an A/B slot stick ({}, {}, {})",
+            self.disk.id, BOOT_LABEL, SLOT_A_LABEL, SLOT_B_LABEL
+        )
+    }
+
+    fn phase(&self) -> &'static str {
+        "partition"
+    }
+
+    fn disk_id(&self) -> Option<&str> {
+        Some(&self.disk.id)
+    }
+
+    fn execute(&mut self, logs: &mut StepLog, _tracker: Option<&mut RunTracker>) -> Result<Vec<PathBuf>> {
+        if phoenix_core::mock::is_active() {
+            let path = phoenix_core::mock::disk_file(self.disk)?;
+            let mut file = fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(&path)
+                .with_context(|| format!("open {}", path.display()))?;
+            self.plan.write_gpt(&mut file)?;
+            let mut mounts = Vec::new();
+            for partition in &self.plan.partitions {
+                let mount = mount_volume(self.disk, partition.number)?;
+                phoenix_core::mock::format_volume(&mount)?;
+                logs.push(format!("formatted={} label={}", mount.display(), partition.spec.name));
+                mounts.push(mount);
+            }
+            return Ok(mounts);
+        }
+        partition_device(self.disk, self.plan, logs)
+    }
+
+    fn post_verify(&self, mounts: &Vec<PathBuf>, _logs: &mut StepLog) -> Result<()> {
+        for mount in mounts {
+            if !mount.is_dir() {
+                return Err(anyhow!("{} is not mounted after the format", mount.display()));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn partition_device(disk: &Disk, plan: &PartitionPlan, logs: &mut StepLog) -> Result<Vec<PathBuf>> {
+    crate::unmount_target_disk(disk, logs)?;
+    let device = Path::new("/dev").join(&disk.id);
+    let mut file = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(&device)
+        .with_context(|| format!("open {}", device.display()))?;
+    plan.write_gpt(&mut file)?;
+    file.sync_all()?;
+    drop(file);
+    let names = phoenix_host_linux::reread_partition_table(&device)?;
+    logs.push(format!("partition_reread=ok partitions={}", names.join(",")));
+    if names.len() != plan.partitions.len() {
+        return Err(anyhow!(
+            "{} shows {} partitions after the re-read, expected {}",
+            device.display(),
+            names.len(),
+            plan.partitions.len()
+        ));
+    }
+    let mut mounts = Vec::new();
+    for (partition, name) in plan.partitions.iter().zip(&names) {
+        let node = Path::new("/dev").join(name);
+        mounts.push(format_and_mount(disk, &node, partition, logs)?);
+    }
+    Ok(mounts)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn partition_device(_disk: &Disk, _plan: &PartitionPlan, _logs: &mut StepLog) -> Result<Vec<PathBuf>> {
+    Err(anyhow!("A/B stick workflow requires linux"))
+}
+
+/// Formats `node` FAT32 with the partition's name as label and mounts it.
+fn format_and_mount(
+    disk: &Disk,
+    node: &Path,
+    partition: &PlannedPartition,
+    logs: &mut StepLog,
+) -> Result<PathBuf> {
+    let label = partition.spec.name.as_str();
+    let layout = phoenix_fs_fat32::format_fat32_with_cluster_size(
+        node,
+        partition.length_bytes(DEFAULT_SECTOR_SIZE),
+        Some(label),
+        None,
+    )?;
+    logs.push(format!("format_fat32={} label={}", node.display(), label));
+    for warning in &layout.label_warnings {
+        logs.push(format!("label_warning={}", warning));
+    }
+    let name = node.file_name().unwrap_or_default().to_string_lossy();
+    let mount = std::env::temp_dir().join(format!("phoenix-ab-{}-{}", disk.id, name));
+    phoenix_host_linux::mount_partition(node, &mount, "vfat")?;
+    logs.push(format!("mounted={} at {}", node.display(), mount.display()));
+    Ok(mount)
+}
+
+/// Reformats the inactive slot, leaving the rest of the stick mounted.
+/// Returns where the empty slot is mounted.
+struct FormatSlot<'a> {
+    disk: &'a Disk,
+    partition: &'a PlannedPartition,
+    slot: Slot,
+}
+
+impl DestructiveOperation for FormatSlot<'_> {
+    type Output = PathBuf;
+
+    fn description(&self) -> String {
+        format!(
+            "format slot {} ({}) on {}",
+            self.slot.as_str().to_ascii_uppercase(),
+            self.slot.label(),
+            self.disk.id
+        )
+    }
+
+    fn phase(&self) -> &'static str {
+        "format"
+    }
+
+    fn disk_id(&self) -> Option<&str> {
+        Some(&self.disk.id)
+    }
+
+    fn execute(&mut self, logs: &mut StepLog, _tracker: Option<&mut RunTracker>) -> Result<PathBuf> {
+        if phoenix_core::mock::is_active() {
+            let mount = mount_volume(self.disk, self.partition.number)?;
+            phoenix_core::mock::format_volume(&mount)?;
+            logs.push(format!("formatted={} label={}", mount.display(), self.slot.label()));
+            return Ok(mount);
+        }
+        let node = partition_node(self.disk, self.partition.number)?;
+        let name = node.file_name().unwrap_or_default().to_string_lossy().to_string();
+        if let Some(mounted) = self.disk.partitions.iter().find(|partition| partition.id == name) {
+            for mount in phoenix_host_linux::unmount_partition(mounted)? {
+                logs.push(format!("unmounted={}", mount));
+            }
+        }
+        format_and_mount(self.disk, &node, self.partition, logs)
+    }
+
+    fn post_verify(&self, mount: &PathBuf, _logs: &mut StepLog) -> Result<()> {
+        if !mount.is_dir() {
+            return Err(anyhow!("{} is not mounted after the format", mount.display()));
+        }
+        Ok(())
+    }
+}
+
+/// Rewrites the menu and the slot table so `table.active` boots by
+/// default. Returns the path of `grub.cfg`.
+struct SwitchSlot<'a> {
+    disk: &'a Disk,
+    boot_mount: &'a Path,
+    table: &'a SlotTable,
+}
+
+impl DestructiveOperation for SwitchSlot<'_> {
+    type Output = PathBuf;
+
+    fn description(&self) -> String {
+        format!(
+            "boot slot {} by default on {}",
+            self.table.active.as_str().to_ascii_uppercase(),
+            self.disk.id
+        )
+    }
+
+    fn phase(&self) -> &'static str {
+        "boot_menu"
+    }
+
+    fn disk_id(&self) -> Option<&str> {
+        Some(&self.disk.id)
+    }
+
+    fn execute(&mut self, logs: &mut StepLog, _tracker: Option<&mut RunTracker>) -> Result<PathBuf> {
+        let config = write_selector(self.table, self.boot_mount, self.boot_mount)?;
+        logs.push(format!("boot_menu={}", config.display()));
+        logs.push(format!("active_slot={}", self.table.active.as_str()));
+        Ok(config)
+    }
+
+    fn post_verify(&self, _config: &PathBuf, _logs: &mut StepLog) -> Result<()> {
+        let written = read_slot_table(self.boot_mount)?;
+        if written.active != self.table.active {
+            return Err(anyhow!("{} still boots slot {}", SLOT_TABLE_PATH, written.active.as_str()));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,671 @@
+//! Fleet agent: a bench machine polls a central server for queued jobs,
+//! claims one its attached disks and capabilities can serve, runs it like
+//! a kiosk run, and uploads the report. The server picks which job and
+//! which offered disk; the agent only ever runs on a disk it offered.
+//! Every step of a job's definition must target that disk, and hooks and
+//! `secret://` references run only when the local `allow` list names them.
+//! Front ends drive it through `AgentObserver` and stop it with a
+//! `CancelToken`. With a client certificate from the fleet CA the agent
+//! authenticates over mutual TLS, and the certificate's identity is
+//! recorded in the audit log and every report it writes.
+
+use crate::cancel::is_cancelled;
+use crate::capabilities::{host_capabilities, HostCapabilities};
+use crate::audit_log::{append_audit_entry, AuditEntry};
+use crate::cleanup::{CleanupAction, CleanupGuard};
+use crate::fleet_ca::read_agent_identity;
+use crate::hooks::step_hooks;
+use crate::kiosk::{
+    bind_definition, on_served_port, port_name, present_disks, run_on_disk, StationRun,
+};
+use crate::secrets::{resolve_secret, SECRET_SCHEME};
+use crate::steplog::StepLog;
+use crate::{build_device_graph, disk_id_from_device_path, signing_key_from_env, to_hex};
+use anyhow::{anyhow, Context, Result};
+use phoenix_core::{Disk, WorkflowDefinition, WorkflowStep};
+use phoenix_fetch::ClientTls;
+use phoenix_report::{
+    create_report_bundle_with_meta_signing_and_artifacts, write_report_zip, AgentIdentity,
+    ReportArtifact, ReportPaths, ZipExportOptions,
+};
+use phoenix_workflow_plan::value::optional_string;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs;
+use std::io::{self, Seek, SeekFrom};
+use std::path::{Component, Path, PathBuf};
+use std::time::Duration;
+
+pub const AGENT_JOBS_FILE: &str = "agent_jobs.json";
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+const UPLOAD_TIMEOUT: Duration = Duration::from_secs(300);
+/// Step params naming a disk by id; each must be the claimed disk.
+const DISK_ID_PARAMS: &[&str] = &["disk_id", "target_disk_id", "source_disk_id"];
+/// Step params naming a device node; each must be the claimed disk's.
+const DEVICE_PARAMS: &[&str] = &["target_device", "format_device"];
+/// Step params naming a mounted volume; each must be on the claimed disk.
+const MOUNT_PARAMS: &[&str] = &["target_mount", "target_dir"];
+/// Step params joined onto the target mount; each must stay under it.
+const TARGET_RELATIVE_PARAMS: &[&str] = &["driver_target", "target_subdir", "grub_dir"];
+/// Actions a remote job may run, each with the param that must name the
+/// claimed disk. Host-scoped and multi-disk actions are left out.
+const REMOTE_ACTIONS: &[(&str, &str)] = &[
+    ("windows_installer_usb", "target_disk_id"),
+    ("linux_installer_usb", "target_mount"),
+    ("linux_write_image", "target_device"),
+    ("linux_boot_prep", "target_mount"),
+    ("macos_write_image", "target_device"),
+    ("macos_installer_usb", "target_device"),
+    ("macos_boot_prep", "target_mount"),
+    ("stage_bootloader", "target_mount"),
+    ("stage_files", "target_mount"),
+    ("stage_firstboot", "target_mount"),
+    ("stage_provisioning", "target_mount"),
+    ("disk_hash_report", "disk_id"),
+    ("bad_block_scan", "disk_id"),
+    ("combo_stick", "disk_id"),
+    ("ab_stick", "disk_id"),
+    ("ab_update", "disk_id"),
+    ("resize_partition", "disk_id"),
+    ("restore_report", "target_disk_id"),
+];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AgentParams {
+    /// Base URL of the orchestration server, e.g. `https://fleet.example`.
+    pub server: String,
+    /// How the server knows this bench.
+    pub agent_id: String,
+    /// Secret holding the bearer token sent with every request.
+    #[serde(default)]
+    pub token_secret: Option<String>,
+    /// Client certificate from the fleet CA, whose common name must be
+    /// `agent_id`; turns on mutual TLS.
+    #[serde(default)]
+    pub client_cert: Option<PathBuf>,
+    #[serde(default)]
+    pub client_key: Option<PathBuf>,
+    /// CA the server certificate must chain to; defaults to the network
+    /// settings' trust.
+    #[serde(default)]
+    pub server_ca: Option<PathBuf>,
+    #[serde(default = "crate::params::default_report_base")]
+    pub report_base: PathBuf,
+    /// USB port labels or paths to offer; empty offers every removable
+    /// disk.
+    #[serde(default)]
+    pub ports: Vec<String>,
+    #[serde(rename = "poll_interval_ms", with = "crate::params::millis")]
+    pub poll_interval: Duration,
+    /// Stop after this many jobs; `None` runs until cancelled.
+    #[serde(default)]
+    pub max_jobs: Option<usize>,
+    /// Hooks and secrets server-sent definitions may use.
+    #[serde(default)]
+    pub allow: RemoteAllowList,
+}
+
+/// What a server-sent definition may use besides plain steps. Anything
+/// not listed is refused before the job touches a disk.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RemoteAllowList {
+    /// Hook programs (a command's first element) or plugin names.
+    #[serde(default)]
+    pub hooks: Vec<String>,
+    /// Names a definition may reference as `secret://<name>`.
+    #[serde(default)]
+    pub secrets: Vec<String>,
+}
+
+/// An attached disk as offered to the server.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AgentDevice {
+    pub disk_id: String,
+    pub serial: Option<String>,
+    pub size_bytes: u64,
+    pub port: Option<String>,
+    pub friendly_name: String,
+}
+
+/// Body of `POST /v1/jobs/claim`.
+#[derive(Debug, Clone, Serialize)]
+pub struct AgentClaim {
+    pub agent_id: String,
+    pub os: &'static str,
+    pub version: &'static str,
+    pub capabilities: HostCapabilities,
+    pub devices: Vec<AgentDevice>,
+}
+
+/// A job the server handed out; names one of the offered disks.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AgentJob {
+    pub job_id: String,
+    pub definition: WorkflowDefinition,
+    #[serde(default)]
+    pub target_serial: Option<String>,
+    #[serde(default)]
+    pub target_disk: Option<String>,
+}
+
+/// Body of `POST /v1/jobs/<job_id>/result`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AgentJobRun {
+    pub job_id: String,
+    pub agent_id: String,
+    pub definition: String,
+    /// `completed` or `failed`.
+    pub status: String,
+    /// `None` when the job failed before it reached a disk.
+    pub run: Option<StationRun>,
+    pub error: Option<String>,
+    pub report_uploaded: bool,
+    #[serde(default)]
+    pub agent_identity: Option<AgentIdentity>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AgentResult {
+    /// Session report, listing every job.
+    pub report: ReportPaths,
+    pub jobs: Vec<AgentJobRun>,
+    pub cancelled: bool,
+}
+
+/// Status changes, in loop order, for the front end to show.
+#[derive(Debug)]
+pub enum AgentEvent<'a> {
+    /// Asking for work with `devices` disks on offer.
+    Polling { devices: usize },
+    /// The server could not be reached or refused the request; the agent
+    /// tries again after the poll interval.
+    ServerError { error: String },
+    Claimed { job: &'a AgentJob, disk: Option<&'a Disk> },
+    Finished { job: &'a AgentJobRun },
+}
+
+pub trait AgentObserver {
+    fn event(&mut self, event: &AgentEvent);
+}
+
+/// Polls until `max_jobs` jobs are done or the thread's cancel token
+/// fires, then writes a session report. Only an authentication failure
+/// ends the loop early; other server errors are retried, and a failed job
+/// is reported to the server and the loop goes on.
+pub fn run_agent(params: &AgentParams, observer: &mut dyn AgentObserver) -> Result<AgentResult> {
+    let server = check_server(params)?;
+    let (identity, tls) = agent_tls(params)?;
+    let client = AgentClient {
+        server,
+        token: params.token_secret.as_deref().map(resolve_secret).transpose()?,
+        tls,
+    };
+    let mut logs = StepLog::new("agent");
+    logs.push(format!("server={}", client.server));
+    logs.push(format!("agent_id={}", params.agent_id));
+    if let Some(identity) = &identity {
+        logs.push(format!(
+            "mtls=true cert_serial={} issuer={} not_after={}",
+            identity.serial, identity.issuer, identity.not_after_utc
+        ));
+    }
+    let mut jobs = Vec::new();
+    let mut cancelled = false;
+    let mut polling = true;
+
+    loop {
+        if params.max_jobs.is_some_and(|max| jobs.len() >= max) {
+            break;
+        }
+        if is_cancelled() {
+            cancelled = true;
+            break;
+        }
+        let disks: Vec<Disk> = present_disks()?
+            .into_iter()
+            .filter(|disk| on_served_port(&params.ports, disk))
+            .collect();
+        if polling {
+            observer.event(&AgentEvent::Polling { devices: disks.len() });
+            polling = false;
+        }
+        let claim = AgentClaim {
+            agent_id: params.agent_id.clone(),
+            os: std::env::consts::OS,
+            version: env!("CARGO_PKG_VERSION"),
+            capabilities: host_capabilities(),
+            devices: disks.iter().map(agent_device).collect(),
+        };
+        let job = match client.claim(&claim) {
+            Ok(Some(job)) => job,
+            Ok(None) => {
+                std::thread::sleep(params.poll_interval);
+                continue;
+            }
+            Err(ClaimError::Unauthorized(code)) => {
+                return Err(anyhow!("agent server refused {} (HTTP {})", params.agent_id, code))
+            }
+            Err(ClaimError::Other(err)) => {
+                let error = format!("{:#}", err);
+                logs.push(format!("claim_error={}", error));
+                observer.event(&AgentEvent::ServerError { error });
+                std::thread::sleep(params.poll_interval);
+                continue;
+            }
+        };
+        polling = true;
+        logs.push(format!("claimed={} definition={}", job.job_id, job.definition.name));
+
+        let disk = job_disk(&job, &disks);
+        observer.event(&AgentEvent::Claimed {
+            job: &job,
+            disk: disk.as_ref().ok(),
+        });
+        let prepared = match &disk {
+            Ok(disk) => {
+                remote_definition(&job.definition, disk, &params.allow, &params.report_base)
+                    .map(|definition| (disk, definition))
+            }
+            Err(err) => Err(anyhow!("{:#}", err)),
+        };
+        let (run, error) = match prepared {
+            Ok((disk, definition)) => {
+                let entry = AuditEntry::new(
+                    "agent_job",
+                    &job.definition.name,
+                    disk,
+                    &format!("job {} from {}", job.job_id, client.server),
+                );
+                // No remote job runs unrecorded.
+                match append_audit_entry(&entry) {
+                    Ok(()) => {
+                        logs.push(format!("run={} disk={}", job.job_id, disk.id));
+                        let run = run_on_disk(&definition, &params.report_base, disk);
+                        let error = run.error.clone();
+                        (Some(run), error)
+                    }
+                    Err(err) => (None, Some(format!("{:#}", err))),
+                }
+            }
+            Err(err) => (None, Some(format!("{:#}", err))),
+        };
+        let report_uploaded = match run.as_ref().and_then(|run| run.report_root.as_deref()) {
+            Some(root) => match client.upload_report(&job.job_id, root) {
+                Ok(()) => true,
+                Err(err) => {
+                    logs.push(format!("report_upload_error={} error={:#}", job.job_id, err));
+                    false
+                }
+            },
+            None => false,
+        };
+        let job_run = AgentJobRun {
+            job_id: job.job_id.clone(),
+            agent_id: params.agent_id.clone(),
+            definition: job.definition.name.clone(),
+            status: if error.is_none() { "completed" } else { "failed" }.to_string(),
+            run,
+            error,
+            report_uploaded,
+            agent_identity: identity.clone(),
+        };
+        match &job_run.error {
+            None => logs.push(format!("completed={}", job.job_id)),
+            Some(err) => logs.push(format!("failed={} error={}", job.job_id, err)),
+        }
+        if let Err(err) = client.post_result(&job_run) {
+            logs.push(format!("result_post_error={} error={:#}", job.job_id, err));
+        }
+        observer.event(&AgentEvent::Finished { job: &job_run });
+        jobs.push(job_run);
+    }
+    if cancelled {
+        logs.push("cancelled=true");
+    }
+
+    let failed = jobs.iter().filter(|job| job.error.is_some()).count();
+    let jobs_artifact = ReportArtifact::json(AGENT_JOBS_FILE, &jobs)?;
+    let (log_text, timing) = logs.finish()?;
+    let meta = serde_json::json!({
+        "workflow": "agent",
+        "status": if failed == 0 { "completed" } else { "failed" },
+        "server": client.server,
+        "agent_id": params.agent_id,
+        "agent_identity": identity,
+        "jobs": jobs.len(),
+        "failed_jobs": failed,
+        "cancelled": cancelled,
+        "artifacts": [&jobs_artifact.name, &timing.name]
+    });
+    let graph = build_device_graph()?;
+    let report = create_report_bundle_with_meta_signing_and_artifacts(
+        &params.report_base,
+        &graph,
+        Some(meta),
+        Some(&log_text),
+        signing_key_from_env().as_deref(),
+        &[jobs_artifact, timing],
+    )?;
+    Ok(AgentResult {
+        report,
+        jobs,
+        cancelled,
+    })
+}
+
+/// `params.server` without a trailing slash. A bearer token only goes to
+/// an https server.
+pub(crate) fn check_server(params: &AgentParams) -> Result<String> {
+    let server = params.server.trim_end_matches('/').to_string();
+    if !phoenix_fetch::is_url(&server) {
+        return Err(anyhow!("agent server must be an http(s) URL: {}", params.server));
+    }
+    if params.token_secret.is_some() && !server.starts_with("https://") {
+        return Err(anyhow!(
+            "token_secret needs an https server; the token would go out in cleartext: {}",
+            params.server
+        ));
+    }
+    Ok(server)
+}
+
+/// The certificate identity and TLS settings for `params.client_cert`;
+/// the identity is also set for every report the process writes.
+fn agent_tls(params: &AgentParams) -> Result<(Option<AgentIdentity>, Option<ClientTls>)> {
+    let (cert, key) = match (&params.client_cert, &params.client_key) {
+        (Some(cert), Some(key)) => (cert, key),
+        (None, None) if params.server_ca.is_none() => return Ok((None, None)),
+        (None, None) => return Err(anyhow!("server_ca needs client_cert and client_key")),
+        _ => return Err(anyhow!("client_cert and client_key go together")),
+    };
+    if !params.server.starts_with("https://") {
+        return Err(anyhow!("a client certificate needs an https server: {}", params.server));
+    }
+    let identity = read_agent_identity(cert)?;
+    if identity.agent_id != params.agent_id {
+        return Err(anyhow!(
+            "client certificate {} is for {}, not {}",
+            cert.display(),
+            identity.agent_id,
+            params.agent_id
+        ));
+    }
+    let tls = ClientTls::new(params.server_ca.as_deref(), cert, key)?;
+    phoenix_report::set_agent_identity(identity.clone());
+    Ok((Some(identity), Some(tls)))
+}
+
+fn agent_device(disk: &Disk) -> AgentDevice {
+    AgentDevice {
+        disk_id: disk.id.clone(),
+        serial: disk.serial.clone(),
+        size_bytes: disk.size_bytes,
+        port: port_name(disk),
+        friendly_name: disk.friendly_name.clone(),
+    }
+}
+
+/// The offered disk the job names, by serial first since disk ids move
+/// between replugs.
+pub(crate) fn job_disk(job: &AgentJob, disks: &[Disk]) -> Result<Disk> {
+    let found = match (&job.target_serial, &job.target_disk) {
+        (Some(serial), _) => disks
+            .iter()
+            .find(|disk| disk.serial.as_deref() == Some(serial.as_str())),
+        (None, Some(id)) => disks.iter().find(|disk| disk.id.eq_ignore_ascii_case(id)),
+        (None, None) => return Err(anyhow!("job {} names no target disk", job.job_id)),
+    };
+    found.cloned().ok_or_else(|| {
+        anyhow!(
+            "job {} targets {} which this agent did not offer",
+            job.job_id,
+            job.target_serial
+                .as_deref()
+                .or(job.target_disk.as_deref())
+                .unwrap_or("-")
+        )
+    })
+}
+
+/// `definition` bound to `disk`, refused unless every step targets that
+/// disk and uses only the hooks and secrets `allow` lists.
+pub(crate) fn remote_definition(
+    definition: &WorkflowDefinition,
+    disk: &Disk,
+    allow: &RemoteAllowList,
+    report_base: &Path,
+) -> Result<WorkflowDefinition> {
+    let bound = bind_definition(definition, disk, port_name(disk).as_deref())?;
+    for step in &bound.steps {
+        check_step_target(step, disk, report_base)
+            .map_err(|err| anyhow!("step {}: {}", step.id, err))?;
+        if let Some(hooks) = step_hooks(step)? {
+            for hook in hooks.pre.iter().chain(&hooks.post) {
+                let program = hook
+                    .plugin
+                    .as_deref()
+                    .or(hook.command.first().map(String::as_str))
+                    .unwrap_or_default();
+                if !allow.hooks.iter().any(|allowed| allowed == program) {
+                    return Err(anyhow!(
+                        "step {}: hook {} is not in the agent's allow list",
+                        step.id,
+                        program
+                    ));
+                }
+            }
+        }
+        check_secrets(&step.params, allow).map_err(|err| anyhow!("step {}: {}", step.id, err))?;
+    }
+    Ok(bound)
+}
+
+/// The step's action must be a remote one whose target param is set, and
+/// every disk, device or mount it names must be `disk`'s. Outputs stay
+/// under `report_base` and the target mount.
+fn check_step_target(step: &WorkflowStep, disk: &Disk, report_base: &Path) -> Result<()> {
+    let params = &step.params;
+    let (_, target_key) = REMOTE_ACTIONS
+        .iter()
+        .find(|(action, _)| *action == step.action)
+        .ok_or_else(|| anyhow!("{} is not allowed in remote jobs", step.action))?;
+    if optional_string(params, target_key).is_none() {
+        return Err(anyhow!("{} must name the claimed disk {}", target_key, disk.id));
+    }
+    if params.get("allow_system_target").and_then(Value::as_bool) == Some(true) {
+        return Err(anyhow!("allow_system_target is not allowed in remote jobs"));
+    }
+    for key in DISK_ID_PARAMS {
+        if let Some(id) = optional_string(params, key) {
+            if !id.eq_ignore_ascii_case(&disk.id) {
+                return Err(anyhow!("{} {} is not the claimed disk {}", key, id, disk.id));
+            }
+        }
+    }
+    let device = crate::resize::device_path(disk)?;
+    for key in DEVICE_PARAMS {
+        if let Some(path) = optional_string(params, key) {
+            let on_disk = Path::new(path) == device
+                || disk_id_from_device_path(Path::new(path))
+                    .is_some_and(|id| id.eq_ignore_ascii_case(&disk.id));
+            if !on_disk {
+                return Err(anyhow!("{} {} is not on the claimed disk {}", key, path, disk.id));
+            }
+        }
+    }
+    for key in MOUNT_PARAMS {
+        if let Some(mount) = optional_string(params, key) {
+            let path = Path::new(mount);
+            let on_disk = !path.components().any(|part| part == Component::ParentDir)
+                && disk
+                    .partitions
+                    .iter()
+                    .flat_map(|partition| partition.mount_points.iter())
+                    .any(|point| path.starts_with(point));
+            if !on_disk {
+                return Err(anyhow!("{} {} is not on the claimed disk {}", key, mount, disk.id));
+            }
+        }
+    }
+    if let Some(id) = optional_string(params, "partition_id") {
+        if !disk.partitions.iter().any(|partition| partition.id.eq_ignore_ascii_case(id)) {
+            return Err(anyhow!("partition_id {} is not on the claimed disk {}", id, disk.id));
+        }
+    }
+    if let Some(base) = optional_string(params, "report_base") {
+        let path = Path::new(base);
+        if path.components().any(|part| part == Component::ParentDir)
+            || !path.starts_with(report_base)
+        {
+            return Err(anyhow!("report_base {} is outside {}", base, report_base.display()));
+        }
+    }
+    for key in TARGET_RELATIVE_PARAMS {
+        if let Some(relative) = optional_string(params, key) {
+            let inside = Path::new(relative)
+                .components()
+                .all(|part| matches!(part, Component::Normal(_) | Component::CurDir));
+            if !inside {
+                return Err(anyhow!("{} {} must be relative to the target", key, relative));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// `secret://<name>` values, and `*_secret` params (which name a secret
+/// outright), must name allowed secrets.
+fn check_secrets(value: &Value, allow: &RemoteAllowList) -> Result<()> {
+    let allowed = |name: &str| {
+        if allow.secrets.iter().any(|allowed| allowed == name) {
+            Ok(())
+        } else {
+            Err(anyhow!("secret {} is not in the agent's allow list", name))
+        }
+    };
+    match value {
+        Value::String(text) => text.strip_prefix(SECRET_SCHEME).map_or(Ok(()), allowed),
+        Value::Array(items) => items.iter().try_for_each(|item| check_secrets(item, allow)),
+        Value::Object(map) => map.iter().try_for_each(|(key, item)| match item {
+            Value::String(name) if key.ends_with("_secret") => {
+                allowed(name.strip_prefix(SECRET_SCHEME).unwrap_or(name))
+            }
+            _ => check_secrets(item, allow),
+        }),
+        _ => Ok(()),
+    }
+}
+
+enum ClaimError {
+    Unauthorized(u16),
+    Other(anyhow::Error),
+}
+
+struct AgentClient {
+    server: String,
+    token: Option<String>,
+    tls: Option<ClientTls>,
+}
+
+impl AgentClient {
+    fn request(&self, method: &str, path: &str, timeout: Duration) -> Result<ureq::Request> {
+        let url = format!("{}{}", self.server, path);
+        let builder = match &self.tls {
+            Some(tls) => phoenix_fetch::agent_builder_with_tls(&url, tls)?,
+            None => phoenix_fetch::agent_builder(&url)?,
+        };
+        let mut request = builder
+            .build()
+            .request(method, &url)
+            .timeout(timeout);
+        if let Some(token) = &self.token {
+            request = request.set("Authorization", &format!("Bearer {}", token));
+        }
+        Ok(request)
+    }
+
+    /// `None` when the server has nothing for this agent (HTTP 204).
+    fn claim(&self, claim: &AgentClaim) -> std::result::Result<Option<AgentJob>, ClaimError> {
+        let request = self
+            .request("POST", "/v1/jobs/claim", REQUEST_TIMEOUT)
+            .map_err(ClaimError::Other)?
+            .set("Content-Type", "application/json");
+        let body = serde_json::to_string(claim).map_err(|err| ClaimError::Other(err.into()))?;
+        match request.send_string(&body) {
+            Ok(response) if response.status() == 204 => Ok(None),
+            Ok(response) => {
+                let text = response
+                    .into_string()
+                    .map_err(|err| ClaimError::Other(err.into()))?;
+                let job: AgentJob = serde_json::from_str(&text)
+                    .map_err(|err| ClaimError::Other(anyhow!("bad job from server: {}", err)))?;
+                // The id goes into request paths.
+                if job.job_id.is_empty()
+                    || !job
+                        .job_id
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+                {
+                    return Err(ClaimError::Other(anyhow!("bad job id from server: {}", job.job_id)));
+                }
+                Ok(Some(job))
+            }
+            Err(ureq::Error::Status(code @ (401 | 403), _)) => Err(ClaimError::Unauthorized(code)),
+            Err(err) => Err(ClaimError::Other(http_error(err))),
+        }
+    }
+
+    /// `PUT /v1/jobs/<job_id>/report` with the bundle as a zip, streamed
+    /// from a temp file so a large bundle never sits in memory.
+    fn upload_report(&self, job_id: &str, report_root: &Path) -> Result<()> {
+        let mut suffix = [0u8; 8];
+        getrandom::fill(&mut suffix).map_err(|err| anyhow!("temp file name: {}", err))?;
+        let guard = CleanupGuard::register(
+            CleanupAction::RemoveFile,
+            &std::env::temp_dir().join(format!(
+                "phoenix-agent-{}-{}.zip",
+                std::process::id(),
+                to_hex(&suffix)
+            )),
+        );
+        // `create_new` refuses a file or symlink already at the path.
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(guard.path())
+            .with_context(|| format!("create {}", guard.path().display()))?;
+        let options = ZipExportOptions::default();
+        let mut file = write_report_zip(report_root, io::BufWriter::new(file), &options)?
+            .into_inner()
+            .map_err(|err| err.into_error())?;
+        let bytes = file.seek(SeekFrom::End(0))?;
+        file.seek(SeekFrom::Start(0))?;
+        self.request("PUT", &format!("/v1/jobs/{}/report", job_id), UPLOAD_TIMEOUT)?
+            .set("Content-Type", "application/zip")
+            .set("Content-Length", &bytes.to_string())
+            .send(file)
+            .map(|_| ())
+            .map_err(http_error)
+    }
+
+    fn post_result(&self, job: &AgentJobRun) -> Result<()> {
+        self.request("POST", &format!("/v1/jobs/{}/result", job.job_id), REQUEST_TIMEOUT)?
+            .set("Content-Type", "application/json")
+            .send_string(&serde_json::to_string(job)?)
+            .map(|_| ())
+            .map_err(http_error)
+    }
+}
+
+fn http_error(err: ureq::Error) -> anyhow::Error {
+    match err {
+        ureq::Error::Status(code, _) => anyhow!("HTTP {}", code),
+        ureq::Error::Transport(transport) => anyhow!(
+            "{}: {}",
+            transport.kind(),
+            transport.message().unwrap_or("no details")
+        ),
+    }
+}
@@ -0,0 +1,60 @@
+//! `asset://<sha256>[/<file name>]` references in workflow step params,
+//! resolved to files in the local asset store when the step runs. A pack
+//! fills the store with `cache_pack_assets`; a reference to an asset the
+//! store lacks fails the step instead of downloading it mid-run.
+
+use crate::ledger::state_dir;
+use anyhow::{anyhow, Result};
+use phoenix_content::AssetStore;
+use serde_json::Value;
+use std::path::PathBuf;
+
+pub const ASSET_SCHEME: &str = "asset://";
+
+/// `$PHOENIX_ASSET_DIR`, else `assets/` in the state directory.
+pub fn asset_store() -> Result<AssetStore> {
+    let root = match std::env::var("PHOENIX_ASSET_DIR") {
+        Ok(dir) => PathBuf::from(dir),
+        Err(_) => state_dir()?.join("assets"),
+    };
+    Ok(AssetStore::new(root))
+}
+
+/// The stored file a reference (without the scheme) names.
+pub fn resolve_asset(reference: &str) -> Result<PathBuf> {
+    let store = asset_store()?;
+    let found = match reference.split_once('/') {
+        Some((sha256, name)) => store.get_named(sha256, name)?,
+        None => store.get(reference)?,
+    };
+    found.ok_or_else(|| {
+        anyhow!(
+            "asset {} is not in the asset store {}; cache its pack first",
+            reference,
+            store.root().display()
+        )
+    })
+}
+
+/// `params` with every string that is exactly `asset://<reference>`
+/// replaced by the stored file's path.
+pub(crate) fn resolve_asset_params(params: &Value) -> Result<Value> {
+    Ok(match params {
+        Value::String(text) => match text.strip_prefix(ASSET_SCHEME) {
+            Some(reference) => Value::String(resolve_asset(reference)?.display().to_string()),
+            None => params.clone(),
+        },
+        Value::Array(items) => Value::Array(
+            items
+                .iter()
+                .map(resolve_asset_params)
+                .collect::<Result<_>>()?,
+        ),
+        Value::Object(map) => Value::Object(
+            map.iter()
+                .map(|(key, value)| Ok((key.clone(), resolve_asset_params(value)?)))
+                .collect::<Result<_>>()?,
+        ),
+        _ => params.clone(),
+    })
+}
@@ -0,0 +1,147 @@
+//! Re-checks finished media against the copy manifest of the run that
+//! built it, so a stick that sat in a drawer can be trusted (or not)
+//! before it is handed out.
+
+use crate::filter::SourceFilter;
+use crate::{collect_files, hash_file, normalize_mount_for_unix, signing_key_from_env};
+use anyhow::{anyhow, Context, Result};
+use phoenix_report::{read_report_artifact, verify_report_bundle};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+const COPY_MANIFEST_FILE: &str = "copy_manifest.json";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MediaAuditParams {
+    pub mount: PathBuf,
+    /// Report bundle of the run that wrote the media.
+    pub report: PathBuf,
+    /// Patterns for files written after the copy (drivers, staged tools,
+    /// setup selections) that should not count as added.
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    /// Key for a signed report; defaults to `PHOENIX_SIGNING_KEY`.
+    #[serde(default)]
+    pub signing_key: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModifiedFile {
+    pub path: String,
+    pub expected_bytes: u64,
+    pub actual_bytes: u64,
+    pub expected_sha256: String,
+    /// `None` when the sizes already differ and the file was not hashed.
+    pub actual_sha256: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MediaAuditResult {
+    pub mount: PathBuf,
+    pub report: PathBuf,
+    pub run_id: String,
+    pub checked_files: usize,
+    pub matching_files: usize,
+    pub added: Vec<String>,
+    pub modified: Vec<ModifiedFile>,
+    pub removed: Vec<String>,
+}
+
+impl MediaAuditResult {
+    pub fn unchanged(&self) -> bool {
+        self.added.is_empty() && self.modified.is_empty() && self.removed.is_empty()
+    }
+}
+
+#[derive(Deserialize)]
+struct ManifestFile {
+    path: String,
+    bytes: u64,
+    sha256: String,
+}
+
+/// Hashes every file the manifest lists and walks the mount for files it
+/// does not. The report itself must verify first; a tampered baseline
+/// proves nothing.
+pub fn run_media_audit(params: &MediaAuditParams) -> Result<MediaAuditResult> {
+    let mount = normalize_mount_for_unix(&params.mount);
+    if !mount.is_dir() {
+        return Err(anyhow!("mount is not a directory: {}", mount.display()));
+    }
+    let key = params.signing_key.clone().or_else(signing_key_from_env);
+    let verification = verify_report_bundle(&params.report, key.as_deref())?;
+    if !verification.ok {
+        return Err(anyhow!(
+            "report {} does not verify; run report-verify for details",
+            params.report.display()
+        ));
+    }
+    let run: serde_json::Value = serde_json::from_slice(
+        &read_report_artifact(&params.report, "run.json").context("read run.json")?,
+    )?;
+    let run_id = run
+        .get("run_id")
+        .and_then(serde_json::Value::as_str)
+        .unwrap_or_default()
+        .to_string();
+    let manifest = read_report_artifact(&params.report, COPY_MANIFEST_FILE).map_err(|_| {
+        anyhow!(
+            "report has no {}; only runs with hash_manifest can be audited",
+            COPY_MANIFEST_FILE
+        )
+    })?;
+    let manifest: Vec<ManifestFile> =
+        serde_json::from_slice(&manifest).context("parse copy manifest")?;
+    let expected: BTreeMap<String, ManifestFile> = manifest
+        .into_iter()
+        .map(|entry| (entry.path.replace('\\', "/"), entry))
+        .collect();
+
+    let filter = SourceFilter::new(Vec::new(), params.exclude.clone())?;
+    let mut on_media = BTreeMap::new();
+    for entry in collect_files(&mount)? {
+        let relative = entry.relative_path.to_string_lossy().replace('\\', "/");
+        on_media.insert(relative, entry);
+    }
+
+    let mut result = MediaAuditResult {
+        mount: mount.clone(),
+        report: params.report.clone(),
+        run_id,
+        checked_files: expected.len(),
+        matching_files: 0,
+        added: Vec::new(),
+        modified: Vec::new(),
+        removed: Vec::new(),
+    };
+    for (path, file) in &expected {
+        let Some(entry) = on_media.remove(path) else {
+            result.removed.push(path.clone());
+            continue;
+        };
+        let actual_sha256 = if entry.size == file.bytes {
+            let hash = hash_file(&entry.absolute_path)?;
+            if hash.eq_ignore_ascii_case(&file.sha256) {
+                result.matching_files += 1;
+                continue;
+            }
+            Some(hash)
+        } else {
+            None
+        };
+        result.modified.push(ModifiedFile {
+            path: path.clone(),
+            expected_bytes: file.bytes,
+            actual_bytes: entry.size,
+            expected_sha256: file.sha256.clone(),
+            actual_sha256,
+        });
+    }
+    result.added = on_media
+        .into_iter()
+        .filter(|(_, entry)| filter.matches(&entry.relative_path))
+        .map(|(path, _)| path)
+        .collect();
+    Ok(result)
+}
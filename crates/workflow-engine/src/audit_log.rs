@@ -0,0 +1,95 @@
+//! Append-only log of safety overrides and of jobs a fleet server had a
+//! bench run: one JSON object per line in `audit.jsonl`, so a line
+//! supervisor can see who pushed a run past a policy, or started it
+//! remotely, and why.
+
+use crate::ledger::state_dir;
+use anyhow::{anyhow, Context, Result};
+use phoenix_core::{now_utc_rfc3339, Disk};
+use phoenix_report::AgentIdentity;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub time_utc: String,
+    /// What was overridden, e.g. `device_wear_override`, or `agent_job`.
+    pub event: String,
+    pub workflow: String,
+    pub target_disk: String,
+    pub target_serial: Option<String>,
+    pub reason: String,
+    #[serde(default)]
+    pub correlation_id: Option<String>,
+    /// Login of the process owner, when the platform says.
+    #[serde(default)]
+    pub user: Option<String>,
+    /// The fleet agent's certificate identity, when running as one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub agent: Option<AgentIdentity>,
+}
+
+impl AuditEntry {
+    pub fn new(event: &str, workflow: &str, disk: &Disk, reason: &str) -> Self {
+        Self {
+            time_utc: now_utc_rfc3339(),
+            event: event.to_string(),
+            workflow: workflow.to_string(),
+            target_disk: disk.id.clone(),
+            target_serial: disk.serial.clone(),
+            reason: reason.to_string(),
+            correlation_id: phoenix_report::correlation_id(),
+            user: ["USER", "USERNAME"]
+                .into_iter()
+                .find_map(|name| std::env::var(name).ok().filter(|value| !value.is_empty())),
+            agent: phoenix_report::agent_identity().cloned(),
+        }
+    }
+}
+
+/// `$PHOENIX_AUDIT_LOG`, else `audit.jsonl` in the state directory.
+pub fn audit_log_path() -> Result<PathBuf> {
+    match std::env::var("PHOENIX_AUDIT_LOG") {
+        Ok(path) => Ok(PathBuf::from(path)),
+        Err(_) => Ok(state_dir()?.join("audit.jsonl")),
+    }
+}
+
+/// Appends `entry` and syncs it. Callers fail the run when this fails, so
+/// no override goes unrecorded.
+pub fn append_audit_entry(entry: &AuditEntry) -> Result<()> {
+    let path = audit_log_path()?;
+    if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        fs::create_dir_all(parent).with_context(|| format!("create {}", parent.display()))?;
+    }
+    let mut line = serde_json::to_vec(entry)?;
+    line.push(b'\n');
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .with_context(|| format!("open audit log {}", path.display()))?;
+    file.write_all(&line)
+        .and_then(|_| file.sync_all())
+        .with_context(|| format!("write audit log {}", path.display()))
+}
+
+/// Every entry, oldest first. A missing log is empty.
+pub fn read_audit_log() -> Result<Vec<AuditEntry>> {
+    let path = audit_log_path()?;
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(anyhow!("read {} failed: {}", path.display(), err)),
+    };
+    text.lines()
+        .filter(|line| !line.trim().is_empty())
+        .enumerate()
+        .map(|(index, line)| {
+            serde_json::from_str(line)
+                .with_context(|| format!("parse {} entry {}", path.display(), index + 1))
+        })
+        .collect()
+}
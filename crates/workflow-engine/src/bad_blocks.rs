@@ -0,0 +1,354 @@
+//! Bad block scan of a target before it is used, so a duplication line
+//! can bin a failing stick before spending time flashing it. The read
+//! scan is safe on any disk; the write scan destroys the contents and is
+//! gated like an image write.
+
+use crate::ledger::RunTracker;
+use crate::operation::{DestructiveOperation, DestructiveSession};
+use crate::{
+    build_device_graph, check_overwrite, check_target_claim, check_target_disk_size,
+    check_target_wear, join_reasons, resolve_chunk_size, signing_key_from_env, target, StepLog,
+};
+use anyhow::{anyhow, Result};
+use phoenix_imaging::{ScanMode, ScanObserver, ScanOptions, ScanProgress, ScanResult};
+use phoenix_report::{
+    create_report_bundle_with_meta_signing_and_artifacts, ReportArtifact, ReportPaths,
+};
+use phoenix_safety::SafetyContext;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+/// Report artifact with the scan result and its bad ranges.
+pub const BAD_BLOCKS_FILE_NAME: &str = "bad_blocks.json";
+const DEFAULT_SECTOR_SIZE: u64 = 512;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BadBlockScanParams {
+    pub disk_id: String,
+    #[serde(default = "crate::params::default_scan_mode")]
+    pub mode: ScanMode,
+    /// `None` probes the device for the fastest chunk size.
+    #[serde(default, with = "crate::params::chunk_size")]
+    pub chunk_size: Option<u64>,
+    /// Granularity of the bad ranges; 512 by default.
+    #[serde(default, with = "crate::params::byte_size")]
+    pub sector_size: Option<u64>,
+    /// Stop once more than this many bytes are bad; the stick fails anyway.
+    #[serde(default, with = "crate::params::byte_size")]
+    pub max_bad_bytes: Option<u64>,
+    #[serde(default = "crate::params::default_report_base")]
+    pub report_base: PathBuf,
+    /// The rest only apply to a write scan; a read scan always runs.
+    #[serde(default)]
+    pub force: bool,
+    #[serde(default)]
+    pub confirmation_token: Option<String>,
+    #[serde(default)]
+    pub acknowledge_target_size: bool,
+    /// Proceed on a device past the wear limits; recorded in the report
+    /// and the audit log.
+    #[serde(default)]
+    pub acknowledge_device_wear: bool,
+    #[serde(default)]
+    pub confirm_overwrite: bool,
+    #[serde(default = "crate::params::default_true")]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BadBlockScanResult {
+    pub report: ReportPaths,
+    pub disk_id: String,
+    pub mode: ScanMode,
+    /// Empty for a dry run.
+    pub scan: ScanResult,
+    pub dry_run: bool,
+}
+
+impl BadBlockScanResult {
+    pub fn passed(&self) -> bool {
+        self.scan.bad_ranges.is_empty()
+    }
+}
+
+pub fn parse_scan_mode(value: &str) -> Result<ScanMode> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "read" => Ok(ScanMode::Read),
+        "write" => Ok(ScanMode::Write),
+        other => Err(anyhow!("unknown scan mode: {} (read, write)", other)),
+    }
+}
+
+pub fn run_bad_block_scan(params: &BadBlockScanParams) -> Result<BadBlockScanResult> {
+    let started = Instant::now();
+    let graph = build_device_graph()?;
+    let disk = graph
+        .disks
+        .iter()
+        .find(|disk| disk.id.eq_ignore_ascii_case(&params.disk_id))
+        .ok_or_else(|| anyhow!("disk not found: {}", params.disk_id))?;
+    let destructive = params.mode == ScanMode::Write;
+    let dry_run = destructive && params.dry_run;
+
+    let mut target_size_acknowledged = None;
+    let mut device_wear = None;
+    let mut overwrite_triggers = Vec::new();
+    if destructive {
+        if !cfg!(any(target_os = "linux", target_os = "macos")) {
+            return Err(anyhow!("write scan requires linux or macos"));
+        }
+        if disk.is_system_disk {
+            return Err(anyhow!("refusing to target system disk: {}", disk.id));
+        }
+        if !disk.removable {
+            return Err(anyhow!("target disk is not marked removable: {}", disk.id));
+        }
+        if let Some(reason) = target::stack_usage(&graph, disk) {
+            return Err(anyhow!(reason));
+        }
+        target_size_acknowledged = check_target_disk_size(disk, params.acknowledge_target_size)?;
+        let device_claim = check_target_claim(disk, "bad-block-scan", dry_run)?;
+        device_wear = join_reasons(
+            device_claim,
+            check_target_wear(disk, "bad-block-scan", params.acknowledge_device_wear, dry_run)?,
+        );
+        overwrite_triggers = check_overwrite(disk, params.confirm_overwrite, dry_run)?;
+    }
+    let sector_size = params.sector_size.unwrap_or(DEFAULT_SECTOR_SIZE);
+    let device = scan_device_path(disk)?;
+
+    let mut logs = StepLog::new("bad-block-scan");
+    logs.push(format!("disk_id={}", disk.id));
+    logs.push(format!("device={}", device.display()));
+    logs.push(format!("mode={}", params.mode.as_str()));
+    logs.push(format!("sector_size={}", sector_size));
+    if let Some(reason) = &target_size_acknowledged {
+        logs.push(format!("target_size_acknowledged={}", reason));
+    }
+    if let Some(reason) = &device_wear {
+        logs.push(format!("device_wear={}", reason));
+    }
+    for trigger in &overwrite_triggers {
+        logs.push(format!("overwrite_trigger={}", trigger));
+    }
+    logs.push(format!("dry_run={}", dry_run));
+
+    let mut scan = ScanResult::default();
+    let mut chunk_size = params.chunk_size.unwrap_or(phoenix_imaging::DEFAULT_CHUNK_SIZE);
+    let mut chunk_tuning = serde_json::Value::Null;
+    // Only the write scan changes the disk; a read scan needs no session.
+    let mut session = None;
+    if destructive {
+        let ctx = SafetyContext {
+            force_mode: params.force,
+            confirmation_token: params.confirmation_token.clone(),
+            allow_system_disk: false,
+            armed_until: phoenix_safety::armed_until(),
+        };
+        session = Some(DestructiveSession::begin(
+            "bad-block-scan",
+            Some(disk),
+            &ctx,
+            disk.is_system_disk,
+            dry_run,
+            &mut logs,
+        )?);
+    }
+    if !dry_run {
+        (chunk_size, chunk_tuning) =
+            resolve_chunk_size(params.chunk_size, &device, disk.size_bytes, &mut logs);
+    }
+    let options = ScanOptions {
+        chunk_size: chunk_size.div_ceil(sector_size) * sector_size,
+        sector_size,
+        max_bad_bytes: params.max_bad_bytes,
+        seed: pattern_seed(),
+    };
+    match &mut session {
+        Some(session) => {
+            let mut write_scan = WriteScan {
+                disk,
+                device: &device,
+                options: &options,
+            };
+            if let Some(result) = session.perform(&mut write_scan, &mut logs)? {
+                scan = result;
+            }
+        }
+        None => {
+            logs.phase("scan");
+            scan = scan_disk(disk, &device, params.mode, &options, &mut logs)?;
+        }
+    }
+    if !dry_run {
+        logs.push(format!("scanned_bytes={}", scan.scanned_bytes));
+        logs.push(format!("bad_bytes={}", scan.bad_bytes()));
+        for range in &scan.bad_ranges {
+            logs.push(format!(
+                "bad offset={} length={} kind={}",
+                range.offset,
+                range.length,
+                range.kind.as_str()
+            ));
+        }
+        if scan.stopped_early {
+            logs.push("stopped_early=true".to_string());
+        }
+    }
+
+    let (log_text, timing) = logs.finish()?;
+
+    let artifact = ReportArtifact::json(
+        BAD_BLOCKS_FILE_NAME,
+        &serde_json::json!({
+            "mode": params.mode,
+            "sector_size": sector_size,
+            "total_bytes": disk.size_bytes,
+            "scanned_bytes": scan.scanned_bytes,
+            "bad_bytes": scan.bad_bytes(),
+            "stopped_early": scan.stopped_early,
+            "bad_ranges": scan.bad_ranges
+        }),
+    )?;
+    let meta = serde_json::json!({
+        "workflow": "bad-block-scan",
+        "disk_id": disk.id,
+        "target_serial": disk.serial,
+        "mode": params.mode,
+        "sector_size": sector_size,
+        "chunk_size": chunk_size,
+        "chunk_tuning": chunk_tuning,
+        "max_bad_bytes": params.max_bad_bytes,
+        "scanned_bytes": scan.scanned_bytes,
+        "bad_bytes": scan.bad_bytes(),
+        "bad_range_count": scan.bad_ranges.len(),
+        "stopped_early": scan.stopped_early,
+        "passed": scan.bad_ranges.is_empty(),
+        "target_size_acknowledged": target_size_acknowledged,
+        "device_wear": device_wear,
+        "device_wear_acknowledged": params.acknowledge_device_wear,
+        "overwrite_triggers": overwrite_triggers,
+        "overwrite_confirmed": params.confirm_overwrite,
+        "dry_run": dry_run,
+        "duration_ms": started.elapsed().as_millis() as u64
+    });
+
+    let report = create_report_bundle_with_meta_signing_and_artifacts(
+        &params.report_base,
+        &graph,
+        Some(meta),
+        Some(&log_text),
+        signing_key_from_env().as_deref(),
+        &[artifact, timing],
+    )?;
+    if let Some(session) = session {
+        session.complete(&report.root)?;
+    }
+
+    Ok(BadBlockScanResult {
+        report,
+        disk_id: disk.id.clone(),
+        mode: params.mode,
+        scan,
+        dry_run,
+    })
+}
+
+fn scan_device_path(disk: &phoenix_core::Disk) -> Result<PathBuf> {
+    if phoenix_core::mock::is_active() {
+        return Ok(phoenix_core::mock::disk_file(disk)?);
+    }
+    #[cfg(target_os = "windows")]
+    {
+        Ok(PathBuf::from(format!(r"\\.\{}", disk.id)))
+    }
+    #[cfg(target_os = "macos")]
+    {
+        // The raw node skips the buffer cache, which is also what the
+        // write scan's read-back needs.
+        Ok(phoenix_host_macos::raw_device_path(Path::new(&format!(
+            "/dev/{}",
+            disk.id
+        ))))
+    }
+    #[cfg(not(any(target_os = "windows", target_os = "macos")))]
+    {
+        Ok(PathBuf::from(format!("/dev/{}", disk.id)))
+    }
+}
+
+/// A write scan unmounts the disk first; on macOS it holds the same
+/// exclusive claim as an image write.
+fn scan_disk(
+    disk: &phoenix_core::Disk,
+    device: &Path,
+    mode: ScanMode,
+    options: &ScanOptions,
+    logs: &mut StepLog,
+) -> Result<ScanResult> {
+    let mut observer = CancelObserver;
+    if mode == ScanMode::Read || phoenix_core::mock::is_active() {
+        return phoenix_imaging::scan_device(device, disk.size_bytes, mode, options, &mut observer);
+    }
+    #[cfg(target_os = "macos")]
+    {
+        let mut claimed = phoenix_host_macos::open_device_exclusive(device, true)?;
+        crate::log_exclusive_open(disk, &claimed, logs);
+        phoenix_imaging::scan_open_device(
+            &mut claimed.file,
+            disk.size_bytes,
+            mode,
+            options,
+            &mut observer,
+        )
+    }
+    #[cfg(not(target_os = "macos"))]
+    {
+        crate::unmount_target_disk(disk, logs)?;
+        phoenix_imaging::scan_device(device, disk.size_bytes, mode, options, &mut observer)
+    }
+}
+
+/// The destructive write scan: patterns written over the whole disk and
+/// read back.
+struct WriteScan<'a> {
+    disk: &'a phoenix_core::Disk,
+    device: &'a Path,
+    options: &'a ScanOptions,
+}
+
+impl DestructiveOperation for WriteScan<'_> {
+    type Output = ScanResult;
+
+    fn description(&self) -> String {
+        format!("write-scan {} (erases it)", self.device.display())
+    }
+
+    fn phase(&self) -> &'static str {
+        "write_scan"
+    }
+
+    fn disk_id(&self) -> Option<&str> {
+        Some(&self.disk.id)
+    }
+
+    fn execute(&mut self, logs: &mut StepLog, _tracker: Option<&mut RunTracker>) -> Result<ScanResult> {
+        scan_disk(self.disk, self.device, ScanMode::Write, self.options, logs)
+    }
+}
+
+struct CancelObserver;
+
+impl ScanObserver for CancelObserver {
+    fn on_progress(&mut self, _progress: ScanProgress) -> bool {
+        !crate::cancel::is_cancelled()
+    }
+}
+
+fn pattern_seed() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|duration| duration.as_nanos() as u64)
+        .unwrap_or(0)
+}
@@ -0,0 +1,95 @@
+//! Named chunk-map baselines for `disk_hash_report`. A golden master stick
+//! is hashed once with `save_baseline`; later runs with `compare_baseline`
+//! report which byte ranges changed since, so drift between provisioning
+//! sessions shows up before the stick is copied again.
+
+use crate::ledger::{state_dir, write_record};
+use anyhow::{anyhow, Result};
+use phoenix_hashmap::{parse_hashmap, ChunkHashMap, ChunkRange};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+/// Report artifact holding the `BaselineDrift` of a compared run.
+pub const DRIFT_FILE_NAME: &str = "baseline_drift.json";
+
+/// How a hashed disk differs from a stored baseline.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BaselineDrift {
+    pub baseline: String,
+    pub baseline_source: Option<String>,
+    pub baseline_generated_at_utc: Option<String>,
+    pub drifted: bool,
+    /// Set when the device is not the size the baseline was taken at.
+    pub size_changed: bool,
+    pub changed_chunks: usize,
+    pub changed_bytes: u64,
+    pub changed_ranges: Vec<ChunkRange>,
+    /// Chunks hashed on one side only, e.g. under `max_chunks`; not
+    /// counted as drift.
+    pub unmatched_chunks: usize,
+}
+
+/// `$PHOENIX_BASELINE_DIR`, else `baselines/` in the state directory.
+pub fn baseline_dir() -> Result<PathBuf> {
+    match std::env::var("PHOENIX_BASELINE_DIR") {
+        Ok(dir) => Ok(PathBuf::from(dir)),
+        Err(_) => Ok(state_dir()?.join("baselines")),
+    }
+}
+
+pub fn validate_baseline_name(name: &str) -> Result<()> {
+    if name.is_empty() || name.contains(['/', '\\']) || name.starts_with('.') {
+        return Err(anyhow!("baseline name must be a plain name: {}", name));
+    }
+    Ok(())
+}
+
+fn baseline_path(name: &str) -> Result<PathBuf> {
+    validate_baseline_name(name)?;
+    Ok(baseline_dir()?.join(format!("{}.json", name)))
+}
+
+pub fn load_baseline(name: &str) -> Result<ChunkHashMap> {
+    let path = baseline_path(name)?;
+    let data = match std::fs::read(&path) {
+        Ok(data) => data,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            return Err(anyhow!("no baseline named {} in {}", name, path.display()));
+        }
+        Err(err) => return Err(anyhow!("read baseline {} failed: {}", path.display(), err)),
+    };
+    parse_hashmap(&data).map_err(|err| anyhow!("baseline {}: {}", name, err))
+}
+
+/// Stores `map` under `name`, replacing any earlier baseline of that name.
+pub fn save_baseline(name: &str, map: &ChunkHashMap) -> Result<PathBuf> {
+    let path = baseline_path(name)?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    write_record(&path, map)?;
+    Ok(path)
+}
+
+pub fn compare_to_baseline(
+    name: &str,
+    baseline: &ChunkHashMap,
+    current: &ChunkHashMap,
+) -> Result<BaselineDrift> {
+    let comparison = current
+        .compare(baseline)
+        .map_err(|err| anyhow!("baseline {}: {}", name, err))?;
+    let changed_ranges = comparison.differing_ranges(current);
+    let size_changed = baseline.total_bytes != current.total_bytes;
+    Ok(BaselineDrift {
+        baseline: name.to_string(),
+        baseline_source: baseline.source.clone(),
+        baseline_generated_at_utc: baseline.generated_at_utc.clone(),
+        drifted: size_changed || !comparison.differing.is_empty(),
+        size_changed,
+        changed_chunks: comparison.differing.len(),
+        changed_bytes: changed_ranges.iter().map(|range| range.length).sum(),
+        changed_ranges,
+        unmatched_chunks: comparison.only_left.len() + comparison.only_right.len(),
+    })
+}
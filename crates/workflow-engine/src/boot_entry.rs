@@ -0,0 +1,206 @@
+//! The `boot_entry` step: points the host's firmware at a system a
+//! workflow has just staged, through `phoenix-efivars`, so nobody has to
+//! pick it from the firmware setup by hand. On macOS the volume is
+//! blessed instead.
+
+use crate::ledger::RunTracker;
+use crate::operation::{DestructiveOperation, DestructiveSession};
+use crate::{current_os, normalize_mount_for_unix, report_graph, signing_key_from_env, StepLog};
+use anyhow::{anyhow, Result};
+use phoenix_efivars::{BootEntries, BootPosition, EspLocation, NewBootEntry};
+use phoenix_report::{create_report_bundle_with_meta_signing_and_artifacts, ReportPaths};
+use phoenix_safety::SafetyContext;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BootEntryParams {
+    /// The staged system's EFI system partition; on macOS, the volume to
+    /// bless.
+    pub target_mount: PathBuf,
+    /// Loader on the ESP, such as `\EFI\ubuntu\shimx64.efi`. Not used on
+    /// macOS.
+    #[serde(default)]
+    pub loader: Option<String>,
+    #[serde(default = "crate::params::default_boot_description")]
+    pub description: String,
+    #[serde(default = "crate::params::default_boot_position")]
+    pub position: BootPosition,
+    #[serde(default = "crate::params::default_report_base")]
+    pub report_base: PathBuf,
+    #[serde(default)]
+    pub force: bool,
+    #[serde(default)]
+    pub confirmation_token: Option<String>,
+    #[serde(default = "crate::params::default_true")]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BootEntryResult {
+    pub report: ReportPaths,
+    /// The `Boot####` written; `None` for dry runs and on macOS.
+    pub boot_number: Option<u16>,
+    pub dry_run: bool,
+}
+
+pub fn run_boot_entry(params: &BootEntryParams) -> Result<BootEntryResult> {
+    let target_mount = normalize_mount_for_unix(&params.target_mount);
+    if !target_mount.is_dir() {
+        return Err(anyhow!("target mount is invalid"));
+    }
+    let bless = current_os() == "macos";
+    let mut logs = StepLog::new("boot-entry");
+    logs.push(format!("target_mount={}", target_mount.display()));
+    logs.push(format!("position={}", params.position.as_str()));
+
+    let mut esp: Option<EspLocation> = None;
+    let mut loader = None;
+    let mut before: Option<BootEntries> = None;
+    if bless {
+        if params.position == BootPosition::Last {
+            return Err(anyhow!(
+                "bless makes a volume the startup disk (first) or boots it once (next)"
+            ));
+        }
+        logs.push(format!("bless next_only={}", params.position == BootPosition::Next));
+    } else {
+        let path = params
+            .loader
+            .as_deref()
+            .ok_or_else(|| anyhow!("loader is required for a UEFI boot entry"))?;
+        let relative = path.trim_start_matches(['/', '\\']).replace('\\', "/");
+        if relative.split('/').any(|part| part == "..") {
+            return Err(anyhow!("loader path {} leaves the ESP", path));
+        }
+        if !target_mount.join(&relative).is_file() {
+            return Err(anyhow!("{} not found on {}", path, target_mount.display()));
+        }
+        let location = phoenix_efivars::esp_location(&target_mount)?;
+        logs.push(format!(
+            "esp partition={} guid={} start_lba={} size_lba={}",
+            location.partition_number,
+            location.partition_guid,
+            location.start_lba,
+            location.size_lba
+        ));
+        logs.push(format!("loader={}", path));
+        before = Some(phoenix_efivars::list_boot_entries()?);
+        esp = Some(location);
+        loader = Some(path.to_string());
+    }
+
+    let ctx = SafetyContext {
+        force_mode: params.force,
+        confirmation_token: params.confirmation_token.clone(),
+        allow_system_disk: false,
+        armed_until: phoenix_safety::armed_until(),
+    };
+    let mut session =
+        DestructiveSession::begin("boot-entry", None, &ctx, false, params.dry_run, &mut logs)?;
+    let mut write = WriteBootEntry {
+        target_mount: &target_mount,
+        description: &params.description,
+        position: params.position,
+        esp: esp.as_ref(),
+        loader: loader.as_deref(),
+    };
+    let boot_number = session.perform(&mut write, &mut logs)?.flatten();
+    let mut after = None;
+    if boot_number.is_some() {
+        after = Some(phoenix_efivars::list_boot_entries()?);
+    }
+    if params.dry_run {
+        logs.push("dry_run=true".to_string());
+    }
+
+    let (log_text, timing) = logs.finish()?;
+    let meta = serde_json::json!({
+        "workflow": "boot-entry",
+        "status": if params.dry_run { "dry_run" } else { "completed" },
+        "target_mount": target_mount.display().to_string(),
+        "method": if bless { "bless" } else { "uefi" },
+        "description": params.description,
+        "loader": loader,
+        "position": params.position.as_str(),
+        "esp": esp,
+        "boot_number": boot_number,
+        "boot_entries_before": before,
+        "boot_entries_after": after,
+        "dry_run": params.dry_run
+    });
+    let report = create_report_bundle_with_meta_signing_and_artifacts(
+        &params.report_base,
+        &report_graph(),
+        Some(meta),
+        Some(&log_text),
+        signing_key_from_env().as_deref(),
+        &[timing],
+    )?;
+
+    Ok(BootEntryResult {
+        report,
+        boot_number,
+        dry_run: params.dry_run,
+    })
+}
+
+/// A UEFI `Boot####` entry for `loader` on `esp`; without them, `bless`
+/// of the volume. Returns the entry number written.
+struct WriteBootEntry<'a> {
+    target_mount: &'a Path,
+    description: &'a str,
+    position: BootPosition,
+    esp: Option<&'a EspLocation>,
+    loader: Option<&'a str>,
+}
+
+impl DestructiveOperation for WriteBootEntry<'_> {
+    type Output = Option<u16>;
+
+    fn description(&self) -> String {
+        match self.loader {
+            Some(loader) => format!("add boot entry {} for {}", self.description, loader),
+            None => format!("bless {}", self.target_mount.display()),
+        }
+    }
+
+    fn phase(&self) -> &'static str {
+        "boot_entry"
+    }
+
+    fn disk_id(&self) -> Option<&str> {
+        None
+    }
+
+    fn pre_check(&self) -> Result<()> {
+        if !phoenix_core::mock::is_active() && !crate::doctor::is_elevated() {
+            return Err(anyhow!("writing boot entries needs an elevated process"));
+        }
+        Ok(())
+    }
+
+    fn execute(&mut self, logs: &mut StepLog, _tracker: Option<&mut RunTracker>) -> Result<Option<u16>> {
+        match (self.esp, self.loader) {
+            (Some(esp), Some(loader)) => {
+                let number = phoenix_efivars::create_boot_entry(
+                    &NewBootEntry {
+                        description: self.description.to_string(),
+                        esp: esp.clone(),
+                        loader: loader.to_string(),
+                    },
+                    self.position,
+                )?;
+                logs.push(format!("boot_entry=Boot{:04X}", number));
+                Ok(Some(number))
+            }
+            _ => {
+                phoenix_efivars::bless_volume(
+                    self.target_mount,
+                    self.position == BootPosition::Next,
+                )?;
+                Ok(None)
+            }
+        }
+    }
+}
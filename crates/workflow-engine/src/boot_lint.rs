@@ -0,0 +1,142 @@
+//! Boot lint: checks a GRUB menu against the volumes it boots from. Every
+//! entry must find its volume by label, every file it loads must be on
+//! that volume, and what it chainloads must be a PE image. Run on the
+//! planned files before anything is written, and on the stick after.
+
+use anyhow::{Context, Result};
+use phoenix_bootcfg::GrubMenu;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// A volume a menu entry can boot from: its label and files.
+#[derive(Debug, Clone, Default)]
+pub struct BootVolume {
+    pub label: String,
+    /// Lowercase `/`-separated path on the volume, as FAT matches names,
+    /// to where the file can be read now.
+    files: BTreeMap<String, PathBuf>,
+}
+
+impl BootVolume {
+    pub fn new(label: impl Into<String>) -> Self {
+        Self {
+            label: label.into(),
+            files: BTreeMap::new(),
+        }
+    }
+
+    /// Every file under a mounted volume.
+    pub fn scan(label: impl Into<String>, root: &Path) -> Result<Self> {
+        let mut volume = Self::new(label);
+        volume.scan_dir(root, root)?;
+        Ok(volume)
+    }
+
+    /// Records that `relative` on the volume holds the file at `source`.
+    pub fn insert(&mut self, relative: &Path, source: PathBuf) {
+        self.files.insert(volume_key(&relative.to_string_lossy()), source);
+    }
+
+    fn scan_dir(&mut self, root: &Path, dir: &Path) -> Result<()> {
+        for entry in fs::read_dir(dir).with_context(|| format!("read {}", dir.display()))? {
+            let path = entry?.path();
+            if path.is_dir() {
+                self.scan_dir(root, &path)?;
+            } else if let Ok(relative) = path.strip_prefix(root) {
+                let relative = relative.to_path_buf();
+                self.insert(&relative, path);
+            }
+        }
+        Ok(())
+    }
+
+    fn file(&self, path: &str) -> Option<&PathBuf> {
+        self.files.get(&volume_key(path))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BootLintFinding {
+    /// Title of the menu entry.
+    pub entry: String,
+    pub problem: String,
+}
+
+/// Problems with `menu`'s entries; empty when every entry can boot. An
+/// entry starts on the volume labelled `boot_label` until a `search
+/// --label` moves it.
+pub fn lint_boot_menu(menu: &GrubMenu, boot_label: &str, volumes: &[BootVolume]) -> Vec<BootLintFinding> {
+    let mut findings = Vec::new();
+    for entry in &menu.entries {
+        let mut problem = |problem: String| {
+            findings.push(BootLintFinding {
+                entry: entry.title.clone(),
+                problem,
+            })
+        };
+        let mut root = volumes.iter().find(|volume| volume.label == boot_label);
+        for command in &entry.commands {
+            let words: Vec<&str> = command.split_whitespace().collect();
+            match words.as_slice() {
+                ["search", args @ ..] => {
+                    let Some(label) = args
+                        .iter()
+                        .position(|arg| *arg == "--label" || *arg == "-l")
+                        .and_then(|index| args.get(index + 1))
+                    else {
+                        continue;
+                    };
+                    root = volumes.iter().find(|volume| volume.label == *label);
+                    if root.is_none() {
+                        problem(format!("no volume is labelled {}", label));
+                    }
+                }
+                ["chainloader", .., path] => match root.and_then(|volume| volume.file(path)) {
+                    Some(source) => {
+                        if !is_pe_image(source) {
+                            problem(format!("{} is not an EFI executable", path));
+                        }
+                    }
+                    None => problem(missing(root, path)),
+                },
+                ["linux" | "linuxefi" | "initrd" | "initrdefi", files @ ..] => {
+                    // `linux` takes the kernel command line after the path.
+                    let files = if words[0].starts_with("linux") {
+                        &files[..files.len().min(1)]
+                    } else {
+                        files
+                    };
+                    for path in files {
+                        if root.and_then(|volume| volume.file(path)).is_none() {
+                            problem(missing(root, path));
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    findings
+}
+
+fn missing(root: Option<&BootVolume>, path: &str) -> String {
+    match root {
+        Some(volume) => format!("{} is not on {}", path, volume.label),
+        None => format!("{} has no volume to load from", path),
+    }
+}
+
+fn volume_key(path: &str) -> String {
+    path.replace('\\', "/").trim_start_matches('/').to_ascii_lowercase()
+}
+
+/// Starts with the DOS `MZ` stub every PE image has.
+fn is_pe_image(path: &Path) -> bool {
+    let mut magic = [0u8; 2];
+    fs::File::open(path)
+        .and_then(|mut file| file.read_exact(&mut magic))
+        .is_ok_and(|_| &magic == b"MZ")
+}
@@ -0,0 +1,130 @@
+use anyhow::{anyhow, Result};
+use std::cell::RefCell;
+use std::io::Read;
+use std::process::{Child, Command, ExitStatus, Output, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+/// How often a waiting child process is checked for exit and cancel.
+const CHILD_POLL: Duration = Duration::from_millis(50);
+
+/// Cooperative cancellation flag for a workflow. Copy loops and device
+/// writes check it between files/chunks, so a cancelled run stops at the next
+/// safe point instead of mid-write.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+thread_local! {
+    static CURRENT: RefCell<Option<CancelToken>> = const { RefCell::new(None) };
+}
+
+/// Runs `f` with `token` installed for the current thread. Workflows started
+/// inside `f` observe the token at their cancellation points.
+pub fn with_cancel_token<T>(token: &CancelToken, f: impl FnOnce() -> T) -> T {
+    let previous = CURRENT.with(|current| current.replace(Some(token.clone())));
+    // Puts the previous token back even when `f` panics.
+    let _restore = RestoreToken(previous);
+    f()
+}
+
+struct RestoreToken(Option<CancelToken>);
+
+impl Drop for RestoreToken {
+    fn drop(&mut self) {
+        let previous = self.0.take();
+        let _ = CURRENT.try_with(|current| *current.borrow_mut() = previous);
+    }
+}
+
+/// The token installed on this thread, for handing to worker threads.
+pub(crate) fn current_token() -> Option<CancelToken> {
+    CURRENT.with(|current| current.borrow().clone())
+}
+
+/// Whether the token installed on this thread was cancelled. Blocking
+/// code run under `with_cancel_token` can poll it between units of work.
+pub fn is_cancelled() -> bool {
+    CURRENT.with(|current| {
+        current
+            .borrow()
+            .as_ref()
+            .is_some_and(CancelToken::is_cancelled)
+    })
+}
+
+pub(crate) fn check_cancelled() -> Result<()> {
+    if is_cancelled() {
+        return Err(anyhow!("workflow cancelled"));
+    }
+    Ok(())
+}
+
+/// `Command::output`, except a cancel of this thread's token (a step's
+/// time limit included) kills and reaps the child instead of waiting
+/// on a wedged tool forever.
+pub(crate) fn command_output(command: &mut Command) -> Result<Output> {
+    let child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    wait_with_output(child)
+}
+
+/// `Child::wait_with_output`, killing and reaping the child on cancel.
+pub(crate) fn wait_with_output(mut child: Child) -> Result<Output> {
+    let stdout = read_to_end(child.stdout.take());
+    let stderr = read_to_end(child.stderr.take());
+    let status = wait_child(&mut child)?;
+    Ok(Output {
+        status,
+        stdout: stdout.join().unwrap_or_default(),
+        stderr: stderr.join().unwrap_or_default(),
+    })
+}
+
+/// Polls `child` until it exits, killing and reaping it on cancel.
+pub(crate) fn wait_child(child: &mut Child) -> Result<ExitStatus> {
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(status);
+        }
+        check_child_cancelled(child)?;
+        std::thread::sleep(CHILD_POLL);
+    }
+}
+
+/// Kills and reaps `child` when this thread's token was cancelled.
+pub(crate) fn check_child_cancelled(child: &mut Child) -> Result<()> {
+    if is_cancelled() {
+        let _ = child.kill();
+        let _ = child.wait();
+    }
+    check_cancelled()
+}
+
+fn read_to_end(pipe: Option<impl Read + Send + 'static>) -> JoinHandle<Vec<u8>> {
+    std::thread::spawn(move || {
+        let mut bytes = Vec::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut bytes);
+        }
+        bytes
+    })
+}
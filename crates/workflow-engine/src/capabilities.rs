@@ -0,0 +1,164 @@
+use crate::doctor::is_elevated;
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Capability {
+    /// This OS and build implement the operation and its tools are present.
+    pub available: bool,
+    /// The operation needs an elevated process and this one is not; dry
+    /// runs still work.
+    pub needs_elevation: bool,
+    pub detail: String,
+}
+
+impl Capability {
+    fn supported(detail: impl Into<String>, privileged: bool) -> Self {
+        Self {
+            available: true,
+            needs_elevation: privileged && !is_elevated() && !phoenix_core::mock::is_active(),
+            detail: detail.into(),
+        }
+    }
+
+    fn unsupported(detail: impl Into<String>) -> Self {
+        Self {
+            available: false,
+            needs_elevation: false,
+            detail: detail.into(),
+        }
+    }
+
+    #[cfg(windows)]
+    fn probed(result: std::result::Result<(), String>, ok: &str, privileged: bool) -> Self {
+        match result {
+            Ok(()) => Self::supported(ok, privileged),
+            Err(err) => Self::unsupported(err),
+        }
+    }
+
+    /// Usable right now, for a real run.
+    pub fn usable(&self) -> bool {
+        self.available && !self.needs_elevation
+    }
+}
+
+/// What this host can actually do, for validation and for hiding
+/// unsupported features in a UI.
+#[derive(Debug, Clone, Serialize)]
+pub struct HostCapabilities {
+    pub os: &'static str,
+    pub elevated: bool,
+    /// Every destructive path fails while set; see `phoenix_safety::is_read_only`.
+    pub read_only: bool,
+    pub iso_mount: Capability,
+    pub wim_apply: Capability,
+    pub raw_write: Capability,
+    pub repartition: Capability,
+    pub exfat_format: Capability,
+}
+
+impl HostCapabilities {
+    pub fn iter(&self) -> impl Iterator<Item = (&'static str, &Capability)> {
+        [
+            ("iso_mount", &self.iso_mount),
+            ("wim_apply", &self.wim_apply),
+            ("raw_write", &self.raw_write),
+            ("repartition", &self.repartition),
+            ("exfat_format", &self.exfat_format),
+        ]
+        .into_iter()
+    }
+
+    /// Errors unless `name` is available; elevation is only required when
+    /// the step is not a dry run.
+    pub fn require(&self, name: &str, dry_run: bool) -> Result<()> {
+        let (_, capability) = self
+            .iter()
+            .find(|(candidate, _)| *candidate == name)
+            .ok_or_else(|| anyhow!("unknown capability {}", name))?;
+        if !capability.available {
+            return Err(anyhow!("{} unavailable on this host: {}", name, capability.detail));
+        }
+        if capability.needs_elevation && !dry_run {
+            return Err(anyhow!("{} requires an elevated process", name));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(windows)]
+pub fn host_capabilities() -> HostCapabilities {
+    use crate::doctor::library_exports;
+
+    HostCapabilities {
+        os: crate::current_os(),
+        elevated: is_elevated(),
+        read_only: phoenix_safety::is_read_only(),
+        iso_mount: Capability::probed(
+            library_exports("virtdisk.dll", "AttachVirtualDisk"),
+            "virtdisk.dll AttachVirtualDisk",
+            false,
+        ),
+        // wimgapi.dll, else DISM from the ADK or System32.
+        wim_apply: match phoenix_wim::backend() {
+            Ok(backend) => Capability::supported(backend.detail(), true),
+            Err(err) => Capability::unsupported(err.to_string()),
+        },
+        raw_write: Capability::unsupported("raw image writes are implemented for Linux and macOS"),
+        repartition: Capability::supported("GPT layout through IOCTL_DISK_SET_DRIVE_LAYOUT_EX", true),
+        exfat_format: Capability::probed(
+            library_exports("fmifs.dll", "FormatEx"),
+            "fmifs.dll FormatEx",
+            true,
+        ),
+    }
+}
+
+#[cfg(target_os = "macos")]
+pub fn host_capabilities() -> HostCapabilities {
+    let raw_write = if std::path::Path::new("/usr/libexec/authopen").exists() {
+        Capability::supported("direct open as root, else authopen", false)
+    } else {
+        Capability::supported("direct device open", true)
+    };
+    HostCapabilities {
+        os: crate::current_os(),
+        elevated: is_elevated(),
+        read_only: phoenix_safety::is_read_only(),
+        iso_mount: Capability::unsupported("ISO sources are mounted on Windows only"),
+        wim_apply: Capability::unsupported("WIM apply needs wimgapi (Windows)"),
+        raw_write,
+        repartition: Capability::unsupported("custom partition layouts are applied on Windows only"),
+        exfat_format: Capability::unsupported("only FAT32 formatting is implemented on macOS"),
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub fn host_capabilities() -> HostCapabilities {
+    HostCapabilities {
+        os: crate::current_os(),
+        elevated: is_elevated(),
+        read_only: phoenix_safety::is_read_only(),
+        iso_mount: Capability::unsupported("ISO sources are mounted on Windows only"),
+        wim_apply: Capability::unsupported("WIM apply needs wimgapi (Windows)"),
+        raw_write: Capability::supported("direct block device open", true),
+        repartition: Capability::unsupported("custom partition layouts are applied on Windows only"),
+        exfat_format: Capability::unsupported("only FAT32 formatting is implemented on Linux"),
+    }
+}
+
+#[cfg(not(any(windows, target_os = "macos", target_os = "linux")))]
+pub fn host_capabilities() -> HostCapabilities {
+    let none = || Capability::unsupported("unsupported OS");
+    HostCapabilities {
+        os: crate::current_os(),
+        elevated: is_elevated(),
+        read_only: phoenix_safety::is_read_only(),
+        iso_mount: none(),
+        wim_apply: none(),
+        raw_write: none(),
+        repartition: none(),
+        exfat_format: none(),
+    }
+}
@@ -0,0 +1,143 @@
+use anyhow::{anyhow, Result};
+use phoenix_host_windows::format::FileSystem;
+use serde::{Deserialize, Serialize};
+
+const KIB: u64 = 1024;
+const MIB: u64 = 1024 * KIB;
+const GIB: u64 = 1024 * MIB;
+const SECTOR: u64 = 512;
+
+/// What a freshly formatted volume leaves for files.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CapacityEstimate {
+    pub filesystem: String,
+    pub volume_bytes: u64,
+    pub cluster_bytes: u64,
+    /// Boot region, FATs / allocation bitmap / up-case table, or the NTFS
+    /// system files.
+    pub overhead_bytes: u64,
+    pub usable_bytes: u64,
+}
+
+impl CapacityEstimate {
+    /// Space `files` take once written: each file rounded up to whole
+    /// clusters, one cluster per directory, and on NTFS a 1 KiB MFT record
+    /// per entry (files under ~700 bytes stay resident in it).
+    pub fn required_bytes(&self, file_sizes: impl Iterator<Item = u64>, directories: usize) -> u64 {
+        let ntfs = self.filesystem == FileSystem::Ntfs.as_str();
+        let mut entries = directories as u64;
+        let mut bytes = directories as u64 * self.cluster_bytes;
+        for size in file_sizes {
+            entries += 1;
+            if ntfs && size <= 700 {
+                continue;
+            }
+            bytes += size.div_ceil(self.cluster_bytes) * self.cluster_bytes;
+        }
+        if ntfs {
+            bytes += entries * KIB;
+        }
+        bytes
+    }
+}
+
+/// Post-format capacity next to what the source will occupy.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FormatCapacity {
+    #[serde(flatten)]
+    pub estimate: CapacityEstimate,
+    pub required_bytes: u64,
+}
+
+/// Estimates usable space after formatting `volume_bytes` with
+/// `filesystem`, using `cluster_bytes` or else the cluster size Windows
+/// picks by default.
+pub fn estimate_capacity(
+    filesystem: FileSystem,
+    volume_bytes: u64,
+    cluster_bytes: Option<u64>,
+) -> CapacityEstimate {
+    let cluster_bytes =
+        cluster_bytes.unwrap_or_else(|| default_cluster_bytes(filesystem, volume_bytes));
+    let clusters = volume_bytes / cluster_bytes;
+    let overhead_bytes = match filesystem {
+        FileSystem::Fat32 => {
+            // 32 reserved sectors, two FATs of 4-byte entries, root directory.
+            let fat = round_up((clusters + 2) * 4, SECTOR);
+            32 * SECTOR + 2 * fat + cluster_bytes
+        }
+        FileSystem::ExFat => {
+            // FAT aligned at 1 MiB, one FAT, allocation bitmap, up-case
+            // table and root directory each in their own clusters.
+            let fat = round_up(clusters * 4, cluster_bytes);
+            let bitmap = round_up(clusters.div_ceil(8), cluster_bytes);
+            MIB + fat + bitmap + 2 * cluster_bytes
+        }
+        FileSystem::Ntfs => {
+            // $LogFile scales with the volume up to 64 MiB; $MFT starts with
+            // its reserved first records plus the $MFTMirr, $Bitmap, boot
+            // file and $UpCase/$Secure/$Extend metadata.
+            let log_file = (volume_bytes / 100).clamp(2 * MIB, 64 * MIB);
+            let bitmap = round_up(clusters.div_ceil(8), cluster_bytes);
+            log_file + 256 * KIB + bitmap + 8 * KIB + 512 * KIB
+        }
+    };
+    CapacityEstimate {
+        filesystem: filesystem.as_str().to_string(),
+        volume_bytes,
+        cluster_bytes,
+        overhead_bytes,
+        usable_bytes: volume_bytes.saturating_sub(overhead_bytes),
+    }
+}
+
+/// Checks a user-chosen cluster size before anything is formatted. FAT32
+/// follows its cluster-count limits; exFAT allows 512 B to 32 MiB and NTFS
+/// 512 B to 2 MiB, each with at most 2^32 - 11 clusters.
+pub fn check_cluster_bytes(filesystem: FileSystem, volume_bytes: u64, cluster_bytes: u64) -> Result<()> {
+    let max = match filesystem {
+        FileSystem::Fat32 => {
+            phoenix_fs_fat32::check_cluster_size(volume_bytes, cluster_bytes)?;
+            return Ok(());
+        }
+        FileSystem::ExFat => 32 * MIB,
+        FileSystem::Ntfs => 2 * MIB,
+    };
+    if !cluster_bytes.is_power_of_two() || !(SECTOR..=max).contains(&cluster_bytes) {
+        return Err(anyhow!(
+            "{} cluster size must be a power of two from {} to {} bytes, got {}",
+            filesystem.as_str(),
+            SECTOR,
+            max,
+            cluster_bytes
+        ));
+    }
+    let clusters = volume_bytes / cluster_bytes;
+    if clusters > 0xFFFF_FFF5 {
+        return Err(anyhow!(
+            "{} byte clusters give {} clusters on a {} byte volume; {} allows at most {}",
+            cluster_bytes,
+            clusters,
+            volume_bytes,
+            filesystem.as_str(),
+            0xFFFF_FFF5u64
+        ));
+    }
+    Ok(())
+}
+
+fn default_cluster_bytes(filesystem: FileSystem, volume_bytes: u64) -> u64 {
+    match filesystem {
+        FileSystem::Fat32 => phoenix_fs_fat32::default_cluster_size(volume_bytes),
+        FileSystem::ExFat => match volume_bytes {
+            size if size <= 256 * MIB => 4 * KIB,
+            size if size <= 32 * GIB => 32 * KIB,
+            _ => 128 * KIB,
+        },
+        FileSystem::Ntfs => 4 * KIB,
+    }
+}
+
+fn round_up(value: u64, unit: u64) -> u64 {
+    value.div_ceil(unit) * unit
+}
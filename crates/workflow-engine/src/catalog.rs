@@ -0,0 +1,156 @@
+//! Discovery of the workflows installed as packs, so the CLI picker and a
+//! GUI selection screen list the same entries. Every definition is
+//! validated against this host; one that cannot run here is still listed,
+//! with the reason.
+
+use crate::ledger::state_dir;
+use crate::{action_os, step_capabilities, validate_workflow_definition};
+use anyhow::Result;
+use phoenix_core::WorkflowDefinition;
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+/// Manifest names looked for in a pack directory and its subdirectories.
+const MANIFEST_NAMES: [&str; 3] = ["pack.json", "pack.yaml", "pack.yml"];
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CatalogEntry {
+    pub name: String,
+    pub description: Option<String>,
+    pub path: PathBuf,
+    /// Name and version of the pack listing the workflow.
+    pub pack: String,
+    pub pack_version: String,
+    /// More than one entry means no host can run every step.
+    pub required_os: Vec<String>,
+    pub required_capabilities: Vec<String>,
+    /// Why the workflow cannot run on this host; `None` when it validates.
+    pub error: Option<String>,
+}
+
+impl CatalogEntry {
+    pub fn runnable(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// A manifest or workflow file that could not be loaded at all.
+#[derive(Debug, Clone, Serialize)]
+pub struct CatalogProblem {
+    pub path: PathBuf,
+    pub error: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct WorkflowCatalog {
+    pub entries: Vec<CatalogEntry>,
+    pub problems: Vec<CatalogProblem>,
+}
+
+/// `$PHOENIX_PACK_DIRS` (a path list), else `packs/` in the state
+/// directory.
+pub fn pack_dirs() -> Result<Vec<PathBuf>> {
+    match std::env::var_os("PHOENIX_PACK_DIRS") {
+        Some(dirs) => Ok(std::env::split_paths(&dirs).collect()),
+        None => Ok(vec![state_dir()?.join("packs")]),
+    }
+}
+
+/// Lists every workflow of the packs found in `dirs`. A pack is a
+/// manifest named `pack.json`, `pack.yaml` or `pack.yml`, directly in a
+/// directory or one level down. Missing directories are skipped.
+pub fn list_workflows(dirs: &[PathBuf]) -> WorkflowCatalog {
+    let mut catalog = WorkflowCatalog::default();
+    for manifest in dirs.iter().flat_map(|dir| find_manifests(dir)) {
+        let pack = match phoenix_content::load_pack_manifest(&manifest) {
+            Ok(pack) => pack,
+            Err(err) => {
+                catalog.problems.push(CatalogProblem {
+                    path: manifest,
+                    error: err.to_string(),
+                });
+                continue;
+            }
+        };
+        let base = manifest.parent().unwrap_or(Path::new("."));
+        for workflow in &pack.workflows {
+            let path = base.join(workflow);
+            match phoenix_content::load_workflow_definition(&path) {
+                Ok(definition) => catalog.entries.push(catalog_entry(
+                    &definition,
+                    path,
+                    &pack.name,
+                    &pack.version,
+                )),
+                Err(err) => catalog.problems.push(CatalogProblem {
+                    path,
+                    error: err.to_string(),
+                }),
+            }
+        }
+    }
+    catalog
+}
+
+fn find_manifests(dir: &Path) -> Vec<PathBuf> {
+    let mut manifests: Vec<PathBuf> = Vec::new();
+    let mut push_manifest = |dir: &Path| {
+        if let Some(path) = MANIFEST_NAMES
+            .iter()
+            .map(|name| dir.join(name))
+            .find(|path| path.is_file())
+        {
+            manifests.push(path);
+        }
+    };
+    push_manifest(dir);
+    let mut subdirs: Vec<PathBuf> = match std::fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.is_dir())
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    subdirs.sort();
+    for subdir in &subdirs {
+        push_manifest(subdir);
+    }
+    manifests
+}
+
+fn catalog_entry(
+    definition: &WorkflowDefinition,
+    path: PathBuf,
+    pack: &str,
+    pack_version: &str,
+) -> CatalogEntry {
+    let required_os: BTreeSet<&str> = definition
+        .steps
+        .iter()
+        .filter_map(|step| action_os(&step.action))
+        .collect();
+    // A step whose params do not parse is reported by the validation below.
+    let required_capabilities: BTreeSet<&str> = definition
+        .steps
+        .iter()
+        .filter_map(|step| step_capabilities(step).ok())
+        .flatten()
+        .map(|need| need.name)
+        .collect();
+    CatalogEntry {
+        name: definition.name.clone(),
+        description: definition.description.clone(),
+        path,
+        pack: pack.to_string(),
+        pack_version: pack_version.to_string(),
+        required_os: required_os.into_iter().map(str::to_string).collect(),
+        required_capabilities: required_capabilities
+            .into_iter()
+            .map(str::to_string)
+            .collect(),
+        error: validate_workflow_definition(definition)
+            .err()
+            .map(|err| err.to_string()),
+    }
+}
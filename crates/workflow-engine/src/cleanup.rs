@@ -0,0 +1,288 @@
+//! Cleanup registry for the temporary things a workflow leaves on the host
+//! or the target: DMG mounts, scratch directories and write-test files.
+//! Each is recorded under `<state dir>/cleanup/` while it exists and undone
+//! when its guard drops, on error paths too. `reap_leftovers` undoes what a
+//! crashed or killed process never got to.
+
+use crate::build_device_graph;
+use crate::ledger::{now_unix, process_alive, state_dir, write_record};
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Created and removed again to prove a mounted volume takes writes.
+pub const WRITE_TEST_FILE: &str = ".phoenix_write_test";
+/// Temp-dir prefixes of per-process scratch paths; the owner's pid follows
+/// the prefix.
+const TEMP_PREFIXES: &[(&str, CleanupAction)] = &[
+    ("phoenix_dmg_", CleanupAction::DetachDmg),
+    ("phoenix-hook-", CleanupAction::RemoveDir),
+    ("phoenix-archive-", CleanupAction::RemoveFile),
+    ("phoenix-agent-", CleanupAction::RemoveFile),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CleanupAction {
+    RemoveFile,
+    RemoveDir,
+    /// `hdiutil detach` the mount point, then remove it.
+    DetachDmg,
+}
+
+impl CleanupAction {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::RemoveFile => "remove_file",
+            Self::RemoveDir => "remove_dir",
+            Self::DetachDmg => "detach_dmg",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CleanupEntry {
+    pub id: String,
+    pub action: CleanupAction,
+    pub path: String,
+    pub pid: u32,
+    pub created_unix: u64,
+}
+
+/// Undoes its entry when dropped. Registration is best effort: without a
+/// writable state dir the guard still cleans up, but a crash leaves
+/// nothing for `reap_leftovers` to find.
+#[derive(Debug)]
+pub struct CleanupGuard {
+    entry: CleanupEntry,
+    path: PathBuf,
+    record: Option<PathBuf>,
+}
+
+impl CleanupGuard {
+    pub fn register(action: CleanupAction, path: &Path) -> Self {
+        let pid = std::process::id();
+        let created_unix = now_unix();
+        let id = format!("{}-{}-{}", pid, created_unix, next_sequence());
+        let entry = CleanupEntry {
+            id: id.clone(),
+            action,
+            path: path.display().to_string(),
+            pid,
+            created_unix,
+        };
+        let record = registry_dir().ok().and_then(|dir| {
+            fs::create_dir_all(&dir).ok()?;
+            let record = dir.join(format!("{}.json", id));
+            write_record(&record, &entry).ok()?;
+            Some(record)
+        });
+        Self {
+            entry,
+            path: path.to_path_buf(),
+            record,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for CleanupGuard {
+    /// A failed undo keeps the record, so `phoenix cleanup` retries it
+    /// once this process is gone.
+    fn drop(&mut self) {
+        if undo(self.entry.action, &self.path).is_ok() {
+            if let Some(record) = &self.record {
+                let _ = fs::remove_file(record);
+            }
+        }
+    }
+}
+
+fn next_sequence() -> u64 {
+    use std::sync::atomic::{AtomicU64, Ordering};
+    static SEQUENCE: AtomicU64 = AtomicU64::new(0);
+    SEQUENCE.fetch_add(1, Ordering::Relaxed)
+}
+
+/// `<state dir>/cleanup`.
+pub fn registry_dir() -> Result<PathBuf> {
+    Ok(state_dir()?.join("cleanup"))
+}
+
+/// Proves `mount` takes writes with a file that is removed again, even when
+/// the write itself fails halfway.
+pub fn write_test(mount: &Path) -> Result<()> {
+    let guard = CleanupGuard::register(CleanupAction::RemoveFile, &mount.join(WRITE_TEST_FILE));
+    fs::write(guard.path(), b"")
+        .with_context(|| format!("write test on {} failed", mount.display()))
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Leftover {
+    pub action: CleanupAction,
+    pub path: String,
+    /// Process that created it, when known.
+    pub pid: Option<u32>,
+    /// `registry`, `temp_dir` or `volume`.
+    pub found_in: &'static str,
+    pub removed: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Finds what dead processes left behind: registry entries, per-process
+/// scratch paths in the temp dir and write-test files on mounted volumes
+/// outside the system disk. Everything a live process still owns is
+/// skipped. With `dry_run` nothing is undone.
+pub fn reap_leftovers(dry_run: bool) -> Result<Vec<Leftover>> {
+    let mut leftovers = Vec::new();
+    let mut live_paths = Vec::new();
+    let dir = registry_dir()?;
+    if dir.is_dir() {
+        for item in fs::read_dir(&dir).with_context(|| format!("read {}", dir.display()))? {
+            let record = item?.path();
+            if record.extension().and_then(|ext| ext.to_str()) != Some("json") {
+                continue;
+            }
+            let Some(entry) = fs::read(&record)
+                .ok()
+                .and_then(|bytes| serde_json::from_slice::<CleanupEntry>(&bytes).ok())
+            else {
+                continue;
+            };
+            if process_alive(entry.pid) {
+                live_paths.push(PathBuf::from(&entry.path));
+                continue;
+            }
+            let mut leftover = Leftover {
+                action: entry.action,
+                path: entry.path.clone(),
+                pid: Some(entry.pid),
+                found_in: "registry",
+                removed: false,
+                error: None,
+            };
+            if !dry_run {
+                match undo(entry.action, Path::new(&entry.path)) {
+                    Ok(()) => {
+                        leftover.removed = true;
+                        let _ = fs::remove_file(&record);
+                    }
+                    Err(err) => leftover.error = Some(format!("{:#}", err)),
+                }
+            }
+            leftovers.push(leftover);
+        }
+    }
+
+    let mut candidates = Vec::new();
+    if let Ok(items) = fs::read_dir(std::env::temp_dir()) {
+        for item in items.flatten() {
+            let name = item.file_name().to_string_lossy().to_string();
+            let Some((prefix, action)) = TEMP_PREFIXES
+                .iter()
+                .find(|(prefix, _)| name.starts_with(prefix))
+            else {
+                continue;
+            };
+            let pid = name[prefix.len()..]
+                .split(['_', '-'])
+                .next()
+                .and_then(|pid| pid.parse::<u32>().ok());
+            candidates.push((*action, item.path(), pid, "temp_dir"));
+        }
+    }
+    let graph = build_device_graph()?;
+    let mounts = graph
+        .disks
+        .iter()
+        .filter(|disk| !disk.is_system_disk)
+        .flat_map(|disk| &disk.partitions)
+        .flat_map(|partition| &partition.mount_points);
+    for mount in mounts {
+        let path = Path::new(mount).join(WRITE_TEST_FILE);
+        if path.is_file() {
+            candidates.push((CleanupAction::RemoveFile, path, None, "volume"));
+        }
+    }
+    for (action, path, pid, found_in) in candidates {
+        let owned = pid.is_some_and(process_alive) || live_paths.contains(&path);
+        if owned || leftovers.iter().any(|known| Path::new(&known.path) == path) {
+            continue;
+        }
+        let mut leftover = Leftover {
+            action,
+            path: path.display().to_string(),
+            pid,
+            found_in,
+            removed: false,
+            error: None,
+        };
+        if !dry_run {
+            match undo(action, &path) {
+                Ok(()) => leftover.removed = true,
+                Err(err) => leftover.error = Some(format!("{:#}", err)),
+            }
+        }
+        leftovers.push(leftover);
+    }
+    Ok(leftovers)
+}
+
+fn undo(action: CleanupAction, path: &Path) -> Result<()> {
+    let removed = match action {
+        CleanupAction::RemoveFile => fs::remove_file(path),
+        CleanupAction::RemoveDir => fs::remove_dir_all(path),
+        CleanupAction::DetachDmg => {
+            if !path.exists() {
+                return Ok(());
+            }
+            // An empty mount point was never attached, or already detached.
+            let attached = fs::read_dir(path).map(|mut items| items.next().is_some())?;
+            if attached {
+                detach_dmg(path)?;
+            }
+            fs::remove_dir(path)
+        }
+    };
+    match removed {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+            Err(anyhow!("remove {}: {}", path.display(), err))
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn detach_dmg(mount_point: &Path) -> Result<()> {
+    if phoenix_core::mock::is_active() {
+        let path = mount_point.to_string_lossy();
+        return Ok(phoenix_core::mock::record_command(
+            "/usr/bin/hdiutil",
+            &["detach", path.as_ref()],
+        )?);
+    }
+    let output = crate::cancel::command_output(
+        std::process::Command::new("/usr/bin/hdiutil")
+            .arg("detach")
+            .arg(mount_point),
+    )
+    .context("run hdiutil")?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "hdiutil detach failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+    }
+}
+
+#[cfg(not(target_os = "macos"))]
+fn detach_dmg(mount_point: &Path) -> Result<()> {
+    Err(anyhow!("{} is a DMG mount; detach it on macOS", mount_point.display()))
+}
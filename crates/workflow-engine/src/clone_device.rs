@@ -0,0 +1,628 @@
+//! Stick to stick: reads one removable disk and writes it to another in a
+//! single pass, optionally compressing the same bytes into an archive on
+//! the way through. The target gets every check an image write does and
+//! the source the disk checks, and the run ends by comparing the two
+//! disks byte for byte over the source's length. With `archive_in_report`
+//! the archive travels in the report bundle, where `restore-report` can
+//! put it back on a disk.
+
+use crate::cleanup::{CleanupAction, CleanupGuard};
+use crate::io_limits::acquire_write_slot;
+use crate::ledger::RunTracker;
+use crate::operation::{DestructiveOperation, DestructiveSession};
+use crate::resize::device_path;
+use crate::restore::ImageArtifact;
+use crate::{
+    build_device_graph, check_overwrite, check_target_claim, check_target_disk_size,
+    check_target_wear, find_disk_by_mount, join_reasons, reread_partitions, resolve_chunk_size,
+    signing_key_from_env, target, to_hex, StepLog, ThroughputObserver,
+};
+use anyhow::{anyhow, Context, Result};
+use phoenix_core::{DeviceGraph, Disk};
+use phoenix_report::{
+    create_report_bundle_with_meta_signing_and_artifacts, ReportArtifact, ReportPaths,
+};
+use phoenix_safety::SafetyContext;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+/// Compression of `archive`; zstd's default, which keeps up with a stick.
+pub const ARCHIVE_ZSTD_LEVEL: i32 = 3;
+/// Name of the archive inside the report bundle.
+pub const CLONE_ARCHIVE_ARTIFACT: &str = "disk.img.zst";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CloneDeviceParams {
+    pub source_disk_id: String,
+    pub target_disk_id: String,
+    /// Also compress what is read into this zstd file; it must not be on
+    /// either disk.
+    #[serde(default)]
+    pub archive: Option<PathBuf>,
+    /// Keep the archive in the report bundle as `disk.img.zst`. Without
+    /// `archive` it is staged in the temp dir until the report is written.
+    #[serde(default)]
+    pub archive_in_report: bool,
+    #[serde(default = "crate::params::default_report_base")]
+    pub report_base: PathBuf,
+    #[serde(default)]
+    pub force: bool,
+    #[serde(default)]
+    pub confirmation_token: Option<String>,
+    #[serde(default)]
+    pub acknowledge_target_size: bool,
+    /// Proceed on a target past the wear limits; recorded in the report
+    /// and the audit log.
+    #[serde(default)]
+    pub acknowledge_device_wear: bool,
+    #[serde(default)]
+    pub confirm_overwrite: bool,
+    #[serde(default = "crate::params::default_true")]
+    pub dry_run: bool,
+    /// `None` probes the target for the fastest chunk size.
+    #[serde(default, with = "crate::params::chunk_size")]
+    pub chunk_size: Option<u64>,
+    #[serde(default, with = "crate::params::byte_size")]
+    pub flush_every_bytes: Option<u64>,
+}
+
+/// The compressed copy written alongside the target.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CloneArchive {
+    /// Relative to the report bundle when the archive is kept there.
+    pub path: PathBuf,
+    /// Always `zstd`.
+    pub compression: String,
+    /// Size of the compressed file.
+    pub bytes: u64,
+    pub sha256: String,
+    /// Size and SHA-256 of the disk it holds, once decompressed.
+    pub image_bytes: u64,
+    pub image_sha256: String,
+}
+
+/// The byte-for-byte comparison of source and target.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DeviceComparison {
+    pub bytes_compared: u64,
+    pub mismatched_chunks: u64,
+    /// Offset of the first chunk that differs.
+    pub first_mismatch: Option<u64>,
+}
+
+impl DeviceComparison {
+    pub fn matches(&self) -> bool {
+        self.mismatched_chunks == 0
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CloneDeviceResult {
+    pub report: ReportPaths,
+    pub source_disk_id: String,
+    pub target_disk_id: String,
+    /// The source's size; the target keeps whatever lies past it.
+    pub bytes_copied: u64,
+    /// SHA-256 of the bytes read from the source.
+    pub sha256: String,
+    pub comparison: Option<DeviceComparison>,
+    pub archive: Option<CloneArchive>,
+    pub chunk_size: u64,
+    pub throughput_bytes_per_sec: u64,
+    pub dry_run: bool,
+}
+
+pub fn run_clone_device(params: &CloneDeviceParams) -> Result<CloneDeviceResult> {
+    let started = Instant::now();
+    if !cfg!(unix) {
+        return Err(anyhow!("clone workflow requires linux or macos"));
+    }
+    if params.source_disk_id.eq_ignore_ascii_case(&params.target_disk_id) {
+        return Err(anyhow!("source and target are the same disk: {}", params.source_disk_id));
+    }
+    let graph = build_device_graph()?;
+    let source = find_disk(&graph, &params.source_disk_id, "source")?;
+    let disk = find_disk(&graph, &params.target_disk_id, "target")?;
+    if disk.size_bytes < source.size_bytes {
+        return Err(anyhow!(
+            "target {} holds {} bytes but source {} is {}",
+            disk.id,
+            disk.size_bytes,
+            source.id,
+            source.size_bytes
+        ));
+    }
+    let target_size_acknowledged = check_target_disk_size(disk, params.acknowledge_target_size)?;
+    let device_claim = check_target_claim(disk, "clone-device", params.dry_run)?;
+    let device_wear = join_reasons(
+        device_claim,
+        check_target_wear(disk, "clone-device", params.acknowledge_device_wear, params.dry_run)?,
+    );
+    let overwrite_triggers = check_overwrite(disk, params.confirm_overwrite, params.dry_run)?;
+    if let Some(archive) = &params.archive {
+        check_archive_path(&graph, archive, &[source, disk])?;
+    }
+    // The staged archive is gone once the report holds its copy, or the
+    // run fails.
+    let staged = match (&params.archive, params.archive_in_report) {
+        (None, true) => Some(std::env::temp_dir().join(format!(
+            "phoenix-archive-{}-{}.img.zst",
+            std::process::id(),
+            disk.id.replace(['/', '\\'], "_")
+        ))),
+        _ => None,
+    };
+    let _staged_guard = staged
+        .as_deref()
+        .filter(|_| !params.dry_run)
+        .map(|path| CleanupGuard::register(CleanupAction::RemoveFile, path));
+    let archive_path = params.archive.clone().or(staged);
+
+    let mut logs = StepLog::new("clone-device");
+    logs.push(format!(
+        "source_disk_id={} serial={} size_bytes={}",
+        source.id,
+        source.serial.as_deref().unwrap_or("-"),
+        source.size_bytes
+    ));
+    logs.push(format!(
+        "target_disk_id={} serial={} size_bytes={}",
+        disk.id,
+        disk.serial.as_deref().unwrap_or("-"),
+        disk.size_bytes
+    ));
+    if let Some(reason) = &target_size_acknowledged {
+        logs.push(format!("target_size_acknowledged={}", reason));
+    }
+    if let Some(reason) = &device_wear {
+        logs.push(format!("device_wear={}", reason));
+    }
+    for trigger in &overwrite_triggers {
+        logs.push(format!("overwrite_trigger={}", trigger));
+    }
+    if let Some(archive) = &archive_path {
+        logs.push(format!(
+            "archive={} compression=zstd in_report={}",
+            archive.display(),
+            params.archive_in_report
+        ));
+    }
+    logs.push(format!("dry_run={}", params.dry_run));
+
+    let ctx = SafetyContext {
+        force_mode: params.force,
+        confirmation_token: params.confirmation_token.clone(),
+        allow_system_disk: false,
+        armed_until: phoenix_safety::armed_until(),
+    };
+    let mut session = DestructiveSession::begin(
+        "clone-device",
+        Some(disk),
+        &ctx,
+        disk.is_system_disk,
+        params.dry_run,
+        &mut logs,
+    )?;
+    let mut clone = CloneDisk {
+        source,
+        disk,
+        archive: archive_path.as_deref(),
+        params,
+    };
+    let cloned = session.perform(&mut clone, &mut logs)?;
+
+    let (log_text, timing) = logs.finish()?;
+    let (bytes_copied, sha256, comparison, mut archive, chunk_size, throughput) = match cloned {
+        Some(cloned) => (
+            cloned.bytes_copied,
+            cloned.sha256,
+            Some(cloned.comparison),
+            cloned.archive,
+            cloned.chunk_size,
+            cloned.throughput_bytes_per_sec,
+        ),
+        None => (0, String::new(), None, None, params.chunk_size.unwrap_or_default(), 0),
+    };
+    let mut artifacts = Vec::new();
+    let mut image_artifact = None;
+    if let Some(kept) = archive.as_mut().filter(|_| params.archive_in_report) {
+        artifacts.push(ReportArtifact::image(CLONE_ARCHIVE_ARTIFACT, &kept.path));
+        image_artifact = Some(ImageArtifact {
+            name: CLONE_ARCHIVE_ARTIFACT.to_string(),
+            compression: kept.compression.clone(),
+            image_bytes: kept.image_bytes,
+            image_sha256: kept.image_sha256.clone(),
+        });
+        if params.archive.is_none() {
+            kept.path = PathBuf::from(CLONE_ARCHIVE_ARTIFACT);
+        }
+    }
+    artifacts.push(timing);
+    let artifact_names: Vec<&str> = artifacts.iter().map(|artifact| artifact.name.as_str()).collect();
+    let meta = serde_json::json!({
+        "workflow": "clone-device",
+        "status": if params.dry_run { "dry_run" } else { "completed" },
+        "source_disk_id": source.id,
+        "source_serial": source.serial,
+        "target_disk_id": disk.id,
+        "target_serial": disk.serial,
+        "bytes_copied": bytes_copied,
+        "sha256": sha256,
+        "comparison": comparison,
+        "archive": archive,
+        "image_artifact": image_artifact,
+        "artifacts": artifact_names,
+        "chunk_size": chunk_size,
+        "throughput_bytes_per_sec": throughput,
+        "flush_every_bytes": params.flush_every_bytes,
+        "target_size_acknowledged": target_size_acknowledged,
+        "device_wear": device_wear,
+        "device_wear_acknowledged": params.acknowledge_device_wear,
+        "overwrite_triggers": overwrite_triggers,
+        "overwrite_confirmed": params.confirm_overwrite,
+        "destructive_operations": session.operations(),
+        "dry_run": params.dry_run,
+        "duration_ms": started.elapsed().as_millis() as u64
+    });
+    let report = create_report_bundle_with_meta_signing_and_artifacts(
+        &params.report_base,
+        &graph,
+        Some(meta),
+        Some(&log_text),
+        signing_key_from_env().as_deref(),
+        &artifacts,
+    )?;
+    session.complete(&report.root)?;
+    if let Some(kept) = archive.as_mut().filter(|_| params.archive.is_none()) {
+        kept.path = report.root.join(CLONE_ARCHIVE_ARTIFACT);
+    }
+
+    Ok(CloneDeviceResult {
+        report,
+        source_disk_id: source.id.clone(),
+        target_disk_id: disk.id.clone(),
+        bytes_copied,
+        sha256,
+        comparison,
+        archive,
+        chunk_size,
+        throughput_bytes_per_sec: throughput,
+        dry_run: params.dry_run,
+    })
+}
+
+/// The disk checks of a write target, for the source too: a system disk
+/// or one under LUKS, LVM or RAID is neither read nor written.
+fn find_disk<'a>(graph: &'a DeviceGraph, disk_id: &str, role: &str) -> Result<&'a Disk> {
+    let disk = graph
+        .disks
+        .iter()
+        .find(|disk| disk.id.eq_ignore_ascii_case(disk_id))
+        .ok_or_else(|| anyhow!("{} disk not found: {}", role, disk_id))?;
+    if disk.is_system_disk {
+        return Err(anyhow!("refusing to clone {} system disk: {}", role, disk.id));
+    }
+    if !disk.removable {
+        return Err(anyhow!("{} disk is not marked removable: {}", role, disk.id));
+    }
+    if let Some(reason) = target::stack_usage(graph, disk) {
+        return Err(anyhow!(reason));
+    }
+    Ok(disk)
+}
+
+/// Both disks are unmounted for the clone, so the archive cannot live on
+/// either of them.
+fn check_archive_path(graph: &DeviceGraph, archive: &Path, disks: &[&Disk]) -> Result<()> {
+    let parent = match archive.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    let parent = fs::canonicalize(&parent)
+        .with_context(|| format!("archive directory {}", parent.display()))?;
+    for dir in parent.ancestors() {
+        if let Some(disk) = find_disk_by_mount(graph, dir) {
+            if disks.iter().any(|cloned| cloned.id == disk.id) {
+                return Err(anyhow!(
+                    "archive {} is on {}, which the clone unmounts",
+                    archive.display(),
+                    disk.id
+                ));
+            }
+            return Ok(());
+        }
+    }
+    Ok(())
+}
+
+/// What `CloneDisk` copied and how the target compared.
+pub struct ClonedDisk {
+    pub bytes_copied: u64,
+    pub sha256: String,
+    pub comparison: DeviceComparison,
+    pub archive: Option<CloneArchive>,
+    pub chunk_size: u64,
+    pub throughput_bytes_per_sec: u64,
+}
+
+/// The source disk written over the start of the target.
+pub struct CloneDisk<'a> {
+    pub source: &'a Disk,
+    pub disk: &'a Disk,
+    /// `params.archive`, or the staged file kept in the report.
+    pub archive: Option<&'a Path>,
+    pub params: &'a CloneDeviceParams,
+}
+
+impl DestructiveOperation for CloneDisk<'_> {
+    type Output = ClonedDisk;
+
+    fn description(&self) -> String {
+        match self.archive {
+            Some(archive) => format!(
+                "clone {} to {} and {}",
+                self.source.id,
+                self.disk.id,
+                archive.display()
+            ),
+            None => format!("clone {} to {}", self.source.id, self.disk.id),
+        }
+    }
+
+    fn phase(&self) -> &'static str {
+        "clone"
+    }
+
+    fn disk_id(&self) -> Option<&str> {
+        Some(&self.disk.id)
+    }
+
+    fn execute(&mut self, logs: &mut StepLog, tracker: Option<&mut RunTracker>) -> Result<ClonedDisk> {
+        let source_device = device_path(self.source)?;
+        let target_device = device_path(self.disk)?;
+        let total_bytes = self.source.size_bytes;
+        let (chunk_size, _) =
+            resolve_chunk_size(self.params.chunk_size, &target_device, total_bytes, logs);
+        let mut slot = acquire_write_slot(logs, &self.disk.id, &source_device)?;
+        let mut disks = open_devices(self, &source_device, &target_device, logs)?;
+
+        let mut archive = match self.archive {
+            Some(path) => Some(ArchiveWriter::create(path)?),
+            None => None,
+        };
+        let mut observer = ThroughputObserver::new(tracker, logs.progress_reporter())
+            .with_write_slot(slot.as_mut(), true);
+        let written = {
+            let mut tee = Tee {
+                reader: &mut disks.source,
+                copy: archive.as_mut(),
+            };
+            phoenix_imaging::write_stream_to_open_device(
+                &mut tee,
+                total_bytes,
+                &mut disks.target,
+                chunk_size,
+                false,
+                self.params.flush_every_bytes,
+                &mut observer,
+            )?
+        };
+        let throughput_bytes_per_sec = observer.finish();
+        logs.push(format!("bytes_copied={}", written.bytes_written));
+        logs.push(format!("sha256={}", written.sha256));
+        logs.push(format!("throughput_bytes_per_sec={}", throughput_bytes_per_sec));
+        logs.push(format!("flushes={}", written.flushes));
+        let archive = match (archive, self.archive) {
+            (Some(writer), Some(path)) => {
+                let (bytes, sha256) = writer.finish()?;
+                logs.push(format!("archive_bytes={} archive_sha256={}", bytes, sha256));
+                Some(CloneArchive {
+                    path: path.to_path_buf(),
+                    compression: "zstd".to_string(),
+                    bytes,
+                    sha256,
+                    image_bytes: written.bytes_written,
+                    image_sha256: written.sha256.clone(),
+                })
+            }
+            _ => None,
+        };
+
+        logs.phase("compare");
+        let comparison = compare_devices(&mut disks.source, &mut disks.target, total_bytes, chunk_size)?;
+        logs.push(format!(
+            "compare bytes={} mismatched_chunks={}",
+            comparison.bytes_compared, comparison.mismatched_chunks
+        ));
+        drop(slot);
+        drop(disks);
+        reread_partitions(&target_device, logs);
+        Ok(ClonedDisk {
+            bytes_copied: written.bytes_written,
+            sha256: written.sha256,
+            comparison,
+            archive,
+            chunk_size,
+            throughput_bytes_per_sec,
+        })
+    }
+
+    fn post_verify(&self, cloned: &ClonedDisk, _logs: &mut StepLog) -> Result<()> {
+        if let Some(offset) = cloned.comparison.first_mismatch {
+            return Err(anyhow!(
+                "{} differs from {} in {} chunk(s), the first at byte {}",
+                self.disk.id,
+                self.source.id,
+                cloned.comparison.mismatched_chunks,
+                offset
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Both disks opened for the clone. On macOS they stay unmounted and
+/// claimed through DiskArbitration until this is dropped.
+struct OpenDisks {
+    source: File,
+    target: File,
+    #[cfg(target_os = "macos")]
+    _claims: Vec<phoenix_host_macos::ExclusiveDevice>,
+}
+
+/// Opens the source read-only and the target read/write, unmounting the
+/// volumes of both first.
+fn open_devices(
+    clone: &CloneDisk,
+    source_device: &Path,
+    target_device: &Path,
+    logs: &mut StepLog,
+) -> Result<OpenDisks> {
+    let open = |path: &Path, write: bool| {
+        fs::OpenOptions::new()
+            .read(true)
+            .write(write)
+            .open(path)
+            .with_context(|| format!("open {}", path.display()))
+    };
+    if phoenix_core::mock::is_active() {
+        return Ok(OpenDisks {
+            source: open(source_device, false)?,
+            target: open(target_device, true)?,
+            #[cfg(target_os = "macos")]
+            _claims: Vec::new(),
+        });
+    }
+    #[cfg(target_os = "macos")]
+    {
+        let source = phoenix_host_macos::open_device_exclusive(source_device, false)?;
+        crate::log_exclusive_open(clone.source, &source, logs);
+        let target = phoenix_host_macos::open_device_exclusive(target_device, true)?;
+        crate::log_exclusive_open(clone.disk, &target, logs);
+        Ok(OpenDisks {
+            source: source.file.try_clone()?,
+            target: target.file.try_clone()?,
+            _claims: vec![source, target],
+        })
+    }
+    #[cfg(not(target_os = "macos"))]
+    {
+        crate::unmount_target_disk(clone.source, logs)?;
+        crate::unmount_target_disk(clone.disk, logs)?;
+        Ok(OpenDisks {
+            source: open(source_device, false)?,
+            target: open(target_device, true)?,
+        })
+    }
+}
+
+/// Reads `total_bytes` of both devices from the start and counts the
+/// chunks that differ.
+pub(crate) fn compare_devices(
+    source: &mut File,
+    target: &mut File,
+    total_bytes: u64,
+    chunk_size: u64,
+) -> Result<DeviceComparison> {
+    if chunk_size == 0 {
+        return Err(anyhow!("chunk_size must be greater than zero"));
+    }
+    source.seek(SeekFrom::Start(0))?;
+    target.seek(SeekFrom::Start(0))?;
+    let mut left = vec![0u8; chunk_size as usize];
+    let mut right = vec![0u8; chunk_size as usize];
+    let mut comparison = DeviceComparison::default();
+    while comparison.bytes_compared < total_bytes {
+        let offset = comparison.bytes_compared;
+        let len = (total_bytes - offset).min(chunk_size) as usize;
+        source
+            .read_exact(&mut left[..len])
+            .with_context(|| format!("read source at {}", offset))?;
+        target
+            .read_exact(&mut right[..len])
+            .with_context(|| format!("read target at {}", offset))?;
+        if left[..len] != right[..len] {
+            comparison.mismatched_chunks += 1;
+            comparison.first_mismatch.get_or_insert(offset);
+        }
+        comparison.bytes_compared += len as u64;
+    }
+    Ok(comparison)
+}
+
+/// Hands every byte read from `reader` to `copy` as well.
+struct Tee<'a, R> {
+    reader: &'a mut R,
+    copy: Option<&'a mut ArchiveWriter>,
+}
+
+impl<R: Read> Read for Tee<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.reader.read(buf)?;
+        if let Some(copy) = self.copy.as_mut() {
+            copy.encoder.write_all(&buf[..read])?;
+        }
+        Ok(read)
+    }
+}
+
+/// A zstd stream into a file, hashing the compressed bytes as they land.
+pub(crate) struct ArchiveWriter {
+    encoder: zstd::stream::write::Encoder<'static, HashingFile>,
+}
+
+impl ArchiveWriter {
+    pub(crate) fn create(path: &Path) -> Result<Self> {
+        let file = File::create(path).with_context(|| format!("create archive {}", path.display()))?;
+        let file = HashingFile {
+            file: BufWriter::new(file),
+            hasher: Sha256::new(),
+            bytes: 0,
+        };
+        let encoder = zstd::stream::write::Encoder::new(file, ARCHIVE_ZSTD_LEVEL)
+            .context("start zstd archive")?;
+        Ok(Self { encoder })
+    }
+
+    /// Ends the zstd frame and syncs the file; returns its size and
+    /// SHA-256.
+    pub(crate) fn finish(self) -> Result<(u64, String)> {
+        let mut file = self.encoder.finish().context("finish zstd archive")?;
+        file.file.flush()?;
+        file.file.get_ref().sync_all()?;
+        Ok((file.bytes, to_hex(&file.hasher.finalize())))
+    }
+}
+
+impl Write for ArchiveWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.encoder.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.encoder.flush()
+    }
+}
+
+struct HashingFile {
+    file: BufWriter<File>,
+    hasher: Sha256,
+    bytes: u64,
+}
+
+impl Write for HashingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.file.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        self.bytes += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
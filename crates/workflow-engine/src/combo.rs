@@ -0,0 +1,928 @@
+//! Combo stick: one large stick carrying a Windows installer, a Linux
+//! rescue system and a shared exFAT data partition. Each payload gets its
+//! own FAT32 partition, staged from its own source, and a GRUB menu on a
+//! small EFI system partition chainloads either one. Rescue and
+//! diagnostics tools from a signed pack can get a partition of their own,
+//! and the data partition can be LUKS2 or VeraCrypt encrypted for techs
+//! carrying customer data.
+
+use crate::boot_lint::{lint_boot_menu, BootLintFinding, BootVolume};
+use crate::ledger::RunTracker;
+use crate::operation::{DestructiveOperation, DestructiveSession};
+use crate::secrets::resolve_secret;
+use crate::{
+    build_device_graph, check_format_capacity, check_overwrite, check_target_claim,
+    check_target_disk_size, check_target_wear, collect_files, copy_file_with_mtime,
+    ensure_boot_files, ensure_unix_boot_files, estimate_capacity, join_reasons, max_file_size,
+    signing_key_from_env, staging_backend, target, verify_copy, FileEntry, StepLog,
+};
+use anyhow::{anyhow, Context, Result};
+use phoenix_bootcfg::{stage_grub_menu, GrubMenu, GrubMenuEntry, DEFAULT_GRUB_DIR};
+use phoenix_content::{
+    load_pack_manifest, pack_signature_exists, prepare_source, select_payloads,
+    verify_pack_manifest, verify_payload,
+};
+use phoenix_core::Disk;
+use phoenix_host_windows::format::FileSystem;
+use phoenix_partition::plan::{PartitionPlan, PlannedPartition, TYPE_EFI_SYSTEM};
+use phoenix_partition::{plan_partitions, PartitionSpec, DEFAULT_SECTOR_SIZE};
+use phoenix_report::{create_report_bundle_with_meta_signing_and_artifacts, ReportPaths};
+use phoenix_safety::SafetyContext;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+pub const BOOT_LABEL: &str = "PHX-BOOT";
+pub const WINDOWS_LABEL: &str = "WINSETUP";
+pub const RESCUE_LABEL: &str = "RESCUE";
+pub const TOOLS_LABEL: &str = "TOOLS";
+const DEFAULT_DATA_LABEL: &str = "DATA";
+const DEFAULT_MENU_TIMEOUT: u32 = 10;
+const BOOT_PARTITION_BYTES: u64 = 512 * 1024 * 1024;
+/// Room left on a payload partition past its files, for logs and updates.
+const PAYLOAD_HEADROOM_BYTES: u64 = 256 * 1024 * 1024;
+/// Below this the stick is too small to be worth a data partition.
+const MIN_DATA_BYTES: u64 = 1024 * 1024 * 1024;
+/// exFAT volume labels hold 11 characters.
+const MAX_LABEL_CHARS: usize = 11;
+/// VeraCrypt asks before taking anything shorter, which a batch run can't
+/// answer.
+const MIN_DATA_KEY_CHARS: usize = 20;
+const EFI_LOADER: &str = "efi/boot/bootx64.efi";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ComboStickParams {
+    pub disk_id: String,
+    /// Extracted Windows installer files; staged on `WINSETUP`.
+    pub windows_source: PathBuf,
+    /// Linux rescue system with its own `EFI/BOOT/BOOTX64.EFI`; staged on
+    /// `RESCUE`.
+    pub rescue_source: PathBuf,
+    /// GRUB EFI package for the boot partition, as `stage_bootloader`
+    /// takes it.
+    pub bootloader_source: PathBuf,
+    /// Size of `WINSETUP`; the source plus headroom when unset.
+    #[serde(default, with = "crate::params::byte_size")]
+    pub windows_size: Option<u64>,
+    /// Size of `RESCUE`; the source plus headroom when unset.
+    #[serde(default, with = "crate::params::byte_size")]
+    pub rescue_size: Option<u64>,
+    /// Signed pack whose payloads are staged on a `TOOLS` partition; the
+    /// signature is checked with `$PHOENIX_PACK_KEY`.
+    #[serde(default)]
+    pub payload_pack: Option<PathBuf>,
+    /// Payloads of `payload_pack` to stage; all of them when empty.
+    #[serde(default)]
+    pub payloads: Vec<String>,
+    /// Size of `TOOLS`; the payloads plus headroom when unset.
+    #[serde(default, with = "crate::params::byte_size")]
+    pub tools_size: Option<u64>,
+    /// Label of the exFAT data partition; `DATA` by default.
+    #[serde(default)]
+    pub data_label: Option<String>,
+    /// Encrypts the data partition with a passphrase from
+    /// `data_key_secret`.
+    #[serde(default)]
+    pub data_encryption: Option<DataEncryption>,
+    /// Name of the secret holding the passphrase, as the `KeyProvider`
+    /// looks it up. Only the name is kept in params and the report.
+    #[serde(default)]
+    pub data_key_secret: Option<String>,
+    #[serde(default)]
+    pub menu_title: Option<String>,
+    /// Seconds before the first entry boots; 10 by default.
+    #[serde(default)]
+    pub menu_timeout: Option<u32>,
+    #[serde(default = "crate::params::default_report_base")]
+    pub report_base: PathBuf,
+    #[serde(default)]
+    pub force: bool,
+    #[serde(default)]
+    pub confirmation_token: Option<String>,
+    #[serde(default)]
+    pub acknowledge_target_size: bool,
+    /// Proceed on a device past the wear limits; recorded in the report
+    /// and the audit log.
+    #[serde(default)]
+    pub acknowledge_device_wear: bool,
+    #[serde(default)]
+    pub confirm_overwrite: bool,
+    #[serde(default = "crate::params::default_true")]
+    pub dry_run: bool,
+}
+
+/// How the data partition is encrypted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DataEncryption {
+    /// LUKS2 by `cryptsetup`; opens on Linux.
+    Luks2,
+    /// A VeraCrypt volume; opens on Windows, macOS and Linux.
+    Veracrypt,
+}
+
+impl DataEncryption {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Luks2 => "luks2",
+            Self::Veracrypt => "veracrypt",
+        }
+    }
+
+    fn filesystem(self) -> &'static str {
+        match self {
+            Self::Luks2 => "exFAT in LUKS2",
+            Self::Veracrypt => "exFAT in VeraCrypt",
+        }
+    }
+}
+
+pub fn parse_data_encryption(value: &str) -> Result<DataEncryption> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "luks2" | "luks" => Ok(DataEncryption::Luks2),
+        "veracrypt" => Ok(DataEncryption::Veracrypt),
+        other => Err(anyhow!("unknown data encryption: {} (luks2, veracrypt)", other)),
+    }
+}
+
+/// One partition of the stick as planned, and what was staged on it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ComboPartition {
+    pub number: u32,
+    pub label: String,
+    pub filesystem: String,
+    pub size_bytes: u64,
+    pub source: Option<PathBuf>,
+    pub files: usize,
+    pub bytes: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ComboStickResult {
+    pub report: ReportPaths,
+    pub disk_id: String,
+    pub partitions: Vec<ComboPartition>,
+    /// Problems boot lint found on the stick after staging; the run fails
+    /// on any found before.
+    pub boot_lint: Vec<BootLintFinding>,
+    pub dry_run: bool,
+}
+
+/// A FAT32 partition: its label, source, files and requested size.
+struct Payload {
+    label: &'static str,
+    source: PathBuf,
+    files: Vec<FileEntry>,
+    size_bytes: Option<u64>,
+}
+
+impl Payload {
+    fn bytes(&self) -> u64 {
+        self.files.iter().map(|entry| entry.size).sum()
+    }
+
+    /// The files as boot lint sees them before anything is copied.
+    fn planned_volume(&self) -> BootVolume {
+        let mut volume = BootVolume::new(self.label);
+        for entry in &self.files {
+            volume.insert(&entry.relative_path, entry.absolute_path.clone());
+        }
+        volume
+    }
+}
+
+/// An EFI payload of the pack, as its menu entry needs it.
+struct EfiTool {
+    title: String,
+    loader: String,
+}
+
+pub fn run_combo_stick(params: &ComboStickParams) -> Result<ComboStickResult> {
+    let started = Instant::now();
+    if !cfg!(target_os = "linux") {
+        return Err(anyhow!("combo stick workflow requires linux"));
+    }
+    let data_label = params.data_label.as_deref().unwrap_or(DEFAULT_DATA_LABEL);
+    check_label(data_label)?;
+    let data_key = data_key(params)?;
+
+    let graph = build_device_graph()?;
+    let disk = graph
+        .disks
+        .iter()
+        .find(|disk| disk.id.eq_ignore_ascii_case(&params.disk_id))
+        .ok_or_else(|| anyhow!("disk not found: {}", params.disk_id))?;
+    if disk.is_system_disk {
+        return Err(anyhow!("refusing to target system disk: {}", disk.id));
+    }
+    if !disk.removable {
+        return Err(anyhow!("target disk is not marked removable: {}", disk.id));
+    }
+    if let Some(reason) = target::stack_usage(&graph, disk) {
+        return Err(anyhow!(reason));
+    }
+    let target_size_acknowledged = check_target_disk_size(disk, params.acknowledge_target_size)?;
+    let device_claim = check_target_claim(disk, "combo-stick", params.dry_run)?;
+    let device_wear = join_reasons(
+        device_claim,
+        check_target_wear(disk, "combo-stick", params.acknowledge_device_wear, params.dry_run)?,
+    );
+    let overwrite_triggers = check_overwrite(disk, params.confirm_overwrite, params.dry_run)?;
+
+    let bootloader = phoenix_bootloader_core::validate_bootloader_package(&params.bootloader_source)?;
+    let boot = Payload {
+        label: BOOT_LABEL,
+        source: bootloader.root.clone(),
+        files: collect_files(&bootloader.root)?,
+        size_bytes: Some(BOOT_PARTITION_BYTES),
+    };
+    let windows_source = prepare_source(&params.windows_source)?;
+    let windows_files = collect_files(&windows_source.root)?;
+    ensure_boot_files(&windows_files)?;
+    let windows = Payload {
+        label: WINDOWS_LABEL,
+        source: windows_source.root.clone(),
+        files: windows_files,
+        size_bytes: params.windows_size,
+    };
+    let rescue_source = prepare_source(&params.rescue_source)?;
+    let rescue_files = collect_files(&rescue_source.root)?;
+    ensure_unix_boot_files(&rescue_files, "linux")?;
+    let rescue = Payload {
+        label: RESCUE_LABEL,
+        source: rescue_source.root.clone(),
+        files: rescue_files,
+        size_bytes: params.rescue_size,
+    };
+    let windows_loader = efi_loader(windows.label, &windows.files)?;
+    let rescue_loader = efi_loader(rescue.label, &rescue.files)?;
+    let fat32 = staging_backend(FileSystem::Fat32);
+    if let Some(problem) = fat32.file_size_problem(max_file_size(&windows.files)) {
+        return Err(anyhow!(
+            "windows source: {} Split sources/install.wim into .swm parts first.",
+            problem
+        ));
+    }
+    if let Some(problem) = fat32.file_size_problem(max_file_size(&rescue.files)) {
+        return Err(anyhow!("rescue source: {}", problem));
+    }
+    let mut payloads = vec![boot, windows, rescue];
+    let mut efi_tools = Vec::new();
+    let mut payload_names = Vec::new();
+    if let Some(pack) = &params.payload_pack {
+        let (tools, tools_efi, names) = pack_payloads(pack, &params.payloads)?;
+        if let Some(problem) = fat32.file_size_problem(max_file_size(&tools)) {
+            return Err(anyhow!("payload pack: {}", problem));
+        }
+        payloads.push(Payload {
+            label: TOOLS_LABEL,
+            source: pack.clone(),
+            files: tools,
+            size_bytes: params.tools_size,
+        });
+        efi_tools = tools_efi;
+        payload_names = names;
+    } else if !params.payloads.is_empty() {
+        return Err(anyhow!("payloads requires payload_pack"));
+    }
+
+    let mut specs: Vec<PartitionSpec> = payloads
+        .iter()
+        .map(|payload| PartitionSpec {
+            size_bytes: Some(payload.size_bytes.unwrap_or_else(|| payload_size(payload))),
+            ..PartitionSpec::basic_data(payload.label)
+        })
+        .collect();
+    specs[0].type_guid = TYPE_EFI_SYSTEM;
+    specs.push(PartitionSpec::basic_data(data_label));
+    let plan = plan_partitions(disk.size_bytes, DEFAULT_SECTOR_SIZE, &specs)
+        .with_context(|| format!("{} is too small for the combo layout", disk.id))?;
+    let data_bytes = plan
+        .partitions
+        .last()
+        .map_or(0, |partition| partition.length_bytes(DEFAULT_SECTOR_SIZE));
+    if data_bytes < MIN_DATA_BYTES {
+        return Err(anyhow!(
+            "{} leaves only {} bytes for {}; use a larger stick or smaller partitions",
+            disk.id,
+            data_bytes,
+            data_label
+        ));
+    }
+
+    let mut logs = StepLog::new("combo-stick");
+    logs.push(format!("disk_id={}", disk.id));
+    if let Some(reason) = &target_size_acknowledged {
+        logs.push(format!("target_size_acknowledged={}", reason));
+    }
+    if let Some(reason) = &device_wear {
+        logs.push(format!("device_wear={}", reason));
+    }
+    for trigger in &overwrite_triggers {
+        logs.push(format!("overwrite_trigger={}", trigger));
+    }
+    for payload in &payloads {
+        logs.push(format!("source={} path={}", payload.label, payload.source.display()));
+    }
+    if let Some(encryption) = params.data_encryption {
+        logs.push(format!("data_encryption={}", encryption.as_str()));
+    }
+    for name in &payload_names {
+        logs.push(format!("payload={}", name));
+    }
+    for partition in &plan.partitions {
+        logs.push(format!(
+            "partition={} name={} type={} first_lba={} last_lba={}",
+            partition.number,
+            partition.spec.name,
+            partition.spec.type_guid,
+            partition.first_lba,
+            partition.last_lba
+        ));
+    }
+    let mut format_capacity = Vec::new();
+    for (payload, partition) in payloads.iter().zip(&plan.partitions).skip(1) {
+        let estimate =
+            estimate_capacity(FileSystem::Fat32, partition.length_bytes(DEFAULT_SECTOR_SIZE), None);
+        format_capacity.push(
+            check_format_capacity(&estimate, &payload.files, &mut logs)
+                .with_context(|| format!("{} partition", payload.label))?,
+        );
+    }
+
+    let menu = combo_menu(params, &windows_loader, &rescue_loader, &efi_tools);
+    let planned: Vec<BootVolume> = payloads.iter().map(Payload::planned_volume).collect();
+    let findings = lint_boot_menu(&menu, BOOT_LABEL, &planned);
+    if !findings.is_empty() {
+        return Err(anyhow!("boot lint failed: {}", describe_findings(&findings)));
+    }
+    logs.push(format!("boot_lint=ok entries={}", menu.entries.len()));
+    let ctx = SafetyContext {
+        force_mode: params.force,
+        confirmation_token: params.confirmation_token.clone(),
+        allow_system_disk: false,
+        armed_until: phoenix_safety::armed_until(),
+    };
+    let mut session = DestructiveSession::begin(
+        "combo-stick",
+        Some(disk),
+        &ctx,
+        disk.is_system_disk,
+        params.dry_run,
+        &mut logs,
+    )?;
+    let mut partition = PartitionComboStick {
+        disk,
+        plan: &plan,
+        encryption: params.data_encryption.zip(data_key.as_deref()),
+    };
+    let mounts = session.perform(&mut partition, &mut logs)?;
+
+    let mut staged_menu = None;
+    let mut boot_lint = Vec::new();
+    if let Some(mounts) = &mounts {
+        session.phase("copy", &mut logs)?;
+        let total_bytes = payloads.iter().map(Payload::bytes).sum::<u64>();
+        let mut copied_bytes = 0u64;
+        for (payload, mount) in payloads.iter().zip(mounts) {
+            logs.push(format!("stage={} mount={}", payload.label, mount.display()));
+            for entry in &payload.files {
+                let dest = mount.join(&entry.relative_path);
+                if let Some(parent) = dest.parent() {
+                    fs::create_dir_all(parent)
+                        .with_context(|| format!("create dir {}", parent.display()))?;
+                }
+                copy_file_with_mtime(&entry.absolute_path, &dest).with_context(|| {
+                    format!("copy {} to {}", entry.absolute_path.display(), dest.display())
+                })?;
+                copied_bytes = copied_bytes.saturating_add(entry.size);
+                logs.progress(copied_bytes, total_bytes);
+            }
+            session.checkpoint(copied_bytes);
+        }
+        logs.push("copy_complete".to_string());
+
+        session.phase("boot_menu", &mut logs)?;
+        let staged = stage_grub_menu(
+            &menu,
+            &bootloader.root,
+            "combo",
+            &mounts[0],
+            DEFAULT_GRUB_DIR,
+            None,
+        )?;
+        logs.push(format!("boot_menu={}", staged.config.display()));
+        staged_menu = Some(staged);
+
+        session.phase("verify", &mut logs)?;
+        for (payload, mount) in payloads.iter().zip(mounts) {
+            verify_copy(mount, &payload.files)?;
+        }
+        logs.push("verify_complete".to_string());
+        let staged_volumes = payloads
+            .iter()
+            .zip(mounts)
+            .map(|(payload, mount)| BootVolume::scan(payload.label, mount))
+            .collect::<Result<Vec<_>>>()?;
+        boot_lint = lint_boot_menu(&menu, BOOT_LABEL, &staged_volumes);
+        for finding in &boot_lint {
+            logs.push(format!("boot_lint_finding={}: {}", finding.entry, finding.problem));
+        }
+    } else {
+        logs.push("dry_run=true".to_string());
+    }
+
+    let partitions: Vec<ComboPartition> = plan
+        .partitions
+        .iter()
+        .map(|partition| {
+            let payload = payloads
+                .iter()
+                .find(|payload| payload.label == partition.spec.name);
+            ComboPartition {
+                number: partition.number,
+                label: partition.spec.name.clone(),
+                filesystem: match (payload, params.data_encryption) {
+                    (Some(_), _) => "FAT32",
+                    (None, Some(encryption)) => encryption.filesystem(),
+                    (None, None) => "exFAT",
+                }
+                .to_string(),
+                size_bytes: partition.length_bytes(DEFAULT_SECTOR_SIZE),
+                source: payload.map(|payload| payload.source.clone()),
+                files: payload.map_or(0, |payload| payload.files.len()),
+                bytes: payload.map_or(0, Payload::bytes),
+            }
+        })
+        .collect();
+
+    let (log_text, timing) = logs.finish()?;
+    let meta = serde_json::json!({
+        "workflow": "combo-stick",
+        "status": if params.dry_run { "dry_run" } else { "completed" },
+        "disk_id": disk.id,
+        "target_serial": disk.serial,
+        "partitions": partitions,
+        "payload_pack": params.payload_pack.as_ref().map(|pack| pack.display().to_string()),
+        "payloads": payload_names,
+        "format_capacity": format_capacity,
+        "boot_menu": {
+            "title": menu.title,
+            "timeout": menu.timeout,
+            "entries": menu.entries.iter().map(|entry| entry.title.clone()).collect::<Vec<_>>(),
+            "config": staged_menu.as_ref().map(|staged| staged.config.display().to_string()),
+        },
+        "boot_lint": boot_lint,
+        "data_encryption": params.data_encryption,
+        "data_key_secret": params.data_key_secret,
+        "destructive_operations": session.operations(),
+        "target_size_acknowledged": target_size_acknowledged,
+        "device_wear": device_wear,
+        "device_wear_acknowledged": params.acknowledge_device_wear,
+        "overwrite_triggers": overwrite_triggers,
+        "overwrite_confirmed": params.confirm_overwrite,
+        "dry_run": params.dry_run,
+        "duration_ms": started.elapsed().as_millis() as u64
+    });
+
+    let report = create_report_bundle_with_meta_signing_and_artifacts(
+        &params.report_base,
+        &graph,
+        Some(meta),
+        Some(&log_text),
+        signing_key_from_env().as_deref(),
+        &[timing],
+    )?;
+    session.complete(&report.root)?;
+
+    Ok(ComboStickResult {
+        report,
+        disk_id: disk.id.clone(),
+        partitions,
+        boot_lint,
+        dry_run: params.dry_run,
+    })
+}
+
+/// The data partition passphrase, from the `KeyProvider`.
+fn data_key(params: &ComboStickParams) -> Result<Option<String>> {
+    let (encryption, name) = match (params.data_encryption, &params.data_key_secret) {
+        (Some(encryption), Some(name)) => (encryption, name),
+        (Some(encryption), None) => {
+            return Err(anyhow!("{} data encryption needs data_key_secret", encryption.as_str()))
+        }
+        (None, Some(_)) => return Err(anyhow!("data_key_secret needs data_encryption")),
+        (None, None) => return Ok(None),
+    };
+    let key = resolve_secret(name)?;
+    if key.chars().count() < MIN_DATA_KEY_CHARS {
+        return Err(anyhow!(
+            "{} passphrase in secret {} must be at least {} characters",
+            encryption.as_str(),
+            name,
+            MIN_DATA_KEY_CHARS
+        ));
+    }
+    Ok(Some(key))
+}
+
+fn check_label(label: &str) -> Result<()> {
+    if label.trim().is_empty() || label.chars().count() > MAX_LABEL_CHARS {
+        return Err(anyhow!(
+            "data label must be 1 to {} characters: {}",
+            MAX_LABEL_CHARS,
+            label
+        ));
+    }
+    Ok(())
+}
+
+/// The payload plus a twentieth and the headroom, on a MiB boundary.
+fn payload_size(payload: &Payload) -> u64 {
+    let bytes = payload.bytes();
+    let size = bytes + bytes / 20 + PAYLOAD_HEADROOM_BYTES;
+    size.div_ceil(1024 * 1024) * 1024 * 1024
+}
+
+/// The source's own x64 EFI loader, as the menu chainloads it.
+pub(crate) fn efi_loader(label: &str, files: &[FileEntry]) -> Result<String> {
+    files
+        .iter()
+        .map(|entry| entry.relative_path.to_string_lossy().replace('\\', "/"))
+        .find(|path| path.eq_ignore_ascii_case(EFI_LOADER))
+        .map(|path| format!("/{}", path))
+        .ok_or_else(|| anyhow!("{} source has no EFI/BOOT/BOOTX64.EFI to chainload", label))
+}
+
+/// The verified files of the pack's selected payloads, each under a
+/// directory named after its payload, with the EFI ones and the names.
+fn pack_payloads(pack: &Path, names: &[String]) -> Result<(Vec<FileEntry>, Vec<EfiTool>, Vec<String>)> {
+    let manifest = load_pack_manifest(pack)?;
+    if !pack_signature_exists(pack) {
+        return Err(anyhow!("payload pack {} is not signed", pack.display()));
+    }
+    let key = std::env::var("PHOENIX_PACK_KEY")
+        .map_err(|_| anyhow!("set PHOENIX_PACK_KEY to verify payload pack {}", pack.display()))?;
+    if !verify_pack_manifest(pack, &key)? {
+        return Err(anyhow!("payload pack {} signature does not verify", pack.display()));
+    }
+    let pack_root = pack.parent().unwrap_or_else(|| Path::new("."));
+    let selected = select_payloads(&manifest, names)?;
+    if selected.is_empty() {
+        return Err(anyhow!("payload pack {} has no payloads", pack.display()));
+    }
+    let mut files = Vec::new();
+    let mut tools = Vec::new();
+    let mut selected_names = Vec::new();
+    for payload in selected {
+        for file in verify_payload(pack_root, payload)? {
+            files.push(FileEntry {
+                absolute_path: file.absolute_path,
+                relative_path: Path::new(&payload.name).join(&file.relative_path),
+                size: file.size,
+            });
+        }
+        if let Some(efi) = &payload.efi {
+            tools.push(EfiTool {
+                title: payload.title.clone().unwrap_or_else(|| payload.name.clone()),
+                loader: format!("/{}/{}", payload.name, efi),
+            });
+        }
+        selected_names.push(payload.name.clone());
+    }
+    Ok((files, tools, selected_names))
+}
+
+pub(crate) fn describe_findings(findings: &[BootLintFinding]) -> String {
+    findings
+        .iter()
+        .map(|finding| format!("{}: {}", finding.entry, finding.problem))
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+fn combo_menu(
+    params: &ComboStickParams,
+    windows_loader: &str,
+    rescue_loader: &str,
+    tools: &[EfiTool],
+) -> GrubMenu {
+    GrubMenu {
+        title: params.menu_title.clone(),
+        timeout: Some(params.menu_timeout.unwrap_or(DEFAULT_MENU_TIMEOUT)),
+        default: None,
+        theme: None,
+        entries: [
+            GrubMenuEntry {
+                title: "Windows Setup".to_string(),
+                class: vec!["windows".to_string()],
+                commands: chainload_commands(WINDOWS_LABEL, windows_loader),
+            },
+            GrubMenuEntry {
+                title: "Linux Rescue".to_string(),
+                class: vec!["linux".to_string()],
+                commands: chainload_commands(RESCUE_LABEL, rescue_loader),
+            },
+        ]
+        .into_iter()
+        .chain(tools.iter().map(|tool| GrubMenuEntry {
+            title: tool.title.clone(),
+            class: vec!["efi".to_string()],
+            commands: chainload_commands(TOOLS_LABEL, &tool.loader),
+        }))
+        .chain([GrubMenuEntry {
+            title: "Firmware Setup".to_string(),
+            class: vec!["efi".to_string()],
+            commands: vec!["fwsetup".to_string()],
+        }])
+        .collect(),
+    }
+}
+
+/// Boots the EFI loader at `loader` on the FAT volume labelled `label`.
+pub(crate) fn chainload_commands(label: &str, loader: &str) -> Vec<String> {
+    vec![
+        "insmod part_gpt".to_string(),
+        "insmod fat".to_string(),
+        "insmod chain".to_string(),
+        format!("search --no-floppy --set=root --label {}", label),
+        format!("chainloader {}", loader),
+    ]
+}
+
+/// Writes the combo GPT and formats every partition: FAT32 for the boot
+/// and payload partitions, exFAT for the data one, which comes last.
+/// Returns where the FAT32 volumes are mounted, in partition order.
+struct PartitionComboStick<'a> {
+    disk: &'a Disk,
+    plan: &'a PartitionPlan,
+    /// How the data partition is encrypted, and the passphrase.
+    encryption: Option<(DataEncryption, &'a str)>,
+}
+
+impl DestructiveOperation for PartitionComboStick<'_> {
+    type Output = Vec<PathBuf>;
+
+    fn description(&self) -> String {
+        let names: Vec<&str> = self
+            .plan
+            .partitions
+            .iter()
+            .map(|partition| partition.spec.name.as_str())
+            .collect();
+        let mut description =
+            format!("repartition {} as a combo stick ({})", self.disk.id, names.join(", "));
+        if let Some((encryption, _)) = self.encryption {
+            description.push_str(&format!(", data encrypted with {}", encryption.as_str()));
+        }
+        description
+    }
+
+    fn phase(&self) -> &'static str {
+        "partition"
+    }
+
+    fn disk_id(&self) -> Option<&str> {
+        Some(&self.disk.id)
+    }
+
+    fn execute(&mut self, logs: &mut StepLog, _tracker: Option<&mut RunTracker>) -> Result<Vec<PathBuf>> {
+        let encryption = self.encryption.map(|(encryption, _)| encryption);
+        if phoenix_core::mock::is_active() {
+            return mock_partition(self.disk, self.plan, encryption, logs);
+        }
+        partition_device(self.disk, self.plan, self.encryption, logs)
+    }
+
+    fn post_verify(&self, mounts: &Vec<PathBuf>, _logs: &mut StepLog) -> Result<()> {
+        for mount in mounts {
+            if !mount.is_dir() {
+                return Err(anyhow!("{} is not mounted after the format", mount.display()));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// `PHOENIX_HOST=mock`: the GPT goes into the disk's sandbox file and
+/// every partition gets an empty sandbox volume. Encryption is recorded
+/// as the command that would run, without the passphrase.
+fn mock_partition(
+    disk: &Disk,
+    plan: &PartitionPlan,
+    encryption: Option<DataEncryption>,
+    logs: &mut StepLog,
+) -> Result<Vec<PathBuf>> {
+    let path = phoenix_core::mock::disk_file(disk)?;
+    let mut file = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(&path)
+        .with_context(|| format!("open {}", path.display()))?;
+    plan.write_gpt(&mut file)?;
+    let mut mounts = Vec::new();
+    for partition in &plan.partitions {
+        let mount =
+            phoenix_core::mock::mount_dir(&format!("{}-Partition{}", disk.id, partition.number))?;
+        phoenix_core::mock::format_volume(&mount)?;
+        logs.push(format!("formatted={} label={}", mount.display(), partition.spec.name));
+        if !is_data_partition(plan, partition) {
+            mounts.push(mount);
+        } else if let Some(encryption) = encryption {
+            let device = format!("/dev/{}{}", disk.id, partition.number);
+            let (program, args) = encryption_command(encryption, &device, &partition.spec.name);
+            phoenix_core::mock::record_command(program, &args)?;
+            logs.push(format!("data_encryption={} device={}", encryption.as_str(), device));
+        }
+    }
+    Ok(mounts)
+}
+
+fn is_data_partition(plan: &PartitionPlan, partition: &PlannedPartition) -> bool {
+    plan.partitions.last().is_some_and(|last| last.number == partition.number)
+}
+
+#[cfg(target_os = "linux")]
+fn partition_device(
+    disk: &Disk,
+    plan: &PartitionPlan,
+    encryption: Option<(DataEncryption, &str)>,
+    logs: &mut StepLog,
+) -> Result<Vec<PathBuf>> {
+    crate::unmount_target_disk(disk, logs)?;
+    let device = Path::new("/dev").join(&disk.id);
+    let mut file = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(&device)
+        .with_context(|| format!("open {}", device.display()))?;
+    plan.write_gpt(&mut file)?;
+    file.sync_all()?;
+    drop(file);
+    let names = phoenix_host_linux::reread_partition_table(&device)?;
+    logs.push(format!("partition_reread=ok partitions={}", names.join(",")));
+    if names.len() != plan.partitions.len() {
+        return Err(anyhow!(
+            "{} shows {} partitions after the re-read, expected {}",
+            device.display(),
+            names.len(),
+            plan.partitions.len()
+        ));
+    }
+
+    let mut mounts = Vec::new();
+    for (partition, name) in plan.partitions.iter().zip(&names) {
+        let node = Path::new("/dev").join(name);
+        let label = partition.spec.name.as_str();
+        if is_data_partition(plan, partition) {
+            match encryption {
+                Some((encryption, key)) => {
+                    format_encrypted_exfat(&node, label, encryption, key, &disk.id)?;
+                    logs.push(format!(
+                        "format_exfat={} label={} encryption={}",
+                        node.display(),
+                        label,
+                        encryption.as_str()
+                    ));
+                }
+                None => {
+                    format_exfat(&node, label)?;
+                    logs.push(format!("format_exfat={} label={}", node.display(), label));
+                }
+            }
+            continue;
+        }
+        let layout = phoenix_fs_fat32::format_fat32_with_cluster_size(
+            &node,
+            partition.length_bytes(DEFAULT_SECTOR_SIZE),
+            Some(label),
+            None,
+        )?;
+        logs.push(format!("format_fat32={} label={}", node.display(), label));
+        for warning in &layout.label_warnings {
+            logs.push(format!("label_warning={}", warning));
+        }
+        let mount = std::env::temp_dir().join(format!("phoenix-combo-{}-{}", disk.id, name));
+        phoenix_host_linux::mount_partition(&node, &mount, "vfat")?;
+        logs.push(format!("mounted={} at {}", node.display(), mount.display()));
+        mounts.push(mount);
+    }
+    Ok(mounts)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn partition_device(
+    _disk: &Disk,
+    _plan: &PartitionPlan,
+    _encryption: Option<(DataEncryption, &str)>,
+    _logs: &mut StepLog,
+) -> Result<Vec<PathBuf>> {
+    Err(anyhow!("combo stick workflow requires linux"))
+}
+
+/// `mkfs.exfat` from exfatprogs; there is no built-in exFAT formatter.
+#[cfg(target_os = "linux")]
+fn format_exfat(device: &Path, label: &str) -> Result<()> {
+    let device = device.to_string_lossy();
+    run_tool("mkfs.exfat", &["-n", label, &device], None, "exfatprogs")
+}
+
+/// Creates the encrypted container on `device` with `key` and an exFAT
+/// volume inside it. The key only ever goes to the tool's stdin.
+#[cfg(target_os = "linux")]
+fn format_encrypted_exfat(
+    device: &Path,
+    label: &str,
+    encryption: DataEncryption,
+    key: &str,
+    disk_id: &str,
+) -> Result<()> {
+    let device_name = device.to_string_lossy();
+    let (program, args) = encryption_command(encryption, &device_name, label);
+    match encryption {
+        DataEncryption::Luks2 => {
+            run_tool(program, &args, Some(key), "cryptsetup")?;
+            let mapping = format!("phoenix-{}-data", disk_id);
+            run_tool(
+                "cryptsetup",
+                &["open", "--key-file", "-", &device_name, &mapping],
+                Some(key),
+                "cryptsetup",
+            )?;
+            let formatted = format_exfat(&Path::new("/dev/mapper").join(&mapping), label);
+            let closed = run_tool("cryptsetup", &["close", &mapping], None, "cryptsetup");
+            formatted.and(closed)
+        }
+        // VeraCrypt formats the exFAT volume itself, with mkfs.exfat.
+        DataEncryption::Veracrypt => run_tool(program, &args, Some(key), "veracrypt"),
+    }
+}
+
+/// The command that creates the encrypted container; it reads the
+/// passphrase from stdin.
+fn encryption_command<'a>(
+    encryption: DataEncryption,
+    device: &'a str,
+    label: &'a str,
+) -> (&'static str, Vec<&'a str>) {
+    match encryption {
+        DataEncryption::Luks2 => (
+            "cryptsetup",
+            vec![
+                "luksFormat", "--type", "luks2", "--batch-mode", "--label", label, "--key-file", "-",
+                device,
+            ],
+        ),
+        DataEncryption::Veracrypt => (
+            "veracrypt",
+            vec![
+                "--text",
+                "--non-interactive",
+                "--stdin",
+                "--create",
+                device,
+                "--volume-type=normal",
+                "--encryption=AES",
+                "--hash=SHA-512",
+                "--filesystem=exFAT",
+                "--pim=0",
+                "--keyfiles=",
+                "--random-source=/dev/urandom",
+                "--quick",
+            ],
+        ),
+    }
+}
+
+/// Runs `program`, feeding `input` to its stdin, and fails with its
+/// stderr when it does.
+#[cfg(target_os = "linux")]
+fn run_tool(program: &str, args: &[&str], input: Option<&str>, package: &str) -> Result<()> {
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(if input.is_some() { Stdio::piped() } else { Stdio::null() })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("run {} (install {})", program, package))?;
+    if let (Some(input), Some(mut stdin)) = (input, child.stdin.take()) {
+        stdin
+            .write_all(input.as_bytes())
+            .with_context(|| format!("write {} stdin", program))?;
+    }
+    let output = crate::cancel::wait_with_output(child)?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "{} {} failed ({}): {}",
+            program,
+            args.first().copied().unwrap_or_default(),
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(())
+}
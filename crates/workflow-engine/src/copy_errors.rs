@@ -0,0 +1,125 @@
+//! `continue_on_error` for installer copies. A file that cannot be read
+//! from the source (a scratched disc) or written to the stick is recorded
+//! and skipped, and the run copies the rest and ends `degraded` instead of
+//! failing. The skipped files go to a failure manifest in the report.
+
+use crate::{cancel, FileEntry};
+use anyhow::Result;
+use phoenix_report::ReportArtifact;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+pub const COPY_FAILURES_FILE: &str = "copy_failures.json";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FailureSide {
+    /// The source no longer reads back in full.
+    Source,
+    /// The source reads; writing or verifying the copy failed.
+    Destination,
+}
+
+impl FailureSide {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Source => "source",
+            Self::Destination => "destination",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CopyFailure {
+    /// Relative to the source root, `/`-separated.
+    pub path: String,
+    pub bytes: u64,
+    pub side: FailureSide,
+    pub error: String,
+}
+
+pub(crate) struct CopyErrors {
+    continue_on_error: bool,
+    failures: Vec<CopyFailure>,
+}
+
+impl CopyErrors {
+    pub(crate) fn new(continue_on_error: bool) -> Self {
+        Self {
+            continue_on_error,
+            failures: Vec::new(),
+        }
+    }
+
+    /// Records `err` for `entry` and returns `Ok` so the copy goes on.
+    /// Without `continue_on_error`, or once the run is cancelled, `err` is
+    /// returned as is. The partial copy is removed so a later verify does
+    /// not find a truncated file.
+    pub(crate) fn record(
+        &mut self,
+        entry: &FileEntry,
+        dest: &Path,
+        err: anyhow::Error,
+    ) -> Result<&CopyFailure> {
+        if !self.continue_on_error || cancel::is_cancelled() {
+            return Err(err);
+        }
+        let _ = fs::remove_file(dest);
+        let source_reads = fs::File::open(&entry.absolute_path)
+            .and_then(|mut file| io::copy(&mut file, &mut io::sink()))
+            .is_ok();
+        self.failures.push(CopyFailure {
+            path: entry.relative_path.to_string_lossy().replace('\\', "/"),
+            bytes: entry.size,
+            side: if source_reads {
+                FailureSide::Destination
+            } else {
+                FailureSide::Source
+            },
+            error: format!("{:#}", err),
+        });
+        Ok(&self.failures[self.failures.len() - 1])
+    }
+
+    pub(crate) fn failures(&self) -> &[CopyFailure] {
+        &self.failures
+    }
+
+    /// The entries that were copied, for the verify pass.
+    pub(crate) fn copied<'a>(&self, files: &'a [FileEntry]) -> Vec<&'a FileEntry> {
+        files
+            .iter()
+            .filter(|entry| {
+                let path = entry.relative_path.to_string_lossy().replace('\\', "/");
+                !self.failures.iter().any(|failure| failure.path == path)
+            })
+            .collect()
+    }
+
+    /// Report `status`: `degraded` when any file was skipped.
+    pub(crate) fn status(&self, dry_run: bool) -> &'static str {
+        if dry_run {
+            "dry_run"
+        } else if self.failures.is_empty() {
+            "completed"
+        } else {
+            "degraded"
+        }
+    }
+
+    pub(crate) fn push_artifact(
+        &self,
+        artifacts: &mut Vec<ReportArtifact>,
+        artifact_names: &mut Vec<String>,
+    ) -> Result<()> {
+        if self.failures.is_empty() {
+            return Ok(());
+        }
+        let artifact = ReportArtifact::json(COPY_FAILURES_FILE, &self.failures)?;
+        artifact_names.push(artifact.name.clone());
+        artifacts.push(artifact);
+        Ok(())
+    }
+}
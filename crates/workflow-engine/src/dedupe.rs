@@ -0,0 +1,135 @@
+//! Duplicate payloads in installer sources. Identical files are found by
+//! size then SHA-256, and later copies are hardlinked to the first one on
+//! filesystems that support links (NTFS, ext4, APFS). FAT32 and exFAT do
+//! not, so every file is copied there.
+
+use crate::{hash_file, FileEntry};
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+/// Smaller files are always copied; a link saves almost nothing.
+pub const DEDUPE_MIN_BYTES: u64 = 64 * 1024;
+
+pub const DEDUPE_MAP_FILE: &str = "dedupe_map.json";
+
+/// Files with identical contents; `original` is copied, `duplicates` are
+/// linked to it.
+#[derive(Debug, Clone, Serialize)]
+pub struct DedupeGroup {
+    pub sha256: String,
+    pub bytes: u64,
+    pub original: String,
+    pub duplicates: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct DedupeSummary {
+    pub groups: Vec<DedupeGroup>,
+    /// Bytes the duplicates add up to; saved when links work.
+    pub duplicate_bytes: u64,
+    pub linked_files: usize,
+    pub saved_bytes: u64,
+    /// `false` once the target refused a link; the rest were copied.
+    pub links_supported: bool,
+}
+
+pub(crate) struct Deduper {
+    /// Duplicate index to the index of its original.
+    originals: HashMap<usize, usize>,
+    hashes: HashMap<usize, String>,
+    summary: DedupeSummary,
+}
+
+impl Deduper {
+    /// Hashes only files that share their size with another file.
+    pub(crate) fn plan(files: &[FileEntry]) -> Result<Self> {
+        let mut by_size: HashMap<u64, Vec<usize>> = HashMap::new();
+        for (index, entry) in files.iter().enumerate() {
+            if entry.size >= DEDUPE_MIN_BYTES {
+                by_size.entry(entry.size).or_default().push(index);
+            }
+        }
+        let mut hashes = HashMap::new();
+        let mut by_hash: HashMap<String, Vec<usize>> = HashMap::new();
+        for indices in by_size.into_values().filter(|indices| indices.len() > 1) {
+            for index in indices {
+                let hash = hash_file(&files[index].absolute_path)?;
+                by_hash.entry(hash.clone()).or_default().push(index);
+                hashes.insert(index, hash);
+            }
+        }
+
+        let mut originals = HashMap::new();
+        let mut summary = DedupeSummary {
+            links_supported: true,
+            ..DedupeSummary::default()
+        };
+        for (sha256, mut indices) in by_hash.into_iter().filter(|(_, indices)| indices.len() > 1) {
+            // The first file in copy order is the one actually copied.
+            indices.sort_unstable();
+            let original = indices[0];
+            for &duplicate in &indices[1..] {
+                originals.insert(duplicate, original);
+            }
+            let bytes = files[original].size;
+            summary.duplicate_bytes += bytes * (indices.len() as u64 - 1);
+            summary.groups.push(DedupeGroup {
+                sha256,
+                bytes,
+                original: relative(&files[original]),
+                duplicates: indices[1..].iter().map(|&index| relative(&files[index])).collect(),
+            });
+        }
+        summary.groups.sort_by(|a, b| a.original.cmp(&b.original));
+        Ok(Self {
+            originals,
+            hashes,
+            summary,
+        })
+    }
+
+    /// Links `dest` to the already-copied original of `files[index]`.
+    /// Returns the original's relative path, or `None` when the file must
+    /// be copied.
+    pub(crate) fn try_link(
+        &mut self,
+        files: &[FileEntry],
+        index: usize,
+        target_root: &Path,
+        dest: &Path,
+    ) -> Option<String> {
+        if !self.summary.links_supported {
+            return None;
+        }
+        let original = &files[*self.originals.get(&index)?];
+        let _ = fs::remove_file(dest);
+        match fs::hard_link(target_root.join(&original.relative_path), dest) {
+            Ok(()) => {
+                self.summary.linked_files += 1;
+                self.summary.saved_bytes += files[index].size;
+                Some(relative(original))
+            }
+            Err(_) => {
+                self.summary.links_supported = false;
+                None
+            }
+        }
+    }
+
+    /// SHA-256 computed while planning, so the copy manifest need not
+    /// hash the file again.
+    pub(crate) fn sha256(&self, index: usize) -> Option<&str> {
+        self.hashes.get(&index).map(String::as_str)
+    }
+
+    pub(crate) fn summary(&self) -> &DedupeSummary {
+        &self.summary
+    }
+}
+
+fn relative(entry: &FileEntry) -> String {
+    entry.relative_path.to_string_lossy().replace('\\', "/")
+}
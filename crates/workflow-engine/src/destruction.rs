@@ -0,0 +1,201 @@
+//! Plain-language summary of what a destructive run will wipe, for
+//! confirmation prompts. Built from the same disk facts the safety checks
+//! use, so the prompt warns about whatever the run would refuse.
+
+use crate::overwrite::inspect_disk;
+use crate::target::{resolve, stack_usage};
+use anyhow::{anyhow, Result};
+use phoenix_core::{DeviceGraph, Disk};
+use phoenix_safety::{check_target_size, is_read_only, TargetSizeDecision, TargetSizeLimits};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DestructiveAction {
+    /// The whole disk: repartitioning or writing an image.
+    Erase,
+    /// The existing volumes are reformatted in place.
+    Format,
+}
+
+impl DestructiveAction {
+    pub fn parse(value: &str) -> Result<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "erase" => Ok(Self::Erase),
+            "format" => Ok(Self::Format),
+            other => Err(anyhow!("unknown destructive action: {} (erase, format)", other)),
+        }
+    }
+
+    fn verb(self) -> &'static str {
+        match self {
+            Self::Erase => "ERASE",
+            Self::Format => "FORMAT",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DestructionParams {
+    /// Disk id, device path, mount point, or USB port label or path.
+    pub target: String,
+    pub action: DestructiveAction,
+    /// Read the mounted volumes for used space and personal data. Leave
+    /// off for a graph that does not describe this host.
+    #[serde(default)]
+    pub inspect_contents: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DestructionVolume {
+    pub partition_id: String,
+    pub label: Option<String>,
+    pub fs: Option<String>,
+    pub size_bytes: u64,
+    pub mount_point: Option<String>,
+    /// Known for mounted volumes when contents are inspected.
+    pub used_bytes: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DestructionSummary {
+    pub disk_id: String,
+    pub action: DestructiveAction,
+    /// `This will ERASE SanDisk Ultra (disk sdb, 64.0 GB, serial X,
+    /// currently labeled 'FamilyPhotos', 61.2 GB used)`.
+    pub headline: String,
+    /// Reasons the run would be refused or needs an extra confirmation.
+    pub warnings: Vec<String>,
+    pub volumes: Vec<DestructionVolume>,
+}
+
+impl DestructionSummary {
+    /// The headline followed by one `- ` line per warning.
+    pub fn text(&self) -> String {
+        let mut text = self.headline.clone();
+        for warning in &self.warnings {
+            text.push_str("\n- ");
+            text.push_str(warning);
+        }
+        text
+    }
+}
+
+/// Describes what `params.action` would destroy on the target in `graph`.
+pub fn describe_destruction(
+    params: &DestructionParams,
+    graph: &DeviceGraph,
+) -> Result<DestructionSummary> {
+    let (disk, _) = resolve(graph, &params.target)
+        .ok_or_else(|| anyhow!("disk not found: {}", params.target))?;
+    let volumes: Vec<DestructionVolume> = disk
+        .partitions
+        .iter()
+        .map(|partition| {
+            let mount_point = partition.mount_points.first().cloned();
+            let used_bytes = mount_point
+                .as_deref()
+                .filter(|_| params.inspect_contents)
+                .and_then(|mount| crate::doctor::free_bytes(Path::new(mount)))
+                .map(|free| partition.size_bytes.saturating_sub(free));
+            DestructionVolume {
+                partition_id: partition.id.clone(),
+                label: partition.label.clone().filter(|label| !label.trim().is_empty()),
+                fs: partition.fs.clone(),
+                size_bytes: partition.size_bytes,
+                mount_point,
+                used_bytes,
+            }
+        })
+        .collect();
+
+    let mut details = vec![format!("disk {}", disk.id), human_bytes(disk.size_bytes)];
+    if let Some(serial) = &disk.serial {
+        details.push(format!("serial {}", serial));
+    }
+    if let Some(port) = &disk.usb_port {
+        details.push(format!("port {}", port.label.as_deref().unwrap_or(&port.path)));
+    }
+    let labels: Vec<String> = volumes
+        .iter()
+        .filter_map(|volume| volume.label.as_ref())
+        .map(|label| format!("'{}'", label))
+        .collect();
+    if !labels.is_empty() {
+        details.push(format!("currently labeled {}", labels.join(" and ")));
+    }
+    if volumes.iter().any(|volume| volume.used_bytes.is_some()) {
+        let used = volumes.iter().filter_map(|volume| volume.used_bytes).sum();
+        details.push(format!("{} used", human_bytes(used)));
+    } else if disk.partitions.is_empty() {
+        details.push("no partitions".to_string());
+    }
+    let headline = format!(
+        "This will {} {} ({})",
+        params.action.verb(),
+        disk.friendly_name,
+        details.join(", ")
+    );
+
+    Ok(DestructionSummary {
+        disk_id: disk.id.clone(),
+        action: params.action,
+        headline,
+        warnings: warnings(graph, disk, params.inspect_contents),
+        volumes,
+    })
+}
+
+fn warnings(graph: &DeviceGraph, disk: &Disk, inspect_contents: bool) -> Vec<String> {
+    let mut warnings = Vec::new();
+    if is_read_only() {
+        warnings.push("read-only mode is on; the run will be refused".to_string());
+    }
+    if disk.is_system_disk {
+        warnings.push(format!(
+            "{} is the SYSTEM disk; the computer may not start afterwards",
+            disk.id
+        ));
+    }
+    if !disk.removable {
+        warnings.push(format!("{} is not marked removable", disk.id));
+    }
+    if let Some(reason) = stack_usage(graph, disk) {
+        warnings.push(reason);
+    }
+    match TargetSizeLimits::from_env() {
+        Ok(limits) => {
+            let decision = check_target_size(disk.size_bytes, &limits, false);
+            if let TargetSizeDecision::Deny(reason) = decision {
+                warnings.push(format!(
+                    "{}; it may not be the disk you meant",
+                    reason.trim_start_matches("Denied: ")
+                ));
+            }
+        }
+        Err(err) => warnings.push(err),
+    }
+    if inspect_contents {
+        let check = inspect_disk(disk);
+        if !check.triggers.is_empty() {
+            warnings.push(format!("looks like personal data: {}", check.triggers.join("; ")));
+        }
+    }
+    warnings
+}
+
+/// Decimal units, as printed on the packaging.
+fn human_bytes(bytes: u64) -> String {
+    const UNITS: &[&str] = &["KB", "MB", "GB", "TB"];
+    if bytes < 1000 {
+        return format!("{} bytes", bytes);
+    }
+    let mut value = bytes as f64 / 1000.0;
+    let mut unit = 0;
+    while value >= 1000.0 && unit + 1 < UNITS.len() {
+        value /= 1000.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
@@ -0,0 +1,290 @@
+//! Failure diagnostics: when a workflow fails, a report bundle with status
+//! `failed` is still written, holding what remote support needs under
+//! `diagnostics/`: the error chain, the logs of the runs that failed,
+//! their last I/O errors and excerpts of the host's kernel and system logs.
+//! The bundle's `device_graph.json` is the graph right after the failure.
+
+use crate::steplog::{take_failed_runs, FailedRun};
+use crate::{build_device_graph, signing_key_from_env};
+use anyhow::Result;
+use phoenix_report::{
+    create_report_bundle_with_meta_signing_and_artifacts, ReportArtifact, ReportPaths,
+};
+use serde::Serialize;
+use std::io::Read;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+/// Set to `0` to write no failure bundle.
+pub const DIAGNOSTICS_ENV: &str = "PHOENIX_DIAGNOSTICS";
+pub const DIAGNOSTICS_DIR: &str = "diagnostics";
+/// Lines kept from each host log.
+const LOG_LINES: usize = 200;
+const LOG_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// One file under `diagnostics/`, or why a host log could not be read.
+#[derive(Debug, Clone, Serialize)]
+pub struct DiagnosticFile {
+    pub file: String,
+    pub source: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Writes a failure bundle under `report_base` for the runs that failed on
+/// this thread. Returns `None` when no run failed there, e.g. the error
+/// came from argument checks before any workflow started, or when
+/// `PHOENIX_DIAGNOSTICS=0`.
+pub fn write_failure_diagnostics(
+    report_base: &Path,
+    error: &anyhow::Error,
+) -> Result<Option<ReportPaths>> {
+    let failed = take_failed_runs();
+    let disabled = std::env::var(DIAGNOSTICS_ENV).is_ok_and(|value| value.trim() == "0");
+    // The outermost run is dropped last.
+    let Some(outer) = failed.last().filter(|_| !disabled) else {
+        return Ok(None);
+    };
+    let mut files = Vec::new();
+    let mut artifacts = Vec::new();
+    let error_text = format!("{:?}\n", error);
+    push(&mut artifacts, &mut files, "error.txt", "error", error_text.into_bytes());
+    for (index, run) in failed.iter().enumerate() {
+        let prefix = format!("run{}-{}", index + 1, file_safe(&run.workflow));
+        push(
+            &mut artifacts,
+            &mut files,
+            &format!("{}.log.txt", prefix),
+            &run.workflow,
+            run.log.clone().into_bytes(),
+        );
+        push(
+            &mut artifacts,
+            &mut files,
+            &format!("{}.timing.json", prefix),
+            &run.workflow,
+            serde_json::to_vec_pretty(&run.timing)?,
+        );
+    }
+    let io_errors = io_errors(&failed);
+    if !io_errors.is_empty() {
+        push(
+            &mut artifacts,
+            &mut files,
+            "io_errors.json",
+            "retried and failed I/O",
+            serde_json::to_vec_pretty(&io_errors)?,
+        );
+    }
+    for source in host_logs() {
+        match source.collect() {
+            Ok(text) => push(&mut artifacts, &mut files, source.file, &source.describe(), text),
+            Err(err) => files.push(DiagnosticFile {
+                file: format!("{}/{}", DIAGNOSTICS_DIR, source.file),
+                source: source.describe(),
+                error: Some(err),
+            }),
+        }
+    }
+
+    let meta = serde_json::json!({
+        "workflow": outer.workflow,
+        "status": "failed",
+        "error": format!("{:#}", error),
+        "error_code": crate::watchdog::error_code(error),
+        "failed_runs": failed.iter().map(|run| &run.workflow).collect::<Vec<_>>(),
+        "diagnostics": files
+    });
+    let graph = build_device_graph()?;
+    let report = create_report_bundle_with_meta_signing_and_artifacts(
+        report_base,
+        &graph,
+        Some(meta),
+        Some(&outer.log),
+        signing_key_from_env().as_deref(),
+        &artifacts,
+    )?;
+    Ok(Some(report))
+}
+
+fn push(
+    artifacts: &mut Vec<ReportArtifact>,
+    files: &mut Vec<DiagnosticFile>,
+    name: &str,
+    source: &str,
+    bytes: Vec<u8>,
+) {
+    let file = format!("{}/{}", DIAGNOSTICS_DIR, name);
+    artifacts.push(ReportArtifact::bytes(file.clone(), bytes));
+    files.push(DiagnosticFile {
+        file,
+        source: source.to_string(),
+        error: None,
+    });
+}
+
+#[derive(Serialize)]
+struct RunIoErrors<'a> {
+    workflow: &'a str,
+    retries: &'a [phoenix_imaging::retry::RetryEvent],
+    failures: &'a [phoenix_imaging::retry::IoFailure],
+}
+
+fn io_errors(failed: &[FailedRun]) -> Vec<RunIoErrors<'_>> {
+    failed
+        .iter()
+        .filter(|run| !run.timing.io_retries.is_empty() || !run.io_failures.is_empty())
+        .map(|run| RunIoErrors {
+            workflow: &run.workflow,
+            retries: &run.timing.io_retries,
+            failures: &run.io_failures,
+        })
+        .collect()
+}
+
+/// A host log read by running a tool; the first program that runs wins.
+struct HostLog {
+    file: &'static str,
+    programs: &'static [&'static [&'static str]],
+    /// Keep only lines about storage and USB.
+    storage_only: bool,
+}
+
+impl HostLog {
+    fn describe(&self) -> String {
+        self.programs
+            .iter()
+            .map(|argv| argv.join(" "))
+            .collect::<Vec<_>>()
+            .join(" | ")
+    }
+
+    fn collect(&self) -> std::result::Result<Vec<u8>, String> {
+        let mut problems = Vec::new();
+        for argv in self.programs {
+            match run_tool(argv) {
+                Ok(text) => {
+                    let lines: Vec<&str> = text
+                        .lines()
+                        .filter(|line| !self.storage_only || is_storage_line(line))
+                        .collect();
+                    let kept = &lines[lines.len().saturating_sub(LOG_LINES)..];
+                    return Ok((kept.join("\n") + "\n").into_bytes());
+                }
+                Err(err) => problems.push(format!("{}: {}", argv[0], err)),
+            }
+        }
+        Err(problems.join("; "))
+    }
+}
+
+/// Under `PHOENIX_HOST=mock` the host's logs say nothing about the
+/// sandbox, so none are read.
+fn host_logs() -> Vec<HostLog> {
+    if phoenix_core::mock::is_active() {
+        return Vec::new();
+    }
+    if cfg!(target_os = "linux") {
+        vec![
+            HostLog {
+                file: "kernel_storage.txt",
+                programs: &[&["dmesg"], &["journalctl", "-k", "-b", "--no-pager", "-n", "5000"]],
+                storage_only: true,
+            },
+            HostLog {
+                file: "system_log.txt",
+                programs: &[&["journalctl", "-b", "-p", "warning", "--no-pager", "-n", "200"]],
+                storage_only: false,
+            },
+        ]
+    } else if cfg!(target_os = "macos") {
+        vec![HostLog {
+            file: "system_log.txt",
+            programs: &[&[
+                "log",
+                "show",
+                "--last",
+                "15m",
+                "--style",
+                "syslog",
+                "--predicate",
+                "process == \"kernel\" OR process == \"diskarbitrationd\" OR subsystem BEGINSWITH \"com.apple.iokit\"",
+            ]],
+            storage_only: false,
+        }]
+    } else if cfg!(windows) {
+        vec![HostLog {
+            file: "system_events.txt",
+            programs: &[&[
+                "wevtutil",
+                "qe",
+                "System",
+                "/c:100",
+                "/rd:true",
+                "/f:text",
+                "/q:*[System[(Level=1 or Level=2 or Level=3)]]",
+            ]],
+            storage_only: false,
+        }]
+    } else {
+        Vec::new()
+    }
+}
+
+/// Kernel lines from USB, SCSI and block drivers and the filesystems
+/// media use.
+fn is_storage_line(line: &str) -> bool {
+    const WORDS: &[&str] = &[
+        "usb", "uas", "xhci", "ehci", "hub", "scsi", "sd ", "blk_update", "i/o error",
+        "buffer i/o", "nvme", "mmc", "fat-fs", "exfat", "ext4-fs", "ntfs",
+    ];
+    let line = line.to_ascii_lowercase();
+    WORDS.iter().any(|word| line.contains(word))
+}
+
+/// Stdout of `argv`, killed after `LOG_TIMEOUT`.
+fn run_tool(argv: &[&str]) -> std::result::Result<String, String> {
+    let mut child = Command::new(argv[0])
+        .args(&argv[1..])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| err.to_string())?;
+    let mut stdout = child.stdout.take();
+    let reader = std::thread::spawn(move || {
+        let mut bytes = Vec::new();
+        if let Some(stdout) = stdout.as_mut() {
+            let _ = stdout.read_to_end(&mut bytes);
+        }
+        bytes
+    });
+    let start = Instant::now();
+    let status = loop {
+        match child.try_wait().map_err(|err| err.to_string())? {
+            Some(status) => break status,
+            None if start.elapsed() >= LOG_TIMEOUT => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(format!("timed out after {}s", LOG_TIMEOUT.as_secs()));
+            }
+            None => std::thread::sleep(Duration::from_millis(50)),
+        }
+    };
+    let stdout = reader.join().unwrap_or_default();
+    if !status.success() {
+        let mut stderr = String::new();
+        if let Some(mut pipe) = child.stderr.take() {
+            let _ = pipe.read_to_string(&mut stderr);
+        }
+        return Err(format!("{}: {}", status, stderr.trim()));
+    }
+    Ok(String::from_utf8_lossy(&stdout).to_string())
+}
+
+fn file_safe(name: &str) -> String {
+    name.chars()
+        .map(|ch| if ch.is_ascii_alphanumeric() || ch == '-' { ch } else { '_' })
+        .collect()
+}
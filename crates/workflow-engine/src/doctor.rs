@@ -0,0 +1,266 @@
+use serde::Serialize;
+use std::path::Path;
+
+/// Free space below this in the temp directory is reported as a warning;
+/// ISO extraction and image staging need room there.
+const MIN_TEMP_FREE_BYTES: u64 = 8 * 1024 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckStatus {
+    Ok,
+    Warn,
+    Fail,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DoctorCheck {
+    pub name: String,
+    pub status: CheckStatus,
+    pub detail: String,
+    /// What to do about a warning or failure.
+    pub remediation: Option<String>,
+}
+
+impl DoctorCheck {
+    fn ok(name: &str, detail: impl Into<String>) -> Self {
+        Self {
+            name: name.to_string(),
+            status: CheckStatus::Ok,
+            detail: detail.into(),
+            remediation: None,
+        }
+    }
+
+    fn problem(
+        name: &str,
+        status: CheckStatus,
+        detail: impl Into<String>,
+        remediation: impl Into<String>,
+    ) -> Self {
+        Self {
+            name: name.to_string(),
+            status,
+            detail: detail.into(),
+            remediation: Some(remediation.into()),
+        }
+    }
+}
+
+/// Checks the prerequisites the workflows on this OS rely on, so a missing
+/// tool or privilege shows up before a run instead of halfway through it.
+pub fn run_doctor() -> Vec<DoctorCheck> {
+    let mut checks = vec![check_privileges()];
+    checks.extend(platform_checks());
+    checks.push(check_temp_space());
+    checks
+}
+
+fn check_privileges() -> DoctorCheck {
+    let elevated = is_elevated();
+    if elevated {
+        return DoctorCheck::ok("privileges", "running elevated");
+    }
+    if cfg!(windows) {
+        DoctorCheck::problem(
+            "privileges",
+            CheckStatus::Fail,
+            "not running as Administrator",
+            "start the terminal with \"Run as administrator\"; raw disk access needs it",
+        )
+    } else if cfg!(target_os = "macos") {
+        DoctorCheck::problem(
+            "privileges",
+            CheckStatus::Warn,
+            "not running as root",
+            "device opens will prompt through authopen; run with sudo for unattended use",
+        )
+    } else {
+        DoctorCheck::problem(
+            "privileges",
+            CheckStatus::Warn,
+            "not running as root",
+            "run with sudo, or use the udisks options so polkit grants access",
+        )
+    }
+}
+
+#[cfg(unix)]
+pub(crate) fn is_elevated() -> bool {
+    unsafe { libc::geteuid() == 0 }
+}
+
+#[cfg(windows)]
+pub(crate) fn is_elevated() -> bool {
+    #[link(name = "shell32")]
+    extern "system" {
+        fn IsUserAnAdmin() -> i32;
+    }
+    unsafe { IsUserAnAdmin() != 0 }
+}
+
+#[cfg(not(any(unix, windows)))]
+pub(crate) fn is_elevated() -> bool {
+    false
+}
+
+#[cfg(windows)]
+fn platform_checks() -> Vec<DoctorCheck> {
+    vec![
+        match library_exports("wimgapi.dll", "WIMApplyImage") {
+            Ok(()) => DoctorCheck::ok("wimgapi", "wimgapi.dll loaded, WIMApplyImage present"),
+            Err(err) => match phoenix_wim::dism_path() {
+                Some(dism) => DoctorCheck::problem(
+                    "wimgapi",
+                    CheckStatus::Warn,
+                    format!("{}; WIM operations fall back to {}", err, dism.display()),
+                    "repair the OS (sfc /scannow) to use wimgapi directly",
+                ),
+                None => DoctorCheck::problem(
+                    "wimgapi",
+                    CheckStatus::Fail,
+                    err,
+                    "install the Windows ADK Deployment Tools or repair the OS (sfc /scannow)",
+                ),
+            },
+        },
+        match library_exports("fmifs.dll", "FormatEx") {
+            Ok(()) => DoctorCheck::ok("fmifs", "fmifs.dll loaded, FormatEx present"),
+            Err(err) => DoctorCheck::problem(
+                "fmifs",
+                CheckStatus::Fail,
+                err,
+                "fmifs.dll ships with Windows; on Server Core or WinPE add the storage components",
+            ),
+        },
+    ]
+}
+
+#[cfg(target_os = "macos")]
+fn platform_checks() -> Vec<DoctorCheck> {
+    [
+        ("hdiutil", "/usr/bin/hdiutil", "needed to attach DMG/ISO sources"),
+        ("diskutil", "/usr/sbin/diskutil", "needed to enumerate and erase disks"),
+        ("asr", "/usr/sbin/asr", "needed to restore installer images"),
+        ("authopen", "/usr/libexec/authopen", "needed to open devices without root"),
+    ]
+    .iter()
+    .map(|(name, path, purpose)| {
+        if Path::new(path).exists() {
+            DoctorCheck::ok(name, format!("{} present", path))
+        } else {
+            DoctorCheck::problem(
+                name,
+                CheckStatus::Fail,
+                format!("{} missing ({})", path, purpose),
+                "these ship with macOS; reinstall the OS or run from a full macOS install, not Recovery",
+            )
+        }
+    })
+    .collect()
+}
+
+#[cfg(target_os = "linux")]
+fn platform_checks() -> Vec<DoctorCheck> {
+    let udev = if Path::new("/run/udev/data").is_dir() {
+        DoctorCheck::ok("udev", "udev database present")
+    } else {
+        DoctorCheck::problem(
+            "udev",
+            CheckStatus::Warn,
+            "/run/udev/data not found; disk serials and bus types may be missing",
+            "run on a host with systemd-udevd (containers need /run/udev mounted)",
+        )
+    };
+
+    let bus = Path::new("/run/dbus/system_bus_socket").exists();
+    let service = Path::new("/usr/share/dbus-1/system-services/org.freedesktop.UDisks2.service")
+        .exists();
+    let udisks = match (cfg!(feature = "udisks2"), bus && service) {
+        (true, true) => DoctorCheck::ok("udisks", "udisks2 available on the system bus"),
+        (true, false) => DoctorCheck::problem(
+            "udisks",
+            CheckStatus::Warn,
+            "udisks2 not reachable (system bus or UDisks2 service missing)",
+            "install udisks2 (apt install udisks2 / dnf install udisks2) or run as root without --udisks",
+        ),
+        (false, _) => DoctorCheck::ok("udisks", "not built in (optional; needs the udisks2 feature)"),
+    };
+    vec![udev, udisks]
+}
+
+#[cfg(not(any(windows, target_os = "macos", target_os = "linux")))]
+fn platform_checks() -> Vec<DoctorCheck> {
+    vec![DoctorCheck::problem(
+        "platform",
+        CheckStatus::Fail,
+        std::env::consts::OS,
+        "Phoenix workflows support Windows, macOS and Linux",
+    )]
+}
+
+fn check_temp_space() -> DoctorCheck {
+    let temp = std::env::temp_dir();
+    match free_bytes(&temp) {
+        Some(free) if free >= MIN_TEMP_FREE_BYTES => DoctorCheck::ok(
+            "temp_space",
+            format!("{} has {} MiB free", temp.display(), free / (1024 * 1024)),
+        ),
+        Some(free) => DoctorCheck::problem(
+            "temp_space",
+            CheckStatus::Warn,
+            format!("{} has only {} MiB free", temp.display(), free / (1024 * 1024)),
+            format!(
+                "free at least {} GiB or point TMP/TMPDIR at a larger volume",
+                MIN_TEMP_FREE_BYTES / (1024 * 1024 * 1024)
+            ),
+        ),
+        None => DoctorCheck::problem(
+            "temp_space",
+            CheckStatus::Warn,
+            format!("could not query free space of {}", temp.display()),
+            "check that the temp directory exists and is writable",
+        ),
+    }
+}
+
+#[cfg(windows)]
+pub(crate) fn free_bytes(path: &Path) -> Option<u64> {
+    phoenix_host_windows::space::free_space_bytes(&path.display().to_string()).ok()
+}
+
+#[cfg(not(windows))]
+pub(crate) fn free_bytes(path: &Path) -> Option<u64> {
+    crate::mount_free_space_bytes(path).ok().flatten()
+}
+
+/// Loads `library` and looks up `symbol`, then unloads it again.
+#[cfg(windows)]
+pub(crate) fn library_exports(library: &str, symbol: &str) -> Result<(), String> {
+    use std::ffi::{c_void, CString};
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn LoadLibraryW(name: *const u16) -> *mut c_void;
+        fn GetProcAddress(module: *mut c_void, name: *const i8) -> *mut c_void;
+        fn FreeLibrary(module: *mut c_void) -> i32;
+    }
+
+    let wide: Vec<u16> = library.encode_utf16().chain(std::iter::once(0)).collect();
+    let module = unsafe { LoadLibraryW(wide.as_ptr()) };
+    if module.is_null() {
+        return Err(format!(
+            "{} could not be loaded: {}",
+            library,
+            std::io::Error::last_os_error()
+        ));
+    }
+    let name = CString::new(symbol).map_err(|err| err.to_string())?;
+    let found = unsafe { !GetProcAddress(module, name.as_ptr()).is_null() };
+    unsafe { FreeLibrary(module) };
+    if found {
+        Ok(())
+    } else {
+        Err(format!("{} does not export {}", library, symbol))
+    }
+}
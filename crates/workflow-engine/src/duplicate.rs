@@ -0,0 +1,227 @@
+//! One source, many targets: runs a workflow definition on every
+//! attached removable disk at once, after a single confirmation, with one
+//! report covering all of them.
+
+use crate::cancel::{current_token, with_cancel_token};
+use crate::kiosk::{on_served_port, port_name, present_disks, run_on_disk, StationRun};
+use crate::steplog::StepLog;
+use crate::{build_device_graph, signing_key_from_env};
+use anyhow::{anyhow, Result};
+use phoenix_core::{Disk, WorkflowDefinition};
+use phoenix_report::{
+    create_report_bundle_with_meta_signing_and_artifacts, ReportArtifact, ReportPaths,
+};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+pub const DUPLICATE_RUNS_FILE: &str = "duplicate_runs.json";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DuplicateParams {
+    /// Run once per target, with the `{target_*}` placeholders of
+    /// `run_kiosk` filled in.
+    pub definition: WorkflowDefinition,
+    #[serde(default = "crate::params::default_report_base")]
+    pub report_base: PathBuf,
+    /// Disks smaller than this are left out.
+    #[serde(default)]
+    pub min_size_bytes: u64,
+    /// Serials that are never targeted, whatever else matches.
+    #[serde(default)]
+    pub never_touch_serials: Vec<String>,
+    /// USB port labels or paths to use; empty means any port.
+    #[serde(default)]
+    pub ports: Vec<String>,
+    /// Targets written at the same time; 0 means all of them.
+    #[serde(default)]
+    pub max_parallel: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DuplicateTarget {
+    pub disk_id: String,
+    pub friendly_name: String,
+    pub serial: Option<String>,
+    pub port: Option<String>,
+    pub size_bytes: u64,
+}
+
+impl DuplicateTarget {
+    fn from_disk(disk: &Disk) -> Self {
+        Self {
+            disk_id: disk.id.clone(),
+            friendly_name: disk.friendly_name.clone(),
+            serial: disk.serial.clone(),
+            port: port_name(disk),
+            size_bytes: disk.size_bytes,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ExcludedDisk {
+    pub disk_id: String,
+    pub serial: Option<String>,
+    pub reason: String,
+}
+
+/// What `run_duplicate_to_all` would write, to show the operator before
+/// the single confirmation.
+#[derive(Debug, Clone, Serialize)]
+pub struct DuplicatePlan {
+    pub targets: Vec<DuplicateTarget>,
+    pub excluded: Vec<ExcludedDisk>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DuplicateResult {
+    /// Consolidated report listing every target's run.
+    pub report: ReportPaths,
+    pub runs: Vec<StationRun>,
+}
+
+impl DuplicateResult {
+    pub fn failed(&self) -> usize {
+        self.runs.iter().filter(|run| run.error.is_some()).count()
+    }
+}
+
+/// Removable, non-system disks above the size threshold, minus the
+/// never-touch serials and disks on other ports.
+pub fn plan_duplicate_to_all(params: &DuplicateParams) -> Result<DuplicatePlan> {
+    let mut plan = DuplicatePlan {
+        targets: Vec::new(),
+        excluded: Vec::new(),
+    };
+    for disk in present_disks()? {
+        let reason = if disk.size_bytes < params.min_size_bytes {
+            Some(format!(
+                "{} bytes is below the {} byte minimum",
+                disk.size_bytes, params.min_size_bytes
+            ))
+        } else if disk.serial.as_deref().is_some_and(|serial| {
+            params
+                .never_touch_serials
+                .iter()
+                .any(|never| never.eq_ignore_ascii_case(serial))
+        }) {
+            Some("serial is on the never-touch list".to_string())
+        } else if !on_served_port(&params.ports, &disk) {
+            Some("not plugged into a selected USB port".to_string())
+        } else {
+            None
+        };
+        match reason {
+            Some(reason) => plan.excluded.push(ExcludedDisk {
+                disk_id: disk.id,
+                serial: disk.serial,
+                reason,
+            }),
+            None => plan.targets.push(DuplicateTarget::from_disk(&disk)),
+        }
+    }
+    Ok(plan)
+}
+
+/// Runs the definition on every target of `plan` in parallel. The plan is
+/// what the operator confirmed, so the attached disks must still match it
+/// (same ids and serials); any change refuses the whole run. One target
+/// failing does not stop the others.
+pub fn run_duplicate_to_all(
+    params: &DuplicateParams,
+    plan: &DuplicatePlan,
+) -> Result<DuplicateResult> {
+    if plan.targets.is_empty() {
+        return Err(anyhow!("no target disks to duplicate to"));
+    }
+    let current = plan_duplicate_to_all(params)?;
+    let key = |targets: &[DuplicateTarget]| {
+        let mut key: Vec<(String, Option<String>)> = targets
+            .iter()
+            .map(|target| (target.disk_id.clone(), target.serial.clone()))
+            .collect();
+        key.sort();
+        key
+    };
+    if key(&current.targets) != key(&plan.targets) {
+        return Err(anyhow!(
+            "attached disks changed since the plan was confirmed; plan and confirm again"
+        ));
+    }
+    let disks: Vec<Disk> = present_disks()?
+        .into_iter()
+        .filter(|disk| plan.targets.iter().any(|target| target.disk_id == disk.id))
+        .collect();
+
+    let mut logs = StepLog::new("duplicate-to-all");
+    logs.push(format!("definition={}", params.definition.name));
+    for target in &plan.targets {
+        logs.push(format!(
+            "target={} serial={} port={}",
+            target.disk_id,
+            target.serial.as_deref().unwrap_or("-"),
+            target.port.as_deref().unwrap_or("-")
+        ));
+    }
+    for excluded in &plan.excluded {
+        logs.push(format!("excluded={} reason={}", excluded.disk_id, excluded.reason));
+    }
+    let workers = match params.max_parallel {
+        0 => disks.len(),
+        max => max.min(disks.len()),
+    };
+    logs.push(format!("parallel={}", workers));
+    logs.phase("duplicate");
+
+    let queue = Mutex::new(disks.iter());
+    let runs = Mutex::new(Vec::new());
+    let token = current_token().unwrap_or_default();
+    std::thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|| {
+                with_cancel_token(&token, || {
+                    // The guard moves into the closure, so the queue is
+                    // unlocked while the run goes.
+                    while let Some(disk) = queue.lock().ok().and_then(|mut queue| queue.next()) {
+                        let run = run_on_disk(&params.definition, &params.report_base, disk);
+                        if let Ok(mut runs) = runs.lock() {
+                            runs.push(run);
+                        }
+                    }
+                })
+            });
+        }
+    });
+    let mut runs = runs.into_inner().map_err(|_| anyhow!("a target run panicked"))?;
+    runs.sort_by(|a, b| a.disk_id.cmp(&b.disk_id));
+    for run in &runs {
+        match &run.error {
+            None => logs.push(format!("completed={} duration_ms={}", run.disk_id, run.duration_ms)),
+            Some(err) => logs.push(format!("failed={} error={}", run.disk_id, err)),
+        }
+    }
+
+    let failed = runs.iter().filter(|run| run.error.is_some()).count();
+    let runs_artifact = ReportArtifact::json(DUPLICATE_RUNS_FILE, &runs)?;
+    let (log_text, timing) = logs.finish()?;
+    let meta = serde_json::json!({
+        "workflow": "duplicate-to-all",
+        "status": if failed == 0 { "completed" } else { "failed" },
+        "definition": params.definition.name,
+        "targets": plan.targets,
+        "excluded": plan.excluded,
+        "failed_targets": failed,
+        "artifacts": [&runs_artifact.name, &timing.name]
+    });
+    let graph = build_device_graph()?;
+    let report = create_report_bundle_with_meta_signing_and_artifacts(
+        &params.report_base,
+        &graph,
+        Some(meta),
+        Some(&log_text),
+        signing_key_from_env().as_deref(),
+        &[runs_artifact, timing],
+    )?;
+    Ok(DuplicateResult { report, runs })
+}
@@ -0,0 +1,159 @@
+//! `edition_selector`: picks the install image of multi-edition media by
+//! index, EditionID or name, and checks boot.wim can set it up, so the
+//! image a run uses is an explicit decision in its report rather than a
+//! guessed index.
+
+use anyhow::{anyhow, Result};
+use phoenix_content::find_windows_image;
+use phoenix_wim::{list_images as wim_list_images, WimImageInfo};
+use serde::Serialize;
+use std::path::Path;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct EditionImage {
+    pub index: u32,
+    pub name: Option<String>,
+    pub edition_id: Option<String>,
+    pub architecture: Option<String>,
+}
+
+impl From<&WimImageInfo> for EditionImage {
+    fn from(image: &WimImageInfo) -> Self {
+        Self {
+            index: image.index,
+            name: image.name.clone(),
+            edition_id: image.edition_id.clone(),
+            architecture: image.architecture.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct EditionSelection {
+    pub selector: String,
+    /// `index`, `edition_id` or `name`.
+    pub matched_by: &'static str,
+    pub image: EditionImage,
+    /// Every image the file holds.
+    pub candidates: Vec<EditionImage>,
+    /// boot.wim's Setup image, when the media has a boot.wim.
+    pub boot_image: Option<EditionImage>,
+}
+
+/// Matches `selector` as an index, then an EditionID, then an image name,
+/// all case-insensitive. An EditionID shared by several images (one per
+/// architecture, say) is ambiguous and has to be narrowed by index or
+/// name.
+pub fn select_edition(images: &[WimImageInfo], selector: &str) -> Result<EditionSelection> {
+    let wanted = selector.trim();
+    let (matched_by, matches): (&'static str, Vec<&WimImageInfo>) =
+        if let Ok(index) = wanted.parse::<u32>() {
+            ("index", images.iter().filter(|image| image.index == index).collect())
+        } else {
+            let by_edition: Vec<&WimImageInfo> = images
+                .iter()
+                .filter(|image| {
+                    image
+                        .edition_id
+                        .as_deref()
+                        .is_some_and(|id| id.eq_ignore_ascii_case(wanted))
+                })
+                .collect();
+            if by_edition.is_empty() {
+                let by_name = images
+                    .iter()
+                    .filter(|image| {
+                        image
+                            .name
+                            .as_deref()
+                            .is_some_and(|name| name.eq_ignore_ascii_case(wanted))
+                    })
+                    .collect();
+                ("name", by_name)
+            } else {
+                ("edition_id", by_edition)
+            }
+        };
+    let image = match matches.as_slice() {
+        [image] => *image,
+        [] => {
+            return Err(anyhow!(
+                "edition_selector {} matches no image; available: {}",
+                selector,
+                describe(images)
+            ))
+        }
+        _ => {
+            return Err(anyhow!(
+                "edition_selector {} matches images {}; select by index or name",
+                selector,
+                describe(&matches.into_iter().cloned().collect::<Vec<_>>())
+            ))
+        }
+    };
+    Ok(EditionSelection {
+        selector: selector.to_string(),
+        matched_by,
+        image: image.into(),
+        candidates: images.iter().map(EditionImage::from).collect(),
+        boot_image: None,
+    })
+}
+
+/// Selects from the install image under the media `root` and checks
+/// `sources/boot.wim`'s Setup image has the same architecture, since
+/// Setup cannot install an image built for another one.
+pub fn select_from_media(root: &Path, selector: &str) -> Result<EditionSelection> {
+    let install = find_windows_image(root)?;
+    let mut selection = select_edition(&wim_list_images(&install)?, selector)?;
+    let boot_wim = root.join("sources").join("boot.wim");
+    if boot_wim.is_file() {
+        let boot_images = wim_list_images(&boot_wim)?;
+        let boot = setup_image(&boot_images)
+            .ok_or_else(|| anyhow!("{} holds no images", boot_wim.display()))?;
+        if let (Some(boot_arch), Some(image_arch)) = (&boot.architecture, &selection.image.architecture)
+        {
+            if !boot_arch.eq_ignore_ascii_case(image_arch) {
+                return Err(anyhow!(
+                    "boot.wim index {} is {} but the selected image {} is {}",
+                    boot.index,
+                    boot_arch,
+                    selection.image.index,
+                    image_arch
+                ));
+            }
+        }
+        selection.boot_image = Some(boot.into());
+    }
+    Ok(selection)
+}
+
+/// Setup runs from the boot.wim image named "Microsoft Windows Setup",
+/// normally index 2; index 1 is plain Windows PE.
+fn setup_image(images: &[WimImageInfo]) -> Option<&WimImageInfo> {
+    images
+        .iter()
+        .find(|image| {
+            image
+                .name
+                .as_deref()
+                .is_some_and(|name| name.to_ascii_lowercase().contains("setup"))
+        })
+        .or_else(|| images.iter().max_by_key(|image| image.index))
+}
+
+fn describe(images: &[WimImageInfo]) -> String {
+    images
+        .iter()
+        .map(|image| {
+            format!(
+                "{}: {} ({}, {})",
+                image.index,
+                image.name.as_deref().unwrap_or("unnamed"),
+                image.edition_id.as_deref().unwrap_or("?"),
+                image.architecture.as_deref().unwrap_or("?")
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("; ")
+}
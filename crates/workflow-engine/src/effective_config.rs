@@ -0,0 +1,490 @@
+//! The settings a process runs with and where each one came from: a
+//! command-line flag (or the binding call that does the same), an
+//! environment variable, a config file, the build, or the default. The CLI
+//! and the desktop app can each dump theirs; `config_drift` lists where
+//! two dumps disagree, which is usually why a run works in one and not the
+//! other.
+
+use crate::ledger::{network_config_path, notify_config_path, state_dir, usb_port_labels_path};
+use crate::{to_hex, IoLimits, VerifyPolicy};
+use anyhow::{Context, Result};
+use phoenix_core::units::{LOCALE_ENV, UNITS_ENV};
+use phoenix_core::{now_utc_rfc3339, DisplayFormat};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS))]
+#[serde(rename_all = "snake_case")]
+pub enum ConfigSource {
+    /// Set for this process by a flag or a binding call.
+    Flag,
+    Env,
+    File,
+    /// Fixed when the binary was built.
+    Build,
+    Default,
+}
+
+impl ConfigSource {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Flag => "flag",
+            Self::Env => "env",
+            Self::File => "file",
+            Self::Build => "build",
+            Self::Default => "default",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS))]
+pub struct ConfigSetting {
+    pub key: String,
+    /// `None` when unset. Keys and tokens show as a fingerprint.
+    pub value: Option<String>,
+    pub source: ConfigSource,
+    /// The variable that sets it, when it came from one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub env: Option<String>,
+    /// The config file it is read from, whether or not it exists.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "ts", ts(as = "Option<String>"))]
+    pub path: Option<PathBuf>,
+    /// Why the value could not be worked out, e.g. a file that does not
+    /// parse. The process fails the same way when it needs the setting.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl ConfigSetting {
+    pub fn new(key: &str, value: Option<String>, source: ConfigSource) -> Self {
+        Self {
+            key: key.to_string(),
+            value,
+            source,
+            env: None,
+            path: None,
+            error: None,
+        }
+    }
+
+    fn with_env(mut self, env: &str) -> Self {
+        self.env = Some(env.to_string());
+        self
+    }
+
+    fn failed(key: &str, err: impl std::fmt::Display) -> Self {
+        Self {
+            error: Some(err.to_string()),
+            ..Self::new(key, None, ConfigSource::Default)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS))]
+pub struct EffectiveConfig {
+    pub generated_utc: String,
+    /// The process that reported it, e.g. `phoenix-cli 0.1.0`.
+    pub process: String,
+    pub pid: u32,
+    pub settings: Vec<ConfigSetting>,
+}
+
+impl EffectiveConfig {
+    pub fn get(&self, key: &str) -> Option<&ConfigSetting> {
+        self.settings.iter().find(|setting| setting.key == key)
+    }
+
+    /// Replaces the setting with the same key, or appends it.
+    pub fn set(&mut self, setting: ConfigSetting) {
+        match self.settings.iter_mut().find(|current| current.key == setting.key) {
+            Some(current) => *current = setting,
+            None => self.settings.push(setting),
+        }
+    }
+
+    /// A dump written by `config-show --effective --json`.
+    pub fn load(path: &Path) -> Result<Self> {
+        let bytes = fs::read(path).with_context(|| format!("read {}", path.display()))?;
+        serde_json::from_slice(&bytes).with_context(|| format!("parse {}", path.display()))
+    }
+}
+
+/// A key whose value differs between two processes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS))]
+pub struct ConfigDrift {
+    pub key: String,
+    pub left: Option<String>,
+    pub left_source: Option<ConfigSource>,
+    pub right: Option<String>,
+    pub right_source: Option<ConfigSource>,
+}
+
+/// Keys whose values differ, in `left`'s order, then keys only `right`
+/// has. Sources alone differing is not drift.
+pub fn config_drift(left: &EffectiveConfig, right: &EffectiveConfig) -> Vec<ConfigDrift> {
+    let keys = left
+        .settings
+        .iter()
+        .chain(&right.settings)
+        .map(|setting| setting.key.as_str())
+        .fold(Vec::new(), |mut keys, key| {
+            if !keys.contains(&key) {
+                keys.push(key);
+            }
+            keys
+        });
+    keys.into_iter()
+        .filter_map(|key| {
+            let (a, b) = (left.get(key), right.get(key));
+            let value = |setting: Option<&ConfigSetting>| setting.and_then(|s| s.value.clone());
+            if value(a) == value(b) {
+                return None;
+            }
+            Some(ConfigDrift {
+                key: key.to_string(),
+                left: value(a),
+                left_source: a.map(|s| s.source),
+                right: value(b),
+                right_source: b.map(|s| s.source),
+            })
+        })
+        .collect()
+}
+
+/// Every setting the engine reads, as this process sees it now. `process`
+/// names the caller in the dump.
+pub fn effective_config(process: &str) -> EffectiveConfig {
+    let mut settings = vec![
+        path_setting("state_dir", "PHOENIX_STATE_DIR", state_dir()),
+        path_setting("asset_dir", "PHOENIX_ASSET_DIR", state_path("assets")),
+        path_setting("pack_dirs", "PHOENIX_PACK_DIRS", pack_dirs()),
+        path_setting("plugin_dir", "PHOENIX_PLUGIN_DIR", crate::hooks::plugin_dir()),
+        path_setting("secrets_dir", "PHOENIX_SECRETS_DIR", state_path("secrets")),
+        path_setting("baseline_dir", "PHOENIX_BASELINE_DIR", crate::baseline_dir()),
+        path_setting("audit_log", "PHOENIX_AUDIT_LOG", crate::audit_log_path()),
+        path_setting("device_registry", "PHOENIX_DEVICE_REGISTRY", state_path("devices.json")),
+    ];
+
+    settings.push(file_setting(
+        "verify_policy",
+        "PHOENIX_VERIFY_POLICY",
+        crate::verify_policy_path(),
+        |path| Ok(VerifyPolicy::load(path)?.map(|policy| policy.name)),
+    ));
+    settings.push(file_setting(
+        "io_limits",
+        "PHOENIX_IO_LIMITS",
+        crate::io_limits_path(),
+        |path| {
+            let limits = IoLimits::load(path)?;
+            Ok(Some(format!(
+                "max_concurrent_writes={} max_write_bytes_per_sec={}",
+                or_none(limits.max_concurrent_writes),
+                or_none(limits.max_write_bytes_per_sec)
+            )))
+        },
+    ));
+    settings.push(file_setting(
+        "notify",
+        "PHOENIX_NOTIFY_CONFIG",
+        notify_config_path(),
+        |path| {
+            let config = phoenix_notify::NotifyConfig::load(path)?;
+            Ok(Some(format!(
+                "webhooks={} email={} system_log={} signal={}",
+                config.webhooks.len(),
+                config.email.len(),
+                config.system_log,
+                config.signal.is_some()
+            )))
+        },
+    ));
+    settings.push(file_setting(
+        "network",
+        "PHOENIX_NETWORK_CONFIG",
+        network_config_path(),
+        |path| {
+            let config = phoenix_fetch::NetworkConfig::load(path)?;
+            Ok(Some(format!(
+                "proxy={} ca_bundles={} client_cert={} max_mbps={}",
+                config.proxy.is_some(),
+                config.ca_bundles.len(),
+                config.client_cert.is_some(),
+                or_none(config.max_mbps)
+            )))
+        },
+    ));
+    settings.push(file_setting(
+        "usb_port_labels",
+        "PHOENIX_USB_PORT_LABELS",
+        usb_port_labels_path(),
+        |_| Ok(Some(format!("{} ports", crate::usb_port_labels()?.len()))),
+    ));
+
+    settings.push(read_only_setting());
+    let env_armed = env_value(phoenix_safety::ARMED_UNTIL_ENV)
+        .map(|value| value.trim().parse::<u64>().unwrap_or(0).to_string());
+    settings.push(process_setting(
+        "armed_until",
+        phoenix_safety::ARMED_UNTIL_ENV,
+        phoenix_safety::armed_until().map(|until| until.to_string()),
+        env_armed,
+    ));
+    let target = phoenix_safety::TargetSizeLimits::default();
+    settings.extend([
+        env_setting(
+            "target_min_bytes",
+            phoenix_safety::TARGET_MIN_BYTES_ENV,
+            Some(target.min_bytes.to_string()),
+        ),
+        env_setting(
+            "target_max_bytes",
+            phoenix_safety::TARGET_MAX_BYTES_ENV,
+            Some(target.max_bytes.to_string()),
+        ),
+        env_setting("max_flash_count", phoenix_safety::MAX_FLASH_COUNT_ENV, None),
+        env_setting(
+            "max_throughput_drop_percent",
+            phoenix_safety::MAX_THROUGHPUT_DROP_ENV,
+            None,
+        ),
+        env_setting(
+            "wear_action",
+            phoenix_safety::WEAR_ACTION_ENV,
+            Some("deny".to_string()),
+        ),
+    ]);
+
+    let display = phoenix_core::display_format();
+    let env_display = DisplayFormat::from_env();
+    settings.push(process_setting(
+        "units",
+        UNITS_ENV,
+        Some(display.units.as_str().to_string()),
+        Some(env_display.units.as_str().to_string()),
+    ));
+    settings.push(locale_setting(display, env_display));
+    let env_encoding = env_value(phoenix_report::ARTIFACT_ENCODING_ENV)
+        .and_then(|value| phoenix_report::ArtifactEncoding::parse(&value).ok())
+        .unwrap_or_default();
+    settings.push(process_setting(
+        "artifact_encoding",
+        phoenix_report::ARTIFACT_ENCODING_ENV,
+        Some(phoenix_report::artifact_encoding().as_str().to_string()),
+        Some(env_encoding.as_str().to_string()),
+    ));
+    settings.push(process_setting(
+        "correlation_id",
+        "PHOENIX_CORRELATION_ID",
+        phoenix_report::correlation_id(),
+        env_value("PHOENIX_CORRELATION_ID"),
+    ));
+    settings.push(otlp_setting());
+    settings.push(env_setting(
+        "diagnostics",
+        crate::DIAGNOSTICS_ENV,
+        Some("1".to_string()),
+    ));
+    settings.push(secret_setting("signing_key", "PHOENIX_SIGNING_KEY"));
+    settings.push(secret_setting("pack_key", "PHOENIX_PACK_KEY"));
+
+    settings.push(env_setting(
+        "host",
+        phoenix_core::mock::HOST_ENV,
+        Some("native".to_string()),
+    ));
+    if phoenix_core::mock::is_active() {
+        settings.push(path_setting(
+            "mock_sandbox",
+            phoenix_core::mock::SANDBOX_ENV,
+            Ok(phoenix_core::mock::sandbox_dir()),
+        ));
+        settings.push(env_setting("mock_graph", phoenix_core::mock::GRAPH_ENV, None));
+    }
+    settings.push(env_setting(
+        "io_retries",
+        phoenix_imaging::retry::RETRIES_ENV,
+        None,
+    ));
+    if let Some(setting) = settings.last_mut() {
+        setting.value = Some(phoenix_imaging::retry::max_retries().to_string());
+    }
+    settings.push(env_setting(
+        "wim_backend",
+        "PHOENIX_WIM_BACKEND",
+        Some("auto".to_string()),
+    ));
+    settings.push(env_setting("dism", "PHOENIX_DISM", None));
+
+    EffectiveConfig {
+        generated_utc: now_utc_rfc3339(),
+        process: process.to_string(),
+        pid: std::process::id(),
+        settings,
+    }
+}
+
+fn env_value(name: &str) -> Option<String> {
+    std::env::var(name)
+        .ok()
+        .filter(|value| !value.trim().is_empty())
+}
+
+fn or_none<T: ToString>(value: Option<T>) -> String {
+    value.map_or_else(|| "none".to_string(), |value| value.to_string())
+}
+
+fn state_path(name: &str) -> Result<PathBuf> {
+    Ok(state_dir()?.join(name))
+}
+
+/// `PHOENIX_PACK_DIRS` as one path list.
+fn pack_dirs() -> Result<PathBuf> {
+    let dirs = crate::pack_dirs()?;
+    Ok(PathBuf::from(std::env::join_paths(dirs)?))
+}
+
+/// The variable's value, else `default`.
+fn env_setting(key: &str, env: &str, default: Option<String>) -> ConfigSetting {
+    match env_value(env) {
+        Some(value) => ConfigSetting::new(key, Some(value), ConfigSource::Env).with_env(env),
+        None => ConfigSetting::new(key, default, ConfigSource::Default),
+    }
+}
+
+fn path_setting(key: &str, env: &str, path: Result<PathBuf>) -> ConfigSetting {
+    match path {
+        Ok(path) => {
+            let value = Some(path.display().to_string());
+            match env_value(env) {
+                Some(_) => ConfigSetting::new(key, value, ConfigSource::Env).with_env(env),
+                None => ConfigSetting::new(key, value, ConfigSource::Default),
+            }
+        }
+        Err(err) => ConfigSetting::failed(key, err),
+    }
+}
+
+/// A config file, summarized by `summary`. Unset when the file does not
+/// exist.
+fn file_setting(
+    key: &str,
+    env: &str,
+    path: Result<PathBuf>,
+    summary: impl FnOnce(&Path) -> Result<Option<String>>,
+) -> ConfigSetting {
+    let path = match path {
+        Ok(path) => path,
+        Err(err) => return ConfigSetting::failed(key, err),
+    };
+    let mut setting = ConfigSetting::new(key, None, ConfigSource::Default);
+    if env_value(env).is_some() {
+        setting.env = Some(env.to_string());
+    }
+    if path.exists() {
+        setting.source = ConfigSource::File;
+        match summary(&path) {
+            Ok(value) => setting.value = value,
+            Err(err) => setting.error = Some(format!("{:#}", err)),
+        }
+    }
+    setting.path = Some(path);
+    setting
+}
+
+/// A setting a flag or binding call can override. `from_env` is what the
+/// environment alone gives; a live value that differs was set in-process.
+fn process_setting(
+    key: &str,
+    env: &str,
+    current: Option<String>,
+    from_env: Option<String>,
+) -> ConfigSetting {
+    if current != from_env {
+        ConfigSetting::new(key, current, ConfigSource::Flag)
+    } else if env_value(env).is_some() {
+        ConfigSetting::new(key, current, ConfigSource::Env).with_env(env)
+    } else {
+        ConfigSetting::new(key, current, ConfigSource::Default)
+    }
+}
+
+fn read_only_setting() -> ConfigSetting {
+    let env = phoenix_safety::READONLY_ENV;
+    let on = phoenix_safety::is_read_only();
+    let env_on = env_value(env).is_some_and(|value| {
+        matches!(
+            value.trim().to_ascii_lowercase().as_str(),
+            "1" | "true" | "yes"
+        )
+    });
+    let value = Some(on.to_string());
+    if phoenix_safety::READ_ONLY_BUILD {
+        ConfigSetting::new("read_only", value, ConfigSource::Build)
+    } else if env_on {
+        ConfigSetting::new("read_only", value, ConfigSource::Env).with_env(env)
+    } else if on {
+        ConfigSetting::new("read_only", value, ConfigSource::Flag)
+    } else {
+        ConfigSetting::new("read_only", value, ConfigSource::Default)
+    }
+}
+
+/// The decimal separator, and the locale variable it was taken from.
+fn locale_setting(display: DisplayFormat, env_display: DisplayFormat) -> ConfigSetting {
+    let separator = if display.decimal_comma {
+        "decimal_comma"
+    } else {
+        "decimal_point"
+    };
+    if display.decimal_comma != env_display.decimal_comma {
+        return ConfigSetting::new("locale", Some(separator.to_string()), ConfigSource::Flag);
+    }
+    let found = [LOCALE_ENV, "LC_ALL", "LC_NUMERIC", "LANG"]
+        .into_iter()
+        .find_map(|name| env_value(name).map(|value| (name, value)));
+    match found {
+        Some((name, locale)) => ConfigSetting::new(
+            "locale",
+            Some(format!("{} ({})", locale, separator)),
+            ConfigSource::Env,
+        )
+        .with_env(name),
+        None => ConfigSetting::new("locale", Some(separator.to_string()), ConfigSource::Default),
+    }
+}
+
+/// `sha256:<12 hex digits>`, enough to tell two keys apart without
+/// printing either.
+fn secret_setting(key: &str, env: &str) -> ConfigSetting {
+    match env_value(env) {
+        Some(value) => {
+            let digest = to_hex(&Sha256::digest(value.trim().as_bytes()));
+            let fingerprint = format!("sha256:{}", &digest[..12]);
+            ConfigSetting::new(key, Some(fingerprint), ConfigSource::Env).with_env(env)
+        }
+        None => ConfigSetting::new(key, None, ConfigSource::Default),
+    }
+}
+
+fn otlp_setting() -> ConfigSetting {
+    let traces = phoenix_notify::OTLP_TRACES_ENDPOINT_ENV;
+    let base = phoenix_notify::OTLP_ENDPOINT_ENV;
+    let env = if env_value(traces).is_some() { traces } else { base };
+    let from_env = env_value(traces).or_else(|| {
+        env_value(base).map(|url| format!("{}/v1/traces", url.trim_end_matches('/')))
+    });
+    process_setting(
+        "otlp_endpoint",
+        env,
+        phoenix_notify::otlp_traces_endpoint(),
+        from_env,
+    )
+}
@@ -0,0 +1,380 @@
+//! `startosinstall --eraseinstall`: erases the Mac's internal disk and
+//! reinstalls macOS from an installer app, no install media involved. The
+//! target is always the running system's disk, so besides force mode the
+//! run needs the system-target opt-in and a `PHX-SYS-` token.
+
+use crate::cancel::{check_child_cancelled, wait_child};
+use crate::ledger::RunTracker;
+use crate::mac_compat::{check_installer_for_model, inspect_installer_app, MacArch};
+use crate::operation::{DestructiveOperation, DestructiveSession};
+use crate::{build_device_graph, signing_key_from_env, StepLog};
+use anyhow::{anyhow, Context, Result};
+use phoenix_core::Disk;
+use phoenix_report::{create_report_bundle_with_meta_signing_and_artifacts, ReportPaths};
+use phoenix_safety::SafetyContext;
+use serde::{Deserialize, Serialize};
+use std::io::{BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+const STARTOSINSTALL: &str = "Contents/Resources/startosinstall";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MacosEraseInstallParams {
+    /// `Install macOS <name>.app`.
+    pub source_app: PathBuf,
+    #[serde(default = "crate::params::default_report_base")]
+    pub report_base: PathBuf,
+    /// Name of the reinstalled volume; the installer's default when unset.
+    #[serde(default)]
+    pub new_volume_name: Option<String>,
+    /// Installed after macOS, in order (`--installpackage`).
+    #[serde(default)]
+    pub packages: Vec<PathBuf>,
+    /// Administrator authorizing the erase; required on Apple Silicon.
+    #[serde(default)]
+    pub admin_user: Option<String>,
+    /// Usually a `secret://` reference. Passed on stdin, never as an
+    /// argument.
+    #[serde(default)]
+    pub admin_password: Option<String>,
+    #[serde(default)]
+    pub force: bool,
+    #[serde(default)]
+    pub confirmation_token: Option<String>,
+    /// Policy opt-in for erasing the running system's disk; also needs a
+    /// `PHX-SYS-` token.
+    #[serde(default)]
+    pub allow_system_target: bool,
+    #[serde(default = "crate::params::default_true")]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MacosEraseInstallResult {
+    pub report: ReportPaths,
+    pub target_disk: String,
+    /// Last progress `startosinstall` printed before handing over to the
+    /// restart.
+    pub progress_percent: Option<f64>,
+    pub dry_run: bool,
+}
+
+/// Checks the installer and the host, then runs `startosinstall`. The Mac
+/// restarts once preparation finishes; the run ledger's `erase_install`
+/// phase is what survives when the report cannot be written first.
+pub fn run_macos_erase_install(params: &MacosEraseInstallParams) -> Result<MacosEraseInstallResult> {
+    let started = Instant::now();
+    if !cfg!(target_os = "macos") {
+        return Err(anyhow!("macos erase install requires macOS"));
+    }
+    let tool = params.source_app.join(STARTOSINSTALL);
+    if !tool.is_file() {
+        return Err(anyhow!(
+            "startosinstall not found in {}",
+            params.source_app.display()
+        ));
+    }
+    for package in &params.packages {
+        if !package.is_file() {
+            return Err(anyhow!("package not found: {}", package.display()));
+        }
+    }
+    if params.admin_user.is_some() != params.admin_password.is_some() {
+        return Err(anyhow!("admin_user and admin_password must be given together"));
+    }
+    let apple_silicon = cfg!(target_arch = "aarch64");
+    if apple_silicon && params.admin_user.is_none() {
+        return Err(anyhow!(
+            "Apple Silicon Macs need admin_user and admin_password to authorize the erase"
+        ));
+    }
+
+    let graph = build_device_graph()?;
+    let support = inspect_installer_app(&params.source_app)?;
+    // The host's own model; a mock or odd graph just skips the check.
+    if MacArch::of_model(&graph.host.machine).is_ok() {
+        check_installer_for_model(&support, &graph.host.machine)?;
+    }
+    let disk = graph
+        .disks
+        .iter()
+        .find(|disk| disk.is_system_disk)
+        .ok_or_else(|| anyhow!("system disk not found"))?;
+    let args = erase_install_args(params);
+
+    let mut logs = StepLog::new("macos-erase-install");
+    logs.push(format!("source_app={}", params.source_app.display()));
+    logs.push(format!("target_disk={}", disk.id));
+    logs.push(format!("apple_silicon={}", apple_silicon));
+    logs.push(format!("model={}", graph.host.machine));
+    if let Some(version) = &support.macos_version {
+        logs.push(format!("installer_version={}", version));
+    }
+    logs.push(format!("command={} {}", tool.display(), args.join(" ")));
+    logs.push(format!("dry_run={}", params.dry_run));
+
+    let ctx = SafetyContext {
+        force_mode: params.force,
+        confirmation_token: params.confirmation_token.clone(),
+        allow_system_disk: params.allow_system_target,
+        armed_until: phoenix_safety::armed_until(),
+    };
+    let mut session = DestructiveSession::begin(
+        "macos-erase-install",
+        Some(disk),
+        &ctx,
+        disk.is_system_disk,
+        params.dry_run,
+        &mut logs,
+    )?;
+    let mut erase = EraseInstall {
+        disk,
+        tool: &tool,
+        args: &args,
+        admin_password: params.admin_password.as_deref(),
+    };
+    let progress_percent = session.perform(&mut erase, &mut logs)?.flatten();
+
+    let (log_text, timing) = logs.finish()?;
+
+    let meta = serde_json::json!({
+        "workflow": "macos-erase-install",
+        "status": if params.dry_run { "dry_run" } else { "completed" },
+        "source_app": params.source_app.display().to_string(),
+        "target_disk": disk.id,
+        "target_serial": disk.serial,
+        "new_volume_name": params.new_volume_name,
+        "packages": params
+            .packages
+            .iter()
+            .map(|package| package.display().to_string())
+            .collect::<Vec<_>>(),
+        "admin_user": params.admin_user,
+        "apple_silicon": apple_silicon,
+        "model": graph.host.machine,
+        "installer_support": support,
+        "progress_percent": progress_percent,
+        "dry_run": params.dry_run,
+        "duration_ms": started.elapsed().as_millis() as u64
+    });
+
+    let report = create_report_bundle_with_meta_signing_and_artifacts(
+        &params.report_base,
+        &graph,
+        Some(meta),
+        Some(&log_text),
+        signing_key_from_env().as_deref(),
+        &[timing],
+    )?;
+    session.complete(&report.root)?;
+
+    Ok(MacosEraseInstallResult {
+        report,
+        target_disk: disk.id.clone(),
+        progress_percent,
+        dry_run: params.dry_run,
+    })
+}
+
+/// `startosinstall --eraseinstall` on the system disk; returns the last
+/// progress it printed.
+struct EraseInstall<'a> {
+    disk: &'a Disk,
+    tool: &'a Path,
+    args: &'a [String],
+    admin_password: Option<&'a str>,
+}
+
+impl DestructiveOperation for EraseInstall<'_> {
+    type Output = Option<f64>;
+
+    fn description(&self) -> String {
+        format!("erase {} and reinstall macOS with {}", self.disk.id, self.tool.display())
+    }
+
+    fn phase(&self) -> &'static str {
+        "erase_install"
+    }
+
+    fn disk_id(&self) -> Option<&str> {
+        Some(&self.disk.id)
+    }
+
+    fn pre_check(&self) -> Result<()> {
+        if phoenix_core::mock::is_active() {
+            return Ok(());
+        }
+        if !crate::doctor::is_elevated() {
+            return Err(anyhow!("startosinstall must run as root"));
+        }
+        if on_ac_power() == Some(false) {
+            return Err(anyhow!("connect the Mac to power before erasing"));
+        }
+        Ok(())
+    }
+
+    fn execute(&mut self, logs: &mut StepLog, _tracker: Option<&mut RunTracker>) -> Result<Option<f64>> {
+        run_startosinstall(self.tool, self.args, self.admin_password, logs)
+    }
+}
+
+fn erase_install_args(params: &MacosEraseInstallParams) -> Vec<String> {
+    let mut args: Vec<String> = [
+        "--eraseinstall",
+        "--agreetolicense",
+        "--nointeraction",
+        "--forcequitapps",
+    ]
+    .iter()
+    .map(|arg| arg.to_string())
+    .collect();
+    if let Some(name) = &params.new_volume_name {
+        args.push("--newvolumename".to_string());
+        args.push(name.clone());
+    }
+    for package in &params.packages {
+        args.push("--installpackage".to_string());
+        args.push(package.display().to_string());
+    }
+    if let Some(user) = &params.admin_user {
+        args.push("--user".to_string());
+        args.push(user.clone());
+        args.push("--stdinpass".to_string());
+    }
+    args
+}
+
+fn run_startosinstall(
+    tool: &Path,
+    args: &[String],
+    password: Option<&str>,
+    logs: &mut StepLog,
+) -> Result<Option<f64>> {
+    if phoenix_core::mock::is_active() {
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        phoenix_core::mock::record_command(tool.to_string_lossy().as_ref(), &args)?;
+        return Ok(Some(100.0));
+    }
+    let mut child = Command::new(tool)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("run {}", tool.display()))?;
+    if let (Some(mut stdin), Some(password)) = (child.stdin.take(), password) {
+        stdin
+            .write_all(format!("{}\n", password).as_bytes())
+            .context("pass admin password to startosinstall")?;
+    }
+    let stderr = child.stderr.take().map(|mut stderr| {
+        std::thread::spawn(move || {
+            let mut text = String::new();
+            let _ = stderr.read_to_string(&mut text);
+            text
+        })
+    });
+
+    // Stdout is read on its own thread so a cancel can kill a silent tool.
+    let mut progress = ProgressParser::default();
+    if let Some(stdout) = child.stdout.take() {
+        let (bytes, received) = mpsc::channel();
+        std::thread::spawn(move || {
+            for byte in BufReader::new(stdout).bytes() {
+                if bytes.send(byte).is_err() {
+                    break;
+                }
+            }
+        });
+        loop {
+            check_child_cancelled(&mut child)?;
+            match received.recv_timeout(Duration::from_millis(250)) {
+                Ok(byte) => progress.feed(byte?, logs),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+        }
+        progress.feed(b'\n', logs);
+    }
+    let status = wait_child(&mut child)?;
+    let stderr = stderr
+        .and_then(|handle| handle.join().ok())
+        .unwrap_or_default();
+    if !status.success() {
+        return Err(anyhow!("startosinstall failed ({}): {}", status, stderr.trim()));
+    }
+    Ok(progress.percent)
+}
+
+/// `startosinstall` prints `Preparing: 0.0%... 1.0%... 2.0%...` on a
+/// single line. Every tenth percent is logged; other lines as they are.
+#[derive(Default)]
+struct ProgressParser {
+    line: Vec<u8>,
+    token_start: usize,
+    percent: Option<f64>,
+    logged_tenth: Option<u32>,
+}
+
+impl ProgressParser {
+    fn feed(&mut self, byte: u8, logs: &mut StepLog) {
+        match byte {
+            b'\n' | b'\r' => {
+                self.end_token(logs);
+                let line = String::from_utf8_lossy(&self.line).trim().to_string();
+                if !line.is_empty() && !line.contains('%') {
+                    logs.push(format!("startosinstall: {}", line));
+                }
+                self.line.clear();
+                self.token_start = 0;
+            }
+            b' ' | b'\t' => {
+                self.end_token(logs);
+                self.line.push(b' ');
+                self.token_start = self.line.len();
+            }
+            other => self.line.push(other),
+        }
+    }
+
+    fn end_token(&mut self, logs: &mut StepLog) {
+        let token = String::from_utf8_lossy(&self.line[self.token_start..]).to_string();
+        let Some(percent) = parse_percent(&token) else {
+            return;
+        };
+        self.percent = Some(percent);
+        let tenth = (percent / 10.0) as u32;
+        if self.logged_tenth != Some(tenth) {
+            self.logged_tenth = Some(tenth);
+            logs.push(format!("progress={:.0}%", percent));
+        }
+    }
+}
+
+fn parse_percent(token: &str) -> Option<f64> {
+    token
+        .trim_end_matches('.')
+        .strip_suffix('%')?
+        .parse::<f64>()
+        .ok()
+        .filter(|percent| (0.0..=100.0).contains(percent))
+}
+
+/// `None` when the power source cannot be read.
+#[cfg(target_os = "macos")]
+fn on_ac_power() -> Option<bool> {
+    let mut pmset = Command::new("/usr/bin/pmset");
+    let output = crate::cancel::command_output(pmset.args(["-g", "ps"])).ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&output.stdout).contains("'AC Power'"))
+}
+
+#[cfg(not(target_os = "macos"))]
+fn on_ac_power() -> Option<bool> {
+    None
+}
@@ -0,0 +1,363 @@
+//! Duration estimates for a workflow, for scheduling how many sticks a
+//! shift can produce. Each step is costed from the bytes it moves and the
+//! throughput the device registry has recorded for sticks on this host;
+//! nothing is written or read from a device.
+
+use crate::registry::DeviceRegistry;
+use crate::{io_limits, plan_workflow_definition};
+use anyhow::{anyhow, Result};
+use phoenix_core::{WorkflowDefinition, WorkflowStep};
+use phoenix_workflow_plan::value::{optional_bool, optional_string, optional_string_list};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+/// Sustained write rate assumed with no history: a mid-range USB 3 stick.
+pub const DEFAULT_WRITE_BYTES_PER_SEC: u64 = 20_000_000;
+/// Read-back rate of a stick, for verify passes and scans.
+pub const DEFAULT_READ_BYTES_PER_SEC: u64 = 80_000_000;
+/// Reading and hashing a source on local storage.
+pub const DEFAULT_SOURCE_BYTES_PER_SEC: u64 = 400_000_000;
+/// Setup, device checks and the report every step writes.
+pub const STEP_OVERHEAD_MS: u64 = 2_000;
+
+#[derive(Debug, Clone)]
+pub struct EstimateParams {
+    /// Source bytes by step id, for sources not on this host.
+    pub source_sizes: BTreeMap<String, u64>,
+    /// Capacity of the target stick, for steps that scan all of it.
+    pub target_bytes: Option<u64>,
+    /// Registry key of the stick model to estimate for; else the median
+    /// of every stick with a recorded write.
+    pub device_key: Option<String>,
+    pub write_bytes_per_sec: Option<u64>,
+    pub read_bytes_per_sec: Option<u64>,
+    /// Sticks written at once.
+    pub parallel: u32,
+    pub shift_secs: Option<u64>,
+}
+
+impl Default for EstimateParams {
+    fn default() -> Self {
+        Self {
+            source_sizes: BTreeMap::new(),
+            target_bytes: None,
+            device_key: None,
+            write_bytes_per_sec: None,
+            read_bytes_per_sec: None,
+            parallel: 1,
+            shift_secs: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ThroughputSource {
+    /// Passed in by the caller.
+    Given,
+    /// The `device_key` stick's last recorded write.
+    Device,
+    /// Median over the registry.
+    Registry,
+    Default,
+}
+
+impl ThroughputSource {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Given => "given",
+            Self::Device => "device",
+            Self::Registry => "registry",
+            Self::Default => "default",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StepEstimate {
+    pub id: String,
+    pub action: String,
+    /// `None` when the step has no source or its size is unknown.
+    pub source_bytes: Option<u64>,
+    pub write_bytes: u64,
+    pub read_bytes: u64,
+    pub duration_ms: u64,
+    /// How the duration was worked out, or why it is only the overhead.
+    pub basis: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorkflowEstimate {
+    pub workflow: String,
+    /// Per stick, after any host-wide cap is shared between `parallel`.
+    pub write_bytes_per_sec: u64,
+    pub read_bytes_per_sec: u64,
+    pub throughput_source: ThroughputSource,
+    /// Registry records the rate was taken from.
+    pub devices_sampled: usize,
+    pub steps: Vec<StepEstimate>,
+    pub total_ms: u64,
+    pub parallel: u32,
+    /// Finished sticks per hour across `parallel` slots.
+    pub sticks_per_hour: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shift_secs: Option<u64>,
+    /// Complete runs that fit in the shift, across all slots.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sticks_per_shift: Option<u64>,
+    /// Steps whose size is unknown and limits that changed the inputs.
+    pub notes: Vec<String>,
+}
+
+/// Estimates `definition` step by step. Dry-run steps are costed as live
+/// runs, since the estimate is for scheduling real ones.
+pub fn estimate_workflow(
+    definition: &WorkflowDefinition,
+    params: &EstimateParams,
+) -> Result<WorkflowEstimate> {
+    plan_workflow_definition(definition)?;
+    if params.parallel == 0 {
+        return Err(anyhow!("parallel must be at least 1"));
+    }
+    let mut notes = Vec::new();
+    let (mut write_rate, throughput_source, devices_sampled) = write_throughput(params)?;
+    let read_rate = params
+        .read_bytes_per_sec
+        .unwrap_or(DEFAULT_READ_BYTES_PER_SEC);
+    if write_rate == 0 || read_rate == 0 {
+        return Err(anyhow!("throughput must be positive"));
+    }
+
+    let limits = io_limits()?;
+    let mut parallel = params.parallel;
+    if let Some(max) = limits.max_concurrent_writes {
+        let max = max.clamp(1, u32::MAX as usize) as u32;
+        if parallel > max {
+            notes.push(format!(
+                "parallel {} capped at max_concurrent_writes {}",
+                parallel, max
+            ));
+            parallel = max;
+        }
+    }
+    if let Some(cap) = limits.max_write_bytes_per_sec {
+        let share = (cap / u64::from(parallel)).max(1);
+        if share < write_rate {
+            notes.push(format!(
+                "write rate capped at {} per stick by max_write_bytes_per_sec",
+                phoenix_core::display_format().rate(share)
+            ));
+            write_rate = share;
+        }
+    }
+
+    let steps: Vec<StepEstimate> = definition
+        .steps
+        .iter()
+        .map(|step| estimate_step(step, params, write_rate, read_rate))
+        .collect();
+    for step in &steps {
+        if step.source_bytes.is_none() && step.basis.starts_with("unknown") {
+            notes.push(format!("step {}: {}", step.id, step.basis));
+        }
+    }
+    let total_ms = steps.iter().map(|step| step.duration_ms).sum::<u64>().max(1);
+    let sticks_per_hour = 3_600_000.0 / total_ms as f64 * f64::from(parallel);
+    let sticks_per_shift = params
+        .shift_secs
+        .map(|secs| secs.saturating_mul(1000) / total_ms * u64::from(parallel));
+
+    Ok(WorkflowEstimate {
+        workflow: definition.name.clone(),
+        write_bytes_per_sec: write_rate,
+        read_bytes_per_sec: read_rate,
+        throughput_source,
+        devices_sampled,
+        steps,
+        total_ms,
+        parallel,
+        sticks_per_hour,
+        shift_secs: params.shift_secs,
+        sticks_per_shift,
+        notes,
+    })
+}
+
+/// The rate passed in, else the registry's, else the default.
+fn write_throughput(params: &EstimateParams) -> Result<(u64, ThroughputSource, usize)> {
+    if let Some(rate) = params.write_bytes_per_sec {
+        return Ok((rate, ThroughputSource::Given, 0));
+    }
+    let records = DeviceRegistry::open_default()?.list()?;
+    if let Some(key) = &params.device_key {
+        let record = records
+            .iter()
+            .find(|record| &record.key == key)
+            .ok_or_else(|| anyhow!("device {} is not in the registry", key))?;
+        let rate = record
+            .last_write_bytes_per_sec
+            .or(record.best_write_bytes_per_sec)
+            .ok_or_else(|| anyhow!("device {} has no recorded write", key))?;
+        return Ok((rate, ThroughputSource::Device, 1));
+    }
+    let mut rates: Vec<u64> = records
+        .iter()
+        .filter_map(|record| record.last_write_bytes_per_sec)
+        .filter(|rate| *rate > 0)
+        .collect();
+    if rates.is_empty() {
+        return Ok((DEFAULT_WRITE_BYTES_PER_SEC, ThroughputSource::Default, 0));
+    }
+    rates.sort_unstable();
+    Ok((rates[rates.len() / 2], ThroughputSource::Registry, rates.len()))
+}
+
+/// What an action does with its bytes.
+enum Cost {
+    /// Writes the source to the stick, read back when `verify` is set.
+    WriteSource,
+    /// Reads the source from local storage.
+    ReadSource,
+    /// Reads (and with `mode: write`, first writes) the whole stick.
+    ScanTarget,
+    /// Only the fixed overhead.
+    Fixed,
+}
+
+fn action_cost(action: &str) -> Cost {
+    match action {
+        "windows_installer_usb" | "windows_apply_image" | "linux_installer_usb"
+        | "linux_write_image" | "macos_write_image" | "macos_installer_usb" | "ipsw_restore"
+        | "combo_stick" | "ab_stick" | "ab_update" | "clone_device" | "restore_report"
+        | "stage_files" => Cost::WriteSource,
+        "validate_source" | "slim_windows_media" | "merge_windows_languages"
+        | "macos_legacy_patch" => Cost::ReadSource,
+        "bad_block_scan" | "disk_hash_report" => Cost::ScanTarget,
+        _ => Cost::Fixed,
+    }
+}
+
+pub(crate) fn estimate_step(
+    step: &WorkflowStep,
+    params: &EstimateParams,
+    write_rate: u64,
+    read_rate: u64,
+) -> StepEstimate {
+    let mut estimate = StepEstimate {
+        id: step.id.clone(),
+        action: step.action.clone(),
+        source_bytes: None,
+        write_bytes: 0,
+        read_bytes: 0,
+        duration_ms: STEP_OVERHEAD_MS,
+        basis: "fixed overhead".to_string(),
+    };
+    let mut transfer_ms = 0;
+    match action_cost(&step.action) {
+        Cost::WriteSource | Cost::ReadSource => {
+            let source = params
+                .source_sizes
+                .get(&step.id)
+                .copied()
+                .or_else(|| source_bytes(step));
+            let Some(bytes) = source else {
+                estimate.basis = "unknown source size".to_string();
+                return estimate;
+            };
+            estimate.source_bytes = Some(bytes);
+            if matches!(action_cost(&step.action), Cost::ReadSource) {
+                estimate.read_bytes = bytes;
+                transfer_ms = millis(bytes, DEFAULT_SOURCE_BYTES_PER_SEC);
+                estimate.basis = "source read from local storage".to_string();
+            } else {
+                estimate.write_bytes = bytes;
+                transfer_ms = millis(bytes, write_rate);
+                estimate.basis = "source written to the stick".to_string();
+                if optional_bool(&step.params, "verify", false) {
+                    estimate.read_bytes = bytes;
+                    transfer_ms += millis(bytes, read_rate);
+                    estimate.basis.push_str(", then read back");
+                }
+            }
+        }
+        Cost::ScanTarget => {
+            let Some(bytes) = params.target_bytes else {
+                estimate.basis = "unknown target size".to_string();
+                return estimate;
+            };
+            estimate.read_bytes = bytes;
+            transfer_ms = millis(bytes, read_rate);
+            estimate.basis = "whole stick read".to_string();
+            if step.action == "bad_block_scan"
+                && optional_string(&step.params, "mode").is_some_and(|mode| mode != "read")
+            {
+                estimate.write_bytes = bytes;
+                transfer_ms += millis(bytes, write_rate);
+                estimate.basis = "whole stick written and read".to_string();
+            }
+        }
+        Cost::Fixed => {}
+    }
+    estimate.duration_ms += transfer_ms;
+    estimate
+}
+
+fn millis(bytes: u64, bytes_per_sec: u64) -> u64 {
+    (u128::from(bytes) * 1000 / u128::from(bytes_per_sec.max(1))) as u64
+}
+
+/// Total size of the step's sources that exist on this host, or `None`
+/// when none does.
+fn source_bytes(step: &WorkflowStep) -> Option<u64> {
+    let params = &step.params;
+    let mut paths: Vec<String> = [
+        "source_path",
+        "source_image",
+        "image_source",
+        "ipsw",
+        "windows_source",
+        "rescue_source",
+    ]
+    .iter()
+    .filter_map(|key| optional_string(params, key).map(str::to_string))
+    .collect();
+    if step.action == "merge_windows_languages" {
+        paths.extend(optional_string_list(params, "language_sources").unwrap_or_default());
+    }
+    if let Some(files) = params.get("files").and_then(|files| files.as_array()) {
+        paths.extend(
+            files
+                .iter()
+                .filter_map(|rule| rule.get("source").and_then(|source| source.as_str()))
+                .map(str::to_string),
+        );
+    }
+    let sizes: Vec<u64> = paths
+        .iter()
+        .filter_map(|path| tree_bytes(Path::new(path)))
+        .collect();
+    (!sizes.is_empty()).then(|| sizes.iter().sum())
+}
+
+/// A file's length, or the total of the files under a directory.
+/// Symlinks inside a directory are not followed.
+fn tree_bytes(path: &Path) -> Option<u64> {
+    let metadata = fs::metadata(path).ok()?;
+    if !metadata.is_dir() {
+        return Some(metadata.len());
+    }
+    let mut total = 0;
+    let mut pending = vec![path.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        for entry in fs::read_dir(&dir).into_iter().flatten().flatten() {
+            match entry.metadata() {
+                Ok(metadata) if metadata.is_dir() => pending.push(entry.path()),
+                Ok(metadata) if metadata.is_file() => total += metadata.len(),
+                _ => {}
+            }
+        }
+    }
+    Some(total)
+}
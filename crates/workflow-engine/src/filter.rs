@@ -0,0 +1,118 @@
+//! Include/exclude globs applied while collecting source files, so unused
+//! languages or components never reach the target.
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// Patterns use `/` separators, `*` and `?` within one component and `**`
+/// across directories, matched case-insensitively. A pattern without `/`
+/// matches a file or directory name at any depth (`*.mui`); one with `/`
+/// matches from the source root (`sources/sxs`). A pattern that matches a
+/// directory covers everything below it.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(try_from = "RawSourceFilter")]
+pub struct SourceFilter {
+    /// When non-empty, only files matching one of these are collected.
+    pub include: Vec<String>,
+    /// Files matching any of these are skipped, even when included.
+    pub exclude: Vec<String>,
+}
+
+/// Patterns as written, before `SourceFilter::new` normalizes them.
+#[derive(Deserialize)]
+struct RawSourceFilter {
+    #[serde(default)]
+    include: Vec<String>,
+    #[serde(default)]
+    exclude: Vec<String>,
+}
+
+impl TryFrom<RawSourceFilter> for SourceFilter {
+    type Error = anyhow::Error;
+
+    fn try_from(raw: RawSourceFilter) -> Result<Self> {
+        Self::new(raw.include, raw.exclude)
+    }
+}
+
+impl SourceFilter {
+    pub fn new(include: Vec<String>, exclude: Vec<String>) -> Result<Self> {
+        let normalize = |patterns: Vec<String>| -> Result<Vec<String>> {
+            patterns
+                .into_iter()
+                .map(|pattern| {
+                    let pattern = pattern.trim().replace('\\', "/");
+                    let pattern = pattern.trim_matches('/');
+                    if pattern.is_empty() {
+                        return Err(anyhow!("empty source filter pattern"));
+                    }
+                    Ok(pattern.to_string())
+                })
+                .collect()
+        };
+        Ok(Self {
+            include: normalize(include)?,
+            exclude: normalize(exclude)?,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.include.is_empty() && self.exclude.is_empty()
+    }
+
+    /// Whether the file at `relative` (to the source root) is collected.
+    pub fn matches(&self, relative: &Path) -> bool {
+        let path = relative.to_string_lossy().replace('\\', "/").to_ascii_lowercase();
+        let parts: Vec<&str> = path.split('/').filter(|part| !part.is_empty()).collect();
+        let any = |patterns: &[String]| {
+            patterns.iter().any(|pattern| pattern_matches(pattern, &parts))
+        };
+        (self.include.is_empty() || any(&self.include)) && !any(&self.exclude)
+    }
+}
+
+fn pattern_matches(pattern: &str, parts: &[&str]) -> bool {
+    let pattern = pattern.to_ascii_lowercase();
+    let mut components: Vec<&str> = pattern.split('/').collect();
+    if components.len() == 1 {
+        components.insert(0, "**");
+    }
+    // Matching any leading directory covers the files below it.
+    (1..=parts.len()).any(|len| match_path(&components, &parts[..len]))
+}
+
+/// `**` matches any number of directories; `*` and `?` stay within one.
+pub(crate) fn match_path(pattern: &[&str], path: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((&"**", rest)) => (0..=path.len()).any(|skip| match_path(rest, &path[skip..])),
+        Some((first, rest)) => match path.split_first() {
+            Some((name, remaining)) => match_component(first, name) && match_path(rest, remaining),
+            None => false,
+        },
+    }
+}
+
+fn match_component(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, n));
+            p += 1;
+        } else if let Some((star_p, star_n)) = star {
+            p = star_p + 1;
+            n = star_n + 1;
+            star = Some((star_p, star_n + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
@@ -0,0 +1,496 @@
+//! Renders first-boot scripts from pack templates onto media or an applied
+//! system as the `stage_firstboot` step: Windows `SetupComplete.cmd`,
+//! systemd units and macOS LaunchDaemons, plus the files they call. Every
+//! file written is tracked in the report manifest, so nobody has to edit
+//! staged media by hand afterwards.
+
+use crate::ledger::RunTracker;
+use crate::operation::{DestructiveOperation, DestructiveSession};
+use crate::stage::{normalize_destination, StageOverwrite};
+use crate::steplog::StepLog;
+use crate::{build_device_graph, normalize_mount_for_unix, signing_key_from_env, to_hex};
+use anyhow::{anyhow, Context, Result};
+use phoenix_core::{DeviceGraph, Disk};
+use phoenix_report::{
+    create_report_bundle_with_meta_signing_and_artifacts, ReportArtifact, ReportPaths,
+};
+use phoenix_safety::SafetyContext;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const FIRSTBOOT_MANIFEST_FILE: &str = "firstboot_manifest.json";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FirstbootKind {
+    /// `SetupComplete.cmd`, run once by Windows Setup before first logon.
+    /// Written with CRLF line endings.
+    SetupComplete,
+    /// A systemd unit, enabled through the `WantedBy=` targets of its
+    /// `[Install]` section.
+    SystemdUnit,
+    /// A macOS LaunchDaemon property list, mode 0644.
+    LaunchDaemon,
+    /// Any other file, such as the script a unit runs.
+    File,
+}
+
+impl FirstbootKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::SetupComplete => "setup_complete",
+            Self::SystemdUnit => "systemd_unit",
+            Self::LaunchDaemon => "launch_daemon",
+            Self::File => "file",
+        }
+    }
+}
+
+/// One template to render.
+///
+/// ```json
+/// { "kind": "systemd_unit", "template": "pack/firstboot.service",
+///   "name": "phoenix-firstboot.service" }
+/// ```
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct FirstbootScript {
+    pub kind: FirstbootKind,
+    pub template: String,
+    /// Unit or property list file name; required for `systemd_unit` and
+    /// `launch_daemon`.
+    #[serde(default)]
+    pub name: Option<String>,
+    /// Path relative to the target; required for `file`.
+    #[serde(default)]
+    pub destination: Option<String>,
+    /// Octal permissions such as `0755`, applied on Unix hosts.
+    #[serde(default)]
+    pub mode: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StageFirstbootParams {
+    #[serde(default)]
+    pub scripts: Vec<FirstbootScript>,
+    /// Values for `{{name}}` placeholders; `secret://` references are
+    /// resolved like any other step param.
+    #[serde(default)]
+    pub vars: BTreeMap<String, String>,
+    /// Windows Setup media, an applied Windows volume, or the root of an
+    /// installed Linux or macOS system.
+    pub target_mount: PathBuf,
+    #[serde(default)]
+    pub overwrite: StageOverwrite,
+    #[serde(default = "crate::params::default_report_base")]
+    pub report_base: PathBuf,
+    #[serde(default)]
+    pub force: bool,
+    #[serde(default)]
+    pub confirmation_token: Option<String>,
+    #[serde(default = "crate::params::default_true")]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FirstbootFile {
+    pub kind: FirstbootKind,
+    /// Empty for enable links.
+    pub template: String,
+    pub template_sha256: String,
+    /// Path on the target, relative to the mount, with `/` separators.
+    pub destination: String,
+    /// Set for systemd enable links instead of contents.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub link_target: Option<String>,
+    pub bytes: u64,
+    pub sha256: String,
+    /// Variables the template used; their values are not recorded.
+    pub vars: Vec<String>,
+    /// `write`, `replace` or `skip`.
+    pub action: String,
+    #[serde(skip)]
+    contents: Vec<u8>,
+    #[serde(skip)]
+    mode: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StageFirstbootResult {
+    pub report: ReportPaths,
+    pub files: Vec<FirstbootFile>,
+    pub dry_run: bool,
+}
+
+/// Replaces every `{{name}}` in `template` and returns the names used. A
+/// placeholder without a value is an error, so a typo cannot ship a
+/// script with `{{domian}}` in it.
+pub fn render_template(
+    template: &str,
+    vars: &BTreeMap<String, String>,
+) -> Result<(String, BTreeSet<String>)> {
+    let mut out = String::with_capacity(template.len());
+    let mut used = BTreeSet::new();
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let end = after
+            .find("}}")
+            .ok_or_else(|| anyhow!("unterminated {{{{ placeholder"))?;
+        let name = after[..end].trim();
+        let value = vars
+            .get(name)
+            .ok_or_else(|| anyhow!("undefined variable {}", name))?;
+        out.push_str(value);
+        used.insert(name.to_string());
+        rest = &after[end + 2..];
+    }
+    out.push_str(rest);
+    Ok((out, used))
+}
+
+pub fn run_stage_firstboot(params: &StageFirstbootParams) -> Result<StageFirstbootResult> {
+    if params.scripts.is_empty() {
+        return Err(anyhow!("stage_firstboot needs at least one script"));
+    }
+    let graph = build_device_graph()?;
+    let target_mount = normalize_mount_for_unix(&params.target_mount);
+    if !target_mount.is_dir() {
+        return Err(anyhow!("target mount is invalid"));
+    }
+    let disk = owning_disk(&graph, &target_mount).ok_or_else(|| {
+        anyhow!("{} is not on a mounted disk in the device graph", target_mount.display())
+    })?;
+    let is_system_target = disk.is_system_disk;
+    if is_system_target {
+        return Err(anyhow!(
+            "refusing to stage first-boot scripts into the running system: {}",
+            target_mount.display()
+        ));
+    }
+
+    // Render everything first so a bad template leaves the target untouched.
+    let mut files = Vec::new();
+    for script in &params.scripts {
+        files.extend(
+            plan_script(script, &params.vars, &target_mount)
+                .with_context(|| format!("template {}", script.template))?,
+        );
+    }
+    let mut destinations = HashSet::new();
+    for file in &mut files {
+        if !destinations.insert(file.destination.to_ascii_lowercase()) {
+            return Err(anyhow!("more than one file is staged to {}", file.destination));
+        }
+        let dest = target_mount.join(&file.destination);
+        let exists = fs::symlink_metadata(&dest).is_ok();
+        file.action = match (exists, params.overwrite) {
+            (false, _) => "write",
+            (true, StageOverwrite::Fail) => {
+                return Err(anyhow!(
+                    "{} already exists on the target (overwrite policy is fail)",
+                    file.destination
+                ))
+            }
+            (true, StageOverwrite::Skip) => "skip",
+            (true, StageOverwrite::Replace) => "replace",
+        }
+        .to_string();
+    }
+
+    let mut logs = StepLog::new("stage-firstboot");
+    logs.push(format!("target_mount={}", target_mount.display()));
+    logs.push(format!("overwrite={}", params.overwrite.as_str()));
+    let ctx = SafetyContext {
+        force_mode: params.force,
+        confirmation_token: params.confirmation_token.clone(),
+        allow_system_disk: false,
+        armed_until: phoenix_safety::armed_until(),
+    };
+    let mut session = DestructiveSession::begin(
+        "stage-firstboot",
+        None,
+        &ctx,
+        is_system_target,
+        params.dry_run,
+        &mut logs,
+    )?;
+    session.perform(
+        &mut WriteFirstbootFiles {
+            target_mount: &target_mount,
+            files: &files,
+        },
+        &mut logs,
+    )?;
+    if params.dry_run {
+        logs.push("dry_run=true".to_string());
+    }
+    for file in &files {
+        logs.push(format!(
+            "{} {}={} <- {} vars={}",
+            file.kind.as_str(),
+            file.action,
+            file.destination,
+            file.link_target.as_deref().unwrap_or(&file.template),
+            file.vars.join(",")
+        ));
+    }
+
+    let manifest = ReportArtifact::json(FIRSTBOOT_MANIFEST_FILE, &files)?;
+    let (log_text, timing) = logs.finish()?;
+    let written = files.iter().filter(|file| file.action != "skip").count();
+    let meta = serde_json::json!({
+        "workflow": "stage-firstboot",
+        "status": if params.dry_run { "dry_run" } else { "completed" },
+        "target_mount": target_mount.display().to_string(),
+        "overwrite": params.overwrite.as_str(),
+        "scripts": params.scripts.len(),
+        "copied_files": written,
+        "skipped_files": files.len() - written,
+        "artifacts": [&manifest.name, &timing.name],
+        "dry_run": params.dry_run
+    });
+    let report = create_report_bundle_with_meta_signing_and_artifacts(
+        &params.report_base,
+        &graph,
+        Some(meta),
+        Some(&log_text),
+        signing_key_from_env().as_deref(),
+        &[manifest, timing],
+    )?;
+
+    Ok(StageFirstbootResult {
+        report,
+        files,
+        dry_run: params.dry_run,
+    })
+}
+
+/// The rendered file for `script`, plus enable links for systemd units.
+fn plan_script(
+    script: &FirstbootScript,
+    vars: &BTreeMap<String, String>,
+    target: &Path,
+) -> Result<Vec<FirstbootFile>> {
+    let source = fs::read(&script.template)?;
+    let template_sha256 = to_hex(&Sha256::digest(&source));
+    let text = String::from_utf8(source).map_err(|_| anyhow!("template is not UTF-8 text"))?;
+    let (rendered, used) = render_template(&text, vars)?;
+    let mode = script
+        .mode
+        .as_deref()
+        .map(|mode| {
+            u32::from_str_radix(mode.trim_start_matches("0o"), 8)
+                .map_err(|_| anyhow!("mode {} is not octal", mode))
+        })
+        .transpose()?;
+    let name = |suffixes: &[&str]| -> Result<String> {
+        let name = script
+            .name
+            .as_deref()
+            .ok_or_else(|| anyhow!("{} needs a name", script.kind.as_str()))?;
+        if name.contains(['/', '\\']) || !suffixes.iter().any(|suffix| name.ends_with(suffix)) {
+            return Err(anyhow!(
+                "name {} must be a plain file name ending in {}",
+                name,
+                suffixes.join(" or ")
+            ));
+        }
+        Ok(name.to_string())
+    };
+
+    let mut links = Vec::new();
+    let (destination, contents, mode) = match script.kind {
+        FirstbootKind::SetupComplete => {
+            let destination = if target.join("setup.exe").is_file() {
+                "sources/$OEM$/$$/Setup/Scripts/SetupComplete.cmd"
+            } else if target.join("Windows").join("System32").is_dir() {
+                "Windows/Setup/Scripts/SetupComplete.cmd"
+            } else {
+                return Err(anyhow!(
+                    "target is neither Windows Setup media nor an applied Windows volume"
+                ));
+            };
+            let crlf = rendered.replace("\r\n", "\n").replace('\n', "\r\n");
+            (destination.to_string(), crlf.into_bytes(), mode)
+        }
+        FirstbootKind::SystemdUnit => {
+            let name = name(&[".service", ".timer"])?;
+            if !target.join("etc").is_dir() {
+                return Err(anyhow!("target has no Linux root (no etc/)"));
+            }
+            let wanted_by = install_targets(&rendered);
+            if wanted_by.is_empty() {
+                return Err(anyhow!(
+                    "unit has no WantedBy= in [Install], so it would never start"
+                ));
+            }
+            for wanted in wanted_by {
+                links.push(format!("etc/systemd/system/{}.wants/{}", wanted, name));
+            }
+            let destination = format!("etc/systemd/system/{}", name);
+            (destination, rendered.into_bytes(), Some(mode.unwrap_or(0o644)))
+        }
+        FirstbootKind::LaunchDaemon => {
+            let name = name(&[".plist"])?;
+            if !target.join("Library").is_dir() {
+                return Err(anyhow!("target has no macOS root (no Library/)"));
+            }
+            if !rendered.contains("<key>Label</key>") {
+                return Err(anyhow!("property list has no Label key"));
+            }
+            (format!("Library/LaunchDaemons/{}", name), rendered.into_bytes(), Some(0o644))
+        }
+        FirstbootKind::File => {
+            let destination = script
+                .destination
+                .as_deref()
+                .ok_or_else(|| anyhow!("file needs a destination"))?;
+            let destination = normalize_destination(destination)?;
+            if destination.is_empty() {
+                return Err(anyhow!("file destination is empty"));
+            }
+            (destination, rendered.into_bytes(), mode)
+        }
+    };
+
+    let vars: Vec<String> = used.into_iter().collect();
+    let unit_path = format!("/{}", destination);
+    let mut files = vec![FirstbootFile {
+        kind: script.kind,
+        template: script.template.clone(),
+        template_sha256: template_sha256.clone(),
+        bytes: contents.len() as u64,
+        sha256: to_hex(&Sha256::digest(&contents)),
+        destination,
+        link_target: None,
+        vars: vars.clone(),
+        action: "write".to_string(),
+        contents,
+        mode,
+    }];
+    for link in links {
+        files.push(FirstbootFile {
+            kind: script.kind,
+            template: String::new(),
+            template_sha256: String::new(),
+            destination: link,
+            link_target: Some(unit_path.clone()),
+            bytes: 0,
+            sha256: String::new(),
+            vars: Vec::new(),
+            action: "write".to_string(),
+            contents: Vec::new(),
+            mode: None,
+        });
+    }
+    Ok(files)
+}
+
+/// The disk whose partition is mounted deepest above `path`, so a
+/// directory inside the running system resolves to the system disk.
+fn owning_disk<'a>(graph: &'a DeviceGraph, path: &Path) -> Option<&'a Disk> {
+    graph
+        .disks
+        .iter()
+        .flat_map(|disk| {
+            disk.partitions
+                .iter()
+                .flat_map(|partition| partition.mount_points.iter())
+                .map(move |mount| (disk, Path::new(mount)))
+        })
+        .filter(|(_, mount)| path.starts_with(mount))
+        .max_by_key(|(_, mount)| mount.components().count())
+        .map(|(disk, _)| disk)
+}
+
+/// `WantedBy=` targets from the `[Install]` section.
+fn install_targets(unit: &str) -> Vec<String> {
+    let mut section = "";
+    let mut targets = Vec::new();
+    for line in unit.lines().map(str::trim) {
+        if line.starts_with('[') && line.ends_with(']') {
+            section = line;
+        } else if section == "[Install]" {
+            if let Some(value) = line.strip_prefix("WantedBy=") {
+                targets.extend(value.split_whitespace().map(str::to_string));
+            }
+        }
+    }
+    targets
+}
+
+/// The rendered scripts and enable links, written onto the target mount.
+struct WriteFirstbootFiles<'a> {
+    target_mount: &'a Path,
+    files: &'a [FirstbootFile],
+}
+
+impl DestructiveOperation for WriteFirstbootFiles<'_> {
+    type Output = ();
+
+    fn description(&self) -> String {
+        let writes = self.files.iter().filter(|file| file.action != "skip").count();
+        format!("write {} first-boot file(s) to {}", writes, self.target_mount.display())
+    }
+
+    fn phase(&self) -> &'static str {
+        "stage_firstboot"
+    }
+
+    fn disk_id(&self) -> Option<&str> {
+        None
+    }
+
+    fn execute(&mut self, _logs: &mut StepLog, _tracker: Option<&mut RunTracker>) -> Result<()> {
+        for file in self.files.iter().filter(|file| file.action != "skip") {
+            write_file(self.target_mount, file)?;
+        }
+        Ok(())
+    }
+}
+
+fn write_file(target: &Path, file: &FirstbootFile) -> Result<()> {
+    let dest = target.join(&file.destination);
+    if let Some(parent) = dest.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    if let Some(link) = &file.link_target {
+        if fs::symlink_metadata(&dest).is_ok() {
+            fs::remove_file(&dest)?;
+        }
+        return symlink(link, &dest);
+    }
+    fs::write(&dest, &file.contents).with_context(|| format!("write {}", dest.display()))?;
+    match file.mode {
+        Some(mode) => set_mode(&dest, mode),
+        None => Ok(()),
+    }
+}
+
+#[cfg(unix)]
+fn symlink(link: &str, dest: &Path) -> Result<()> {
+    std::os::unix::fs::symlink(link, dest)
+        .with_context(|| format!("link {} -> {}", dest.display(), link))
+}
+
+#[cfg(not(unix))]
+fn symlink(link: &str, _dest: &Path) -> Result<()> {
+    Err(anyhow!("cannot enable {} from this host; systemd links need a Unix host", link))
+}
+
+#[cfg(unix)]
+fn set_mode(dest: &Path, mode: u32) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(dest, fs::Permissions::from_mode(mode))
+        .with_context(|| format!("chmod {}", dest.display()))
+}
+
+/// Windows cannot express Unix modes; the files are written as they are.
+#[cfg(not(unix))]
+fn set_mode(_dest: &Path, _mode: u32) -> Result<()> {
+    Ok(())
+}
@@ -0,0 +1,258 @@
+//! A small certificate authority for a fleet of agents. `init_fleet_ca`
+//! makes the CA; `issue_fleet_cert` makes a client certificate per agent,
+//! whose common name is the agent id, and the orchestration server's
+//! certificate. The server accepts only client certificates from the CA
+//! and the agents trust only the CA for the server, so both ends of a job
+//! know who they talk to. Every certificate made is appended to
+//! `issued.jsonl` in the CA directory.
+
+use crate::to_hex;
+use anyhow::{anyhow, Context, Result};
+use phoenix_core::now_utc_rfc3339;
+use phoenix_report::AgentIdentity;
+use rcgen::{
+    BasicConstraints, CertificateParams, DnType, ExtendedKeyUsagePurpose, IsCa, KeyPair,
+    KeyUsagePurpose, SerialNumber,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use time::format_description::well_known::Rfc3339;
+use time::{Duration, OffsetDateTime};
+
+pub const FLEET_CA_CERT: &str = "ca.pem";
+pub const FLEET_CA_KEY: &str = "ca.key";
+pub const FLEET_ISSUED_FILE: &str = "issued.jsonl";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CertKind {
+    Ca,
+    /// Client certificate; the common name is the agent id.
+    Agent,
+    /// Server certificate for a host name or IP address.
+    Server,
+}
+
+impl CertKind {
+    pub fn parse(value: &str) -> Result<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "agent" => Ok(Self::Agent),
+            "server" => Ok(Self::Server),
+            other => Err(anyhow!("unknown certificate kind {} (expected agent or server)", other)),
+        }
+    }
+}
+
+/// One line of `issued.jsonl`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IssuedCert {
+    pub kind: CertKind,
+    /// Agent id, server host name or CA name.
+    pub name: String,
+    pub serial: String,
+    pub cert_sha256: String,
+    pub issued_utc: String,
+    pub not_after_utc: String,
+    pub cert_path: PathBuf,
+    pub key_path: PathBuf,
+}
+
+/// Creates the CA in `dir`; refuses to replace an existing one.
+pub fn init_fleet_ca(dir: &Path, name: &str, days: u32) -> Result<IssuedCert> {
+    check_name(name)?;
+    let cert_path = dir.join(FLEET_CA_CERT);
+    let key_path = dir.join(FLEET_CA_KEY);
+    refuse_existing(&[&cert_path, &key_path])?;
+    fs::create_dir_all(dir).with_context(|| format!("create {}", dir.display()))?;
+
+    let mut params = base_params(&format!("{} fleet CA", name), days)?;
+    params.is_ca = IsCa::Ca(BasicConstraints::Constrained(0));
+    params.key_usages = vec![KeyUsagePurpose::KeyCertSign, KeyUsagePurpose::CrlSign];
+    let key = KeyPair::generate()?;
+    let cert = params.self_signed(&key)?;
+    write_pair(&cert_path, &cert.pem(), &key_path, &key.serialize_pem())?;
+    record(dir, CertKind::Ca, name, cert.der(), &cert_path, &key_path)
+}
+
+/// Issues a certificate of `kind` for `name` from the CA in `dir`, written
+/// next to it as `<name>.pem` and `<name>.key`.
+pub fn issue_fleet_cert(dir: &Path, kind: CertKind, name: &str, days: u32) -> Result<IssuedCert> {
+    check_name(name)?;
+    if kind == CertKind::Ca {
+        return Err(anyhow!("use init_fleet_ca for the CA"));
+    }
+    let ca_pem = fs::read_to_string(dir.join(FLEET_CA_CERT))
+        .with_context(|| format!("read {}; run fleet-ca-init first", dir.join(FLEET_CA_CERT).display()))?;
+    let ca_key = KeyPair::from_pem(&read_private(&dir.join(FLEET_CA_KEY))?)
+        .map_err(|err| anyhow!("read CA key: {}", err))?;
+    let ca = CertificateParams::from_ca_cert_pem(&ca_pem)
+        .map_err(|err| anyhow!("read CA certificate: {}", err))?
+        .self_signed(&ca_key)?;
+
+    let cert_path = dir.join(format!("{}.pem", name));
+    let key_path = dir.join(format!("{}.key", name));
+    refuse_existing(&[&cert_path, &key_path])?;
+    let mut params = match kind {
+        CertKind::Server => {
+            let mut params = base_params(name, days)?;
+            params.subject_alt_names =
+                CertificateParams::new(vec![name.to_string()])?.subject_alt_names;
+            params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ServerAuth];
+            params
+        }
+        _ => {
+            let mut params = base_params(name, days)?;
+            params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ClientAuth];
+            params
+        }
+    };
+    params.key_usages = vec![KeyUsagePurpose::DigitalSignature];
+    params.use_authority_key_identifier_extension = true;
+    let key = KeyPair::generate()?;
+    let cert = params.signed_by(&key, &ca, &ca_key)?;
+    // The file holds the chain, so the peer sees which CA issued it.
+    let chain = format!("{}{}", cert.pem(), ca_pem);
+    write_pair(&cert_path, &chain, &key_path, &key.serialize_pem())?;
+    record(dir, kind, name, cert.der(), &cert_path, &key_path)
+}
+
+/// The identity in the agent certificate at `path`: the first certificate
+/// of the PEM chain.
+pub fn read_agent_identity(path: &Path) -> Result<AgentIdentity> {
+    let bytes = fs::read(path).with_context(|| format!("read {}", path.display()))?;
+    let (_, pem) = x509_parser::pem::parse_x509_pem(&bytes)
+        .map_err(|err| anyhow!("{} is not a PEM certificate: {}", path.display(), err))?;
+    let cert = pem
+        .parse_x509()
+        .map_err(|err| anyhow!("parse {}: {}", path.display(), err))?;
+    let common_name = |name: &x509_parser::x509::X509Name| {
+        name.iter_common_name()
+            .next()
+            .and_then(|cn| cn.as_str().ok())
+            .map(str::to_string)
+    };
+    let agent_id = common_name(cert.subject())
+        .ok_or_else(|| anyhow!("{} has no subject common name", path.display()))?;
+    Ok(AgentIdentity {
+        agent_id,
+        cert_sha256: to_hex(&Sha256::digest(&pem.contents)),
+        serial: to_hex(cert.raw_serial()),
+        issuer: common_name(cert.issuer()).unwrap_or_default(),
+        not_after_utc: format_unix(cert.validity().not_after.timestamp()),
+    })
+}
+
+fn base_params(common_name: &str, days: u32) -> Result<CertificateParams> {
+    if days == 0 {
+        return Err(anyhow!("days must be greater than zero"));
+    }
+    let mut params = CertificateParams::default();
+    params.distinguished_name = rcgen::DistinguishedName::new();
+    params.distinguished_name.push(DnType::CommonName, common_name);
+    let now = OffsetDateTime::now_utc();
+    // Allows for benches whose clocks run a little behind.
+    params.not_before = now - Duration::hours(1);
+    params.not_after = now + Duration::days(days.into());
+    let mut serial = [0u8; 16];
+    getrandom::fill(&mut serial).map_err(|err| anyhow!("generate serial number: {}", err))?;
+    // Positive, and never shortened by a leading zero byte.
+    serial[0] = (serial[0] & 0x7f) | 0x40;
+    params.serial_number = Some(SerialNumber::from_slice(&serial));
+    Ok(params)
+}
+
+/// Agent ids and host names become file names.
+fn check_name(name: &str) -> Result<()> {
+    if name.is_empty()
+        || name.starts_with('.')
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+    {
+        return Err(anyhow!(
+            "name may only contain letters, digits, '.', '_' and '-': {}",
+            name
+        ));
+    }
+    Ok(())
+}
+
+fn refuse_existing(paths: &[&Path]) -> Result<()> {
+    match paths.iter().find(|path| path.exists()) {
+        Some(path) => Err(anyhow!("{} already exists; remove it to reissue", path.display())),
+        None => Ok(()),
+    }
+}
+
+fn write_pair(cert_path: &Path, cert_pem: &str, key_path: &Path, key_pem: &str) -> Result<()> {
+    fs::write(cert_path, cert_pem).with_context(|| format!("write {}", cert_path.display()))?;
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options
+        .open(key_path)
+        .and_then(|mut file| file.write_all(key_pem.as_bytes()))
+        .with_context(|| format!("write {}", key_path.display()))
+}
+
+/// Reads a private key, refusing one other users can read.
+fn read_private(path: &Path) -> Result<String> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = fs::metadata(path)
+            .with_context(|| format!("read {}", path.display()))?
+            .permissions()
+            .mode();
+        if mode & 0o077 != 0 {
+            return Err(anyhow!("{} is readable by other users; chmod 600 it", path.display()));
+        }
+    }
+    fs::read_to_string(path).with_context(|| format!("read {}", path.display()))
+}
+
+fn record(
+    dir: &Path,
+    kind: CertKind,
+    name: &str,
+    der: &[u8],
+    cert_path: &Path,
+    key_path: &Path,
+) -> Result<IssuedCert> {
+    let (_, cert) = x509_parser::parse_x509_certificate(der)
+        .map_err(|err| anyhow!("parse issued certificate: {}", err))?;
+    let issued = IssuedCert {
+        kind,
+        name: name.to_string(),
+        serial: to_hex(cert.raw_serial()),
+        cert_sha256: to_hex(&Sha256::digest(der)),
+        issued_utc: now_utc_rfc3339(),
+        not_after_utc: format_unix(cert.validity().not_after.timestamp()),
+        cert_path: cert_path.to_path_buf(),
+        key_path: key_path.to_path_buf(),
+    };
+    let path = dir.join(FLEET_ISSUED_FILE);
+    let mut line = serde_json::to_vec(&issued)?;
+    line.push(b'\n');
+    fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .and_then(|mut file| file.write_all(&line))
+        .with_context(|| format!("append {}", path.display()))?;
+    Ok(issued)
+}
+
+fn format_unix(secs: i64) -> String {
+    OffsetDateTime::from_unix_timestamp(secs)
+        .ok()
+        .and_then(|time| time.format(&Rfc3339).ok())
+        .unwrap_or_else(|| "unknown".to_string())
+}
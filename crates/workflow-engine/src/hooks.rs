@@ -0,0 +1,414 @@
+//! Pre/post hook commands declared per workflow step under `params.hooks`,
+//! for site-specific work around a step (e.g. updating an asset database).
+//! Every hook states its sandbox policy; its output becomes a report
+//! artifact of the workflow run.
+
+use crate::cleanup::{CleanupAction, CleanupGuard};
+use anyhow::{anyhow, Context, Result};
+use phoenix_core::WorkflowStep;
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Captured output per stream; the rest is dropped.
+const MAX_OUTPUT_BYTES: usize = 1024 * 1024;
+
+/// How long output is still read after the hook exits. A process that
+/// left the hook's group can hold the pipes open indefinitely.
+const OUTPUT_GRACE: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StepHooks {
+    /// Run before the step; a failure stops the step from running.
+    #[serde(default)]
+    pub pre: Vec<HookSpec>,
+    /// Run after the step succeeds.
+    #[serde(default)]
+    pub post: Vec<HookSpec>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HookSpec {
+    /// Program and arguments.
+    #[serde(default)]
+    pub command: Vec<String>,
+    /// Executable in the plugin directory, run with `args`.
+    #[serde(default)]
+    pub plugin: Option<String>,
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Required: there is no implicit policy.
+    pub sandbox: HookSandbox,
+    /// A failing optional hook is recorded but does not fail the step.
+    #[serde(default = "default_required")]
+    pub required: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HookSandbox {
+    /// Whether the hook may reach the network. Without it the hook runs in
+    /// an empty network namespace (Linux) or under a deny-network profile
+    /// (macOS); other hosts refuse the hook.
+    pub network: bool,
+    /// Environment variables passed through besides `PATH`; everything
+    /// else is cleared.
+    #[serde(default)]
+    pub env: Vec<String>,
+    #[serde(default = "default_timeout_secs")]
+    pub timeout_secs: u64,
+    /// Working directory; default is a fresh empty directory.
+    #[serde(default)]
+    pub working_dir: Option<PathBuf>,
+}
+
+fn default_required() -> bool {
+    true
+}
+
+fn default_timeout_secs() -> u64 {
+    300
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HookPhase {
+    Pre,
+    Post,
+}
+
+impl HookPhase {
+    fn as_str(self) -> &'static str {
+        match self {
+            HookPhase::Pre => "pre",
+            HookPhase::Post => "post",
+        }
+    }
+}
+
+/// One finished hook.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HookRun {
+    pub phase: HookPhase,
+    pub index: usize,
+    pub program: String,
+    pub exit_code: Option<i32>,
+    pub timed_out: bool,
+    pub required: bool,
+    pub duration_ms: u128,
+    #[serde(skip)]
+    pub stdout: Vec<u8>,
+    #[serde(skip)]
+    pub stderr: Vec<u8>,
+}
+
+impl HookRun {
+    pub fn succeeded(&self) -> bool {
+        self.exit_code == Some(0) && !self.timed_out
+    }
+
+    /// Report artifact name prefix, e.g. `hooks/<step>/pre-0`.
+    pub fn artifact_prefix(&self, step_id: &str) -> String {
+        format!("hooks/{}/{}-{}", safe_name(step_id), self.phase.as_str(), self.index)
+    }
+}
+
+fn safe_name(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .collect()
+}
+
+/// `params.hooks` of a step, if any.
+pub fn step_hooks(step: &WorkflowStep) -> Result<Option<StepHooks>> {
+    match step.params.get("hooks") {
+        None | Some(serde_json::Value::Null) => Ok(None),
+        Some(value) => {
+            let hooks: StepHooks = serde_json::from_value(value.clone())
+                .map_err(|err| anyhow!("step {}: invalid hooks: {}", step.id, err))?;
+            for hook in hooks.pre.iter().chain(&hooks.post) {
+                validate_hook(hook).map_err(|err| anyhow!("step {}: {}", step.id, err))?;
+            }
+            Ok(Some(hooks))
+        }
+    }
+}
+
+fn validate_hook(hook: &HookSpec) -> Result<()> {
+    match (hook.command.is_empty(), &hook.plugin) {
+        (false, None) => {}
+        (true, Some(plugin)) => {
+            if plugin.is_empty() || plugin.contains(['/', '\\']) || plugin.starts_with('.') {
+                return Err(anyhow!("hook plugin must be a plain name: {}", plugin));
+            }
+        }
+        _ => return Err(anyhow!("hook needs exactly one of command or plugin")),
+    }
+    if !hook.command.is_empty() && !hook.args.is_empty() {
+        return Err(anyhow!("hook args only apply to plugin hooks"));
+    }
+    if hook.sandbox.timeout_secs == 0 {
+        return Err(anyhow!("hook timeout_secs must be positive"));
+    }
+    if !hook.sandbox.network && cfg!(not(any(target_os = "linux", target_os = "macos"))) {
+        return Err(anyhow!(
+            "network isolation is not available on this host; set sandbox.network to true"
+        ));
+    }
+    Ok(())
+}
+
+/// Runs the hooks of one phase in order, appending to `runs`. A failing
+/// required hook stops the remaining ones and fails with its stderr.
+pub fn run_hooks(
+    step: &WorkflowStep,
+    phase: HookPhase,
+    hooks: &[HookSpec],
+    report_root: Option<&Path>,
+    runs: &mut Vec<HookRun>,
+) -> Result<()> {
+    for (index, hook) in hooks.iter().enumerate() {
+        crate::cancel::check_cancelled()?;
+        let run = run_hook(step, phase, index, hook, report_root)
+            .with_context(|| format!("step {} {} hook {}", step.id, phase.as_str(), index))?;
+        let failed = !run.succeeded() && run.required;
+        let summary = format!(
+            "step {} {} hook {} ({}) {}: {}",
+            step.id,
+            phase.as_str(),
+            index,
+            run.program,
+            if run.timed_out { "timed out".to_string() } else { format!("exited with {:?}", run.exit_code) },
+            String::from_utf8_lossy(&run.stderr).trim()
+        );
+        runs.push(run);
+        if failed {
+            return Err(anyhow!(summary));
+        }
+    }
+    Ok(())
+}
+
+fn run_hook(
+    step: &WorkflowStep,
+    phase: HookPhase,
+    index: usize,
+    hook: &HookSpec,
+    report_root: Option<&Path>,
+) -> Result<HookRun> {
+    let argv = match &hook.plugin {
+        Some(plugin) => {
+            let path = plugin_dir()?.join(plugin);
+            if !path.is_file() {
+                return Err(anyhow!("plugin {} not found at {}", plugin, path.display()));
+            }
+            std::iter::once(path.display().to_string())
+                .chain(hook.args.iter().cloned())
+                .collect::<Vec<_>>()
+        }
+        None => hook.command.clone(),
+    };
+    let program = argv[0].clone();
+
+    let scratch = match &hook.sandbox.working_dir {
+        Some(_) => None,
+        None => {
+            let dir = std::env::temp_dir().join(format!(
+                "phoenix-hook-{}-{}-{}-{}",
+                std::process::id(),
+                safe_name(&step.id),
+                phase.as_str(),
+                index
+            ));
+            let guard = CleanupGuard::register(CleanupAction::RemoveDir, &dir);
+            std::fs::create_dir_all(&dir).with_context(|| format!("create {}", dir.display()))?;
+            Some(guard)
+        }
+    };
+    let working_dir = hook
+        .sandbox
+        .working_dir
+        .clone()
+        .or_else(|| scratch.as_ref().map(|guard| guard.path().to_path_buf()))
+        .unwrap_or_default();
+
+    let mut command = sandboxed_command(&argv, hook.sandbox.network);
+    command.current_dir(&working_dir).env_clear();
+    for name in std::iter::once("PATH").chain(hook.sandbox.env.iter().map(String::as_str)) {
+        if let Ok(value) = std::env::var(name) {
+            command.env(name, value);
+        }
+    }
+    command
+        .env("PHOENIX_HOOK_PHASE", phase.as_str())
+        .env("PHOENIX_STEP_ID", &step.id)
+        .env("PHOENIX_STEP_ACTION", &step.action);
+    if let Some(root) = report_root {
+        command.env("PHOENIX_STEP_REPORT", root);
+    }
+    if let Some(id) = phoenix_report::correlation_id() {
+        command.env("PHOENIX_CORRELATION_ID", id);
+    }
+
+    // The step itself goes to stdin as JSON, so hooks need no param parsing.
+    let context = serde_json::json!({
+        "phase": phase,
+        "step": step,
+        "report_root": report_root.map(|root| root.display().to_string()),
+    });
+    // Its own process group, so a timeout also kills what the hook started.
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::process_group(&mut command, 0);
+    let start = Instant::now();
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("start {}", program))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // A hook that ignores stdin may exit before reading it.
+        let _ = stdin.write_all(context.to_string().as_bytes());
+    }
+    let stdout = capture(child.stdout.take());
+    let stderr = capture(child.stderr.take());
+
+    let timeout = Duration::from_secs(hook.sandbox.timeout_secs);
+    let mut timed_out = false;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        // A cancelled run, e.g. one past its timeout_secs, kills the hook.
+        if crate::cancel::is_cancelled() {
+            kill_group(&mut child);
+            let _ = child.wait();
+            crate::cancel::check_cancelled()?;
+        }
+        if start.elapsed() >= timeout {
+            kill_group(&mut child);
+            timed_out = true;
+            break child.wait()?;
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    };
+    let deadline = Instant::now() + OUTPUT_GRACE;
+    let stdout = stdout.finish(deadline);
+    let stderr = stderr.finish(deadline);
+    drop(scratch);
+
+    Ok(HookRun {
+        phase,
+        index,
+        program,
+        exit_code: status.code(),
+        timed_out,
+        required: hook.required,
+        duration_ms: start.elapsed().as_millis(),
+        stdout,
+        stderr,
+    })
+}
+
+/// Kills the hook and everything else in its process group.
+fn kill_group(child: &mut Child) {
+    #[cfg(unix)]
+    unsafe {
+        libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+    }
+    let _ = child.kill();
+}
+
+/// Output read so far by a capture thread.
+struct Capture {
+    bytes: Arc<Mutex<Vec<u8>>>,
+    done: mpsc::Receiver<()>,
+}
+
+impl Capture {
+    /// Waits for the stream to close, but no later than `deadline`.
+    fn finish(self, deadline: Instant) -> Vec<u8> {
+        let _ = self.done.recv_timeout(deadline.saturating_duration_since(Instant::now()));
+        let mut bytes = self.bytes.lock().unwrap_or_else(|err| err.into_inner());
+        std::mem::take(&mut *bytes)
+    }
+}
+
+fn capture<R: Read + Send + 'static>(stream: Option<R>) -> Capture {
+    let bytes = Arc::new(Mutex::new(Vec::new()));
+    let (done, finished) = mpsc::channel::<()>();
+    let shared = bytes.clone();
+    std::thread::spawn(move || {
+        // Dropping `done` on return tells `finish` the stream closed.
+        let _done = done;
+        let Some(mut stream) = stream else { return };
+        // Past the cap the rest is drained so the hook never blocks on a
+        // full pipe.
+        let mut buf = [0u8; 64 * 1024];
+        loop {
+            let read = match stream.read(&mut buf) {
+                Ok(0) => break,
+                Ok(read) => read,
+                Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(_) => break,
+            };
+            let mut bytes = shared.lock().unwrap_or_else(|err| err.into_inner());
+            let room = MAX_OUTPUT_BYTES.saturating_sub(bytes.len());
+            bytes.extend_from_slice(&buf[..read.min(room)]);
+        }
+    });
+    Capture { bytes, done: finished }
+}
+
+#[cfg(target_os = "linux")]
+fn sandboxed_command(argv: &[String], network: bool) -> Command {
+    if network {
+        let mut command = Command::new(&argv[0]);
+        command.args(&argv[1..]);
+        return command;
+    }
+    // Root can unshare directly; others need a user namespace.
+    let mut command = Command::new("unshare");
+    if unsafe { libc::geteuid() } != 0 {
+        command.arg("--map-root-user");
+    }
+    command.arg("--net").arg("--").args(argv);
+    command
+}
+
+#[cfg(target_os = "macos")]
+fn sandboxed_command(argv: &[String], network: bool) -> Command {
+    if network {
+        let mut command = Command::new(&argv[0]);
+        command.args(&argv[1..]);
+        return command;
+    }
+    let mut command = Command::new("sandbox-exec");
+    command
+        .arg("-p")
+        .arg("(version 1)(allow default)(deny network*)")
+        .args(argv);
+    command
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn sandboxed_command(argv: &[String], _network: bool) -> Command {
+    // validate_hook refuses network: false here.
+    let mut command = Command::new(&argv[0]);
+    command.args(&argv[1..]);
+    command
+}
+
+/// `$PHOENIX_PLUGIN_DIR`, else `plugins` in the state directory.
+pub(crate) fn plugin_dir() -> Result<PathBuf> {
+    match std::env::var("PHOENIX_PLUGIN_DIR") {
+        Ok(dir) => Ok(PathBuf::from(dir)),
+        Err(_) => Ok(crate::ledger::state_dir()?.join("plugins")),
+    }
+}
@@ -0,0 +1,347 @@
+//! Host-wide limits on image writes, so a bench running many flashes at
+//! once keeps headroom for the ones verifying. A write takes one of
+//! `max_concurrent_writes` slots before it starts and holds it through its
+//! verify; the rest queue. `max_write_bytes_per_sec` is split evenly
+//! between the writes holding a slot, and read-backs are not charged.
+//!
+//! Slots are locked files in the `io` state directory, so the limits hold
+//! across every Phoenix process on the host and a crashed process frees
+//! its slot. Each slot and queued write has a JSON file beside its lock
+//! describing it, which `io_status` reads.
+
+use crate::cancel::is_cancelled;
+use crate::ledger::state_dir;
+use crate::StepLog;
+use anyhow::{anyhow, Context, Result};
+use phoenix_core::now_utc_rfc3339;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// How often a queued write looks for a free slot.
+const QUEUE_POLL: Duration = Duration::from_millis(500);
+/// How often a write recounts the writes sharing the bandwidth cap.
+const SHARE_REFRESH: Duration = Duration::from_secs(1);
+
+/// `io_limits.json`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct IoLimits {
+    /// Image writes that may run at once; later ones wait their turn.
+    #[serde(default)]
+    pub max_concurrent_writes: Option<usize>,
+    /// Combined write rate of every image write on the host.
+    #[serde(default, with = "crate::params::byte_size")]
+    pub max_write_bytes_per_sec: Option<u64>,
+}
+
+impl IoLimits {
+    /// The limits at `path`; none when there is no file.
+    pub fn load(path: &Path) -> Result<Self> {
+        let bytes = match fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(err) => return Err(anyhow!("read {} failed: {}", path.display(), err)),
+        };
+        let limits: Self =
+            serde_json::from_slice(&bytes).with_context(|| format!("parse {}", path.display()))?;
+        if limits.max_concurrent_writes == Some(0) {
+            return Err(anyhow!("{}: max_concurrent_writes must be at least 1", path.display()));
+        }
+        if limits.max_write_bytes_per_sec == Some(0) {
+            return Err(anyhow!("{}: max_write_bytes_per_sec must be positive", path.display()));
+        }
+        Ok(limits)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.max_concurrent_writes.is_none() && self.max_write_bytes_per_sec.is_none()
+    }
+}
+
+/// `$PHOENIX_IO_LIMITS`, else `io_limits.json` in the state directory.
+pub fn io_limits_path() -> Result<PathBuf> {
+    match std::env::var("PHOENIX_IO_LIMITS") {
+        Ok(path) => Ok(PathBuf::from(path)),
+        Err(_) => Ok(state_dir()?.join("io_limits.json")),
+    }
+}
+
+pub fn io_limits() -> Result<IoLimits> {
+    IoLimits::load(&io_limits_path()?)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IoPhase {
+    Queued,
+    Writing,
+    Verifying,
+}
+
+impl IoPhase {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Queued => "queued",
+            Self::Writing => "writing",
+            Self::Verifying => "verifying",
+        }
+    }
+}
+
+/// One write holding a slot or queued for one.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IoHolder {
+    pub pid: u32,
+    pub workflow: String,
+    pub target_disk: String,
+    pub source: String,
+    pub phase: IoPhase,
+    pub since_utc: String,
+    /// Slot number; `None` while queued.
+    #[serde(default)]
+    pub slot: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IoStatus {
+    pub limits: IoLimits,
+    pub writes: Vec<IoHolder>,
+    /// Oldest first.
+    pub queued: Vec<IoHolder>,
+}
+
+fn io_dir() -> Result<PathBuf> {
+    Ok(state_dir()?.join("io"))
+}
+
+/// The writes holding a slot and those queued. Writes are only tracked
+/// while limits are set.
+pub fn io_status() -> Result<IoStatus> {
+    let limits = io_limits()?;
+    let dir = io_dir()?;
+    let mut writes = Vec::new();
+    let mut queued = Vec::new();
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            return Ok(IoStatus { limits, writes, queued })
+        }
+        Err(err) => return Err(anyhow!("read {} failed: {}", dir.display(), err)),
+    };
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().and_then(|ext| ext.to_str()) != Some("json") || !is_held(&path) {
+            continue;
+        }
+        // Gone or half written when the write just ended or began.
+        let Ok(holder) = fs::read(&path)
+            .map_err(anyhow::Error::from)
+            .and_then(|bytes| Ok(serde_json::from_slice::<IoHolder>(&bytes)?))
+        else {
+            continue;
+        };
+        match holder.phase {
+            IoPhase::Queued => queued.push(holder),
+            _ => writes.push(holder),
+        }
+    }
+    writes.sort_by_key(|holder| holder.slot);
+    queued.sort_by(|a, b| a.since_utc.cmp(&b.since_utc));
+    Ok(IoStatus { limits, writes, queued })
+}
+
+/// Whether the lock beside the info file at `info` is held by a live
+/// process. Briefly takes the lock when it is free.
+fn is_held(info: &Path) -> bool {
+    match OpenOptions::new().write(true).open(info.with_extension("lock")) {
+        Ok(file) => matches!(file.try_lock(), Err(fs::TryLockError::WouldBlock)),
+        Err(_) => false,
+    }
+}
+
+/// A slot held for one image write; freed on drop.
+pub(crate) struct WriteSlot {
+    /// Held locked for the life of the slot.
+    _lock: File,
+    info_path: PathBuf,
+    holder: IoHolder,
+    dir: PathBuf,
+    bytes_per_sec: Option<u64>,
+    share: u64,
+    share_checked: Option<Instant>,
+    next_free: Option<Instant>,
+}
+
+/// Takes a write slot under the host's limits, queueing while all are
+/// taken; `None` when no limits are set.
+pub(crate) fn acquire_write_slot(
+    logs: &mut StepLog,
+    target_disk: &str,
+    source: &Path,
+) -> Result<Option<WriteSlot>> {
+    let limits = io_limits()?;
+    if limits.is_empty() {
+        return Ok(None);
+    }
+    let dir = io_dir()?;
+    fs::create_dir_all(&dir).with_context(|| format!("create {}", dir.display()))?;
+    let mut holder = IoHolder {
+        pid: std::process::id(),
+        workflow: logs.workflow().to_string(),
+        target_disk: target_disk.to_string(),
+        source: source.display().to_string(),
+        phase: IoPhase::Queued,
+        since_utc: now_utc_rfc3339(),
+        slot: None,
+    };
+    let started = Instant::now();
+    let mut queue_entry: Option<(File, PathBuf)> = None;
+    let (slot, lock) = loop {
+        if let Some(found) = free_slot(&dir, limits.max_concurrent_writes)? {
+            break found;
+        }
+        if queue_entry.is_none() {
+            queue_entry = Some(enter_queue(&dir, &holder)?);
+            logs.push(format!(
+                "io_queued=true max_concurrent_writes={}",
+                limits.max_concurrent_writes.unwrap_or_default()
+            ));
+        }
+        if is_cancelled() {
+            leave_queue(queue_entry);
+            return Err(anyhow!("cancelled while waiting for a write slot"));
+        }
+        std::thread::sleep(QUEUE_POLL);
+    };
+    leave_queue(queue_entry);
+    holder.phase = IoPhase::Writing;
+    holder.slot = Some(slot);
+    holder.since_utc = now_utc_rfc3339();
+    let info_path = dir.join(format!("write-{}.json", slot));
+    write_info(&info_path, &holder)?;
+    logs.push(format!(
+        "io_slot={} io_wait_ms={}",
+        slot,
+        started.elapsed().as_millis()
+    ));
+    if let Some(rate) = limits.max_write_bytes_per_sec {
+        logs.push(format!("io_max_write_bytes_per_sec={}", rate));
+    }
+    Ok(Some(WriteSlot {
+        _lock: lock,
+        info_path,
+        holder,
+        dir,
+        bytes_per_sec: limits.max_write_bytes_per_sec,
+        share: 0,
+        share_checked: None,
+        next_free: None,
+    }))
+}
+
+/// The lowest free slot below `max`, locked.
+fn free_slot(dir: &Path, max: Option<usize>) -> Result<Option<(usize, File)>> {
+    for slot in 0..max.unwrap_or(usize::MAX) {
+        let path = dir.join(format!("write-{}.lock", slot));
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)
+            .with_context(|| format!("open {}", path.display()))?;
+        match file.try_lock() {
+            Ok(()) => return Ok(Some((slot, file))),
+            Err(fs::TryLockError::WouldBlock) => continue,
+            Err(fs::TryLockError::Error(err)) => {
+                return Err(anyhow!("lock {} failed: {}", path.display(), err))
+            }
+        }
+    }
+    Ok(None)
+}
+
+fn enter_queue(dir: &Path, holder: &IoHolder) -> Result<(File, PathBuf)> {
+    use std::sync::atomic::{AtomicU64, Ordering};
+    static SEQUENCE: AtomicU64 = AtomicU64::new(0);
+    let stem = format!("queue-{}-{}", holder.pid, SEQUENCE.fetch_add(1, Ordering::Relaxed));
+    let lock_path = dir.join(format!("{}.lock", stem));
+    let lock = File::create(&lock_path).with_context(|| format!("create {}", lock_path.display()))?;
+    lock.try_lock()
+        .map_err(|err| anyhow!("lock {} failed: {}", lock_path.display(), err))?;
+    let info_path = dir.join(format!("{}.json", stem));
+    write_info(&info_path, holder)?;
+    Ok((lock, info_path))
+}
+
+fn leave_queue(entry: Option<(File, PathBuf)>) {
+    if let Some((lock, info_path)) = entry {
+        let _ = fs::remove_file(&info_path);
+        let _ = fs::remove_file(info_path.with_extension("lock"));
+        drop(lock);
+    }
+}
+
+fn write_info(path: &Path, holder: &IoHolder) -> Result<()> {
+    fs::write(path, serde_json::to_vec(holder)?).with_context(|| format!("write {}", path.display()))
+}
+
+impl WriteSlot {
+    pub(crate) fn throttles(&self) -> bool {
+        self.bytes_per_sec.is_some()
+    }
+
+    /// Charges `bytes` written against this write's share of the cap,
+    /// sleeping until it is back under it.
+    pub(crate) fn throttle(&mut self, bytes: u64) {
+        let Some(rate) = self.bytes_per_sec else {
+            return;
+        };
+        let now = Instant::now();
+        if self
+            .share_checked
+            .is_none_or(|checked| now.duration_since(checked) >= SHARE_REFRESH)
+        {
+            self.share = (rate / active_writes(&self.dir).max(1) as u64).max(1);
+            self.share_checked = Some(now);
+        }
+        let cost = Duration::from_secs_f64(bytes as f64 / self.share as f64);
+        let done = self.next_free.map_or(now, |free| free.max(now)) + cost;
+        self.next_free = Some(done);
+        std::thread::sleep(done.saturating_duration_since(now));
+    }
+
+    /// Marks the write as reading back, for `io_status`.
+    pub(crate) fn verifying(&mut self) {
+        if self.holder.phase != IoPhase::Verifying {
+            self.holder.phase = IoPhase::Verifying;
+            let _ = write_info(&self.info_path, &self.holder);
+        }
+    }
+}
+
+impl Drop for WriteSlot {
+    fn drop(&mut self) {
+        // The lock is released after, so the next holder's info is never
+        // removed.
+        let _ = fs::remove_file(&self.info_path);
+    }
+}
+
+/// Slots currently held on the host, this one included.
+fn active_writes(dir: &Path) -> usize {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return 1;
+    };
+    entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with("write-") && name.ends_with(".json"))
+        })
+        .filter(|path| is_held(path))
+        .count()
+}
@@ -0,0 +1,386 @@
+//! Revive or restore an Apple Silicon Mac held in DFU mode from an IPSW,
+//! through Apple Configurator's `cfgutil` or `idevicerestore`. Neither is
+//! bundled; the one found on `PATH` is run as an external tool.
+
+use crate::cancel::command_output;
+use crate::tools::find_program;
+use crate::{begin_run, report_graph, signing_key_from_env, StepLog};
+use anyhow::{anyhow, Context, Result};
+use phoenix_core::Disk;
+use phoenix_report::{create_report_bundle_with_meta_signing_and_artifacts, ReportPaths};
+use phoenix_safety::{can_write_to_disk, SafetyContext, SafetyDecision};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Instant;
+
+/// ECID of the device `PHOENIX_HOST=mock` pretends is in DFU mode.
+const MOCK_ECID: &str = "0x1A2B3C4D5E6F";
+/// Tool output lines kept in the log.
+const OUTPUT_TAIL_LINES: usize = 40;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RestoreTool {
+    Cfgutil,
+    Idevicerestore,
+}
+
+impl RestoreTool {
+    pub fn parse(value: &str) -> Result<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "cfgutil" => Ok(Self::Cfgutil),
+            "idevicerestore" => Ok(Self::Idevicerestore),
+            other => Err(anyhow!("unknown restore tool: {} (cfgutil, idevicerestore)", other)),
+        }
+    }
+
+    fn program(self) -> &'static str {
+        match self {
+            Self::Cfgutil => "cfgutil",
+            Self::Idevicerestore => "idevicerestore",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RestoreMode {
+    /// Reinstalls firmware and recoveryOS; the data volume is kept.
+    Revive,
+    /// Erases the Mac and installs the IPSW's macOS.
+    Restore,
+}
+
+impl RestoreMode {
+    pub fn parse(value: &str) -> Result<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "revive" => Ok(Self::Revive),
+            "restore" => Ok(Self::Restore),
+            other => Err(anyhow!("unknown restore mode: {} (revive, restore)", other)),
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Revive => "revive",
+            Self::Restore => "restore",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DfuDevice {
+    pub ecid: String,
+    /// Model or board, as the tool reports it.
+    pub product: Option<String>,
+    pub location: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IpswRestoreParams {
+    /// `None` lets the tool download the latest IPSW for the device.
+    #[serde(default)]
+    pub ipsw: Option<PathBuf>,
+    /// Needed when more than one device is in DFU mode.
+    #[serde(default)]
+    pub ecid: Option<String>,
+    pub mode: RestoreMode,
+    /// `None` prefers `cfgutil`, then `idevicerestore`.
+    #[serde(default)]
+    pub tool: Option<RestoreTool>,
+    #[serde(default = "crate::params::default_report_base")]
+    pub report_base: PathBuf,
+    #[serde(default)]
+    pub force: bool,
+    #[serde(default)]
+    pub confirmation_token: Option<String>,
+    #[serde(default = "crate::params::default_true")]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IpswRestoreResult {
+    pub report: ReportPaths,
+    pub device: DfuDevice,
+    pub tool: RestoreTool,
+    pub dry_run: bool,
+}
+
+/// Devices in DFU mode, as seen by `tool`'s listing command.
+pub fn list_dfu_devices(tool: RestoreTool) -> Result<Vec<DfuDevice>> {
+    if phoenix_core::mock::is_active() {
+        return Ok(vec![DfuDevice {
+            ecid: MOCK_ECID.to_string(),
+            product: Some("Mac14,2".to_string()),
+            location: Some("0x00100000".to_string()),
+        }]);
+    }
+    match tool {
+        RestoreTool::Cfgutil => {
+            let output = run_tool(Path::new("cfgutil"), &["--format", "JSON", "list"])?;
+            parse_cfgutil_list(&output)
+        }
+        // idevicerestore has no listing; irecovery ships alongside it.
+        RestoreTool::Idevicerestore => match run_tool(Path::new("irecovery"), &["-q"]) {
+            Ok(output) => Ok(parse_irecovery_query(&output).into_iter().collect()),
+            Err(_) => Ok(Vec::new()),
+        },
+    }
+}
+
+pub fn run_ipsw_restore(params: &IpswRestoreParams) -> Result<IpswRestoreResult> {
+    let started = Instant::now();
+    if let Some(ipsw) = &params.ipsw {
+        if !ipsw.is_file() {
+            return Err(anyhow!("ipsw not found: {}", ipsw.display()));
+        }
+    }
+    let (tool, program) = resolve_tool(params.tool)?;
+    let devices = list_dfu_devices(tool)?;
+    let device = select_device(&devices, params.ecid.as_deref())?;
+    let args = restore_args(tool, params, &device.ecid);
+
+    let mut logs = StepLog::new("ipsw-restore");
+    logs.push(format!("tool={}", program.display()));
+    logs.push(format!("mode={}", params.mode.as_str()));
+    logs.push(format!("ecid={}", device.ecid));
+    if let Some(product) = &device.product {
+        logs.push(format!("product={}", product));
+    }
+    match &params.ipsw {
+        Some(ipsw) => logs.push(format!("ipsw={}", ipsw.display())),
+        None => logs.push("ipsw=latest".to_string()),
+    }
+    logs.push(format!("command={} {}", program.display(), args.join(" ")));
+    logs.push(format!("dry_run={}", params.dry_run));
+
+    let mut run = None;
+    if !params.dry_run {
+        let ctx = SafetyContext {
+            force_mode: params.force,
+            confirmation_token: params.confirmation_token.clone(),
+            allow_system_disk: false,
+            armed_until: phoenix_safety::armed_until(),
+        };
+        match can_write_to_disk(&ctx, false) {
+            SafetyDecision::Allow => {}
+            SafetyDecision::Deny(reason) => return Err(anyhow!(reason)),
+            SafetyDecision::ReadOnly(err) => return Err(err.into()),
+        }
+        let mut tracker = begin_run("ipsw-restore", &device_target(&device), &mut logs)?;
+        tracker.phase("ipsw_restore", params.mode == RestoreMode::Restore)?;
+        logs.phase("ipsw_restore");
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        let output = if phoenix_core::mock::is_active() {
+            phoenix_core::mock::record_command(program.to_string_lossy().as_ref(), &args)
+                .map(|_| String::new())
+                .map_err(anyhow::Error::from)
+        } else {
+            run_tool(&program, &args)
+        };
+        let output = match output {
+            Ok(output) => output,
+            Err(err) => {
+                tracker.fail(&err);
+                return Err(err);
+            }
+        };
+        let lines: Vec<&str> = output.lines().filter(|line| !line.trim().is_empty()).collect();
+        for line in &lines[lines.len().saturating_sub(OUTPUT_TAIL_LINES)..] {
+            logs.push(format!("{}: {}", tool.program(), line.trim()));
+        }
+        run = Some(tracker);
+    }
+
+    let (log_text, timing) = logs.finish()?;
+
+    let meta = serde_json::json!({
+        "workflow": "ipsw-restore",
+        "status": if params.dry_run { "dry_run" } else { "completed" },
+        "tool": tool,
+        "mode": params.mode,
+        "ecid": device.ecid,
+        "product": device.product,
+        "ipsw": params.ipsw.as_ref().map(|ipsw| ipsw.display().to_string()),
+        "dry_run": params.dry_run,
+        "duration_ms": started.elapsed().as_millis() as u64
+    });
+
+    let report = create_report_bundle_with_meta_signing_and_artifacts(
+        &params.report_base,
+        &report_graph(),
+        Some(meta),
+        Some(&log_text),
+        signing_key_from_env().as_deref(),
+        &[timing],
+    )?;
+    if let Some(tracker) = run {
+        tracker.complete(&report.root)?;
+    }
+
+    Ok(IpswRestoreResult {
+        report,
+        device,
+        tool,
+        dry_run: params.dry_run,
+    })
+}
+
+fn resolve_tool(requested: Option<RestoreTool>) -> Result<(RestoreTool, PathBuf)> {
+    let candidates = match requested {
+        Some(tool) => vec![tool],
+        None => vec![RestoreTool::Cfgutil, RestoreTool::Idevicerestore],
+    };
+    for tool in &candidates {
+        if phoenix_core::mock::is_active() {
+            return Ok((*tool, PathBuf::from(tool.program())));
+        }
+        if let Some(path) = find_program(tool.program()) {
+            return Ok((*tool, path));
+        }
+    }
+    let names: Vec<&str> = candidates.iter().map(|tool| tool.program()).collect();
+    Err(anyhow!(
+        "{} not found on PATH; install Apple Configurator's automation tools or idevicerestore",
+        names.join(" or ")
+    ))
+}
+
+fn select_device(devices: &[DfuDevice], ecid: Option<&str>) -> Result<DfuDevice> {
+    match ecid {
+        Some(ecid) => devices
+            .iter()
+            .find(|device| same_ecid(&device.ecid, ecid))
+            .cloned()
+            .ok_or_else(|| anyhow!("no device in DFU mode with ECID {}", ecid)),
+        None => match devices {
+            [] => Err(anyhow!(
+                "no device in DFU mode; connect the Mac's DFU port and hold the key combination"
+            )),
+            [device] => Ok(device.clone()),
+            _ => {
+                let ecids: Vec<&str> = devices.iter().map(|device| device.ecid.as_str()).collect();
+                Err(anyhow!(
+                    "{} devices in DFU mode ({}); pick one with ecid",
+                    devices.len(),
+                    ecids.join(", ")
+                ))
+            }
+        },
+    }
+}
+
+/// ECIDs compare as numbers; tools print them in hex or decimal.
+fn same_ecid(left: &str, right: &str) -> bool {
+    match (parse_ecid(left), parse_ecid(right)) {
+        (Some(left), Some(right)) => left == right,
+        _ => left.eq_ignore_ascii_case(right),
+    }
+}
+
+fn parse_ecid(value: &str) -> Option<u64> {
+    let value = value.trim();
+    match value.strip_prefix("0x").or_else(|| value.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    }
+}
+
+fn restore_args(tool: RestoreTool, params: &IpswRestoreParams, ecid: &str) -> Vec<String> {
+    let mut args = Vec::new();
+    match tool {
+        RestoreTool::Cfgutil => {
+            args.extend(["--ecid".to_string(), ecid.to_string()]);
+            args.push(params.mode.as_str().to_string());
+            if let Some(ipsw) = &params.ipsw {
+                args.extend(["-I".to_string(), ipsw.display().to_string()]);
+            }
+        }
+        RestoreTool::Idevicerestore => {
+            args.extend(["--ecid".to_string(), ecid.to_string()]);
+            if params.mode == RestoreMode::Restore {
+                args.push("--erase".to_string());
+            }
+            match &params.ipsw {
+                Some(ipsw) => args.push(ipsw.display().to_string()),
+                None => args.push("--latest".to_string()),
+            }
+        }
+    }
+    args
+}
+
+/// The ledger tracks disks; a DFU device is recorded under its ECID.
+fn device_target(device: &DfuDevice) -> Disk {
+    Disk {
+        id: format!("dfu-{}", device.ecid),
+        friendly_name: device.product.clone().unwrap_or_else(|| "Mac in DFU mode".to_string()),
+        serial: Some(device.ecid.clone()),
+        size_bytes: 0,
+        removable: true,
+        is_system_disk: false,
+        partitions: Vec::new(),
+        usb_port: None,
+        history: None,
+        booted_esp: None,
+    }
+}
+
+fn run_tool(program: &Path, args: &[&str]) -> Result<String> {
+    let output = command_output(Command::new(program).args(args))
+        .with_context(|| format!("run {}", program.display()))?;
+    let mut text = String::from_utf8_lossy(&output.stdout).to_string();
+    text.push_str(&String::from_utf8_lossy(&output.stderr));
+    if !output.status.success() {
+        let tail: Vec<&str> = text.lines().rev().take(5).collect();
+        return Err(anyhow!(
+            "{} failed ({}): {}",
+            program.display(),
+            output.status,
+            tail.into_iter().rev().collect::<Vec<_>>().join(" | ")
+        ));
+    }
+    Ok(text)
+}
+
+/// `cfgutil --format JSON list`: devices keyed by ECID under `Output`.
+fn parse_cfgutil_list(output: &str) -> Result<Vec<DfuDevice>> {
+    let start = output.find('{').ok_or_else(|| anyhow!("cfgutil list printed no JSON"))?;
+    let value: serde_json::Value =
+        serde_json::from_str(&output[start..]).context("parse cfgutil list output")?;
+    let Some(devices) = value.get("Output").and_then(|output| output.as_object()) else {
+        return Ok(Vec::new());
+    };
+    let field = |device: &serde_json::Value, key: &str| match device.get(key) {
+        Some(serde_json::Value::String(text)) => Some(text.clone()),
+        Some(serde_json::Value::Number(number)) => Some(number.to_string()),
+        _ => None,
+    };
+    Ok(devices
+        .iter()
+        .map(|(key, device)| DfuDevice {
+            ecid: field(device, "ECID").unwrap_or_else(|| key.clone()),
+            product: field(device, "deviceType"),
+            location: field(device, "locationID"),
+        })
+        .collect())
+}
+
+/// `irecovery -q`: `KEY: value` lines; only DFU mode counts.
+fn parse_irecovery_query(output: &str) -> Option<DfuDevice> {
+    let field = |key: &str| {
+        output.lines().find_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            name.trim().eq_ignore_ascii_case(key).then(|| value.trim().to_string())
+        })
+    };
+    if !field("MODE")?.eq_ignore_ascii_case("DFU") {
+        return None;
+    }
+    Some(DfuDevice {
+        ecid: field("ECID")?,
+        product: field("PRODUCT").or_else(|| field("MODEL")),
+        location: None,
+    })
+}
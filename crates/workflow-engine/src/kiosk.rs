@@ -0,0 +1,382 @@
+//! Duplication-station loop: wait for a removable disk to be plugged in,
+//! run a workflow definition against it, wait for it to be pulled, repeat.
+//! Front ends (the CLI, a desktop backend) drive it through
+//! `KioskObserver` and stop it with a `CancelToken`.
+
+use crate::cancel::is_cancelled;
+use crate::steplog::StepLog;
+use crate::watchdog::error_code;
+use crate::{build_device_graph, run_workflow_definition_with_report, signing_key_from_env};
+use anyhow::{anyhow, Result};
+use phoenix_core::{Disk, WorkflowDefinition};
+use phoenix_report::{
+    create_report_bundle_with_meta_signing_and_artifacts, ReportArtifact, ReportPaths,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+pub const KIOSK_RUNS_FILE: &str = "kiosk_runs.json";
+
+/// How a newly plugged-in disk is confirmed before the workflow runs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KioskConfirm {
+    /// Ask the observer for every disk.
+    #[default]
+    Operator,
+    /// Run on every disk that passes the policy.
+    Auto,
+}
+
+impl KioskConfirm {
+    pub fn parse(value: &str) -> Result<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "operator" => Ok(Self::Operator),
+            "auto" => Ok(Self::Auto),
+            other => Err(anyhow!(
+                "unknown confirmation policy {} (expected operator or auto)",
+                other
+            )),
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Operator => "operator",
+            Self::Auto => "auto",
+        }
+    }
+}
+
+/// Which disks the loop takes. Disks outside it are reported as skipped
+/// and left alone until they are pulled.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct KioskPolicy {
+    #[serde(default)]
+    pub confirm: KioskConfirm,
+    #[serde(default)]
+    pub min_size_bytes: Option<u64>,
+    #[serde(default)]
+    pub max_size_bytes: Option<u64>,
+    /// USB port labels or paths to serve; empty means any port.
+    #[serde(default)]
+    pub ports: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KioskParams {
+    /// Run once per disk. String step params may use `{target_disk}`,
+    /// `{target_device}`, `{target_mount}`, `{target_serial}` and
+    /// `{target_port}`.
+    pub definition: WorkflowDefinition,
+    #[serde(default = "crate::params::default_report_base")]
+    pub report_base: PathBuf,
+    #[serde(default)]
+    pub policy: KioskPolicy,
+    #[serde(rename = "poll_interval_ms", with = "crate::params::millis")]
+    pub poll_interval: Duration,
+    /// Stop after this many runs; `None` runs until cancelled.
+    #[serde(default)]
+    pub max_runs: Option<usize>,
+}
+
+/// One workflow run on one disk, by the kiosk loop or `duplicate_to_all`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StationRun {
+    pub disk_id: String,
+    pub serial: Option<String>,
+    pub port: Option<String>,
+    pub report_root: Option<PathBuf>,
+    /// `None` when the workflow completed.
+    pub error: Option<String>,
+    /// `workflow_timeout` when the run or a step ran out of time.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_code: Option<String>,
+    pub duration_ms: u128,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KioskResult {
+    /// Session report, listing every run.
+    pub report: ReportPaths,
+    pub runs: Vec<StationRun>,
+    pub cancelled: bool,
+}
+
+/// Status changes, in loop order, for the front end to show.
+#[derive(Debug)]
+pub enum KioskEvent<'a> {
+    /// Waiting for a disk; `known` were present at start or are not served.
+    Waiting { known: usize },
+    Skipped { disk: &'a Disk, reason: String },
+    Declined { disk: &'a Disk },
+    Running { disk: &'a Disk },
+    Finished { run: &'a StationRun },
+    /// The run is over; waiting for the disk to be pulled.
+    AwaitingRemoval { disk: &'a Disk },
+    Removed { disk_id: &'a str },
+}
+
+pub trait KioskObserver {
+    fn event(&mut self, event: &KioskEvent);
+
+    /// Asked for each disk under `KioskConfirm::Operator`.
+    fn confirm(&mut self, disk: &Disk) -> bool;
+}
+
+/// Disks present when the loop starts are never touched; only disks
+/// plugged in afterwards are candidates. Returns once `max_runs` runs are
+/// done or the thread's cancel token fires, after writing a session
+/// report. A failed run is recorded and the loop goes on.
+pub fn run_kiosk(params: &KioskParams, observer: &mut dyn KioskObserver) -> Result<KioskResult> {
+    let mut logs = StepLog::new("kiosk");
+    logs.push(format!("definition={}", params.definition.name));
+    logs.push(format!("confirm={}", params.policy.confirm.as_str()));
+    let mut known: BTreeSet<String> = present_disks()?
+        .into_iter()
+        .map(|disk| disk.id)
+        .collect();
+    logs.push(format!("present_at_start={}", known.len()));
+    let mut runs = Vec::new();
+    let mut cancelled = false;
+    observer.event(&KioskEvent::Waiting { known: known.len() });
+
+    loop {
+        if params.max_runs.is_some_and(|max| runs.len() >= max) {
+            break;
+        }
+        if is_cancelled() {
+            cancelled = true;
+            break;
+        }
+        let disks = present_disks()?;
+        // Forget pulled disks, so plugging one back in counts as new.
+        for id in known.clone() {
+            if !disks.iter().any(|disk| disk.id == id) {
+                known.remove(&id);
+                logs.push(format!("removed={}", id));
+                observer.event(&KioskEvent::Removed { disk_id: &id });
+            }
+        }
+        let Some(disk) = disks.into_iter().find(|disk| !known.contains(&disk.id)) else {
+            std::thread::sleep(params.poll_interval);
+            continue;
+        };
+        known.insert(disk.id.clone());
+        logs.push(format!("inserted={}", disk.id));
+
+        if let Some(reason) = policy_refusal(&params.policy, &disk) {
+            logs.push(format!("skipped={} reason={}", disk.id, reason));
+            observer.event(&KioskEvent::Skipped { disk: &disk, reason });
+            continue;
+        }
+        if params.policy.confirm == KioskConfirm::Operator && !observer.confirm(&disk) {
+            logs.push(format!("declined={}", disk.id));
+            observer.event(&KioskEvent::Declined { disk: &disk });
+            continue;
+        }
+
+        observer.event(&KioskEvent::Running { disk: &disk });
+        logs.push(format!("run={}", disk.id));
+        let run = run_on_disk(&params.definition, &params.report_base, &disk);
+        match &run.error {
+            None => logs.push(format!("completed={} duration_ms={}", disk.id, run.duration_ms)),
+            Some(err) => logs.push(format!("failed={} error={}", disk.id, err)),
+        }
+        observer.event(&KioskEvent::Finished { run: &run });
+        runs.push(run);
+
+        observer.event(&KioskEvent::AwaitingRemoval { disk: &disk });
+        while present_disks()?.iter().any(|present| present.id == disk.id) {
+            if is_cancelled() {
+                break;
+            }
+            std::thread::sleep(params.poll_interval);
+        }
+        if !is_cancelled() {
+            known.remove(&disk.id);
+            logs.push(format!("removed={}", disk.id));
+            observer.event(&KioskEvent::Removed { disk_id: &disk.id });
+            observer.event(&KioskEvent::Waiting { known: known.len() });
+        }
+    }
+    if cancelled {
+        logs.push("cancelled=true");
+    }
+
+    let failed = runs.iter().filter(|run| run.error.is_some()).count();
+    let runs_artifact = ReportArtifact::json(KIOSK_RUNS_FILE, &runs)?;
+    let (log_text, timing) = logs.finish()?;
+    let meta = serde_json::json!({
+        "workflow": "kiosk",
+        "status": if failed == 0 { "completed" } else { "failed" },
+        "definition": params.definition.name,
+        "confirm": params.policy.confirm.as_str(),
+        "runs": runs.len(),
+        "failed_runs": failed,
+        "cancelled": cancelled,
+        "artifacts": [&runs_artifact.name, &timing.name]
+    });
+    let graph = build_device_graph()?;
+    let report = create_report_bundle_with_meta_signing_and_artifacts(
+        &params.report_base,
+        &graph,
+        Some(meta),
+        Some(&log_text),
+        signing_key_from_env().as_deref(),
+        &[runs_artifact, timing],
+    )?;
+    Ok(KioskResult {
+        report,
+        runs,
+        cancelled,
+    })
+}
+
+/// Removable, non-system disks: the only ones a station may ever take.
+pub(crate) fn present_disks() -> Result<Vec<Disk>> {
+    Ok(build_device_graph()?
+        .disks
+        .into_iter()
+        .filter(|disk| disk.removable && !disk.is_system_disk)
+        .collect())
+}
+
+fn policy_refusal(policy: &KioskPolicy, disk: &Disk) -> Option<String> {
+    if let Some(min) = policy.min_size_bytes.filter(|min| disk.size_bytes < *min) {
+        return Some(format!("{} bytes is below the {} byte minimum", disk.size_bytes, min));
+    }
+    if let Some(max) = policy.max_size_bytes.filter(|max| disk.size_bytes > *max) {
+        return Some(format!("{} bytes is above the {} byte maximum", disk.size_bytes, max));
+    }
+    if !on_served_port(&policy.ports, disk) {
+        return Some("not plugged into a served USB port".to_string());
+    }
+    None
+}
+
+/// True when `ports` is empty or names the disk's USB port by label or
+/// path.
+pub(crate) fn on_served_port(ports: &[String], disk: &Disk) -> bool {
+    ports.is_empty()
+        || disk.usb_port.as_ref().is_some_and(|port| {
+            ports.iter().any(|wanted| {
+                *wanted == port.path
+                    || port
+                        .label
+                        .as_deref()
+                        .is_some_and(|label| label.eq_ignore_ascii_case(wanted))
+            })
+        })
+}
+
+/// Label of the disk's USB port, else its path.
+pub(crate) fn port_name(disk: &Disk) -> Option<String> {
+    disk.usb_port
+        .as_ref()
+        .map(|port| port.label.clone().unwrap_or_else(|| port.path.clone()))
+}
+
+/// Runs `definition` bound to `disk`. Errors are recorded in the run,
+/// not returned.
+pub(crate) fn run_on_disk(
+    definition: &WorkflowDefinition,
+    report_base: &Path,
+    disk: &Disk,
+) -> StationRun {
+    let start = std::time::Instant::now();
+    let port = port_name(disk);
+    let result = bind_definition(definition, disk, port.as_deref()).and_then(|definition| {
+        run_workflow_definition_with_report(&definition, report_base.to_path_buf())
+    });
+    let (report_root, error, error_code) = match result {
+        Ok(result) => (Some(result.report.root), None, None),
+        Err(err) => (None, Some(format!("{:#}", err)), error_code(&err).map(str::to_string)),
+    };
+    StationRun {
+        disk_id: disk.id.clone(),
+        serial: disk.serial.clone(),
+        port,
+        report_root,
+        error,
+        error_code,
+        duration_ms: start.elapsed().as_millis(),
+    }
+}
+
+/// The definition with the `{target_*}` placeholders filled in for `disk`,
+/// including in `idempotency_key` and `correlation_id`.
+pub(crate) fn bind_definition(
+    definition: &WorkflowDefinition,
+    disk: &Disk,
+    port: Option<&str>,
+) -> Result<WorkflowDefinition> {
+    let mount = disk
+        .partitions
+        .iter()
+        .flat_map(|partition| partition.mount_points.iter())
+        .next()
+        .cloned();
+    let values = [
+        ("{target_disk}", Some(disk.id.clone())),
+        ("{target_device}", Some(device_path(disk)?)),
+        ("{target_mount}", mount),
+        ("{target_serial}", disk.serial.clone()),
+        ("{target_port}", port.map(str::to_string)),
+    ];
+    let bind = |text: &str| -> Result<String> {
+        let mut text = text.to_string();
+        for (placeholder, value) in &values {
+            if !text.contains(placeholder) {
+                continue;
+            }
+            let value = value.as_deref().ok_or_else(|| {
+                anyhow!("{} has no value for {} on {}", definition.name, placeholder, disk.id)
+            })?;
+            text = text.replace(placeholder, value);
+        }
+        Ok(text)
+    };
+    let mut bound = definition.clone();
+    for step in &mut bound.steps {
+        bind_value(&mut step.params, &bind)?;
+    }
+    bound.idempotency_key = bound.idempotency_key.as_deref().map(bind).transpose()?;
+    bound.correlation_id = bound.correlation_id.as_deref().map(bind).transpose()?;
+    Ok(bound)
+}
+
+fn bind_value(value: &mut Value, bind: &dyn Fn(&str) -> Result<String>) -> Result<()> {
+    match value {
+        Value::String(text) => *text = bind(text)?,
+        Value::Array(items) => {
+            for item in items {
+                bind_value(item, bind)?;
+            }
+        }
+        Value::Object(map) => {
+            for item in map.values_mut() {
+                bind_value(item, bind)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn device_path(disk: &Disk) -> Result<String> {
+    #[cfg(target_os = "windows")]
+    {
+        Ok(format!(r"\\.\{}", disk.id))
+    }
+    #[cfg(not(target_os = "windows"))]
+    {
+        if phoenix_core::mock::is_active() {
+            return Ok(phoenix_core::mock::disk_file(disk)?.display().to_string());
+        }
+        Ok(format!("/dev/{}", disk.id))
+    }
+}
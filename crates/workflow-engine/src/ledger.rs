@@ -0,0 +1,564 @@
+use anyhow::{anyhow, Context, Result};
+use phoenix_core::Disk;
+use phoenix_notify::{NotifyConfig, RunEvent, RunOutcome};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RunStatus {
+    Running,
+    Completed,
+    Failed,
+    /// Interrupted run an operator has looked at and dismissed.
+    Dismissed,
+}
+
+/// One workflow run as persisted in the ledger. The record is rewritten
+/// before every phase, so after a crash it names the phase that was in
+/// flight.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunRecord {
+    pub run_id: String,
+    pub workflow: String,
+    pub target_disk: String,
+    pub target_name: String,
+    pub target_serial: Option<String>,
+    /// USB port label, else port path, of the target.
+    #[serde(default)]
+    pub target_port: Option<String>,
+    pub target_size_bytes: u64,
+    pub phase: String,
+    /// True once any destructive phase (partition, format, raw write) began.
+    pub destructive: bool,
+    pub status: RunStatus,
+    pub pid: u32,
+    pub started_unix: u64,
+    pub updated_unix: u64,
+    pub phases: Vec<String>,
+    pub error: Option<String>,
+    #[serde(default)]
+    pub report_root: Option<String>,
+    #[serde(default)]
+    pub correlation_id: Option<String>,
+    /// Notification channels that could not be reached when the run ended.
+    #[serde(default)]
+    pub notify_errors: Vec<String>,
+    /// Bytes last synced to the target, for runs with `flush_every_bytes`.
+    #[serde(default)]
+    pub durable_bytes: Option<u64>,
+    /// Raw image write speed, for the device registry's wear tracking.
+    #[serde(default)]
+    pub write_bytes_per_sec: Option<u64>,
+}
+
+/// A completed destructive step, keyed by the idempotency key it ran under.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IdempotencyRecord {
+    pub key: String,
+    pub action: String,
+    pub target_disk: String,
+    pub target_serial: Option<String>,
+    pub report_root: String,
+    pub completed_unix: u64,
+}
+
+/// Directory of `<run_id>.json` records.
+pub struct RunLedger {
+    dir: PathBuf,
+}
+
+impl RunLedger {
+    pub fn open(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// `$PHOENIX_STATE_DIR/runs`, else the platform state directory.
+    pub fn open_default() -> Result<Self> {
+        Ok(Self::open(state_dir()?.join("runs")))
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Records a new run against `disk` and returns its tracker.
+    pub fn begin(&self, workflow: &str, disk: &Disk) -> Result<RunTracker> {
+        fs::create_dir_all(&self.dir)
+            .with_context(|| format!("create run ledger {}", self.dir.display()))?;
+        let now = now_unix();
+        let pid = std::process::id();
+        let record = RunRecord {
+            // The disk keeps ids unique when one process runs the same
+            // workflow on several disks at once.
+            run_id: format!("{}-{}-{}-{}", workflow, now, pid, file_safe(&disk.id)),
+            workflow: workflow.to_string(),
+            target_disk: disk.id.clone(),
+            target_name: disk.friendly_name.clone(),
+            target_serial: disk.serial.clone(),
+            target_port: disk
+                .usb_port
+                .as_ref()
+                .map(|port| port.label.clone().unwrap_or_else(|| port.path.clone())),
+            target_size_bytes: disk.size_bytes,
+            phase: "start".to_string(),
+            destructive: false,
+            status: RunStatus::Running,
+            pid,
+            started_unix: now,
+            updated_unix: now,
+            phases: vec!["start".to_string()],
+            error: None,
+            report_root: None,
+            correlation_id: phoenix_report::correlation_id(),
+            notify_errors: Vec::new(),
+            durable_bytes: None,
+            write_bytes_per_sec: None,
+        };
+        let mut tracker = RunTracker {
+            path: self.dir.join(format!("{}.json", record.run_id)),
+            record,
+            finished: false,
+        };
+        tracker.save()?;
+        if let Err(err) = log_started(&tracker.record) {
+            tracker.record.notify_errors.push(format!("system_log: {:#}", err));
+            tracker.save()?;
+        }
+        Ok(tracker)
+    }
+
+    pub fn list(&self) -> Result<Vec<RunRecord>> {
+        let mut records = Vec::new();
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(records),
+            Err(err) => return Err(anyhow!("read {} failed: {}", self.dir.display(), err)),
+        };
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some("json") {
+                continue;
+            }
+            let bytes = fs::read(&path).with_context(|| format!("read {}", path.display()))?;
+            let record: RunRecord = serde_json::from_slice(&bytes)
+                .with_context(|| format!("parse {}", path.display()))?;
+            records.push(record);
+        }
+        records.sort_by_key(|record| record.started_unix);
+        Ok(records)
+    }
+
+    /// Runs still marked running whose process is gone.
+    pub fn interrupted(&self) -> Result<Vec<RunRecord>> {
+        Ok(self
+            .list()?
+            .into_iter()
+            .filter(|record| record.status == RunStatus::Running && !process_alive(record.pid))
+            .collect())
+    }
+
+    pub fn find_idempotent(&self, key: &str) -> Result<Option<IdempotencyRecord>> {
+        let path = self.idempotency_path(key);
+        let bytes = match fs::read(&path) {
+            Ok(bytes) => bytes,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(anyhow!("read {} failed: {}", path.display(), err)),
+        };
+        let record = serde_json::from_slice(&bytes)
+            .with_context(|| format!("parse {}", path.display()))?;
+        Ok(Some(record))
+    }
+
+    pub fn record_idempotent(&self, record: &IdempotencyRecord) -> Result<()> {
+        let path = self.idempotency_path(&record.key);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("create {}", parent.display()))?;
+        }
+        write_record(&path, record)
+    }
+
+    /// Keys are caller supplied, so they are hashed into the file name.
+    fn idempotency_path(&self, key: &str) -> PathBuf {
+        let digest = Sha256::digest(key.as_bytes());
+        let name: String = digest.iter().map(|byte| format!("{:02x}", byte)).collect();
+        self.dir.join("idempotency").join(format!("{}.json", name))
+    }
+
+    pub fn dismiss(&self, run_id: &str) -> Result<RunRecord> {
+        let path = self.dir.join(format!("{}.json", run_id));
+        let bytes = fs::read(&path).with_context(|| format!("run {} not found", run_id))?;
+        let mut record: RunRecord = serde_json::from_slice(&bytes)?;
+        if record.status == RunStatus::Running && process_alive(record.pid) {
+            return Err(anyhow!("run {} is still in progress (pid {})", run_id, record.pid));
+        }
+        record.status = RunStatus::Dismissed;
+        record.updated_unix = now_unix();
+        write_record(&path, &record)?;
+        Ok(record)
+    }
+}
+
+/// Live handle for a run. Dropping it without `complete` marks the run
+/// failed, so only a killed process leaves a record in `running`.
+pub struct RunTracker {
+    path: PathBuf,
+    record: RunRecord,
+    finished: bool,
+}
+
+impl RunTracker {
+    pub fn run_id(&self) -> &str {
+        &self.record.run_id
+    }
+
+    /// Persists `phase` before it starts. Destructive phases must not start
+    /// if this fails.
+    pub fn phase(&mut self, phase: &str, destructive: bool) -> Result<()> {
+        self.record.phase = phase.to_string();
+        self.record.destructive |= destructive;
+        self.record.phases.push(phase.to_string());
+        self.record.updated_unix = now_unix();
+        self.save()
+    }
+
+    /// Records how many bytes are synced to the target. Only a change is
+    /// written, so this can be called on every progress update. Best
+    /// effort: a failed save keeps the previous checkpoint.
+    pub fn checkpoint(&mut self, durable_bytes: u64) {
+        if durable_bytes == 0 || self.record.durable_bytes == Some(durable_bytes) {
+            return;
+        }
+        self.record.durable_bytes = Some(durable_bytes);
+        self.record.updated_unix = now_unix();
+        let _ = self.save();
+    }
+
+    /// Records the speed of a raw image write; saved when the run ends.
+    pub fn write_throughput(&mut self, bytes_per_sec: u64) {
+        self.record.write_bytes_per_sec = Some(bytes_per_sec);
+    }
+
+    /// Marks the run completed once its report bundle exists.
+    pub fn complete(mut self, report_root: &Path) -> Result<()> {
+        self.record.report_root = Some(report_root.display().to_string());
+        self.finish(RunStatus::Completed)
+    }
+
+    pub fn fail(mut self, error: &anyhow::Error) {
+        self.record.error = Some(phoenix_report::redact_secrets(&format!("{:#}", error)));
+        self.finish(RunStatus::Failed).ok();
+    }
+
+    fn finish(&mut self, status: RunStatus) -> Result<()> {
+        self.finished = true;
+        self.record.status = status;
+        self.record.updated_unix = now_unix();
+        if status == RunStatus::Completed && self.record.destructive {
+            record_flash(&self.record);
+        }
+        self.record.notify_errors = notify_finished(&self.record);
+        self.save()
+    }
+
+    fn save(&self) -> Result<()> {
+        write_record(&self.path, &self.record)
+    }
+}
+
+impl Drop for RunTracker {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.finish(RunStatus::Failed).ok();
+        }
+    }
+}
+
+/// Counts the run in the device registry. Best effort, like notifications.
+fn record_flash(record: &RunRecord) {
+    if let Ok(registry) = crate::registry::DeviceRegistry::open_default() {
+        let _ = registry.record_flash(
+            record.target_serial.as_deref(),
+            &record.target_name,
+            record.target_size_bytes,
+            &record.workflow,
+            record.write_bytes_per_sec,
+        );
+    }
+}
+
+/// Fires the configured notification channels for a finished run and
+/// returns the deliveries that failed. Notifications never fail the run.
+fn notify_finished(record: &RunRecord) -> Vec<String> {
+    let config = match notify_config() {
+        Ok(config) if !config.is_empty() => config,
+        Ok(_) => return Vec::new(),
+        Err(err) => return vec![format!("{:#}", err)],
+    };
+    let event = RunEvent {
+        run_id: record.run_id.clone(),
+        workflow: record.workflow.clone(),
+        status: match record.status {
+            RunStatus::Completed => RunOutcome::Completed,
+            _ => RunOutcome::Failed,
+        },
+        target_disk: record.target_disk.clone(),
+        target_serial: record.target_serial.clone(),
+        target_port: record.target_port.clone(),
+        target_device: target_device(&record.target_disk),
+        phase: record.phase.clone(),
+        report: record
+            .report_root
+            .as_ref()
+            .map(|root| config.report_link(Path::new(root))),
+        report_root: record.report_root.as_ref().map(PathBuf::from),
+        error: record.error.clone(),
+        duration_secs: record.updated_unix.saturating_sub(record.started_unix),
+        correlation_id: record.correlation_id.clone(),
+    };
+    phoenix_notify::notify(&config, &event)
+        .into_iter()
+        .filter_map(|delivery| {
+            delivery
+                .error
+                .map(|err| format!("{}: {}", delivery.channel, err))
+        })
+        .collect()
+}
+
+/// Raw device of a ledger target, for the LED blink. None under the mock
+/// host, whose disks are files.
+fn target_device(disk_id: &str) -> Option<PathBuf> {
+    if phoenix_core::mock::is_active() {
+        return None;
+    }
+    #[cfg(windows)]
+    let path = format!(r"\\.\{}", disk_id);
+    #[cfg(target_os = "macos")]
+    let path = format!("/dev/r{}", disk_id);
+    #[cfg(not(any(windows, target_os = "macos")))]
+    let path = format!("/dev/{}", disk_id);
+    Some(PathBuf::from(path))
+}
+
+fn log_started(record: &RunRecord) -> Result<()> {
+    if !notify_config()?.system_log {
+        return Ok(());
+    }
+    phoenix_notify::log_run_started(
+        &record.run_id,
+        &record.workflow,
+        &record.target_disk,
+        record.target_serial.as_deref(),
+        record.correlation_id.as_deref(),
+    )
+}
+
+/// `$PHOENIX_NOTIFY_CONFIG`, else `notify.json` in the state directory.
+pub fn notify_config_path() -> Result<PathBuf> {
+    match std::env::var("PHOENIX_NOTIFY_CONFIG") {
+        Ok(path) => Ok(PathBuf::from(path)),
+        Err(_) => Ok(state_dir()?.join("notify.json")),
+    }
+}
+
+pub fn notify_config() -> Result<NotifyConfig> {
+    NotifyConfig::load(&notify_config_path()?)
+}
+
+/// `$PHOENIX_NETWORK_CONFIG`, else `network.json` in the state directory.
+pub fn network_config_path() -> Result<PathBuf> {
+    match std::env::var("PHOENIX_NETWORK_CONFIG") {
+        Ok(path) => Ok(PathBuf::from(path)),
+        Err(_) => Ok(state_dir()?.join("network.json")),
+    }
+}
+
+pub fn network_config() -> Result<phoenix_fetch::NetworkConfig> {
+    phoenix_fetch::NetworkConfig::load(&network_config_path()?)
+}
+
+/// `$PHOENIX_USB_PORT_LABELS`, else `usb_ports.json` in the state
+/// directory.
+pub fn usb_port_labels_path() -> Result<PathBuf> {
+    match std::env::var("PHOENIX_USB_PORT_LABELS") {
+        Ok(path) => Ok(PathBuf::from(path)),
+        Err(_) => Ok(state_dir()?.join("usb_ports.json")),
+    }
+}
+
+/// A map from USB port path to a label such as `front-left`. A missing
+/// file means no labels.
+pub fn usb_port_labels() -> Result<BTreeMap<String, String>> {
+    let path = usb_port_labels_path()?;
+    let bytes = match fs::read(&path) {
+        Ok(bytes) => bytes,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(BTreeMap::new()),
+        Err(err) => return Err(anyhow!("read {} failed: {}", path.display(), err)),
+    };
+    serde_json::from_slice(&bytes).with_context(|| format!("parse {}", path.display()))
+}
+
+/// Operator guidance for an interrupted run, based on the phase it died in.
+pub fn recovery_guidance(record: &RunRecord) -> Vec<String> {
+    let mut steps = Vec::new();
+    let target = match &record.target_serial {
+        Some(serial) => format!("{} (serial {})", record.target_disk, serial),
+        None => record.target_disk.clone(),
+    };
+    match record.phase.as_str() {
+        "partition" | "format" => {
+            steps.push(format!(
+                "{} may have a half-written partition table or filesystem; do not trust its contents",
+                target
+            ));
+            steps.push(format!(
+                "re-run {} with repartition/format to rebuild the layout",
+                record.workflow
+            ));
+        }
+        "write_image" => {
+            steps.push(format!("{} holds a partial image and will not boot", target));
+            if let Some(bytes) = record.durable_bytes {
+                steps.push(format!("the first {} bytes of the image were synced to it", bytes));
+            }
+            steps.push(format!("re-run {} to write the full image", record.workflow));
+        }
+        "copy" | "driver_copy" | "verify" => {
+            steps.push(format!("{} has an incomplete copy", target));
+            if let Some(bytes) = record.durable_bytes {
+                steps.push(format!("{} bytes of copied files were synced to it", bytes));
+            }
+            steps.push(format!(
+                "re-run {}; existing files are overwritten and the copy is verified",
+                record.workflow
+            ));
+        }
+        "installer" => {
+            steps.push(format!("installer creation on {} did not finish", target));
+            steps.push(format!("re-run {} to erase and recreate the volume", record.workflow));
+        }
+        "ipsw_restore" => {
+            steps.push(format!(
+                "{} may be left without working firmware; it will not start normally",
+                target
+            ));
+            steps.push(format!(
+                "put it back into DFU mode and re-run {} (revive keeps the data, restore erases it)",
+                record.workflow
+            ));
+        }
+        "erase_install" => {
+            steps.push(format!(
+                "startosinstall was erasing {}; if the Mac restarted into the installer, let it finish",
+                target
+            ));
+            steps.push(format!(
+                "otherwise start macOS Recovery and reinstall, or re-run {} from another volume",
+                record.workflow
+            ));
+        }
+        _ => {
+            steps.push(format!(
+                "interrupted before any destructive phase; {} should be unchanged",
+                target
+            ));
+        }
+    }
+    if record.destructive {
+        steps.push("confirm the disk serial before re-running; device ids can change after a replug".to_string());
+    }
+    steps.push(format!("dismiss with: phoenix-cli runs-recover --dismiss {}", record.run_id));
+    steps
+}
+
+fn file_safe(id: &str) -> String {
+    id.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+pub(crate) fn write_record<T: Serialize>(path: &Path, record: &T) -> Result<()> {
+    let tmp = path.with_extension("json.tmp");
+    let bytes = serde_json::to_vec_pretty(record)?;
+    let mut file = fs::File::create(&tmp).with_context(|| format!("create {}", tmp.display()))?;
+    file.write_all(&bytes)?;
+    file.sync_all()?;
+    fs::rename(&tmp, path).with_context(|| format!("write {}", path.display()))?;
+    Ok(())
+}
+
+pub(crate) fn state_dir() -> Result<PathBuf> {
+    if let Ok(dir) = std::env::var("PHOENIX_STATE_DIR") {
+        return Ok(PathBuf::from(dir));
+    }
+    #[cfg(windows)]
+    {
+        let base = std::env::var("LOCALAPPDATA").map_err(|_| anyhow!("LOCALAPPDATA not set"))?;
+        Ok(PathBuf::from(base).join("Phoenix"))
+    }
+    #[cfg(target_os = "macos")]
+    {
+        let home = std::env::var("HOME").map_err(|_| anyhow!("HOME not set"))?;
+        Ok(PathBuf::from(home).join("Library/Application Support/Phoenix"))
+    }
+    #[cfg(not(any(windows, target_os = "macos")))]
+    {
+        if let Ok(state) = std::env::var("XDG_STATE_HOME") {
+            return Ok(PathBuf::from(state).join("phoenix"));
+        }
+        let home = std::env::var("HOME").map_err(|_| anyhow!("HOME not set"))?;
+        Ok(PathBuf::from(home).join(".local/state/phoenix"))
+    }
+}
+
+#[cfg(unix)]
+pub(crate) fn process_alive(pid: u32) -> bool {
+    if pid == std::process::id() {
+        return true;
+    }
+    let rc = unsafe { libc::kill(pid as libc::pid_t, 0) };
+    rc == 0 || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+#[cfg(windows)]
+pub(crate) fn process_alive(pid: u32) -> bool {
+    use windows::Win32::Foundation::{CloseHandle, ERROR_ACCESS_DENIED, STILL_ACTIVE};
+    use windows::Win32::System::Threading::{
+        GetExitCodeProcess, OpenProcess, PROCESS_QUERY_LIMITED_INFORMATION,
+    };
+    if pid == std::process::id() {
+        return true;
+    }
+    unsafe {
+        let handle = match OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, false, pid) {
+            Ok(handle) => handle,
+            // It exists, but belongs to someone we may not query.
+            Err(err) => return err.code() == ERROR_ACCESS_DENIED.to_hresult(),
+        };
+        // An exited process still opens while anything holds a handle to
+        // it; only a running one reports STILL_ACTIVE.
+        let mut code = 0u32;
+        let queried = GetExitCodeProcess(handle, &mut code);
+        let _ = CloseHandle(handle);
+        queried.is_ok() && code == STILL_ACTIVE.0 as u32
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+pub(crate) fn process_alive(pid: u32) -> bool {
+    pid == std::process::id()
+}
+
+pub(crate) fn now_unix() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
@@ -1,25 +1,232 @@
 use anyhow::{anyhow, Context, Result};
 use phoenix_report::{
-    create_report_bundle_with_meta_and_signing, create_report_bundle_with_meta_signing_and_artifacts,
-    ReportArtifact, ReportPaths,
+    create_report_bundle_with_meta_signing_and_artifacts, ReportArtifact, ReportPaths,
 };
-use phoenix_safety::{can_write_to_disk, SafetyContext, SafetyDecision};
-use phoenix_content::{prepare_source, resolve_windows_image, SourceKind};
+use phoenix_safety::{
+    can_write_to_disk, check_device_wear, check_target_size, SafetyContext, SafetyDecision,
+    TargetSizeDecision, TargetSizeLimits, WearDecision, WearLimits,
+};
+use phoenix_content::{load_pack_manifest, prepare_source, resolve_windows_image};
 use phoenix_host_windows::format::{format_existing_volume, prepare_usb_disk, FileSystem};
 use phoenix_host_windows::space::free_space_bytes;
-use phoenix_imaging::{
-    hash_device_readonly, hash_disk_readonly_physicaldrive, make_chunk_plan,
-    write_image_to_device,
-};
+use phoenix_imaging::{tune_chunk_size, WriteObserver, WriteProgress, DEFAULT_CHUNK_SIZE};
+#[cfg(not(target_os = "windows"))]
+use phoenix_imaging::hash_device_range_readonly;
+#[cfg(target_os = "windows")]
+use phoenix_imaging::hash_disk_range_readonly_physicaldrive;
+use phoenix_efivars::BootPosition;
 use phoenix_wim::{apply_image as wim_apply_image, list_images as wim_list_images};
-use phoenix_core::{DeviceGraph, WorkflowDefinition, WORKFLOW_SCHEMA_VERSION};
-use phoenix_fs_fat32::format_fat32;
+use phoenix_core::{DeviceGraph, WorkflowDefinition, WorkflowStep};
+use phoenix_workflow_plan::value::{
+    optional_bool, optional_chunk_size, optional_size, optional_string, optional_string_list,
+    require_string,
+};
+use phoenix_workflow_plan::{action_os, step_capabilities};
+use phoenix_hashmap::{ChunkHashMap, HASHMAP_FILE_NAME, HASHMAP_SCHEMA_VERSION};
+use phoenix_partition::plan::{MBR_TYPE_FAT32_LBA, MBR_TYPE_NTFS_EXFAT};
+use phoenix_partition::{
+    parse_size, plan_partitions, GptAttributes, PartitionSpec, DEFAULT_SECTOR_SIZE,
+};
+use phoenix_bootloader_core::mok::plan_mok_enrollment;
 use phoenix_bootloader_core::validate_bootloader_package;
+use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 use std::time::Instant;
 use std::fs;
+use copy_errors::CopyErrors;
+use dedupe::{Deduper, DEDUPE_MAP_FILE};
+use transform::Transforms;
+use io_limits::WriteSlot;
+use verify_policy::{apply_verify, log_verify, resolve_verify};
 use std::path::{Path, PathBuf};
 
+pub mod ab_stick;
+pub mod agent;
+pub mod assets;
+pub mod audit;
+pub mod audit_log;
+pub mod bad_blocks;
+pub mod baseline;
+pub mod boot_entry;
+pub mod boot_lint;
+pub mod cancel;
+pub mod capacity;
+pub mod capabilities;
+pub mod catalog;
+pub mod cleanup;
+pub mod clone_device;
+pub mod copy_errors;
+pub mod combo;
+pub mod dedupe;
+pub mod destruction;
+pub mod diagnostics;
+pub mod doctor;
+pub mod duplicate;
+pub mod editions;
+pub mod effective_config;
+pub mod estimate;
+pub mod erase_install;
+pub mod filter;
+pub mod firstboot;
+pub mod fleet_ca;
+pub mod hooks;
+pub mod io_limits;
+pub mod ipsw;
+pub mod kiosk;
+pub mod ledger;
+pub mod mac_compat;
+pub mod media;
+pub mod operation;
+pub mod overwrite;
+pub mod pack_kit;
+pub mod params;
+pub mod planning;
+pub mod preflight;
+pub mod provisioning;
+pub mod registry;
+pub mod resize;
+pub mod restore;
+pub mod secrets;
+pub mod stage;
+pub mod staging;
+pub mod steplog;
+pub mod target;
+pub mod tools;
+pub mod transform;
+pub mod verify_policy;
+pub mod watchdog;
+pub mod wizard;
+
+pub use ab_stick::{
+    read_slot_table, run_ab_stick, run_ab_update, AbStickParams, AbStickResult,
+    AbUpdateParams, AbUpdateResult, Slot, SlotImage, SlotTable,
+};
+pub use agent::{
+    run_agent, AgentDevice, AgentEvent, AgentJob, AgentJobRun, AgentObserver, AgentParams,
+    AgentResult, RemoteAllowList, AGENT_JOBS_FILE,
+};
+pub use assets::{asset_store, resolve_asset, ASSET_SCHEME};
+pub use audit_log::{append_audit_entry, audit_log_path, read_audit_log, AuditEntry};
+pub use audit::{run_media_audit, MediaAuditParams, MediaAuditResult, ModifiedFile};
+pub use bad_blocks::{
+    parse_scan_mode, run_bad_block_scan, BadBlockScanParams, BadBlockScanResult, BAD_BLOCKS_FILE_NAME,
+};
+pub use baseline::{baseline_dir, BaselineDrift, DRIFT_FILE_NAME};
+pub use boot_entry::{run_boot_entry, BootEntryParams, BootEntryResult};
+pub use cancel::{is_cancelled, with_cancel_token, CancelToken};
+pub use capacity::{check_cluster_bytes, estimate_capacity, CapacityEstimate, FormatCapacity};
+pub use capabilities::{host_capabilities, Capability, HostCapabilities};
+pub use catalog::{list_workflows, pack_dirs, CatalogEntry, CatalogProblem, WorkflowCatalog};
+pub use cleanup::{
+    reap_leftovers, CleanupAction, CleanupEntry, CleanupGuard, Leftover, WRITE_TEST_FILE,
+};
+pub use boot_lint::{lint_boot_menu, BootLintFinding, BootVolume};
+pub use clone_device::{
+    run_clone_device, CloneArchive, CloneDeviceParams, CloneDeviceResult, DeviceComparison,
+    ARCHIVE_ZSTD_LEVEL, CLONE_ARCHIVE_ARTIFACT,
+};
+pub use combo::{
+    parse_data_encryption, run_combo_stick, ComboPartition, ComboStickParams, ComboStickResult,
+    DataEncryption,
+};
+pub use copy_errors::{CopyFailure, FailureSide, COPY_FAILURES_FILE};
+pub use dedupe::{DedupeGroup, DedupeSummary};
+pub use destruction::{
+    describe_destruction, DestructionParams, DestructionSummary, DestructionVolume,
+    DestructiveAction,
+};
+pub use diagnostics::{write_failure_diagnostics, DiagnosticFile, DIAGNOSTICS_ENV};
+pub use doctor::{run_doctor, CheckStatus, DoctorCheck};
+pub use effective_config::{
+    config_drift, effective_config, ConfigDrift, ConfigSetting, ConfigSource, EffectiveConfig,
+};
+pub use duplicate::{
+    plan_duplicate_to_all, run_duplicate_to_all, DuplicateParams, DuplicatePlan, DuplicateResult,
+    DuplicateTarget, ExcludedDisk,
+};
+pub use estimate::{
+    estimate_workflow, EstimateParams, StepEstimate, ThroughputSource, WorkflowEstimate,
+};
+pub use editions::{select_edition, select_from_media, EditionImage, EditionSelection};
+pub use erase_install::{run_macos_erase_install, MacosEraseInstallParams, MacosEraseInstallResult};
+pub use filter::SourceFilter;
+pub use firstboot::{
+    render_template, run_stage_firstboot, FirstbootFile, FirstbootKind, FirstbootScript,
+    StageFirstbootParams, StageFirstbootResult,
+};
+pub use fleet_ca::{
+    init_fleet_ca, issue_fleet_cert, read_agent_identity, CertKind, IssuedCert, FLEET_CA_CERT,
+    FLEET_CA_KEY, FLEET_ISSUED_FILE,
+};
+pub use mac_compat::{check_installer_for_model, inspect_installer_app, InstallerSupport, MacArch};
+pub use media::{
+    parse_edition_id, parse_product_key, run_merge_windows_languages, run_slim_windows_media,
+    MediaKeepList, MergeWindowsLanguagesParams, MergeWindowsLanguagesResult, SlimWindowsMediaParams, SlimWindowsMediaResult};
+pub use hooks::{HookPhase, HookRun, HookSandbox, HookSpec, StepHooks};
+pub use io_limits::{io_limits, io_limits_path, io_status, IoHolder, IoLimits, IoPhase, IoStatus};
+pub use ipsw::{
+    list_dfu_devices, run_ipsw_restore, DfuDevice, IpswRestoreParams, IpswRestoreResult, RestoreMode,
+    RestoreTool,
+};
+pub use kiosk::{
+    run_kiosk, KioskConfirm, KioskEvent, KioskObserver, KioskParams, KioskPolicy, KioskResult,
+    StationRun,
+};
+pub use operation::{
+    ApplyImage, DestructiveOperation, DestructiveSession, FormatDevice, FormatVolume, ImageWrite,
+    MacosInstallerMedia, MacosMedia, RepartitionDisk, WriteImage,
+};
+pub use overwrite::{inspect_disk, OverwriteCheck};
+pub use pack_kit::{
+    lint_pack, run_pack_test, scaffold_pack, LintLevel, LintProblem, PackLint, PackTestCase,
+    PackTestParams, PackTestResult, PACK_TEST_FILE,
+};
+pub use phoenix_workflow_plan::{CapabilityUse, PlannedStep, WorkflowPlan};
+pub use planning::{write_plan_report, PlanReportResult};
+pub use preflight::{
+    require_workflow_host, step_requirements, validate_workflow_against_host, PreflightCheck,
+    PreflightChecklist, StepRequirements,
+};
+pub use provisioning::{
+    run_stage_provisioning, validate_autopilot_config, AutopilotProfile, ProvisioningLayout,
+    StageProvisioningParams, StageProvisioningResult,
+};
+pub use registry::{device_key, DeviceRecord, DeviceRegistry};
+pub use resize::{
+    parse_resize_mode, run_resize_partition, ResizeMode, ResizePartitionParams,
+    ResizePartitionResult, DEFAULT_RESIZE_MARGIN,
+};
+pub use restore::{run_restore_report, ImageArtifact, RestoreReportParams, RestoreReportResult};
+pub use secrets::{
+    resolve_secret, set_key_provider, DirKeyProvider, EnvKeyProvider, KeyProvider, SECRET_SCHEME,
+};
+pub use stage::{
+    run_stage_files, StageFileRule, StageFilesParams, StageFilesResult, StageOverwrite, StagedFile,
+};
+pub use staging::{
+    staging_backend, staging_backend_for_mount, staging_backend_named, ExFatStaging, Ext4Staging,
+    Fat32Staging, NtfsStaging, RawImageStaging, StagingBackend,
+};
+pub use steplog::{
+    take_failed_runs, with_log_listener, with_progress_listener, FailedRun, LogEntry, LogListener,
+    PhaseTiming, ProgressEvent, ProgressListener, ProgressReporter, RunTiming, StepLog,
+    TransferProgress, TIMING_FILE,
+};
+pub use transform::{TransformedFile, MAX_TRANSFORM_BYTES, TRANSFORMED_FILES_FILE};
+pub use target::{explain_target, TargetExplanation};
+pub use tools::{check_workflow_tools, require_workflow_tools, ToolCheck};
+pub use verify_policy::{
+    verify_policy, verify_policy_path, AppliedVerify, VerifyLevel, VerifyPolicy, VerifyRequirement,
+    VerifyTarget,
+};
+pub use watchdog::{error_code, WorkflowTimeout};
+pub use wizard::{QuestionKind, WizardChoice, WizardQuestion, WizardSession, WIZARD_ACTIONS};
+pub use ledger::{
+    network_config, network_config_path, notify_config, notify_config_path, recovery_guidance,
+    usb_port_labels, usb_port_labels_path, IdempotencyRecord, RunLedger, RunRecord, RunStatus,
+    RunTracker,
+};
+
 pub trait Workflow {
     fn name(&self) -> &'static str;
     fn run(&self) -> Result<()>;
@@ -29,25 +236,91 @@ pub fn run_workflow<W: Workflow>(workflow: W) -> Result<()> {
     workflow.run()
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct WindowsInstallerUsbParams {
     pub target_disk_id: String,
     pub source_path: PathBuf,
+    #[serde(default)]
     pub target_mount: Option<PathBuf>,
+    #[serde(default = "crate::params::default_report_base")]
     pub report_base: PathBuf,
+    #[serde(default)]
     pub force: bool,
+    #[serde(default)]
     pub confirmation_token: Option<String>,
+    /// Proceed on a disk outside `TargetSizeLimits`; recorded in the report.
+    #[serde(default)]
+    pub acknowledge_target_size: bool,
+    /// Proceed on a device past the wear limits; recorded in the report
+    /// and the audit log.
+    #[serde(default)]
+    pub acknowledge_device_wear: bool,
+    /// Wipe even if the target looks like personal data; see `overwrite`.
+    #[serde(default)]
+    pub confirm_overwrite: bool,
+    #[serde(default = "crate::params::default_true")]
     pub dry_run: bool,
+    #[serde(default)]
     pub repartition: bool,
+    #[serde(default)]
     pub format: bool,
+    #[serde(default = "crate::params::default_filesystem")]
     pub filesystem: FileSystem,
+    #[serde(default)]
     pub label: Option<String>,
+    /// Allocation unit for `format` or `repartition`; `None` lets Windows
+    /// choose.
+    #[serde(default, with = "crate::params::byte_size")]
+    pub cluster_bytes: Option<u64>,
+    #[serde(default)]
     pub driver_source: Option<PathBuf>,
+    #[serde(default)]
     pub driver_target: Option<PathBuf>,
+    #[serde(default)]
     pub hash_manifest: bool,
+    /// Read each copied file back and check it against the source hash;
+    /// needs `hash_manifest`.
+    #[serde(default)]
+    pub hash_destination: bool,
+    /// Custom GPT layout used with `repartition`; empty means one basic-data
+    /// partition spanning the disk.
+    #[serde(default)]
+    pub partitions: Vec<PartitionSpec>,
+    /// Mirror the first partition into the MBR for legacy BIOS boot.
+    #[serde(default)]
+    pub hybrid_mbr: bool,
+    /// EditionID written to `sources/EI.cfg` so Setup skips edition choice.
+    #[serde(default)]
+    pub edition: Option<String>,
+    /// Index, EditionID or name of the install image Setup should use; its
+    /// EditionID goes to `EI.cfg` unless `edition` is set, and must agree
+    /// with it when it is.
+    #[serde(default)]
+    pub edition_selector: Option<String>,
+    /// Product key written to `sources/PID.txt`.
+    #[serde(default)]
+    pub pid_txt: Option<String>,
+    /// Which source files are copied; empty copies everything.
+    #[serde(flatten)]
+    pub source_filter: SourceFilter,
+    /// Hardlink identical source files instead of copying each; see
+    /// `dedupe`.
+    #[serde(default)]
+    pub dedupe: bool,
+    /// Sync copied files each time this many bytes accumulate, so the run
+    /// ledger's `durable_bytes` says how much survives a power cut.
+    #[serde(default, with = "crate::params::byte_size")]
+    pub flush_every_bytes: Option<u64>,
+    /// Skip files that fail to copy and finish the rest; see `copy_errors`.
+    #[serde(default)]
+    pub continue_on_error: bool,
+    /// Pack manifest whose `transforms` edit matching source files as they
+    /// are staged; see `transform`.
+    #[serde(default)]
+    pub transform_pack: Option<PathBuf>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct WindowsInstallerUsbResult {
     pub report: ReportPaths,
     pub target_mount: PathBuf,
@@ -55,58 +328,174 @@ pub struct WindowsInstallerUsbResult {
     pub copied_bytes: u64,
     pub driver_files: usize,
     pub driver_bytes: u64,
+    /// Set when the run formats the target.
+    pub format_capacity: Option<FormatCapacity>,
+    /// Files skipped under `continue_on_error`.
+    pub copy_failures: Vec<CopyFailure>,
+    /// Files edited by `transform_pack`.
+    pub transformed_files: Vec<TransformedFile>,
     pub dry_run: bool,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UnixInstallerUsbParams {
     pub source_path: PathBuf,
     pub target_mount: PathBuf,
+    #[serde(default = "crate::params::default_report_base")]
     pub report_base: PathBuf,
+    #[serde(default)]
     pub force: bool,
+    #[serde(default)]
     pub confirmation_token: Option<String>,
+    /// Proceed on a disk outside `TargetSizeLimits`; recorded in the report.
+    #[serde(default)]
+    pub acknowledge_target_size: bool,
+    /// Proceed on a device past the wear limits; recorded in the report
+    /// and the audit log.
+    #[serde(default)]
+    pub acknowledge_device_wear: bool,
+    /// Wipe even if the target looks like personal data; see `overwrite`.
+    #[serde(default)]
+    pub confirm_overwrite: bool,
+    #[serde(default = "crate::params::default_true")]
     pub dry_run: bool,
+    #[serde(default)]
     pub hash_manifest: bool,
+    /// Read each copied file back and check it against the source hash;
+    /// needs `hash_manifest`.
+    #[serde(default)]
+    pub hash_destination: bool,
+    #[serde(default)]
     pub format_device: Option<PathBuf>,
+    #[serde(default)]
     pub format_size_bytes: Option<u64>,
+    #[serde(default)]
     pub format_label: Option<String>,
+    /// FAT32 cluster size for `format_device`; `None` picks the smallest
+    /// legal one. Not supported with `udisks`.
+    #[serde(default, with = "crate::params::byte_size")]
+    pub format_cluster_bytes: Option<u64>,
+    /// Linux only: unmount/format/mount through udisks2 (polkit) instead of
+    /// raw device access, so the workflow runs without root. Needs
+    /// `format_device` or `power_off`.
+    #[serde(default)]
+    pub udisks: bool,
+    /// Power off the drive through udisks2 once staging is verified; needs
+    /// `udisks`.
+    #[serde(default)]
+    pub power_off: bool,
+    /// Which source files are copied; empty copies everything.
+    #[serde(flatten)]
+    pub source_filter: SourceFilter,
+    /// Hardlink identical source files instead of copying each; see
+    /// `dedupe`.
+    #[serde(default)]
+    pub dedupe: bool,
+    /// Sync copied files each time this many bytes accumulate, so the run
+    /// ledger's `durable_bytes` says how much survives a power cut.
+    #[serde(default, with = "crate::params::byte_size")]
+    pub flush_every_bytes: Option<u64>,
+    /// Skip files that fail to copy and finish the rest; see `copy_errors`.
+    #[serde(default)]
+    pub continue_on_error: bool,
+    /// Pack manifest whose `transforms` edit matching source files as they
+    /// are staged; see `transform`.
+    #[serde(default)]
+    pub transform_pack: Option<PathBuf>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UnixWriteImageParams {
+    /// Image file, or an http(s) URL streamed straight to the device.
     pub source_image: PathBuf,
+    /// Other URLs serving the same image as a URL `source_image`. The
+    /// stream starts on the fastest healthy one and fails over between
+    /// them.
+    #[serde(default)]
+    pub source_mirrors: Vec<String>,
+    /// Expected SHA-256 of the image; required for plain http URLs.
+    #[serde(default)]
+    pub source_sha256: Option<String>,
     pub target_device: PathBuf,
+    #[serde(default = "crate::params::default_report_base")]
     pub report_base: PathBuf,
+    #[serde(default)]
     pub force: bool,
+    #[serde(default)]
     pub confirmation_token: Option<String>,
+    /// Proceed on a disk outside `TargetSizeLimits`; recorded in the report.
+    #[serde(default)]
+    pub acknowledge_target_size: bool,
+    /// Proceed on a device past the wear limits; recorded in the report
+    /// and the audit log.
+    #[serde(default)]
+    pub acknowledge_device_wear: bool,
+    /// Wipe even if the target looks like personal data; see `overwrite`.
+    #[serde(default)]
+    pub confirm_overwrite: bool,
+    #[serde(default = "crate::params::default_true")]
     pub dry_run: bool,
+    #[serde(default)]
     pub verify: bool,
-    pub chunk_size: u64,
+    /// `None` probes the device for the fastest chunk size.
+    #[serde(default, with = "crate::params::chunk_size")]
+    pub chunk_size: Option<u64>,
+    /// Overlap image reads with device writes (`write_image_pipelined`).
+    #[serde(default)]
+    pub fast_io: bool,
+    /// Sync the device every this many bytes written, so the run ledger's
+    /// `durable_bytes` says how much survives a power cut. `None` syncs
+    /// once at the end.
+    #[serde(default, with = "crate::params::byte_size")]
+    pub flush_every_bytes: Option<u64>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UnixWriteImageResult {
     pub report: ReportPaths,
     pub bytes_written: u64,
     pub sha256: String,
     pub verify_ok: Option<bool>,
     pub dry_run: bool,
+    pub chunk_size: u64,
+    pub throughput_bytes_per_sec: u64,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MacosInstallerUsbParams {
     pub source_path: PathBuf,
     pub target_device: PathBuf,
+    #[serde(default = "crate::params::default_report_base")]
     pub report_base: PathBuf,
+    #[serde(default = "crate::params::default_volume_name")]
     pub volume_name: String,
+    #[serde(default)]
     pub macos_version: Option<String>,
+    #[serde(default)]
     pub filesystem: Option<String>,
+    /// Model identifier of the Mac the media is for (`MacBookPro18,3`);
+    /// an installer that cannot boot it is refused before the erase.
+    #[serde(default)]
+    pub target_model: Option<String>,
+    #[serde(default)]
     pub force: bool,
+    #[serde(default)]
     pub confirmation_token: Option<String>,
+    /// Proceed on a disk outside `TargetSizeLimits`; recorded in the report.
+    #[serde(default)]
+    pub acknowledge_target_size: bool,
+    /// Proceed on a device past the wear limits; recorded in the report
+    /// and the audit log.
+    #[serde(default)]
+    pub acknowledge_device_wear: bool,
+    /// Wipe even if the target looks like personal data; see `overwrite`.
+    #[serde(default)]
+    pub confirm_overwrite: bool,
+    #[serde(default = "crate::params::default_true")]
     pub dry_run: bool,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MacosInstallerUsbResult {
     pub report: ReportPaths,
     pub mode: String,
@@ -114,18 +503,27 @@ pub struct MacosInstallerUsbResult {
     pub dry_run: bool,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UnixBootPrepParams {
     pub source_path: PathBuf,
     pub target_mount: PathBuf,
+    #[serde(default = "crate::params::default_report_base")]
     pub report_base: PathBuf,
+    #[serde(default)]
     pub force: bool,
+    #[serde(default)]
     pub confirmation_token: Option<String>,
+    #[serde(default = "crate::params::default_true")]
     pub dry_run: bool,
+    #[serde(default)]
     pub hash_manifest: bool,
+    /// Read each copied file back and check it against the source hash;
+    /// needs `hash_manifest`.
+    #[serde(default)]
+    pub hash_destination: bool,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UnixBootPrepResult {
     pub report: ReportPaths,
     pub copied_files: usize,
@@ -133,19 +531,46 @@ pub struct UnixBootPrepResult {
     pub dry_run: bool,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BootloaderStageParams {
     pub source_path: PathBuf,
     pub target_mount: PathBuf,
+    #[serde(default)]
     pub target_subdir: Option<PathBuf>,
+    #[serde(default = "crate::params::default_report_base")]
     pub report_base: PathBuf,
+    #[serde(default)]
     pub force: bool,
+    #[serde(default)]
     pub confirmation_token: Option<String>,
+    #[serde(default = "crate::params::default_true")]
     pub dry_run: bool,
+    #[serde(default)]
     pub hash_manifest: bool,
+    /// Read each copied file back and check it against the source hash;
+    /// needs `hash_manifest`.
+    #[serde(default)]
+    pub hash_destination: bool,
+    /// Signing certificate (PEM or DER) to stage for MokManager's "Enroll
+    /// key from disk".
+    #[serde(default)]
+    pub mok_certificate: Option<PathBuf>,
+    /// Write the Authenticode hashes of the package's binaries for
+    /// "Enroll hash from disk".
+    #[serde(default)]
+    pub mok_hashes: bool,
+    /// MokManager binary (`mmx64.efi` and so on) to stage next to shim.
+    #[serde(default)]
+    pub mok_manager: Option<PathBuf>,
+    /// Pack manifest whose `grub` menu is rendered over the package's.
+    #[serde(default)]
+    pub grub_pack: Option<PathBuf>,
+    /// GRUB directory under the staging root; default `boot/grub`.
+    #[serde(default)]
+    pub grub_dir: Option<String>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BootloaderStageResult {
     pub report: ReportPaths,
     pub copied_files: usize,
@@ -153,19 +578,29 @@ pub struct BootloaderStageResult {
     pub dry_run: bool,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MacosKextStageParams {
     pub source_path: PathBuf,
     pub target_mount: PathBuf,
+    #[serde(default)]
     pub target_subdir: Option<PathBuf>,
+    #[serde(default = "crate::params::default_report_base")]
     pub report_base: PathBuf,
+    #[serde(default)]
     pub force: bool,
+    #[serde(default)]
     pub confirmation_token: Option<String>,
+    #[serde(default = "crate::params::default_true")]
     pub dry_run: bool,
+    #[serde(default)]
     pub hash_manifest: bool,
+    /// Read each copied file back and check it against the source hash;
+    /// needs `hash_manifest`.
+    #[serde(default)]
+    pub hash_destination: bool,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MacosKextStageResult {
     pub report: ReportPaths,
     pub copied_files: usize,
@@ -173,12 +608,18 @@ pub struct MacosKextStageResult {
     pub dry_run: bool,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UnixInstallerUsbResult {
     pub report: ReportPaths,
     pub target_mount: PathBuf,
     pub copied_files: usize,
     pub copied_bytes: u64,
+    /// Set when the run formats the target.
+    pub format_capacity: Option<FormatCapacity>,
+    /// Files skipped under `continue_on_error`.
+    pub copy_failures: Vec<CopyFailure>,
+    /// Files edited by `transform_pack`.
+    pub transformed_files: Vec<TransformedFile>,
     pub dry_run: bool,
 }
 
@@ -207,6 +648,20 @@ impl Workflow for WindowsInstallerUsbWorkflow {
 }
 
 pub fn run_windows_installer_usb(params: &WindowsInstallerUsbParams) -> Result<WindowsInstallerUsbResult> {
+    let started = Instant::now();
+    // Checked before anything is formatted or copied.
+    if let Some(edition) = &params.edition {
+        parse_edition_id(edition)?;
+    }
+    if let Some(key) = &params.pid_txt {
+        parse_product_key(key)?;
+    }
+    let mut copier = Copier::new(
+        CopyHashing::new(params.hash_manifest, params.hash_destination)?,
+        params.flush_every_bytes,
+    );
+    let mut copy_errors = CopyErrors::new(params.continue_on_error);
+    let mut transforms = load_transforms(params.transform_pack.as_deref(), params.dedupe)?;
     let graph = build_device_graph()?;
     let disk = graph
         .disks
@@ -224,6 +679,20 @@ pub fn run_windows_installer_usb(params: &WindowsInstallerUsbParams) -> Result<W
             disk.id
         ));
     }
+    if let Some(reason) = target::stack_usage(&graph, disk) {
+        return Err(anyhow!(reason));
+    }
+    let target_size_acknowledged = check_target_disk_size(disk, params.acknowledge_target_size)?;
+    let device_claim = check_target_claim(disk, "windows-installer-usb", params.dry_run)?;
+    let device_wear = join_reasons(
+        device_claim,
+        check_target_wear(disk, "windows-installer-usb", params.acknowledge_device_wear, params.dry_run)?,
+    );
+    let overwrite_triggers = if params.format || params.repartition {
+        check_overwrite(disk, params.confirm_overwrite, params.dry_run)?
+    } else {
+        Vec::new()
+    };
 
     let mut target_mount = if let Some(path) = &params.target_mount {
         let normalized = normalize_mount_path(path);
@@ -248,10 +717,11 @@ pub fn run_windows_installer_usb(params: &WindowsInstallerUsbParams) -> Result<W
             .flat_map(|partition| partition.mount_points.iter())
             .next()
             .map(|mount| normalize_mount_path(&PathBuf::from(mount)))
-            .unwrap_or_else(|| PathBuf::new())
+            .unwrap_or_else(PathBuf::new)
     };
 
     let mut fs_label = None;
+    let mut target_partition_bytes = None;
     let target_mount_string = target_mount.display().to_string();
     for partition in &disk.partitions {
         if partition
@@ -260,6 +730,7 @@ pub fn run_windows_installer_usb(params: &WindowsInstallerUsbParams) -> Result<W
             .any(|mount| mount.eq_ignore_ascii_case(&target_mount_string))
         {
             fs_label = partition.fs.clone();
+            target_partition_bytes = Some(partition.size_bytes);
             break;
         }
     }
@@ -285,19 +756,139 @@ pub fn run_windows_installer_usb(params: &WindowsInstallerUsbParams) -> Result<W
         ));
     }
 
-    let files = collect_files(&source_root)?;
+    let edition_selection = params
+        .edition_selector
+        .as_deref()
+        .map(|selector| select_from_media(&source_root, selector))
+        .transpose()?;
+    let mut edition = params.edition.clone();
+    if let Some(selection) = &edition_selection {
+        let selected = selection.image.edition_id.as_deref();
+        match (&edition, selected) {
+            (Some(explicit), Some(selected)) if !explicit.eq_ignore_ascii_case(selected) => {
+                return Err(anyhow!(
+                    "edition {} disagrees with edition_selector {} ({})",
+                    explicit,
+                    selection.selector,
+                    selected
+                ));
+            }
+            // EI.cfg only takes the client EditionIDs Setup knows.
+            (None, Some(selected)) if parse_edition_id(selected).is_ok() => {
+                edition = Some(selected.to_string());
+            }
+            _ => {}
+        }
+    }
+
+    let (mut files, excluded_files) = collect_files_filtered(&source_root, &params.source_filter)?;
     ensure_boot_files(&files)?;
+    if let Some(transforms) = &mut transforms {
+        transforms.prepare(&mut files)?;
+    }
     let total_bytes = files.iter().map(|entry| entry.size).sum::<u64>();
+    let mut dedupe = params.dedupe.then(|| Deduper::plan(&files)).transpose()?;
+    let verify = resolve_verify(VerifyTarget::FileCopies, params.hash_destination, total_bytes)?;
+    copier.hashing = copier.hashing.with_verify(&verify);
+    let hash_manifest = params.hash_manifest || verify.enabled();
 
-    let mut logs = Vec::new();
-    logs.push(format!("workflow=windows-installer-usb"));
+    let mut logs = StepLog::new("windows-installer-usb");
     logs.push(format!("target_disk={}", disk.id));
+    if let Some(reason) = &target_size_acknowledged {
+        logs.push(format!("target_size_acknowledged={}", reason));
+    }
+    if let Some(reason) = &device_wear {
+        logs.push(format!("device_wear={}", reason));
+    }
+    for trigger in &overwrite_triggers {
+        logs.push(format!("overwrite_trigger={}", trigger));
+    }
     logs.push(format!("target_mount={}", target_mount.display()));
     logs.push(format!("source_path={}", source_root.display()));
     logs.push(format!("source_kind={:?}", source_kind));
     logs.push(format!("file_count={}", files.len()));
+    log_source_filter(&mut logs, &params.source_filter, excluded_files);
+    if let Some(transforms) = &transforms {
+        log_transforms(&mut logs, transforms);
+    }
     logs.push(format!("total_bytes={}", total_bytes));
+    log_verify(&mut logs, &verify);
     logs.push(format!("filesystem={}", params.filesystem.as_str()));
+    if let Some(selection) = &edition_selection {
+        logs.push(format!(
+            "edition_selector={} matched_by={} index={} edition_id={} boot_index={}",
+            selection.selector,
+            selection.matched_by,
+            selection.image.index,
+            selection.image.edition_id.as_deref().unwrap_or("-"),
+            selection
+                .boot_image
+                .as_ref()
+                .map(|image| image.index.to_string())
+                .unwrap_or_else(|| "-".to_string())
+        ));
+    }
+    let mut partition_warnings = Vec::new();
+    let partition_plan = if params.repartition {
+        let specs = if params.partitions.is_empty() {
+            vec![PartitionSpec::basic_data(
+                params.label.as_deref().unwrap_or("PHOENIX"),
+            )]
+        } else {
+            params.partitions.clone()
+        };
+        let mut plan = plan_partitions(disk.size_bytes, DEFAULT_SECTOR_SIZE, &specs)?;
+        if params.hybrid_mbr {
+            let mbr_type = match params.filesystem {
+                FileSystem::Fat32 => MBR_TYPE_FAT32_LBA,
+                _ => MBR_TYPE_NTFS_EXFAT,
+            };
+            partition_warnings = plan.enable_hybrid_mbr(mbr_type)?;
+        }
+        for partition in &plan.partitions {
+            logs.push(format!(
+                "partition={} name={} type={} first_lba={} last_lba={} attributes=0x{:016x}",
+                partition.number,
+                partition.spec.name,
+                partition.spec.type_guid,
+                partition.first_lba,
+                partition.last_lba,
+                partition.spec.attributes.bits()
+            ));
+        }
+        for warning in &partition_warnings {
+            logs.push(format!("partition_warning={}", warning));
+        }
+        Some(plan)
+    } else {
+        None
+    };
+    let format_volume_bytes = match &partition_plan {
+        Some(plan) => plan
+            .mountable()
+            .next()
+            .map(|partition| partition.length_bytes(DEFAULT_SECTOR_SIZE)),
+        None if params.format => target_partition_bytes,
+        None => None,
+    };
+    if params.cluster_bytes.is_some() && !params.repartition && !params.format {
+        return Err(anyhow!("cluster_bytes requires format or repartition"));
+    }
+    if let (Some(cluster_bytes), Some(volume_bytes)) = (params.cluster_bytes, format_volume_bytes) {
+        check_cluster_bytes(params.filesystem, volume_bytes, cluster_bytes)?;
+    }
+    let format_capacity = match format_volume_bytes {
+        Some(volume_bytes) => Some(check_format_capacity(
+            &estimate_capacity(params.filesystem, volume_bytes, params.cluster_bytes),
+            &files,
+            &mut logs,
+        )?),
+        None => None,
+    };
+    let cluster_bytes = params
+        .cluster_bytes
+        .map(|bytes| u32::try_from(bytes).map_err(|_| anyhow!("cluster_bytes too large: {}", bytes)))
+        .transpose()?;
 
     let mut copied_files = 0usize;
     let mut copied_bytes = 0u64;
@@ -308,47 +899,58 @@ pub fn run_windows_installer_usb(params: &WindowsInstallerUsbParams) -> Result<W
     let mut artifacts = Vec::new();
     let mut artifact_names = Vec::new();
 
-    if params.filesystem.as_str().eq_ignore_ascii_case("FAT32") {
-        let max = max_file_size(&files);
-        if max > FAT32_MAX_FILE {
-            return Err(anyhow!(
-                "FAT32 cannot store files > 4GB (max file {} bytes). Use NTFS/exFAT.",
-                max
-            ));
-        }
+    let backend = staging_backend(params.filesystem);
+    if let Some(problem) = backend.file_size_problem(max_file_size(&files)) {
+        return Err(anyhow!(problem));
+    }
+    let name_warnings =
+        backend.path_warnings(&mut files.iter().map(|entry| entry.relative_path.as_path()));
+    for warning in &name_warnings {
+        logs.push(format!("name_warning={}", warning));
     }
 
-    if !params.dry_run {
-        let ctx = SafetyContext {
-            force_mode: params.force,
-            confirmation_token: params.confirmation_token.clone(),
+    let ctx = SafetyContext {
+        force_mode: params.force,
+        confirmation_token: params.confirmation_token.clone(),
+        allow_system_disk: false,
+        armed_until: phoenix_safety::armed_until(),
+    };
+    let mut session = DestructiveSession::begin(
+        "windows-installer-usb",
+        Some(disk),
+        &ctx,
+        disk.is_system_disk,
+        params.dry_run,
+        &mut logs,
+    )?;
+    if let Some(plan) = &partition_plan {
+        let mut repartition = RepartitionDisk {
+            disk,
+            plan,
+            filesystem: params.filesystem,
+            label: params.label.as_deref(),
+            cluster_bytes,
         };
-
-        match can_write_to_disk(&ctx, disk.is_system_disk) {
-            SafetyDecision::Allow => {}
-            SafetyDecision::Deny(reason) => return Err(anyhow!(reason)),
-        }
-
-        if params.repartition {
-            let disk_number = parse_disk_number(&disk.id)
-                .ok_or_else(|| anyhow!("invalid disk id {}", disk.id))?;
-            let letter = prepare_usb_disk(
-                disk_number,
-                disk.size_bytes,
-                params.filesystem,
-                params.label.as_deref(),
-            )?;
-            target_mount = normalize_mount_path(&PathBuf::from(format!("{}:\\", letter)));
+        if let Some(mount) = session.perform(&mut repartition, &mut logs)? {
+            target_mount = mount;
             logs.push("partition_format=completed".to_string());
-        } else if params.format {
-            let letter = extract_drive_letter(&target_mount)
-                .ok_or_else(|| anyhow!("unable to parse drive letter from mount path"))?;
-            format_existing_volume(letter, params.filesystem, params.label.as_deref())?;
+        }
+    } else if params.format {
+        let mut format = FormatVolume {
+            disk_id: &disk.id,
+            mount: &target_mount,
+            filesystem: params.filesystem,
+            label: params.label.as_deref(),
+            cluster_bytes,
+        };
+        if session.perform(&mut format, &mut logs)?.is_some() {
             logs.push("partition_format=formatted".to_string());
-        } else {
-            logs.push("partition_format=skipped".to_string());
         }
+    } else if !params.dry_run {
+        logs.push("partition_format=skipped".to_string());
+    }
 
+    if !params.dry_run {
         if target_mount.as_os_str().is_empty() {
             return Err(anyhow!("no mounted volume found for {}", disk.id));
         }
@@ -363,36 +965,108 @@ pub fn run_windows_installer_usb(params: &WindowsInstallerUsbParams) -> Result<W
             }
         }
 
+        session.phase("copy", &mut logs)?;
         logs.push("copy_start".to_string());
-        for entry in &files {
+        let mut staged_bytes = 0u64;
+        for (index, entry) in files.iter().enumerate() {
             let dest_path = target_mount.join(&entry.relative_path);
             if let Some(parent) = dest_path.parent() {
                 fs::create_dir_all(parent)
                     .with_context(|| format!("create dir {}", parent.display()))?;
             }
-            fs::copy(&entry.absolute_path, &dest_path).with_context(|| {
-                format!(
-                    "copy {} to {}",
-                    entry.absolute_path.display(),
-                    dest_path.display()
-                )
-            })?;
+            let linked_to = dedupe
+                .as_mut()
+                .and_then(|dedupe| dedupe.try_link(&files, index, &target_mount, &dest_path));
+            let copied = match linked_to {
+                // A link shares the original's timestamps.
+                Some(_) => CopiedFile {
+                    mtime_unix: None,
+                    mtime_preserved: false,
+                    sha256: None,
+                    destination_sha256: None,
+                },
+                None => {
+                    let result = match transforms.as_ref().and_then(|t| t.get(index)) {
+                        Some((_, content)) => {
+                            copier.write(&entry.absolute_path, &dest_path, content)
+                        }
+                        None => copier.copy(&entry.absolute_path, &dest_path),
+                    };
+                    let result = result.with_context(|| {
+                        format!(
+                            "copy {} to {}",
+                            entry.absolute_path.display(),
+                            dest_path.display()
+                        )
+                    });
+                    let copied = match result {
+                        Ok(copied) => copied,
+                        Err(err) => {
+                            let failure = copy_errors.record(entry, &dest_path, err)?;
+                            logs.push(format!(
+                                "copy_failed={} side={} error={}",
+                                failure.path,
+                                failure.side.as_str(),
+                                failure.error
+                            ));
+                            staged_bytes = staged_bytes.saturating_add(entry.size);
+                            continue;
+                        }
+                    };
+                    copied_bytes = copied_bytes.saturating_add(entry.size);
+                    session.checkpoint(copier.durable_bytes());
+                    copied
+                }
+            };
             copied_files += 1;
-            copied_bytes = copied_bytes.saturating_add(entry.size);
-            if params.hash_manifest {
-                let hash = hash_file(&entry.absolute_path)?;
+            staged_bytes = staged_bytes.saturating_add(entry.size);
+            logs.progress(staged_bytes, total_bytes);
+            if hash_manifest {
+                // A link was not copied, so its hash is the original's.
+                let hash = match copied.sha256 {
+                    Some(hash) => hash,
+                    None => match dedupe.as_ref().and_then(|dedupe| dedupe.sha256(index)) {
+                        Some(hash) => hash.to_string(),
+                        None => hash_file(&entry.absolute_path)?,
+                    },
+                };
                 copy_manifest.push(CopyManifestEntry {
                     path: entry.relative_path.to_string_lossy().to_string(),
                     bytes: entry.size,
                     sha256: hash,
+                    original_sha256: transforms
+                        .as_ref()
+                        .and_then(|t| t.get(index))
+                        .map(|(file, _)| file.original_sha256.clone()),
+                    mtime_unix: copied.mtime_unix,
+                    mtime_preserved: copied.mtime_preserved,
+                    linked_to,
+                    destination_sha256: copied.destination_sha256,
                 });
             }
         }
+        copier.flush()?;
+        session.checkpoint(copier.durable_bytes());
         logs.push("copy_complete".to_string());
+        if let Some(dedupe) = &dedupe {
+            log_dedupe(&mut logs, dedupe.summary());
+        }
 
-        verify_copy(&target_mount, &files)?;
+        if !copy_errors.failures().is_empty() {
+            logs.push(format!("copy_failures={}", copy_errors.failures().len()));
+        }
+        session.phase("verify", &mut logs)?;
+        verify_copy(&target_mount, copy_errors.copied(&files))?;
         logs.push("verify_complete".to_string());
 
+        for path in media::write_setup_selection(
+            &target_mount,
+            edition.as_deref(),
+            params.pid_txt.as_deref(),
+        )? {
+            logs.push(format!("setup_selection_written={}", path.display()));
+        }
+
         if let Some(driver_source) = &params.driver_source {
             let driver_source = fs::canonicalize(driver_source)
                 .unwrap_or_else(|_| driver_source.clone());
@@ -406,6 +1080,7 @@ pub fn run_windows_installer_usb(params: &WindowsInstallerUsbParams) -> Result<W
             let driver_target = target_mount.join(driver_target);
             let driver_entries = collect_files(&driver_source)?;
 
+            session.phase("driver_copy", &mut logs)?;
             logs.push(format!("driver_source={}", driver_source.display()));
             logs.push(format!("driver_target={}", driver_target.display()));
             logs.push(format!("driver_file_count={}", driver_entries.len()));
@@ -417,7 +1092,7 @@ pub fn run_windows_installer_usb(params: &WindowsInstallerUsbParams) -> Result<W
                         format!("create dir {}", parent.display())
                     })?;
                 }
-                fs::copy(&entry.absolute_path, &dest_path).with_context(|| {
+                let copied = copier.copy(&entry.absolute_path, &dest_path).with_context(|| {
                     format!(
                         "copy driver {} to {}",
                         entry.absolute_path.display(),
@@ -426,63 +1101,103 @@ pub fn run_windows_installer_usb(params: &WindowsInstallerUsbParams) -> Result<W
                 })?;
                 driver_files += 1;
                 driver_bytes = driver_bytes.saturating_add(entry.size);
-                if params.hash_manifest {
-                    let hash = hash_file(&entry.absolute_path)?;
+                if let Some(hash) = copied.sha256 {
                     driver_manifest.push(CopyManifestEntry {
                         path: entry.relative_path.to_string_lossy().to_string(),
                         bytes: entry.size,
                         sha256: hash,
+                        original_sha256: None,
+                        mtime_unix: copied.mtime_unix,
+                        mtime_preserved: copied.mtime_preserved,
+                        linked_to: None,
+                        destination_sha256: copied.destination_sha256,
                     });
                 }
             }
+            copier.flush()?;
+            session.checkpoint(copier.durable_bytes());
             logs.push("driver_copy_complete".to_string());
         }
 
-        if params.hash_manifest {
+        if hash_manifest {
             if !copy_manifest.is_empty() {
-                let bytes = serde_json::to_vec_pretty(&copy_manifest)?;
-                artifacts.push(ReportArtifact {
-                    name: "copy_manifest.json".to_string(),
-                    bytes,
-                });
-                artifact_names.push("copy_manifest.json".to_string());
+                let artifact = ReportArtifact::json("copy_manifest.json", &copy_manifest)?;
+                artifact_names.push(artifact.name.clone());
+                artifacts.push(artifact);
             }
             if !driver_manifest.is_empty() {
-                let bytes = serde_json::to_vec_pretty(&driver_manifest)?;
-                artifacts.push(ReportArtifact {
-                    name: "driver_manifest.json".to_string(),
-                    bytes,
-                });
-                artifact_names.push("driver_manifest.json".to_string());
+                let artifact = ReportArtifact::json("driver_manifest.json", &driver_manifest)?;
+                artifact_names.push(artifact.name.clone());
+                artifacts.push(artifact);
             }
         }
     } else {
         logs.push("dry_run=true".to_string());
+        if let Some(dedupe) = &dedupe {
+            log_dedupe(&mut logs, dedupe.summary());
+        }
+    }
+    if let Some(dedupe) = &dedupe {
+        push_dedupe_map(dedupe.summary(), &mut artifacts, &mut artifact_names)?;
+    }
+    copy_errors.push_artifact(&mut artifacts, &mut artifact_names)?;
+    if let Some(transforms) = &transforms {
+        transforms.push_artifact(&mut artifacts, &mut artifact_names)?;
     }
 
+    let (log_text, timing) = logs.finish()?;
+    artifact_names.push(timing.name.clone());
+    artifacts.push(timing);
+
     let meta = serde_json::json!({
         "workflow": "windows-installer-usb",
-        "status": if params.dry_run { "dry_run" } else { "completed" },
+        "status": copy_errors.status(params.dry_run),
         "target_disk_id": disk.id,
+        "target_serial": disk.serial,
         "target_mount": target_mount.display().to_string(),
         "source_path": source_root.display().to_string(),
         "source_kind": format!("{:?}", source_kind),
+        "source_filter": params.source_filter,
+        "excluded_files": excluded_files,
         "copied_files": copied_files,
         "copied_bytes": copied_bytes,
+        "continue_on_error": params.continue_on_error,
+        "copy_failures": copy_errors.failures().len(),
+        "transform_pack": transforms.as_ref().map(|t| t.pack_name()),
+        "transformed_files": transforms.as_ref().map_or(0, |t| t.files().len()),
+        "dedupe": dedupe.as_ref().map(|dedupe| dedupe_meta(dedupe.summary())),
+        "flush_every_bytes": params.flush_every_bytes,
+        "flushes": copier.flushes(),
+        "verify_policy": verify,
         "driver_files": driver_files,
         "driver_bytes": driver_bytes,
+        "name_warnings": name_warnings,
+        "hybrid_mbr": params.hybrid_mbr,
+        "partition_warnings": partition_warnings,
+        "edition": edition,
+        "edition_selection": edition_selection,
+        "pid_txt": params.pid_txt.is_some(),
+        "format_capacity": format_capacity,
+        "destructive_operations": session.operations(),
         "artifacts": artifact_names,
-        "dry_run": params.dry_run
+        "target_size_acknowledged": target_size_acknowledged,
+        "device_wear": device_wear,
+        "device_wear_acknowledged": params.acknowledge_device_wear,
+        "overwrite_triggers": overwrite_triggers,
+        "overwrite_confirmed": params.confirm_overwrite,
+        "dry_run": params.dry_run,
+        "duration_ms": started.elapsed().as_millis() as u64
     });
 
     let report = create_report_bundle_with_meta_signing_and_artifacts(
         &params.report_base,
         &graph,
         Some(meta),
-        Some(&logs.join("\n")),
+        Some(&log_text),
         signing_key_from_env().as_deref(),
         &artifacts,
     )?;
+    session.complete(&report.root)?;
 
     Ok(WindowsInstallerUsbResult {
         report,
@@ -491,18 +1206,29 @@ pub fn run_windows_installer_usb(params: &WindowsInstallerUsbParams) -> Result<W
         copied_bytes,
         driver_files,
         driver_bytes,
+        format_capacity,
+        copy_failures: copy_errors.failures().to_vec(),
+        transformed_files: transforms.map(|t| t.files()).unwrap_or_default(),
         dry_run: params.dry_run,
     })
 }
 
 pub fn run_unix_installer_usb(params: &UnixInstallerUsbParams) -> Result<UnixInstallerUsbResult> {
+    let started = Instant::now();
     #[cfg(not(any(target_os = "linux", target_os = "macos")))]
     {
         return Err(anyhow!("unix installer workflow requires linux or macos"));
     }
+    check_udisks_options(params)?;
+    let mut copier = Copier::new(
+        CopyHashing::new(params.hash_manifest, params.hash_destination)?,
+        params.flush_every_bytes,
+    );
+    let mut copy_errors = CopyErrors::new(params.continue_on_error);
+    let mut transforms = load_transforms(params.transform_pack.as_deref(), params.dedupe)?;
 
     let graph = build_device_graph()?;
-    let target_mount = normalize_mount_for_unix(&params.target_mount);
+    let mut target_mount = normalize_mount_for_unix(&params.target_mount);
     if !target_mount.exists() {
         return Err(anyhow!("target mount does not exist"));
     }
@@ -521,6 +1247,20 @@ pub fn run_unix_installer_usb(params: &UnixInstallerUsbParams) -> Result<UnixIns
             disk.id
         ));
     }
+    if let Some(reason) = target::stack_usage(&graph, disk) {
+        return Err(anyhow!(reason));
+    }
+    let target_size_acknowledged = check_target_disk_size(disk, params.acknowledge_target_size)?;
+    let device_claim = check_target_claim(disk, "unix-installer-usb", params.dry_run)?;
+    let device_wear = join_reasons(
+        device_claim,
+        check_target_wear(disk, "unix-installer-usb", params.acknowledge_device_wear, params.dry_run)?,
+    );
+    let overwrite_triggers = if params.format_device.is_some() {
+        check_overwrite(disk, params.confirm_overwrite, params.dry_run)?
+    } else {
+        Vec::new()
+    };
 
     let prepared = prepare_source(&params.source_path)?;
     let source_root = prepared.root.clone();
@@ -528,12 +1268,25 @@ pub fn run_unix_installer_usb(params: &UnixInstallerUsbParams) -> Result<UnixIns
         return Err(anyhow!("source root is not a directory"));
     }
 
-    let files = collect_files(&source_root)?;
+    let (mut files, excluded_files) = collect_files_filtered(&source_root, &params.source_filter)?;
+    if let Some(transforms) = &mut transforms {
+        transforms.prepare(&mut files)?;
+    }
     let total_bytes = files.iter().map(|entry| entry.size).sum::<u64>();
+    let mut dedupe = params.dedupe.then(|| Deduper::plan(&files)).transpose()?;
+    let verify = resolve_verify(VerifyTarget::FileCopies, params.hash_destination, total_bytes)?;
+    copier.hashing = copier.hashing.with_verify(&verify);
+    let hash_manifest = params.hash_manifest || verify.enabled();
 
     ensure_unix_boot_files(&files, current_os())?;
 
-    if let Some(free_bytes) = free_space_bytes(&target_mount)? {
+    // A volume about to be formatted is checked against its post-format
+    // capacity below instead of its current free space.
+    let free_bytes = match params.format_device {
+        Some(_) => None,
+        None => mount_free_space_bytes(&target_mount)?,
+    };
+    if let Some(free_bytes) = free_bytes {
         if free_bytes < total_bytes {
             return Err(anyhow!(
                 "insufficient free space: required {}, available {}",
@@ -543,115 +1296,293 @@ pub fn run_unix_installer_usb(params: &UnixInstallerUsbParams) -> Result<UnixIns
         }
     }
 
-    let mut logs = Vec::new();
-    logs.push("workflow=unix-installer-usb".to_string());
+    let mut logs = StepLog::new("unix-installer-usb");
     logs.push(format!("target_disk={}", disk.id));
+    if let Some(reason) = &target_size_acknowledged {
+        logs.push(format!("target_size_acknowledged={}", reason));
+    }
+    if let Some(reason) = &device_wear {
+        logs.push(format!("device_wear={}", reason));
+    }
+    for trigger in &overwrite_triggers {
+        logs.push(format!("overwrite_trigger={}", trigger));
+    }
     logs.push(format!("target_mount={}", target_mount.display()));
     logs.push(format!("source_path={}", source_root.display()));
     logs.push(format!("file_count={}", files.len()));
+    log_source_filter(&mut logs, &params.source_filter, excluded_files);
+    if let Some(transforms) = &transforms {
+        log_transforms(&mut logs, transforms);
+    }
     logs.push(format!("total_bytes={}", total_bytes));
+    log_verify(&mut logs, &verify);
+
+    let format_volume_bytes = params.format_device.as_ref().and_then(|device| {
+        if params.udisks {
+            partition_for_device(disk, device).map(|partition| partition.size_bytes)
+        } else {
+            params.format_size_bytes
+        }
+    });
+    if let Some(cluster_bytes) = params.format_cluster_bytes {
+        if params.format_device.is_none() {
+            return Err(anyhow!("format_cluster_bytes requires format_device"));
+        }
+        if params.udisks {
+            return Err(anyhow!("format_cluster_bytes is not supported with udisks"));
+        }
+        if let Some(volume_bytes) = format_volume_bytes {
+            check_cluster_bytes(FileSystem::Fat32, volume_bytes, cluster_bytes)?;
+        }
+    }
+    let format_capacity = match format_volume_bytes {
+        Some(volume_bytes) => Some(check_format_capacity(
+            &estimate_capacity(FileSystem::Fat32, volume_bytes, params.format_cluster_bytes),
+            &files,
+            &mut logs,
+        )?),
+        None => None,
+    };
+
+    let backend = match params.format_device {
+        Some(_) => Some(staging_backend(FileSystem::Fat32)),
+        None => staging_backend_for_mount(disk, &target_mount),
+    };
+    let mut name_warnings = Vec::new();
+    if let Some(backend) = backend {
+        if let Some(problem) = backend.file_size_problem(max_file_size(&files)) {
+            return Err(anyhow!(problem));
+        }
+        name_warnings =
+            backend.path_warnings(&mut files.iter().map(|entry| entry.relative_path.as_path()));
+        for warning in &name_warnings {
+            logs.push(format!("name_warning={}", warning));
+        }
+    }
 
     let mut copied_files = 0usize;
     let mut copied_bytes = 0u64;
     let mut artifacts = Vec::new();
     let mut artifact_names = Vec::new();
 
-    if !params.dry_run {
-        let ctx = SafetyContext {
-            force_mode: params.force,
-            confirmation_token: params.confirmation_token.clone(),
+    let ctx = SafetyContext {
+        force_mode: params.force,
+        confirmation_token: params.confirmation_token.clone(),
+        allow_system_disk: false,
+        armed_until: phoenix_safety::armed_until(),
+    };
+    let mut session = DestructiveSession::begin(
+        "unix-installer-usb",
+        Some(disk),
+        &ctx,
+        disk.is_system_disk,
+        params.dry_run,
+        &mut logs,
+    )?;
+    if let Some(device_path) = &params.format_device {
+        let mut format = FormatDevice {
+            disk,
+            device: device_path,
+            mount: &target_mount,
+            size_bytes: params.format_size_bytes,
+            label: params.format_label.as_deref(),
+            cluster_bytes: params.format_cluster_bytes,
+            udisks: params.udisks,
         };
-        match can_write_to_disk(&ctx, disk.is_system_disk) {
-            SafetyDecision::Allow => {}
-            SafetyDecision::Deny(reason) => return Err(anyhow!(reason)),
-        }
-
-        if let Some(device_path) = &params.format_device {
-            let size_bytes = params
-                .format_size_bytes
-                .ok_or_else(|| anyhow!("format_size_bytes required when format_device set"))?;
-            format_fat32(device_path, size_bytes, params.format_label.as_deref())?;
-            logs.push(format!("format_fat32={}", device_path.display()));
+        if let Some(mount) = session.perform(&mut format, &mut logs)? {
+            target_mount = mount;
         }
+    }
 
-        let test_path = target_mount.join(".phoenix_write_test");
-        fs::write(&test_path, b"")?;
-        fs::remove_file(&test_path).ok();
+    if !params.dry_run {
+        cleanup::write_test(&target_mount)?;
         logs.push("write_test=ok".to_string());
 
+        session.phase("copy", &mut logs)?;
         logs.push("copy_start".to_string());
+        let mut staged_bytes = 0u64;
         let mut copy_manifest = Vec::new();
-        for entry in &files {
+        for (index, entry) in files.iter().enumerate() {
             let dest_path = target_mount.join(&entry.relative_path);
             if let Some(parent) = dest_path.parent() {
                 fs::create_dir_all(parent)
                     .with_context(|| format!("create dir {}", parent.display()))?;
             }
-            fs::copy(&entry.absolute_path, &dest_path).with_context(|| {
-                format!(
-                    "copy {} to {}",
-                    entry.absolute_path.display(),
-                    dest_path.display()
-                )
-            })?;
+            let linked_to = dedupe
+                .as_mut()
+                .and_then(|dedupe| dedupe.try_link(&files, index, &target_mount, &dest_path));
+            let copied = match linked_to {
+                // A link shares the original's timestamps.
+                Some(_) => CopiedFile {
+                    mtime_unix: None,
+                    mtime_preserved: false,
+                    sha256: None,
+                    destination_sha256: None,
+                },
+                None => {
+                    let result = match transforms.as_ref().and_then(|t| t.get(index)) {
+                        Some((_, content)) => {
+                            copier.write(&entry.absolute_path, &dest_path, content)
+                        }
+                        None => copier.copy(&entry.absolute_path, &dest_path),
+                    };
+                    let result = result.with_context(|| {
+                        format!(
+                            "copy {} to {}",
+                            entry.absolute_path.display(),
+                            dest_path.display()
+                        )
+                    });
+                    let copied = match result {
+                        Ok(copied) => copied,
+                        Err(err) => {
+                            let failure = copy_errors.record(entry, &dest_path, err)?;
+                            logs.push(format!(
+                                "copy_failed={} side={} error={}",
+                                failure.path,
+                                failure.side.as_str(),
+                                failure.error
+                            ));
+                            staged_bytes = staged_bytes.saturating_add(entry.size);
+                            continue;
+                        }
+                    };
+                    copied_bytes = copied_bytes.saturating_add(entry.size);
+                    session.checkpoint(copier.durable_bytes());
+                    copied
+                }
+            };
             copied_files += 1;
-            copied_bytes = copied_bytes.saturating_add(entry.size);
-            if params.hash_manifest {
-                let hash = hash_file(&entry.absolute_path)?;
+            staged_bytes = staged_bytes.saturating_add(entry.size);
+            logs.progress(staged_bytes, total_bytes);
+            if hash_manifest {
+                // A link was not copied, so its hash is the original's.
+                let hash = match copied.sha256 {
+                    Some(hash) => hash,
+                    None => match dedupe.as_ref().and_then(|dedupe| dedupe.sha256(index)) {
+                        Some(hash) => hash.to_string(),
+                        None => hash_file(&entry.absolute_path)?,
+                    },
+                };
                 copy_manifest.push(CopyManifestEntry {
                     path: entry.relative_path.to_string_lossy().to_string(),
                     bytes: entry.size,
                     sha256: hash,
+                    original_sha256: transforms
+                        .as_ref()
+                        .and_then(|t| t.get(index))
+                        .map(|(file, _)| file.original_sha256.clone()),
+                    mtime_unix: copied.mtime_unix,
+                    mtime_preserved: copied.mtime_preserved,
+                    linked_to,
+                    destination_sha256: copied.destination_sha256,
                 });
             }
         }
+        copier.flush()?;
+        session.checkpoint(copier.durable_bytes());
         logs.push("copy_complete".to_string());
-        verify_copy(&target_mount, &files)?;
+        if let Some(dedupe) = &dedupe {
+            log_dedupe(&mut logs, dedupe.summary());
+        }
+        if !copy_errors.failures().is_empty() {
+            logs.push(format!("copy_failures={}", copy_errors.failures().len()));
+        }
+        session.phase("verify", &mut logs)?;
+        verify_copy(&target_mount, copy_errors.copied(&files))?;
         logs.push("verify_complete".to_string());
 
-        if params.hash_manifest && !copy_manifest.is_empty() {
-            let bytes = serde_json::to_vec_pretty(&copy_manifest)?;
-            artifacts.push(ReportArtifact {
-                name: "copy_manifest.json".to_string(),
-                bytes,
-            });
-            artifact_names.push("copy_manifest.json".to_string());
+        if hash_manifest && !copy_manifest.is_empty() {
+            let artifact = ReportArtifact::json("copy_manifest.json", &copy_manifest)?;
+            artifact_names.push(artifact.name.clone());
+            artifacts.push(artifact);
+        }
+
+        if params.power_off {
+            let device = match &params.format_device {
+                Some(device) => device.clone(),
+                None => partition_device_for_mount(disk, &params.target_mount)
+                    .ok_or_else(|| anyhow!("no partition device for target mount"))?,
+            };
+            if !phoenix_core::mock::is_active() {
+                udisks_power_off(&device)?;
+            }
+            logs.push(format!("power_off={}", device.display()));
         }
     } else {
         logs.push("dry_run=true".to_string());
+        if let Some(dedupe) = &dedupe {
+            log_dedupe(&mut logs, dedupe.summary());
+        }
+    }
+    if let Some(dedupe) = &dedupe {
+        push_dedupe_map(dedupe.summary(), &mut artifacts, &mut artifact_names)?;
+    }
+    copy_errors.push_artifact(&mut artifacts, &mut artifact_names)?;
+    if let Some(transforms) = &transforms {
+        transforms.push_artifact(&mut artifacts, &mut artifact_names)?;
     }
 
+    let (log_text, timing) = logs.finish()?;
+    artifact_names.push(timing.name.clone());
+    artifacts.push(timing);
+
     let meta = serde_json::json!({
         "workflow": "unix-installer-usb",
-        "status": if params.dry_run { "dry_run" } else { "completed" },
+        "status": copy_errors.status(params.dry_run),
         "target_disk_id": disk.id,
+        "target_serial": disk.serial,
         "target_mount": target_mount.display().to_string(),
         "source_path": source_root.display().to_string(),
+        "source_filter": params.source_filter,
+        "excluded_files": excluded_files,
         "copied_files": copied_files,
         "copied_bytes": copied_bytes,
+        "continue_on_error": params.continue_on_error,
+        "copy_failures": copy_errors.failures().len(),
+        "transform_pack": transforms.as_ref().map(|t| t.pack_name()),
+        "transformed_files": transforms.as_ref().map_or(0, |t| t.files().len()),
+        "dedupe": dedupe.as_ref().map(|dedupe| dedupe_meta(dedupe.summary())),
+        "flush_every_bytes": params.flush_every_bytes,
+        "flushes": copier.flushes(),
+        "verify_policy": verify,
+        "name_warnings": name_warnings,
+        "format_capacity": format_capacity,
+        "destructive_operations": session.operations(),
         "artifacts": artifact_names,
-        "dry_run": params.dry_run
+        "target_size_acknowledged": target_size_acknowledged,
+        "device_wear": device_wear,
+        "device_wear_acknowledged": params.acknowledge_device_wear,
+        "overwrite_triggers": overwrite_triggers,
+        "overwrite_confirmed": params.confirm_overwrite,
+        "dry_run": params.dry_run,
+        "duration_ms": started.elapsed().as_millis() as u64
     });
 
     let report = create_report_bundle_with_meta_signing_and_artifacts(
         &params.report_base,
         &graph,
         Some(meta),
-        Some(&logs.join("\n")),
+        Some(&log_text),
         signing_key_from_env().as_deref(),
         &artifacts,
     )?;
+    session.complete(&report.root)?;
 
     Ok(UnixInstallerUsbResult {
         report,
         target_mount,
         copied_files,
         copied_bytes,
+        format_capacity,
+        copy_failures: copy_errors.failures().to_vec(),
+        transformed_files: transforms.map(|t| t.files()).unwrap_or_default(),
         dry_run: params.dry_run,
     })
 }
 
 pub fn run_unix_write_image(params: &UnixWriteImageParams) -> Result<UnixWriteImageResult> {
+    let started = Instant::now();
     #[cfg(not(any(target_os = "linux", target_os = "macos")))]
     {
         return Err(anyhow!("unix image writer requires linux or macos"));
@@ -675,37 +1606,102 @@ pub fn run_unix_write_image(params: &UnixWriteImageParams) -> Result<UnixWriteIm
             disk.id
         ));
     }
+    if let Some(reason) = target::stack_usage(&graph, disk) {
+        return Err(anyhow!(reason));
+    }
+    let target_size_acknowledged = check_target_disk_size(disk, params.acknowledge_target_size)?;
+    let device_claim = check_target_claim(disk, "unix-write-image", params.dry_run)?;
+    let device_wear = join_reasons(
+        device_claim,
+        check_target_wear(disk, "unix-write-image", params.acknowledge_device_wear, params.dry_run)?,
+    );
+    let overwrite_triggers = check_overwrite(disk, params.confirm_overwrite, params.dry_run)?;
+
+    let source_url = image_url(params);
+    if source_url.is_none() && !params.source_mirrors.is_empty() {
+        return Err(anyhow!("source_mirrors need an http(s) source_image"));
+    }
+    let expected_sha256 = match &params.source_sha256 {
+        Some(digest) => Some(parse_sha256(digest)?),
+        None if source_url
+            .into_iter()
+            .chain(params.source_mirrors.iter().map(String::as_str))
+            .any(|url| !phoenix_fetch::is_https(url)) =>
+        {
+            return Err(anyhow!("plain http sources need source_sha256"));
+        }
+        None => None,
+    };
+    // Only a policy that samples needs the size of the disk in the image,
+    // and a streamed source is never sampled.
+    let policy = verify_policy()?;
+    let image_bytes = match (&policy, source_url) {
+        (Some(policy), None) if policy.sample_above.is_some() => {
+            phoenix_imaging::open_image(&params.source_image)?.disk_bytes
+        }
+        _ => 0,
+    };
+    let verify = apply_verify(policy.as_ref(), VerifyTarget::ImageWrites, params.verify, image_bytes);
 
-    let mut logs = Vec::new();
-    logs.push("workflow=unix-write-image".to_string());
+    let mut logs = StepLog::new("unix-write-image");
     logs.push(format!("target_device={}", params.target_device.display()));
+    if let Some(reason) = &target_size_acknowledged {
+        logs.push(format!("target_size_acknowledged={}", reason));
+    }
+    if let Some(reason) = &device_wear {
+        logs.push(format!("device_wear={}", reason));
+    }
+    for trigger in &overwrite_triggers {
+        logs.push(format!("overwrite_trigger={}", trigger));
+    }
     logs.push(format!("source_image={}", params.source_image.display()));
     logs.push(format!("verify={}", params.verify));
+    log_verify(&mut logs, &verify);
+    logs.push(format!("fast_io={}", params.fast_io));
     logs.push(format!("dry_run={}", params.dry_run));
 
     let mut bytes_written = 0u64;
     let mut sha256 = String::new();
     let mut verify_ok = None;
-
-    if !params.dry_run {
-        let ctx = SafetyContext {
-            force_mode: params.force,
-            confirmation_token: params.confirmation_token.clone(),
-        };
-        match can_write_to_disk(&ctx, disk.is_system_disk) {
-            SafetyDecision::Allow => {}
-            SafetyDecision::Deny(reason) => return Err(anyhow!(reason)),
-        }
-
-        let result = write_image_to_device(
-            &params.source_image,
-            &params.target_device,
-            params.chunk_size,
-            params.verify,
-        )?;
-        bytes_written = result.bytes_written;
-        sha256 = result.sha256;
-        verify_ok = result.verify_ok;
+    let mut chunk_size = params.chunk_size.unwrap_or(DEFAULT_CHUNK_SIZE);
+    let mut chunk_tuning = serde_json::Value::Null;
+    let mut throughput = 0u64;
+    let mut flushes = 0u64;
+    let mut partition_reread = None;
+    let mut mirrors = Vec::new();
+    let mut sampled_verify = None;
+
+    let ctx = SafetyContext {
+        force_mode: params.force,
+        confirmation_token: params.confirmation_token.clone(),
+        allow_system_disk: false,
+        armed_until: phoenix_safety::armed_until(),
+    };
+    let mut session = DestructiveSession::begin(
+        "unix-write-image",
+        Some(disk),
+        &ctx,
+        disk.is_system_disk,
+        params.dry_run,
+        &mut logs,
+    )?;
+    let mut write = WriteImage {
+        disk,
+        params,
+        expected_sha256: expected_sha256.as_deref(),
+        verify: &verify,
+    };
+    if let Some(written) = session.perform(&mut write, &mut logs)? {
+        chunk_size = written.chunk_size;
+        chunk_tuning = written.chunk_tuning;
+        throughput = written.throughput_bytes_per_sec;
+        partition_reread = written.partition_reread;
+        mirrors = written.mirrors;
+        sampled_verify = written.sampled_verify;
+        flushes = written.result.flushes;
+        bytes_written = written.result.bytes_written;
+        sha256 = written.result.sha256;
+        verify_ok = written.result.verify_ok;
         logs.push(format!("bytes_written={}", bytes_written));
         logs.push(format!("sha256={}", sha256));
         if let Some(ok) = verify_ok {
@@ -713,24 +1709,48 @@ pub fn run_unix_write_image(params: &UnixWriteImageParams) -> Result<UnixWriteIm
         }
     }
 
+    let (log_text, timing) = logs.finish()?;
+
     let meta = serde_json::json!({
         "workflow": "unix-write-image",
         "target_device": params.target_device.display().to_string(),
+        "target_serial": disk.serial,
         "source_image": params.source_image.display().to_string(),
+        "source_streamed": source_url.is_some(),
+        "source_mirrors": mirrors,
+        "source_sha256": expected_sha256,
         "bytes_written": bytes_written,
         "sha256": sha256,
         "verify": params.verify,
         "verify_ok": verify_ok,
-        "dry_run": params.dry_run
+        "verify_policy": verify,
+        "sampled_verify": sampled_verify,
+        "target_size_acknowledged": target_size_acknowledged,
+        "device_wear": device_wear,
+        "device_wear_acknowledged": params.acknowledge_device_wear,
+        "overwrite_triggers": overwrite_triggers,
+        "overwrite_confirmed": params.confirm_overwrite,
+        "dry_run": params.dry_run,
+        "fast_io": params.fast_io,
+        "flush_every_bytes": params.flush_every_bytes,
+        "flushes": flushes,
+        "partition_reread": partition_reread,
+        "chunk_size": chunk_size,
+        "chunk_tuning": chunk_tuning,
+        "throughput_bytes_per_sec": throughput,
+        "destructive_operations": session.operations(),
+        "duration_ms": started.elapsed().as_millis() as u64
     });
 
-    let report = create_report_bundle_with_meta_and_signing(
+    let report = create_report_bundle_with_meta_signing_and_artifacts(
         &params.report_base,
         &graph,
         Some(meta),
-        Some(&logs.join("\n")),
+        Some(&log_text),
         signing_key_from_env().as_deref(),
+        &[timing],
     )?;
+    session.complete(&report.root)?;
 
     Ok(UnixWriteImageResult {
         report,
@@ -738,12 +1758,14 @@ pub fn run_unix_write_image(params: &UnixWriteImageParams) -> Result<UnixWriteIm
         sha256,
         verify_ok,
         dry_run: params.dry_run,
+        chunk_size,
+        throughput_bytes_per_sec: throughput,
     })
 }
 
 pub fn run_macos_installer_usb(params: &MacosInstallerUsbParams) -> Result<MacosInstallerUsbResult> {
-    #[cfg(not(target_os = "macos"))]
-    {
+    let started = Instant::now();
+    if !cfg!(target_os = "macos") {
         return Err(anyhow!("macos installer workflow requires macOS"));
     }
 
@@ -765,6 +1787,16 @@ pub fn run_macos_installer_usb(params: &MacosInstallerUsbParams) -> Result<Macos
             disk.id
         ));
     }
+    if let Some(reason) = target::stack_usage(&graph, disk) {
+        return Err(anyhow!(reason));
+    }
+    let target_size_acknowledged = check_target_disk_size(disk, params.acknowledge_target_size)?;
+    let device_claim = check_target_claim(disk, "macos-installer-usb", params.dry_run)?;
+    let device_wear = join_reasons(
+        device_claim,
+        check_target_wear(disk, "macos-installer-usb", params.acknowledge_device_wear, params.dry_run)?,
+    );
+    let overwrite_triggers = check_overwrite(disk, params.confirm_overwrite, params.dry_run)?;
 
     let fs = params
         .filesystem
@@ -772,67 +1804,89 @@ pub fn run_macos_installer_usb(params: &MacosInstallerUsbParams) -> Result<Macos
         .or_else(|| params.macos_version.as_deref().and_then(select_macos_fs))
         .unwrap_or_else(|| "APFS".to_string());
 
-    let mut logs = Vec::new();
-    logs.push("workflow=macos-installer-usb".to_string());
+    let mut logs = StepLog::new("macos-installer-usb");
     logs.push(format!("target_device={}", params.target_device.display()));
+    if let Some(reason) = &target_size_acknowledged {
+        logs.push(format!("target_size_acknowledged={}", reason));
+    }
+    if let Some(reason) = &device_wear {
+        logs.push(format!("device_wear={}", reason));
+    }
+    for trigger in &overwrite_triggers {
+        logs.push(format!("overwrite_trigger={}", trigger));
+    }
     logs.push(format!("volume_name={}", params.volume_name));
     logs.push(format!("filesystem={}", fs));
     logs.push(format!("dry_run={}", params.dry_run));
 
+    if let Some(model) = &params.target_model {
+        logs.push(format!("target_model={}", model));
+    }
+    let mut installer_support = None;
+    if is_macos_app(&params.source_path) {
+        installer_support = Some(check_installer_app(&params.source_path, params, &mut logs)?);
+    }
+
     let mut mode = "unknown".to_string();
     let mut target_volume = PathBuf::from(format!("/Volumes/{}", params.volume_name));
 
-    if !params.dry_run {
-        let ctx = SafetyContext {
-            force_mode: params.force,
-            confirmation_token: params.confirmation_token.clone(),
-        };
-        match can_write_to_disk(&ctx, disk.is_system_disk) {
-            SafetyDecision::Allow => {}
-            SafetyDecision::Deny(reason) => return Err(anyhow!(reason)),
-        }
-
-        let source_path = params.source_path.clone();
-        if source_path.extension().and_then(|e| e.to_str()).map(|e| e.eq_ignore_ascii_case("dmg")).unwrap_or(false) {
-            let mounted = mount_dmg(&source_path)?;
-            if let Some(app) = find_install_app(&mounted.mount_point) {
-                mode = "createinstallmedia".to_string();
-                logs.push(format!("installer_app={}", app.display()));
-                erase_disk(&params.target_device, &fs, &params.volume_name)?;
-                target_volume = PathBuf::from(format!("/Volumes/{}", params.volume_name));
-                run_createinstallmedia(&app, &target_volume)?;
-            } else {
-                mode = "asr_restore".to_string();
-                run_asr_restore(&source_path, &params.target_device)?;
-            }
-            drop(mounted);
-        } else if is_macos_app(&source_path) {
-            mode = "createinstallmedia".to_string();
-            erase_disk(&params.target_device, &fs, &params.volume_name)?;
-            target_volume = PathBuf::from(format!("/Volumes/{}", params.volume_name));
-            run_createinstallmedia(&source_path, &target_volume)?;
-        } else {
-            return Err(anyhow!("unsupported macos source path"));
+    let ctx = SafetyContext {
+        force_mode: params.force,
+        confirmation_token: params.confirmation_token.clone(),
+        allow_system_disk: false,
+        armed_until: phoenix_safety::armed_until(),
+    };
+    let mut session = DestructiveSession::begin(
+        "macos-installer-usb",
+        Some(disk),
+        &ctx,
+        disk.is_system_disk,
+        params.dry_run,
+        &mut logs,
+    )?;
+    let mut media = MacosInstallerMedia {
+        disk,
+        params,
+        filesystem: &fs,
+    };
+    if let Some(written) = session.perform(&mut media, &mut logs)? {
+        mode = written.mode.to_string();
+        target_volume = written.target_volume;
+        if written.installer_support.is_some() {
+            installer_support = written.installer_support;
         }
     }
 
+    let (log_text, timing) = logs.finish()?;
+
     let meta = serde_json::json!({
         "workflow": "macos-installer-usb",
         "status": if params.dry_run { "dry_run" } else { "completed" },
         "target_device": params.target_device.display().to_string(),
+        "target_serial": disk.serial,
         "volume_name": params.volume_name,
         "filesystem": fs,
         "mode": mode,
-        "dry_run": params.dry_run
+        "target_model": params.target_model,
+        "installer_support": installer_support,
+        "target_size_acknowledged": target_size_acknowledged,
+        "device_wear": device_wear,
+        "device_wear_acknowledged": params.acknowledge_device_wear,
+        "overwrite_triggers": overwrite_triggers,
+        "overwrite_confirmed": params.confirm_overwrite,
+        "dry_run": params.dry_run,
+        "duration_ms": started.elapsed().as_millis() as u64
     });
 
-    let report = create_report_bundle_with_meta_and_signing(
+    let report = create_report_bundle_with_meta_signing_and_artifacts(
         &params.report_base,
         &graph,
         Some(meta),
-        Some(&logs.join("\n")),
+        Some(&log_text),
         signing_key_from_env().as_deref(),
+        &[timing],
     )?;
+    session.complete(&report.root)?;
 
     Ok(MacosInstallerUsbResult {
         report,
@@ -842,7 +1896,31 @@ pub fn run_macos_installer_usb(params: &MacosInstallerUsbParams) -> Result<Macos
     })
 }
 
+/// Logs what the installer app runs on and refuses it when it cannot boot
+/// `params.target_model`.
+fn check_installer_app(
+    app: &Path,
+    params: &MacosInstallerUsbParams,
+    logs: &mut StepLog,
+) -> Result<InstallerSupport> {
+    let support = inspect_installer_app(app)?;
+    if let Some(version) = &support.macos_version {
+        logs.push(format!("installer_version={}", version));
+    }
+    for arch in &support.architectures {
+        logs.push(format!("installer_arch={}", arch.as_str()));
+    }
+    if let Some(model) = &params.target_model {
+        check_installer_for_model(&support, model)?;
+    }
+    Ok(support)
+}
+
 pub fn run_stage_bootloader(params: &BootloaderStageParams) -> Result<BootloaderStageResult> {
+    // Boot files stay far below any sampling threshold.
+    let verify = resolve_verify(VerifyTarget::FileCopies, params.hash_destination, 0)?;
+    let hash_manifest = params.hash_manifest || verify.enabled();
+    let hashing = CopyHashing::new(hash_manifest, params.hash_destination)?.with_verify(&verify);
     let graph = build_device_graph()?;
     let target_mount = normalize_mount_for_unix(&params.target_mount);
     if !target_mount.exists() || !target_mount.is_dir() {
@@ -868,9 +1946,9 @@ pub fn run_stage_bootloader(params: &BootloaderStageParams) -> Result<Bootloader
         target_mount.clone()
     };
 
-    let mut logs = Vec::new();
-    logs.push("workflow=stage-bootloader".to_string());
+    let mut logs = StepLog::new("stage-bootloader");
     logs.push(format!("target_mount={}", target_mount.display()));
+    log_verify(&mut logs, &verify);
     logs.push(format!("source_path={}", package.root.display()));
     logs.push(format!("entries={}", package.boot_entries.len()));
 
@@ -879,36 +1957,133 @@ pub fn run_stage_bootloader(params: &BootloaderStageParams) -> Result<Bootloader
     let mut artifacts = Vec::new();
     let mut artifact_names = Vec::new();
 
+    let enrollment = if params.mok_certificate.is_some()
+        || params.mok_hashes
+        || params.mok_manager.is_some()
+    {
+        let enrollment = plan_mok_enrollment(
+            &package,
+            params.mok_certificate.as_deref(),
+            params.mok_hashes,
+            params.mok_manager.as_deref(),
+            params.target_subdir.as_deref(),
+        )?;
+        if let Some(certificate) = &enrollment.certificate {
+            logs.push(format!("mok_certificate sha256={}", certificate.fingerprint));
+        }
+        for hash in &enrollment.hashes {
+            logs.push(format!("mok_hash {} {}", hash.media_path, hash.sha256));
+        }
+        if let Some(manager) = &enrollment.mok_manager {
+            logs.push(format!("mok_manager={}", manager.media_path));
+        }
+        for artifact in [
+            ReportArtifact::json("mok_enrollment.json", &enrollment)?,
+            ReportArtifact::bytes("secure_boot_enrollment.txt", enrollment.guide().into_bytes()),
+        ] {
+            artifact_names.push(artifact.name.clone());
+            artifacts.push(artifact);
+        }
+        Some(enrollment)
+    } else {
+        None
+    };
+
+    let grub_dir = params
+        .grub_dir
+        .clone()
+        .unwrap_or_else(|| phoenix_bootcfg::DEFAULT_GRUB_DIR.to_string());
+    let grub_pack = match &params.grub_pack {
+        Some(path) => {
+            let manifest = load_pack_manifest(path)?;
+            let menu = manifest
+                .grub
+                .ok_or_else(|| anyhow!("pack {} declares no grub menu", manifest.name))?;
+            let base = path
+                .parent()
+                .ok_or_else(|| anyhow!("pack manifest has no parent directory"))?
+                .to_path_buf();
+            logs.push(format!(
+                "grub_menu pack={} entries={} theme={}",
+                manifest.name,
+                menu.entries.len(),
+                menu.theme.as_deref().unwrap_or("-")
+            ));
+            let theme_path = (menu.theme.is_some() || menu.title.is_some()).then(|| {
+                phoenix_bootcfg::theme_path(
+                    &grub_dir,
+                    &manifest.name,
+                    params.target_subdir.as_deref(),
+                )
+            });
+            let preview = format!(
+                "{}{}",
+                phoenix_bootcfg::render_branding(&menu, theme_path.as_deref(), &[]),
+                phoenix_bootcfg::render_entries(&menu.entries)
+            );
+            let artifact = ReportArtifact::bytes("grub_menu.cfg", preview.into_bytes());
+            artifact_names.push(artifact.name.clone());
+            artifacts.push(artifact);
+            Some((manifest.name, menu, base))
+        }
+        None => None,
+    };
+    let mut grub_menu = None;
+
     if !params.dry_run {
         let ctx = SafetyContext {
             force_mode: params.force,
             confirmation_token: params.confirmation_token.clone(),
+            allow_system_disk: false,
+            armed_until: phoenix_safety::armed_until(),
         };
         match can_write_to_disk(&ctx, disk.is_system_disk) {
             SafetyDecision::Allow => {}
             SafetyDecision::Deny(reason) => return Err(anyhow!(reason)),
+            SafetyDecision::ReadOnly(err) => return Err(err.into()),
         }
 
-        let test_path = target_mount.join(".phoenix_write_test");
-        fs::write(&test_path, b"")?;
-        fs::remove_file(&test_path).ok();
+        cleanup::write_test(&target_mount)?;
 
-        let stats = copy_dir_recursive(&package.root, &staging_root, params.hash_manifest)?;
+        let stats = copy_dir_recursive(&package.root, &staging_root, hashing)?;
         copied_files = stats.files;
         copied_bytes = stats.bytes;
-        if params.hash_manifest && !stats.manifest.is_empty() {
-            let bytes = serde_json::to_vec_pretty(&stats.manifest)?;
-            artifacts.push(ReportArtifact {
-                name: "bootloader_manifest.json".to_string(),
-                bytes,
-            });
-            artifact_names.push("bootloader_manifest.json".to_string());
+        if hash_manifest && !stats.manifest.is_empty() {
+            let artifact = ReportArtifact::json("bootloader_manifest.json", &stats.manifest)?;
+            artifact_names.push(artifact.name.clone());
+            artifacts.push(artifact);
+        }
+        if let Some(enrollment) = &enrollment {
+            for file in enrollment.stage(&staging_root)? {
+                logs.push(format!("mok_staged={}", file));
+            }
+        }
+        if let Some((name, menu, base)) = &grub_pack {
+            let staged = phoenix_bootcfg::stage_grub_menu(
+                menu,
+                base,
+                name,
+                &staging_root,
+                &grub_dir,
+                params.target_subdir.as_deref(),
+            )?;
+            logs.push(format!(
+                "grub_config={} replaced_menu={} theme_files={}",
+                staged.config.display(),
+                staged.replaced_menu,
+                staged.theme_files
+            ));
+            grub_menu = Some(staged);
         }
         logs.push(format!("staged_to={}", staging_root.display()));
     } else {
         logs.push("dry_run=true".to_string());
     }
 
+    let (log_text, timing) = logs.finish()?;
+    artifact_names.push(timing.name.clone());
+    artifacts.push(timing);
+
     let meta = serde_json::json!({
         "workflow": "stage-bootloader",
         "status": if params.dry_run { "dry_run" } else { "completed" },
@@ -916,6 +2091,9 @@ pub fn run_stage_bootloader(params: &BootloaderStageParams) -> Result<Bootloader
         "staging_root": staging_root.display().to_string(),
         "copied_files": copied_files,
         "copied_bytes": copied_bytes,
+        "verify_policy": verify,
+        "mok_enrollment": enrollment,
+        "grub_menu": grub_menu,
         "artifacts": artifact_names,
         "dry_run": params.dry_run
     });
@@ -924,7 +2102,7 @@ pub fn run_stage_bootloader(params: &BootloaderStageParams) -> Result<Bootloader
         &params.report_base,
         &graph,
         Some(meta),
-        Some(&logs.join("\n")),
+        Some(&log_text),
         signing_key_from_env().as_deref(),
         &artifacts,
     )?;
@@ -938,10 +2116,13 @@ pub fn run_stage_bootloader(params: &BootloaderStageParams) -> Result<Bootloader
 }
 
 pub fn run_macos_kext_stage(params: &MacosKextStageParams) -> Result<MacosKextStageResult> {
-    #[cfg(not(target_os = "macos"))]
-    {
+    if !cfg!(target_os = "macos") {
         return Err(anyhow!("macos kext staging requires macOS"));
     }
+    // Boot files stay far below any sampling threshold.
+    let verify = resolve_verify(VerifyTarget::FileCopies, params.hash_destination, 0)?;
+    let hash_manifest = params.hash_manifest || verify.enabled();
+    let hashing = CopyHashing::new(hash_manifest, params.hash_destination)?.with_verify(&verify);
 
     let graph = build_device_graph()?;
     let target_mount = normalize_mount_for_unix(&params.target_mount);
@@ -977,9 +2158,9 @@ pub fn run_macos_kext_stage(params: &MacosKextStageParams) -> Result<MacosKextSt
         target_mount.join("EFI/OC/Kexts")
     };
 
-    let mut logs = Vec::new();
-    logs.push("workflow=macos-kext-stage".to_string());
+    let mut logs = StepLog::new("macos-kext-stage");
     logs.push(format!("target_mount={}", target_mount.display()));
+    log_verify(&mut logs, &verify);
     logs.push(format!("source_path={}", source_root.display()));
     logs.push(format!("kext_count={}", kexts.len()));
 
@@ -992,41 +2173,43 @@ pub fn run_macos_kext_stage(params: &MacosKextStageParams) -> Result<MacosKextSt
         let ctx = SafetyContext {
             force_mode: params.force,
             confirmation_token: params.confirmation_token.clone(),
+            allow_system_disk: false,
+            armed_until: phoenix_safety::armed_until(),
         };
         match can_write_to_disk(&ctx, disk.is_system_disk) {
             SafetyDecision::Allow => {}
             SafetyDecision::Deny(reason) => return Err(anyhow!(reason)),
+            SafetyDecision::ReadOnly(err) => return Err(err.into()),
         }
 
         fs::create_dir_all(&staging_root)?;
-        let test_path = target_mount.join(".phoenix_write_test");
-        fs::write(&test_path, b"")?;
-        fs::remove_file(&test_path).ok();
+        cleanup::write_test(&target_mount)?;
 
         let mut manifest = Vec::new();
         for kext in kexts {
             let dest = staging_root.join(kext.file_name().unwrap_or_default());
-            let stats = copy_dir_recursive(&kext, &dest, params.hash_manifest)?;
+            let stats = copy_dir_recursive(&kext, &dest, hashing)?;
             copied_files += stats.files;
             copied_bytes += stats.bytes;
-            if params.hash_manifest {
+            if hash_manifest {
                 manifest.extend(stats.manifest);
             }
             logs.push(format!("staged_kext={}", dest.display()));
         }
 
-        if params.hash_manifest && !manifest.is_empty() {
-            let bytes = serde_json::to_vec_pretty(&manifest)?;
-            artifacts.push(ReportArtifact {
-                name: "kext_manifest.json".to_string(),
-                bytes,
-            });
-            artifact_names.push("kext_manifest.json".to_string());
+        if hash_manifest && !manifest.is_empty() {
+            let artifact = ReportArtifact::json("kext_manifest.json", &manifest)?;
+            artifact_names.push(artifact.name.clone());
+            artifacts.push(artifact);
         }
     } else {
         logs.push("dry_run=true".to_string());
     }
 
+    let (log_text, timing) = logs.finish()?;
+    artifact_names.push(timing.name.clone());
+    artifacts.push(timing);
+
     let meta = serde_json::json!({
         "workflow": "macos-kext-stage",
         "status": if params.dry_run { "dry_run" } else { "completed" },
@@ -1034,6 +2217,7 @@ pub fn run_macos_kext_stage(params: &MacosKextStageParams) -> Result<MacosKextSt
         "staging_root": staging_root.display().to_string(),
         "copied_files": copied_files,
         "copied_bytes": copied_bytes,
+        "verify_policy": verify,
         "artifacts": artifact_names,
         "dry_run": params.dry_run
     });
@@ -1042,7 +2226,7 @@ pub fn run_macos_kext_stage(params: &MacosKextStageParams) -> Result<MacosKextSt
         &params.report_base,
         &graph,
         Some(meta),
-        Some(&logs.join("\n")),
+        Some(&log_text),
         signing_key_from_env().as_deref(),
         &artifacts,
     )?;
@@ -1060,6 +2244,10 @@ pub fn run_unix_boot_prep(params: &UnixBootPrepParams) -> Result<UnixBootPrepRes
     {
         return Err(anyhow!("unix boot prep requires linux or macos"));
     }
+    // Boot files stay far below any sampling threshold.
+    let verify = resolve_verify(VerifyTarget::FileCopies, params.hash_destination, 0)?;
+    let hash_manifest = params.hash_manifest || verify.enabled();
+    let hashing = CopyHashing::new(hash_manifest, params.hash_destination)?.with_verify(&verify);
 
     let graph = build_device_graph()?;
     let target_mount = normalize_mount_for_unix(&params.target_mount);
@@ -1091,10 +2279,10 @@ pub fn run_unix_boot_prep(params: &UnixBootPrepParams) -> Result<UnixBootPrepRes
         return Err(anyhow!("no boot prep candidates found in source"));
     }
 
-    let mut logs = Vec::new();
-    logs.push("workflow=unix-boot-prep".to_string());
+    let mut logs = StepLog::new("unix-boot-prep");
     logs.push(format!("target_disk={}", disk.id));
     logs.push(format!("target_mount={}", target_mount.display()));
+    log_verify(&mut logs, &verify);
     logs.push(format!("source_path={}", source_root.display()));
 
     let mut copied_files = 0usize;
@@ -1106,15 +2294,16 @@ pub fn run_unix_boot_prep(params: &UnixBootPrepParams) -> Result<UnixBootPrepRes
         let ctx = SafetyContext {
             force_mode: params.force,
             confirmation_token: params.confirmation_token.clone(),
+            allow_system_disk: false,
+            armed_until: phoenix_safety::armed_until(),
         };
         match can_write_to_disk(&ctx, disk.is_system_disk) {
             SafetyDecision::Allow => {}
             SafetyDecision::Deny(reason) => return Err(anyhow!(reason)),
+            SafetyDecision::ReadOnly(err) => return Err(err.into()),
         }
 
-        let test_path = target_mount.join(".phoenix_write_test");
-        fs::write(&test_path, b"")?;
-        fs::remove_file(&test_path).ok();
+        cleanup::write_test(&target_mount)?;
         logs.push("write_test=ok".to_string());
 
         let mut copy_manifest = Vec::new();
@@ -1126,7 +2315,7 @@ pub fn run_unix_boot_prep(params: &UnixBootPrepParams) -> Result<UnixBootPrepRes
             }
 
             if candidate.is_dir {
-                let stats = copy_dir_recursive(&candidate.source, &target_path, params.hash_manifest)?;
+                let stats = copy_dir_recursive(&candidate.source, &target_path, hashing)?;
                 copied_files += stats.files;
                 copied_bytes += stats.bytes;
                 copy_manifest.extend(stats.manifest);
@@ -1134,42 +2323,49 @@ pub fn run_unix_boot_prep(params: &UnixBootPrepParams) -> Result<UnixBootPrepRes
                 if let Some(parent) = target_path.parent() {
                     fs::create_dir_all(parent)?;
                 }
-                fs::copy(&candidate.source, &target_path)?;
+                let copied = copy_file(&candidate.source, &target_path, hashing)?;
                 copied_files += 1;
                 let size = fs::metadata(&candidate.source)?.len();
                 copied_bytes = copied_bytes.saturating_add(size);
-                if params.hash_manifest {
-                    let hash = hash_file(&candidate.source)?;
+                if let Some(hash) = copied.sha256 {
                     copy_manifest.push(CopyManifestEntry {
                         path: candidate.relative.to_string(),
                         bytes: size,
                         sha256: hash,
+                        original_sha256: None,
+                        mtime_unix: copied.mtime_unix,
+                        mtime_preserved: copied.mtime_preserved,
+                        linked_to: None,
+                        destination_sha256: copied.destination_sha256,
                     });
                 }
             }
             logs.push(format!("copied={}", candidate.relative));
         }
 
-        if params.hash_manifest && !copy_manifest.is_empty() {
-            let bytes = serde_json::to_vec_pretty(&copy_manifest)?;
-            artifacts.push(ReportArtifact {
-                name: "bootprep_manifest.json".to_string(),
-                bytes,
-            });
-            artifact_names.push("bootprep_manifest.json".to_string());
+        if hash_manifest && !copy_manifest.is_empty() {
+            let artifact = ReportArtifact::json("bootprep_manifest.json", &copy_manifest)?;
+            artifact_names.push(artifact.name.clone());
+            artifacts.push(artifact);
         }
     } else {
         logs.push("dry_run=true".to_string());
     }
 
+    let (log_text, timing) = logs.finish()?;
+    artifact_names.push(timing.name.clone());
+    artifacts.push(timing);
+
     let meta = serde_json::json!({
         "workflow": "unix-boot-prep",
         "status": if params.dry_run { "dry_run" } else { "completed" },
         "target_disk_id": disk.id,
+        "target_serial": disk.serial,
         "target_mount": target_mount.display().to_string(),
         "source_path": source_root.display().to_string(),
         "copied_files": copied_files,
         "copied_bytes": copied_bytes,
+        "verify_policy": verify,
         "artifacts": artifact_names,
         "dry_run": params.dry_run
     });
@@ -1178,7 +2374,7 @@ pub fn run_unix_boot_prep(params: &UnixBootPrepParams) -> Result<UnixBootPrepRes
         &params.report_base,
         &graph,
         Some(meta),
-        Some(&logs.join("\n")),
+        Some(&log_text),
         signing_key_from_env().as_deref(),
         &artifacts,
     )?;
@@ -1191,15 +2387,19 @@ pub fn run_unix_boot_prep(params: &UnixBootPrepParams) -> Result<UnixBootPrepRes
     })
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct WorkflowStepResult {
     pub id: String,
     pub action: String,
     pub report_root: Option<PathBuf>,
     pub duration_ms: u128,
+    /// Completed earlier under the same idempotency key; not re-run.
+    pub reused: bool,
+    /// Pre/post hooks that ran around the step.
+    pub hooks: Vec<HookRun>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct WorkflowRunResult {
     pub report: ReportPaths,
     pub steps: Vec<WorkflowStepResult>,
@@ -1210,162 +2410,370 @@ pub fn run_workflow_definition(
     default_report_base: Option<PathBuf>,
 ) -> Result<Vec<WorkflowStepResult>> {
     validate_workflow_definition(definition)?;
+    require_workflow_host(definition)?;
+    phoenix_report::with_correlation_id(definition.correlation_id.as_deref(), || {
+        run_workflow_steps(definition, default_report_base)
+    })
+}
+
+fn run_workflow_steps(
+    definition: &WorkflowDefinition,
+    default_report_base: Option<PathBuf>,
+) -> Result<Vec<WorkflowStepResult>> {
     let base = default_report_base.unwrap_or_else(|| PathBuf::from("."));
-    let mut results = Vec::new();
+    watchdog::with_time_limit(format!("run {}", definition.name), definition.timeout_secs, || {
+        let mut results = Vec::new();
+        for step in &definition.steps {
+            results.push(watchdog::with_time_limit(
+                format!("step {}", step.id),
+                step.timeout_secs,
+                || run_definition_step(definition, step, &base),
+            )?);
+        }
+        Ok(results)
+    })
+}
 
-    for step in &definition.steps {
-        let start = Instant::now();
-        match step.action.as_str() {
-            "windows_installer_usb" => {
-                let params = build_usb_params(&step.params, &base)?;
-                let result = run_windows_installer_usb(&params)?;
-                results.push(WorkflowStepResult {
-                    id: step.id.clone(),
-                    action: step.action.clone(),
-                    report_root: Some(result.report.root),
-                    duration_ms: start.elapsed().as_millis(),
-                });
-            }
-            "windows_apply_image" => {
-                let params = build_apply_params(&step.params, &base)?;
-                let result = run_windows_apply_image(&params)?;
-                results.push(WorkflowStepResult {
-                    id: step.id.clone(),
-                    action: step.action.clone(),
-                    report_root: Some(result.report.root),
-                    duration_ms: start.elapsed().as_millis(),
-                });
-            }
-            "linux_installer_usb" => {
-                let params = build_unix_usb_params(&step.params, &base)?;
-                let result = run_unix_installer_usb(&params)?;
-                results.push(WorkflowStepResult {
-                    id: step.id.clone(),
-                    action: step.action.clone(),
-                    report_root: Some(result.report.root),
-                    duration_ms: start.elapsed().as_millis(),
-                });
-            }
-            "macos_installer_usb" => {
-                let params = build_unix_usb_params(&step.params, &base)?;
-                let result = run_unix_installer_usb(&params)?;
-                results.push(WorkflowStepResult {
-                    id: step.id.clone(),
-                    action: step.action.clone(),
-                    report_root: Some(result.report.root),
-                    duration_ms: start.elapsed().as_millis(),
-                });
-            }
-            "linux_write_image" => {
-                let params = build_unix_write_params(&step.params, &base)?;
-                let result = run_unix_write_image(&params)?;
-                results.push(WorkflowStepResult {
-                    id: step.id.clone(),
-                    action: step.action.clone(),
-                    report_root: Some(result.report.root),
-                    duration_ms: start.elapsed().as_millis(),
-                });
-            }
-            "macos_write_image" => {
-                let params = build_unix_write_params(&step.params, &base)?;
-                let result = run_unix_write_image(&params)?;
-                results.push(WorkflowStepResult {
-                    id: step.id.clone(),
-                    action: step.action.clone(),
-                    report_root: Some(result.report.root),
-                    duration_ms: start.elapsed().as_millis(),
-                });
-            }
-            "linux_boot_prep" => {
-                let params = build_unix_boot_params(&step.params, &base)?;
-                let result = run_unix_boot_prep(&params)?;
-                results.push(WorkflowStepResult {
-                    id: step.id.clone(),
-                    action: step.action.clone(),
-                    report_root: Some(result.report.root),
-                    duration_ms: start.elapsed().as_millis(),
-                });
-            }
-            "macos_boot_prep" => {
-                let params = build_unix_boot_params(&step.params, &base)?;
-                let result = run_unix_boot_prep(&params)?;
-                results.push(WorkflowStepResult {
-                    id: step.id.clone(),
-                    action: step.action.clone(),
-                    report_root: Some(result.report.root),
-                    duration_ms: start.elapsed().as_millis(),
-                });
-            }
-            "macos_installer_usb" => {
-                let params = build_macos_installer_params(&step.params, &base)?;
-                let result = run_macos_installer_usb(&params)?;
-                results.push(WorkflowStepResult {
-                    id: step.id.clone(),
-                    action: step.action.clone(),
-                    report_root: Some(result.report.root),
-                    duration_ms: start.elapsed().as_millis(),
-                });
-            }
-            "stage_bootloader" => {
-                let params = build_stage_bootloader_params(&step.params, &base)?;
-                let result = run_stage_bootloader(&params)?;
-                results.push(WorkflowStepResult {
-                    id: step.id.clone(),
-                    action: step.action.clone(),
-                    report_root: Some(result.report.root),
-                    duration_ms: start.elapsed().as_millis(),
-                });
-            }
-            "macos_legacy_patch" => {
-                let params = build_legacy_patch_params(&step.params, &base)?;
-                let result = phoenix_legacy_patcher::run_legacy_patch(&params)?;
-                results.push(WorkflowStepResult {
-                    id: step.id.clone(),
-                    action: step.action.clone(),
-                    report_root: Some(result.report.root),
-                    duration_ms: start.elapsed().as_millis(),
-                });
-            }
-            "macos_kext_stage" => {
-                let params = build_kext_stage_params(&step.params, &base)?;
-                let result = run_macos_kext_stage(&params)?;
-                results.push(WorkflowStepResult {
-                    id: step.id.clone(),
-                    action: step.action.clone(),
-                    report_root: Some(result.report.root),
-                    duration_ms: start.elapsed().as_millis(),
-                });
+fn run_definition_step(
+    definition: &WorkflowDefinition,
+    step: &WorkflowStep,
+    base: &Path,
+) -> Result<WorkflowStepResult> {
+    cancel::check_cancelled()?;
+    let start = Instant::now();
+    let idempotent = step_idempotency(definition, step)?;
+    if let Some((ledger, key, disk)) = &idempotent {
+        if let Some(root) = find_completed_step(ledger, key, &step.action, disk)? {
+            return Ok(WorkflowStepResult {
+                id: step.id.clone(),
+                action: step.action.clone(),
+                report_root: Some(root),
+                duration_ms: start.elapsed().as_millis(),
+                reused: true,
+                hooks: Vec::new(),
+            });
+        }
+    }
+
+    let step_hooks = hooks::step_hooks(step)?.unwrap_or_default();
+    let mut hook_runs = Vec::new();
+    hooks::run_hooks(step, HookPhase::Pre, &step_hooks.pre, None, &mut hook_runs)?;
+    let report_root = run_workflow_step(step, base)?;
+    hooks::run_hooks(
+        step,
+        HookPhase::Post,
+        &step_hooks.post,
+        report_root.as_deref(),
+        &mut hook_runs,
+    )?;
+    if let (Some((ledger, key, disk)), Some(root)) = (&idempotent, &report_root) {
+        ledger.record_idempotent(&IdempotencyRecord {
+            key: key.clone(),
+            action: step.action.clone(),
+            target_disk: disk.id.clone(),
+            target_serial: disk.serial.clone(),
+            report_root: root.display().to_string(),
+            completed_unix: ledger::now_unix(),
+        })?;
+    }
+    Ok(WorkflowStepResult {
+        id: step.id.clone(),
+        action: step.action.clone(),
+        report_root,
+        duration_ms: start.elapsed().as_millis(),
+        reused: false,
+        hooks: hook_runs,
+    })
+}
+
+fn run_workflow_step(step: &WorkflowStep, base: &Path) -> Result<Option<PathBuf>> {
+    let base = base.to_path_buf();
+    // Resolved here so only the running step sees secret values.
+    let step_params = secrets::resolve_secret_params(&step.params)?;
+    let step_params = assets::resolve_asset_params(&step_params)?;
+    let report_root = match step.action.as_str() {
+        "windows_installer_usb" => {
+            let params = build_usb_params(&step_params, &base)?;
+            let result = run_windows_installer_usb(&params)?;
+            Some(result.report.root)
+        }
+        "windows_apply_image" => {
+            let params = build_apply_params(&step_params, &base)?;
+            let result = run_windows_apply_image(&params)?;
+            Some(result.report.root)
+        }
+        "linux_installer_usb" => {
+            let params = build_unix_usb_params(&step_params, &base)?;
+            let result = run_unix_installer_usb(&params)?;
+            Some(result.report.root)
+        }
+        "linux_write_image" => {
+            let params = build_unix_write_params(&step_params, &base)?;
+            let result = run_unix_write_image(&params)?;
+            Some(result.report.root)
+        }
+        "macos_write_image" => {
+            let params = build_unix_write_params(&step_params, &base)?;
+            let result = run_unix_write_image(&params)?;
+            Some(result.report.root)
+        }
+        "linux_boot_prep" => {
+            let params = build_unix_boot_params(&step_params, &base)?;
+            let result = run_unix_boot_prep(&params)?;
+            Some(result.report.root)
+        }
+        "macos_boot_prep" => {
+            let params = build_unix_boot_params(&step_params, &base)?;
+            let result = run_unix_boot_prep(&params)?;
+            Some(result.report.root)
+        }
+        "macos_installer_usb" => {
+            let params = build_macos_installer_params(&step_params, &base)?;
+            let result = run_macos_installer_usb(&params)?;
+            Some(result.report.root)
+        }
+        "ipsw_restore" => {
+            let params = build_ipsw_restore_params(&step_params, &base)?;
+            let result = run_ipsw_restore(&params)?;
+            Some(result.report.root)
+        }
+        "macos_erase_install" => {
+            let params = build_macos_erase_install_params(&step_params, &base)?;
+            let result = run_macos_erase_install(&params)?;
+            Some(result.report.root)
+        }
+        "stage_bootloader" => {
+            let params = build_stage_bootloader_params(&step_params, &base)?;
+            let result = run_stage_bootloader(&params)?;
+            Some(result.report.root)
+        }
+        "stage_files" => {
+            let params = build_stage_files_params(&step_params, &base)?;
+            let result = run_stage_files(&params)?;
+            Some(result.report.root)
+        }
+        "stage_firstboot" => {
+            let params = build_stage_firstboot_params(&step_params, &base)?;
+            let result = run_stage_firstboot(&params)?;
+            Some(result.report.root)
+        }
+        "stage_provisioning" => {
+            let params = build_stage_provisioning_params(&step_params, &base)?;
+            let result = run_stage_provisioning(&params)?;
+            Some(result.report.root)
+        }
+        "boot_entry" => {
+            let params = build_boot_entry_params(&step_params, &base)?;
+            let result = run_boot_entry(&params)?;
+            Some(result.report.root)
+        }
+        "macos_legacy_patch" => {
+            let params = build_legacy_patch_params(&step_params, &base)?;
+            let result = phoenix_legacy_patcher::run_legacy_patch(&params)?;
+            Some(result.report.root)
+        }
+        "macos_kext_stage" => {
+            let params = build_kext_stage_params(&step_params, &base)?;
+            let result = run_macos_kext_stage(&params)?;
+            Some(result.report.root)
+        }
+        "report_verify" => {
+            let (path, key) = build_verify_params(&step_params)?;
+            let verification = phoenix_report::verify_report_bundle(path, key.as_deref())?;
+            if !verification.ok {
+                return Err(anyhow!("report verification failed"));
             }
-            "report_verify" => {
-                let (path, key) = build_verify_params(&step.params)?;
-                let verification = phoenix_report::verify_report_bundle(path, key.as_deref())?;
-                if !verification.ok {
-                    return Err(anyhow!("report verification failed"));
-                }
-                results.push(WorkflowStepResult {
-                    id: step.id.clone(),
-                    action: step.action.clone(),
-                    report_root: None,
-                    duration_ms: start.elapsed().as_millis(),
-                });
+            None
+        }
+        "disk_hash_report" => {
+            let params = build_hash_params(&step_params, &base)?;
+            let result = run_disk_hash_report(&params)?;
+            if let Some(drift) = result.drift.as_ref().filter(|drift| drift.drifted) {
+                return Err(anyhow!(
+                    "disk drifted from baseline {}: {} bytes in {} ranges changed (report {})",
+                    drift.baseline,
+                    drift.changed_bytes,
+                    drift.changed_ranges.len(),
+                    result.report.root.display()
+                ));
             }
-            "disk_hash_report" => {
-                let params = build_hash_params(&step.params, &base)?;
-                let result = run_disk_hash_report(&params)?;
-                results.push(WorkflowStepResult {
-                    id: step.id.clone(),
-                    action: step.action.clone(),
-                    report_root: Some(result.report.root),
-                    duration_ms: start.elapsed().as_millis(),
-                });
+            Some(result.report.root)
+        }
+        "bad_block_scan" => {
+            let params = build_bad_block_scan_params(&step_params, &base)?;
+            let result = run_bad_block_scan(&params)?;
+            if !result.passed() {
+                return Err(anyhow!(
+                    "{} has {} bad bytes in {} ranges (report {})",
+                    result.disk_id,
+                    result.scan.bad_bytes(),
+                    result.scan.bad_ranges.len(),
+                    result.report.root.display()
+                ));
             }
-            other => {
-                return Err(anyhow!("unknown workflow action {}", other));
+            Some(result.report.root)
+        }
+        "combo_stick" => {
+            let params = build_combo_stick_params(&step_params, &base)?;
+            let result = run_combo_stick(&params)?;
+            Some(result.report.root)
+        }
+        "ab_stick" => {
+            let params = build_ab_stick_params(&step_params, &base)?;
+            let result = run_ab_stick(&params)?;
+            Some(result.report.root)
+        }
+        "ab_update" => {
+            let params = build_ab_update_params(&step_params, &base)?;
+            let result = run_ab_update(&params)?;
+            Some(result.report.root)
+        }
+        "clone_device" => {
+            let params = build_clone_device_params(&step_params, &base)?;
+            let result = run_clone_device(&params)?;
+            Some(result.report.root)
+        }
+        "restore_report" => {
+            let params = build_restore_report_params(&step_params, &base)?;
+            let result = run_restore_report(&params)?;
+            Some(result.report.root)
+        }
+        "resize_partition" => {
+            let params = build_resize_partition_params(&step_params, &base)?;
+            let result = run_resize_partition(&params)?;
+            Some(result.report.root)
+        }
+        "validate_source" => {
+            let params = build_validate_source_params(&step_params, &base)?;
+            let result = run_validate_source(&params)?;
+            if !result.problems.is_empty() {
+                return Err(anyhow!(
+                    "source invalid: {} (report {})",
+                    result.problems.join("; "),
+                    result.report.root.display()
+                ));
             }
+            Some(result.report.root)
+        }
+        "slim_windows_media" => {
+            let params = build_slim_media_params(&step_params, &base)?;
+            let result = run_slim_windows_media(&params)?;
+            Some(result.report.root)
+        }
+        "merge_windows_languages" => {
+            let params = build_merge_languages_params(&step_params, &base)?;
+            let result = run_merge_windows_languages(&params)?;
+            Some(result.report.root)
+        }
+        other => {
+            return Err(anyhow!("unknown workflow action {}", other));
         }
+    };
+    Ok(report_root)
+}
+
+/// Destructive actions whose target disk an idempotency key is recorded
+/// against. An `idempotency_key` param on any other action is refused.
+const IDEMPOTENT_ACTIONS: &[&str] = &[
+    "windows_installer_usb",
+    "windows_apply_image",
+    "linux_installer_usb",
+    "linux_write_image",
+    "macos_write_image",
+    "macos_installer_usb",
+    "clone_device",
+    "restore_report",
+    "combo_stick",
+    "ab_stick",
+    "ab_update",
+    "resize_partition",
+    "bad_block_scan",
+];
+
+/// Destructive steps run with an idempotency key (the step's own
+/// `idempotency_key`, else the definition's key suffixed with the step id)
+/// resolve their target disk so a retried submission can be matched to an
+/// earlier completed run. Dry runs, read-only scans and steps without a
+/// disk target opt out.
+fn step_idempotency(
+    definition: &WorkflowDefinition,
+    step: &WorkflowStep,
+) -> Result<Option<(RunLedger, String, phoenix_core::Disk)>> {
+    let key = match optional_string(&step.params, "idempotency_key") {
+        Some(key) => key.to_string(),
+        None => match &definition.idempotency_key {
+            Some(key) => format!("{}/{}", key, step.id),
+            None => return Ok(None),
+        },
+    };
+    if optional_bool(&step.params, "dry_run", true) {
+        return Ok(None);
+    }
+    let params = &step.params;
+    // A read-only scan changes nothing worth deduplicating.
+    if step.action == "bad_block_scan"
+        && !optional_string(params, "mode").is_some_and(|mode| mode.eq_ignore_ascii_case("write"))
+    {
+        return Ok(None);
     }
+    if !IDEMPOTENT_ACTIONS.contains(&step.action.as_str()) {
+        return Ok(None);
+    }
+    let graph = build_device_graph()?;
+    let by_id = |key: &str| {
+        optional_string(params, key)
+            .and_then(|id| graph.disks.iter().find(|disk| disk.id.eq_ignore_ascii_case(id)))
+    };
+    let by_mount = |key: &str| {
+        optional_string(params, key).and_then(|mount| find_disk_by_mount(&graph, Path::new(mount)))
+    };
+    let disk = match step.action.as_str() {
+        "windows_installer_usb" | "clone_device" | "restore_report" => by_id("target_disk_id"),
+        "linux_installer_usb" => by_mount("target_mount"),
+        "windows_apply_image" => by_mount("target_dir"),
+        "linux_write_image" | "macos_write_image" | "macos_installer_usb" => {
+            optional_string(params, "target_device")
+                .and_then(|device| disk_id_from_device_path(Path::new(device)))
+                .and_then(|id| graph.disks.iter().find(|disk| disk.id.eq_ignore_ascii_case(&id)))
+        }
+        _ => by_id("disk_id"),
+    };
+    let disk = disk.ok_or_else(|| anyhow!("step {}: target disk not found", step.id))?;
+    Ok(Some((RunLedger::open_default()?, key, disk.clone())))
+}
 
-    Ok(results)
+/// Report root of an earlier completed run for `key`. The same key against a
+/// different action or disk is refused rather than silently re-run.
+fn find_completed_step(
+    ledger: &RunLedger,
+    key: &str,
+    action: &str,
+    disk: &phoenix_core::Disk,
+) -> Result<Option<PathBuf>> {
+    let Some(record) = ledger.find_idempotent(key)? else {
+        return Ok(None);
+    };
+    let same_target = match (&record.target_serial, &disk.serial) {
+        (Some(recorded), Some(current)) => recorded == current,
+        _ => record.target_disk.eq_ignore_ascii_case(&disk.id),
+    };
+    if record.action != action || !same_target {
+        return Err(anyhow!(
+            "idempotency_key {} was already used for {} on {}",
+            key,
+            record.action,
+            record.target_serial.as_deref().unwrap_or(&record.target_disk)
+        ));
+    }
+    let root = PathBuf::from(&record.report_root);
+    if !root.exists() {
+        return Err(anyhow!(
+            "idempotency_key {} completed earlier but its report {} is gone",
+            key,
+            root.display()
+        ));
+    }
+    Ok(Some(root))
 }
 
 pub fn run_workflow_definition_with_report(
@@ -1373,7 +2781,19 @@ pub fn run_workflow_definition_with_report(
     report_base: PathBuf,
 ) -> Result<WorkflowRunResult> {
     validate_workflow_definition(definition)?;
-    let steps = run_workflow_definition(definition, Some(report_base.clone()))?;
+    require_workflow_host(definition)?;
+    phoenix_report::with_correlation_id(definition.correlation_id.as_deref(), || {
+        run_workflow_with_report(definition, report_base)
+    })
+}
+
+fn run_workflow_with_report(
+    definition: &WorkflowDefinition,
+    report_base: PathBuf,
+) -> Result<WorkflowRunResult> {
+    let mut logs = StepLog::new(&definition.name);
+    // Step spans nest under the definition's span.
+    let steps = logs.in_trace(|| run_workflow_steps(definition, Some(report_base.clone())))?;
     let graph = build_device_graph()?;
 
     let step_meta: Vec<serde_json::Value> = steps
@@ -1383,101 +2803,136 @@ pub fn run_workflow_definition_with_report(
                 "id": step.id,
                 "action": step.action,
                 "duration_ms": step.duration_ms,
-                "report_root": step.report_root.as_ref().map(|p| p.display().to_string())
+                "reused": step.reused,
+                "report_root": step.report_root.as_ref().map(|p| p.display().to_string()),
+                "hooks": step.hooks
             })
         })
         .collect();
 
-    let mut logs = Vec::new();
-    logs.push(format!("workflow={}", definition.name));
     for step in &steps {
         logs.push(format!(
-            "step={} action={} duration_ms={}",
-            step.id, step.action, step.duration_ms
+            "step={} action={} duration_ms={} reused={}",
+            step.id, step.action, step.duration_ms, step.reused
         ));
+        for hook in &step.hooks {
+            logs.push(format!(
+                "step={} hook={:?} index={} program={} exit_code={:?} timed_out={}",
+                step.id, hook.phase, hook.index, hook.program, hook.exit_code, hook.timed_out
+            ));
+        }
     }
+    let mut artifacts: Vec<ReportArtifact> = steps
+        .iter()
+        .flat_map(|step| {
+            step.hooks.iter().flat_map(move |hook| {
+                let prefix = hook.artifact_prefix(&step.id);
+                [
+                    ReportArtifact::bytes(format!("{}.stdout.txt", prefix), hook.stdout.clone()),
+                    ReportArtifact::bytes(format!("{}.stderr.txt", prefix), hook.stderr.clone()),
+                ]
+            })
+        })
+        .collect();
+
+    let (log_text, timing) = logs.finish()?;
+    artifacts.push(timing);
 
     let meta = serde_json::json!({
         "workflow": definition.name,
         "schema_version": definition.schema_version,
+        "idempotency_key": definition.idempotency_key,
+        "tools": check_workflow_tools(definition)?,
         "steps": step_meta
     });
 
-    let report = create_report_bundle_with_meta_and_signing(
+    let report = create_report_bundle_with_meta_signing_and_artifacts(
         &report_base,
         &graph,
         Some(meta),
-        Some(&logs.join("\n")),
+        Some(&log_text),
         signing_key_from_env().as_deref(),
+        &artifacts,
     )?;
 
     Ok(WorkflowRunResult { report, steps })
 }
 
+/// Checks a definition for this host: `plan_workflow_definition`, then
+/// `require_host_support`.
 pub fn validate_workflow_definition(definition: &WorkflowDefinition) -> Result<()> {
-    if definition.schema_version != WORKFLOW_SCHEMA_VERSION {
-        return Err(anyhow!(
-            "unsupported workflow schema version {}",
-            definition.schema_version
-        ));
-    }
-    if definition.steps.is_empty() {
-        return Err(anyhow!("workflow has no steps"));
+    plan_workflow_definition(definition)?;
+    require_host_support(definition)
+}
+
+/// Every check that does not depend on the host: the definition's shape
+/// and each step's params. Any OS can plan any workflow.
+pub fn plan_workflow_definition(definition: &WorkflowDefinition) -> Result<WorkflowPlan> {
+    let plan = phoenix_workflow_plan::plan_workflow(definition)?;
+    for step in &definition.steps {
+        validate_step(step)?;
     }
+    Ok(plan)
+}
 
-    let mut seen = std::collections::HashSet::new();
+/// Fails fast when this host cannot execute a planned definition, instead
+/// of erroring after earlier steps have already written to disk: the OS of
+/// each step, the host capabilities it uses and its external tools.
+pub fn require_host_support(definition: &WorkflowDefinition) -> Result<()> {
+    let capabilities = host_capabilities();
     for step in &definition.steps {
-        if step.id.trim().is_empty() {
-            return Err(anyhow!("workflow step id is empty"));
+        if let Some(os) = action_os(&step.action) {
+            ensure_os(os).map_err(|err| anyhow!("step {}: {}", step.id, err))?;
         }
-        if !seen.insert(step.id.clone()) {
-            return Err(anyhow!("duplicate step id {}", step.id));
+        for need in step_capabilities(step)? {
+            capabilities
+                .require(need.name, need.dry_run)
+                .map_err(|err| anyhow!("step {}: {}", step.id, err))?;
         }
-        validate_step(step)?;
     }
+    require_workflow_tools(definition)?;
     Ok(())
 }
 
 fn validate_step(step: &phoenix_core::WorkflowStep) -> Result<()> {
+    hooks::step_hooks(step)?;
+    step_requirements(step)?;
     match step.action.as_str() {
         "windows_installer_usb" => {
-            ensure_os("windows")?;
             require_string(&step.params, "target_disk_id")?;
             require_string(&step.params, "source_path")?;
+            parse_partition_specs(&step.params)?;
+            if let Some(edition) = optional_string(&step.params, "edition") {
+                parse_edition_id(edition)?;
+            }
+            // A secret:// key is checked once resolved, when the step runs.
+            if let Some(key) = optional_string(&step.params, "pid_txt")
+                .filter(|key| !key.starts_with(secrets::SECRET_SCHEME))
+            {
+                parse_product_key(key)?;
+            }
         }
         "windows_apply_image" => {
-            ensure_os("windows")?;
-            require_string(&step.params, "source_path")?;
-            require_u32(&step.params, "image_index")?;
-            require_string(&step.params, "target_dir")?;
+            build_apply_params(&step.params, Path::new("."))?;
         }
         "linux_installer_usb" => {
-            ensure_os("linux")?;
-            require_string(&step.params, "source_path")?;
-            require_string(&step.params, "target_mount")?;
-        }
-        "macos_installer_usb" => {
-            ensure_os("macos")?;
-            require_string(&step.params, "source_path")?;
-            require_string(&step.params, "target_mount")?;
+            check_udisks_options(&build_unix_usb_params(&step.params, Path::new("."))?)?;
         }
         "linux_write_image" => {
-            ensure_os("linux")?;
             require_string(&step.params, "source_image")?;
             require_string(&step.params, "target_device")?;
+            optional_chunk_size(&step.params)?;
         }
         "macos_write_image" => {
-            ensure_os("macos")?;
             require_string(&step.params, "source_image")?;
             require_string(&step.params, "target_device")?;
+            optional_chunk_size(&step.params)?;
         }
         "linux_boot_prep" => {
-            ensure_os("linux")?;
             require_string(&step.params, "source_path")?;
             require_string(&step.params, "target_mount")?;
         }
         "macos_boot_prep" => {
-            ensure_os("macos")?;
             require_string(&step.params, "source_path")?;
             require_string(&step.params, "target_mount")?;
         }
@@ -1485,17 +2940,31 @@ fn validate_step(step: &phoenix_core::WorkflowStep) -> Result<()> {
             require_string(&step.params, "source_path")?;
             require_string(&step.params, "target_mount")?;
         }
+        "stage_files" => {
+            build_stage_files_params(&step.params, Path::new("."))?;
+        }
+        "stage_provisioning" => {
+            build_stage_provisioning_params(&step.params, Path::new("."))?;
+        }
+        "stage_firstboot" => {
+            build_stage_firstboot_params(&step.params, Path::new("."))?;
+        }
+        "boot_entry" => {
+            build_boot_entry_params(&step.params, Path::new("."))?;
+        }
         "macos_installer_usb" => {
-            ensure_os("macos")?;
-            require_string(&step.params, "source_path")?;
-            require_string(&step.params, "target_device")?;
+            build_macos_installer_params(&step.params, Path::new("."))?;
+        }
+        "ipsw_restore" => {
+            build_ipsw_restore_params(&step.params, Path::new("."))?;
+        }
+        "macos_erase_install" => {
+            build_macos_erase_install_params(&step.params, Path::new("."))?;
         }
         "macos_legacy_patch" => {
-            ensure_os("macos")?;
             require_string(&step.params, "source_path")?;
         }
         "macos_kext_stage" => {
-            ensure_os("macos")?;
             require_string(&step.params, "source_path")?;
             require_string(&step.params, "target_mount")?;
         }
@@ -1504,11 +2973,58 @@ fn validate_step(step: &phoenix_core::WorkflowStep) -> Result<()> {
         }
         "disk_hash_report" => {
             require_string(&step.params, "disk_id")?;
+            optional_chunk_size(&step.params)?;
+            for key in ["compare_baseline", "save_baseline"] {
+                if let Some(name) = optional_string(&step.params, key) {
+                    baseline::validate_baseline_name(name)?;
+                }
+            }
+        }
+        "bad_block_scan" => {
+            build_bad_block_scan_params(&step.params, Path::new("."))?;
+        }
+        "combo_stick" => {
+            build_combo_stick_params(&step.params, Path::new("."))?;
+        }
+        "ab_stick" => {
+            build_ab_stick_params(&step.params, Path::new("."))?;
+        }
+        "ab_update" => {
+            build_ab_update_params(&step.params, Path::new("."))?;
+        }
+        "clone_device" => {
+            build_clone_device_params(&step.params, Path::new("."))?;
+        }
+        "restore_report" => {
+            build_restore_report_params(&step.params, Path::new("."))?;
+        }
+        "resize_partition" => {
+            build_resize_partition_params(&step.params, Path::new("."))?;
+        }
+        "validate_source" => {
+            require_string(&step.params, "source_path")?;
+            parse_filesystem_value(optional_string(&step.params, "filesystem").unwrap_or("fat32"))?;
+        }
+        "slim_windows_media" => {
+            require_string(&step.params, "source_path")?;
+            media_keep_list(&step.params)?;
+        }
+        "merge_windows_languages" => {
+            require_string(&step.params, "source_path")?;
+            if optional_string_list(&step.params, "language_sources")?.is_empty() {
+                return Err(anyhow!("language_sources must list at least one source"));
+            }
+            optional_string_list(&step.params, "languages")?;
         }
         other => {
             return Err(anyhow!("unknown workflow action {}", other));
         }
     }
+    if optional_string(&step.params, "idempotency_key").is_some()
+        && !IDEMPOTENT_ACTIONS.contains(&step.action.as_str())
+    {
+        return Err(anyhow!("{} does not support idempotency_key", step.action));
+    }
     Ok(())
 }
 
@@ -1532,19 +3048,34 @@ fn current_os() -> &'static str {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct WindowsApplyImageParams {
     pub source_path: PathBuf,
-    pub image_index: u32,
+    /// Needed unless `edition_selector` picks the image; checked against
+    /// its match when both are given.
+    #[serde(default)]
+    pub image_index: Option<u32>,
+    /// Index, EditionID or image name; see `editions`.
+    #[serde(default)]
+    pub edition_selector: Option<String>,
     pub target_dir: PathBuf,
+    #[serde(default = "crate::params::default_report_base")]
     pub report_base: PathBuf,
+    #[serde(default)]
     pub force: bool,
+    #[serde(default)]
     pub confirmation_token: Option<String>,
+    /// Policy opt-in for applying onto the running system's disk; also
+    /// needs a `PHX-SYS-` token.
+    #[serde(default)]
+    pub allow_system_target: bool,
+    #[serde(default = "crate::params::default_true")]
     pub dry_run: bool,
+    #[serde(default)]
     pub verify: bool,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct WindowsApplyImageResult {
     pub report: ReportPaths,
     pub target_dir: PathBuf,
@@ -1583,49 +3114,79 @@ pub fn run_windows_apply_image(params: &WindowsApplyImageParams) -> Result<Windo
 
     let (image_path, _prepared) = resolve_windows_image(&params.source_path)?;
     let images = wim_list_images(&image_path)?;
+    let selection = params
+        .edition_selector
+        .as_deref()
+        .map(|selector| select_edition(&images, selector))
+        .transpose()?;
+    let image_index = match (&selection, params.image_index) {
+        (Some(selection), Some(index)) if selection.image.index != index => {
+            return Err(anyhow!(
+                "image_index {} disagrees with edition_selector {} (index {})",
+                index,
+                selection.selector,
+                selection.image.index
+            ))
+        }
+        (Some(selection), _) => selection.image.index,
+        (None, Some(index)) => index,
+        (None, None) => return Err(anyhow!("image_index or edition_selector is required")),
+    };
     let image_info = images
         .iter()
-        .find(|image| image.index == params.image_index)
+        .find(|image| image.index == image_index)
         .ok_or_else(|| anyhow!("image index not found"))?;
 
-    let mut logs = Vec::new();
-    logs.push("workflow=windows-apply-image".to_string());
+    // The size check reads no file data, so there is nothing to sample.
+    let verify = resolve_verify(VerifyTarget::ImageWrites, params.verify, 0)?;
+
+    let mut logs = StepLog::new("windows-apply-image");
     logs.push(format!("image_path={}", image_path.display()));
-    logs.push(format!("image_index={}", params.image_index));
+    if let Some(selection) = &selection {
+        logs.push(format!(
+            "edition_selector={} matched_by={}",
+            selection.selector, selection.matched_by
+        ));
+    }
+    logs.push(format!("image_index={}", image_index));
     logs.push(format!("target_dir={}", params.target_dir.display()));
+    logs.push(format!("system_target={}", is_system_target));
+    log_verify(&mut logs, &verify);
     logs.push(format!("dry_run={}", params.dry_run));
 
+    // The session targets a directory, so it cannot check the disk itself.
     if !params.dry_run {
-        let ctx = SafetyContext {
-            force_mode: params.force,
-            confirmation_token: params.confirmation_token.clone(),
-        };
-        match can_write_to_disk(&ctx, is_system_target) {
-            SafetyDecision::Allow => {}
-            SafetyDecision::Deny(reason) => return Err(anyhow!(reason)),
-        }
-
-        if !params.target_dir.exists() {
-            fs::create_dir_all(&params.target_dir).context("create target dir")?;
-        }
-        if let Some(expected) = image_info.total_bytes {
-            if let Ok(free_bytes) = free_space_bytes(&params.target_dir.display().to_string()) {
-                if free_bytes < expected {
-                    return Err(anyhow!(
-                        "insufficient free space: required {}, available {}",
-                        expected,
-                        free_bytes
-                    ));
-                }
-            }
+        if let Some(disk) = find_disk_by_mount(&graph, &params.target_dir) {
+            operation::refuse_booted_esp(disk)?;
         }
-        wim_apply_image(&image_path, params.image_index, &params.target_dir)?;
+    }
+    let ctx = SafetyContext {
+        force_mode: params.force,
+        confirmation_token: params.confirmation_token.clone(),
+        allow_system_disk: params.allow_system_target,
+        armed_until: phoenix_safety::armed_until(),
+    };
+    let mut session = DestructiveSession::begin(
+        "windows-apply-image",
+        None,
+        &ctx,
+        is_system_target,
+        params.dry_run,
+        &mut logs,
+    )?;
+    let mut apply = ApplyImage {
+        image: &image_path,
+        index: image_index,
+        target_dir: &params.target_dir,
+        expected_bytes: image_info.total_bytes,
+    };
+    if session.perform(&mut apply, &mut logs)?.is_some() {
         logs.push("apply_complete".to_string());
     } else {
         logs.push("apply_skipped_dry_run".to_string());
     }
 
-    let stats = if params.verify && !params.dry_run {
+    let stats = if verify.enabled() && !params.dry_run {
         let stats = dir_stats(&params.target_dir)?;
         if let Some(expected) = image_info.total_bytes {
             let tolerance = expected / 100;
@@ -1647,27 +3208,37 @@ pub fn run_windows_apply_image(params: &WindowsApplyImageParams) -> Result<Windo
         }
     };
 
+    let (log_text, timing) = logs.finish()?;
+
     let meta = serde_json::json!({
         "workflow": "windows-apply-image",
         "status": if params.dry_run { "dry_run" } else { "completed" },
         "image_path": image_path.display().to_string(),
-        "image_index": params.image_index,
+        "image_index": image_index,
         "image_name": image_info.name,
         "image_description": image_info.description,
+        "image_edition_id": image_info.edition_id,
+        "image_architecture": image_info.architecture,
+        "edition_selection": selection,
         "target_dir": params.target_dir.display().to_string(),
         "verify": params.verify,
+        "verify_policy": verify,
         "file_count": stats.file_count,
         "total_bytes": stats.total_bytes,
+        "wim_backend": phoenix_wim::backend().ok().map(|backend| backend.as_str()),
+        "destructive_operations": session.operations(),
         "dry_run": params.dry_run
     });
 
-    let report = create_report_bundle_with_meta_and_signing(
+    let report = create_report_bundle_with_meta_signing_and_artifacts(
         &params.report_base,
         &graph,
         Some(meta),
-        Some(&logs.join("\n")),
+        Some(&log_text),
         signing_key_from_env().as_deref(),
+        &[timing],
     )?;
+    session.complete(&report.root)?;
 
     Ok(WindowsApplyImageResult {
         report,
@@ -1678,19 +3249,38 @@ pub fn run_windows_apply_image(params: &WindowsApplyImageParams) -> Result<Windo
     })
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DiskHashReportParams {
     pub disk_id: String,
-    pub chunk_size: u64,
+    /// `None` probes the device for the fastest chunk size.
+    #[serde(default, with = "crate::params::chunk_size")]
+    pub chunk_size: Option<u64>,
+    #[serde(default)]
     pub max_chunks: Option<u64>,
+    #[serde(default = "crate::params::default_report_base")]
     pub report_base: PathBuf,
+    /// Hashes only this partition of the disk, at the offset the device
+    /// graph reports for it.
+    #[serde(default)]
+    pub partition_id: Option<String>,
+    /// Baseline to compare the chunk map against; its chunk size is used
+    /// unless `chunk_size` is set.
+    #[serde(default)]
+    pub compare_baseline: Option<String>,
+    /// Stores the chunk map under this name, after any comparison.
+    #[serde(default)]
+    pub save_baseline: Option<String>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DiskHashReportResult {
     pub report: ReportPaths,
     pub disk_id: String,
+    pub partition_id: Option<String>,
     pub chunk_count: usize,
+    pub chunk_size: u64,
+    pub throughput_bytes_per_sec: u64,
+    pub drift: Option<BaselineDrift>,
 }
 
 pub fn run_disk_hash_report(params: &DiskHashReportParams) -> Result<DiskHashReportResult> {
@@ -1701,70 +3291,375 @@ pub fn run_disk_hash_report(params: &DiskHashReportParams) -> Result<DiskHashRep
         .find(|disk| disk.id.eq_ignore_ascii_case(&params.disk_id))
         .ok_or_else(|| anyhow!("disk not found: {}", params.disk_id))?;
 
-    let plan = make_chunk_plan(disk.size_bytes, params.chunk_size);
+    #[cfg(target_os = "windows")]
+    let device_path = format!(r"\\.\{}", disk.id);
+    #[cfg(not(target_os = "windows"))]
+    let device_path = if phoenix_core::mock::is_active() {
+        phoenix_core::mock::disk_file(disk)?.display().to_string()
+    } else {
+        format!("/dev/{}", disk.id)
+    };
+    #[cfg(target_os = "windows")]
+    if phoenix_core::mock::is_active() {
+        return Err(anyhow!("disk_hash_report is not simulated on Windows"));
+    }
+
+    // Byte range hashed, and the id the chunk map is recorded under.
+    let (range_offset, range_len, source) = match &params.partition_id {
+        Some(partition_id) => {
+            let partition = disk
+                .partitions
+                .iter()
+                .find(|partition| partition.id.eq_ignore_ascii_case(partition_id))
+                .ok_or_else(|| anyhow!("partition {} not found on {}", partition_id, disk.id))?;
+            let offset = partition.offset_bytes.ok_or_else(|| {
+                anyhow!("host did not report the offset of partition {}", partition.id)
+            })?;
+            (offset, partition.size_bytes, partition.id.clone())
+        }
+        None => (0, disk.size_bytes, disk.id.clone()),
+    };
+    if let Some(name) = &params.save_baseline {
+        baseline::validate_baseline_name(name)?;
+    }
+    let baseline = params
+        .compare_baseline
+        .as_deref()
+        .map(baseline::load_baseline)
+        .transpose()?;
+
+    let mut logs = StepLog::new("disk-hash-report");
+    let (chunk_size, chunk_tuning) = match (&baseline, params.chunk_size) {
+        (Some(baseline), None) => {
+            logs.push(format!("chunk_size={} source=baseline", baseline.chunk_size));
+            (baseline.chunk_size, serde_json::json!({ "source": "baseline" }))
+        }
+        _ => resolve_chunk_size(
+            params.chunk_size,
+            Path::new(&device_path),
+            range_len,
+            &mut logs,
+        ),
+    };
+    if params.partition_id.is_some() {
+        logs.push(format!(
+            "partition={} offset={} length={}",
+            source, range_offset, range_len
+        ));
+    }
+    let started = std::time::Instant::now();
     let hashes = {
         #[cfg(target_os = "windows")]
         {
-            hash_disk_readonly_physicaldrive(
+            hash_disk_range_readonly_physicaldrive(
                 &disk.id,
-                disk.size_bytes,
-                params.chunk_size,
+                range_offset,
+                range_len,
+                chunk_size,
                 params.max_chunks,
             )?
         }
         #[cfg(not(target_os = "windows"))]
         {
-            let device_path = format!("/dev/{}", disk.id);
-            hash_device_readonly(
+            hash_device_range_readonly(
                 &device_path,
-                disk.size_bytes,
-                params.chunk_size,
+                range_offset,
+                range_len,
+                chunk_size,
                 params.max_chunks,
             )?
         }
     };
-
-    let entries: Vec<DiskHashEntry> = hashes
-        .into_iter()
-        .filter_map(|(index, sha256)| {
-            let chunk = plan.get(index as usize)?;
-            Some(DiskHashEntry {
-                index,
-                offset: chunk.offset,
-                length: chunk.size,
-                sha256,
-            })
-        })
-        .collect();
-
-    let artifact = ReportArtifact {
-        name: "disk_hashes.json".to_string(),
-        bytes: serde_json::to_vec_pretty(&entries)?,
+    let hashed_bytes = range_len.min((hashes.len() as u64).saturating_mul(chunk_size));
+    let throughput = bytes_per_sec(hashed_bytes, started.elapsed());
+    logs.push(format!("throughput_bytes_per_sec={}", throughput));
+
+    let hashmap = ChunkHashMap::from_hashes(chunk_size, range_len, hashes)?.with_source(source);
+
+    let mut artifacts = vec![ReportArtifact::bytes(HASHMAP_FILE_NAME, hashmap.to_json_bytes()?)];
+
+    let drift = match (&params.compare_baseline, &baseline) {
+        (Some(name), Some(baseline)) => {
+            let drift = baseline::compare_to_baseline(name, baseline, &hashmap)?;
+            logs.push(format!(
+                "baseline {}: drifted={} changed_chunks={} changed_bytes={} ranges={}",
+                name,
+                drift.drifted,
+                drift.changed_chunks,
+                drift.changed_bytes,
+                drift.changed_ranges.len()
+            ));
+            for range in &drift.changed_ranges {
+                logs.push(format!(
+                    "changed offset={} length={} chunks={}..={}",
+                    range.offset, range.length, range.first_index, range.last_index
+                ));
+            }
+            artifacts.push(ReportArtifact::json(DRIFT_FILE_NAME, &drift)?);
+            Some(drift)
+        }
+        _ => None,
     };
+    if let Some(name) = &params.save_baseline {
+        let path = baseline::save_baseline(name, &hashmap)?;
+        logs.push(format!("saved baseline {} to {}", name, path.display()));
+    }
+
+    let (log_text, timing) = logs.finish()?;
+    artifacts.push(timing);
 
     let meta = serde_json::json!({
         "workflow": "disk-hash-report",
         "disk_id": disk.id,
-        "chunk_size": params.chunk_size,
-        "chunk_count": entries.len()
+        "partition_id": params.partition_id,
+        "range_offset": range_offset,
+        "range_bytes": range_len,
+        "chunk_size": chunk_size,
+        "chunk_tuning": chunk_tuning,
+        "throughput_bytes_per_sec": throughput,
+        "chunk_count": hashmap.chunks.len(),
+        "hashmap_schema_version": HASHMAP_SCHEMA_VERSION,
+        "compare_baseline": params.compare_baseline,
+        "save_baseline": params.save_baseline,
+        "drifted": drift.as_ref().map(|drift| drift.drifted),
+        "changed_bytes": drift.as_ref().map(|drift| drift.changed_bytes)
     });
 
     let report = create_report_bundle_with_meta_signing_and_artifacts(
         &params.report_base,
         &graph,
         Some(meta),
-        None,
+        Some(&log_text),
         signing_key_from_env().as_deref(),
-        &[artifact],
+        &artifacts,
     )?;
 
     Ok(DiskHashReportResult {
         report,
         disk_id: disk.id.clone(),
-        chunk_count: entries.len(),
+        partition_id: params.partition_id.clone(),
+        chunk_count: hashmap.chunks.len(),
+        chunk_size,
+        throughput_bytes_per_sec: throughput,
+        drift,
+    })
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ValidateSourceParams {
+    pub source_path: PathBuf,
+    /// `windows`, `linux` or `macos`; `None` detects it from the layout.
+    #[serde(default)]
+    pub os: Option<String>,
+    /// Filesystem the media will use; its staging backend sets the file
+    /// size limit and name rules.
+    #[serde(default = "crate::params::default_filesystem")]
+    pub filesystem: FileSystem,
+    /// Checks only the files an installer run with this filter would copy.
+    #[serde(flatten)]
+    pub source_filter: SourceFilter,
+    /// Mac model a macOS source must be able to boot (`MacBookPro18,3`).
+    #[serde(default)]
+    pub target_model: Option<String>,
+    #[serde(default = "crate::params::default_report_base")]
+    pub report_base: PathBuf,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ValidateSourceResult {
+    pub report: ReportPaths,
+    pub os: String,
+    pub distro: Option<String>,
+    pub file_count: usize,
+    pub total_bytes: u64,
+    pub max_file_bytes: u64,
+    /// Everything that would make a staging run fail; empty means valid.
+    pub problems: Vec<String>,
+}
+
+/// Mounts and checks a source the way the installer workflows would, without
+/// a target device: boot files, the file size and name rules of the target
+/// filesystem, and which distro or edition it is. Problems are collected
+/// rather than stopping at the first, and the report is written either way.
+pub fn run_validate_source(params: &ValidateSourceParams) -> Result<ValidateSourceResult> {
+    let prepared = prepare_source(&params.source_path)?;
+    let source_root = prepared.root.clone();
+    if !source_root.is_dir() {
+        return Err(anyhow!("source root is not a directory"));
+    }
+    let (files, excluded_files) = collect_files_filtered(&source_root, &params.source_filter)?;
+    let total_bytes = files.iter().map(|entry| entry.size).sum::<u64>();
+    let max_file_bytes = max_file_size(&files);
+    let os = match &params.os {
+        Some(os) => os.to_ascii_lowercase(),
+        None => detect_source_os(&source_root).to_string(),
+    };
+
+    let mut problems = Vec::new();
+    match os.as_str() {
+        "windows" => {
+            if !source_root.join("setup.exe").exists() {
+                problems.push("source missing setup.exe".to_string());
+            }
+            if let Err(err) = ensure_boot_files(&files) {
+                problems.push(err.to_string());
+            }
+            if let Err(err) = phoenix_content::find_windows_image(&source_root) {
+                problems.push(err.to_string());
+            }
+        }
+        "linux" | "macos" => {
+            if let Err(err) = ensure_unix_boot_files(&files, &os) {
+                problems.push(err.to_string());
+            }
+        }
+        other => problems.push(format!("unsupported source os {}", other)),
+    }
+    let mut installer_support = None;
+    if let (Some(model), "macos") = (&params.target_model, os.as_str()) {
+        match find_install_app(&source_root).map(|app| inspect_installer_app(&app)) {
+            Some(Ok(support)) => {
+                if let Err(err) = check_installer_for_model(&support, model) {
+                    problems.push(err.to_string());
+                }
+                installer_support = Some(support);
+            }
+            Some(Err(err)) => problems.push(err.to_string()),
+            None => problems.push(format!(
+                "no installer app to check against {}",
+                model
+            )),
+        }
+    }
+    let backend = staging_backend(params.filesystem);
+    problems.extend(backend.file_size_problem(max_file_bytes));
+    let name_warnings =
+        backend.path_warnings(&mut files.iter().map(|entry| entry.relative_path.as_path()));
+    let distro = detect_distro(&source_root, &os);
+
+    let mut logs = StepLog::new("validate-source");
+    logs.push(format!("source_path={}", params.source_path.display()));
+    logs.push(format!("source_kind={:?}", prepared.kind));
+    logs.push(format!("os={}", os));
+    if let Some(distro) = &distro {
+        logs.push(format!("distro={}", distro));
+    }
+    logs.push(format!("filesystem={}", params.filesystem.as_str()));
+    if let Some(model) = &params.target_model {
+        logs.push(format!("target_model={}", model));
+    }
+    logs.push(format!("file_count={}", files.len()));
+    log_source_filter(&mut logs, &params.source_filter, excluded_files);
+    logs.push(format!("total_bytes={}", total_bytes));
+    logs.push(format!("max_file_bytes={}", max_file_bytes));
+    for warning in &name_warnings {
+        logs.push(format!("name_warning={}", warning));
+    }
+    for problem in &problems {
+        logs.push(format!("problem={}", problem));
+    }
+
+    let (log_text, timing) = logs.finish()?;
+
+    let meta = serde_json::json!({
+        "workflow": "validate-source",
+        "status": if problems.is_empty() { "valid" } else { "invalid" },
+        "source_path": params.source_path.display().to_string(),
+        "source_kind": format!("{:?}", prepared.kind),
+        "os": os,
+        "distro": distro,
+        "filesystem": params.filesystem.as_str(),
+        "file_count": files.len(),
+        "source_filter": params.source_filter,
+        "target_model": params.target_model,
+        "installer_support": installer_support,
+        "excluded_files": excluded_files,
+        "total_bytes": total_bytes,
+        "max_file_bytes": max_file_bytes,
+        "name_warnings": name_warnings,
+        "problems": problems,
+    });
+    let report = create_report_bundle_with_meta_signing_and_artifacts(
+        &params.report_base,
+        &report_graph(),
+        Some(meta),
+        Some(&log_text),
+        signing_key_from_env().as_deref(),
+        &[timing],
+    )?;
+
+    Ok(ValidateSourceResult {
+        report,
+        os,
+        distro,
+        file_count: files.len(),
+        total_bytes,
+        max_file_bytes,
+        problems,
     })
 }
 
+fn detect_source_os(root: &Path) -> &'static str {
+    if root.join("sources").is_dir() && root.join("setup.exe").exists() {
+        return "windows";
+    }
+    if root.join("System/Library/CoreServices/boot.efi").exists() || find_installer_app(root).is_some() {
+        return "macos";
+    }
+    "linux"
+}
+
+fn find_installer_app(root: &Path) -> Option<String> {
+    fs::read_dir(root)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .find(|path| is_macos_app(path))
+        .and_then(|path| path.file_stem().map(|stem| stem.to_string_lossy().to_string()))
+}
+
+/// Best-effort name of what the source installs, from the metadata files
+/// distributions ship on their media.
+fn detect_distro(root: &Path, os: &str) -> Option<String> {
+    match os {
+        "windows" => {
+            let image = phoenix_content::find_windows_image(root).ok()?;
+            wim_list_images(&image)
+                .ok()
+                .and_then(|images| images.into_iter().find_map(|image| image.name))
+                .or_else(|| Some("Windows".to_string()))
+        }
+        "macos" => find_installer_app(root),
+        _ => {
+            // Debian/Ubuntu
+            if let Ok(info) = fs::read_to_string(root.join(".disk/info")) {
+                if let Some(line) = info.lines().next().filter(|line| !line.trim().is_empty()) {
+                    return Some(line.trim().to_string());
+                }
+            }
+            // Fedora/RHEL and derivatives
+            if let Ok(treeinfo) = fs::read_to_string(root.join(".treeinfo")) {
+                let value = |key: &str| {
+                    treeinfo.lines().find_map(|line| {
+                        let (name, value) = line.split_once('=')?;
+                        (name.trim() == key).then(|| value.trim().to_string())
+                    })
+                };
+                if let Some(name) = value("name").or_else(|| value("family")) {
+                    return Some(match value("version") {
+                        Some(version) if !name.contains(&version) => format!("{} {}", name, version),
+                        _ => name,
+                    });
+                }
+            }
+            if root.join("arch/boot").is_dir() {
+                return Some("Arch Linux".to_string());
+            }
+            None
+        }
+    }
+}
+
 #[derive(Debug)]
 struct FileEntry {
     absolute_path: PathBuf,
@@ -1773,23 +3668,39 @@ struct FileEntry {
 }
 
 fn collect_files(root: &Path) -> Result<Vec<FileEntry>> {
+    Ok(collect_files_filtered(root, &SourceFilter::default())?.0)
+}
+
+/// Files under `root` that pass `filter`, and how many were filtered out.
+fn collect_files_filtered(root: &Path, filter: &SourceFilter) -> Result<(Vec<FileEntry>, usize)> {
     let mut entries = Vec::new();
-    collect_files_inner(root, root, &mut entries)?;
-    Ok(entries)
+    let mut excluded = 0;
+    collect_files_inner(root, root, filter, &mut entries, &mut excluded)?;
+    Ok((entries, excluded))
 }
 
-fn collect_files_inner(root: &Path, current: &Path, entries: &mut Vec<FileEntry>) -> Result<()> {
+fn collect_files_inner(
+    root: &Path,
+    current: &Path,
+    filter: &SourceFilter,
+    entries: &mut Vec<FileEntry>,
+    excluded: &mut usize,
+) -> Result<()> {
     for entry in fs::read_dir(current).with_context(|| format!("read {}", current.display()))? {
         let entry = entry?;
         let path = entry.path();
         let metadata = entry.metadata()?;
         if metadata.is_dir() {
-            collect_files_inner(root, &path, entries)?;
+            collect_files_inner(root, &path, filter, entries, excluded)?;
         } else if metadata.is_file() {
             let relative_path = path
                 .strip_prefix(root)
                 .map(PathBuf::from)
                 .context("strip source prefix")?;
+            if !filter.matches(&relative_path) {
+                *excluded += 1;
+                continue;
+            }
             entries.push(FileEntry {
                 absolute_path: path,
                 relative_path,
@@ -1800,7 +3711,82 @@ fn collect_files_inner(root: &Path, current: &Path, entries: &mut Vec<FileEntry>
     Ok(())
 }
 
-fn verify_copy(target_root: &Path, entries: &[FileEntry]) -> Result<()> {
+fn log_dedupe(logs: &mut StepLog, summary: &DedupeSummary) {
+    logs.push(format!("dedupe_groups={}", summary.groups.len()));
+    logs.push(format!("dedupe_duplicate_bytes={}", summary.duplicate_bytes));
+    logs.push(format!("dedupe_linked_files={}", summary.linked_files));
+    logs.push(format!("dedupe_saved_bytes={}", summary.saved_bytes));
+    if !summary.links_supported {
+        logs.push("dedupe_links_supported=false".to_string());
+    }
+}
+
+fn push_dedupe_map(
+    summary: &DedupeSummary,
+    artifacts: &mut Vec<ReportArtifact>,
+    artifact_names: &mut Vec<String>,
+) -> Result<()> {
+    if !summary.groups.is_empty() {
+        let artifact = ReportArtifact::json(DEDUPE_MAP_FILE, summary)?;
+        artifact_names.push(artifact.name.clone());
+        artifacts.push(artifact);
+    }
+    Ok(())
+}
+
+/// Totals only; the groups go to `dedupe_map.json`.
+fn dedupe_meta(summary: &DedupeSummary) -> serde_json::Value {
+    serde_json::json!({
+        "groups": summary.groups.len(),
+        "duplicate_bytes": summary.duplicate_bytes,
+        "linked_files": summary.linked_files,
+        "saved_bytes": summary.saved_bytes,
+        "links_supported": summary.links_supported,
+    })
+}
+
+/// `transform_pack` loaded before anything is formatted, so a broken pack
+/// fails the run early.
+fn load_transforms(transform_pack: Option<&Path>, dedupe: bool) -> Result<Option<Transforms>> {
+    let Some(path) = transform_pack else {
+        return Ok(None);
+    };
+    if dedupe {
+        return Err(anyhow!("dedupe cannot be combined with transform_pack"));
+    }
+    Transforms::load(path).map(Some)
+}
+
+fn log_transforms(logs: &mut StepLog, transforms: &Transforms) {
+    logs.push(format!("transform_pack={}", transforms.pack_name()));
+    for file in transforms.files() {
+        logs.push(format!(
+            "transformed={} transforms={} bytes={}->{}",
+            file.path,
+            file.transforms.join(","),
+            file.original_bytes,
+            file.bytes
+        ));
+    }
+}
+
+fn log_source_filter(logs: &mut StepLog, filter: &SourceFilter, excluded_files: usize) {
+    if filter.is_empty() {
+        return;
+    }
+    for pattern in &filter.include {
+        logs.push(format!("source_include={}", pattern));
+    }
+    for pattern in &filter.exclude {
+        logs.push(format!("source_exclude={}", pattern));
+    }
+    logs.push(format!("excluded_files={}", excluded_files));
+}
+
+fn verify_copy<'a>(
+    target_root: &Path,
+    entries: impl IntoIterator<Item = &'a FileEntry>,
+) -> Result<()> {
     for entry in entries {
         let dest_path = target_root.join(&entry.relative_path);
         let metadata = fs::metadata(&dest_path).with_context(|| {
@@ -1818,36 +3804,37 @@ fn verify_copy(target_root: &Path, entries: &[FileEntry]) -> Result<()> {
     Ok(())
 }
 
+/// Windows reports mount points with a trailing backslash: `E:\`,
+/// `C:\mnt\usb\` and `\\?\Volume{GUID}\`.
 fn normalize_mount_path(path: &Path) -> PathBuf {
     let mut value = path.display().to_string();
+    if let Some(rest) = value.strip_prefix(r"\\.\Volume{") {
+        value = format!(r"\\?\Volume{{{}", rest);
+    }
     if value.len() == 2 && value.ends_with(':') {
         value.push('\\');
     }
     if value.len() == 3 && value.ends_with(":\\") {
         return PathBuf::from(value);
     }
-    if !value.ends_with('\\') && value.ends_with(':') {
+    if !value.ends_with('\\') && (value.ends_with(':') || is_windows_mount_path(&value)) {
         value.push('\\');
     }
     PathBuf::from(value)
 }
 
+/// `C:\folder` or `\\?\Volume{GUID}`, as opposed to a Unix path.
+fn is_windows_mount_path(value: &str) -> bool {
+    let bytes = value.as_bytes();
+    value.starts_with(r"\\?\Volume{")
+        || (bytes.len() > 3 && bytes[0].is_ascii_alphabetic() && &bytes[1..3] == b":\\")
+}
+
 fn parse_disk_number(id: &str) -> Option<u32> {
     let suffix = id.strip_prefix("PhysicalDrive")?;
     suffix.parse().ok()
 }
 
-fn extract_drive_letter(path: &Path) -> Option<char> {
-    let value = path.display().to_string();
-    let mut chars = value.chars();
-    let first = chars.next()?;
-    if chars.next() == Some(':') {
-        Some(first.to_ascii_uppercase())
-    } else {
-        None
-    }
-}
-
 fn is_system_mount_path(path: &Path, graph: &phoenix_core::DeviceGraph) -> bool {
     let value = normalize_mount_path(path).display().to_string();
     graph.disks.iter().any(|disk| {
@@ -1915,11 +3902,16 @@ fn find_disk_by_mount<'a>(graph: &'a DeviceGraph, mount: &Path) -> Option<&'a ph
     })
 }
 
-fn disk_id_from_device_path(path: &Path) -> Option<String> {
+pub(crate) fn disk_id_from_device_path(path: &Path) -> Option<String> {
     let name = path.file_name()?.to_string_lossy().to_string();
-    if name.starts_with("disk") {
-        if let Some(idx) = name.find('s') {
-            return Some(name[..idx].to_string());
+    // macOS raw nodes (`rdisk4s1`) belong to the same disk as `disk4s1`.
+    let name = match name.strip_prefix("rdisk") {
+        Some(rest) => format!("disk{}", rest),
+        None => name,
+    };
+    if let Some(rest) = name.strip_prefix("disk") {
+        if let Some(idx) = rest.find('s') {
+            return Some(name[..4 + idx].to_string());
         }
         return Some(name);
     }
@@ -1952,15 +3944,16 @@ fn parse_macos_version(value: &str) -> Option<(u32, u32)> {
     }
 
     let name = value.replace('-', " ").trim().to_string();
+    // "mountain lion" and "high sierra" come before the names they contain.
     let map = [
         ("snow leopard", (10, 6)),
-        ("lion", (10, 7)),
         ("mountain lion", (10, 8)),
+        ("lion", (10, 7)),
         ("mavericks", (10, 9)),
         ("yosemite", (10, 10)),
         ("el capitan", (10, 11)),
-        ("sierra", (10, 12)),
         ("high sierra", (10, 13)),
+        ("sierra", (10, 12)),
         ("mojave", (10, 14)),
         ("catalina", (10, 15)),
         ("big sur", (11, 0)),
@@ -1983,12 +3976,11 @@ fn parse_numeric_version(value: &str) -> Option<(u32, u32)> {
     if parts.is_empty() {
         return None;
     }
-    let major = parts.get(0)?.parse::<u32>().ok()?;
+    let major = parts.first()?.parse::<u32>().ok()?;
     let minor = parts.get(1).and_then(|v| v.parse::<u32>().ok()).unwrap_or(0);
     Some((major, minor))
 }
 
-#[cfg(target_os = "macos")]
 fn is_macos_app(path: &Path) -> bool {
     path.extension()
         .and_then(|ext| ext.to_str())
@@ -1997,30 +3989,23 @@ fn is_macos_app(path: &Path) -> bool {
         && path.join("Contents/Resources/createinstallmedia").exists()
 }
 
-#[cfg(target_os = "macos")]
+/// Detached and its mount point removed on drop.
 struct MountedDmg {
     mount_point: PathBuf,
+    _cleanup: CleanupGuard,
 }
 
-#[cfg(target_os = "macos")]
-impl Drop for MountedDmg {
-    fn drop(&mut self) {
-        let _ = run_cmd(
-            "/usr/bin/hdiutil",
-            &["detach", self.mount_point.to_string_lossy().as_ref()],
-        );
-    }
-}
-
-#[cfg(target_os = "macos")]
 fn mount_dmg(path: &Path) -> Result<MountedDmg> {
     let mount_point = std::env::temp_dir().join(format!(
-        "phoenix_dmg_{}",
+        "phoenix_dmg_{}_{}",
+        std::process::id(),
         std::time::SystemTime::now()
             .duration_since(std::time::UNIX_EPOCH)
             .unwrap_or_default()
-            .as_secs()
+            .as_nanos()
     ));
+    // Registered first, so a failed attach still removes the directory.
+    let cleanup = CleanupGuard::register(CleanupAction::DetachDmg, &mount_point);
     fs::create_dir_all(&mount_point)?;
     run_cmd(
         "/usr/bin/hdiutil",
@@ -2033,10 +4018,12 @@ fn mount_dmg(path: &Path) -> Result<MountedDmg> {
             mount_point.to_string_lossy().as_ref(),
         ],
     )?;
-    Ok(MountedDmg { mount_point })
+    Ok(MountedDmg {
+        mount_point,
+        _cleanup: cleanup,
+    })
 }
 
-#[cfg(target_os = "macos")]
 fn find_install_app(root: &Path) -> Option<PathBuf> {
     let entries = fs::read_dir(root).ok()?;
     for entry in entries.flatten() {
@@ -2048,7 +4035,6 @@ fn find_install_app(root: &Path) -> Option<PathBuf> {
     None
 }
 
-#[cfg(target_os = "macos")]
 fn erase_disk(target_device: &Path, fs: &str, name: &str) -> Result<()> {
     run_cmd(
         "/usr/sbin/diskutil",
@@ -2061,7 +4047,6 @@ fn erase_disk(target_device: &Path, fs: &str, name: &str) -> Result<()> {
     )
 }
 
-#[cfg(target_os = "macos")]
 fn run_createinstallmedia(app: &Path, target_volume: &Path) -> Result<()> {
     let tool = app.join("Contents/Resources/createinstallmedia");
     if !tool.exists() {
@@ -2077,7 +4062,6 @@ fn run_createinstallmedia(app: &Path, target_volume: &Path) -> Result<()> {
     )
 }
 
-#[cfg(target_os = "macos")]
 fn run_asr_restore(source: &Path, target_device: &Path) -> Result<()> {
     run_cmd(
         "/usr/sbin/asr",
@@ -2095,9 +4079,10 @@ fn run_asr_restore(source: &Path, target_device: &Path) -> Result<()> {
 
 #[cfg(target_os = "macos")]
 fn run_cmd(cmd: &str, args: &[&str]) -> Result<()> {
-    let output = std::process::Command::new(cmd)
-        .args(args)
-        .output()
+    if phoenix_core::mock::is_active() {
+        return Ok(phoenix_core::mock::record_command(cmd, args)?);
+    }
+    let output = cancel::command_output(std::process::Command::new(cmd).args(args))
         .with_context(|| format!("run {}", cmd))?;
     if output.status.success() {
         Ok(())
@@ -2115,7 +4100,7 @@ fn run_cmd(_cmd: &str, _args: &[&str]) -> Result<()> {
     Err(anyhow!("macos tool requires macOS"))
 }
 
-fn free_space_bytes(path: &Path) -> Result<Option<u64>> {
+fn mount_free_space_bytes(path: &Path) -> Result<Option<u64>> {
     #[cfg(unix)]
     {
         use libc::statvfs;
@@ -2130,7 +4115,7 @@ fn free_space_bytes(path: &Path) -> Result<Option<u64>> {
             return Ok(None);
         }
         let stats = unsafe { stats.assume_init() };
-        let free = (stats.f_bavail as u64).saturating_mul(stats.f_frsize as u64);
+        let free = stats.f_bavail.saturating_mul(stats.f_frsize);
         Ok(Some(free))
     }
     #[cfg(not(unix))]
@@ -2140,121 +4125,1166 @@ fn free_space_bytes(path: &Path) -> Result<Option<u64>> {
     }
 }
 
-fn build_device_graph() -> Result<DeviceGraph> {
-    #[cfg(target_os = "windows")]
-    {
-        return phoenix_host_windows::build_device_graph();
+/// Opens a run ledger record for `disk` once the safety checks passed, so a
+/// crash during the destructive phases shows up in `runs-recover`.
+fn begin_run(
+    workflow: &str,
+    disk: &phoenix_core::Disk,
+    logs: &mut StepLog,
+) -> Result<RunTracker> {
+    let tracker = RunLedger::open_default()?.begin(workflow, disk)?;
+    logs.push(format!("run_id={}", tracker.run_id()));
+    Ok(tracker)
+}
+
+/// Unmounts every mounted partition of the target disk before a destructive
+/// step, logging each one. Busy volumes fail the workflow. macOS goes
+/// through the DiskArbitration claim in `open_device_exclusive` instead.
+#[cfg(not(target_os = "macos"))]
+fn unmount_target_disk(disk: &phoenix_core::Disk, logs: &mut StepLog) -> Result<()> {
+    let mounted: Vec<String> = disk
+        .partitions
+        .iter()
+        .flat_map(|partition| partition.mount_points.iter().cloned())
+        .collect();
+    if mounted.is_empty() || phoenix_core::mock::is_active() {
+        return Ok(());
     }
     #[cfg(target_os = "linux")]
     {
-        return phoenix_host_linux::build_device_graph();
-    }
-    #[cfg(target_os = "macos")]
-    {
-        return phoenix_host_macos::build_device_graph();
+        for mount in phoenix_host_linux::unmount_disk_partitions(disk)? {
+            logs.push(format!("unmounted={}", mount));
+        }
+        Ok(())
     }
-    #[cfg(not(any(target_os = "windows", target_os = "linux", target_os = "macos")))]
+    #[cfg(not(target_os = "linux"))]
     {
-        Err(anyhow!("unsupported OS"))
+        let _ = logs;
+        Err(anyhow!(
+            "{} has mounted volumes: {}",
+            disk.id,
+            mounted.join(", ")
+        ))
     }
 }
 
-const FAT32_MAX_FILE: u64 = 4_294_967_295;
-
-fn max_file_size(entries: &[FileEntry]) -> u64 {
-    entries.iter().map(|entry| entry.size).max().unwrap_or(0)
+/// Makes the kernel pick up the layout just written to a whole disk; see
+/// `phoenix_host_linux::reread_partition_table`. A failure is logged, not
+/// fatal: the write itself succeeded. `None` when no re-read was needed.
+#[cfg(target_os = "linux")]
+fn reread_partitions(device: &Path, logs: &mut StepLog) -> Option<bool> {
+    if phoenix_core::mock::is_active() || !phoenix_host_linux::is_whole_disk(device) {
+        return None;
+    }
+    match phoenix_host_linux::reread_partition_table(device) {
+        Ok(partitions) => {
+            logs.push(format!("partition_reread=ok partitions={}", partitions.join(",")));
+            Some(true)
+        }
+        Err(err) => {
+            logs.push(format!("partition_reread_warning={}", err));
+            Some(false)
+        }
+    }
 }
 
-#[derive(serde::Serialize)]
-struct CopyManifestEntry {
-    path: String,
-    bytes: u64,
-    sha256: String,
+#[cfg(not(target_os = "linux"))]
+fn reread_partitions(_device: &Path, _logs: &mut StepLog) -> Option<bool> {
+    None
 }
 
-#[derive(serde::Serialize)]
-struct DiskHashEntry {
-    index: u64,
-    offset: u64,
-    length: u64,
-    sha256: String,
+/// Writes the image to the target device. On macOS the disk is unmounted
+/// and claimed through DiskArbitration for the duration of the write so
+/// nothing remounts it midway; the device falls back to `authopen` when the
+/// process lacks permission.
+fn write_target_image(
+    disk: &phoenix_core::Disk,
+    params: &UnixWriteImageParams,
+    write_device: &Path,
+    chunk_size: u64,
+    observer: &mut dyn WriteObserver,
+    logs: &mut StepLog,
+) -> Result<(phoenix_imaging::WriteResult, Vec<phoenix_fetch::MirrorStats>)> {
+    if let Some(url) = image_url(params) {
+        return write_streamed_image(disk, url, params, write_device, chunk_size, observer, logs);
+    }
+    write_file_image(disk, params, write_device, chunk_size, observer, logs)
+        .map(|result| (result, Vec::new()))
 }
 
-fn ensure_boot_files(entries: &[FileEntry]) -> Result<()> {
-    let mut has_boot_wim = false;
-    let mut has_efi = false;
-    for entry in entries {
-        let rel = entry.relative_path.to_string_lossy().replace('\\', "/");
-        let rel_lower = rel.to_ascii_lowercase();
-        if rel_lower == "sources/boot.wim" {
-            has_boot_wim = true;
-        }
-        if rel_lower.starts_with("efi/boot/") && rel_lower.ends_with(".efi") {
-            has_efi = true;
-        }
+fn write_file_image(
+    disk: &phoenix_core::Disk,
+    params: &UnixWriteImageParams,
+    write_device: &Path,
+    chunk_size: u64,
+    observer: &mut dyn WriteObserver,
+    logs: &mut StepLog,
+) -> Result<phoenix_imaging::WriteResult> {
+    // The pipelined writer reads the file as it is, so containers go
+    // through the sequential writer, which reads the disk they hold.
+    let format = phoenix_imaging::detect_image_format(&params.source_image)?;
+    logs.push(format!("image_format={}", format.as_str()));
+    let fast_io = params.fast_io && format == phoenix_imaging::ImageFormat::Raw;
+    if params.fast_io && !fast_io {
+        logs.push("fast_io_skipped=image container".to_string());
     }
-    if !has_boot_wim {
-        return Err(anyhow!("missing sources/boot.wim in installer source"));
+    if phoenix_core::mock::is_active() {
+        return phoenix_imaging::write_image_to_device_with_progress(
+            &params.source_image,
+            write_device,
+            chunk_size,
+            params.verify,
+            params.flush_every_bytes,
+            observer,
+        );
     }
-    if !has_efi {
-        return Err(anyhow!("missing EFI bootloader in installer source"));
+    #[cfg(target_os = "macos")]
+    {
+        let mut device = phoenix_host_macos::open_device_exclusive(write_device, true)?;
+        log_exclusive_open(disk, &device, logs);
+        if fast_io {
+            return phoenix_imaging::write_image_pipelined(
+                &params.source_image,
+                &device.file,
+                chunk_size,
+                params.verify,
+                params.flush_every_bytes,
+                observer,
+            );
+        }
+        phoenix_imaging::write_image_to_open_device(
+            &params.source_image,
+            &mut device.file,
+            chunk_size,
+            params.verify,
+            params.flush_every_bytes,
+            observer,
+        )
+    }
+    #[cfg(not(target_os = "macos"))]
+    {
+        unmount_target_disk(disk, logs)?;
+        if fast_io {
+            let device = fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(write_device)
+                .with_context(|| format!("open {}", write_device.display()))?;
+            return phoenix_imaging::write_image_pipelined(
+                &params.source_image,
+                &device,
+                chunk_size,
+                params.verify,
+                params.flush_every_bytes,
+                observer,
+            );
+        }
+        phoenix_imaging::write_image_to_device_with_progress(
+            &params.source_image,
+            write_device,
+            chunk_size,
+            params.verify,
+            params.flush_every_bytes,
+            observer,
+        )
     }
-    Ok(())
 }
 
-fn ensure_unix_boot_files(entries: &[FileEntry], os: &str) -> Result<()> {
-    let mut has_efi = false;
-    let mut has_grub = false;
-    let mut has_isolinux = false;
-    let mut has_macos_boot = false;
-    for entry in entries {
-        let rel = entry.relative_path.to_string_lossy().replace('\\', "/");
-        let rel_lower = rel.to_ascii_lowercase();
-        if rel_lower.starts_with("efi/boot/") {
-            has_efi = true;
-        }
-        if rel_lower.starts_with("boot/grub") {
-            has_grub = true;
+/// `source_image` as a URL when it is one.
+fn image_url(params: &UnixWriteImageParams) -> Option<&str> {
+    params
+        .source_image
+        .to_str()
+        .filter(|source| phoenix_fetch::is_url(source))
+}
+
+/// Streams `url` (or the best of it and `source_mirrors`) to the device
+/// without a local copy, returning what each mirror did. `fast_io` does
+/// not apply: the pipelined writer needs positional reads from a file.
+fn write_streamed_image(
+    disk: &phoenix_core::Disk,
+    url: &str,
+    params: &UnixWriteImageParams,
+    write_device: &Path,
+    chunk_size: u64,
+    observer: &mut dyn WriteObserver,
+    logs: &mut StepLog,
+) -> Result<(phoenix_imaging::WriteResult, Vec<phoenix_fetch::MirrorStats>)> {
+    if let Some(wait) = phoenix_fetch::window_wait() {
+        logs.push(format!("fetch_window_wait_secs={}", wait.as_secs()));
+    }
+    let urls: Vec<String> = std::iter::once(url.to_string())
+        .chain(params.source_mirrors.iter().cloned())
+        .collect();
+    let mut source = phoenix_fetch::HttpSource::open_mirrors(&urls)?;
+    logs.push(format!(
+        "stream_bytes={} accepts_ranges={}",
+        source.total_bytes(),
+        source.accepts_ranges()
+    ));
+    if source.total_bytes() > disk.size_bytes {
+        return Err(anyhow!(
+            "image is {} bytes but the device holds {}",
+            source.total_bytes(),
+            disk.size_bytes
+        ));
+    }
+    let total_bytes = source.total_bytes();
+    let open = |path: &Path| {
+        fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+            .with_context(|| format!("open {}", path.display()))
+    };
+    let result = if phoenix_core::mock::is_active() {
+        let mut device = open(write_device)?;
+        phoenix_imaging::write_stream_to_open_device(
+            &mut source, total_bytes, &mut device, chunk_size, params.verify,
+                params.flush_every_bytes, observer,
+        )
+    } else {
+        #[cfg(target_os = "macos")]
+        {
+            let mut device = phoenix_host_macos::open_device_exclusive(write_device, true)?;
+            log_exclusive_open(disk, &device, logs);
+            phoenix_imaging::write_stream_to_open_device(
+                &mut source, total_bytes, &mut device.file, chunk_size, params.verify,
+                params.flush_every_bytes, observer,
+            )
         }
-        if rel_lower.starts_with("isolinux/") {
-            has_isolinux = true;
+        #[cfg(not(target_os = "macos"))]
+        {
+            unmount_target_disk(disk, logs)?;
+            let mut device = open(write_device)?;
+            phoenix_imaging::write_stream_to_open_device(
+                &mut source, total_bytes, &mut device, chunk_size, params.verify,
+                params.flush_every_bytes, observer,
+            )
         }
-        if rel_lower == "system/library/coreservices/boot.efi" {
-            has_macos_boot = true;
+    };
+    logs.push(format!("stream_resumes={}", source.resumes()));
+    let mirrors = source.mirror_stats();
+    if mirrors.len() > 1 {
+        logs.push(format!("stream_failovers={}", source.failovers()));
+        for mirror in &mirrors {
+            logs.push(format!(
+                "mirror url={} healthy={} probe_ms={} connections={} failures={} bytes={}",
+                mirror.url,
+                mirror.healthy,
+                mirror.probe_ms.map_or("-".to_string(), |ms| ms.to_string()),
+                mirror.connections,
+                mirror.failures,
+                mirror.bytes
+            ));
         }
     }
+    Ok((result?, mirrors))
+}
 
-    match os {
-        "linux" => {
-            if !has_efi && !has_grub && !has_isolinux {
-                return Err(anyhow!(
-                    "linux source missing EFI/BOOT, boot/grub, or isolinux"
+fn parse_sha256(digest: &str) -> Result<String> {
+    let digest = digest.trim().to_ascii_lowercase();
+    if digest.len() != 64 || !digest.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(anyhow!("source_sha256 must be 64 hex characters"));
+    }
+    Ok(digest)
+}
+
+/// Returns the requested chunk size, or the one `tune_chunk_size` measures
+/// fastest on `device`, plus a JSON summary for the report. A failed probe
+/// falls back to `DEFAULT_CHUNK_SIZE`.
+fn resolve_chunk_size(
+    requested: Option<u64>,
+    device: &Path,
+    total_size: u64,
+    logs: &mut StepLog,
+) -> (u64, serde_json::Value) {
+    if let Some(size) = requested {
+        logs.push(format!("chunk_size={} source=fixed", size));
+        return (size, serde_json::json!({ "source": "fixed" }));
+    }
+    match tune_chunk_size(device, total_size) {
+        Ok(tuning) => {
+            logs.push(format!("chunk_size={} source=auto", tuning.chunk_size));
+            for sample in &tuning.samples {
+                logs.push(format!(
+                    "chunk_probe={} bytes_per_sec={}",
+                    sample.chunk_size, sample.bytes_per_sec
                 ));
             }
+            let samples: Vec<serde_json::Value> = tuning
+                .samples
+                .iter()
+                .map(|sample| {
+                    serde_json::json!({
+                        "chunk_size": sample.chunk_size,
+                        "bytes_per_sec": sample.bytes_per_sec
+                    })
+                })
+                .collect();
+            let summary = serde_json::json!({
+                "source": "auto",
+                "bus": tuning.hints.bus,
+                "preferred_io_bytes": tuning.hints.preferred_io_bytes,
+                "max_transfer_bytes": tuning.hints.max_transfer_bytes,
+                "samples": samples
+            });
+            (tuning.chunk_size, summary)
         }
-        "macos" => {
-            if !has_macos_boot && !has_efi {
-                return Err(anyhow!(
-                    "macos source missing System/Library/CoreServices/boot.efi or EFI/BOOT"
-                ));
-            }
+        Err(err) => {
+            logs.push(format!("chunk_probe_error={}", err));
+            logs.push(format!("chunk_size={} source=default", DEFAULT_CHUNK_SIZE));
+            let summary = serde_json::json!({
+                "source": "default",
+                "error": err.to_string()
+            });
+            (DEFAULT_CHUNK_SIZE, summary)
         }
-        _ => {}
     }
-    Ok(())
 }
 
-#[derive(Debug, Clone)]
-struct BootCandidate {
-    source: PathBuf,
-    relative: String,
-    is_dir: bool,
+/// Times the write phase: the clock stops at the last progress callback, so
+/// a verify pass afterwards does not dilute the figure.
+/// With a tracker, each flush the writer reports is checkpointed in the run
+/// ledger; with a write slot, each chunk is held to the host's bandwidth
+/// cap.
+struct ThroughputObserver<'a> {
+    started: std::time::Instant,
+    bytes: u64,
+    elapsed: std::time::Duration,
+    tracker: Option<&'a mut RunTracker>,
+    progress: ProgressReporter,
+    slot: Option<&'a mut WriteSlot>,
+    verifies: bool,
 }
 
-fn boot_prep_candidates(os: &str, source_root: &Path) -> Result<Vec<BootCandidate>> {
-    let mut candidates = Vec::new();
-    match os {
-        "linux" => {
+impl<'a> ThroughputObserver<'a> {
+    fn new(tracker: Option<&'a mut RunTracker>, progress: ProgressReporter) -> Self {
+        Self {
+            started: std::time::Instant::now(),
+            bytes: 0,
+            elapsed: std::time::Duration::ZERO,
+            tracker,
+            progress,
+            slot: None,
+            verifies: false,
+        }
+    }
+
+    /// `verifies` marks the slot as verifying once the last chunk is
+    /// written.
+    fn with_write_slot(mut self, slot: Option<&'a mut WriteSlot>, verifies: bool) -> Self {
+        self.slot = slot;
+        self.verifies = verifies;
+        self
+    }
+
+    /// The write speed, also handed to the tracker for the device
+    /// registry when the write was long enough to mean something.
+    fn finish(&mut self) -> u64 {
+        let rate = bytes_per_sec(self.bytes, self.elapsed);
+        // A capped write measures the cap, not the stick.
+        let capped = self.slot.as_ref().is_some_and(|slot| slot.throttles());
+        if self.bytes >= WEAR_SAMPLE_MIN_BYTES && rate > 0 && !capped {
+            if let Some(tracker) = self.tracker.as_mut() {
+                tracker.write_throughput(rate);
+            }
+        }
+        rate
+    }
+}
+
+/// Shorter writes mostly measure caches, not the stick.
+const WEAR_SAMPLE_MIN_BYTES: u64 = 64 * 1024 * 1024;
+
+impl WriteObserver for ThroughputObserver<'_> {
+    fn on_progress(&mut self, progress: WriteProgress) -> bool {
+        if let Some(slot) = self.slot.as_mut() {
+            slot.throttle(progress.bytes_written.saturating_sub(self.bytes));
+            if self.verifies && progress.chunk_index + 1 == progress.total_chunks {
+                slot.verifying();
+            }
+        }
+        self.bytes = progress.bytes_written;
+        self.elapsed = self.started.elapsed();
+        if let Some(tracker) = self.tracker.as_mut() {
+            tracker.checkpoint(progress.durable_bytes);
+        }
+        self.progress.report(progress.bytes_written, progress.total_bytes);
+        !cancel::is_cancelled()
+    }
+}
+
+fn bytes_per_sec(bytes: u64, elapsed: std::time::Duration) -> u64 {
+    let secs = elapsed.as_secs_f64();
+    if secs <= 0.0 {
+        return 0;
+    }
+    (bytes as f64 / secs) as u64
+}
+
+/// Picks the node to write through. On macOS `/dev/diskN` is swapped for the
+/// unbuffered `/dev/rdiskN`, which only accepts sector-multiple transfers, so
+/// the buffered node is kept when the image or chunk size is not aligned.
+fn write_device_path(params: &UnixWriteImageParams, chunk_size: u64) -> Result<PathBuf> {
+    #[cfg(target_os = "macos")]
+    {
+        const RAW_ALIGNMENT: u64 = 512;
+        if image_url(params).is_some() {
+            // The stream's length is not known yet; stay on the buffered node.
+            return Ok(params.target_device.clone());
+        }
+        let image_len = phoenix_imaging::open_image(&params.source_image)?.disk_bytes;
+        if image_len % RAW_ALIGNMENT == 0 && chunk_size % RAW_ALIGNMENT == 0 {
+            return Ok(phoenix_host_macos::raw_device_path(&params.target_device));
+        }
+        Ok(params.target_device.clone())
+    }
+    #[cfg(not(target_os = "macos"))]
+    {
+        let _ = chunk_size;
+        Ok(params.target_device.clone())
+    }
+}
+
+/// Formats `device` as FAT32, holding the same exclusive claim as
+/// `write_target_image` on macOS.
+fn format_target_fat32(
+    disk: &phoenix_core::Disk,
+    device: &Path,
+    size_bytes: u64,
+    label: Option<&str>,
+    cluster_bytes: Option<u64>,
+    logs: &mut StepLog,
+) -> Result<phoenix_fs_fat32::Fat32Layout> {
+    if phoenix_core::mock::is_active() {
+        let file = mock_device_file(disk, device)?;
+        return phoenix_fs_fat32::format_fat32_with_cluster_size(&file, size_bytes, label, cluster_bytes);
+    }
+    #[cfg(target_os = "macos")]
+    {
+        let mut handle = phoenix_host_macos::open_device_exclusive(device, true)?;
+        log_exclusive_open(disk, &handle, logs);
+        phoenix_fs_fat32::format_fat32_device(
+            &mut handle.file,
+            size_bytes,
+            label,
+            phoenix_fs_fat32::OemCodepage::default(),
+            cluster_bytes,
+        )
+    }
+    #[cfg(not(target_os = "macos"))]
+    {
+        unmount_target_disk(disk, logs)?;
+        phoenix_fs_fat32::format_fat32_with_cluster_size(device, size_bytes, label, cluster_bytes)
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn log_exclusive_open(
+    disk: &phoenix_core::Disk,
+    device: &phoenix_host_macos::ExclusiveDevice,
+    logs: &mut StepLog,
+) {
+    for partition in &disk.partitions {
+        for mount in &partition.mount_points {
+            logs.push(format!("unmounted={}", mount));
+        }
+    }
+    logs.push(format!("disk_claim={}", disk.id));
+    logs.push(format!(
+        "device_open={}",
+        if device.via_authopen { "authopen" } else { "direct" }
+    ));
+}
+
+fn partition_device_for_mount(disk: &phoenix_core::Disk, mount: &Path) -> Option<PathBuf> {
+    let mount_str = normalize_mount_for_unix(mount).display().to_string();
+    disk.partitions
+        .iter()
+        .find(|partition| {
+            partition.mount_points.iter().any(|mp| {
+                normalize_mount_for_unix(&PathBuf::from(mp)).display().to_string() == mount_str
+            })
+        })
+        .map(|partition| PathBuf::from("/dev").join(&partition.id))
+}
+
+/// Unmounts the disk's volumes, creates a FAT32 filesystem on `device` and
+/// mounts it again, all through udisks2. Returns the new mount point.
+#[cfg(all(target_os = "linux", feature = "udisks2"))]
+fn udisks_format_and_mount(
+    disk: &phoenix_core::Disk,
+    device: &Path,
+    label: Option<&str>,
+    logs: &mut StepLog,
+) -> Result<PathBuf> {
+    let client = phoenix_host_linux::udisks::UdisksClient::connect()?;
+    for partition in &disk.partitions {
+        if partition.mount_points.is_empty() {
+            continue;
+        }
+        client.unmount(&PathBuf::from("/dev").join(&partition.id))?;
+        for mount in &partition.mount_points {
+            logs.push(format!("unmounted={}", mount));
+        }
+    }
+    client.format(device, "vfat", label)?;
+    client.mount(device)
+}
+
+#[cfg(not(all(target_os = "linux", feature = "udisks2")))]
+fn udisks_format_and_mount(
+    _disk: &phoenix_core::Disk,
+    _device: &Path,
+    _label: Option<&str>,
+    _logs: &mut StepLog,
+) -> Result<PathBuf> {
+    Err(anyhow!("udisks requires linux and the udisks2 feature"))
+}
+
+/// Unmounts `device` (if mounted) and powers off its drive via udisks2.
+#[cfg(all(target_os = "linux", feature = "udisks2"))]
+fn udisks_power_off(device: &Path) -> Result<()> {
+    let client = phoenix_host_linux::udisks::UdisksClient::connect()?;
+    let _ = client.unmount(device);
+    client.power_off(device)
+}
+
+#[cfg(not(all(target_os = "linux", feature = "udisks2")))]
+fn udisks_power_off(_device: &Path) -> Result<()> {
+    Err(anyhow!("power_off requires linux and the udisks2 feature"))
+}
+
+fn remount_formatted(device: &Path, mount_point: &Path) -> Result<()> {
+    if phoenix_core::mock::is_active() {
+        return Ok(());
+    }
+    #[cfg(target_os = "linux")]
+    {
+        phoenix_host_linux::mount_partition(device, mount_point, "vfat")
+    }
+    #[cfg(target_os = "macos")]
+    {
+        phoenix_host_macos::mount_volume(device, mount_point)
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+    {
+        let _ = (device, mount_point);
+        Err(anyhow!("remount requires linux or macos"))
+    }
+}
+
+/// Sandbox file for a device node under `PHOENIX_HOST=mock`: the matching
+/// partition's (`sdb1`, `disk4s1`, `rdisk4s1`), else the whole disk's.
+fn mock_device_file(disk: &phoenix_core::Disk, device: &Path) -> Result<PathBuf> {
+    match partition_for_device(disk, device) {
+        Some(partition) => Ok(phoenix_core::mock::partition_file(partition)?),
+        None => Ok(phoenix_core::mock::disk_file(disk)?),
+    }
+}
+
+fn partition_for_device<'a>(
+    disk: &'a phoenix_core::Disk,
+    device: &Path,
+) -> Option<&'a phoenix_core::Partition> {
+    let name = device.file_name()?.to_string_lossy().to_string();
+    let name = match name.strip_prefix("rdisk") {
+        Some(rest) => format!("disk{}", rest),
+        None => name,
+    };
+    disk.partitions
+        .iter()
+        .find(|partition| partition.id.eq_ignore_ascii_case(&name))
+}
+
+/// Holds the target to its operator claim. Returns the label of a `warn`
+/// claim; a real run let through that way is written to the audit log
+/// first and fails if it cannot be. A `deny` claim has no per-run
+/// override.
+fn check_target_claim(disk: &phoenix_core::Disk, workflow: &str, dry_run: bool) -> Result<Option<String>> {
+    let Some(claim) = disk.history.as_ref().and_then(|history| history.claim.as_ref()) else {
+        return Ok(None);
+    };
+    match registry::claim_problem(claim) {
+        Ok(None) => Ok(None),
+        Ok(Some(reason)) => {
+            if !dry_run {
+                append_audit_entry(&AuditEntry::new("claimed_device_write", workflow, disk, &reason))?;
+            }
+            Ok(Some(reason))
+        }
+        Err(reason) => Err(anyhow!(
+            "{} is {}; clear the claim with device-annotate to proceed",
+            disk.id,
+            reason
+        )),
+    }
+}
+
+/// Holds the target to the wear policy with the counts from the device
+/// registry. Returns the reason a worn-out device is let through: a
+/// warn-only policy or the run's acknowledgement. A real run let through
+/// that way is written to the audit log first and fails if it cannot be.
+fn check_target_wear(
+    disk: &phoenix_core::Disk,
+    workflow: &str,
+    acknowledged: bool,
+    dry_run: bool,
+) -> Result<Option<String>> {
+    let mut reasons = Vec::new();
+    let limits = WearLimits::from_env().map_err(|err| anyhow!(err))?;
+    if limits.is_enabled() {
+        let wear = DeviceRegistry::open_default()?
+            .get(&registry::disk_key(disk))?
+            .map(|record| record.wear())
+            .unwrap_or_default();
+        match check_device_wear(&wear, &limits, acknowledged) {
+            WearDecision::Allow => {}
+            WearDecision::Warn(reason) => reasons.push(reason),
+            WearDecision::Acknowledged(reason) => {
+                if !dry_run {
+                    append_audit_entry(&AuditEntry::new("device_wear_override", workflow, disk, &reason))?;
+                }
+                reasons.push(reason);
+            }
+            WearDecision::Deny(reason) => {
+                return Err(anyhow!(
+                    "{} ({}); acknowledge the device wear to proceed",
+                    reason,
+                    disk.id
+                ))
+            }
+        }
+    }
+    Ok((!reasons.is_empty()).then(|| reasons.join("; ")))
+}
+
+/// The claim and wear reasons a target was let through with, as reported
+/// under `device_wear`.
+fn join_reasons(claim: Option<String>, wear: Option<String>) -> Option<String> {
+    match (claim, wear) {
+        (Some(claim), Some(wear)) => Some(format!("{}; {}", claim, wear)),
+        (claim, wear) => claim.or(wear),
+    }
+}
+
+/// Refuses to wipe a disk whose contents look like personal data unless
+/// the run confirmed the overwrite. Dry runs only report the triggers.
+fn check_overwrite(disk: &phoenix_core::Disk, confirmed: bool, dry_run: bool) -> Result<Vec<String>> {
+    let check = overwrite::inspect_disk(disk);
+    if !check.triggers.is_empty() && !confirmed && !dry_run {
+        return Err(anyhow!(
+            "{} looks like personal data ({}); confirm the overwrite to proceed",
+            disk.id,
+            check.triggers.join("; ")
+        ));
+    }
+    Ok(check.triggers)
+}
+
+/// Holds the target disk to the safety layer's size limits. Returns the
+/// reason when the run acknowledged a disk outside them.
+fn check_target_disk_size(disk: &phoenix_core::Disk, acknowledged: bool) -> Result<Option<String>> {
+    let limits = TargetSizeLimits::from_env().map_err(|err| anyhow!(err))?;
+    match check_target_size(disk.size_bytes, &limits, acknowledged) {
+        TargetSizeDecision::Allow => Ok(None),
+        TargetSizeDecision::Acknowledged(reason) => Ok(Some(reason)),
+        TargetSizeDecision::Deny(reason) => Err(anyhow!(
+            "{} ({}); acknowledge the target size to proceed",
+            reason,
+            disk.id
+        )),
+    }
+}
+
+/// Fails before anything is formatted when the source cannot fit the
+/// volume once the filesystem's own structures and cluster slack are taken
+/// out. Returns the estimate for the report.
+fn check_format_capacity(
+    estimate: &CapacityEstimate,
+    files: &[FileEntry],
+    logs: &mut StepLog,
+) -> Result<FormatCapacity> {
+    let required = estimate.required_bytes(files.iter().map(|entry| entry.size), directory_count(files));
+    logs.push(format!(
+        "format_capacity filesystem={} volume_bytes={} cluster_bytes={} usable_bytes={} required_bytes={}",
+        estimate.filesystem,
+        estimate.volume_bytes,
+        estimate.cluster_bytes,
+        estimate.usable_bytes,
+        required
+    ));
+    if required > estimate.usable_bytes {
+        return Err(anyhow!(
+            "source needs {} bytes on {} but the {} byte volume holds only {} after formatting",
+            required,
+            estimate.filesystem,
+            estimate.volume_bytes,
+            estimate.usable_bytes
+        ));
+    }
+    Ok(FormatCapacity {
+        estimate: estimate.clone(),
+        required_bytes: required,
+    })
+}
+
+fn directory_count(files: &[FileEntry]) -> usize {
+    let mut directories = std::collections::HashSet::new();
+    for entry in files {
+        let mut parent = entry.relative_path.parent();
+        while let Some(dir) = parent.filter(|dir| !dir.as_os_str().is_empty()) {
+            if !directories.insert(dir) {
+                break;
+            }
+            parent = dir.parent();
+        }
+    }
+    directories.len()
+}
+
+/// `PHOENIX_HOST=mock` stand-in for `prepare_usb_disk`: writes the planned
+/// GPT into the disk's sandbox file and returns an empty sandbox volume.
+fn mock_repartition(
+    disk: &phoenix_core::Disk,
+    plan: &phoenix_partition::plan::PartitionPlan,
+) -> Result<PathBuf> {
+    let path = phoenix_core::mock::disk_file(disk)?;
+    let mut file = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(&path)
+        .with_context(|| format!("open {}", path.display()))?;
+    plan.write_gpt(&mut file)?;
+    let mount = phoenix_core::mock::mount_dir(&format!("{}-Partition1", disk.id))?;
+    phoenix_core::mock::format_volume(&mount)?;
+    Ok(mount)
+}
+
+/// This host's device graph, with the configured USB port labels.
+pub fn build_device_graph() -> Result<DeviceGraph> {
+    let mut graph = host_device_graph()?;
+    graph.apply_usb_port_labels(&usb_port_labels()?);
+    if let Ok(Some(guid)) = phoenix_efivars::booted_esp_guid() {
+        mark_booted_esp(&mut graph, &guid);
+    }
+    observe_devices(&mut graph);
+    Ok(graph)
+}
+
+/// Makes the disk holding the ESP the firmware booted from a system disk.
+/// Hosts that boot from an external SSD see it as removable, and the
+/// removable check alone would offer the running system as a target.
+pub(crate) fn mark_booted_esp(graph: &mut DeviceGraph, esp_guid: &str) {
+    for disk in &mut graph.disks {
+        let holds_esp = disk.partitions.iter().any(|partition| {
+            partition
+                .part_uuid
+                .as_deref()
+                .is_some_and(|uuid| uuid.eq_ignore_ascii_case(esp_guid))
+        });
+        if holds_esp {
+            disk.is_system_disk = true;
+            disk.booted_esp = Some(esp_guid.to_ascii_lowercase());
+        }
+    }
+}
+
+/// Records the graph's disks in the device registry and attaches their
+/// history. A registry that cannot be read or written leaves the graph
+/// without history rather than failing enumeration.
+pub fn observe_devices(graph: &mut DeviceGraph) {
+    if let Ok(registry) = DeviceRegistry::open_default() {
+        let _ = registry.observe(graph);
+    }
+}
+
+fn host_device_graph() -> Result<DeviceGraph> {
+    #[cfg(target_os = "windows")]
+    {
+        phoenix_host_windows::build_device_graph()
+    }
+    #[cfg(target_os = "linux")]
+    {
+        phoenix_host_linux::build_device_graph()
+    }
+    #[cfg(target_os = "macos")]
+    {
+        phoenix_host_macos::build_device_graph()
+    }
+    #[cfg(not(any(target_os = "windows", target_os = "linux", target_os = "macos")))]
+    {
+        Err(anyhow!("unsupported OS"))
+    }
+}
+
+/// Graph for reports of steps without a target; it only records the host
+/// when enumeration fails.
+fn report_graph() -> DeviceGraph {
+    build_device_graph().unwrap_or_else(|_| {
+        DeviceGraph::new(
+            phoenix_core::HostInfo {
+                os: current_os().to_string(),
+                os_version: String::new(),
+                machine: String::new(),
+                os_edition: None,
+                os_display_version: None,
+            },
+            Vec::new(),
+            phoenix_core::now_utc_rfc3339(),
+        )
+    })
+}
+
+fn max_file_size(entries: &[FileEntry]) -> u64 {
+    entries.iter().map(|entry| entry.size).max().unwrap_or(0)
+}
+
+#[derive(serde::Serialize)]
+struct CopyManifestEntry {
+    path: String,
+    bytes: u64,
+    sha256: String,
+    mtime_unix: Option<i64>,
+    mtime_preserved: bool,
+    /// Source hash before a pack transform edited the file; `sha256` is
+    /// then the edited file's.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    original_sha256: Option<String>,
+    /// Original this file was hardlinked to instead of being copied.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    linked_to: Option<String>,
+    /// Destination hash read back, with `hash_destination`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    destination_sha256: Option<String>,
+}
+
+struct CopiedFile {
+    mtime_unix: Option<i64>,
+    mtime_preserved: bool,
+    /// Source hash taken from the copy buffers, when hashing.
+    sha256: Option<String>,
+    /// Hash of the destination read back, with `CopyHashing::Destination`.
+    destination_sha256: Option<String>,
+}
+
+/// What a copy hashes on the way for the manifest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CopyHashing {
+    Off,
+    /// Hash the bytes as they are copied; the source is read once.
+    Source,
+    /// Also read the destination back and check it matches.
+    Destination,
+    /// Read back only this percentage of the files, picked by path.
+    Sampled(u32),
+}
+
+impl CopyHashing {
+    fn new(hash_manifest: bool, hash_destination: bool) -> Result<Self> {
+        match (hash_manifest, hash_destination) {
+            (false, false) => Ok(Self::Off),
+            (false, true) => Err(anyhow!("hash_destination needs hash_manifest")),
+            (true, false) => Ok(Self::Source),
+            (true, true) => Ok(Self::Destination),
+        }
+    }
+
+    /// `self` with the read-back the verification policy settled on.
+    fn with_verify(self, verify: &AppliedVerify) -> Self {
+        match verify.level {
+            VerifyLevel::Off => self,
+            VerifyLevel::Full => Self::Destination,
+            VerifyLevel::Sampled => Self::Sampled(verify.sample_percent.unwrap_or(100)),
+        }
+    }
+
+    /// Whether the copy to `dest` is read back.
+    fn reads_back(self, dest: &Path) -> bool {
+        match self {
+            Self::Destination => true,
+            Self::Sampled(percent) => {
+                let digest = Sha256::digest(dest.to_string_lossy().as_bytes());
+                u32::from_le_bytes([digest[0], digest[1], digest[2], digest[3]]) % 100 < percent
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Copies a file and carries the source mtime over when the destination
+/// filesystem accepts it. FAT targets round to 2 seconds, so callers record
+/// the source value rather than reading the destination back.
+fn copy_file_with_mtime(source: &Path, dest: &Path) -> Result<CopiedFile> {
+    copy_file(source, dest, CopyHashing::Off)
+}
+
+/// `copy_file_with_mtime` that also hashes per `hashing`. A destination
+/// that reads back different from the source fails the copy.
+fn copy_file(source: &Path, dest: &Path, hashing: CopyHashing) -> Result<CopiedFile> {
+    cancel::check_cancelled()?;
+    // A retry copies the whole file again; both ways truncate first.
+    let target = || format!("copy {}", source.display());
+    let sha256 = match hashing {
+        CopyHashing::Off => {
+            phoenix_imaging::retry::retry_io(target, || fs::copy(source, dest))?;
+            None
+        }
+        _ => Some(phoenix_imaging::retry::retry_io(target, || {
+            copy_and_hash(source, dest)
+        })?),
+    };
+    let (modified, mtime_preserved) = carry_mtime(source, dest);
+    let destination_sha256 = match &sha256 {
+        Some(expected) if hashing.reads_back(dest) => Some(read_back(source, dest, expected)?),
+        _ => None,
+    };
+    Ok(CopiedFile {
+        mtime_unix: modified.map(system_time_unix),
+        mtime_preserved,
+        sha256,
+        destination_sha256,
+    })
+}
+
+/// `copy_file` for a file a pack transform edited: `content` is written
+/// in place of `source`'s bytes. The hash, of `content`, is always taken.
+fn write_file(source: &Path, dest: &Path, content: &[u8], hashing: CopyHashing) -> Result<CopiedFile> {
+    cancel::check_cancelled()?;
+    phoenix_imaging::retry::retry_io(
+        || format!("write {}", dest.display()),
+        || fs::write(dest, content),
+    )?;
+    if let Ok(metadata) = fs::metadata(source) {
+        fs::set_permissions(dest, metadata.permissions())?;
+    }
+    let sha256 = to_hex(&Sha256::digest(content));
+    let (modified, mtime_preserved) = carry_mtime(source, dest);
+    let destination_sha256 = if hashing.reads_back(dest) {
+        Some(read_back(source, dest, &sha256)?)
+    } else {
+        None
+    };
+    Ok(CopiedFile {
+        mtime_unix: modified.map(system_time_unix),
+        mtime_preserved,
+        sha256: Some(sha256),
+        destination_sha256,
+    })
+}
+
+/// Sets `dest`'s mtime to `source`'s; the mtime, and whether it took.
+fn carry_mtime(source: &Path, dest: &Path) -> (Option<std::time::SystemTime>, bool) {
+    let modified = fs::metadata(source).and_then(|meta| meta.modified()).ok();
+    let preserved = match modified {
+        Some(time) => fs::OpenOptions::new()
+            .write(true)
+            .open(dest)
+            .and_then(|file| file.set_modified(time))
+            .is_ok(),
+        None => false,
+    };
+    (modified, preserved)
+}
+
+/// Hashes `dest` and fails unless it is `expected`.
+fn read_back(source: &Path, dest: &Path, expected: &str) -> Result<String> {
+    let actual = hash_file(dest)?;
+    if actual != expected {
+        return Err(anyhow!(
+            "{} reads back as sha256 {} but {} is {}",
+            dest.display(),
+            actual,
+            source.display(),
+            expected
+        ));
+    }
+    Ok(actual)
+}
+
+/// `fs::copy` that feeds every buffer it writes to SHA-256 as well.
+fn copy_and_hash(source: &Path, dest: &Path) -> std::io::Result<String> {
+    use std::io::{Read, Write};
+    let mut input = fs::File::open(source)?;
+    let mut output = fs::File::create(dest)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; 1024 * 1024];
+    loop {
+        let read = input.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+        output.write_all(&buffer[..read])?;
+    }
+    output.set_permissions(input.metadata()?.permissions())?;
+    Ok(to_hex(&hasher.finalize()))
+}
+
+/// `copy_file` for the bulk copy of an installer run. With
+/// `flush_every_bytes` set, the files copied since the last flush are
+/// synced each time that many bytes accumulate, and once more at the end.
+struct Copier {
+    hashing: CopyHashing,
+    /// `0` never syncs.
+    flush_every: u64,
+    unsynced: Vec<PathBuf>,
+    unsynced_bytes: u64,
+    copied_bytes: u64,
+    durable_bytes: u64,
+    flushes: u64,
+}
+
+impl Copier {
+    fn new(hashing: CopyHashing, flush_every_bytes: Option<u64>) -> Self {
+        Self {
+            hashing,
+            flush_every: flush_every_bytes.unwrap_or(0),
+            unsynced: Vec::new(),
+            unsynced_bytes: 0,
+            copied_bytes: 0,
+            durable_bytes: 0,
+            flushes: 0,
+        }
+    }
+
+    fn copy(&mut self, source: &Path, dest: &Path) -> Result<CopiedFile> {
+        let copied = copy_file(source, dest, self.hashing)?;
+        self.track(dest)?;
+        Ok(copied)
+    }
+
+    /// `copy` of `content`, the edited `source`.
+    fn write(&mut self, source: &Path, dest: &Path, content: &[u8]) -> Result<CopiedFile> {
+        let copied = write_file(source, dest, content, self.hashing)?;
+        self.track(dest)?;
+        Ok(copied)
+    }
+
+    fn track(&mut self, dest: &Path) -> Result<()> {
+        if self.flush_every > 0 {
+            let bytes = fs::metadata(dest)?.len();
+            self.unsynced.push(dest.to_path_buf());
+            self.unsynced_bytes = self.unsynced_bytes.saturating_add(bytes);
+            self.copied_bytes = self.copied_bytes.saturating_add(bytes);
+            if self.unsynced_bytes >= self.flush_every {
+                self.flush()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Syncs every file copied since the last flush.
+    fn flush(&mut self) -> Result<()> {
+        if self.unsynced.is_empty() {
+            return Ok(());
+        }
+        for path in self.unsynced.drain(..) {
+            // Windows only flushes handles opened for writing.
+            let file = fs::OpenOptions::new()
+                .write(true)
+                .open(&path)
+                .with_context(|| format!("open {} to flush", path.display()))?;
+            phoenix_imaging::retry::retry_io(
+                || format!("flush {}", path.display()),
+                || file.sync_all(),
+            )?;
+        }
+        self.unsynced_bytes = 0;
+        self.durable_bytes = self.copied_bytes;
+        self.flushes += 1;
+        Ok(())
+    }
+
+    /// Bytes of copied files known to be on the target.
+    fn durable_bytes(&self) -> u64 {
+        self.durable_bytes
+    }
+
+    fn flushes(&self) -> u64 {
+        self.flushes
+    }
+}
+
+fn system_time_unix(time: std::time::SystemTime) -> i64 {
+    match time.duration_since(std::time::UNIX_EPOCH) {
+        Ok(duration) => duration.as_secs() as i64,
+        Err(err) => -(err.duration().as_secs() as i64),
+    }
+}
+
+fn ensure_boot_files(entries: &[FileEntry]) -> Result<()> {
+    let mut has_boot_wim = false;
+    let mut has_efi = false;
+    for entry in entries {
+        let rel = entry.relative_path.to_string_lossy().replace('\\', "/");
+        let rel_lower = rel.to_ascii_lowercase();
+        if rel_lower == "sources/boot.wim" {
+            has_boot_wim = true;
+        }
+        if rel_lower.starts_with("efi/boot/") && rel_lower.ends_with(".efi") {
+            has_efi = true;
+        }
+    }
+    if !has_boot_wim {
+        return Err(anyhow!("missing sources/boot.wim in installer source"));
+    }
+    if !has_efi {
+        return Err(anyhow!("missing EFI bootloader in installer source"));
+    }
+    Ok(())
+}
+
+fn ensure_unix_boot_files(entries: &[FileEntry], os: &str) -> Result<()> {
+    let mut has_efi = false;
+    let mut has_grub = false;
+    let mut has_isolinux = false;
+    let mut has_macos_boot = false;
+    for entry in entries {
+        let rel = entry.relative_path.to_string_lossy().replace('\\', "/");
+        let rel_lower = rel.to_ascii_lowercase();
+        if rel_lower.starts_with("efi/boot/") {
+            has_efi = true;
+        }
+        if rel_lower.starts_with("boot/grub") {
+            has_grub = true;
+        }
+        if rel_lower.starts_with("isolinux/") {
+            has_isolinux = true;
+        }
+        if rel_lower == "system/library/coreservices/boot.efi" {
+            has_macos_boot = true;
+        }
+    }
+
+    match os {
+        "linux" if !has_efi && !has_grub && !has_isolinux => {
+            return Err(anyhow!(
+                "linux source missing EFI/BOOT, boot/grub, or isolinux"
+            ));
+        }
+        "macos" if !has_macos_boot && !has_efi => {
+            return Err(anyhow!(
+                "macos source missing System/Library/CoreServices/boot.efi or EFI/BOOT"
+            ));
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone)]
+struct BootCandidate {
+    source: PathBuf,
+    relative: String,
+    is_dir: bool,
+}
+
+fn boot_prep_candidates(os: &str, source_root: &Path) -> Result<Vec<BootCandidate>> {
+    let mut candidates = Vec::new();
+    match os {
+        "linux" => {
             add_candidate_dir(&mut candidates, source_root, "EFI/BOOT");
             add_candidate_dir(&mut candidates, source_root, "boot/grub");
             add_candidate_dir(&mut candidates, source_root, "isolinux");
@@ -2304,10 +5334,10 @@ struct CopyStats {
 fn copy_dir_recursive(
     source: &Path,
     dest: &Path,
-    hash_manifest: bool,
+    hashing: CopyHashing,
 ) -> Result<CopyStats> {
     let mut stats = CopyStats::default();
-    copy_dir_recursive_inner(source, dest, source, hash_manifest, &mut stats)?;
+    copy_dir_recursive_inner(source, dest, source, hashing, &mut stats)?;
     Ok(stats)
 }
 
@@ -2315,7 +5345,7 @@ fn copy_dir_recursive_inner(
     source_root: &Path,
     dest_root: &Path,
     current: &Path,
-    hash_manifest: bool,
+    hashing: CopyHashing,
     stats: &mut CopyStats,
 ) -> Result<()> {
     for entry in fs::read_dir(current)? {
@@ -2330,20 +5360,24 @@ fn copy_dir_recursive_inner(
         let dest_path = dest_root.join(&relative);
         if metadata.is_dir() {
             fs::create_dir_all(&dest_path)?;
-            copy_dir_recursive_inner(source_root, dest_root, &path, hash_manifest, stats)?;
+            copy_dir_recursive_inner(source_root, dest_root, &path, hashing, stats)?;
         } else if metadata.is_file() {
             if let Some(parent) = dest_path.parent() {
                 fs::create_dir_all(parent)?;
             }
-            fs::copy(&path, &dest_path)?;
+            let copied = copy_file(&path, &dest_path, hashing)?;
             stats.files += 1;
             stats.bytes = stats.bytes.saturating_add(metadata.len());
-            if hash_manifest {
-                let hash = hash_file(&path)?;
+            if let Some(hash) = copied.sha256 {
                 stats.manifest.push(CopyManifestEntry {
                     path: relative,
                     bytes: metadata.len(),
                     sha256: hash,
+                    original_sha256: None,
+                    mtime_unix: copied.mtime_unix,
+                    mtime_preserved: copied.mtime_preserved,
+                    linked_to: None,
+                    destination_sha256: copied.destination_sha256,
                 });
             }
         }
@@ -2391,88 +5425,425 @@ fn to_hex(bytes: &[u8]) -> String {
     out
 }
 
-fn default_driver_target() -> PathBuf {
-    PathBuf::from(r"sources\$OEM$\$1\Drivers")
+fn default_driver_target() -> PathBuf {
+    PathBuf::from(r"sources\$OEM$\$1\Drivers")
+}
+
+fn build_usb_params(value: &serde_json::Value, default_report: &Path) -> Result<WindowsInstallerUsbParams> {
+    let target_disk_id = require_string(value, "target_disk_id")?;
+    let source_path = PathBuf::from(require_string(value, "source_path")?);
+    let report_base = optional_string(value, "report_base")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| default_report.to_path_buf());
+    let filesystem = parse_filesystem_value(optional_string(value, "filesystem").unwrap_or("fat32"))?;
+    let label = optional_string(value, "label").map(str::to_string);
+
+    Ok(WindowsInstallerUsbParams {
+        target_disk_id: target_disk_id.to_string(),
+        source_path,
+        target_mount: optional_string(value, "target_mount").map(PathBuf::from),
+        report_base,
+        force: optional_bool(value, "force", false),
+        confirmation_token: optional_string(value, "confirmation_token").map(str::to_string),
+        acknowledge_target_size: optional_bool(value, "acknowledge_target_size", false),
+        acknowledge_device_wear: optional_bool(value, "acknowledge_device_wear", false),
+        confirm_overwrite: optional_bool(value, "confirm_overwrite", false),
+        dry_run: optional_bool(value, "dry_run", true),
+        repartition: optional_bool(value, "repartition", false),
+        format: optional_bool(value, "format", false),
+        filesystem,
+        label,
+        cluster_bytes: optional_size(value, "cluster_bytes")?,
+        driver_source: optional_string(value, "driver_source").map(PathBuf::from),
+        driver_target: optional_string(value, "driver_target").map(PathBuf::from),
+        hash_manifest: optional_bool(value, "hash_manifest", false),
+        hash_destination: optional_bool(value, "hash_destination", false),
+        partitions: parse_partition_specs(value)?,
+        hybrid_mbr: optional_bool(value, "hybrid_mbr", false),
+        edition: optional_string(value, "edition").map(str::to_string),
+        edition_selector: optional_string(value, "edition_selector").map(str::to_string),
+        pid_txt: optional_string(value, "pid_txt").map(str::to_string),
+        source_filter: source_filter(value)?,
+        dedupe: optional_bool(value, "dedupe", false),
+        flush_every_bytes: optional_size(value, "flush_every_bytes")?,
+        continue_on_error: optional_bool(value, "continue_on_error", false),
+        transform_pack: optional_string(value, "transform_pack").map(PathBuf::from),
+    })
+}
+
+/// Reads the optional `partitions` array. Entries are either spec strings
+/// (`NAME:TYPE[:SIZE][:ATTR+ATTR]`) or objects with `name`, `type`,
+/// `size` and `attributes`.
+fn parse_partition_specs(value: &serde_json::Value) -> Result<Vec<PartitionSpec>> {
+    let Some(entries) = value.get("partitions") else {
+        return Ok(Vec::new());
+    };
+    let entries = entries
+        .as_array()
+        .ok_or_else(|| anyhow!("partitions must be an array"))?;
+    let mut specs = Vec::with_capacity(entries.len());
+    for entry in entries {
+        if let Some(spec) = entry.as_str() {
+            specs.push(PartitionSpec::parse(spec)?);
+            continue;
+        }
+        let name = require_string(entry, "name")?;
+        let mut spec = PartitionSpec::basic_data(name);
+        if let Some(kind) = optional_string(entry, "type") {
+            spec.type_guid = phoenix_partition::parse_partition_type(kind)?;
+        }
+        spec.size_bytes = match entry.get("size") {
+            None | Some(serde_json::Value::Null) => None,
+            Some(serde_json::Value::Number(size)) => Some(
+                size.as_u64()
+                    .ok_or_else(|| anyhow!("invalid partition size for {}", name))?,
+            ),
+            Some(serde_json::Value::String(size)) if size == "rest" => None,
+            Some(serde_json::Value::String(size)) => Some(parse_size(size)?),
+            Some(_) => return Err(anyhow!("invalid partition size for {}", name)),
+        };
+        let mut attributes = GptAttributes::default();
+        if let Some(list) = entry.get("attributes").and_then(|v| v.as_array()) {
+            for attr in list {
+                attributes.set(
+                    attr.as_str()
+                        .ok_or_else(|| anyhow!("partition attributes must be strings"))?,
+                )?;
+            }
+        }
+        spec.attributes = attributes;
+        specs.push(spec);
+    }
+    Ok(specs)
+}
+
+fn build_apply_params(value: &serde_json::Value, default_report: &Path) -> Result<WindowsApplyImageParams> {
+    let source_path = PathBuf::from(require_string(value, "source_path")?);
+    let target_dir = PathBuf::from(require_string(value, "target_dir")?);
+    let report_base = optional_string(value, "report_base")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| default_report.to_path_buf());
+    let image_index = value
+        .get("image_index")
+        .and_then(|v| v.as_u64())
+        .map(|v| v as u32);
+    let edition_selector = optional_string(value, "edition_selector").map(str::to_string);
+    if image_index.is_none() && edition_selector.is_none() {
+        return Err(anyhow!("image_index or edition_selector is required"));
+    }
+
+    Ok(WindowsApplyImageParams {
+        source_path,
+        image_index,
+        edition_selector,
+        target_dir,
+        report_base,
+        force: optional_bool(value, "force", false),
+        confirmation_token: optional_string(value, "confirmation_token").map(str::to_string),
+        allow_system_target: optional_bool(value, "allow_system_target", false),
+        dry_run: optional_bool(value, "dry_run", true),
+        verify: optional_bool(value, "verify", false),
+    })
+}
+
+fn build_verify_params(value: &serde_json::Value) -> Result<(PathBuf, Option<String>)> {
+    let path = PathBuf::from(require_string(value, "path")?);
+    let key = optional_string(value, "signing_key").map(str::to_string);
+    Ok((path, key))
+}
+
+fn build_validate_source_params(
+    value: &serde_json::Value,
+    default_report: &Path,
+) -> Result<ValidateSourceParams> {
+    Ok(ValidateSourceParams {
+        source_path: PathBuf::from(require_string(value, "source_path")?),
+        os: optional_string(value, "os").map(str::to_string),
+        filesystem: parse_filesystem_value(optional_string(value, "filesystem").unwrap_or("fat32"))?,
+        source_filter: source_filter(value)?,
+        target_model: optional_string(value, "target_model").map(str::to_string),
+        report_base: optional_string(value, "report_base")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| default_report.to_path_buf()),
+    })
+}
+
+fn build_slim_media_params(
+    value: &serde_json::Value,
+    default_report: &Path,
+) -> Result<SlimWindowsMediaParams> {
+    Ok(SlimWindowsMediaParams {
+        source_path: PathBuf::from(require_string(value, "source_path")?),
+        keep: media_keep_list(value)?,
+        report_base: optional_string(value, "report_base")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| default_report.to_path_buf()),
+        dry_run: optional_bool(value, "dry_run", true),
+    })
+}
+
+fn build_merge_languages_params(
+    value: &serde_json::Value,
+    default_report: &Path,
+) -> Result<MergeWindowsLanguagesParams> {
+    Ok(MergeWindowsLanguagesParams {
+        source_path: PathBuf::from(require_string(value, "source_path")?),
+        language_sources: optional_string_list(value, "language_sources")?
+            .into_iter()
+            .map(PathBuf::from)
+            .collect(),
+        languages: optional_string_list(value, "languages")?,
+        report_base: optional_string(value, "report_base")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| default_report.to_path_buf()),
+        dry_run: optional_bool(value, "dry_run", true),
+    })
+}
+
+/// The `keep_list` file, if any, extended by inline `languages` and
+/// `editions` arrays.
+fn source_filter(value: &serde_json::Value) -> Result<SourceFilter> {
+    SourceFilter::new(
+        optional_string_list(value, "include")?,
+        optional_string_list(value, "exclude")?,
+    )
+}
+
+fn media_keep_list(value: &serde_json::Value) -> Result<MediaKeepList> {
+    let mut keep = match optional_string(value, "keep_list") {
+        Some(path) => MediaKeepList::load(Path::new(path))?,
+        None => MediaKeepList::default(),
+    };
+    keep.languages.extend(optional_string_list(value, "languages")?);
+    keep.editions.extend(optional_string_list(value, "editions")?);
+    Ok(keep)
+}
+
+fn build_hash_params(value: &serde_json::Value, default_report: &Path) -> Result<DiskHashReportParams> {
+    let disk_id = require_string(value, "disk_id")?;
+    let report_base = optional_string(value, "report_base")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| default_report.to_path_buf());
+    let chunk_size = optional_chunk_size(value)?;
+    let max_chunks = value.get("max_chunks").and_then(|v| v.as_u64());
+
+    Ok(DiskHashReportParams {
+        disk_id: disk_id.to_string(),
+        chunk_size,
+        max_chunks,
+        report_base,
+        partition_id: optional_string(value, "partition_id").map(str::to_string),
+        compare_baseline: optional_string(value, "compare_baseline").map(str::to_string),
+        save_baseline: optional_string(value, "save_baseline").map(str::to_string),
+    })
+}
+
+fn build_bad_block_scan_params(
+    value: &serde_json::Value,
+    default_report: &Path,
+) -> Result<BadBlockScanParams> {
+    let disk_id = require_string(value, "disk_id")?;
+    let report_base = optional_string(value, "report_base")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| default_report.to_path_buf());
+    let sector_size = optional_size(value, "sector_size")?;
+    if sector_size == Some(0) {
+        return Err(anyhow!("sector_size must be positive"));
+    }
+
+    Ok(BadBlockScanParams {
+        disk_id: disk_id.to_string(),
+        mode: parse_scan_mode(optional_string(value, "mode").unwrap_or("read"))?,
+        chunk_size: optional_chunk_size(value)?,
+        sector_size,
+        max_bad_bytes: optional_size(value, "max_bad_bytes")?,
+        report_base,
+        force: optional_bool(value, "force", false),
+        confirmation_token: optional_string(value, "confirmation_token").map(str::to_string),
+        acknowledge_target_size: optional_bool(value, "acknowledge_target_size", false),
+        acknowledge_device_wear: optional_bool(value, "acknowledge_device_wear", false),
+        confirm_overwrite: optional_bool(value, "confirm_overwrite", false),
+        dry_run: optional_bool(value, "dry_run", true),
+    })
+}
+
+fn build_combo_stick_params(
+    value: &serde_json::Value,
+    default_report: &Path,
+) -> Result<ComboStickParams> {
+    let report_base = optional_string(value, "report_base")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| default_report.to_path_buf());
+    let menu_timeout = value
+        .get("menu_timeout")
+        .and_then(|v| v.as_u64())
+        .map(|secs| u32::try_from(secs).map_err(|_| anyhow!("menu_timeout too large: {}", secs)))
+        .transpose()?;
+
+    Ok(ComboStickParams {
+        disk_id: require_string(value, "disk_id")?.to_string(),
+        windows_source: PathBuf::from(require_string(value, "windows_source")?),
+        rescue_source: PathBuf::from(require_string(value, "rescue_source")?),
+        bootloader_source: PathBuf::from(require_string(value, "bootloader_source")?),
+        windows_size: optional_size(value, "windows_size")?,
+        rescue_size: optional_size(value, "rescue_size")?,
+        payload_pack: optional_string(value, "payload_pack").map(PathBuf::from),
+        payloads: optional_string_list(value, "payloads")?,
+        tools_size: optional_size(value, "tools_size")?,
+        data_label: optional_string(value, "data_label").map(str::to_string),
+        data_encryption: optional_string(value, "data_encryption")
+            .map(parse_data_encryption)
+            .transpose()?,
+        data_key_secret: optional_string(value, "data_key_secret").map(str::to_string),
+        menu_title: optional_string(value, "menu_title").map(str::to_string),
+        menu_timeout,
+        report_base,
+        force: optional_bool(value, "force", false),
+        confirmation_token: optional_string(value, "confirmation_token").map(str::to_string),
+        acknowledge_target_size: optional_bool(value, "acknowledge_target_size", false),
+        acknowledge_device_wear: optional_bool(value, "acknowledge_device_wear", false),
+        confirm_overwrite: optional_bool(value, "confirm_overwrite", false),
+        dry_run: optional_bool(value, "dry_run", true),
+    })
+}
+
+fn build_ab_stick_params(value: &serde_json::Value, default_report: &Path) -> Result<AbStickParams> {
+    let report_base = optional_string(value, "report_base")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| default_report.to_path_buf());
+    let menu_timeout = value
+        .get("menu_timeout")
+        .and_then(|v| v.as_u64())
+        .map(|secs| u32::try_from(secs).map_err(|_| anyhow!("menu_timeout too large: {}", secs)))
+        .transpose()?;
+
+    Ok(AbStickParams {
+        disk_id: require_string(value, "disk_id")?.to_string(),
+        image_source: PathBuf::from(require_string(value, "image_source")?),
+        bootloader_source: PathBuf::from(require_string(value, "bootloader_source")?),
+        slot_size: optional_size(value, "slot_size")?,
+        version: optional_string(value, "version").map(str::to_string),
+        menu_title: optional_string(value, "menu_title").map(str::to_string),
+        menu_timeout,
+        report_base,
+        force: optional_bool(value, "force", false),
+        confirmation_token: optional_string(value, "confirmation_token").map(str::to_string),
+        acknowledge_target_size: optional_bool(value, "acknowledge_target_size", false),
+        acknowledge_device_wear: optional_bool(value, "acknowledge_device_wear", false),
+        confirm_overwrite: optional_bool(value, "confirm_overwrite", false),
+        dry_run: optional_bool(value, "dry_run", true),
+    })
 }
 
-fn build_usb_params(value: &serde_json::Value, default_report: &Path) -> Result<WindowsInstallerUsbParams> {
-    let target_disk_id = require_string(value, "target_disk_id")?;
-    let source_path = PathBuf::from(require_string(value, "source_path")?);
-    let report_base = PathBuf::from(optional_string(value, "report_base").unwrap_or_else(|| {
-        default_report.display().to_string()
-    }));
-    let filesystem = parse_filesystem_value(optional_string(value, "filesystem").unwrap_or("fat32"))?;
-    let label = optional_string(value, "label").map(str::to_string);
+fn build_ab_update_params(value: &serde_json::Value, default_report: &Path) -> Result<AbUpdateParams> {
+    let report_base = optional_string(value, "report_base")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| default_report.to_path_buf());
 
-    Ok(WindowsInstallerUsbParams {
-        target_disk_id: target_disk_id.to_string(),
-        source_path,
-        target_mount: optional_string(value, "target_mount").map(PathBuf::from),
+    Ok(AbUpdateParams {
+        disk_id: require_string(value, "disk_id")?.to_string(),
+        image_source: optional_string(value, "image_source").map(PathBuf::from),
+        version: optional_string(value, "version").map(str::to_string),
+        rollback: optional_bool(value, "rollback", false),
         report_base,
         force: optional_bool(value, "force", false),
         confirmation_token: optional_string(value, "confirmation_token").map(str::to_string),
         dry_run: optional_bool(value, "dry_run", true),
-        repartition: optional_bool(value, "repartition", false),
-        format: optional_bool(value, "format", false),
-        filesystem,
-        label,
-        driver_source: optional_string(value, "driver_source").map(PathBuf::from),
-        driver_target: optional_string(value, "driver_target").map(PathBuf::from),
-        hash_manifest: optional_bool(value, "hash_manifest", false),
     })
 }
 
-fn build_apply_params(value: &serde_json::Value, default_report: &Path) -> Result<WindowsApplyImageParams> {
-    let source_path = PathBuf::from(require_string(value, "source_path")?);
-    let target_dir = PathBuf::from(require_string(value, "target_dir")?);
-    let report_base = PathBuf::from(optional_string(value, "report_base").unwrap_or_else(|| {
-        default_report.display().to_string()
-    }));
-    let image_index = require_u32(value, "image_index")?;
-
-    Ok(WindowsApplyImageParams {
-        source_path,
-        image_index,
-        target_dir,
+fn build_clone_device_params(
+    value: &serde_json::Value,
+    default_report: &Path,
+) -> Result<CloneDeviceParams> {
+    let report_base = optional_string(value, "report_base")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| default_report.to_path_buf());
+
+    Ok(CloneDeviceParams {
+        source_disk_id: require_string(value, "source_disk_id")?.to_string(),
+        target_disk_id: require_string(value, "target_disk_id")?.to_string(),
+        archive: optional_string(value, "archive").map(PathBuf::from),
+        archive_in_report: optional_bool(value, "archive_in_report", false),
         report_base,
         force: optional_bool(value, "force", false),
         confirmation_token: optional_string(value, "confirmation_token").map(str::to_string),
+        acknowledge_target_size: optional_bool(value, "acknowledge_target_size", false),
+        acknowledge_device_wear: optional_bool(value, "acknowledge_device_wear", false),
+        confirm_overwrite: optional_bool(value, "confirm_overwrite", false),
         dry_run: optional_bool(value, "dry_run", true),
-        verify: optional_bool(value, "verify", false),
+        chunk_size: optional_chunk_size(value)?,
+        flush_every_bytes: optional_size(value, "flush_every_bytes")?,
     })
 }
 
-fn build_verify_params(value: &serde_json::Value) -> Result<(PathBuf, Option<String>)> {
-    let path = PathBuf::from(require_string(value, "path")?);
-    let key = optional_string(value, "signing_key").map(str::to_string);
-    Ok((path, key))
+fn build_restore_report_params(
+    value: &serde_json::Value,
+    default_report: &Path,
+) -> Result<RestoreReportParams> {
+    let report_base = optional_string(value, "report_base")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| default_report.to_path_buf());
+
+    Ok(RestoreReportParams {
+        report: PathBuf::from(require_string(value, "report")?),
+        target_disk_id: require_string(value, "target_disk_id")?.to_string(),
+        report_base,
+        force: optional_bool(value, "force", false),
+        confirmation_token: optional_string(value, "confirmation_token").map(str::to_string),
+        acknowledge_target_size: optional_bool(value, "acknowledge_target_size", false),
+        acknowledge_device_wear: optional_bool(value, "acknowledge_device_wear", false),
+        confirm_overwrite: optional_bool(value, "confirm_overwrite", false),
+        allow_unsigned: optional_bool(value, "allow_unsigned", false),
+        dry_run: optional_bool(value, "dry_run", true),
+        chunk_size: optional_chunk_size(value)?,
+        flush_every_bytes: optional_size(value, "flush_every_bytes")?,
+    })
 }
 
-fn build_hash_params(value: &serde_json::Value, default_report: &Path) -> Result<DiskHashReportParams> {
-    let disk_id = require_string(value, "disk_id")?;
-    let report_base = PathBuf::from(optional_string(value, "report_base").unwrap_or_else(|| {
-        default_report.display().to_string()
-    }));
-    let chunk_size = value
-        .get("chunk_size")
+fn build_resize_partition_params(
+    value: &serde_json::Value,
+    default_report: &Path,
+) -> Result<ResizePartitionParams> {
+    let report_base = optional_string(value, "report_base")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| default_report.to_path_buf());
+    let partition = value
+        .get("partition")
         .and_then(|v| v.as_u64())
-        .unwrap_or(8 * 1024 * 1024);
-    let max_chunks = value.get("max_chunks").and_then(|v| v.as_u64());
-
-    Ok(DiskHashReportParams {
-        disk_id: disk_id.to_string(),
-        chunk_size,
-        max_chunks,
+        .map(|number| u32::try_from(number).map_err(|_| anyhow!("partition too large: {}", number)))
+        .transpose()?;
+
+    Ok(ResizePartitionParams {
+        disk_id: require_string(value, "disk_id")?.to_string(),
+        partition,
+        mode: parse_resize_mode(require_string(value, "mode")?)?,
+        margin: optional_size(value, "margin")?,
         report_base,
+        force: optional_bool(value, "force", false),
+        confirmation_token: optional_string(value, "confirmation_token").map(str::to_string),
+        dry_run: optional_bool(value, "dry_run", true),
     })
 }
 
+/// `udisks` only drives the format and the power-off, and the power-off
+/// has no other path.
+fn check_udisks_options(params: &UnixInstallerUsbParams) -> Result<()> {
+    if params.udisks && params.format_device.is_none() && !params.power_off {
+        return Err(anyhow!("udisks only applies with format_device or power_off"));
+    }
+    if params.power_off && !params.udisks {
+        return Err(anyhow!("power_off goes through udisks2 and needs udisks"));
+    }
+    Ok(())
+}
+
 fn build_unix_usb_params(value: &serde_json::Value, default_report: &Path) -> Result<UnixInstallerUsbParams> {
     let source_path = PathBuf::from(require_string(value, "source_path")?);
     let target_mount = PathBuf::from(require_string(value, "target_mount")?);
-    let report_base = PathBuf::from(optional_string(value, "report_base").unwrap_or_else(|| {
-        default_report.display().to_string()
-    }));
+    let report_base = optional_string(value, "report_base")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| default_report.to_path_buf());
 
     Ok(UnixInstallerUsbParams {
         source_path,
@@ -2480,11 +5851,23 @@ fn build_unix_usb_params(value: &serde_json::Value, default_report: &Path) -> Re
         report_base,
         force: optional_bool(value, "force", false),
         confirmation_token: optional_string(value, "confirmation_token").map(str::to_string),
+        acknowledge_target_size: optional_bool(value, "acknowledge_target_size", false),
+        acknowledge_device_wear: optional_bool(value, "acknowledge_device_wear", false),
+        confirm_overwrite: optional_bool(value, "confirm_overwrite", false),
         dry_run: optional_bool(value, "dry_run", true),
         hash_manifest: optional_bool(value, "hash_manifest", false),
+        hash_destination: optional_bool(value, "hash_destination", false),
         format_device: optional_string(value, "format_device").map(PathBuf::from),
         format_size_bytes: value.get("format_size_bytes").and_then(|v| v.as_u64()),
         format_label: optional_string(value, "format_label").map(str::to_string),
+        format_cluster_bytes: optional_size(value, "format_cluster_bytes")?,
+        udisks: optional_bool(value, "udisks", false),
+        power_off: optional_bool(value, "power_off", false),
+        source_filter: source_filter(value)?,
+        dedupe: optional_bool(value, "dedupe", false),
+        flush_every_bytes: optional_size(value, "flush_every_bytes")?,
+        continue_on_error: optional_bool(value, "continue_on_error", false),
+        transform_pack: optional_string(value, "transform_pack").map(PathBuf::from),
     })
 }
 
@@ -2494,23 +5877,27 @@ fn build_unix_write_params(
 ) -> Result<UnixWriteImageParams> {
     let source_image = PathBuf::from(require_string(value, "source_image")?);
     let target_device = PathBuf::from(require_string(value, "target_device")?);
-    let report_base = PathBuf::from(optional_string(value, "report_base").unwrap_or_else(|| {
-        default_report.display().to_string()
-    }));
-    let chunk_size = value
-        .get("chunk_size")
-        .and_then(|v| v.as_u64())
-        .unwrap_or(8 * 1024 * 1024);
+    let report_base = optional_string(value, "report_base")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| default_report.to_path_buf());
+    let chunk_size = optional_chunk_size(value)?;
 
     Ok(UnixWriteImageParams {
         source_image,
+        source_mirrors: optional_string_list(value, "source_mirrors")?,
         target_device,
         report_base,
         force: optional_bool(value, "force", false),
         confirmation_token: optional_string(value, "confirmation_token").map(str::to_string),
+        acknowledge_target_size: optional_bool(value, "acknowledge_target_size", false),
+        acknowledge_device_wear: optional_bool(value, "acknowledge_device_wear", false),
+        confirm_overwrite: optional_bool(value, "confirm_overwrite", false),
         dry_run: optional_bool(value, "dry_run", true),
         verify: optional_bool(value, "verify", false),
         chunk_size,
+        fast_io: optional_bool(value, "fast_io", false),
+        flush_every_bytes: optional_size(value, "flush_every_bytes")?,
+        source_sha256: optional_string(value, "source_sha256").map(str::to_string),
     })
 }
 
@@ -2520,9 +5907,9 @@ fn build_unix_boot_params(
 ) -> Result<UnixBootPrepParams> {
     let source_path = PathBuf::from(require_string(value, "source_path")?);
     let target_mount = PathBuf::from(require_string(value, "target_mount")?);
-    let report_base = PathBuf::from(optional_string(value, "report_base").unwrap_or_else(|| {
-        default_report.display().to_string()
-    }));
+    let report_base = optional_string(value, "report_base")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| default_report.to_path_buf());
 
     Ok(UnixBootPrepParams {
         source_path,
@@ -2532,6 +5919,7 @@ fn build_unix_boot_params(
         confirmation_token: optional_string(value, "confirmation_token").map(str::to_string),
         dry_run: optional_bool(value, "dry_run", true),
         hash_manifest: optional_bool(value, "hash_manifest", false),
+        hash_destination: optional_bool(value, "hash_destination", false),
     })
 }
 
@@ -2541,14 +5929,18 @@ fn build_macos_installer_params(
 ) -> Result<MacosInstallerUsbParams> {
     let source_path = PathBuf::from(require_string(value, "source_path")?);
     let target_device = PathBuf::from(require_string(value, "target_device")?);
-    let report_base = PathBuf::from(optional_string(value, "report_base").unwrap_or_else(|| {
-        default_report.display().to_string()
-    }));
+    let report_base = optional_string(value, "report_base")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| default_report.to_path_buf());
     let volume_name = optional_string(value, "volume_name")
         .unwrap_or("PHOENIX-MACOS")
         .to_string();
     let macos_version = optional_string(value, "macos_version").map(str::to_string);
     let filesystem = optional_string(value, "filesystem").map(str::to_string);
+    let target_model = optional_string(value, "target_model").map(str::to_string);
+    if let Some(model) = &target_model {
+        MacArch::of_model(model)?;
+    }
 
     Ok(MacosInstallerUsbParams {
         source_path,
@@ -2557,8 +5949,12 @@ fn build_macos_installer_params(
         volume_name,
         macos_version,
         filesystem,
+        target_model,
         force: optional_bool(value, "force", false),
         confirmation_token: optional_string(value, "confirmation_token").map(str::to_string),
+        acknowledge_target_size: optional_bool(value, "acknowledge_target_size", false),
+        acknowledge_device_wear: optional_bool(value, "acknowledge_device_wear", false),
+        confirm_overwrite: optional_bool(value, "confirm_overwrite", false),
         dry_run: optional_bool(value, "dry_run", true),
     })
 }
@@ -2569,9 +5965,9 @@ fn build_stage_bootloader_params(
 ) -> Result<BootloaderStageParams> {
     let source_path = PathBuf::from(require_string(value, "source_path")?);
     let target_mount = PathBuf::from(require_string(value, "target_mount")?);
-    let report_base = PathBuf::from(optional_string(value, "report_base").unwrap_or_else(|| {
-        default_report.display().to_string()
-    }));
+    let report_base = optional_string(value, "report_base")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| default_report.to_path_buf());
     let target_subdir = optional_string(value, "target_subdir").map(PathBuf::from);
 
     Ok(BootloaderStageParams {
@@ -2583,6 +5979,166 @@ fn build_stage_bootloader_params(
         confirmation_token: optional_string(value, "confirmation_token").map(str::to_string),
         dry_run: optional_bool(value, "dry_run", true),
         hash_manifest: optional_bool(value, "hash_manifest", false),
+        hash_destination: optional_bool(value, "hash_destination", false),
+        mok_certificate: optional_string(value, "mok_certificate").map(PathBuf::from),
+        mok_hashes: optional_bool(value, "mok_hashes", false),
+        mok_manager: optional_string(value, "mok_manager").map(PathBuf::from),
+        grub_pack: optional_string(value, "grub_pack").map(PathBuf::from),
+        grub_dir: optional_string(value, "grub_dir").map(str::to_string),
+    })
+}
+
+fn build_stage_files_params(
+    value: &serde_json::Value,
+    default_report: &Path,
+) -> Result<StageFilesParams> {
+    let rules: Vec<StageFileRule> = match value.get("files") {
+        Some(files) => serde_json::from_value(files.clone())
+            .map_err(|err| anyhow!("files must be an array of {{source, destination}}: {}", err))?,
+        None => Vec::new(),
+    };
+    if rules.is_empty() {
+        return Err(anyhow!("files must list at least one file rule"));
+    }
+    let overwrite = match optional_string(value, "overwrite") {
+        Some(policy) => StageOverwrite::parse(policy)?,
+        None => StageOverwrite::default(),
+    };
+
+    Ok(StageFilesParams {
+        rules,
+        target_mount: PathBuf::from(require_string(value, "target_mount")?),
+        overwrite,
+        report_base: optional_string(value, "report_base")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| default_report.to_path_buf()),
+        force: optional_bool(value, "force", false),
+        confirmation_token: optional_string(value, "confirmation_token").map(str::to_string),
+        dry_run: optional_bool(value, "dry_run", true),
+    })
+}
+
+fn build_stage_firstboot_params(
+    value: &serde_json::Value,
+    default_report: &Path,
+) -> Result<StageFirstbootParams> {
+    let scripts: Vec<FirstbootScript> = match value.get("scripts") {
+        Some(scripts) => serde_json::from_value(scripts.clone()).map_err(|err| {
+            anyhow!("scripts must be an array of {{kind, template, ...}}: {}", err)
+        })?,
+        None => Vec::new(),
+    };
+    if scripts.is_empty() {
+        return Err(anyhow!("scripts must list at least one script"));
+    }
+    let vars = match value.get("vars") {
+        Some(vars) => serde_json::from_value(vars.clone())
+            .map_err(|err| anyhow!("vars must be an object of strings: {}", err))?,
+        None => std::collections::BTreeMap::new(),
+    };
+    let overwrite = match optional_string(value, "overwrite") {
+        Some(policy) => StageOverwrite::parse(policy)?,
+        None => StageOverwrite::default(),
+    };
+
+    Ok(StageFirstbootParams {
+        scripts,
+        vars,
+        target_mount: PathBuf::from(require_string(value, "target_mount")?),
+        overwrite,
+        report_base: optional_string(value, "report_base")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| default_report.to_path_buf()),
+        force: optional_bool(value, "force", false),
+        confirmation_token: optional_string(value, "confirmation_token").map(str::to_string),
+        dry_run: optional_bool(value, "dry_run", true),
+    })
+}
+
+fn build_stage_provisioning_params(
+    value: &serde_json::Value,
+    default_report: &Path,
+) -> Result<StageProvisioningParams> {
+    let packages = match value.get("packages") {
+        Some(packages) => serde_json::from_value::<Vec<String>>(packages.clone())
+            .map_err(|err| anyhow!("packages must be an array of paths: {}", err))?,
+        None => Vec::new(),
+    };
+    let overwrite = match optional_string(value, "overwrite") {
+        Some(policy) => StageOverwrite::parse(policy)?,
+        None => StageOverwrite::default(),
+    };
+
+    Ok(StageProvisioningParams {
+        autopilot: optional_string(value, "autopilot").map(PathBuf::from),
+        packages: packages.into_iter().map(PathBuf::from).collect(),
+        target_mount: PathBuf::from(require_string(value, "target_mount")?),
+        layout: ProvisioningLayout::parse(optional_string(value, "layout").unwrap_or("installer"))?,
+        overwrite,
+        report_base: optional_string(value, "report_base")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| default_report.to_path_buf()),
+        force: optional_bool(value, "force", false),
+        confirmation_token: optional_string(value, "confirmation_token").map(str::to_string),
+        dry_run: optional_bool(value, "dry_run", true),
+    })
+}
+
+fn build_boot_entry_params(value: &serde_json::Value, default_report: &Path) -> Result<BootEntryParams> {
+    Ok(BootEntryParams {
+        target_mount: PathBuf::from(require_string(value, "target_mount")?),
+        loader: optional_string(value, "loader").map(str::to_string),
+        description: optional_string(value, "description")
+            .unwrap_or("Phoenix")
+            .to_string(),
+        position: BootPosition::parse(optional_string(value, "position").unwrap_or("first"))?,
+        report_base: optional_string(value, "report_base")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| default_report.to_path_buf()),
+        force: optional_bool(value, "force", false),
+        confirmation_token: optional_string(value, "confirmation_token").map(str::to_string),
+        dry_run: optional_bool(value, "dry_run", true),
+    })
+}
+
+fn build_ipsw_restore_params(
+    value: &serde_json::Value,
+    default_report: &Path,
+) -> Result<IpswRestoreParams> {
+    Ok(IpswRestoreParams {
+        ipsw: optional_string(value, "ipsw").map(PathBuf::from),
+        ecid: optional_string(value, "ecid").map(str::to_string),
+        mode: RestoreMode::parse(require_string(value, "mode")?)?,
+        tool: optional_string(value, "tool").map(RestoreTool::parse).transpose()?,
+        report_base: optional_string(value, "report_base")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| default_report.to_path_buf()),
+        force: optional_bool(value, "force", false),
+        confirmation_token: optional_string(value, "confirmation_token").map(str::to_string),
+        dry_run: optional_bool(value, "dry_run", true),
+    })
+}
+
+fn build_macos_erase_install_params(
+    value: &serde_json::Value,
+    default_report: &Path,
+) -> Result<MacosEraseInstallParams> {
+    Ok(MacosEraseInstallParams {
+        source_app: PathBuf::from(require_string(value, "source_app")?),
+        report_base: optional_string(value, "report_base")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| default_report.to_path_buf()),
+        new_volume_name: optional_string(value, "new_volume_name").map(str::to_string),
+        packages: optional_string_list(value, "packages")?
+            .into_iter()
+            .map(PathBuf::from)
+            .collect(),
+        admin_user: optional_string(value, "admin_user").map(str::to_string),
+        admin_password: optional_string(value, "admin_password").map(str::to_string),
+        force: optional_bool(value, "force", false),
+        confirmation_token: optional_string(value, "confirmation_token").map(str::to_string),
+        allow_system_target: optional_bool(value, "allow_system_target", false),
+        dry_run: optional_bool(value, "dry_run", true),
     })
 }
 
@@ -2591,9 +6147,9 @@ fn build_legacy_patch_params(
     default_report: &Path,
 ) -> Result<phoenix_legacy_patcher::LegacyPatchParams> {
     let source_path = PathBuf::from(require_string(value, "source_path")?);
-    let report_base = PathBuf::from(optional_string(value, "report_base").unwrap_or_else(|| {
-        default_report.display().to_string()
-    }));
+    let report_base = optional_string(value, "report_base")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| default_report.to_path_buf());
     Ok(phoenix_legacy_patcher::LegacyPatchParams {
         source_path,
         report_base,
@@ -2611,9 +6167,9 @@ fn build_kext_stage_params(
 ) -> Result<MacosKextStageParams> {
     let source_path = PathBuf::from(require_string(value, "source_path")?);
     let target_mount = PathBuf::from(require_string(value, "target_mount")?);
-    let report_base = PathBuf::from(optional_string(value, "report_base").unwrap_or_else(|| {
-        default_report.display().to_string()
-    }));
+    let report_base = optional_string(value, "report_base")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| default_report.to_path_buf());
     let target_subdir = optional_string(value, "target_subdir").map(PathBuf::from);
 
     Ok(MacosKextStageParams {
@@ -2625,32 +6181,10 @@ fn build_kext_stage_params(
         confirmation_token: optional_string(value, "confirmation_token").map(str::to_string),
         dry_run: optional_bool(value, "dry_run", true),
         hash_manifest: optional_bool(value, "hash_manifest", false),
+        hash_destination: optional_bool(value, "hash_destination", false),
     })
 }
 
-fn require_string<'a>(value: &'a serde_json::Value, key: &str) -> Result<&'a str> {
-    value
-        .get(key)
-        .and_then(|v| v.as_str())
-        .ok_or_else(|| anyhow!("missing string field {}", key))
-}
-
-fn optional_string<'a>(value: &'a serde_json::Value, key: &str) -> Option<&'a str> {
-    value.get(key).and_then(|v| v.as_str())
-}
-
-fn require_u32(value: &serde_json::Value, key: &str) -> Result<u32> {
-    value
-        .get(key)
-        .and_then(|v| v.as_u64())
-        .map(|v| v as u32)
-        .ok_or_else(|| anyhow!("missing number field {}", key))
-}
-
-fn optional_bool(value: &serde_json::Value, key: &str, default: bool) -> bool {
-    value.get(key).and_then(|v| v.as_bool()).unwrap_or(default)
-}
-
 fn parse_filesystem_value(value: &str) -> Result<FileSystem> {
     match value.trim().to_ascii_lowercase().as_str() {
         "fat32" => Ok(FileSystem::Fat32),
@@ -2659,3 +6193,1006 @@ fn parse_filesystem_value(value: &str) -> Result<FileSystem> {
         other => Err(anyhow!("unsupported filesystem {}", other)),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::de::DeserializeOwned;
+    use serde_json::json;
+
+    /// The step builder and serde read `params` the same way, and what
+    /// serde writes reads back unchanged.
+    fn same_schema<T: Serialize + DeserializeOwned>(
+        build: fn(&serde_json::Value, &Path) -> Result<T>,
+        mut params: serde_json::Value,
+    ) {
+        params["report_base"] = json!("reports");
+        let built = serde_json::to_value(build(&params, Path::new("unused")).unwrap()).unwrap();
+        let read: T = serde_json::from_value(params).unwrap();
+        let read = serde_json::to_value(read).unwrap();
+        assert_eq!(built, read);
+        let again: T = serde_json::from_value(read.clone()).unwrap();
+        assert_eq!(serde_json::to_value(again).unwrap(), read);
+    }
+
+    /// A linux host graph with `disks`, given as device graph JSON.
+    fn test_graph(disks: serde_json::Value) -> DeviceGraph {
+        let host = phoenix_core::HostInfo {
+            os: "linux".to_string(),
+            os_version: String::new(),
+            machine: String::new(),
+            os_edition: None,
+            os_display_version: None,
+        };
+        DeviceGraph::new(host, serde_json::from_value(disks).unwrap(), phoenix_core::now_utc_rfc3339())
+    }
+
+    #[test]
+    fn params_match_step_builders() {
+        same_schema(
+            build_usb_params,
+            json!({
+                "target_disk_id": "1", "source_path": "win.iso", "repartition": true,
+                "filesystem": "ntfs", "cluster_bytes": "64K", "flush_every_bytes": 1048576,
+                "partitions": ["EFI:esp:100M:required", {"name": "Data", "size": "rest"}],
+                "include": ["sources/**"], "exclude": ["\\support\\"], "dry_run": false,
+                "continue_on_error": true
+            }),
+        );
+        same_schema(
+            build_apply_params,
+            json!({"source_path": "install.wim", "image_index": 3, "target_dir": "W:\\"}),
+        );
+        same_schema(
+            build_validate_source_params,
+            json!({"source_path": "ubuntu.iso", "os": "linux", "filesystem": "exfat"}),
+        );
+        same_schema(
+            build_slim_media_params,
+            json!({"source_path": "media", "languages": ["en-us"], "editions": ["Pro"]}),
+        );
+        same_schema(
+            build_merge_languages_params,
+            json!({"source_path": "media", "language_sources": ["de.iso"], "languages": ["de-de"]}),
+        );
+        same_schema(
+            build_hash_params,
+            json!({"disk_id": "sdb", "chunk_size": "auto", "max_chunks": 8, "save_baseline": "gold"}),
+        );
+        same_schema(
+            build_bad_block_scan_params,
+            json!({"disk_id": "sdb", "mode": "write", "sector_size": "4K", "max_bad_bytes": "1M"}),
+        );
+        same_schema(
+            build_combo_stick_params,
+            json!({
+                "disk_id": "sdb", "windows_source": "win", "rescue_source": "rescue",
+                "bootloader_source": "grub", "windows_size": "8G", "menu_timeout": 5,
+                "payload_pack": "tools/pack.json", "payloads": ["memtest86plus"], "tools_size": "1G",
+                "data_encryption": "luks2", "data_key_secret": "stick-key"
+            }),
+        );
+        same_schema(
+            build_ab_stick_params,
+            json!({
+                "disk_id": "sdb", "image_source": "appliance", "bootloader_source": "grub",
+                "slot_size": "4G", "version": "2.1.0", "menu_timeout": 3
+            }),
+        );
+        same_schema(
+            build_ab_update_params,
+            json!({"disk_id": "sdb", "image_source": "appliance", "version": "2.2.0", "rollback": false}),
+        );
+        same_schema(
+            build_clone_device_params,
+            json!({
+                "source_disk_id": "sdb", "target_disk_id": "sdc", "archive": "golden.img.zst",
+                "archive_in_report": true, "chunk_size": 4194304, "flush_every_bytes": "256M"
+            }),
+        );
+        same_schema(
+            build_restore_report_params,
+            json!({
+                "report": "reports/run-1", "target_disk_id": "sdc", "allow_unsigned": true,
+                "flush_every_bytes": "256M"
+            }),
+        );
+        same_schema(
+            build_resize_partition_params,
+            json!({"disk_id": "sdb", "partition": 2, "mode": "shrink", "margin": "128M"}),
+        );
+        same_schema(
+            build_unix_usb_params,
+            json!({
+                "source_path": "debian.iso", "target_mount": "/mnt/usb", "format_device": "/dev/sdb",
+                "format_size_bytes": 8589934592u64, "format_cluster_bytes": "32K", "udisks": true
+            }),
+        );
+        same_schema(
+            build_unix_write_params,
+            json!({"source_image": "disk.img", "target_device": "/dev/sdb", "chunk_size": 4194304, "verify": true}),
+        );
+        same_schema(
+            build_unix_boot_params,
+            json!({"source_path": "boot", "target_mount": "/mnt/esp", "hash_manifest": true}),
+        );
+        same_schema(
+            build_macos_installer_params,
+            json!({"source_path": "Install macOS.app", "target_device": "/dev/disk4", "target_model": "MacBookPro18,3"}),
+        );
+        same_schema(
+            build_stage_bootloader_params,
+            json!({"source_path": "shim", "target_mount": "/mnt/esp", "mok_hashes": true, "grub_dir": "boot/grub2"}),
+        );
+        same_schema(
+            build_stage_files_params,
+            json!({"files": [{"source": "tools/", "destination": "tools"}], "target_mount": "/mnt/usb", "overwrite": "replace"}),
+        );
+        same_schema(
+            build_stage_firstboot_params,
+            json!({
+                "scripts": [{"kind": "setup_complete", "template": "setup.cmd"}],
+                "vars": {"hostname": "kiosk-01"}, "target_mount": "/mnt/usb"
+            }),
+        );
+        same_schema(
+            build_stage_provisioning_params,
+            json!({"autopilot": "profile.json", "packages": ["a.ppkg"], "target_mount": "W:\\", "layout": "applied"}),
+        );
+        same_schema(
+            build_boot_entry_params,
+            json!({"target_mount": "/boot/efi", "loader": "\\EFI\\ubuntu\\shimx64.efi", "position": "next"}),
+        );
+        same_schema(
+            build_ipsw_restore_params,
+            json!({"mode": "revive", "tool": "cfgutil", "ecid": "0x1A2B"}),
+        );
+        same_schema(
+            build_macos_erase_install_params,
+            json!({"source_app": "Install macOS.app", "packages": ["agent.pkg"], "admin_user": "admin"}),
+        );
+        same_schema(
+            build_legacy_patch_params,
+            json!({"source_path": "Install macOS.app", "model": "MacBookPro11,1"}),
+        );
+        same_schema(
+            build_kext_stage_params,
+            json!({"source_path": "kexts", "target_mount": "/Volumes/EFI", "target_subdir": "OC/Kexts"}),
+        );
+    }
+
+    #[test]
+    fn staging_backends_own_filesystem_rules() {
+        let paths = [Path::new("sources/a:b.txt"), Path::new("Boot/BCD"), Path::new("boot/bcd")];
+        let ntfs = staging_backend_named("ntfs3").unwrap();
+        assert_eq!(ntfs.path_warnings(&mut paths.iter().copied()).len(), 2);
+        let ext4 = staging_backend_named("ext4").unwrap();
+        assert!(ext4.path_warnings(&mut paths.iter().copied()).is_empty());
+        let fat32 = staging_backend(FileSystem::Fat32);
+        assert!(fat32.file_size_problem(5 << 30).is_some());
+        assert!(staging_backend(FileSystem::ExFat).file_size_problem(5 << 30).is_none());
+        assert!(staging_backend_named("apfs").is_none());
+    }
+
+    #[test]
+    fn wizard_checks_each_answer() {
+        let graph = test_graph(json!([
+            {"id": "sda", "friendly_name": "Internal", "size_bytes": 512_000_000_000u64, "removable": false, "is_system_disk": true},
+            {"id": "sdb", "friendly_name": "Tiny", "size_bytes": 100_000_000u64, "removable": true, "is_system_disk": false},
+            {"id": "sdc", "friendly_name": "Stick", "size_bytes": 8_000_000_000u64, "removable": true, "is_system_disk": false}
+        ]));
+        let mut wizard = WizardSession::with_graph("linux_write_image", graph).unwrap();
+
+        let question = wizard.next_question().unwrap();
+        assert_eq!(question.key, "target_device");
+        let refused: Vec<bool> = question.choices.iter().map(|choice| choice.refused.is_some()).collect();
+        assert_eq!(refused, [true, false, false]);
+        assert!(wizard.answer("target_device", json!("sda")).is_err());
+        assert!(wizard.answer("source_image", json!("disk.img")).is_err());
+
+        let question = wizard.answer("target_device", json!("sdb")).unwrap().unwrap();
+        assert_eq!(question.key, "acknowledge_target_size");
+        assert!(wizard.answer("acknowledge_target_size", json!(false)).is_err());
+        wizard.answer("acknowledge_target_size", json!(true)).unwrap();
+        let question = wizard
+            .answer("source_image", json!("http://example.com/disk.img"))
+            .unwrap()
+            .unwrap();
+        assert!(question.required);
+        assert!(wizard.answer("source_sha256", json!(null)).is_err());
+        assert!(wizard.answer("source_sha256", json!("AB".repeat(32))).unwrap().is_none());
+        let step = wizard.finish().unwrap();
+        assert_eq!(step.params["target_device"], "/dev/sdb");
+        assert_eq!(step.params["source_sha256"], "ab".repeat(32));
+
+        // A new target drops the answers checked against the old one.
+        let question = wizard.answer("target_device", json!("sdc")).unwrap().unwrap();
+        assert_eq!(question.key, "source_image");
+        assert!(wizard.finish().is_err());
+    }
+
+    #[test]
+    fn agent_jobs_stay_on_the_claimed_disk() {
+        let disks: Vec<phoenix_core::Disk> = serde_json::from_value(json!([
+            {"id": "sdb", "friendly_name": "Stick", "serial": "AA01", "size_bytes": 8_000_000_000u64, "removable": true, "is_system_disk": false,
+             "partitions": [{"id": "sdb1", "label": null, "fs": "vfat", "size_bytes": 8_000_000u64, "mount_points": ["/media/stick"]}]},
+            {"id": "sdc", "friendly_name": "Other", "serial": "BB02", "size_bytes": 8_000_000_000u64, "removable": true, "is_system_disk": false}
+        ]))
+        .unwrap();
+        let job = |target: serde_json::Value, action: &str, params: serde_json::Value| -> AgentJob {
+            let mut job = json!({
+                "job_id": "job-1",
+                "definition": {
+                    "schema_version": phoenix_core::WORKFLOW_SCHEMA_VERSION, "name": "remote",
+                    "steps": [{"id": "write", "action": action, "params": params}]
+                }
+            });
+            job.as_object_mut().unwrap().extend(target.as_object().unwrap().clone());
+            serde_json::from_value(job).unwrap()
+        };
+        let plain = json!({"target_device": "{target_device}", "source_image": "disk.img"});
+
+        let write = "linux_write_image";
+        let by_serial =
+            job(json!({"target_serial": "BB02", "target_disk": "sdb"}), write, plain.clone());
+        assert_eq!(agent::job_disk(&by_serial, &disks).unwrap().id, "sdc");
+        let by_id = job(json!({"target_disk": "SDB"}), write, plain.clone());
+        assert_eq!(agent::job_disk(&by_id, &disks).unwrap().id, "sdb");
+        let unknown = job(json!({"target_serial": "ZZ"}), write, plain.clone());
+        assert!(agent::job_disk(&unknown, &disks).is_err());
+        assert!(agent::job_disk(&job(json!({}), write, plain.clone()), &disks).is_err());
+
+        let allow = RemoteAllowList::default();
+        let sdb = &disks[0];
+        let base = Path::new("/srv/agent/reports");
+        let bound = agent::remote_definition(&by_id.definition, sdb, &allow, base).unwrap();
+        assert_eq!(bound.steps[0].params["target_device"], "/dev/sdb");
+        let run = |action: &str, params: serde_json::Value, allow: &RemoteAllowList| {
+            agent::remote_definition(&job(json!({}), action, params).definition, sdb, allow, base)
+        };
+        let remote = |params: serde_json::Value, allow: &RemoteAllowList| run(write, params, allow);
+        assert!(remote(json!({"target_device": "/dev/sdb1"}), &allow).is_ok());
+        assert!(remote(json!({"target_device": "/dev/sdc"}), &allow).is_err());
+        let usb = "windows_installer_usb";
+        assert!(run(usb, json!({"target_disk_id": "sdc"}), &allow).is_err());
+        let both = json!({"source_disk_id": "sdc", "target_disk_id": "sdb"});
+        assert!(run(usb, both, &allow).is_err());
+        let stage = "linux_installer_usb";
+        assert!(run(stage, json!({"target_mount": "/media/stick/data"}), &allow).is_ok());
+        assert!(run(stage, json!({"target_mount": "/media/stick/../../etc"}), &allow).is_err());
+        assert!(run(stage, json!({"target_mount": "/home"}), &allow).is_err());
+
+        // Host-scoped actions, unnamed targets and escaping outputs are refused.
+        let erase = json!({"target_device": "/dev/sdb", "confirmation_token": "ERASE"});
+        assert!(run("macos_erase_install", erase, &allow).is_err());
+        assert!(remote(json!({"source_image": "disk.img"}), &allow).is_err());
+        let system = json!({"target_device": "/dev/sdb", "allow_system_target": true});
+        assert!(remote(system, &allow).is_err());
+        let inside = json!({"target_device": "/dev/sdb", "report_base": "/srv/agent/reports/j1"});
+        assert!(remote(inside, &allow).is_ok());
+        for report_base in ["/etc/cron.d", "/srv/agent/reports/../../../etc"] {
+            let outside = json!({"target_device": "/dev/sdb", "report_base": report_base});
+            assert!(remote(outside, &allow).is_err());
+        }
+        let drivers = |driver_target: &str| {
+            json!({"target_mount": "/media/stick", "driver_target": driver_target})
+        };
+        assert!(run("stage_bootloader", drivers("drivers"), &allow).is_ok());
+        assert!(run("stage_bootloader", drivers("/etc/drivers"), &allow).is_err());
+        assert!(run("stage_bootloader", drivers("../drivers"), &allow).is_err());
+
+        let hooked = json!({
+            "target_device": "{target_device}",
+            "hooks": {"post": [{"command": ["eject"], "sandbox": {"network": false}}]}
+        });
+        assert!(remote(hooked.clone(), &allow).is_err());
+        let secret = json!({"target_device": "{target_device}", "password": "secret://wifi-psk"});
+        assert!(remote(secret.clone(), &allow).is_err());
+        let named = json!({"disk_id": "sdb", "data_key_secret": "stick-key"});
+        assert!(run("combo_stick", named.clone(), &allow).is_err());
+        let allow = RemoteAllowList {
+            hooks: vec!["eject".to_string()],
+            secrets: vec!["wifi-psk".to_string(), "stick-key".to_string()],
+        };
+        assert!(remote(hooked, &allow).is_ok());
+        assert!(remote(secret, &allow).is_ok());
+        assert!(run("combo_stick", named, &allow).is_ok());
+    }
+
+    #[test]
+    fn agent_token_needs_https() {
+        let params = |server: &str, token_secret: Option<&str>| AgentParams {
+            server: server.to_string(),
+            agent_id: "bench-1".to_string(),
+            token_secret: token_secret.map(str::to_string),
+            client_cert: None,
+            client_key: None,
+            server_ca: None,
+            report_base: PathBuf::from("reports"),
+            ports: Vec::new(),
+            poll_interval: std::time::Duration::from_secs(5),
+            max_jobs: None,
+            allow: RemoteAllowList::default(),
+        };
+        assert_eq!(
+            agent::check_server(&params("https://fleet.example/", Some("fleet-token"))).unwrap(),
+            "https://fleet.example"
+        );
+        assert!(agent::check_server(&params("http://fleet.example", None)).is_ok());
+        let err = agent::check_server(&params("http://fleet.example", Some("fleet-token"))).unwrap_err();
+        assert!(err.to_string().contains("cleartext"), "{}", err);
+        assert!(agent::check_server(&params("fleet.example", None)).is_err());
+    }
+
+    #[test]
+    fn dry_run_session_plans_on_the_mock_host() {
+        let graph = phoenix_core::mock::device_graph().unwrap();
+        let disk = graph.disks.iter().find(|disk| disk.removable).unwrap();
+        let other = graph.disks.iter().find(|other| other.id != disk.id).unwrap();
+        let mount = PathBuf::from(&disk.partitions[0].mount_points[0]);
+        let marker = mount.join(format!("dry-run-{}.txt", std::process::id()));
+        std::fs::write(&marker, b"keep").unwrap();
+        let plan = phoenix_partition::plan_partitions(
+            disk.size_bytes,
+            512,
+            &[phoenix_partition::PartitionSpec::basic_data("PHOENIX")],
+        )
+        .unwrap();
+        // No force or token: a dry run never reaches the safety gate.
+        let ctx = SafetyContext {
+            force_mode: false,
+            confirmation_token: None,
+            allow_system_disk: false,
+            armed_until: None,
+        };
+        let mut logs = StepLog::new("dry-run-test");
+        let mut session =
+            DestructiveSession::begin("dry-run-test", Some(disk), &ctx, false, true, &mut logs)
+                .unwrap();
+        let mut repartition = RepartitionDisk {
+            disk,
+            plan: &plan,
+            filesystem: FileSystem::Fat32,
+            label: None,
+            cluster_bytes: None,
+        };
+        assert!(session.perform(&mut repartition, &mut logs).unwrap().is_none());
+        let mut format = FormatVolume {
+            disk_id: &disk.id,
+            mount: &mount,
+            filesystem: FileSystem::Fat32,
+            label: None,
+            cluster_bytes: None,
+        };
+        assert!(session.perform(&mut format, &mut logs).unwrap().is_none());
+        assert_eq!(session.operations().len(), 2);
+        assert!(logs.text().contains(&format!("planned=format {} as", mount.display())));
+        assert!(marker.exists());
+
+        let mut elsewhere = FormatVolume {
+            disk_id: &other.id,
+            ..format
+        };
+        let err = session.perform(&mut elsewhere, &mut logs).err().unwrap();
+        assert!(err.to_string().contains("but this run is for"), "{}", err);
+        assert_eq!(session.operations().len(), 2);
+        let _ = std::fs::remove_file(&marker);
+    }
+
+    #[test]
+    fn failed_runs_keep_their_error() {
+        let dir = std::env::temp_dir().join(format!("phoenix-ledger-{}", std::process::id()));
+        let graph = phoenix_core::mock::device_graph().unwrap();
+        let disk = graph.disks.iter().find(|disk| disk.removable).unwrap();
+        let secret = "ledger-token-5a0d9c";
+        phoenix_report::register_secret(secret);
+
+        let ledger = RunLedger::open(&dir);
+        let tracker = ledger.begin("ledger-test", disk).unwrap();
+        let run_id = tracker.run_id().to_string();
+        tracker.fail(&anyhow!("write failed with {}", secret));
+        let record = ledger
+            .list()
+            .unwrap()
+            .into_iter()
+            .find(|record| record.run_id == run_id)
+            .unwrap();
+        assert_eq!(record.status, RunStatus::Failed);
+        assert_eq!(record.error.as_deref(), Some("write failed with <secret>"));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn hooks_are_validated_and_captured() {
+        let step = |hooks: serde_json::Value| WorkflowStep {
+            id: "hooked".to_string(),
+            action: "noop".to_string(),
+            params: json!({ "hooks": hooks }),
+            timeout_secs: None,
+        };
+        let open = json!({ "network": true });
+        for bad in [
+            json!({ "post": [{ "sandbox": open }] }),
+            json!({ "post": [{ "command": ["true"], "plugin": "x", "sandbox": open }] }),
+            json!({ "post": [{ "plugin": "../x", "sandbox": open }] }),
+            json!({ "post": [{ "command": ["true"], "args": ["-v"], "sandbox": open }] }),
+            json!({ "post": [{ "command": ["true"], "sandbox": { "network": true, "timeout_secs": 0 } }] }),
+            json!({ "post": [{ "command": ["true"] }] }),
+        ] {
+            assert!(hooks::step_hooks(&step(bad.clone())).is_err(), "{}", bad);
+        }
+        assert!(hooks::step_hooks(&step(json!({ "pre": [{ "plugin": "asset-db", "sandbox": open }] })))
+            .unwrap()
+            .is_some());
+        if !cfg!(unix) {
+            return;
+        }
+
+        let hooked = step(json!({
+            "pre": [{ "command": ["sh", "-c", "echo out; echo err >&2; exit 3"], "sandbox": open,
+                      "required": false }],
+            "post": [{ "command": ["sh", "-c", "sleep 30 & echo started; sleep 30"],
+                       "sandbox": { "network": true, "timeout_secs": 1 } }]
+        }));
+        let spec = hooks::step_hooks(&hooked).unwrap().unwrap();
+        let mut runs = Vec::new();
+        hooks::run_hooks(&hooked, HookPhase::Pre, &spec.pre, None, &mut runs).unwrap();
+        assert_eq!(runs[0].exit_code, Some(3));
+        assert_eq!(runs[0].stdout, b"out\n");
+        assert_eq!(runs[0].stderr, b"err\n");
+
+        // The background sleep holds stdout open; killing the group ends it.
+        let start = std::time::Instant::now();
+        let err = hooks::run_hooks(&hooked, HookPhase::Post, &spec.post, None, &mut runs).unwrap_err();
+        assert!(err.to_string().contains("timed out"), "{}", err);
+        assert!(start.elapsed() < std::time::Duration::from_secs(10));
+        assert!(runs[1].timed_out);
+        assert_eq!(runs[1].stdout, b"started\n");
+    }
+
+    #[test]
+    fn idempotency_key_needs_a_disk_action() {
+        let step = |action: &str, params: serde_json::Value| WorkflowStep {
+            id: "write".to_string(),
+            action: action.to_string(),
+            params,
+            timeout_secs: None,
+        };
+        let write =
+            json!({"source_image": "disk.img", "target_device": "/dev/sdb", "idempotency_key": "k"});
+        assert!(validate_step(&step("linux_write_image", write)).is_ok());
+        let scan = json!({"disk_id": "sdb", "mode": "write", "idempotency_key": "k"});
+        assert!(validate_step(&step("bad_block_scan", scan)).is_ok());
+        let hash = json!({"disk_id": "sdb", "idempotency_key": "k"});
+        let err = validate_step(&step("disk_hash_report", hash)).unwrap_err();
+        assert!(err.to_string().contains("does not support idempotency_key"), "{}", err);
+    }
+
+    #[test]
+    fn issued_agent_certs_identify_the_agent() {
+        let dir = std::env::temp_dir().join(format!("phoenix-fleet-ca-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        init_fleet_ca(&dir, "bench", 30).unwrap();
+        let agent = issue_fleet_cert(&dir, CertKind::Agent, "agent-7", 30).unwrap();
+        let server = issue_fleet_cert(&dir, CertKind::Server, "fleet.example", 30).unwrap();
+        assert_eq!(agent.serial.len(), 32);
+        assert_ne!(agent.serial, server.serial);
+
+        let identity = read_agent_identity(&agent.cert_path).unwrap();
+        assert_eq!(identity.agent_id, "agent-7");
+        assert_eq!(identity.issuer, "bench fleet CA");
+        assert_eq!(identity.serial, agent.serial);
+        assert_eq!(identity.cert_sha256, agent.cert_sha256);
+        phoenix_fetch::ClientTls::new(Some(&dir.join(FLEET_CA_CERT)), &agent.cert_path, &agent.key_path)
+            .unwrap();
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn cancel_token_is_restored_after_a_panic() {
+        let token = CancelToken::new();
+        token.cancel();
+        let _ = std::panic::catch_unwind(|| with_cancel_token(&token, || panic!("step failed")));
+        assert!(!is_cancelled());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn time_limit_kills_a_wedged_tool() {
+        let start = std::time::Instant::now();
+        let err = watchdog::with_time_limit("step sleep".to_string(), Some(1), || {
+            cancel::command_output(std::process::Command::new("sleep").arg("30"))
+        })
+        .unwrap_err();
+        assert_eq!(error_code(&err), Some(WorkflowTimeout::CODE));
+        assert!(start.elapsed() < std::time::Duration::from_secs(10));
+    }
+
+    #[test]
+    fn booted_esp_disk_is_refused() {
+        let esp = "0FC63DAF-8483-4772-8E79-3D69D8477DE4";
+        let mut graph = test_graph(json!([
+            {"id": "sdb", "friendly_name": "External SSD", "size_bytes": 500_000_000_000u64, "removable": true, "is_system_disk": false,
+             "partitions": [{"id": "sdb1", "label": null, "fs": "vfat", "size_bytes": 500_000_000u64, "mount_points": ["/boot/efi"], "part_uuid": esp.to_ascii_lowercase()}]},
+            {"id": "sdc", "friendly_name": "Stick", "size_bytes": 8_000_000_000u64, "removable": true, "is_system_disk": false}
+        ]));
+        mark_booted_esp(&mut graph, esp);
+        assert!(graph.disks[0].is_system_disk);
+        assert!(!graph.disks[1].is_system_disk);
+        let explanation = target::explain_target(&graph, "sdb");
+        assert!(!explanation.eligible);
+        assert!(explanation.reasons[0].contains("booted from"));
+        assert!(target::explain_target(&graph, "sdc").eligible);
+
+        // The system-disk override does not unlock the booted ESP's disk.
+        let ctx = SafetyContext {
+            force_mode: true,
+            confirmation_token: Some(format!("{}ESP", phoenix_safety::SYSTEM_TOKEN_PREFIX)),
+            allow_system_disk: true,
+            armed_until: None,
+        };
+        let mut logs = StepLog::new("booted-esp-test");
+        let sdb = Some(&graph.disks[0]);
+        let err = DestructiveSession::begin("booted-esp-test", sdb, &ctx, true, false, &mut logs)
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("booted from"), "{}", err);
+    }
+
+    #[test]
+    fn registry_counts_flashes() {
+        let path = std::env::temp_dir().join(format!("phoenix-devices-{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let registry = DeviceRegistry::open(&path);
+        let mut graph = test_graph(json!([
+            {"id": "sdb", "friendly_name": "Stick", "serial": "AA01", "size_bytes": 8_000_000_000u64, "removable": true, "is_system_disk": false},
+            {"id": "sdc", "friendly_name": "Blank", "size_bytes": 4_000_000_000u64, "removable": true, "is_system_disk": false}
+        ]));
+        registry.observe(&mut graph).unwrap();
+        assert_eq!(graph.disks[0].history.as_ref().unwrap().flash_count, 0);
+
+        registry
+            .record_flash(Some("AA01"), "Stick", 8_000_000_000, "linux_write_image", Some(40_000_000))
+            .unwrap();
+        let record = registry
+            .record_flash(Some("AA01"), "Stick", 8_000_000_000, "linux_write_image", Some(20_000_000))
+            .unwrap();
+        assert_eq!(record.flash_count, 2);
+        assert_eq!(record.best_write_bytes_per_sec, Some(40_000_000));
+        assert_eq!(record.last_write_bytes_per_sec, Some(20_000_000));
+        registry.observe(&mut graph).unwrap();
+        let history = graph.disks[0].history.as_ref().unwrap();
+        assert_eq!(history.flash_count, 2);
+        assert_eq!(history.last_workflow.as_deref(), Some("linux_write_image"));
+        assert_eq!(graph.disks[1].history.as_ref().unwrap().flash_count, 0);
+        assert!(registry.get("Blank|4000000000").unwrap().is_some());
+        assert_eq!(registry.list().unwrap().len(), 2);
+
+        let claim = phoenix_core::DeviceClaim {
+            label: "Customer loaner".to_string(),
+            notes: Some("never wipe".to_string()),
+            action: phoenix_core::ClaimAction::Deny,
+            claimed_utc: phoenix_core::now_utc_rfc3339(),
+        };
+        registry.set_claim("AA01", Some(claim)).unwrap();
+        assert!(registry.set_claim("unseen", None).is_err());
+        registry.observe(&mut graph).unwrap();
+        let explanation = explain_target(&graph, "sdb");
+        assert!(!explanation.eligible);
+        assert!(explanation.reasons[0].contains("Customer loaner"), "{:?}", explanation.reasons);
+        let record = registry.set_claim("AA01", None).unwrap();
+        assert!(record.claim.is_none() && record.flash_count == 2);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn boot_lint_follows_search_labels() {
+        let dir = std::env::temp_dir().join(format!("phoenix-boot-lint-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let loader = dir.join("bootx64.efi");
+        let text = dir.join("readme.txt");
+        std::fs::write(&loader, b"MZ\x90\x00").unwrap();
+        std::fs::write(&text, b"not a program").unwrap();
+        let mut tools = BootVolume::new("TOOLS");
+        tools.insert(Path::new("memtest/memtest64.efi"), loader.clone());
+        tools.insert(Path::new("memtest/README.txt"), text);
+        let volumes = [BootVolume::new("PHX-BOOT"), tools];
+        let entry = |title: &str, label: &str, path: &str| phoenix_bootcfg::GrubMenuEntry {
+            title: title.to_string(),
+            class: Vec::new(),
+            commands: vec![
+                format!("search --no-floppy --set=root --label {}", label),
+                format!("chainloader {}", path),
+            ],
+        };
+        let menu = phoenix_bootcfg::GrubMenu {
+            title: None,
+            timeout: None,
+            default: None,
+            theme: None,
+            entries: vec![
+                entry("memtest", "TOOLS", "/MEMTEST/memtest64.EFI"),
+                entry("readme", "TOOLS", "/memtest/readme.txt"),
+                entry("gone", "TOOLS", "/gdisk/gdisk.efi"),
+                entry("nowhere", "RESCUE", "/efi/boot/bootx64.efi"),
+            ],
+        };
+        let findings = lint_boot_menu(&menu, "PHX-BOOT", &volumes);
+        let entries: Vec<&str> = findings.iter().map(|finding| finding.entry.as_str()).collect();
+        assert_eq!(entries, ["readme", "gone", "nowhere", "nowhere"]);
+        assert!(findings[0].problem.contains("not an EFI executable"));
+        assert!(findings[1].problem.contains("not on TOOLS"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn pack_lint_flags_scaffold_breakage() {
+        let dir = std::env::temp_dir().join(format!("phoenix-pack-kit-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        scaffold_pack(&dir, "demo").unwrap();
+        let manifest = dir.join("pack.json");
+        let lint = lint_pack(&manifest).unwrap();
+        assert_eq!((lint.workflows, lint.errors()), (1, 0), "{:?}", lint.problems);
+
+        let workflow = dir.join("workflows/demo.json");
+        let text = std::fs::read_to_string(&workflow)
+            .unwrap()
+            .replace("{target_mount}", "{target_mnt}")
+            .replace("assets/README.txt", "assets/gone.txt");
+        std::fs::write(&workflow, text).unwrap();
+        let messages: Vec<String> = lint_pack(&manifest)
+            .unwrap()
+            .problems
+            .into_iter()
+            .map(|problem| problem.message)
+            .collect();
+        assert_eq!(
+            messages,
+            ["assets/gone.txt does not exist in the pack", "unknown placeholder {target_mnt}"]
+        );
+        assert!(scaffold_pack(&dir, "demo").is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn copy_errors_skip_failed_files() {
+        let dir = std::env::temp_dir().join(format!("phoenix-copy-errors-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("good.txt"), b"ok").unwrap();
+        let files = vec![
+            FileEntry {
+                absolute_path: dir.join("good.txt"),
+                relative_path: PathBuf::from("good.txt"),
+                size: 2,
+            },
+            FileEntry {
+                absolute_path: dir.join("scratched.cab"),
+                relative_path: PathBuf::from("sources/scratched.cab"),
+                size: 4096,
+            },
+        ];
+        let dest = dir.join("partial.cab");
+        std::fs::write(&dest, b"half").unwrap();
+
+        let mut strict = CopyErrors::new(false);
+        assert!(strict.record(&files[1], &dest, anyhow!("read error")).is_err());
+
+        let mut errors = CopyErrors::new(true);
+        assert_eq!(errors.status(false), "completed");
+        let failure = errors.record(&files[1], &dest, anyhow!("read error")).unwrap();
+        assert_eq!(failure.path, "sources/scratched.cab");
+        assert_eq!(failure.side, FailureSide::Source);
+        assert!(!dest.exists());
+        errors.record(&files[0], &dest, anyhow!("disk full")).unwrap();
+        assert_eq!(errors.failures()[1].side, FailureSide::Destination);
+        assert_eq!(errors.status(false), "degraded");
+        assert_eq!(errors.status(true), "dry_run");
+        assert!(errors.copied(&files).is_empty());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn transform_pack_edits_staged_files() {
+        let dir = std::env::temp_dir().join(format!("phoenix-transform-{}", std::process::id()));
+        let pack = dir.join("pack");
+        std::fs::create_dir_all(&pack).unwrap();
+        std::fs::write(
+            pack.join("pack.json"),
+            json!({
+                "schema_version": "1.0.0",
+                "name": "fleet",
+                "version": "2.1",
+                "workflows": [],
+                "transforms": [
+                    {"name": "timeout", "path": "boot/grub/grub.cfg", "kind": "regex",
+                     "pattern": "timeout=\\d+", "replacement": "timeout=3"},
+                    {"path": "**/*.cfg", "kind": "patch", "file": "args.diff"},
+                    {"path": "boot/grub/grub.cfg", "kind": "template", "file": "banner.tpl",
+                     "vars": {"org": "Acme"}}
+                ]
+            })
+            .to_string(),
+        )
+        .unwrap();
+        std::fs::write(
+            pack.join("args.diff"),
+            "--- a/grub.cfg\n+++ b/grub.cfg\n@@ -2,2 +2,2 @@\n menuentry Install {\n-  linux /vmlinuz quiet\n+  linux /vmlinuz quiet nomodeset\n",
+        )
+        .unwrap();
+        std::fs::write(pack.join("banner.tpl"), "# {{org}} {{ pack_name }} {{pack_version}}\n{{original}}").unwrap();
+        let source = dir.join("source");
+        std::fs::create_dir_all(source.join("boot/grub")).unwrap();
+        let grub = "set timeout=30\nmenuentry Install {\n  linux /vmlinuz quiet\n}\n";
+        std::fs::write(source.join("boot/grub/grub.cfg"), grub).unwrap();
+        std::fs::write(source.join("setup.exe"), b"MZ").unwrap();
+        let mut files = vec![
+            FileEntry {
+                absolute_path: source.join("boot/grub/grub.cfg"),
+                relative_path: PathBuf::from("boot/grub/grub.cfg"),
+                size: grub.len() as u64,
+            },
+            FileEntry {
+                absolute_path: source.join("setup.exe"),
+                relative_path: PathBuf::from("setup.exe"),
+                size: 2,
+            },
+        ];
+
+        let manifest = pack.join("pack.json");
+        assert!(load_transforms(Some(&manifest), true).is_err());
+        let mut transforms = load_transforms(Some(&manifest), false).unwrap().unwrap();
+        transforms.prepare(&mut files).unwrap();
+        let (record, content) = transforms.get(0).unwrap();
+        let expected = "# Acme fleet 2.1\nset timeout=3\nmenuentry Install {\n  linux /vmlinuz quiet nomodeset\n}\n";
+        assert_eq!(std::str::from_utf8(content).unwrap(), expected);
+        assert_eq!(record.transforms, ["timeout", "patch#1", "template#2"]);
+        assert_eq!(record.original_sha256, to_hex(&Sha256::digest(grub.as_bytes())));
+        assert_eq!(files[0].size, expected.len() as u64);
+        assert!(transforms.get(1).is_none());
+
+        let dest = dir.join("grub.cfg");
+        let copied = write_file(&files[0].absolute_path, &dest, content, CopyHashing::Destination).unwrap();
+        assert_eq!(copied.sha256.as_deref(), Some(record.sha256.as_str()));
+        assert_eq!(copied.destination_sha256, copied.sha256);
+        assert!(copied.mtime_preserved);
+
+        std::fs::write(source.join("boot/grub/grub.cfg"), "set timeout=30\n").unwrap();
+        files[0].size = 15;
+        let err = transforms.prepare(&mut files[..1]).unwrap_err();
+        assert!(format!("{:#}", err).contains("does not apply"), "{:#}", err);
+
+        // Inside a hunk, `--- `/`+++ ` lines are a removed `-- ` and an
+        // added `++ ` line, not headers.
+        std::fs::write(
+            pack.join("args.diff"),
+            "--- a/grub.cfg\n+++ b/grub.cfg\n@@ -1,2 +1,2 @@\n--- old\n+++ new\n menu\n",
+        )
+        .unwrap();
+        std::fs::write(source.join("boot/grub/grub.cfg"), "-- old\nmenu\n").unwrap();
+        files[0].size = 12;
+        transforms.prepare(&mut files[..1]).unwrap();
+        let (_, content) = transforms.get(0).unwrap();
+        assert_eq!(
+            std::str::from_utf8(content).unwrap(),
+            "# Acme fleet 2.1\n++ new\nmenu\n"
+        );
+
+        std::fs::write(source.join("boot/grub/grub.cfg"), b"set\0timeout=30\n").unwrap();
+        let err = transforms.prepare(&mut files[..1]).unwrap_err();
+        assert!(err.to_string().contains("is binary"), "{}", err);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn clone_compares_and_archives_the_source() {
+        use std::io::Write;
+
+        let dir = std::env::temp_dir().join(format!("phoenix-clone-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let data: Vec<u8> = (0..10_000u32).map(|i| (i % 251) as u8).collect();
+        std::fs::write(dir.join("source.img"), &data).unwrap();
+        let mut target = data.clone();
+        target.extend_from_slice(b"tail past the source");
+        std::fs::write(dir.join("target.img"), &target).unwrap();
+        let open = |name: &str| std::fs::File::open(dir.join(name)).unwrap();
+
+        let same = clone_device::compare_devices(&mut open("source.img"), &mut open("target.img"), 10_000, 4096).unwrap();
+        assert_eq!((same.bytes_compared, same.mismatched_chunks), (10_000, 0));
+        target[9_000] ^= 0xff;
+        target[5_000] ^= 0xff;
+        std::fs::write(dir.join("target.img"), &target).unwrap();
+        let differs = clone_device::compare_devices(&mut open("source.img"), &mut open("target.img"), 10_000, 4096).unwrap();
+        assert_eq!((differs.mismatched_chunks, differs.first_mismatch), (2, Some(4096)));
+
+        let path = dir.join("source.img.zst");
+        let mut archive = clone_device::ArchiveWriter::create(&path).unwrap();
+        archive.write_all(&data).unwrap();
+        let (bytes, sha256) = archive.finish().unwrap();
+        let compressed = std::fs::read(&path).unwrap();
+        assert_eq!(bytes, compressed.len() as u64);
+        assert_eq!(sha256, to_hex(&Sha256::digest(&compressed)));
+        assert_eq!(zstd::decode_all(compressed.as_slice()).unwrap(), data);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn restore_reads_the_image_a_verified_bundle_carries() {
+        use std::io::Read;
+
+        let dir = std::env::temp_dir().join(format!("phoenix-restore-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let data: Vec<u8> = (0..10_000u32).map(|i| (i % 251) as u8).collect();
+        let image = dir.join("disk.img.zst");
+        std::fs::write(&image, zstd::encode_all(data.as_slice(), 3).unwrap()).unwrap();
+        let graph = test_graph(json!([]));
+        let meta = json!({
+            "workflow": "clone-device",
+            "status": "completed",
+            "image_artifact": {
+                "name": CLONE_ARCHIVE_ARTIFACT, "compression": "zstd",
+                "image_bytes": data.len(), "image_sha256": to_hex(&Sha256::digest(&data))
+            },
+            "artifacts": [CLONE_ARCHIVE_ARTIFACT]
+        });
+        let report = phoenix_report::create_report_bundle_with_meta_signing_and_artifacts(
+            dir.join("reports"),
+            &graph,
+            Some(meta),
+            None,
+            None,
+            &[phoenix_report::ReportArtifact::image(CLONE_ARCHIVE_ARTIFACT, &image)],
+        )
+        .unwrap();
+
+        let unsigned = restore::open_bundle(&report.root, false).err().unwrap();
+        assert!(unsigned.to_string().contains("not signed"), "{}", unsigned);
+        let bundle = restore::open_bundle(&report.root, true).unwrap();
+        assert_eq!(bundle.image.image_bytes, data.len() as u64);
+        let mut restored = Vec::new();
+        restore::image_reader(&report.root.join(&bundle.image.name), &bundle.image.compression)
+            .unwrap()
+            .read_to_end(&mut restored)
+            .unwrap();
+        assert_eq!(restored, data);
+
+        std::fs::write(report.root.join(CLONE_ARCHIVE_ARTIFACT), b"not the image").unwrap();
+        let tampered = restore::open_bundle(&report.root, true).err().unwrap();
+        assert!(tampered.to_string().contains("failed verification"), "{}", tampered);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn secrets_never_reach_report_files() {
+        let dir = std::env::temp_dir().join(format!("phoenix-secrets-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let secret = "wifi-psk-8d1e6b0c44";
+
+        let provider = DirKeyProvider { dir: dir.join("secrets") };
+        std::fs::create_dir_all(&provider.dir).unwrap();
+        std::fs::write(provider.dir.join("wifi"), format!("{}\n", secret)).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let file = provider.dir.join("wifi");
+            std::fs::set_permissions(&file, std::fs::Permissions::from_mode(0o640)).unwrap();
+            let err = provider.secret("wifi").err().unwrap();
+            assert!(err.to_string().contains("readable by other users"), "{}", err);
+            std::fs::set_permissions(&file, std::fs::Permissions::from_mode(0o600)).unwrap();
+        }
+        assert_eq!(provider.secret("wifi").unwrap().as_deref(), Some(secret));
+        assert_eq!(provider.secret("missing").unwrap(), None);
+
+        std::env::set_var("PHOENIX_SECRET_REPORT_TEST_PSK", secret);
+        let params =
+            secrets::resolve_secret_params(&json!({"psk": "secret://report-test-psk"})).unwrap();
+        assert_eq!(params["psk"], secret);
+
+        let source = dir.join("unattend.xml");
+        std::fs::write(&source, format!("<Key>{}</Key>", secret)).unwrap();
+        let graph = test_graph(json!([]));
+        let report = phoenix_report::create_report_bundle_with_meta_signing_and_artifacts(
+            dir.join("reports"),
+            &graph,
+            Some(json!({
+                "workflow": "provision", "status": "failed",
+                "error": format!("bad psk {}", secret)
+            })),
+            Some(&format!("joining with {}\n", secret)),
+            None,
+            &[
+                phoenix_report::ReportArtifact::bytes("wlan.xml", secret.as_bytes().to_vec()),
+                phoenix_report::ReportArtifact::file("unattend.xml", &source),
+            ],
+        )
+        .unwrap();
+
+        for name in ["logs.txt", "run.json", "wlan.xml", "unattend.xml", "manifest.json"] {
+            let text = std::fs::read_to_string(report.root.join(name)).unwrap();
+            assert!(!text.contains(secret), "{} holds the secret", name);
+        }
+        assert!(std::fs::read_to_string(report.root.join("logs.txt"))
+            .unwrap()
+            .contains(phoenix_report::REDACTED));
+        let verified = phoenix_report::verify_report_bundle(&report.root, None).unwrap();
+        assert!(verified.mismatches.is_empty(), "{:?}", verified.mismatches);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn estimates_cost_the_bytes_moved() {
+        let image = std::env::temp_dir().join(format!("phoenix-estimate-{}.img", std::process::id()));
+        std::fs::File::create(&image).unwrap().set_len(40_000_000).unwrap();
+        let step = |id: &str, action: &str, params: serde_json::Value| WorkflowStep {
+            id: id.to_string(),
+            action: action.to_string(),
+            params,
+            timeout_secs: None,
+        };
+        let mut params = EstimateParams {
+            target_bytes: Some(8_000_000_000),
+            ..Default::default()
+        };
+        params.source_sizes.insert("check".to_string(), 4_000_000_000);
+        let cost = |step: &WorkflowStep| estimate::estimate_step(step, &params, 20_000_000, 80_000_000);
+
+        let write = cost(&step(
+            "write",
+            "linux_write_image",
+            json!({"source_image": image.display().to_string(), "target_device": "/dev/sdb", "verify": true}),
+        ));
+        assert_eq!((write.write_bytes, write.read_bytes), (40_000_000, 40_000_000));
+        assert_eq!(write.duration_ms, estimate::STEP_OVERHEAD_MS + 2_000 + 500);
+        let check = cost(&step("check", "validate_source", json!({"source_path": "elsewhere.iso"})));
+        assert_eq!(check.duration_ms, estimate::STEP_OVERHEAD_MS + 10_000);
+        let scan = cost(&step("scan", "bad_block_scan", json!({"disk_id": "sdb", "mode": "write"})));
+        assert_eq!(scan.duration_ms, estimate::STEP_OVERHEAD_MS + 400_000 + 100_000);
+        let missing = cost(&step("copy", "linux_installer_usb", json!({"source_path": "missing.iso"})));
+        assert_eq!((missing.source_bytes, missing.duration_ms), (None, estimate::STEP_OVERHEAD_MS));
+        assert!(missing.basis.starts_with("unknown"));
+        let fixed = cost(&step("entry", "boot_entry", json!({})));
+        assert_eq!(fixed.duration_ms, estimate::STEP_OVERHEAD_MS);
+        std::fs::remove_file(&image).unwrap();
+    }
+
+    #[test]
+    fn verify_policy_samples_large_reads() {
+        let policy: VerifyPolicy = serde_json::from_value(json!({
+            "name": "fleet", "file_copies": "required", "sample_above": "1G", "sample_percent": 25
+        }))
+        .unwrap();
+        let small = policy.apply(VerifyTarget::FileCopies, false, 1 << 20);
+        assert_eq!(small.level, VerifyLevel::Full);
+        assert!(small.required_by_policy);
+        let large = policy.apply(VerifyTarget::FileCopies, true, 2 << 30);
+        assert_eq!((large.level, large.sample_percent), (VerifyLevel::Sampled, Some(25)));
+        assert!(!large.required_by_policy);
+        assert_eq!(policy.apply(VerifyTarget::ImageWrites, false, 1).level, VerifyLevel::Off);
+
+        let hashing = CopyHashing::Source.with_verify(&large);
+        assert_eq!(hashing, CopyHashing::Sampled(25));
+        let picked = (0..400)
+            .filter(|index| hashing.reads_back(Path::new(&format!("sources/{}.cab", index))))
+            .count();
+        assert!((50..150).contains(&picked), "picked {}", picked);
+    }
+
+    #[test]
+    fn results_round_trip() {
+        let run = json!({
+            "report": {
+                "run_id": "run-1", "root": "reports/run-1", "device_graph_json": "reports/run-1/device_graph.json",
+                "run_json": "reports/run-1/run.json", "environment_json": "reports/run-1/environment.json",
+                "logs_path": "reports/run-1/logs.txt", "manifest_path": "reports/run-1/manifest.json",
+                "signature_path": null
+            },
+            "steps": [{
+                "id": "write", "action": "unix_write_image", "report_root": "reports/run-1/write",
+                "duration_ms": 1500, "reused": false,
+                "hooks": [{"phase": "post", "index": 0, "program": "eject", "exit_code": 0,
+                           "timed_out": false, "required": true, "duration_ms": 20}]
+            }]
+        });
+        let read: WorkflowRunResult = serde_json::from_value(run.clone()).unwrap();
+        assert_eq!(serde_json::to_value(read).unwrap(), run);
+    }
+}
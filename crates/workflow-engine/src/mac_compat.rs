@@ -0,0 +1,211 @@
+//! Whether a macOS installer can boot a given Mac. The architectures come
+//! from the installer's Mach-O executable and the version from its name,
+//! so a mismatch fails before the target is erased.
+
+use crate::parse_macos_version;
+use anyhow::{anyhow, Context, Result};
+use serde::Serialize;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+const CPU_TYPE_X86: u32 = 7;
+const CPU_TYPE_X86_64: u32 = 0x0100_0007;
+const CPU_TYPE_ARM64: u32 = 0x0100_000c;
+
+/// Oldest macOS each Apple Silicon generation shipped with, by model
+/// family and major number.
+const APPLE_SILICON_MINIMUMS: &[(&str, u32, (u32, u32))] = &[
+    ("MacBookAir", 10, (11, 0)),
+    ("MacBookPro", 17, (11, 0)),
+    ("Macmini", 9, (11, 0)),
+    ("iMac", 21, (11, 3)),
+    ("MacBookPro", 18, (12, 0)),
+    ("Mac", 13, (12, 3)),
+    ("Mac", 14, (12, 4)),
+    ("Mac", 15, (14, 0)),
+    ("Mac", 16, (15, 0)),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MacArch {
+    AppleSilicon,
+    Intel,
+}
+
+impl MacArch {
+    /// Architecture of a model identifier such as `MacBookPro18,3`.
+    pub fn of_model(model: &str) -> Result<Self> {
+        let (family, major) = parse_model(model)?;
+        let apple_silicon = match family {
+            "Mac" => true,
+            "MacBookAir" => major >= 10,
+            "MacBookPro" => major >= 17,
+            "Macmini" => major >= 9,
+            "iMac" => major >= 21,
+            _ => false,
+        };
+        Ok(if apple_silicon { Self::AppleSilicon } else { Self::Intel })
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::AppleSilicon => "apple_silicon",
+            Self::Intel => "intel",
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            Self::AppleSilicon => "Apple Silicon",
+            Self::Intel => "Intel",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct InstallerSupport {
+    pub app: PathBuf,
+    pub name: String,
+    /// `major.minor`, when the name gives it away.
+    pub macos_version: Option<String>,
+    pub architectures: Vec<MacArch>,
+}
+
+/// Reads `Info.plist` and the executable's Mach-O header of an
+/// `Install macOS <name>.app`.
+pub fn inspect_installer_app(app: &Path) -> Result<InstallerSupport> {
+    let info_path = app.join("Contents/Info.plist");
+    let info = plist::Value::from_file(&info_path)
+        .with_context(|| format!("read {}", info_path.display()))?;
+    let info = info
+        .as_dictionary()
+        .ok_or_else(|| anyhow!("{} is not a dictionary", info_path.display()))?;
+    let field = |key: &str| info.get(key).and_then(|value| value.as_string());
+    let executable = field("CFBundleExecutable")
+        .ok_or_else(|| anyhow!("{} has no CFBundleExecutable", info_path.display()))?;
+    let name = field("CFBundleDisplayName")
+        .or_else(|| field("CFBundleName"))
+        .map(str::to_string)
+        .or_else(|| app.file_stem().map(|stem| stem.to_string_lossy().to_string()))
+        .unwrap_or_default();
+    let macos_version = parse_macos_version(&name.to_ascii_lowercase())
+        .or_else(|| {
+            app.file_stem()
+                .and_then(|stem| parse_macos_version(&stem.to_string_lossy().to_ascii_lowercase()))
+        })
+        .map(|(major, minor)| format!("{}.{}", major, minor));
+    let architectures = macho_architectures(&app.join("Contents/MacOS").join(executable))?;
+
+    Ok(InstallerSupport {
+        app: app.to_path_buf(),
+        name,
+        macos_version,
+        architectures,
+    })
+}
+
+/// Fails with the reason when `support` cannot boot `model`.
+pub fn check_installer_for_model(support: &InstallerSupport, model: &str) -> Result<()> {
+    let arch = MacArch::of_model(model)?;
+    if !support.architectures.contains(&arch) {
+        let runs_on: Vec<&str> = support.architectures.iter().map(|arch| arch.label()).collect();
+        return Err(anyhow!(
+            "{} runs on {} only; {} is an {} Mac and will not boot it",
+            support.name,
+            runs_on.join(" and "),
+            model,
+            arch.label()
+        ));
+    }
+    if arch == MacArch::AppleSilicon {
+        let minimum = minimum_version(model)?;
+        let version = support.macos_version.as_deref().and_then(parse_macos_version);
+        if let (Some(minimum), Some(version)) = (minimum, version) {
+            if version < minimum {
+                return Err(anyhow!(
+                    "{} needs macOS {}.{} or later; {} is {}.{}",
+                    model,
+                    minimum.0,
+                    minimum.1,
+                    support.name,
+                    version.0,
+                    version.1
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn parse_model(model: &str) -> Result<(&str, u32)> {
+    let model = model.trim();
+    let invalid = || {
+        anyhow!(
+            "unrecognized Mac model identifier: {} (expected e.g. MacBookPro18,3)",
+            model
+        )
+    };
+    let digits = model.find(|c: char| c.is_ascii_digit()).ok_or_else(invalid)?;
+    let (family, numbers) = model.split_at(digits);
+    let (major, minor) = numbers.split_once(',').ok_or_else(invalid)?;
+    if family.is_empty() || minor.parse::<u32>().is_err() {
+        return Err(invalid());
+    }
+    Ok((family, major.parse().map_err(|_| invalid())?))
+}
+
+fn minimum_version(model: &str) -> Result<Option<(u32, u32)>> {
+    let (family, major) = parse_model(model)?;
+    Ok(APPLE_SILICON_MINIMUMS
+        .iter()
+        .find(|(known, known_major, _)| *known == family && *known_major == major)
+        .map(|(_, _, version)| *version)
+        // Newer generations than the table: at least the newest known.
+        .or_else(|| (family == "Mac" && major > 16).then_some((15, 0))))
+}
+
+/// Architectures of a thin or universal Mach-O binary.
+fn macho_architectures(path: &Path) -> Result<Vec<MacArch>> {
+    let mut header = Vec::new();
+    std::fs::File::open(path)
+        .with_context(|| format!("open {}", path.display()))?
+        .take(4096)
+        .read_to_end(&mut header)?;
+    let be = |at: usize| {
+        header
+            .get(at..at + 4)
+            .map(|bytes| u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    };
+    let not_macho = || anyhow!("{} is not a Mach-O executable", path.display());
+    let magic = be(0).ok_or_else(not_macho)?;
+
+    let cpu_types: Vec<u32> = match magic {
+        // Universal: big-endian fat_arch (20 bytes) or fat_arch_64 (32 bytes) records.
+        0xcafe_babe | 0xcafe_babf => {
+            let stride = if magic == 0xcafe_babe { 20 } else { 32 };
+            let count = be(4).ok_or_else(not_macho)? as usize;
+            (0..count.min(16))
+                .map(|index| be(8 + index * stride).ok_or_else(not_macho))
+                .collect::<Result<_>>()?
+        }
+        // Thin, little-endian: cputype follows the magic.
+        0xcffa_edfe | 0xcefa_edfe => vec![be(4).ok_or_else(not_macho)?.swap_bytes()],
+        _ => return Err(not_macho()),
+    };
+    let mut architectures = Vec::new();
+    for cpu_type in cpu_types {
+        let arch = match cpu_type {
+            CPU_TYPE_ARM64 => MacArch::AppleSilicon,
+            CPU_TYPE_X86 | CPU_TYPE_X86_64 => MacArch::Intel,
+            _ => continue,
+        };
+        if !architectures.contains(&arch) {
+            architectures.push(arch);
+        }
+    }
+    if architectures.is_empty() {
+        return Err(anyhow!("{} has no Mac architecture", path.display()));
+    }
+    Ok(architectures)
+}
@@ -0,0 +1,614 @@
+//! Edits to extracted Windows installer media, run as workflow steps before
+//! staging.
+
+use crate::steplog::StepLog;
+use crate::{collect_files, copy_file_with_mtime, dir_stats, report_graph, signing_key_from_env};
+use phoenix_content::prepare_source;
+use anyhow::{anyhow, Context, Result};
+use phoenix_report::{create_report_bundle_with_meta_signing_and_artifacts, ReportPaths};
+use phoenix_wim::{export_images as wim_export_images, list_images as wim_list_images};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// EditionIDs Windows Setup accepts in `EI.cfg`.
+const EDITION_IDS: &[&str] = &[
+    "Core",
+    "CoreN",
+    "CoreSingleLanguage",
+    "CoreCountrySpecific",
+    "Professional",
+    "ProfessionalN",
+    "ProfessionalEducation",
+    "ProfessionalEducationN",
+    "ProfessionalWorkstation",
+    "ProfessionalWorkstationN",
+    "Education",
+    "EducationN",
+    "Enterprise",
+    "EnterpriseN",
+    "IoTEnterprise",
+];
+
+/// Product key characters; `N` may appear once in Windows 8 and later keys.
+const KEY_ALPHABET: &str = "BCDFGHJKMPQRTVWXY2346789";
+
+/// Canonical EditionID for `edition`, matched case-insensitively.
+pub fn parse_edition_id(edition: &str) -> Result<&'static str> {
+    EDITION_IDS
+        .iter()
+        .find(|id| id.eq_ignore_ascii_case(edition.trim()))
+        .copied()
+        .ok_or_else(|| anyhow!("unknown edition {} (expected one of {})", edition, EDITION_IDS.join(", ")))
+}
+
+/// Checks the `XXXXX-XXXXX-XXXXX-XXXXX-XXXXX` shape and alphabet; returns
+/// the key upper-cased. Whether the key activates is not checked.
+pub fn parse_product_key(key: &str) -> Result<String> {
+    let key = key.trim().to_ascii_uppercase();
+    let groups: Vec<&str> = key.split('-').collect();
+    if groups.len() != 5 || groups.iter().any(|group| group.len() != 5) {
+        return Err(anyhow!("product key must be five groups of five characters"));
+    }
+    let mut n_count = 0;
+    for c in key.chars().filter(|c| *c != '-') {
+        if c == 'N' {
+            n_count += 1;
+        } else if !KEY_ALPHABET.contains(c) {
+            return Err(anyhow!("product key contains invalid character {}", c));
+        }
+    }
+    if n_count > 1 {
+        return Err(anyhow!("product key contains more than one N"));
+    }
+    Ok(key)
+}
+
+/// Writes `sources/EI.cfg` (edition, Retail channel; Volume for Enterprise
+/// editions) and/or `sources/PID.txt` (product key) under the staged media
+/// `root`. Returns the files written.
+pub(crate) fn write_setup_selection(
+    root: &Path,
+    edition: Option<&str>,
+    product_key: Option<&str>,
+) -> Result<Vec<PathBuf>> {
+    let sources = root.join("sources");
+    let mut written = Vec::new();
+    if let Some(edition) = edition {
+        let edition = parse_edition_id(edition)?;
+        let volume = edition.contains("Enterprise");
+        let text = format!(
+            "[EditionID]\r\n{}\r\n[Channel]\r\n{}\r\n[VL]\r\n{}\r\n",
+            edition,
+            if volume { "Volume" } else { "Retail" },
+            if volume { 1 } else { 0 }
+        );
+        let path = sources.join("EI.cfg");
+        fs::write(&path, text).with_context(|| format!("write {}", path.display()))?;
+        written.push(path);
+    }
+    if let Some(key) = product_key {
+        let key = parse_product_key(key)?;
+        let path = sources.join("PID.txt");
+        fs::write(&path, format!("[PID]\r\nValue={}\r\n", key))
+            .with_context(|| format!("write {}", path.display()))?;
+        written.push(path);
+    }
+    Ok(written)
+}
+
+/// What `slim_windows_media` keeps; an empty list leaves that part of the
+/// media alone. Packs ship it as JSON:
+///
+/// ```json
+/// { "languages": ["en-us", "de-de"], "editions": ["Windows 11 Pro", "6"] }
+/// ```
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MediaKeepList {
+    /// Setup language tags, matched against the directories under `sources/`.
+    #[serde(default)]
+    pub languages: Vec<String>,
+    /// `install.wim` image names or indices.
+    #[serde(default)]
+    pub editions: Vec<String>,
+}
+
+impl MediaKeepList {
+    pub fn load(path: &Path) -> Result<Self> {
+        let data = fs::read_to_string(path).with_context(|| format!("read {}", path.display()))?;
+        serde_json::from_str(&data).with_context(|| format!("parse keep-list {}", path.display()))
+    }
+
+    fn keeps_language(&self, tag: &str) -> bool {
+        self.languages.iter().any(|keep| keep.eq_ignore_ascii_case(tag))
+    }
+
+    fn keeps_edition(&self, index: u32, name: Option<&str>) -> bool {
+        self.editions.iter().any(|keep| {
+            keep.trim().parse::<u32>().ok() == Some(index)
+                || name.is_some_and(|name| keep.trim().eq_ignore_ascii_case(name))
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SlimWindowsMediaParams {
+    /// Extracted media directory; it is modified in place.
+    pub source_path: PathBuf,
+    #[serde(flatten)]
+    pub keep: MediaKeepList,
+    #[serde(default = "crate::params::default_report_base")]
+    pub report_base: PathBuf,
+    #[serde(default = "crate::params::default_true")]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SlimWindowsMediaResult {
+    pub report: ReportPaths,
+    pub removed_languages: Vec<String>,
+    /// `index:name` of each image dropped from `install.wim`.
+    pub removed_editions: Vec<String>,
+    /// Language directories plus the `install.wim` shrink; in a dry run the
+    /// WIM part is not known and only the language bytes are counted.
+    pub freed_bytes: u64,
+    pub dry_run: bool,
+}
+
+/// Removes setup languages and `install.wim` images the keep-list does not
+/// name, so the media fits a smaller stick. Images are dropped by exporting
+/// the kept ones into a new WIM that replaces the original.
+pub fn run_slim_windows_media(params: &SlimWindowsMediaParams) -> Result<SlimWindowsMediaResult> {
+    let root = &params.source_path;
+    if !root.is_dir() {
+        return Err(anyhow!(
+            "slim_windows_media needs an extracted media directory, not {}",
+            root.display()
+        ));
+    }
+    let sources = root.join("sources");
+    if !sources.is_dir() {
+        return Err(anyhow!("{} has no sources directory", root.display()));
+    }
+
+    let mut logs = StepLog::new("slim-windows-media");
+    logs.push(format!("source_path={}", root.display()));
+    logs.push(format!("dry_run={}", params.dry_run));
+
+    let mut removed_languages = Vec::new();
+    let mut freed_bytes = 0u64;
+    if !params.keep.languages.is_empty() {
+        let present = setup_languages(&sources)?;
+        let (kept, removed): (Vec<_>, Vec<_>) = present
+            .into_iter()
+            .partition(|tag| params.keep.keeps_language(tag));
+        if kept.is_empty() {
+            return Err(anyhow!(
+                "keep-list languages {:?} match none of the media's languages {:?}",
+                params.keep.languages,
+                removed
+            ));
+        }
+        for tag in removed {
+            let dir = sources.join(&tag);
+            let bytes = dir_stats(&dir)?.total_bytes;
+            logs.push(format!("remove_language={} bytes={}", tag, bytes));
+            if !params.dry_run {
+                fs::remove_dir_all(&dir).with_context(|| format!("remove {}", dir.display()))?;
+            }
+            freed_bytes += bytes;
+            removed_languages.push(tag);
+        }
+        if !params.dry_run && !removed_languages.is_empty() {
+            remove_lang_ini_entries(&sources.join("lang.ini"), &removed_languages)?;
+        }
+    }
+
+    let mut removed_editions = Vec::new();
+    if !params.keep.editions.is_empty() {
+        let wim = sources.join("install.wim");
+        if !wim.is_file() {
+            return Err(anyhow!(
+                "edition pruning needs sources/install.wim (install.esd cannot be re-exported)"
+            ));
+        }
+        let images = wim_list_images(&wim)?;
+        let (kept, removed): (Vec<_>, Vec<_>) = images
+            .iter()
+            .partition(|image| params.keep.keeps_edition(image.index, image.name.as_deref()));
+        if kept.is_empty() {
+            return Err(anyhow!(
+                "keep-list editions {:?} match no image in {}",
+                params.keep.editions,
+                wim.display()
+            ));
+        }
+        for image in &removed {
+            let label = format!("{}:{}", image.index, image.name.as_deref().unwrap_or("unnamed"));
+            logs.push(format!("remove_edition={}", label));
+            removed_editions.push(label);
+        }
+        if !params.dry_run && !removed.is_empty() {
+            let before = fs::metadata(&wim)?.len();
+            let slim = wim.with_extension("wim.slim");
+            let indices: Vec<u32> = kept.iter().map(|image| image.index).collect();
+            if let Err(err) = wim_export_images(&wim, &indices, &slim) {
+                fs::remove_file(&slim).ok();
+                return Err(err);
+            }
+            fs::rename(&slim, &wim).with_context(|| format!("replace {}", wim.display()))?;
+            let after = fs::metadata(&wim)?.len();
+            logs.push(format!("install_wim_bytes before={} after={}", before, after));
+            freed_bytes += before.saturating_sub(after);
+        }
+    }
+    logs.push(format!("freed_bytes={}", freed_bytes));
+
+    let (log_text, timing) = logs.finish()?;
+    let meta = serde_json::json!({
+        "workflow": "slim-windows-media",
+        "source_path": root.display().to_string(),
+        "dry_run": params.dry_run,
+        "keep_languages": params.keep.languages,
+        "keep_editions": params.keep.editions,
+        "removed_languages": removed_languages,
+        "removed_editions": removed_editions,
+        "freed_bytes": freed_bytes,
+        "wim_backend": (!params.keep.editions.is_empty())
+            .then(|| phoenix_wim::backend().ok().map(|backend| backend.as_str()))
+            .flatten(),
+    });
+    let report = create_report_bundle_with_meta_signing_and_artifacts(
+        &params.report_base,
+        &report_graph(),
+        Some(meta),
+        Some(&log_text),
+        signing_key_from_env().as_deref(),
+        &[timing],
+    )?;
+
+    Ok(SlimWindowsMediaResult {
+        report,
+        removed_languages,
+        removed_editions,
+        freed_bytes,
+        dry_run: params.dry_run,
+    })
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MergeWindowsLanguagesParams {
+    /// Extracted media directory; it is modified in place.
+    pub source_path: PathBuf,
+    /// Localized Windows media (folders or ISOs) to take setup languages from.
+    #[serde(default)]
+    pub language_sources: Vec<PathBuf>,
+    /// Tags to take; empty takes every language a source has.
+    #[serde(default)]
+    pub languages: Vec<String>,
+    #[serde(default = "crate::params::default_report_base")]
+    pub report_base: PathBuf,
+    #[serde(default = "crate::params::default_true")]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MergeWindowsLanguagesResult {
+    pub report: ReportPaths,
+    pub added_languages: Vec<String>,
+    pub copied_files: usize,
+    pub copied_bytes: u64,
+    pub dry_run: bool,
+}
+
+/// Directories holding one language's Setup and boot manager resources.
+fn language_dirs(tag: &str) -> [PathBuf; 3] {
+    [
+        Path::new("sources").join(tag),
+        Path::new("boot").join(tag),
+        Path::new("efi").join("microsoft").join("boot").join(tag),
+    ]
+}
+
+/// Copies setup languages from localized media into `source_path` and lists
+/// them in `sources/lang.ini`, so Setup offers them next to the media's own
+/// language. Languages the media already has are skipped. Only the Setup UI
+/// is merged; language packs for the installed OS are not.
+pub fn run_merge_windows_languages(
+    params: &MergeWindowsLanguagesParams,
+) -> Result<MergeWindowsLanguagesResult> {
+    let root = &params.source_path;
+    let sources = root.join("sources");
+    if !root.is_dir() || !sources.is_dir() {
+        return Err(anyhow!(
+            "merge_windows_languages needs an extracted media directory with sources/, not {}",
+            root.display()
+        ));
+    }
+    let lang_ini_path = sources.join("lang.ini");
+    let mut lang_ini = LangIni::read(&lang_ini_path)?;
+    let existing = setup_languages(&sources)?;
+
+    let mut logs = StepLog::new("merge-windows-languages");
+    logs.push(format!("source_path={}", root.display()));
+    logs.push(format!("dry_run={}", params.dry_run));
+    let mut added_languages: Vec<String> = Vec::new();
+    let mut copied_files = 0usize;
+    let mut copied_bytes = 0u64;
+    for language_source in &params.language_sources {
+        let prepared = prepare_source(language_source)?;
+        let tags = setup_languages(&prepared.root.join("sources"))?;
+        logs.push(format!(
+            "language_source={} languages={}",
+            language_source.display(),
+            tags.join(",")
+        ));
+        for tag in tags {
+            let wanted = params.languages.is_empty()
+                || params.languages.iter().any(|lang| lang.eq_ignore_ascii_case(&tag));
+            let present = existing
+                .iter()
+                .chain(added_languages.iter())
+                .any(|lang| lang.eq_ignore_ascii_case(&tag));
+            if !wanted || present {
+                continue;
+            }
+            for dir in language_dirs(&tag) {
+                let from = prepared.root.join(&dir);
+                if !from.is_dir() {
+                    continue;
+                }
+                for entry in collect_files(&from)? {
+                    let dest = root.join(&dir).join(&entry.relative_path);
+                    if !params.dry_run {
+                        if let Some(parent) = dest.parent() {
+                            fs::create_dir_all(parent)
+                                .with_context(|| format!("create dir {}", parent.display()))?;
+                        }
+                        copy_file_with_mtime(&entry.absolute_path, &dest)
+                            .with_context(|| format!("copy {}", entry.absolute_path.display()))?;
+                    }
+                    copied_files += 1;
+                    copied_bytes = copied_bytes.saturating_add(entry.size);
+                }
+            }
+            logs.push(format!("add_language={}", tag));
+            added_languages.push(tag);
+        }
+    }
+    for lang in &params.languages {
+        let found = existing
+            .iter()
+            .chain(added_languages.iter())
+            .any(|tag| tag.eq_ignore_ascii_case(lang));
+        if !found {
+            return Err(anyhow!("language {} not found in any language source", lang));
+        }
+    }
+
+    if !added_languages.is_empty() {
+        lang_ini.add_languages(&added_languages)?;
+        if !params.dry_run {
+            lang_ini.write(&lang_ini_path)?;
+        }
+    }
+    logs.push(format!("copied_files={}", copied_files));
+    logs.push(format!("copied_bytes={}", copied_bytes));
+
+    let (log_text, timing) = logs.finish()?;
+    let meta = serde_json::json!({
+        "workflow": "merge-windows-languages",
+        "source_path": root.display().to_string(),
+        "language_sources": params
+            .language_sources
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect::<Vec<_>>(),
+        "existing_languages": existing,
+        "added_languages": added_languages,
+        "copied_files": copied_files,
+        "copied_bytes": copied_bytes,
+        "dry_run": params.dry_run,
+    });
+    let report = create_report_bundle_with_meta_signing_and_artifacts(
+        &params.report_base,
+        &report_graph(),
+        Some(meta),
+        Some(&log_text),
+        signing_key_from_env().as_deref(),
+        &[timing],
+    )?;
+
+    Ok(MergeWindowsLanguagesResult {
+        report,
+        added_languages,
+        copied_files,
+        copied_bytes,
+        dry_run: params.dry_run,
+    })
+}
+
+/// Language directories under `sources/` (`en-us`, `sr-latn-rs`, ...) that
+/// hold setup resources.
+pub(crate) fn setup_languages(sources: &Path) -> Result<Vec<String>> {
+    let mut tags = Vec::new();
+    for entry in fs::read_dir(sources).with_context(|| format!("read {}", sources.display()))? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        if !entry.file_type()?.is_dir() || !is_language_tag(&name) {
+            continue;
+        }
+        let has_mui = fs::read_dir(entry.path())?.flatten().any(|file| {
+            file.file_name()
+                .to_string_lossy()
+                .to_ascii_lowercase()
+                .ends_with(".mui")
+        });
+        if has_mui {
+            tags.push(name);
+        }
+    }
+    tags.sort();
+    Ok(tags)
+}
+
+fn is_language_tag(name: &str) -> bool {
+    let parts: Vec<&str> = name.split('-').collect();
+    (2..=3).contains(&parts.len())
+        && (2..=3).contains(&parts[0].len())
+        && parts
+            .iter()
+            .all(|part| !part.is_empty() && part.len() <= 4 && part.chars().all(|c| c.is_ascii_alphabetic()))
+}
+
+/// `sources/lang.ini`, kept in its original encoding (UTF-16LE with a BOM on
+/// most media, otherwise plain text).
+pub(crate) struct LangIni {
+    pub lines: Vec<String>,
+    utf16: bool,
+}
+
+impl LangIni {
+    pub fn read(path: &Path) -> Result<Self> {
+        let bytes = fs::read(path).with_context(|| format!("read {}", path.display()))?;
+        let (text, utf16) = match bytes.strip_prefix(&[0xFF, 0xFE]) {
+            Some(rest) => {
+                let units: Vec<u16> = rest
+                    .chunks_exact(2)
+                    .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+                    .collect();
+                (String::from_utf16_lossy(&units), true)
+            }
+            None => (String::from_utf8_lossy(&bytes).to_string(), false),
+        };
+        Ok(Self {
+            lines: text.lines().map(str::to_string).collect(),
+            utf16,
+        })
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let text = self.lines.join("\r\n") + "\r\n";
+        let bytes = if self.utf16 {
+            let mut bytes = vec![0xFF, 0xFE];
+            bytes.extend(text.encode_utf16().flat_map(u16::to_le_bytes));
+            bytes
+        } else {
+            text.into_bytes()
+        };
+        fs::write(path, bytes).with_context(|| format!("write {}", path.display()))
+    }
+
+    /// Lists `tags` as secondary UI languages (`= 2`) falling back to the
+    /// media's default language (the `= 3` entry).
+    pub fn add_languages(&mut self, tags: &[String]) -> Result<()> {
+        let default = self
+            .section("Available UI Languages")
+            .and_then(|range| {
+                self.lines[range].iter().find_map(|line| {
+                    let (key, value) = line.split_once('=')?;
+                    (value.trim() == "3").then(|| key.trim().to_string())
+                })
+            })
+            .ok_or_else(|| anyhow!("lang.ini has no default UI language"))?;
+        let listed: Vec<String> = self
+            .section("Available UI Languages")
+            .map(|range| {
+                self.lines[range]
+                    .iter()
+                    .filter_map(|line| Self::entry_tag(line))
+                    .map(str::to_ascii_lowercase)
+                    .collect()
+            })
+            .unwrap_or_default();
+        let tags: Vec<String> = tags
+            .iter()
+            .filter(|tag| !listed.contains(&tag.to_ascii_lowercase()))
+            .map(|tag| ini_tag(tag))
+            .collect();
+        let available: Vec<String> = tags.iter().map(|tag| format!("{} = 2", tag)).collect();
+        let fallback: Vec<String> = tags
+            .iter()
+            .map(|tag| format!("{} = {}", tag, default.to_ascii_lowercase()))
+            .collect();
+        self.append_to_section("Available UI Languages", available);
+        self.append_to_section("Fallback Languages", fallback);
+        Ok(())
+    }
+
+    /// Entry lines of `[name]`, excluding the header.
+    fn section(&self, name: &str) -> Option<std::ops::Range<usize>> {
+        let header = format!("[{}]", name);
+        let start = self
+            .lines
+            .iter()
+            .position(|line| line.trim().eq_ignore_ascii_case(&header))?
+            + 1;
+        let end = self.lines[start..]
+            .iter()
+            .position(|line| line.trim().starts_with('['))
+            .map(|offset| start + offset)
+            .unwrap_or(self.lines.len());
+        Some(start..end)
+    }
+
+    /// Inserts after the section's last entry, creating the section if the
+    /// file has none.
+    fn append_to_section(&mut self, name: &str, entries: Vec<String>) {
+        match self.section(name) {
+            Some(range) => {
+                let at = self.lines[range.clone()]
+                    .iter()
+                    .rposition(|line| !line.trim().is_empty())
+                    .map(|offset| range.start + offset + 1)
+                    .unwrap_or(range.start);
+                self.lines.splice(at..at, entries);
+            }
+            None => {
+                if self.lines.last().is_some_and(|line| !line.trim().is_empty()) {
+                    self.lines.push(String::new());
+                }
+                self.lines.push(format!("[{}]", name));
+                self.lines.extend(entries);
+            }
+        }
+    }
+
+    /// Language tag an entry line (`en-US = 3`) is keyed by.
+    pub fn entry_tag(line: &str) -> Option<&str> {
+        let line = line.trim();
+        if line.starts_with('[') || line.starts_with(';') {
+            return None;
+        }
+        line.split_once('=').map(|(key, _)| key.trim())
+    }
+}
+
+/// `sr-latn-rs` as lang.ini spells it: `sr-Latn-RS`.
+fn ini_tag(tag: &str) -> String {
+    tag.split('-')
+        .enumerate()
+        .map(|(index, part)| match (index, part.len()) {
+            (0, _) => part.to_ascii_lowercase(),
+            (_, 4) => {
+                let (first, rest) = part.split_at(1);
+                first.to_ascii_uppercase() + &rest.to_ascii_lowercase()
+            }
+            _ => part.to_ascii_uppercase(),
+        })
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+fn remove_lang_ini_entries(path: &Path, removed: &[String]) -> Result<()> {
+    if !path.is_file() {
+        return Ok(());
+    }
+    let mut ini = LangIni::read(path)?;
+    ini.lines.retain(|line| {
+        LangIni::entry_tag(line)
+            .map(|tag| !removed.iter().any(|removed| removed.eq_ignore_ascii_case(tag)))
+            .unwrap_or(true)
+    });
+    ini.write(path)
+}
@@ -0,0 +1,654 @@
+//! Destructive operations (partition, format, image write, image apply)
+//! behind one trait, run through a `DestructiveSession` that owns the
+//! sequence every workflow used to repeat: the safety gate, the run
+//! ledger phase, pre-checks, execution, post-verification and the step
+//! log entry. A dry run plans the same operations without touching them.
+
+use crate::io_limits::acquire_write_slot;
+use crate::ledger::RunTracker;
+use crate::mac_compat::InstallerSupport;
+use crate::steplog::StepLog;
+use crate::verify_policy::{AppliedVerify, VerifyLevel};
+use crate::{
+    begin_run, check_installer_app, erase_disk, find_install_app, format_existing_volume,
+    image_url, format_target_fat32, is_macos_app, mock_device_file, mock_repartition, mount_dmg,
+    normalize_mount_path, parse_disk_number, prepare_usb_disk, reread_partitions,
+    remount_formatted, resolve_chunk_size, run_asr_restore, run_createinstallmedia,
+    udisks_format_and_mount, write_device_path, write_target_image, MacosInstallerUsbParams,
+    ThroughputObserver, UnixWriteImageParams,
+};
+use anyhow::{anyhow, Context, Result};
+use phoenix_core::Disk;
+use phoenix_host_windows::format::FileSystem;
+use phoenix_host_windows::space::free_space_bytes;
+use phoenix_partition::plan::PartitionPlan;
+use phoenix_safety::{can_write_to_disk, SafetyContext, SafetyDecision};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub trait DestructiveOperation {
+    type Output;
+
+    /// One line for plans and the step log, e.g. `format E:\ as NTFS`.
+    fn description(&self) -> String;
+
+    /// Run ledger phase it executes in.
+    fn phase(&self) -> &'static str;
+
+    /// Id of the disk it changes; `None` for a directory target. Must be
+    /// the disk the session was begun for.
+    fn disk_id(&self) -> Option<&str>;
+
+    /// Checks made right before `execute`; a failure leaves the target
+    /// untouched.
+    fn pre_check(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn execute(
+        &mut self,
+        logs: &mut StepLog,
+        tracker: Option<&mut RunTracker>,
+    ) -> Result<Self::Output>;
+
+    /// Confirms the target holds what `execute` meant to leave there.
+    fn post_verify(&self, _output: &Self::Output, _logs: &mut StepLog) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// One workflow run's destructive operations. Only a real run passes the
+/// safety gate and, for a disk target, opens a run ledger record.
+pub struct DestructiveSession {
+    dry_run: bool,
+    disk_id: Option<String>,
+    tracker: Option<RunTracker>,
+    operations: Vec<String>,
+}
+
+impl DestructiveSession {
+    pub fn begin(
+        workflow: &str,
+        disk: Option<&Disk>,
+        ctx: &SafetyContext,
+        is_system_target: bool,
+        dry_run: bool,
+        logs: &mut StepLog,
+    ) -> Result<Self> {
+        let mut session = Self {
+            dry_run,
+            disk_id: disk.map(|disk| disk.id.clone()),
+            tracker: None,
+            operations: Vec::new(),
+        };
+        if dry_run {
+            return Ok(session);
+        }
+        if let Some(disk) = disk {
+            refuse_booted_esp(disk)?;
+        }
+        match can_write_to_disk(ctx, is_system_target) {
+            SafetyDecision::Allow => {}
+            SafetyDecision::Deny(reason) => return Err(anyhow!(reason)),
+            SafetyDecision::ReadOnly(err) => return Err(err.into()),
+        }
+        if let Some(disk) = disk {
+            session.tracker = Some(begin_run(workflow, disk, logs)?);
+        }
+        Ok(session)
+    }
+
+    /// Runs `operation`, or in a dry run logs it as planned and returns
+    /// `None`. A failure is recorded in the run ledger before it is
+    /// returned.
+    pub fn perform<O: DestructiveOperation>(
+        &mut self,
+        operation: &mut O,
+        logs: &mut StepLog,
+    ) -> Result<Option<O::Output>> {
+        let description = operation.description();
+        if operation.disk_id() != self.disk_id.as_deref() {
+            return Err(anyhow!(
+                "{} targets disk {} but this run is for {}",
+                description,
+                operation.disk_id().unwrap_or("none"),
+                self.disk_id.as_deref().unwrap_or("a directory")
+            ));
+        }
+        self.operations.push(description.clone());
+        if self.dry_run {
+            logs.push(format!("planned={}", description));
+            return Ok(None);
+        }
+        let result = self.run(operation, &description, logs);
+        self.record(result).map(Some)
+    }
+
+    fn run<O: DestructiveOperation>(
+        &mut self,
+        operation: &mut O,
+        description: &str,
+        logs: &mut StepLog,
+    ) -> Result<O::Output> {
+        operation.pre_check()?;
+        if let Some(tracker) = &mut self.tracker {
+            tracker.phase(operation.phase(), true)?;
+        }
+        logs.phase(operation.phase());
+        logs.push(format!("destructive={}", description));
+        let output = operation.execute(logs, self.tracker.as_mut())?;
+        operation.post_verify(&output, logs)?;
+        Ok(output)
+    }
+
+    /// Starts a phase that writes no more than files.
+    pub fn phase(&mut self, phase: &str, logs: &mut StepLog) -> Result<()> {
+        let result = match &mut self.tracker {
+            Some(tracker) => tracker.phase(phase, false),
+            None => Ok(()),
+        };
+        self.record(result)?;
+        logs.phase(phase);
+        Ok(())
+    }
+
+    /// Marks the ledger record failed with the error of `result`.
+    fn record<T>(&mut self, result: Result<T>) -> Result<T> {
+        if let Err(err) = &result {
+            if let Some(tracker) = self.tracker.take() {
+                tracker.fail(err);
+            }
+        }
+        result
+    }
+
+    pub fn checkpoint(&mut self, durable_bytes: u64) {
+        if let Some(tracker) = &mut self.tracker {
+            tracker.checkpoint(durable_bytes);
+        }
+    }
+
+    /// Descriptions of the operations run or planned, for the report.
+    pub fn operations(&self) -> &[String] {
+        &self.operations
+    }
+
+    /// Marks the ledger record completed once the report exists.
+    pub fn complete(self, report_root: &Path) -> Result<()> {
+        match self.tracker {
+            Some(tracker) => tracker.complete(report_root),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Refuses the disk holding the EFI system partition this host booted
+/// from. Unlike other system disks, neither `allow_system_disk` nor a
+/// `PHX-SYS-` token unlocks it.
+pub(crate) fn refuse_booted_esp(disk: &Disk) -> Result<()> {
+    match &disk.booted_esp {
+        Some(guid) => Err(anyhow!(
+            "Denied: {} holds the EFI system partition this host booted from ({})",
+            disk.id,
+            guid
+        )),
+        None => Ok(()),
+    }
+}
+
+/// A new GPT from `plan`, formatted and mounted; returns the mount.
+pub struct RepartitionDisk<'a> {
+    pub disk: &'a Disk,
+    pub plan: &'a PartitionPlan,
+    pub filesystem: FileSystem,
+    pub label: Option<&'a str>,
+    pub cluster_bytes: Option<u32>,
+}
+
+impl DestructiveOperation for RepartitionDisk<'_> {
+    type Output = PathBuf;
+
+    fn description(&self) -> String {
+        format!(
+            "repartition {} with {} GPT partition(s), {}",
+            self.disk.id,
+            self.plan.partitions.len(),
+            self.filesystem.as_str()
+        )
+    }
+
+    fn phase(&self) -> &'static str {
+        "partition"
+    }
+
+    fn disk_id(&self) -> Option<&str> {
+        Some(&self.disk.id)
+    }
+
+    fn execute(&mut self, _logs: &mut StepLog, _tracker: Option<&mut RunTracker>) -> Result<PathBuf> {
+        if phoenix_core::mock::is_active() {
+            return mock_repartition(self.disk, self.plan);
+        }
+        let disk_number = parse_disk_number(&self.disk.id)
+            .ok_or_else(|| anyhow!("invalid disk id {}", self.disk.id))?;
+        let mount = prepare_usb_disk(
+            disk_number,
+            self.plan,
+            self.filesystem,
+            self.label,
+            self.cluster_bytes,
+        )?;
+        Ok(normalize_mount_path(&PathBuf::from(mount)))
+    }
+
+    fn post_verify(&self, mount: &PathBuf, _logs: &mut StepLog) -> Result<()> {
+        if mount.as_os_str().is_empty() {
+            return Err(anyhow!("no mounted volume found for {}", self.disk.id));
+        }
+        Ok(())
+    }
+}
+
+/// Reformats the volume mounted at `mount` in place.
+pub struct FormatVolume<'a> {
+    pub disk_id: &'a str,
+    pub mount: &'a Path,
+    pub filesystem: FileSystem,
+    pub label: Option<&'a str>,
+    pub cluster_bytes: Option<u32>,
+}
+
+impl DestructiveOperation for FormatVolume<'_> {
+    type Output = ();
+
+    fn description(&self) -> String {
+        format!("format {} as {}", self.mount.display(), self.filesystem.as_str())
+    }
+
+    fn phase(&self) -> &'static str {
+        "format"
+    }
+
+    fn disk_id(&self) -> Option<&str> {
+        Some(self.disk_id)
+    }
+
+    fn pre_check(&self) -> Result<()> {
+        if self.mount.as_os_str().is_empty() {
+            return Err(anyhow!("no mounted volume found for {}", self.disk_id));
+        }
+        Ok(())
+    }
+
+    fn execute(&mut self, _logs: &mut StepLog, _tracker: Option<&mut RunTracker>) -> Result<()> {
+        if phoenix_core::mock::is_active() {
+            return Ok(phoenix_core::mock::format_volume(self.mount)?);
+        }
+        format_existing_volume(
+            &self.mount.display().to_string(),
+            self.filesystem,
+            self.label,
+            self.cluster_bytes,
+        )
+    }
+
+    fn post_verify(&self, _output: &(), _logs: &mut StepLog) -> Result<()> {
+        if !self.mount.is_dir() {
+            return Err(anyhow!("{} is not mounted after the format", self.mount.display()));
+        }
+        Ok(())
+    }
+}
+
+/// FAT32 on a Unix device node, directly or through udisks2, remounted at
+/// `mount`; returns the mount in use afterwards.
+pub struct FormatDevice<'a> {
+    pub disk: &'a Disk,
+    pub device: &'a Path,
+    pub mount: &'a Path,
+    /// Needed unless `udisks` formats the partition as it is.
+    pub size_bytes: Option<u64>,
+    pub label: Option<&'a str>,
+    pub cluster_bytes: Option<u64>,
+    pub udisks: bool,
+}
+
+impl DestructiveOperation for FormatDevice<'_> {
+    type Output = PathBuf;
+
+    fn description(&self) -> String {
+        let via = if self.udisks { " through udisks" } else { "" };
+        format!("format {} as FAT32{}", self.device.display(), via)
+    }
+
+    fn phase(&self) -> &'static str {
+        "format"
+    }
+
+    fn disk_id(&self) -> Option<&str> {
+        Some(&self.disk.id)
+    }
+
+    fn pre_check(&self) -> Result<()> {
+        if !self.udisks && self.size_bytes.is_none() {
+            return Err(anyhow!("format_size_bytes required when format_device set"));
+        }
+        Ok(())
+    }
+
+    fn execute(&mut self, logs: &mut StepLog, _tracker: Option<&mut RunTracker>) -> Result<PathBuf> {
+        if self.udisks {
+            let mount = if phoenix_core::mock::is_active() {
+                let file = mock_device_file(self.disk, self.device)?;
+                let size_bytes = fs::metadata(&file)?.len();
+                phoenix_fs_fat32::format_fat32(&file, size_bytes, self.label)?;
+                self.mount.to_path_buf()
+            } else {
+                udisks_format_and_mount(self.disk, self.device, self.label, logs)?
+            };
+            logs.push(format!("udisks_format={}", self.device.display()));
+            logs.push(format!("remounted={}", mount.display()));
+            return Ok(mount);
+        }
+        let size_bytes = self.size_bytes.unwrap_or_default();
+        let layout = format_target_fat32(
+            self.disk,
+            self.device,
+            size_bytes,
+            self.label,
+            self.cluster_bytes,
+            logs,
+        )?;
+        logs.push(format!("format_fat32={}", self.device.display()));
+        for warning in &layout.label_warnings {
+            logs.push(format!("label_warning={}", warning));
+        }
+        // A whole-disk format drops the old table; stale partition nodes
+        // must go before the remount.
+        reread_partitions(self.device, logs);
+        remount_formatted(self.device, self.mount)?;
+        logs.push(format!("remounted={}", self.mount.display()));
+        Ok(self.mount.to_path_buf())
+    }
+}
+
+/// What `WriteImage` wrote and how fast.
+pub struct ImageWrite {
+    pub result: phoenix_imaging::WriteResult,
+    pub chunk_size: u64,
+    pub chunk_tuning: serde_json::Value,
+    pub throughput_bytes_per_sec: u64,
+    pub partition_reread: Option<bool>,
+    /// Per-mirror statistics of a streamed source; empty for a file.
+    pub mirrors: Vec<phoenix_fetch::MirrorStats>,
+    /// The read-back when the verification policy sampled it.
+    pub sampled_verify: Option<phoenix_imaging::SampledVerify>,
+}
+
+/// An image written over the whole of `params.target_device`.
+pub struct WriteImage<'a> {
+    pub disk: &'a Disk,
+    pub params: &'a UnixWriteImageParams,
+    /// Checked against the digest of what was written.
+    pub expected_sha256: Option<&'a str>,
+    pub verify: &'a AppliedVerify,
+}
+
+impl DestructiveOperation for WriteImage<'_> {
+    type Output = ImageWrite;
+
+    fn description(&self) -> String {
+        format!(
+            "write {} to {}",
+            self.params.source_image.display(),
+            self.params.target_device.display()
+        )
+    }
+
+    fn phase(&self) -> &'static str {
+        "write_image"
+    }
+
+    fn disk_id(&self) -> Option<&str> {
+        Some(&self.disk.id)
+    }
+
+    fn execute(&mut self, logs: &mut StepLog, tracker: Option<&mut RunTracker>) -> Result<ImageWrite> {
+        let mock_device = if phoenix_core::mock::is_active() {
+            Some(phoenix_core::mock::disk_file(self.disk)?)
+        } else {
+            None
+        };
+        let (chunk_size, chunk_tuning) = resolve_chunk_size(
+            self.params.chunk_size,
+            mock_device.as_deref().unwrap_or(&self.params.target_device),
+            self.disk.size_bytes,
+            logs,
+        );
+        let write_device = match mock_device {
+            Some(file) => file,
+            None => write_device_path(self.params, chunk_size)?,
+        };
+        logs.push(format!("write_device={}", write_device.display()));
+        // A streamed source cannot be read a second time to sample it.
+        let streamed = image_url(self.params).is_some();
+        let sampled = self.verify.level == VerifyLevel::Sampled && !streamed;
+        if self.verify.level == VerifyLevel::Sampled && streamed {
+            logs.push("verify_sample_skipped=streamed source".to_string());
+        }
+        let params = UnixWriteImageParams {
+            verify: self.verify.enabled() && !sampled,
+            ..self.params.clone()
+        };
+        let mut slot = acquire_write_slot(logs, &self.disk.id, &self.params.source_image)?;
+        let mut observer = ThroughputObserver::new(tracker, logs.progress_reporter())
+            .with_write_slot(slot.as_mut(), self.verify.enabled());
+        let (mut result, mirrors) = write_target_image(
+            self.disk,
+            &params,
+            &write_device,
+            chunk_size,
+            &mut observer,
+            logs,
+        )?;
+        let throughput_bytes_per_sec = observer.finish();
+        logs.push(format!("throughput_bytes_per_sec={}", throughput_bytes_per_sec));
+        let sampled_verify = if sampled {
+            if let Some(slot) = slot.as_mut() {
+                slot.verifying();
+            }
+            let percent = self.verify.sample_percent.unwrap_or(100);
+            let sample = phoenix_imaging::verify_image_sampled(
+                &self.params.source_image,
+                &write_device,
+                chunk_size,
+                percent,
+            )?;
+            logs.push(format!(
+                "verify_sampled chunks={}/{} bytes={} mismatches={}",
+                sample.chunks_checked,
+                sample.total_chunks,
+                sample.bytes_checked,
+                sample.mismatches.len()
+            ));
+            result.verify_ok = Some(sample.passed());
+            Some(sample)
+        } else {
+            None
+        };
+        drop(slot);
+        let partition_reread = reread_partitions(&write_device, logs);
+        logs.push(format!("flushes={}", result.flushes));
+        Ok(ImageWrite {
+            result,
+            chunk_size,
+            chunk_tuning,
+            throughput_bytes_per_sec,
+            partition_reread,
+            mirrors,
+            sampled_verify,
+        })
+    }
+
+    fn post_verify(&self, written: &ImageWrite, logs: &mut StepLog) -> Result<()> {
+        if let Some(expected) = self.expected_sha256 {
+            if !written.result.sha256.eq_ignore_ascii_case(expected) {
+                return Err(anyhow!(
+                    "image sha256 {} does not match expected {}; the device holds a bad image",
+                    written.result.sha256,
+                    expected
+                ));
+            }
+            logs.push("source_sha256_ok=true".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// A WIM image applied into `target_dir`.
+pub struct ApplyImage<'a> {
+    pub image: &'a Path,
+    pub index: u32,
+    pub target_dir: &'a Path,
+    /// Expanded size of the image, when the WIM records it.
+    pub expected_bytes: Option<u64>,
+}
+
+impl DestructiveOperation for ApplyImage<'_> {
+    type Output = ();
+
+    fn description(&self) -> String {
+        format!(
+            "apply image {} of {} to {}",
+            self.index,
+            self.image.display(),
+            self.target_dir.display()
+        )
+    }
+
+    fn phase(&self) -> &'static str {
+        "apply_image"
+    }
+
+    fn disk_id(&self) -> Option<&str> {
+        None
+    }
+
+    /// Creates `target_dir` first, so free space is read on its volume.
+    fn pre_check(&self) -> Result<()> {
+        if !self.target_dir.exists() {
+            fs::create_dir_all(self.target_dir).context("create target dir")?;
+        }
+        if let Some(expected) = self.expected_bytes {
+            if let Ok(free_bytes) = free_space_bytes(&self.target_dir.display().to_string()) {
+                if free_bytes < expected {
+                    return Err(anyhow!(
+                        "insufficient free space: required {}, available {}",
+                        expected,
+                        free_bytes
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn execute(&mut self, _logs: &mut StepLog, _tracker: Option<&mut RunTracker>) -> Result<()> {
+        crate::wim_apply_image(self.image, self.index, self.target_dir)
+    }
+}
+
+/// What `MacosInstallerMedia` left on the target.
+pub struct MacosMedia {
+    /// `createinstallmedia` or `asr_restore`.
+    pub mode: &'static str,
+    pub target_volume: PathBuf,
+    /// The installer app found inside a DMG source.
+    pub installer_support: Option<InstallerSupport>,
+}
+
+/// macOS install media on `params.target_device`: `createinstallmedia`
+/// from an installer app, or from the app inside a DMG, else an `asr`
+/// restore of the DMG itself.
+pub struct MacosInstallerMedia<'a> {
+    pub disk: &'a Disk,
+    pub params: &'a MacosInstallerUsbParams,
+    pub filesystem: &'a str,
+}
+
+impl MacosInstallerMedia<'_> {
+    fn source_is_dmg(&self) -> bool {
+        self.params
+            .source_path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("dmg"))
+    }
+
+    fn create_install_media(&self, app: &Path) -> Result<PathBuf> {
+        let params = self.params;
+        erase_disk(&params.target_device, self.filesystem, &params.volume_name)?;
+        let volume = PathBuf::from(format!("/Volumes/{}", params.volume_name));
+        run_createinstallmedia(app, &volume)?;
+        Ok(volume)
+    }
+}
+
+impl DestructiveOperation for MacosInstallerMedia<'_> {
+    type Output = MacosMedia;
+
+    fn description(&self) -> String {
+        format!(
+            "write macOS installer {} to {} as {}",
+            self.params.source_path.display(),
+            self.params.target_device.display(),
+            self.filesystem
+        )
+    }
+
+    fn phase(&self) -> &'static str {
+        "installer"
+    }
+
+    fn disk_id(&self) -> Option<&str> {
+        Some(&self.disk.id)
+    }
+
+    fn pre_check(&self) -> Result<()> {
+        if !self.source_is_dmg() && !is_macos_app(&self.params.source_path) {
+            return Err(anyhow!("unsupported macos source path"));
+        }
+        Ok(())
+    }
+
+    fn execute(&mut self, logs: &mut StepLog, _tracker: Option<&mut RunTracker>) -> Result<MacosMedia> {
+        let source = &self.params.source_path;
+        if !self.source_is_dmg() {
+            return Ok(MacosMedia {
+                mode: "createinstallmedia",
+                target_volume: self.create_install_media(source)?,
+                installer_support: None,
+            });
+        }
+        let mounted = mount_dmg(source)?;
+        match find_install_app(&mounted.mount_point) {
+            Some(app) => {
+                logs.push(format!("installer_app={}", app.display()));
+                let support = check_installer_app(&app, self.params, logs)?;
+                Ok(MacosMedia {
+                    mode: "createinstallmedia",
+                    target_volume: self.create_install_media(&app)?,
+                    installer_support: Some(support),
+                })
+            }
+            None => {
+                run_asr_restore(source, &self.params.target_device)?;
+                Ok(MacosMedia {
+                    mode: "asr_restore",
+                    target_volume: PathBuf::from(format!("/Volumes/{}", self.params.volume_name)),
+                    installer_support: None,
+                })
+            }
+        }
+    }
+}
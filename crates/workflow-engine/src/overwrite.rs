@@ -0,0 +1,134 @@
+//! Heuristics run before a target is wiped. A camera card or backup drive
+//! picked by mistake needs `confirm_overwrite` on top of force and token.
+
+use phoenix_core::Disk;
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
+
+/// More regular files than this, outside installer and system folders,
+/// looks like someone's data rather than old installer media.
+pub const USER_FILE_THRESHOLD: usize = 200;
+
+const MAX_DEPTH: usize = 4;
+
+/// Label fragments used by cameras and backup tools.
+const PERSONAL_LABELS: &[&str] = &[
+    "BACKUP", "PHOTO", "PICTURE", "CAMERA", "EOS_DIGITAL", "NIKON", "CANON", "SONY", "LUMIX",
+    "GOPRO", "OLYMPUS", "FUJIFILM", "TIME MACHINE", "DOCUMENTS", "PERSONAL",
+];
+
+const PERSONAL_DIRS: &[&str] = &[
+    "DCIM", "Documents", "My Documents", "Pictures", "Photos", "Music", "Videos", "Movies",
+    "Desktop", "Downloads", "Backups.backupdb", "PRIVATE", "AVCHD",
+];
+
+/// Top-level folders of installer media; files under them are not counted.
+const INSTALLER_DIRS: &[&str] = &[
+    "boot", "efi", "sources", "support", "casper", "isolinux", "syslinux", "live", "images",
+    "System Volume Information",
+];
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct OverwriteCheck {
+    /// Mount points that were inspected.
+    pub volumes: Vec<String>,
+    /// Why the contents look like personal data; empty when they do not.
+    pub triggers: Vec<String>,
+    /// Regular files counted, capped just above `USER_FILE_THRESHOLD`.
+    pub user_files: usize,
+}
+
+/// Inspects the label and contents of every mounted partition on `disk`.
+/// Unmounted partitions cannot be inspected and are skipped.
+pub fn inspect_disk(disk: &Disk) -> OverwriteCheck {
+    let mut check = OverwriteCheck::default();
+    for partition in &disk.partitions {
+        let Some(mount) = partition.mount_points.first() else {
+            continue;
+        };
+        check.volumes.push(mount.clone());
+        inspect_volume(partition.label.as_deref(), Path::new(mount), &mut check);
+    }
+    check
+}
+
+pub fn inspect_volume(label: Option<&str>, root: &Path, check: &mut OverwriteCheck) {
+    let volume = root.display();
+    if let Some(label) = label {
+        let upper = label.to_ascii_uppercase();
+        if let Some(hit) = PERSONAL_LABELS.iter().find(|fragment| upper.contains(*fragment)) {
+            check
+                .triggers
+                .push(format!("{}: label {} contains {}", volume, label, hit));
+        }
+    }
+
+    let Ok(entries) = fs::read_dir(root) else {
+        return;
+    };
+    let mut files = 0;
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        let name = entry.file_name().to_string_lossy().to_string();
+        let Ok(kind) = entry.file_type() else {
+            continue;
+        };
+        if kind.is_dir() {
+            if let Some(dir) = PERSONAL_DIRS
+                .iter()
+                .find(|dir| dir.eq_ignore_ascii_case(&name))
+            {
+                check.triggers.push(format!("{}: top-level {}/", volume, dir));
+            }
+            if is_skipped(&name) || INSTALLER_DIRS.iter().any(|dir| dir.eq_ignore_ascii_case(&name)) {
+                continue;
+            }
+            files += count_files(&entry.path(), 1, USER_FILE_THRESHOLD + 1 - files);
+        } else if kind.is_file() && !is_skipped(&name) {
+            files += 1;
+        }
+        if files > USER_FILE_THRESHOLD {
+            break;
+        }
+    }
+    check.user_files += files;
+    if files > USER_FILE_THRESHOLD {
+        check.triggers.push(format!(
+            "{}: more than {} user files",
+            volume, USER_FILE_THRESHOLD
+        ));
+    }
+}
+
+/// Counts regular files below `dir`, stopping once `limit` is reached.
+fn count_files(dir: &Path, depth: usize, limit: usize) -> usize {
+    if depth > MAX_DEPTH {
+        return 0;
+    }
+    let Ok(entries) = fs::read_dir(dir) else {
+        return 0;
+    };
+    let mut files = 0;
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        if files >= limit {
+            break;
+        }
+        let name = entry.file_name();
+        if is_skipped(&name.to_string_lossy()) {
+            continue;
+        }
+        match entry.file_type() {
+            Ok(kind) if kind.is_dir() => {
+                files += count_files(&entry.path(), depth + 1, limit - files)
+            }
+            Ok(kind) if kind.is_file() => files += 1,
+            _ => {}
+        }
+    }
+    files
+}
+
+/// Hidden and filesystem-maintained entries (`.Trashes`, `$RECYCLE.BIN`).
+fn is_skipped(name: &str) -> bool {
+    name.starts_with('.') || name.starts_with('$')
+}
@@ -0,0 +1,466 @@
+//! Tools for pack authors: `scaffold_pack` writes a skeleton to start
+//! from, `lint_pack` checks everything a pack references without running
+//! it, and `run_pack_test` runs its workflows on the mock host, so a pack
+//! gets a red/green loop without hardware.
+
+use crate::firstboot::{render_template, FirstbootScript};
+use crate::kiosk::{present_disks, run_on_disk, StationRun};
+use crate::steplog::StepLog;
+use crate::{
+    build_device_graph, plan_workflow_definition, run_workflow_definition_with_report,
+    signing_key_from_env,
+};
+use anyhow::{anyhow, Context, Result};
+use phoenix_core::WorkflowDefinition;
+use phoenix_report::{
+    create_report_bundle_with_meta_signing_and_artifacts, ReportArtifact, ReportPaths,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const PACK_TEST_FILE: &str = "pack_test.json";
+
+/// The placeholders a kiosk, agent or pack test fills in for the disk.
+const TARGET_PLACEHOLDERS: [&str; 5] = [
+    "{target_disk}",
+    "{target_device}",
+    "{target_mount}",
+    "{target_serial}",
+    "{target_port}",
+];
+
+/// Writes a pack named `name` into `dir`: a manifest, one workflow that
+/// stages a file onto a stick, and the file. Returns the files written.
+/// Refuses a directory that already holds a manifest.
+pub fn scaffold_pack(dir: &Path, name: &str) -> Result<Vec<PathBuf>> {
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_'))
+    {
+        return Err(anyhow!("pack name may only contain letters, digits, '-' and '_': {}", name));
+    }
+    let manifest_path = dir.join("pack.json");
+    if manifest_path.exists() {
+        return Err(anyhow!("{} already exists", manifest_path.display()));
+    }
+    let workflow = format!("workflows/{}.json", name);
+    // Written as text so the files keep a readable key order.
+    let manifest = format!(
+        r#"{{
+  "schema_version": "{schema}",
+  "name": "{name}",
+  "version": "0.1.0",
+  "description": "{name} pack",
+  "workflows": ["{workflow}"],
+  "assets": "assets/"
+}}
+"#,
+        schema = phoenix_content::PACK_SCHEMA_VERSION,
+    );
+    let definition = format!(
+        r#"{{
+  "schema_version": "{schema}",
+  "name": "{name}-stage",
+  "description": "Copies the pack's readme onto the stick",
+  "steps": [
+    {{
+      "id": "stage",
+      "action": "stage_files",
+      "params": {{
+        "target_mount": "{{target_mount}}",
+        "files": [{{ "source": "assets/README.txt", "destination": "README.txt" }}],
+        "dry_run": false,
+        "force": true,
+        "confirmation_token": "PHX-PACK"
+      }}
+    }}
+  ]
+}}
+"#,
+        schema = phoenix_core::WORKFLOW_SCHEMA_VERSION,
+    );
+    let files = [
+        (manifest_path, manifest),
+        (dir.join(&workflow), definition),
+        (dir.join("assets/README.txt"), format!("Staged by the {} pack.\n", name)),
+    ];
+    let mut written = Vec::new();
+    for (path, text) in files {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).with_context(|| format!("create {}", parent.display()))?;
+        }
+        fs::write(&path, text).with_context(|| format!("write {}", path.display()))?;
+        written.push(path);
+    }
+    Ok(written)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LintLevel {
+    Error,
+    /// Runs, but probably not as meant.
+    Warning,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LintProblem {
+    pub level: LintLevel,
+    pub file: PathBuf,
+    /// Step id, when the problem is in a step.
+    pub step: Option<String>,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PackLint {
+    pub workflows: usize,
+    pub problems: Vec<LintProblem>,
+}
+
+impl PackLint {
+    pub fn errors(&self) -> usize {
+        self.problems
+            .iter()
+            .filter(|problem| problem.level == LintLevel::Error)
+            .count()
+    }
+
+    fn push(&mut self, level: LintLevel, file: &Path, step: Option<&str>, message: String) {
+        self.problems.push(LintProblem {
+            level,
+            file: file.to_path_buf(),
+            step: step.map(str::to_string),
+            message,
+        });
+    }
+}
+
+/// Checks the manifest, every workflow and what they reference, on any
+/// host: workflows plan (as `workflow-plan` does), files under the pack's
+/// `assets` directory and firstboot templates exist, templates render with
+/// their step's `vars`, `asset://` references name a `cached_assets` entry,
+/// and `{target_*}` placeholders are spelled right. Every problem is
+/// collected rather than stopping at the first.
+pub fn lint_pack(manifest_path: &Path) -> Result<PackLint> {
+    let mut lint = PackLint::default();
+    let manifest = match phoenix_content::load_pack_manifest(manifest_path) {
+        Ok(manifest) => manifest,
+        Err(err) => {
+            lint.push(LintLevel::Error, manifest_path, None, format!("{:#}", err));
+            return Ok(lint);
+        }
+    };
+    let base = manifest_path
+        .parent()
+        .ok_or_else(|| anyhow!("pack manifest has no parent directory"))?;
+    let assets_dir = manifest.assets.as_deref().map(|assets| assets.trim_end_matches('/'));
+    if let Some(assets) = assets_dir {
+        if !base.join(assets).is_dir() {
+            lint.push(
+                LintLevel::Error,
+                manifest_path,
+                None,
+                format!("assets directory {} does not exist", assets),
+            );
+        }
+    }
+    for asset in &manifest.cached_assets {
+        if !phoenix_fetch::is_url(&asset.source) && !base.join(&asset.source).is_file() {
+            lint.push(
+                LintLevel::Error,
+                manifest_path,
+                None,
+                format!("cached asset {} source {} does not exist", asset.name, asset.source),
+            );
+        }
+    }
+    let cached: BTreeSet<String> = manifest
+        .cached_assets
+        .iter()
+        .map(|asset| asset.sha256.to_ascii_lowercase())
+        .collect();
+
+    let mut names = BTreeMap::new();
+    for workflow in &manifest.workflows {
+        let path = base.join(workflow);
+        let definition = match phoenix_content::load_workflow_definition(&path) {
+            Ok(definition) => definition,
+            Err(err) => {
+                lint.push(LintLevel::Error, &path, None, format!("{:#}", err));
+                continue;
+            }
+        };
+        lint.workflows += 1;
+        if let Some(first) = names.insert(definition.name.clone(), path.clone()) {
+            lint.push(
+                LintLevel::Error,
+                &path,
+                None,
+                format!("workflow name {} is also used by {}", definition.name, first.display()),
+            );
+        }
+        if let Err(err) = plan_workflow_definition(&definition) {
+            lint.push(LintLevel::Error, &path, None, format!("{:#}", err));
+        }
+        for step in &definition.steps {
+            let mut strings = Vec::new();
+            collect_strings(&step.params, &mut strings);
+            for text in strings {
+                lint_string(&mut lint, &path, &step.id, text, base, assets_dir, &cached);
+            }
+            if step.action == "stage_firstboot" {
+                lint_templates(&mut lint, &path, &step.id, &step.params, base);
+            }
+        }
+    }
+    Ok(lint)
+}
+
+fn collect_strings<'a>(value: &'a serde_json::Value, out: &mut Vec<&'a str>) {
+    match value {
+        serde_json::Value::String(text) => out.push(text),
+        serde_json::Value::Array(items) => items.iter().for_each(|item| collect_strings(item, out)),
+        serde_json::Value::Object(map) => map.values().for_each(|item| collect_strings(item, out)),
+        _ => {}
+    }
+}
+
+fn lint_string(
+    lint: &mut PackLint,
+    path: &Path,
+    step: &str,
+    text: &str,
+    base: &Path,
+    assets_dir: Option<&str>,
+    cached: &BTreeSet<String>,
+) {
+    let mut rest = text;
+    while let Some(start) = rest.find("{target_") {
+        let placeholder = match rest[start..].find('}') {
+            Some(end) => &rest[start..start + end + 1],
+            None => &rest[start..],
+        };
+        if !TARGET_PLACEHOLDERS.contains(&placeholder) {
+            lint.push(
+                LintLevel::Error,
+                path,
+                Some(step),
+                format!("unknown placeholder {}", placeholder),
+            );
+        }
+        rest = &rest[start + placeholder.len()..];
+    }
+    if let Some(reference) = text.strip_prefix(crate::ASSET_SCHEME) {
+        let digest = reference.split('/').next().unwrap_or_default().to_ascii_lowercase();
+        if !cached.contains(&digest) {
+            lint.push(
+                LintLevel::Warning,
+                path,
+                Some(step),
+                format!("{} is not among the pack's cached_assets", text),
+            );
+        }
+    }
+    let in_assets = assets_dir.is_some_and(|assets| {
+        Path::new(text).starts_with(assets) && Path::new(text) != Path::new(assets)
+    });
+    if in_assets && !base.join(text).exists() {
+        lint.push(
+            LintLevel::Error,
+            path,
+            Some(step),
+            format!("{} does not exist in the pack", text),
+        );
+    }
+}
+
+/// Each firstboot template exists and renders with the step's `vars`.
+/// `secret://` values are not resolved; any value will do for rendering.
+fn lint_templates(
+    lint: &mut PackLint,
+    path: &Path,
+    step: &str,
+    params: &serde_json::Value,
+    base: &Path,
+) {
+    let scripts: Vec<FirstbootScript> = match params.get("scripts") {
+        Some(scripts) => serde_json::from_value(scripts.clone()).unwrap_or_default(),
+        None => Vec::new(),
+    };
+    let vars: BTreeMap<String, String> = params
+        .get("vars")
+        .and_then(|vars| serde_json::from_value(vars.clone()).ok())
+        .unwrap_or_default();
+    for script in &scripts {
+        let template = base.join(&script.template);
+        let text = match fs::read_to_string(&template) {
+            Ok(text) => text,
+            Err(err) => {
+                lint.push(
+                    LintLevel::Error,
+                    path,
+                    Some(step),
+                    format!("template {}: {}", script.template, err),
+                );
+                continue;
+            }
+        };
+        match render_template(&text, &vars) {
+            Ok((_, used)) => {
+                for unused in vars.keys().filter(|name| !used.contains(*name)) {
+                    lint.push(
+                        LintLevel::Warning,
+                        path,
+                        Some(step),
+                        format!("template {} does not use variable {}", script.template, unused),
+                    );
+                }
+            }
+            Err(err) => lint.push(
+                LintLevel::Error,
+                path,
+                Some(step),
+                format!("template {}: {}", script.template, err),
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PackTestParams {
+    pub manifest: PathBuf,
+    #[serde(default = "crate::params::default_report_base")]
+    pub report_base: PathBuf,
+}
+
+/// One workflow's run in a pack test.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PackTestCase {
+    pub workflow: String,
+    pub path: PathBuf,
+    /// The mock disk bound to `{target_*}`; `None` for a workflow without
+    /// placeholders.
+    pub disk_id: Option<String>,
+    pub report_root: Option<PathBuf>,
+    /// `None` when the workflow completed.
+    pub error: Option<String>,
+    pub duration_ms: u128,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PackTestResult {
+    pub lint: PackLint,
+    pub cases: Vec<PackTestCase>,
+    pub report: ReportPaths,
+}
+
+impl PackTestResult {
+    pub fn passed(&self) -> bool {
+        self.lint.errors() == 0 && self.cases.iter().all(|case| case.error.is_none())
+    }
+}
+
+/// Lints the pack, then runs each workflow on the mock host. A workflow
+/// using `{target_*}` placeholders runs on the first removable mock disk,
+/// as a kiosk would; others run as written. Refuses to run unless
+/// `PHOENIX_HOST=mock`, so a pack under test never touches real disks.
+/// Lint errors skip the runs.
+pub fn run_pack_test(params: &PackTestParams) -> Result<PackTestResult> {
+    if !phoenix_core::mock::is_active() {
+        return Err(anyhow!(
+            "pack tests run on the mock host only; set {}=mock",
+            phoenix_core::mock::HOST_ENV
+        ));
+    }
+    let mut logs = StepLog::new("pack-test");
+    logs.push(format!("manifest={}", params.manifest.display()));
+    logs.push(format!("sandbox={}", phoenix_core::mock::sandbox_dir().display()));
+    let lint = lint_pack(&params.manifest)?;
+    logs.push(format!(
+        "lint_errors={} lint_warnings={}",
+        lint.errors(),
+        lint.problems.len() - lint.errors()
+    ));
+    let mut cases = Vec::new();
+    if lint.errors() == 0 {
+        for (path, definition) in phoenix_content::resolve_pack_workflows(&params.manifest)? {
+            let case = run_case(&path, &definition, &params.report_base);
+            match &case.error {
+                None => logs.push(format!("passed={} duration_ms={}", case.workflow, case.duration_ms)),
+                Some(err) => logs.push(format!("failed={} error={}", case.workflow, err)),
+            }
+            cases.push(case);
+        }
+    }
+
+    let failed = cases.iter().filter(|case| case.error.is_some()).count();
+    let passed = lint.errors() == 0 && failed == 0;
+    let cases_artifact = ReportArtifact::json(
+        PACK_TEST_FILE,
+        &serde_json::json!({ "lint": lint, "cases": cases }),
+    )?;
+    let (log_text, timing) = logs.finish()?;
+    let meta = serde_json::json!({
+        "workflow": "pack-test",
+        "status": if passed { "completed" } else { "failed" },
+        "manifest": params.manifest,
+        "mock_graph": std::env::var_os(phoenix_core::mock::GRAPH_ENV)
+            .map(|graph| PathBuf::from(graph).display().to_string()),
+        "workflows": cases.len(),
+        "failed_workflows": failed,
+        "lint_errors": lint.errors(),
+        "artifacts": [&cases_artifact.name, &timing.name]
+    });
+    let graph = build_device_graph()?;
+    let report = create_report_bundle_with_meta_signing_and_artifacts(
+        &params.report_base,
+        &graph,
+        Some(meta),
+        Some(&log_text),
+        signing_key_from_env().as_deref(),
+        &[cases_artifact, timing],
+    )?;
+    Ok(PackTestResult { lint, cases, report })
+}
+
+fn run_case(path: &Path, definition: &WorkflowDefinition, report_base: &Path) -> PackTestCase {
+    let start = std::time::Instant::now();
+    let case = |disk_id: Option<String>, report_root, error| PackTestCase {
+        workflow: definition.name.clone(),
+        path: path.to_path_buf(),
+        disk_id,
+        report_root,
+        error,
+        duration_ms: start.elapsed().as_millis(),
+    };
+    let placeholders = serde_json::to_string(&definition.steps)
+        .map(|steps| TARGET_PLACEHOLDERS.iter().any(|name| steps.contains(name)))
+        .unwrap_or(false);
+    if !placeholders {
+        return match run_workflow_definition_with_report(definition, report_base.to_path_buf()) {
+            Ok(result) => case(None, Some(result.report.root), None),
+            Err(err) => case(None, None, Some(format!("{:#}", err))),
+        };
+    }
+    match present_disks().and_then(|disks| {
+        disks
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow!("the mock device graph has no removable disk"))
+    }) {
+        Ok(disk) => {
+            let StationRun {
+                disk_id,
+                report_root,
+                error,
+                ..
+            } = run_on_disk(definition, report_base, &disk);
+            case(Some(disk_id), report_root, error)
+        }
+        Err(err) => case(None, None, Some(format!("{:#}", err))),
+    }
+}
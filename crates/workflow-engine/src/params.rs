@@ -0,0 +1,106 @@
+//! Serde defaults and field formats shared by the `*Params` structs, so a
+//! step's `params` object deserializes straight into them with the same
+//! defaults the step builders apply.
+
+use phoenix_efivars::BootPosition;
+use phoenix_host_windows::format::FileSystem;
+use phoenix_imaging::ScanMode;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::ProvisioningLayout;
+
+pub(crate) fn default_true() -> bool {
+    true
+}
+
+/// Steps without `report_base` write under the run's base; on its own a
+/// params object falls back to the current directory.
+pub(crate) fn default_report_base() -> PathBuf {
+    PathBuf::from(".")
+}
+
+pub(crate) fn default_filesystem() -> FileSystem {
+    FileSystem::Fat32
+}
+
+pub(crate) fn default_scan_mode() -> ScanMode {
+    ScanMode::Read
+}
+
+pub(crate) fn default_boot_position() -> BootPosition {
+    BootPosition::First
+}
+
+pub(crate) fn default_layout() -> ProvisioningLayout {
+    ProvisioningLayout::Installer
+}
+
+pub(crate) fn default_volume_name() -> String {
+    "PHOENIX-MACOS".to_string()
+}
+
+pub(crate) fn default_boot_description() -> String {
+    "Phoenix".to_string()
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SizeValue {
+    Bytes(u64),
+    Text(String),
+}
+
+/// A byte count given as a number or a size string such as `"64K"`;
+/// written as a number.
+pub(crate) mod byte_size {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(value: &Option<u64>, serializer: S) -> Result<S::Ok, S::Error> {
+        value.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<u64>, D::Error> {
+        match Option::<SizeValue>::deserialize(deserializer)? {
+            None => Ok(None),
+            Some(SizeValue::Bytes(bytes)) => Ok(Some(bytes)),
+            Some(SizeValue::Text(text)) => phoenix_partition::parse_size(&text)
+                .map(Some)
+                .map_err(serde::de::Error::custom),
+        }
+    }
+}
+
+/// A positive byte count, or `"auto"` and `null` to probe the device.
+pub(crate) mod chunk_size {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(value: &Option<u64>, serializer: S) -> Result<S::Ok, S::Error> {
+        value.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<u64>, D::Error> {
+        match Option::<SizeValue>::deserialize(deserializer)? {
+            None => Ok(None),
+            Some(SizeValue::Text(text)) if text.eq_ignore_ascii_case("auto") => Ok(None),
+            Some(SizeValue::Bytes(size)) if size > 0 => Ok(Some(size)),
+            Some(_) => Err(serde::de::Error::custom(
+                "chunk_size must be a positive number or \"auto\"",
+            )),
+        }
+    }
+}
+
+/// A duration as whole milliseconds.
+pub(crate) mod millis {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(value: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        (value.as_millis() as u64).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        u64::deserialize(deserializer).map(Duration::from_millis)
+    }
+}
@@ -0,0 +1,98 @@
+//! Dry-run reports of a workflow plan. They are written on any host,
+//! including one that cannot execute the workflow, and record whether
+//! this one could and why not.
+
+use crate::estimate::{estimate_workflow, EstimateParams, WorkflowEstimate};
+use crate::steplog::StepLog;
+use crate::{
+    current_os, plan_workflow_definition, report_graph, require_host_support,
+    signing_key_from_env,
+};
+use anyhow::Result;
+use phoenix_core::WorkflowDefinition;
+use phoenix_report::{create_report_bundle_with_meta_signing_and_artifacts, ReportPaths};
+use phoenix_workflow_plan::WorkflowPlan;
+use serde::Serialize;
+use std::path::Path;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PlanReportResult {
+    pub report: ReportPaths,
+    pub plan: WorkflowPlan,
+    /// Why this host cannot execute the plan; `None` when it can.
+    pub host_problem: Option<String>,
+    /// How long a live run would take, from the sources on this host and
+    /// the registry's throughput. `None` when it cannot be worked out.
+    pub estimate: Option<WorkflowEstimate>,
+}
+
+/// Plans `definition` and writes the plan as a report under
+/// `report_base`. Fails only when the definition itself is invalid.
+pub fn write_plan_report(
+    definition: &WorkflowDefinition,
+    report_base: &Path,
+) -> Result<PlanReportResult> {
+    let plan = plan_workflow_definition(definition)?;
+    let host_problem = require_host_support(definition)
+        .err()
+        .map(|err| err.to_string());
+    let estimate = estimate_workflow(definition, &EstimateParams::default()).ok();
+
+    let mut logs = StepLog::new("workflow-plan");
+    logs.push(format!("workflow={}", plan.name));
+    logs.push(format!("target_os={}", plan.target_os.unwrap_or("any")));
+    for step in &plan.steps {
+        let capabilities: Vec<&str> = step.capabilities.iter().map(|need| need.name).collect();
+        logs.push(format!(
+            "step={} action={} dry_run={} capabilities={}",
+            step.id,
+            step.action,
+            step.dry_run,
+            capabilities.join(",")
+        ));
+    }
+    logs.push(format!("host_os={}", current_os()));
+    match &host_problem {
+        Some(problem) => logs.push(format!("executable_here=false reason={}", problem)),
+        None => logs.push("executable_here=true"),
+    }
+    if let Some(estimate) = &estimate {
+        logs.push(format!(
+            "estimate_total_ms={} write_bytes_per_sec={} throughput_source={}",
+            estimate.total_ms,
+            estimate.write_bytes_per_sec,
+            estimate.throughput_source.as_str()
+        ));
+    }
+    let (log_text, timing) = logs.finish()?;
+
+    let meta = serde_json::json!({
+        "workflow": "workflow-plan",
+        "status": "planned",
+        "name": plan.name,
+        "target_os": plan.target_os,
+        "steps": plan.steps,
+        "capabilities": plan.capabilities(),
+        "host_os": current_os(),
+        "executable_here": host_problem.is_none(),
+        "host_problem": host_problem,
+        "estimate": estimate
+    });
+    let report = phoenix_report::with_correlation_id(definition.correlation_id.as_deref(), || {
+        create_report_bundle_with_meta_signing_and_artifacts(
+            report_base,
+            &report_graph(),
+            Some(meta),
+            Some(&log_text),
+            signing_key_from_env().as_deref(),
+            &[timing],
+        )
+    })?;
+
+    Ok(PlanReportResult {
+        report,
+        plan,
+        host_problem,
+        estimate,
+    })
+}
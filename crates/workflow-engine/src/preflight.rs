@@ -0,0 +1,281 @@
+//! Host pre-flight for a whole workflow. Steps declare what they need
+//! under `params.requires`; those needs, the host capabilities the steps
+//! use and their external tools are merged into one checklist with a
+//! result per requirement, checked before the first step runs.
+//!
+//! ```json
+//! "requires": {
+//!   "admin": true,
+//!   "temp_free_bytes": "8G",
+//!   "binaries": ["qemu-img"],
+//!   "target_min_bytes": "16G"
+//! }
+//! ```
+
+use crate::doctor::{free_bytes, is_elevated};
+use crate::tools::{check_workflow_tools, find_program};
+use crate::{
+    build_device_graph, disk_id_from_device_path, find_disk_by_mount, host_capabilities,
+    optional_bool, optional_size, optional_string, optional_string_list, step_capabilities,
+    WorkflowDefinition, WorkflowStep,
+};
+use anyhow::{anyhow, Result};
+use phoenix_core::DeviceGraph;
+use serde::Serialize;
+use std::path::Path;
+
+const REQUIREMENT_KEYS: [&str; 4] = ["admin", "temp_free_bytes", "binaries", "target_min_bytes"];
+
+/// What one step declares under `params.requires`.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct StepRequirements {
+    /// Root, or Administrator on Windows.
+    pub admin: bool,
+    /// Free space in the temp directory.
+    pub temp_free_bytes: Option<u64>,
+    /// Programs that must be on `PATH`.
+    pub binaries: Vec<String>,
+    /// Smallest size of the step's target disk.
+    pub target_min_bytes: Option<u64>,
+}
+
+/// `params.requires` of a step, if any.
+pub fn step_requirements(step: &WorkflowStep) -> Result<Option<StepRequirements>> {
+    let requires = match step.params.get("requires") {
+        None | Some(serde_json::Value::Null) => return Ok(None),
+        Some(value) => value,
+    };
+    let parse = || -> Result<StepRequirements> {
+        let fields = requires
+            .as_object()
+            .ok_or_else(|| anyhow!("requires must be an object"))?;
+        if let Some(key) = fields.keys().find(|key| !REQUIREMENT_KEYS.contains(&key.as_str())) {
+            return Err(anyhow!("unknown requirement {}", key));
+        }
+        Ok(StepRequirements {
+            admin: optional_bool(requires, "admin", false),
+            temp_free_bytes: optional_size(requires, "temp_free_bytes")?,
+            binaries: optional_string_list(requires, "binaries")?,
+            target_min_bytes: optional_size(requires, "target_min_bytes")?,
+        })
+    };
+    parse()
+        .map(Some)
+        .map_err(|err| anyhow!("step {}: invalid requires: {}", step.id, err))
+}
+
+/// One line of the checklist, shared by every step needing it.
+#[derive(Debug, Clone, Serialize)]
+pub struct PreflightCheck {
+    /// e.g. `admin`, `temp_space`, `binary:qemu-img`, `capability:raw_write`,
+    /// `tool:diskutil` or `target_capacity:sdb`.
+    pub requirement: String,
+    pub steps: Vec<String>,
+    pub passed: bool,
+    pub detail: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct PreflightChecklist {
+    pub checks: Vec<PreflightCheck>,
+}
+
+impl PreflightChecklist {
+    pub fn passed(&self) -> bool {
+        self.checks.iter().all(|check| check.passed)
+    }
+
+    pub fn failures(&self) -> impl Iterator<Item = &PreflightCheck> {
+        self.checks.iter().filter(|check| !check.passed)
+    }
+}
+
+/// Checks everything the workflow's steps need from this host. Each
+/// requirement is checked once, against the largest amount any step asks
+/// for, and lists the steps asking.
+pub fn validate_workflow_against_host(definition: &WorkflowDefinition) -> Result<PreflightChecklist> {
+    let mut needs = Needs::default();
+    for step in &definition.steps {
+        for need in step_capabilities(step)? {
+            needs.add(
+                &format!("capability:{}", need.name),
+                &step.id,
+                Need::Capability {
+                    name: need.name,
+                    dry_run: need.dry_run,
+                },
+            );
+        }
+        let Some(requires) = step_requirements(step)? else {
+            continue;
+        };
+        if requires.admin {
+            needs.add("admin", &step.id, Need::Admin);
+        }
+        if let Some(bytes) = requires.temp_free_bytes {
+            needs.add("temp_space", &step.id, Need::TempSpace(bytes));
+        }
+        for binary in &requires.binaries {
+            needs.add(&format!("binary:{}", binary), &step.id, Need::Binary(binary.clone()));
+        }
+        if let Some(bytes) = requires.target_min_bytes {
+            let target = step_target(step).unwrap_or_else(|| "unknown".to_string());
+            needs.add(
+                &format!("target_capacity:{}", target),
+                &step.id,
+                Need::TargetCapacity { target, bytes },
+            );
+        }
+    }
+
+    let graph = build_device_graph()?;
+    let mut checklist = PreflightChecklist::default();
+    for (requirement, steps, need) in needs.0 {
+        let (passed, detail) = need.check(&graph);
+        checklist.checks.push(PreflightCheck {
+            requirement,
+            steps,
+            passed,
+            detail,
+        });
+    }
+    for tool in check_workflow_tools(definition)? {
+        let requirement = format!("tool:{}", tool.tool);
+        if let Some(check) = checklist
+            .checks
+            .iter_mut()
+            .find(|check| check.requirement == requirement)
+        {
+            if !check.steps.contains(&tool.step) {
+                check.steps.push(tool.step);
+            }
+            continue;
+        }
+        let detail = match (&tool.problem, &tool.path) {
+            (Some(problem), _) => problem.clone(),
+            (None, Some(path)) => path.display().to_string(),
+            (None, None) => String::new(),
+        };
+        checklist.checks.push(PreflightCheck {
+            requirement,
+            steps: vec![tool.step],
+            passed: tool.problem.is_none(),
+            detail,
+        });
+    }
+    Ok(checklist)
+}
+
+/// Fails with every unmet requirement at once.
+pub fn require_workflow_host(definition: &WorkflowDefinition) -> Result<PreflightChecklist> {
+    let checklist = validate_workflow_against_host(definition)?;
+    let failures: Vec<String> = checklist
+        .failures()
+        .map(|check| format!("{} ({}): {}", check.requirement, check.steps.join(", "), check.detail))
+        .collect();
+    if !failures.is_empty() {
+        return Err(anyhow!(
+            "{} pre-flight requirement(s) not met: {}",
+            failures.len(),
+            failures.join("; ")
+        ));
+    }
+    Ok(checklist)
+}
+
+#[derive(Default)]
+struct Needs(Vec<(String, Vec<String>, Need)>);
+
+impl Needs {
+    /// Adds a step to the requirement, keeping the larger of two amounts.
+    fn add(&mut self, requirement: &str, step: &str, need: Need) {
+        match self.0.iter_mut().find(|(name, _, _)| name == requirement) {
+            Some((_, steps, existing)) => {
+                if !steps.iter().any(|id| id == step) {
+                    steps.push(step.to_string());
+                }
+                existing.merge(need);
+            }
+            None => self.0.push((requirement.to_string(), vec![step.to_string()], need)),
+        }
+    }
+}
+
+enum Need {
+    Admin,
+    TempSpace(u64),
+    Binary(String),
+    TargetCapacity { target: String, bytes: u64 },
+    Capability { name: &'static str, dry_run: bool },
+}
+
+impl Need {
+    fn merge(&mut self, other: Need) {
+        match (self, other) {
+            (Need::TempSpace(bytes), Need::TempSpace(more)) => *bytes = (*bytes).max(more),
+            (Need::TargetCapacity { bytes, .. }, Need::TargetCapacity { bytes: more, .. }) => {
+                *bytes = (*bytes).max(more)
+            }
+            (Need::Capability { dry_run, .. }, Need::Capability { dry_run: other, .. }) => {
+                *dry_run = *dry_run && other
+            }
+            _ => {}
+        }
+    }
+
+    fn check(&self, graph: &DeviceGraph) -> (bool, String) {
+        match self {
+            Need::Admin => {
+                if is_elevated() || phoenix_core::mock::is_active() {
+                    (true, "running elevated".to_string())
+                } else {
+                    (false, "not running as root or Administrator".to_string())
+                }
+            }
+            Need::TempSpace(bytes) => {
+                let temp = std::env::temp_dir();
+                match free_bytes(&temp) {
+                    Some(free) => (
+                        free >= *bytes,
+                        format!("{} has {} of {} bytes free", temp.display(), free, bytes),
+                    ),
+                    None => (false, format!("could not query free space of {}", temp.display())),
+                }
+            }
+            Need::Binary(name) => match find_program(name) {
+                Some(path) => (true, path.display().to_string()),
+                None => (false, format!("{} not found on PATH", name)),
+            },
+            Need::TargetCapacity { target, bytes } => {
+                match graph.disks.iter().find(|disk| disk.id.eq_ignore_ascii_case(target)) {
+                    Some(disk) => (
+                        disk.size_bytes >= *bytes,
+                        format!("{} is {} of {} bytes", disk.id, disk.size_bytes, bytes),
+                    ),
+                    None => (false, format!("target disk {} not found", target)),
+                }
+            }
+            Need::Capability { name, dry_run } => {
+                match host_capabilities().require(name, *dry_run) {
+                    Ok(()) => (true, "available".to_string()),
+                    Err(err) => (false, err.to_string()),
+                }
+            }
+        }
+    }
+}
+
+/// Id of the disk a step writes to, from whichever target param it uses.
+fn step_target(step: &WorkflowStep) -> Option<String> {
+    let params = &step.params;
+    if let Some(id) = optional_string(params, "target_disk_id").or(optional_string(params, "disk_id"))
+    {
+        return Some(id.to_string());
+    }
+    if let Some(device) = optional_string(params, "target_device") {
+        return disk_id_from_device_path(Path::new(device));
+    }
+    let mount = optional_string(params, "target_mount")?;
+    let graph = build_device_graph().ok()?;
+    find_disk_by_mount(&graph, Path::new(mount)).map(|disk| disk.id.clone())
+}
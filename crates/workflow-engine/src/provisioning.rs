@@ -0,0 +1,408 @@
+//! Stages Windows Autopilot and provisioning packages as the
+//! `stage_provisioning` step, so machines built from the media enroll on
+//! first boot. The files are validated before anything is written.
+
+use crate::operation::DestructiveSession;
+use crate::stage::{CopyToMount, StageOverwrite, StagedFile};
+use crate::steplog::StepLog;
+use crate::{
+    build_device_graph, find_disk_by_mount, hash_file,
+    is_system_mount_path, normalize_mount_for_unix, signing_key_from_env,
+};
+use anyhow::{anyhow, Context, Result};
+use phoenix_report::{
+    create_report_bundle_with_meta_signing_and_artifacts, ReportArtifact, ReportPaths,
+};
+use phoenix_safety::SafetyContext;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const PROVISIONING_MANIFEST_FILE: &str = "provisioning_manifest.json";
+/// Windows only reads the Autopilot profile under this exact name.
+pub const AUTOPILOT_FILE_NAME: &str = "AutopilotConfigurationFile.json";
+
+/// Keys every offline Autopilot profile carries.
+const AUTOPILOT_REQUIRED: &[&str] = &[
+    "CloudAssignedTenantId",
+    "CloudAssignedTenantDomain",
+    "CloudAssignedAadServerData",
+    "CloudAssignedOobeConfig",
+    "CloudAssignedDomainJoinMethod",
+    "ZtdCorrelationId",
+    "Version",
+];
+const AUTOPILOT_INTEGERS: &[&str] = &[
+    "CloudAssignedOobeConfig",
+    "CloudAssignedDomainJoinMethod",
+    "CloudAssignedForcedEnrollment",
+    "CloudAssignedAutopilotUpdateDisabled",
+    "CloudAssignedAutopilotUpdateTimeout",
+    "Version",
+];
+const AUTOPILOT_OPTIONAL: &[&str] = &[
+    "Comment_File",
+    "CloudAssignedForcedEnrollment",
+    "CloudAssignedAutopilotUpdateDisabled",
+    "CloudAssignedAutopilotUpdateTimeout",
+    "CloudAssignedDeviceName",
+    "CloudAssignedLanguage",
+    "CloudAssignedRegion",
+];
+
+/// What the target holds, which decides where the files go.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProvisioningLayout {
+    /// Windows Setup media. The profile goes under `sources/$OEM$/$$`,
+    /// which Setup copies into `%WINDIR%`. Packages go to the media root,
+    /// where OOBE picks them up.
+    Installer,
+    /// A volume `windows_apply_image` applied Windows to. Files go straight
+    /// into `Windows/Provisioning`.
+    Applied,
+}
+
+impl ProvisioningLayout {
+    pub fn parse(value: &str) -> Result<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "installer" => Ok(Self::Installer),
+            "applied" => Ok(Self::Applied),
+            other => Err(anyhow!(
+                "unknown provisioning layout {} (expected installer or applied)",
+                other
+            )),
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Installer => "installer",
+            Self::Applied => "applied",
+        }
+    }
+
+    fn autopilot_destination(self) -> String {
+        match self {
+            Self::Installer => {
+                format!("sources/$OEM$/$$/Provisioning/Autopilot/{}", AUTOPILOT_FILE_NAME)
+            }
+            Self::Applied => format!("Windows/Provisioning/Autopilot/{}", AUTOPILOT_FILE_NAME),
+        }
+    }
+
+    fn package_destination(self, name: &str) -> String {
+        match self {
+            Self::Installer => name.to_string(),
+            Self::Applied => format!("Windows/Provisioning/Packages/{}", name),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StageProvisioningParams {
+    /// An `AutopilotConfigurationFile.json`; the file name may differ.
+    #[serde(default)]
+    pub autopilot: Option<PathBuf>,
+    /// `.ppkg` provisioning packages.
+    #[serde(default)]
+    pub packages: Vec<PathBuf>,
+    /// Media mount, or the root Windows was applied to.
+    pub target_mount: PathBuf,
+    #[serde(default = "crate::params::default_layout")]
+    pub layout: ProvisioningLayout,
+    #[serde(default)]
+    pub overwrite: StageOverwrite,
+    #[serde(default = "crate::params::default_report_base")]
+    pub report_base: PathBuf,
+    #[serde(default)]
+    pub force: bool,
+    #[serde(default)]
+    pub confirmation_token: Option<String>,
+    #[serde(default = "crate::params::default_true")]
+    pub dry_run: bool,
+}
+
+/// The parts of a validated Autopilot profile worth recording.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AutopilotProfile {
+    pub tenant_id: String,
+    pub tenant_domain: String,
+    pub correlation_id: String,
+    /// Keys Phoenix does not know; Windows may still accept them.
+    pub unknown_keys: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StageProvisioningResult {
+    pub report: ReportPaths,
+    pub files: Vec<StagedFile>,
+    pub autopilot: Option<AutopilotProfile>,
+    pub dry_run: bool,
+}
+
+/// Checks an offline Autopilot profile the way Windows reads it: plain
+/// ASCII JSON with no byte order mark, the required keys present and
+/// typed, and `CloudAssignedAadServerData` itself a JSON document.
+pub fn validate_autopilot_config(bytes: &[u8]) -> Result<AutopilotProfile> {
+    if bytes.starts_with(&[0xEF, 0xBB, 0xBF])
+        || bytes.starts_with(&[0xFF, 0xFE])
+        || bytes.starts_with(&[0xFE, 0xFF])
+    {
+        return Err(anyhow!(
+            "Autopilot profile has a byte order mark; save it as ANSI without one"
+        ));
+    }
+    if !bytes.is_ascii() {
+        return Err(anyhow!("Autopilot profile must be ANSI (ASCII) text"));
+    }
+    let value: Value =
+        serde_json::from_slice(bytes).context("Autopilot profile is not valid JSON")?;
+    let object = value
+        .as_object()
+        .ok_or_else(|| anyhow!("Autopilot profile must be a JSON object"))?;
+
+    let missing: Vec<&str> = AUTOPILOT_REQUIRED
+        .iter()
+        .copied()
+        .filter(|key| !object.contains_key(*key))
+        .collect();
+    if !missing.is_empty() {
+        return Err(anyhow!("Autopilot profile is missing {}", missing.join(", ")));
+    }
+    for key in AUTOPILOT_INTEGERS {
+        if let Some(value) = object.get(*key) {
+            if !value.is_i64() {
+                return Err(anyhow!("Autopilot profile {} must be an integer", key));
+            }
+        }
+    }
+    let string = |key: &str| -> Result<String> {
+        object
+            .get(key)
+            .and_then(Value::as_str)
+            .filter(|value| !value.trim().is_empty())
+            .map(str::to_string)
+            .ok_or_else(|| anyhow!("Autopilot profile {} must be a non-empty string", key))
+    };
+    let tenant_id = string("CloudAssignedTenantId")?;
+    let correlation_id = string("ZtdCorrelationId")?;
+    let ids = [("CloudAssignedTenantId", &tenant_id), ("ZtdCorrelationId", &correlation_id)];
+    for (key, id) in ids {
+        if !is_guid(id) {
+            return Err(anyhow!("Autopilot profile {} is not a GUID: {}", key, id));
+        }
+    }
+    let tenant_domain = string("CloudAssignedTenantDomain")?;
+    let server_data: Value = serde_json::from_str(&string("CloudAssignedAadServerData")?)
+        .context("Autopilot profile CloudAssignedAadServerData is not a JSON document")?;
+    if server_data.get("ZeroTouchConfig").and_then(Value::as_object).is_none() {
+        return Err(anyhow!(
+            "Autopilot profile CloudAssignedAadServerData has no ZeroTouchConfig object"
+        ));
+    }
+
+    let unknown_keys = object
+        .keys()
+        .filter(|key| {
+            !AUTOPILOT_REQUIRED.contains(&key.as_str()) && !AUTOPILOT_OPTIONAL.contains(&key.as_str())
+        })
+        .cloned()
+        .collect();
+    Ok(AutopilotProfile {
+        tenant_id,
+        tenant_domain,
+        correlation_id,
+        unknown_keys,
+    })
+}
+
+pub fn run_stage_provisioning(params: &StageProvisioningParams) -> Result<StageProvisioningResult> {
+    if params.autopilot.is_none() && params.packages.is_empty() {
+        return Err(anyhow!(
+            "stage_provisioning needs an Autopilot profile or at least one package"
+        ));
+    }
+    let graph = build_device_graph()?;
+    let target_mount = normalize_mount_for_unix(&params.target_mount);
+    if !target_mount.is_dir() {
+        return Err(anyhow!("target mount is invalid"));
+    }
+    let is_system_target = match params.layout {
+        ProvisioningLayout::Installer => {
+            let disk = find_disk_by_mount(&graph, &target_mount)
+                .ok_or_else(|| anyhow!("target mount not found in device graph"))?;
+            if disk.is_system_disk {
+                return Err(anyhow!("refusing to target system disk: {}", disk.id));
+            }
+            if !disk.removable {
+                return Err(anyhow!("target disk is not marked removable: {}", disk.id));
+            }
+            if !target_mount.join("setup.exe").is_file() {
+                return Err(anyhow!(
+                    "{} is not Windows Setup media (no setup.exe)",
+                    target_mount.display()
+                ));
+            }
+            false
+        }
+        ProvisioningLayout::Applied => {
+            if !target_mount.join("Windows").join("System32").is_dir() {
+                return Err(anyhow!(
+                    "{} has no applied Windows (no Windows/System32)",
+                    target_mount.display()
+                ));
+            }
+            is_system_mount_path(&target_mount, &graph)
+        }
+    };
+    if is_system_target {
+        return Err(anyhow!(
+            "refusing to stage provisioning into the running system: {}",
+            target_mount.display()
+        ));
+    }
+
+    let mut planned: Vec<(&'static str, &Path, String)> = Vec::new();
+    let mut autopilot = None;
+    if let Some(path) = &params.autopilot {
+        let bytes = fs::read(path).with_context(|| format!("read {}", path.display()))?;
+        let profile = validate_autopilot_config(&bytes)
+            .with_context(|| format!("validate {}", path.display()))?;
+        autopilot = Some(profile);
+        planned.push(("autopilot", path, params.layout.autopilot_destination()));
+    }
+    let mut names = HashSet::new();
+    for path in &params.packages {
+        let name = package_name(path)?;
+        if !names.insert(name.to_ascii_lowercase()) {
+            return Err(anyhow!("more than one package is named {}", name));
+        }
+        planned.push(("ppkg", path, params.layout.package_destination(&name)));
+    }
+
+    let mut files = Vec::new();
+    for (_, source, destination) in &planned {
+        let exists = target_mount.join(destination).exists();
+        let action = match (exists, params.overwrite) {
+            (false, _) => "copy",
+            (true, StageOverwrite::Fail) => {
+                return Err(anyhow!(
+                    "{} already exists on the target (overwrite policy is fail)",
+                    destination
+                ))
+            }
+            (true, StageOverwrite::Skip) => "skip",
+            (true, StageOverwrite::Replace) => "replace",
+        };
+        files.push(StagedFile {
+            source: source.display().to_string(),
+            destination: destination.clone(),
+            bytes: fs::metadata(source)?.len(),
+            sha256: hash_file(source)?,
+            action: action.to_string(),
+        });
+    }
+
+    let mut logs = StepLog::new("stage-provisioning");
+    logs.push(format!("target_mount={}", target_mount.display()));
+    logs.push(format!("layout={}", params.layout.as_str()));
+    logs.push(format!("overwrite={}", params.overwrite.as_str()));
+    if let Some(profile) = &autopilot {
+        logs.push(format!(
+            "autopilot tenant={} domain={} correlation={}",
+            profile.tenant_id, profile.tenant_domain, profile.correlation_id
+        ));
+        for key in &profile.unknown_keys {
+            logs.push(format!("autopilot_unknown_key={}", key));
+        }
+    }
+    let ctx = SafetyContext {
+        force_mode: params.force,
+        confirmation_token: params.confirmation_token.clone(),
+        allow_system_disk: false,
+        armed_until: phoenix_safety::armed_until(),
+    };
+    let mut session = DestructiveSession::begin(
+        "stage-provisioning",
+        None,
+        &ctx,
+        is_system_target,
+        params.dry_run,
+        &mut logs,
+    )?;
+    let mut copy = CopyToMount {
+        target_mount: &target_mount,
+        sources: planned.iter().map(|(_, source, _)| *source).collect(),
+        files: &files,
+        phase: "stage_provisioning",
+    };
+    session.perform(&mut copy, &mut logs)?;
+    if params.dry_run {
+        logs.push("dry_run=true".to_string());
+    }
+    for (file, (kind, _, _)) in files.iter().zip(&planned) {
+        logs.push(format!("{} {}={} <- {}", kind, file.action, file.destination, file.source));
+    }
+
+    let copied: Vec<&StagedFile> = files.iter().filter(|file| file.action != "skip").collect();
+    let manifest = ReportArtifact::json(PROVISIONING_MANIFEST_FILE, &files)?;
+    let (log_text, timing) = logs.finish()?;
+    let meta = serde_json::json!({
+        "workflow": "stage-provisioning",
+        "status": if params.dry_run { "dry_run" } else { "completed" },
+        "target_mount": target_mount.display().to_string(),
+        "layout": params.layout.as_str(),
+        "overwrite": params.overwrite.as_str(),
+        "autopilot": autopilot,
+        "packages": params.packages.len(),
+        "copied_files": copied.len(),
+        "copied_bytes": copied.iter().map(|file| file.bytes).sum::<u64>(),
+        "artifacts": [&manifest.name, &timing.name],
+        "dry_run": params.dry_run
+    });
+    let report = create_report_bundle_with_meta_signing_and_artifacts(
+        &params.report_base,
+        &graph,
+        Some(meta),
+        Some(&log_text),
+        signing_key_from_env().as_deref(),
+        &[manifest, timing],
+    )?;
+
+    Ok(StageProvisioningResult {
+        report,
+        files,
+        autopilot,
+        dry_run: params.dry_run,
+    })
+}
+
+/// File name of a package, which must be a non-empty `.ppkg`.
+fn package_name(path: &Path) -> Result<String> {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .ok_or_else(|| anyhow!("package {} has no file name", path.display()))?;
+    if !name.to_ascii_lowercase().ends_with(".ppkg") {
+        return Err(anyhow!("package {} is not a .ppkg file", path.display()));
+    }
+    let metadata = fs::metadata(path).with_context(|| format!("read {}", path.display()))?;
+    if !metadata.is_file() || metadata.len() == 0 {
+        return Err(anyhow!("package {} is empty or not a file", path.display()));
+    }
+    Ok(name)
+}
+
+/// `8-4-4-4-12` hex digits, with or without braces.
+fn is_guid(value: &str) -> bool {
+    let value = value.trim_start_matches('{').trim_end_matches('}');
+    let groups: Vec<&str> = value.split('-').collect();
+    groups.len() == 5
+        && groups
+            .iter()
+            .zip([8, 4, 4, 4, 12])
+            .all(|(group, len)| group.len() == len && group.chars().all(|c| c.is_ascii_hexdigit()))
+}
@@ -0,0 +1,239 @@
+//! Every disk this host has seen, kept across runs. `build_device_graph`
+//! refreshes last-seen times and attaches each disk's history; completed
+//! destructive runs bump its flash count, so a line can spot a stick that
+//! has been flashed hundreds of times. Operators can also claim a disk
+//! with a label that pickers show and that can refuse writes to it.
+
+use crate::ledger::{state_dir, write_record};
+use anyhow::{anyhow, Context, Result};
+use phoenix_core::{now_utc_rfc3339, ClaimAction, DeviceClaim, DeviceGraph, Disk, DiskHistory};
+use phoenix_safety::DeviceWear;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Serializes read-modify-write of the registry file within a process;
+/// parallel duplicate runs finish at the same time.
+static REGISTRY_LOCK: Mutex<()> = Mutex::new(());
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS))]
+pub struct DeviceRecord {
+    /// Serial, else `<friendly name>|<size>` for disks without one.
+    pub key: String,
+    pub serial: Option<String>,
+    pub friendly_name: String,
+    #[cfg_attr(feature = "ts", ts(as = "f64"))]
+    pub size_bytes: u64,
+    pub first_seen_utc: String,
+    pub last_seen_utc: String,
+    pub flash_count: u32,
+    #[serde(default)]
+    pub last_workflow: Option<String>,
+    #[serde(default)]
+    pub last_flashed_utc: Option<String>,
+    #[serde(default)]
+    #[cfg_attr(feature = "ts", ts(as = "Option<f64>"))]
+    pub best_write_bytes_per_sec: Option<u64>,
+    #[serde(default)]
+    #[cfg_attr(feature = "ts", ts(as = "Option<f64>"))]
+    pub last_write_bytes_per_sec: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "ts", ts(optional))]
+    pub claim: Option<DeviceClaim>,
+}
+
+impl DeviceRecord {
+    fn new(key: String, serial: Option<&str>, friendly_name: &str, size_bytes: u64) -> Self {
+        let now = now_utc_rfc3339();
+        Self {
+            key,
+            serial: serial.map(str::to_string),
+            friendly_name: friendly_name.to_string(),
+            size_bytes,
+            first_seen_utc: now.clone(),
+            last_seen_utc: now,
+            flash_count: 0,
+            last_workflow: None,
+            last_flashed_utc: None,
+            best_write_bytes_per_sec: None,
+            last_write_bytes_per_sec: None,
+            claim: None,
+        }
+    }
+
+    pub fn history(&self) -> DiskHistory {
+        DiskHistory {
+            first_seen_utc: self.first_seen_utc.clone(),
+            last_seen_utc: self.last_seen_utc.clone(),
+            flash_count: self.flash_count,
+            last_workflow: self.last_workflow.clone(),
+            last_flashed_utc: self.last_flashed_utc.clone(),
+            best_write_bytes_per_sec: self.best_write_bytes_per_sec,
+            last_write_bytes_per_sec: self.last_write_bytes_per_sec,
+            claim: self.claim.clone(),
+        }
+    }
+
+    pub fn wear(&self) -> DeviceWear {
+        DeviceWear {
+            flash_count: self.flash_count,
+            best_write_bytes_per_sec: self.best_write_bytes_per_sec,
+            last_write_bytes_per_sec: self.last_write_bytes_per_sec,
+        }
+    }
+}
+
+/// The wear figures of a graph disk's history.
+pub(crate) fn history_wear(history: &DiskHistory) -> DeviceWear {
+    DeviceWear {
+        flash_count: history.flash_count,
+        best_write_bytes_per_sec: history.best_write_bytes_per_sec,
+        last_write_bytes_per_sec: history.last_write_bytes_per_sec,
+    }
+}
+
+/// Registry key of a disk. Sticks without a readable serial are told
+/// apart by model and size only, so identical ones share a record.
+pub fn device_key(serial: Option<&str>, friendly_name: &str, size_bytes: u64) -> String {
+    match serial.map(str::trim).filter(|serial| !serial.is_empty()) {
+        Some(serial) => serial.to_string(),
+        None => format!("{}|{}", friendly_name, size_bytes),
+    }
+}
+
+pub(crate) fn disk_key(disk: &Disk) -> String {
+    device_key(disk.serial.as_deref(), &disk.friendly_name, disk.size_bytes)
+}
+
+/// `devices.json`: a map from device key to `DeviceRecord`.
+pub struct DeviceRegistry {
+    path: PathBuf,
+}
+
+impl DeviceRegistry {
+    pub fn open(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    /// `$PHOENIX_DEVICE_REGISTRY`, else `devices.json` in the state
+    /// directory.
+    pub fn open_default() -> Result<Self> {
+        let path = match std::env::var("PHOENIX_DEVICE_REGISTRY") {
+            Ok(path) => PathBuf::from(path),
+            Err(_) => state_dir()?.join("devices.json"),
+        };
+        Ok(Self::open(path))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Records, most recently seen first.
+    pub fn list(&self) -> Result<Vec<DeviceRecord>> {
+        let mut records: Vec<DeviceRecord> = self.load()?.into_values().collect();
+        records.sort_by(|a, b| b.last_seen_utc.cmp(&a.last_seen_utc));
+        Ok(records)
+    }
+
+    pub fn get(&self, key: &str) -> Result<Option<DeviceRecord>> {
+        Ok(self.load()?.remove(key))
+    }
+
+    /// Marks every disk in `graph` as seen now and attaches its history.
+    pub fn observe(&self, graph: &mut DeviceGraph) -> Result<()> {
+        self.update(|records| {
+            let now = now_utc_rfc3339();
+            for disk in &mut graph.disks {
+                let key = disk_key(disk);
+                let record = records.entry(key.clone()).or_insert_with(|| {
+                    DeviceRecord::new(key, disk.serial.as_deref(), &disk.friendly_name, disk.size_bytes)
+                });
+                record.friendly_name = disk.friendly_name.clone();
+                record.last_seen_utc = now.clone();
+                disk.history = Some(record.history());
+            }
+        })
+    }
+
+    /// Counts a completed destructive run of `workflow` against a disk,
+    /// with the raw write speed when the run measured one.
+    pub fn record_flash(
+        &self,
+        serial: Option<&str>,
+        friendly_name: &str,
+        size_bytes: u64,
+        workflow: &str,
+        write_bytes_per_sec: Option<u64>,
+    ) -> Result<DeviceRecord> {
+        let key = device_key(serial, friendly_name, size_bytes);
+        self.update(|records| {
+            let now = now_utc_rfc3339();
+            let record = records
+                .entry(key.clone())
+                .or_insert_with(|| DeviceRecord::new(key, serial, friendly_name, size_bytes));
+            record.last_seen_utc = now.clone();
+            record.flash_count = record.flash_count.saturating_add(1);
+            record.last_workflow = Some(workflow.to_string());
+            record.last_flashed_utc = Some(now);
+            if let Some(rate) = write_bytes_per_sec {
+                record.last_write_bytes_per_sec = Some(rate);
+                record.best_write_bytes_per_sec =
+                    Some(record.best_write_bytes_per_sec.map_or(rate, |best| best.max(rate)));
+            }
+            record.clone()
+        })
+    }
+
+    /// Labels a disk the registry has seen, or clears its label with
+    /// `None`.
+    pub fn set_claim(&self, key: &str, claim: Option<DeviceClaim>) -> Result<DeviceRecord> {
+        self.update(|records| {
+            let record = records.get_mut(key).ok_or_else(|| {
+                anyhow!(
+                    "device {} is not in the registry; connect it once so it is recorded",
+                    key
+                )
+            })?;
+            record.claim = claim;
+            Ok(record.clone())
+        })?
+    }
+
+    fn load(&self) -> Result<BTreeMap<String, DeviceRecord>> {
+        let bytes = match fs::read(&self.path) {
+            Ok(bytes) => bytes,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(BTreeMap::new()),
+            Err(err) => return Err(anyhow!("read {} failed: {}", self.path.display(), err)),
+        };
+        serde_json::from_slice(&bytes).with_context(|| format!("parse {}", self.path.display()))
+    }
+
+    fn update<T>(&self, change: impl FnOnce(&mut BTreeMap<String, DeviceRecord>) -> T) -> Result<T> {
+        let _guard = REGISTRY_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+        let mut records = self.load()?;
+        let changed = change(&mut records);
+        if let Some(parent) = self.path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            fs::create_dir_all(parent).with_context(|| format!("create {}", parent.display()))?;
+        }
+        write_record(&self.path, &records)?;
+        Ok(changed)
+    }
+}
+
+/// Why writing a disk with this claim is refused (`Err`) or goes on with a
+/// warning (`Ok(Some)`).
+pub(crate) fn claim_problem(claim: &DeviceClaim) -> Result<Option<String>, String> {
+    let mut reason = format!("claimed as \"{}\"", claim.label);
+    if let Some(notes) = claim.notes.as_deref().filter(|notes| !notes.trim().is_empty()) {
+        reason.push_str(&format!(" ({})", notes.trim()));
+    }
+    match claim.action {
+        ClaimAction::Label => Ok(None),
+        ClaimAction::Warn => Ok(Some(reason)),
+        ClaimAction::Deny => Err(reason),
+    }
+}
@@ -0,0 +1,403 @@
+//! Resizing a staged partition: shrink it to its contents plus a margin,
+//! so an image export of a finished stick carries no empty space, or grow
+//! it to fill the stick again. The file system is resized in place by
+//! phoenix-fs-fat32 and the partition entry by the partition planner;
+//! no file is moved.
+
+use crate::ledger::RunTracker;
+use crate::operation::{DestructiveOperation, DestructiveSession};
+use crate::{build_device_graph, check_target_claim, signing_key_from_env, target, StepLog};
+use anyhow::{anyhow, Context, Result};
+use phoenix_core::Disk;
+use phoenix_partition::{read_gpt, PartitionPlan, DEFAULT_SECTOR_SIZE};
+use phoenix_report::{create_report_bundle_with_meta_signing_and_artifacts, ReportPaths};
+use phoenix_safety::SafetyContext;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+/// Free space left on a shrunk volume when no margin is given.
+pub const DEFAULT_RESIZE_MARGIN: u64 = 64 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ResizeMode {
+    /// Down to the contents plus `margin`.
+    Shrink,
+    /// Up to the next partition, or the end of the stick.
+    Expand,
+}
+
+impl ResizeMode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Shrink => "shrink",
+            Self::Expand => "expand",
+        }
+    }
+}
+
+pub fn parse_resize_mode(value: &str) -> Result<ResizeMode> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "shrink" => Ok(ResizeMode::Shrink),
+        "expand" => Ok(ResizeMode::Expand),
+        other => Err(anyhow!("unknown resize mode: {} (shrink, expand)", other)),
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResizePartitionParams {
+    pub disk_id: String,
+    /// GPT partition number; the last partition when unset.
+    #[serde(default)]
+    pub partition: Option<u32>,
+    pub mode: ResizeMode,
+    /// Free space a shrink leaves; 64 MiB by default.
+    #[serde(default, with = "crate::params::byte_size")]
+    pub margin: Option<u64>,
+    #[serde(default = "crate::params::default_report_base")]
+    pub report_base: PathBuf,
+    #[serde(default)]
+    pub force: bool,
+    #[serde(default)]
+    pub confirmation_token: Option<String>,
+    #[serde(default = "crate::params::default_true")]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResizePartitionResult {
+    pub report: ReportPaths,
+    pub disk_id: String,
+    pub partition: u32,
+    pub label: String,
+    pub mode: ResizeMode,
+    pub old_bytes: u64,
+    pub new_bytes: u64,
+    /// Bytes the files take on the volume.
+    pub used_bytes: u64,
+    /// Bytes from the start of the stick to the end of its last partition;
+    /// what an image export has to copy.
+    pub image_bytes: u64,
+    pub dry_run: bool,
+}
+
+/// How far the volume on a partition can shrink and grow.
+struct VolumeSpace {
+    used_bytes: u64,
+    /// Where the last used data ends, from the start of the volume.
+    content_bytes: u64,
+    min_bytes: u64,
+    max_bytes: u64,
+}
+
+pub fn run_resize_partition(params: &ResizePartitionParams) -> Result<ResizePartitionResult> {
+    let started = Instant::now();
+    if !cfg!(target_os = "linux") {
+        return Err(anyhow!("partition resize requires linux"));
+    }
+    let graph = build_device_graph()?;
+    let disk = graph
+        .disks
+        .iter()
+        .find(|disk| disk.id.eq_ignore_ascii_case(&params.disk_id))
+        .ok_or_else(|| anyhow!("disk not found: {}", params.disk_id))?;
+    if disk.is_system_disk {
+        return Err(anyhow!("refusing to target system disk: {}", disk.id));
+    }
+    if !disk.removable {
+        return Err(anyhow!("target disk is not marked removable: {}", disk.id));
+    }
+    if let Some(reason) = target::stack_usage(&graph, disk) {
+        return Err(anyhow!(reason));
+    }
+    let device_claim = check_target_claim(disk, "resize-partition", params.dry_run)?;
+
+    let device = device_path(disk)?;
+    let mut file = fs::File::open(&device).with_context(|| format!("open {}", device.display()))?;
+    let plan = read_gpt(&mut file, disk.size_bytes, DEFAULT_SECTOR_SIZE)?;
+    drop(file);
+    let number = match params.partition {
+        Some(number) => number,
+        None => plan
+            .partitions
+            .last()
+            .map(|partition| partition.number)
+            .ok_or_else(|| anyhow!("{} has no partitions", disk.id))?,
+    };
+    let partition = plan
+        .partitions
+        .iter()
+        .find(|partition| partition.number == number)
+        .ok_or_else(|| anyhow!("{} has no partition {}", disk.id, number))?;
+    let label = partition.spec.name.clone();
+    let old_bytes = partition.length_bytes(DEFAULT_SECTOR_SIZE);
+    let space = volume_space(disk, number)?;
+    let margin = params.margin.unwrap_or(DEFAULT_RESIZE_MARGIN);
+    let wanted = match params.mode {
+        ResizeMode::Shrink => (space.content_bytes + margin).max(space.min_bytes).min(old_bytes),
+        ResizeMode::Expand => plan.max_length_bytes(number)?.min(space.max_bytes).max(old_bytes),
+    };
+    let mut resized = plan.clone();
+    let mut new_bytes = resized
+        .resize_partition(number, wanted)?
+        .length_bytes(DEFAULT_SECTOR_SIZE);
+    // Rounding up to the partition alignment can undo a small shrink.
+    let changed = match params.mode {
+        ResizeMode::Shrink => new_bytes < old_bytes,
+        ResizeMode::Expand => new_bytes > old_bytes,
+    };
+    if !changed {
+        resized = plan.clone();
+        new_bytes = old_bytes;
+    }
+    // A grown partition can end past what the FAT addresses; the volume
+    // then stops short of it.
+    let volume_bytes = new_bytes.min(space.max_bytes);
+    let image_bytes = resized
+        .partitions
+        .iter()
+        .map(|partition| (partition.last_lba + 1) * DEFAULT_SECTOR_SIZE)
+        .max()
+        .unwrap_or(0);
+
+    let mut logs = StepLog::new("resize-partition");
+    logs.push(format!("disk_id={}", disk.id));
+    logs.push(format!("partition={} label={}", number, label));
+    logs.push(format!("mode={}", params.mode.as_str()));
+    logs.push(format!("used_bytes={}", space.used_bytes));
+    logs.push(format!("content_bytes={}", space.content_bytes));
+    logs.push(format!("old_bytes={}", old_bytes));
+    logs.push(format!("new_bytes={}", new_bytes));
+    logs.push(format!("volume_bytes={}", volume_bytes));
+    logs.push(format!("image_bytes={}", image_bytes));
+    if let Some(reason) = &device_claim {
+        logs.push(format!("device_wear={}", reason));
+    }
+
+    let ctx = SafetyContext {
+        force_mode: params.force,
+        confirmation_token: params.confirmation_token.clone(),
+        allow_system_disk: false,
+        armed_until: phoenix_safety::armed_until(),
+    };
+    if !changed {
+        logs.push("unchanged=true".to_string());
+    }
+    let mut session = DestructiveSession::begin(
+        "resize-partition",
+        Some(disk),
+        &ctx,
+        disk.is_system_disk,
+        params.dry_run || !changed,
+        &mut logs,
+    )?;
+    let mut operation = ResizePartition {
+        disk,
+        device: &device,
+        plan: &resized,
+        number,
+        mode: params.mode,
+        volume_bytes,
+    };
+    if session.perform(&mut operation, &mut logs)?.is_none() && params.dry_run {
+        logs.push("dry_run=true".to_string());
+    }
+
+    let (log_text, timing) = logs.finish()?;
+    let meta = serde_json::json!({
+        "workflow": "resize-partition",
+        "status": if !changed {
+            "unchanged"
+        } else if params.dry_run {
+            "dry_run"
+        } else {
+            "completed"
+        },
+        "disk_id": disk.id,
+        "target_serial": disk.serial,
+        "partition": number,
+        "label": label,
+        "mode": params.mode,
+        "margin": margin,
+        "used_bytes": space.used_bytes,
+        "old_bytes": old_bytes,
+        "new_bytes": new_bytes,
+        "volume_bytes": volume_bytes,
+        "image_bytes": image_bytes,
+        "changed": changed,
+        "device_wear": device_claim,
+        "destructive_operations": session.operations(),
+        "dry_run": params.dry_run,
+        "duration_ms": started.elapsed().as_millis() as u64
+    });
+    let report = create_report_bundle_with_meta_signing_and_artifacts(
+        &params.report_base,
+        &graph,
+        Some(meta),
+        Some(&log_text),
+        signing_key_from_env().as_deref(),
+        &[timing],
+    )?;
+    session.complete(&report.root)?;
+
+    Ok(ResizePartitionResult {
+        report,
+        disk_id: disk.id.clone(),
+        partition: number,
+        label,
+        mode: params.mode,
+        old_bytes,
+        new_bytes,
+        used_bytes: space.used_bytes,
+        image_bytes,
+        dry_run: params.dry_run,
+    })
+}
+
+pub(crate) fn device_path(disk: &Disk) -> Result<PathBuf> {
+    if phoenix_core::mock::is_active() {
+        return Ok(phoenix_core::mock::disk_file(disk)?);
+    }
+    Ok(Path::new("/dev").join(&disk.id))
+}
+
+/// `PHOENIX_HOST=mock` volumes are sandbox directories: their files are
+/// the contents and they can grow without limit.
+fn volume_space(disk: &Disk, number: u32) -> Result<VolumeSpace> {
+    if phoenix_core::mock::is_active() {
+        let mount = phoenix_core::mock::mount_dir(&format!("{}-Partition{}", disk.id, number))?;
+        let used_bytes = crate::collect_files(&mount)?.iter().map(|entry| entry.size).sum();
+        return Ok(VolumeSpace {
+            used_bytes,
+            content_bytes: used_bytes,
+            min_bytes: used_bytes,
+            max_bytes: u64::MAX,
+        });
+    }
+    let node = partition_node(disk, number)?;
+    let mut file = fs::File::open(&node).with_context(|| format!("open {}", node.display()))?;
+    let usage = phoenix_fs_fat32::read_fat32_usage(&mut file)
+        .with_context(|| format!("{}: only FAT32 partitions can be resized", node.display()))?;
+    Ok(VolumeSpace {
+        used_bytes: usage.used_clusters * usage.cluster_bytes(),
+        content_bytes: usage.data_start_sector as u64 * usage.bytes_per_sector as u64
+            + usage.highest_used_cluster.saturating_sub(1) * usage.cluster_bytes(),
+        min_bytes: usage.min_bytes(),
+        max_bytes: usage.max_bytes(),
+    })
+}
+
+/// The device node of partition `number`, as sysfs numbers it.
+pub(crate) fn partition_node(disk: &Disk, number: u32) -> Result<PathBuf> {
+    let block = Path::new("/sys/class/block").join(&disk.id);
+    for entry in fs::read_dir(&block).with_context(|| format!("read {}", block.display()))? {
+        let entry = entry?;
+        let Ok(text) = fs::read_to_string(entry.path().join("partition")) else {
+            continue;
+        };
+        if text.trim().parse::<u32>().ok() == Some(number) {
+            return Ok(Path::new("/dev").join(entry.file_name()));
+        }
+    }
+    Err(anyhow!("{} has no device node for partition {}", disk.id, number))
+}
+
+/// Resizes the volume and rewrites the partition table. A shrink resizes
+/// the volume first and a grow the partition first, so the volume never
+/// claims space past its partition.
+struct ResizePartition<'a> {
+    disk: &'a Disk,
+    device: &'a Path,
+    plan: &'a PartitionPlan,
+    number: u32,
+    mode: ResizeMode,
+    volume_bytes: u64,
+}
+
+impl ResizePartition<'_> {
+    fn write_table(&self, logs: &mut StepLog) -> Result<()> {
+        let mut file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(self.device)
+            .with_context(|| format!("open {}", self.device.display()))?;
+        self.plan.write_gpt(&mut file)?;
+        file.sync_all()?;
+        drop(file);
+        logs.push(format!("partition_table={}", self.device.display()));
+        if !phoenix_core::mock::is_active() {
+            let names = phoenix_host_linux::reread_partition_table(self.device)?;
+            logs.push(format!("partition_reread=ok partitions={}", names.join(",")));
+        }
+        Ok(())
+    }
+
+    fn resize_volume(&self, logs: &mut StepLog) -> Result<()> {
+        if phoenix_core::mock::is_active() {
+            return Ok(());
+        }
+        let node = partition_node(self.disk, self.number)?;
+        let mut file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&node)
+            .with_context(|| format!("open {}", node.display()))?;
+        let usage = phoenix_fs_fat32::resize_fat32(&mut file, self.volume_bytes)?;
+        file.sync_all()?;
+        logs.push(format!("fat32_resized={} bytes={}", node.display(), usage.total_bytes()));
+        Ok(())
+    }
+}
+
+impl DestructiveOperation for ResizePartition<'_> {
+    type Output = ();
+
+    fn description(&self) -> String {
+        format!(
+            "{} partition {} of {} to {} bytes",
+            self.mode.as_str(),
+            self.number,
+            self.disk.id,
+            self.volume_bytes
+        )
+    }
+
+    fn phase(&self) -> &'static str {
+        "resize"
+    }
+
+    fn disk_id(&self) -> Option<&str> {
+        Some(&self.disk.id)
+    }
+
+    fn execute(&mut self, logs: &mut StepLog, _tracker: Option<&mut RunTracker>) -> Result<()> {
+        crate::unmount_target_disk(self.disk, logs)?;
+        match self.mode {
+            ResizeMode::Shrink => {
+                self.resize_volume(logs)?;
+                self.write_table(logs)
+            }
+            ResizeMode::Expand => {
+                self.write_table(logs)?;
+                self.resize_volume(logs)
+            }
+        }
+    }
+
+    fn post_verify(&self, _output: &(), _logs: &mut StepLog) -> Result<()> {
+        let mut file = fs::File::open(self.device).with_context(|| format!("open {}", self.device.display()))?;
+        let plan = read_gpt(&mut file, self.disk.size_bytes, DEFAULT_SECTOR_SIZE)?;
+        let expected = self.plan.partitions.iter().find(|partition| partition.number == self.number);
+        let found = plan.partitions.iter().find(|partition| partition.number == self.number);
+        match (expected, found) {
+            (Some(expected), Some(found)) if expected.last_lba == found.last_lba => Ok(()),
+            _ => Err(anyhow!(
+                "partition {} of {} does not end where planned after the resize",
+                self.number,
+                self.disk.id
+            )),
+        }
+    }
+}
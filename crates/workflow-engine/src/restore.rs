@@ -0,0 +1,465 @@
+//! Restore from a report: writes the disk image a report bundle carries
+//! (a clone's `disk.img.zst`) back onto a removable disk. Nothing touches
+//! the target until the bundle's signature and every manifest hash check
+//! out, and the bytes written must hash to the image the report recorded.
+
+use crate::io_limits::acquire_write_slot;
+use crate::ledger::RunTracker;
+use crate::operation::{DestructiveOperation, DestructiveSession};
+use crate::resize::device_path;
+use crate::{
+    build_device_graph, check_overwrite, check_target_claim, check_target_disk_size,
+    check_target_wear, join_reasons, reread_partitions, resolve_chunk_size, signing_key_from_env,
+    target, StepLog, ThroughputObserver,
+};
+use anyhow::{anyhow, Context, Result};
+use phoenix_core::Disk;
+use phoenix_report::{
+    create_report_bundle_with_meta_signing_and_artifacts, verify_report_bundle, FindingSeverity,
+    Manifest, ReportPaths,
+};
+use phoenix_safety::SafetyContext;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+/// `image_artifact` in a report's `run.json`: a disk image kept in the
+/// bundle.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImageArtifact {
+    /// Path inside the bundle, as listed in its manifest.
+    pub name: String,
+    /// `zstd` or `none`.
+    pub compression: String,
+    /// Size and SHA-256 of the disk image, once decompressed.
+    pub image_bytes: u64,
+    pub image_sha256: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RestoreReportParams {
+    /// The report bundle directory holding the image.
+    pub report: PathBuf,
+    pub target_disk_id: String,
+    #[serde(default = "crate::params::default_report_base")]
+    pub report_base: PathBuf,
+    #[serde(default)]
+    pub force: bool,
+    #[serde(default)]
+    pub confirmation_token: Option<String>,
+    #[serde(default)]
+    pub acknowledge_target_size: bool,
+    /// Proceed on a target past the wear limits; recorded in the report
+    /// and the audit log.
+    #[serde(default)]
+    pub acknowledge_device_wear: bool,
+    #[serde(default)]
+    pub confirm_overwrite: bool,
+    /// Restore from a bundle with no `manifest.sig`; its hashes are still
+    /// checked.
+    #[serde(default)]
+    pub allow_unsigned: bool,
+    #[serde(default = "crate::params::default_true")]
+    pub dry_run: bool,
+    /// `None` probes the target for the fastest chunk size.
+    #[serde(default, with = "crate::params::chunk_size")]
+    pub chunk_size: Option<u64>,
+    #[serde(default, with = "crate::params::byte_size")]
+    pub flush_every_bytes: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RestoreReportResult {
+    pub report: ReportPaths,
+    pub source_report: PathBuf,
+    pub source_run_id: Option<String>,
+    pub source_workflow: Option<String>,
+    /// `None` when the bundle is unsigned and `allow_unsigned` let it in.
+    pub signature_valid: Option<bool>,
+    pub entries_checked: usize,
+    pub image: ImageArtifact,
+    pub target_disk_id: String,
+    pub bytes_written: u64,
+    pub sha256: String,
+    pub verify_ok: Option<bool>,
+    pub chunk_size: u64,
+    pub throughput_bytes_per_sec: u64,
+    pub dry_run: bool,
+}
+
+pub fn run_restore_report(params: &RestoreReportParams) -> Result<RestoreReportResult> {
+    let started = Instant::now();
+    if !cfg!(unix) {
+        return Err(anyhow!("restore workflow requires linux or macos"));
+    }
+    let bundle = open_bundle(&params.report, params.allow_unsigned)?;
+    let graph = build_device_graph()?;
+    let disk = graph
+        .disks
+        .iter()
+        .find(|disk| disk.id.eq_ignore_ascii_case(&params.target_disk_id))
+        .ok_or_else(|| anyhow!("target disk not found: {}", params.target_disk_id))?;
+    if disk.is_system_disk {
+        return Err(anyhow!("refusing to restore onto system disk: {}", disk.id));
+    }
+    if !disk.removable {
+        return Err(anyhow!("target disk is not marked removable: {}", disk.id));
+    }
+    if let Some(reason) = target::stack_usage(&graph, disk) {
+        return Err(anyhow!(reason));
+    }
+    if disk.size_bytes < bundle.image.image_bytes {
+        return Err(anyhow!(
+            "target {} holds {} bytes but the image is {}",
+            disk.id,
+            disk.size_bytes,
+            bundle.image.image_bytes
+        ));
+    }
+    let target_size_acknowledged = check_target_disk_size(disk, params.acknowledge_target_size)?;
+    let device_claim = check_target_claim(disk, "restore-report", params.dry_run)?;
+    let device_wear = join_reasons(
+        device_claim,
+        check_target_wear(disk, "restore-report", params.acknowledge_device_wear, params.dry_run)?,
+    );
+    let overwrite_triggers = check_overwrite(disk, params.confirm_overwrite, params.dry_run)?;
+
+    let mut logs = StepLog::new("restore-report");
+    logs.push(format!(
+        "source_report={} run_id={} workflow={}",
+        params.report.display(),
+        bundle.run_id.as_deref().unwrap_or("-"),
+        bundle.workflow.as_deref().unwrap_or("-")
+    ));
+    logs.push(format!(
+        "signature_valid={} entries_checked={}",
+        signature_label(bundle.signature_valid),
+        bundle.entries_checked
+    ));
+    logs.push(format!(
+        "image={} compression={} image_bytes={} image_sha256={}",
+        bundle.image.name,
+        bundle.image.compression,
+        bundle.image.image_bytes,
+        bundle.image.image_sha256
+    ));
+    logs.push(format!(
+        "target_disk_id={} serial={} size_bytes={}",
+        disk.id,
+        disk.serial.as_deref().unwrap_or("-"),
+        disk.size_bytes
+    ));
+    if let Some(reason) = &target_size_acknowledged {
+        logs.push(format!("target_size_acknowledged={}", reason));
+    }
+    if let Some(reason) = &device_wear {
+        logs.push(format!("device_wear={}", reason));
+    }
+    for trigger in &overwrite_triggers {
+        logs.push(format!("overwrite_trigger={}", trigger));
+    }
+    logs.push(format!("dry_run={}", params.dry_run));
+
+    let ctx = SafetyContext {
+        force_mode: params.force,
+        confirmation_token: params.confirmation_token.clone(),
+        allow_system_disk: false,
+        armed_until: phoenix_safety::armed_until(),
+    };
+    let mut session = DestructiveSession::begin(
+        "restore-report",
+        Some(disk),
+        &ctx,
+        disk.is_system_disk,
+        params.dry_run,
+        &mut logs,
+    )?;
+    let mut restore = RestoreImage {
+        image_path: params.report.join(&bundle.image.name),
+        image: &bundle.image,
+        disk,
+        params,
+    };
+    let restored = session.perform(&mut restore, &mut logs)?;
+
+    let (log_text, timing) = logs.finish()?;
+    let (bytes_written, sha256, verify_ok, chunk_size, throughput) = match restored {
+        Some(written) => (
+            written.bytes_written,
+            written.sha256,
+            written.verify_ok,
+            written.chunk_size,
+            written.throughput_bytes_per_sec,
+        ),
+        None => (0, String::new(), None, params.chunk_size.unwrap_or_default(), 0),
+    };
+    let meta = serde_json::json!({
+        "workflow": "restore-report",
+        "status": if params.dry_run { "dry_run" } else { "completed" },
+        "source_report": params.report.display().to_string(),
+        "source_run_id": bundle.run_id,
+        "source_workflow": bundle.workflow,
+        "source_signature_valid": bundle.signature_valid,
+        "source_entries_checked": bundle.entries_checked,
+        "image": bundle.image,
+        "target_disk_id": disk.id,
+        "target_serial": disk.serial,
+        "bytes_written": bytes_written,
+        "sha256": sha256,
+        "verify_ok": verify_ok,
+        "chunk_size": chunk_size,
+        "throughput_bytes_per_sec": throughput,
+        "flush_every_bytes": params.flush_every_bytes,
+        "target_size_acknowledged": target_size_acknowledged,
+        "device_wear": device_wear,
+        "device_wear_acknowledged": params.acknowledge_device_wear,
+        "overwrite_triggers": overwrite_triggers,
+        "overwrite_confirmed": params.confirm_overwrite,
+        "destructive_operations": session.operations(),
+        "dry_run": params.dry_run,
+        "duration_ms": started.elapsed().as_millis() as u64
+    });
+    let report = create_report_bundle_with_meta_signing_and_artifacts(
+        &params.report_base,
+        &graph,
+        Some(meta),
+        Some(&log_text),
+        signing_key_from_env().as_deref(),
+        &[timing],
+    )?;
+    session.complete(&report.root)?;
+
+    Ok(RestoreReportResult {
+        report,
+        source_report: params.report.clone(),
+        source_run_id: bundle.run_id,
+        source_workflow: bundle.workflow,
+        signature_valid: bundle.signature_valid,
+        entries_checked: bundle.entries_checked,
+        image: bundle.image,
+        target_disk_id: disk.id.clone(),
+        bytes_written,
+        sha256,
+        verify_ok,
+        chunk_size,
+        throughput_bytes_per_sec: throughput,
+        dry_run: params.dry_run,
+    })
+}
+
+/// A verified bundle and the image it carries.
+pub(crate) struct VerifiedBundle {
+    pub run_id: Option<String>,
+    pub workflow: Option<String>,
+    pub signature_valid: Option<bool>,
+    pub entries_checked: usize,
+    pub image: ImageArtifact,
+}
+
+/// Verifies the bundle against `PHOENIX_SIGNING_KEY` and finds its image.
+/// The image must be a manifest entry, so the verification has already
+/// hashed it (and, for `.zst`, checked the frame).
+pub(crate) fn open_bundle(root: &Path, allow_unsigned: bool) -> Result<VerifiedBundle> {
+    if !root.is_dir() {
+        return Err(anyhow!("report bundle not found: {}", root.display()));
+    }
+    let verification = verify_report_bundle(root, signing_key_from_env().as_deref())
+        .with_context(|| format!("verify report bundle {}", root.display()))?;
+    match verification.signature_valid {
+        Some(false) => return Err(anyhow!("report bundle signature is invalid: {}", root.display())),
+        None if !allow_unsigned => {
+            return Err(anyhow!(
+                "report bundle is not signed: {}; pass allow_unsigned to restore it anyway",
+                root.display()
+            ))
+        }
+        _ => {}
+    }
+    if !verification.ok {
+        let mut problems = verification.mismatches.clone();
+        problems.extend(
+            verification
+                .findings
+                .iter()
+                .filter(|finding| finding.severity == FindingSeverity::Error)
+                .map(|finding| finding.message.clone()),
+        );
+        return Err(anyhow!(
+            "report bundle failed verification: {}",
+            problems.join("; ")
+        ));
+    }
+
+    let meta: Value = serde_json::from_slice(
+        &fs::read(root.join("run.json")).context("read run.json")?,
+    )
+    .context("parse run.json")?;
+    let image: ImageArtifact = match meta.get("image_artifact") {
+        Some(Value::Null) | None => {
+            return Err(anyhow!("report bundle holds no image artifact: {}", root.display()))
+        }
+        Some(value) => serde_json::from_value(value.clone()).context("parse image_artifact")?,
+    };
+    if !matches!(image.compression.as_str(), "zstd" | "none") {
+        return Err(anyhow!("unsupported image compression: {}", image.compression));
+    }
+    let manifest: Manifest = serde_json::from_slice(
+        &fs::read(root.join("manifest.json")).context("read manifest.json")?,
+    )
+    .context("parse manifest.json")?;
+    if !manifest.entries().iter().any(|entry| entry.path == image.name) {
+        return Err(anyhow!("image {} is not in the manifest", image.name));
+    }
+    Ok(VerifiedBundle {
+        run_id: meta.get("run_id").and_then(Value::as_str).map(str::to_string),
+        workflow: meta.get("workflow").and_then(Value::as_str).map(str::to_string),
+        signature_valid: verification.signature_valid,
+        entries_checked: verification.entries_checked,
+        image,
+    })
+}
+
+fn signature_label(valid: Option<bool>) -> &'static str {
+    match valid {
+        Some(true) => "true",
+        Some(false) => "false",
+        None => "unsigned",
+    }
+}
+
+/// The image's bytes, decompressed when it is zstd.
+pub(crate) fn image_reader(path: &Path, compression: &str) -> Result<Box<dyn Read>> {
+    let file = BufReader::new(
+        File::open(path).with_context(|| format!("open image {}", path.display()))?,
+    );
+    Ok(match compression {
+        "zstd" => Box::new(
+            zstd::stream::read::Decoder::with_buffer(file).context("start zstd image")?,
+        ),
+        _ => Box::new(file),
+    })
+}
+
+/// What `RestoreImage` wrote.
+pub struct RestoredImage {
+    pub bytes_written: u64,
+    pub sha256: String,
+    pub verify_ok: Option<bool>,
+    pub chunk_size: u64,
+    pub throughput_bytes_per_sec: u64,
+}
+
+/// The bundle's image written over the start of the target.
+pub struct RestoreImage<'a> {
+    pub image_path: PathBuf,
+    pub image: &'a ImageArtifact,
+    pub disk: &'a Disk,
+    pub params: &'a RestoreReportParams,
+}
+
+impl DestructiveOperation for RestoreImage<'_> {
+    type Output = RestoredImage;
+
+    fn description(&self) -> String {
+        format!(
+            "restore {} from {} to {}",
+            self.image.name,
+            self.params.report.display(),
+            self.disk.id
+        )
+    }
+
+    fn phase(&self) -> &'static str {
+        "restore"
+    }
+
+    fn disk_id(&self) -> Option<&str> {
+        Some(&self.disk.id)
+    }
+
+    fn execute(&mut self, logs: &mut StepLog, tracker: Option<&mut RunTracker>) -> Result<RestoredImage> {
+        let write_device = device_path(self.disk)?;
+        let total_bytes = self.image.image_bytes;
+        let (chunk_size, _) =
+            resolve_chunk_size(self.params.chunk_size, &write_device, total_bytes, logs);
+        let mut slot = acquire_write_slot(logs, &self.disk.id, &self.image_path)?;
+        let mut source = image_reader(&self.image_path, &self.image.compression)?;
+        let mut observer = ThroughputObserver::new(tracker, logs.progress_reporter())
+            .with_write_slot(slot.as_mut(), true);
+        let open = |path: &Path| {
+            fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(path)
+                .with_context(|| format!("open {}", path.display()))
+        };
+        let written = if phoenix_core::mock::is_active() {
+            let mut device = open(&write_device)?;
+            phoenix_imaging::write_stream_to_open_device(
+                &mut source, total_bytes, &mut device, chunk_size, true,
+                self.params.flush_every_bytes, &mut observer,
+            )?
+        } else {
+            #[cfg(target_os = "macos")]
+            {
+                let mut device = phoenix_host_macos::open_device_exclusive(&write_device, true)?;
+                crate::log_exclusive_open(self.disk, &device, logs);
+                phoenix_imaging::write_stream_to_open_device(
+                    &mut source, total_bytes, &mut device.file, chunk_size, true,
+                    self.params.flush_every_bytes, &mut observer,
+                )?
+            }
+            #[cfg(not(target_os = "macos"))]
+            {
+                crate::unmount_target_disk(self.disk, logs)?;
+                let mut device = open(&write_device)?;
+                phoenix_imaging::write_stream_to_open_device(
+                    &mut source, total_bytes, &mut device, chunk_size, true,
+                    self.params.flush_every_bytes, &mut observer,
+                )?
+            }
+        };
+        let throughput_bytes_per_sec = observer.finish();
+        // Anything past the recorded size means the image is not the one
+        // the report describes.
+        let trailing = io::copy(&mut source.take(1), &mut io::sink())?;
+        logs.push(format!("bytes_written={}", written.bytes_written));
+        logs.push(format!("sha256={}", written.sha256));
+        logs.push(format!("verify_ok={:?}", written.verify_ok));
+        logs.push(format!("throughput_bytes_per_sec={}", throughput_bytes_per_sec));
+        logs.push(format!("flushes={}", written.flushes));
+        drop(slot);
+        reread_partitions(&write_device, logs);
+        if trailing > 0 {
+            return Err(anyhow!(
+                "image {} is longer than the {} bytes its report records",
+                self.image.name,
+                total_bytes
+            ));
+        }
+        Ok(RestoredImage {
+            bytes_written: written.bytes_written,
+            sha256: written.sha256,
+            verify_ok: written.verify_ok,
+            chunk_size,
+            throughput_bytes_per_sec,
+        })
+    }
+
+    fn post_verify(&self, restored: &RestoredImage, _logs: &mut StepLog) -> Result<()> {
+        if !restored.sha256.eq_ignore_ascii_case(&self.image.image_sha256) {
+            return Err(anyhow!(
+                "restored image hashes to {} but the report records {}",
+                restored.sha256,
+                self.image.image_sha256
+            ));
+        }
+        if restored.verify_ok == Some(false) {
+            return Err(anyhow!("read-back of {} does not match the image", self.disk.id));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,139 @@
+//! `secret://<name>` references in workflow step params, resolved through a
+//! `KeyProvider` when the step runs. The definition only ever holds the
+//! reference; the resolved value is registered with the report layer,
+//! which redacts it from logs, `run.json` and artifacts.
+
+use crate::ledger::state_dir;
+use anyhow::{anyhow, Context, Result};
+use serde_json::Value;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+
+pub const SECRET_SCHEME: &str = "secret://";
+/// Prefix of the environment variables `EnvKeyProvider` reads.
+pub const SECRET_ENV_PREFIX: &str = "PHOENIX_SECRET_";
+
+/// Where secret values come from.
+pub trait KeyProvider: Send + Sync {
+    /// The secret called `name`, or `None` when this provider has no such
+    /// secret.
+    fn secret(&self, name: &str) -> Result<Option<String>>;
+}
+
+/// `secret://wifi-psk` reads `PHOENIX_SECRET_WIFI_PSK`.
+#[derive(Debug, Clone, Default)]
+pub struct EnvKeyProvider;
+
+impl KeyProvider for EnvKeyProvider {
+    fn secret(&self, name: &str) -> Result<Option<String>> {
+        let var: String = name
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+            .collect();
+        Ok(std::env::var(format!("{}{}", SECRET_ENV_PREFIX, var)).ok())
+    }
+}
+
+/// One file per secret, named after it; a trailing newline is dropped.
+/// On Unix, files readable by group or others are refused.
+#[derive(Debug, Clone)]
+pub struct DirKeyProvider {
+    pub dir: PathBuf,
+}
+
+impl DirKeyProvider {
+    /// `$PHOENIX_SECRETS_DIR`, else `secrets/` in the state directory.
+    pub fn default_dir() -> Result<Self> {
+        let dir = match std::env::var("PHOENIX_SECRETS_DIR") {
+            Ok(dir) => PathBuf::from(dir),
+            Err(_) => state_dir()?.join("secrets"),
+        };
+        Ok(Self { dir })
+    }
+}
+
+impl KeyProvider for DirKeyProvider {
+    fn secret(&self, name: &str) -> Result<Option<String>> {
+        let path = self.dir.join(name);
+        let metadata = match std::fs::metadata(&path) {
+            Ok(metadata) => metadata,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(anyhow!("read secret {} failed: {}", path.display(), err)),
+        };
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            if metadata.permissions().mode() & 0o077 != 0 {
+                return Err(anyhow!(
+                    "secret file {} is readable by other users; chmod 600 it",
+                    path.display()
+                ));
+            }
+        }
+        #[cfg(not(unix))]
+        let _ = metadata;
+        let value = std::fs::read_to_string(&path)
+            .with_context(|| format!("read secret {}", path.display()))?;
+        let value = value.strip_suffix('\n').unwrap_or(&value);
+        Ok(Some(value.strip_suffix('\r').unwrap_or(value).to_string()))
+    }
+}
+
+static PROVIDER: RwLock<Option<Arc<dyn KeyProvider>>> = RwLock::new(None);
+
+/// Replaces the default lookup (environment, then the secrets directory)
+/// for the rest of the process; `None` restores it.
+pub fn set_key_provider(provider: Option<Arc<dyn KeyProvider>>) {
+    if let Ok(mut current) = PROVIDER.write() {
+        *current = provider;
+    }
+}
+
+/// Resolves `name` and registers the value for redaction.
+pub fn resolve_secret(name: &str) -> Result<String> {
+    if name.is_empty()
+        || name.starts_with('.')
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+    {
+        return Err(anyhow!(
+            "secret name may only contain letters, digits, '.', '_' and '-': {}",
+            name
+        ));
+    }
+    let provider = PROVIDER.read().ok().and_then(|current| current.clone());
+    let value = match provider {
+        Some(provider) => provider.secret(name)?,
+        None => match EnvKeyProvider.secret(name)? {
+            Some(value) => Some(value),
+            None => DirKeyProvider::default_dir()?.secret(name)?,
+        },
+    };
+    let value = value.ok_or_else(|| anyhow!("secret not found: {}", name))?;
+    phoenix_report::register_secret(&value);
+    Ok(value)
+}
+
+/// `params` with every string that is exactly `secret://<name>` replaced
+/// by the secret's value.
+pub(crate) fn resolve_secret_params(params: &Value) -> Result<Value> {
+    Ok(match params {
+        Value::String(text) => match text.strip_prefix(SECRET_SCHEME) {
+            Some(name) => Value::String(resolve_secret(name)?),
+            None => params.clone(),
+        },
+        Value::Array(items) => Value::Array(
+            items
+                .iter()
+                .map(resolve_secret_params)
+                .collect::<Result<_>>()?,
+        ),
+        Value::Object(map) => Value::Object(
+            map.iter()
+                .map(|(key, value)| Ok((key.clone(), resolve_secret_params(value)?)))
+                .collect::<Result<_>>()?,
+        ),
+        _ => params.clone(),
+    })
+}
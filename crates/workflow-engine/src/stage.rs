@@ -0,0 +1,381 @@
+//! Copies extra files (tools, scripts, documentation) onto finished media
+//! as the `stage_files` step, so packs do not need to route them through
+//! `driver_source`.
+
+use crate::filter::match_path;
+use crate::ledger::RunTracker;
+use crate::operation::{DestructiveOperation, DestructiveSession};
+use crate::steplog::StepLog;
+use crate::{
+    build_device_graph, collect_files, copy_file_with_mtime, find_disk_by_mount, hash_file,
+    normalize_mount_for_unix, signing_key_from_env,
+};
+use anyhow::{anyhow, Result};
+use phoenix_report::{
+    create_report_bundle_with_meta_signing_and_artifacts, ReportArtifact, ReportPaths,
+};
+use phoenix_safety::SafetyContext;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+pub const STAGE_MANIFEST_FILE: &str = "staged_files_manifest.json";
+
+/// One `source` → `destination` mapping. `source` is a file, a directory
+/// (copied recursively) or a glob with `*`, `?` and `**`; `destination` is
+/// relative to the target mount.
+///
+/// ```json
+/// { "source": "pack/tools/**/*.exe", "destination": "Tools/" }
+/// ```
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct StageFileRule {
+    pub source: String,
+    #[serde(default)]
+    pub destination: String,
+}
+
+impl StageFileRule {
+    /// Parses the CLI form `SOURCE=DESTINATION`.
+    pub fn parse(value: &str) -> Result<Self> {
+        let (source, destination) = value
+            .split_once('=')
+            .ok_or_else(|| anyhow!("file rule {} must be SOURCE=DESTINATION", value))?;
+        if source.trim().is_empty() {
+            return Err(anyhow!("file rule {} has an empty source", value));
+        }
+        Ok(Self {
+            source: source.trim().to_string(),
+            destination: destination.trim().to_string(),
+        })
+    }
+}
+
+/// What to do when a destination file already exists on the target.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StageOverwrite {
+    /// Refuse the whole step before anything is copied.
+    #[default]
+    Fail,
+    /// Keep the existing file.
+    Skip,
+    Replace,
+}
+
+impl StageOverwrite {
+    pub fn parse(value: &str) -> Result<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "fail" => Ok(Self::Fail),
+            "skip" => Ok(Self::Skip),
+            "replace" => Ok(Self::Replace),
+            other => Err(anyhow!(
+                "unknown overwrite policy {} (expected fail, skip or replace)",
+                other
+            )),
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Fail => "fail",
+            Self::Skip => "skip",
+            Self::Replace => "replace",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StageFilesParams {
+    #[serde(rename = "files")]
+    pub rules: Vec<StageFileRule>,
+    pub target_mount: PathBuf,
+    #[serde(default)]
+    pub overwrite: StageOverwrite,
+    #[serde(default = "crate::params::default_report_base")]
+    pub report_base: PathBuf,
+    #[serde(default)]
+    pub force: bool,
+    #[serde(default)]
+    pub confirmation_token: Option<String>,
+    #[serde(default = "crate::params::default_true")]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StagedFile {
+    pub source: String,
+    /// Path on the target, relative to the mount, with `/` separators.
+    pub destination: String,
+    pub bytes: u64,
+    pub sha256: String,
+    /// `copy`, `replace` or `skip`.
+    pub action: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StageFilesResult {
+    pub report: ReportPaths,
+    pub files: Vec<StagedFile>,
+    pub copied_files: usize,
+    pub copied_bytes: u64,
+    pub skipped_files: usize,
+    pub dry_run: bool,
+}
+
+pub fn run_stage_files(params: &StageFilesParams) -> Result<StageFilesResult> {
+    if params.rules.is_empty() {
+        return Err(anyhow!("stage_files needs at least one file rule"));
+    }
+    let graph = build_device_graph()?;
+    let target_mount = normalize_mount_for_unix(&params.target_mount);
+    if !target_mount.is_dir() {
+        return Err(anyhow!("target mount is invalid"));
+    }
+    let disk = find_disk_by_mount(&graph, &target_mount)
+        .ok_or_else(|| anyhow!("target mount not found in device graph"))?;
+    if disk.is_system_disk {
+        return Err(anyhow!("refusing to target system disk: {}", disk.id));
+    }
+    if !disk.removable {
+        return Err(anyhow!("target disk is not marked removable: {}", disk.id));
+    }
+
+    // Plan everything first so a conflict under `fail` leaves the target
+    // untouched.
+    let mut planned = Vec::new();
+    let mut destinations = HashSet::new();
+    for rule in &params.rules {
+        for (source, destination) in resolve_rule(rule)? {
+            if !destinations.insert(destination.clone()) {
+                return Err(anyhow!("more than one file is staged to {}", destination));
+            }
+            planned.push((source, destination));
+        }
+    }
+
+    let mut files = Vec::new();
+    for (source, destination) in &planned {
+        let exists = target_mount.join(destination).exists();
+        let action = match (exists, params.overwrite) {
+            (false, _) => "copy",
+            (true, StageOverwrite::Fail) => {
+                return Err(anyhow!(
+                    "{} already exists on the target (overwrite policy is fail)",
+                    destination
+                ))
+            }
+            (true, StageOverwrite::Skip) => "skip",
+            (true, StageOverwrite::Replace) => "replace",
+        };
+        files.push(StagedFile {
+            source: source.display().to_string(),
+            destination: destination.clone(),
+            bytes: fs::metadata(source)?.len(),
+            sha256: hash_file(source)?,
+            action: action.to_string(),
+        });
+    }
+
+    let mut logs = StepLog::new("stage-files");
+    logs.push(format!("target_mount={}", target_mount.display()));
+    logs.push(format!("overwrite={}", params.overwrite.as_str()));
+    let ctx = SafetyContext {
+        force_mode: params.force,
+        confirmation_token: params.confirmation_token.clone(),
+        allow_system_disk: false,
+        armed_until: phoenix_safety::armed_until(),
+    };
+    let mut session = DestructiveSession::begin(
+        "stage-files",
+        None,
+        &ctx,
+        disk.is_system_disk,
+        params.dry_run,
+        &mut logs,
+    )?;
+    let mut copy = CopyToMount {
+        target_mount: &target_mount,
+        sources: planned.iter().map(|(source, _)| source.as_path()).collect(),
+        files: &files,
+        phase: "stage_files",
+    };
+    session.perform(&mut copy, &mut logs)?;
+    if params.dry_run {
+        logs.push("dry_run=true".to_string());
+    }
+    for file in &files {
+        logs.push(format!("{}={} <- {}", file.action, file.destination, file.source));
+    }
+
+    let copied: Vec<&StagedFile> = files.iter().filter(|file| file.action != "skip").collect();
+    let copied_files = copied.len();
+    let copied_bytes = copied.iter().map(|file| file.bytes).sum();
+    let skipped_files = files.len() - copied_files;
+    let manifest = ReportArtifact::json(STAGE_MANIFEST_FILE, &files)?;
+    let (log_text, timing) = logs.finish()?;
+    let meta = serde_json::json!({
+        "workflow": "stage-files",
+        "status": if params.dry_run { "dry_run" } else { "completed" },
+        "target_mount": target_mount.display().to_string(),
+        "overwrite": params.overwrite.as_str(),
+        "copied_files": copied_files,
+        "copied_bytes": copied_bytes,
+        "skipped_files": skipped_files,
+        "artifacts": [&manifest.name, &timing.name],
+        "dry_run": params.dry_run
+    });
+    let artifacts = [manifest, timing];
+    let report = create_report_bundle_with_meta_signing_and_artifacts(
+        &params.report_base,
+        &graph,
+        Some(meta),
+        Some(&log_text),
+        signing_key_from_env().as_deref(),
+        &artifacts,
+    )?;
+
+    Ok(StageFilesResult {
+        report,
+        files,
+        copied_files,
+        copied_bytes,
+        skipped_files,
+        dry_run: params.dry_run,
+    })
+}
+
+/// Copies planned files onto a mounted target, leaving `skip` entries
+/// alone. Shared with `stage_provisioning`.
+pub(crate) struct CopyToMount<'a> {
+    pub target_mount: &'a Path,
+    /// Source of each entry of `files`, in the same order.
+    pub sources: Vec<&'a Path>,
+    pub files: &'a [StagedFile],
+    pub phase: &'static str,
+}
+
+impl DestructiveOperation for CopyToMount<'_> {
+    type Output = ();
+
+    fn description(&self) -> String {
+        let copies = self.files.iter().filter(|file| file.action != "skip").count();
+        format!("copy {} file(s) to {}", copies, self.target_mount.display())
+    }
+
+    fn phase(&self) -> &'static str {
+        self.phase
+    }
+
+    fn disk_id(&self) -> Option<&str> {
+        None
+    }
+
+    fn execute(&mut self, _logs: &mut StepLog, _tracker: Option<&mut RunTracker>) -> Result<()> {
+        for (file, source) in self.files.iter().zip(&self.sources) {
+            if file.action == "skip" {
+                continue;
+            }
+            let dest = self.target_mount.join(&file.destination);
+            if let Some(parent) = dest.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            copy_file_with_mtime(source, &dest)?;
+        }
+        Ok(())
+    }
+}
+
+/// Source files for `rule` paired with their destination on the target.
+/// A rule that matches nothing is an error, so a typo in a pack does not
+/// silently ship media without its tools.
+fn resolve_rule(rule: &StageFileRule) -> Result<Vec<(PathBuf, String)>> {
+    let destination = normalize_destination(&rule.destination)?;
+    let source = rule.source.replace('\\', "/");
+    let (base, pattern) = split_glob(&source);
+    let mut resolved = Vec::new();
+    if pattern.is_empty() {
+        if base.is_file() {
+            // A trailing `/` (or an empty destination) names a directory.
+            let dest = if rule.destination.is_empty() || rule.destination.ends_with(['/', '\\']) {
+                join_destination(&destination, &file_name(&base))
+            } else {
+                destination
+            };
+            resolved.push((base, dest));
+        } else if base.is_dir() {
+            for entry in collect_files(&base)? {
+                let relative = slash_path(&entry.relative_path);
+                resolved.push((entry.absolute_path, join_destination(&destination, &relative)));
+            }
+        } else {
+            return Err(anyhow!("source {} not found", rule.source));
+        }
+    } else if base.is_dir() {
+        for entry in collect_files(&base)? {
+            let relative = slash_path(&entry.relative_path);
+            let parts: Vec<&str> = relative.split('/').collect();
+            if match_path(&pattern, &parts) {
+                resolved.push((entry.absolute_path, join_destination(&destination, &relative)));
+            }
+        }
+    }
+    if resolved.is_empty() {
+        return Err(anyhow!("source {} matched no files", rule.source));
+    }
+    resolved.sort();
+    Ok(resolved)
+}
+
+/// Splits a glob into the literal directory before the first wildcard and
+/// the remaining components.
+fn split_glob(source: &str) -> (PathBuf, Vec<&str>) {
+    let parts: Vec<&str> = source.split('/').collect();
+    let wildcard = parts
+        .iter()
+        .position(|part| part.contains(['*', '?']))
+        .unwrap_or(parts.len());
+    let mut base = parts[..wildcard].join("/");
+    if base.is_empty() {
+        base = if source.starts_with('/') { "/" } else { "." }.to_string();
+    }
+    let pattern = parts[wildcard..].iter().copied().filter(|part| !part.is_empty()).collect();
+    (PathBuf::from(base), pattern)
+}
+
+/// Destinations stay inside the target mount: relative, without `..`.
+pub(crate) fn normalize_destination(destination: &str) -> Result<String> {
+    let mut parts = Vec::new();
+    for component in Path::new(&destination.replace('\\', "/")).components() {
+        match component {
+            Component::Normal(part) => parts.push(part.to_string_lossy().to_string()),
+            Component::CurDir => {}
+            _ => {
+                return Err(anyhow!(
+                    "destination {} must be relative to the target and not use ..",
+                    destination
+                ))
+            }
+        }
+    }
+    Ok(parts.join("/"))
+}
+
+fn join_destination(dir: &str, relative: &str) -> String {
+    if dir.is_empty() {
+        relative.to_string()
+    } else {
+        format!("{}/{}", dir, relative)
+    }
+}
+
+fn slash_path(path: &Path) -> String {
+    path.to_string_lossy().replace('\\', "/")
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
@@ -0,0 +1,263 @@
+//! What each target filesystem accepts from a staging run: the largest
+//! file it stores, which names survive it, and which file attributes it
+//! keeps. Workflows pick a backend from the filesystem they format or the
+//! one the device graph reports for the target mount, and check the
+//! collected files against it before anything is written.
+
+use crate::normalize_mount_for_unix;
+use phoenix_core::Disk;
+use phoenix_host_windows::format::FileSystem;
+use std::collections::HashSet;
+use std::path::{Component, Path, PathBuf};
+
+pub trait StagingBackend: Send + Sync {
+    /// Name used in logs and reports.
+    fn name(&self) -> &'static str;
+
+    /// Largest file the filesystem stores; `None` when no source file can
+    /// reach the limit.
+    fn max_file_bytes(&self) -> Option<u64>;
+
+    /// Paths that cannot be created as named, or that collide with another
+    /// one on this filesystem.
+    fn path_warnings(&self, paths: &mut dyn Iterator<Item = &Path>) -> Vec<String>;
+
+    /// Whether Unix permission bits are kept.
+    fn keeps_unix_modes(&self) -> bool;
+
+    /// Whether hardlinks can be created; see `dedupe`.
+    fn supports_hardlinks(&self) -> bool;
+
+    /// Why a file of `max_file` bytes cannot be staged, if it cannot.
+    fn file_size_problem(&self, max_file: u64) -> Option<String> {
+        let limit = self.max_file_bytes()?;
+        (max_file > limit).then(|| {
+            format!(
+                "{} cannot store files larger than {} bytes (max file {} bytes)",
+                self.name(),
+                limit,
+                max_file
+            )
+        })
+    }
+}
+
+pub struct Fat32Staging;
+pub struct NtfsStaging;
+pub struct ExFatStaging;
+pub struct Ext4Staging;
+/// A whole-device image written block by block; it carries its own
+/// filesystem, so nothing about its files is checked.
+pub struct RawImageStaging;
+
+const FAT32_MAX_FILE: u64 = 4_294_967_295;
+const EXT4_MAX_FILE: u64 = 16 * 1024 * 1024 * 1024 * 1024;
+
+impl StagingBackend for Fat32Staging {
+    fn name(&self) -> &'static str {
+        "FAT32"
+    }
+
+    fn max_file_bytes(&self) -> Option<u64> {
+        Some(FAT32_MAX_FILE)
+    }
+
+    fn path_warnings(&self, paths: &mut dyn Iterator<Item = &Path>) -> Vec<String> {
+        phoenix_fs_fat32::fat_path_warnings(paths)
+    }
+
+    fn keeps_unix_modes(&self) -> bool {
+        false
+    }
+
+    fn supports_hardlinks(&self) -> bool {
+        false
+    }
+
+    fn file_size_problem(&self, max_file: u64) -> Option<String> {
+        (max_file > FAT32_MAX_FILE).then(|| {
+            format!(
+                "FAT32 cannot store files > 4GB (max file {} bytes). Use NTFS/exFAT.",
+                max_file
+            )
+        })
+    }
+}
+
+impl StagingBackend for NtfsStaging {
+    fn name(&self) -> &'static str {
+        "NTFS"
+    }
+
+    fn max_file_bytes(&self) -> Option<u64> {
+        None
+    }
+
+    fn path_warnings(&self, paths: &mut dyn Iterator<Item = &Path>) -> Vec<String> {
+        windows_path_warnings(paths)
+    }
+
+    fn keeps_unix_modes(&self) -> bool {
+        false
+    }
+
+    fn supports_hardlinks(&self) -> bool {
+        true
+    }
+}
+
+impl StagingBackend for ExFatStaging {
+    fn name(&self) -> &'static str {
+        "exFAT"
+    }
+
+    fn max_file_bytes(&self) -> Option<u64> {
+        None
+    }
+
+    fn path_warnings(&self, paths: &mut dyn Iterator<Item = &Path>) -> Vec<String> {
+        windows_path_warnings(paths)
+    }
+
+    fn keeps_unix_modes(&self) -> bool {
+        false
+    }
+
+    fn supports_hardlinks(&self) -> bool {
+        false
+    }
+}
+
+impl StagingBackend for Ext4Staging {
+    fn name(&self) -> &'static str {
+        "ext4"
+    }
+
+    /// With 4 KiB blocks.
+    fn max_file_bytes(&self) -> Option<u64> {
+        Some(EXT4_MAX_FILE)
+    }
+
+    fn path_warnings(&self, paths: &mut dyn Iterator<Item = &Path>) -> Vec<String> {
+        let mut warnings = Vec::new();
+        for path in paths {
+            for part in normal_components(path) {
+                if part.len() > 255 {
+                    warnings.push(format!("{}: name is longer than 255 bytes", display(path)));
+                }
+            }
+        }
+        warnings
+    }
+
+    fn keeps_unix_modes(&self) -> bool {
+        true
+    }
+
+    fn supports_hardlinks(&self) -> bool {
+        true
+    }
+}
+
+impl StagingBackend for RawImageStaging {
+    fn name(&self) -> &'static str {
+        "raw"
+    }
+
+    fn max_file_bytes(&self) -> Option<u64> {
+        None
+    }
+
+    fn path_warnings(&self, _paths: &mut dyn Iterator<Item = &Path>) -> Vec<String> {
+        Vec::new()
+    }
+
+    fn keeps_unix_modes(&self) -> bool {
+        true
+    }
+
+    fn supports_hardlinks(&self) -> bool {
+        true
+    }
+}
+
+/// The backend for a filesystem the engine formats.
+pub fn staging_backend(filesystem: FileSystem) -> &'static dyn StagingBackend {
+    match filesystem {
+        FileSystem::Fat32 => &Fat32Staging,
+        FileSystem::Ntfs => &NtfsStaging,
+        FileSystem::ExFat => &ExFatStaging,
+    }
+}
+
+/// The backend for a filesystem name as the device graph or a step param
+/// gives it (`vfat`, `ntfs3`, `ext4`, `raw`); `None` for filesystems
+/// without one, which are staged unchecked.
+pub fn staging_backend_named(name: &str) -> Option<&'static dyn StagingBackend> {
+    let backend: &'static dyn StagingBackend = match name.trim().to_ascii_lowercase().as_str() {
+        "fat32" | "vfat" | "msdos" | "fat" => &Fat32Staging,
+        "ntfs" | "ntfs3" => &NtfsStaging,
+        "exfat" => &ExFatStaging,
+        "ext4" | "ext3" | "ext2" => &Ext4Staging,
+        "raw" => &RawImageStaging,
+        _ => return None,
+    };
+    Some(backend)
+}
+
+/// The backend for the filesystem mounted at `mount` on `disk`.
+pub fn staging_backend_for_mount(disk: &Disk, mount: &Path) -> Option<&'static dyn StagingBackend> {
+    let mount = normalize_mount_for_unix(mount);
+    disk.partitions
+        .iter()
+        .find(|partition| {
+            partition
+                .mount_points
+                .iter()
+                .any(|mp| normalize_mount_for_unix(&PathBuf::from(mp)) == mount)
+        })
+        .and_then(|partition| partition.fs.as_deref())
+        .and_then(staging_backend_named)
+}
+
+/// Win32 naming rules, which NTFS and exFAT volumes are written under:
+/// no `"*:<>?\|` or control characters, no trailing dot or space, at most
+/// 255 UTF-16 units, and names compared without case.
+fn windows_path_warnings(paths: &mut dyn Iterator<Item = &Path>) -> Vec<String> {
+    let mut warnings = Vec::new();
+    let mut seen = HashSet::new();
+    for path in paths {
+        let display = display(path);
+        for part in normal_components(path) {
+            let Some(name) = part.to_str() else {
+                warnings.push(format!("{}: name is not valid Unicode", display));
+                continue;
+            };
+            if let Some(bad) = name
+                .chars()
+                .find(|c| c.is_control() || "\"*:<>?\\|".contains(*c))
+            {
+                warnings.push(format!("{}: {:?} is not allowed in a name", display, bad));
+            } else if name.ends_with('.') || name.ends_with(' ') {
+                warnings.push(format!("{}: name ends with a dot or space", display));
+            } else if name.encode_utf16().count() > 255 {
+                warnings.push(format!("{}: name is longer than 255 characters", display));
+            }
+        }
+        if !seen.insert(display.to_lowercase()) {
+            warnings.push(format!("{}: collides with another path ignoring case", display));
+        }
+    }
+    warnings
+}
+
+fn normal_components(path: &Path) -> impl Iterator<Item = &std::ffi::OsStr> {
+    path.components().filter_map(|component| match component {
+        Component::Normal(part) => Some(part),
+        _ => None,
+    })
+}
+
+fn display(path: &Path) -> String {
+    path.to_string_lossy().replace('\\', "/")
+}
@@ -0,0 +1,411 @@
+//! Workflow logs with a wall-clock timestamp and a monotonic offset on
+//! every line, plus per-phase durations written to `timing.json`, so slow
+//! runs show where the time went. Transient I/O errors that were retried
+//! during the run are listed there too. With an OTLP endpoint configured
+//! the run and its phases are also exported as trace spans.
+
+use anyhow::Result;
+use phoenix_core::now_utc_rfc3339;
+use phoenix_imaging::retry::{take_failures, take_retries, IoFailure, RetryEvent};
+use phoenix_notify::{export_spans, new_span_id, new_trace_id, otlp_traces_endpoint, Span};
+use phoenix_report::ReportArtifact;
+use serde::Serialize;
+use std::cell::RefCell;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+pub const TIMING_FILE: &str = "timing.json";
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LogEntry {
+    pub utc: String,
+    /// Milliseconds since the workflow started, from a monotonic clock.
+    pub elapsed_ms: u64,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PhaseTiming {
+    pub phase: String,
+    pub start_ms: u64,
+    pub duration_ms: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RunTiming {
+    pub started_utc: String,
+    pub total_ms: u64,
+    pub phases: Vec<PhaseTiming>,
+    /// Set when the run was exported as an OTLP trace.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trace_id: Option<String>,
+    /// Transient device errors that succeeded on a retry.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub io_retries: Vec<RetryEvent>,
+}
+
+#[derive(Debug, Clone)]
+struct TraceContext {
+    endpoint: String,
+    trace_id: String,
+    span_id: String,
+    parent_span_id: Option<String>,
+}
+
+/// A run whose log was dropped before `finish`, kept for failure
+/// diagnostics.
+#[derive(Debug, Clone)]
+pub struct FailedRun {
+    pub workflow: String,
+    pub log: String,
+    pub timing: RunTiming,
+    /// The last I/O errors the run gave up on.
+    pub io_failures: Vec<IoFailure>,
+}
+
+/// Failed runs kept per thread until someone takes them.
+const KEPT_FAILED_RUNS: usize = 8;
+
+thread_local! {
+    /// Trace and span id of the enclosing workflow definition run.
+    static TRACE_PARENT: RefCell<Option<(String, String)>> = const { RefCell::new(None) };
+    static FAILED_RUNS: RefCell<Vec<FailedRun>> = const { RefCell::new(Vec::new()) };
+    static LOG_LISTENER: RefCell<Option<LogListener>> = const { RefCell::new(None) };
+    static PROGRESS_LISTENER: RefCell<Option<ProgressListener>> = const { RefCell::new(None) };
+}
+
+/// Called with the workflow name and each line as it is logged.
+pub type LogListener = Box<dyn FnMut(&str, &LogEntry)>;
+
+/// A log line as the language bindings pass it to progress callbacks.
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS))]
+pub struct ProgressEvent {
+    pub workflow: String,
+    pub message: String,
+    #[cfg_attr(feature = "ts", ts(as = "f64"))]
+    pub elapsed_ms: u64,
+}
+
+impl ProgressEvent {
+    pub fn new(workflow: &str, entry: &LogEntry) -> Self {
+        Self {
+            workflow: workflow.to_string(),
+            message: entry.message.clone(),
+            elapsed_ms: entry.elapsed_ms,
+        }
+    }
+}
+
+/// Runs `f` with `listener` seeing every log line pushed on this thread,
+/// e.g. to show a run's progress live in an embedding application.
+pub fn with_log_listener<T>(listener: LogListener, f: impl FnOnce() -> T) -> T {
+    let previous = LOG_LISTENER.with(|current| current.borrow_mut().replace(listener));
+    let result = f();
+    LOG_LISTENER.with(|current| *current.borrow_mut() = previous);
+    result
+}
+
+/// Bytes moved so far by a long phase (file copies, image writes). Sent to
+/// progress listeners only; it is not a log line.
+#[derive(Debug, Clone, Serialize)]
+pub struct TransferProgress {
+    pub workflow: String,
+    pub phase: String,
+    pub done_bytes: u64,
+    pub total_bytes: u64,
+    /// Since the phase started.
+    pub elapsed_ms: u64,
+}
+
+/// Called with every transfer update on the thread; updates can come per
+/// file or per chunk, so listeners rate-limit their own output.
+pub type ProgressListener = Box<dyn FnMut(&TransferProgress)>;
+
+/// Runs `f` with `listener` seeing the transfer progress of every log on
+/// this thread, e.g. to draw a progress bar.
+pub fn with_progress_listener<T>(listener: ProgressListener, f: impl FnOnce() -> T) -> T {
+    let previous = PROGRESS_LISTENER.with(|current| current.borrow_mut().replace(listener));
+    let result = f();
+    PROGRESS_LISTENER.with(|current| *current.borrow_mut() = previous);
+    result
+}
+
+/// Reports progress for the phase a log was in when it was made, for
+/// observers that cannot borrow the log while it is written to.
+#[derive(Debug, Clone)]
+pub struct ProgressReporter {
+    workflow: String,
+    phase: String,
+    started: Instant,
+}
+
+impl ProgressReporter {
+    pub fn report(&self, done_bytes: u64, total_bytes: u64) {
+        PROGRESS_LISTENER.with(|listener| {
+            if let Ok(mut listener) = listener.try_borrow_mut() {
+                if let Some(listener) = listener.as_mut() {
+                    listener(&TransferProgress {
+                        workflow: self.workflow.clone(),
+                        phase: self.phase.clone(),
+                        done_bytes,
+                        total_bytes,
+                        elapsed_ms: self.started.elapsed().as_millis() as u64,
+                    });
+                }
+            }
+        });
+    }
+}
+
+/// Runs that failed on this thread since the last call, innermost first.
+pub fn take_failed_runs() -> Vec<FailedRun> {
+    FAILED_RUNS.with(|runs| std::mem::take(&mut *runs.borrow_mut()))
+}
+
+#[derive(Debug)]
+pub struct StepLog {
+    workflow: String,
+    started: Instant,
+    started_utc: String,
+    started_unix_nanos: u64,
+    entries: Vec<LogEntry>,
+    phases: Vec<PhaseTiming>,
+    /// Index into `phases` of the phase still running.
+    open_phase: Option<usize>,
+    io_retries: Vec<RetryEvent>,
+    trace: Option<TraceContext>,
+    finished: bool,
+}
+
+impl StepLog {
+    /// Starts the log with a `workflow=<name>` line.
+    pub fn new(workflow: &str) -> Self {
+        let trace = otlp_traces_endpoint().map(|endpoint| {
+            let parent = TRACE_PARENT.with(|parent| parent.borrow().clone());
+            TraceContext {
+                endpoint,
+                trace_id: parent.as_ref().map_or_else(new_trace_id, |(trace, _)| trace.clone()),
+                span_id: new_span_id(),
+                parent_span_id: parent.map(|(_, span)| span),
+            }
+        });
+        let mut log = Self {
+            workflow: workflow.to_string(),
+            started: Instant::now(),
+            started_utc: now_utc_rfc3339(),
+            started_unix_nanos: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|elapsed| elapsed.as_nanos() as u64)
+                .unwrap_or_default(),
+            entries: Vec::new(),
+            phases: Vec::new(),
+            open_phase: None,
+            io_retries: Vec::new(),
+            trace,
+            finished: false,
+        };
+        log.push(format!("workflow={}", workflow));
+        log
+    }
+
+    pub(crate) fn workflow(&self) -> &str {
+        &self.workflow
+    }
+
+    /// Runs `f` with this log's span as the parent of every `StepLog`
+    /// created inside it on this thread.
+    pub(crate) fn in_trace<T>(&self, f: impl FnOnce() -> T) -> T {
+        let Some(trace) = &self.trace else {
+            return f();
+        };
+        let context = (trace.trace_id.clone(), trace.span_id.clone());
+        let previous = TRACE_PARENT.with(|parent| parent.borrow_mut().replace(context));
+        let result = f();
+        TRACE_PARENT.with(|parent| *parent.borrow_mut() = previous);
+        result
+    }
+
+    pub fn push(&mut self, message: impl Into<String>) {
+        let entry = LogEntry {
+            utc: now_utc_rfc3339(),
+            elapsed_ms: self.elapsed_ms(),
+            message: message.into(),
+        };
+        // A listener that logs through a StepLog itself is not re-entered.
+        LOG_LISTENER.with(|listener| {
+            if let Ok(mut listener) = listener.try_borrow_mut() {
+                if let Some(listener) = listener.as_mut() {
+                    listener(&self.workflow, &entry);
+                }
+            }
+        });
+        self.entries.push(entry);
+    }
+
+    /// Reports `done_bytes` of `total_bytes` moved in the running phase.
+    pub fn progress(&self, done_bytes: u64, total_bytes: u64) {
+        self.progress_reporter().report(done_bytes, total_bytes);
+    }
+
+    pub fn progress_reporter(&self) -> ProgressReporter {
+        let phase = self.open_phase.map(|index| &self.phases[index]);
+        ProgressReporter {
+            workflow: self.workflow.clone(),
+            phase: phase.map(|phase| phase.phase.clone()).unwrap_or_default(),
+            started: self.started
+                + std::time::Duration::from_millis(phase.map_or(0, |phase| phase.start_ms)),
+        }
+    }
+
+    /// Ends the running phase, if any, and starts `phase`.
+    pub fn phase(&mut self, phase: &str) {
+        self.end_phase();
+        let start_ms = self.elapsed_ms();
+        self.open_phase = Some(self.phases.len());
+        self.phases.push(PhaseTiming {
+            phase: phase.to_string(),
+            start_ms,
+            duration_ms: 0,
+        });
+        self.push(format!("phase={}", phase));
+    }
+
+    /// Ends the running phase, logging its duration.
+    pub fn end_phase(&mut self) {
+        let Some(index) = self.open_phase.take() else {
+            return;
+        };
+        let elapsed = self.elapsed_ms();
+        let phase = &mut self.phases[index];
+        phase.duration_ms = elapsed.saturating_sub(phase.start_ms);
+        let line = format!("phase_done={} duration_ms={}", phase.phase, phase.duration_ms);
+        self.push(line);
+    }
+
+    pub fn entries(&self) -> &[LogEntry] {
+        &self.entries
+    }
+
+    /// Contents of `logs.txt`: `<utc> +<elapsed>ms <message>` per line.
+    pub fn text(&self) -> String {
+        self.entries
+            .iter()
+            .map(|entry| format!("{} +{}ms {}", entry.utc, entry.elapsed_ms, entry.message))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    pub fn timing(&self) -> RunTiming {
+        let total_ms = self.elapsed_ms();
+        let mut phases = self.phases.clone();
+        // A phase still open when the report is written ran until now.
+        if let Some(index) = self.open_phase {
+            phases[index].duration_ms = total_ms.saturating_sub(phases[index].start_ms);
+        }
+        RunTiming {
+            started_utc: self.started_utc.clone(),
+            total_ms,
+            phases,
+            trace_id: self.trace.as_ref().map(|trace| trace.trace_id.clone()),
+            io_retries: self.io_retries.clone(),
+        }
+    }
+
+    /// Ends the running phase, collects the I/O retries made on this
+    /// thread, exports the trace and returns the text for `logs.txt` and
+    /// the `timing.json` artifact. A failed export is logged; it never
+    /// fails the run.
+    pub(crate) fn finish(&mut self) -> Result<(String, ReportArtifact)> {
+        self.end_phase();
+        self.finished = true;
+        // Errors the run recovered from are not what failed a later one.
+        take_failures();
+        for retry in take_retries() {
+            self.push(format!(
+                "io_retry target={} attempt={} delay_ms={} error={}",
+                retry.target, retry.attempt, retry.delay_ms, retry.error
+            ));
+            self.io_retries.push(retry);
+        }
+        let exported = self.trace.as_ref().map(|trace| {
+            match export_spans(&trace.endpoint, &self.spans(None)) {
+                Ok(()) => format!("otlp_trace_id={}", trace.trace_id),
+                Err(err) => format!("otlp_export_error={:#}", err),
+            }
+        });
+        if let Some(line) = exported {
+            self.push(line);
+        }
+        let timing = ReportArtifact::json(TIMING_FILE, &self.timing())?;
+        Ok((self.text(), timing))
+    }
+
+    /// The run span and one child per phase.
+    fn spans(&self, error: Option<&str>) -> Vec<Span> {
+        let Some(trace) = &self.trace else {
+            return Vec::new();
+        };
+        let timing = self.timing();
+        let at = |ms: u64| self.started_unix_nanos + ms * 1_000_000;
+        let mut attributes = vec![("phoenix.workflow".to_string(), self.workflow.clone())];
+        if let Some(id) = phoenix_report::correlation_id() {
+            attributes.push(("phoenix.correlation_id".to_string(), id));
+        }
+        let mut spans = vec![Span {
+            trace_id: trace.trace_id.clone(),
+            span_id: trace.span_id.clone(),
+            parent_span_id: trace.parent_span_id.clone(),
+            name: self.workflow.clone(),
+            start_unix_nanos: self.started_unix_nanos,
+            end_unix_nanos: at(timing.total_ms),
+            error: error.map(str::to_string),
+            attributes,
+        }];
+        for phase in &timing.phases {
+            spans.push(Span {
+                trace_id: trace.trace_id.clone(),
+                span_id: new_span_id(),
+                parent_span_id: Some(trace.span_id.clone()),
+                name: format!("{} {}", self.workflow, phase.phase),
+                start_unix_nanos: at(phase.start_ms),
+                end_unix_nanos: at(phase.start_ms + phase.duration_ms),
+                error: None,
+                attributes: vec![("phoenix.phase".to_string(), phase.phase.clone())],
+            });
+        }
+        spans
+    }
+
+    fn elapsed_ms(&self) -> u64 {
+        self.started.elapsed().as_millis() as u64
+    }
+}
+
+impl Drop for StepLog {
+    /// A log dropped before `finish` belongs to a run that returned an
+    /// error; its span is exported as failed and the run is kept for
+    /// `take_failed_runs`.
+    fn drop(&mut self) {
+        if self.finished {
+            return;
+        }
+        self.io_retries.extend(take_retries());
+        let failed = FailedRun {
+            workflow: self.workflow.clone(),
+            log: self.text(),
+            timing: self.timing(),
+            io_failures: take_failures(),
+        };
+        FAILED_RUNS.with(|runs| {
+            let mut runs = runs.borrow_mut();
+            if runs.len() == KEPT_FAILED_RUNS {
+                runs.remove(0);
+            }
+            runs.push(failed);
+        });
+        if let Some(trace) = &self.trace {
+            let spans = self.spans(Some("run failed before its report was written"));
+            let _ = export_spans(&trace.endpoint, &spans);
+        }
+    }
+}
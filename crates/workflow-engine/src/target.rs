@@ -0,0 +1,139 @@
+use crate::{disk_id_from_device_path, find_disk_by_mount, normalize_mount_path};
+use phoenix_core::{DeviceGraph, Disk};
+use serde::Serialize;
+use std::path::Path;
+
+/// Why a target would or would not be selected, replaying the resolution
+/// and disk checks the workflows run.
+#[derive(Debug, Clone, Serialize)]
+pub struct TargetExplanation {
+    pub target: String,
+    pub disk_id: Option<String>,
+    /// `disk_id`, `device_path`, `mount` or `usb_port`.
+    pub matched_by: Option<String>,
+    pub eligible: bool,
+    pub reasons: Vec<String>,
+}
+
+/// Resolves `target` (a disk id, device path, mount point, or USB port
+/// label or path) against `graph`, e.g. one loaded from a customer's
+/// report bundle.
+pub fn explain_target(graph: &DeviceGraph, target: &str) -> TargetExplanation {
+    let (disk, matched_by) = match resolve(graph, target) {
+        Some((disk, matched_by)) => (disk, matched_by),
+        None => {
+            let known: Vec<&str> = graph.disks.iter().map(|disk| disk.id.as_str()).collect();
+            return TargetExplanation {
+                target: target.to_string(),
+                disk_id: None,
+                matched_by: None,
+                eligible: false,
+                reasons: vec![format!(
+                    "no disk, device or mount point matches; disks in graph: {}",
+                    known.join(", ")
+                )],
+            };
+        }
+    };
+
+    let mut reasons = Vec::new();
+    if let Some(guid) = &disk.booted_esp {
+        reasons.push(format!(
+            "refusing to target system disk: {} holds the EFI system partition this host booted from ({})",
+            disk.id, guid
+        ));
+    } else if disk.is_system_disk {
+        reasons.push(format!("refusing to target system disk: {}", disk.id));
+    }
+    if !disk.removable {
+        reasons.push(format!("target disk is not marked removable: {}", disk.id));
+    }
+    if let Some(reason) = stack_usage(graph, disk) {
+        reasons.push(reason);
+    }
+    if let Some(claim) = disk.history.as_ref().and_then(|history| history.claim.as_ref()) {
+        if let Err(reason) = crate::registry::claim_problem(claim) {
+            reasons.push(format!("{} is {}", disk.id, reason));
+        }
+    }
+    let eligible = reasons.is_empty();
+    if eligible {
+        reasons.push(format!(
+            "{} passes the disk checks; a real run still needs --force and a PHX- token",
+            disk.id
+        ));
+    }
+    TargetExplanation {
+        target: target.to_string(),
+        disk_id: Some(disk.id.clone()),
+        matched_by: Some(matched_by.to_string()),
+        eligible,
+        reasons,
+    }
+}
+
+/// Describes the LUKS, LVM and RAID devices assembled from `disk`, or
+/// `None` when there are none. Writing such a disk corrupts the device
+/// above it, mounted or not.
+pub(crate) fn stack_usage(graph: &DeviceGraph, disk: &Disk) -> Option<String> {
+    let stacks = graph.stacks_on_disk(disk);
+    if stacks.is_empty() {
+        return None;
+    }
+    let described: Vec<String> = stacks
+        .iter()
+        .map(|stack| {
+            let mut text = match &stack.name {
+                Some(name) => format!("{} ({} {})", stack.id, stack.kind, name),
+                None => format!("{} ({})", stack.id, stack.kind),
+            };
+            if !stack.mount_points.is_empty() {
+                text.push_str(&format!(" mounted at {}", stack.mount_points.join(", ")));
+            }
+            text
+        })
+        .collect();
+    Some(format!(
+        "target disk {} is in use by {}; close or stop it first",
+        disk.id,
+        described.join(", ")
+    ))
+}
+
+pub(crate) fn resolve<'a>(graph: &'a DeviceGraph, target: &str) -> Option<(&'a Disk, &'static str)> {
+    let id = target.strip_prefix(r"\\.\").unwrap_or(target);
+    if let Some(disk) = graph.disks.iter().find(|disk| disk.id.eq_ignore_ascii_case(id)) {
+        return Some((disk, "disk_id"));
+    }
+    if let Some(disk) = graph.disks.iter().find(|disk| {
+        disk.usb_port.as_ref().is_some_and(|port| {
+            port.path == target
+                || port
+                    .label
+                    .as_deref()
+                    .is_some_and(|label| label.eq_ignore_ascii_case(target))
+        })
+    }) {
+        return Some((disk, "usb_port"));
+    }
+    let path = Path::new(target);
+    if let Some(disk) = find_disk_by_mount(graph, path) {
+        return Some((disk, "mount"));
+    }
+    // Windows reports mount points as `E:\`.
+    let windows_mount = normalize_mount_path(path).display().to_string();
+    if let Some(disk) = graph.disks.iter().find(|disk| {
+        disk.partitions
+            .iter()
+            .flat_map(|partition| partition.mount_points.iter())
+            .any(|mount| mount.eq_ignore_ascii_case(&windows_mount))
+    }) {
+        return Some((disk, "mount"));
+    }
+    let id = disk_id_from_device_path(path)?;
+    graph
+        .disks
+        .iter()
+        .find(|disk| disk.id.eq_ignore_ascii_case(&id))
+        .map(|disk| (disk, "device_path"))
+}
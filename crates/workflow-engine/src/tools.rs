@@ -0,0 +1,320 @@
+//! Pre-flight check of the external programs a workflow's steps run:
+//! `diskutil`, `hdiutil`, `asr`, `createinstallmedia`, `startosinstall`,
+//! `cfgutil`, `idevicerestore`, `mkfs.exfat`, `cryptsetup`, `veracrypt`
+//! and DISM. Every missing or
+//! outdated tool is listed in one error before the first step starts,
+//! instead of the run failing half way through.
+
+use crate::cancel::command_output;
+use crate::ipsw::RestoreTool;
+use crate::mac_compat::inspect_installer_app;
+use crate::{
+    media_keep_list, optional_string, parse_data_encryption, DataEncryption, WorkflowDefinition,
+    WorkflowStep,
+};
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// `createinstallmedia` before Mojave also needs `--applicationpath`,
+/// which the installer workflow does not pass.
+const CREATEINSTALLMEDIA_MINIMUM: (u32, u32) = (10, 14);
+/// `--eraseinstall` shipped with the High Sierra installer.
+const STARTOSINSTALL_MINIMUM: (u32, u32) = (10, 13);
+/// Apple Configurator 2.14 added Apple Silicon revive and restore.
+const CFGUTIL_MINIMUM: (u32, u32) = (2, 14);
+const IDEVICERESTORE_MINIMUM: (u32, u32) = (1, 0);
+/// Any exfatprogs or exfat-utils release takes `-n <label>`.
+const MKFS_EXFAT_MINIMUM: (u32, u32) = (1, 0);
+/// LUKS2 arrived in cryptsetup 2.0.
+const CRYPTSETUP_MINIMUM: (u32, u32) = (2, 0);
+/// `--stdin` for the password arrived in VeraCrypt 1.24.
+const VERACRYPT_MINIMUM: (u32, u32) = (1, 24);
+/// `/Export-Image` and `/Split-Image` need the Windows 8 DISM.
+const DISM_MINIMUM: (u32, u32) = (6, 2);
+
+/// One external tool a step needs, as found on this host.
+#[derive(Debug, Clone, Serialize)]
+pub struct ToolCheck {
+    pub step: String,
+    pub tool: String,
+    pub path: Option<PathBuf>,
+    pub version: Option<String>,
+    pub minimum: Option<String>,
+    /// Why the tool is unusable; `None` when it is fine.
+    pub problem: Option<String>,
+}
+
+/// Ways of finding a tool; a step that can use either of two tools lists
+/// both and needs one.
+enum Probe {
+    /// Ships with the OS at a fixed path; being there is enough.
+    System(&'static str),
+    /// Searched on `PATH` and run with `version_args` for its version.
+    Program {
+        name: &'static str,
+        version_args: &'static [&'static str],
+        minimum: (u32, u32),
+    },
+    /// Inside an `Install macOS` app; its version is the installer's.
+    Installer {
+        app: PathBuf,
+        tool: &'static str,
+        minimum: (u32, u32),
+    },
+    /// The `dism.exe` phoenix-wim falls back to.
+    Dism,
+}
+
+impl Probe {
+    fn name(&self) -> &str {
+        match self {
+            Self::System(path) => path.rsplit('/').next().unwrap_or(path),
+            Self::Program { name, .. } => name,
+            Self::Installer { tool, .. } => tool,
+            Self::Dism => "dism",
+        }
+    }
+
+    fn minimum(&self) -> Option<(u32, u32)> {
+        match self {
+            Self::System(_) => None,
+            Self::Program { minimum, .. } | Self::Installer { minimum, .. } => Some(*minimum),
+            Self::Dism => Some(DISM_MINIMUM),
+        }
+    }
+
+    /// Path and version, or why the tool cannot be used.
+    fn run(&self) -> std::result::Result<(PathBuf, Option<String>), String> {
+        let (path, version) = match self {
+            Self::System(path) => {
+                let path = PathBuf::from(path);
+                if !path.exists() {
+                    return Err(format!("{} not found", path.display()));
+                }
+                (path, None)
+            }
+            Self::Program {
+                name, version_args, ..
+            } => {
+                let path = find_program(name).ok_or_else(|| format!("{} not found on PATH", name))?;
+                let version = program_version(&path, version_args);
+                (path, version)
+            }
+            Self::Installer { app, tool, .. } => {
+                let path = app.join("Contents/Resources").join(tool);
+                if !path.is_file() {
+                    return Err(format!("{} not found in {}", tool, app.display()));
+                }
+                let version = inspect_installer_app(app)
+                    .ok()
+                    .and_then(|support| support.macos_version);
+                (path, version)
+            }
+            Self::Dism => {
+                let path = phoenix_wim::dism_path().ok_or_else(|| "dism.exe not found".to_string())?;
+                let version = program_version(&path, &["/English", "/?"]);
+                (path, version)
+            }
+        };
+        if let (Some(minimum), Some(found)) = (self.minimum(), version.as_deref()) {
+            if parse_version(found).is_some_and(|found| found < minimum) {
+                return Err(format!(
+                    "{} {} is older than the required {}.{}",
+                    self.name(),
+                    found,
+                    minimum.0,
+                    minimum.1
+                ));
+            }
+        }
+        Ok((path, version))
+    }
+}
+
+/// Checks the tools of every step and fails with all problems at once.
+pub fn require_workflow_tools(definition: &WorkflowDefinition) -> Result<Vec<ToolCheck>> {
+    let checks = check_workflow_tools(definition)?;
+    let problems: Vec<String> = checks
+        .iter()
+        .filter_map(|check| {
+            check
+                .problem
+                .as_ref()
+                .map(|problem| format!("step {}: {}", check.step, problem))
+        })
+        .collect();
+    if !problems.is_empty() {
+        return Err(anyhow!(
+            "{} external tool(s) missing or outdated: {}",
+            problems.len(),
+            problems.join("; ")
+        ));
+    }
+    Ok(checks)
+}
+
+/// Tools the steps will run, found or not. Empty under `PHOENIX_HOST=mock`,
+/// where tool runs are only recorded.
+pub fn check_workflow_tools(definition: &WorkflowDefinition) -> Result<Vec<ToolCheck>> {
+    if phoenix_core::mock::is_active() {
+        return Ok(Vec::new());
+    }
+    let mut checks = Vec::new();
+    for step in &definition.steps {
+        for probes in step_tools(step)? {
+            checks.push(check_alternatives(&step.id, &probes));
+        }
+    }
+    Ok(checks)
+}
+
+/// The first alternative that works, else every alternative's problem.
+fn check_alternatives(step: &str, probes: &[Probe]) -> ToolCheck {
+    let names: Vec<&str> = probes.iter().map(Probe::name).collect();
+    let mut problems = Vec::new();
+    for probe in probes {
+        match probe.run() {
+            Ok((path, version)) => {
+                return ToolCheck {
+                    step: step.to_string(),
+                    tool: probe.name().to_string(),
+                    path: Some(path),
+                    version,
+                    minimum: probe.minimum().map(|(major, minor)| format!("{}.{}", major, minor)),
+                    problem: None,
+                }
+            }
+            Err(problem) => problems.push(problem),
+        }
+    }
+    ToolCheck {
+        step: step.to_string(),
+        tool: names.join(" or "),
+        path: None,
+        version: None,
+        minimum: None,
+        problem: Some(problems.join(", ")),
+    }
+}
+
+/// For each tool the step runs, the probes that can satisfy it.
+fn step_tools(step: &WorkflowStep) -> Result<Vec<Vec<Probe>>> {
+    let params = &step.params;
+    let extension = |key: &str| {
+        optional_string(params, key)
+            .and_then(|path| Path::new(path).extension().map(|ext| ext.to_ascii_lowercase()))
+            .and_then(|ext| ext.to_str().map(str::to_string))
+            .unwrap_or_default()
+    };
+    let mut tools = Vec::new();
+    match step.action.as_str() {
+        "macos_installer_usb" => {
+            tools.push(vec![Probe::System("/usr/sbin/diskutil")]);
+            match extension("source_path").as_str() {
+                "dmg" => {
+                    tools.push(vec![Probe::System("/usr/bin/hdiutil")]);
+                    tools.push(vec![Probe::System("/usr/sbin/asr")]);
+                }
+                "app" => tools.push(vec![Probe::Installer {
+                    app: PathBuf::from(optional_string(params, "source_path").unwrap_or_default()),
+                    tool: "createinstallmedia",
+                    minimum: CREATEINSTALLMEDIA_MINIMUM,
+                }]),
+                _ => {}
+            }
+        }
+        "macos_erase_install" => {
+            if let Some(app) = optional_string(params, "source_app") {
+                tools.push(vec![Probe::Installer {
+                    app: PathBuf::from(app),
+                    tool: "startosinstall",
+                    minimum: STARTOSINSTALL_MINIMUM,
+                }]);
+            }
+        }
+        "ipsw_restore" => {
+            let candidates = match optional_string(params, "tool") {
+                Some(tool) => vec![RestoreTool::parse(tool)?],
+                None => vec![RestoreTool::Cfgutil, RestoreTool::Idevicerestore],
+            };
+            tools.push(candidates.into_iter().map(restore_probe).collect());
+        }
+        "combo_stick" => {
+            tools.push(vec![Probe::Program {
+                name: "mkfs.exfat",
+                version_args: &["--version"],
+                minimum: MKFS_EXFAT_MINIMUM,
+            }]);
+            match optional_string(params, "data_encryption").map(parse_data_encryption).transpose()? {
+                Some(DataEncryption::Luks2) => tools.push(vec![Probe::Program {
+                    name: "cryptsetup",
+                    version_args: &["--version"],
+                    minimum: CRYPTSETUP_MINIMUM,
+                }]),
+                Some(DataEncryption::Veracrypt) => tools.push(vec![Probe::Program {
+                    name: "veracrypt",
+                    version_args: &["--text", "--version"],
+                    minimum: VERACRYPT_MINIMUM,
+                }]),
+                None => {}
+            }
+        }
+        "windows_apply_image" => tools.extend(dism_tools()),
+        "slim_windows_media" if !media_keep_list(params)?.editions.is_empty() => {
+            tools.extend(dism_tools())
+        }
+        _ => {}
+    }
+    Ok(tools)
+}
+
+fn restore_probe(tool: RestoreTool) -> Probe {
+    match tool {
+        RestoreTool::Cfgutil => Probe::Program {
+            name: "cfgutil",
+            version_args: &["version"],
+            minimum: CFGUTIL_MINIMUM,
+        },
+        RestoreTool::Idevicerestore => Probe::Program {
+            name: "idevicerestore",
+            version_args: &["--version"],
+            minimum: IDEVICERESTORE_MINIMUM,
+        },
+    }
+}
+
+/// DISM is only a tool to check when phoenix-wim falls back to it; a host
+/// without any backend fails the `wim_apply` capability instead.
+fn dism_tools() -> Option<Vec<Probe>> {
+    matches!(phoenix_wim::backend(), Ok(phoenix_wim::WimBackend::Dism(_))).then(|| vec![Probe::Dism])
+}
+
+/// Searches `PATH` for `name`, with the platform's executable suffix.
+pub fn find_program(name: &str) -> Option<PathBuf> {
+    let path = std::env::var_os("PATH")?;
+    let file = format!("{}{}", name, std::env::consts::EXE_SUFFIX);
+    std::env::split_paths(&path)
+        .map(|dir| dir.join(&file))
+        .find(|candidate| candidate.is_file())
+}
+
+/// The first dotted number the tool prints, such as `1.0.0` from
+/// `idevicerestore 1.0.0` or `10.0.22621.1` from DISM's `Version:` line.
+fn program_version(path: &Path, args: &[&str]) -> Option<String> {
+    let output = command_output(Command::new(path).args(args)).ok()?;
+    let mut text = String::from_utf8_lossy(&output.stdout).to_string();
+    text.push_str(&String::from_utf8_lossy(&output.stderr));
+    text.split(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .map(|token| token.trim_matches('.'))
+        .find(|token| token.contains('.') && parse_version(token).is_some())
+        .map(str::to_string)
+}
+
+fn parse_version(value: &str) -> Option<(u32, u32)> {
+    let mut parts = value.trim().split('.');
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next().map_or(Some(0), |minor| minor.parse().ok())?;
+    Some((major, minor))
+}
@@ -0,0 +1,158 @@
+//! A pack's `transforms` applied to the installer copy. Matching files are
+//! read and edited before the copy starts, so the free-space check and the
+//! verify pass see their edited sizes, and then written in place of a
+//! plain copy. Both hashes of each edited file go to the report.
+
+use crate::{to_hex, FileEntry, SourceFilter};
+use anyhow::{anyhow, Context, Result};
+use phoenix_content::{apply_transform, load_pack_manifest, PackManifest, TransformContext};
+use phoenix_report::ReportArtifact;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+pub const TRANSFORMED_FILES_FILE: &str = "transformed_files.json";
+
+/// Larger files are not read into memory to be edited.
+pub const MAX_TRANSFORM_BYTES: u64 = 16 * 1024 * 1024;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransformedFile {
+    /// Relative to the source root, `/`-separated.
+    pub path: String,
+    /// Labels of the transforms applied, in order.
+    pub transforms: Vec<String>,
+    pub original_bytes: u64,
+    pub original_sha256: String,
+    pub bytes: u64,
+    pub sha256: String,
+}
+
+pub(crate) struct Transforms {
+    manifest: PackManifest,
+    pack_root: PathBuf,
+    filters: Vec<SourceFilter>,
+    /// Edited content by index into the file list.
+    files: BTreeMap<usize, (TransformedFile, Vec<u8>)>,
+}
+
+impl Transforms {
+    pub(crate) fn load(manifest_path: &Path) -> Result<Self> {
+        let manifest = load_pack_manifest(manifest_path)
+            .with_context(|| format!("load transform pack {}", manifest_path.display()))?;
+        if manifest.transforms.is_empty() {
+            return Err(anyhow!("pack {} declares no transforms", manifest.name));
+        }
+        let pack_root = manifest_path
+            .parent()
+            .ok_or_else(|| anyhow!("pack manifest has no parent directory"))?
+            .to_path_buf();
+        let filters = manifest
+            .transforms
+            .iter()
+            .map(|transform| SourceFilter::new(vec![transform.path.clone()], Vec::new()))
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            manifest,
+            pack_root,
+            filters,
+            files: BTreeMap::new(),
+        })
+    }
+
+    pub(crate) fn pack_name(&self) -> &str {
+        &self.manifest.name
+    }
+
+    /// Edits every file a transform matches and sets its entry's size to
+    /// the edited size.
+    pub(crate) fn prepare(&mut self, files: &mut [FileEntry]) -> Result<()> {
+        for (index, entry) in files.iter_mut().enumerate() {
+            let matching: Vec<usize> = (0..self.filters.len())
+                .filter(|at| self.filters[*at].matches(&entry.relative_path))
+                .collect();
+            if matching.is_empty() {
+                continue;
+            }
+            let path = entry.relative_path.to_string_lossy().replace('\\', "/");
+            if entry.size > MAX_TRANSFORM_BYTES {
+                return Err(anyhow!(
+                    "{} is {} bytes; transforms edit files up to {}",
+                    path,
+                    entry.size,
+                    MAX_TRANSFORM_BYTES
+                ));
+            }
+            // The file may have grown since it was listed.
+            let mut original = Vec::new();
+            fs::File::open(&entry.absolute_path)
+                .and_then(|file| file.take(MAX_TRANSFORM_BYTES + 1).read_to_end(&mut original))
+                .with_context(|| format!("read {}", entry.absolute_path.display()))?;
+            if original.len() as u64 > MAX_TRANSFORM_BYTES {
+                return Err(anyhow!(
+                    "{} is over {} bytes; transforms edit files up to that size",
+                    path,
+                    MAX_TRANSFORM_BYTES
+                ));
+            }
+            if original.contains(&0) {
+                return Err(anyhow!("{} is binary and cannot be transformed", path));
+            }
+            let mut text = String::from_utf8(original.clone())
+                .map_err(|_| anyhow!("{} is not UTF-8 text and cannot be transformed", path))?;
+            let context = TransformContext {
+                pack_root: &self.pack_root,
+                pack_name: &self.manifest.name,
+                pack_version: &self.manifest.version,
+                path: &path,
+            };
+            let mut labels = Vec::new();
+            for at in matching {
+                let transform = &self.manifest.transforms[at];
+                let label = transform.label(at);
+                text = apply_transform(transform, &context, &text)
+                    .with_context(|| format!("transform {} on {}", label, path))?;
+                labels.push(label);
+            }
+            let content = text.into_bytes();
+            entry.size = content.len() as u64;
+            let record = TransformedFile {
+                path,
+                transforms: labels,
+                original_bytes: original.len() as u64,
+                original_sha256: to_hex(&Sha256::digest(&original)),
+                bytes: content.len() as u64,
+                sha256: to_hex(&Sha256::digest(&content)),
+            };
+            self.files.insert(index, (record, content));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn get(&self, index: usize) -> Option<(&TransformedFile, &[u8])> {
+        self.files
+            .get(&index)
+            .map(|(record, content)| (record, content.as_slice()))
+    }
+
+    pub(crate) fn files(&self) -> Vec<TransformedFile> {
+        self.files.values().map(|(record, _)| record.clone()).collect()
+    }
+
+    pub(crate) fn push_artifact(
+        &self,
+        artifacts: &mut Vec<ReportArtifact>,
+        artifact_names: &mut Vec<String>,
+    ) -> Result<()> {
+        if self.files.is_empty() {
+            return Ok(());
+        }
+        let artifact = ReportArtifact::json(TRANSFORMED_FILES_FILE, &self.files())?;
+        artifact_names.push(artifact.name.clone());
+        artifacts.push(artifact);
+        Ok(())
+    }
+}
@@ -0,0 +1,183 @@
+//! The organization's verification policy: which checks every run must
+//! make, whatever its step asked for, and when a check samples instead of
+//! reading everything back. Workflows consult it rather than relying on
+//! each caller to pass `verify` or `hash_destination`, and name the policy
+//! they applied in their report.
+
+use crate::ledger::state_dir;
+use crate::StepLog;
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const DEFAULT_SAMPLE_PERCENT: u32 = 10;
+
+/// Whether a kind of check is up to the step or always made.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VerifyRequirement {
+    #[default]
+    Optional,
+    Required,
+}
+
+/// `verify_policy.json`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct VerifyPolicy {
+    /// Named in the report of every run the policy applies to.
+    pub name: String,
+    /// Read image writes back (`verify`); for WIM applies, check the
+    /// applied size.
+    #[serde(default)]
+    pub image_writes: VerifyRequirement,
+    /// Hash copied files and read them back (`hash_manifest` and
+    /// `hash_destination`).
+    #[serde(default)]
+    pub file_copies: VerifyRequirement,
+    /// A read-back of more than this many bytes is sampled.
+    #[serde(default, with = "crate::params::byte_size")]
+    pub sample_above: Option<u64>,
+    /// Share of image chunks or copied files a sampled read-back checks.
+    #[serde(default = "default_sample_percent")]
+    pub sample_percent: u32,
+}
+
+fn default_sample_percent() -> u32 {
+    DEFAULT_SAMPLE_PERCENT
+}
+
+/// What a run's read-back covers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VerifyLevel {
+    Off,
+    Sampled,
+    Full,
+}
+
+/// The checks of one run once the policy is applied; `verify_policy` in
+/// its report meta.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AppliedVerify {
+    /// `None` without a policy file.
+    pub policy: Option<String>,
+    pub level: VerifyLevel,
+    /// The policy turned on a check the step left off.
+    pub required_by_policy: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sample_percent: Option<u32>,
+}
+
+impl AppliedVerify {
+    pub fn enabled(&self) -> bool {
+        self.level != VerifyLevel::Off
+    }
+}
+
+/// The kinds of check a policy covers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VerifyTarget {
+    ImageWrites,
+    FileCopies,
+}
+
+impl VerifyPolicy {
+    /// The policy at `path`; `None` when there is no file.
+    pub fn load(path: &Path) -> Result<Option<Self>> {
+        let bytes = match fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(anyhow!("read {} failed: {}", path.display(), err)),
+        };
+        let policy: Self =
+            serde_json::from_slice(&bytes).with_context(|| format!("parse {}", path.display()))?;
+        if policy.name.trim().is_empty() {
+            return Err(anyhow!("{}: name must not be empty", path.display()));
+        }
+        if policy.sample_percent == 0 || policy.sample_percent > 100 {
+            return Err(anyhow!(
+                "{}: sample_percent must be 1 to 100, got {}",
+                path.display(),
+                policy.sample_percent
+            ));
+        }
+        Ok(Some(policy))
+    }
+
+    fn requirement(&self, target: VerifyTarget) -> VerifyRequirement {
+        match target {
+            VerifyTarget::ImageWrites => self.image_writes,
+            VerifyTarget::FileCopies => self.file_copies,
+        }
+    }
+
+    /// The check for `target` when the step asked for `requested` and the
+    /// read-back would cover `bytes`.
+    pub fn apply(&self, target: VerifyTarget, requested: bool, bytes: u64) -> AppliedVerify {
+        let required = self.requirement(target) == VerifyRequirement::Required;
+        let sampled = self.sample_above.is_some_and(|limit| bytes > limit);
+        let level = match (requested || required, sampled) {
+            (false, _) => VerifyLevel::Off,
+            (true, true) => VerifyLevel::Sampled,
+            (true, false) => VerifyLevel::Full,
+        };
+        AppliedVerify {
+            policy: Some(self.name.clone()),
+            level,
+            required_by_policy: required && !requested,
+            sample_percent: (level == VerifyLevel::Sampled).then_some(self.sample_percent),
+        }
+    }
+}
+
+/// `$PHOENIX_VERIFY_POLICY`, else `verify_policy.json` in the state
+/// directory.
+pub fn verify_policy_path() -> Result<PathBuf> {
+    match std::env::var("PHOENIX_VERIFY_POLICY") {
+        Ok(path) => Ok(PathBuf::from(path)),
+        Err(_) => Ok(state_dir()?.join("verify_policy.json")),
+    }
+}
+
+pub fn verify_policy() -> Result<Option<VerifyPolicy>> {
+    VerifyPolicy::load(&verify_policy_path()?)
+}
+
+/// The check for `target`: the policy's, or exactly what the step asked
+/// for without one.
+pub(crate) fn resolve_verify(target: VerifyTarget, requested: bool, bytes: u64) -> Result<AppliedVerify> {
+    Ok(apply_verify(verify_policy()?.as_ref(), target, requested, bytes))
+}
+
+pub(crate) fn apply_verify(
+    policy: Option<&VerifyPolicy>,
+    target: VerifyTarget,
+    requested: bool,
+    bytes: u64,
+) -> AppliedVerify {
+    match policy {
+        Some(policy) => policy.apply(target, requested, bytes),
+        None => AppliedVerify {
+            policy: None,
+            level: if requested { VerifyLevel::Full } else { VerifyLevel::Off },
+            required_by_policy: false,
+            sample_percent: None,
+        },
+    }
+}
+
+pub(crate) fn log_verify(logs: &mut StepLog, verify: &AppliedVerify) {
+    let level = match verify.level {
+        VerifyLevel::Off => "off".to_string(),
+        VerifyLevel::Sampled => format!("sampled:{}%", verify.sample_percent.unwrap_or(100)),
+        VerifyLevel::Full => "full".to_string(),
+    };
+    logs.push(format!(
+        "verify_policy={} verify_level={} required_by_policy={}",
+        verify.policy.as_deref().unwrap_or("none"),
+        level,
+        verify.required_by_policy
+    ));
+}
@@ -0,0 +1,98 @@
+//! Time limits for workflow runs and steps (`timeout_secs`). A watchdog
+//! thread cancels the step through its cancel token once the limit
+//! passes, and external tools waited on through `cancel::command_output`
+//! are killed, so a stuck device or a wedged tool fails the run with
+//! `WorkflowTimeout` instead of holding a station forever.
+
+use crate::cancel::{current_token, with_cancel_token, CancelToken};
+use anyhow::Result;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// How often the watchdog passes on a cancel of the enclosing token.
+const POLL: Duration = Duration::from_millis(250);
+
+/// A run or step that went past its `timeout_secs`.
+#[derive(Debug, Clone)]
+pub struct WorkflowTimeout {
+    /// `run <name>` or `step <id>`.
+    pub scope: String,
+    pub limit_secs: u64,
+}
+
+impl WorkflowTimeout {
+    /// Stable code for reports, kiosk runs and scripts.
+    pub const CODE: &'static str = "workflow_timeout";
+    /// CLI exit status, as `timeout(1)` uses.
+    pub const EXIT_CODE: i32 = 124;
+}
+
+impl std::fmt::Display for WorkflowTimeout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} exceeded its {}s time limit and was cancelled",
+            self.scope, self.limit_secs
+        )
+    }
+}
+
+impl std::error::Error for WorkflowTimeout {}
+
+/// Machine-readable code of a workflow error, when it has one.
+pub fn error_code(err: &anyhow::Error) -> Option<&'static str> {
+    err.downcast_ref::<WorkflowTimeout>()
+        .map(|_| WorkflowTimeout::CODE)
+}
+
+/// Runs `f` under its own cancel token, which the watchdog cancels once
+/// `limit_secs` pass or the enclosing token is cancelled. An error after
+/// the limit fired becomes `WorkflowTimeout`; a result that made it back
+/// anyway is kept.
+pub(crate) fn with_time_limit<T>(
+    scope: String,
+    limit_secs: Option<u64>,
+    f: impl FnOnce() -> Result<T>,
+) -> Result<T> {
+    let Some(limit_secs) = limit_secs else {
+        return f();
+    };
+    let outer = current_token();
+    let token = CancelToken::new();
+    let fired = Arc::new(AtomicBool::new(false));
+    let (done_tx, done_rx) = mpsc::channel::<()>();
+    let watchdog = {
+        let token = token.clone();
+        let fired = fired.clone();
+        std::thread::spawn(move || {
+            let deadline = Instant::now() + Duration::from_secs(limit_secs);
+            loop {
+                let left = deadline.saturating_duration_since(Instant::now());
+                if left.is_zero() {
+                    fired.store(true, Ordering::SeqCst);
+                    token.cancel();
+                    return;
+                }
+                match done_rx.recv_timeout(left.min(POLL)) {
+                    Err(RecvTimeoutError::Timeout) => {
+                        if outer.as_ref().is_some_and(CancelToken::is_cancelled) {
+                            token.cancel();
+                        }
+                    }
+                    _ => return,
+                }
+            }
+        })
+    };
+    let result = with_cancel_token(&token, f);
+    drop(done_tx);
+    let _ = watchdog.join();
+    match result {
+        Err(_) if fired.load(Ordering::SeqCst) => {
+            Err(WorkflowTimeout { scope, limit_secs }.into())
+        }
+        result => result,
+    }
+}
@@ -0,0 +1,536 @@
+//! Step params built one question at a time, for guided front ends. Each
+//! answer is checked when it is given — the target against the device
+//! graph, the source on disk, the filesystem against the source's largest
+//! file, edition and product key against what Setup accepts — so a bad
+//! choice is refused at its own question instead of after submit. The
+//! session serializes, so a host can keep it between calls.
+
+use crate::media::{parse_edition_id, parse_product_key};
+use crate::staging::staging_backend;
+use crate::{
+    build_device_graph, collect_files, ensure_boot_files, explain_target, overwrite,
+    parse_filesystem_value, parse_sha256, registry, secrets, target, validate_step,
+};
+use anyhow::{anyhow, Result};
+use phoenix_content::prepare_source;
+use phoenix_core::{DeviceGraph, Disk, WorkflowStep};
+use phoenix_safety::{
+    check_device_wear, check_target_size, TargetSizeDecision, TargetSizeLimits, WearDecision,
+    WearLimits,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Actions a wizard can build.
+pub const WIZARD_ACTIONS: [&str; 3] = ["windows_installer_usb", "linux_write_image", "macos_write_image"];
+
+const INSTALLER_QUESTIONS: [&str; 8] = [
+    "target_disk_id",
+    "acknowledge_target_size",
+    "acknowledge_device_wear",
+    "confirm_overwrite",
+    "source_path",
+    "filesystem",
+    "edition",
+    "pid_txt",
+];
+
+const WRITE_IMAGE_QUESTIONS: [&str; 6] = [
+    "target_device",
+    "acknowledge_target_size",
+    "acknowledge_device_wear",
+    "confirm_overwrite",
+    "source_image",
+    "source_sha256",
+];
+
+const FILESYSTEMS: [&str; 3] = ["fat32", "ntfs", "exfat"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS))]
+#[serde(rename_all = "snake_case")]
+pub enum QuestionKind {
+    /// One of `choices`.
+    Choice,
+    /// A file or directory path; write image sources also take a URL.
+    Path,
+    /// Must be answered `true` to go on.
+    Confirm,
+    Text,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS))]
+pub struct WizardChoice {
+    pub value: String,
+    pub label: String,
+    /// Why the choice would be refused; `None` when it can be picked.
+    pub refused: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS))]
+pub struct WizardQuestion {
+    /// The param the answer becomes.
+    pub key: String,
+    pub prompt: String,
+    pub kind: QuestionKind,
+    pub choices: Vec<WizardChoice>,
+    pub default: Option<Value>,
+    /// Optional questions are skipped by answering `null`.
+    pub required: bool,
+}
+
+/// What the accepted target answer found, for the questions after it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS))]
+struct TargetFacts {
+    disk_id: String,
+    #[cfg_attr(feature = "ts", ts(as = "f64"))]
+    size_bytes: u64,
+    /// Why the disk is outside `TargetSizeLimits`, if it is.
+    size_problem: Option<String>,
+    /// Why the disk is past the wear limits, from its registry history.
+    #[serde(default)]
+    wear_problem: Option<String>,
+    overwrite_triggers: Vec<String>,
+}
+
+/// What the accepted source answer found.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS))]
+struct SourceFacts {
+    #[cfg_attr(feature = "ts", ts(as = "f64"))]
+    max_file_bytes: u64,
+    #[cfg_attr(feature = "ts", ts(as = "f64"))]
+    total_bytes: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "ts", derive(ts_rs::TS))]
+pub struct WizardSession {
+    action: String,
+    graph: DeviceGraph,
+    /// Accepted answers by param name; `null` for skipped ones.
+    answers: BTreeMap<String, Value>,
+    target: Option<TargetFacts>,
+    source: Option<SourceFacts>,
+}
+
+impl WizardSession {
+    /// A session for `action` over this host's device graph.
+    pub fn start(action: &str) -> Result<Self> {
+        Self::with_graph(action, build_device_graph()?)
+    }
+
+    pub fn with_graph(action: &str, graph: DeviceGraph) -> Result<Self> {
+        if !WIZARD_ACTIONS.contains(&action) {
+            return Err(anyhow!(
+                "no wizard for {}; wizards exist for {}",
+                action,
+                WIZARD_ACTIONS.join(", ")
+            ));
+        }
+        Ok(Self {
+            action: action.to_string(),
+            graph,
+            answers: BTreeMap::new(),
+            target: None,
+            source: None,
+        })
+    }
+
+    pub fn action(&self) -> &str {
+        &self.action
+    }
+
+    pub fn answers(&self) -> &BTreeMap<String, Value> {
+        &self.answers
+    }
+
+    /// Re-enumerates the disks. When the chosen target is gone or no longer
+    /// passes the disk checks, its answer and every later one are dropped.
+    pub fn refresh(&mut self) -> Result<()> {
+        self.graph = build_device_graph()?;
+        let key = self.keys()[0];
+        if let Some(Value::String(target)) = self.answers.get(key).cloned() {
+            if self.check_target(&target).is_err() {
+                self.clear_from(0);
+            }
+        }
+        Ok(())
+    }
+
+    /// The first question still unanswered; `None` once `finish` can run.
+    pub fn next_question(&self) -> Option<WizardQuestion> {
+        self.applicable_keys()
+            .into_iter()
+            .find(|key| !self.answers.contains_key(*key))
+            .map(|key| self.question(key))
+    }
+
+    /// Checks and records the answer to `key`, which is the next question
+    /// or one already answered. Changing an earlier answer drops the later
+    /// ones, since they were checked against it. Returns the next question.
+    pub fn answer(&mut self, key: &str, value: Value) -> Result<Option<WizardQuestion>> {
+        let keys = self.applicable_keys();
+        let position = keys
+            .iter()
+            .position(|candidate| *candidate == key)
+            .ok_or_else(|| anyhow!("{} is not a question for {} now", key, self.action))?;
+        if let Some(pending) = keys[..position]
+            .iter()
+            .find(|earlier| !self.answers.contains_key(**earlier))
+        {
+            return Err(anyhow!("answer {} before {}", pending, key));
+        }
+        let value = self.check(key, value)?;
+        let index = self.keys().iter().position(|candidate| *candidate == key).unwrap_or(0);
+        self.clear_from(index + 1);
+        self.answers.insert(key.to_string(), value);
+        Ok(self.next_question())
+    }
+
+    /// The finished step, checked the way a workflow file's step is.
+    pub fn finish(&self) -> Result<WorkflowStep> {
+        if let Some(question) = self.next_question() {
+            return Err(anyhow!("{} is not answered yet", question.key));
+        }
+        let params: serde_json::Map<String, Value> = self
+            .answers
+            .iter()
+            .filter(|(_, value)| !value.is_null())
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+        let step = WorkflowStep {
+            id: self.action.replace('_', "-"),
+            action: self.action.clone(),
+            params: Value::Object(params),
+            timeout_secs: None,
+        };
+        validate_step(&step)?;
+        Ok(step)
+    }
+
+    fn keys(&self) -> &'static [&'static str] {
+        match self.action.as_str() {
+            "windows_installer_usb" => &INSTALLER_QUESTIONS,
+            _ => &WRITE_IMAGE_QUESTIONS,
+        }
+    }
+
+    fn installer(&self) -> bool {
+        self.action == "windows_installer_usb"
+    }
+
+    /// Questions in order, without confirmations the chosen target does
+    /// not need.
+    fn applicable_keys(&self) -> Vec<&'static str> {
+        let target = self.target.as_ref();
+        self.keys()
+            .iter()
+            .copied()
+            .filter(|key| match *key {
+                "acknowledge_target_size" => target.is_some_and(|facts| facts.size_problem.is_some()),
+                "acknowledge_device_wear" => target.is_some_and(|facts| facts.wear_problem.is_some()),
+                "confirm_overwrite" => target.is_some_and(|facts| !facts.overwrite_triggers.is_empty()),
+                _ => true,
+            })
+            .collect()
+    }
+
+    /// Drops the answers to `keys()[index..]` and what they found.
+    fn clear_from(&mut self, index: usize) {
+        for key in &self.keys()[index.min(self.keys().len())..] {
+            self.answers.remove(*key);
+            match *key {
+                "target_disk_id" | "target_device" => self.target = None,
+                "source_path" | "source_image" => self.source = None,
+                _ => {}
+            }
+        }
+    }
+
+    fn question(&self, key: &'static str) -> WizardQuestion {
+        let ask = |prompt: String, kind: QuestionKind| WizardQuestion {
+            key: key.to_string(),
+            prompt,
+            kind,
+            choices: Vec::new(),
+            default: None,
+            required: true,
+        };
+        match key {
+            "target_disk_id" | "target_device" => WizardQuestion {
+                choices: self.target_choices(),
+                ..ask("Disk to write".to_string(), QuestionKind::Choice)
+            },
+            "acknowledge_target_size" => {
+                let problem = self
+                    .target
+                    .as_ref()
+                    .and_then(|facts| facts.size_problem.clone())
+                    .unwrap_or_default();
+                ask(format!("{}; write it anyway?", problem), QuestionKind::Confirm)
+            }
+            "acknowledge_device_wear" => {
+                let problem = self
+                    .target
+                    .as_ref()
+                    .and_then(|facts| facts.wear_problem.clone())
+                    .unwrap_or_default();
+                ask(
+                    format!("{}; write it anyway? The override is audited.", problem),
+                    QuestionKind::Confirm,
+                )
+            }
+            "confirm_overwrite" => {
+                let facts = self.target.as_ref();
+                ask(
+                    format!(
+                        "{} looks like personal data ({}); overwrite it?",
+                        facts.map(|facts| facts.disk_id.as_str()).unwrap_or_default(),
+                        facts.map(|facts| facts.overwrite_triggers.join("; ")).unwrap_or_default()
+                    ),
+                    QuestionKind::Confirm,
+                )
+            }
+            "source_path" => ask(
+                "Windows installer ISO or extracted installer folder".to_string(),
+                QuestionKind::Path,
+            ),
+            "source_image" => ask("Image file or http(s) URL".to_string(), QuestionKind::Path),
+            "source_sha256" => WizardQuestion {
+                required: !self.optional(key),
+                ..ask("Expected SHA-256 of the image".to_string(), QuestionKind::Text)
+            },
+            "filesystem" => {
+                let choices: Vec<WizardChoice> = FILESYSTEMS
+                    .iter()
+                    .map(|name| WizardChoice {
+                        value: name.to_string(),
+                        label: name.to_ascii_uppercase(),
+                        refused: self.check_filesystem(name).err().map(|err| err.to_string()),
+                    })
+                    .collect();
+                let default = choices
+                    .iter()
+                    .find(|choice| choice.refused.is_none())
+                    .map(|choice| Value::String(choice.value.clone()));
+                WizardQuestion {
+                    choices,
+                    default,
+                    ..ask("Filesystem for the installer volume".to_string(), QuestionKind::Choice)
+                }
+            }
+            "edition" => WizardQuestion {
+                required: !self.optional(key),
+                ..ask(
+                    "EditionID written to sources/EI.cfg so Setup skips edition choice".to_string(),
+                    QuestionKind::Text,
+                )
+            },
+            _ => WizardQuestion {
+                required: !self.optional(key),
+                ..ask("Product key written to sources/PID.txt".to_string(), QuestionKind::Text)
+            },
+        }
+    }
+
+    fn target_choices(&self) -> Vec<WizardChoice> {
+        self.graph
+            .disks
+            .iter()
+            .map(|disk| WizardChoice {
+                value: self.target_value(disk),
+                label: match disk.history.as_ref().and_then(|history| history.claim.as_ref()) {
+                    Some(claim) => format!(
+                        "{} ({}, {} bytes) [{}]",
+                        disk.friendly_name, disk.id, disk.size_bytes, claim.label
+                    ),
+                    None => format!("{} ({}, {} bytes)", disk.friendly_name, disk.id, disk.size_bytes),
+                },
+                refused: self.check_target(&disk.id).err().map(|err| err.to_string()),
+            })
+            .collect()
+    }
+
+    /// How the step names `disk`: its id for the installer, its device for
+    /// image writes.
+    fn target_value(&self, disk: &Disk) -> String {
+        if self.installer() {
+            disk.id.clone()
+        } else {
+            format!("/dev/{}", disk.id)
+        }
+    }
+
+    fn check(&mut self, key: &str, value: Value) -> Result<Value> {
+        if value.is_null() {
+            if !self.optional(key) {
+                return Err(anyhow!("{} is required", key));
+            }
+            return Ok(Value::Null);
+        }
+        match key {
+            "acknowledge_target_size" | "acknowledge_device_wear" | "confirm_overwrite" => {
+                match value.as_bool() {
+                    Some(true) => Ok(Value::Bool(true)),
+                    Some(false) => Err(anyhow!("choose another target, or confirm to write this one")),
+                    None => Err(anyhow!("{} must be true or false", key)),
+                }
+            }
+            _ => {
+                let text = value
+                    .as_str()
+                    .map(str::trim)
+                    .filter(|text| !text.is_empty())
+                    .ok_or_else(|| anyhow!("{} must be a non-empty string", key))?;
+                let accepted = match key {
+                    "target_disk_id" | "target_device" => {
+                        let (facts, value) = self.check_target(text)?;
+                        self.target = Some(facts);
+                        value
+                    }
+                    "source_path" => {
+                        self.source = Some(self.check_installer_source(text)?);
+                        text.to_string()
+                    }
+                    "source_image" => {
+                        self.source = self.check_image_source(text)?;
+                        text.to_string()
+                    }
+                    "source_sha256" => parse_sha256(text)?,
+                    "filesystem" => self.check_filesystem(text)?,
+                    "edition" => parse_edition_id(text)?.to_string(),
+                    // A secret:// key is checked once resolved, when the step runs.
+                    "pid_txt" if text.starts_with(secrets::SECRET_SCHEME) => text.to_string(),
+                    "pid_txt" => parse_product_key(text)?,
+                    other => return Err(anyhow!("{} is not a question for {}", other, self.action)),
+                };
+                Ok(Value::String(accepted))
+            }
+        }
+    }
+
+    fn optional(&self, key: &str) -> bool {
+        match key {
+            "edition" | "pid_txt" => true,
+            "source_sha256" => !self.plain_http_source(),
+            _ => false,
+        }
+    }
+
+    /// The disk checks a run makes before writing, and the param value for
+    /// the disk.
+    fn check_target(&self, text: &str) -> Result<(TargetFacts, String)> {
+        let explanation = explain_target(&self.graph, text);
+        let disk = target::resolve(&self.graph, text)
+            .map(|(disk, _)| disk)
+            .filter(|_| explanation.eligible)
+            .ok_or_else(|| anyhow!(explanation.reasons.join("; ")))?;
+        let limits = TargetSizeLimits::from_env().map_err(|err| anyhow!(err))?;
+        let size_problem = match check_target_size(disk.size_bytes, &limits, false) {
+            TargetSizeDecision::Deny(reason) => Some(reason),
+            _ => None,
+        };
+        let wear_limits = WearLimits::from_env().map_err(|err| anyhow!(err))?;
+        let wear = disk.history.as_ref().map(registry::history_wear).unwrap_or_default();
+        let wear_problem = match check_device_wear(&wear, &wear_limits, false) {
+            WearDecision::Deny(reason) => Some(reason),
+            _ => None,
+        };
+        if let Some(source) = &self.source {
+            check_fits(disk, source.total_bytes)?;
+        }
+        let facts = TargetFacts {
+            disk_id: disk.id.clone(),
+            size_bytes: disk.size_bytes,
+            size_problem,
+            wear_problem,
+            overwrite_triggers: overwrite::inspect_disk(disk).triggers,
+        };
+        Ok((facts, self.target_value(disk)))
+    }
+
+    /// Mounts the source the way the run will and checks its installer
+    /// layout and that it fits the target.
+    fn check_installer_source(&self, text: &str) -> Result<SourceFacts> {
+        let prepared = prepare_source(text)?;
+        if !prepared.root.join("setup.exe").exists() {
+            return Err(anyhow!(
+                "source missing setup.exe (provide extracted Windows installer files)"
+            ));
+        }
+        let files = collect_files(&prepared.root)?;
+        ensure_boot_files(&files)?;
+        let facts = SourceFacts {
+            max_file_bytes: files.iter().map(|entry| entry.size).max().unwrap_or(0),
+            total_bytes: files.iter().map(|entry| entry.size).sum(),
+        };
+        self.check_source_fits(facts.total_bytes)?;
+        Ok(facts)
+    }
+
+    /// A URL is taken as given; its size is known once the stream starts.
+    /// A container (DMG, VHD, qcow2, ...) needs the size of the disk it
+    /// holds, not of the file.
+    fn check_image_source(&self, text: &str) -> Result<Option<SourceFacts>> {
+        if phoenix_fetch::is_url(text) {
+            return Ok(None);
+        }
+        let path = Path::new(text);
+        if !path.exists() {
+            return Err(anyhow!("image not found: {}", path.display()));
+        }
+        let size = phoenix_imaging::open_image(path)?.disk_bytes;
+        self.check_source_fits(size)?;
+        Ok(Some(SourceFacts {
+            max_file_bytes: size,
+            total_bytes: size,
+        }))
+    }
+
+    fn check_source_fits(&self, total_bytes: u64) -> Result<()> {
+        let Some(facts) = &self.target else {
+            return Ok(());
+        };
+        match self.graph.disks.iter().find(|disk| disk.id == facts.disk_id) {
+            Some(disk) => check_fits(disk, total_bytes),
+            None => Ok(()),
+        }
+    }
+
+    fn check_filesystem(&self, text: &str) -> Result<String> {
+        let filesystem = parse_filesystem_value(text)?;
+        if let Some(problem) = self
+            .source
+            .as_ref()
+            .and_then(|source| staging_backend(filesystem).file_size_problem(source.max_file_bytes))
+        {
+            return Err(anyhow!(problem));
+        }
+        Ok(text.trim().to_ascii_lowercase())
+    }
+
+    fn plain_http_source(&self) -> bool {
+        self.answers
+            .get("source_image")
+            .and_then(Value::as_str)
+            .is_some_and(|source| phoenix_fetch::is_url(source) && !phoenix_fetch::is_https(source))
+    }
+}
+
+fn check_fits(disk: &Disk, total_bytes: u64) -> Result<()> {
+    if total_bytes > disk.size_bytes {
+        return Err(anyhow!(
+            "source needs {} bytes but {} holds {}",
+            total_bytes,
+            disk.id,
+            disk.size_bytes
+        ));
+    }
+    Ok(())
+}
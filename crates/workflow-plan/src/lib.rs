@@ -0,0 +1,300 @@
+//! The host-independent half of the workflow engine: which actions exist,
+//! the OS each one runs on, the host capabilities a step will use, and
+//! the checks a definition must pass before it can run anywhere. Nothing
+//! here looks at the machine it runs on, so a Mac can author and validate
+//! a Windows provisioning pack it cannot execute; the engine adds the
+//! host checks before it executes a plan.
+
+pub mod value;
+
+use anyhow::{anyhow, Result};
+use phoenix_core::{WorkflowDefinition, WorkflowStep, WORKFLOW_SCHEMA_VERSION};
+use serde::Serialize;
+use std::collections::{BTreeSet, HashSet};
+use std::path::Path;
+use value::{optional_bool, optional_string, optional_string_list};
+
+/// Every workflow action, with the only OS it runs on, if any.
+pub const ACTIONS: [(&str, Option<&str>); 29] = [
+    ("windows_installer_usb", Some("windows")),
+    ("windows_apply_image", Some("windows")),
+    ("linux_installer_usb", Some("linux")),
+    ("linux_write_image", Some("linux")),
+    ("linux_boot_prep", Some("linux")),
+    ("macos_write_image", Some("macos")),
+    ("macos_boot_prep", Some("macos")),
+    ("macos_installer_usb", Some("macos")),
+    ("macos_erase_install", Some("macos")),
+    ("macos_legacy_patch", Some("macos")),
+    ("macos_kext_stage", Some("macos")),
+    ("stage_bootloader", None),
+    ("stage_files", None),
+    ("stage_provisioning", None),
+    ("stage_firstboot", None),
+    ("boot_entry", None),
+    ("ipsw_restore", None),
+    ("report_verify", None),
+    ("disk_hash_report", None),
+    ("bad_block_scan", None),
+    ("combo_stick", Some("linux")),
+    ("ab_stick", Some("linux")),
+    ("ab_update", Some("linux")),
+    ("clone_device", None),
+    ("restore_report", None),
+    ("resize_partition", Some("linux")),
+    ("validate_source", None),
+    ("slim_windows_media", None),
+    ("merge_windows_languages", None),
+];
+
+/// The OS an action only runs on, if any.
+pub fn action_os(action: &str) -> Option<&'static str> {
+    ACTIONS
+        .iter()
+        .find(|(name, _)| *name == action)
+        .and_then(|(_, os)| *os)
+}
+
+pub fn is_known_action(action: &str) -> bool {
+    ACTIONS.iter().any(|(name, _)| *name == action)
+}
+
+/// A host capability a step uses; see the engine's `HostCapabilities`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CapabilityUse {
+    pub name: &'static str,
+    /// Used by a dry run, which needs no elevation.
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PlannedStep {
+    pub id: String,
+    pub action: String,
+    pub os: Option<&'static str>,
+    pub dry_run: bool,
+    pub capabilities: Vec<CapabilityUse>,
+}
+
+/// A definition that passed every check not depending on the host.
+#[derive(Debug, Clone, Serialize)]
+pub struct WorkflowPlan {
+    pub name: String,
+    /// The OS every OS-specific step runs on; `None` when all steps run
+    /// anywhere.
+    pub target_os: Option<&'static str>,
+    pub steps: Vec<PlannedStep>,
+}
+
+impl WorkflowPlan {
+    /// Capability names the steps use, sorted and without repeats.
+    pub fn capabilities(&self) -> BTreeSet<&'static str> {
+        self.steps
+            .iter()
+            .flat_map(|step| step.capabilities.iter().map(|capability| capability.name))
+            .collect()
+    }
+
+    pub fn runs_on(&self, os: &str) -> bool {
+        self.target_os.is_none_or(|target| target == os)
+    }
+}
+
+/// Checks the shape of a definition and the OS of its actions, and lists
+/// what every step needs. Action params are checked by the engine.
+pub fn plan_workflow(definition: &WorkflowDefinition) -> Result<WorkflowPlan> {
+    if definition.schema_version != WORKFLOW_SCHEMA_VERSION {
+        return Err(anyhow!(
+            "unsupported workflow schema version {}",
+            definition.schema_version
+        ));
+    }
+    if definition.steps.is_empty() {
+        return Err(anyhow!("workflow has no steps"));
+    }
+    if let Some(id) = &definition.correlation_id {
+        phoenix_report::validate_correlation_id(id)?;
+    }
+    if definition.timeout_secs == Some(0) {
+        return Err(anyhow!("workflow timeout_secs must be positive"));
+    }
+
+    let mut seen = HashSet::new();
+    let mut steps = Vec::new();
+    for step in &definition.steps {
+        if step.id.trim().is_empty() {
+            return Err(anyhow!("workflow step id is empty"));
+        }
+        if !seen.insert(step.id.clone()) {
+            return Err(anyhow!("duplicate step id {}", step.id));
+        }
+        if step.timeout_secs == Some(0) {
+            return Err(anyhow!("step {}: timeout_secs must be positive", step.id));
+        }
+        if !is_known_action(&step.action) {
+            return Err(anyhow!("unknown workflow action {}", step.action));
+        }
+        steps.push(PlannedStep {
+            id: step.id.clone(),
+            action: step.action.clone(),
+            os: action_os(&step.action),
+            dry_run: optional_bool(&step.params, "dry_run", true),
+            capabilities: step_capabilities(step)
+                .map_err(|err| anyhow!("step {}: {}", step.id, err))?,
+        });
+    }
+
+    let os: BTreeSet<&str> = steps.iter().filter_map(|step| step.os).collect();
+    if os.len() > 1 {
+        return Err(anyhow!(
+            "steps need {}; no host runs them all",
+            os.into_iter().collect::<Vec<_>>().join(" and ")
+        ));
+    }
+    Ok(WorkflowPlan {
+        name: definition.name.clone(),
+        target_os: os.into_iter().next(),
+        steps,
+    })
+}
+
+/// Host capabilities a step uses.
+pub fn step_capabilities(step: &WorkflowStep) -> Result<Vec<CapabilityUse>> {
+    let mut needs = Vec::new();
+    let mut need = |name, dry_run| needs.push(CapabilityUse { name, dry_run });
+    let params = &step.params;
+    let dry_run = optional_bool(params, "dry_run", true);
+    let iso_source = optional_string(params, "source_path")
+        .map(|path| path.to_ascii_lowercase().ends_with(".iso"))
+        .unwrap_or(false);
+    match step.action.as_str() {
+        "windows_installer_usb" => {
+            if iso_source {
+                need("iso_mount", dry_run);
+            }
+            let repartition = optional_bool(params, "repartition", false);
+            if repartition {
+                need("repartition", dry_run);
+            }
+            let exfat = optional_string(params, "filesystem")
+                .map(|fs| fs.trim().eq_ignore_ascii_case("exfat"))
+                .unwrap_or(false);
+            if exfat && (repartition || optional_bool(params, "format", false)) {
+                need("exfat_format", dry_run);
+            }
+            if optional_string(params, "edition_selector").is_some() {
+                // Listing the images needs wimgapi even in a dry run.
+                need("wim_apply", true);
+            }
+        }
+        "validate_source" if iso_source => {
+            need("iso_mount", true);
+        }
+        "merge_windows_languages" => {
+            let iso = optional_string_list(params, "language_sources")?
+                .iter()
+                .any(|path| path.to_ascii_lowercase().ends_with(".iso"));
+            if iso {
+                need("iso_mount", true);
+            }
+        }
+        "slim_windows_media" if keeps_editions(params)? => {
+            // Exporting images needs wimgapi but not elevation.
+            need("wim_apply", true);
+        }
+        "windows_apply_image" => {
+            if iso_source {
+                need("iso_mount", dry_run);
+            }
+            need("wim_apply", dry_run);
+        }
+        "linux_write_image" | "macos_write_image" | "combo_stick" | "ab_stick" | "ab_update"
+        | "clone_device" | "restore_report" | "resize_partition" => {
+            need("raw_write", dry_run);
+        }
+        "linux_installer_usb"
+            if optional_string(params, "format_device").is_some()
+                && !optional_bool(params, "udisks", false) =>
+        {
+            need("raw_write", dry_run);
+        }
+        _ => {}
+    }
+    Ok(needs)
+}
+
+/// Whether `slim_windows_media` keeps editions, from `editions` or the
+/// `keep_list` file.
+fn keeps_editions(params: &serde_json::Value) -> Result<bool> {
+    if !optional_string_list(params, "editions")?.is_empty() {
+        return Ok(true);
+    }
+    let Some(path) = optional_string(params, "keep_list") else {
+        return Ok(false);
+    };
+    let data = std::fs::read_to_string(Path::new(path))
+        .map_err(|err| anyhow!("read {}: {}", path, err))?;
+    let keep: serde_json::Value = serde_json::from_str(&data)
+        .map_err(|err| anyhow!("parse keep-list {}: {}", path, err))?;
+    Ok(!optional_string_list(&keep, "editions")?.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn step(id: &str, action: &str, params: serde_json::Value) -> WorkflowStep {
+        WorkflowStep {
+            id: id.to_string(),
+            action: action.to_string(),
+            params,
+            timeout_secs: None,
+        }
+    }
+
+    #[test]
+    fn plans_without_looking_at_the_host() {
+        let definition = WorkflowDefinition::new(
+            "windows-pack",
+            vec![
+                step("check", "validate_source", json!({"source_path": "win.iso"})),
+                step(
+                    "usb",
+                    "windows_installer_usb",
+                    json!({"source_path": "win.iso", "dry_run": false, "repartition": true}),
+                ),
+            ],
+        );
+        let plan = plan_workflow(&definition).unwrap();
+        assert_eq!(plan.target_os, Some("windows"));
+        assert!(plan.runs_on("windows") && !plan.runs_on("macos"));
+        assert_eq!(
+            plan.steps[1].capabilities,
+            vec![
+                CapabilityUse { name: "iso_mount", dry_run: false },
+                CapabilityUse { name: "repartition", dry_run: false },
+            ]
+        );
+        assert_eq!(
+            plan.capabilities().into_iter().collect::<Vec<_>>(),
+            ["iso_mount", "repartition"]
+        );
+    }
+
+    #[test]
+    fn rejects_steps_no_host_runs() {
+        let mixed = WorkflowDefinition::new(
+            "mixed",
+            vec![
+                step("a", "windows_apply_image", json!({})),
+                step("b", "macos_kext_stage", json!({})),
+            ],
+        );
+        let err = plan_workflow(&mixed).unwrap_err().to_string();
+        assert!(err.contains("macos and windows"), "{}", err);
+
+        let unknown = WorkflowDefinition::new("unknown", vec![step("a", "flash_bios", json!({}))]);
+        assert!(plan_workflow(&unknown).is_err());
+    }
+}
@@ -0,0 +1,59 @@
+//! Readers for the fields of a step's `params` object, shared by planning
+//! and by the engine's step builders.
+
+use anyhow::{anyhow, Result};
+
+pub fn require_string<'a>(value: &'a serde_json::Value, key: &str) -> Result<&'a str> {
+    value
+        .get(key)
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| anyhow!("missing string field {}", key))
+}
+
+pub fn optional_string<'a>(value: &'a serde_json::Value, key: &str) -> Option<&'a str> {
+    value.get(key).and_then(|v| v.as_str())
+}
+
+pub fn optional_string_list(value: &serde_json::Value, key: &str) -> Result<Vec<String>> {
+    let Some(entries) = value.get(key) else {
+        return Ok(Vec::new());
+    };
+    entries
+        .as_array()
+        .ok_or_else(|| anyhow!("{} must be an array", key))?
+        .iter()
+        .map(|entry| match entry {
+            serde_json::Value::String(text) => Ok(text.clone()),
+            serde_json::Value::Number(number) => Ok(number.to_string()),
+            _ => Err(anyhow!("{} entries must be strings", key)),
+        })
+        .collect()
+}
+
+pub fn optional_bool(value: &serde_json::Value, key: &str, default: bool) -> bool {
+    value.get(key).and_then(|v| v.as_bool()).unwrap_or(default)
+}
+
+/// `chunk_size` as a positive byte count; absent or `"auto"` means probe.
+pub fn optional_chunk_size(value: &serde_json::Value) -> Result<Option<u64>> {
+    match value.get("chunk_size") {
+        None | Some(serde_json::Value::Null) => Ok(None),
+        Some(serde_json::Value::String(text)) if text.eq_ignore_ascii_case("auto") => Ok(None),
+        Some(other) => match other.as_u64() {
+            Some(size) if size > 0 => Ok(Some(size)),
+            _ => Err(anyhow!("chunk_size must be a positive number or \"auto\"")),
+        },
+    }
+}
+
+/// A byte count given as a number or a size string such as `"64K"`.
+pub fn optional_size(value: &serde_json::Value, key: &str) -> Result<Option<u64>> {
+    match value.get(key) {
+        None | Some(serde_json::Value::Null) => Ok(None),
+        Some(serde_json::Value::String(text)) => Ok(Some(phoenix_partition::parse_size(text)?)),
+        Some(other) => other
+            .as_u64()
+            .map(Some)
+            .ok_or_else(|| anyhow!("{} must be a byte count", key)),
+    }
+}